@@ -177,7 +177,7 @@ fn create_cpu_nodes(
         if numa_nodes.len() > 1 {
             for numa_node_idx in 0..numa_nodes.len() {
                 let numa_node = numa_nodes.get(&(numa_node_idx as u32));
-                if numa_node.unwrap().cpus.contains(&(cpu_id as u8)) {
+                if numa_node.unwrap().cpus.contains(&(cpu_id as u32)) {
                     fdt.property_u32("numa-node-id", numa_node_idx as u32)?;
                 }
             }
@@ -510,6 +510,51 @@ fn create_gpio_node<T: DeviceInfoForFdt + Clone + Debug>(
     Ok(())
 }
 
+fn create_pflash_node<T: DeviceInfoForFdt + Clone + Debug>(
+    fdt: &mut FdtWriter,
+    dev_info: &T,
+) -> FdtWriterResult<()> {
+    // CFI flash holding the UEFI variables
+    let pflash_reg_prop = [dev_info.addr(), dev_info.length()];
+
+    let pflash_node = fdt.begin_node(&format!("flash@{:x}", dev_info.addr()))?;
+    fdt.property_string("compatible", "cfi-flash")?;
+    fdt.property_array_u64("reg", &pflash_reg_prop)?;
+    fdt.property_u32("bank-width", 4)?;
+    fdt.end_node(pflash_node)?;
+
+    Ok(())
+}
+
+fn create_fw_cfg_node<T: DeviceInfoForFdt + Clone + Debug>(
+    fdt: &mut FdtWriter,
+    dev_info: &T,
+) -> FdtWriterResult<()> {
+    let fw_cfg_reg_prop = [dev_info.addr(), dev_info.length()];
+
+    let fw_cfg_node = fdt.begin_node(&format!("fw-cfg@{:x}", dev_info.addr()))?;
+    fdt.property_string("compatible", "qemu,fw-cfg-mmio")?;
+    fdt.property_array_u64("reg", &fw_cfg_reg_prop)?;
+    fdt.property_null("dma-coherent")?;
+    fdt.end_node(fw_cfg_node)?;
+
+    Ok(())
+}
+
+fn create_pvpanic_node<T: DeviceInfoForFdt + Clone + Debug>(
+    fdt: &mut FdtWriter,
+    dev_info: &T,
+) -> FdtWriterResult<()> {
+    let pvpanic_reg_prop = [dev_info.addr(), dev_info.length()];
+
+    let pvpanic_node = fdt.begin_node(&format!("pvpanic@{:x}", dev_info.addr()))?;
+    fdt.property_string("compatible", "qemu,pvpanic-mmio")?;
+    fdt.property_array_u64("reg", &pvpanic_reg_prop)?;
+    fdt.end_node(pvpanic_node)?;
+
+    Ok(())
+}
+
 fn create_devices_node<T: DeviceInfoForFdt + Clone + Debug, S: ::std::hash::BuildHasher>(
     fdt: &mut FdtWriter,
     dev_info: &HashMap<(DeviceType, String), T, S>,
@@ -519,7 +564,10 @@ fn create_devices_node<T: DeviceInfoForFdt + Clone + Debug, S: ::std::hash::Buil
 
     for ((device_type, _device_id), info) in dev_info {
         match device_type {
+            DeviceType::FwCfg => create_fw_cfg_node(fdt, info)?,
             DeviceType::Gpio => create_gpio_node(fdt, info)?,
+            DeviceType::Pflash => create_pflash_node(fdt, info)?,
+            DeviceType::PvPanic => create_pvpanic_node(fdt, info)?,
             DeviceType::Rtc => create_rtc_node(fdt, info)?,
             DeviceType::Serial => create_serial_node(fdt, info)?,
             DeviceType::Virtio(_) => {
@@ -45,6 +45,8 @@
 //           |                                                               |
 //           |                    Reserved (now GIC is here)                 |
 //           |                                                               |
+//  8  M     +---------------------------------------------------------------+
+//           |                     UEFI variable store flash                 |
 //  4  M     +---------------------------------------------------------------+
 //           |                          UEFI flash                           |
 // 0GB       +---------------------------------------------------------------+
@@ -58,6 +60,11 @@ use vm_memory::GuestAddress;
 pub const UEFI_START: GuestAddress = GuestAddress(0);
 pub const UEFI_SIZE: u64 = 0x040_0000;
 
+/// 0x40_0000 ~ 0x80_0000 (4 MiB) is reserved to the flash holding the
+/// persistent UEFI variables.
+pub const UEFI_VARS_START: GuestAddress = GuestAddress(UEFI_START.0 + UEFI_SIZE);
+pub const UEFI_VARS_SIZE: u64 = 0x040_0000;
+
 /// Below this address will reside the GIC, above this address will reside the MMIO devices.
 const MAPPED_IO_START: GuestAddress = GuestAddress(0x0900_0000);
 
@@ -76,6 +83,8 @@ pub const GIC_V3_ITS_SIZE: u64 = 0x02_0000;
 pub const LEGACY_SERIAL_MAPPED_IO_START: GuestAddress = MAPPED_IO_START;
 pub const LEGACY_RTC_MAPPED_IO_START: GuestAddress = GuestAddress(0x0901_0000);
 pub const LEGACY_GPIO_MAPPED_IO_START: GuestAddress = GuestAddress(0x0902_0000);
+pub const LEGACY_FW_CFG_MAPPED_IO_START: GuestAddress = GuestAddress(0x0903_0000);
+pub const LEGACY_PVPANIC_MAPPED_IO_START: GuestAddress = GuestAddress(0x0904_0000);
 
 /// Space 0x0905_0000 ~ 0x0906_0000 is reserved for pcie io address
 pub const MEM_PCI_IO_START: GuestAddress = GuestAddress(0x0905_0000);
@@ -48,6 +48,10 @@ pub enum Error {
     ModlistSetup(#[source] vm_memory::GuestMemoryError),
     #[error("RSDP extends past the end of guest memory")]
     RsdpPastRamEnd,
+    #[error("The zero page extends past the end of guest memory")]
+    ZeroPagePastRamEnd,
+    #[error("Error writing the zero page to guest memory: {0}")]
+    ZeroPageSetup(#[source] vm_memory::GuestMemoryError),
 }
 
 /// Type for returning public functions outcome.
@@ -91,8 +95,9 @@ pub mod x86_64;
 #[cfg(target_arch = "x86_64")]
 pub use x86_64::{
     arch_memory_regions, configure_system, configure_vcpu, generate_common_cpuid,
-    get_host_cpu_phys_bits, initramfs_load_addr, layout, layout::CMDLINE_MAX_SIZE,
-    layout::CMDLINE_START, regs, CpuidFeatureEntry, EntryPoint,
+    generate_msr_features, get_host_cpu_phys_bits, host_supports_la57, initramfs_load_addr, layout,
+    layout::CMDLINE_MAX_SIZE, layout::CMDLINE_START, regs, BootProtocol, CoreType, CpuModel,
+    CpuidConfig, CpuidFeature, CpuidFeatureEntry, EntryPoint, SmbiosMemoryDevice,
 };
 
 /// Safe wrapper for `sysconf(_SC_PAGESIZE)`.
@@ -107,11 +112,13 @@ fn pagesize() -> usize {
 pub struct NumaNode {
     pub memory_regions: Vec<Arc<GuestRegionMmap>>,
     pub hotplug_regions: Vec<Arc<GuestRegionMmap>>,
-    pub cpus: Vec<u8>,
+    pub cpus: Vec<u32>,
     pub distances: BTreeMap<u32, u8>,
     pub memory_zones: Vec<String>,
     #[cfg(target_arch = "x86_64")]
     pub sgx_epc_sections: Vec<SgxEpcSection>,
+    pub memory_latency: Option<u32>,
+    pub memory_bandwidth: Option<u32>,
 }
 
 pub type NumaNodes = BTreeMap<u32, NumaNode>;
@@ -138,6 +145,15 @@ pub enum DeviceType {
     /// Device Type: GPIO.
     #[cfg(target_arch = "aarch64")]
     Gpio,
+    /// Device Type: Parallel flash.
+    #[cfg(target_arch = "aarch64")]
+    Pflash,
+    /// Device Type: Firmware configuration.
+    #[cfg(target_arch = "aarch64")]
+    FwCfg,
+    /// Device Type: Panic notifier.
+    #[cfg(target_arch = "aarch64")]
+    PvPanic,
 }
 
 /// Default (smallest) memory page size for the supported architectures.
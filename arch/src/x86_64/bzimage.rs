@@ -0,0 +1,246 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Extraction of the compressed kernel embedded in a bzImage, so that it can
+//! be loaded directly instead of running the guest-side decompressor. The
+//! layout of the setup header is described by
+//! <https://www.kernel.org/doc/html/latest/x86/boot.html>
+
+use std::io::{self, Read, Seek};
+use std::result;
+
+const SETUP_SECTS_OFFSET: usize = 0x1f1;
+const BOOT_FLAG_OFFSET: usize = 0x1fe;
+const HEADER_OFFSET: usize = 0x202;
+const VERSION_OFFSET: usize = 0x206;
+const PAYLOAD_OFFSET_OFFSET: usize = 0x248;
+const PAYLOAD_LENGTH_OFFSET: usize = 0x24c;
+const SETUP_HEADER_END: usize = 0x250;
+
+const BOOT_FLAG: u16 = 0xaa55;
+const HEADER_MAGIC: &[u8] = b"HdrS";
+// payload_offset and payload_length were introduced with version 2.08
+const MIN_PAYLOAD_VERSION: u16 = 0x0208;
+const SECTOR_SIZE: usize = 512;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const GZIP_METHOD_DEFLATE: u8 = 8;
+const GZIP_HEADER_SIZE: usize = 10;
+const GZIP_TRAILER_SIZE: usize = 8;
+const GZIP_FHCRC: u8 = 1 << 1;
+const GZIP_FEXTRA: u8 = 1 << 2;
+const GZIP_FNAME: u8 = 1 << 3;
+const GZIP_FCOMMENT: u8 = 1 << 4;
+
+// Upper bound on the size of the decompressed kernel, protecting against
+// bogus size fields.
+const MAX_KERNEL_SIZE: usize = 1 << 30;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Error reading the kernel image
+    ReadKernel(io::Error),
+    /// The payload location from the setup header is out of the image bounds
+    InvalidPayload,
+    /// Invalid gzip header
+    InvalidGzipHeader,
+    /// Error inflating the payload
+    Decompress,
+    /// The decompressed size doesn't match the one recorded in the payload
+    SizeMismatch,
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+// Return the range of the payload within the image if the image is a
+// bzImage recent enough to describe it.
+fn payload_range(image: &[u8]) -> Result<Option<(usize, usize)>> {
+    if image.len() < SETUP_HEADER_END
+        || u16_at(image, BOOT_FLAG_OFFSET) != BOOT_FLAG
+        || &image[HEADER_OFFSET..HEADER_OFFSET + 4] != HEADER_MAGIC
+        || u16_at(image, VERSION_OFFSET) < MIN_PAYLOAD_VERSION
+    {
+        return Ok(None);
+    }
+
+    // A value of 0 means 4 setup sectors, as per the boot protocol
+    let setup_sects = match image[SETUP_SECTS_OFFSET] {
+        0 => 4,
+        s => s as usize,
+    };
+    // The payload offset is relative to the protected mode kernel, which
+    // follows the boot sector and the setup sectors.
+    let start = (setup_sects + 1) * SECTOR_SIZE + u32_at(image, PAYLOAD_OFFSET_OFFSET) as usize;
+    let end = start
+        .checked_add(u32_at(image, PAYLOAD_LENGTH_OFFSET) as usize)
+        .ok_or(Error::InvalidPayload)?;
+    if end > image.len() {
+        return Err(Error::InvalidPayload);
+    }
+
+    Ok(Some((start, end)))
+}
+
+// Skip a null terminated field of the gzip header
+fn skip_string(data: &[u8], offset: usize) -> Result<usize> {
+    let len = data
+        .get(offset..)
+        .and_then(|s| s.iter().position(|&b| b == 0))
+        .ok_or(Error::InvalidGzipHeader)?;
+    Ok(offset + len + 1)
+}
+
+// Return the raw deflate stream out of a gzip member, as described by
+// RFC 1952, along with the decompressed size stored in its trailer.
+fn gzip_deflate_stream(data: &[u8]) -> Result<(&[u8], usize)> {
+    if data.len() < GZIP_HEADER_SIZE + GZIP_TRAILER_SIZE || data[2] != GZIP_METHOD_DEFLATE {
+        return Err(Error::InvalidGzipHeader);
+    }
+
+    let flags = data[3];
+    let mut offset = GZIP_HEADER_SIZE;
+    if flags & GZIP_FEXTRA != 0 {
+        let xlen = data
+            .get(offset..offset + 2)
+            .ok_or(Error::InvalidGzipHeader)?;
+        offset += 2 + u16_at(xlen, 0) as usize;
+    }
+    if flags & GZIP_FNAME != 0 {
+        offset = skip_string(data, offset)?;
+    }
+    if flags & GZIP_FCOMMENT != 0 {
+        offset = skip_string(data, offset)?;
+    }
+    if flags & GZIP_FHCRC != 0 {
+        offset += 2;
+    }
+
+    let trailer = data.len() - GZIP_TRAILER_SIZE;
+    if offset > trailer {
+        return Err(Error::InvalidGzipHeader);
+    }
+    // The last 4 bytes hold the size of the decompressed data modulo 2^32
+    let size = u32_at(data, data.len() - 4) as usize;
+
+    Ok((&data[offset..trailer], size))
+}
+
+/// Decompress the kernel embedded in a bzImage.
+///
+/// Returns the uncompressed kernel (an ELF binary) if the image is a bzImage
+/// carrying a gzip compressed payload, or `None` otherwise, in which case the
+/// image must be booted through the guest-side decompressor.
+pub fn decompress<F: Read + Seek>(kernel: &mut F) -> Result<Option<Vec<u8>>> {
+    let mut image = Vec::new();
+    kernel.rewind().map_err(Error::ReadKernel)?;
+    kernel.read_to_end(&mut image).map_err(Error::ReadKernel)?;
+    kernel.rewind().map_err(Error::ReadKernel)?;
+
+    let (start, end) = match payload_range(&image)? {
+        Some(range) => range,
+        None => return Ok(None),
+    };
+    let payload = &image[start..end];
+    if !payload.starts_with(GZIP_MAGIC) {
+        return Ok(None);
+    }
+
+    let (stream, size) = gzip_deflate_stream(payload)?;
+    if size > MAX_KERNEL_SIZE {
+        return Err(Error::InvalidGzipHeader);
+    }
+    let vmlinux = miniz_oxide::inflate::decompress_to_vec_with_limit(stream, size)
+        .map_err(|_| Error::Decompress)?;
+    if vmlinux.len() != size {
+        return Err(Error::SizeMismatch);
+    }
+
+    Ok(Some(vmlinux))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn gzip(data: &[u8], flags: u8, extra: &[u8]) -> Vec<u8> {
+        let mut gz = vec![0x1f, 0x8b, GZIP_METHOD_DEFLATE, flags, 0, 0, 0, 0, 0, 3];
+        gz.extend_from_slice(extra);
+        gz.extend_from_slice(&miniz_oxide::deflate::compress_to_vec(data, 6));
+        // The CRC32 isn't checked
+        gz.extend_from_slice(&0u32.to_le_bytes());
+        gz.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        gz
+    }
+
+    fn bzimage(setup_sects: u8, version: u16, payload: &[u8]) -> Vec<u8> {
+        let sects = if setup_sects == 0 { 4 } else { setup_sects };
+        let setup_size = (sects as usize + 1) * SECTOR_SIZE;
+        let mut image = vec![0u8; setup_size + 0x100];
+        image[SETUP_SECTS_OFFSET] = setup_sects;
+        image[BOOT_FLAG_OFFSET..BOOT_FLAG_OFFSET + 2].copy_from_slice(&BOOT_FLAG.to_le_bytes());
+        image[HEADER_OFFSET..HEADER_OFFSET + 4].copy_from_slice(HEADER_MAGIC);
+        image[VERSION_OFFSET..VERSION_OFFSET + 2].copy_from_slice(&version.to_le_bytes());
+        image[PAYLOAD_OFFSET_OFFSET..PAYLOAD_OFFSET_OFFSET + 4]
+            .copy_from_slice(&0x100u32.to_le_bytes());
+        image[PAYLOAD_LENGTH_OFFSET..PAYLOAD_LENGTH_OFFSET + 4]
+            .copy_from_slice(&(payload.len() as u32).to_le_bytes());
+        image.extend_from_slice(payload);
+        image
+    }
+
+    #[test]
+    fn test_decompress() {
+        let vmlinux: Vec<u8> = b"\x7fELF".iter().cycle().take(4096).copied().collect();
+
+        let mut image = Cursor::new(bzimage(0, 0x20f, &gzip(&vmlinux, 0, &[])));
+        assert_eq!(decompress(&mut image).unwrap(), Some(vmlinux.clone()));
+        assert_eq!(image.position(), 0);
+
+        // Optional gzip header fields
+        let extra = [2, 0, 0xaa, 0xbb, b'v', b'm', 0, b'c', 0, 0x12, 0x34];
+        let payload = gzip(
+            &vmlinux,
+            GZIP_FEXTRA | GZIP_FNAME | GZIP_FCOMMENT | GZIP_FHCRC,
+            &extra,
+        );
+        let mut image = Cursor::new(bzimage(27, 0x20f, &payload));
+        assert_eq!(decompress(&mut image).unwrap(), Some(vmlinux.clone()));
+
+        // Boot protocol too old to describe the payload
+        let mut image = Cursor::new(bzimage(0, 0x207, &gzip(&vmlinux, 0, &[])));
+        assert_eq!(decompress(&mut image).unwrap(), None);
+
+        // Payload compressed with an unsupported format
+        let mut image = Cursor::new(bzimage(0, 0x20f, &[0x28, 0xb5, 0x2f, 0xfd, 0, 0]));
+        assert_eq!(decompress(&mut image).unwrap(), None);
+
+        // Not a bzImage
+        let mut image = Cursor::new(vmlinux.clone());
+        assert_eq!(decompress(&mut image).unwrap(), None);
+
+        // Truncated payload
+        let mut data = bzimage(0, 0x20f, &gzip(&vmlinux, 0, &[]));
+        data.truncate(data.len() - 1);
+        assert!(matches!(
+            decompress(&mut Cursor::new(data)),
+            Err(Error::InvalidPayload)
+        ));
+
+        // Wrong decompressed size
+        let mut payload = gzip(&vmlinux, 0, &[]);
+        let len = payload.len();
+        payload[len - 4..].copy_from_slice(&8u32.to_le_bytes());
+        let mut image = Cursor::new(bzimage(0, 0x20f, &payload));
+        assert!(decompress(&mut image).is_err());
+    }
+}
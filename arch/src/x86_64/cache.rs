@@ -0,0 +1,249 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use super::VENDOR_AMD_EBX;
+use hypervisor::arch::x86::{CpuIdEntry, CPUID_FLAG_VALID_INDEX};
+
+// Intel deterministic cache parameters leaf
+const INTEL_CACHE_LEAF: u32 = 0x4;
+// AMD L2/L3 cache and TLB identifiers leaf
+const AMD_L2_L3_CACHE_LEAF: u32 = 0x8000_0006;
+// AMD cache topology leaf
+const AMD_CACHE_LEAF: u32 = 0x8000_001d;
+
+const CACHE_LINE_SIZE: u32 = 64;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CacheType {
+    Data = 1,
+    Instruction = 2,
+    Unified = 3,
+}
+
+// Which vCPUs a cache is shared between
+#[derive(Clone, Copy)]
+enum CacheSharing {
+    Core,
+    Die,
+}
+
+struct Cache {
+    level: u32,
+    cache_type: CacheType,
+    size: u32,
+    ways: u32,
+    sharing: CacheSharing,
+}
+
+impl Cache {
+    fn sets(&self) -> u32 {
+        self.size / (self.ways * CACHE_LINE_SIZE)
+    }
+
+    // Register EBX layout shared by leaves 0x4 and 0x8000_001d
+    fn ebx(&self) -> u32 {
+        (self.ways - 1) << 22 | (CACHE_LINE_SIZE - 1)
+    }
+
+    // Associativity encoding used by leaf 0x8000_0006
+    fn legacy_associativity(&self) -> u32 {
+        match self.ways {
+            1 => 0x1,
+            2 => 0x2,
+            4 => 0x4,
+            8 => 0x6,
+            16 => 0x8,
+            32 => 0xa,
+            _ => 0xf,
+        }
+    }
+}
+
+/// The cache hierarchy exposed to the guest, independent from the host.
+const CACHES: &[Cache] = &[
+    Cache {
+        level: 1,
+        cache_type: CacheType::Data,
+        size: 32 << 10,
+        ways: 8,
+        sharing: CacheSharing::Core,
+    },
+    Cache {
+        level: 1,
+        cache_type: CacheType::Instruction,
+        size: 32 << 10,
+        ways: 8,
+        sharing: CacheSharing::Core,
+    },
+    Cache {
+        level: 2,
+        cache_type: CacheType::Unified,
+        size: 1 << 20,
+        ways: 16,
+        sharing: CacheSharing::Core,
+    },
+    Cache {
+        level: 3,
+        cache_type: CacheType::Unified,
+        size: 32 << 20,
+        ways: 16,
+        sharing: CacheSharing::Die,
+    },
+];
+
+fn width(count: u8) -> u32 {
+    8 - (count - 1).leading_zeros()
+}
+
+/// Replace the cache description leaves with a deterministic hierarchy
+/// matching the guest topology, rather than the host one. Each core owns
+/// its L1 and L2 caches, while the L3 cache is shared by the whole die.
+pub(super) fn update_cpuid_cache(
+    cpuid: &mut Vec<CpuIdEntry>,
+    threads_per_core: u8,
+    cores_per_die: u8,
+    dies_per_package: u8,
+) {
+    let thread_width = width(threads_per_core);
+    let core_width = width(cores_per_die) + thread_width;
+    let die_width = width(dies_per_package) + core_width;
+
+    let is_amd = cpuid
+        .iter()
+        .any(|e| e.function == 0 && e.ebx == VENDOR_AMD_EBX);
+    let function = if is_amd {
+        AMD_CACHE_LEAF
+    } else {
+        INTEL_CACHE_LEAF
+    };
+
+    // Number of APIC IDs reserved for the vCPUs sharing a cache
+    let sharing = |cache: &Cache| -> u32 {
+        match cache.sharing {
+            CacheSharing::Core => 1 << thread_width,
+            CacheSharing::Die => 1 << core_width,
+        }
+    };
+
+    cpuid.retain(|c| c.function != function);
+    for (index, cache) in CACHES.iter().enumerate() {
+        let mut eax = cache.cache_type as u32
+            | cache.level << 5
+            | 1 << 8 // Self initializing
+            | (sharing(cache) - 1) << 14;
+        if !is_amd {
+            // Number of APIC IDs reserved for the cores of the package
+            let cores = std::cmp::min(1 << (die_width - thread_width), 64);
+            eax |= (cores - 1) << 26;
+        }
+        cpuid.push(CpuIdEntry {
+            function,
+            index: index as u32,
+            flags: CPUID_FLAG_VALID_INDEX,
+            eax,
+            ebx: cache.ebx(),
+            ecx: cache.sets() - 1,
+            edx: 0,
+        });
+    }
+    // Terminate the list with a null cache type
+    cpuid.push(CpuIdEntry {
+        function,
+        index: CACHES.len() as u32,
+        flags: CPUID_FLAG_VALID_INDEX,
+        ..Default::default()
+    });
+
+    // Legacy L2 and L3 descriptors. The L3 descriptor is reserved on Intel.
+    let l2 = CACHES.iter().find(|c| c.level == 2).unwrap();
+    let l3 = CACHES.iter().find(|c| c.level == 3).unwrap();
+    let ecx = (l2.size >> 10) << 16 | l2.legacy_associativity() << 12 | 1 << 8 | CACHE_LINE_SIZE;
+    let edx = if is_amd {
+        (l3.size >> 19) << 18 | l3.legacy_associativity() << 12 | 1 << 8 | CACHE_LINE_SIZE
+    } else {
+        0
+    };
+    if let Some(entry) = cpuid
+        .iter_mut()
+        .find(|e| e.function == AMD_L2_L3_CACHE_LEAF)
+    {
+        entry.ecx = ecx;
+        entry.edx = edx;
+    } else {
+        cpuid.push(CpuIdEntry {
+            function: AMD_L2_L3_CACHE_LEAF,
+            ecx,
+            edx,
+            ..Default::default()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vendor_leaf(ebx: u32) -> CpuIdEntry {
+        CpuIdEntry {
+            function: 0,
+            eax: 0x1f,
+            ebx,
+            ..Default::default()
+        }
+    }
+
+    fn leaf(cpuid: &[CpuIdEntry], function: u32, index: u32) -> CpuIdEntry {
+        *cpuid
+            .iter()
+            .find(|e| e.function == function && e.index == index)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_update_cpuid_cache_intel() {
+        let mut cpuid = vec![
+            vendor_leaf(0x756e_6547),
+            CpuIdEntry {
+                function: INTEL_CACHE_LEAF,
+                index: 0,
+                eax: 0xdead_beef,
+                ..Default::default()
+            },
+        ];
+        update_cpuid_cache(&mut cpuid, 2, 4, 1);
+
+        // L1 data cache, shared by the 2 threads of a core, 4 cores
+        let l1d = leaf(&cpuid, INTEL_CACHE_LEAF, 0);
+        assert_eq!(l1d.eax, 1 | 1 << 5 | 1 << 8 | 1 << 14 | 3 << 26);
+        assert_eq!(l1d.ebx, 7 << 22 | 63);
+        assert_eq!(l1d.ecx, 63);
+
+        // L3 cache shared by the 8 threads of the die
+        let l3 = leaf(&cpuid, INTEL_CACHE_LEAF, 3);
+        assert_eq!(l3.eax, 3 | 3 << 5 | 1 << 8 | 7 << 14 | 3 << 26);
+        assert_eq!(l3.ecx, 32767);
+
+        assert_eq!(leaf(&cpuid, INTEL_CACHE_LEAF, 4).eax, 0);
+        assert!(cpuid.iter().all(|e| e.function != AMD_CACHE_LEAF));
+
+        let legacy = leaf(&cpuid, AMD_L2_L3_CACHE_LEAF, 0);
+        assert_eq!(legacy.ecx, 1024 << 16 | 0x8 << 12 | 1 << 8 | 64);
+        assert_eq!(legacy.edx, 0);
+    }
+
+    #[test]
+    fn test_update_cpuid_cache_amd() {
+        let mut cpuid = vec![vendor_leaf(VENDOR_AMD_EBX)];
+        update_cpuid_cache(&mut cpuid, 1, 6, 2);
+
+        let l2 = leaf(&cpuid, AMD_CACHE_LEAF, 2);
+        assert_eq!(l2.eax, 3 | 2 << 5 | 1 << 8);
+        let l3 = leaf(&cpuid, AMD_CACHE_LEAF, 3);
+        assert_eq!(l3.eax, 3 | 3 << 5 | 1 << 8 | 7 << 14);
+        assert!(cpuid.iter().all(|e| e.function != INTEL_CACHE_LEAF));
+
+        let legacy = leaf(&cpuid, AMD_L2_L3_CACHE_LEAF, 0);
+        assert_eq!(legacy.edx, 64 << 18 | 0x8 << 12 | 1 << 8 | 64);
+    }
+}
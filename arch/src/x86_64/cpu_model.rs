@@ -0,0 +1,733 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{CpuidPatch, CpuidReg, Error};
+use hypervisor::arch::x86::CpuIdEntry;
+use std::arch::x86_64;
+
+/// A CPUID feature bit, named after its Linux `/proc/cpuinfo` flag.
+pub struct CpuidFeature {
+    pub name: &'static str,
+    pub function: u32,
+    pub index: u32,
+    pub reg: CpuidReg,
+    pub bit: u8,
+}
+
+const fn feature(
+    name: &'static str,
+    function: u32,
+    index: u32,
+    reg: CpuidReg,
+    bit: u8,
+) -> CpuidFeature {
+    CpuidFeature {
+        name,
+        function,
+        index,
+        reg,
+        bit,
+    }
+}
+
+/// Feature bits which can be named in a CPU model or individually enabled
+/// and disabled. Any bit from the registers listed here which is not part of
+/// the selected CPU model is hidden from the guest.
+pub const CPUID_FEATURES: &[CpuidFeature] = &[
+    // Leaf 0x1 EDX
+    feature("fpu", 0x1, 0, CpuidReg::EDX, 0),
+    feature("vme", 0x1, 0, CpuidReg::EDX, 1),
+    feature("de", 0x1, 0, CpuidReg::EDX, 2),
+    feature("pse", 0x1, 0, CpuidReg::EDX, 3),
+    feature("tsc", 0x1, 0, CpuidReg::EDX, 4),
+    feature("msr", 0x1, 0, CpuidReg::EDX, 5),
+    feature("pae", 0x1, 0, CpuidReg::EDX, 6),
+    feature("mce", 0x1, 0, CpuidReg::EDX, 7),
+    feature("cx8", 0x1, 0, CpuidReg::EDX, 8),
+    feature("apic", 0x1, 0, CpuidReg::EDX, 9),
+    feature("sep", 0x1, 0, CpuidReg::EDX, 11),
+    feature("mtrr", 0x1, 0, CpuidReg::EDX, 12),
+    feature("pge", 0x1, 0, CpuidReg::EDX, 13),
+    feature("mca", 0x1, 0, CpuidReg::EDX, 14),
+    feature("cmov", 0x1, 0, CpuidReg::EDX, 15),
+    feature("pat", 0x1, 0, CpuidReg::EDX, 16),
+    feature("pse36", 0x1, 0, CpuidReg::EDX, 17),
+    feature("clflush", 0x1, 0, CpuidReg::EDX, 19),
+    feature("mmx", 0x1, 0, CpuidReg::EDX, 23),
+    feature("fxsr", 0x1, 0, CpuidReg::EDX, 24),
+    feature("sse", 0x1, 0, CpuidReg::EDX, 25),
+    feature("sse2", 0x1, 0, CpuidReg::EDX, 26),
+    feature("ss", 0x1, 0, CpuidReg::EDX, 27),
+    feature("ht", 0x1, 0, CpuidReg::EDX, 28),
+    // Leaf 0x1 ECX
+    feature("pni", 0x1, 0, CpuidReg::ECX, 0),
+    feature("pclmulqdq", 0x1, 0, CpuidReg::ECX, 1),
+    feature("monitor", 0x1, 0, CpuidReg::ECX, 3),
+    feature("vmx", 0x1, 0, CpuidReg::ECX, 5),
+    feature("ssse3", 0x1, 0, CpuidReg::ECX, 9),
+    feature("fma", 0x1, 0, CpuidReg::ECX, 12),
+    feature("cx16", 0x1, 0, CpuidReg::ECX, 13),
+    feature("pdcm", 0x1, 0, CpuidReg::ECX, 15),
+    feature("pcid", 0x1, 0, CpuidReg::ECX, 17),
+    feature("sse4_1", 0x1, 0, CpuidReg::ECX, 19),
+    feature("sse4_2", 0x1, 0, CpuidReg::ECX, 20),
+    feature("x2apic", 0x1, 0, CpuidReg::ECX, 21),
+    feature("movbe", 0x1, 0, CpuidReg::ECX, 22),
+    feature("popcnt", 0x1, 0, CpuidReg::ECX, 23),
+    feature("tsc_deadline_timer", 0x1, 0, CpuidReg::ECX, 24),
+    feature("aes", 0x1, 0, CpuidReg::ECX, 25),
+    feature("xsave", 0x1, 0, CpuidReg::ECX, 26),
+    feature("osxsave", 0x1, 0, CpuidReg::ECX, 27),
+    feature("avx", 0x1, 0, CpuidReg::ECX, 28),
+    feature("f16c", 0x1, 0, CpuidReg::ECX, 29),
+    feature("rdrand", 0x1, 0, CpuidReg::ECX, 30),
+    feature("hypervisor", 0x1, 0, CpuidReg::ECX, 31),
+    // Leaf 0x7 subleaf 0 EBX
+    feature("fsgsbase", 0x7, 0, CpuidReg::EBX, 0),
+    feature("tsc_adjust", 0x7, 0, CpuidReg::EBX, 1),
+    feature("sgx", 0x7, 0, CpuidReg::EBX, 2),
+    feature("bmi1", 0x7, 0, CpuidReg::EBX, 3),
+    feature("hle", 0x7, 0, CpuidReg::EBX, 4),
+    feature("avx2", 0x7, 0, CpuidReg::EBX, 5),
+    feature("smep", 0x7, 0, CpuidReg::EBX, 7),
+    feature("bmi2", 0x7, 0, CpuidReg::EBX, 8),
+    feature("erms", 0x7, 0, CpuidReg::EBX, 9),
+    feature("invpcid", 0x7, 0, CpuidReg::EBX, 10),
+    feature("rtm", 0x7, 0, CpuidReg::EBX, 11),
+    feature("avx512f", 0x7, 0, CpuidReg::EBX, 16),
+    feature("avx512dq", 0x7, 0, CpuidReg::EBX, 17),
+    feature("rdseed", 0x7, 0, CpuidReg::EBX, 18),
+    feature("adx", 0x7, 0, CpuidReg::EBX, 19),
+    feature("smap", 0x7, 0, CpuidReg::EBX, 20),
+    feature("avx512ifma", 0x7, 0, CpuidReg::EBX, 21),
+    feature("clflushopt", 0x7, 0, CpuidReg::EBX, 23),
+    feature("clwb", 0x7, 0, CpuidReg::EBX, 24),
+    feature("avx512cd", 0x7, 0, CpuidReg::EBX, 28),
+    feature("sha_ni", 0x7, 0, CpuidReg::EBX, 29),
+    feature("avx512bw", 0x7, 0, CpuidReg::EBX, 30),
+    feature("avx512vl", 0x7, 0, CpuidReg::EBX, 31),
+    // Leaf 0x7 subleaf 0 ECX
+    feature("avx512vbmi", 0x7, 0, CpuidReg::ECX, 1),
+    feature("umip", 0x7, 0, CpuidReg::ECX, 2),
+    feature("pku", 0x7, 0, CpuidReg::ECX, 3),
+    feature("ospke", 0x7, 0, CpuidReg::ECX, 4),
+    feature("waitpkg", 0x7, 0, CpuidReg::ECX, 5),
+    feature("avx512_vbmi2", 0x7, 0, CpuidReg::ECX, 6),
+    feature("gfni", 0x7, 0, CpuidReg::ECX, 8),
+    feature("vaes", 0x7, 0, CpuidReg::ECX, 9),
+    feature("vpclmulqdq", 0x7, 0, CpuidReg::ECX, 10),
+    feature("avx512_vnni", 0x7, 0, CpuidReg::ECX, 11),
+    feature("avx512_bitalg", 0x7, 0, CpuidReg::ECX, 12),
+    feature("avx512_vpopcntdq", 0x7, 0, CpuidReg::ECX, 14),
+    feature("la57", 0x7, 0, CpuidReg::ECX, 16),
+    feature("rdpid", 0x7, 0, CpuidReg::ECX, 22),
+    feature("cldemote", 0x7, 0, CpuidReg::ECX, 25),
+    feature("movdiri", 0x7, 0, CpuidReg::ECX, 27),
+    feature("movdir64b", 0x7, 0, CpuidReg::ECX, 28),
+    feature("sgx_lc", 0x7, 0, CpuidReg::ECX, 30),
+    // Leaf 0x7 subleaf 0 EDX
+    feature("fsrm", 0x7, 0, CpuidReg::EDX, 4),
+    feature("md_clear", 0x7, 0, CpuidReg::EDX, 10),
+    feature("serialize", 0x7, 0, CpuidReg::EDX, 14),
+    feature("tsxldtrk", 0x7, 0, CpuidReg::EDX, 16),
+    feature("amx_bf16", 0x7, 0, CpuidReg::EDX, 22),
+    feature("avx512_fp16", 0x7, 0, CpuidReg::EDX, 23),
+    feature("amx_tile", 0x7, 0, CpuidReg::EDX, 24),
+    feature("amx_int8", 0x7, 0, CpuidReg::EDX, 25),
+    feature("spec_ctrl", 0x7, 0, CpuidReg::EDX, 26),
+    feature("intel_stibp", 0x7, 0, CpuidReg::EDX, 27),
+    feature("arch_capabilities", 0x7, 0, CpuidReg::EDX, 29),
+    feature("ssbd", 0x7, 0, CpuidReg::EDX, 31),
+    // Leaf 0xd subleaf 1 EAX
+    feature("xsaveopt", 0xd, 1, CpuidReg::EAX, 0),
+    feature("xsavec", 0xd, 1, CpuidReg::EAX, 1),
+    feature("xgetbv1", 0xd, 1, CpuidReg::EAX, 2),
+    feature("xsaves", 0xd, 1, CpuidReg::EAX, 3),
+    // Leaf 0x8000_0001 ECX
+    feature("lahf_lm", 0x8000_0001, 0, CpuidReg::ECX, 0),
+    feature("svm", 0x8000_0001, 0, CpuidReg::ECX, 2),
+    feature("abm", 0x8000_0001, 0, CpuidReg::ECX, 5),
+    feature("sse4a", 0x8000_0001, 0, CpuidReg::ECX, 6),
+    feature("misalignsse", 0x8000_0001, 0, CpuidReg::ECX, 7),
+    feature("3dnowprefetch", 0x8000_0001, 0, CpuidReg::ECX, 8),
+    feature("xop", 0x8000_0001, 0, CpuidReg::ECX, 11),
+    feature("fma4", 0x8000_0001, 0, CpuidReg::ECX, 16),
+    feature("tbm", 0x8000_0001, 0, CpuidReg::ECX, 21),
+    feature("topoext", 0x8000_0001, 0, CpuidReg::ECX, 22),
+    // Leaf 0x8000_0001 EDX
+    feature("syscall", 0x8000_0001, 0, CpuidReg::EDX, 11),
+    feature("nx", 0x8000_0001, 0, CpuidReg::EDX, 20),
+    feature("mmxext", 0x8000_0001, 0, CpuidReg::EDX, 22),
+    feature("fxsr_opt", 0x8000_0001, 0, CpuidReg::EDX, 25),
+    feature("pdpe1gb", 0x8000_0001, 0, CpuidReg::EDX, 26),
+    feature("rdtscp", 0x8000_0001, 0, CpuidReg::EDX, 27),
+    feature("lm", 0x8000_0001, 0, CpuidReg::EDX, 29),
+    // Leaf 0x8000_0007 EDX
+    feature("invtsc", 0x8000_0007, 0, CpuidReg::EDX, 8),
+    // Leaf 0x8000_0008 EBX
+    feature("clzero", 0x8000_0008, 0, CpuidReg::EBX, 0),
+    feature("xsaveerptr", 0x8000_0008, 0, CpuidReg::EBX, 2),
+    feature("wbnoinvd", 0x8000_0008, 0, CpuidReg::EBX, 9),
+    feature("ibpb", 0x8000_0008, 0, CpuidReg::EBX, 12),
+    feature("amd_stibp", 0x8000_0008, 0, CpuidReg::EBX, 15),
+    feature("amd_ssbd", 0x8000_0008, 0, CpuidReg::EBX, 24),
+];
+
+/// Feature bits which are always left untouched by a CPU model, either
+/// because they are managed by the VMM itself or because they reflect the
+/// guest's own control register state.
+const PRESERVED_FEATURES: &[&str] = &[
+    "ht",
+    "x2apic",
+    "tsc_deadline_timer",
+    "osxsave",
+    "hypervisor",
+    "ospke",
+];
+
+impl CpuidFeature {
+    pub fn from_name(name: &str) -> Option<&'static CpuidFeature> {
+        CPUID_FEATURES.iter().find(|f| f.name == name)
+    }
+
+    pub fn patch(&self) -> CpuidPatch {
+        let bit = Some(self.bit);
+        CpuidPatch {
+            function: self.function,
+            index: self.index,
+            flags_bit: None,
+            eax_bit: if self.reg == CpuidReg::EAX { bit } else { None },
+            ebx_bit: if self.reg == CpuidReg::EBX { bit } else { None },
+            ecx_bit: if self.reg == CpuidReg::ECX { bit } else { None },
+            edx_bit: if self.reg == CpuidReg::EDX { bit } else { None },
+        }
+    }
+
+    pub fn is_set(&self, cpuid: &[CpuIdEntry]) -> bool {
+        cpuid
+            .iter()
+            .find(|entry| entry.function == self.function && entry.index == self.index)
+            .map(|entry| reg_value(entry, self.reg) & (1 << self.bit) != 0)
+            .unwrap_or(false)
+    }
+}
+
+fn reg_value(entry: &CpuIdEntry, reg: CpuidReg) -> u32 {
+    match reg {
+        CpuidReg::EAX => entry.eax,
+        CpuidReg::EBX => entry.ebx,
+        CpuidReg::ECX => entry.ecx,
+        CpuidReg::EDX => entry.edx,
+    }
+}
+
+fn reg_value_mut(entry: &mut CpuIdEntry, reg: CpuidReg) -> &mut u32 {
+    match reg {
+        CpuidReg::EAX => &mut entry.eax,
+        CpuidReg::EBX => &mut entry.ebx,
+        CpuidReg::ECX => &mut entry.ecx,
+        CpuidReg::EDX => &mut entry.edx,
+    }
+}
+
+const BASE_FEATURES: &[&str] = &[
+    "fpu", "vme", "de", "pse", "tsc", "msr", "pae", "mce", "cx8", "apic", "sep", "mtrr", "pge",
+    "mca", "cmov", "pat", "pse36", "clflush", "mmx", "fxsr", "sse", "sse2", "syscall", "nx", "lm",
+    "lahf_lm", "pni", "ssse3", "cx16", "sse4_1", "sse4_2", "popcnt",
+];
+
+const HASWELL_FEATURES: &[&str] = &[
+    "pclmulqdq",
+    "fma",
+    "pcid",
+    "movbe",
+    "aes",
+    "xsave",
+    "avx",
+    "f16c",
+    "rdrand",
+    "fsgsbase",
+    "bmi1",
+    "avx2",
+    "smep",
+    "bmi2",
+    "erms",
+    "invpcid",
+    "xsaveopt",
+    "abm",
+    "rdtscp",
+    "spec_ctrl",
+    "ssbd",
+];
+
+const BROADWELL_FEATURES: &[&str] = &["rdseed", "adx", "smap", "3dnowprefetch"];
+
+const SKYLAKE_CLIENT_FEATURES: &[&str] = &["clflushopt", "xsavec", "xgetbv1"];
+
+const SKYLAKE_SERVER_FEATURES: &[&str] = &[
+    "pku", "avx512f", "avx512dq", "avx512cd", "avx512bw", "avx512vl", "clwb", "pdpe1gb",
+];
+
+const CASCADELAKE_SERVER_FEATURES: &[&str] = &["avx512_vnni"];
+
+const ICELAKE_SERVER_FEATURES: &[&str] = &[
+    "avx512ifma",
+    "sha_ni",
+    "avx512vbmi",
+    "umip",
+    "avx512_vbmi2",
+    "gfni",
+    "vaes",
+    "vpclmulqdq",
+    "avx512_bitalg",
+    "avx512_vpopcntdq",
+    "rdpid",
+    "fsrm",
+    "wbnoinvd",
+];
+
+const EPYC_FEATURES: &[&str] = &[
+    "pclmulqdq",
+    "fma",
+    "movbe",
+    "aes",
+    "xsave",
+    "avx",
+    "f16c",
+    "rdrand",
+    "fsgsbase",
+    "bmi1",
+    "avx2",
+    "smep",
+    "bmi2",
+    "rdseed",
+    "adx",
+    "smap",
+    "clflushopt",
+    "sha_ni",
+    "xsaveopt",
+    "xsavec",
+    "xgetbv1",
+    "abm",
+    "sse4a",
+    "misalignsse",
+    "3dnowprefetch",
+    "topoext",
+    "mmxext",
+    "fxsr_opt",
+    "pdpe1gb",
+    "rdtscp",
+    "clzero",
+    "xsaveerptr",
+];
+
+const EPYC_ROME_FEATURES: &[&str] = &["clwb", "umip", "rdpid", "wbnoinvd", "ibpb", "amd_stibp"];
+
+const EPYC_MILAN_FEATURES: &[&str] = &[
+    "pcid",
+    "erms",
+    "invpcid",
+    "pku",
+    "fsrm",
+    "vaes",
+    "vpclmulqdq",
+    "amd_ssbd",
+];
+
+const VENDOR_INTEL: &[u8; 12] = b"GenuineIntel";
+const VENDOR_AMD: &[u8; 12] = b"AuthenticAMD";
+
+/// A named guest CPU model, describing the exact set of features exposed to
+/// the guest along with the CPU identification. Models are meant to provide
+/// a stable baseline across hosts, which is why none of them include
+/// features which can't be migrated (such as `invtsc`) or which have been
+/// disabled by microcode updates (such as TSX).
+pub struct CpuModel {
+    pub name: &'static str,
+    vendor: &'static [u8; 12],
+    // Processor signature as reported through leaf 0x1 EAX
+    signature: u32,
+    model_id: &'static str,
+    features: &'static [&'static [&'static str]],
+}
+
+pub const CPU_MODELS: &[CpuModel] = &[
+    CpuModel {
+        name: "Haswell",
+        vendor: VENDOR_INTEL,
+        signature: 0x306c4,
+        model_id: "Intel Core Processor (Haswell)",
+        features: &[BASE_FEATURES, HASWELL_FEATURES],
+    },
+    CpuModel {
+        name: "Broadwell",
+        vendor: VENDOR_INTEL,
+        signature: 0x306d2,
+        model_id: "Intel Core Processor (Broadwell)",
+        features: &[BASE_FEATURES, HASWELL_FEATURES, BROADWELL_FEATURES],
+    },
+    CpuModel {
+        name: "Skylake-Client",
+        vendor: VENDOR_INTEL,
+        signature: 0x506e3,
+        model_id: "Intel Core Processor (Skylake)",
+        features: &[
+            BASE_FEATURES,
+            HASWELL_FEATURES,
+            BROADWELL_FEATURES,
+            SKYLAKE_CLIENT_FEATURES,
+        ],
+    },
+    CpuModel {
+        name: "Skylake-Server",
+        vendor: VENDOR_INTEL,
+        signature: 0x50654,
+        model_id: "Intel Xeon Processor (Skylake)",
+        features: &[
+            BASE_FEATURES,
+            HASWELL_FEATURES,
+            BROADWELL_FEATURES,
+            SKYLAKE_CLIENT_FEATURES,
+            SKYLAKE_SERVER_FEATURES,
+        ],
+    },
+    CpuModel {
+        name: "Cascadelake-Server",
+        vendor: VENDOR_INTEL,
+        signature: 0x50656,
+        model_id: "Intel Xeon Processor (Cascadelake)",
+        features: &[
+            BASE_FEATURES,
+            HASWELL_FEATURES,
+            BROADWELL_FEATURES,
+            SKYLAKE_CLIENT_FEATURES,
+            SKYLAKE_SERVER_FEATURES,
+            CASCADELAKE_SERVER_FEATURES,
+        ],
+    },
+    CpuModel {
+        name: "Icelake-Server",
+        vendor: VENDOR_INTEL,
+        signature: 0x606a6,
+        model_id: "Intel Xeon Processor (Icelake)",
+        features: &[
+            BASE_FEATURES,
+            HASWELL_FEATURES,
+            BROADWELL_FEATURES,
+            SKYLAKE_CLIENT_FEATURES,
+            SKYLAKE_SERVER_FEATURES,
+            CASCADELAKE_SERVER_FEATURES,
+            ICELAKE_SERVER_FEATURES,
+        ],
+    },
+    CpuModel {
+        name: "EPYC",
+        vendor: VENDOR_AMD,
+        signature: 0x800f12,
+        model_id: "AMD EPYC Processor",
+        features: &[BASE_FEATURES, EPYC_FEATURES],
+    },
+    CpuModel {
+        name: "EPYC-Rome",
+        vendor: VENDOR_AMD,
+        signature: 0x830f10,
+        model_id: "AMD EPYC-Rome Processor",
+        features: &[BASE_FEATURES, EPYC_FEATURES, EPYC_ROME_FEATURES],
+    },
+    CpuModel {
+        name: "EPYC-Milan",
+        vendor: VENDOR_AMD,
+        signature: 0xa00f11,
+        model_id: "AMD EPYC-Milan Processor",
+        features: &[
+            BASE_FEATURES,
+            EPYC_FEATURES,
+            EPYC_ROME_FEATURES,
+            EPYC_MILAN_FEATURES,
+        ],
+    },
+];
+
+// XSAVE state components (leaf 0xd subleaf 0 EAX) along with the feature
+// needed for the guest to make use of them. The other components, including
+// the MPX ones as MPX is deprecated and not part of any model, are hidden.
+const XSAVE_COMPONENTS: &[(u32, &str)] = &[
+    (1 << 2, "avx"),
+    (1 << 5 | 1 << 6 | 1 << 7, "avx512f"),
+    (1 << 9, "pku"),
+    (1 << 17 | 1 << 18, "amx_tile"),
+];
+
+// x87 and SSE state components, always part of the XSAVE area
+const XSAVE_LEGACY_COMPONENTS: u32 = 1 << 0 | 1 << 1;
+
+// MPX feature bit (leaf 0x7 subleaf 0 EBX)
+const MPX_EBX_BIT: u8 = 14;
+
+// Size of the legacy region and of the header of the XSAVE area
+const XSAVE_LEGACY_AND_HEADER_SIZE: u32 = 512 + 64;
+
+impl CpuModel {
+    pub fn from_name(name: &str) -> Option<&'static CpuModel> {
+        CPU_MODELS.iter().find(|m| m.name == name)
+    }
+
+    fn has_feature(&self, name: &str) -> bool {
+        self.features.iter().any(|f| f.contains(&name))
+    }
+
+    fn features(&self) -> impl Iterator<Item = &'static CpuidFeature> + '_ {
+        CPUID_FEATURES.iter().filter(|f| self.has_feature(f.name))
+    }
+
+    /// Restrict the CPUID to the features from the model, and replace the
+    /// CPU identification. Fails if the host vendor doesn't match the model
+    /// or if some of the features from the model aren't supported.
+    pub fn apply(&self, cpuid: &mut Vec<CpuIdEntry>) -> crate::Result<()> {
+        // SAFETY: cpuid called with valid leaves
+        let leaf = unsafe { x86_64::__cpuid(0) };
+        let mut vendor = [0u8; 12];
+        vendor[0..4].copy_from_slice(&leaf.ebx.to_le_bytes());
+        vendor[4..8].copy_from_slice(&leaf.edx.to_le_bytes());
+        vendor[8..12].copy_from_slice(&leaf.ecx.to_le_bytes());
+        if &vendor != self.vendor {
+            return Err(Error::CpuModelVendorMismatch(self.name.to_string()).into());
+        }
+
+        let missing: Vec<&str> = self
+            .features()
+            .filter(|f| !f.is_set(cpuid))
+            .map(|f| f.name)
+            .collect();
+        if !missing.is_empty() {
+            return Err(
+                Error::CpuModelMissingFeatures(self.name.to_string(), missing.join(",")).into(),
+            );
+        }
+
+        self.restrict(cpuid);
+
+        Ok(())
+    }
+
+    // Hide from the CPUID everything not described by the model.
+    fn restrict(&self, cpuid: &mut Vec<CpuIdEntry>) {
+        // Leaves and subleaves which aren't described by any feature are
+        // hidden altogether, as they would expose what the model is meant
+        // to hide: the leaf 0x7 subleaves beyond the first one, the SGX
+        // capabilities unless the model has SGX, and the processor trace
+        // capabilities.
+        cpuid.retain(|entry| match entry.function {
+            0x7 => entry.index == 0,
+            0x12 => self.has_feature("sgx"),
+            0x14 => false,
+            _ => true,
+        });
+
+        for entry in cpuid.iter_mut() {
+            // No leaf 0x7 subleaf beyond the first one.
+            if entry.function == 0x7 && entry.index == 0 {
+                entry.eax = 0;
+            }
+            // None of the supervisor state components (IA32_XSS) are
+            // described, their subleaves are hidden with the other
+            // components.
+            if entry.function == 0xd && entry.index == 1 {
+                entry.ecx = 0;
+                entry.edx = 0;
+            }
+
+            for reg in [CpuidReg::EAX, CpuidReg::EBX, CpuidReg::ECX, CpuidReg::EDX] {
+                let mut known = false;
+                let mut mask = 0;
+                for f in CPUID_FEATURES.iter().filter(|f| {
+                    f.function == entry.function && f.index == entry.index && f.reg == reg
+                }) {
+                    known = true;
+                    if self.has_feature(f.name) || PRESERVED_FEATURES.contains(&f.name) {
+                        mask |= 1 << f.bit;
+                    }
+                }
+                if known {
+                    *reg_value_mut(entry, reg) &= mask;
+                }
+            }
+
+            if entry.function == 0x1 && entry.index == 0 {
+                entry.eax = self.signature;
+            }
+        }
+    }
+
+    /// Brand string reported through leaves 0x8000_0002 to 0x8000_0004.
+    pub fn brand_string(&self) -> [u32; 12] {
+        let mut brand = [0u8; 48];
+        let len = self.model_id.len().min(brand.len() - 1);
+        brand[..len].copy_from_slice(&self.model_id.as_bytes()[..len]);
+
+        let mut regs = [0u32; 12];
+        for (i, reg) in regs.iter_mut().enumerate() {
+            *reg = u32::from_le_bytes(brand[i * 4..i * 4 + 4].try_into().unwrap());
+        }
+        regs
+    }
+}
+
+/// Hide the XSAVE state components (leaf 0xd subleaf 0) which belong to
+/// features not exposed to the guest, and size the XSAVE area accordingly.
+pub(super) fn update_xsave_components(cpuid: &mut Vec<CpuIdEntry>) {
+    let mut components = XSAVE_LEGACY_COMPONENTS;
+    for (feature_components, name) in XSAVE_COMPONENTS {
+        if CpuidFeature::from_name(name).unwrap().is_set(cpuid) {
+            components |= feature_components;
+        }
+    }
+
+    // Drop the size and offset of the hidden components, which would
+    // otherwise be accounted for in the XSAVE area size.
+    cpuid.retain(|e| {
+        e.function != 0xd || e.index < 2 || (e.index < 32 && components & 1 << e.index != 0)
+    });
+
+    // The maximum size of the XSAVE area must cover all the components which
+    // can be enabled, including the dynamic ones such as the AMX tile data.
+    let max_size = cpuid
+        .iter()
+        .filter(|e| e.function == 0xd && (2..32).contains(&e.index))
+        .map(|e| e.ebx + e.eax)
+        .fold(XSAVE_LEGACY_AND_HEADER_SIZE, std::cmp::max);
+    for entry in cpuid.iter_mut() {
+        if entry.function == 0xd && entry.index == 0 {
+            entry.eax &= components;
+            entry.ecx = max_size;
+            entry.edx = 0;
+        }
+        // MPX goes along with its hidden state components.
+        if entry.function == 0x7 && entry.index == 0 {
+            entry.ebx &= !(1 << MPX_EBX_BIT);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cpu_model_features() {
+        for model in CPU_MODELS {
+            for features in model.features {
+                for name in features.iter() {
+                    assert!(
+                        CpuidFeature::from_name(name).is_some(),
+                        "Unknown feature {name} in model {}",
+                        model.name
+                    );
+                }
+            }
+        }
+        for name in PRESERVED_FEATURES {
+            assert!(CpuidFeature::from_name(name).is_some());
+        }
+        for (_, name) in XSAVE_COMPONENTS {
+            assert!(CpuidFeature::from_name(name).is_some());
+        }
+
+        assert!(CpuModel::from_name("Skylake-Server").is_some());
+        assert!(CpuModel::from_name("skylake-server").is_none());
+        let model = CpuModel::from_name("EPYC-Milan").unwrap();
+        assert!(model.has_feature("amd_ssbd"));
+        assert!(model.has_feature("sse4a"));
+        assert!(!model.has_feature("invtsc"));
+    }
+
+    #[test]
+    fn test_cpu_model_brand_string() {
+        let model = CpuModel::from_name("EPYC").unwrap();
+        let regs = model.brand_string();
+        let bytes: Vec<u8> = regs.iter().flat_map(|r| r.to_le_bytes()).collect();
+        assert_eq!(&bytes[..18], b"AMD EPYC Processor");
+        assert!(bytes[18..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn test_update_xsave_components() {
+        let xsave_leaf = |index: u32, eax: u32, ebx: u32| CpuIdEntry {
+            function: 0xd,
+            index,
+            eax,
+            ebx,
+            ..Default::default()
+        };
+        let mut cpuid = vec![
+            xsave_leaf(0, 0x7 | 1 << 17 | 1 << 18, 0),
+            xsave_leaf(2, 256, 576),
+            xsave_leaf(17, 64, 2752),
+            xsave_leaf(18, 8192, 2816),
+            CpuIdEntry {
+                function: 0x7,
+                edx: 1 << 24,
+                ..Default::default()
+            },
+        ];
+
+        // AVX being hidden, the tile data sets the size of the XSAVE area
+        update_xsave_components(&mut cpuid);
+        assert_eq!(
+            (cpuid[0].eax, cpuid[0].ecx),
+            (0x3 | 1 << 17 | 1 << 18, 11008)
+        );
+        assert!(!cpuid.iter().any(|e| e.function == 0xd && e.index == 2));
+
+        // Without AMX, only the legacy area and header remain
+        cpuid.retain(|e| e.function != 0x7);
+        update_xsave_components(&mut cpuid);
+        assert_eq!((cpuid[0].eax, cpuid[0].ecx), (0x3, 576));
+        assert_eq!(cpuid.len(), 1);
+    }
+
+    #[test]
+    fn test_cpu_model_restrict() {
+        let leaf = |function: u32, index: u32| CpuIdEntry {
+            function,
+            index,
+            eax: 0xffff_ffff,
+            ebx: 0xffff_ffff,
+            ecx: 0xffff_ffff,
+            edx: 0xffff_ffff,
+            ..Default::default()
+        };
+        let mut cpuid = vec![
+            leaf(0x1, 0),
+            leaf(0x7, 0),
+            leaf(0x7, 1),
+            leaf(0xd, 0),
+            leaf(0xd, 1),
+            leaf(0xd, 11),
+            leaf(0x12, 0),
+            leaf(0x12, 1),
+            leaf(0x14, 0),
+            leaf(0x14, 1),
+        ];
+
+        let model = CpuModel::from_name("Skylake-Server").unwrap();
+        model.restrict(&mut cpuid);
+        update_xsave_components(&mut cpuid);
+        let leaves: Vec<(u32, u32)> = cpuid.iter().map(|e| (e.function, e.index)).collect();
+        assert_eq!(leaves, vec![(0x1, 0), (0x7, 0), (0xd, 0), (0xd, 1)]);
+
+        // No subleaf, TSX, SGX nor MPX
+        assert_eq!(cpuid[1].eax, 0);
+        assert_eq!(cpuid[1].ebx & (1 << 2 | 1 << 4 | 1 << 11 | 1 << 14), 0);
+        assert_ne!(cpuid[1].ebx & 1 << 16, 0);
+
+        // Neither MPX nor supervisor state components
+        assert_eq!(
+            cpuid[2].eax,
+            0x3 | 1 << 2 | 1 << 5 | 1 << 6 | 1 << 7 | 1 << 9
+        );
+        assert_eq!(cpuid[2].edx, 0);
+        assert_eq!((cpuid[3].ecx, cpuid[3].edx), (0, 0));
+    }
+}
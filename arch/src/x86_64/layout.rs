@@ -60,6 +60,10 @@ pub const CMDLINE_START: GuestAddress = GuestAddress(0x20000);
 /// Kernel command line start address maximum size.
 pub const CMDLINE_MAX_SIZE: usize = 0x10000;
 
+/// Address of the Multiboot2 boot information structure, which spans up to
+/// the EBDA.
+pub const MULTIBOOT2_INFO_START: GuestAddress = GuestAddress(0x30000);
+
 // MPTABLE, describing VCPUS.
 pub const MPTABLE_START: GuestAddress = GuestAddress(0x9fc00);
 
@@ -82,9 +86,12 @@ pub const HIGH_RAM_START: GuestAddress = GuestAddress(0x100000);
 
 // == No fixed addresses in the "High RAM" range ==
 
-// ** 32-bit reserved area (start: 3GiB, length: 896MiB) **
+// ** 32-bit reserved area (default start: 3GiB, default length: 896MiB) **
 pub const MEM_32BIT_RESERVED_START: GuestAddress = GuestAddress(0xc000_0000);
 pub const MEM_32BIT_RESERVED_SIZE: u64 = PCI_MMCONFIG_SIZE + MEM_32BIT_DEVICES_SIZE;
+// The area can be enlarged up to 3GiB by moving its start down, giving more
+// room to the 32-bit PCI devices. Its end is fixed.
+pub const MEM_32BIT_RESERVED_MAX_SIZE: u64 = 3 << 30;
 
 // == Fixed constants within the "32-bit reserved" range ==
 
@@ -103,6 +110,21 @@ pub const PCI_MMIO_CONFIG_SIZE_PER_SEGMENT: u64 = 4096 * 256;
 pub const KVM_TSS_START: GuestAddress = GuestAddress(PCI_MMCONFIG_START.0 + PCI_MMCONFIG_SIZE);
 pub const KVM_TSS_SIZE: u64 = (3 * 4) << 10;
 
+/// Start of the 32-bit reserved area of the given size.
+pub fn mem_32bit_reserved_start(size: u64) -> GuestAddress {
+    GuestAddress(KVM_TSS_START.0 - size)
+}
+
+/// 32-bit PCI devices area, as start and size, for a 32-bit reserved area of
+/// the given size. The devices area covers what is not used by the PCI
+/// MMCONFIG space.
+pub fn mem_32bit_devices_area(reserved_size: u64) -> (GuestAddress, u64) {
+    (
+        mem_32bit_reserved_start(reserved_size),
+        reserved_size - PCI_MMCONFIG_SIZE,
+    )
+}
+
 // Identity map is a one page region after the TSS
 pub const KVM_IDENTITY_MAP_START: GuestAddress = GuestAddress(KVM_TSS_START.0 + KVM_TSS_SIZE);
 pub const KVM_IDENTITY_MAP_SIZE: u64 = 4 << 10;
@@ -123,3 +145,11 @@ pub const APIC_START: GuestAddress = GuestAddress(0xfee0_0000);
 
 // ** 64-bit RAM start (start: 4GiB, length: varies) **
 pub const RAM_64BIT_START: GuestAddress = GuestAddress(0x1_0000_0000);
+// The 64-bit RAM is split in regions no larger than what a KVM memory slot
+// can hold (just under 8TiB).
+pub const RAM_64BIT_REGION_MAX_SIZE: u64 = 1 << 42;
+
+// End of the physical memory the guest kernel can map with 4-level paging
+// (64TiB). RAM beyond requires 5-level paging (LA57), which extends the limit
+// to the whole 52-bit physical address space.
+pub const RAM_4LEVEL_PAGING_END: GuestAddress = GuestAddress(1 << 46);
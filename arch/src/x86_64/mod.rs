@@ -7,17 +7,21 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE-BSD-3-Clause file.
 use std::sync::Arc;
+pub mod bzimage;
+mod cache;
+mod cpu_model;
 pub mod interrupts;
 pub mod layout;
 mod mpspec;
 mod mptable;
+pub mod multiboot2;
 pub mod regs;
 use crate::GuestMemoryMmap;
 use crate::InitramfsConfig;
 use crate::RegionType;
-use hypervisor::arch::x86::{CpuIdEntry, CPUID_FLAG_VALID_INDEX};
+use hypervisor::arch::x86::{CpuIdEntry, MsrEntry, CPUID_FLAG_VALID_INDEX};
 use hypervisor::{HypervisorCpuError, HypervisorError};
-use linux_loader::loader::bootparam::boot_params;
+use linux_loader::loader::bootparam::{boot_params, setup_header};
 use linux_loader::loader::elf::start_info::{
     hvm_memmap_table_entry, hvm_modlist_entry, hvm_start_info,
 };
@@ -28,7 +32,11 @@ use vm_memory::{
     GuestMemoryRegion, GuestUsize,
 };
 mod smbios;
+pub use cpu_model::{CpuModel, CpuidFeature};
+pub use smbios::SmbiosMemoryDevice;
 use std::arch::x86_64;
+#[cfg(feature = "sev_snp")]
+pub mod sev_snp;
 #[cfg(feature = "tdx")]
 pub mod tdx;
 
@@ -37,21 +45,127 @@ const TSC_DEADLINE_TIMER_ECX_BIT: u8 = 24; // tsc deadline timer ecx bit.
 const HYPERVISOR_ECX_BIT: u8 = 31; // Hypervisor ecx bit.
 const MTRR_EDX_BIT: u8 = 12; // Hypervisor ecx bit.
 const INVARIANT_TSC_EDX_BIT: u8 = 8; // Invariant TSC bit on 0x8000_0007 EDX
+const HYBRID_EDX_BIT: u8 = 15; // Hybrid part bit on 0x7 EDX
+const LA57_ECX_BIT: u8 = 16; // 5-level paging bit on 0x7 ECX
+const SGX_ATTRIBUTE_PROVISIONKEY_BIT: u8 = 4; // PROVISIONKEY attribute bit on 0x12.1 EAX
+const SEV_EAX_BIT: u8 = 1; // SEV bit on 0x8000_001f EAX
+const SEV_ES_EAX_BIT: u8 = 3; // SEV-ES bit on 0x8000_001f EAX
+const SEV_SNP_EAX_BIT: u8 = 4; // SEV-SNP bit on 0x8000_001f EAX
+const SEV_CBIT_POSITION_MASK: u32 = 0x3f; // C-bit position on 0x8000_001f EBX
+const SEV_PHYS_ADDR_REDUCTION_MASK: u32 = 0x3f << 6; // Physical address bits reduction on 0x8000_001f EBX
+const XLF_CAN_BE_LOADED_ABOVE_4G: u16 = 1 << 1; // Setup header xloadflags bit
+
+// Native model ID leaf
+const NATIVE_MODEL_ID_LEAF: u32 = 0x1a;
+
+// TSC and processor frequency leaves
+const TSC_FREQUENCY_LEAF: u32 = 0x15;
+const CPU_FREQUENCY_LEAF: u32 = 0x16;
+const CRYSTAL_CLOCK_KHZ: u32 = 25_000;
+
+// "GenuineIntel" as found in leaf 0x0 EBX
+const VENDOR_INTEL_EBX: u32 = 0x756e_6547;
+// "AuthenticAMD" as found in leaf 0x0 EBX
+const VENDOR_AMD_EBX: u32 = 0x6874_7541;
+
+// AMD topology leaves
+const AMD_EXT_APIC_ID_LEAF: u32 = 0x8000_001e;
+const AMD_EXT_TOPOLOGY_LEAF: u32 = 0x8000_0026;
+const TOPOEXT_ECX_BIT: u8 = 22; // Topology extensions bit on 0x8000_0001 ECX
 
 // KVM feature bits
 const KVM_FEATURE_ASYNC_PF_INT_BIT: u8 = 14;
-#[cfg(feature = "tdx")]
+const KVM_FEATURE_MSI_EXT_DEST_ID_BIT: u8 = 15;
 const KVM_FEATURE_CLOCKSOURCE_BIT: u8 = 0;
-#[cfg(feature = "tdx")]
 const KVM_FEATURE_CLOCKSOURCE2_BIT: u8 = 3;
-#[cfg(feature = "tdx")]
 const KVM_FEATURE_CLOCKSOURCE_STABLE_BIT: u8 = 24;
-#[cfg(feature = "tdx")]
 const KVM_FEATURE_ASYNC_PF_BIT: u8 = 4;
-#[cfg(feature = "tdx")]
 const KVM_FEATURE_ASYNC_PF_VMEXIT_BIT: u8 = 10;
-#[cfg(feature = "tdx")]
 const KVM_FEATURE_STEAL_TIME_BIT: u8 = 5;
+const KVM_FEATURE_PV_EOI_BIT: u8 = 6;
+const KVM_FEATURE_PV_UNHALT_BIT: u8 = 7;
+
+// Hyper-V feature bits from leaf 0x4000_0003
+const HV_ACCESS_FREQUENCY_MSRS_EAX_BIT: u8 = 11;
+const HV_FREQUENCY_MSRS_AVAILABLE_EDX_BIT: u8 = 8;
+const HV_STIMER_DIRECT_MODE_EDX_BIT: u8 = 19;
+// Hyper-V recommendation bits from leaf 0x4000_0004
+const HV_DEPRECATING_AEOI_EAX_BIT: u8 = 9;
+
+/// KVM paravirtualized features which can be hidden from the guest, along
+/// with the bits they cover in leaf 0x4000_0001 EAX.
+const KVM_PV_FEATURES: &[(&str, &[u8])] = &[
+    (
+        "kvmclock",
+        &[
+            KVM_FEATURE_CLOCKSOURCE_BIT,
+            KVM_FEATURE_CLOCKSOURCE2_BIT,
+            KVM_FEATURE_CLOCKSOURCE_STABLE_BIT,
+        ],
+    ),
+    ("kvm_steal_time", &[KVM_FEATURE_STEAL_TIME_BIT]),
+    ("kvm_pv_eoi", &[KVM_FEATURE_PV_EOI_BIT]),
+    ("kvm_pv_unhalt", &[KVM_FEATURE_PV_UNHALT_BIT]),
+    (
+        "kvm_async_pf",
+        &[
+            KVM_FEATURE_ASYNC_PF_BIT,
+            KVM_FEATURE_ASYNC_PF_VMEXIT_BIT,
+            KVM_FEATURE_ASYNC_PF_INT_BIT,
+        ],
+    ),
+];
+
+/// Returns whether `name` is a KVM paravirtualized feature that can be
+/// disabled through `CpuidConfig::disabled_kvm_features`.
+pub fn is_kvm_pv_feature(name: &str) -> bool {
+    KVM_PV_FEATURES.iter().any(|(n, _)| *n == name)
+}
+
+// Mask of the leaf 0x4000_0001 EAX bits covered by the given KVM features.
+fn kvm_pv_features_mask(names: &[String]) -> super::Result<u32> {
+    let mut mask = 0;
+    for name in names {
+        let (_, bits) = KVM_PV_FEATURES
+            .iter()
+            .find(|(n, _)| n == name)
+            .ok_or_else(|| Error::UnknownCpuidFeature(name.clone()))?;
+        for bit in bits.iter() {
+            mask |= 1 << bit;
+        }
+    }
+    Ok(mask)
+}
+
+/// Boot protocols supported by the VMM to configure the guest initial state.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum BootProtocol {
+    /// Linux 64-bit boot protocol, through the zero page (boot_params)
+    LinuxBoot,
+    /// PVH boot protocol, through the hvm_start_info structure
+    #[default]
+    PvhBoot,
+    /// Multiboot2 boot protocol, through the boot information structure
+    Multiboot2Boot,
+}
+
+/// Type of a core on a hybrid CPU, as reported through CPUID leaf 0x1a.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CoreType {
+    /// Performance core (Intel Core)
+    Performance,
+    /// Efficiency core (Intel Atom)
+    Efficiency,
+}
+
+impl CoreType {
+    fn native_model_id(&self) -> u32 {
+        match self {
+            CoreType::Performance => 0x40 << 24,
+            CoreType::Efficiency => 0x20 << 24,
+        }
+    }
+}
 
 #[derive(Debug, Copy, Clone)]
 /// Specifies the entry point address where the guest must start
@@ -60,10 +174,57 @@ const KVM_FEATURE_STEAL_TIME_BIT: u8 = 5;
 pub struct EntryPoint {
     /// Address in guest memory where the guest must start execution
     pub entry_addr: Option<GuestAddress>,
+    /// Protocol used to boot the guest
+    pub protocol: BootProtocol,
+    /// Setup header from the bzImage, only relevant to the Linux boot protocol
+    pub setup_header: Option<setup_header>,
+}
+
+impl EntryPoint {
+    /// Whether the guest can be handed an initramfs loaded above 4GiB.
+    ///
+    /// The PVH start info carries 64-bit addresses, while a kernel booted
+    /// through the Linux boot protocol has to advertise it through the
+    /// setup header. Multiboot2 modules are limited to 32-bit addresses.
+    pub fn initramfs_above_4g(&self) -> bool {
+        match self.protocol {
+            BootProtocol::PvhBoot => true,
+            BootProtocol::LinuxBoot => self.setup_header.map_or(false, |hdr| {
+                hdr.xloadflags & XLF_CAN_BE_LOADED_ABOVE_4G != 0
+            }),
+            BootProtocol::Multiboot2Boot => false,
+        }
+    }
 }
 
 const E820_RAM: u32 = 1;
 const E820_RESERVED: u32 = 2;
+const E820_ACPI: u32 = 3;
+const E820_NVS: u32 = 4;
+const E820_PMEM: u32 = 7;
+const E820_SOFT_RESERVED: u32 = 0xefff_ffff;
+
+/// Type of a range reported in the memory map on top of the RAM.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemmapType {
+    Reserved,
+    AcpiReclaim,
+    AcpiNvs,
+    Pmem,
+    SoftReserved,
+}
+
+impl MemmapType {
+    fn e820_type(self) -> u32 {
+        match self {
+            MemmapType::Reserved => E820_RESERVED,
+            MemmapType::AcpiReclaim => E820_ACPI,
+            MemmapType::AcpiNvs => E820_NVS,
+            MemmapType::Pmem => E820_PMEM,
+            MemmapType::SoftReserved => E820_SOFT_RESERVED,
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct SgxEpcSection {
@@ -191,15 +352,42 @@ pub enum Error {
     /// Error checking CPUID compatibility
     CpuidCheckCompatibility,
 
+    /// Error getting the feature MSRs through the hypervisor (kvm/mshv) API
+    MsrFeaturesGetSupported(HypervisorError),
+
+    /// Error checking feature MSRs compatibility
+    MsrCheckCompatibility,
+
     // Error writing EBDA address
     EbdaSetup(vm_memory::GuestMemoryError),
 
     // Error getting CPU TSC frequency
     GetTscFrequency(HypervisorCpuError),
 
+    /// Error enabling the Hyper-V synthetic interrupt controller
+    EnableHypervSynic(HypervisorCpuError),
+
+    /// Too many entries in the E820 table of the zero page
+    E820Configuration,
+
+    /// Error setting up the Multiboot2 boot information
+    Multiboot2Setup(multiboot2::Error),
+
     /// Error retrieving TDX capabilities through the hypervisor (kvm/mshv) API
     #[cfg(feature = "tdx")]
     TdxCapabilities(HypervisorError),
+
+    /// Unknown CPU model
+    UnknownCpuModel(String),
+
+    /// The CPU model is from a different vendor than the host
+    CpuModelVendorMismatch(String),
+
+    /// Some features from the CPU model aren't supported
+    CpuModelMissingFeatures(String, String),
+
+    /// Unknown CPUID feature
+    UnknownCpuidFeature(String),
 }
 
 impl From<Error> for super::Error {
@@ -209,7 +397,7 @@ impl From<Error> for super::Error {
 }
 
 #[allow(clippy::upper_case_acronyms)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum CpuidReg {
     EAX,
     EBX,
@@ -311,6 +499,30 @@ impl CpuidPatch {
         }
     }
 
+    pub fn unpatch_cpuid(cpuid: &mut [CpuIdEntry], patches: Vec<CpuidPatch>) {
+        for entry in cpuid {
+            for patch in patches.iter() {
+                if entry.function == patch.function && entry.index == patch.index {
+                    if let Some(flags_bit) = patch.flags_bit {
+                        entry.flags &= !(1 << flags_bit);
+                    }
+                    if let Some(eax_bit) = patch.eax_bit {
+                        entry.eax &= !(1 << eax_bit);
+                    }
+                    if let Some(ebx_bit) = patch.ebx_bit {
+                        entry.ebx &= !(1 << ebx_bit);
+                    }
+                    if let Some(ecx_bit) = patch.ecx_bit {
+                        entry.ecx &= !(1 << ecx_bit);
+                    }
+                    if let Some(edx_bit) = patch.edx_bit {
+                        entry.edx &= !(1 << edx_bit);
+                    }
+                }
+            }
+        }
+    }
+
     pub fn is_feature_enabled(
         cpuid: &[CpuIdEntry],
         function: u32,
@@ -344,6 +556,41 @@ enum CpuidCompatibleCheck {
     NumNotGreater, // smaller or equal as a number
 }
 
+impl CpuidCompatibleCheck {
+    fn is_compatible(&self, src_vm_feature: u64, dest_vm_feature: u64) -> bool {
+        match self {
+            CpuidCompatibleCheck::BitwiseSubset => {
+                let different_feature_bits = src_vm_feature ^ dest_vm_feature;
+                let src_vm_feature_bits_only = different_feature_bits & src_vm_feature;
+                src_vm_feature_bits_only == 0
+            }
+            CpuidCompatibleCheck::Equal => src_vm_feature == dest_vm_feature,
+            CpuidCompatibleCheck::NumNotGreater => src_vm_feature <= dest_vm_feature,
+        }
+    }
+}
+
+const MSR_IA32_CORE_CAPABILITIES: u32 = 0xcf;
+const MSR_IA32_ARCH_CAPABILITIES: u32 = 0x10a;
+
+struct MsrFeatureEntry {
+    index: u32,
+    compatible_check: CpuidCompatibleCheck,
+}
+
+// Feature MSRs enumerating capabilities not reported through CPUID, such as
+// the absence of hardware vulnerabilities the guest would otherwise mitigate.
+const CHECKED_MSR_FEATURE_ENTRY_LIST: [MsrFeatureEntry; 2] = [
+    MsrFeatureEntry {
+        index: MSR_IA32_ARCH_CAPABILITIES,
+        compatible_check: CpuidCompatibleCheck::BitwiseSubset,
+    },
+    MsrFeatureEntry {
+        index: MSR_IA32_CORE_CAPABILITIES,
+        compatible_check: CpuidCompatibleCheck::BitwiseSubset,
+    },
+];
+
 pub struct CpuidFeatureEntry {
     function: u32,
     index: u32,
@@ -414,6 +661,29 @@ impl CpuidFeatureEntry {
                 feature_reg: CpuidReg::EDX,
                 compatible_check: CpuidCompatibleCheck::BitwiseSubset,
             },
+            // Leaf 0x8000_0007, EDX, advanced power management features
+            // such as the invariant TSC
+            CpuidFeatureEntry {
+                function: 0x8000_0007,
+                index: 0,
+                feature_reg: CpuidReg::EDX,
+                compatible_check: CpuidCompatibleCheck::BitwiseSubset,
+            },
+            // Leaf 0x8000_0008, EBX, AMD extended features (speculation
+            // control, WBNOINVD, ...)
+            CpuidFeatureEntry {
+                function: 0x8000_0008,
+                index: 0,
+                feature_reg: CpuidReg::EBX,
+                compatible_check: CpuidCompatibleCheck::BitwiseSubset,
+            },
+            // Leaf 0x8000_001f, EAX, AMD memory encryption features
+            CpuidFeatureEntry {
+                function: 0x8000_001f,
+                index: 0,
+                feature_reg: CpuidReg::EAX,
+                compatible_check: CpuidCompatibleCheck::BitwiseSubset,
+            },
             // KVM CPUID bits: https://www.kernel.org/doc/html/latest/virt/kvm/cpuid.html
             // Leaf 0x4000_0000, EAX/EBX/ECX/EDX, KVM CPUID SIGNATURE
             CpuidFeatureEntry {
@@ -520,16 +790,10 @@ impl CpuidFeatureEntry {
             .enumerate()
         {
             let entry = &feature_entry_list[i];
-            let entry_compatible = match entry.compatible_check {
-                CpuidCompatibleCheck::BitwiseSubset => {
-                    let different_feature_bits = src_vm_feature ^ dest_vm_feature;
-                    let src_vm_feature_bits_only = different_feature_bits & src_vm_feature;
-                    src_vm_feature_bits_only == 0
-                }
-                CpuidCompatibleCheck::Equal => src_vm_feature == dest_vm_feature,
-                CpuidCompatibleCheck::NumNotGreater => src_vm_feature <= dest_vm_feature,
-            };
-            if !entry_compatible {
+            if !entry
+                .compatible_check
+                .is_compatible(u64::from(*src_vm_feature), u64::from(*dest_vm_feature))
+            {
                 error!(
                     "Detected incompatible CPUID entry: leaf={:#02x} (subleaf={:#02x}), register='{:?}', \
                     compatilbe_check='{:?}', source VM feature='{:#04x}', destination VM feature'{:#04x}'.",
@@ -548,16 +812,142 @@ impl CpuidFeatureEntry {
             Err(Error::CpuidCheckCompatibility)
         }
     }
+
+    // The function returns `Error` (a.k.a. "incompatible"), when the feature MSRs from
+    // `src_vm_msr_features` are not a subset of those of the `dest_vm_msr_features`.
+    // A feature MSR missing from the list is considered as having no feature bits set.
+    pub fn check_msr_compatibility(
+        src_vm_msr_features: &[MsrEntry],
+        dest_vm_msr_features: &[MsrEntry],
+    ) -> Result<(), Error> {
+        let msr_feature = |msr_features: &[MsrEntry], index: u32| {
+            msr_features
+                .iter()
+                .find(|msr| msr.index == index)
+                .map_or(0, |msr| msr.data)
+        };
+
+        let mut compatible = true;
+        for entry in CHECKED_MSR_FEATURE_ENTRY_LIST.iter() {
+            let src_vm_feature = msr_feature(src_vm_msr_features, entry.index);
+            let dest_vm_feature = msr_feature(dest_vm_msr_features, entry.index);
+            if !entry
+                .compatible_check
+                .is_compatible(src_vm_feature, dest_vm_feature)
+            {
+                error!(
+                    "Detected incompatible feature MSR: index={:#x}, compatible_check='{:?}', \
+                    source VM feature='{:#x}', destination VM feature='{:#x}'.",
+                    entry.index, entry.compatible_check, src_vm_feature, dest_vm_feature
+                );
+
+                compatible = false;
+            }
+        }
+
+        if compatible {
+            info!("No feature MSR incompatibility detected.");
+            Ok(())
+        } else {
+            Err(Error::MsrCheckCompatibility)
+        }
+    }
+}
+
+/// Get the value of the feature MSRs checked for migration compatibility.
+pub fn generate_msr_features(
+    hypervisor: &Arc<dyn hypervisor::Hypervisor>,
+) -> super::Result<Vec<MsrEntry>> {
+    let indices: Vec<u32> = CHECKED_MSR_FEATURE_ENTRY_LIST
+        .iter()
+        .map(|entry| entry.index)
+        .collect();
+
+    hypervisor
+        .get_msr_features(&indices)
+        .map_err(|e| Error::MsrFeaturesGetSupported(e).into())
+}
+
+#[derive(Default)]
+pub struct CpuidConfig {
+    pub topology: Option<(u8, u8, u8)>,
+    pub sgx_epc_sections: Option<Vec<SgxEpcSection>>,
+    /// Let SGX enclaves access the provisioning key, used for attestation
+    pub sgx_provisioning: bool,
+    pub phys_bits: u8,
+    pub kvm_hyperv: bool,
+    pub cpu_model: Option<String>,
+    pub enabled_features: Vec<String>,
+    pub disabled_features: Vec<String>,
+    /// Expose a hybrid CPU, with per vCPU core types set from configure_vcpu()
+    pub hybrid: bool,
+    /// Expose AMX, which requires the dynamic XSTATE permission for the tile
+    /// data to be granted to the VMM beforehand.
+    pub amx: bool,
+    /// KVM paravirtualized features hidden from the guest
+    pub disabled_kvm_features: Vec<String>,
+    /// Expose the memory encryption capabilities required by a SEV-SNP guest
+    #[cfg(feature = "sev_snp")]
+    pub sev_snp: bool,
+    /// Expose the memory encryption capabilities required by a SEV-ES guest
+    #[cfg(feature = "sev_snp")]
+    pub sev_es: bool,
+    #[cfg(feature = "tdx")]
+    pub tdx: bool,
 }
 
 pub fn generate_common_cpuid(
     hypervisor: &Arc<dyn hypervisor::Hypervisor>,
-    topology: Option<(u8, u8, u8)>,
-    sgx_epc_sections: Option<Vec<SgxEpcSection>>,
-    phys_bits: u8,
-    kvm_hyperv: bool,
-    #[cfg(feature = "tdx")] tdx_enabled: bool,
+    config: CpuidConfig,
 ) -> super::Result<Vec<CpuIdEntry>> {
+    let CpuidConfig {
+        topology,
+        sgx_epc_sections,
+        sgx_provisioning,
+        phys_bits,
+        kvm_hyperv,
+        cpu_model,
+        enabled_features,
+        disabled_features,
+        hybrid,
+        amx,
+        disabled_kvm_features,
+        #[cfg(feature = "sev_snp")]
+            sev_snp: sev_snp_enabled,
+        #[cfg(feature = "sev_snp")]
+            sev_es: sev_es_enabled,
+        #[cfg(feature = "tdx")]
+            tdx: tdx_enabled,
+    } = config;
+    #[cfg(not(feature = "sev_snp"))]
+    let (sev_snp_enabled, sev_es_enabled) = (false, false);
+
+    let cpu_model = cpu_model
+        .map(|name| CpuModel::from_name(&name).ok_or(Error::UnknownCpuModel(name)))
+        .transpose()?;
+    let feature_patches = |names: &[String]| -> Result<Vec<CpuidPatch>, Error> {
+        names
+            .iter()
+            .map(|name| {
+                CpuidFeature::from_name(name)
+                    .map(CpuidFeature::patch)
+                    .ok_or_else(|| Error::UnknownCpuidFeature(name.clone()))
+            })
+            .collect()
+    };
+    let mut enabled_features = enabled_features;
+    if amx {
+        // Advertise AMX through leaf 0x7 even if masked by the CPU model
+        for name in ["amx_bf16", "amx_tile", "amx_int8"] {
+            if !enabled_features.iter().any(|f| f == name) {
+                enabled_features.push(name.to_string());
+            }
+        }
+    }
+    let enabled_patches = feature_patches(&enabled_features)?;
+    let disabled_patches = feature_patches(&disabled_features)?;
+    let disabled_kvm_mask = kvm_pv_features_mask(&disabled_kvm_features)?;
+
     // SAFETY: cpuid called with valid leaves
     if unsafe { x86_64::__cpuid(1) }.ecx & 1 << HYPERVISOR_ECX_BIT == 1 << HYPERVISOR_ECX_BIT {
         // SAFETY: cpuid called with valid leaves
@@ -615,12 +1005,37 @@ pub fn generate_common_cpuid(
 
     CpuidPatch::patch_cpuid(&mut cpuid, cpuid_patches);
 
+    if let Some(cpu_model) = cpu_model {
+        info!("Applying CPU model {}", cpu_model.name);
+        cpu_model.apply(&mut cpuid)?;
+    }
+
+    // Explicitly enabled or disabled features take precedence over the
+    // CPU model.
+    for name in enabled_features.iter() {
+        if !CpuidFeature::from_name(name).unwrap().is_set(&cpuid) {
+            warn!("Enabling CPUID feature {name} which is not supported by the host");
+        }
+    }
+    CpuidPatch::patch_cpuid(&mut cpuid, enabled_patches);
+    CpuidPatch::unpatch_cpuid(&mut cpuid, disabled_patches);
+
+    if cpu_model.is_some() || !enabled_features.is_empty() || !disabled_features.is_empty() {
+        cpu_model::update_xsave_components(&mut cpuid);
+    }
+
     if let Some(t) = topology {
         update_cpuid_topology(&mut cpuid, t.0, t.1, t.2);
+        update_cpuid_amd_topology(&mut cpuid, t.0, t.1, t.2);
+        cache::update_cpuid_cache(&mut cpuid, t.0, t.1, t.2);
+    }
+
+    if hybrid {
+        update_cpuid_hybrid(&mut cpuid);
     }
 
     if let Some(sgx_epc_sections) = sgx_epc_sections {
-        update_cpuid_sgx(&mut cpuid, sgx_epc_sections)?;
+        update_cpuid_sgx(&mut cpuid, sgx_epc_sections, sgx_provisioning)?;
     }
 
     #[cfg(feature = "tdx")]
@@ -634,6 +1049,8 @@ pub fn generate_common_cpuid(
         None
     };
 
+    let la57 = CpuidPatch::is_feature_enabled(&cpuid, 0x7, 0, CpuidReg::ECX, LA57_ECX_BIT.into());
+
     // Update some existing CPUID
     for entry in cpuid.as_mut_slice().iter_mut() {
         match entry.function {
@@ -670,9 +1087,33 @@ pub fn generate_common_cpuid(
                     }
                 }
             }
-            // Set CPU physical bits
+            // Set CPU physical bits, along with the linear address bits
+            // matching the paging modes exposed to the guest.
             0x8000_0008 => {
-                entry.eax = (entry.eax & 0xffff_ff00) | (phys_bits as u32 & 0xff);
+                let linear_bits = if la57 { 57 } else { 48 };
+                entry.eax =
+                    (entry.eax & 0xffff_0000) | linear_bits << 8 | (phys_bits as u32 & 0xff);
+            }
+            // Only expose the memory encryption capabilities a SEV-SNP or
+            // SEV-ES guest relies on, namely the encryption bit position and
+            // the number of physical address bits it takes away. Other guests
+            // can't make use of them.
+            0x8000_001f => {
+                if sev_snp_enabled || sev_es_enabled {
+                    let mut eax_mask = 1 << SEV_EAX_BIT | 1 << SEV_ES_EAX_BIT;
+                    if sev_snp_enabled {
+                        eax_mask |= 1 << SEV_SNP_EAX_BIT;
+                    }
+                    entry.eax &= eax_mask;
+                    entry.ebx &= SEV_CBIT_POSITION_MASK | SEV_PHYS_ADDR_REDUCTION_MASK;
+                    entry.ecx = 0;
+                    entry.edx = 0;
+                } else {
+                    entry.eax = 0;
+                    entry.ebx = 0;
+                    entry.ecx = 0;
+                    entry.edx = 0;
+                }
             }
             // Disable KVM_FEATURE_ASYNC_PF_INT
             // This is required until we find out why the asynchronous page
@@ -682,6 +1123,12 @@ pub fn generate_common_cpuid(
             0x4000_0001 => {
                 entry.eax &= !(1 << KVM_FEATURE_ASYNC_PF_INT_BIT);
 
+                // Let the guest encode APIC IDs above 255 in the MSI address,
+                // as KVM is set up with 32-bit x2APIC IDs.
+                entry.eax |= 1 << KVM_FEATURE_MSI_EXT_DEST_ID_BIT;
+
+                entry.eax &= !disabled_kvm_mask;
+
                 // These features are not supported by TDX
                 #[cfg(feature = "tdx")]
                 if tdx_enabled {
@@ -697,17 +1144,24 @@ pub fn generate_common_cpuid(
         }
     }
 
-    // Copy CPU identification string
+    // Copy CPU identification string, unless it is provided by the CPU model
+    let brand_string = cpu_model.map(|m| m.brand_string());
     for i in 0x8000_0002..=0x8000_0004 {
         cpuid.retain(|c| c.function != i);
-        // SAFETY: call cpuid with valid leaves
-        let leaf = unsafe { std::arch::x86_64::__cpuid(i) };
+        let (eax, ebx, ecx, edx) = if let Some(brand) = &brand_string {
+            let regs = &brand[(i - 0x8000_0002) as usize * 4..];
+            (regs[0], regs[1], regs[2], regs[3])
+        } else {
+            // SAFETY: call cpuid with valid leaves
+            let leaf = unsafe { std::arch::x86_64::__cpuid(i) };
+            (leaf.eax, leaf.ebx, leaf.ecx, leaf.edx)
+        };
         cpuid.push(CpuIdEntry {
             function: i,
-            eax: leaf.eax,
-            ebx: leaf.ebx,
-            ecx: leaf.ecx,
-            edx: leaf.edx,
+            eax,
+            ebx,
+            ecx,
+            edx,
             ..Default::default()
         });
     }
@@ -743,12 +1197,18 @@ pub fn generate_common_cpuid(
                    | 1 << 2 // AccessSynicRegs
                    | 1 << 3 // AccessSyntheticTimerRegs
                    | 1 << 9, // AccessPartitionReferenceTsc
-            edx: 1 << 3, // CPU dynamic partitioning
+            edx: 1 << 3 // CPU dynamic partitioning
+                   | 1 << HV_STIMER_DIRECT_MODE_EDX_BIT,
             ..Default::default()
         });
+        let mut recommendations = 1 << 5; // Recommend relaxed timing
+        if host_apicv_enabled() {
+            // AutoEOI defeats the hardware APIC virtualization
+            recommendations |= 1 << HV_DEPRECATING_AEOI_EAX_BIT;
+        }
         cpuid.push(CpuIdEntry {
             function: 0x4000_0004,
-            eax: 1 << 5, // Recommend relaxed timing
+            eax: recommendations,
             ..Default::default()
         });
         for i in 0x4000_0005..=0x4000_000a {
@@ -764,25 +1224,55 @@ pub fn generate_common_cpuid(
 
 pub fn configure_vcpu(
     vcpu: &Arc<dyn hypervisor::Vcpu>,
-    id: u8,
+    apic_id: u32,
     boot_setup: Option<(EntryPoint, &GuestMemoryAtomic<GuestMemoryMmap>)>,
     cpuid: Vec<CpuIdEntry>,
     kvm_hyperv: bool,
+    core_type: Option<CoreType>,
+    microcode_revision: Option<u32>,
 ) -> super::Result<()> {
     // Per vCPU CPUID changes; common are handled via generate_common_cpuid()
     let mut cpuid = cpuid;
-    CpuidPatch::set_cpuid_reg(&mut cpuid, 0xb, None, CpuidReg::EDX, u32::from(id));
-    CpuidPatch::set_cpuid_reg(&mut cpuid, 0x1f, None, CpuidReg::EDX, u32::from(id));
-
-    // The TSC frequency CPUID leaf should not be included when running with HyperV emulation
-    if !kvm_hyperv {
-        if let Some(tsc_khz) = vcpu.tsc_khz().map_err(Error::GetTscFrequency)? {
-            // Need to check that the TSC doesn't vary with dynamic frequency
-            // SAFETY: cpuid called with valid leaves
-            if unsafe { std::arch::x86_64::__cpuid(0x8000_0007) }.edx
-                & (1u32 << INVARIANT_TSC_EDX_BIT)
-                > 0
-            {
+    // The initial APIC ID is reported in the top 8 bits of EBX
+    for entry in cpuid.iter_mut().filter(|e| e.function == 1) {
+        entry.ebx = (entry.ebx & 0x00ff_ffff) | (apic_id & 0xff) << 24;
+    }
+    // The x2APIC ID of the vCPU spans the whole 32 bits of EDX
+    CpuidPatch::set_cpuid_reg(&mut cpuid, 0xb, None, CpuidReg::EDX, apic_id);
+    CpuidPatch::set_cpuid_reg(&mut cpuid, 0x1f, None, CpuidReg::EDX, apic_id);
+    update_cpuid_amd_apic_id(&mut cpuid, apic_id);
+    if let Some(core_type) = core_type {
+        CpuidPatch::set_cpuid_reg(
+            &mut cpuid,
+            NATIVE_MODEL_ID_LEAF,
+            Some(0),
+            CpuidReg::EAX,
+            core_type.native_model_id(),
+        );
+    }
+
+    if let Some(tsc_khz) = vcpu.tsc_khz().map_err(Error::GetTscFrequency)? {
+        // Need to check that the TSC doesn't vary with dynamic frequency
+        // SAFETY: cpuid called with valid leaves
+        if unsafe { std::arch::x86_64::__cpuid(0x8000_0007) }.edx & (1u32 << INVARIANT_TSC_EDX_BIT)
+            > 0
+        {
+            if kvm_hyperv {
+                // The frequency MSRs report the now known TSC frequency
+                CpuidPatch::patch_cpuid(
+                    &mut cpuid,
+                    vec![CpuidPatch {
+                        function: 0x4000_0003,
+                        index: 0,
+                        flags_bit: None,
+                        eax_bit: Some(HV_ACCESS_FREQUENCY_MSRS_EAX_BIT),
+                        ebx_bit: None,
+                        ecx_bit: None,
+                        edx_bit: Some(HV_FREQUENCY_MSRS_AVAILABLE_EDX_BIT),
+                    }],
+                );
+            } else {
+                // The TSC frequency CPUID leaf should not be included when running with HyperV emulation
                 CpuidPatch::set_cpuid_reg(
                     &mut cpuid,
                     0x4000_0000,
@@ -798,24 +1288,47 @@ pub fn configure_vcpu(
                                    * APIC_BUS_CYCLE_NS */
                     ..Default::default()
                 });
-            };
+            }
+
+            update_cpuid_tsc_frequency(&mut cpuid, tsc_khz);
         }
     }
 
+    let la57 = CpuidPatch::is_feature_enabled(&cpuid, 0x7, 0, CpuidReg::ECX, LA57_ECX_BIT.into());
     vcpu.set_cpuid2(&cpuid)
         .map_err(|e| Error::SetSupportedCpusFailed(e.into()))?;
 
     if kvm_hyperv {
-        vcpu.enable_hyperv_synic().unwrap();
+        vcpu.enable_hyperv_synic()
+            .map_err(Error::EnableHypervSynic)?;
     }
 
-    regs::setup_msrs(vcpu).map_err(Error::MsrsConfiguration)?;
+    // Intel reports the microcode revision in the upper half of the MSR,
+    // while AMD reports the patch level in its lower half.
+    let amd = cpuid
+        .iter()
+        .any(|e| e.function == 0 && e.ebx == VENDOR_AMD_EBX);
+    let microcode_revision = microcode_revision.map(|revision| {
+        if amd {
+            u64::from(revision)
+        } else {
+            u64::from(revision) << 32
+        }
+    });
+    regs::setup_msrs(vcpu, microcode_revision).map_err(Error::MsrsConfiguration)?;
     if let Some((kernel_entry_point, guest_memory)) = boot_setup {
         if let Some(entry_addr) = kernel_entry_point.entry_addr {
             // Safe to unwrap because this method is called after the VM is configured
-            regs::setup_regs(vcpu, entry_addr.raw_value()).map_err(Error::RegsConfiguration)?;
+            regs::setup_regs(vcpu, entry_addr.raw_value(), kernel_entry_point.protocol)
+                .map_err(Error::RegsConfiguration)?;
             regs::setup_fpu(vcpu).map_err(Error::FpuConfiguration)?;
-            regs::setup_sregs(&guest_memory.memory(), vcpu).map_err(Error::SregsConfiguration)?;
+            regs::setup_sregs(
+                &guest_memory.memory(),
+                vcpu,
+                kernel_entry_point.protocol,
+                la57,
+            )
+            .map_err(Error::SregsConfiguration)?;
         }
     }
     interrupts::set_lint(vcpu).map_err(|e| Error::LocalIntConfiguration(e.into()))?;
@@ -825,44 +1338,50 @@ pub fn configure_vcpu(
 /// Returns a Vec of the valid memory addresses.
 /// These should be used to configure the GuestMemory structure for the platform.
 /// For x86_64 all addresses are valid from the start of the kernel except a
-/// carve out at the end of 32bit address space.
-pub fn arch_memory_regions(size: GuestUsize) -> Vec<(GuestAddress, usize, RegionType)> {
-    let reserved_memory_gap_start = layout::MEM_32BIT_RESERVED_START
-        .checked_add(layout::MEM_32BIT_DEVICES_SIZE)
+/// carve out of `mem_32bit_reserved_size` bytes at the end of 32bit address space.
+pub fn arch_memory_regions(
+    size: GuestUsize,
+    mem_32bit_reserved_size: u64,
+) -> Vec<(GuestAddress, usize, RegionType)> {
+    let reserved_memory_start = layout::mem_32bit_reserved_start(mem_32bit_reserved_size);
+    let (devices_start, devices_size) = layout::mem_32bit_devices_area(mem_32bit_reserved_size);
+    let reserved_memory_gap_start = devices_start
+        .checked_add(devices_size)
         .expect("32-bit reserved region is too large");
 
     let requested_memory_size = GuestAddress(size);
     let mut regions = Vec::new();
 
     // case1: guest memory fits before the gap
-    if size <= layout::MEM_32BIT_RESERVED_START.raw_value() {
+    if size <= reserved_memory_start.raw_value() {
         regions.push((GuestAddress(0), size as usize, RegionType::Ram));
     // case2: guest memory extends beyond the gap
     } else {
         // push memory before the gap
         regions.push((
             GuestAddress(0),
-            layout::MEM_32BIT_RESERVED_START.raw_value() as usize,
-            RegionType::Ram,
-        ));
-        regions.push((
-            layout::RAM_64BIT_START,
-            requested_memory_size.unchecked_offset_from(layout::MEM_32BIT_RESERVED_START) as usize,
+            reserved_memory_start.raw_value() as usize,
             RegionType::Ram,
         ));
+        // push memory after the gap, which can span beyond the 4-level
+        // paging limit
+        let mut start = layout::RAM_64BIT_START;
+        let mut remaining = requested_memory_size.unchecked_offset_from(reserved_memory_start);
+        while remaining > 0 {
+            let size = remaining.min(layout::RAM_64BIT_REGION_MAX_SIZE);
+            regions.push((start, size as usize, RegionType::Ram));
+            start = start.unchecked_add(size);
+            remaining -= size;
+        }
     }
 
     // Add the 32-bit device memory hole as a sub region.
-    regions.push((
-        layout::MEM_32BIT_RESERVED_START,
-        layout::MEM_32BIT_DEVICES_SIZE as usize,
-        RegionType::SubRegion,
-    ));
+    regions.push((devices_start, devices_size as usize, RegionType::SubRegion));
 
     // Add the 32-bit reserved memory hole as a sub region.
     regions.push((
         reserved_memory_gap_start,
-        (layout::MEM_32BIT_RESERVED_SIZE - layout::MEM_32BIT_DEVICES_SIZE) as usize,
+        (mem_32bit_reserved_size - devices_size) as usize,
         RegionType::Reserved,
     ));
 
@@ -875,32 +1394,58 @@ pub fn arch_memory_regions(size: GuestUsize) -> Vec<(GuestAddress, usize, Region
 ///
 /// * `guest_mem` - The memory to be used by the guest.
 /// * `cmdline_addr` - Address in `guest_mem` where the kernel command line was loaded.
-/// * `cmdline_size` - Size of the kernel command line in bytes including the null terminator.
-/// * `num_cpus` - Number of virtual CPUs the guest will have.
+/// * `apic_ids` - APIC IDs of the virtual CPUs the guest boots with.
+/// * `boot_protocol` - Protocol used to boot the guest.
+/// * `setup_header` - Setup header from the bzImage, if any.
+/// * `memmap_regions` - Additional ranges reported with their own type in the memory map.
+/// * `memory_devices` - Memory devices described through SMBIOS.
+/// * `smbios_table` - SMBIOS table installed in place of the synthesized one.
 #[allow(clippy::too_many_arguments)]
 pub fn configure_system(
     guest_mem: &GuestMemoryMmap,
     cmdline_addr: GuestAddress,
     initramfs: &Option<InitramfsConfig>,
-    _num_cpus: u8,
+    apic_ids: &[u32],
     rsdp_addr: Option<GuestAddress>,
     sgx_epc_region: Option<SgxEpcRegion>,
     serial_number: Option<&str>,
     uuid: Option<&str>,
     oem_strings: Option<&[&str]>,
+    boot_protocol: BootProtocol,
+    setup_header: Option<setup_header>,
+    memmap_regions: &[(GuestAddress, u64, MemmapType)],
+    memory_devices: &[SmbiosMemoryDevice],
+    smbios_table: Option<&[u8]>,
+    enable_mptable: bool,
 ) -> super::Result<()> {
     // Write EBDA address to location where ACPICA expects to find it
     guest_mem
         .write_obj((layout::EBDA_START.0 >> 4) as u16, layout::EBDA_POINTER)
         .map_err(Error::EbdaSetup)?;
 
-    let size = smbios::setup_smbios(guest_mem, serial_number, uuid, oem_strings)
-        .map_err(Error::SmbiosSetup)?;
-
-    // Place the MP table after the SMIOS table aligned to 16 bytes
-    let offset = GuestAddress(layout::SMBIOS_START).unchecked_add(size);
-    let offset = GuestAddress((offset.0 + 16) & !0xf);
-    mptable::setup_mptable(offset, guest_mem, _num_cpus).map_err(Error::MpTableSetup)?;
+    let size = smbios::setup_smbios(
+        guest_mem,
+        serial_number,
+        uuid,
+        oem_strings,
+        memory_devices,
+        smbios_table,
+    )
+    .map_err(Error::SmbiosSetup)?;
+
+    // Place the MP table after the SMIOS table aligned to 16 bytes. The MP
+    // table can't describe more than 254 CPUs, nor APIC IDs beyond 253, in
+    // which case the guest has to rely on the ACPI MADT only. It can also be
+    // omitted for ACPI only guests.
+    if enable_mptable
+        && apic_ids.len() as u32 <= mptable::MAX_SUPPORTED_CPUS
+        && apic_ids.iter().all(|id| *id < mptable::MAX_SUPPORTED_CPUS)
+    {
+        let offset = GuestAddress(layout::SMBIOS_START).unchecked_add(size);
+        let offset = GuestAddress((offset.0 + 16) & !0xf);
+        let apic_ids: Vec<u8> = apic_ids.iter().map(|id| *id as u8).collect();
+        mptable::setup_mptable(offset, guest_mem, &apic_ids).map_err(Error::MpTableSetup)?;
+    }
 
     // Check that the RAM is not smaller than the RSDP start address
     if let Some(rsdp_addr) = rsdp_addr {
@@ -909,13 +1454,113 @@ pub fn configure_system(
         }
     }
 
-    configure_pvh(
-        guest_mem,
-        cmdline_addr,
-        initramfs,
-        rsdp_addr,
-        sgx_epc_region,
-    )
+    match boot_protocol {
+        BootProtocol::PvhBoot => configure_pvh(
+            guest_mem,
+            cmdline_addr,
+            initramfs,
+            rsdp_addr,
+            sgx_epc_region,
+            memmap_regions,
+        ),
+        BootProtocol::LinuxBoot => configure_64bit_boot(
+            guest_mem,
+            cmdline_addr,
+            initramfs,
+            setup_header,
+            rsdp_addr,
+            sgx_epc_region,
+            memmap_regions,
+        ),
+        BootProtocol::Multiboot2Boot => {
+            let memmap = generate_memory_map(guest_mem, sgx_epc_region, memmap_regions);
+            multiboot2::setup_boot_information(
+                guest_mem,
+                cmdline_addr,
+                initramfs,
+                rsdp_addr,
+                &memmap,
+            )
+            .map_err(|e| Error::Multiboot2Setup(e).into())
+        }
+    }
+}
+
+fn configure_64bit_boot(
+    guest_mem: &GuestMemoryMmap,
+    cmdline_addr: GuestAddress,
+    initramfs: &Option<InitramfsConfig>,
+    setup_hdr: Option<setup_header>,
+    rsdp_addr: Option<GuestAddress>,
+    sgx_epc_region: Option<SgxEpcRegion>,
+    memmap_regions: &[(GuestAddress, u64, MemmapType)],
+) -> super::Result<()> {
+    const KERNEL_BOOT_FLAG_MAGIC: u16 = 0xaa55;
+    const KERNEL_HDR_MAGIC: u32 = 0x5372_6448;
+    const KERNEL_LOADER_OTHER: u8 = 0xff;
+    const KERNEL_MIN_ALIGNMENT_BYTES: u32 = 0x0100_0000; // Must be non-zero.
+
+    let mut params: BootParamsWrapper = BootParamsWrapper(boot_params::default());
+
+    if let Some(hdr) = setup_hdr {
+        params.0.hdr = hdr;
+    } else {
+        params.0.hdr.boot_flag = KERNEL_BOOT_FLAG_MAGIC;
+        params.0.hdr.header = KERNEL_HDR_MAGIC;
+        params.0.hdr.kernel_alignment = KERNEL_MIN_ALIGNMENT_BYTES;
+    }
+
+    // Common bootparams settings
+    if params.0.hdr.type_of_loader == 0 {
+        params.0.hdr.type_of_loader = KERNEL_LOADER_OTHER;
+    }
+    params.0.hdr.cmd_line_ptr = cmdline_addr.raw_value() as u32;
+
+    if let Some(initramfs_config) = initramfs {
+        // The upper 32 bits are needed when loaded above 4GiB
+        let address = initramfs_config.address.raw_value();
+        let size = initramfs_config.size as u64;
+        params.0.hdr.ramdisk_image = address as u32;
+        params.0.hdr.ramdisk_size = size as u32;
+        params.0.ext_ramdisk_image = (address >> 32) as u32;
+        params.0.ext_ramdisk_size = (size >> 32) as u32;
+    }
+
+    for entry in generate_memory_map(guest_mem, sgx_epc_region, memmap_regions) {
+        add_e820_entry(&mut params.0, entry.addr, entry.size, entry.type_)?;
+    }
+
+    if let Some(rsdp_addr) = rsdp_addr {
+        params.0.acpi_rsdp_addr = rsdp_addr.0;
+    }
+
+    let zero_page_addr = layout::ZERO_PAGE_START;
+    guest_mem
+        .checked_offset(zero_page_addr, mem::size_of::<boot_params>())
+        .ok_or(super::Error::ZeroPagePastRamEnd)?;
+    guest_mem
+        .write_obj(params, zero_page_addr)
+        .map_err(super::Error::ZeroPageSetup)?;
+
+    Ok(())
+}
+
+fn add_e820_entry(
+    params: &mut boot_params,
+    addr: u64,
+    size: u64,
+    mem_type: u32,
+) -> Result<(), Error> {
+    if params.e820_entries >= params.e820_table.len() as u8 {
+        return Err(Error::E820Configuration);
+    }
+
+    params.e820_table[params.e820_entries as usize].addr = addr;
+    params.e820_table[params.e820_entries as usize].size = size;
+    params.e820_table[params.e820_entries as usize].type_ = mem_type;
+    params.e820_entries += 1;
+
+    Ok(())
 }
 
 fn configure_pvh(
@@ -924,6 +1569,7 @@ fn configure_pvh(
     initramfs: &Option<InitramfsConfig>,
     rsdp_addr: Option<GuestAddress>,
     sgx_epc_region: Option<SgxEpcRegion>,
+    memmap_regions: &[(GuestAddress, u64, MemmapType)],
 ) -> super::Result<()> {
     const XEN_HVM_START_MAGIC_VALUE: u32 = 0x336ec578;
 
@@ -959,14 +1605,72 @@ fn configure_pvh(
 
     // Vector to hold the memory maps which needs to be written to guest memory
     // at MEMMAP_START after all of the mappings are recorded.
+    let memmap = generate_memory_map(guest_mem, sgx_epc_region, memmap_regions);
+
+    start_info.0.memmap_entries = memmap.len() as u32;
+
+    // Copy the vector with the memmap table to the MEMMAP_START address
+    // which is already saved in the memmap_paddr field of hvm_start_info struct.
+    let mut memmap_start_addr = layout::MEMMAP_START;
+
+    guest_mem
+        .checked_offset(
+            memmap_start_addr,
+            mem::size_of::<hvm_memmap_table_entry>() * start_info.0.memmap_entries as usize,
+        )
+        .ok_or(super::Error::MemmapTablePastRamEnd)?;
+
+    // For every entry in the memmap vector, create a MemmapTableEntryWrapper
+    // and write it to guest memory.
+    for memmap_entry in memmap {
+        let map_entry_wrapper: MemmapTableEntryWrapper = MemmapTableEntryWrapper(memmap_entry);
+
+        guest_mem
+            .write_obj(map_entry_wrapper, memmap_start_addr)
+            .map_err(|_| super::Error::MemmapTableSetup)?;
+        memmap_start_addr =
+            memmap_start_addr.unchecked_add(mem::size_of::<hvm_memmap_table_entry>() as u64);
+    }
+
+    // The hvm_start_info struct itself must be stored at PVH_START_INFO
+    // address, and %rbx will be initialized to contain PVH_INFO_START prior to
+    // starting the guest, as required by the PVH ABI.
+    let start_info_addr = layout::PVH_INFO_START;
+
+    guest_mem
+        .checked_offset(start_info_addr, mem::size_of::<hvm_start_info>())
+        .ok_or(super::Error::StartInfoPastRamEnd)?;
+
+    // Write the start_info struct to guest memory.
+    guest_mem
+        .write_obj(start_info, start_info_addr)
+        .map_err(|_| super::Error::StartInfoSetup)?;
+
+    Ok(())
+}
+
+// Memory map shared by the PVH, Linux and Multiboot2 boot protocols.
+fn generate_memory_map(
+    guest_mem: &GuestMemoryMmap,
+    sgx_epc_region: Option<SgxEpcRegion>,
+    memmap_regions: &[(GuestAddress, u64, MemmapType)],
+) -> Vec<hvm_memmap_table_entry> {
     let mut memmap: Vec<hvm_memmap_table_entry> = Vec::new();
 
     // Create the memory map entries.
     add_memmap_entry(&mut memmap, 0, layout::EBDA_START.raw_value(), E820_RAM);
 
     let mem_end = guest_mem.last_addr();
-
-    if mem_end < layout::MEM_32BIT_RESERVED_START {
+    // The RAM below 4GiB stops where the 32-bit reserved area starts, which
+    // depends on the size of the area.
+    let low_mem_end = guest_mem
+        .iter()
+        .filter(|r| r.start_addr() < layout::RAM_64BIT_START)
+        .map(|r| r.last_addr())
+        .max()
+        .unwrap_or(mem_end);
+
+    if mem_end < layout::RAM_64BIT_START {
         add_memmap_entry(
             &mut memmap,
             layout::HIGH_RAM_START.raw_value(),
@@ -977,7 +1681,7 @@ fn configure_pvh(
         add_memmap_entry(
             &mut memmap,
             layout::HIGH_RAM_START.raw_value(),
-            layout::MEM_32BIT_RESERVED_START.unchecked_offset_from(layout::HIGH_RAM_START),
+            low_mem_end.unchecked_offset_from(layout::HIGH_RAM_START) + 1,
             E820_RAM,
         );
         if mem_end > layout::RAM_64BIT_START {
@@ -1006,47 +1710,19 @@ fn configure_pvh(
         );
     }
 
-    start_info.0.memmap_entries = memmap.len() as u32;
+    // User defined regions. They can overlap with the RAM, in which case
+    // the guest gives precedence to the non-RAM type.
+    for (start, size, memmap_type) in memmap_regions {
+        add_memmap_entry(
+            &mut memmap,
+            start.raw_value(),
+            *size,
+            memmap_type.e820_type(),
+        );
+    }
 
-    // Copy the vector with the memmap table to the MEMMAP_START address
-    // which is already saved in the memmap_paddr field of hvm_start_info struct.
-    let mut memmap_start_addr = layout::MEMMAP_START;
-
-    guest_mem
-        .checked_offset(
-            memmap_start_addr,
-            mem::size_of::<hvm_memmap_table_entry>() * start_info.0.memmap_entries as usize,
-        )
-        .ok_or(super::Error::MemmapTablePastRamEnd)?;
-
-    // For every entry in the memmap vector, create a MemmapTableEntryWrapper
-    // and write it to guest memory.
-    for memmap_entry in memmap {
-        let map_entry_wrapper: MemmapTableEntryWrapper = MemmapTableEntryWrapper(memmap_entry);
-
-        guest_mem
-            .write_obj(map_entry_wrapper, memmap_start_addr)
-            .map_err(|_| super::Error::MemmapTableSetup)?;
-        memmap_start_addr =
-            memmap_start_addr.unchecked_add(mem::size_of::<hvm_memmap_table_entry>() as u64);
-    }
-
-    // The hvm_start_info struct itself must be stored at PVH_START_INFO
-    // address, and %rbx will be initialized to contain PVH_INFO_START prior to
-    // starting the guest, as required by the PVH ABI.
-    let start_info_addr = layout::PVH_INFO_START;
-
-    guest_mem
-        .checked_offset(start_info_addr, mem::size_of::<hvm_start_info>())
-        .ok_or(super::Error::StartInfoPastRamEnd)?;
-
-    // Write the start_info struct to guest memory.
-    guest_mem
-        .write_obj(start_info, start_info_addr)
-        .map_err(|_| super::Error::StartInfoSetup)?;
-
-    Ok(())
-}
+    memmap
+}
 
 fn add_memmap_entry(memmap: &mut Vec<hvm_memmap_table_entry>, addr: u64, size: u64, mem_type: u32) {
     // Add the table entry to the vector
@@ -1059,9 +1735,14 @@ fn add_memmap_entry(memmap: &mut Vec<hvm_memmap_table_entry>, addr: u64, size: u
 }
 
 /// Returns the memory address where the initramfs could be loaded.
+///
+/// The end of the RAM below 4GiB is preferred, falling back to the end of
+/// the RAM above 4GiB for images which are too large, if `above_4g` tells
+/// the guest can handle it.
 pub fn initramfs_load_addr(
     guest_mem: &GuestMemoryMmap,
     initramfs_size: usize,
+    above_4g: bool,
 ) -> super::Result<u64> {
     let first_region = guest_mem
         .find_region(GuestAddress::new(0))
@@ -1069,12 +1750,46 @@ pub fn initramfs_load_addr(
     // It's safe to cast to usize because the size of a region can't be greater than usize.
     let lowmem_size = first_region.len() as usize;
 
-    if lowmem_size < initramfs_size {
+    if lowmem_size >= initramfs_size {
+        let aligned_addr: u64 = ((lowmem_size - initramfs_size) & !(crate::pagesize() - 1)) as u64;
+        return Ok(aligned_addr);
+    }
+    if !above_4g {
         return Err(super::Error::InitramfsAddress);
     }
 
-    let aligned_addr: u64 = ((lowmem_size - initramfs_size) & !(crate::pagesize() - 1)) as u64;
-    Ok(aligned_addr)
+    // Too large for the RAM below 4GiB, place it at the end of the RAM above
+    // 4GiB instead.
+    let high_region = guest_mem
+        .find_region(layout::RAM_64BIT_START)
+        .ok_or(super::Error::InitramfsAddress)?;
+    let highmem_size = high_region.len() as usize;
+
+    if highmem_size < initramfs_size {
+        return Err(super::Error::InitramfsAddress);
+    }
+
+    let aligned_offset = (highmem_size - initramfs_size) & !(crate::pagesize() - 1);
+    Ok(high_region.start_addr().raw_value() + aligned_offset as u64)
+}
+
+// Whether KVM runs the guests with the hardware APIC virtualization, APICv on
+// Intel or AVIC on AMD, based on the KVM module parameters.
+fn host_apicv_enabled() -> bool {
+    [
+        "/sys/module/kvm_intel/parameters/enable_apicv",
+        "/sys/module/kvm_amd/parameters/avic",
+    ]
+    .iter()
+    .filter_map(|path| std::fs::read_to_string(path).ok())
+    .any(|value| matches!(value.trim(), "Y" | "1"))
+}
+
+/// Whether the host supports 5-level paging (LA57).
+pub fn host_supports_la57() -> bool {
+    // SAFETY: call cpuid with valid leaves
+    let leaf = unsafe { x86_64::__cpuid_count(0x7, 0) };
+    leaf.ecx & 1 << LA57_ECX_BIT != 0
 }
 
 pub fn get_host_cpu_phys_bits() -> u8 {
@@ -1132,7 +1847,7 @@ fn update_cpuid_topology(
         0xb,
         Some(1),
         CpuidReg::EBX,
-        u32::from(dies_per_package * cores_per_die * threads_per_core),
+        u32::from(dies_per_package) * u32::from(cores_per_die) * u32::from(threads_per_core),
     );
     CpuidPatch::set_cpuid_reg(cpuid, 0xb, Some(1), CpuidReg::ECX, 2 << 8);
 
@@ -1153,7 +1868,7 @@ fn update_cpuid_topology(
         0x1f,
         Some(1),
         CpuidReg::EBX,
-        u32::from(cores_per_die * threads_per_core),
+        u32::from(cores_per_die) * u32::from(threads_per_core),
     );
     CpuidPatch::set_cpuid_reg(cpuid, 0x1f, Some(1), CpuidReg::ECX, 2 << 8);
 
@@ -1163,16 +1878,203 @@ fn update_cpuid_topology(
         0x1f,
         Some(2),
         CpuidReg::EBX,
-        u32::from(dies_per_package * cores_per_die * threads_per_core),
+        u32::from(dies_per_package) * u32::from(cores_per_die) * u32::from(threads_per_core),
     );
     CpuidPatch::set_cpuid_reg(cpuid, 0x1f, Some(2), CpuidReg::ECX, 5 << 8);
 }
 
+// Describe the topology through the AMD specific leaves 0x8000_001e and
+// 0x8000_0026, which is what Linux relies on to build the core, CCX and node
+// maps of AMD guests. Each die is exposed as a single CCX and NUMA node.
+fn update_cpuid_amd_topology(
+    cpuid: &mut Vec<CpuIdEntry>,
+    threads_per_core: u8,
+    cores_per_die: u8,
+    dies_per_package: u8,
+) {
+    if !cpuid
+        .iter()
+        .any(|e| e.function == 0 && e.ebx == VENDOR_AMD_EBX)
+    {
+        return;
+    }
+
+    let thread_width = 8 - (threads_per_core - 1).leading_zeros();
+    let core_width = (8 - (cores_per_die - 1).leading_zeros()) + thread_width;
+    let die_width = (8 - (dies_per_package - 1).leading_zeros()) + core_width;
+
+    let threads_per_die = u32::from(cores_per_die) * u32::from(threads_per_core);
+    let threads_per_package = u32::from(dies_per_package) * threads_per_die;
+
+    // Both leaves are only consumed when topology extensions are advertised
+    CpuidPatch::patch_cpuid(
+        cpuid,
+        vec![CpuidPatch {
+            function: 0x8000_0001,
+            index: 0,
+            flags_bit: None,
+            eax_bit: None,
+            ebx_bit: None,
+            ecx_bit: Some(TOPOEXT_ECX_BIT),
+            edx_bit: None,
+        }],
+    );
+
+    // Number of threads and APIC ID size of the package
+    for entry in cpuid.iter_mut() {
+        if entry.function == 0x8000_0008 {
+            entry.ecx = (entry.ecx & !0xf0ff) | die_width << 12 | (threads_per_package - 1) & 0xff;
+        }
+    }
+
+    // Threads per compute unit and nodes per processor, the extended APIC,
+    // core and node IDs being filled on a per vCPU basis.
+    cpuid.retain(|c| c.function != AMD_EXT_APIC_ID_LEAF && c.function != AMD_EXT_TOPOLOGY_LEAF);
+    cpuid.push(CpuIdEntry {
+        function: AMD_EXT_APIC_ID_LEAF,
+        ebx: u32::from(threads_per_core - 1) << 8,
+        ecx: (u32::from(dies_per_package - 1) & 0x7) << 8,
+        ..Default::default()
+    });
+
+    // Extended CPU topology: SMT, CCX, CCD and socket levels
+    let levels = [
+        (thread_width, u32::from(threads_per_core), 1),
+        (core_width, threads_per_die, 2),
+        (core_width, threads_per_die, 3),
+        (die_width, threads_per_package, 4),
+    ];
+    for (index, (width, threads, level_type)) in levels.iter().enumerate() {
+        cpuid.push(CpuIdEntry {
+            function: AMD_EXT_TOPOLOGY_LEAF,
+            index: index as u32,
+            flags: CPUID_FLAG_VALID_INDEX,
+            eax: *width,
+            ebx: *threads,
+            ecx: level_type << 8 | index as u32,
+            ..Default::default()
+        });
+    }
+    // Terminate the list with an invalid level type
+    cpuid.push(CpuIdEntry {
+        function: AMD_EXT_TOPOLOGY_LEAF,
+        index: levels.len() as u32,
+        flags: CPUID_FLAG_VALID_INDEX,
+        ecx: levels.len() as u32,
+        ..Default::default()
+    });
+
+    // Make sure the leaves are reachable
+    for entry in cpuid.iter_mut() {
+        if entry.function == 0x8000_0000 && entry.eax < AMD_EXT_TOPOLOGY_LEAF {
+            entry.eax = AMD_EXT_TOPOLOGY_LEAF;
+        }
+    }
+}
+
+// Fill the extended APIC, core and node IDs of leaf 0x8000_001e, along with
+// the x2APIC ID of leaf 0x8000_0026, based on the widths found in the latter.
+fn update_cpuid_amd_apic_id(cpuid: &mut [CpuIdEntry], id: u32) {
+    let width = |index: u32| {
+        cpuid
+            .iter()
+            .find(|e| e.function == AMD_EXT_TOPOLOGY_LEAF && e.index == index)
+            .map(|e| e.eax & 0x1f)
+    };
+    let (thread_width, core_width) = match (width(0), width(1)) {
+        (Some(thread_width), Some(core_width)) => (thread_width, core_width),
+        _ => return,
+    };
+
+    for entry in cpuid.iter_mut() {
+        match entry.function {
+            AMD_EXT_APIC_ID_LEAF => {
+                entry.eax = id;
+                entry.ebx = (entry.ebx & !0xff) | (id >> thread_width) & 0xff;
+                entry.ecx = (entry.ecx & !0xff) | (id >> core_width) & 0xff;
+            }
+            AMD_EXT_TOPOLOGY_LEAF => entry.edx = id,
+            _ => {}
+        }
+    }
+}
+
+// Enumerate the TSC and processor frequencies through leaves 0x15 and 0x16,
+// letting the guest skip the TSC calibration. These leaves are only defined
+// on Intel CPUs.
+fn update_cpuid_tsc_frequency(cpuid: &mut Vec<CpuIdEntry>, tsc_khz: u32) {
+    if !cpuid
+        .iter()
+        .any(|e| e.function == 0 && e.ebx == VENDOR_INTEL_EBX)
+    {
+        return;
+    }
+
+    // TSC frequency = ECX * EBX / EAX, from a nominal 25MHz crystal clock
+    cpuid.retain(|c| c.function != TSC_FREQUENCY_LEAF && c.function != CPU_FREQUENCY_LEAF);
+    cpuid.push(CpuIdEntry {
+        function: TSC_FREQUENCY_LEAF,
+        eax: CRYSTAL_CLOCK_KHZ,
+        ebx: tsc_khz,
+        ecx: CRYSTAL_CLOCK_KHZ * 1000,
+        ..Default::default()
+    });
+    // Base, maximum and bus (reference) frequencies in MHz
+    cpuid.push(CpuIdEntry {
+        function: CPU_FREQUENCY_LEAF,
+        eax: tsc_khz / 1000,
+        ebx: tsc_khz / 1000,
+        ecx: 100,
+        ..Default::default()
+    });
+
+    // Make sure the leaves are reachable
+    for entry in cpuid.iter_mut() {
+        if entry.function == 0 && entry.eax < CPU_FREQUENCY_LEAF {
+            entry.eax = CPU_FREQUENCY_LEAF;
+        }
+    }
+}
+
+// Advertise a hybrid part through leaf 0x7 and expose the native model ID
+// leaf 0x1a, whose core type is filled on a per vCPU basis.
+fn update_cpuid_hybrid(cpuid: &mut Vec<CpuIdEntry>) {
+    CpuidPatch::patch_cpuid(
+        cpuid,
+        vec![CpuidPatch {
+            function: 0x7,
+            index: 0,
+            flags_bit: None,
+            eax_bit: None,
+            ebx_bit: None,
+            ecx_bit: None,
+            edx_bit: Some(HYBRID_EDX_BIT),
+        }],
+    );
+
+    cpuid.retain(|c| c.function != NATIVE_MODEL_ID_LEAF);
+    CpuidPatch::set_cpuid_reg(
+        cpuid,
+        NATIVE_MODEL_ID_LEAF,
+        Some(0),
+        CpuidReg::EAX,
+        CoreType::Performance.native_model_id(),
+    );
+
+    // Make sure the leaf is reachable
+    for entry in cpuid.iter_mut() {
+        if entry.function == 0 && entry.eax < NATIVE_MODEL_ID_LEAF {
+            entry.eax = NATIVE_MODEL_ID_LEAF;
+        }
+    }
+}
+
 // The goal is to update the CPUID sub-leaves to reflect the number of EPC
 // sections exposed to the guest.
 fn update_cpuid_sgx(
     cpuid: &mut Vec<CpuIdEntry>,
     epc_sections: Vec<SgxEpcSection>,
+    provisioning: bool,
 ) -> Result<(), Error> {
     // Something's wrong if there's no EPC section.
     if epc_sections.is_empty() {
@@ -1187,6 +2089,15 @@ fn update_cpuid_sgx(
         return Err(Error::MissingSgxLaunchControlFeature);
     }
 
+    // Only advertise the PROVISIONKEY attribute if the VM is allowed to use it
+    if !provisioning {
+        for entry in cpuid.iter_mut() {
+            if entry.function == 0x12 && entry.index == 1 {
+                entry.eax &= !(1 << SGX_ATTRIBUTE_PROVISIONKEY_BIT);
+            }
+        }
+    }
+
     // Get host CPUID for leaf 0x12, subleaf 0x2. This is to retrieve EPC
     // properties such as confidentiality and integrity.
     // SAFETY: call cpuid with valid leaves
@@ -1221,10 +2132,11 @@ fn update_cpuid_sgx(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use linux_loader::loader::bootparam::boot_e820_entry;
 
     #[test]
     fn regions_lt_4gb() {
-        let regions = arch_memory_regions(1 << 29);
+        let regions = arch_memory_regions(1 << 29, layout::MEM_32BIT_RESERVED_SIZE);
         assert_eq!(3, regions.len());
         assert_eq!(GuestAddress(0), regions[0].0);
         assert_eq!(1usize << 29, regions[0].1);
@@ -1232,32 +2144,195 @@ mod tests {
 
     #[test]
     fn regions_gt_4gb() {
-        let regions = arch_memory_regions((1 << 32) + 0x8000);
+        let regions = arch_memory_regions((1 << 32) + 0x8000, layout::MEM_32BIT_RESERVED_SIZE);
         assert_eq!(4, regions.len());
         assert_eq!(GuestAddress(0), regions[0].0);
         assert_eq!(GuestAddress(1 << 32), regions[1].0);
     }
 
+    #[test]
+    fn regions_gt_4level_paging_end() {
+        // RAM spanning beyond the 4-level paging limit is split in regions
+        // fitting in a memory slot.
+        let mem_size = layout::RAM_4LEVEL_PAGING_END.0 + (1 << 40);
+        let regions = arch_memory_regions(mem_size, layout::MEM_32BIT_RESERVED_SIZE);
+        let ram: Vec<_> = regions.iter().filter(|r| r.2 == RegionType::Ram).collect();
+        assert_eq!(18, ram.len());
+        assert_eq!(GuestAddress(1 << 32), ram[1].0);
+        for (region, next) in ram[1..].iter().zip(ram[2..].iter()) {
+            assert_eq!(layout::RAM_64BIT_REGION_MAX_SIZE as usize, region.1);
+            assert_eq!(region.0.unchecked_add(region.1 as u64), next.0);
+        }
+
+        let last = ram.last().unwrap();
+        assert!(last.0 > layout::RAM_4LEVEL_PAGING_END);
+        assert_eq!(mem_size, ram.iter().map(|r| r.1 as u64).sum::<u64>());
+    }
+
+    #[test]
+    fn regions_large_32bit_hole() {
+        let mem_size: u64 = 1 << 32;
+        let reserved_size: u64 = 2 << 30;
+        // The reserved area grows downwards, its end being fixed at the TSS.
+        let reserved_start = layout::KVM_TSS_START.0 - reserved_size;
+        assert_eq!(
+            layout::mem_32bit_reserved_start(reserved_size).0,
+            reserved_start
+        );
+
+        let regions = arch_memory_regions(mem_size, reserved_size);
+        assert_eq!(4, regions.len());
+        assert_eq!(
+            (GuestAddress(0), reserved_start as usize, RegionType::Ram),
+            regions[0]
+        );
+        assert_eq!(
+            (
+                layout::RAM_64BIT_START,
+                (mem_size - reserved_start) as usize,
+                RegionType::Ram
+            ),
+            regions[1]
+        );
+        assert_eq!(
+            (
+                GuestAddress(reserved_start),
+                (reserved_size - layout::PCI_MMCONFIG_SIZE) as usize,
+                RegionType::SubRegion
+            ),
+            regions[2]
+        );
+        assert_eq!(
+            (
+                layout::PCI_MMCONFIG_START,
+                layout::PCI_MMCONFIG_SIZE as usize,
+                RegionType::Reserved
+            ),
+            regions[3]
+        );
+    }
+
+    #[test]
+    fn test_initramfs_load_addr() {
+        let mem_size = 4 << 30;
+        let arch_mem_regions = arch_memory_regions(mem_size, layout::MEM_32BIT_RESERVED_SIZE);
+        let ram_regions: Vec<(GuestAddress, usize)> = arch_mem_regions
+            .iter()
+            .filter(|r| r.2 == RegionType::Ram)
+            .map(|r| (r.0, r.1))
+            .collect();
+        let gm = GuestMemoryMmap::from_ranges(&ram_regions).unwrap();
+
+        // Fits below 4GiB
+        for above_4g in [false, true] {
+            assert_eq!(
+                initramfs_load_addr(&gm, 0x1800, above_4g).unwrap(),
+                layout::MEM_32BIT_RESERVED_START.0 - 0x2000
+            );
+        }
+        // Only fits above 4GiB, if the guest can handle it
+        let high_ram_size = mem_size - layout::MEM_32BIT_RESERVED_START.0;
+        let size = (high_ram_size - 0x1800) as usize;
+        assert_eq!(
+            initramfs_load_addr(&gm, size, true).unwrap(),
+            layout::RAM_64BIT_START.0 + 0x1000
+        );
+        assert!(initramfs_load_addr(&gm, size, false).is_err());
+        // Too large
+        assert!(initramfs_load_addr(&gm, (high_ram_size + 0x1000) as usize, true).is_err());
+    }
+
+    #[test]
+    fn test_initramfs_above_4g() {
+        let entry_point = |protocol, setup_header| EntryPoint {
+            entry_addr: None,
+            protocol,
+            setup_header,
+        };
+        let hdr = |xloadflags| setup_header {
+            xloadflags,
+            ..Default::default()
+        };
+
+        assert!(entry_point(BootProtocol::PvhBoot, None).initramfs_above_4g());
+        assert!(!entry_point(BootProtocol::Multiboot2Boot, None).initramfs_above_4g());
+        // ELF kernels booted through their 64-bit entry point don't tell
+        assert!(!entry_point(BootProtocol::LinuxBoot, None).initramfs_above_4g());
+        assert!(!entry_point(BootProtocol::LinuxBoot, Some(hdr(0x1))).initramfs_above_4g());
+        assert!(entry_point(
+            BootProtocol::LinuxBoot,
+            Some(hdr(XLF_CAN_BE_LOADED_ABOVE_4G | 0x1))
+        )
+        .initramfs_above_4g());
+    }
+
+    #[test]
+    fn test_system_configuration_without_mptable() {
+        let mem_size = 128 << 20;
+        let arch_mem_regions = arch_memory_regions(mem_size, layout::MEM_32BIT_RESERVED_SIZE);
+        let ram_regions: Vec<(GuestAddress, usize)> = arch_mem_regions
+            .iter()
+            .filter(|r| r.2 == RegionType::Ram)
+            .map(|r| (r.0, r.1))
+            .collect();
+
+        let has_mptable = |enable_mptable: bool| {
+            let gm = GuestMemoryMmap::from_ranges(&ram_regions).unwrap();
+            configure_system(
+                &gm,
+                GuestAddress(0),
+                &None,
+                &[0, 1, 2, 3],
+                None,
+                None,
+                None,
+                None,
+                None,
+                BootProtocol::PvhBoot,
+                None,
+                &[],
+                &[],
+                None,
+                enable_mptable,
+            )
+            .unwrap();
+
+            let mut bios = vec![0u8; (layout::HIGH_RAM_START.0 - layout::SMBIOS_START) as usize];
+            gm.read_slice(&mut bios, GuestAddress(layout::SMBIOS_START))
+                .unwrap();
+            bios.windows(4).any(|w| w == b"_MP_")
+        };
+
+        assert!(has_mptable(true));
+        assert!(!has_mptable(false));
+    }
+
     #[test]
     fn test_system_configuration() {
-        let no_vcpus = 4;
+        let apic_ids: Vec<u32> = (0..4).collect();
         let gm = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap();
         let config_err = configure_system(
             &gm,
             GuestAddress(0),
             &None,
-            1,
+            &[0],
             Some(layout::RSDP_POINTER),
             None,
             None,
             None,
             None,
+            BootProtocol::PvhBoot,
+            None,
+            &[],
+            &[],
+            None,
+            true,
         );
         assert!(config_err.is_err());
 
         // Now assigning some memory that falls before the 32bit memory hole.
         let mem_size = 128 << 20;
-        let arch_mem_regions = arch_memory_regions(mem_size);
+        let arch_mem_regions = arch_memory_regions(mem_size, layout::MEM_32BIT_RESERVED_SIZE);
         let ram_regions: Vec<(GuestAddress, usize)> = arch_mem_regions
             .iter()
             .filter(|r| r.2 == RegionType::Ram)
@@ -1269,18 +2344,24 @@ mod tests {
             &gm,
             GuestAddress(0),
             &None,
-            no_vcpus,
+            &apic_ids,
+            None,
+            None,
             None,
             None,
             None,
+            BootProtocol::PvhBoot,
             None,
+            &[],
+            &[],
             None,
+            true,
         )
         .unwrap();
 
         // Now assigning some memory that is equal to the start of the 32bit memory hole.
         let mem_size = 3328 << 20;
-        let arch_mem_regions = arch_memory_regions(mem_size);
+        let arch_mem_regions = arch_memory_regions(mem_size, layout::MEM_32BIT_RESERVED_SIZE);
         let ram_regions: Vec<(GuestAddress, usize)> = arch_mem_regions
             .iter()
             .filter(|r| r.2 == RegionType::Ram)
@@ -1291,12 +2372,18 @@ mod tests {
             &gm,
             GuestAddress(0),
             &None,
-            no_vcpus,
+            &apic_ids,
             None,
             None,
             None,
             None,
             None,
+            BootProtocol::PvhBoot,
+            None,
+            &[],
+            &[],
+            None,
+            true,
         )
         .unwrap();
 
@@ -1304,18 +2391,24 @@ mod tests {
             &gm,
             GuestAddress(0),
             &None,
-            no_vcpus,
+            &apic_ids,
+            None,
+            None,
             None,
             None,
             None,
+            BootProtocol::LinuxBoot,
             None,
+            &[],
+            &[],
             None,
+            true,
         )
         .unwrap();
 
         // Now assigning some memory that falls after the 32bit memory hole.
         let mem_size = 3330 << 20;
-        let arch_mem_regions = arch_memory_regions(mem_size);
+        let arch_mem_regions = arch_memory_regions(mem_size, layout::MEM_32BIT_RESERVED_SIZE);
         let ram_regions: Vec<(GuestAddress, usize)> = arch_mem_regions
             .iter()
             .filter(|r| r.2 == RegionType::Ram)
@@ -1326,12 +2419,18 @@ mod tests {
             &gm,
             GuestAddress(0),
             &None,
-            no_vcpus,
+            &apic_ids,
             None,
             None,
             None,
             None,
             None,
+            BootProtocol::PvhBoot,
+            None,
+            &[],
+            &[],
+            None,
+            true,
         )
         .unwrap();
 
@@ -1339,14 +2438,60 @@ mod tests {
             &gm,
             GuestAddress(0),
             &None,
-            no_vcpus,
+            &apic_ids,
+            None,
+            None,
             None,
             None,
             None,
+            BootProtocol::LinuxBoot,
             None,
+            &[],
+            &[],
             None,
+            true,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_add_e820_entry() {
+        let e820_table = [(boot_e820_entry {
+            addr: 0x1,
+            size: 4,
+            type_: 1,
+        }); 128];
+
+        let expected_params = boot_params {
+            e820_table,
+            e820_entries: 1,
+            ..Default::default()
+        };
+
+        let mut params: boot_params = Default::default();
+        add_e820_entry(
+            &mut params,
+            e820_table[0].addr,
+            e820_table[0].size,
+            e820_table[0].type_,
         )
         .unwrap();
+        assert_eq!(
+            format!("{:?}", params.e820_table[0]),
+            format!("{:?}", expected_params.e820_table[0])
+        );
+        assert_eq!(params.e820_entries, expected_params.e820_entries);
+
+        // Exercise the scenario where the field storing the length of the e820 entry table is
+        // is bigger than the allocated memory.
+        params.e820_entries = params.e820_table.len() as u8 + 1;
+        assert!(add_e820_entry(
+            &mut params,
+            e820_table[0].addr,
+            e820_table[0].size,
+            e820_table[0].type_
+        )
+        .is_err());
     }
 
     #[test]
@@ -1373,4 +2518,261 @@ mod tests {
 
         assert_eq!(format!("{memmap:?}"), format!("{expected_memmap:?}"));
     }
+
+    #[test]
+    fn test_check_amd_cpuid_compatibility() {
+        let src_cpuid = vec![CpuIdEntry {
+            function: 0x8000_0008,
+            ebx: 0x1 << 12 | 0x1 << 15,
+            ..Default::default()
+        }];
+        let mut dest_cpuid = src_cpuid.clone();
+        assert!(CpuidFeatureEntry::check_cpuid_compatibility(&src_cpuid, &dest_cpuid).is_ok());
+
+        dest_cpuid[0].ebx = 0x1 << 12;
+        assert!(CpuidFeatureEntry::check_cpuid_compatibility(&src_cpuid, &dest_cpuid).is_err());
+    }
+
+    #[test]
+    fn test_check_msr_compatibility() {
+        let src_msr_features = vec![MsrEntry {
+            index: MSR_IA32_ARCH_CAPABILITIES,
+            data: 0x1 | 0x1 << 5,
+        }];
+        let mut dest_msr_features = vec![
+            MsrEntry {
+                index: MSR_IA32_ARCH_CAPABILITIES,
+                data: 0x1 | 0x1 << 3 | 0x1 << 5,
+            },
+            MsrEntry {
+                index: MSR_IA32_CORE_CAPABILITIES,
+                data: 0x1 << 5,
+            },
+        ];
+        assert!(
+            CpuidFeatureEntry::check_msr_compatibility(&src_msr_features, &dest_msr_features)
+                .is_ok()
+        );
+
+        dest_msr_features[0].data = 0x1;
+        assert!(
+            CpuidFeatureEntry::check_msr_compatibility(&src_msr_features, &dest_msr_features)
+                .is_err()
+        );
+        // A feature MSR unknown to the destination has no feature bits set
+        assert!(CpuidFeatureEntry::check_msr_compatibility(&src_msr_features, &[]).is_err());
+    }
+
+    #[test]
+    fn test_update_cpuid_tsc_frequency() {
+        let mut cpuid = vec![CpuIdEntry {
+            function: 0,
+            eax: 0xd,
+            ebx: VENDOR_INTEL_EBX,
+            ..Default::default()
+        }];
+        update_cpuid_tsc_frequency(&mut cpuid, 2_995_200);
+
+        assert_eq!(cpuid[0].eax, CPU_FREQUENCY_LEAF);
+        let leaf = cpuid
+            .iter()
+            .find(|e| e.function == TSC_FREQUENCY_LEAF)
+            .unwrap();
+        assert_eq!(
+            leaf.ecx as u64 * leaf.ebx as u64 / leaf.eax as u64,
+            2_995_200_000
+        );
+        let leaf = cpuid
+            .iter()
+            .find(|e| e.function == CPU_FREQUENCY_LEAF)
+            .unwrap();
+        assert_eq!((leaf.eax, leaf.ebx, leaf.ecx), (2995, 2995, 100));
+
+        // Nothing to do on AMD
+        let mut cpuid = vec![CpuIdEntry {
+            function: 0,
+            eax: 0xd,
+            ebx: VENDOR_AMD_EBX,
+            ..Default::default()
+        }];
+        update_cpuid_tsc_frequency(&mut cpuid, 2_995_200);
+        assert_eq!(cpuid.len(), 1);
+    }
+
+    #[test]
+    fn test_kvm_pv_features_mask() {
+        assert!(is_kvm_pv_feature("kvm_steal_time"));
+        assert!(!is_kvm_pv_feature("avx2"));
+        assert_eq!(kvm_pv_features_mask(&[]).unwrap(), 0);
+        assert_eq!(
+            kvm_pv_features_mask(&["kvm_steal_time".to_string(), "kvm_pv_eoi".to_string()])
+                .unwrap(),
+            1 << KVM_FEATURE_STEAL_TIME_BIT | 1 << KVM_FEATURE_PV_EOI_BIT
+        );
+        assert_eq!(
+            kvm_pv_features_mask(&["kvmclock".to_string()]).unwrap(),
+            1 << KVM_FEATURE_CLOCKSOURCE_BIT
+                | 1 << KVM_FEATURE_CLOCKSOURCE2_BIT
+                | 1 << KVM_FEATURE_CLOCKSOURCE_STABLE_BIT
+        );
+        assert!(kvm_pv_features_mask(&["avx2".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_update_cpuid_sgx_provisioning() {
+        let cpuid = vec![
+            CpuIdEntry {
+                function: 0x7,
+                ebx: 1 << 2,
+                ecx: 1 << 30,
+                ..Default::default()
+            },
+            CpuIdEntry {
+                function: 0x12,
+                index: 1,
+                flags: CPUID_FLAG_VALID_INDEX,
+                eax: 0x36,
+                ..Default::default()
+            },
+        ];
+        let sections = vec![SgxEpcSection::new(GuestAddress(0x1_0000_0000), 0x400_0000)];
+
+        let mut enabled = cpuid.clone();
+        update_cpuid_sgx(&mut enabled, sections.clone(), true).unwrap();
+        assert_eq!(enabled[1].eax, 0x36);
+
+        let mut disabled = cpuid;
+        update_cpuid_sgx(&mut disabled, sections, false).unwrap();
+        assert_eq!(disabled[1].eax, 0x26);
+        let section = disabled
+            .iter()
+            .find(|e| e.function == 0x12 && e.index == 2)
+            .unwrap();
+        assert_eq!((section.eax, section.ebx), (1, 1));
+    }
+
+    #[test]
+    fn test_update_cpuid_amd_topology() {
+        let mut cpuid = vec![
+            CpuIdEntry {
+                function: 0,
+                eax: 0xd,
+                ebx: VENDOR_AMD_EBX,
+                ..Default::default()
+            },
+            CpuIdEntry {
+                function: 0x8000_0000,
+                eax: 0x8000_0021,
+                ..Default::default()
+            },
+            CpuIdEntry {
+                function: 0x8000_0001,
+                ..Default::default()
+            },
+            CpuIdEntry {
+                function: 0x8000_0008,
+                eax: 0x3030,
+                ..Default::default()
+            },
+        ];
+        // 2 threads per core, 6 cores per die, 2 dies
+        update_cpuid_amd_topology(&mut cpuid, 2, 6, 2);
+
+        let leaf = |cpuid: &[CpuIdEntry], function: u32, index: u32| {
+            *cpuid
+                .iter()
+                .find(|e| e.function == function && e.index == index)
+                .unwrap()
+        };
+        assert_eq!(leaf(&cpuid, 0x8000_0000, 0).eax, AMD_EXT_TOPOLOGY_LEAF);
+        assert_eq!(leaf(&cpuid, 0x8000_0001, 0).ecx, 1 << TOPOEXT_ECX_BIT);
+        let entry = leaf(&cpuid, 0x8000_0008, 0);
+        assert_eq!((entry.eax, entry.ecx), (0x3030, 5 << 12 | 23));
+
+        let entry = leaf(&cpuid, AMD_EXT_TOPOLOGY_LEAF, 1);
+        assert_eq!((entry.eax, entry.ebx, entry.ecx), (4, 12, 2 << 8 | 1));
+        let entry = leaf(&cpuid, AMD_EXT_TOPOLOGY_LEAF, 3);
+        assert_eq!((entry.eax, entry.ebx, entry.ecx), (5, 24, 4 << 8 | 3));
+        assert_eq!(leaf(&cpuid, AMD_EXT_TOPOLOGY_LEAF, 4).ecx >> 8, 0);
+
+        // Second thread of the third core of the second die
+        update_cpuid_amd_apic_id(&mut cpuid, 16 + 4 + 1);
+        let entry = leaf(&cpuid, AMD_EXT_APIC_ID_LEAF, 0);
+        assert_eq!(
+            (entry.eax, entry.ebx, entry.ecx),
+            (21, 1 << 8 | 10, 1 << 8 | 1)
+        );
+        assert_eq!(leaf(&cpuid, AMD_EXT_TOPOLOGY_LEAF, 2).edx, 21);
+
+        // Nothing to do on Intel
+        let mut cpuid = vec![CpuIdEntry {
+            function: 0,
+            eax: 0xd,
+            ebx: VENDOR_INTEL_EBX,
+            ..Default::default()
+        }];
+        update_cpuid_amd_topology(&mut cpuid, 2, 6, 2);
+        assert_eq!(cpuid.len(), 1);
+    }
+
+    #[test]
+    fn test_generate_memory_map_reserved_regions() {
+        let gm = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 128 << 20)]).unwrap();
+        let memmap = generate_memory_map(
+            &gm,
+            None,
+            &[
+                (GuestAddress(0x400_0000), 0x10_0000, MemmapType::Reserved),
+                (GuestAddress(0xfd00_0000), 0x1000, MemmapType::Reserved),
+            ],
+        );
+
+        let reserved: Vec<(u64, u64)> = memmap
+            .iter()
+            .filter(|e| e.type_ == E820_RESERVED)
+            .map(|e| (e.addr, e.size))
+            .collect();
+        assert_eq!(
+            reserved,
+            vec![
+                (layout::PCI_MMCONFIG_START.0, layout::PCI_MMCONFIG_SIZE),
+                (0x400_0000, 0x10_0000),
+                (0xfd00_0000, 0x1000)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_generate_memory_map_typed_regions() {
+        let gm = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 128 << 20)]).unwrap();
+        let memmap = generate_memory_map(
+            &gm,
+            None,
+            &[
+                (GuestAddress(0x200_0000), 0x1000, MemmapType::AcpiReclaim),
+                (GuestAddress(0x300_0000), 0x1000, MemmapType::AcpiNvs),
+                (GuestAddress(0x400_0000), 0x100_0000, MemmapType::Pmem),
+                (
+                    GuestAddress(0x600_0000),
+                    0x100_0000,
+                    MemmapType::SoftReserved,
+                ),
+            ],
+        );
+
+        let typed: Vec<(u64, u32)> = memmap
+            .iter()
+            .filter(|e| e.type_ != E820_RAM && e.type_ != E820_RESERVED)
+            .map(|e| (e.addr, e.type_))
+            .collect();
+        assert_eq!(
+            typed,
+            vec![
+                (0x200_0000, E820_ACPI),
+                (0x300_0000, E820_NVS),
+                (0x400_0000, E820_PMEM),
+                (0x600_0000, E820_SOFT_RESERVED)
+            ]
+        );
+    }
 }
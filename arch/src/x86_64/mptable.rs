@@ -125,9 +125,13 @@ fn compute_mp_size(num_cpus: u8) -> usize {
         + mem::size_of::<MpcLintsrcWrapper>() * 2
 }
 
-/// Performs setup of the MP table for the given `num_cpus`.
-pub fn setup_mptable(offset: GuestAddress, mem: &GuestMemoryMmap, num_cpus: u8) -> Result<()> {
-    if num_cpus as u32 > MAX_SUPPORTED_CPUS {
+/// Performs setup of the MP table for the CPUs with the given `apic_ids`, the
+/// first one being the bootstrap processor.
+pub fn setup_mptable(offset: GuestAddress, mem: &GuestMemoryMmap, apic_ids: &[u8]) -> Result<()> {
+    let num_cpus = apic_ids.len() as u8;
+    if apic_ids.len() as u32 > MAX_SUPPORTED_CPUS
+        || apic_ids.iter().any(|id| *id as u32 >= MAX_SUPPORTED_CPUS)
+    {
         return Err(Error::TooManyCpus);
     }
 
@@ -142,7 +146,8 @@ pub fn setup_mptable(offset: GuestAddress, mem: &GuestMemoryMmap, num_cpus: u8)
     }
 
     let mut checksum: u8 = 0;
-    let ioapicid: u8 = num_cpus + 1;
+    // Keep the IOAPIC ID clear of the APIC IDs
+    let ioapicid: u8 = apic_ids.iter().max().map_or(1, |id| id + 2);
 
     // The checked_add here ensures the all of the following base_mp.unchecked_add's will be without
     // overflow.
@@ -177,10 +182,10 @@ pub fn setup_mptable(offset: GuestAddress, mem: &GuestMemoryMmap, num_cpus: u8)
 
     {
         let size = mem::size_of::<MpcCpuWrapper>();
-        for cpu_id in 0..num_cpus {
+        for (cpu_id, apic_id) in apic_ids.iter().enumerate() {
             let mut mpc_cpu = MpcCpuWrapper(mpspec::mpc_cpu::default());
             mpc_cpu.0.type_ = mpspec::MP_PROCESSOR as u8;
-            mpc_cpu.0.apicid = cpu_id;
+            mpc_cpu.0.apicid = *apic_id;
             mpc_cpu.0.apicver = APIC_VERSION;
             mpc_cpu.0.cpuflag = mpspec::CPU_ENABLED as u8
                 | if cpu_id == 0 {
@@ -293,6 +298,10 @@ mod tests {
     use crate::layout::MPTABLE_START;
     use vm_memory::{GuestAddress, GuestUsize};
 
+    fn apic_ids(num_cpus: u8) -> Vec<u8> {
+        (0..num_cpus).collect()
+    }
+
     fn table_entry_size(type_: u8) -> usize {
         match type_ as u32 {
             mpspec::MP_PROCESSOR => mem::size_of::<MpcCpuWrapper>(),
@@ -310,7 +319,7 @@ mod tests {
         let mem =
             GuestMemoryMmap::from_ranges(&[(MPTABLE_START, compute_mp_size(num_cpus))]).unwrap();
 
-        setup_mptable(MPTABLE_START, &mem, num_cpus).unwrap();
+        setup_mptable(MPTABLE_START, &mem, &apic_ids(num_cpus)).unwrap();
     }
 
     #[test]
@@ -319,7 +328,7 @@ mod tests {
         let mem = GuestMemoryMmap::from_ranges(&[(MPTABLE_START, compute_mp_size(num_cpus) - 1)])
             .unwrap();
 
-        assert!(setup_mptable(MPTABLE_START, &mem, num_cpus).is_err());
+        assert!(setup_mptable(MPTABLE_START, &mem, &apic_ids(num_cpus)).is_err());
     }
 
     #[test]
@@ -328,7 +337,7 @@ mod tests {
         let mem =
             GuestMemoryMmap::from_ranges(&[(MPTABLE_START, compute_mp_size(num_cpus))]).unwrap();
 
-        setup_mptable(MPTABLE_START, &mem, num_cpus).unwrap();
+        setup_mptable(MPTABLE_START, &mem, &apic_ids(num_cpus)).unwrap();
 
         let mpf_intel: MpfIntelWrapper = mem.read_obj(MPTABLE_START).unwrap();
 
@@ -344,7 +353,7 @@ mod tests {
         let mem =
             GuestMemoryMmap::from_ranges(&[(MPTABLE_START, compute_mp_size(num_cpus))]).unwrap();
 
-        setup_mptable(MPTABLE_START, &mem, num_cpus).unwrap();
+        setup_mptable(MPTABLE_START, &mem, &apic_ids(num_cpus)).unwrap();
 
         let mpf_intel: MpfIntelWrapper = mem.read_obj(MPTABLE_START).unwrap();
         let mpc_offset = GuestAddress(mpf_intel.0.physptr as GuestUsize);
@@ -378,7 +387,7 @@ mod tests {
         .unwrap();
 
         for i in 0..MAX_SUPPORTED_CPUS as u8 {
-            setup_mptable(MPTABLE_START, &mem, i).unwrap();
+            setup_mptable(MPTABLE_START, &mem, &apic_ids(i)).unwrap();
 
             let mpf_intel: MpfIntelWrapper = mem.read_obj(MPTABLE_START).unwrap();
             let mpc_offset = GuestAddress(mpf_intel.0.physptr as GuestUsize);
@@ -411,7 +420,39 @@ mod tests {
         let mem =
             GuestMemoryMmap::from_ranges(&[(MPTABLE_START, compute_mp_size(cpus as u8))]).unwrap();
 
-        let result = setup_mptable(MPTABLE_START, &mem, cpus as u8);
+        let result = setup_mptable(MPTABLE_START, &mem, &apic_ids(cpus as u8));
         assert!(result.is_err());
     }
+
+    #[test]
+    fn cpu_entry_sparse_apic_ids() {
+        let apic_ids = [0, 2, 8, 10];
+        let mem = GuestMemoryMmap::from_ranges(&[(MPTABLE_START, compute_mp_size(4))]).unwrap();
+
+        setup_mptable(MPTABLE_START, &mem, &apic_ids).unwrap();
+
+        let mpf_intel: MpfIntelWrapper = mem.read_obj(MPTABLE_START).unwrap();
+        let mpc_offset = GuestAddress(mpf_intel.0.physptr as GuestUsize);
+        let mut entry_offset = mpc_offset
+            .checked_add(mem::size_of::<MpcTableWrapper>() as GuestUsize)
+            .unwrap();
+        let mut entry_apic_ids = Vec::new();
+        for _ in 0..apic_ids.len() {
+            let mpc_cpu: MpcCpuWrapper = mem.read_obj(entry_offset).unwrap();
+            entry_apic_ids.push(mpc_cpu.0.apicid);
+            entry_offset = entry_offset
+                .checked_add(mem::size_of::<MpcCpuWrapper>() as GuestUsize)
+                .unwrap();
+        }
+        assert_eq!(entry_apic_ids, apic_ids);
+
+        // The IOAPIC entry follows the bus entry
+        entry_offset = entry_offset
+            .checked_add(mem::size_of::<MpcBusWrapper>() as GuestUsize)
+            .unwrap();
+        let mpc_ioapic: MpcIoapicWrapper = mem.read_obj(entry_offset).unwrap();
+        assert_eq!(mpc_ioapic.0.apicid, 12);
+
+        assert!(setup_mptable(MPTABLE_START, &mem, &[0, 254]).is_err());
+    }
 }
@@ -0,0 +1,665 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Multiboot2 kernel loading and boot information structure, as described by
+//! <https://www.gnu.org/software/grub/manual/multiboot2/multiboot.html>
+
+use crate::layout::{EBDA_START, HIGH_RAM_START, MULTIBOOT2_INFO_START};
+use crate::{GuestMemoryMmap, InitramfsConfig};
+use linux_loader::loader::elf::start_info::hvm_memmap_table_entry;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::result;
+use vm_memory::{Bytes, GuestAddress, GuestMemoryError};
+
+/// Value found in %eax when the kernel is entered
+pub const BOOTLOADER_MAGIC: u32 = 0x36d7_6289;
+
+const HEADER_MAGIC: u32 = 0xe852_50d6;
+// The header must be contained in the first 32KiB of the image
+const HEADER_SEARCH_SIZE: usize = 32768;
+const HEADER_ALIGN: usize = 8;
+const ARCHITECTURE_I386: u32 = 0;
+
+// Header tags
+const HEADER_TAG_END: u16 = 0;
+const HEADER_TAG_INFORMATION_REQUEST: u16 = 1;
+const HEADER_TAG_ADDRESS: u16 = 2;
+const HEADER_TAG_ENTRY_ADDRESS: u16 = 3;
+const HEADER_TAG_CONSOLE_FLAGS: u16 = 4;
+const HEADER_TAG_MODULE_ALIGN: u16 = 6;
+const HEADER_TAG_RELOCATABLE: u16 = 10;
+const HEADER_TAG_OPTIONAL: u16 = 1;
+
+// Boot information tags
+const TAG_END: u32 = 0;
+const TAG_CMDLINE: u32 = 1;
+const TAG_BOOT_LOADER_NAME: u32 = 2;
+const TAG_MODULE: u32 = 3;
+const TAG_BASIC_MEMINFO: u32 = 4;
+const TAG_MMAP: u32 = 6;
+const TAG_ACPI_OLD: u32 = 14;
+const TAG_ACPI_NEW: u32 = 15;
+const SUPPORTED_TAGS: &[u32] = &[
+    TAG_CMDLINE,
+    TAG_BOOT_LOADER_NAME,
+    TAG_MODULE,
+    TAG_BASIC_MEMINFO,
+    TAG_MMAP,
+    TAG_ACPI_OLD,
+    TAG_ACPI_NEW,
+];
+
+const BOOT_LOADER_NAME: &str = "cloud-hypervisor";
+const RSDP_V1_SIZE: usize = 20;
+const RSDP_V2_SIZE: usize = 36;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const ELFCLASS32: u8 = 1;
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const PT_LOAD: u32 = 1;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Error reading the kernel image
+    ReadKernel(io::Error),
+    /// Header tag required by the kernel is not supported
+    UnsupportedHeaderTag(u16),
+    /// Boot information required by the kernel is not supported
+    UnsupportedInformationRequest(u32),
+    /// Invalid header tag
+    InvalidHeaderTag,
+    /// Invalid load addresses from the address tag
+    InvalidAddressTag,
+    /// Invalid ELF image, while no address tag is provided
+    InvalidElf,
+    /// Could not find the kernel entry point
+    MissingEntryAddress,
+    /// Error loading the kernel in guest memory
+    LoadKernel(GuestMemoryError),
+    /// Error reading the command line or the RSDP from guest memory
+    ReadGuestMemory(GuestMemoryError),
+    /// The initramfs is located above 4GiB
+    InitramfsAddress,
+    /// The boot information structure doesn't fit in the low memory
+    BootInformationTooLarge,
+    /// Error writing the boot information structure to guest memory
+    WriteBootInformation(GuestMemoryError),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+fn u16_at(buf: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        buf.get(offset..offset + 2)?.try_into().unwrap(),
+    ))
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        buf.get(offset..offset + 4)?.try_into().unwrap(),
+    ))
+}
+
+fn u64_at(buf: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        buf.get(offset..offset + 8)?.try_into().unwrap(),
+    ))
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct AddressTag {
+    header_addr: u32,
+    load_addr: u32,
+    load_end_addr: u32,
+    bss_end_addr: u32,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Header {
+    // Offset of the header in the image
+    offset: usize,
+    address: Option<AddressTag>,
+    entry_addr: Option<u32>,
+}
+
+// Look for a valid Multiboot2 header in the first bytes of the image
+fn find_header(image: &[u8]) -> Result<Option<Header>> {
+    for offset in (0..image.len()).step_by(HEADER_ALIGN) {
+        if u32_at(image, offset) != Some(HEADER_MAGIC) {
+            continue;
+        }
+        let architecture = u32_at(image, offset + 4);
+        let length = u32_at(image, offset + 8);
+        let checksum = u32_at(image, offset + 12);
+        let (architecture, length, checksum) = match (architecture, length, checksum) {
+            (Some(a), Some(l), Some(c)) => (a, l, c),
+            _ => return Ok(None),
+        };
+        if HEADER_MAGIC
+            .wrapping_add(architecture)
+            .wrapping_add(length)
+            .wrapping_add(checksum)
+            != 0
+            || architecture != ARCHITECTURE_I386
+        {
+            continue;
+        }
+
+        let end = offset + length as usize;
+        let tags = image.get(offset + 16..end).ok_or(Error::InvalidHeaderTag)?;
+        return parse_header_tags(tags, offset).map(Some);
+    }
+
+    Ok(None)
+}
+
+fn parse_header_tags(tags: &[u8], offset: usize) -> Result<Header> {
+    let mut header = Header {
+        offset,
+        ..Default::default()
+    };
+
+    let mut pos = 0;
+    loop {
+        let tag_type = u16_at(tags, pos).ok_or(Error::InvalidHeaderTag)?;
+        let flags = u16_at(tags, pos + 2).ok_or(Error::InvalidHeaderTag)?;
+        let size = u32_at(tags, pos + 4).ok_or(Error::InvalidHeaderTag)? as usize;
+        if size < 8 {
+            return Err(Error::InvalidHeaderTag);
+        }
+        let tag = tags.get(pos..pos + size).ok_or(Error::InvalidHeaderTag)?;
+        let optional = flags & HEADER_TAG_OPTIONAL != 0;
+
+        match tag_type {
+            HEADER_TAG_END => break,
+            HEADER_TAG_INFORMATION_REQUEST => {
+                for request in tag[8..].chunks_exact(4) {
+                    let request = u32::from_le_bytes(request.try_into().unwrap());
+                    if !optional && !SUPPORTED_TAGS.contains(&request) {
+                        return Err(Error::UnsupportedInformationRequest(request));
+                    }
+                }
+            }
+            HEADER_TAG_ADDRESS => {
+                header.address = Some(AddressTag {
+                    header_addr: u32_at(tag, 8).ok_or(Error::InvalidHeaderTag)?,
+                    load_addr: u32_at(tag, 12).ok_or(Error::InvalidHeaderTag)?,
+                    load_end_addr: u32_at(tag, 16).ok_or(Error::InvalidHeaderTag)?,
+                    bss_end_addr: u32_at(tag, 20).ok_or(Error::InvalidHeaderTag)?,
+                });
+            }
+            HEADER_TAG_ENTRY_ADDRESS => {
+                header.entry_addr = Some(u32_at(tag, 8).ok_or(Error::InvalidHeaderTag)?);
+            }
+            // Serial console only, modules are page aligned and the kernel
+            // is always loaded at its preferred address.
+            HEADER_TAG_CONSOLE_FLAGS | HEADER_TAG_MODULE_ALIGN | HEADER_TAG_RELOCATABLE => {}
+            t if !optional => return Err(Error::UnsupportedHeaderTag(t)),
+            _ => {}
+        }
+
+        pos += (size + HEADER_ALIGN - 1) & !(HEADER_ALIGN - 1);
+    }
+
+    Ok(header)
+}
+
+// Load the image as described by the address tag
+fn load_binary<F: Read + Seek>(
+    guest_mem: &GuestMemoryMmap,
+    kernel: &mut F,
+    header: &Header,
+    address: &AddressTag,
+) -> Result<()> {
+    // A load address of -1 means the text segment starts with the image
+    let (load_offset, load_addr) = if address.load_addr == u32::MAX {
+        let load_addr = (address.header_addr as usize)
+            .checked_sub(header.offset)
+            .ok_or(Error::InvalidAddressTag)?;
+        (0, load_addr as u64)
+    } else {
+        let load_offset = header
+            .offset
+            .checked_sub(address.header_addr.wrapping_sub(address.load_addr) as usize)
+            .ok_or(Error::InvalidAddressTag)?;
+        (load_offset as u64, u64::from(address.load_addr))
+    };
+
+    let kernel_size = kernel.seek(SeekFrom::End(0)).map_err(Error::ReadKernel)?;
+    let load_size = if address.load_end_addr == 0 {
+        kernel_size
+            .checked_sub(load_offset)
+            .ok_or(Error::InvalidAddressTag)?
+    } else {
+        u64::from(address.load_end_addr)
+            .checked_sub(load_addr)
+            .ok_or(Error::InvalidAddressTag)?
+    };
+    if load_addr < HIGH_RAM_START.0 || load_offset + load_size > kernel_size {
+        return Err(Error::InvalidAddressTag);
+    }
+
+    kernel
+        .seek(SeekFrom::Start(load_offset))
+        .map_err(Error::ReadKernel)?;
+    guest_mem
+        .read_exact_from(GuestAddress(load_addr), kernel, load_size as usize)
+        .map_err(Error::LoadKernel)?;
+
+    if address.bss_end_addr != 0 {
+        let bss_start = load_addr + load_size;
+        let bss_size = u64::from(address.bss_end_addr)
+            .checked_sub(bss_start)
+            .ok_or(Error::InvalidAddressTag)?;
+        guest_mem
+            .write_slice(&vec![0; bss_size as usize], GuestAddress(bss_start))
+            .map_err(Error::LoadKernel)?;
+    }
+
+    Ok(())
+}
+
+// Load the segments of an ELF32 or ELF64 image at their physical address,
+// returning the ELF entry point.
+fn load_elf<F: Read + Seek>(guest_mem: &GuestMemoryMmap, kernel: &mut F) -> Result<u64> {
+    let mut ehdr = [0u8; 64];
+    kernel.rewind().map_err(Error::ReadKernel)?;
+    kernel.read_exact(&mut ehdr).map_err(Error::ReadKernel)?;
+    if &ehdr[0..4] != ELF_MAGIC || ehdr[5] != ELFDATA2LSB {
+        return Err(Error::InvalidElf);
+    }
+
+    let is_64bit = match ehdr[4] {
+        ELFCLASS32 => false,
+        ELFCLASS64 => true,
+        _ => return Err(Error::InvalidElf),
+    };
+    let (entry, phoff, phentsize, phnum) = if is_64bit {
+        (
+            u64_at(&ehdr, 24).unwrap(),
+            u64_at(&ehdr, 32).unwrap(),
+            u16_at(&ehdr, 54).unwrap(),
+            u16_at(&ehdr, 56).unwrap(),
+        )
+    } else {
+        (
+            u64::from(u32_at(&ehdr, 24).unwrap()),
+            u64::from(u32_at(&ehdr, 28).unwrap()),
+            u16_at(&ehdr, 42).unwrap(),
+            u16_at(&ehdr, 44).unwrap(),
+        )
+    };
+    let min_phentsize = if is_64bit { 56 } else { 32 };
+    if (phentsize as usize) < min_phentsize {
+        return Err(Error::InvalidElf);
+    }
+
+    let mut phdrs = vec![0u8; phentsize as usize * phnum as usize];
+    kernel
+        .seek(SeekFrom::Start(phoff))
+        .map_err(Error::ReadKernel)?;
+    kernel.read_exact(&mut phdrs).map_err(Error::ReadKernel)?;
+
+    for phdr in phdrs.chunks_exact(phentsize as usize) {
+        if u32_at(phdr, 0).unwrap() != PT_LOAD {
+            continue;
+        }
+        let (offset, paddr, filesz, memsz) = if is_64bit {
+            (
+                u64_at(phdr, 8).unwrap(),
+                u64_at(phdr, 24).unwrap(),
+                u64_at(phdr, 32).unwrap(),
+                u64_at(phdr, 40).unwrap(),
+            )
+        } else {
+            (
+                u64::from(u32_at(phdr, 4).unwrap()),
+                u64::from(u32_at(phdr, 12).unwrap()),
+                u64::from(u32_at(phdr, 16).unwrap()),
+                u64::from(u32_at(phdr, 20).unwrap()),
+            )
+        };
+        if paddr < HIGH_RAM_START.0 || filesz > memsz {
+            return Err(Error::InvalidElf);
+        }
+
+        kernel
+            .seek(SeekFrom::Start(offset))
+            .map_err(Error::ReadKernel)?;
+        guest_mem
+            .read_exact_from(GuestAddress(paddr), kernel, filesz as usize)
+            .map_err(Error::LoadKernel)?;
+        guest_mem
+            .write_slice(
+                &vec![0; (memsz - filesz) as usize],
+                GuestAddress(paddr + filesz),
+            )
+            .map_err(Error::LoadKernel)?;
+    }
+
+    Ok(entry)
+}
+
+/// Load a Multiboot2 kernel in guest memory, returning its entry point.
+/// Returns `None` if the image doesn't carry a Multiboot2 header.
+pub fn load<F: Read + Seek>(
+    guest_mem: &GuestMemoryMmap,
+    kernel: &mut F,
+) -> Result<Option<GuestAddress>> {
+    let mut image = Vec::with_capacity(HEADER_SEARCH_SIZE);
+    kernel.rewind().map_err(Error::ReadKernel)?;
+    kernel
+        .by_ref()
+        .take(HEADER_SEARCH_SIZE as u64)
+        .read_to_end(&mut image)
+        .map_err(Error::ReadKernel)?;
+
+    let header = match find_header(&image)? {
+        Some(header) => header,
+        None => {
+            kernel.rewind().map_err(Error::ReadKernel)?;
+            return Ok(None);
+        }
+    };
+
+    let entry_addr = if let Some(address) = &header.address {
+        load_binary(guest_mem, kernel, &header, address)?;
+        header.entry_addr.map(u64::from)
+    } else {
+        let elf_entry = load_elf(guest_mem, kernel)?;
+        Some(header.entry_addr.map_or(elf_entry, u64::from))
+    };
+
+    entry_addr
+        .map(|entry_addr| Some(GuestAddress(entry_addr)))
+        .ok_or(Error::MissingEntryAddress)
+}
+
+// Boot information structure, made of a list of 8 bytes aligned tags
+struct BootInformation(Vec<u8>);
+
+impl BootInformation {
+    fn new() -> Self {
+        // Total size and reserved fields, the former being set when done
+        BootInformation(vec![0; 8])
+    }
+
+    fn add_tag(&mut self, tag_type: u32, content: &[u8]) {
+        let size = 8 + content.len() as u32;
+        self.0.extend_from_slice(&tag_type.to_le_bytes());
+        self.0.extend_from_slice(&size.to_le_bytes());
+        self.0.extend_from_slice(content);
+        self.0.resize((self.0.len() + 7) & !7, 0);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.add_tag(TAG_END, &[]);
+        let total_size = self.0.len() as u32;
+        self.0[0..4].copy_from_slice(&total_size.to_le_bytes());
+        self.0
+    }
+}
+
+/// Build the boot information structure the kernel finds in %ebx, describing
+/// the command line, the initramfs as a module, the memory map and the ACPI
+/// tables.
+pub fn setup_boot_information(
+    guest_mem: &GuestMemoryMmap,
+    cmdline_addr: GuestAddress,
+    initramfs: &Option<InitramfsConfig>,
+    rsdp_addr: Option<GuestAddress>,
+    memmap: &[hvm_memmap_table_entry],
+) -> Result<()> {
+    let mut info = BootInformation::new();
+
+    // The command line has already been written to guest memory
+    let mut cmdline = vec![0u8; crate::layout::CMDLINE_MAX_SIZE];
+    guest_mem
+        .read_slice(&mut cmdline, cmdline_addr)
+        .map_err(Error::ReadGuestMemory)?;
+    let cmdline_len = cmdline
+        .iter()
+        .position(|c| *c == 0)
+        .unwrap_or(cmdline.len() - 1);
+    cmdline.truncate(cmdline_len);
+    cmdline.push(0);
+    info.add_tag(TAG_CMDLINE, &cmdline);
+
+    info.add_tag(
+        TAG_BOOT_LOADER_NAME,
+        format!("{BOOT_LOADER_NAME}\0").as_bytes(),
+    );
+
+    if let Some(initramfs) = initramfs {
+        let start = u32::try_from(initramfs.address.0).map_err(|_| Error::InitramfsAddress)?;
+        let end = u32::try_from(initramfs.address.0 + initramfs.size as u64)
+            .map_err(|_| Error::InitramfsAddress)?;
+        let mut module = Vec::new();
+        module.extend_from_slice(&start.to_le_bytes());
+        module.extend_from_slice(&end.to_le_bytes());
+        module.push(0);
+        info.add_tag(TAG_MODULE, &module);
+    }
+
+    // Amount of lower and upper memory in KiB, the latter being the first
+    // contiguous RAM range starting at 1MiB.
+    let mem_upper = memmap
+        .iter()
+        .find(|e| e.addr == HIGH_RAM_START.0)
+        .map_or(0, |e| e.size >> 10) as u32;
+    let mut meminfo = Vec::new();
+    meminfo.extend_from_slice(&((EBDA_START.0 >> 10) as u32).to_le_bytes());
+    meminfo.extend_from_slice(&mem_upper.to_le_bytes());
+    info.add_tag(TAG_BASIC_MEMINFO, &meminfo);
+
+    // Memory map entries share the layout of the E820 ones
+    let mut mmap = Vec::new();
+    mmap.extend_from_slice(&24u32.to_le_bytes());
+    mmap.extend_from_slice(&0u32.to_le_bytes());
+    for entry in memmap {
+        mmap.extend_from_slice(&entry.addr.to_le_bytes());
+        mmap.extend_from_slice(&entry.size.to_le_bytes());
+        mmap.extend_from_slice(&entry.type_.to_le_bytes());
+        mmap.extend_from_slice(&0u32.to_le_bytes());
+    }
+    info.add_tag(TAG_MMAP, &mmap);
+
+    if let Some(rsdp_addr) = rsdp_addr {
+        let mut rsdp = [0u8; RSDP_V2_SIZE];
+        guest_mem
+            .read_slice(&mut rsdp, rsdp_addr)
+            .map_err(Error::ReadGuestMemory)?;
+        info.add_tag(TAG_ACPI_OLD, &rsdp[..RSDP_V1_SIZE]);
+        info.add_tag(TAG_ACPI_NEW, &rsdp);
+    }
+
+    let info = info.finish();
+    if MULTIBOOT2_INFO_START.0 + info.len() as u64 > EBDA_START.0 {
+        return Err(Error::BootInformationTooLarge);
+    }
+    guest_mem
+        .write_slice(&info, MULTIBOOT2_INFO_START)
+        .map_err(Error::WriteBootInformation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vm_memory::Address;
+
+    fn header(tags: &[u8]) -> Vec<u8> {
+        let length = 16 + tags.len() as u32;
+        let checksum = 0u32
+            .wrapping_sub(HEADER_MAGIC)
+            .wrapping_sub(ARCHITECTURE_I386)
+            .wrapping_sub(length);
+        let mut header = Vec::new();
+        header.extend_from_slice(&HEADER_MAGIC.to_le_bytes());
+        header.extend_from_slice(&ARCHITECTURE_I386.to_le_bytes());
+        header.extend_from_slice(&length.to_le_bytes());
+        header.extend_from_slice(&checksum.to_le_bytes());
+        header.extend_from_slice(tags);
+        header
+    }
+
+    fn tag(tag_type: u16, flags: u16, content: &[u32]) -> Vec<u8> {
+        let mut tag = Vec::new();
+        tag.extend_from_slice(&tag_type.to_le_bytes());
+        tag.extend_from_slice(&flags.to_le_bytes());
+        tag.extend_from_slice(&(8 + 4 * content.len() as u32).to_le_bytes());
+        for value in content {
+            tag.extend_from_slice(&value.to_le_bytes());
+        }
+        tag.resize((tag.len() + 7) & !7, 0);
+        tag
+    }
+
+    #[test]
+    fn test_find_header() {
+        let tags = [
+            tag(HEADER_TAG_ADDRESS, 0, &[0x10_0040, 0x10_0000, 0, 0x20_0000]),
+            tag(HEADER_TAG_ENTRY_ADDRESS, 0, &[0x10_0100]),
+            tag(HEADER_TAG_END, 0, &[]),
+        ]
+        .concat();
+        let mut image = vec![0u8; 0x40];
+        image.extend_from_slice(&header(&tags));
+
+        assert_eq!(
+            find_header(&image).unwrap(),
+            Some(Header {
+                offset: 0x40,
+                address: Some(AddressTag {
+                    header_addr: 0x10_0040,
+                    load_addr: 0x10_0000,
+                    load_end_addr: 0,
+                    bss_end_addr: 0x20_0000,
+                }),
+                entry_addr: Some(0x10_0100),
+            })
+        );
+
+        // Misaligned header
+        let mut image = vec![0u8; 4];
+        image.extend_from_slice(&header(&tags));
+        assert_eq!(find_header(&image).unwrap(), None);
+
+        // Invalid checksum
+        let mut image = header(&tags);
+        image[12] ^= 1;
+        assert_eq!(find_header(&image).unwrap(), None);
+    }
+
+    #[test]
+    fn test_find_header_unsupported_tags() {
+        // Framebuffer tag, which can only be ignored when optional
+        let framebuffer = tag(5, HEADER_TAG_OPTIONAL, &[0, 0, 32]);
+        let tags = [framebuffer, tag(HEADER_TAG_END, 0, &[])].concat();
+        assert!(find_header(&header(&tags)).unwrap().is_some());
+        let framebuffer = tag(5, 0, &[0, 0, 32]);
+        let tags = [framebuffer, tag(HEADER_TAG_END, 0, &[])].concat();
+        assert!(matches!(
+            find_header(&header(&tags)),
+            Err(Error::UnsupportedHeaderTag(5))
+        ));
+
+        // EFI memory map information request
+        let request = tag(HEADER_TAG_INFORMATION_REQUEST, 0, &[TAG_MMAP, 17]);
+        let tags = [request, tag(HEADER_TAG_END, 0, &[])].concat();
+        assert!(matches!(
+            find_header(&header(&tags)),
+            Err(Error::UnsupportedInformationRequest(17))
+        ));
+    }
+
+    #[test]
+    fn test_load_binary() {
+        let gm = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 4 << 20)]).unwrap();
+        let tags = [
+            tag(HEADER_TAG_ADDRESS, 0, &[0x10_0000, 0x10_0000, 0, 0x10_2000]),
+            tag(HEADER_TAG_ENTRY_ADDRESS, 0, &[0x10_0080]),
+            tag(HEADER_TAG_END, 0, &[]),
+        ]
+        .concat();
+        let mut image = header(&tags);
+        image.resize(0x1000, 0xcc);
+        gm.write_slice(&[0xffu8; 0x1000], GuestAddress(0x10_1000))
+            .unwrap();
+
+        let entry = load(&gm, &mut io::Cursor::new(&image)).unwrap();
+        assert_eq!(entry, Some(GuestAddress(0x10_0080)));
+        assert_eq!(gm.read_obj::<u8>(GuestAddress(0x10_0fff)).unwrap(), 0xcc);
+        // The BSS is cleared
+        assert_eq!(gm.read_obj::<u8>(GuestAddress(0x10_1000)).unwrap(), 0);
+        assert_eq!(gm.read_obj::<u8>(GuestAddress(0x10_1fff)).unwrap(), 0);
+
+        // Not a Multiboot2 image
+        let image = vec![0u8; 0x1000];
+        assert_eq!(load(&gm, &mut io::Cursor::new(&image)).unwrap(), None);
+    }
+
+    #[test]
+    fn test_setup_boot_information() {
+        let gm = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 4 << 20)]).unwrap();
+        gm.write_slice(b"console=ttyS0\0", GuestAddress(0x2_0000))
+            .unwrap();
+        let memmap = [
+            hvm_memmap_table_entry {
+                addr: 0,
+                size: EBDA_START.0,
+                type_: 1,
+                ..Default::default()
+            },
+            hvm_memmap_table_entry {
+                addr: HIGH_RAM_START.0,
+                size: (4 << 20) - HIGH_RAM_START.0,
+                type_: 1,
+                ..Default::default()
+            },
+        ];
+        let initramfs = Some(InitramfsConfig {
+            address: GuestAddress(0x30_0000),
+            size: 0x1000,
+        });
+        setup_boot_information(&gm, GuestAddress(0x2_0000), &initramfs, None, &memmap).unwrap();
+
+        let mut tags = Vec::new();
+        let total_size: u32 = gm.read_obj(MULTIBOOT2_INFO_START).unwrap();
+        let mut addr = MULTIBOOT2_INFO_START.unchecked_add(8);
+        loop {
+            let tag_type: u32 = gm.read_obj(addr).unwrap();
+            let size: u32 = gm.read_obj(addr.unchecked_add(4)).unwrap();
+            tags.push((tag_type, addr));
+            addr = addr.unchecked_add((u64::from(size) + 7) & !7);
+            if tag_type == TAG_END {
+                break;
+            }
+        }
+        assert_eq!(
+            addr.unchecked_offset_from(MULTIBOOT2_INFO_START),
+            u64::from(total_size)
+        );
+        assert_eq!(
+            tags.iter().map(|t| t.0).collect::<Vec<u32>>(),
+            vec![
+                TAG_CMDLINE,
+                TAG_BOOT_LOADER_NAME,
+                TAG_MODULE,
+                TAG_BASIC_MEMINFO,
+                TAG_MMAP,
+                TAG_END
+            ]
+        );
+
+        let mut cmdline = [0u8; 14];
+        gm.read_slice(&mut cmdline, tags[0].1.unchecked_add(8))
+            .unwrap();
+        assert_eq!(&cmdline, b"console=ttyS0\0");
+        let module_end: u32 = gm.read_obj(tags[2].1.unchecked_add(12)).unwrap();
+        assert_eq!(module_end, 0x30_1000);
+        let mem_upper: u32 = gm.read_obj(tags[3].1.unchecked_add(12)).unwrap();
+        assert_eq!(mem_upper, 3072);
+    }
+}
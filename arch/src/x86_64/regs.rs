@@ -6,11 +6,15 @@
 // Portions Copyright 2017 The Chromium OS Authors. All rights reserved.
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE-BSD-3-Clause file.
-use crate::layout::{BOOT_GDT_START, BOOT_IDT_START, PVH_INFO_START};
+use super::{multiboot2, BootProtocol};
+use crate::layout::{
+    BOOT_GDT_START, BOOT_IDT_START, BOOT_STACK_POINTER, MULTIBOOT2_INFO_START, PDE_START,
+    PDPTE_START, PML4_START, PML5_START, PVH_INFO_START, ZERO_PAGE_START,
+};
 use crate::GuestMemoryMmap;
 use hypervisor::arch::x86::gdt::{gdt_entry, segment_from_gdt};
-use hypervisor::arch::x86::regs::CR0_PE;
-use hypervisor::arch::x86::{FpuState, SpecialRegisters, StandardRegisters};
+use hypervisor::arch::x86::regs::{CR0_PE, CR0_PG, CR4_LA57, CR4_PAE, EFER_LMA, EFER_LME};
+use hypervisor::arch::x86::{msr_index, FpuState, MsrEntry, SpecialRegisters, StandardRegisters};
 use std::sync::Arc;
 use std::{mem, result};
 use vm_memory::{Address, Bytes, GuestMemory, GuestMemoryError};
@@ -19,6 +23,8 @@ use vm_memory::{Address, Bytes, GuestMemory, GuestMemoryError};
 pub enum Error {
     /// Failed to get SREGs for this CPU.
     GetStatusRegisters(hypervisor::HypervisorCpuError),
+    /// Failed to get base registers for this CPU.
+    GetBaseRegisters(hypervisor::HypervisorCpuError),
     /// Failed to set base registers for this CPU.
     SetBaseRegisters(hypervisor::HypervisorCpuError),
     /// Failed to configure the FPU.
@@ -65,8 +71,16 @@ pub fn setup_fpu(vcpu: &Arc<dyn hypervisor::Vcpu>) -> Result<()> {
 /// # Arguments
 ///
 /// * `vcpu` - Structure for the VCPU that holds the VCPU's fd.
-pub fn setup_msrs(vcpu: &Arc<dyn hypervisor::Vcpu>) -> Result<()> {
-    vcpu.set_msrs(&vcpu.boot_msr_entries())
+/// * `microcode_revision` - Value of IA32_BIOS_SIGN_ID reported to the guest, if any.
+pub fn setup_msrs(vcpu: &Arc<dyn hypervisor::Vcpu>, microcode_revision: Option<u64>) -> Result<()> {
+    let mut msrs = vcpu.boot_msr_entries();
+    if let Some(microcode_revision) = microcode_revision {
+        msrs.push(MsrEntry {
+            index: msr_index::MSR_IA32_UCODE_REV,
+            data: microcode_revision,
+        });
+    }
+    vcpu.set_msrs(&msrs)
         .map_err(Error::SetModelSpecificRegisters)?;
 
     Ok(())
@@ -78,25 +92,82 @@ pub fn setup_msrs(vcpu: &Arc<dyn hypervisor::Vcpu>) -> Result<()> {
 ///
 /// * `vcpu` - Structure for the VCPU that holds the VCPU's fd.
 /// * `boot_ip` - Starting instruction pointer.
-pub fn setup_regs(vcpu: &Arc<dyn hypervisor::Vcpu>, boot_ip: u64) -> Result<()> {
-    let regs = StandardRegisters {
-        rflags: 0x0000000000000002u64,
-        rbx: PVH_INFO_START.raw_value(),
-        rip: boot_ip,
-        ..Default::default()
+/// * `boot_prot` - Boot protocol the guest is started with.
+pub fn setup_regs(
+    vcpu: &Arc<dyn hypervisor::Vcpu>,
+    boot_ip: u64,
+    boot_prot: BootProtocol,
+) -> Result<()> {
+    let regs = match boot_prot {
+        // Configure regs as required by PVH boot protocol.
+        BootProtocol::PvhBoot => StandardRegisters {
+            rflags: 0x0000000000000002u64,
+            rbx: PVH_INFO_START.raw_value(),
+            rip: boot_ip,
+            ..Default::default()
+        },
+        // Configure regs as required by Multiboot2 boot protocol.
+        BootProtocol::Multiboot2Boot => StandardRegisters {
+            rflags: 0x0000000000000002u64,
+            rax: u64::from(multiboot2::BOOTLOADER_MAGIC),
+            rbx: MULTIBOOT2_INFO_START.raw_value(),
+            rip: boot_ip,
+            ..Default::default()
+        },
+        // Configure regs as required by Linux 64bit boot protocol.
+        BootProtocol::LinuxBoot => StandardRegisters {
+            rflags: 0x0000000000000002u64,
+            rip: boot_ip,
+            rsp: BOOT_STACK_POINTER.raw_value(),
+            rbp: BOOT_STACK_POINTER.raw_value(),
+            rsi: ZERO_PAGE_START.raw_value(),
+            ..Default::default()
+        },
     };
     vcpu.set_regs(&regs).map_err(Error::SetBaseRegisters)
 }
 
+/// Points an application processor of a SEV-ES guest to the reset vector
+/// advertised by the firmware, as it can't be started through INIT-SIPI.
+///
+/// # Arguments
+///
+/// * `vcpu` - Structure for the VCPU that holds the VCPU's fd.
+/// * `reset_vector` - Code segment base and instruction pointer, respectively
+///   stored in the upper and lower 16 bits.
+#[cfg(feature = "sev_snp")]
+pub fn setup_sev_es_ap_regs(vcpu: &Arc<dyn hypervisor::Vcpu>, reset_vector: u32) -> Result<()> {
+    let mut sregs: SpecialRegisters = vcpu.get_sregs().map_err(Error::GetStatusRegisters)?;
+    sregs.cs.selector = 0xf000;
+    sregs.cs.base = u64::from(reset_vector & 0xffff_0000);
+    vcpu.set_sregs(&sregs).map_err(Error::SetStatusRegisters)?;
+
+    let mut regs = vcpu.get_regs().map_err(Error::GetBaseRegisters)?;
+    regs.rip = u64::from(reset_vector & 0xffff);
+    vcpu.set_regs(&regs).map_err(Error::SetBaseRegisters)
+}
+
 /// Configures the segment registers and system page tables for a given CPU.
 ///
 /// # Arguments
 ///
 /// * `mem` - The memory that will be passed to the guest.
 /// * `vcpu` - Structure for the VCPU that holds the VCPU's fd.
-pub fn setup_sregs(mem: &GuestMemoryMmap, vcpu: &Arc<dyn hypervisor::Vcpu>) -> Result<()> {
+/// * `boot_prot` - Boot protocol the guest is started with.
+/// * `la57` - Whether the guest is started with 5-level paging.
+pub fn setup_sregs(
+    mem: &GuestMemoryMmap,
+    vcpu: &Arc<dyn hypervisor::Vcpu>,
+    boot_prot: BootProtocol,
+    la57: bool,
+) -> Result<()> {
     let mut sregs: SpecialRegisters = vcpu.get_sregs().map_err(Error::GetStatusRegisters)?;
-    configure_segments_and_sregs(mem, &mut sregs)?;
+    configure_segments_and_sregs(mem, &mut sregs, boot_prot)?;
+
+    if boot_prot == BootProtocol::LinuxBoot {
+        setup_page_tables(mem, &mut sregs, la57)?;
+    }
+
     vcpu.set_sregs(&sregs).map_err(Error::SetStatusRegisters)
 }
 
@@ -123,15 +194,24 @@ fn write_idt_value(val: u64, guest_mem: &GuestMemoryMmap) -> Result<()> {
 pub fn configure_segments_and_sregs(
     mem: &GuestMemoryMmap,
     sregs: &mut SpecialRegisters,
+    boot_prot: BootProtocol,
 ) -> Result<()> {
-    let gdt_table: [u64; BOOT_GDT_MAX] = {
-        // Configure GDT entries as specified by PVH boot protocol
-        [
+    let gdt_table: [u64; BOOT_GDT_MAX] = match boot_prot {
+        // Configure GDT entries as specified by PVH boot protocol, Multiboot2
+        // requiring the same flat 32-bit segments.
+        BootProtocol::PvhBoot | BootProtocol::Multiboot2Boot => [
             gdt_entry(0, 0, 0),               // NULL
             gdt_entry(0xc09b, 0, 0xffffffff), // CODE
             gdt_entry(0xc093, 0, 0xffffffff), // DATA
             gdt_entry(0x008b, 0, 0x67),       // TSS
-        ]
+        ],
+        // Configure GDT entries as specified by the Linux 64-bit boot protocol
+        BootProtocol::LinuxBoot => [
+            gdt_entry(0, 0, 0),            // NULL
+            gdt_entry(0xa09b, 0, 0xfffff), // CODE
+            gdt_entry(0xc093, 0, 0xfffff), // DATA
+            gdt_entry(0x808b, 0, 0xfffff), // TSS
+        ],
     };
 
     let code_seg = segment_from_gdt(gdt_table[1], 1);
@@ -158,6 +238,50 @@ pub fn configure_segments_and_sregs(
     sregs.cr0 = CR0_PE;
     sregs.cr4 = 0;
 
+    if boot_prot == BootProtocol::LinuxBoot {
+        // 64-bit protected mode
+        sregs.efer |= EFER_LME | EFER_LMA;
+    }
+
+    Ok(())
+}
+
+fn setup_page_tables(
+    mem: &GuestMemoryMmap,
+    sregs: &mut SpecialRegisters,
+    la57: bool,
+) -> Result<()> {
+    // Puts PML4 right after zero page but aligned to 4k.
+
+    // Entry covering VA [0..512GB)
+    mem.write_obj(PDPTE_START.raw_value() | 0x03, PML4_START)
+        .map_err(Error::WritePml4Address)?;
+
+    // Entry covering VA [0..1GB)
+    mem.write_obj(PDE_START.raw_value() | 0x03, PDPTE_START)
+        .map_err(Error::WritePdpteAddress)?;
+
+    // 512 2MB entries together covering VA [0..1GB). Note we are assuming
+    // CPU supports 2MB pages (/proc/cpuinfo has 'pse'). All modern CPUs do.
+    for i in 0..512 {
+        mem.write_obj((i << 21) + 0x83u64, PDE_START.unchecked_add(i * 8))
+            .map_err(Error::WritePdeAddress)?;
+    }
+
+    sregs.cr3 = PML4_START.raw_value();
+    sregs.cr4 |= CR4_PAE;
+    sregs.cr0 |= CR0_PG;
+
+    // The kernel keeps the paging mode it is started with, which must be
+    // 5-level for it to address more than 64TiB of memory.
+    if la57 {
+        // Entry covering VA [0..128PB)
+        mem.write_obj(PML4_START.raw_value() | 0x03, PML5_START)
+            .map_err(Error::WritePml5Address)?;
+        sregs.cr3 = PML5_START.raw_value();
+        sregs.cr4 |= CR4_LA57;
+    }
+
     Ok(())
 }
 
@@ -179,7 +303,7 @@ mod tests {
     fn segments_and_sregs() {
         let mut sregs: SpecialRegisters = Default::default();
         let gm = create_guest_mem();
-        configure_segments_and_sregs(&gm, &mut sregs).unwrap();
+        configure_segments_and_sregs(&gm, &mut sregs, BootProtocol::PvhBoot).unwrap();
         assert_eq!(0x0, read_u64(&gm, BOOT_GDT_START));
         assert_eq!(
             0xcf9b000000ffff,
@@ -208,5 +332,61 @@ mod tests {
         assert_eq!(0, sregs.tr.avl);
         assert_eq!(CR0_PE, sregs.cr0);
         assert_eq!(0, sregs.cr4);
+
+        let mut sregs: SpecialRegisters = Default::default();
+        configure_segments_and_sregs(&gm, &mut sregs, BootProtocol::LinuxBoot).unwrap();
+        assert_eq!(
+            0xaf9b000000ffff,
+            read_u64(&gm, BOOT_GDT_START.unchecked_add(8))
+        );
+        assert_eq!(
+            0xcf93000000ffff,
+            read_u64(&gm, BOOT_GDT_START.unchecked_add(16))
+        );
+        assert_eq!(
+            0x8f8b000000ffff,
+            read_u64(&gm, BOOT_GDT_START.unchecked_add(24))
+        );
+        assert_eq!(EFER_LME | EFER_LMA, sregs.efer);
+    }
+
+    #[test]
+    fn page_tables() {
+        let mut sregs: SpecialRegisters = Default::default();
+        let gm = create_guest_mem();
+        setup_page_tables(&gm, &mut sregs, false).unwrap();
+
+        assert_eq!(0xb003, read_u64(&gm, PML4_START));
+        assert_eq!(0xc003, read_u64(&gm, PDPTE_START));
+        for i in 0..512 {
+            assert_eq!(
+                (i << 21) + 0x83u64,
+                read_u64(&gm, PDE_START.unchecked_add(i * 8))
+            );
+        }
+
+        assert_eq!(PML4_START.raw_value(), sregs.cr3);
+        assert_eq!(CR4_PAE, sregs.cr4);
+        assert_eq!(CR0_PG, sregs.cr0);
+    }
+
+    #[test]
+    fn page_tables_la57() {
+        let mut sregs: SpecialRegisters = Default::default();
+        let gm = create_guest_mem();
+        setup_page_tables(&gm, &mut sregs, true).unwrap();
+
+        // The single PML5 entry points to the regular 4-level hierarchy.
+        assert_eq!(0xa003, read_u64(&gm, PML5_START));
+        for i in 1..512 {
+            assert_eq!(0, read_u64(&gm, PML5_START.unchecked_add(i * 8)));
+        }
+        assert_eq!(0xb003, read_u64(&gm, PML4_START));
+        assert_eq!(0xc003, read_u64(&gm, PDPTE_START));
+        assert_eq!(0x83, read_u64(&gm, PDE_START));
+
+        assert_eq!(PML5_START.raw_value(), sregs.cr3);
+        assert_eq!(CR4_PAE | CR4_LA57, sregs.cr4);
+        assert_eq!(CR0_PG, sregs.cr0);
     }
 }
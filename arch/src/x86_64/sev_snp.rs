@@ -0,0 +1,359 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! AMD SEV launch helpers: parsing of the SEV metadata and SEV-ES reset
+//! block exposed by OVMF through its table of GUIDs, and generation of the
+//! CPUID page validated by the AMD Secure Processor during the SEV-SNP
+//! launch sequence.
+
+use crate::GuestMemoryMmap;
+use hypervisor::arch::x86::CpuIdEntry;
+use std::io::{Read, Seek, SeekFrom};
+use std::str::FromStr;
+use thiserror::Error;
+use uuid::Uuid;
+use vm_memory::{Address, ByteValued, Bytes, GuestAddress, GuestMemoryError};
+
+#[derive(Error, Debug)]
+pub enum SevSnpError {
+    #[error("Failed read GUID table: {0}")]
+    ReadGuidTable(#[source] std::io::Error),
+    #[error("Failed read SEV metadata: {0}")]
+    ReadMetadata(#[source] std::io::Error),
+    #[error("SEV metadata GUID not found in the firmware")]
+    MetadataNotFound,
+    #[error("SEV-ES reset block GUID not found in the firmware")]
+    ResetBlockNotFound,
+    #[error("Invalid SEV metadata signature")]
+    InvalidMetadataSignature,
+    #[error("Invalid SEV metadata size")]
+    InvalidMetadataSize,
+    #[error("Invalid SEV metadata version")]
+    InvalidMetadataVersion,
+    #[error("Unknown SEV metadata section type: {0}")]
+    UnknownSectionType(u32),
+    #[error("Failed to create Uuid: {0}")]
+    UuidCreation(#[source] uuid::Error),
+    #[error("Too many CPUID entries for the SEV-SNP CPUID page: {0}")]
+    TooManyCpuidEntries(usize),
+    #[error("Failed to write the CPUID page to guest memory: {0}")]
+    GuestMemoryWriteCpuid(#[source] GuestMemoryError),
+}
+
+const TABLE_FOOTER_GUID: &str = "96b582de-1fb2-45f7-baea-a366c55a082d";
+const SEV_METADATA_OFFSET_GUID: &str = "dc886566-984a-4798-a75e-5585a7bf67cc";
+const SEV_ES_RESET_BLOCK_GUID: &str = "00f771de-1a7e-4fcb-890e-68c77e2fb44e";
+
+// Size of a GUID table entry holding a 32-bit value
+const OFFSET_ENTRY_SIZE: usize = 22;
+
+const SEV_METADATA_SIGNATURE: &[u8; 4] = b"ASEV";
+const SEV_METADATA_HEADER_SIZE: usize = 16;
+const SEV_METADATA_SECTION_SIZE: usize = 12;
+
+// OVMF_SNP_* section types
+const SEV_SECTION_SNP_SEC_MEM: u32 = 1;
+const SEV_SECTION_SNP_SECRETS: u32 = 2;
+const SEV_SECTION_CPUID: u32 = 3;
+
+// Maximum number of entries in the CPUID page, as defined by the
+// SEV-SNP firmware ABI specification
+const SNP_CPUID_COUNT_MAX: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SevSnpSectionType {
+    /// Memory used by the firmware before it can validate its own pages
+    SecMem,
+    /// Secrets page, populated by the AMD Secure Processor
+    Secrets,
+    /// CPUID page, validated by the AMD Secure Processor
+    Cpuid,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SevSnpSection {
+    pub address: u64,
+    pub size: u64,
+    pub r#type: SevSnpSectionType,
+}
+
+// Look for the 32-bit value associated with `guid` in the table of GUIDs
+// located right before the reset vector.
+fn guid_table_entry<R: Read + Seek>(file: &mut R, guid: &str) -> Result<Option<u32>, SevSnpError> {
+    file.seek(SeekFrom::End(-0x30))
+        .map_err(SevSnpError::ReadGuidTable)?;
+    let mut table_footer_guid: [u8; 16] = [0; 16];
+    file.read_exact(&mut table_footer_guid)
+        .map_err(SevSnpError::ReadGuidTable)?;
+    let uuid =
+        Uuid::from_slice_le(table_footer_guid.as_slice()).map_err(SevSnpError::UuidCreation)?;
+    let expected_uuid = Uuid::from_str(TABLE_FOOTER_GUID).map_err(SevSnpError::UuidCreation)?;
+    if uuid != expected_uuid {
+        return Ok(None);
+    }
+
+    // Retrieve the table size
+    file.seek(SeekFrom::End(-0x32))
+        .map_err(SevSnpError::ReadGuidTable)?;
+    let mut table_size: [u8; 2] = [0; 2];
+    file.read_exact(&mut table_size)
+        .map_err(SevSnpError::ReadGuidTable)?;
+    let table_size = u16::from_le_bytes(table_size) as usize;
+    if table_size < 18 {
+        return Ok(None);
+    }
+    let mut table: Vec<u8> = vec![0; table_size];
+
+    // Read the entire table
+    file.seek(SeekFrom::End(-(table_size as i64 + 0x20)))
+        .map_err(SevSnpError::ReadGuidTable)?;
+    file.read_exact(table.as_mut_slice())
+        .map_err(SevSnpError::ReadGuidTable)?;
+
+    let expected_uuid = Uuid::from_str(guid).map_err(SevSnpError::UuidCreation)?;
+
+    // Walk the table backward, starting after the footer GUID and the
+    // table length.
+    let mut offset = table_size - 18;
+    while offset >= 18 {
+        let entry_uuid =
+            Uuid::from_slice_le(&table[offset - 16..offset]).map_err(SevSnpError::UuidCreation)?;
+        let entry_size =
+            u16::from_le_bytes(table[offset - 18..offset - 16].try_into().unwrap()) as usize;
+
+        // Avoid going through an infinite loop or out of the table
+        if entry_size < 18 || entry_size > offset {
+            break;
+        }
+
+        offset -= entry_size;
+
+        if entry_uuid == expected_uuid && entry_size == OFFSET_ENTRY_SIZE {
+            return Ok(Some(u32::from_le_bytes(
+                table[offset..offset + 4].try_into().unwrap(),
+            )));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Retrieve the address SEV-ES application processors start from, as
+/// advertised by the reset block of an OVMF image built with SEV support.
+/// The low 16 bits hold the instruction pointer and the upper ones the code
+/// segment base.
+pub fn sev_es_reset_vector<R: Read + Seek>(file: &mut R) -> Result<u32, SevSnpError> {
+    guid_table_entry(file, SEV_ES_RESET_BLOCK_GUID)?.ok_or(SevSnpError::ResetBlockNotFound)
+}
+
+/// Parse the sections described by the SEV metadata of an OVMF image built
+/// with SEV-SNP support.
+pub fn parse_sev_snp_sections<R: Read + Seek>(
+    file: &mut R,
+) -> Result<Vec<SevSnpSection>, SevSnpError> {
+    let metadata_offset = guid_table_entry(file, SEV_METADATA_OFFSET_GUID)?
+        .ok_or(SevSnpError::MetadataNotFound)? as u64;
+
+    file.seek(SeekFrom::End(-(metadata_offset as i64)))
+        .map_err(SevSnpError::ReadMetadata)?;
+    let mut header = [0u8; SEV_METADATA_HEADER_SIZE];
+    file.read_exact(&mut header)
+        .map_err(SevSnpError::ReadMetadata)?;
+
+    if &header[0..4] != SEV_METADATA_SIGNATURE {
+        return Err(SevSnpError::InvalidMetadataSignature);
+    }
+
+    let length = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+    let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+    let num_sections = u32::from_le_bytes(header[12..16].try_into().unwrap()) as usize;
+
+    if length != SEV_METADATA_HEADER_SIZE + SEV_METADATA_SECTION_SIZE * num_sections {
+        return Err(SevSnpError::InvalidMetadataSize);
+    }
+
+    if version != 1 {
+        return Err(SevSnpError::InvalidMetadataVersion);
+    }
+
+    let mut sections = Vec::with_capacity(num_sections);
+    for _ in 0..num_sections {
+        let mut section = [0u8; SEV_METADATA_SECTION_SIZE];
+        file.read_exact(&mut section)
+            .map_err(SevSnpError::ReadMetadata)?;
+
+        let r#type = match u32::from_le_bytes(section[8..12].try_into().unwrap()) {
+            SEV_SECTION_SNP_SEC_MEM => SevSnpSectionType::SecMem,
+            SEV_SECTION_SNP_SECRETS => SevSnpSectionType::Secrets,
+            SEV_SECTION_CPUID => SevSnpSectionType::Cpuid,
+            t => return Err(SevSnpError::UnknownSectionType(t)),
+        };
+
+        sections.push(SevSnpSection {
+            address: u32::from_le_bytes(section[0..4].try_into().unwrap()) as u64,
+            size: u32::from_le_bytes(section[4..8].try_into().unwrap()) as u64,
+            r#type,
+        });
+    }
+
+    Ok(sections)
+}
+
+// SNP_CPUID_FUNCTION
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct SnpCpuidFunction {
+    eax_in: u32,
+    ecx_in: u32,
+    xcr0_in: u64,
+    xss_in: u64,
+    eax: u32,
+    ebx: u32,
+    ecx: u32,
+    edx: u32,
+    reserved: u64,
+}
+
+// SAFETY: data structure only contain a series of integers
+unsafe impl ByteValued for SnpCpuidFunction {}
+
+/// Write the CPUID page describing `cpuid` at `address`, so that it can be
+/// validated by the AMD Secure Processor and then trusted by the guest.
+pub fn create_cpuid_page(
+    guest_mem: &GuestMemoryMmap,
+    address: GuestAddress,
+    cpuid: &[CpuIdEntry],
+) -> Result<(), SevSnpError> {
+    if cpuid.len() > SNP_CPUID_COUNT_MAX {
+        return Err(SevSnpError::TooManyCpuidEntries(cpuid.len()));
+    }
+
+    // The header is made of the entry count followed by 12 reserved bytes
+    let mut header = [0u8; 16];
+    header[0..4].copy_from_slice(&(cpuid.len() as u32).to_le_bytes());
+    guest_mem
+        .write_slice(&header, address)
+        .map_err(SevSnpError::GuestMemoryWriteCpuid)?;
+
+    let mut entry_address = address.unchecked_add(header.len() as u64);
+    for entry in cpuid {
+        let function = SnpCpuidFunction {
+            eax_in: entry.function,
+            ecx_in: entry.index,
+            // The XSAVE area sizes are reported for the legacy x87 state
+            xcr0_in: if entry.function == 0xd && entry.index <= 1 {
+                1
+            } else {
+                0
+            },
+            eax: entry.eax,
+            ebx: entry.ebx,
+            ecx: entry.ecx,
+            edx: entry.edx,
+            ..Default::default()
+        };
+        guest_mem
+            .write_obj(function, entry_address)
+            .map_err(SevSnpError::GuestMemoryWriteCpuid)?;
+        entry_address = entry_address.unchecked_add(std::mem::size_of::<SnpCpuidFunction>() as u64);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn push_guid(image: &mut Vec<u8>, guid: &str) {
+        image.extend_from_slice(&Uuid::from_str(guid).unwrap().to_bytes_le());
+    }
+
+    #[test]
+    fn test_parse_sev_snp_sections() {
+        let mut image = vec![0u8; 0x100];
+
+        // SEV metadata describing one section of each type
+        let metadata_start = image.len();
+        image.extend_from_slice(SEV_METADATA_SIGNATURE);
+        image.extend_from_slice(&(16u32 + 3 * 12).to_le_bytes());
+        image.extend_from_slice(&1u32.to_le_bytes());
+        image.extend_from_slice(&3u32.to_le_bytes());
+        for (address, size, r#type) in [
+            (0x80_0000u32, 0x9000u32, SEV_SECTION_SNP_SEC_MEM),
+            (0x80_9000, 0x1000, SEV_SECTION_SNP_SECRETS),
+            (0x80_a000, 0x1000, SEV_SECTION_CPUID),
+        ] {
+            image.extend_from_slice(&address.to_le_bytes());
+            image.extend_from_slice(&size.to_le_bytes());
+            image.extend_from_slice(&r#type.to_le_bytes());
+        }
+        image.resize(0x200, 0);
+
+        // Table of GUIDs, made of the metadata offset entry and the footer,
+        // followed by the reset vector area.
+        let image_size = 0x200 + OFFSET_ENTRY_SIZE + 18 + 0x20;
+        image.extend_from_slice(&((image_size - metadata_start) as u32).to_le_bytes());
+        image.extend_from_slice(&(OFFSET_ENTRY_SIZE as u16).to_le_bytes());
+        push_guid(&mut image, SEV_METADATA_OFFSET_GUID);
+        image.extend_from_slice(&((OFFSET_ENTRY_SIZE + 18) as u16).to_le_bytes());
+        push_guid(&mut image, TABLE_FOOTER_GUID);
+        image.resize(image_size, 0);
+
+        let sections = parse_sev_snp_sections(&mut Cursor::new(&image)).unwrap();
+        assert_eq!(
+            sections,
+            vec![
+                SevSnpSection {
+                    address: 0x80_0000,
+                    size: 0x9000,
+                    r#type: SevSnpSectionType::SecMem
+                },
+                SevSnpSection {
+                    address: 0x80_9000,
+                    size: 0x1000,
+                    r#type: SevSnpSectionType::Secrets
+                },
+                SevSnpSection {
+                    address: 0x80_a000,
+                    size: 0x1000,
+                    r#type: SevSnpSectionType::Cpuid
+                },
+            ]
+        );
+
+        // Firmware without any table of GUIDs
+        assert!(matches!(
+            parse_sev_snp_sections(&mut Cursor::new(vec![0u8; 0x1000])),
+            Err(SevSnpError::MetadataNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_sev_es_reset_vector() {
+        let mut image = vec![0u8; 0x100];
+
+        // Table of GUIDs, made of the SEV-ES reset block and the footer
+        image.extend_from_slice(&0x80_b004u32.to_le_bytes());
+        image.extend_from_slice(&(OFFSET_ENTRY_SIZE as u16).to_le_bytes());
+        push_guid(&mut image, SEV_ES_RESET_BLOCK_GUID);
+        image.extend_from_slice(&((OFFSET_ENTRY_SIZE + 18) as u16).to_le_bytes());
+        push_guid(&mut image, TABLE_FOOTER_GUID);
+        image.resize(image.len() + 0x20, 0);
+
+        assert_eq!(
+            sev_es_reset_vector(&mut Cursor::new(&image)).unwrap(),
+            0x80_b004
+        );
+        assert!(matches!(
+            parse_sev_snp_sections(&mut Cursor::new(&image)),
+            Err(SevSnpError::MetadataNotFound)
+        ));
+        assert!(matches!(
+            sev_es_reset_vector(&mut Cursor::new(vec![0u8; 0x1000])),
+            Err(SevSnpError::ResetBlockNotFound)
+        ));
+    }
+}
@@ -30,6 +30,8 @@ pub enum Error {
     WriteData,
     /// Failure to parse uuid, uuid format may be error
     ParseUuid(uuid::Error),
+    /// The SMBIOS table provided by the user is too large
+    TableTooLarge(usize),
 }
 
 impl std::error::Error for Error {}
@@ -49,6 +51,9 @@ impl Display for Error {
             WriteSmbiosEp => "Failure to write SMBIOS entrypoint structure".to_string(),
             WriteData => "Failure to write additional data to memory".to_string(),
             ParseUuid(e) => format!("Failure to parse uuid: {e}"),
+            TableTooLarge(size) => format!(
+                "The SMBIOS table is too large: {size} bytes (max {MAX_USER_TABLE_SIZE} bytes)"
+            ),
         };
 
         write!(f, "SMBIOS error: {description}")
@@ -59,12 +64,37 @@ pub type Result<T> = result::Result<T, Error>;
 
 // Constants sourced from SMBIOS Spec 3.2.0.
 const SM3_MAGIC_IDENT: &[u8; 5usize] = b"_SM3_";
+const SM_MAGIC_IDENT: &[u8; 4usize] = b"_SM_";
+// Leave room in the BIOS area for the MP table placed after the SMBIOS table
+const MAX_USER_TABLE_SIZE: usize = 32 << 10;
 const BIOS_INFORMATION: u8 = 0;
 const SYSTEM_INFORMATION: u8 = 1;
 const OEM_STRINGS: u8 = 11;
+const PHYSICAL_MEMORY_ARRAY: u8 = 16;
+const MEMORY_DEVICE: u8 = 17;
 const END_OF_TABLE: u8 = 127;
 const PCI_SUPPORTED: u64 = 1 << 7;
 const IS_VIRTUAL_MACHINE: u8 = 1 << 4;
+const LOCATION_OTHER: u8 = 0x01;
+const USE_SYSTEM_MEMORY: u8 = 0x03;
+const ERROR_CORRECTION_MULTI_BIT_ECC: u8 = 0x06;
+const NO_ERROR_INFORMATION: u16 = 0xfffe;
+const FORM_FACTOR_DIMM: u8 = 0x09;
+const MEMORY_TYPE_RAM: u8 = 0x07;
+const TYPE_DETAIL_UNKNOWN: u16 = 1 << 2;
+// Capacity and sizes above these values are reported through the extended fields
+const MAX_CAPACITY_USE_EXTENDED: u32 = 0x8000_0000;
+const SIZE_USE_EXTENDED: u16 = 0x7fff;
+
+/// Memory device (DIMM) exposed to the guest through SMBIOS.
+pub struct SmbiosMemoryDevice {
+    /// Size of the device in bytes
+    pub size: u64,
+    /// Identifies the device, e.g. "DIMM 0"
+    pub device_locator: String,
+    /// Identifies the bank the device belongs to, e.g. "NODE 0"
+    pub bank_locator: Option<String>,
+}
 
 fn compute_checksum<T: Copy>(v: &T) -> u8 {
     // SAFETY: we are only reading the bytes within the size of the `T` reference `v`.
@@ -136,6 +166,53 @@ struct SmbiosOemStrings {
     count: u8,
 }
 
+#[repr(C)]
+#[repr(packed)]
+#[derive(Default, Copy, Clone)]
+struct SmbiosPhysicalMemoryArray {
+    r#type: u8,
+    length: u8,
+    handle: u16,
+    location: u8,
+    r#use: u8,
+    error_correction: u8,
+    maximum_capacity: u32,
+    error_information_handle: u16,
+    number_of_devices: u16,
+    extended_maximum_capacity: u64,
+}
+
+#[repr(C)]
+#[repr(packed)]
+#[derive(Default, Copy, Clone)]
+struct SmbiosMemoryDeviceInfo {
+    r#type: u8,
+    length: u8,
+    handle: u16,
+    physical_memory_array_handle: u16,
+    error_information_handle: u16,
+    total_width: u16,
+    data_width: u16,
+    size: u16,
+    form_factor: u8,
+    device_set: u8,
+    device_locator: u8,
+    bank_locator: u8,
+    memory_type: u8,
+    type_detail: u16,
+    speed: u16,
+    manufacturer: u8,
+    serial_number: u8,
+    asset_tag: u8,
+    part_number: u8,
+    attributes: u8,
+    extended_size: u32,
+    configured_memory_speed: u16,
+    minimum_voltage: u16,
+    maximum_voltage: u16,
+    configured_voltage: u16,
+}
+
 #[repr(C)]
 #[repr(packed)]
 #[derive(Default, Copy, Clone)]
@@ -154,6 +231,10 @@ unsafe impl ByteValued for SmbiosSysInfo {}
 // SAFETY: data structure only contain a series of integers
 unsafe impl ByteValued for SmbiosOemStrings {}
 // SAFETY: data structure only contain a series of integers
+unsafe impl ByteValued for SmbiosPhysicalMemoryArray {}
+// SAFETY: data structure only contain a series of integers
+unsafe impl ByteValued for SmbiosMemoryDeviceInfo {}
+// SAFETY: data structure only contain a series of integers
 unsafe impl ByteValued for SmbiosEndOfTable {}
 
 fn write_and_incr<T: ByteValued>(
@@ -180,15 +261,15 @@ fn write_string(
     Ok(curptr)
 }
 
-pub fn setup_smbios(
+// Synthesize the SMBIOS structures, starting at `physptr`.
+fn write_tables(
     mem: &GuestMemoryMmap,
+    physptr: GuestAddress,
     serial_number: Option<&str>,
     uuid: Option<&str>,
     oem_strings: Option<&[&str]>,
-) -> Result<u64> {
-    let physptr = GuestAddress(SMBIOS_START)
-        .checked_add(mem::size_of::<Smbios30Entrypoint>() as u64)
-        .ok_or(Error::NotEnoughMemory)?;
+    memory_devices: &[SmbiosMemoryDevice],
+) -> Result<GuestAddress> {
     let mut curptr = physptr;
     let mut handle = 0;
 
@@ -256,6 +337,75 @@ pub fn setup_smbios(
         curptr = write_and_incr(mem, 0u8, curptr)?;
     }
 
+    if !memory_devices.is_empty() {
+        handle += 1;
+        let array_handle = handle;
+
+        let capacity: u64 = memory_devices.iter().map(|d| d.size).sum();
+        let capacity_kib = capacity >> 10;
+        let smbios_memory_array = SmbiosPhysicalMemoryArray {
+            r#type: PHYSICAL_MEMORY_ARRAY,
+            length: mem::size_of::<SmbiosPhysicalMemoryArray>() as u8,
+            handle,
+            location: LOCATION_OTHER,
+            r#use: USE_SYSTEM_MEMORY,
+            error_correction: ERROR_CORRECTION_MULTI_BIT_ECC,
+            maximum_capacity: if capacity_kib < MAX_CAPACITY_USE_EXTENDED as u64 {
+                capacity_kib as u32
+            } else {
+                MAX_CAPACITY_USE_EXTENDED
+            },
+            error_information_handle: NO_ERROR_INFORMATION,
+            number_of_devices: memory_devices.len() as u16,
+            extended_maximum_capacity: if capacity_kib < MAX_CAPACITY_USE_EXTENDED as u64 {
+                0
+            } else {
+                capacity
+            },
+        };
+        curptr = write_and_incr(mem, smbios_memory_array, curptr)?;
+        // No strings, terminate the structure with two null bytes
+        curptr = write_and_incr(mem, 0u8, curptr)?;
+        curptr = write_and_incr(mem, 0u8, curptr)?;
+
+        for device in memory_devices {
+            handle += 1;
+
+            let size_mib = device.size >> 20;
+            let smbios_memory_device = SmbiosMemoryDeviceInfo {
+                r#type: MEMORY_DEVICE,
+                length: mem::size_of::<SmbiosMemoryDeviceInfo>() as u8,
+                handle,
+                physical_memory_array_handle: array_handle,
+                error_information_handle: NO_ERROR_INFORMATION,
+                total_width: 64,
+                data_width: 64,
+                size: if size_mib < SIZE_USE_EXTENDED as u64 {
+                    size_mib as u16
+                } else {
+                    SIZE_USE_EXTENDED
+                },
+                form_factor: FORM_FACTOR_DIMM,
+                device_locator: 1, // First string written in this section
+                bank_locator: device.bank_locator.as_ref().map(|_| 2).unwrap_or_default(), // 2nd string
+                memory_type: MEMORY_TYPE_RAM,
+                type_detail: TYPE_DETAIL_UNKNOWN,
+                extended_size: if size_mib < SIZE_USE_EXTENDED as u64 {
+                    0
+                } else {
+                    size_mib as u32
+                },
+                ..Default::default()
+            };
+            curptr = write_and_incr(mem, smbios_memory_device, curptr)?;
+            curptr = write_string(mem, &device.device_locator, curptr)?;
+            if let Some(bank_locator) = &device.bank_locator {
+                curptr = write_string(mem, bank_locator, curptr)?;
+            }
+            curptr = write_and_incr(mem, 0u8, curptr)?;
+        }
+    }
+
     {
         handle += 1;
         let smbios_end = SmbiosEndOfTable {
@@ -268,6 +418,62 @@ pub fn setup_smbios(
         curptr = write_and_incr(mem, 0u8, curptr)?;
     }
 
+    Ok(curptr)
+}
+
+// Install the SMBIOS structures from a table provided by the user. An entry
+// point found at the beginning of the table is dropped since it is replaced
+// with one pointing to the table location in guest memory.
+fn write_user_table(
+    mem: &GuestMemoryMmap,
+    physptr: GuestAddress,
+    table: &[u8],
+) -> Result<GuestAddress> {
+    let table = if table.starts_with(SM3_MAGIC_IDENT) && table.len() > 6 {
+        &table[std::cmp::min(table[6] as usize, table.len())..]
+    } else if table.starts_with(SM_MAGIC_IDENT) && table.len() > 5 {
+        // The length of the legacy entry point covers the "_DMI_" one
+        &table[std::cmp::min(table[5] as usize, table.len())..]
+    } else {
+        table
+    };
+
+    if table.len() > MAX_USER_TABLE_SIZE {
+        return Err(Error::TableTooLarge(table.len()));
+    }
+
+    mem.write_slice(table, physptr)
+        .map_err(|_| Error::WriteData)?;
+
+    physptr
+        .checked_add(table.len() as u64)
+        .ok_or(Error::NotEnoughMemory)
+}
+
+pub fn setup_smbios(
+    mem: &GuestMemoryMmap,
+    serial_number: Option<&str>,
+    uuid: Option<&str>,
+    oem_strings: Option<&[&str]>,
+    memory_devices: &[SmbiosMemoryDevice],
+    smbios_table: Option<&[u8]>,
+) -> Result<u64> {
+    let physptr = GuestAddress(SMBIOS_START)
+        .checked_add(mem::size_of::<Smbios30Entrypoint>() as u64)
+        .ok_or(Error::NotEnoughMemory)?;
+    let curptr = if let Some(table) = smbios_table {
+        write_user_table(mem, physptr, table)?
+    } else {
+        write_tables(
+            mem,
+            physptr,
+            serial_number,
+            uuid,
+            oem_strings,
+            memory_devices,
+        )?
+    };
+
     {
         let mut smbios_ep = Smbios30Entrypoint {
             signature: *SM3_MAGIC_IDENT,
@@ -310,16 +516,113 @@ mod tests {
             0x1busize,
             concat!("Size of: ", stringify!(SmbiosSysInfo))
         );
+        assert_eq!(
+            mem::size_of::<SmbiosPhysicalMemoryArray>(),
+            0x17usize,
+            concat!("Size of: ", stringify!(SmbiosPhysicalMemoryArray))
+        );
+        assert_eq!(
+            mem::size_of::<SmbiosMemoryDeviceInfo>(),
+            0x28usize,
+            concat!("Size of: ", stringify!(SmbiosMemoryDeviceInfo))
+        );
     }
 
     #[test]
     fn entrypoint_checksum() {
         let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(SMBIOS_START), 4096)]).unwrap();
 
-        setup_smbios(&mem, None, None, None).unwrap();
+        setup_smbios(&mem, None, None, None, &[], None).unwrap();
+
+        let smbios_ep: Smbios30Entrypoint = mem.read_obj(GuestAddress(SMBIOS_START)).unwrap();
+
+        assert_eq!(compute_checksum(&smbios_ep), 0);
+    }
+
+    #[test]
+    fn memory_devices() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(SMBIOS_START), 4096)]).unwrap();
 
+        let memory_devices = [
+            SmbiosMemoryDevice {
+                size: 1 << 30,
+                device_locator: "mem0".to_string(),
+                bank_locator: Some("NODE 0".to_string()),
+            },
+            SmbiosMemoryDevice {
+                size: 64 << 30,
+                device_locator: "mem1".to_string(),
+                bank_locator: None,
+            },
+        ];
+        setup_smbios(&mem, None, None, None, &memory_devices, None).unwrap();
+
+        // Walk the table up to the physical memory array
         let smbios_ep: Smbios30Entrypoint = mem.read_obj(GuestAddress(SMBIOS_START)).unwrap();
+        let mut addr = GuestAddress(smbios_ep.physptr);
+        loop {
+            let r#type: u8 = mem.read_obj(addr).unwrap();
+            if r#type == PHYSICAL_MEMORY_ARRAY {
+                break;
+            }
+            assert_ne!(r#type, END_OF_TABLE);
+            let length: u8 = mem.read_obj(addr.unchecked_add(1)).unwrap();
+            addr = addr.unchecked_add(length as u64);
+            // Skip the strings, terminated by a double null byte
+            while mem.read_obj::<u16>(addr).unwrap() != 0 {
+                addr = addr.unchecked_add(1);
+            }
+            addr = addr.unchecked_add(2);
+        }
+
+        let array: SmbiosPhysicalMemoryArray = mem.read_obj(addr).unwrap();
+        assert_eq!({ array.maximum_capacity }, 65 << 20);
+        assert_eq!({ array.number_of_devices }, 2);
+
+        let addr = addr.unchecked_add(mem::size_of::<SmbiosPhysicalMemoryArray>() as u64 + 2);
+        let device: SmbiosMemoryDeviceInfo = mem.read_obj(addr).unwrap();
+        assert_eq!({ device.physical_memory_array_handle }, { array.handle });
+        assert_eq!({ device.size }, 1024);
+        assert_eq!(device.bank_locator, 2);
+
+        let addr = addr.unchecked_add(mem::size_of::<SmbiosMemoryDeviceInfo>() as u64);
+        let mut strings = [0u8; 13];
+        mem.read_slice(&mut strings, addr).unwrap();
+        assert_eq!(&strings, b"mem0\0NODE 0\0\0");
+        let addr = addr.unchecked_add(strings.len() as u64);
+        let device: SmbiosMemoryDeviceInfo = mem.read_obj(addr).unwrap();
+        assert_eq!({ device.size }, SIZE_USE_EXTENDED);
+        assert_eq!({ device.extended_size }, 64 << 10);
+        assert_eq!(device.bank_locator, 0);
+    }
 
+    #[test]
+    fn user_table() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(SMBIOS_START), 4096)]).unwrap();
+
+        // OEM specific structure followed by the end of table
+        let structures = [0x80u8, 4, 1, 0, 0, 0, END_OF_TABLE, 4, 2, 0, 0, 0];
+        let mut table = vec![0u8; mem::size_of::<Smbios30Entrypoint>()];
+        table[..5].copy_from_slice(SM3_MAGIC_IDENT);
+        table[6] = mem::size_of::<Smbios30Entrypoint>() as u8;
+        table.extend_from_slice(&structures);
+
+        let size = setup_smbios(&mem, Some("serial"), None, None, &[], Some(&table)).unwrap();
+        assert_eq!(
+            size,
+            (mem::size_of::<Smbios30Entrypoint>() + structures.len()) as u64
+        );
+
+        let smbios_ep: Smbios30Entrypoint = mem.read_obj(GuestAddress(SMBIOS_START)).unwrap();
         assert_eq!(compute_checksum(&smbios_ep), 0);
+        assert_eq!({ smbios_ep.max_size }, structures.len() as u32);
+
+        let mut installed = [0u8; 12];
+        mem.read_slice(&mut installed, GuestAddress(smbios_ep.physptr))
+            .unwrap();
+        assert_eq!(installed, structures);
+
+        let table = vec![0u8; MAX_USER_TABLE_SIZE + 1];
+        assert!(setup_smbios(&mem, None, None, None, &[], Some(&table)).is_err());
     }
 }
@@ -2,9 +2,11 @@
 //
 // SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
 
-use libc::{ioctl, S_IFBLK, S_IFMT};
+use libc::{
+    ioctl, FALLOC_FL_KEEP_SIZE, FALLOC_FL_PUNCH_HOLE, FALLOC_FL_ZERO_RANGE, S_IFBLK, S_IFMT,
+};
 use std::fs::File;
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, RawFd};
 use thiserror::Error;
 use vmm_sys_util::eventfd::EventFd;
 use vmm_sys_util::{ioctl_io_nr, ioctl_ioc_nr};
@@ -42,6 +44,89 @@ ioctl_io_nr!(BLKSSZGET, 0x12, 104);
 ioctl_io_nr!(BLKPBSZGET, 0x12, 123);
 ioctl_io_nr!(BLKIOMIN, 0x12, 120);
 ioctl_io_nr!(BLKIOOPT, 0x12, 121);
+ioctl_io_nr!(BLKDISCARD, 0x12, 119);
+ioctl_io_nr!(BLKZEROOUT, 0x12, 127);
+
+pub(crate) fn is_block_device(fd: RawFd) -> std::io::Result<bool> {
+    let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
+    // SAFETY: FFI call with a valid fd and buffer
+    let ret = unsafe { libc::fstat(fd, stat.as_mut_ptr()) };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    // SAFETY: stat is valid at this point
+    let is_block = unsafe { (*stat.as_ptr()).st_mode & S_IFMT == S_IFBLK };
+    Ok(is_block)
+}
+
+fn fallocate(fd: RawFd, mode: libc::c_int, offset: u64, length: u64) -> std::io::Result<()> {
+    // SAFETY: FFI call with a valid fd
+    let ret =
+        unsafe { libc::fallocate64(fd, mode, offset as libc::off64_t, length as libc::off64_t) };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+fn block_range_ioctl(
+    fd: RawFd,
+    request: libc::c_ulong,
+    offset: u64,
+    length: u64,
+) -> std::io::Result<()> {
+    let range: [u64; 2] = [offset, length];
+    // SAFETY: FFI call with a valid fd and the range expected by the ioctl
+    let ret = unsafe { ioctl(fd, request as _, &range) };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Releases the storage backing a range of a raw disk, either by punching a
+/// hole in the image file or by discarding the range of the block device.
+pub(crate) fn raw_punch_hole(
+    fd: RawFd,
+    block_device: bool,
+    offset: u64,
+    length: u64,
+) -> std::io::Result<()> {
+    if block_device {
+        block_range_ioctl(fd, BLKDISCARD(), offset, length)
+    } else {
+        fallocate(
+            fd,
+            FALLOC_FL_PUNCH_HOLE | FALLOC_FL_KEEP_SIZE,
+            offset,
+            length,
+        )
+    }
+}
+
+/// Zeroes a range of a raw disk. Image files may have the range deallocated
+/// at the same time if `unmap` is allowed.
+pub(crate) fn raw_write_zeroes(
+    fd: RawFd,
+    block_device: bool,
+    offset: u64,
+    length: u64,
+    unmap: bool,
+) -> std::io::Result<()> {
+    if block_device {
+        block_range_ioctl(fd, BLKZEROOUT(), offset, length)
+    } else {
+        let mode = if unmap {
+            FALLOC_FL_PUNCH_HOLE
+        } else {
+            FALLOC_FL_ZERO_RANGE
+        };
+        fallocate(fd, mode | FALLOC_FL_KEEP_SIZE, offset, length)
+    }
+}
 
 enum BlockSize {
     LogicalBlock,
@@ -51,19 +136,6 @@ enum BlockSize {
 }
 
 impl DiskTopology {
-    fn is_block_device(f: &mut File) -> std::io::Result<bool> {
-        let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
-        // SAFETY: FFI call with a valid fd and buffer
-        let ret = unsafe { libc::fstat(f.as_raw_fd(), stat.as_mut_ptr()) };
-        if ret != 0 {
-            return Err(std::io::Error::last_os_error());
-        }
-
-        // SAFETY: stat is valid at this point
-        let is_block = unsafe { (*stat.as_ptr()).st_mode & S_IFMT == S_IFBLK };
-        Ok(is_block)
-    }
-
     // libc::ioctl() takes different types on different architectures
     fn query_block_size(f: &mut File, block_size_type: BlockSize) -> std::io::Result<u64> {
         let mut block_size = 0;
@@ -88,7 +160,7 @@ impl DiskTopology {
     }
 
     pub fn probe(f: &mut File) -> std::io::Result<Self> {
-        if !Self::is_block_device(f)? {
+        if !is_block_device(f.as_raw_fd())? {
             return Ok(DiskTopology::default());
         }
 
@@ -109,6 +181,12 @@ pub trait DiskFile: Send {
     fn topology(&mut self) -> DiskTopology {
         DiskTopology::default()
     }
+
+    /// Whether the AsyncIo instances of this disk can deallocate and zero
+    /// ranges through punch_hole() and write_zeroes().
+    fn supports_discard(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Error, Debug)]
@@ -122,6 +200,12 @@ pub enum AsyncIoError {
     /// Failed synchronizing file.
     #[error("Failed synchronizing file: {0}")]
     Fsync(#[source] std::io::Error),
+    /// Failed punching a hole in the file.
+    #[error("Failed punching a hole in the file: {0}")]
+    PunchHole(#[source] std::io::Error),
+    /// Failed writing zeroes to the file.
+    #[error("Failed writing zeroes to the file: {0}")]
+    WriteZeroes(#[source] std::io::Error),
 }
 
 pub type AsyncIoResult<T> = std::result::Result<T, AsyncIoError>;
@@ -141,5 +225,26 @@ pub trait AsyncIo: Send {
         user_data: u64,
     ) -> AsyncIoResult<()>;
     fn fsync(&mut self, user_data: Option<u64>) -> AsyncIoResult<()>;
+    fn punch_hole(
+        &mut self,
+        _offset: libc::off_t,
+        _length: u64,
+        _user_data: u64,
+    ) -> AsyncIoResult<()> {
+        Err(AsyncIoError::PunchHole(std::io::Error::from_raw_os_error(
+            libc::EOPNOTSUPP,
+        )))
+    }
+    fn write_zeroes(
+        &mut self,
+        _offset: libc::off_t,
+        _length: u64,
+        _unmap: bool,
+        _user_data: u64,
+    ) -> AsyncIoResult<()> {
+        Err(AsyncIoError::WriteZeroes(
+            std::io::Error::from_raw_os_error(libc::EOPNOTSUPP),
+        ))
+    }
     fn next_completed_request(&mut self) -> Option<(u64, i32)>;
 }
@@ -46,6 +46,7 @@ use vm_memory::{
 };
 use vm_virtio::{AccessPlatform, Translatable};
 use vmm_sys_util::eventfd::EventFd;
+use vmm_sys_util::write_zeroes::{PunchHole, WriteZeroesAt};
 
 type GuestMemoryMmap = vm_memory::GuestMemoryMmap<AtomicBitmap>;
 
@@ -72,6 +73,8 @@ pub enum Error {
     InvalidOffset,
     #[error("The requested operation does not support multiple descriptors")]
     TooManyDescriptors,
+    #[error("Guest gave us more discard or write zeroes segments than supported")]
+    TooManySegments,
 }
 
 fn build_device_id(disk_path: &Path) -> result::Result<String, Error> {
@@ -130,6 +133,10 @@ pub enum ExecuteError {
     AsyncWrite(AsyncIoError),
     #[error("failed to async flush: {0}")]
     AsyncFlush(AsyncIoError),
+    #[error("Failed to async discard: {0}")]
+    AsyncDiscard(AsyncIoError),
+    #[error("Failed to async write zeroes: {0}")]
+    AsyncWriteZeroes(AsyncIoError),
     #[error("Failed allocating a temporary buffer: {0}")]
     TemporaryBufferAllocation(io::Error),
 }
@@ -148,6 +155,14 @@ impl ExecuteError {
             ExecuteError::AsyncRead(_) => VIRTIO_BLK_S_IOERR,
             ExecuteError::AsyncWrite(_) => VIRTIO_BLK_S_IOERR,
             ExecuteError::AsyncFlush(_) => VIRTIO_BLK_S_IOERR,
+            ExecuteError::AsyncDiscard(AsyncIoError::PunchHole(ref e))
+            | ExecuteError::AsyncWriteZeroes(AsyncIoError::WriteZeroes(ref e))
+                if e.raw_os_error() == Some(libc::EOPNOTSUPP) =>
+            {
+                VIRTIO_BLK_S_UNSUPP
+            }
+            ExecuteError::AsyncDiscard(_) => VIRTIO_BLK_S_IOERR,
+            ExecuteError::AsyncWriteZeroes(_) => VIRTIO_BLK_S_IOERR,
             ExecuteError::TemporaryBufferAllocation(_) => VIRTIO_BLK_S_IOERR,
         }
     }
@@ -159,6 +174,8 @@ pub enum RequestType {
     Out,
     Flush,
     GetDeviceId,
+    Discard,
+    WriteZeroes,
     Unsupported(u32),
 }
 
@@ -172,6 +189,8 @@ pub fn request_type(
         VIRTIO_BLK_T_OUT => Ok(RequestType::Out),
         VIRTIO_BLK_T_FLUSH => Ok(RequestType::Flush),
         VIRTIO_BLK_T_GET_ID => Ok(RequestType::GetDeviceId),
+        VIRTIO_BLK_T_DISCARD => Ok(RequestType::Discard),
+        VIRTIO_BLK_T_WRITE_ZEROES => Ok(RequestType::WriteZeroes),
         t => Ok(RequestType::Unsupported(t)),
     }
 }
@@ -186,6 +205,20 @@ fn sector(mem: &GuestMemoryMmap, desc_addr: GuestAddress) -> result::Result<u64,
     mem.read_obj(addr).map_err(Error::GuestMemory)
 }
 
+const VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP: u32 = 1;
+
+// Range of a discard or write zeroes request.
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+struct DiscardWriteZeroesSegment {
+    sector: u64,
+    num_sectors: u32,
+    flags: u32,
+}
+
+// SAFETY: data structure only contain a series of integers
+unsafe impl ByteValued for DiscardWriteZeroesSegment {}
+
 #[derive(Debug)]
 pub struct AlignedOperation {
     origin_ptr: u64,
@@ -256,7 +289,12 @@ impl Request {
         } else {
             req.data_descriptors.reserve_exact(1);
             while desc.has_next() {
-                if desc.is_write_only() && req.request_type == RequestType::Out {
+                if desc.is_write_only()
+                    && matches!(
+                        req.request_type,
+                        RequestType::Out | RequestType::Discard | RequestType::WriteZeroes
+                    )
+                {
                     return Err(Error::UnexpectedWriteOnlyDescriptor);
                 }
                 if !desc.is_write_only() && req.request_type == RequestType::In {
@@ -341,12 +379,72 @@ impl Request {
                     mem.write_slice(disk_id, *data_addr)
                         .map_err(ExecuteError::Write)?;
                 }
+                RequestType::Discard => {
+                    return Err(ExecuteError::Unsupported(VIRTIO_BLK_T_DISCARD))
+                }
+                RequestType::WriteZeroes => {
+                    return Err(ExecuteError::Unsupported(VIRTIO_BLK_T_WRITE_ZEROES))
+                }
                 RequestType::Unsupported(t) => return Err(ExecuteError::Unsupported(t)),
             };
         }
         Ok(len)
     }
 
+    // Reads the segment of a discard or write zeroes request, returning the
+    // byte range it covers on the disk along with its unmap flag.
+    fn discard_write_zeroes_range(
+        &self,
+        mem: &GuestMemoryMmap,
+        disk_nsectors: u64,
+    ) -> result::Result<(u64, u64, bool), ExecuteError> {
+        let (data_addr, data_len) = if self.data_descriptors.len() == 1 {
+            (self.data_descriptors[0].0, self.data_descriptors[0].1)
+        } else {
+            return Err(ExecuteError::BadRequest(Error::TooManyDescriptors));
+        };
+
+        // Only a single segment is advertised through max_discard_seg and
+        // max_write_zeroes_seg.
+        let segment_size = std::mem::size_of::<DiscardWriteZeroesSegment>() as u32;
+        if data_len < segment_size {
+            return Err(ExecuteError::BadRequest(Error::DescriptorLengthTooSmall));
+        }
+        if data_len > segment_size {
+            return Err(ExecuteError::BadRequest(Error::TooManySegments));
+        }
+
+        let segment: DiscardWriteZeroesSegment =
+            mem.read_obj(data_addr).map_err(ExecuteError::Read)?;
+
+        // "For discard commands, the device MUST set the status to
+        // VIRTIO_BLK_S_UNSUPP if the unmap flag is set", and any other flag is
+        // reserved.
+        let unmap = segment.flags & VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP != 0;
+        if segment.flags & !VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP != 0
+            || (unmap && self.request_type == RequestType::Discard)
+        {
+            return Err(ExecuteError::Unsupported(match self.request_type {
+                RequestType::Discard => VIRTIO_BLK_T_DISCARD,
+                _ => VIRTIO_BLK_T_WRITE_ZEROES,
+            }));
+        }
+
+        let top = segment
+            .sector
+            .checked_add(u64::from(segment.num_sectors))
+            .ok_or(ExecuteError::BadRequest(Error::InvalidOffset))?;
+        if top > disk_nsectors {
+            return Err(ExecuteError::BadRequest(Error::InvalidOffset));
+        }
+
+        Ok((
+            segment.sector << SECTOR_SHIFT,
+            u64::from(segment.num_sectors) << SECTOR_SHIFT,
+            unmap,
+        ))
+    }
+
     pub fn execute_async(
         &mut self,
         mem: &GuestMemoryMmap,
@@ -359,6 +457,27 @@ impl Request {
         let request_type = self.request_type;
         let offset = (sector << SECTOR_SHIFT) as libc::off_t;
 
+        // The range of discard and write zeroes requests is carried by their
+        // data, which is not transferred to the disk.
+        match request_type {
+            RequestType::Discard => {
+                let (offset, length, _) = self.discard_write_zeroes_range(mem, disk_nsectors)?;
+                disk_image
+                    .punch_hole(offset as libc::off_t, length, user_data)
+                    .map_err(ExecuteError::AsyncDiscard)?;
+                return Ok(true);
+            }
+            RequestType::WriteZeroes => {
+                let (offset, length, unmap) =
+                    self.discard_write_zeroes_range(mem, disk_nsectors)?;
+                disk_image
+                    .write_zeroes(offset as libc::off_t, length, unmap, user_data)
+                    .map_err(ExecuteError::AsyncWriteZeroes)?;
+                return Ok(true);
+            }
+            _ => {}
+        }
+
         let mut iovecs: SmallVec<[libc::iovec; 1]> =
             SmallVec::with_capacity(self.data_descriptors.len());
         for (data_addr, data_len) in &self.data_descriptors {
@@ -463,6 +582,8 @@ impl Request {
                     .map_err(ExecuteError::Write)?;
                 return Ok(false);
             }
+            // Submitted above, without mapping any data.
+            RequestType::Discard | RequestType::WriteZeroes => unreachable!(),
             RequestType::Unsupported(t) => return Err(ExecuteError::Unsupported(t)),
         }
 
@@ -682,6 +803,56 @@ where
         Ok(())
     }
 
+    fn punch_hole_sync(
+        &mut self,
+        offset: libc::off_t,
+        length: u64,
+        user_data: u64,
+        eventfd: &EventFd,
+        completion_list: &mut VecDeque<(u64, i32)>,
+    ) -> AsyncIoResult<()>
+    where
+        F: PunchHole,
+    {
+        {
+            let mut file = self.file();
+
+            // Deallocate
+            file.punch_hole(offset as u64, length)
+                .map_err(AsyncIoError::PunchHole)?;
+        }
+
+        completion_list.push_back((user_data, 0));
+        eventfd.write(1).unwrap();
+
+        Ok(())
+    }
+
+    fn write_zeroes_sync(
+        &mut self,
+        offset: libc::off_t,
+        length: u64,
+        user_data: u64,
+        eventfd: &EventFd,
+        completion_list: &mut VecDeque<(u64, i32)>,
+    ) -> AsyncIoResult<()>
+    where
+        F: WriteZeroesAt,
+    {
+        {
+            let mut file = self.file();
+
+            // Write zeroes
+            file.write_all_zeroes_at(offset as u64, length as usize)
+                .map_err(AsyncIoError::WriteZeroes)?;
+        }
+
+        completion_list.push_back((user_data, 0));
+        eventfd.write(1).unwrap();
+
+        Ok(())
+    }
+
     fn file(&mut self) -> MutexGuard<F>;
 }
 
@@ -720,3 +891,153 @@ pub fn detect_image_type(f: &mut File) -> std::io::Result<ImageType> {
 
     Ok(image_type)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DISK_NSECTORS: u64 = 0x100;
+    const SEGMENT_ADDR: GuestAddress = GuestAddress(0x1000);
+
+    fn create_request(
+        mem: &GuestMemoryMmap,
+        request_type: RequestType,
+        segments: &[DiscardWriteZeroesSegment],
+    ) -> Request {
+        for (i, segment) in segments.iter().enumerate() {
+            let addr = GuestAddress(
+                SEGMENT_ADDR.0 + (i * std::mem::size_of::<DiscardWriteZeroesSegment>()) as u64,
+            );
+            mem.write_obj(*segment, addr).unwrap();
+        }
+
+        let mut data_descriptors = SmallVec::new();
+        data_descriptors.push((
+            SEGMENT_ADDR,
+            (segments.len() * std::mem::size_of::<DiscardWriteZeroesSegment>()) as u32,
+        ));
+
+        Request {
+            request_type,
+            sector: 0,
+            data_descriptors,
+            status_addr: GuestAddress(0),
+            writeback: true,
+            aligned_operations: SmallVec::new(),
+            start: Instant::now(),
+        }
+    }
+
+    fn segment(sector: u64, num_sectors: u32, flags: u32) -> DiscardWriteZeroesSegment {
+        DiscardWriteZeroesSegment {
+            sector,
+            num_sectors,
+            flags,
+        }
+    }
+
+    #[test]
+    fn test_discard_write_zeroes_range() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap();
+
+        // Byte range and unmap flag of a valid segment
+        let request = create_request(&mem, RequestType::Discard, &[segment(2, 3, 0)]);
+        assert_eq!(
+            request
+                .discard_write_zeroes_range(&mem, DISK_NSECTORS)
+                .unwrap(),
+            (2 << SECTOR_SHIFT, 3 << SECTOR_SHIFT, false)
+        );
+        let request = create_request(
+            &mem,
+            RequestType::WriteZeroes,
+            &[segment(0, 0x100, VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP)],
+        );
+        assert_eq!(
+            request
+                .discard_write_zeroes_range(&mem, DISK_NSECTORS)
+                .unwrap(),
+            (0, DISK_NSECTORS << SECTOR_SHIFT, true)
+        );
+
+        // The unmap flag is only valid for write zeroes requests
+        let request = create_request(
+            &mem,
+            RequestType::Discard,
+            &[segment(0, 1, VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP)],
+        );
+        let err = request
+            .discard_write_zeroes_range(&mem, DISK_NSECTORS)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            ExecuteError::Unsupported(VIRTIO_BLK_T_DISCARD)
+        ));
+        assert_eq!(err.status(), VIRTIO_BLK_S_UNSUPP);
+
+        // Reserved flags
+        let request = create_request(&mem, RequestType::WriteZeroes, &[segment(0, 1, 1 << 1)]);
+        assert!(matches!(
+            request.discard_write_zeroes_range(&mem, DISK_NSECTORS),
+            Err(ExecuteError::Unsupported(VIRTIO_BLK_T_WRITE_ZEROES))
+        ));
+
+        // Range overflowing or beyond the end of the disk
+        let request = create_request(&mem, RequestType::Discard, &[segment(u64::MAX, 1, 0)]);
+        assert!(matches!(
+            request.discard_write_zeroes_range(&mem, DISK_NSECTORS),
+            Err(ExecuteError::BadRequest(Error::InvalidOffset))
+        ));
+        let request = create_request(&mem, RequestType::Discard, &[segment(0xff, 2, 0)]);
+        assert!(matches!(
+            request.discard_write_zeroes_range(&mem, DISK_NSECTORS),
+            Err(ExecuteError::BadRequest(Error::InvalidOffset))
+        ));
+
+        // Only a single segment is supported
+        let request = create_request(
+            &mem,
+            RequestType::Discard,
+            &[segment(0, 1, 0), segment(1, 1, 0)],
+        );
+        assert!(matches!(
+            request.discard_write_zeroes_range(&mem, DISK_NSECTORS),
+            Err(ExecuteError::BadRequest(Error::TooManySegments))
+        ));
+        let mut request = create_request(&mem, RequestType::Discard, &[segment(0, 1, 0)]);
+        request.data_descriptors[0].1 -= 1;
+        assert!(matches!(
+            request.discard_write_zeroes_range(&mem, DISK_NSECTORS),
+            Err(ExecuteError::BadRequest(Error::DescriptorLengthTooSmall))
+        ));
+        let mut request = create_request(&mem, RequestType::Discard, &[segment(0, 1, 0)]);
+        request.data_descriptors.push((SEGMENT_ADDR, 16));
+        assert!(matches!(
+            request.discard_write_zeroes_range(&mem, DISK_NSECTORS),
+            Err(ExecuteError::BadRequest(Error::TooManyDescriptors))
+        ));
+    }
+
+    #[test]
+    fn test_discard_write_zeroes_status() {
+        let unsupported = || io::Error::from_raw_os_error(libc::EOPNOTSUPP);
+        let failed = || io::Error::from_raw_os_error(libc::EIO);
+
+        assert_eq!(
+            ExecuteError::AsyncDiscard(AsyncIoError::PunchHole(unsupported())).status(),
+            VIRTIO_BLK_S_UNSUPP
+        );
+        assert_eq!(
+            ExecuteError::AsyncWriteZeroes(AsyncIoError::WriteZeroes(unsupported())).status(),
+            VIRTIO_BLK_S_UNSUPP
+        );
+        assert_eq!(
+            ExecuteError::AsyncDiscard(AsyncIoError::PunchHole(failed())).status(),
+            VIRTIO_BLK_S_IOERR
+        );
+        assert_eq!(
+            ExecuteError::AsyncWriteZeroes(AsyncIoError::WriteZeroes(failed())).status(),
+            VIRTIO_BLK_S_IOERR
+        );
+    }
+}
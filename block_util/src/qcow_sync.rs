@@ -33,6 +33,10 @@ impl DiskFile for QcowDiskSync {
     fn new_async_io(&self, _ring_depth: u32) -> DiskFileResult<Box<dyn AsyncIo>> {
         Ok(Box::new(QcowSync::new(self.qcow_file.clone())) as Box<dyn AsyncIo>)
     }
+
+    fn supports_discard(&self) -> bool {
+        true
+    }
 }
 
 pub struct QcowSync {
@@ -98,6 +102,39 @@ impl AsyncIo for QcowSync {
             .fsync_sync(user_data, &self.eventfd, &mut self.completion_list)
     }
 
+    fn punch_hole(
+        &mut self,
+        offset: libc::off_t,
+        length: u64,
+        user_data: u64,
+    ) -> AsyncIoResult<()> {
+        self.qcow_file.punch_hole_sync(
+            offset,
+            length,
+            user_data,
+            &self.eventfd,
+            &mut self.completion_list,
+        )
+    }
+
+    // Zeroed clusters are always deallocated by the QCOW implementation,
+    // regardless of the guest allowing it or not.
+    fn write_zeroes(
+        &mut self,
+        offset: libc::off_t,
+        length: u64,
+        _unmap: bool,
+        user_data: u64,
+    ) -> AsyncIoResult<()> {
+        self.qcow_file.write_zeroes_sync(
+            offset,
+            length,
+            user_data,
+            &self.eventfd,
+            &mut self.completion_list,
+        )
+    }
+
     fn next_completed_request(&mut self) -> Option<(u64, i32)> {
         self.completion_list.pop_front()
     }
@@ -3,7 +3,8 @@
 // SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
 
 use crate::async_io::{
-    AsyncIo, AsyncIoError, AsyncIoResult, DiskFile, DiskFileError, DiskFileResult, DiskTopology,
+    is_block_device, raw_punch_hole, raw_write_zeroes, AsyncIo, AsyncIoError, AsyncIoResult,
+    DiskFile, DiskFileError, DiskFileResult, DiskTopology,
 };
 use io_uring::{opcode, squeue, types, IoUring};
 use std::fs::File;
@@ -43,10 +44,15 @@ impl DiskFile for RawFileDisk {
             DiskTopology::default()
         }
     }
+
+    fn supports_discard(&self) -> bool {
+        true
+    }
 }
 
 pub struct RawFileAsync {
     fd: RawFd,
+    block_device: bool,
     io_uring: IoUring,
     eventfd: EventFd,
 }
@@ -62,10 +68,25 @@ impl RawFileAsync {
 
         Ok(RawFileAsync {
             fd,
+            block_device: is_block_device(fd)?,
             io_uring,
             eventfd,
         })
     }
+
+    // Operations carried out synchronously are still completed through the
+    // ring, so that they are reaped along with the asynchronous ones.
+    fn submit_nop(&mut self, user_data: u64) -> std::io::Result<()> {
+        let (submitter, mut sq, _) = self.io_uring.split();
+
+        // SAFETY: a no-op doesn't access any file descriptor nor buffer.
+        let _ = unsafe { sq.push(&opcode::Nop::new().build().user_data(user_data)) };
+
+        sq.sync();
+        submitter.submit()?;
+
+        Ok(())
+    }
 }
 
 impl AsyncIo for RawFileAsync {
@@ -155,6 +176,30 @@ impl AsyncIo for RawFileAsync {
         Ok(())
     }
 
+    fn punch_hole(
+        &mut self,
+        offset: libc::off_t,
+        length: u64,
+        user_data: u64,
+    ) -> AsyncIoResult<()> {
+        raw_punch_hole(self.fd, self.block_device, offset as u64, length)
+            .map_err(AsyncIoError::PunchHole)?;
+        self.submit_nop(user_data).map_err(AsyncIoError::PunchHole)
+    }
+
+    fn write_zeroes(
+        &mut self,
+        offset: libc::off_t,
+        length: u64,
+        unmap: bool,
+        user_data: u64,
+    ) -> AsyncIoResult<()> {
+        raw_write_zeroes(self.fd, self.block_device, offset as u64, length, unmap)
+            .map_err(AsyncIoError::WriteZeroes)?;
+        self.submit_nop(user_data)
+            .map_err(AsyncIoError::WriteZeroes)
+    }
+
     fn next_completed_request(&mut self) -> Option<(u64, i32)> {
         self.io_uring
             .completion()
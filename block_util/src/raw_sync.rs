@@ -3,7 +3,8 @@
 // SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
 
 use crate::async_io::{
-    AsyncIo, AsyncIoError, AsyncIoResult, DiskFile, DiskFileError, DiskFileResult, DiskTopology,
+    is_block_device, raw_punch_hole, raw_write_zeroes, AsyncIo, AsyncIoError, AsyncIoResult,
+    DiskFile, DiskFileError, DiskFileResult, DiskTopology,
 };
 use std::collections::VecDeque;
 use std::fs::File;
@@ -40,10 +41,15 @@ impl DiskFile for RawFileDiskSync {
             DiskTopology::default()
         }
     }
+
+    fn supports_discard(&self) -> bool {
+        true
+    }
 }
 
 pub struct RawFileSync {
     fd: RawFd,
+    block_device: bool,
     eventfd: EventFd,
     completion_list: VecDeque<(u64, i32)>,
 }
@@ -52,6 +58,7 @@ impl RawFileSync {
     pub fn new(fd: RawFd) -> Self {
         RawFileSync {
             fd,
+            block_device: is_block_device(fd).unwrap_or(false),
             eventfd: EventFd::new(libc::EFD_NONBLOCK).expect("Failed creating EventFd for RawFile"),
             completion_list: VecDeque::new(),
         }
@@ -128,6 +135,37 @@ impl AsyncIo for RawFileSync {
         Ok(())
     }
 
+    fn punch_hole(
+        &mut self,
+        offset: libc::off_t,
+        length: u64,
+        user_data: u64,
+    ) -> AsyncIoResult<()> {
+        raw_punch_hole(self.fd, self.block_device, offset as u64, length)
+            .map_err(AsyncIoError::PunchHole)?;
+
+        self.completion_list.push_back((user_data, 0));
+        self.eventfd.write(1).unwrap();
+
+        Ok(())
+    }
+
+    fn write_zeroes(
+        &mut self,
+        offset: libc::off_t,
+        length: u64,
+        unmap: bool,
+        user_data: u64,
+    ) -> AsyncIoResult<()> {
+        raw_write_zeroes(self.fd, self.block_device, offset as u64, length, unmap)
+            .map_err(AsyncIoError::WriteZeroes)?;
+
+        self.completion_list.push_back((user_data, 0));
+        self.eventfd.write(1).unwrap();
+
+        Ok(())
+    }
+
     fn next_completed_request(&mut self) -> Option<(u64, i32)> {
         self.completion_list.pop_front()
     }
@@ -5,6 +5,7 @@
 
 use super::AcpiNotificationFlags;
 use acpi_tables::{aml, Aml, AmlSink};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Barrier};
 use std::time::Instant;
 use vm_device::interrupt::InterruptSourceGroup;
@@ -14,18 +15,25 @@ use vmm_sys_util::eventfd::EventFd;
 
 pub const GED_DEVICE_ACPI_SIZE: usize = 0x1;
 
-/// A device for handling ACPI shutdown and reboot
+/// A device for handling ACPI shutdown, hibernation and reboot
 pub struct AcpiShutdownDevice {
     exit_evt: EventFd,
     reset_evt: EventFd,
+    hibernated: Arc<AtomicBool>,
 }
 
 impl AcpiShutdownDevice {
     /// Constructs a device that will signal the given event when the guest requests it.
-    pub fn new(exit_evt: EventFd, reset_evt: EventFd) -> AcpiShutdownDevice {
+    /// Hibernation is reported through `hibernated` before signalling the exit event.
+    pub fn new(
+        exit_evt: EventFd,
+        reset_evt: EventFd,
+        hibernated: Arc<AtomicBool>,
+    ) -> AcpiShutdownDevice {
         AcpiShutdownDevice {
             exit_evt,
             reset_evt,
+            hibernated,
         }
     }
 }
@@ -44,10 +52,21 @@ impl BusDevice for AcpiShutdownDevice {
                 error!("Error triggering ACPI reset event: {}", e);
             }
         }
-        // The ACPI DSDT table specifies the S5 sleep state (shutdown) as value 5
+        // The ACPI DSDT table specifies the S4 sleep state (hibernation) as
+        // value 4 and the S5 sleep state (shutdown) as value 5
+        const S4_SLEEP_VALUE: u8 = 4;
         const S5_SLEEP_VALUE: u8 = 5;
         const SLEEP_STATUS_EN_BIT: u8 = 5;
         const SLEEP_VALUE_BIT: u8 = 2;
+        if data[0] == (S4_SLEEP_VALUE << SLEEP_VALUE_BIT) | (1 << SLEEP_STATUS_EN_BIT) {
+            // The guest saved its memory image to its own storage, the
+            // machine can be powered off.
+            info!("ACPI Hibernation signalled");
+            self.hibernated.store(true, Ordering::SeqCst);
+            if let Err(e) = self.exit_evt.write(1) {
+                error!("Error triggering ACPI hibernation event: {}", e);
+            }
+        }
         if data[0] == (S5_SLEEP_VALUE << SLEEP_VALUE_BIT) | (1 << SLEEP_STATUS_EN_BIT) {
             info!("ACPI Shutdown signalled");
             if let Err(e) = self.exit_evt.write(1) {
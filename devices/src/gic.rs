@@ -40,7 +40,7 @@ pub struct Gic {
 
 impl Gic {
     pub fn new(
-        vcpu_count: u8,
+        vcpu_count: u32,
         interrupt_manager: Arc<dyn InterruptManager<GroupConfig = MsiIrqGroupConfig>>,
         vm: Arc<dyn hypervisor::Vm>,
     ) -> Result<Gic> {
@@ -52,7 +52,7 @@ impl Gic {
             .map_err(Error::CreateInterruptSourceGroup)?;
 
         let vgic = vm
-            .create_vgic(Gic::create_default_config(vcpu_count as u64))
+            .create_vgic(Gic::create_default_config(u64::from(vcpu_count)))
             .map_err(Error::CreateGic)?;
 
         let gic = Gic {
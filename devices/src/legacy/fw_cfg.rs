@@ -0,0 +1,456 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! QEMU compatible firmware configuration (fw_cfg) device.
+//!
+//! The device exposes items identified by a 16-bit key, which the guest
+//! selects and then reads either byte by byte through the data register, or
+//! in one go through DMA. Next to a few well-known items, such as the kernel
+//! or the command line, opaque blobs are exposed as named files listed in the
+//! file directory item. See docs/specs/fw_cfg.txt in the QEMU code.
+//!
+//! On x86-64 the registers are I/O ports, the selector at offset 0, the data
+//! at offset 1 and the DMA address at offset 4. On AArch64 they're MMIO, the
+//! data at offset 0, the selector at offset 8 and the DMA address at offset
+//! 16.
+
+use crate::GuestMemoryMmap;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Barrier};
+use std::{fmt, result};
+use vm_device::BusDevice;
+use vm_memory::{Bytes, GuestAddress, GuestAddressSpace, GuestMemoryAtomic};
+
+#[cfg(target_arch = "x86_64")]
+mod registers {
+    pub const SELECTOR: u64 = 0;
+    pub const DATA: u64 = 1;
+    pub const DMA: u64 = 4;
+}
+#[cfg(target_arch = "aarch64")]
+mod registers {
+    pub const DATA: u64 = 0;
+    pub const SELECTOR: u64 = 8;
+    pub const DMA: u64 = 16;
+}
+
+/// Size of the register range.
+#[cfg(target_arch = "x86_64")]
+pub const FW_CFG_REGISTERS_SIZE: u64 = 0xc;
+#[cfg(target_arch = "aarch64")]
+pub const FW_CFG_REGISTERS_SIZE: u64 = 0x18;
+
+const FW_CFG_SIGNATURE: u16 = 0x00;
+const FW_CFG_ID: u16 = 0x01;
+const FW_CFG_KERNEL_SIZE: u16 = 0x08;
+const FW_CFG_INITRD_SIZE: u16 = 0x0b;
+const FW_CFG_KERNEL_DATA: u16 = 0x11;
+const FW_CFG_INITRD_DATA: u16 = 0x12;
+const FW_CFG_CMDLINE_SIZE: u16 = 0x14;
+const FW_CFG_CMDLINE_DATA: u16 = 0x15;
+const FW_CFG_SETUP_SIZE: u16 = 0x17;
+const FW_CFG_SETUP_DATA: u16 = 0x18;
+const FW_CFG_FILE_DIR: u16 = 0x19;
+const FW_CFG_FILE_FIRST: u16 = 0x20;
+// The write channel bit of the selector is obsolete, and not part of the key
+const FW_CFG_WRITE_CHANNEL: u16 = 0x4000;
+
+const FW_CFG_MAX_FILES: usize = 0x1000;
+const FW_CFG_MAX_FILE_NAME: usize = 56;
+
+// Traditional interface and DMA interface
+const FW_CFG_FEATURES: u32 = 0x3;
+// "QEMU CFG", returned when reading the DMA address register
+const FW_CFG_DMA_SIGNATURE: u64 = 0x5145_4d55_2043_4647;
+
+const FW_CFG_DMA_CTL_ERROR: u32 = 0x01;
+const FW_CFG_DMA_CTL_READ: u32 = 0x02;
+const FW_CFG_DMA_CTL_SKIP: u32 = 0x04;
+const FW_CFG_DMA_CTL_SELECT: u32 = 0x08;
+const FW_CFG_DMA_CTL_WRITE: u32 = 0x10;
+// Guest memory is written in chunks of at most this size
+const FW_CFG_DMA_CHUNK_SIZE: usize = 0x1000;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The file name doesn't fit in a directory entry.
+    FileNameTooLong(String),
+    /// A file with the same name is already exposed.
+    DuplicateFile(String),
+    /// The file directory is full.
+    TooManyFiles,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::FileNameTooLong(name) => write!(
+                f,
+                "fw_cfg file name {name} is longer than {} characters",
+                FW_CFG_MAX_FILE_NAME - 1
+            ),
+            Error::DuplicateFile(name) => write!(f, "Duplicate fw_cfg file {name}"),
+            Error::TooManyFiles => write!(f, "Too many fw_cfg files"),
+        }
+    }
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// Firmware configuration device.
+pub struct FwCfg {
+    items: BTreeMap<u16, Vec<u8>>,
+    files: Vec<(String, u16)>,
+    selector: u16,
+    offset: usize,
+    // Big endian guest address of the DMA access descriptor
+    dma_address: [u8; 8],
+    memory: GuestMemoryAtomic<GuestMemoryMmap>,
+}
+
+impl FwCfg {
+    pub fn new(memory: GuestMemoryAtomic<GuestMemoryMmap>) -> Self {
+        let mut items = BTreeMap::new();
+        items.insert(FW_CFG_SIGNATURE, b"QEMU".to_vec());
+        items.insert(FW_CFG_ID, FW_CFG_FEATURES.to_le_bytes().to_vec());
+        items.insert(FW_CFG_FILE_DIR, 0u32.to_be_bytes().to_vec());
+
+        FwCfg {
+            items,
+            files: Vec::new(),
+            selector: 0,
+            offset: 0,
+            dma_address: [0; 8],
+            memory,
+        }
+    }
+
+    /// Exposes the kernel, split into its real mode setup code and the rest
+    /// of the image. The setup is empty for anything but an x86 bzImage.
+    pub fn add_kernel(&mut self, setup: Vec<u8>, kernel: Vec<u8>) {
+        self.add_sized_item(FW_CFG_SETUP_SIZE, FW_CFG_SETUP_DATA, setup);
+        self.add_sized_item(FW_CFG_KERNEL_SIZE, FW_CFG_KERNEL_DATA, kernel);
+    }
+
+    pub fn add_initramfs(&mut self, initramfs: Vec<u8>) {
+        self.add_sized_item(FW_CFG_INITRD_SIZE, FW_CFG_INITRD_DATA, initramfs);
+    }
+
+    pub fn add_cmdline(&mut self, cmdline: &str) {
+        let mut data = cmdline.as_bytes().to_vec();
+        data.push(0);
+        self.add_sized_item(FW_CFG_CMDLINE_SIZE, FW_CFG_CMDLINE_DATA, data);
+    }
+
+    /// Exposes an opaque blob under the given name, e.g. `opt/org.example/blob`.
+    pub fn add_file(&mut self, name: &str, data: Vec<u8>) -> Result<()> {
+        if name.len() >= FW_CFG_MAX_FILE_NAME {
+            return Err(Error::FileNameTooLong(name.to_string()));
+        }
+        if self.files.iter().any(|(n, _)| n == name) {
+            return Err(Error::DuplicateFile(name.to_string()));
+        }
+        if self.files.len() >= FW_CFG_MAX_FILES {
+            return Err(Error::TooManyFiles);
+        }
+
+        let key = FW_CFG_FILE_FIRST + self.files.len() as u16;
+        self.items.insert(key, data);
+        self.files.push((name.to_string(), key));
+        // The directory is sorted by name
+        self.files.sort();
+        self.update_file_dir();
+
+        Ok(())
+    }
+
+    fn add_sized_item(&mut self, size_key: u16, data_key: u16, data: Vec<u8>) {
+        self.items
+            .insert(size_key, (data.len() as u32).to_le_bytes().to_vec());
+        self.items.insert(data_key, data);
+    }
+
+    // Each entry is made of the size, the key, a reserved field and the name,
+    // all fields being big endian.
+    fn update_file_dir(&mut self) {
+        let mut dir = (self.files.len() as u32).to_be_bytes().to_vec();
+        for (name, key) in self.files.iter() {
+            let size = self.items.get(key).map(|d| d.len()).unwrap_or_default();
+            dir.extend_from_slice(&(size as u32).to_be_bytes());
+            dir.extend_from_slice(&key.to_be_bytes());
+            dir.extend_from_slice(&0u16.to_be_bytes());
+            let mut entry_name = [0u8; FW_CFG_MAX_FILE_NAME];
+            entry_name[..name.len()].copy_from_slice(name.as_bytes());
+            dir.extend_from_slice(&entry_name);
+        }
+        self.items.insert(FW_CFG_FILE_DIR, dir);
+    }
+
+    fn select(&mut self, selector: u16) {
+        self.selector = selector & !FW_CFG_WRITE_CHANNEL;
+        self.offset = 0;
+    }
+
+    // Copies the selected item from the current offset, missing items and
+    // data past their end reading as zeroes.
+    fn read_data(&mut self, data: &mut [u8]) {
+        let item = self
+            .items
+            .get(&self.selector)
+            .map(|i| i.as_slice())
+            .unwrap_or_default();
+        for byte in data.iter_mut() {
+            *byte = item.get(self.offset).copied().unwrap_or(0);
+            self.offset = self.offset.saturating_add(1);
+        }
+    }
+
+    fn dma_transfer(&mut self, control: u32, length: u32, address: u64) -> u32 {
+        if control & FW_CFG_DMA_CTL_SELECT != 0 {
+            self.select((control >> 16) as u16);
+        }
+
+        // The length is controlled by the guest, only what remains of the
+        // selected item is transferred or skipped.
+        let item = self
+            .items
+            .get(&self.selector)
+            .map(|i| i.as_slice())
+            .unwrap_or_default();
+        let remaining = item.get(self.offset..).unwrap_or_default();
+        let length = remaining.len().min(length as usize);
+
+        if control & FW_CFG_DMA_CTL_READ != 0 {
+            let mem = self.memory.memory();
+            for (i, chunk) in remaining[..length]
+                .chunks(FW_CFG_DMA_CHUNK_SIZE)
+                .enumerate()
+            {
+                let chunk_address =
+                    match GuestAddress(address).checked_add((i * FW_CFG_DMA_CHUNK_SIZE) as u64) {
+                        Some(chunk_address) => chunk_address,
+                        None => return FW_CFG_DMA_CTL_ERROR,
+                    };
+                if mem.write_slice(chunk, chunk_address).is_err() {
+                    return FW_CFG_DMA_CTL_ERROR;
+                }
+                self.offset += chunk.len();
+            }
+        } else if control & FW_CFG_DMA_CTL_SKIP != 0 {
+            self.offset += length;
+        } else if control & FW_CFG_DMA_CTL_WRITE != 0 {
+            // None of the items is writable
+            return FW_CFG_DMA_CTL_ERROR;
+        }
+
+        0
+    }
+
+    // Processes the access described at the given address, the descriptor
+    // being made of a 32-bit control, a 32-bit length and a 64-bit address,
+    // all big endian. The control is cleared once the transfer completes.
+    fn dma(&mut self, descriptor: u64) {
+        let mut access = [0u8; 16];
+        let mem = self.memory.memory();
+        if let Err(e) = mem.read_slice(&mut access, GuestAddress(descriptor)) {
+            error!("Invalid fw_cfg DMA descriptor address: {:?}", e);
+            return;
+        }
+        drop(mem);
+
+        let control = u32::from_be_bytes(access[0..4].try_into().unwrap());
+        let length = u32::from_be_bytes(access[4..8].try_into().unwrap());
+        let address = u64::from_be_bytes(access[8..16].try_into().unwrap());
+
+        let status = self.dma_transfer(control, length, address);
+        if let Err(e) = self
+            .memory
+            .memory()
+            .write_slice(&status.to_be_bytes(), GuestAddress(descriptor))
+        {
+            error!("Error completing fw_cfg DMA access: {:?}", e);
+        }
+    }
+}
+
+impl BusDevice for FwCfg {
+    fn read(&mut self, _base: u64, offset: u64, data: &mut [u8]) {
+        match offset {
+            o if o == registers::DATA => self.read_data(data),
+            o if (registers::DMA..registers::DMA + 8).contains(&o) => {
+                let start = (o - registers::DMA) as usize;
+                let signature = FW_CFG_DMA_SIGNATURE.to_be_bytes();
+                for (byte, s) in data.iter_mut().zip(signature[start..].iter()) {
+                    *byte = *s;
+                }
+            }
+            _ => {
+                warn!("Invalid fw_cfg read: offset {:#x}", offset);
+                data.fill(0);
+            }
+        }
+    }
+
+    fn write(&mut self, _base: u64, offset: u64, data: &[u8]) -> Option<Arc<Barrier>> {
+        match offset {
+            o if o == registers::SELECTOR && data.len() == 2 => {
+                let selector = [data[0], data[1]];
+                // Little endian as an I/O port, big endian as MMIO
+                #[cfg(target_arch = "x86_64")]
+                self.select(u16::from_le_bytes(selector));
+                #[cfg(target_arch = "aarch64")]
+                self.select(u16::from_be_bytes(selector));
+            }
+            o if (registers::DMA..registers::DMA + 8).contains(&o) => {
+                let start = (o - registers::DMA) as usize;
+                let end = (start + data.len()).min(8);
+                self.dma_address[start..end].copy_from_slice(&data[..end - start]);
+                // Writing the low half of the address triggers the transfer
+                if end == 8 {
+                    let descriptor = u64::from_be_bytes(self.dma_address);
+                    self.dma_address = [0; 8];
+                    self.dma(descriptor);
+                }
+            }
+            _ => warn!(
+                "Invalid fw_cfg write: offset {:#x}, size {}",
+                offset,
+                data.len()
+            ),
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DMA_DESCRIPTOR: u64 = 0x1000;
+    const DMA_BUFFER: u64 = 0x2000;
+
+    fn fw_cfg() -> FwCfg {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap();
+        FwCfg::new(GuestMemoryAtomic::new(mem))
+    }
+
+    fn select(fw_cfg: &mut FwCfg, selector: u16) {
+        #[cfg(target_arch = "x86_64")]
+        let selector = selector.to_le_bytes();
+        #[cfg(target_arch = "aarch64")]
+        let selector = selector.to_be_bytes();
+        fw_cfg.write(0, registers::SELECTOR, &selector);
+    }
+
+    fn read(fw_cfg: &mut FwCfg, len: usize) -> Vec<u8> {
+        let mut data = vec![0u8; len];
+        for byte in data.iter_mut() {
+            fw_cfg.read(0, registers::DATA, std::slice::from_mut(byte));
+        }
+        data
+    }
+
+    #[test]
+    fn test_signature() {
+        let mut fw_cfg = fw_cfg();
+        select(&mut fw_cfg, FW_CFG_SIGNATURE);
+        assert_eq!(read(&mut fw_cfg, 4), b"QEMU");
+        select(&mut fw_cfg, FW_CFG_ID);
+        assert_eq!(read(&mut fw_cfg, 4), FW_CFG_FEATURES.to_le_bytes());
+
+        let mut signature = [0u8; 8];
+        fw_cfg.read(0, registers::DMA, &mut signature);
+        assert_eq!(&signature, b"QEMU CFG");
+    }
+
+    #[test]
+    fn test_file_dir() {
+        let mut fw_cfg = fw_cfg();
+        fw_cfg.add_file("opt/org.test/b", vec![1, 2]).unwrap();
+        fw_cfg.add_file("opt/org.test/a", vec![3]).unwrap();
+        assert!(fw_cfg.add_file("opt/org.test/a", vec![4]).is_err());
+        assert!(fw_cfg.add_file(&"x".repeat(56), vec![]).is_err());
+
+        select(&mut fw_cfg, FW_CFG_FILE_DIR);
+        assert_eq!(read(&mut fw_cfg, 4), 2u32.to_be_bytes());
+        let entry = read(&mut fw_cfg, 64);
+        assert_eq!(&entry[0..4], 1u32.to_be_bytes());
+        assert_eq!(&entry[4..6], (FW_CFG_FILE_FIRST + 1).to_be_bytes());
+        assert_eq!(&entry[8..22], b"opt/org.test/a");
+        assert_eq!(entry[22], 0);
+
+        select(&mut fw_cfg, FW_CFG_FILE_FIRST);
+        assert_eq!(read(&mut fw_cfg, 3), [1, 2, 0]);
+    }
+
+    // Runs a DMA access through the descriptor, returning its control once
+    // completed.
+    fn dma(fw_cfg: &mut FwCfg, control: u32, length: u32) -> u32 {
+        let mut access = control.to_be_bytes().to_vec();
+        access.extend_from_slice(&length.to_be_bytes());
+        access.extend_from_slice(&DMA_BUFFER.to_be_bytes());
+        let mem = fw_cfg.memory.memory();
+        mem.write_slice(&access, GuestAddress(DMA_DESCRIPTOR))
+            .unwrap();
+        drop(mem);
+
+        let descriptor = DMA_DESCRIPTOR.to_be_bytes();
+        fw_cfg.write(0, registers::DMA, &descriptor[0..4]);
+        fw_cfg.write(0, registers::DMA + 4, &descriptor[4..8]);
+
+        let mut control = [0u8; 4];
+        fw_cfg
+            .memory
+            .memory()
+            .read_slice(&mut control, GuestAddress(DMA_DESCRIPTOR))
+            .unwrap();
+        u32::from_be_bytes(control)
+    }
+
+    #[test]
+    fn test_dma() {
+        let mut fw_cfg = fw_cfg();
+        fw_cfg.add_cmdline("console=ttyS0");
+
+        let control =
+            (FW_CFG_CMDLINE_DATA as u32) << 16 | FW_CFG_DMA_CTL_SELECT | FW_CFG_DMA_CTL_READ;
+        assert_eq!(dma(&mut fw_cfg, control, 14), 0);
+
+        let mut cmdline = [0u8; 14];
+        fw_cfg
+            .memory
+            .memory()
+            .read_slice(&mut cmdline, GuestAddress(DMA_BUFFER))
+            .unwrap();
+        assert_eq!(&cmdline, b"console=ttyS0\0");
+    }
+
+    #[test]
+    fn test_dma_length_clamped() {
+        let mut fw_cfg = fw_cfg();
+        fw_cfg.add_file("opt/org.test/a", vec![1; 0x1800]).unwrap();
+
+        // Only what remains of the item is written, despite the length
+        // going past the end of guest memory.
+        let control =
+            (FW_CFG_FILE_FIRST as u32) << 16 | FW_CFG_DMA_CTL_SELECT | FW_CFG_DMA_CTL_SKIP;
+        assert_eq!(dma(&mut fw_cfg, control, 0x800), 0);
+        assert_eq!(dma(&mut fw_cfg, FW_CFG_DMA_CTL_READ, u32::MAX), 0);
+        assert_eq!(fw_cfg.offset, 0x1800);
+
+        let mut data = vec![0u8; 0x1001];
+        fw_cfg
+            .memory
+            .memory()
+            .read_slice(&mut data, GuestAddress(DMA_BUFFER))
+            .unwrap();
+        assert!(data[..0x1000].iter().all(|b| *b == 1));
+        assert_eq!(data[0x1000], 0);
+
+        // Skipping past the end doesn't move the offset further
+        assert_eq!(dma(&mut fw_cfg, FW_CFG_DMA_CTL_SKIP, u32::MAX), 0);
+        assert_eq!(fw_cfg.offset, 0x1800);
+    }
+}
@@ -2,44 +2,950 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE-BSD-3-Clause file.
 
+use std::collections::VecDeque;
 use std::sync::{Arc, Barrier};
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+use vm_device::interrupt::InterruptSourceGroup;
 use vm_device::BusDevice;
+use vm_migration::{
+    Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable, VersionMapped,
+};
 use vmm_sys_util::eventfd::EventFd;
 
-/// A i8042 PS/2 controller that emulates just enough to shutdown the machine.
+// Offsets from the i8042 base I/O port 0x60
+const DATA_OFFSET: u64 = 0x0; // 0x60
+const PORT_B_OFFSET: u64 = 0x1; // 0x61
+const COMMAND_OFFSET: u64 = 0x4; // 0x64
+
+// Status register bits
+const STATUS_OBF: u8 = 0x01;
+const STATUS_SYS: u8 = 0x04;
+const STATUS_CMD: u8 = 0x08;
+const STATUS_UNLOCKED: u8 = 0x10;
+const STATUS_AUX_OBF: u8 = 0x20;
+
+// Controller configuration byte bits
+const CTR_KBD_INT: u8 = 0x01;
+const CTR_AUX_INT: u8 = 0x02;
+const CTR_SYS: u8 = 0x04;
+const CTR_KBD_DISABLE: u8 = 0x10;
+const CTR_AUX_DISABLE: u8 = 0x20;
+const CTR_XLATE: u8 = 0x40;
+const DEFAULT_CTR: u8 = CTR_KBD_INT | CTR_SYS | CTR_AUX_DISABLE | CTR_XLATE;
+
+// Controller commands
+const CMD_READ_CTR: u8 = 0x20;
+const CMD_WRITE_CTR: u8 = 0x60;
+const CMD_AUX_DISABLE: u8 = 0xa7;
+const CMD_AUX_ENABLE: u8 = 0xa8;
+const CMD_AUX_TEST: u8 = 0xa9;
+const CMD_SELF_TEST: u8 = 0xaa;
+const CMD_KBD_TEST: u8 = 0xab;
+const CMD_KBD_DISABLE: u8 = 0xad;
+const CMD_KBD_ENABLE: u8 = 0xae;
+const CMD_READ_OUTPUT_PORT: u8 = 0xd0;
+const CMD_WRITE_OUTPUT_PORT: u8 = 0xd1;
+const CMD_KBD_LOOP: u8 = 0xd2;
+const CMD_AUX_LOOP: u8 = 0xd3;
+const CMD_AUX_SEND: u8 = 0xd4;
+const CMD_RESET_CPU: u8 = 0xfe;
+
+// Controller responses
+const SELF_TEST_OK: u8 = 0x55;
+const INTERFACE_TEST_OK: u8 = 0x00;
+// A20 enabled and reset line deasserted
+const DEFAULT_OUTPUT_PORT: u8 = 0x03;
+
+// Commands understood by both the keyboard and the mouse
+const DEV_GET_ID: u8 = 0xf2;
+const DEV_SET_RATE: u8 = 0xf3;
+const DEV_ENABLE: u8 = 0xf4;
+const DEV_DISABLE: u8 = 0xf5;
+const DEV_SET_DEFAULTS: u8 = 0xf6;
+const DEV_RESET: u8 = 0xff;
+
+// Keyboard specific commands
+const KBD_SET_LEDS: u8 = 0xed;
+const KBD_ECHO: u8 = 0xee;
+const KBD_SCANCODE_SET: u8 = 0xf0;
+
+// Mouse specific commands
+const AUX_SET_SCALING_1_1: u8 = 0xe6;
+const AUX_SET_SCALING_2_1: u8 = 0xe7;
+const AUX_SET_RESOLUTION: u8 = 0xe8;
+const AUX_STATUS_REQUEST: u8 = 0xe9;
+const AUX_SET_STREAM_MODE: u8 = 0xea;
+const AUX_READ_DATA: u8 = 0xeb;
+const AUX_SET_REMOTE_MODE: u8 = 0xf0;
+
+// Device responses
+const DEV_ACK: u8 = 0xfa;
+const DEV_RESEND: u8 = 0xfe;
+const DEV_SELF_TEST_OK: u8 = 0xaa;
+const KBD_ID: u8 = 0xab;
+// Second keyboard ID byte, as seen with and without scancode translation
+const KBD_ID_XLATE: u8 = 0x41;
+const KBD_ID_RAW: u8 = 0x83;
+const AUX_ID: u8 = 0x00;
+
+const DEFAULT_AUX_SAMPLE_RATE: u8 = 100;
+const DEFAULT_AUX_RESOLUTION: u8 = 2;
+
+// Bytes the guest hasn't consumed yet beyond which input is dropped
+const OUTPUT_QUEUE_SIZE: usize = 1024;
+
+// Scancode set 1 prefix for the extended keys
+const EXTENDED_PREFIX: u8 = 0xe0;
+// Scancode set 1 break codes are the make codes with the top bit set
+const BREAK_BIT: u8 = 0x80;
+
+// Key names, following the QEMU sendkey naming, along with their scancode set
+// 1 make code and whether they are part of the extended (0xe0 prefixed) set.
+const KEYS: &[(&str, u8, bool)] = &[
+    ("esc", 0x01, false),
+    ("1", 0x02, false),
+    ("2", 0x03, false),
+    ("3", 0x04, false),
+    ("4", 0x05, false),
+    ("5", 0x06, false),
+    ("6", 0x07, false),
+    ("7", 0x08, false),
+    ("8", 0x09, false),
+    ("9", 0x0a, false),
+    ("0", 0x0b, false),
+    ("minus", 0x0c, false),
+    ("equal", 0x0d, false),
+    ("backspace", 0x0e, false),
+    ("tab", 0x0f, false),
+    ("q", 0x10, false),
+    ("w", 0x11, false),
+    ("e", 0x12, false),
+    ("r", 0x13, false),
+    ("t", 0x14, false),
+    ("y", 0x15, false),
+    ("u", 0x16, false),
+    ("i", 0x17, false),
+    ("o", 0x18, false),
+    ("p", 0x19, false),
+    ("bracket_left", 0x1a, false),
+    ("bracket_right", 0x1b, false),
+    ("ret", 0x1c, false),
+    ("enter", 0x1c, false),
+    ("ctrl", 0x1d, false),
+    ("a", 0x1e, false),
+    ("s", 0x1f, false),
+    ("d", 0x20, false),
+    ("f", 0x21, false),
+    ("g", 0x22, false),
+    ("h", 0x23, false),
+    ("j", 0x24, false),
+    ("k", 0x25, false),
+    ("l", 0x26, false),
+    ("semicolon", 0x27, false),
+    ("apostrophe", 0x28, false),
+    ("grave_accent", 0x29, false),
+    ("shift", 0x2a, false),
+    ("backslash", 0x2b, false),
+    ("z", 0x2c, false),
+    ("x", 0x2d, false),
+    ("c", 0x2e, false),
+    ("v", 0x2f, false),
+    ("b", 0x30, false),
+    ("n", 0x31, false),
+    ("m", 0x32, false),
+    ("comma", 0x33, false),
+    ("dot", 0x34, false),
+    ("slash", 0x35, false),
+    ("shift_r", 0x36, false),
+    ("kp_multiply", 0x37, false),
+    ("alt", 0x38, false),
+    ("spc", 0x39, false),
+    ("space", 0x39, false),
+    ("caps_lock", 0x3a, false),
+    ("f1", 0x3b, false),
+    ("f2", 0x3c, false),
+    ("f3", 0x3d, false),
+    ("f4", 0x3e, false),
+    ("f5", 0x3f, false),
+    ("f6", 0x40, false),
+    ("f7", 0x41, false),
+    ("f8", 0x42, false),
+    ("f9", 0x43, false),
+    ("f10", 0x44, false),
+    ("num_lock", 0x45, false),
+    ("scroll_lock", 0x46, false),
+    ("kp_7", 0x47, false),
+    ("kp_8", 0x48, false),
+    ("kp_9", 0x49, false),
+    ("kp_subtract", 0x4a, false),
+    ("kp_4", 0x4b, false),
+    ("kp_5", 0x4c, false),
+    ("kp_6", 0x4d, false),
+    ("kp_add", 0x4e, false),
+    ("kp_1", 0x4f, false),
+    ("kp_2", 0x50, false),
+    ("kp_3", 0x51, false),
+    ("kp_0", 0x52, false),
+    ("kp_decimal", 0x53, false),
+    ("less", 0x56, false),
+    ("f11", 0x57, false),
+    ("f12", 0x58, false),
+    ("kp_enter", 0x1c, true),
+    ("ctrl_r", 0x1d, true),
+    ("kp_divide", 0x35, true),
+    ("alt_r", 0x38, true),
+    ("home", 0x47, true),
+    ("up", 0x48, true),
+    ("pgup", 0x49, true),
+    ("left", 0x4b, true),
+    ("right", 0x4d, true),
+    ("end", 0x4f, true),
+    ("down", 0x50, true),
+    ("pgdn", 0x51, true),
+    ("insert", 0x52, true),
+    ("delete", 0x53, true),
+    ("del", 0x53, true),
+    ("meta_l", 0x5b, true),
+    ("meta_r", 0x5c, true),
+    ("menu", 0x5d, true),
+];
+
+/// A key of the emulated PS/2 keyboard.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Key {
+    scancode: u8,
+    extended: bool,
+}
+
+impl Key {
+    /// Looks a key up by its name, e.g. "a", "ret", "ctrl" or "f12".
+    pub fn from_name(name: &str) -> Option<Key> {
+        let name = name.to_lowercase();
+        KEYS.iter()
+            .find(|(key_name, _, _)| *key_name == name)
+            .map(|(_, scancode, extended)| Key {
+                scancode: *scancode,
+                extended: *extended,
+            })
+    }
+
+    /// Parses a combination of keys pressed together, e.g. "ctrl-alt-delete".
+    pub fn parse_combination(combination: &str) -> Option<Vec<Key>> {
+        combination.split('-').map(Key::from_name).collect()
+    }
+
+    /// Returns the Linux input event code of the key.
+    pub fn code(&self) -> u16 {
+        // Outside of the extended set, the Linux codes are the scancode set 1
+        // make codes.
+        if !self.extended {
+            return self.scancode as u16;
+        }
+
+        match self.scancode {
+            0x1c => 96,  // kp_enter
+            0x1d => 97,  // ctrl_r
+            0x35 => 98,  // kp_divide
+            0x38 => 100, // alt_r
+            0x47 => 102, // home
+            0x48 => 103, // up
+            0x49 => 104, // pgup
+            0x4b => 105, // left
+            0x4d => 106, // right
+            0x4f => 107, // end
+            0x50 => 108, // down
+            0x51 => 109, // pgdn
+            0x52 => 110, // insert
+            0x53 => 111, // delete
+            0x5b => 125, // meta_l
+            0x5c => 126, // meta_r
+            0x5d => 127, // menu
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Interrupts raised by the keyboard and auxiliary (mouse) ports.
+pub struct Ps2Interrupts {
+    pub keyboard: Arc<dyn InterruptSourceGroup>,
+    pub aux: Arc<dyn InterruptSourceGroup>,
+}
+
+struct Ps2Keyboard {
+    scanning: bool,
+    pending_command: Option<u8>,
+}
+
+impl Default for Ps2Keyboard {
+    fn default() -> Self {
+        Ps2Keyboard {
+            scanning: true,
+            pending_command: None,
+        }
+    }
+}
+
+struct Ps2Mouse {
+    reporting: bool,
+    remote: bool,
+    scaling_2_1: bool,
+    sample_rate: u8,
+    resolution: u8,
+    buttons: u8,
+    pending_command: Option<u8>,
+}
+
+impl Default for Ps2Mouse {
+    fn default() -> Self {
+        Ps2Mouse {
+            reporting: false,
+            remote: false,
+            scaling_2_1: false,
+            sample_rate: DEFAULT_AUX_SAMPLE_RATE,
+            resolution: DEFAULT_AUX_RESOLUTION,
+            buttons: 0,
+            pending_command: None,
+        }
+    }
+}
+
+/// A i8042 PS/2 controller. Without interrupts it emulates just enough to
+/// shutdown the machine, otherwise it also emulates a PS/2 keyboard and mouse
+/// which can be fed with input events from the VMM.
+///
+/// Keyboard input is emitted as scancode set 1, which is what the guest gets
+/// from the controller as long as it keeps scancode translation enabled.
 pub struct I8042Device {
+    id: String,
     reset_evt: EventFd,
+    interrupts: Option<Ps2Interrupts>,
+    ctr: u8,
+    last_write_command: bool,
+    pending_command: Option<u8>,
+    // Bytes waiting for the guest, along with whether they come from the
+    // auxiliary port.
+    output: VecDeque<(u8, bool)>,
+    keyboard: Ps2Keyboard,
+    mouse: Ps2Mouse,
 }
 
+#[derive(Versionize)]
+pub struct I8042State {
+    ctr: u8,
+    last_write_command: bool,
+    pending_command: Option<u8>,
+    output: Vec<u8>,
+    output_aux: Vec<bool>,
+    keyboard_scanning: bool,
+    keyboard_pending_command: Option<u8>,
+    mouse_reporting: bool,
+    mouse_remote: bool,
+    mouse_scaling_2_1: bool,
+    mouse_sample_rate: u8,
+    mouse_resolution: u8,
+    mouse_buttons: u8,
+    mouse_pending_command: Option<u8>,
+}
+impl VersionMapped for I8042State {}
+
 impl I8042Device {
-    /// Constructs a i8042 device that will signal the given event when the guest requests it.
-    pub fn new(reset_evt: EventFd) -> I8042Device {
-        I8042Device { reset_evt }
+    /// Constructs a i8042 device that will signal the given event when the
+    /// guest requests it. The PS/2 keyboard and mouse are only emulated when
+    /// `interrupts` are provided.
+    pub fn new(
+        id: String,
+        reset_evt: EventFd,
+        interrupts: Option<Ps2Interrupts>,
+        state: Option<I8042State>,
+    ) -> I8042Device {
+        let mut i8042 = I8042Device {
+            id,
+            reset_evt,
+            interrupts,
+            ctr: DEFAULT_CTR,
+            last_write_command: false,
+            pending_command: None,
+            output: VecDeque::new(),
+            keyboard: Ps2Keyboard::default(),
+            mouse: Ps2Mouse::default(),
+        };
+
+        if let Some(state) = state {
+            i8042.set_state(state);
+        }
+
+        i8042
+    }
+
+    fn state(&self) -> I8042State {
+        I8042State {
+            ctr: self.ctr,
+            last_write_command: self.last_write_command,
+            pending_command: self.pending_command,
+            output: self.output.iter().map(|(byte, _)| *byte).collect(),
+            output_aux: self.output.iter().map(|(_, aux)| *aux).collect(),
+            keyboard_scanning: self.keyboard.scanning,
+            keyboard_pending_command: self.keyboard.pending_command,
+            mouse_reporting: self.mouse.reporting,
+            mouse_remote: self.mouse.remote,
+            mouse_scaling_2_1: self.mouse.scaling_2_1,
+            mouse_sample_rate: self.mouse.sample_rate,
+            mouse_resolution: self.mouse.resolution,
+            mouse_buttons: self.mouse.buttons,
+            mouse_pending_command: self.mouse.pending_command,
+        }
+    }
+
+    fn set_state(&mut self, state: I8042State) {
+        self.ctr = state.ctr;
+        self.last_write_command = state.last_write_command;
+        self.pending_command = state.pending_command;
+        self.output = state.output.into_iter().zip(state.output_aux).collect();
+        self.keyboard = Ps2Keyboard {
+            scanning: state.keyboard_scanning,
+            pending_command: state.keyboard_pending_command,
+        };
+        self.mouse = Ps2Mouse {
+            reporting: state.mouse_reporting,
+            remote: state.mouse_remote,
+            scaling_2_1: state.mouse_scaling_2_1,
+            sample_rate: state.mouse_sample_rate,
+            resolution: state.mouse_resolution,
+            buttons: state.mouse_buttons,
+            pending_command: state.mouse_pending_command,
+        };
+    }
+
+    /// Returns whether the guest has enabled the keyboard.
+    pub fn keyboard_ready(&self) -> bool {
+        self.interrupts.is_some() && self.keyboard.scanning && self.ctr & CTR_KBD_DISABLE == 0
+    }
+
+    /// Returns whether the guest has enabled the mouse in stream mode.
+    pub fn mouse_ready(&self) -> bool {
+        self.interrupts.is_some()
+            && self.mouse.reporting
+            && !self.mouse.remote
+            && self.ctr & CTR_AUX_DISABLE == 0
+    }
+
+    /// Queues the make (pressed) or break (released) scancode of `key`.
+    /// Returns false if the guest hasn't enabled the keyboard.
+    pub fn key_event(&mut self, key: Key, pressed: bool) -> bool {
+        if !self.keyboard_ready() {
+            return false;
+        }
+
+        if self.ctr & CTR_XLATE == 0 {
+            warn!("i8042 scancode translation is disabled, the guest may misread keys");
+        }
+
+        if key.extended {
+            self.push(EXTENDED_PREFIX, false);
+        }
+        let scancode = if pressed {
+            key.scancode
+        } else {
+            key.scancode | BREAK_BIT
+        };
+        self.push(scancode, false);
+
+        true
+    }
+
+    /// Queues relative mouse movement packets, with `dy` growing downwards.
+    /// `buttons` is a bitmap of the left (bit 0), right (bit 1) and middle
+    /// (bit 2) buttons. Returns false if the guest hasn't enabled the mouse.
+    pub fn pointer_event(&mut self, dx: i32, dy: i32, buttons: u8) -> bool {
+        if !self.mouse_ready() {
+            return false;
+        }
+
+        self.mouse.buttons = buttons & 0x7;
+
+        // The PS/2 Y axis grows upwards, and each packet carries 9 bits
+        // signed deltas.
+        let (mut dx, mut dy) = (dx, dy.saturating_neg());
+        loop {
+            let x = dx.clamp(-256, 255);
+            let y = dy.clamp(-256, 255);
+            dx -= x;
+            dy -= y;
+
+            self.push_mouse_packet(x, y);
+
+            if dx == 0 && dy == 0 {
+                break;
+            }
+        }
+
+        true
+    }
+
+    fn push_mouse_packet(&mut self, x: i32, y: i32) {
+        let mut header = 0x08 | self.mouse.buttons;
+        if x < 0 {
+            header |= 0x10;
+        }
+        if y < 0 {
+            header |= 0x20;
+        }
+
+        self.push(header, true);
+        self.push(x as u8, true);
+        self.push(y as u8, true);
+    }
+
+    fn push(&mut self, byte: u8, aux: bool) {
+        if self.output.len() >= OUTPUT_QUEUE_SIZE {
+            warn!("i8042 output queue full, dropping byte 0x{:x}", byte);
+            return;
+        }
+
+        self.output.push_back((byte, aux));
+        if self.output.len() == 1 {
+            self.signal_output();
+        }
+    }
+
+    // Raise the interrupt matching the byte at the head of the output queue.
+    fn signal_output(&self) {
+        let (interrupts, aux) = match (self.interrupts.as_ref(), self.output.front()) {
+            (Some(interrupts), Some((_, aux))) => (interrupts, aux),
+            _ => return,
+        };
+
+        let result = if *aux {
+            if self.ctr & CTR_AUX_INT == 0 {
+                return;
+            }
+            interrupts.aux.trigger(0)
+        } else {
+            if self.ctr & CTR_KBD_INT == 0 {
+                return;
+            }
+            interrupts.keyboard.trigger(0)
+        };
+
+        if let Err(e) = result {
+            error!("Failed to trigger i8042 interrupt: {:?}", e);
+        }
+    }
+
+    fn status(&self) -> u8 {
+        let mut status = STATUS_UNLOCKED;
+        if self.ctr & CTR_SYS != 0 {
+            status |= STATUS_SYS;
+        }
+        if let Some((_, aux)) = self.output.front() {
+            status |= STATUS_OBF;
+            if *aux {
+                status |= STATUS_AUX_OBF;
+            }
+        }
+        if self.last_write_command {
+            status |= STATUS_CMD;
+        }
+
+        status
+    }
+
+    fn controller_command(&mut self, command: u8) {
+        match command {
+            CMD_READ_CTR => self.push(self.ctr, false),
+            CMD_WRITE_CTR | CMD_WRITE_OUTPUT_PORT | CMD_KBD_LOOP | CMD_AUX_LOOP | CMD_AUX_SEND => {
+                self.pending_command = Some(command)
+            }
+            CMD_AUX_DISABLE => self.ctr |= CTR_AUX_DISABLE,
+            CMD_AUX_ENABLE => self.ctr &= !CTR_AUX_DISABLE,
+            CMD_AUX_TEST | CMD_KBD_TEST => self.push(INTERFACE_TEST_OK, false),
+            CMD_SELF_TEST => self.push(SELF_TEST_OK, false),
+            CMD_KBD_DISABLE => self.ctr |= CTR_KBD_DISABLE,
+            CMD_KBD_ENABLE => self.ctr &= !CTR_KBD_DISABLE,
+            CMD_READ_OUTPUT_PORT => self.push(DEFAULT_OUTPUT_PORT, false),
+            _ => debug!("Unsupported i8042 command 0x{:x}", command),
+        }
+    }
+
+    fn controller_data(&mut self, command: u8, data: u8) {
+        match command {
+            CMD_WRITE_CTR => {
+                self.ctr = data;
+                // Interrupts may just have been enabled for pending output.
+                self.signal_output();
+            }
+            CMD_WRITE_OUTPUT_PORT => {}
+            CMD_KBD_LOOP => self.push(data, false),
+            CMD_AUX_LOOP => self.push(data, true),
+            CMD_AUX_SEND => self.mouse_command(data),
+            _ => unreachable!(),
+        }
+    }
+
+    fn keyboard_command(&mut self, command: u8) {
+        if let Some(pending_command) = self.keyboard.pending_command.take() {
+            self.push(DEV_ACK, false);
+            // Scancode set 2 is reported, the guest sees scancode set 1
+            // through the controller translation.
+            if pending_command == KBD_SCANCODE_SET && command == 0 {
+                self.push(0x2, false);
+            }
+            return;
+        }
+
+        match command {
+            KBD_SET_LEDS | DEV_SET_RATE | KBD_SCANCODE_SET => {
+                self.keyboard.pending_command = Some(command);
+                self.push(DEV_ACK, false);
+            }
+            KBD_ECHO => self.push(KBD_ECHO, false),
+            DEV_GET_ID => {
+                self.push(DEV_ACK, false);
+                self.push(KBD_ID, false);
+                if self.ctr & CTR_XLATE != 0 {
+                    self.push(KBD_ID_XLATE, false);
+                } else {
+                    self.push(KBD_ID_RAW, false);
+                }
+            }
+            DEV_ENABLE => {
+                self.keyboard.scanning = true;
+                self.push(DEV_ACK, false);
+            }
+            DEV_DISABLE => {
+                self.keyboard.scanning = false;
+                self.push(DEV_ACK, false);
+            }
+            DEV_SET_DEFAULTS => self.push(DEV_ACK, false),
+            DEV_RESET => {
+                self.keyboard = Ps2Keyboard::default();
+                self.push(DEV_ACK, false);
+                self.push(DEV_SELF_TEST_OK, false);
+            }
+            // Typematic and make/break mode commands
+            0xf7..=0xfd => self.push(DEV_ACK, false),
+            _ => self.push(DEV_RESEND, false),
+        }
+    }
+
+    fn mouse_command(&mut self, command: u8) {
+        if let Some(pending_command) = self.mouse.pending_command.take() {
+            match pending_command {
+                DEV_SET_RATE => self.mouse.sample_rate = command,
+                AUX_SET_RESOLUTION => self.mouse.resolution = command,
+                _ => {}
+            }
+            self.push(DEV_ACK, true);
+            return;
+        }
+
+        match command {
+            DEV_SET_RATE | AUX_SET_RESOLUTION => {
+                self.mouse.pending_command = Some(command);
+                self.push(DEV_ACK, true);
+            }
+            DEV_GET_ID => {
+                self.push(DEV_ACK, true);
+                self.push(AUX_ID, true);
+            }
+            DEV_ENABLE => {
+                self.mouse.reporting = true;
+                self.push(DEV_ACK, true);
+            }
+            DEV_DISABLE => {
+                self.mouse.reporting = false;
+                self.push(DEV_ACK, true);
+            }
+            DEV_SET_DEFAULTS => {
+                self.mouse = Ps2Mouse::default();
+                self.push(DEV_ACK, true);
+            }
+            DEV_RESET => {
+                self.mouse = Ps2Mouse::default();
+                self.push(DEV_ACK, true);
+                self.push(DEV_SELF_TEST_OK, true);
+                self.push(AUX_ID, true);
+            }
+            AUX_SET_SCALING_1_1 | AUX_SET_SCALING_2_1 => {
+                self.mouse.scaling_2_1 = command == AUX_SET_SCALING_2_1;
+                self.push(DEV_ACK, true);
+            }
+            AUX_STATUS_REQUEST => {
+                let mut status = self.mouse.buttons;
+                if self.mouse.scaling_2_1 {
+                    status |= 0x10;
+                }
+                if self.mouse.reporting {
+                    status |= 0x20;
+                }
+                if self.mouse.remote {
+                    status |= 0x40;
+                }
+                self.push(DEV_ACK, true);
+                self.push(status, true);
+                self.push(self.mouse.resolution, true);
+                self.push(self.mouse.sample_rate, true);
+            }
+            AUX_SET_STREAM_MODE | AUX_SET_REMOTE_MODE => {
+                self.mouse.remote = command == AUX_SET_REMOTE_MODE;
+                self.push(DEV_ACK, true);
+            }
+            AUX_READ_DATA => {
+                self.push(DEV_ACK, true);
+                self.push_mouse_packet(0, 0);
+            }
+            _ => self.push(DEV_RESEND, true),
+        }
     }
 }
 
-// i8042 device is located at I/O port 0x61. We partially implement two 8-bit
-// registers: port 0x61 (I8042_PORT_B_REG, offset 0 from base of 0x61), and
-// port 0x64 (I8042_COMMAND_REG, offset 3 from base of 0x61).
+// i8042 device is located at I/O port 0x60. We implement three 8-bit
+// registers: port 0x60 (I8042_DATA_REG, offset 0 from base of 0x60), port
+// 0x61 (I8042_PORT_B_REG, offset 1 from base of 0x60), and port 0x64
+// (I8042_COMMAND_REG, offset 4 from base of 0x60).
 impl BusDevice for I8042Device {
     fn read(&mut self, _base: u64, offset: u64, data: &mut [u8]) {
-        if data.len() == 1 && offset == 3 {
-            data[0] = 0x0;
-        } else if data.len() == 1 && offset == 0 {
+        if data.len() != 1 {
+            return;
+        }
+
+        match offset {
+            DATA_OFFSET if self.interrupts.is_some() => {
+                if let Some((byte, _)) = self.output.pop_front() {
+                    data[0] = byte;
+                    self.signal_output();
+                } else {
+                    data[0] = 0x0;
+                }
+            }
+            COMMAND_OFFSET if self.interrupts.is_some() => data[0] = self.status(),
+            COMMAND_OFFSET => data[0] = 0x0,
             // Like kvmtool, we return bit 5 set in I8042_PORT_B_REG to
             // avoid hang in pit_calibrate_tsc() in Linux kernel.
-            data[0] = 0x20;
+            PORT_B_OFFSET => data[0] = 0x20,
+            _ => {}
         }
     }
 
     fn write(&mut self, _base: u64, offset: u64, data: &[u8]) -> Option<Arc<Barrier>> {
-        if data.len() == 1 && data[0] == 0xfe && offset == 3 {
-            info!("i8042 reset signalled");
-            if let Err(e) = self.reset_evt.write(1) {
-                error!("Error triggering i8042 reset event: {}", e);
+        if data.len() != 1 {
+            return None;
+        }
+
+        match offset {
+            COMMAND_OFFSET if data[0] == CMD_RESET_CPU => {
+                info!("i8042 reset signalled");
+                if let Err(e) = self.reset_evt.write(1) {
+                    error!("Error triggering i8042 reset event: {}", e);
+                }
             }
+            COMMAND_OFFSET if self.interrupts.is_some() => {
+                self.last_write_command = true;
+                self.pending_command = None;
+                self.controller_command(data[0]);
+            }
+            DATA_OFFSET if self.interrupts.is_some() => {
+                self.last_write_command = false;
+                if let Some(command) = self.pending_command.take() {
+                    self.controller_data(command, data[0]);
+                } else {
+                    self.keyboard_command(data[0]);
+                }
+            }
+            _ => {}
         }
 
         None
     }
 }
+
+impl Snapshottable for I8042Device {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn snapshot(&mut self) -> std::result::Result<Snapshot, MigratableError> {
+        Snapshot::new_from_versioned_state(&self.state())
+    }
+}
+
+impl Pausable for I8042Device {}
+impl Transportable for I8042Device {}
+impl Migratable for I8042Device {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::result;
+    use vm_device::interrupt::{InterruptIndex, InterruptSourceConfig};
+
+    struct TestInterrupt;
+
+    impl InterruptSourceGroup for TestInterrupt {
+        fn trigger(&self, _index: InterruptIndex) -> result::Result<(), std::io::Error> {
+            Ok(())
+        }
+        fn update(
+            &self,
+            _index: InterruptIndex,
+            _config: InterruptSourceConfig,
+            _masked: bool,
+        ) -> result::Result<(), std::io::Error> {
+            Ok(())
+        }
+        fn notifier(&self, _index: InterruptIndex) -> Option<EventFd> {
+            None
+        }
+    }
+
+    fn ps2_device(state: Option<I8042State>) -> I8042Device {
+        I8042Device::new(
+            "i8042".to_string(),
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            Some(Ps2Interrupts {
+                keyboard: Arc::new(TestInterrupt),
+                aux: Arc::new(TestInterrupt),
+            }),
+            state,
+        )
+    }
+
+    fn read_output(i8042: &mut I8042Device) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut data = [0u8];
+        loop {
+            i8042.read(0, COMMAND_OFFSET, &mut data);
+            if data[0] & STATUS_OBF == 0 {
+                break;
+            }
+            i8042.read(0, DATA_OFFSET, &mut data);
+            bytes.push(data[0]);
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_key_names() {
+        assert_eq!(
+            Key::from_name("A"),
+            Some(Key {
+                scancode: 0x1e,
+                extended: false
+            })
+        );
+        assert_eq!(
+            Key::parse_combination("ctrl-alt-delete"),
+            Some(vec![
+                Key {
+                    scancode: 0x1d,
+                    extended: false
+                },
+                Key {
+                    scancode: 0x38,
+                    extended: false
+                },
+                Key {
+                    scancode: 0x53,
+                    extended: true
+                },
+            ])
+        );
+        assert_eq!(Key::from_name("a").unwrap().code(), 30);
+        assert_eq!(Key::from_name("ctrl_r").unwrap().code(), 97);
+        assert_eq!(Key::from_name("menu").unwrap().code(), 127);
+        assert_eq!(Key::parse_combination("ctrl-foo"), None);
+        assert_eq!(Key::parse_combination(""), None);
+    }
+
+    #[test]
+    fn test_keyboard() {
+        let mut i8042 = ps2_device(None);
+
+        i8042.write(0, COMMAND_OFFSET, &[CMD_SELF_TEST]);
+        assert_eq!(read_output(&mut i8042), vec![SELF_TEST_OK]);
+
+        i8042.write(0, DATA_OFFSET, &[DEV_GET_ID]);
+        assert_eq!(read_output(&mut i8042), vec![DEV_ACK, KBD_ID, KBD_ID_XLATE]);
+
+        let delete = Key::from_name("delete").unwrap();
+        assert!(i8042.key_event(delete, true));
+        assert!(i8042.key_event(delete, false));
+        assert_eq!(read_output(&mut i8042), vec![0xe0, 0x53, 0xe0, 0xd3]);
+
+        i8042.write(0, DATA_OFFSET, &[DEV_DISABLE]);
+        assert_eq!(read_output(&mut i8042), vec![DEV_ACK]);
+        assert!(!i8042.key_event(delete, true));
+    }
+
+    #[test]
+    fn test_mouse() {
+        let mut i8042 = ps2_device(None);
+
+        i8042.write(0, COMMAND_OFFSET, &[CMD_AUX_LOOP]);
+        i8042.write(0, DATA_OFFSET, &[0x5a]);
+        let mut data = [0u8];
+        i8042.read(0, COMMAND_OFFSET, &mut data);
+        assert_eq!(data[0] & STATUS_AUX_OBF, STATUS_AUX_OBF);
+        assert_eq!(read_output(&mut i8042), vec![0x5a]);
+
+        // Reporting isn't enabled yet
+        assert!(!i8042.pointer_event(1, 1, 0));
+
+        i8042.write(0, COMMAND_OFFSET, &[CMD_AUX_ENABLE]);
+        i8042.write(0, COMMAND_OFFSET, &[CMD_AUX_SEND]);
+        i8042.write(0, DATA_OFFSET, &[DEV_ENABLE]);
+        assert_eq!(read_output(&mut i8042), vec![DEV_ACK]);
+
+        assert!(i8042.pointer_event(-3, 300, 0x1));
+        assert_eq!(
+            read_output(&mut i8042),
+            vec![0x39, 0xfd, 0x00, 0x29, 0x00, 0xd4]
+        );
+    }
+
+    #[test]
+    fn test_state() {
+        let mut i8042 = ps2_device(None);
+
+        i8042.write(0, COMMAND_OFFSET, &[CMD_AUX_ENABLE]);
+        i8042.write(0, COMMAND_OFFSET, &[CMD_AUX_SEND]);
+        i8042.write(0, DATA_OFFSET, &[DEV_ENABLE]);
+        assert_eq!(read_output(&mut i8042), vec![DEV_ACK]);
+        i8042.write(0, COMMAND_OFFSET, &[CMD_AUX_SEND]);
+        i8042.write(0, DATA_OFFSET, &[DEV_SET_RATE]);
+        i8042.write(0, COMMAND_OFFSET, &[CMD_AUX_SEND]);
+        i8042.write(0, DATA_OFFSET, &[40]);
+        assert_eq!(read_output(&mut i8042), vec![DEV_ACK, DEV_ACK]);
+        i8042.write(0, DATA_OFFSET, &[KBD_SET_LEDS]);
+        assert!(i8042.key_event(Key::from_name("a").unwrap(), true));
+
+        // The pending output and the devices settings are carried over.
+        let mut restored = ps2_device(Some(i8042.state()));
+        assert!(restored.mouse_ready());
+        assert_eq!(restored.mouse.sample_rate, 40);
+        assert_eq!(read_output(&mut restored), vec![DEV_ACK, 0x1e]);
+        restored.write(0, DATA_OFFSET, &[0x2]);
+        assert_eq!(read_output(&mut restored), vec![DEV_ACK]);
+    }
+
+    #[test]
+    fn test_reset_only() {
+        let reset_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let mut i8042 = I8042Device::new(
+            "i8042".to_string(),
+            reset_evt.try_clone().unwrap(),
+            None,
+            None,
+        );
+
+        let mut data = [0u8];
+        i8042.read(0, PORT_B_OFFSET, &mut data);
+        assert_eq!(data[0], 0x20);
+
+        assert!(!i8042.key_event(Key::from_name("a").unwrap(), true));
+
+        i8042.write(0, COMMAND_OFFSET, &[CMD_RESET_CPU]);
+        assert_eq!(reset_evt.read().unwrap(), 1);
+    }
+}
@@ -8,12 +8,18 @@
 mod cmos;
 #[cfg(target_arch = "x86_64")]
 mod debug_port;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+mod fw_cfg;
 #[cfg(target_arch = "x86_64")]
 mod fwdebug;
 #[cfg(target_arch = "aarch64")]
 mod gpio_pl061;
 mod i8042;
 #[cfg(target_arch = "aarch64")]
+mod pflash;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+mod pvpanic;
+#[cfg(target_arch = "aarch64")]
 mod rtc_pl031;
 mod serial;
 #[cfg(target_arch = "aarch64")]
@@ -22,9 +28,13 @@ mod uart_pl011;
 pub use self::cmos::Cmos;
 #[cfg(target_arch = "x86_64")]
 pub use self::debug_port::DebugPort;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub use self::fw_cfg::{Error as FwCfgError, FwCfg, FW_CFG_REGISTERS_SIZE};
 #[cfg(target_arch = "x86_64")]
 pub use self::fwdebug::FwDebugDevice;
-pub use self::i8042::I8042Device;
+pub use self::i8042::{I8042Device, Key, Ps2Interrupts};
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub use self::pvpanic::{PvPanic, PVPANIC_REGISTER_SIZE};
 pub use self::serial::Serial;
 
 #[cfg(target_arch = "aarch64")]
@@ -32,6 +42,8 @@ pub use self::gpio_pl061::Error as GpioDeviceError;
 #[cfg(target_arch = "aarch64")]
 pub use self::gpio_pl061::Gpio;
 #[cfg(target_arch = "aarch64")]
+pub use self::pflash::{Error as PflashError, Pflash};
+#[cfg(target_arch = "aarch64")]
 pub use self::rtc_pl031::Rtc;
 #[cfg(target_arch = "aarch64")]
 pub use self::uart_pl011::Pl011;
@@ -0,0 +1,397 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! CFI parallel NOR flash holding the persistent UEFI variables.
+//!
+//! The flash is exposed as two interleaved 16-bit devices implementing the
+//! Intel/Sharp command set, which is what the EDK II NOR flash driver of
+//! ArmVirtPkg expects. Every write to the array is propagated to the backing
+//! file so that the variables survive guest reboots and VM restarts.
+
+use crate::{read_le_u32, write_le_u32};
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::sync::{Arc, Barrier};
+use std::{fmt, io, result};
+use vm_device::BusDevice;
+
+/// Width of the flash bank, in bytes.
+pub const PFLASH_BANK_WIDTH: u64 = 4;
+/// Size of an erase block, in bytes.
+pub const PFLASH_BLOCK_SIZE: u64 = 0x4_0000;
+
+// Each command is replicated on both 16-bit lanes of the bank.
+const LANE_SHIFT: u32 = 16;
+
+const CMD_READ_ARRAY: u8 = 0xff;
+const CMD_READ_ID: u8 = 0x90;
+const CMD_READ_CFI: u8 = 0x98;
+const CMD_READ_STATUS: u8 = 0x70;
+const CMD_CLEAR_STATUS: u8 = 0x50;
+const CMD_WORD_PROGRAM: u8 = 0x40;
+const CMD_WORD_PROGRAM_ALT: u8 = 0x10;
+const CMD_BUFFERED_PROGRAM: u8 = 0xe8;
+const CMD_BLOCK_ERASE: u8 = 0x20;
+const CMD_LOCK_SETUP: u8 = 0x60;
+const CMD_CONFIRM: u8 = 0xd0;
+
+const STATUS_READY: u16 = 0x80;
+const STATUS_ERASE_ERROR: u16 = 0x20;
+const STATUS_PROGRAM_ERROR: u16 = 0x10;
+
+// Intel manufacturer code, and the device code of a 28F128 part.
+const MANUFACTURER_ID: u16 = 0x89;
+const DEVICE_ID: u16 = 0x18;
+
+// Largest buffer accepted by a buffered program, in bytes per lane.
+const WRITE_BUFFER_SIZE: usize = 64;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The backing file size is not a multiple of the erase block size.
+    InvalidSize(u64),
+    /// Error accessing the backing file.
+    BackingFile(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidSize(size) => write!(
+                f,
+                "Invalid flash size {size:#x}, must be a non-zero multiple of {PFLASH_BLOCK_SIZE:#x}"
+            ),
+            Error::BackingFile(e) => write!(f, "Error accessing the flash backing file: {e}"),
+        }
+    }
+}
+
+type Result<T> = result::Result<T, Error>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+    ReadArray,
+    ReadStatus,
+    ReadId,
+    ReadCfi,
+    // Waiting for the data of a word program
+    WordProgram,
+    // Waiting for the confirmation of a block erase
+    BlockErase,
+    // Waiting for the confirmation of a lock operation
+    LockSetup,
+    // Waiting for the number of words of a buffered program
+    BufferedProgramCount,
+    // Collecting the data of a buffered program
+    BufferedProgramData,
+    // Waiting for the confirmation of a buffered program
+    BufferedProgramConfirm,
+}
+
+/// A CFI parallel flash device backed by a file.
+pub struct Pflash {
+    file: File,
+    data: Vec<u8>,
+    mode: Mode,
+    status: u16,
+    // Buffered program state: start offset, remaining words and payload
+    buffer_offset: u64,
+    buffer_remaining: usize,
+    buffer: Vec<u8>,
+}
+
+impl Pflash {
+    /// Constructs a flash device whose content is loaded from `file`.
+    pub fn new(mut file: File, max_size: u64) -> Result<Self> {
+        let size = file.metadata().map_err(Error::BackingFile)?.len();
+        if size == 0 || size % PFLASH_BLOCK_SIZE != 0 || size > max_size {
+            return Err(Error::InvalidSize(size));
+        }
+
+        let mut data = vec![0u8; size as usize];
+        io::Read::read_exact(&mut file, &mut data).map_err(Error::BackingFile)?;
+
+        Ok(Self {
+            file,
+            data,
+            mode: Mode::ReadArray,
+            status: STATUS_READY,
+            buffer_offset: 0,
+            buffer_remaining: 0,
+            buffer: Vec::new(),
+        })
+    }
+
+    /// Size of the flash, in bytes.
+    pub fn size(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    fn replicate(value: u16) -> u32 {
+        u32::from(value) | (u32::from(value) << LANE_SHIFT)
+    }
+
+    // Common Flash Interface query table, for a single 16-bit device.
+    fn cfi_query(&self, index: u64) -> u16 {
+        let blocks = self.size() / PFLASH_BLOCK_SIZE - 1;
+        // Size and erase block size are expressed per device.
+        let device_size = self.size() / 2;
+        let device_block_size = PFLASH_BLOCK_SIZE / 2;
+        match index {
+            // "QRY"
+            0x10 => u16::from(b'Q'),
+            0x11 => u16::from(b'R'),
+            0x12 => u16::from(b'Y'),
+            // Intel/Sharp extended command set, no alternate
+            0x13 => 0x01,
+            0x14 => 0x00,
+            // Vcc and Vpp voltages
+            0x1b => 0x45,
+            0x1c => 0x55,
+            // Typical and maximum timeouts
+            0x1f => 0x01,
+            0x20 => 0x01,
+            0x21 => 0x01,
+            0x23 => 0x01,
+            0x24 => 0x01,
+            0x25 => 0x01,
+            // Device size as 2^n bytes
+            0x27 => device_size.trailing_zeros() as u16,
+            // x16 interface
+            0x28 => 0x01,
+            0x29 => 0x00,
+            // Maximum buffered program size as 2^n bytes
+            0x2a => WRITE_BUFFER_SIZE.trailing_zeros() as u16,
+            0x2b => 0x00,
+            // A single erase block region
+            0x2c => 0x01,
+            0x2d => (blocks & 0xff) as u16,
+            0x2e => ((blocks >> 8) & 0xff) as u16,
+            0x2f => ((device_block_size >> 8) & 0xff) as u16,
+            0x30 => ((device_block_size >> 16) & 0xff) as u16,
+            _ => 0,
+        }
+    }
+
+    fn program(&mut self, offset: u64, data: &[u8]) {
+        let start = offset as usize;
+        let end = start + data.len();
+        if end > self.data.len() {
+            self.status |= STATUS_PROGRAM_ERROR;
+            return;
+        }
+
+        // Programming can only clear bits, erasing is required to set them.
+        for (dst, src) in self.data[start..end].iter_mut().zip(data) {
+            *dst &= *src;
+        }
+        self.flush(start, end);
+    }
+
+    fn erase(&mut self, offset: u64) {
+        let start = (offset & !(PFLASH_BLOCK_SIZE - 1)) as usize;
+        let end = start + PFLASH_BLOCK_SIZE as usize;
+        if end > self.data.len() {
+            self.status |= STATUS_ERASE_ERROR;
+            return;
+        }
+
+        self.data[start..end].fill(0xff);
+        self.flush(start, end);
+    }
+
+    fn flush(&mut self, start: usize, end: usize) {
+        if let Err(e) = self.file.write_all_at(&self.data[start..end], start as u64) {
+            error!("Failed to write flash content to backing file: {}", e);
+            self.status |= STATUS_PROGRAM_ERROR;
+        }
+    }
+
+    fn handle_command(&mut self, offset: u64, cmd: u8) {
+        self.mode = match cmd {
+            CMD_READ_ARRAY => Mode::ReadArray,
+            CMD_READ_ID => Mode::ReadId,
+            CMD_READ_CFI => Mode::ReadCfi,
+            CMD_READ_STATUS => Mode::ReadStatus,
+            CMD_CLEAR_STATUS => {
+                self.status = STATUS_READY;
+                self.mode
+            }
+            CMD_WORD_PROGRAM | CMD_WORD_PROGRAM_ALT => Mode::WordProgram,
+            CMD_BLOCK_ERASE => Mode::BlockErase,
+            CMD_LOCK_SETUP => Mode::LockSetup,
+            CMD_BUFFERED_PROGRAM => {
+                self.buffer_offset = offset;
+                Mode::BufferedProgramCount
+            }
+            _ => {
+                warn!("Unsupported flash command {:#x}", cmd);
+                Mode::ReadArray
+            }
+        };
+    }
+}
+
+impl BusDevice for Pflash {
+    fn read(&mut self, _base: u64, offset: u64, data: &mut [u8]) {
+        match self.mode {
+            Mode::ReadArray => {
+                let start = offset as usize;
+                match self.data.get(start..start + data.len()) {
+                    Some(content) => data.copy_from_slice(content),
+                    None => data.fill(0xff),
+                }
+            }
+            Mode::ReadId => {
+                let value = match offset / PFLASH_BANK_WIDTH {
+                    0 => MANUFACTURER_ID,
+                    1 => DEVICE_ID,
+                    // Block lock status, always unlocked
+                    _ => 0,
+                };
+                write_le_u32(data, Self::replicate(value));
+            }
+            Mode::ReadCfi => {
+                let value = self.cfi_query(offset / PFLASH_BANK_WIDTH);
+                write_le_u32(data, Self::replicate(value));
+            }
+            // Every other mode reports the status register, as the device
+            // would while an operation is pending.
+            _ => write_le_u32(data, Self::replicate(self.status)),
+        }
+    }
+
+    fn write(&mut self, _base: u64, offset: u64, data: &[u8]) -> Option<Arc<Barrier>> {
+        if data.len() > PFLASH_BANK_WIDTH as usize {
+            warn!(
+                "Invalid flash write: offset {}, data length {}",
+                offset,
+                data.len()
+            );
+            return None;
+        }
+
+        match self.mode {
+            Mode::WordProgram => {
+                self.program(offset, data);
+                self.mode = Mode::ReadStatus;
+            }
+            Mode::BlockErase => {
+                if data[0] == CMD_CONFIRM {
+                    self.erase(offset);
+                } else {
+                    self.status |= STATUS_ERASE_ERROR | STATUS_PROGRAM_ERROR;
+                }
+                self.mode = Mode::ReadStatus;
+            }
+            Mode::LockSetup => {
+                // Blocks are never locked, lock and unlock are no-ops.
+                self.mode = Mode::ReadStatus;
+            }
+            Mode::BufferedProgramCount => {
+                // The count is the number of bank-width words minus one.
+                let words = (read_le_u32(data) & 0xffff) as usize + 1;
+                if words * PFLASH_BANK_WIDTH as usize > 2 * WRITE_BUFFER_SIZE {
+                    self.status |= STATUS_PROGRAM_ERROR;
+                    self.mode = Mode::ReadStatus;
+                } else {
+                    self.buffer_remaining = words;
+                    self.buffer.clear();
+                    self.mode = Mode::BufferedProgramData;
+                }
+            }
+            Mode::BufferedProgramData => {
+                if self.buffer.is_empty() {
+                    self.buffer_offset = offset;
+                }
+                self.buffer.extend_from_slice(data);
+                self.buffer_remaining -= 1;
+                if self.buffer_remaining == 0 {
+                    self.mode = Mode::BufferedProgramConfirm;
+                }
+            }
+            Mode::BufferedProgramConfirm => {
+                if data[0] == CMD_CONFIRM {
+                    let buffer = std::mem::take(&mut self.buffer);
+                    self.program(self.buffer_offset, &buffer);
+                } else {
+                    self.status |= STATUS_PROGRAM_ERROR;
+                }
+                self.mode = Mode::ReadStatus;
+            }
+            _ => self.handle_command(offset, data[0]),
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Seek, SeekFrom, Write};
+    use vmm_sys_util::tempfile::TempFile;
+
+    fn cmd(flash: &mut Pflash, offset: u64, cmd: u8) {
+        flash.write(0, offset, &Pflash::replicate(u16::from(cmd)).to_le_bytes());
+    }
+
+    fn read(flash: &mut Pflash, offset: u64) -> u32 {
+        let mut data = [0u8; 4];
+        flash.read(0, offset, &mut data);
+        u32::from_le_bytes(data)
+    }
+
+    #[test]
+    fn test_pflash() {
+        let size = 2 * PFLASH_BLOCK_SIZE;
+        let mut file = TempFile::new().unwrap().into_file();
+        file.write_all(&vec![0xffu8; size as usize]).unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        let backing = file.try_clone().unwrap();
+        let mut flash = Pflash::new(file, 4 * PFLASH_BLOCK_SIZE).unwrap();
+        assert_eq!(flash.size(), size);
+
+        // CFI query
+        cmd(&mut flash, 0x55 * PFLASH_BANK_WIDTH, CMD_READ_CFI);
+        assert_eq!(read(&mut flash, 0x10 * PFLASH_BANK_WIDTH), 0x0051_0051);
+        assert_eq!(read(&mut flash, 0x2d * PFLASH_BANK_WIDTH), 0x0001_0001);
+
+        // Word program, only clearing bits
+        cmd(&mut flash, 0x100, CMD_WORD_PROGRAM);
+        flash.write(0, 0x100, &0x1234_5678u32.to_le_bytes());
+        assert_eq!(read(&mut flash, 0), 0x0080_0080);
+        cmd(&mut flash, 0, CMD_READ_ARRAY);
+        assert_eq!(read(&mut flash, 0x100), 0x1234_5678);
+
+        // Buffered program
+        cmd(&mut flash, PFLASH_BLOCK_SIZE, CMD_BUFFERED_PROGRAM);
+        flash.write(0, PFLASH_BLOCK_SIZE, &Pflash::replicate(1).to_le_bytes());
+        flash.write(0, PFLASH_BLOCK_SIZE + 8, &0xaaaa_aaaau32.to_le_bytes());
+        flash.write(0, PFLASH_BLOCK_SIZE + 12, &0x5555_5555u32.to_le_bytes());
+        cmd(&mut flash, PFLASH_BLOCK_SIZE, CMD_CONFIRM);
+        cmd(&mut flash, 0, CMD_READ_ARRAY);
+        assert_eq!(read(&mut flash, PFLASH_BLOCK_SIZE + 8), 0xaaaa_aaaa);
+        assert_eq!(read(&mut flash, PFLASH_BLOCK_SIZE + 12), 0x5555_5555);
+
+        // The content is written through to the backing file
+        let mut content = [0u8; 4];
+        backing.read_exact_at(&mut content, 0x100).unwrap();
+        assert_eq!(u32::from_le_bytes(content), 0x1234_5678);
+
+        // Block erase
+        cmd(&mut flash, 0, CMD_BLOCK_ERASE);
+        cmd(&mut flash, 0, CMD_CONFIRM);
+        cmd(&mut flash, 0, CMD_READ_ARRAY);
+        assert_eq!(read(&mut flash, 0x100), 0xffff_ffff);
+        assert_eq!(read(&mut flash, PFLASH_BLOCK_SIZE + 8), 0xaaaa_aaaa);
+        backing.read_exact_at(&mut content, 0x100).unwrap();
+        assert_eq!(u32::from_le_bytes(content), 0xffff_ffff);
+
+        // Invalid sizes
+        let file = TempFile::new().unwrap().into_file();
+        file.set_len(PFLASH_BLOCK_SIZE + 1).unwrap();
+        assert!(Pflash::new(file, 4 * PFLASH_BLOCK_SIZE).is_err());
+    }
+}
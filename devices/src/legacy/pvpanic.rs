@@ -0,0 +1,83 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! QEMU compatible pvpanic device, through which the guest reports that it
+//! panicked. See docs/specs/pvpanic.txt in the QEMU code.
+//!
+//! The device is a single byte register, an I/O port on x86-64 and MMIO on
+//! AArch64. Reading it returns the events supported by the device, while the
+//! guest writes the events it reports.
+
+use std::sync::{Arc, Barrier};
+use vm_device::BusDevice;
+use vmm_sys_util::eventfd::EventFd;
+
+/// The guest panicked.
+const PVPANIC_PANICKED: u8 = 1 << 0;
+/// The guest is about to boot a crash kernel.
+const PVPANIC_CRASH_LOADED: u8 = 1 << 1;
+
+/// Size of the register range.
+pub const PVPANIC_REGISTER_SIZE: u64 = 1;
+
+pub struct PvPanic {
+    panic_evt: EventFd,
+}
+
+impl PvPanic {
+    /// Creates a device signalling `panic_evt` when the guest panics.
+    pub fn new(panic_evt: EventFd) -> Self {
+        PvPanic { panic_evt }
+    }
+}
+
+impl BusDevice for PvPanic {
+    fn read(&mut self, _base: u64, offset: u64, data: &mut [u8]) {
+        data.fill(0);
+        if offset == 0 && !data.is_empty() {
+            data[0] = PVPANIC_PANICKED | PVPANIC_CRASH_LOADED;
+        }
+    }
+
+    fn write(&mut self, _base: u64, offset: u64, data: &[u8]) -> Option<Arc<Barrier>> {
+        if offset != 0 || data.is_empty() {
+            return None;
+        }
+
+        if data[0] & PVPANIC_CRASH_LOADED != 0 {
+            info!("Guest is loading a crash kernel");
+        }
+        if data[0] & PVPANIC_PANICKED != 0 {
+            warn!("Guest panicked");
+            if let Err(e) = self.panic_evt.write(1) {
+                error!("Error signalling the guest panic: {}", e);
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libc::EFD_NONBLOCK;
+
+    #[test]
+    fn test_pvpanic() {
+        let panic_evt = EventFd::new(EFD_NONBLOCK).unwrap();
+        let mut pvpanic = PvPanic::new(panic_evt.try_clone().unwrap());
+
+        let mut data = [0u8];
+        pvpanic.read(0, 0, &mut data);
+        assert_eq!(data[0], PVPANIC_PANICKED | PVPANIC_CRASH_LOADED);
+
+        pvpanic.write(0, 0, &[PVPANIC_CRASH_LOADED]);
+        assert!(panic_evt.read().is_err());
+
+        pvpanic.write(0, 0, &[PVPANIC_PANICKED]);
+        assert_eq!(panic_evt.read().unwrap(), 1);
+    }
+}
@@ -23,6 +23,8 @@ pub mod tpm;
 
 pub use self::acpi::{AcpiGedDevice, AcpiPmTimerDevice, AcpiShutdownDevice};
 
+type GuestMemoryMmap = vm_memory::GuestMemoryMmap<vm_memory::bitmap::AtomicBitmap>;
+
 bitflags! {
     pub struct AcpiNotificationFlags: u8 {
         const NO_DEVICES_CHANGED = 0;
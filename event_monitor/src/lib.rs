@@ -3,21 +3,23 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
+use once_cell::sync::Lazy;
 use serde::Serialize;
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::Write;
 use std::os::unix::io::AsRawFd;
+use std::sync::Mutex;
 use std::time::{Duration, Instant};
 
-static mut MONITOR: Option<(File, Instant)> = None;
+static mut MONITOR: Option<File> = None;
 
-/// This function must only be called once from the main process before any threads
-/// are created to avoid race conditions
-pub fn set_monitor(file: File) -> Result<(), std::io::Error> {
-    // SAFETY: there is only one caller of this function, so MONITOR is written to only once
-    assert!(unsafe { MONITOR.is_none() });
+// Clients subscribed to the events at runtime
+static SUBSCRIBERS: Lazy<Mutex<Vec<File>>> = Lazy::new(|| Mutex::new(Vec::new()));
+static START: Lazy<Instant> = Lazy::new(Instant::now);
+
+fn set_nonblocking(file: &File) -> Result<(), std::io::Error> {
     let fd = file.as_raw_fd();
     // SAFETY: FFI call to configure the fd
     let ret = unsafe {
@@ -28,13 +30,32 @@ pub fn set_monitor(file: File) -> Result<(), std::io::Error> {
     if ret < 0 {
         return Err(std::io::Error::last_os_error());
     }
+    Ok(())
+}
+
+/// This function must only be called once from the main process before any threads
+/// are created to avoid race conditions
+pub fn set_monitor(file: File) -> Result<(), std::io::Error> {
+    // SAFETY: there is only one caller of this function, so MONITOR is written to only once
+    assert!(unsafe { MONITOR.is_none() });
+    set_nonblocking(&file)?;
+    Lazy::force(&START);
     // SAFETY: MONITOR is None. Nobody else can hold a reference to it.
     unsafe {
-        MONITOR = Some((file, Instant::now()));
+        MONITOR = Some(file);
     };
     Ok(())
 }
 
+/// Subscribes a client to all the events from now on, written as one JSON
+/// object per line. The client is dropped as soon as it can't keep up with
+/// the events.
+pub fn add_subscriber(file: File) -> Result<(), std::io::Error> {
+    set_nonblocking(&file)?;
+    SUBSCRIBERS.lock().unwrap().push(file);
+    Ok(())
+}
+
 #[derive(Serialize)]
 struct Event<'a> {
     timestamp: Duration,
@@ -44,19 +65,31 @@ struct Event<'a> {
 }
 
 pub fn event_log(source: &str, event: &str, properties: Option<&HashMap<Cow<str>, Cow<str>>>) {
+    let e = Event {
+        timestamp: START.elapsed(),
+        source,
+        event,
+        properties,
+    };
+
     // SAFETY: MONITOR is always in a valid state (None or Some).
-    if let Some((file, start)) = unsafe { MONITOR.as_ref() } {
-        let e = Event {
-            timestamp: start.elapsed(),
-            source,
-            event,
-            properties,
-        };
+    if let Some(file) = unsafe { MONITOR.as_ref() } {
         serde_json::to_writer_pretty(file, &e).ok();
 
         let mut file = file;
         file.write_all(b"\n\n").ok();
     }
+
+    let mut subscribers = SUBSCRIBERS.lock().unwrap();
+    if !subscribers.is_empty() {
+        if let Ok(mut line) = serde_json::to_vec(&e) {
+            line.push(b'\n');
+            subscribers.retain(|file| {
+                let mut file = file;
+                file.write_all(&line).is_ok()
+            });
+        }
+    }
 }
 
 /*
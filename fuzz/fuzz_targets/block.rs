@@ -11,6 +11,7 @@
 use block_util::{async_io::DiskFile, raw_sync::RawFileDiskSync};
 use libfuzzer_sys::fuzz_target;
 use seccompiler::SeccompAction;
+use std::collections::BTreeMap;
 use std::ffi;
 use std::fs::File;
 use std::io;
@@ -59,8 +60,10 @@ fuzz_target!(|bytes| {
         256,
         SeccompAction::Allow,
         None,
+        None,
         EventFd::new(EFD_NONBLOCK).unwrap(),
         None,
+        BTreeMap::new(),
     )
     .unwrap();
 
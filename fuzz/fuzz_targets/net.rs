@@ -6,6 +6,7 @@
 
 use libfuzzer_sys::fuzz_target;
 use seccompiler::SeccompAction;
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::{Read, Write};
 use std::os::unix::io::{AsRawFd, FromRawFd};
@@ -74,11 +75,16 @@ fuzz_target!(|bytes| {
         QUEUE_SIZE,
         SeccompAction::Allow,
         None,
+        None,
         EventFd::new(EFD_NONBLOCK).unwrap(),
         None,
         true,
         true,
         true,
+        true,
+        false,
+        false,
+        BTreeMap::new(),
     )
     .unwrap();
 
@@ -14,10 +14,14 @@ use crate::aarch64::{RegList, StandardRegisters, VcpuInit};
 use crate::arch::x86::{
     CpuIdEntry, FpuState, LapicState, MsrEntry, SpecialRegisters, StandardRegisters,
 };
+#[cfg(feature = "sev_snp")]
+use crate::kvm::SevSnpExitDetails;
 #[cfg(feature = "tdx")]
 use crate::kvm::{TdxExitDetails, TdxExitStatus};
 use crate::CpuState;
 use crate::MpState;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use thiserror::Error;
 use vm_memory::GuestAddress;
 
@@ -205,6 +209,16 @@ pub enum HypervisorCpuError {
     #[error("Failed to set system register: {0}")]
     SetSysRegister(#[source] anyhow::Error),
     ///
+    /// Getting AArch64 SVE register error
+    ///
+    #[error("Failed to get SVE register: {0}")]
+    GetSveRegister(#[source] anyhow::Error),
+    ///
+    /// Setting AArch64 SVE register error
+    ///
+    #[error("Failed to set SVE register: {0}")]
+    SetSveRegister(#[source] anyhow::Error),
+    ///
     /// GVA translation error
     ///
     #[error("Failed to translate GVA: {0}")]
@@ -231,18 +245,69 @@ pub enum HypervisorCpuError {
     #[cfg(feature = "tdx")]
     #[error("Unknown TDX VM call")]
     UnknownTdxVmCall,
+    ///
+    /// Unknown SEV-SNP exit
+    ///
+    #[cfg(feature = "sev_snp")]
+    #[error("Unknown SEV-SNP exit")]
+    UnknownSevSnpExit,
     #[cfg(target_arch = "aarch64")]
     ///
     /// Failed to intialize PMU
     ///
     #[error("Failed to initialize PMU")]
     InitializePmu,
+    ///
+    /// Failed to initialize SVE
+    ///
+    #[error("Failed to initialize SVE: {0}")]
+    InitializeSve(#[source] anyhow::Error),
     #[cfg(target_arch = "x86_64")]
     ///
     /// Error getting TSC frequency
     ///
     #[error("Failed to get TSC frequency: {0}")]
     GetTscKhz(#[source] anyhow::Error),
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Error injecting a Non-Maskable Interrupt
+    ///
+    #[error("Failed to inject NMI: {0}")]
+    Nmi(#[source] anyhow::Error),
+}
+
+///
+/// Number of exits of a vCPU, broken down by reason
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct VcpuExitStats {
+    pub io: u64,
+    pub mmio: u64,
+    pub hlt: u64,
+    pub ept_violation: u64,
+}
+
+///
+/// Counters of the exits seen by the hypervisor backend, updated from the
+/// vCPU thread and read from any other thread.
+///
+#[derive(Debug, Default)]
+pub struct VcpuExitCounters {
+    pub io: AtomicU64,
+    pub mmio: AtomicU64,
+    pub hlt: AtomicU64,
+    pub ept_violation: AtomicU64,
+}
+
+impl VcpuExitCounters {
+    pub fn stats(&self) -> VcpuExitStats {
+        VcpuExitStats {
+            io: self.io.load(Ordering::Relaxed),
+            mmio: self.mmio.load(Ordering::Relaxed),
+            hlt: self.hlt.load(Ordering::Relaxed),
+            ept_violation: self.ept_violation.load(Ordering::Relaxed),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -261,6 +326,8 @@ pub enum VmExit<'a> {
     Hyperv,
     #[cfg(feature = "tdx")]
     Tdx,
+    #[cfg(feature = "sev_snp")]
+    SevSnp,
     #[cfg(feature = "kvm")]
     Debug,
 }
@@ -378,7 +445,7 @@ pub trait Vcpu: Send + Sync {
     /// Configure core registers for a given CPU.
     ///
     #[cfg(target_arch = "aarch64")]
-    fn setup_regs(&self, cpu_id: u8, boot_ip: u64, fdt_start: u64) -> Result<()>;
+    fn setup_regs(&self, cpu_id: u32, boot_ip: u64, fdt_start: u64) -> Result<()>;
     ///
     /// Check if the CPU supports PMU
     ///
@@ -390,6 +457,13 @@ pub trait Vcpu: Send + Sync {
     #[cfg(target_arch = "aarch64")]
     fn init_pmu(&self, irq: u32) -> Result<()>;
     ///
+    /// Restrict the SVE vector lengths to the given maximum (in bits) and
+    /// finalize the SVE configuration. The vCPU must have been initialized
+    /// with the SVE feature.
+    ///
+    #[cfg(target_arch = "aarch64")]
+    fn init_sve(&self, max_vector_length: u32) -> Result<()>;
+    ///
     /// Retrieve the vCPU state.
     /// This function is necessary to snapshot the VM
     ///
@@ -403,6 +477,10 @@ pub trait Vcpu: Send + Sync {
     /// Triggers the running of the current virtual CPU returning an exit reason.
     ///
     fn run(&self) -> std::result::Result<VmExit, HypervisorCpuError>;
+    ///
+    /// Returns the counters of the exits handled by run(), by reason.
+    ///
+    fn exit_counters(&self) -> Arc<VcpuExitCounters>;
     #[cfg(target_arch = "x86_64")]
     ///
     /// Translate guest virtual address to guest physical address
@@ -433,6 +511,20 @@ pub trait Vcpu: Send + Sync {
     fn set_tdx_status(&mut self, _status: TdxExitStatus) {
         unimplemented!()
     }
+    #[cfg(feature = "sev_snp")]
+    ///
+    /// Returns the details about the SEV-SNP exit reason
+    ///
+    fn get_sev_snp_exit_details(&mut self) -> Result<SevSnpExitDetails> {
+        unimplemented!()
+    }
+    #[cfg(feature = "sev_snp")]
+    ///
+    /// Set the return value of the hypercall behind the SEV-SNP exit
+    ///
+    fn set_sev_snp_hypercall_result(&mut self, _ret: u64) {
+        unimplemented!()
+    }
     #[cfg(target_arch = "x86_64")]
     ///
     /// Return the list of initial MSR entries for a VCPU
@@ -445,4 +537,13 @@ pub trait Vcpu: Send + Sync {
     fn tsc_khz(&self) -> Result<Option<u32>> {
         Ok(None)
     }
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Queue a Non-Maskable Interrupt on the vCPU
+    ///
+    fn nmi(&self) -> Result<()> {
+        Err(HypervisorCpuError::Nmi(anyhow::anyhow!(
+            "NMI injection not supported"
+        )))
+    }
 }
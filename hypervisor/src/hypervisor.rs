@@ -8,7 +8,7 @@
 //
 //
 #[cfg(target_arch = "x86_64")]
-use crate::arch::x86::CpuIdEntry;
+use crate::arch::x86::{CpuIdEntry, MsrEntry};
 #[cfg(feature = "tdx")]
 use crate::kvm::TdxCapabilities;
 use crate::vm::Vm;
@@ -56,6 +56,11 @@ pub enum HypervisorError {
     #[error("Failed to get the list of supported MSRs: {0}")]
     GetMsrList(#[source] anyhow::Error),
     ///
+    /// Failed to retrieve the value of the feature MSRs.
+    ///
+    #[error("Failed to get the value of the feature MSRs: {0}")]
+    GetMsrFeatures(#[source] anyhow::Error),
+    ///
     /// API version is not compatible
     ///
     #[error("Incompatible API version")]
@@ -109,6 +114,14 @@ pub trait Hypervisor: Send + Sync {
     /// Get the supported CpuID
     ///
     fn get_supported_cpuid(&self) -> Result<Vec<CpuIdEntry>>;
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Get the value of the feature MSRs from `indices` supported by the
+    /// hypervisor, the unknown ones being left out
+    ///
+    fn get_msr_features(&self, _indices: &[u32]) -> Result<Vec<MsrEntry>> {
+        Ok(Vec::new())
+    }
     ///
     /// Check particular extensions if any
     ///
@@ -121,6 +121,8 @@ pub struct Gicv3ItsState {
     its_baser: [u64; 8],
 }
 
+impl vm_migration::StateUpgrade for Gicv3ItsState {}
+
 impl KvmGicV3Its {
     /// Device trees specific constants
     pub const ARCH_GIC_V3_MAINT_IRQ: u32 = 9;
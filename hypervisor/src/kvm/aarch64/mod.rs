@@ -12,8 +12,8 @@ pub mod gic;
 
 use crate::kvm::{KvmError, KvmResult};
 use kvm_bindings::{
-    kvm_mp_state, kvm_one_reg, kvm_regs, KVM_REG_ARM_COPROC_MASK, KVM_REG_ARM_CORE,
-    KVM_REG_SIZE_MASK, KVM_REG_SIZE_U32, KVM_REG_SIZE_U64,
+    kvm_mp_state, kvm_one_reg, kvm_regs, KVM_REG_ARM64, KVM_REG_ARM_COPROC_MASK, KVM_REG_ARM_CORE,
+    KVM_REG_SIZE_MASK, KVM_REG_SIZE_SHIFT, KVM_REG_SIZE_U32, KVM_REG_SIZE_U64,
 };
 pub use kvm_bindings::{
     kvm_one_reg as Register, kvm_regs as StandardRegisters, kvm_vcpu_init as VcpuInit, RegList,
@@ -83,6 +83,37 @@ macro_rules! arm64_core_reg_id {
     };
 }
 
+/// vCPU feature enabling the Scalable Vector Extension
+pub const KVM_ARM_VCPU_SVE: u32 = 4;
+/// vCPU feature enabling address authentication (APIA, APIB, APDA, APDB keys)
+pub const KVM_ARM_VCPU_PTRAUTH_ADDRESS: u32 = 5;
+/// vCPU feature enabling generic authentication (APGA key)
+pub const KVM_ARM_VCPU_PTRAUTH_GENERIC: u32 = 6;
+/// vCPU feature starting the vCPU at EL2, for nested virtualization
+pub const KVM_ARM_VCPU_HAS_EL2: u32 = 7;
+/// Register group of the SVE registers
+pub const KVM_REG_ARM64_SVE: u64 = 0x0015 << 16;
+const KVM_REG_SIZE_U512: u64 = 0x0060_0000_0000_0000;
+/// Pseudo-register holding the bitmap of the enabled SVE vector lengths
+pub const KVM_REG_ARM64_SVE_VLS: u64 =
+    KVM_REG_ARM64 | KVM_REG_ARM64_SVE | KVM_REG_SIZE_U512 | 0xffff;
+/// Granule of the SVE vector lengths, in bits
+pub const SVE_VQ_BITS: u32 = 128;
+
+/// Specifies whether a particular register is a SVE register or not.
+///
+/// # Arguments
+///
+/// * `regid` - The index of the register we are checking.
+pub fn is_sve_register(regid: u64) -> bool {
+    (regid & KVM_REG_ARM_COPROC_MASK as u64) == KVM_REG_ARM64_SVE
+}
+
+/// Size in bytes of the register with the given index.
+pub fn reg_size(regid: u64) -> usize {
+    1 << ((regid & KVM_REG_SIZE_MASK) >> KVM_REG_SIZE_SHIFT)
+}
+
 /// Specifies whether a particular register is a system register or not.
 /// The kernel splits the registers on aarch64 in core registers and system registers.
 /// So, below we get the system registers by checking that they are not core registers.
@@ -91,7 +122,8 @@ macro_rules! arm64_core_reg_id {
 ///
 /// * `regid` - The index of the register we are checking.
 pub fn is_system_register(regid: u64) -> bool {
-    if (regid & KVM_REG_ARM_COPROC_MASK as u64) == KVM_REG_ARM_CORE as u64 {
+    if (regid & KVM_REG_ARM_COPROC_MASK as u64) == KVM_REG_ARM_CORE as u64 || is_sve_register(regid)
+    {
         return false;
     }
 
@@ -115,9 +147,18 @@ pub fn check_required_kvm_extensions(kvm: &Kvm) -> KvmResult<()> {
     Ok(())
 }
 
+/// SVE registers are wider than 128 bits, their content is kept as raw bytes.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct SveRegister {
+    pub id: u64,
+    pub data: Vec<u8>,
+}
+
 #[derive(Clone, Default, Serialize, Deserialize)]
 pub struct VcpuKvmState {
     pub mp_state: kvm_mp_state,
     pub core_regs: kvm_regs,
     pub sys_regs: Vec<kvm_one_reg>,
+    #[serde(default)]
+    pub sve_regs: Vec<SveRegister>,
 }
@@ -12,8 +12,10 @@
 use crate::aarch64::gic::KvmGicV3Its;
 #[cfg(target_arch = "aarch64")]
 pub use crate::aarch64::{
-    check_required_kvm_extensions, gic::Gicv3ItsState as GicState, is_system_register, VcpuInit,
-    VcpuKvmState,
+    check_required_kvm_extensions, gic::Gicv3ItsState as GicState, is_sve_register,
+    is_system_register, reg_size, SveRegister, VcpuInit, VcpuKvmState, KVM_ARM_VCPU_HAS_EL2,
+    KVM_ARM_VCPU_PTRAUTH_ADDRESS, KVM_ARM_VCPU_PTRAUTH_GENERIC, KVM_ARM_VCPU_SVE,
+    KVM_REG_ARM64_SVE_VLS, SVE_VQ_BITS,
 };
 #[cfg(target_arch = "aarch64")]
 use crate::arch::aarch64::gic::{Vgic, VgicConfig};
@@ -33,14 +35,13 @@ use std::convert::TryInto;
 use std::fs::File;
 #[cfg(target_arch = "x86_64")]
 use std::os::unix::io::AsRawFd;
-#[cfg(feature = "tdx")]
+#[cfg(feature = "sev_snp")]
+use std::os::unix::io::FromRawFd;
+#[cfg(any(feature = "tdx", feature = "sev_snp"))]
 use std::os::unix::io::RawFd;
 use std::result;
-#[cfg(target_arch = "x86_64")]
 use std::sync::atomic::{AtomicBool, Ordering};
-#[cfg(target_arch = "aarch64")]
-use std::sync::Mutex;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 use vmm_sys_util::eventfd::EventFd;
 // x86_64 dependencies
 #[cfg(target_arch = "x86_64")]
@@ -52,6 +53,8 @@ use crate::arch::x86::{
 };
 #[cfg(target_arch = "x86_64")]
 use crate::ClockData;
+#[cfg(feature = "sev_snp")]
+use crate::USER_MEMORY_REGION_PRIVATE;
 use crate::{
     CpuState, IoEventAddress, IrqRoutingEntry, MpState, UserMemoryRegion,
     USER_MEMORY_REGION_LOG_DIRTY, USER_MEMORY_REGION_READ, USER_MEMORY_REGION_WRITE,
@@ -71,7 +74,6 @@ pub use x86_64::{CpuId, ExtendedControlRegisters, MsrEntries, VcpuKvmState, Xsav
 #[cfg(target_arch = "aarch64")]
 pub mod aarch64;
 pub use kvm_bindings;
-#[cfg(feature = "tdx")]
 use kvm_bindings::KVMIO;
 pub use kvm_bindings::{
     kvm_clock_data, kvm_create_device, kvm_device_type_KVM_DEV_TYPE_VFIO, kvm_guest_debug,
@@ -93,7 +95,22 @@ use std::mem;
 use thiserror::Error;
 use vfio_ioctls::VfioDeviceFd;
 #[cfg(feature = "tdx")]
-use vmm_sys_util::{ioctl::ioctl_with_val, ioctl_ioc_nr, ioctl_iowr_nr};
+use vmm_sys_util::ioctl::ioctl_with_val;
+#[cfg(target_arch = "x86_64")]
+use vmm_sys_util::{
+    ioctl::{ioctl, ioctl_with_mut_ptr},
+    ioctl_io_nr, ioctl_ioc_nr, ioctl_iowr_nr,
+};
+#[cfg(feature = "sev_snp")]
+use vmm_sys_util::{
+    ioctl::{ioctl_with_mut_ref, ioctl_with_ref},
+    ioctl_ior_nr, ioctl_iow_nr,
+};
+#[cfg(target_arch = "aarch64")]
+use vmm_sys_util::{
+    ioctl::{ioctl_with_ref, ioctl_with_val},
+    ioctl_io_nr, ioctl_ioc_nr, ioctl_iow_nr,
+};
 ///
 /// Export generically-named wrappers of kvm-bindings for Unix-based platforms
 ///
@@ -104,6 +121,16 @@ pub use {
 
 #[cfg(target_arch = "x86_64")]
 const KVM_CAP_SGX_ATTRIBUTE: u32 = 196;
+#[cfg(target_arch = "x86_64")]
+const KVM_CAP_X2APIC_API: u32 = 129;
+#[cfg(target_arch = "x86_64")]
+const KVM_X2APIC_API_USE_32BIT_IDS: u64 = 1 << 0;
+#[cfg(target_arch = "x86_64")]
+const KVM_X2APIC_API_DISABLE_BROADCAST_QUIRK: u64 = 1 << 1;
+#[cfg(target_arch = "aarch64")]
+const KVM_CAP_ARM_MTE: u32 = 205;
+#[cfg(target_arch = "aarch64")]
+const KVM_CAP_ARM_EL2: u32 = 240;
 
 #[cfg(feature = "tdx")]
 const KVM_EXIT_TDX: u32 = 50;
@@ -116,9 +143,258 @@ const TDG_VP_VMCALL_SUCCESS: u64 = 0;
 #[cfg(feature = "tdx")]
 const TDG_VP_VMCALL_INVALID_OPERAND: u64 = 0x8000000000000000;
 
-#[cfg(feature = "tdx")]
+// Reading MSRs from the system file descriptor only exposes the feature MSRs
+#[cfg(target_arch = "x86_64")]
+ioctl_iowr_nr!(KVM_GET_MSRS, KVMIO, 0x88, kvm_bindings::kvm_msrs);
+
+// The SVE registers don't fit in the 128 bits values handled by kvm-ioctls
+#[cfg(target_arch = "aarch64")]
+ioctl_iow_nr!(KVM_GET_ONE_REG, KVMIO, 0xab, kvm_bindings::kvm_one_reg);
+#[cfg(target_arch = "aarch64")]
+ioctl_iow_nr!(KVM_SET_ONE_REG, KVMIO, 0xac, kvm_bindings::kvm_one_reg);
+#[cfg(target_arch = "aarch64")]
+ioctl_iow_nr!(KVM_ARM_VCPU_FINALIZE, KVMIO, 0xc2, std::os::raw::c_int);
+// The nested virtualization and dirty ring capabilities aren't known to
+// kvm-ioctls
+ioctl_io_nr!(KVM_CHECK_EXTENSION, KVMIO, 0x03);
+
+#[cfg(any(feature = "tdx", feature = "sev_snp"))]
 ioctl_iowr_nr!(KVM_MEMORY_ENCRYPT_OP, KVMIO, 0xba, std::os::raw::c_ulong);
 
+#[cfg(feature = "sev_snp")]
+const KVM_CAP_EXIT_HYPERCALL: u32 = 201;
+#[cfg(feature = "sev_snp")]
+const KVM_EXIT_MEMORY_FAULT: u32 = 39;
+#[cfg(feature = "sev_snp")]
+const KVM_MEMORY_EXIT_FLAG_PRIVATE: u64 = 1 << 3;
+#[cfg(feature = "sev_snp")]
+const KVM_HC_MAP_GPA_RANGE: u64 = 12;
+#[cfg(feature = "sev_snp")]
+const KVM_MAP_GPA_RANGE_PAGE_SZ_MASK: u64 = 0xf;
+#[cfg(feature = "sev_snp")]
+const KVM_MAP_GPA_RANGE_ENCRYPTED: u64 = 1 << 4;
+#[cfg(feature = "sev_snp")]
+const KVM_MEM_GUEST_MEMFD: u32 = 1 << 2;
+#[cfg(feature = "sev_snp")]
+const KVM_MEMORY_ATTRIBUTE_PRIVATE: u64 = 1 << 3;
+#[cfg(feature = "sev_snp")]
+const SEV_GHCB_VERSION: u16 = 2;
+// Size of the SEV launch measurement, followed by its nonce
+#[cfg(feature = "sev_snp")]
+const SEV_LAUNCH_MEASUREMENT_SIZE: usize = 48;
+
+#[cfg(feature = "sev_snp")]
+#[repr(C)]
+struct KvmCreateGuestMemfd {
+    size: u64,
+    flags: u64,
+    reserved: [u64; 6],
+}
+
+#[cfg(feature = "sev_snp")]
+#[repr(C)]
+struct KvmUserspaceMemoryRegion2 {
+    slot: u32,
+    flags: u32,
+    guest_phys_addr: u64,
+    memory_size: u64,
+    userspace_addr: u64,
+    guest_memfd_offset: u64,
+    guest_memfd: u32,
+    pad1: u32,
+    pad2: [u64; 14],
+}
+
+#[cfg(feature = "sev_snp")]
+#[repr(C)]
+struct KvmMemoryAttributes {
+    address: u64,
+    size: u64,
+    attributes: u64,
+    flags: u64,
+}
+
+#[cfg(feature = "sev_snp")]
+#[repr(C)]
+struct KvmEncRegion {
+    addr: u64,
+    size: u64,
+}
+
+#[cfg(feature = "sev_snp")]
+#[repr(C)]
+struct KvmMemoryFault {
+    flags: u64,
+    gpa: u64,
+    size: u64,
+}
+
+#[cfg(feature = "sev_snp")]
+ioctl_iow_nr!(
+    KVM_SET_USER_MEMORY_REGION2,
+    KVMIO,
+    0x49,
+    KvmUserspaceMemoryRegion2
+);
+#[cfg(feature = "sev_snp")]
+ioctl_iow_nr!(KVM_SET_MEMORY_ATTRIBUTES, KVMIO, 0xd2, KvmMemoryAttributes);
+#[cfg(feature = "sev_snp")]
+ioctl_iowr_nr!(KVM_CREATE_GUEST_MEMFD, KVMIO, 0xd4, KvmCreateGuestMemfd);
+#[cfg(feature = "sev_snp")]
+ioctl_ior_nr!(KVM_MEMORY_ENCRYPT_REG_REGION, KVMIO, 0xbb, KvmEncRegion);
+
+#[cfg(target_arch = "x86_64")]
+ioctl_io_nr!(KVM_NMI, KVMIO, 0x9a);
+
+// The dirty ring isn't known to kvm-ioctls
+#[cfg(target_arch = "x86_64")]
+const KVM_CAP_DIRTY_LOG_RING: u32 = 192;
+#[cfg(target_arch = "x86_64")]
+const KVM_EXIT_DIRTY_RING_FULL: u32 = 31;
+#[cfg(target_arch = "x86_64")]
+const KVM_DIRTY_LOG_PAGE_OFFSET: i64 = 64;
+#[cfg(target_arch = "x86_64")]
+const KVM_DIRTY_GFN_F_DIRTY: u32 = 1 << 0;
+#[cfg(target_arch = "x86_64")]
+const KVM_DIRTY_GFN_F_RESET: u32 = 1 << 1;
+// Number of entries of each vCPU dirty ring
+#[cfg(target_arch = "x86_64")]
+const KVM_DIRTY_RING_ENTRIES: u32 = 4096;
+
+#[cfg(target_arch = "x86_64")]
+ioctl_io_nr!(KVM_RESET_DIRTY_RINGS, KVMIO, 0xc7);
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+struct KvmDirtyGfn {
+    flags: u32,
+    slot: u32,
+    offset: u64,
+}
+
+// Ring of the pages dirtied by a vCPU, shared with KVM
+#[cfg(target_arch = "x86_64")]
+struct KvmDirtyRing {
+    entries: *mut KvmDirtyGfn,
+    size: u32,
+    // Index of the next entry to harvest
+    next: u32,
+}
+
+// SAFETY: the ring is only accessed with the lock of KvmDirtyRings held
+#[cfg(target_arch = "x86_64")]
+unsafe impl Send for KvmDirtyRing {}
+
+#[cfg(target_arch = "x86_64")]
+impl KvmDirtyRing {
+    fn new(vcpu_fd: &VcpuFd, size: u32) -> std::io::Result<Self> {
+        // SAFETY: FFI call with a valid name
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        let len = size as usize * std::mem::size_of::<KvmDirtyGfn>();
+        // SAFETY: FFI call mapping the ring exposed by the vCPU file descriptor
+        let addr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                vcpu_fd.as_raw_fd(),
+                KVM_DIRTY_LOG_PAGE_OFFSET * page_size,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        Ok(KvmDirtyRing {
+            entries: addr as *mut KvmDirtyGfn,
+            size,
+            next: 0,
+        })
+    }
+
+    // Collect the entries published by KVM, handing them back for reset
+    fn harvest(&mut self, mut f: impl FnMut(u32, u64)) -> usize {
+        let mut count = 0;
+        loop {
+            // SAFETY: the index is within the ring mapping
+            let entry = unsafe { self.entries.add((self.next % self.size) as usize) };
+            // SAFETY: the flags are shared with KVM, which only writes them
+            // with release semantics once the entry is complete
+            let flags = unsafe {
+                &*(std::ptr::addr_of_mut!((*entry).flags) as *const std::sync::atomic::AtomicU32)
+            };
+            if flags.load(Ordering::Acquire) & KVM_DIRTY_GFN_F_DIRTY == 0 {
+                break;
+            }
+
+            // SAFETY: the entry is owned by userspace until it is reset
+            let (slot, offset) = unsafe { ((*entry).slot, (*entry).offset) };
+            // Drop the address space id
+            f(slot & 0xffff, offset);
+
+            flags.store(KVM_DIRTY_GFN_F_RESET, Ordering::Release);
+            self.next = self.next.wrapping_add(1);
+            count += 1;
+        }
+
+        count
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Drop for KvmDirtyRing {
+    fn drop(&mut self) {
+        // SAFETY: FFI call unmapping the ring mapped on creation
+        unsafe {
+            libc::munmap(
+                self.entries as *mut libc::c_void,
+                self.size as usize * std::mem::size_of::<KvmDirtyGfn>(),
+            )
+        };
+    }
+}
+
+// Dirty rings of all the vCPUs, along with the pages harvested from them
+// and not yet retrieved through get_dirty_log().
+#[cfg(target_arch = "x86_64")]
+struct KvmDirtyRings {
+    vm_fd: Arc<VmFd>,
+    size: u32,
+    rings: Vec<KvmDirtyRing>,
+    bitmaps: HashMap<u32, Vec<u64>>,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl KvmDirtyRings {
+    // Move the dirty pages from the rings to the bitmaps, and let KVM
+    // write-protect them again so that the next writes are caught.
+    fn harvest(&mut self) -> std::io::Result<()> {
+        let bitmaps = &mut self.bitmaps;
+        let mut count = 0;
+        for ring in self.rings.iter_mut() {
+            count += ring.harvest(|slot, offset| {
+                let bitmap = bitmaps.entry(slot).or_default();
+                let index = (offset / 64) as usize;
+                if bitmap.len() <= index {
+                    bitmap.resize(index + 1, 0);
+                }
+                bitmap[index] |= 1 << (offset % 64);
+            });
+        }
+
+        if count > 0 {
+            // SAFETY: valid VM file descriptor
+            let ret = unsafe { ioctl(&*self.vm_fd, KVM_RESET_DIRTY_RINGS()) };
+            if ret < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+}
+
 #[cfg(feature = "tdx")]
 #[repr(u32)]
 enum TdxCommand {
@@ -131,7 +407,11 @@ enum TdxCommand {
 
 #[cfg(feature = "tdx")]
 pub enum TdxExitDetails {
-    GetQuote,
+    /// Quote requested for the TDREPORT held by the shared buffer at `gpa`
+    GetQuote {
+        gpa: u64,
+        size: u64,
+    },
     SetupEventNotifyInterrupt,
 }
 
@@ -141,6 +421,42 @@ pub enum TdxExitStatus {
     InvalidOperand,
 }
 
+#[cfg(feature = "sev_snp")]
+#[repr(u32)]
+#[derive(Clone, Copy)]
+enum SevCommand {
+    LaunchStart = 2,
+    LaunchUpdateData = 3,
+    LaunchUpdateVmsa = 4,
+    LaunchMeasure = 6,
+    LaunchFinish = 7,
+    Init2 = 22,
+    SnpLaunchStart = 100,
+    SnpLaunchUpdate,
+    SnpLaunchFinish,
+}
+
+/// Type of the pages encrypted by the SEV-SNP launch sequence
+#[cfg(feature = "sev_snp")]
+#[repr(u8)]
+#[derive(Clone, Copy, Debug)]
+pub enum SevSnpPageType {
+    Normal = 1,
+    Zero = 3,
+    Unmeasured,
+    Secrets,
+    Cpuid,
+}
+
+#[cfg(feature = "sev_snp")]
+pub enum SevSnpExitDetails {
+    /// Page state change requested by the guest through the
+    /// KVM_HC_MAP_GPA_RANGE hypercall
+    MapGpaRange { gpa: u64, size: u64, private: bool },
+    /// Guest access to memory not matching its private or shared state
+    MemoryFault { gpa: u64, size: u64, private: bool },
+}
+
 #[cfg(feature = "tdx")]
 const TDX_MAX_NR_CPUID_CONFIGS: usize = 6;
 
@@ -178,6 +494,10 @@ impl From<kvm_userspace_memory_region> for UserMemoryRegion {
         if region.flags & KVM_MEM_LOG_DIRTY_PAGES != 0 {
             flags |= USER_MEMORY_REGION_LOG_DIRTY;
         }
+        #[cfg(feature = "sev_snp")]
+        if region.flags & KVM_MEM_GUEST_MEMFD != 0 {
+            flags |= USER_MEMORY_REGION_PRIVATE;
+        }
 
         UserMemoryRegion {
             slot: region.slot,
@@ -203,6 +523,10 @@ impl From<UserMemoryRegion> for kvm_userspace_memory_region {
         if region.flags & USER_MEMORY_REGION_LOG_DIRTY != 0 {
             flags |= KVM_MEM_LOG_DIRTY_PAGES;
         }
+        #[cfg(feature = "sev_snp")]
+        if region.flags & USER_MEMORY_REGION_PRIVATE != 0 {
+            flags |= KVM_MEM_GUEST_MEMFD;
+        }
 
         kvm_userspace_memory_region {
             slot: region.slot,
@@ -315,6 +639,15 @@ pub struct KvmVm {
     #[cfg(target_arch = "x86_64")]
     msrs: Vec<MsrEntry>,
     dirty_log_slots: Arc<RwLock<HashMap<u32, KvmDirtyLogSlot>>>,
+    // Dirty rings replacing the dirty bitmaps when supported
+    #[cfg(target_arch = "x86_64")]
+    dirty_rings: Option<Arc<Mutex<KvmDirtyRings>>>,
+    // Handle on the AMD Secure Processor, required by the SEV commands
+    #[cfg(feature = "sev_snp")]
+    sev_fd: Mutex<Option<File>>,
+    // Private memory backing the slots of a confidential guest
+    #[cfg(feature = "sev_snp")]
+    guest_memfds: Mutex<HashMap<u32, File>>,
 }
 
 impl KvmVm {
@@ -333,6 +666,122 @@ impl KvmVm {
     fn check_extension(&self, c: Cap) -> bool {
         self.fd.check_extension(c)
     }
+    ///
+    /// Creates a guest physical memory region backed by a guest_memfd, and
+    /// marks the whole range as private.
+    ///
+    #[cfg(feature = "sev_snp")]
+    fn create_private_memory_region(
+        &self,
+        region: kvm_userspace_memory_region,
+    ) -> std::io::Result<()> {
+        let data = KvmCreateGuestMemfd {
+            size: region.memory_size,
+            flags: 0,
+            reserved: [0; 6],
+        };
+        // SAFETY: FFI call. All input parameters are valid.
+        let ret = unsafe { ioctl_with_ref(&*self.fd, KVM_CREATE_GUEST_MEMFD(), &data) };
+        if ret < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // SAFETY: the file descriptor was just created by the kernel and is
+        // owned by nobody else.
+        let guest_memfd = unsafe { File::from_raw_fd(ret) };
+
+        let data = KvmUserspaceMemoryRegion2 {
+            slot: region.slot,
+            flags: region.flags,
+            guest_phys_addr: region.guest_phys_addr,
+            memory_size: region.memory_size,
+            userspace_addr: region.userspace_addr,
+            guest_memfd_offset: 0,
+            guest_memfd: guest_memfd.as_raw_fd() as u32,
+            pad1: 0,
+            pad2: [0; 14],
+        };
+        // SAFETY: FFI call. Safe because guest regions are guaranteed not to
+        // overlap.
+        let ret = unsafe { ioctl_with_ref(&*self.fd, KVM_SET_USER_MEMORY_REGION2(), &data) };
+        if ret < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        self.set_memory_attributes(region.guest_phys_addr, region.memory_size, true)?;
+
+        self.guest_memfds
+            .lock()
+            .unwrap()
+            .insert(region.slot, guest_memfd);
+
+        Ok(())
+    }
+    ///
+    /// Sets the private attribute on a range of guest memory.
+    ///
+    #[cfg(feature = "sev_snp")]
+    fn set_memory_attributes(&self, address: u64, size: u64, private: bool) -> std::io::Result<()> {
+        let data = KvmMemoryAttributes {
+            address,
+            size,
+            attributes: if private {
+                KVM_MEMORY_ATTRIBUTE_PRIVATE
+            } else {
+                0
+            },
+            flags: 0,
+        };
+        // SAFETY: FFI call. All input parameters are valid.
+        let ret = unsafe { ioctl_with_ref(&*self.fd, KVM_SET_MEMORY_ATTRIBUTES(), &data) };
+        if ret < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    ///
+    /// Issues a SEV command through the file descriptor of the AMD Secure
+    /// Processor opened by `sev_init()`.
+    ///
+    #[cfg(feature = "sev_snp")]
+    fn sev_issue_command(&self, command: SevCommand, data: u64) -> std::io::Result<()> {
+        let sev_fd = self
+            .sev_fd
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|f| f.as_raw_fd())
+            .ok_or_else(|| std::io::Error::from_raw_os_error(libc::EBADF))?;
+        sev_command(&self.fd.as_raw_fd(), sev_fd, command, data)
+    }
+    ///
+    /// Opens the AMD Secure Processor and initializes SEV for this VM,
+    /// the flavour of SEV being defined by the type of the VM.
+    ///
+    #[cfg(feature = "sev_snp")]
+    fn sev_init(&self, ghcb_version: u16) -> std::io::Result<()> {
+        let sev = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/sev")?;
+        *self.sev_fd.lock().unwrap() = Some(sev);
+
+        #[repr(C)]
+        struct KvmSevInit {
+            vmsa_features: u64,
+            flags: u32,
+            ghcb_version: u16,
+            pad1: u16,
+            pad2: [u32; 8],
+        }
+        let data = KvmSevInit {
+            vmsa_features: 0,
+            flags: 0,
+            ghcb_version,
+            pad1: 0,
+            pad2: [0; 8],
+        };
+        self.sev_issue_command(SevCommand::Init2, &data as *const _ as u64)
+    }
 }
 
 ///
@@ -393,20 +842,34 @@ impl vm::Vm for KvmVm {
     ///
     fn create_vcpu(
         &self,
-        id: u8,
+        id: u32,
         vm_ops: Option<Arc<dyn VmOps>>,
     ) -> vm::Result<Arc<dyn cpu::Vcpu>> {
         let vc = self
             .fd
-            .create_vcpu(id as u64)
+            .create_vcpu(u64::from(id))
             .map_err(|e| vm::HypervisorVmError::CreateVcpu(e.into()))?;
+        #[cfg(target_arch = "x86_64")]
+        if let Some(dirty_rings) = &self.dirty_rings {
+            let mut dirty_rings = dirty_rings.lock().unwrap();
+            let ring = KvmDirtyRing::new(&vc, dirty_rings.size)
+                .map_err(|e| vm::HypervisorVmError::CreateVcpu(e.into()))?;
+            dirty_rings.rings.push(ring);
+        }
         let vcpu = KvmVcpu {
             fd: vc,
             #[cfg(target_arch = "x86_64")]
             msrs: self.msrs.clone(),
+            #[cfg(target_arch = "x86_64")]
+            dirty_rings: self.dirty_rings.clone(),
             vm_ops,
             #[cfg(target_arch = "x86_64")]
             hyperv_synic: AtomicBool::new(false),
+            #[cfg(target_arch = "aarch64")]
+            sve_enabled: AtomicBool::new(false),
+            #[cfg(target_arch = "aarch64")]
+            el2_enabled: AtomicBool::new(false),
+            exit_counters: Arc::new(cpu::VcpuExitCounters::default()),
         };
         Ok(Arc::new(vcpu))
     }
@@ -595,6 +1058,13 @@ impl vm::Vm for KvmVm {
             region.flags = 0;
         }
 
+        #[cfg(feature = "sev_snp")]
+        if (region.flags & KVM_MEM_GUEST_MEMFD) != 0 {
+            return self
+                .create_private_memory_region(region)
+                .map_err(|e| vm::HypervisorVmError::CreateUserMemory(e.into()));
+        }
+
         // SAFETY: Safe because guest regions are guaranteed not to overlap.
         unsafe {
             self.fd
@@ -610,6 +1080,17 @@ impl vm::Vm for KvmVm {
 
         // Remove the corresponding entry from "self.dirty_log_slots" if needed
         self.dirty_log_slots.write().unwrap().remove(&region.slot);
+        #[cfg(target_arch = "x86_64")]
+        if let Some(dirty_rings) = &self.dirty_rings {
+            dirty_rings.lock().unwrap().bitmaps.remove(&region.slot);
+        }
+
+        // The legacy ioctl doesn't accept the private memory flag, which is
+        // not needed for removing the region anyway.
+        #[cfg(feature = "sev_snp")]
+        {
+            region.flags &= !KVM_MEM_GUEST_MEMFD;
+        }
 
         // Setting the size to 0 means "remove"
         region.memory_size = 0;
@@ -617,8 +1098,13 @@ impl vm::Vm for KvmVm {
         unsafe {
             self.fd
                 .set_user_memory_region(region)
-                .map_err(|e| vm::HypervisorVmError::RemoveUserMemory(e.into()))
+                .map_err(|e| vm::HypervisorVmError::RemoveUserMemory(e.into()))?;
         }
+
+        #[cfg(feature = "sev_snp")]
+        self.guest_memfds.lock().unwrap().remove(&region.slot);
+
+        Ok(())
     }
     ///
     /// Returns the preferred CPU target type which can be emulated by KVM on underlying host.
@@ -645,6 +1131,35 @@ impl vm::Vm for KvmVm {
         Ok(())
     }
     #[cfg(target_arch = "x86_64")]
+    fn enable_x2apic_api(&self) -> vm::Result<()> {
+        // Let the APIC IDs, and thus the MSI destinations, span 32 bits
+        let mut cap = kvm_enable_cap {
+            cap: KVM_CAP_X2APIC_API,
+            ..Default::default()
+        };
+        cap.args[0] = KVM_X2APIC_API_USE_32BIT_IDS | KVM_X2APIC_API_DISABLE_BROADCAST_QUIRK;
+        self.fd
+            .enable_cap(&cap)
+            .map_err(|e| vm::HypervisorVmError::EnableX2ApicApi(e.into()))?;
+        Ok(())
+    }
+    #[cfg(target_arch = "aarch64")]
+    fn enable_mte(&self) -> vm::Result<()> {
+        let cap = kvm_bindings::kvm_enable_cap {
+            cap: KVM_CAP_ARM_MTE,
+            ..Default::default()
+        };
+        self.fd
+            .enable_cap(&cap)
+            .map_err(|e| vm::HypervisorVmError::EnableMte(e.into()))
+    }
+    #[cfg(target_arch = "aarch64")]
+    fn has_nested_virt(&self) -> bool {
+        // SAFETY: FFI call with a valid VM fd, KVM_CHECK_EXTENSION doesn't
+        // access any memory.
+        unsafe { ioctl_with_val(&self.fd, KVM_CHECK_EXTENSION(), KVM_CAP_ARM_EL2.into()) > 0 }
+    }
+    #[cfg(target_arch = "x86_64")]
     fn enable_sgx_attribute(&self, file: File) -> vm::Result<()> {
         let mut cap = kvm_enable_cap {
             cap: KVM_CAP_SGX_ATTRIBUTE,
@@ -688,6 +1203,16 @@ impl vm::Vm for KvmVm {
     /// Start logging dirty pages
     ///
     fn start_dirty_log(&self) -> vm::Result<()> {
+        // Discard anything left over from a previous logging session
+        #[cfg(target_arch = "x86_64")]
+        if let Some(dirty_rings) = &self.dirty_rings {
+            let mut dirty_rings = dirty_rings.lock().unwrap();
+            dirty_rings
+                .harvest()
+                .map_err(|e| vm::HypervisorVmError::StartDirtyLog(e.into()))?;
+            dirty_rings.bitmaps.clear();
+        }
+
         let dirty_log_slots = self.dirty_log_slots.read().unwrap();
         for (_, s) in dirty_log_slots.iter() {
             let region = kvm_userspace_memory_region {
@@ -736,6 +1261,21 @@ impl vm::Vm for KvmVm {
     /// Get dirty pages bitmap (one bit per page)
     ///
     fn get_dirty_log(&self, slot: u32, _base_gpa: u64, memory_size: u64) -> vm::Result<Vec<u64>> {
+        // KVM_GET_DIRTY_LOG is not available along with the dirty ring,
+        // the bitmap is built from the entries harvested from all the vCPUs
+        // instead, which avoids scanning the whole slot.
+        #[cfg(target_arch = "x86_64")]
+        if let Some(dirty_rings) = &self.dirty_rings {
+            let mut dirty_rings = dirty_rings.lock().unwrap();
+            dirty_rings
+                .harvest()
+                .map_err(|e| vm::HypervisorVmError::GetDirtyLog(e.into()))?;
+            let mut bitmap = dirty_rings.bitmaps.remove(&slot).unwrap_or_default();
+            let pages = memory_size / 4096;
+            bitmap.resize(((pages + 63) / 64) as usize, 0);
+            return Ok(bitmap);
+        }
+
         self.fd
             .get_dirty_log(slot, memory_size as usize)
             .map_err(|e| vm::HypervisorVmError::GetDirtyLog(e.into()))
@@ -825,6 +1365,250 @@ impl vm::Vm for KvmVm {
         )
         .map_err(vm::HypervisorVmError::InitMemRegionTdx)
     }
+
+    ///
+    /// Initialize SEV-SNP for this VM and start the launch sequence
+    ///
+    #[cfg(feature = "sev_snp")]
+    fn sev_snp_init(&self, policy: u64) -> vm::Result<()> {
+        self.sev_init(SEV_GHCB_VERSION)
+            .map_err(vm::HypervisorVmError::InitializeSevSnp)?;
+
+        // Let the page state changes requested by the guest reach the VMM
+        let mut cap = kvm_enable_cap {
+            cap: KVM_CAP_EXIT_HYPERCALL,
+            ..Default::default()
+        };
+        cap.args[0] = 1 << KVM_HC_MAP_GPA_RANGE;
+        self.fd.enable_cap(&cap).map_err(|e| {
+            vm::HypervisorVmError::InitializeSevSnp(std::io::Error::from_raw_os_error(e.errno()))
+        })?;
+
+        #[repr(C)]
+        struct KvmSevSnpLaunchStart {
+            policy: u64,
+            gosvw: [u8; 16],
+            flags: u16,
+            pad0: [u8; 6],
+            pad1: [u64; 4],
+        }
+        let data = KvmSevSnpLaunchStart {
+            policy,
+            gosvw: [0; 16],
+            flags: 0,
+            pad0: [0; 6],
+            pad1: [0; 4],
+        };
+        self.sev_issue_command(SevCommand::SnpLaunchStart, &data as *const _ as u64)
+            .map_err(vm::HypervisorVmError::InitializeSevSnp)
+    }
+
+    ///
+    /// Encrypt and measure a memory region as part of the SEV-SNP launch
+    ///
+    #[cfg(feature = "sev_snp")]
+    fn sev_snp_launch_update(
+        &self,
+        host_address: u64,
+        guest_address: u64,
+        size: u64,
+        page_type: SevSnpPageType,
+    ) -> vm::Result<()> {
+        #[repr(C)]
+        struct KvmSevSnpLaunchUpdate {
+            gfn_start: u64,
+            uaddr: u64,
+            len: u64,
+            page_type: SevSnpPageType,
+            pad0: u8,
+            flags: u16,
+            pad1: u32,
+            pad2: [u64; 4],
+        }
+        let mut data = KvmSevSnpLaunchUpdate {
+            gfn_start: guest_address >> 12,
+            uaddr: host_address,
+            len: size,
+            page_type,
+            pad0: 0,
+            flags: 0,
+            pad1: 0,
+            pad2: [0; 4],
+        };
+
+        // KVM updates the structure as it goes, so that the command can be
+        // resumed from where it stopped when interrupted.
+        loop {
+            match self.sev_issue_command(SevCommand::SnpLaunchUpdate, &mut data as *mut _ as u64) {
+                Ok(()) if data.len == 0 => return Ok(()),
+                Ok(()) => {}
+                Err(e) if matches!(e.raw_os_error(), Some(libc::EAGAIN) | Some(libc::EINTR)) => {}
+                Err(e) => return Err(vm::HypervisorVmError::LaunchUpdateSevSnp(e)),
+            }
+        }
+    }
+
+    ///
+    /// Complete the SEV-SNP launch sequence
+    ///
+    #[cfg(feature = "sev_snp")]
+    fn sev_snp_launch_finish(&self, host_data: &[u8; 32]) -> vm::Result<()> {
+        #[repr(C)]
+        struct KvmSevSnpLaunchFinish {
+            id_block_uaddr: u64,
+            id_auth_uaddr: u64,
+            id_block_en: u8,
+            auth_key_en: u8,
+            vcek_disabled: u8,
+            host_data: [u8; 32],
+            pad0: [u8; 3],
+            flags: u16,
+            pad1: [u64; 4],
+        }
+        let data = KvmSevSnpLaunchFinish {
+            id_block_uaddr: 0,
+            id_auth_uaddr: 0,
+            id_block_en: 0,
+            auth_key_en: 0,
+            vcek_disabled: 0,
+            host_data: *host_data,
+            pad0: [0; 3],
+            flags: 0,
+            pad1: [0; 4],
+        };
+        self.sev_issue_command(SevCommand::SnpLaunchFinish, &data as *const _ as u64)
+            .map_err(vm::HypervisorVmError::LaunchFinishSevSnp)
+    }
+
+    ///
+    /// Convert a range of guest memory between private and shared
+    ///
+    #[cfg(feature = "sev_snp")]
+    fn set_memory_private(&self, guest_address: u64, size: u64, private: bool) -> vm::Result<()> {
+        self.set_memory_attributes(guest_address, size, private)
+            .map_err(vm::HypervisorVmError::SetMemoryAttributes)
+    }
+
+    ///
+    /// Initialize SEV-ES for this VM and start the launch sequence
+    ///
+    #[cfg(feature = "sev_snp")]
+    fn sev_es_init(&self, policy: u32) -> vm::Result<()> {
+        // Let KVM pick the GHCB protocol version
+        self.sev_init(0)
+            .map_err(vm::HypervisorVmError::InitializeSevEs)?;
+
+        #[repr(C)]
+        struct KvmSevLaunchStart {
+            handle: u32,
+            policy: u32,
+            dh_uaddr: u64,
+            dh_len: u32,
+            pad0: u32,
+            session_uaddr: u64,
+            session_len: u32,
+            pad1: u32,
+        }
+        let data = KvmSevLaunchStart {
+            handle: 0,
+            policy,
+            dh_uaddr: 0,
+            dh_len: 0,
+            pad0: 0,
+            session_uaddr: 0,
+            session_len: 0,
+            pad1: 0,
+        };
+        self.sev_issue_command(SevCommand::LaunchStart, &data as *const _ as u64)
+            .map_err(vm::HypervisorVmError::InitializeSevEs)
+    }
+
+    ///
+    /// Pin a range of host memory backing an encrypted guest
+    ///
+    #[cfg(feature = "sev_snp")]
+    fn sev_register_encrypted_region(&self, host_address: u64, size: u64) -> vm::Result<()> {
+        let region = KvmEncRegion {
+            addr: host_address,
+            size,
+        };
+        // SAFETY: FFI call. All input parameters are valid.
+        let ret = unsafe { ioctl_with_ref(&*self.fd, KVM_MEMORY_ENCRYPT_REG_REGION(), &region) };
+        if ret < 0 {
+            return Err(vm::HypervisorVmError::RegisterEncryptedRegion(
+                std::io::Error::last_os_error(),
+            ));
+        }
+        Ok(())
+    }
+
+    ///
+    /// Encrypt and measure a memory region as part of the SEV-ES launch
+    ///
+    #[cfg(feature = "sev_snp")]
+    fn sev_es_launch_update_data(&self, host_address: u64, size: u64) -> vm::Result<()> {
+        #[repr(C)]
+        struct KvmSevLaunchUpdateData {
+            uaddr: u64,
+            len: u32,
+        }
+        // The length of a single update is limited to 32 bits, larger regions
+        // are measured and encrypted through several page aligned chunks.
+        const MAX_UPDATE_SIZE: u64 = 1 << 31;
+
+        let mut offset = 0;
+        while offset < size {
+            let len = std::cmp::min(size - offset, MAX_UPDATE_SIZE);
+            let data = KvmSevLaunchUpdateData {
+                uaddr: host_address + offset,
+                len: len as u32,
+            };
+            self.sev_issue_command(SevCommand::LaunchUpdateData, &data as *const _ as u64)
+                .map_err(vm::HypervisorVmError::LaunchUpdateSevEs)?;
+            offset += len;
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Encrypt and measure the state of every vCPU of the SEV-ES guest
+    ///
+    #[cfg(feature = "sev_snp")]
+    fn sev_es_launch_update_vmsa(&self) -> vm::Result<()> {
+        self.sev_issue_command(SevCommand::LaunchUpdateVmsa, 0)
+            .map_err(vm::HypervisorVmError::LaunchUpdateVmsaSevEs)
+    }
+
+    ///
+    /// Retrieve the SEV-ES launch measurement
+    ///
+    #[cfg(feature = "sev_snp")]
+    fn sev_es_launch_measure(&self) -> vm::Result<Vec<u8>> {
+        #[repr(C)]
+        struct KvmSevLaunchMeasure {
+            uaddr: u64,
+            len: u32,
+        }
+        let mut measurement = vec![0u8; SEV_LAUNCH_MEASUREMENT_SIZE];
+        let mut data = KvmSevLaunchMeasure {
+            uaddr: measurement.as_mut_ptr() as u64,
+            len: measurement.len() as u32,
+        };
+        self.sev_issue_command(SevCommand::LaunchMeasure, &mut data as *mut _ as u64)
+            .map_err(vm::HypervisorVmError::LaunchMeasureSevEs)?;
+        measurement.truncate(data.len as usize);
+        Ok(measurement)
+    }
+
+    ///
+    /// Complete the SEV-ES launch sequence
+    ///
+    #[cfg(feature = "sev_snp")]
+    fn sev_es_launch_finish(&self) -> vm::Result<()> {
+        self.sev_issue_command(SevCommand::LaunchFinish, 0)
+            .map_err(vm::HypervisorVmError::LaunchFinishSevEs)
+    }
     /// Downcast to the underlying KvmVm type
     fn as_any(&self) -> &dyn Any {
         self
@@ -868,6 +1652,41 @@ fn tdx_command(
     Ok(())
 }
 
+#[cfg(feature = "sev_snp")]
+fn sev_command(
+    fd: &RawFd,
+    sev_fd: RawFd,
+    command: SevCommand,
+    data: u64,
+) -> std::result::Result<(), std::io::Error> {
+    #[repr(C)]
+    struct KvmSevCmd {
+        id: SevCommand,
+        pad0: u32,
+        data: u64,
+        error: u32,
+        sev_fd: u32,
+    }
+    let mut cmd = KvmSevCmd {
+        id: command,
+        pad0: 0,
+        data,
+        error: 0,
+        sev_fd: sev_fd as u32,
+    };
+    // SAFETY: FFI call. All input parameters are valid.
+    let ret = unsafe { ioctl_with_mut_ref(fd, KVM_MEMORY_ENCRYPT_OP(), &mut cmd) };
+
+    if ret < 0 {
+        let e = std::io::Error::last_os_error();
+        if cmd.error != 0 {
+            warn!("SEV firmware error 0x{:x}", cmd.error);
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
 /// Wrapper over KVM system ioctls.
 pub struct KvmHypervisor {
     kvm: Kvm,
@@ -883,6 +1702,43 @@ impl KvmHypervisor {
             .get_msr_index_list()
             .map_err(|e| hypervisor::HypervisorError::GetMsrList(e.into()))
     }
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Enable the dirty ring on a new VM, returning the number of entries of
+    /// each vCPU ring, or None if the dirty bitmap must be used instead.
+    ///
+    fn enable_dirty_ring(&self, vm_fd: &VmFd) -> Option<u32> {
+        // SAFETY: FFI call with a valid VM fd, KVM_CHECK_EXTENSION doesn't
+        // access any memory.
+        let max_size = unsafe {
+            vmm_sys_util::ioctl::ioctl_with_val(
+                vm_fd,
+                KVM_CHECK_EXTENSION(),
+                KVM_CAP_DIRTY_LOG_RING.into(),
+            )
+        };
+        if max_size <= 0 {
+            return None;
+        }
+
+        let entry_size = std::mem::size_of::<KvmDirtyGfn>() as u32;
+        let size = KVM_DIRTY_RING_ENTRIES.min(max_size as u32 / entry_size);
+        let cap = kvm_enable_cap {
+            cap: KVM_CAP_DIRTY_LOG_RING,
+            args: [(size * entry_size).into(), 0, 0, 0],
+            ..Default::default()
+        };
+        match vm_fd.enable_cap(&cap) {
+            Ok(()) => {
+                info!("Using the KVM dirty ring with {} entries per vCPU", size);
+                Some(size)
+            }
+            Err(e) => {
+                warn!("Could not enable the KVM dirty ring: {}", e);
+                None
+            }
+        }
+    }
 }
 
 /// Enum for KVM related error
@@ -975,10 +1831,24 @@ impl hypervisor::Hypervisor for KvmHypervisor {
                 msrs[pos].index = *index;
             }
 
+            let dirty_rings = self.enable_dirty_ring(&vm_fd).map(|size| {
+                Arc::new(Mutex::new(KvmDirtyRings {
+                    vm_fd: vm_fd.clone(),
+                    size,
+                    rings: Vec::new(),
+                    bitmaps: HashMap::new(),
+                }))
+            });
+
             Ok(Arc::new(KvmVm {
                 fd: vm_fd,
                 msrs,
                 dirty_log_slots: Arc::new(RwLock::new(HashMap::new())),
+                dirty_rings,
+                #[cfg(feature = "sev_snp")]
+                sev_fd: Mutex::new(None),
+                #[cfg(feature = "sev_snp")]
+                guest_memfds: Mutex::new(HashMap::new()),
             }))
         }
 
@@ -1034,6 +1904,38 @@ impl hypervisor::Hypervisor for KvmHypervisor {
         Ok(v)
     }
 
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// X86 specific call to get the system supported value of feature MSRs.
+    ///
+    fn get_msr_features(&self, indices: &[u32]) -> hypervisor::Result<Vec<MsrEntry>> {
+        let mut msr_features = Vec::new();
+        // The MSRs are read one by one, as KVM stops at the first one it
+        // doesn't know about.
+        for index in indices {
+            let mut kvm_msrs = MsrEntries::from_entries(&[kvm_msr_entry {
+                index: *index,
+                ..Default::default()
+            }])
+            .unwrap();
+            // SAFETY: FFI call with a valid fd and a kvm_msrs buffer holding
+            // one entry, as advertised by its nmsrs field.
+            let ret = unsafe {
+                ioctl_with_mut_ptr(&self.kvm, KVM_GET_MSRS(), kvm_msrs.as_mut_fam_struct_ptr())
+            };
+            if ret < 0 {
+                return Err(hypervisor::HypervisorError::GetMsrFeatures(
+                    std::io::Error::last_os_error().into(),
+                ));
+            }
+            if ret == 1 {
+                msr_features.push(kvm_msrs.as_slice()[0].into());
+            }
+        }
+
+        Ok(msr_features)
+    }
+
     #[cfg(target_arch = "aarch64")]
     ///
     /// Retrieve AArch64 host maximum IPA size supported by KVM.
@@ -1082,9 +1984,16 @@ pub struct KvmVcpu {
     fd: VcpuFd,
     #[cfg(target_arch = "x86_64")]
     msrs: Vec<MsrEntry>,
+    #[cfg(target_arch = "x86_64")]
+    dirty_rings: Option<Arc<Mutex<KvmDirtyRings>>>,
     vm_ops: Option<Arc<dyn vm::VmOps>>,
     #[cfg(target_arch = "x86_64")]
     hyperv_synic: AtomicBool,
+    #[cfg(target_arch = "aarch64")]
+    sve_enabled: AtomicBool,
+    #[cfg(target_arch = "aarch64")]
+    el2_enabled: AtomicBool,
+    exit_counters: Arc<cpu::VcpuExitCounters>,
 }
 /// Implementation of Vcpu trait for KVM
 /// Example:
@@ -1192,13 +2101,17 @@ impl cpu::Vcpu for KvmVcpu {
 
         // Now moving on to floting point registers which are stored in the user_fpsimd_state in the kernel:
         // https://elixir.free-electrons.com/linux/v4.9.62/source/arch/arm64/include/uapi/asm/kvm.h#L53
-        let mut off = offset_of!(kvm_regs, fp_regs) + offset_of!(user_fpsimd_state, vregs);
-        for i in 0..32 {
-            state.fp_regs.vregs[i] = self
-                .fd
-                .get_one_reg(arm64_core_reg_id!(KVM_REG_SIZE_U128, off))
-                .map_err(|e| cpu::HypervisorCpuError::GetCoreRegister(e.into()))?;
-            off += mem::size_of::<u128>();
+        // With SVE enabled, the vector registers are only accessible through
+        // the SVE Z registers they are part of.
+        if !self.sve_enabled.load(Ordering::Acquire) {
+            let mut off = offset_of!(kvm_regs, fp_regs) + offset_of!(user_fpsimd_state, vregs);
+            for i in 0..32 {
+                state.fp_regs.vregs[i] = self
+                    .fd
+                    .get_one_reg(arm64_core_reg_id!(KVM_REG_SIZE_U128, off))
+                    .map_err(|e| cpu::HypervisorCpuError::GetCoreRegister(e.into()))?;
+                off += mem::size_of::<u128>();
+            }
         }
 
         // Floating-point Status Register
@@ -1302,15 +2215,17 @@ impl cpu::Vcpu for KvmVcpu {
             off += std::mem::size_of::<u64>();
         }
 
-        let mut off = offset_of!(kvm_regs, fp_regs) + offset_of!(user_fpsimd_state, vregs);
-        for i in 0..32 {
-            self.fd
-                .set_one_reg(
-                    arm64_core_reg_id!(KVM_REG_SIZE_U128, off),
-                    state.fp_regs.vregs[i],
-                )
-                .map_err(|e| cpu::HypervisorCpuError::SetCoreRegister(e.into()))?;
-            off += mem::size_of::<u128>();
+        if !self.sve_enabled.load(Ordering::Acquire) {
+            let mut off = offset_of!(kvm_regs, fp_regs) + offset_of!(user_fpsimd_state, vregs);
+            for i in 0..32 {
+                self.fd
+                    .set_one_reg(
+                        arm64_core_reg_id!(KVM_REG_SIZE_U128, off),
+                        state.fp_regs.vregs[i],
+                    )
+                    .map_err(|e| cpu::HypervisorCpuError::SetCoreRegister(e.into()))?;
+                off += mem::size_of::<u128>();
+            }
         }
 
         let off = offset_of!(kvm_regs, fp_regs) + offset_of!(user_fpsimd_state, fpsr);
@@ -1516,6 +2431,7 @@ impl cpu::Vcpu for KvmVcpu {
             Ok(run) => match run {
                 #[cfg(target_arch = "x86_64")]
                 VcpuExit::IoIn(addr, data) => {
+                    self.exit_counters.io.fetch_add(1, Ordering::Relaxed);
                     if let Some(vm_ops) = &self.vm_ops {
                         return vm_ops
                             .pio_read(addr.into(), data)
@@ -1527,6 +2443,7 @@ impl cpu::Vcpu for KvmVcpu {
                 }
                 #[cfg(target_arch = "x86_64")]
                 VcpuExit::IoOut(addr, data) => {
+                    self.exit_counters.io.fetch_add(1, Ordering::Relaxed);
                     if let Some(vm_ops) = &self.vm_ops {
                         return vm_ops
                             .pio_write(addr.into(), data)
@@ -1539,7 +2456,12 @@ impl cpu::Vcpu for KvmVcpu {
                 #[cfg(target_arch = "x86_64")]
                 VcpuExit::IoapicEoi(vector) => Ok(cpu::VmExit::IoapicEoi(vector)),
                 #[cfg(target_arch = "x86_64")]
-                VcpuExit::Shutdown | VcpuExit::Hlt => Ok(cpu::VmExit::Reset),
+                VcpuExit::Shutdown => Ok(cpu::VmExit::Reset),
+                #[cfg(target_arch = "x86_64")]
+                VcpuExit::Hlt => {
+                    self.exit_counters.hlt.fetch_add(1, Ordering::Relaxed);
+                    Ok(cpu::VmExit::Reset)
+                }
 
                 #[cfg(target_arch = "aarch64")]
                 VcpuExit::SystemEvent(event_type, flags) => {
@@ -1560,6 +2482,7 @@ impl cpu::Vcpu for KvmVcpu {
                 }
 
                 VcpuExit::MmioRead(addr, data) => {
+                    self.exit_counters.mmio.fetch_add(1, Ordering::Relaxed);
                     if let Some(vm_ops) = &self.vm_ops {
                         return vm_ops
                             .mmio_read(addr, data)
@@ -1570,6 +2493,7 @@ impl cpu::Vcpu for KvmVcpu {
                     Ok(cpu::VmExit::MmioRead(addr, data))
                 }
                 VcpuExit::MmioWrite(addr, data) => {
+                    self.exit_counters.mmio.fetch_add(1, Ordering::Relaxed);
                     if let Some(vm_ops) = &self.vm_ops {
                         return vm_ops
                             .mmio_write(addr, data)
@@ -1582,6 +2506,21 @@ impl cpu::Vcpu for KvmVcpu {
                 VcpuExit::Hyperv => Ok(cpu::VmExit::Hyperv),
                 #[cfg(feature = "tdx")]
                 VcpuExit::Unsupported(KVM_EXIT_TDX) => Ok(cpu::VmExit::Tdx),
+                // The ring of this vCPU must be harvested before it can run
+                // again, take the opportunity to collect all of them.
+                #[cfg(target_arch = "x86_64")]
+                VcpuExit::Unsupported(KVM_EXIT_DIRTY_RING_FULL) => {
+                    if let Some(dirty_rings) = &self.dirty_rings {
+                        dirty_rings
+                            .lock()
+                            .unwrap()
+                            .harvest()
+                            .map_err(|e| cpu::HypervisorCpuError::RunVcpu(e.into()))?;
+                    }
+                    Ok(cpu::VmExit::Ignore)
+                }
+                #[cfg(feature = "sev_snp")]
+                VcpuExit::Hypercall => Ok(cpu::VmExit::SevSnp),
                 VcpuExit::Debug(_) => Ok(cpu::VmExit::Debug),
 
                 r => Err(cpu::HypervisorCpuError::RunVcpu(anyhow!(
@@ -1592,6 +2531,15 @@ impl cpu::Vcpu for KvmVcpu {
 
             Err(ref e) => match e.errno() {
                 libc::EAGAIN | libc::EINTR => Ok(cpu::VmExit::Ignore),
+                // KVM_EXIT_MEMORY_FAULT comes along with EFAULT, and is
+                // sorted out by get_sev_snp_exit_details().
+                #[cfg(feature = "sev_snp")]
+                libc::EFAULT => {
+                    self.exit_counters
+                        .ept_violation
+                        .fetch_add(1, Ordering::Relaxed);
+                    Ok(cpu::VmExit::SevSnp)
+                }
                 _ => Err(cpu::HypervisorCpuError::RunVcpu(anyhow!(
                     "VCPU error {:?}",
                     e
@@ -1599,6 +2547,12 @@ impl cpu::Vcpu for KvmVcpu {
             },
         }
     }
+    ///
+    /// Returns the counters of the exits handled by run(), by reason.
+    ///
+    fn exit_counters(&self) -> Arc<cpu::VcpuExitCounters> {
+        self.exit_counters.clone()
+    }
     #[cfg(target_arch = "x86_64")]
     ///
     /// Let the guest know that it has been paused, which prevents from
@@ -1673,7 +2627,16 @@ impl cpu::Vcpu for KvmVcpu {
     fn vcpu_init(&self, kvi: &VcpuInit) -> cpu::Result<()> {
         self.fd
             .vcpu_init(kvi)
-            .map_err(|e| cpu::HypervisorCpuError::VcpuInit(e.into()))
+            .map_err(|e| cpu::HypervisorCpuError::VcpuInit(e.into()))?;
+        self.sve_enabled.store(
+            kvi.features[0] & (1 << KVM_ARM_VCPU_SVE) != 0,
+            Ordering::Release,
+        );
+        self.el2_enabled.store(
+            kvi.features[0] & (1 << KVM_ARM_VCPU_HAS_EL2) != 0,
+            Ordering::Release,
+        );
+        Ok(())
     }
     ///
     /// Gets a list of the guest registers that are supported for the
@@ -1722,18 +2685,26 @@ impl cpu::Vcpu for KvmVcpu {
     /// Configure core registers for a given CPU.
     ///
     #[cfg(target_arch = "aarch64")]
-    fn setup_regs(&self, cpu_id: u8, boot_ip: u64, fdt_start: u64) -> cpu::Result<()> {
+    fn setup_regs(&self, cpu_id: u32, boot_ip: u64, fdt_start: u64) -> cpu::Result<()> {
         #[allow(non_upper_case_globals)]
         // PSR (Processor State Register) bits.
         // Taken from arch/arm64/include/uapi/asm/ptrace.h.
         const PSR_MODE_EL1h: u64 = 0x0000_0005;
+        const PSR_MODE_EL2h: u64 = 0x0000_0009;
         const PSR_F_BIT: u64 = 0x0000_0040;
         const PSR_I_BIT: u64 = 0x0000_0080;
         const PSR_A_BIT: u64 = 0x0000_0100;
         const PSR_D_BIT: u64 = 0x0000_0200;
         // Taken from arch/arm64/kvm/inject_fault.c.
-        const PSTATE_FAULT_BITS_64: u64 =
-            PSR_MODE_EL1h | PSR_A_BIT | PSR_F_BIT | PSR_I_BIT | PSR_D_BIT;
+        const PSTATE_FAULT_BITS_64: u64 = PSR_A_BIT | PSR_F_BIT | PSR_I_BIT | PSR_D_BIT;
+
+        // The guest kernel is entered at EL2 when it can host its own
+        // hypervisor.
+        let mode = if self.el2_enabled.load(Ordering::Acquire) {
+            PSR_MODE_EL2h
+        } else {
+            PSR_MODE_EL1h
+        };
 
         let kreg_off = offset_of!(kvm_regs, regs);
 
@@ -1742,7 +2713,7 @@ impl cpu::Vcpu for KvmVcpu {
         self.fd
             .set_one_reg(
                 arm64_core_reg_id!(KVM_REG_SIZE_U64, pstate),
-                PSTATE_FAULT_BITS_64.into(),
+                (mode | PSTATE_FAULT_BITS_64).into(),
             )
             .map_err(|e| cpu::HypervisorCpuError::SetCoreRegister(e.into()))?;
 
@@ -1919,6 +2890,15 @@ impl cpu::Vcpu for KvmVcpu {
         // all of them. We carve out from the list  the core registers which are
         // represented in the kernel by kvm_regs structure and for which we can
         // calculate the id based on the offset in the structure.
+        // The SVE registers are saved separately. The vector lengths must not
+        // be restored, as they are configured before the vCPU is finalized.
+        for index in reg_list.as_slice() {
+            if is_sve_register(*index) && *index != KVM_REG_ARM64_SVE_VLS {
+                let mut data = vec![0u8; reg_size(*index)];
+                self.get_sve_reg(*index, &mut data)?;
+                state.sve_regs.push(SveRegister { id: *index, data });
+            }
+        }
         reg_list.retain(|regid| is_system_register(*regid));
 
         // Now, for the rest of the registers left in the previously fetched
@@ -2040,6 +3020,10 @@ impl cpu::Vcpu for KvmVcpu {
                 .set_one_reg(reg.id, reg.addr.into())
                 .map_err(|e| cpu::HypervisorCpuError::SetSysRegister(e.into()))?;
         }
+        // Set SVE registers
+        for reg in &state.sve_regs {
+            self.set_sve_reg(reg.id, &reg.data)?;
+        }
 
         self.set_mp_state(state.mp_state.into())?;
 
@@ -2078,7 +3062,10 @@ impl cpu::Vcpu for KvmVcpu {
         }
 
         match tdx_vmcall.subfunction {
-            TDG_VP_VMCALL_GET_QUOTE => Ok(TdxExitDetails::GetQuote),
+            TDG_VP_VMCALL_GET_QUOTE => Ok(TdxExitDetails::GetQuote {
+                gpa: tdx_vmcall.in_r12,
+                size: tdx_vmcall.in_r13,
+            }),
             TDG_VP_VMCALL_SETUP_EVENT_NOTIFY_INTERRUPT => {
                 Ok(TdxExitDetails::SetupEventNotifyInterrupt)
             }
@@ -2100,6 +3087,65 @@ impl cpu::Vcpu for KvmVcpu {
             TdxExitStatus::InvalidOperand => TDG_VP_VMCALL_INVALID_OPERAND,
         };
     }
+
+    ///
+    /// Returns the details about the SEV-SNP exit reason
+    ///
+    #[cfg(feature = "sev_snp")]
+    fn get_sev_snp_exit_details(&mut self) -> cpu::Result<SevSnpExitDetails> {
+        let kvm_run = self.fd.get_kvm_run();
+        match kvm_run.exit_reason {
+            kvm_bindings::KVM_EXIT_HYPERCALL => {
+                // SAFETY: accessing a union field in a valid structure
+                let hypercall = unsafe { &kvm_run.__bindgen_anon_1.hypercall };
+                if hypercall.nr != KVM_HC_MAP_GPA_RANGE {
+                    return Err(cpu::HypervisorCpuError::UnknownSevSnpExit);
+                }
+
+                let page_size: u64 = match hypercall.args[2] & KVM_MAP_GPA_RANGE_PAGE_SZ_MASK {
+                    0 => 4 << 10,
+                    1 => 2 << 20,
+                    2 => 1 << 30,
+                    _ => return Err(cpu::HypervisorCpuError::UnknownSevSnpExit),
+                };
+
+                Ok(SevSnpExitDetails::MapGpaRange {
+                    gpa: hypercall.args[0],
+                    size: hypercall.args[1] * page_size,
+                    private: hypercall.args[2] & KVM_MAP_GPA_RANGE_ENCRYPTED != 0,
+                })
+            }
+            KVM_EXIT_MEMORY_FAULT => {
+                // SAFETY: the memory_fault structure, missing from the
+                // bindings, fits in the padding of the union.
+                let fault = unsafe {
+                    std::ptr::read_unaligned(
+                        kvm_run.__bindgen_anon_1.padding.as_ptr() as *const KvmMemoryFault
+                    )
+                };
+
+                Ok(SevSnpExitDetails::MemoryFault {
+                    gpa: fault.gpa,
+                    size: fault.size,
+                    private: fault.flags & KVM_MEMORY_EXIT_FLAG_PRIVATE != 0,
+                })
+            }
+            _ => Err(cpu::HypervisorCpuError::UnknownSevSnpExit),
+        }
+    }
+
+    ///
+    /// Set the return value of the hypercall behind the SEV-SNP exit
+    ///
+    #[cfg(feature = "sev_snp")]
+    fn set_sev_snp_hypercall_result(&mut self, ret: u64) {
+        let kvm_run = self.fd.get_kvm_run();
+        if kvm_run.exit_reason == kvm_bindings::KVM_EXIT_HYPERCALL {
+            // SAFETY: accessing a union field in a valid structure
+            let hypercall = unsafe { &mut kvm_run.__bindgen_anon_1.hypercall };
+            hypercall.ret = ret;
+        }
+    }
     #[cfg(target_arch = "x86_64")]
     ///
     /// Return the list of initial MSR entries for a VCPU
@@ -2156,6 +3202,36 @@ impl cpu::Vcpu for KvmVcpu {
             .set_device_attr(&cpu_attr)
             .map_err(|_| cpu::HypervisorCpuError::InitializePmu)
     }
+    #[cfg(target_arch = "aarch64")]
+    fn init_sve(&self, max_vector_length: u32) -> cpu::Result<()> {
+        // The vector lengths are a little endian bitmap of the supported
+        // multiples of 128 bits, bit n standing for (n + 1) * 128 bits.
+        let mut vls = [0u8; 64];
+        self.get_sve_reg(KVM_REG_ARM64_SVE_VLS, &mut vls)?;
+
+        let max_vq = (max_vector_length / SVE_VQ_BITS) as usize;
+        if max_vq == 0 || vls[(max_vq - 1) / 8] & (1 << ((max_vq - 1) % 8)) == 0 {
+            return Err(cpu::HypervisorCpuError::InitializeSve(anyhow!(
+                "Vector length of {} bits is not supported",
+                max_vector_length
+            )));
+        }
+        for vq in max_vq..vls.len() * 8 {
+            vls[vq / 8] &= !(1 << (vq % 8));
+        }
+        self.set_sve_reg(KVM_REG_ARM64_SVE_VLS, &vls)?;
+
+        let feature = KVM_ARM_VCPU_SVE as std::os::raw::c_int;
+        // SAFETY: FFI call with a valid vCPU fd and feature value.
+        let ret = unsafe { ioctl_with_ref(&self.fd, KVM_ARM_VCPU_FINALIZE(), &feature) };
+        if ret < 0 {
+            return Err(cpu::HypervisorCpuError::InitializeSve(
+                std::io::Error::last_os_error().into(),
+            ));
+        }
+
+        Ok(())
+    }
 
     #[cfg(target_arch = "x86_64")]
     ///
@@ -2173,9 +3249,71 @@ impl cpu::Vcpu for KvmVcpu {
             Ok(v) => Ok(Some(v)),
         }
     }
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Queue a Non-Maskable Interrupt on the vCPU
+    ///
+    fn nmi(&self) -> cpu::Result<()> {
+        // SAFETY: FFI call with a valid vCPU fd, KVM_NMI takes no argument.
+        let ret = unsafe { ioctl(&self.fd, KVM_NMI()) };
+        if ret < 0 {
+            return Err(cpu::HypervisorCpuError::Nmi(
+                std::io::Error::last_os_error().into(),
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 impl KvmVcpu {
+    #[cfg(target_arch = "aarch64")]
+    ///
+    /// Read a register which may be wider than 128 bits into `data`, sized
+    /// after the register.
+    ///
+    fn get_sve_reg(&self, id: u64, data: &mut [u8]) -> cpu::Result<()> {
+        let reg = kvm_bindings::kvm_one_reg {
+            id,
+            addr: data.as_mut_ptr() as u64,
+        };
+        // SAFETY: FFI call with a valid vCPU fd, the kernel writes at most
+        // reg_size(id) bytes, which is the size of data.
+        let ret = unsafe { ioctl_with_ref(&self.fd, KVM_GET_ONE_REG(), &reg) };
+        if ret < 0 {
+            return Err(cpu::HypervisorCpuError::GetSveRegister(
+                std::io::Error::last_os_error().into(),
+            ));
+        }
+        Ok(())
+    }
+    #[cfg(target_arch = "aarch64")]
+    ///
+    /// Write a register which may be wider than 128 bits from `data`, sized
+    /// after the register.
+    ///
+    fn set_sve_reg(&self, id: u64, data: &[u8]) -> cpu::Result<()> {
+        if data.len() != reg_size(id) {
+            return Err(cpu::HypervisorCpuError::SetSveRegister(anyhow!(
+                "Invalid size {} for register 0x{:x}",
+                data.len(),
+                id
+            )));
+        }
+        let reg = kvm_bindings::kvm_one_reg {
+            id,
+            addr: data.as_ptr() as u64,
+        };
+        // SAFETY: FFI call with a valid vCPU fd, the kernel reads
+        // reg_size(id) bytes, which is the size of data.
+        let ret = unsafe { ioctl_with_ref(&self.fd, KVM_SET_ONE_REG(), &reg) };
+        if ret < 0 {
+            return Err(cpu::HypervisorCpuError::SetSveRegister(
+                std::io::Error::last_os_error().into(),
+            ));
+        }
+        Ok(())
+    }
     #[cfg(target_arch = "x86_64")]
     ///
     /// X86 specific call that returns the vcpu's current "xsave struct".
@@ -48,7 +48,7 @@ mod cpu;
 /// Device related module
 mod device;
 
-pub use cpu::{HypervisorCpuError, Vcpu, VmExit};
+pub use cpu::{HypervisorCpuError, Vcpu, VcpuExitCounters, VcpuExitStats, VmExit};
 pub use device::HypervisorDeviceError;
 pub use hypervisor::{Hypervisor, HypervisorError};
 #[cfg(all(feature = "kvm", target_arch = "aarch64"))]
@@ -132,6 +132,8 @@ pub const USER_MEMORY_REGION_READ: u32 = 1;
 pub const USER_MEMORY_REGION_WRITE: u32 = 1 << 1;
 pub const USER_MEMORY_REGION_EXECUTE: u32 = 1 << 2;
 pub const USER_MEMORY_REGION_LOG_DIRTY: u32 = 1 << 3;
+#[cfg(feature = "sev_snp")]
+pub const USER_MEMORY_REGION_PRIVATE: u32 = 1 << 4;
 
 #[derive(Debug)]
 pub enum MpState {
@@ -156,6 +158,8 @@ pub enum CpuState {
     Mshv(mshv::VcpuMshvState),
 }
 
+impl vm_migration::StateUpgrade for CpuState {}
+
 #[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
 #[cfg(target_arch = "x86_64")]
 pub enum ClockData {
@@ -17,6 +17,7 @@ pub use mshv_bindings::*;
 use mshv_ioctls::{set_registers_64, Mshv, NoDatamatch, VcpuFd, VmFd};
 use std::any::Any;
 use std::collections::HashMap;
+use std::sync::atomic::Ordering;
 use std::sync::{Arc, RwLock};
 use vfio_ioctls::VfioDeviceFd;
 use vm::DataMatch;
@@ -282,6 +283,7 @@ pub struct MshvVcpu {
     cpuid: Vec<CpuIdEntry>,
     msrs: Vec<MsrEntry>,
     vm_ops: Option<Arc<dyn vm::VmOps>>,
+    exit_counters: Arc<cpu::VcpuExitCounters>,
 }
 
 /// Implementation of Vcpu trait for Microsoft Hypervisor
@@ -408,6 +410,7 @@ impl cpu::Vcpu for MshvVcpu {
             Ok(x) => match x.header.message_type {
                 hv_message_type_HVMSG_X64_HALT => {
                     debug!("HALT");
+                    self.exit_counters.hlt.fetch_add(1, Ordering::Relaxed);
                     Ok(cpu::VmExit::Reset)
                 }
                 hv_message_type_HVMSG_UNRECOVERABLE_EXCEPTION => {
@@ -415,6 +418,7 @@ impl cpu::Vcpu for MshvVcpu {
                     Ok(cpu::VmExit::Shutdown)
                 }
                 hv_message_type_HVMSG_X64_IO_PORT_INTERCEPT => {
+                    self.exit_counters.io.fetch_add(1, Ordering::Relaxed);
                     let info = x.to_ioport_info().unwrap();
                     let access_info = info.access_info;
                     // SAFETY: access_info is valid, otherwise we won't be here
@@ -505,6 +509,9 @@ impl cpu::Vcpu for MshvVcpu {
                     Ok(cpu::VmExit::Ignore)
                 }
                 hv_message_type_HVMSG_UNMAPPED_GPA => {
+                    self.exit_counters
+                        .ept_violation
+                        .fetch_add(1, Ordering::Relaxed);
                     let info = x.to_memory_info().unwrap();
                     let insn_len = info.instruction_byte_count as usize;
                     assert!(insn_len > 0 && insn_len <= 16);
@@ -564,6 +571,12 @@ impl cpu::Vcpu for MshvVcpu {
             },
         }
     }
+    ///
+    /// Returns the counters of the exits handled by run(), by reason.
+    ///
+    fn exit_counters(&self) -> Arc<cpu::VcpuExitCounters> {
+        self.exit_counters.clone()
+    }
     #[cfg(target_arch = "x86_64")]
     ///
     /// X86 specific call to setup the CPUID registers.
@@ -976,9 +989,10 @@ impl vm::Vm for MshvVm {
     ///
     fn create_vcpu(
         &self,
-        id: u8,
+        id: u32,
         vm_ops: Option<Arc<dyn VmOps>>,
     ) -> vm::Result<Arc<dyn cpu::Vcpu>> {
+        let id = u8::try_from(id).map_err(|e| vm::HypervisorVmError::CreateVcpu(e.into()))?;
         let vcpu_fd = self
             .fd
             .create_vcpu(id)
@@ -989,6 +1003,7 @@ impl vm::Vm for MshvVm {
             cpuid: Vec::new(),
             msrs: self.msrs.clone(),
             vm_ops,
+            exit_counters: Arc::new(cpu::VcpuExitCounters::default()),
         };
         Ok(Arc::new(vcpu))
     }
@@ -997,6 +1012,10 @@ impl vm::Vm for MshvVm {
         Ok(())
     }
     #[cfg(target_arch = "x86_64")]
+    fn enable_x2apic_api(&self) -> vm::Result<()> {
+        Ok(())
+    }
+    #[cfg(target_arch = "x86_64")]
     fn enable_sgx_attribute(&self, _file: File) -> vm::Result<()> {
         Ok(())
     }
@@ -15,6 +15,8 @@ use crate::arch::aarch64::gic::{Vgic, VgicConfig};
 #[cfg(feature = "tdx")]
 use crate::arch::x86::CpuIdEntry;
 use crate::cpu::Vcpu;
+#[cfg(feature = "sev_snp")]
+use crate::kvm::SevSnpPageType;
 #[cfg(target_arch = "x86_64")]
 use crate::ClockData;
 use crate::UserMemoryRegion;
@@ -121,11 +123,21 @@ pub enum HypervisorVmError {
     #[error("Failed to enable split Irq: {0}")]
     EnableSplitIrq(#[source] anyhow::Error),
     ///
+    /// Enable x2APIC API error
+    ///
+    #[error("Failed to enable x2APIC API: {0}")]
+    EnableX2ApicApi(#[source] anyhow::Error),
+    ///
     /// Enable SGX attribute error
     ///
     #[error("Failed to enable SGX attribute: {0}")]
     EnableSgxAttribute(#[source] anyhow::Error),
     ///
+    /// Enable MTE error
+    ///
+    #[error("Failed to enable MTE: {0}")]
+    EnableMte(#[source] anyhow::Error),
+    ///
     /// Get clock error
     ///
     #[error("Failed to get clock: {0}")]
@@ -208,6 +220,66 @@ pub enum HypervisorVmError {
     ///
     #[error("Failed to initialize memory region TDX: {0}")]
     InitMemRegionTdx(#[source] std::io::Error),
+    #[cfg(feature = "sev_snp")]
+    ///
+    /// Error initializing SEV-SNP on the VM
+    ///
+    #[error("Failed to initialize SEV-SNP: {0}")]
+    InitializeSevSnp(#[source] std::io::Error),
+    #[cfg(feature = "sev_snp")]
+    ///
+    /// Error encrypting and measuring a SEV-SNP memory region
+    ///
+    #[error("Failed to update SEV-SNP launch memory region: {0}")]
+    LaunchUpdateSevSnp(#[source] std::io::Error),
+    #[cfg(feature = "sev_snp")]
+    ///
+    /// Error finishing the SEV-SNP launch sequence
+    ///
+    #[error("Failed to finish SEV-SNP launch: {0}")]
+    LaunchFinishSevSnp(#[source] std::io::Error),
+    #[cfg(feature = "sev_snp")]
+    ///
+    /// Error converting memory between private and shared
+    ///
+    #[error("Failed to set memory attributes: {0}")]
+    SetMemoryAttributes(#[source] std::io::Error),
+    #[cfg(feature = "sev_snp")]
+    ///
+    /// Error initializing SEV-ES on the VM
+    ///
+    #[error("Failed to initialize SEV-ES: {0}")]
+    InitializeSevEs(#[source] std::io::Error),
+    #[cfg(feature = "sev_snp")]
+    ///
+    /// Error registering a memory region to be encrypted
+    ///
+    #[error("Failed to register encrypted memory region: {0}")]
+    RegisterEncryptedRegion(#[source] std::io::Error),
+    #[cfg(feature = "sev_snp")]
+    ///
+    /// Error encrypting and measuring a SEV-ES memory region
+    ///
+    #[error("Failed to update SEV-ES launch memory region: {0}")]
+    LaunchUpdateSevEs(#[source] std::io::Error),
+    #[cfg(feature = "sev_snp")]
+    ///
+    /// Error encrypting and measuring the SEV-ES vCPUs state
+    ///
+    #[error("Failed to update SEV-ES launch vCPUs state: {0}")]
+    LaunchUpdateVmsaSevEs(#[source] std::io::Error),
+    #[cfg(feature = "sev_snp")]
+    ///
+    /// Error retrieving the SEV-ES launch measurement
+    ///
+    #[error("Failed to retrieve SEV-ES launch measurement: {0}")]
+    LaunchMeasureSevEs(#[source] std::io::Error),
+    #[cfg(feature = "sev_snp")]
+    ///
+    /// Error finishing the SEV-ES launch sequence
+    ///
+    #[error("Failed to finish SEV-ES launch: {0}")]
+    LaunchFinishSevEs(#[source] std::io::Error),
     ///
     /// Create Vgic error
     ///
@@ -271,7 +343,7 @@ pub trait Vm: Send + Sync + Any {
     /// Unregister an event that will, when signaled, trigger the `gsi` IRQ.
     fn unregister_irqfd(&self, fd: &EventFd, gsi: u32) -> Result<()>;
     /// Creates a new KVM vCPU file descriptor and maps the memory corresponding
-    fn create_vcpu(&self, id: u8, vm_ops: Option<Arc<dyn VmOps>>) -> Result<Arc<dyn Vcpu>>;
+    fn create_vcpu(&self, id: u32, vm_ops: Option<Arc<dyn VmOps>>) -> Result<Arc<dyn Vcpu>>;
     #[cfg(target_arch = "aarch64")]
     fn create_vgic(&self, config: VgicConfig) -> Result<Arc<Mutex<dyn Vgic>>>;
 
@@ -308,8 +380,17 @@ pub trait Vm: Send + Sync + Any {
     /// Enable split Irq capability
     #[cfg(target_arch = "x86_64")]
     fn enable_split_irq(&self) -> Result<()>;
+    /// Enable 32-bit x2APIC IDs, needed to address more than 255 vCPUs
+    #[cfg(target_arch = "x86_64")]
+    fn enable_x2apic_api(&self) -> Result<()>;
     #[cfg(target_arch = "x86_64")]
     fn enable_sgx_attribute(&self, file: File) -> Result<()>;
+    /// Enable the Memory Tagging Extension, before any vCPU gets created
+    #[cfg(target_arch = "aarch64")]
+    fn enable_mte(&self) -> Result<()>;
+    /// Check whether vCPUs can be started at EL2, for nested virtualization
+    #[cfg(target_arch = "aarch64")]
+    fn has_nested_virt(&self) -> bool;
     /// Retrieve guest clock.
     #[cfg(target_arch = "x86_64")]
     fn get_clock(&self) -> Result<ClockData>;
@@ -345,6 +426,63 @@ pub trait Vm: Send + Sync + Any {
     ) -> Result<()> {
         unimplemented!()
     }
+    #[cfg(feature = "sev_snp")]
+    /// Initialize SEV-SNP on this VM and start its launch sequence
+    fn sev_snp_init(&self, _policy: u64) -> Result<()> {
+        unimplemented!()
+    }
+    #[cfg(feature = "sev_snp")]
+    /// Encrypt and measure a memory region as part of the SEV-SNP launch
+    fn sev_snp_launch_update(
+        &self,
+        _host_address: u64,
+        _guest_address: u64,
+        _size: u64,
+        _page_type: SevSnpPageType,
+    ) -> Result<()> {
+        unimplemented!()
+    }
+    #[cfg(feature = "sev_snp")]
+    /// Complete the SEV-SNP launch sequence, sealing the measurement
+    fn sev_snp_launch_finish(&self, _host_data: &[u8; 32]) -> Result<()> {
+        unimplemented!()
+    }
+    #[cfg(feature = "sev_snp")]
+    /// Convert a range of guest memory between private and shared
+    fn set_memory_private(&self, _guest_address: u64, _size: u64, _private: bool) -> Result<()> {
+        unimplemented!()
+    }
+    #[cfg(feature = "sev_snp")]
+    /// Initialize SEV-ES on this VM and start its launch sequence
+    fn sev_es_init(&self, _policy: u32) -> Result<()> {
+        unimplemented!()
+    }
+    #[cfg(feature = "sev_snp")]
+    /// Pin a range of host memory backing the guest, as its content is tied
+    /// to its physical location once encrypted
+    fn sev_register_encrypted_region(&self, _host_address: u64, _size: u64) -> Result<()> {
+        unimplemented!()
+    }
+    #[cfg(feature = "sev_snp")]
+    /// Encrypt and measure a memory region as part of the SEV-ES launch
+    fn sev_es_launch_update_data(&self, _host_address: u64, _size: u64) -> Result<()> {
+        unimplemented!()
+    }
+    #[cfg(feature = "sev_snp")]
+    /// Encrypt and measure the state of every vCPU as part of the SEV-ES launch
+    fn sev_es_launch_update_vmsa(&self) -> Result<()> {
+        unimplemented!()
+    }
+    #[cfg(feature = "sev_snp")]
+    /// Retrieve the SEV-ES launch measurement, to be attested by the guest owner
+    fn sev_es_launch_measure(&self) -> Result<Vec<u8>> {
+        unimplemented!()
+    }
+    #[cfg(feature = "sev_snp")]
+    /// Complete the SEV-ES launch sequence
+    fn sev_es_launch_finish(&self) -> Result<()> {
+        unimplemented!()
+    }
     /// Downcast to the underlying hypervisor VM type
     fn as_any(&self) -> &dyn Any;
 }
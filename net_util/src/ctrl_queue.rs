@@ -2,10 +2,12 @@
 //
 // SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
 
+use crate::rss::{RssConfig, VIRTIO_NET_CTRL_MQ_HASH_CONFIG, VIRTIO_NET_CTRL_MQ_RSS_CONFIG};
 use crate::GuestMemoryMmap;
 use crate::Tap;
+use crate::{VIRTIO_NET_F_GUEST_USO4, VIRTIO_NET_F_GUEST_USO6};
 use libc::c_uint;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use virtio_bindings::virtio_net::{
     VIRTIO_NET_CTRL_GUEST_OFFLOADS, VIRTIO_NET_CTRL_GUEST_OFFLOADS_SET, VIRTIO_NET_CTRL_MQ,
     VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MAX, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MIN,
@@ -37,6 +39,9 @@ pub enum Error {
 
 type Result<T> = std::result::Result<T, Error>;
 
+// Maximum length of the data of a command.
+const MAX_CTRL_DATA_LEN: usize = 4096;
+
 #[repr(C, packed)]
 #[derive(Debug, Clone, Copy, Default)]
 pub struct ControlHeader {
@@ -49,11 +54,41 @@ unsafe impl ByteValued for ControlHeader {}
 
 pub struct CtrlQueue {
     pub taps: Vec<Tap>,
+    // Hash configuration shared with the queue pairs, if RSS or hash
+    // reporting is supported.
+    pub rss: Option<Arc<RwLock<Option<RssConfig>>>>,
 }
 
 impl CtrlQueue {
-    pub fn new(taps: Vec<Tap>) -> Self {
-        CtrlQueue { taps }
+    pub fn new(taps: Vec<Tap>, rss: Option<Arc<RwLock<Option<RssConfig>>>>) -> Self {
+        CtrlQueue { taps, rss }
+    }
+
+    fn set_rss(&mut self, cmd: u32, data: &[u8]) -> bool {
+        let rss = match self.rss.as_ref() {
+            Some(rss) => rss,
+            None => {
+                warn!("Unsupported RSS command: {}", cmd);
+                return false;
+            }
+        };
+
+        let config = if cmd == VIRTIO_NET_CTRL_MQ_RSS_CONFIG {
+            RssConfig::parse_rss(data, self.taps.len() as u16)
+        } else {
+            RssConfig::parse_hash(data)
+        };
+        match config {
+            Some(config) => {
+                info!("Setting RSS configuration: {:?}", config);
+                *rss.write().unwrap() = Some(config);
+                true
+            }
+            None => {
+                warn!("Invalid RSS configuration");
+                false
+            }
+        }
     }
 
     pub fn process(
@@ -73,42 +108,69 @@ impl CtrlQueue {
                         .translate_gva(access_platform, ctrl_desc.len() as usize),
                 )
                 .map_err(Error::GuestMemory)?;
-            let data_desc = desc_chain.next().ok_or(Error::NoDataDescriptor)?;
 
-            let data_desc_addr = data_desc
-                .addr()
-                .translate_gva(access_platform, data_desc.len() as usize);
+            // The data can be spread over several descriptors, followed by
+            // the status one.
+            let mut data = Vec::new();
+            let mut len = ctrl_desc.len();
+            let status_desc = loop {
+                let desc = desc_chain.next().ok_or(Error::NoStatusDescriptor)?;
+                len += desc.len();
+                if desc.is_write_only() {
+                    break desc;
+                }
 
-            let status_desc = desc_chain.next().ok_or(Error::NoStatusDescriptor)?;
+                // Commands are small, anything beyond the limit is ignored.
+                let read_len = (desc.len() as usize).min(MAX_CTRL_DATA_LEN - data.len());
+                let mut buf = vec![0u8; read_len];
+                desc_chain
+                    .memory()
+                    .read_slice(
+                        &mut buf,
+                        desc.addr()
+                            .translate_gva(access_platform, desc.len() as usize),
+                    )
+                    .map_err(Error::GuestMemory)?;
+                data.extend_from_slice(&buf);
+            };
+            if data.is_empty() {
+                return Err(Error::NoDataDescriptor);
+            }
 
             let ok = match u32::from(ctrl_hdr.class) {
                 VIRTIO_NET_CTRL_MQ => {
-                    let queue_pairs = desc_chain
-                        .memory()
-                        .read_obj::<u16>(data_desc_addr)
-                        .map_err(Error::GuestMemory)?;
-                    if u32::from(ctrl_hdr.cmd) != VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET {
+                    let cmd = u32::from(ctrl_hdr.cmd);
+                    if cmd == VIRTIO_NET_CTRL_MQ_RSS_CONFIG || cmd == VIRTIO_NET_CTRL_MQ_HASH_CONFIG
+                    {
+                        self.set_rss(cmd, &data)
+                    } else if cmd != VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET {
                         warn!("Unsupported command: {}", ctrl_hdr.cmd);
                         false
-                    } else if (queue_pairs < VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MIN as u16)
-                        || (queue_pairs > VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MAX as u16)
-                    {
-                        warn!("Number of MQ pairs out of range: {}", queue_pairs);
+                    } else if data.len() < 2 {
+                        warn!("Invalid MQ command length: {}", data.len());
                         false
                     } else {
-                        info!("Number of MQ pairs requested: {}", queue_pairs);
-                        true
+                        let queue_pairs = u16::from_le_bytes([data[0], data[1]]);
+                        if (queue_pairs < VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MIN as u16)
+                            || (queue_pairs > VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MAX as u16)
+                        {
+                            warn!("Number of MQ pairs out of range: {}", queue_pairs);
+                            false
+                        } else {
+                            info!("Number of MQ pairs requested: {}", queue_pairs);
+                            true
+                        }
                     }
                 }
                 VIRTIO_NET_CTRL_GUEST_OFFLOADS => {
-                    let features = desc_chain
-                        .memory()
-                        .read_obj::<u64>(data_desc_addr)
-                        .map_err(Error::GuestMemory)?;
                     if u32::from(ctrl_hdr.cmd) != VIRTIO_NET_CTRL_GUEST_OFFLOADS_SET {
                         warn!("Unsupported command: {}", ctrl_hdr.cmd);
                         false
+                    } else if data.len() < 8 {
+                        warn!("Invalid guest offloads command length: {}", data.len());
+                        false
                     } else {
+                        let features = u64::from_le_bytes(data[..8].try_into().unwrap());
                         let mut ok = true;
                         for tap in self.taps.iter_mut() {
                             info!("Reprogramming tap offload with features: {}", features);
@@ -137,7 +199,6 @@ impl CtrlQueue {
                         .translate_gva(access_platform, status_desc.len() as usize),
                 )
                 .map_err(Error::GuestMemory)?;
-            let len = ctrl_desc.len() + data_desc.len() + status_desc.len();
 
             queue
                 .add_used(desc_chain.memory(), desc_chain.head_index(), len)
@@ -172,6 +233,12 @@ pub fn virtio_features_to_tap_offload(features: u64) -> c_uint {
     if features & (1 << VIRTIO_NET_F_GUEST_UFO) != 0 {
         tap_offloads |= net_gen::TUN_F_UFO;
     }
+    if features & (1 << VIRTIO_NET_F_GUEST_USO4) != 0 {
+        tap_offloads |= net_gen::TUN_F_USO4;
+    }
+    if features & (1 << VIRTIO_NET_F_GUEST_USO6) != 0 {
+        tap_offloads |= net_gen::TUN_F_USO6;
+    }
 
     tap_offloads
 }
@@ -12,6 +12,7 @@ mod ctrl_queue;
 mod mac;
 mod open_tap;
 mod queue_pair;
+mod rss;
 mod tap;
 
 use std::io::Error as IoError;
@@ -30,10 +31,19 @@ use vm_memory::{bitmap::AtomicBitmap, ByteValued};
 
 type GuestMemoryMmap = vm_memory::GuestMemoryMmap<AtomicBitmap>;
 
+// UDP segmentation offload features, missing from the virtio bindings.
+pub const VIRTIO_NET_F_GUEST_USO4: u32 = 54;
+pub const VIRTIO_NET_F_GUEST_USO6: u32 = 55;
+pub const VIRTIO_NET_F_HOST_USO: u32 = 56;
+
 pub use ctrl_queue::{CtrlQueue, Error as CtrlQueueError};
 pub use mac::{MacAddr, MAC_ADDR_LEN};
 pub use open_tap::{open_tap, Error as OpenTapError};
 pub use queue_pair::{NetCounters, NetQueuePair, NetQueuePairError, RxVirtio, TxVirtio};
+pub use rss::{
+    set_vnet_hdr_hash, toeplitz_hash, RssConfig, RSS_MAX_INDIRECTION_TABLE_LEN, RSS_MAX_KEY_SIZE,
+    RSS_SUPPORTED_HASH_TYPES, VIRTIO_NET_F_HASH_REPORT, VIRTIO_NET_F_RSS, VNET_HDR_HASH_LEN,
+};
 pub use tap::{Error as TapError, Tap};
 
 #[derive(Error, Debug)]
@@ -53,6 +63,9 @@ pub struct VirtioNetConfig {
     pub mtu: u16,
     pub speed: u32,
     pub duplex: u8,
+    pub rss_max_key_size: u8,
+    pub rss_max_indirection_table_length: u16,
+    pub supported_hash_types: u32,
 }
 
 // SAFETY: it only has data and has no implicit padding.
@@ -97,7 +110,7 @@ fn create_unix_socket() -> Result<net::UdpSocket> {
     Ok(unsafe { net::UdpSocket::from_raw_fd(sock) })
 }
 
-fn vnet_hdr_len() -> usize {
+pub fn vnet_hdr_len() -> usize {
     std::mem::size_of::<virtio_net_hdr_v1>()
 }
 
@@ -177,6 +190,12 @@ pub fn virtio_features_to_tap_offload(features: u64) -> c_uint {
     if features & (1 << VIRTIO_NET_F_GUEST_UFO) != 0 {
         tap_offloads |= net_gen::TUN_F_UFO;
     }
+    if features & (1 << VIRTIO_NET_F_GUEST_USO4) != 0 {
+        tap_offloads |= net_gen::TUN_F_USO4;
+    }
+    if features & (1 << VIRTIO_NET_F_GUEST_USO6) != 0 {
+        tap_offloads |= net_gen::TUN_F_USO6;
+    }
 
     tap_offloads
 }
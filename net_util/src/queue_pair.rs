@@ -5,7 +5,7 @@
 use super::{register_listener, unregister_listener, vnet_hdr_len, Tap};
 use crate::GuestMemoryMmap;
 use rate_limiter::{RateLimiter, TokenType};
-use std::io;
+use std::io::{self, Read};
 use std::num::Wrapping;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::sync::atomic::{AtomicU64, Ordering};
@@ -19,6 +19,8 @@ use vm_virtio::{AccessPlatform, Translatable};
 pub struct TxVirtio {
     pub counter_bytes: Wrapping<u64>,
     pub counter_frames: Wrapping<u64>,
+    // Length of the virtio-net header preceding each frame
+    pub vnet_hdr_len: usize,
 }
 
 impl Default for TxVirtio {
@@ -32,6 +34,7 @@ impl TxVirtio {
         TxVirtio {
             counter_bytes: Wrapping(0),
             counter_frames: Wrapping(0),
+            vnet_hdr_len: vnet_hdr_len(),
         }
     }
 
@@ -105,11 +108,11 @@ impl TxVirtio {
                     return Err(NetQueuePairError::WriteTap(e));
                 }
 
-                if (result as usize) < vnet_hdr_len() {
+                if (result as usize) < self.vnet_hdr_len {
                     return Err(NetQueuePairError::InvalidVirtioNetHeader);
                 }
 
-                self.counter_bytes += Wrapping(result as u64 - vnet_hdr_len() as u64);
+                self.counter_bytes += Wrapping(result as u64 - self.vnet_hdr_len as u64);
                 self.counter_frames += Wrapping(1);
 
                 result as u32
@@ -145,6 +148,8 @@ impl TxVirtio {
 pub struct RxVirtio {
     pub counter_bytes: Wrapping<u64>,
     pub counter_frames: Wrapping<u64>,
+    // Length of the virtio-net header preceding each frame
+    pub vnet_hdr_len: usize,
 }
 
 impl Default for RxVirtio {
@@ -158,6 +163,7 @@ impl RxVirtio {
         RxVirtio {
             counter_bytes: Wrapping(0),
             counter_frames: Wrapping(0),
+            vnet_hdr_len: vnet_hdr_len(),
         }
     }
 
@@ -244,7 +250,7 @@ impl RxVirtio {
                     return Err(NetQueuePairError::ReadTap(e));
                 }
 
-                if (result as usize) < vnet_hdr_len() {
+                if (result as usize) < self.vnet_hdr_len {
                     return Err(NetQueuePairError::InvalidVirtioNetHeader);
                 }
 
@@ -255,7 +261,7 @@ impl RxVirtio {
                     .write_obj(1u16, num_buffers_addr)
                     .map_err(NetQueuePairError::GuestMemory)?;
 
-                self.counter_bytes += Wrapping(result as u64 - vnet_hdr_len() as u64);
+                self.counter_bytes += Wrapping(result as u64 - self.vnet_hdr_len as u64);
                 self.counter_frames += Wrapping(1);
 
                 result as u32
@@ -286,6 +292,61 @@ impl RxVirtio {
 
         Ok(exhausted_descs)
     }
+
+    /// Copies a frame, starting with its virtio-net header, into the next
+    /// available descriptor chain of the queue. Returns false if the queue
+    /// has no descriptor chain available.
+    pub fn write_frame(
+        &mut self,
+        mem: &GuestMemoryMmap,
+        queue: &mut Queue,
+        frame: &[u8],
+        access_platform: Option<&Arc<dyn AccessPlatform>>,
+    ) -> Result<bool, NetQueuePairError> {
+        let mut desc_chain = match queue.pop_descriptor_chain(mem) {
+            Some(desc_chain) => desc_chain,
+            None => return Ok(false),
+        };
+
+        let mut written = 0;
+        while let Some(desc) = desc_chain.next() {
+            let desc_addr = desc
+                .addr()
+                .translate_gva(access_platform, desc.len() as usize);
+            if !desc.is_write_only() {
+                error!(
+                    "Invalid descriptor chain: address = 0x{:x} length = {} write_only = {}",
+                    desc_addr.0,
+                    desc.len(),
+                    desc.is_write_only()
+                );
+                return Err(NetQueuePairError::DescriptorChainInvalid);
+            }
+
+            let len = (desc.len() as usize).min(frame.len() - written);
+            desc_chain
+                .memory()
+                .write_slice(&frame[written..written + len], desc_addr)
+                .map_err(NetQueuePairError::GuestMemory)?;
+            written += len;
+            if written == frame.len() {
+                break;
+            }
+        }
+
+        if written < self.vnet_hdr_len {
+            return Err(NetQueuePairError::DescriptorInvalidHeader);
+        }
+
+        self.counter_bytes += Wrapping((written - self.vnet_hdr_len) as u64);
+        self.counter_frames += Wrapping(1);
+
+        queue
+            .add_used(desc_chain.memory(), desc_chain.head_index(), written as u32)
+            .map_err(NetQueuePairError::QueueAddUsed)?;
+
+        Ok(true)
+    }
 }
 
 #[derive(Default, Clone)]
@@ -431,6 +492,53 @@ impl NetQueuePair {
             self.rx_tap_listening = false;
         }
 
+        self.update_rx_counters();
+
+        queue
+            .needs_notification(mem)
+            .map_err(NetQueuePairError::QueueNeedsNotification)
+    }
+
+    /// Reads the next frame from the TAP, along with its virtio-net header,
+    /// returning its length or `None` if there is no frame to read.
+    pub fn read_rx_frame(&mut self, buf: &mut [u8]) -> Result<Option<usize>, NetQueuePairError> {
+        match self.tap.read(buf) {
+            Ok(len) if len < self.rx.vnet_hdr_len => Err(NetQueuePairError::InvalidVirtioNetHeader),
+            Ok(len) => Ok(Some(len)),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(e) => {
+                error!("net: rx: failed reading from tap: {}", e);
+                Err(NetQueuePairError::ReadTap(e))
+            }
+        }
+    }
+
+    /// Copies a frame read from the TAP into `queue`, which doesn't have to
+    /// be the receive queue of the pair. Returns false if the frame had to be
+    /// dropped, the queue having no buffer available.
+    pub fn write_rx_frame(
+        &mut self,
+        mem: &GuestMemoryMmap,
+        queue: &mut Queue,
+        frame: &[u8],
+    ) -> Result<bool, NetQueuePairError> {
+        let written = self
+            .rx
+            .write_frame(mem, queue, frame, self.access_platform.as_ref())?;
+
+        // The frame was read from the TAP, whether the guest got it or not.
+        if let Some(rate_limiter) = &mut self.rx_rate_limiter {
+            if rate_limiter.consume(1, TokenType::Ops) {
+                rate_limiter.consume(frame.len() as u64, TokenType::Bytes);
+            }
+        }
+
+        self.update_rx_counters();
+
+        Ok(written)
+    }
+
+    fn update_rx_counters(&mut self) {
         self.counters
             .rx_bytes
             .fetch_add(self.rx.counter_bytes.0, Ordering::AcqRel);
@@ -439,9 +547,5 @@ impl NetQueuePair {
             .fetch_add(self.rx.counter_frames.0, Ordering::AcqRel);
         self.rx.counter_bytes = Wrapping(0);
         self.rx.counter_frames = Wrapping(0);
-
-        queue
-            .needs_notification(mem)
-            .map_err(NetQueuePairError::QueueNeedsNotification)
     }
 }
@@ -0,0 +1,380 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Receive Side Scaling (RSS) and hash reporting for virtio-net.
+//!
+//! The hash of the flow of each received frame is computed with the Toeplitz
+//! function, from the key and the types of flows given by the guest. It is
+//! used to steer the frame to a receive queue through the indirection table,
+//! and can be reported to the guest in the virtio-net header of the frame.
+
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+
+pub const VIRTIO_NET_F_HASH_REPORT: u32 = 57;
+pub const VIRTIO_NET_F_RSS: u32 = 60;
+
+pub const VIRTIO_NET_CTRL_MQ_RSS_CONFIG: u32 = 1;
+pub const VIRTIO_NET_CTRL_MQ_HASH_CONFIG: u32 = 2;
+
+pub const VIRTIO_NET_RSS_HASH_TYPE_IPV4: u32 = 1 << 0;
+pub const VIRTIO_NET_RSS_HASH_TYPE_TCPV4: u32 = 1 << 1;
+pub const VIRTIO_NET_RSS_HASH_TYPE_UDPV4: u32 = 1 << 2;
+pub const VIRTIO_NET_RSS_HASH_TYPE_IPV6: u32 = 1 << 3;
+pub const VIRTIO_NET_RSS_HASH_TYPE_TCPV6: u32 = 1 << 4;
+pub const VIRTIO_NET_RSS_HASH_TYPE_UDPV6: u32 = 1 << 5;
+
+const VIRTIO_NET_HASH_REPORT_IPV4: u16 = 1;
+const VIRTIO_NET_HASH_REPORT_TCPV4: u16 = 2;
+const VIRTIO_NET_HASH_REPORT_UDPV4: u16 = 3;
+const VIRTIO_NET_HASH_REPORT_IPV6: u16 = 4;
+const VIRTIO_NET_HASH_REPORT_TCPV6: u16 = 5;
+const VIRTIO_NET_HASH_REPORT_UDPV6: u16 = 6;
+
+/// Types of flows the hash can be computed on. IPv6 extension headers aren't
+/// looked into, so the IPv6 "EX" types aren't supported.
+pub const RSS_SUPPORTED_HASH_TYPES: u32 = VIRTIO_NET_RSS_HASH_TYPE_IPV4
+    | VIRTIO_NET_RSS_HASH_TYPE_TCPV4
+    | VIRTIO_NET_RSS_HASH_TYPE_UDPV4
+    | VIRTIO_NET_RSS_HASH_TYPE_IPV6
+    | VIRTIO_NET_RSS_HASH_TYPE_TCPV6
+    | VIRTIO_NET_RSS_HASH_TYPE_UDPV6;
+pub const RSS_MAX_KEY_SIZE: u8 = 40;
+pub const RSS_MAX_INDIRECTION_TABLE_LEN: u16 = 128;
+
+/// Length of the virtio-net header when the hash is reported, the header
+/// being followed by the hash value, its type and some padding.
+pub const VNET_HDR_HASH_LEN: usize = 20;
+const VNET_HDR_HASH_VALUE_OFFSET: usize = 12;
+const VNET_HDR_HASH_REPORT_OFFSET: usize = 16;
+
+const ETH_HDR_LEN: usize = 14;
+const ETH_P_IP: u16 = 0x0800;
+const ETH_P_IPV6: u16 = 0x86dd;
+const ETH_P_8021Q: u16 = 0x8100;
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+
+/// Computes the Toeplitz hash of `input` with `key`, missing bits of the key
+/// being zeroes.
+pub fn toeplitz_hash(key: &[u8], input: &[u8]) -> u32 {
+    let key_bit = |n: usize| {
+        key.get(n / 8)
+            .map_or(0, |b| u32::from(b >> (7 - n % 8)) & 1)
+    };
+
+    // 32 bits window sliding over the key, one bit for each bit of input.
+    let mut window = (0..32).fold(0u32, |w, n| (w << 1) | key_bit(n));
+    let mut hash = 0;
+    for (i, byte) in input.iter().enumerate() {
+        for bit in 0..8 {
+            if byte & (0x80 >> bit) != 0 {
+                hash ^= window;
+            }
+            window = (window << 1) | key_bit(32 + i * 8 + bit);
+        }
+    }
+
+    hash
+}
+
+// Addresses and ports of a flow, in the order they are hashed.
+struct Flow {
+    ipv6: bool,
+    addresses: Vec<u8>,
+    // Protocol and ports, if the frame is the first fragment of a TCP or UDP
+    // packet.
+    ports: Option<(u8, [u8; 4])>,
+}
+
+fn parse_flow(frame: &[u8]) -> Option<Flow> {
+    let ether_type = |offset: usize| {
+        frame
+            .get(offset..offset + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+    };
+    let (ether_type, l3_offset) = match ether_type(12)? {
+        ETH_P_8021Q => (ether_type(16)?, ETH_HDR_LEN + 4),
+        t => (t, ETH_HDR_LEN),
+    };
+    let l3 = frame.get(l3_offset..)?;
+
+    let (ipv6, addresses, protocol, l4) = match ether_type {
+        ETH_P_IP => {
+            let ihl = usize::from(*l3.first()? & 0xf) * 4;
+            if ihl < 20 {
+                return None;
+            }
+            // Only the first fragment holds the ports.
+            let fragment = u16::from_be_bytes([*l3.get(6)?, *l3.get(7)?]);
+            let more_fragments = fragment & 0x2000 != 0;
+            let fragment_offset = fragment & 0x1fff;
+            let protocol = (!more_fragments && fragment_offset == 0).then_some(*l3.get(9)?);
+            (false, l3.get(12..20)?, protocol, l3.get(ihl..))
+        }
+        ETH_P_IPV6 => (true, l3.get(8..40)?, Some(*l3.get(6)?), l3.get(40..)),
+        _ => return None,
+    };
+
+    let ports = match (protocol, l4.and_then(|l4| l4.get(..4))) {
+        (Some(p @ (IPPROTO_TCP | IPPROTO_UDP)), Some(ports)) => {
+            Some((p, [ports[0], ports[1], ports[2], ports[3]]))
+        }
+        _ => None,
+    };
+
+    Some(Flow {
+        ipv6,
+        addresses: addresses.to_vec(),
+        ports,
+    })
+}
+
+/// Configuration of the hash set by the guest through the control queue.
+#[derive(Clone, Debug, PartialEq, Eq, Versionize)]
+pub struct RssConfig {
+    hash_types: u32,
+    indirection_table: Vec<u16>,
+    unclassified_queue: u16,
+    key: Vec<u8>,
+    // Whether frames are steered with the hash, rather than only given it.
+    steering: bool,
+}
+
+impl RssConfig {
+    /// Parses the data of a VIRTIO_NET_CTRL_MQ_RSS_CONFIG command, steering
+    /// frames across `num_queue_pairs` queues.
+    pub fn parse_rss(data: &[u8], num_queue_pairs: u16) -> Option<Self> {
+        let u16_at = |offset: usize| {
+            data.get(offset..offset + 2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        };
+
+        let hash_types = u32::from_le_bytes(data.get(..4)?.try_into().ok()?);
+        let table_len = usize::from(u16_at(4)?) + 1;
+        let unclassified_queue = u16_at(6)?;
+        if !table_len.is_power_of_two()
+            || table_len > usize::from(RSS_MAX_INDIRECTION_TABLE_LEN)
+            || unclassified_queue >= num_queue_pairs
+        {
+            return None;
+        }
+
+        let indirection_table = (0..table_len)
+            .map(|i| u16_at(8 + i * 2))
+            .collect::<Option<Vec<u16>>>()?;
+        if indirection_table.iter().any(|q| *q >= num_queue_pairs) {
+            return None;
+        }
+
+        // The maximum number of transmit queues isn't used, each queue pair
+        // transmitting on its own TAP queue.
+        let key_offset = 8 + table_len * 2 + 2;
+        let key = Self::parse_key(data, key_offset)?;
+
+        Some(RssConfig {
+            hash_types: hash_types & RSS_SUPPORTED_HASH_TYPES,
+            indirection_table,
+            unclassified_queue,
+            key,
+            steering: true,
+        })
+    }
+
+    /// Parses the data of a VIRTIO_NET_CTRL_MQ_HASH_CONFIG command, only
+    /// reporting the hash.
+    pub fn parse_hash(data: &[u8]) -> Option<Self> {
+        let hash_types = u32::from_le_bytes(data.get(..4)?.try_into().ok()?);
+        let key = Self::parse_key(data, 12)?;
+
+        Some(RssConfig {
+            hash_types: hash_types & RSS_SUPPORTED_HASH_TYPES,
+            indirection_table: vec![0],
+            unclassified_queue: 0,
+            key,
+            steering: false,
+        })
+    }
+
+    // Parses the key, preceded by its length.
+    fn parse_key(data: &[u8], offset: usize) -> Option<Vec<u8>> {
+        let len = *data.get(offset)?;
+        if len > RSS_MAX_KEY_SIZE {
+            return None;
+        }
+
+        data.get(offset + 1..offset + 1 + usize::from(len))
+            .map(|k| k.to_vec())
+    }
+
+    /// Returns the hash of the flow of an Ethernet frame, along with the type
+    /// reported to the guest, if the flow is of one of the enabled types.
+    pub fn hash(&self, frame: &[u8]) -> Option<(u32, u16)> {
+        let flow = parse_flow(frame)?;
+        let (ip, tcp, udp) = if flow.ipv6 {
+            (
+                (VIRTIO_NET_RSS_HASH_TYPE_IPV6, VIRTIO_NET_HASH_REPORT_IPV6),
+                (VIRTIO_NET_RSS_HASH_TYPE_TCPV6, VIRTIO_NET_HASH_REPORT_TCPV6),
+                (VIRTIO_NET_RSS_HASH_TYPE_UDPV6, VIRTIO_NET_HASH_REPORT_UDPV6),
+            )
+        } else {
+            (
+                (VIRTIO_NET_RSS_HASH_TYPE_IPV4, VIRTIO_NET_HASH_REPORT_IPV4),
+                (VIRTIO_NET_RSS_HASH_TYPE_TCPV4, VIRTIO_NET_HASH_REPORT_TCPV4),
+                (VIRTIO_NET_RSS_HASH_TYPE_UDPV4, VIRTIO_NET_HASH_REPORT_UDPV4),
+            )
+        };
+
+        let mut input = flow.addresses;
+        let l4 = match flow.ports {
+            Some((IPPROTO_TCP, ports)) => Some((tcp, ports)),
+            Some((_, ports)) => Some((udp, ports)),
+            None => None,
+        };
+        let report = match l4 {
+            Some(((hash_type, report), ports)) if self.hash_types & hash_type != 0 => {
+                input.extend_from_slice(&ports);
+                report
+            }
+            _ if self.hash_types & ip.0 != 0 => ip.1,
+            _ => return None,
+        };
+
+        Some((toeplitz_hash(&self.key, &input), report))
+    }
+
+    /// Returns the receive queue pair a frame goes to, given its hash, or
+    /// `None` if the frame isn't steered.
+    pub fn queue(&self, hash: Option<u32>) -> Option<u16> {
+        if !self.steering {
+            return None;
+        }
+
+        Some(match hash {
+            Some(hash) => {
+                self.indirection_table[hash as usize & (self.indirection_table.len() - 1)]
+            }
+            None => self.unclassified_queue,
+        })
+    }
+}
+
+/// Writes the hash of a frame into its virtio-net header, which must be
+/// `VNET_HDR_HASH_LEN` bytes long.
+pub fn set_vnet_hdr_hash(frame: &mut [u8], hash: Option<(u32, u16)>) {
+    let (value, report) = hash.unwrap_or_default();
+    frame[VNET_HDR_HASH_VALUE_OFFSET..VNET_HDR_HASH_REPORT_OFFSET]
+        .copy_from_slice(&value.to_le_bytes());
+    frame[VNET_HDR_HASH_REPORT_OFFSET..VNET_HDR_HASH_REPORT_OFFSET + 2]
+        .copy_from_slice(&report.to_le_bytes());
+    frame[VNET_HDR_HASH_REPORT_OFFSET + 2..VNET_HDR_HASH_LEN].fill(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Key and results from the verification suite of the Microsoft RSS
+    // specification.
+    const KEY: [u8; 40] = [
+        0x6d, 0x5a, 0x56, 0xda, 0x25, 0x5b, 0x0e, 0xc2, 0x41, 0x67, 0x25, 0x3d, 0x43, 0xa3, 0x8f,
+        0xb0, 0xd0, 0xca, 0x2b, 0xcb, 0xae, 0x7b, 0x30, 0xb4, 0x77, 0xcb, 0x2d, 0xa3, 0x80, 0x30,
+        0xf2, 0x0c, 0x6a, 0x42, 0xb7, 0x3b, 0xbe, 0xac, 0x01, 0xfa,
+    ];
+
+    // 66.9.149.187:2794 -> 161.142.100.80:1766
+    const IPV4_ADDRESSES: [u8; 8] = [66, 9, 149, 187, 161, 142, 100, 80];
+    const PORTS: [u8; 4] = [0x0a, 0xea, 0x06, 0xe6];
+
+    fn ipv4_frame(protocol: u8, fragment: u16) -> Vec<u8> {
+        let mut frame = vec![0u8; 12];
+        frame.extend_from_slice(&ETH_P_IP.to_be_bytes());
+        let mut ip = vec![0x45, 0, 0, 40, 0, 0];
+        ip.extend_from_slice(&fragment.to_be_bytes());
+        ip.extend_from_slice(&[64, protocol, 0, 0]);
+        ip.extend_from_slice(&IPV4_ADDRESSES);
+        frame.extend_from_slice(&ip);
+        frame.extend_from_slice(&PORTS);
+        frame.extend_from_slice(&[0; 16]);
+        frame
+    }
+
+    fn rss_command(hash_types: u32, table: &[u16], unclassified_queue: u16) -> Vec<u8> {
+        let mut data = hash_types.to_le_bytes().to_vec();
+        data.extend_from_slice(&(table.len() as u16 - 1).to_le_bytes());
+        data.extend_from_slice(&unclassified_queue.to_le_bytes());
+        for q in table {
+            data.extend_from_slice(&q.to_le_bytes());
+        }
+        data.extend_from_slice(&4u16.to_le_bytes());
+        data.push(KEY.len() as u8);
+        data.extend_from_slice(&KEY);
+        data
+    }
+
+    #[test]
+    fn test_toeplitz_hash() {
+        assert_eq!(toeplitz_hash(&KEY, &IPV4_ADDRESSES), 0x323e_8fc2);
+        let mut input = IPV4_ADDRESSES.to_vec();
+        input.extend_from_slice(&PORTS);
+        assert_eq!(toeplitz_hash(&KEY, &input), 0x51cc_c178);
+
+        // 3ffe:2501:200:1fff::7:2794 -> 3ffe:2501:200:3::1:1766
+        let mut input = vec![
+            0x3f, 0xfe, 0x25, 0x01, 0x02, 0x00, 0x1f, 0xff, 0, 0, 0, 0, 0, 0, 0, 0x07, 0x3f, 0xfe,
+            0x25, 0x01, 0x02, 0x00, 0x00, 0x03, 0, 0, 0, 0, 0, 0, 0, 0x01,
+        ];
+        assert_eq!(toeplitz_hash(&KEY, &input), 0x2cc1_8cd5);
+        input.extend_from_slice(&PORTS);
+        assert_eq!(toeplitz_hash(&KEY, &input), 0x4020_7d3d);
+    }
+
+    #[test]
+    fn test_rss_config() {
+        let table = [0, 1, 2, 3];
+        let rss =
+            RssConfig::parse_rss(&rss_command(RSS_SUPPORTED_HASH_TYPES, &table, 1), 4).unwrap();
+
+        let frame = ipv4_frame(IPPROTO_TCP, 0);
+        assert_eq!(
+            rss.hash(&frame),
+            Some((0x51cc_c178, VIRTIO_NET_HASH_REPORT_TCPV4))
+        );
+        assert_eq!(rss.queue(Some(0x51cc_c178)), Some(0));
+        // Fragments are hashed on their addresses only.
+        assert_eq!(
+            rss.hash(&ipv4_frame(IPPROTO_TCP, 0x2000)),
+            Some((0x323e_8fc2, VIRTIO_NET_HASH_REPORT_IPV4))
+        );
+        // Unclassified frames go to the unclassified queue.
+        assert_eq!(rss.hash(&[0u8; 60]), None);
+        assert_eq!(rss.queue(None), Some(1));
+
+        let rss = RssConfig::parse_rss(&rss_command(VIRTIO_NET_RSS_HASH_TYPE_IPV4, &table, 0), 4)
+            .unwrap();
+        assert_eq!(
+            rss.hash(&ipv4_frame(IPPROTO_UDP, 0)),
+            Some((0x323e_8fc2, VIRTIO_NET_HASH_REPORT_IPV4))
+        );
+        assert_eq!(rss.queue(Some(0x323e_8fc2)), Some(2));
+
+        // Invalid table length, and queues out of range.
+        assert!(RssConfig::parse_rss(&rss_command(0, &[0, 1, 2], 0), 4).is_none());
+        assert!(RssConfig::parse_rss(&rss_command(0, &table, 0), 2).is_none());
+        assert!(RssConfig::parse_rss(&rss_command(0, &[0, 1], 2), 2).is_none());
+
+        // The hash configuration only reports the hash.
+        let mut data = VIRTIO_NET_RSS_HASH_TYPE_UDPV4.to_le_bytes().to_vec();
+        data.extend_from_slice(&[0; 8]);
+        data.push(KEY.len() as u8);
+        data.extend_from_slice(&KEY);
+        let hash = RssConfig::parse_hash(&data).unwrap();
+        assert_eq!(
+            hash.hash(&ipv4_frame(IPPROTO_UDP, 0)),
+            Some((0x51cc_c178, VIRTIO_NET_HASH_REPORT_UDPV4))
+        );
+        assert_eq!(hash.hash(&ipv4_frame(IPPROTO_TCP, 0)), None);
+        assert_eq!(hash.queue(Some(0x51cc_c178)), None);
+    }
+}
@@ -342,6 +342,14 @@ impl Tap {
         unsafe { Self::ioctl_with_val(&self.tap_file, net_gen::TUNSETOFFLOAD(), flags as c_ulong) }
     }
 
+    /// Check whether the tap interface supports UDP segmentation offload,
+    /// available since Linux 6.2. The offload flags are reset by the check.
+    pub fn supports_uso(&self) -> bool {
+        self.set_offload(net_gen::TUN_F_CSUM | net_gen::TUN_F_USO4 | net_gen::TUN_F_USO6)
+            .is_ok()
+            && self.set_offload(0).is_ok()
+    }
+
     /// Enable the tap interface.
     pub fn enable(&self) -> Result<()> {
         let sock = create_unix_socket().map_err(Error::NetUtil)?;
@@ -387,6 +395,12 @@ impl Tap {
         ifreq
     }
 
+    /// Returns the file of the tap interface, e.g. to hand it over to the
+    /// vhost-net module.
+    pub fn file(&self) -> &File {
+        &self.tap_file
+    }
+
     pub fn get_if_name(&self) -> Vec<u8> {
         self.if_name.clone()
     }
@@ -213,6 +213,7 @@ impl FromStr for ByteSized {
 
 pub struct IntegerList(pub Vec<u64>);
 
+#[derive(Debug)]
 pub enum IntegerListParseError {
     InvalidValue(String),
 }
@@ -34,6 +34,13 @@
 //! The granularity for 'wake up' events when the rate limiter is blocked is
 //! currently hardcoded to `100 milliseconds`.
 //!
+//! ## Groups
+//!
+//! Several rate limiters can be created from the same `RateLimiterGroup`, in
+//! which case they all consume from the token buckets of the group instead of
+//! having their own. Each of them keeps its own timer though, so that every
+//! user is woken up independently when the budget of the group runs out.
+//!
 //! ## Limitations
 //!
 //! This rate limiter implementation relies on the *Linux kernel's timerfd* so its
@@ -47,6 +54,7 @@
 extern crate log;
 
 use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use std::{fmt, io};
 use vmm_sys_util::timerfd::TimerFd;
@@ -255,6 +263,77 @@ pub enum BucketUpdate {
     Update(TokenBucket),
 }
 
+// Consumes tokens from the bucket, returning whether that succeeded along
+// with how long the limiter must then be blocked for, if at all.
+fn reduce_bucket(bucket: &mut TokenBucket, tokens: u64) -> (bool, Option<Duration>) {
+    let refill_time = bucket.refill_time_ms();
+    match bucket.reduce(tokens) {
+        // When we report budget is over, there will be no further calls here,
+        // register a timer to replenish the bucket and resume processing.
+        BucketReduction::Failure => (false, Some(TIMER_REFILL_DUR)),
+        // The operation succeeded and further calls can be made.
+        BucketReduction::Success => (true, None),
+        // The operation succeeded as the tokens have been consumed
+        // but the timer still needs to be armed.
+        BucketReduction::OverConsumption(ratio) => {
+            // The operation "borrowed" a number of tokens `ratio` times
+            // greater than the size of the bucket, and since it takes
+            // `refill_time` milliseconds to fill an empty bucket, in
+            // order to enforce the bandwidth limit we need to prevent
+            // further calls to the rate limiter for
+            // `ratio * refill_time` milliseconds.
+            (
+                true,
+                Some(Duration::from_millis((ratio * refill_time as f64) as u64)),
+            )
+        }
+    }
+}
+
+#[derive(Debug)]
+struct GroupBuckets {
+    bandwidth: Option<TokenBucket>,
+    ops: Option<TokenBucket>,
+}
+
+/// Token buckets shared by several rate limiters.
+///
+/// The rate limiters created through `RateLimiter::new_in_group()` are all
+/// throttled against the aggregate budget of the group.
+#[derive(Clone, Debug)]
+pub struct RateLimiterGroup {
+    buckets: Arc<Mutex<GroupBuckets>>,
+}
+
+impl RateLimiterGroup {
+    /// Creates a new group of rate limiters.
+    ///
+    /// The arguments have the same meaning as the ones of `RateLimiter::new()`.
+    pub fn new(
+        bytes_total_capacity: u64,
+        bytes_one_time_burst: u64,
+        bytes_complete_refill_time_ms: u64,
+        ops_total_capacity: u64,
+        ops_one_time_burst: u64,
+        ops_complete_refill_time_ms: u64,
+    ) -> Self {
+        RateLimiterGroup {
+            buckets: Arc::new(Mutex::new(GroupBuckets {
+                bandwidth: TokenBucket::new(
+                    bytes_total_capacity,
+                    bytes_one_time_burst,
+                    bytes_complete_refill_time_ms,
+                ),
+                ops: TokenBucket::new(
+                    ops_total_capacity,
+                    ops_one_time_burst,
+                    ops_complete_refill_time_ms,
+                ),
+            })),
+        }
+    }
+}
+
 /// Rate Limiter that works on both bandwidth and ops/s limiting.
 ///
 /// Bandwidth (bytes/s) and ops/s limiting can be used at the same time or individually.
@@ -274,6 +353,9 @@ pub struct RateLimiter {
     bandwidth: Option<TokenBucket>,
     ops: Option<TokenBucket>,
 
+    // Buckets consumed from instead of the ones above when part of a group.
+    group: Option<RateLimiterGroup>,
+
     timer_fd: TimerFd,
     // Internal flag that quickly determines timer state.
     timer_active: bool,
@@ -340,6 +422,35 @@ impl RateLimiter {
         // We'll need a timer_fd, even if our current config effectively disables rate limiting,
         // because `Self::update_buckets()` might re-enable it later, and we might be
         // seccomp-blocked from creating the timer_fd at that time.
+        let timer_fd = Self::create_timer_fd()?;
+
+        Ok(RateLimiter {
+            bandwidth: bytes_token_bucket,
+            ops: ops_token_bucket,
+            group: None,
+            timer_fd,
+            timer_active: false,
+        })
+    }
+
+    /// Creates a new Rate Limiter consuming from the token buckets of the group.
+    ///
+    /// # Errors
+    ///
+    /// If the timerfd creation fails, an error is returned.
+    pub fn new_in_group(group: RateLimiterGroup) -> io::Result<Self> {
+        let timer_fd = Self::create_timer_fd()?;
+
+        Ok(RateLimiter {
+            bandwidth: None,
+            ops: None,
+            group: Some(group),
+            timer_fd,
+            timer_active: false,
+        })
+    }
+
+    fn create_timer_fd() -> io::Result<TimerFd> {
         let timer_fd = TimerFd::new()?;
         // Note: vmm_sys_util::TimerFd::new() open the fd w/o O_NONBLOCK. We manually add this flag
         // so that `Self::event_handler` won't be blocked with `vmm_sys_util::TimerFd::wait()`.
@@ -354,12 +465,7 @@ impl RateLimiter {
             return Err(std::io::Error::last_os_error());
         }
 
-        Ok(RateLimiter {
-            bandwidth: bytes_token_bucket,
-            ops: ops_token_bucket,
-            timer_fd,
-            timer_active: false,
-        })
+        Ok(timer_fd)
     }
 
     // Arm the timer of the rate limiter with the provided `Duration` (which will fire only once).
@@ -380,44 +486,29 @@ impl RateLimiter {
             return false;
         }
 
-        // Identify the required token bucket.
-        let token_bucket = match token_type {
-            TokenType::Bytes => self.bandwidth.as_mut(),
-            TokenType::Ops => self.ops.as_mut(),
-        };
-        // Try to consume from the token bucket.
-        if let Some(bucket) = token_bucket {
-            let refill_time = bucket.refill_time_ms();
-            match bucket.reduce(tokens) {
-                // When we report budget is over, there will be no further calls here,
-                // register a timer to replenish the bucket and resume processing;
-                // make sure there is only one running timer for this limiter.
-                BucketReduction::Failure => {
-                    if !self.timer_active {
-                        self.activate_timer(TIMER_REFILL_DUR);
-                    }
-                    false
-                }
-                // The operation succeeded and further calls can be made.
-                BucketReduction::Success => true,
-                // The operation succeeded as the tokens have been consumed
-                // but the timer still needs to be armed.
-                BucketReduction::OverConsumption(ratio) => {
-                    // The operation "borrowed" a number of tokens `ratio` times
-                    // greater than the size of the bucket, and since it takes
-                    // `refill_time` milliseconds to fill an empty bucket, in
-                    // order to enforce the bandwidth limit we need to prevent
-                    // further calls to the rate limiter for
-                    // `ratio * refill_time` milliseconds.
-                    self.activate_timer(Duration::from_millis((ratio * refill_time as f64) as u64));
-                    true
-                }
-            }
+        // Try to consume from the required token bucket. If the bucket is not
+        // present rate limiting is disabled on token type, consume() will
+        // always succeed.
+        let (consumed, timer_dur) = if let Some(group) = &self.group {
+            let mut buckets = group.buckets.lock().unwrap();
+            let token_bucket = match token_type {
+                TokenType::Bytes => buckets.bandwidth.as_mut(),
+                TokenType::Ops => buckets.ops.as_mut(),
+            };
+            token_bucket.map_or((true, None), |bucket| reduce_bucket(bucket, tokens))
         } else {
-            // If bucket is not present rate limiting is disabled on token type,
-            // consume() will always succeed.
-            true
+            let token_bucket = match token_type {
+                TokenType::Bytes => self.bandwidth.as_mut(),
+                TokenType::Ops => self.ops.as_mut(),
+            };
+            token_bucket.map_or((true, None), |bucket| reduce_bucket(bucket, tokens))
+        };
+
+        if let Some(dur) = timer_dur {
+            self.activate_timer(dur);
         }
+
+        consumed
     }
 
     /// Adds tokens of `token_type` to their respective bucket.
@@ -425,6 +516,18 @@ impl RateLimiter {
     /// Can be used to *manually* add tokens to a bucket. Useful for reverting a
     /// `consume()` if needed.
     pub fn manual_replenish(&mut self, tokens: u64, token_type: TokenType) {
+        if let Some(group) = &self.group {
+            let mut buckets = group.buckets.lock().unwrap();
+            let token_bucket = match token_type {
+                TokenType::Bytes => buckets.bandwidth.as_mut(),
+                TokenType::Ops => buckets.ops.as_mut(),
+            };
+            if let Some(bucket) = token_bucket {
+                bucket.replenish(tokens);
+            }
+            return;
+        }
+
         // Identify the required token bucket.
         let token_bucket = match token_type {
             TokenType::Bytes => self.bandwidth.as_mut(),
@@ -885,6 +988,39 @@ pub(crate) mod tests {
         assert_eq!(x.ops, None);
     }
 
+    #[test]
+    fn test_rate_limiter_group() {
+        // group with a limit of 1000 bytes/s and 1000 ops/s
+        let group = RateLimiterGroup::new(1000, 0, 1000, 1000, 0, 1000);
+        let mut l1 = RateLimiter::new_in_group(group.clone()).unwrap();
+        let mut l2 = RateLimiter::new_in_group(group).unwrap();
+
+        // the limiters don't have their own buckets
+        assert!(l1.bandwidth().is_none());
+        assert!(l1.ops().is_none());
+
+        // both limiters consume from the same budget
+        assert!(l1.consume(600, TokenType::Bytes));
+        assert!(!l2.consume(600, TokenType::Bytes));
+        assert!(l2.is_blocked());
+        // only the limiter that ran out of budget is blocked
+        assert!(!l1.is_blocked());
+
+        // tokens replenished through one limiter benefit the whole group
+        l1.manual_replenish(600, TokenType::Bytes);
+
+        // the ops budget is shared the same way
+        assert!(l1.consume(900, TokenType::Ops));
+        assert!(!l1.consume(900, TokenType::Ops));
+        assert!(l1.is_blocked());
+
+        // wait for the timer to fire
+        thread::sleep(Duration::from_millis(200));
+        assert!(l2.event_handler().is_ok());
+        assert!(!l2.is_blocked());
+        assert!(l2.consume(600, TokenType::Bytes));
+    }
+
     #[test]
     fn test_rate_limiter_debug() {
         let l = RateLimiter::new(1, 2, 3, 4, 5, 6).unwrap();
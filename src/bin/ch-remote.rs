@@ -8,10 +8,11 @@ use api_client::simple_api_command_with_fds;
 use api_client::simple_api_full_command;
 use api_client::Error as ApiClientError;
 use argh::FromArgs;
-use option_parser::{ByteSized, ByteSizedParseError};
+use option_parser::{ByteSized, ByteSizedParseError, IntegerList, IntegerListParseError};
 use std::fmt;
-use std::io::Read;
+use std::io::{BufRead, BufReader, Read, Write};
 use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
 use std::process;
 
 #[derive(Debug)]
@@ -20,6 +21,12 @@ enum Error {
     ApiClient(ApiClientError),
     InvalidMemorySize(ByteSizedParseError),
     InvalidBalloonSize(ByteSizedParseError),
+    InvalidCpuList(IntegerListParseError),
+    InvalidPointerButton(String),
+    InvalidCompression(String),
+    InvalidShutdownMode(String),
+    InvalidWatchdogAction(String),
+    InvalidCpu(u64),
     AddDeviceConfig(vmm::config::Error),
     AddDiskConfig(vmm::config::Error),
     AddFsConfig(vmm::config::Error),
@@ -31,6 +38,8 @@ enum Error {
     Restore(vmm::config::Error),
     ReadingStdin(std::io::Error),
     ReadingFile(std::io::Error),
+    Watch(std::io::Error),
+    WatchResponse(String),
 }
 
 impl fmt::Display for Error {
@@ -41,6 +50,12 @@ impl fmt::Display for Error {
             Connect(e) => write!(f, "Error opening HTTP socket: {e}"),
             InvalidMemorySize(e) => write!(f, "Error parsing memory size: {e:?}"),
             InvalidBalloonSize(e) => write!(f, "Error parsing balloon size: {e:?}"),
+            InvalidCpuList(e) => write!(f, "Error parsing vCPU list: {e:?}"),
+            InvalidPointerButton(b) => write!(f, "Invalid pointer button: {b}"),
+            InvalidCompression(c) => write!(f, "Invalid compression algorithm: {c}"),
+            InvalidShutdownMode(m) => write!(f, "Invalid shutdown mode: {m}"),
+            InvalidWatchdogAction(a) => write!(f, "Invalid watchdog action: {a}"),
+            InvalidCpu(cpu) => write!(f, "Invalid vCPU: {cpu}"),
             AddDeviceConfig(e) => write!(f, "Error parsing device syntax: {e}"),
             AddDiskConfig(e) => write!(f, "Error parsing disk syntax: {e}"),
             AddFsConfig(e) => write!(f, "Error parsing filesystem syntax: {e}"),
@@ -52,15 +67,18 @@ impl fmt::Display for Error {
             Restore(e) => write!(f, "Error parsing restore syntax: {e}"),
             ReadingStdin(e) => write!(f, "Error reading from stdin: {e}"),
             ReadingFile(e) => write!(f, "Error reading from file: {e}"),
+            Watch(e) => write!(f, "Error reading the events: {e}"),
+            WatchResponse(s) => write!(f, "Server refused the event subscription: {s}"),
         }
     }
 }
 
 fn resize_api_command(
     socket: &mut UnixStream,
-    desired_vcpus: Option<u8>,
+    desired_vcpus: Option<u32>,
     memory: &Option<String>,
     balloon: &Option<String>,
+    remove_cpus: &Option<String>,
 ) -> Result<(), Error> {
     let desired_ram: Option<u64> = if let Some(memory) = memory {
         Some(
@@ -84,10 +102,25 @@ fn resize_api_command(
         None
     };
 
+    let removed_vcpus: Option<Vec<u32>> = if let Some(remove_cpus) = remove_cpus {
+        Some(
+            remove_cpus
+                .parse::<IntegerList>()
+                .map_err(Error::InvalidCpuList)?
+                .0
+                .iter()
+                .map(|cpu| u32::try_from(*cpu).map_err(|_| Error::InvalidCpu(*cpu)))
+                .collect::<Result<_, _>>()?,
+        )
+    } else {
+        None
+    };
+
     let resize = vmm::api::VmResizeData {
         desired_vcpus,
         desired_ram,
         desired_balloon,
+        removed_vcpus,
     };
 
     simple_api_command(
@@ -273,9 +306,14 @@ fn coredump_api_command(socket: &mut UnixStream, destination_url: &str) -> Resul
     .map_err(Error::ApiClient)
 }
 
-fn receive_migration_api_command(socket: &mut UnixStream, url: &str) -> Result<(), Error> {
+fn receive_migration_api_command(
+    socket: &mut UnixStream,
+    url: &str,
+    tls_dir: Option<&str>,
+) -> Result<(), Error> {
     let receive_migration_data = vmm::api::VmReceiveMigrationData {
         receiver_url: url.to_owned(),
+        tls_dir: tls_dir.map(PathBuf::from),
     };
     simple_api_command(
         socket,
@@ -290,10 +328,24 @@ fn send_migration_api_command(
     socket: &mut UnixStream,
     url: &str,
     local: bool,
+    postcopy: bool,
+    auto_converge: bool,
+    connections: u32,
+    compression: Option<&str>,
+    tls_dir: Option<&str>,
 ) -> Result<(), Error> {
+    let compression = compression
+        .map(str::parse::<vmm::api::Compression>)
+        .transpose()
+        .map_err(Error::InvalidCompression)?;
     let send_migration_data = vmm::api::VmSendMigrationData {
         destination_url: url.to_owned(),
         local,
+        postcopy,
+        connections,
+        auto_converge,
+        compression,
+        tls_dir: tls_dir.map(PathBuf::from),
     };
     simple_api_command(
         socket,
@@ -304,6 +356,169 @@ fn send_migration_api_command(
     .map_err(Error::ApiClient)
 }
 
+fn clone_api_command(socket: &mut UnixStream, url: &str) -> Result<(), Error> {
+    let clone_data = vmm::api::VmCloneData {
+        destination_url: url.to_owned(),
+    };
+    simple_api_command(
+        socket,
+        "PUT",
+        "clone",
+        Some(&serde_json::to_string(&clone_data).unwrap()),
+    )
+    .map_err(Error::ApiClient)
+}
+
+fn send_keys_api_command(
+    socket: &mut UnixStream,
+    keys: &[String],
+    hold_time_ms: Option<u64>,
+) -> Result<(), Error> {
+    let send_keys_data = vmm::api::VmSendKeysData {
+        keys: keys.to_vec(),
+        hold_time_ms,
+    };
+    simple_api_command(
+        socket,
+        "PUT",
+        "send-keys",
+        Some(&serde_json::to_string(&send_keys_data).unwrap()),
+    )
+    .map_err(Error::ApiClient)
+}
+
+fn send_pointer_api_command(
+    socket: &mut UnixStream,
+    dx: i32,
+    dy: i32,
+    buttons: &Option<String>,
+    hold_time_ms: Option<u64>,
+) -> Result<(), Error> {
+    let buttons = if let Some(buttons) = buttons {
+        buttons
+            .split(',')
+            .map(|button| match button {
+                "left" => Ok(vmm::api::PointerButton::Left),
+                "right" => Ok(vmm::api::PointerButton::Right),
+                "middle" => Ok(vmm::api::PointerButton::Middle),
+                _ => Err(Error::InvalidPointerButton(button.to_owned())),
+            })
+            .collect::<Result<Vec<_>, Error>>()?
+    } else {
+        Vec::new()
+    };
+
+    let send_pointer_data = vmm::api::VmSendPointerData {
+        dx,
+        dy,
+        buttons,
+        hold_time_ms,
+    };
+    simple_api_command(
+        socket,
+        "PUT",
+        "send-pointer",
+        Some(&serde_json::to_string(&send_pointer_data).unwrap()),
+    )
+    .map_err(Error::ApiClient)
+}
+
+fn dirty_rate_api_command(socket: &mut UnixStream, period_ms: Option<u64>) -> Result<(), Error> {
+    let dirty_rate_data = vmm::api::VmDirtyRateData { period_ms };
+    simple_api_command(
+        socket,
+        "PUT",
+        "dirty-rate",
+        Some(&serde_json::to_string(&dirty_rate_data).unwrap()),
+    )
+    .map_err(Error::ApiClient)
+}
+
+fn guest_exec_api_command(
+    socket: &mut UnixStream,
+    path: &str,
+    args: &[String],
+    env: &[String],
+    timeout_ms: Option<u64>,
+) -> Result<(), Error> {
+    let guest_exec_data = vmm::api::VmGuestExecData {
+        path: path.to_owned(),
+        args: args.to_vec(),
+        env: env.to_vec(),
+        input_data: None,
+        timeout_ms,
+    };
+    simple_api_command(
+        socket,
+        "PUT",
+        "guest-exec",
+        Some(&serde_json::to_string(&guest_exec_data).unwrap()),
+    )
+    .map_err(Error::ApiClient)
+}
+
+fn guest_shutdown_api_command(socket: &mut UnixStream, mode: &str) -> Result<(), Error> {
+    let mode = match mode {
+        "powerdown" => vmm::api::GuestShutdownMode::Powerdown,
+        "reboot" => vmm::api::GuestShutdownMode::Reboot,
+        "halt" => vmm::api::GuestShutdownMode::Halt,
+        _ => return Err(Error::InvalidShutdownMode(mode.to_owned())),
+    };
+    let guest_shutdown_data = vmm::api::VmGuestShutdownData { mode };
+    simple_api_command(
+        socket,
+        "PUT",
+        "guest-shutdown",
+        Some(&serde_json::to_string(&guest_shutdown_data).unwrap()),
+    )
+    .map_err(Error::ApiClient)
+}
+
+fn nmi_api_command(socket: &mut UnixStream, cpu: Option<u32>) -> Result<(), Error> {
+    let nmi_data = vmm::api::VmNmiData { cpu };
+    simple_api_command(
+        socket,
+        "PUT",
+        "nmi",
+        Some(&serde_json::to_string(&nmi_data).unwrap()),
+    )
+    .map_err(Error::ApiClient)
+}
+
+fn change_media_api_command(
+    socket: &mut UnixStream,
+    controller: &str,
+    lun: u16,
+    path: Option<&str>,
+) -> Result<(), Error> {
+    let change_media_data = vmm::api::VmChangeMediaData {
+        controller: controller.to_owned(),
+        lun,
+        path: path.map(PathBuf::from),
+    };
+    simple_api_command(
+        socket,
+        "PUT",
+        "change-media",
+        Some(&serde_json::to_string(&change_media_data).unwrap()),
+    )
+    .map_err(Error::ApiClient)
+}
+
+fn set_watchdog_action_api_command(socket: &mut UnixStream, action: &str) -> Result<(), Error> {
+    let action = action
+        .parse()
+        .map_err(|_| Error::InvalidWatchdogAction(action.to_owned()))?;
+    let watchdog_action_data = vmm::api::VmSetWatchdogActionData { action };
+    simple_api_command(
+        socket,
+        "PUT",
+        "set-watchdog-action",
+        Some(&serde_json::to_string(&watchdog_action_data).unwrap()),
+    )
+    .map_err(Error::ApiClient)
+}
+
 fn create_api_command(socket: &mut UnixStream, path: &str) -> Result<(), Error> {
     let mut data = String::default();
     if path == "-" {
@@ -317,6 +532,78 @@ fn create_api_command(socket: &mut UnixStream, path: &str) -> Result<(), Error>
     simple_api_command(socket, "PUT", "create", Some(&data)).map_err(Error::ApiClient)
 }
 
+// Human readable form of an event, e.g.
+// "12.000408 vm device-added bdf=0000:00:06.0 id=_disk2"
+fn format_event(event: &serde_json::Value) -> String {
+    let secs = event["timestamp"]["secs"].as_u64().unwrap_or_default();
+    let micros = event["timestamp"]["nanos"].as_u64().unwrap_or_default() / 1000;
+    let mut line = format!(
+        "{secs}.{micros:06} {} {}",
+        event["source"].as_str().unwrap_or_default(),
+        event["event"].as_str().unwrap_or_default()
+    );
+    if let Some(properties) = event["properties"].as_object() {
+        let properties: std::collections::BTreeMap<_, _> = properties.iter().collect();
+        for (key, value) in properties {
+            line.push_str(&format!(" {key}={}", value.as_str().unwrap_or_default()));
+        }
+    }
+
+    line
+}
+
+// An event matches either as "<source>:<event>" or as "<event>" alone.
+fn event_matches(event: &serde_json::Value, pattern: &str) -> bool {
+    let source = event["source"].as_str().unwrap_or_default();
+    let name = event["event"].as_str().unwrap_or_default();
+    match pattern.split_once(':') {
+        Some((s, e)) => s == source && e == name,
+        None => pattern == name,
+    }
+}
+
+fn watch_api_command(socket: &mut UnixStream, json: bool, until: &[String]) -> Result<(), Error> {
+    socket
+        .write_all(b"GET /api/v1/vm.events HTTP/1.1\r\nHost: localhost\r\nAccept: */*\r\n\r\n")
+        .map_err(Error::Watch)?;
+
+    let mut reader = BufReader::new(socket);
+    let mut status = String::new();
+    reader.read_line(&mut status).map_err(Error::Watch)?;
+    if !status.starts_with("HTTP/1.1 200") {
+        return Err(Error::WatchResponse(status.trim().to_string()));
+    }
+
+    // Skip the headers, the body carries one event per line until the
+    // connection is closed.
+    let mut lines = reader.lines();
+    for line in lines.by_ref() {
+        if line.map_err(Error::Watch)?.is_empty() {
+            break;
+        }
+    }
+
+    for line in lines {
+        let line = line.map_err(Error::Watch)?;
+        let event: serde_json::Value = match serde_json::from_str(&line) {
+            Ok(event) => event,
+            Err(_) => continue,
+        };
+
+        if json {
+            println!("{line}");
+        } else {
+            println!("{}", format_event(&event));
+        }
+
+        if until.iter().any(|pattern| event_matches(&event, pattern)) {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
 fn do_command(toplevel: &TopLevel) -> Result<(), Error> {
     let mut socket =
         UnixStream::connect(toplevel.api_socket.as_deref().unwrap()).map_err(Error::Connect)?;
@@ -349,6 +636,12 @@ fn do_command(toplevel: &TopLevel) -> Result<(), Error> {
         SubCommandEnum::Counters(_) => {
             simple_api_command(&mut socket, "GET", "counters", None).map_err(Error::ApiClient)
         }
+        SubCommandEnum::VcpuStats(_) => {
+            simple_api_command(&mut socket, "GET", "vcpu-stats", None).map_err(Error::ApiClient)
+        }
+        SubCommandEnum::DirtyRate(ref config) => {
+            dirty_rate_api_command(&mut socket, config.period_ms)
+        }
         SubCommandEnum::Ping(_) => {
             simple_api_full_command(&mut socket, "GET", "vmm.ping", None).map_err(Error::ApiClient)
         }
@@ -356,9 +649,13 @@ fn do_command(toplevel: &TopLevel) -> Result<(), Error> {
             simple_api_full_command(&mut socket, "PUT", "vmm.shutdown", None)
                 .map_err(Error::ApiClient)
         }
-        SubCommandEnum::Resize(ref config) => {
-            resize_api_command(&mut socket, config.cpus, &config.memory, &config.balloon)
-        }
+        SubCommandEnum::Resize(ref config) => resize_api_command(
+            &mut socket,
+            config.cpus,
+            &config.memory,
+            &config.balloon,
+            &config.remove_cpus,
+        ),
         SubCommandEnum::ResizeZone(ref config) => {
             resize_zone_api_command(&mut socket, &config.id, &config.size)
         }
@@ -398,11 +695,52 @@ fn do_command(toplevel: &TopLevel) -> Result<(), Error> {
             &mut socket,
             &config.send_migration_config,
             config.send_migration_local,
+            config.send_migration_postcopy,
+            config.send_migration_auto_converge,
+            config.send_migration_connections,
+            config.send_migration_compression.as_deref(),
+            config.send_migration_tls_dir.as_deref(),
+        ),
+        SubCommandEnum::ReceiveMigration(ref config) => receive_migration_api_command(
+            &mut socket,
+            &config.receive_migration_config,
+            config.receive_migration_tls_dir.as_deref(),
+        ),
+        SubCommandEnum::Clone(ref config) => clone_api_command(&mut socket, &config.clone_config),
+        SubCommandEnum::SendKeys(ref config) => {
+            send_keys_api_command(&mut socket, &config.keys, config.hold_time)
+        }
+        SubCommandEnum::SendPointer(ref config) => send_pointer_api_command(
+            &mut socket,
+            config.dx,
+            config.dy,
+            &config.buttons,
+            config.hold_time,
+        ),
+        SubCommandEnum::GuestExec(ref config) => guest_exec_api_command(
+            &mut socket,
+            &config.path,
+            &config.args,
+            &config.env,
+            config.timeout_ms,
+        ),
+        SubCommandEnum::GuestShutdown(ref config) => {
+            guest_shutdown_api_command(&mut socket, &config.mode)
+        }
+        SubCommandEnum::Nmi(ref config) => nmi_api_command(&mut socket, config.cpu),
+        SubCommandEnum::ChangeMedia(ref config) => change_media_api_command(
+            &mut socket,
+            &config.controller,
+            config.lun,
+            config.path.as_deref(),
         ),
-        SubCommandEnum::ReceiveMigration(ref config) => {
-            receive_migration_api_command(&mut socket, &config.receive_migration_config)
+        SubCommandEnum::SetWatchdogAction(ref config) => {
+            set_watchdog_action_api_command(&mut socket, &config.action)
         }
         SubCommandEnum::Create(ref config) => create_api_command(&mut socket, &config.vm_config),
+        SubCommandEnum::Watch(ref config) => {
+            watch_api_command(&mut socket, config.json, &config.until)
+        }
         SubCommandEnum::Version(_) => {
             // Already handled outside of this function
             panic!()
@@ -435,6 +773,8 @@ enum SubCommandEnum {
     RemoveDevice(RemoveDeviceSubcommand),
     Info(InfoSubcommand),
     Counters(CountersSubcommand),
+    VcpuStats(VcpuStatsSubcommand),
+    DirtyRate(DirtyRateSubcommand),
     Pause(PauseSubcommand),
     Reboot(RebootSubcommand),
     PowerButton(PowerButtonSubcommand),
@@ -451,7 +791,16 @@ enum SubCommandEnum {
     Coredump(CoredumpSubcommand),
     SendMigration(SendMigrationSubcommand),
     ReceiveMigration(ReceiveMigrationSubcommand),
+    Clone(CloneSubcommand),
+    SendKeys(SendKeysSubcommand),
+    SendPointer(SendPointerSubcommand),
+    GuestExec(GuestExecSubcommand),
+    GuestShutdown(GuestShutdownSubcommand),
+    SetWatchdogAction(SetWatchdogActionSubcommand),
+    Nmi(NmiSubcommand),
+    ChangeMedia(ChangeMediaSubcommand),
     Create(CreateSubcommand),
+    Watch(WatchSubcommand),
     Version(VersionSubcommand),
 }
 
@@ -532,7 +881,7 @@ struct AddVsockSubcommand {
 /// Remove VFIO device
 struct RemoveDeviceSubcommand {
     #[argh(positional)]
-    /// device config
+    /// device identifier or alias
     device_config: String,
 }
 
@@ -546,6 +895,20 @@ struct InfoSubcommand {}
 /// Counters from the VM
 struct CountersSubcommand {}
 
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "vcpu-stats")]
+/// Exit statistics of each vCPU
+struct VcpuStatsSubcommand {}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "dirty-rate")]
+/// Measure the rate at which the VM dirties its memory
+struct DirtyRateSubcommand {
+    #[argh(option, long = "period-ms")]
+    /// time over which the dirty memory is measured
+    period_ms: Option<u64>,
+}
+
 #[derive(FromArgs, PartialEq, Debug)]
 #[argh(subcommand, name = "pause")]
 /// Pause the VM
@@ -597,7 +960,7 @@ struct ShutdownVmmSubcommand {}
 struct ResizeSubcommand {
     #[argh(option, long = "cpus")]
     /// new VCPUs count
-    cpus: Option<u8>,
+    cpus: Option<u32>,
 
     #[argh(option, long = "memory")]
     /// new memory size in bytes (supports K/M/G suffix)"
@@ -606,6 +969,10 @@ struct ResizeSubcommand {
     #[argh(option, long = "balloon")]
     /// new balloon size in bytes (supports K/M/G suffix)"
     balloon: Option<String>,
+
+    #[argh(option, long = "remove-cpus")]
+    /// list of VCPUs to eject (e.g. 2,4-5), instead of the last ones
+    remove_cpus: Option<String>,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -656,6 +1023,26 @@ struct SendMigrationSubcommand {
     /// local migration
     send_migration_local: bool,
 
+    #[argh(switch, long = "postcopy")]
+    /// switch to the destination after a single memory pass
+    send_migration_postcopy: bool,
+
+    #[argh(switch, long = "auto-converge")]
+    /// throttle the vCPUs until the migration converges
+    send_migration_auto_converge: bool,
+
+    #[argh(option, long = "connections", default = "1")]
+    /// number of connections transferring the memory in parallel
+    send_migration_connections: u32,
+
+    #[argh(option, long = "compression")]
+    /// compress the memory with "zstd" or "lz4"
+    send_migration_compression: Option<String>,
+
+    #[argh(option, long = "tls-dir")]
+    /// directory holding the certificates to encrypt the migration with TLS
+    send_migration_tls_dir: Option<String>,
+
     #[argh(positional)]
     /// destination_url
     send_migration_config: String,
@@ -665,11 +1052,123 @@ struct SendMigrationSubcommand {
 #[argh(subcommand, name = "receive-migration")]
 /// Receive a VM migration
 struct ReceiveMigrationSubcommand {
+    #[argh(option, long = "tls-dir")]
+    /// directory holding the certificates to encrypt the migration with TLS
+    receive_migration_tls_dir: Option<String>,
+
     #[argh(positional)]
     /// receiver url
     receive_migration_config: String,
 }
 
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "clone")]
+/// Clone the paused VM into another VMM, sharing its memory copy-on-write
+struct CloneSubcommand {
+    #[argh(positional)]
+    /// destination url
+    clone_config: String,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "send-keys")]
+/// Send key combinations to the VM (e.g. ctrl-alt-delete)
+struct SendKeysSubcommand {
+    #[argh(option, long = "hold-time")]
+    /// time in milliseconds each combination is held down
+    hold_time: Option<u64>,
+
+    #[argh(positional)]
+    /// key combinations, sent one after the other
+    keys: Vec<String>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "send-pointer")]
+/// Send a relative pointer motion and/or button click to the VM
+struct SendPointerSubcommand {
+    #[argh(option, long = "dx", default = "0")]
+    /// horizontal motion
+    dx: i32,
+
+    #[argh(option, long = "dy", default = "0")]
+    /// vertical motion, growing downwards
+    dy: i32,
+
+    #[argh(option, long = "buttons")]
+    /// buttons held during the motion (e.g. left,right,middle)
+    buttons: Option<String>,
+
+    #[argh(option, long = "hold-time")]
+    /// time in milliseconds the buttons are held down
+    hold_time: Option<u64>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "guest-exec")]
+/// Run a program in the guest through the guest agent
+struct GuestExecSubcommand {
+    #[argh(option, long = "env")]
+    /// environment variable given to the program (e.g. NAME=value)
+    env: Vec<String>,
+
+    #[argh(option, long = "timeout-ms")]
+    /// time after which the program is killed
+    timeout_ms: Option<u64>,
+
+    #[argh(positional)]
+    /// path of the program in the guest
+    path: String,
+
+    #[argh(positional)]
+    /// arguments of the program
+    args: Vec<String>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "guest-shutdown")]
+/// Shut the guest down through the guest agent
+struct GuestShutdownSubcommand {
+    #[argh(option, long = "mode", default = "String::from(\"powerdown\")")]
+    /// powerdown, reboot or halt
+    mode: String,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "nmi")]
+/// Inject a Non-Maskable Interrupt into the VM
+struct NmiSubcommand {
+    #[argh(option, long = "cpu")]
+    /// vCPU the NMI is injected into, every vCPU by default
+    cpu: Option<u32>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "change-media")]
+/// Insert an image in a SCSI CD-ROM drive, or eject its medium
+struct ChangeMediaSubcommand {
+    #[argh(positional)]
+    /// identifier of the virtio-scsi controller
+    controller: String,
+
+    #[argh(positional)]
+    /// logical unit number of the CD-ROM drive
+    lun: u16,
+
+    #[argh(option, long = "path")]
+    /// image to insert, the drive being emptied when not specified
+    path: Option<String>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "set-watchdog-action")]
+/// Change the action taken when the watchdog expires
+struct SetWatchdogActionSubcommand {
+    #[argh(positional)]
+    /// reset, poweroff, pause or none
+    action: String,
+}
+
 #[derive(FromArgs, PartialEq, Debug)]
 #[argh(subcommand, name = "create")]
 /// Create a VM from a JSON configuration
@@ -679,6 +1178,19 @@ struct CreateSubcommand {
     vm_config: String,
 }
 
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "watch")]
+/// Print the VMM events as they happen
+struct WatchSubcommand {
+    #[argh(switch, long = "json")]
+    /// print the events as JSON lines
+    json: bool,
+
+    #[argh(option, long = "until")]
+    /// stop after an event matching <source>:<event> or <event> (e.g. vm:booted)
+    until: Vec<String>,
+}
+
 #[derive(FromArgs, PartialEq, Debug)]
 #[argh(subcommand, name = "version")]
 /// Print version information
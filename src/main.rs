@@ -9,12 +9,13 @@ extern crate event_monitor;
 use argh::FromArgs;
 use libc::EFD_NONBLOCK;
 use log::LevelFilter;
-use option_parser::OptionParser;
+use option_parser::{IntegerList, OptionParser, Toggle};
 use seccompiler::SeccompAction;
 use signal_hook::consts::SIGSYS;
 use std::env;
 use std::fs::File;
-use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
+use std::path::Path;
 use std::sync::mpsc::channel;
 use std::sync::{Arc, Mutex};
 use thiserror::Error;
@@ -40,6 +41,10 @@ enum Error {
     StartVmmThread(#[source] vmm::Error),
     #[error("Error parsing config: {0}")]
     ParsingConfig(vmm::config::Error),
+    #[error("Error reading config file: {0}")]
+    ReadingConfigFile(#[source] std::io::Error),
+    #[error("Error parsing config file: {0}")]
+    ParsingConfigFile(#[source] anyhow::Error),
     #[error("Error creating VM: {0:?}")]
     VmCreate(vmm::api::ApiError),
     #[error("Error booting VM: {0:?}")]
@@ -48,18 +53,35 @@ enum Error {
     VmRestore(vmm::api::ApiError),
     #[error("Error parsing restore: {0}")]
     ParsingRestore(vmm::config::Error),
+    #[error("Error loading the hibernated VM: {0}")]
+    ResumeFrom(#[source] std::io::Error),
     #[error("Failed to join on VMM thread: {0:?}")]
     ThreadJoin(std::boxed::Box<dyn std::any::Any + std::marker::Send>),
     #[error("VMM thread exited with error: {0}")]
     VmmThread(#[source] vmm::Error),
     #[error("Error parsing --api-socket: {0}")]
     ParsingApiSocket(std::num::ParseIntError),
+    #[error("Error parsing --api-socket options: {0}")]
+    ParsingApiSocketOptions(option_parser::OptionParserError),
+    #[error("Error reading --api-socket token file: {0}")]
+    ReadingApiSocketToken(std::io::Error),
+    #[error("Error parsing --api-socket: invalid vsock address {0}")]
+    ParsingApiSocketVsock(String),
+    #[error("Error parsing --api-socket: uid and gid are only supported with UNIX sockets")]
+    ApiSocketPeerCredentials,
+    #[error("Error parsing --api-socket: token_file is required with TCP and VSOCK listeners")]
+    ApiSocketMissingToken,
+    #[error("Error creating --api-socket listener: {0}")]
+    CreateApiSocketListener(#[source] std::io::Error),
     #[error("Error parsing --event-monitor: {0}")]
     ParsingEventMonitor(option_parser::OptionParserError),
     #[error("Error parsing --event-monitor: path or fd required")]
     BareEventMonitor,
     #[error("Error doing event monitor I/O: {0}")]
     EventMonitorIo(std::io::Error),
+    #[cfg(feature = "dbus_api")]
+    #[error("Error parsing --dbus: {0}")]
+    ParsingDBus(option_parser::OptionParserError),
     #[cfg(feature = "guest_debug")]
     #[error("Error parsing --gdb: {0}")]
     ParsingGdb(option_parser::OptionParserError),
@@ -136,28 +158,37 @@ fn default_rng() -> String {
 #[derive(FromArgs)]
 /// Launch a cloud-hypervisor VMM.
 pub struct TopLevel {
+    #[argh(option, long = "config")]
+    /// path to a TOML, YAML or JSON file defining the VM, whose values are overridden by the other options
+    config: Option<String>,
+
     #[argh(option, long = "cpus", default = "default_vcpus()")]
-    /// boot=<boot_vcpus>,max=<max_vcpus>,topology=<threads_per_core>:<cores_per_die>:<dies_per_package>:<packages>,kvm_hyperv=on|off,max_phys_bits=<maximum_number_of_physical_bits>,affinity=<list_of_vcpus_with_their_associated_cpuset>,features=<list_of_features_to_enable>
+    /// boot=<boot_vcpus>,max=<max_vcpus>,topology=<threads_per_core>:<cores_per_die>:<dies_per_package>:<packages>[:<efficiency_cores_per_die>],kvm_hyperv=on|off,max_phys_bits=<maximum_number_of_physical_bits>,affinity=<list_of_vcpus_with_their_associated_cpuset>,features=<list_of_features_to_enable>,placement=auto-numa|compact|scatter,sched=fifo|rr:<priority>,model=<cpu_model>,microcode_revision=<microcode_revision>,apic_ids=<list_of_apic_ids>,sve_vl=<sve_vector_length>
     cpus: String,
 
     #[argh(option, long = "platform")]
-    /// num_pci_segments=<num_pci_segments>,iommu_segments=<list_of_segments>,serial_number=<dmi_device_serial_number>,uuid=<dmi_device_uuid>,oem_strings=<list_of_strings>
+    /// num_pci_segments=<num_pci_segments>,iommu_segments=<list_of_segments>,serial_number=<dmi_device_serial_number>,uuid=<dmi_device_uuid>,oem_strings=<list_of_strings>,ps2=on|off,reserved_regions=<list_of_start:size>,smbios_file=<smbios_table_file>,mptable=on|off
     platform: Option<String>,
 
     #[argh(option, long = "memory", default = "default_memory()")]
-    /// size=<guest_memory_size>,mergeable=on|off,shared=on|off,hugepages=on|off,hugepage_size=<hugepage_size>,hotplug_method=acpi|virtio-mem,hotplug_size=<hotpluggable_memory_size>,hotplugged_size=<hotplugged_memory_size>,prefault=on|off,thp=on|off
+    /// size=<guest_memory_size>,mergeable=on|off,shared=on|off,hugepages=on|off,hugepage_size=<hugepage_size>,hotplug_method=acpi|virtio-mem,hotplug_size=<hotpluggable_memory_size>,hotplugged_size=<hotplugged_memory_size>,prefault=on|off,thp=on|off,mmio32_hole_size=<mmio32_hole_size>
     memory: String,
 
     #[argh(option, long = "memory-zone")]
-    /// size=<guest_memory_region_size>,file=<backing_file>,shared=on|off,hugepages=on|off,hugepage_size=<hugepage_size>,host_numa_node=<node_id>,id=<zone_identifier>,hotplug_size=<hotpluggable_memory_size>,hotplugged_size=<hotplugged_memory_size>,prefault=on|off
+    /// size=<guest_memory_region_size>,file=<backing_file>,shared=on|off,hugepages=on|off,hugepage_size=<hugepage_size>,host_numa_node=<node_id>,id=<zone_identifier>,hotplug_size=<hotpluggable_memory_size>,hotplugged_size=<hotplugged_memory_size>,prefault=on|off,prefault_threads=<prefault_threads>,memmap_type=ram|acpi_reclaim|acpi_nvs|pmem|soft_reserved
     memory_zone: Vec<String>,
 
     #[argh(option, long = "firmware")]
     /// path to firmware that is loaded in an architectural specific way
     firmware: Option<String>,
 
+    #[cfg(target_arch = "aarch64")]
+    #[argh(option, long = "firmware-vars")]
+    /// path to the file backing the persistent UEFI variable store
+    firmware_vars: Option<String>,
+
     #[argh(option, long = "kernel")]
-    /// path to kernel or firmware that supports a PVH entry point or architecture equivalent
+    /// path to kernel or firmware that supports a PVH entry point or architecture equivalent, or "builtin-fw" to use the embedded firmware
     kernel: Option<String>,
 
     #[argh(option, long = "initramfs")]
@@ -169,11 +200,11 @@ pub struct TopLevel {
     cmdline: Option<String>,
 
     #[argh(option, long = "disk")]
-    /// path=<disk_image_path>,readonly=on|off,direct=on|off,iommu=on|off,num_queues=<number_of_queues>,queue_size=<size_of_each_queue>,vhost_user=on|off,socket=<vhost_user_socket_path>,bw_size=<bytes>,bw_one_time_burst=<bytes>,bw_refill_time=<ms>,ops_size=<io_ops>,ops_one_time_burst=<io_ops>,ops_refill_time=<ms>,id=<device_id>,pci_segment=<segment_id>
+    /// path=<disk_image_path>,readonly=on|off,direct=on|off,iommu=on|off,num_queues=<number_of_queues>,queue_size=<size_of_each_queue>,vhost_user=on|off,socket=<vhost_user_socket_path>,bw_size=<bytes>,bw_one_time_burst=<bytes>,bw_refill_time=<ms>,ops_size=<io_ops>,ops_one_time_burst=<io_ops>,ops_refill_time=<ms>,id=<device_id>,alias=<device_alias>,pci_segment=<segment_id>,iothreads=<list_of_iothreads>,rate_limit_group=<group_id>
     disk: Vec<String>,
 
     #[argh(option, long = "net")]
-    /// tap=<if_name>,ip=<ip_addr>,mask=<net_mask>,mac=<mac_addr>,fd=<fd1,fd2...>,iommu=on|off,num_queues=<number_of_queues>,queue_size=<size_of_each_queue>,id=<device_id>,vhost_user=<vhost_user_enable>,socket=<vhost_user_socket_path>,vhost_mode=client|server,bw_size=<bytes>,bw_one_time_burst=<bytes>,bw_refill_time=<ms>,ops_size=<io_ops>,ops_one_time_burst=<io_ops>,ops_refill_time=<ms>,pci_segment=<segment_id>offload_tso=on|off,offload_ufo=on|off,offload_csum=on|off
+    /// tap=<if_name>,ip=<ip_addr>,mask=<net_mask>,mac=<mac_addr>,fd=<fd1,fd2...>,iommu=on|off,num_queues=<number_of_queues>,queue_size=<size_of_each_queue>,id=<device_id>,alias=<device_alias>,vhost_user=<vhost_user_enable>,socket=<vhost_user_socket_path>,vhost_mode=client|server,bw_size=<bytes>,bw_one_time_burst=<bytes>,bw_refill_time=<ms>,ops_size=<io_ops>,ops_one_time_burst=<io_ops>,ops_refill_time=<ms>,pci_segment=<segment_id>offload_tso=on|off,offload_ufo=on|off,offload_uso=on|off,offload_csum=on|off,iothreads=<list_of_iothreads>,rate_limit_group=<group_id>,rss=on|off,vhost=on|off
     net: Vec<String>,
 
     #[argh(option, long = "rng", default = "default_rng()")]
@@ -181,15 +212,15 @@ pub struct TopLevel {
     rng: String,
 
     #[argh(option, long = "balloon")]
-    /// size=<balloon_size>,deflate_on_oom=on|off,free_page_reporting=on|off
+    /// size=<balloon_size>,deflate_on_oom=on|off,free_page_reporting=on|off,auto_size=on|off,min_size=<balloon_min_size>,max_size=<balloon_max_size>
     balloon: Option<String>,
 
     #[argh(option, long = "fs")]
-    /// tag=<tag_name>,socket=<socket_path>,num_queues=<number_of_queues>,queue_size=<size_of_each_queue>,id=<device_id>,pci_segment=<segment_id>
+    /// tag=<tag_name>,socket=<socket_path>,num_queues=<number_of_queues>,queue_size=<size_of_each_queue>,dax=on|off,cache_size=<DAX_window_size>,id=<device_id>,alias=<device_alias>,pci_segment=<segment_id>
     fs: Vec<String>,
 
     #[argh(option, long = "pmem")]
-    /// file=<backing_file_path>,size=<persistent_memory_size>,iommu=on|off,discard_writes=on|off,id=<device_id>,pci_segment=<segment_id>
+    /// file=<backing_file_path>,size=<persistent_memory_size>,iommu=on|off,discard_writes=on|off,id=<device_id>,alias=<device_alias>,pci_segment=<segment_id>
     pmem: Vec<String>,
 
     #[argh(option, long = "serial", default = "String::from(\"null\")")]
@@ -201,29 +232,33 @@ pub struct TopLevel {
     console: String,
 
     #[argh(option, long = "device")]
-    /// path=<device_path>,iommu=on|off,id=<device_id>,pci_segment=<segment_id>
+    /// path=<device_path>,iommu=on|off,id=<device_id>,alias=<device_alias>,pci_segment=<segment_id>
     device: Vec<String>,
 
     #[argh(option, long = "user-device")]
-    /// socket=<socket_path>,id=<device_id>,pci_segment=<segment_id>
+    /// socket=<socket_path>,id=<device_id>,alias=<device_alias>,pci_segment=<segment_id>
     user_device: Vec<String>,
 
     #[argh(option, long = "vdpa")]
-    /// path=<device_path>,num_queues=<number_of_queues>,iommu=on|off,id=<device_id>,pci_segment=<segment_id>
+    /// path=<device_path>,num_queues=<number_of_queues>,iommu=on|off,id=<device_id>,alias=<device_alias>,pci_segment=<segment_id>
     vdpa: Vec<String>,
 
     #[argh(option, long = "vsock")]
-    /// cid=<context_id>,socket=<socket_path>,iommu=on|off,id=<device_id>,pci_segment=<segment_id>
+    /// cid=<context_id>,socket=<socket_path>,iommu=on|off,id=<device_id>,alias=<device_alias>,pci_segment=<segment_id>
     vsock: Option<String>,
 
     #[argh(option, long = "numa")]
-    /// guest_numa_id=<node_id>,cpus=<cpus_id>,distances=<list_of_distances_to_destination_nodes>,memory_zones=<list_of_memory_zones>,sgx_epc_sections=<list_of_sgx_epc_sections>
+    /// guest_numa_id=<node_id>,cpus=<cpus_id>,distances=<list_of_distances_to_destination_nodes>,memory_zones=<list_of_memory_zones>,sgx_epc_sections=<list_of_sgx_epc_sections>,memory_latency=<latency_in_ns>,memory_bandwidth=<bandwidth_in_mb_per_s>
     numa: Vec<String>,
 
     #[argh(switch, long = "watchdog")]
     /// enable virtio-watchdog
     watchdog: bool,
 
+    #[argh(option, long = "watchdog-action")]
+    /// action on watchdog expiry: reset|poweroff|pause|none
+    watchdog_action: Option<String>,
+
     #[argh(switch, short = 'v')]
     /// set the level of debugging output
     verbosity: u8,
@@ -233,9 +268,14 @@ pub struct TopLevel {
     log_file: Option<String>,
 
     #[argh(option, long = "api-socket")]
-    /// path=<path/to/a/file>|fd=<fd>
+    /// path=<path/to/a/file>|fd=<fd>|tcp=<addr:port>|vsock=<cid:port>,uid=<list_of_allowed_uids>,gid=<list_of_allowed_gids>,token_file=<path/to/token/file>,read_only=on|off
     api_socket: Option<String>,
 
+    #[cfg(feature = "dbus_api")]
+    #[argh(option, long = "dbus")]
+    /// service_name=<dbus_service_name>,object_path=<dbus_object_path>,system_bus=on|off
+    dbus: Option<String>,
+
     #[argh(option, long = "event-monitor")]
     /// path=<path/to/a/file>|fd=<fd>
     event_monitor: Option<String>,
@@ -244,6 +284,58 @@ pub struct TopLevel {
     /// source_url=<source_url>,prefault=on|off
     restore: Option<String>,
 
+    #[argh(option, long = "hibernation")]
+    /// path=<path/to/a/directory>
+    hibernation: Option<String>,
+
+    #[argh(option, long = "cloud-init")]
+    /// user-data=<path/to/user-data>,meta-data=<path/to/meta-data>,network-config=<path/to/network-config>
+    cloud_init: Option<String>,
+
+    #[argh(option, long = "ignition")]
+    /// path=<path/to/ignition/config>
+    ignition: Option<String>,
+
+    #[argh(option, long = "fw-cfg")]
+    /// payload=on|off,files=[<name>:<path>,...]
+    fw_cfg: Option<String>,
+
+    #[argh(option, long = "pvpanic")]
+    /// on_panic=pause|shutdown|coredump,coredump_path=<path>
+    pvpanic: Option<String>,
+
+    #[argh(option, long = "gpu")]
+    /// socket=<vnc_socket_path>,width=<display_width>,height=<display_height>,iommu=on|off
+    gpu: Option<String>,
+
+    #[argh(option, long = "sound")]
+    /// backend=pipe|alsa,playback=<path/to/playback/fifo|alsa_device>,capture=<path/to/capture/fifo|alsa_device>,iommu=on|off
+    sound: Option<String>,
+
+    #[argh(option, long = "input")]
+    /// kind=keyboard|mouse|tablet,evdev=<path/to/evdev/node>,iommu=on|off,id=<device_id>,pci_segment=<segment_id>
+    input: Vec<String>,
+
+    #[argh(option, long = "channel")]
+    /// name=<port_name>,socket=<path/to/socket>,pty=on|off
+    channel: Vec<String>,
+
+    #[argh(option, long = "scsi")]
+    /// iommu=on|off,id=<device_id>,alias=<device_alias>,pci_segment=<segment_id>
+    scsi: Vec<String>,
+
+    #[argh(option, long = "scsi-lun")]
+    /// controller=<device_id>,lun=<lun>,path=<path/to/image/or/device>,readonly=on|off,cdrom=on|off,passthrough=on|off,serial=<serial_number>
+    scsi_lun: Vec<String>,
+
+    #[argh(option, long = "crypto")]
+    /// backend=af-alg|software,num_queues=<number_of_data_queues>,iommu=on|off
+    crypto: Option<String>,
+
+    #[argh(option, long = "resume-from")]
+    /// path to the directory of a VM whose guest hibernated
+    resume_from: Option<String>,
+
     #[argh(option, long = "seccomp", default = "String::from(\"true\")")]
     /// seccomp configuration (true, false or log)
     seccomp: String,
@@ -252,6 +344,14 @@ pub struct TopLevel {
     /// socket=<path/to/a/socket>
     tpm: Option<String>,
 
+    #[argh(option, long = "iothread")]
+    /// id=<iothread_identifier>,host_cpus=<list_of_host_cpus>
+    iothread: Vec<String>,
+
+    #[argh(option, long = "rate-limit-group")]
+    /// id=<group_id>,bw_size=<bytes>,bw_one_time_burst=<bytes>,bw_refill_time=<ms>,ops_size=<io_ops>,ops_one_time_burst=<io_ops>,ops_refill_time=<ms>
+    rate_limit_group: Vec<String>,
+
     #[cfg(target_arch = "x86_64")]
     #[argh(option, long = "sgx-epc")]
     /// id=<epc_section_identifier>,size=<epc_section_size>,prefault=on|off
@@ -279,6 +379,8 @@ impl TopLevel {
         let rng = &self.rng;
         let serial = &self.serial;
         let firmware = self.firmware.as_deref();
+        #[cfg(target_arch = "aarch64")]
+        let firmware_vars = self.firmware_vars.as_deref();
         let kernel = self.kernel.as_deref();
         let initramfs = self.initramfs.as_deref();
         let cmdline = self.cmdline.as_deref();
@@ -338,16 +440,58 @@ impl TopLevel {
             None
         };
         let watchdog = self.watchdog;
+        let watchdog_action = self.watchdog_action.as_deref();
         let platform = self.platform.as_deref();
         #[cfg(feature = "guest_debug")]
         let gdb = self.gdb.is_some();
         let tpm = self.tpm.as_deref();
+        let iothreads = if !self.iothread.is_empty() {
+            Some(self.iothread.iter().map(|x| x.as_str()).collect())
+        } else {
+            None
+        };
+        let rate_limit_groups = if !self.rate_limit_group.is_empty() {
+            Some(self.rate_limit_group.iter().map(|x| x.as_str()).collect())
+        } else {
+            None
+        };
+
+        let hibernation = self.hibernation.as_deref();
+        let cloud_init = self.cloud_init.as_deref();
+        let ignition = self.ignition.as_deref();
+        let fw_cfg = self.fw_cfg.as_deref();
+        let pvpanic = self.pvpanic.as_deref();
+        let gpu = self.gpu.as_deref();
+        let sound = self.sound.as_deref();
+        let input = if !self.input.is_empty() {
+            Some(self.input.iter().map(|x| x.as_str()).collect())
+        } else {
+            None
+        };
+        let channels = if !self.channel.is_empty() {
+            Some(self.channel.iter().map(|x| x.as_str()).collect())
+        } else {
+            None
+        };
+        let scsi = if !self.scsi.is_empty() {
+            Some(self.scsi.iter().map(|x| x.as_str()).collect())
+        } else {
+            None
+        };
+        let scsi_luns = if !self.scsi_lun.is_empty() {
+            Some(self.scsi_lun.iter().map(|x| x.as_str()).collect())
+        } else {
+            None
+        };
+        let crypto = self.crypto.as_deref();
 
         config::VmParams {
             cpus,
             memory,
             memory_zones,
             firmware,
+            #[cfg(target_arch = "aarch64")]
+            firmware_vars,
             kernel,
             initramfs,
             cmdline,
@@ -367,12 +511,102 @@ impl TopLevel {
             sgx_epc,
             numa,
             watchdog,
+            watchdog_action,
             #[cfg(feature = "guest_debug")]
             gdb,
             platform,
             tpm,
+            iothreads,
+            rate_limit_groups,
+            hibernation,
+            cloud_init,
+            ignition,
+            fw_cfg,
+            pvpanic,
+            gpu,
+            sound,
+            input,
+            channels,
+            scsi,
+            scsi_luns,
+            crypto,
+        }
+    }
+
+    fn to_vm_config(&self) -> Result<config::VmConfig, Error> {
+        let vm_config =
+            config::VmConfig::parse(self.to_vm_params()).map_err(Error::ParsingConfig)?;
+
+        if let Some(path) = self.config.as_deref() {
+            let path = Path::new(path);
+            let content = std::fs::read_to_string(path).map_err(Error::ReadingConfigFile)?;
+            let file_config =
+                parse_config_file(&content, path.extension().and_then(|e| e.to_str()))?;
+            override_config_file(file_config, vm_config)
+        } else {
+            Ok(vm_config)
+        }
+    }
+}
+
+// Sections of the VM configuration filled from several options, which are
+// overridden field by field rather than as a whole.
+const MERGED_CONFIG_SECTIONS: [&str; 1] = ["payload"];
+
+fn parse_config_file(content: &str, extension: Option<&str>) -> Result<serde_json::Value, Error> {
+    match extension {
+        Some("toml") => toml::from_str(content).map_err(|e| Error::ParsingConfigFile(e.into())),
+        Some("yaml") | Some("yml") => {
+            serde_yaml::from_str(content).map_err(|e| Error::ParsingConfigFile(e.into()))
+        }
+        _ => serde_json::from_str(content).map_err(|e| Error::ParsingConfigFile(e.into())),
+    }
+}
+
+// Apply the sections of the VM configuration set from the command line
+// on top of the ones defined in the config file. The sections left to their
+// default value are considered unset.
+fn override_config_file(
+    mut file_config: serde_json::Value,
+    vm_config: config::VmConfig,
+) -> Result<config::VmConfig, Error> {
+    // Parsing no argument can't fail
+    let default_toplevel =
+        <TopLevel as FromArgs>::from_args(&[env!("CARGO_BIN_NAME")], &[]).unwrap();
+    let default_config =
+        config::VmConfig::parse(default_toplevel.to_vm_params()).map_err(Error::ParsingConfig)?;
+
+    let to_value = |c| serde_json::to_value(c).map_err(|e| Error::ParsingConfigFile(e.into()));
+    let default_config = to_value(default_config)?;
+    let file_sections = file_config.as_object_mut().ok_or_else(|| {
+        Error::ParsingConfigFile(anyhow::anyhow!("The VM definition must be a map"))
+    })?;
+
+    if let serde_json::Value::Object(sections) = to_value(vm_config)? {
+        for (key, value) in sections {
+            if default_config.get(&key) == Some(&value) {
+                continue;
+            }
+
+            match (file_sections.get_mut(&key), value) {
+                (
+                    Some(serde_json::Value::Object(file_fields)),
+                    serde_json::Value::Object(fields),
+                ) if MERGED_CONFIG_SECTIONS.contains(&key.as_str()) => {
+                    for (field, value) in fields {
+                        if !value.is_null() {
+                            file_fields.insert(field, value);
+                        }
+                    }
+                }
+                (_, value) => {
+                    file_sections.insert(key, value);
+                }
+            }
         }
     }
+
+    serde_json::from_value(file_config).map_err(|e| Error::ParsingConfigFile(e.into()))
 }
 
 fn start_vmm(toplevel: TopLevel) -> Result<Option<String>, Error> {
@@ -396,16 +630,82 @@ fn start_vmm(toplevel: TopLevel) -> Result<Option<String>, Error> {
     .map(|()| log::set_max_level(log_level))
     .map_err(Error::LoggerSetup)?;
 
+    let mut api_socket_access = vmm::api::HttpAccessConfig::default();
     let (api_socket_path, api_socket_fd) = if let Some(ref socket_config) = toplevel.api_socket {
         let mut parser = OptionParser::new();
-        parser.add("path").add("fd");
-        parser.parse(socket_config).unwrap_or_default();
+        parser
+            .add("path")
+            .add("fd")
+            .add("tcp")
+            .add("vsock")
+            .add("uid")
+            .add("gid")
+            .add("token_file")
+            .add("read_only");
+        if let Err(e) = parser.parse(socket_config) {
+            // A bare path is still accepted, but access control options
+            // must not be silently ignored.
+            if socket_config.contains('=') {
+                return Err(Error::ParsingApiSocketOptions(e));
+            }
+        }
+
+        let to_ids = |list: Option<IntegerList>| {
+            list.map(|l| l.0.iter().map(|id| *id as u32).collect())
+                .unwrap_or_default()
+        };
+        api_socket_access.allowed_uids = to_ids(
+            parser
+                .convert::<IntegerList>("uid")
+                .map_err(Error::ParsingApiSocketOptions)?,
+        );
+        api_socket_access.allowed_gids = to_ids(
+            parser
+                .convert::<IntegerList>("gid")
+                .map_err(Error::ParsingApiSocketOptions)?,
+        );
+        if let Some(token_file) = parser.get("token_file") {
+            let token =
+                std::fs::read_to_string(token_file).map_err(Error::ReadingApiSocketToken)?;
+            api_socket_access.token = Some(token.trim().to_string());
+        }
+        api_socket_access.read_only = parser
+            .convert::<Toggle>("read_only")
+            .map_err(Error::ParsingApiSocketOptions)?
+            .unwrap_or(Toggle(false))
+            .0;
+
+        // Peer credentials are only available from UNIX sockets
+        let remote = parser.is_set("tcp") || parser.is_set("vsock");
+        if remote
+            && !(api_socket_access.allowed_uids.is_empty()
+                && api_socket_access.allowed_gids.is_empty())
+        {
+            return Err(Error::ApiSocketPeerCredentials);
+        }
+        // Remote listeners are reachable beyond the host, and the traffic is
+        // not encrypted, so at least require the requests to be authenticated.
+        if remote && api_socket_access.token.is_none() {
+            return Err(Error::ApiSocketMissingToken);
+        }
 
         if let Some(fd) = parser.get("fd") {
             (
                 None,
                 Some(fd.parse::<RawFd>().map_err(Error::ParsingApiSocket)?),
             )
+        } else if let Some(addr) = parser.get("tcp") {
+            let listener =
+                std::net::TcpListener::bind(addr).map_err(Error::CreateApiSocketListener)?;
+            (None, Some(listener.into_raw_fd()))
+        } else if let Some(addr) = parser.get("vsock") {
+            let (cid, port) = addr
+                .split_once(':')
+                .and_then(|(cid, port)| Some((cid.parse().ok()?, port.parse().ok()?)))
+                .ok_or_else(|| Error::ParsingApiSocketVsock(addr.clone()))?;
+            let fd = vmm::api::http::vsock_listener(cid, port)
+                .map_err(Error::CreateApiSocketListener)?;
+            (None, Some(fd))
         } else if let Some(path) = parser.get("path") {
             (Some(path), None)
         } else {
@@ -415,6 +715,32 @@ fn start_vmm(toplevel: TopLevel) -> Result<Option<String>, Error> {
         (None, None)
     };
 
+    #[cfg(feature = "dbus_api")]
+    let dbus_options = if let Some(ref dbus_config) = toplevel.dbus {
+        let mut parser = OptionParser::new();
+        parser
+            .add("service_name")
+            .add("object_path")
+            .add("system_bus");
+        parser.parse(dbus_config).map_err(Error::ParsingDBus)?;
+
+        Some(vmm::api::DBusApiOptions {
+            service_name: parser
+                .get("service_name")
+                .unwrap_or_else(|| "org.cloudhypervisor.DBusApi".to_string()),
+            object_path: parser
+                .get("object_path")
+                .unwrap_or_else(|| "/org/cloudhypervisor/DBusApi".to_string()),
+            system_bus: parser
+                .convert::<Toggle>("system_bus")
+                .map_err(Error::ParsingDBus)?
+                .unwrap_or(Toggle(false))
+                .0,
+        })
+    } else {
+        None
+    };
+
     if let Some(ref monitor_config) = toplevel.event_monitor {
         let mut parser = OptionParser::new();
         parser.add("path").add("fd");
@@ -514,6 +840,9 @@ fn start_vmm(toplevel: TopLevel) -> Result<Option<String>, Error> {
         env!("CARGO_PKG_VERSION").to_string(),
         &api_socket_path,
         api_socket_fd,
+        api_socket_access,
+        #[cfg(feature = "dbus_api")]
+        dbus_options,
         api_evt.try_clone().unwrap(),
         http_sender,
         api_request_receiver,
@@ -528,11 +857,25 @@ fn start_vmm(toplevel: TopLevel) -> Result<Option<String>, Error> {
     )
     .map_err(Error::StartVmmThread)?;
 
-    let payload_present = toplevel.kernel.is_some() || toplevel.firmware.is_some();
+    let payload_present =
+        toplevel.kernel.is_some() || toplevel.firmware.is_some() || toplevel.config.is_some();
 
-    if payload_present {
-        let vm_params = toplevel.to_vm_params();
-        let vm_config = config::VmConfig::parse(vm_params).map_err(Error::ParsingConfig)?;
+    if let Some(resume_from) = toplevel.resume_from.as_deref() {
+        let vm_config =
+            vmm::hibernation::load_config(Path::new(resume_from)).map_err(Error::ResumeFrom)?;
+
+        // Boot the same VM the guest hibernated from, so that it wakes up
+        // from its own hibernation image.
+        let sender = api_request_sender.clone();
+        vmm::api::vm_create(
+            api_evt.try_clone().unwrap(),
+            api_request_sender,
+            Arc::new(Mutex::new(vm_config)),
+        )
+        .map_err(Error::VmCreate)?;
+        vmm::api::vm_boot(api_evt.try_clone().unwrap(), sender).map_err(Error::VmBoot)?;
+    } else if payload_present {
+        let vm_config = toplevel.to_vm_config()?;
 
         // Create and boot the VM based off the VM config we just built.
         let sender = api_request_sender.clone();
@@ -603,11 +946,11 @@ fn main() {
 #[cfg(test)]
 mod unit_tests {
     use crate::config::HotplugMethod;
-    use crate::TopLevel;
+    use crate::{override_config_file, parse_config_file, TopLevel};
     use std::path::PathBuf;
     use vmm::config::{
         ConsoleConfig, ConsoleOutputMode, CpuFeatures, CpusConfig, MemoryConfig, PayloadConfig,
-        RngConfig, VmConfig,
+        RngConfig, VmConfig, WatchdogAction,
     };
 
     // Taken from argh
@@ -681,6 +1024,16 @@ mod unit_tests {
                 max_phys_bits: 46,
                 affinity: None,
                 features: CpuFeatures::default(),
+                placement: None,
+                sched: None,
+                #[cfg(target_arch = "x86_64")]
+                model: None,
+                #[cfg(target_arch = "x86_64")]
+                microcode_revision: None,
+                #[cfg(target_arch = "x86_64")]
+                apic_ids: None,
+                #[cfg(target_arch = "aarch64")]
+                sve_vl: None,
             },
             memory: MemoryConfig {
                 size: 536_870_912,
@@ -694,6 +1047,8 @@ mod unit_tests {
                 prefault: false,
                 zones: None,
                 thp: true,
+                #[cfg(target_arch = "x86_64")]
+                mmio32_hole_size: None,
             },
             payload: Some(PayloadConfig {
                 kernel: Some(PathBuf::from("/path/to/kernel")),
@@ -727,10 +1082,24 @@ mod unit_tests {
             sgx_epc: None,
             numa: None,
             watchdog: false,
+            watchdog_action: WatchdogAction::Reset,
             #[cfg(feature = "guest_debug")]
             gdb: false,
             platform: None,
             tpm: None,
+            iothreads: None,
+            rate_limit_groups: None,
+            hibernation: None,
+            cloud_init: None,
+            ignition: None,
+            fw_cfg: None,
+            pvpanic: None,
+            gpu: None,
+            sound: None,
+            input: None,
+            channels: None,
+            scsi: None,
+            crypto: None,
         };
 
         assert_eq!(expected_vm_config, result_vm_config);
@@ -1681,4 +2050,639 @@ mod unit_tests {
             compare_vm_config_cli_vs_json(cli, openapi, *equal);
         });
     }
+
+    #[test]
+    fn test_valid_vm_config_iothreads() {
+        vec![(
+            vec![
+                "cloud-hypervisor",
+                "--kernel",
+                "/path/to/kernel",
+                "--iothread",
+                "id=io0,host_cpus=[2-3]",
+                "--disk",
+                "path=/path/to/disk,iothreads=[io0]",
+            ],
+            r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "iothreads": [{"id": "io0", "host_cpus": [2, 3]}],
+                    "disks": [{"path": "/path/to/disk", "iothreads": ["io0"]}]
+                }"#,
+            true,
+        )]
+        .iter()
+        .for_each(|(cli, openapi, equal)| {
+            compare_vm_config_cli_vs_json(cli, openapi, *equal);
+        });
+    }
+
+    #[test]
+    fn test_valid_vm_config_rate_limit_groups() {
+        vec![(
+            vec![
+                "cloud-hypervisor",
+                "--kernel",
+                "/path/to/kernel",
+                "--rate-limit-group",
+                "id=group0,ops_size=1000,ops_refill_time=100",
+                "--disk",
+                "path=/path/to/disk,rate_limit_group=group0",
+            ],
+            r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "rate_limit_groups": [{"id": "group0", "rate_limiter_config": {"ops": {"size": 1000, "one_time_burst": 0, "refill_time": 100}}}],
+                    "disks": [{"path": "/path/to/disk", "rate_limit_group": "group0"}]
+                }"#,
+            true,
+        )]
+        .iter()
+        .for_each(|(cli, openapi, equal)| {
+            compare_vm_config_cli_vs_json(cli, openapi, *equal);
+        });
+    }
+
+    #[test]
+    fn test_valid_vm_config_hibernation() {
+        vec![(
+            vec![
+                "cloud-hypervisor",
+                "--kernel",
+                "/path/to/kernel",
+                "--hibernation",
+                "path=/path/to/hibernation",
+            ],
+            r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "hibernation": {"path": "/path/to/hibernation"}
+                }"#,
+            true,
+        )]
+        .iter()
+        .for_each(|(cli, openapi, equal)| {
+            compare_vm_config_cli_vs_json(cli, openapi, *equal);
+        });
+    }
+
+    #[test]
+    fn test_valid_vm_config_cloud_init() {
+        vec![
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--cloud-init",
+                    "user-data=/path/to/user-data",
+                ],
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "cloud_init": {"user_data": "/path/to/user-data"}
+                }"#,
+                true,
+            ),
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--cloud-init",
+                    "user-data=/path/to/user-data,meta-data=/path/to/meta-data",
+                ],
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "cloud_init": {"user_data": "/path/to/user-data", "meta_data": "/path/to/meta-data"}
+                }"#,
+                true,
+            ),
+        ]
+        .iter()
+        .for_each(|(cli, openapi, equal)| {
+            compare_vm_config_cli_vs_json(cli, openapi, *equal);
+        });
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_valid_vm_config_ignition() {
+        vec![(
+            vec![
+                "cloud-hypervisor",
+                "--kernel",
+                "/path/to/kernel",
+                "--ignition",
+                "path=/path/to/config.ign",
+            ],
+            r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "ignition": {"path": "/path/to/config.ign"}
+                }"#,
+            true,
+        )]
+        .iter()
+        .for_each(|(cli, openapi, equal)| {
+            compare_vm_config_cli_vs_json(cli, openapi, *equal);
+        });
+    }
+
+    #[test]
+    fn test_valid_vm_config_fw_cfg() {
+        [
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--fw-cfg",
+                    "payload=off",
+                ],
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "fw_cfg": {"payload": false}
+                }"#,
+                true,
+            ),
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--fw-cfg",
+                    "files=[opt/org.test/blob:/path/to/blob]",
+                ],
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "fw_cfg": {"files": [{"name": "opt/org.test/blob", "path": "/path/to/blob"}]}
+                }"#,
+                true,
+            ),
+        ]
+        .iter()
+        .for_each(|(cli, openapi, equal)| {
+            compare_vm_config_cli_vs_json(cli, openapi, *equal);
+        });
+    }
+
+    #[test]
+    fn test_valid_vm_config_watchdog_action() {
+        [
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--watchdog",
+                ],
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "watchdog": true,
+                    "watchdog_action": "Reset"
+                }"#,
+                true,
+            ),
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--watchdog",
+                    "--watchdog-action",
+                    "pause",
+                ],
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "watchdog": true,
+                    "watchdog_action": "Pause"
+                }"#,
+                true,
+            ),
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--watchdog",
+                    "--watchdog-action",
+                    "none",
+                ],
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "watchdog": true
+                }"#,
+                false,
+            ),
+        ]
+        .iter()
+        .for_each(|(cli, openapi, equal)| {
+            compare_vm_config_cli_vs_json(cli, openapi, *equal);
+        });
+    }
+
+    #[test]
+    fn test_valid_vm_config_gpu() {
+        [
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--gpu",
+                    "socket=/tmp/vnc.sock",
+                ],
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "gpu": {"socket": "/tmp/vnc.sock"}
+                }"#,
+                true,
+            ),
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--gpu",
+                    "socket=/tmp/vnc.sock,width=1280,height=800",
+                ],
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "gpu": {"socket": "/tmp/vnc.sock", "width": 1280, "height": 800}
+                }"#,
+                true,
+            ),
+        ]
+        .iter()
+        .for_each(|(cli, openapi, equal)| {
+            compare_vm_config_cli_vs_json(cli, openapi, *equal);
+        });
+    }
+
+    #[test]
+    fn test_valid_vm_config_sound() {
+        [
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--sound",
+                    "",
+                ],
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "sound": {}
+                }"#,
+                true,
+            ),
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--sound",
+                    "playback=/tmp/playback,capture=/tmp/capture",
+                ],
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "sound": {"playback": "/tmp/playback", "capture": "/tmp/capture"}
+                }"#,
+                true,
+            ),
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--sound",
+                    "backend=alsa,playback=plughw:0",
+                ],
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "sound": {"backend": "Alsa", "playback": "plughw:0"}
+                }"#,
+                true,
+            ),
+        ]
+        .iter()
+        .for_each(|(cli, openapi, equal)| {
+            compare_vm_config_cli_vs_json(cli, openapi, *equal);
+        });
+    }
+
+    #[test]
+    fn test_valid_vm_config_input() {
+        [
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--gpu",
+                    "socket=/tmp/vnc.sock",
+                    "--input",
+                    "kind=keyboard",
+                    "--input",
+                    "kind=tablet",
+                ],
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "gpu": {"socket": "/tmp/vnc.sock"},
+                    "input": [
+                        {"kind": "Keyboard"},
+                        {"kind": "Tablet"}
+                    ]
+                }"#,
+                true,
+            ),
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--input",
+                    "evdev=/dev/input/event0,id=input0",
+                ],
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "input": [
+                        {"evdev": "/dev/input/event0", "id": "input0"}
+                    ]
+                }"#,
+                true,
+            ),
+        ]
+        .iter()
+        .for_each(|(cli, openapi, equal)| {
+            compare_vm_config_cli_vs_json(cli, openapi, *equal);
+        });
+    }
+
+    #[test]
+    fn test_valid_vm_config_channels() {
+        [
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--channel",
+                    "name=org.example.agent,socket=/tmp/agent.sock",
+                    "--channel",
+                    "name=metrics,pty=on",
+                ],
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "channels": [
+                        {"name": "org.example.agent", "socket": "/tmp/agent.sock"},
+                        {"name": "metrics", "pty": true}
+                    ]
+                }"#,
+                true,
+            ),
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--channel",
+                    "name=metrics,pty=on",
+                ],
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "channels": [
+                        {"name": "metrics", "socket": "/tmp/metrics.sock"}
+                    ]
+                }"#,
+                false,
+            ),
+        ]
+        .iter()
+        .for_each(|(cli, openapi, equal)| {
+            compare_vm_config_cli_vs_json(cli, openapi, *equal);
+        });
+    }
+
+    #[test]
+    fn test_valid_vm_config_scsi() {
+        [
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--scsi",
+                    "id=scsi0",
+                    "--scsi-lun",
+                    "controller=scsi0,lun=0,path=/path/to/disk.img",
+                    "--scsi-lun",
+                    "controller=scsi0,lun=1,cdrom=on",
+                ],
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "scsi": [
+                        {
+                            "id": "scsi0",
+                            "luns": [
+                                {"lun": 0, "path": "/path/to/disk.img"},
+                                {"lun": 1, "cdrom": true}
+                            ]
+                        }
+                    ]
+                }"#,
+                true,
+            ),
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--scsi",
+                    "id=scsi0",
+                    "--scsi-lun",
+                    "controller=scsi0,lun=0,path=/path/to/disk.img",
+                ],
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "scsi": [
+                        {
+                            "id": "scsi0",
+                            "luns": [
+                                {"lun": 0, "path": "/path/to/disk.img", "readonly": true}
+                            ]
+                        }
+                    ]
+                }"#,
+                false,
+            ),
+        ]
+        .iter()
+        .for_each(|(cli, openapi, equal)| {
+            compare_vm_config_cli_vs_json(cli, openapi, *equal);
+        });
+    }
+
+    #[test]
+    fn test_valid_vm_config_crypto() {
+        [
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--crypto",
+                    "",
+                ],
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "crypto": {}
+                }"#,
+                true,
+            ),
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--crypto",
+                    "backend=af-alg,num_queues=2",
+                ],
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "crypto": {"backend": "AfAlg", "num_queues": 2}
+                }"#,
+                true,
+            ),
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--crypto",
+                    "num_queues=2",
+                ],
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "crypto": {"backend": "AfAlg", "num_queues": 2}
+                }"#,
+                false,
+            ),
+        ]
+        .iter()
+        .for_each(|(cli, openapi, equal)| {
+            compare_vm_config_cli_vs_json(cli, openapi, *equal);
+        });
+    }
+
+    #[test]
+    fn test_valid_vm_config_pvpanic() {
+        [
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--pvpanic",
+                    "",
+                ],
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "pvpanic": {}
+                }"#,
+                true,
+            ),
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--pvpanic",
+                    "on_panic=shutdown",
+                ],
+                r#"{
+                    "payload": {"kernel": "/path/to/kernel"},
+                    "pvpanic": {"on_panic": "Shutdown"}
+                }"#,
+                true,
+            ),
+        ]
+        .iter()
+        .for_each(|(cli, openapi, equal)| {
+            compare_vm_config_cli_vs_json(cli, openapi, *equal);
+        });
+    }
+
+    #[test]
+    fn test_valid_vm_config_file() {
+        let toml = r#"
+            [cpus]
+            boot_vcpus = 2
+            max_vcpus = 2
+
+            [memory]
+            size = 1073741824
+
+            [payload]
+            kernel = "/path/to/kernel"
+            cmdline = "console=hvc0"
+
+            [[disks]]
+            path = "/path/to/disk"
+        "#;
+        let yaml = r#"
+            cpus:
+              boot_vcpus: 2
+              max_vcpus: 2
+            memory:
+              size: 1073741824
+            payload:
+              kernel: /path/to/kernel
+              cmdline: console=hvc0
+            disks:
+              - path: /path/to/disk
+        "#;
+
+        for (content, extension) in [(toml, "toml"), (yaml, "yaml")] {
+            let file_config = parse_config_file(content, Some(extension)).unwrap();
+
+            // Values from the config file are used when no option is given
+            let vm_config = override_config_file(
+                file_config.clone(),
+                get_vm_config_from_vec(&["cloud-hypervisor"]),
+            )
+            .unwrap();
+            let expected_vm_config: VmConfig = serde_json::from_str(
+                r#"{
+                    "cpus": {"boot_vcpus": 2, "max_vcpus": 2},
+                    "memory": {"size": 1073741824},
+                    "payload": {"kernel": "/path/to/kernel", "cmdline": "console=hvc0"},
+                    "disks": [{"path": "/path/to/disk"}]
+                }"#,
+            )
+            .unwrap();
+            assert_eq!(vm_config, expected_vm_config);
+
+            // Options override the values from the config file
+            let vm_config = override_config_file(
+                file_config,
+                get_vm_config_from_vec(&[
+                    "cloud-hypervisor",
+                    "--cpus",
+                    "boot=4",
+                    "--cmdline",
+                    "console=ttyS0",
+                    "--disk",
+                    "path=/path/to/other/disk",
+                ]),
+            )
+            .unwrap();
+            let expected_vm_config: VmConfig = serde_json::from_str(
+                r#"{
+                    "cpus": {"boot_vcpus": 4, "max_vcpus": 4},
+                    "memory": {"size": 1073741824},
+                    "payload": {"kernel": "/path/to/kernel", "cmdline": "console=ttyS0"},
+                    "disks": [{"path": "/path/to/other/disk"}]
+                }"#,
+            )
+            .unwrap();
+            assert_eq!(vm_config, expected_vm_config);
+        }
+    }
 }
@@ -2063,6 +2063,48 @@ mod common_parallel {
         handle_child_output(r, &output);
     }
 
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    #[cfg(not(feature = "mshv"))]
+    fn test_large_vcpu_count() {
+        let jammy_image = JAMMY_IMAGE_NAME.to_string();
+        let jammy = UbuntuDiskConfig::new(jammy_image);
+        let guest = Guest::new(Box::new(jammy));
+
+        // APIC IDs above 254 require x2APIC entries in the MADT
+        let mut cmd = GuestCommand::new(&guest);
+        cmd.args(["--cpus", "boot=512"])
+            .args(["--memory", "size=4G"])
+            .args(["--kernel", direct_kernel_boot_path().to_str().unwrap()])
+            .args(["--cmdline", DIRECT_KERNEL_BOOT_CMDLINE])
+            .capture_output()
+            .default_disks()
+            .default_net();
+
+        let mut child = cmd.spawn().unwrap();
+
+        let r = std::panic::catch_unwind(|| {
+            guest.wait_vm_boot(Some(300)).unwrap();
+
+            assert_eq!(guest.get_cpu_count().unwrap_or_default(), 512);
+
+            assert_eq!(
+                guest
+                    .ssh_command(
+                        r#"sudo dmesg | grep "smp: Brought up" | sed "s/\[\ *[0-9.]*\] //""#
+                    )
+                    .unwrap()
+                    .trim(),
+                "smp: Brought up 1 node, 512 CPUs"
+            );
+        });
+
+        let _ = child.kill();
+        let output = child.wait_with_output().unwrap();
+
+        handle_child_output(r, &output);
+    }
+
     #[test]
     #[cfg(not(feature = "mshv"))]
     fn test_cpu_topology_421() {
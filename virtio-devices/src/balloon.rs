@@ -13,18 +13,22 @@
 // limitations under the License.
 
 use crate::{
-    seccomp_filters::Thread, thread_helper::spawn_virtio_thread, ActivateResult, EpollHelper,
-    EpollHelperError, EpollHelperHandler, GuestMemoryMmap, VirtioCommon, VirtioDevice,
+    seccomp_filters::Thread, thread_helper::spawn_virtio_thread, ActivateError, ActivateResult,
+    EpollHelper, EpollHelperError, EpollHelperHandler, GuestMemoryMmap, VirtioCommon, VirtioDevice,
     VirtioDeviceType, VirtioInterrupt, VirtioInterruptType, EPOLL_HELPER_EVENT_LAST,
     VIRTIO_F_VERSION_1,
 };
 use anyhow::anyhow;
 use seccompiler::SeccompAction;
+use std::collections::HashMap;
 use std::io::{self, Write};
 use std::mem::size_of;
+use std::num::Wrapping;
+use std::ops::Deref;
 use std::os::unix::io::AsRawFd;
 use std::result;
-use std::sync::{atomic::AtomicBool, Arc, Barrier};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Barrier, Mutex};
 use thiserror::Error;
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
@@ -39,8 +43,12 @@ use vm_migration::{
 use vmm_sys_util::eventfd::EventFd;
 
 const QUEUE_SIZE: u16 = 128;
+const STATS_QUEUE_SIZE: u16 = 1;
 const REPORTING_QUEUE_SIZE: u16 = 32;
 const MIN_NUM_QUEUES: usize = 2;
+// The statistics queue, when there is one, comes right after the inflate and
+// deflate queues.
+const STATS_QUEUE_INDEX: usize = 2;
 
 // Inflate virtio queue event.
 const INFLATE_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 1;
@@ -48,12 +56,22 @@ const INFLATE_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 1;
 const DEFLATE_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 2;
 // Reporting virtio queue event.
 const REPORTING_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 3;
+// Statistics virtio queue event.
+const STATS_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 4;
+// New statistics requested from the guest.
+const STATS_REQUEST_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 5;
 
 // Size of a PFN in the balloon interface.
 const VIRTIO_BALLOON_PFN_SHIFT: u64 = 12;
 
+// Enable an additional virtqueue through which the guest reports its
+// memory statistics.
+const VIRTIO_BALLOON_F_STATS_VQ: u64 = 1;
 // Deflate balloon on OOM
 const VIRTIO_BALLOON_F_DEFLATE_ON_OOM: u64 = 2;
+// Let the guest tell the value its free pages are filled with, so that the
+// pages it reports aren't discarded when they wouldn't read back the same.
+const VIRTIO_BALLOON_F_PAGE_POISON: u64 = 4;
 // Enable an additional virtqueue to let the guest notify the host about free
 // pages.
 const VIRTIO_BALLOON_F_REPORTING: u64 = 5;
@@ -92,14 +110,66 @@ pub struct VirtioBalloonConfig {
     num_pages: u32,
     // Number of pages we've actually got in balloon.
     actual: u32,
+    // Free page hinting command, unused since free page hinting isn't
+    // supported.
+    #[version(start = 2)]
+    free_page_hint_cmd_id: u32,
+    // Value the guest fills its free pages with.
+    #[version(start = 2)]
+    poison_val: u32,
 }
 
 const CONFIG_ACTUAL_OFFSET: u64 = 4;
 const CONFIG_ACTUAL_SIZE: usize = 4;
+const CONFIG_POISON_VAL_OFFSET: u64 = 12;
+const CONFIG_POISON_VAL_SIZE: usize = 4;
 
 // SAFETY: it only has data and has no implicit padding.
 unsafe impl ByteValued for VirtioBalloonConfig {}
 
+// Got from include/uapi/linux/virtio_balloon.h
+const VIRTIO_BALLOON_S_SWAP_IN: u16 = 0;
+const VIRTIO_BALLOON_S_SWAP_OUT: u16 = 1;
+const VIRTIO_BALLOON_S_MAJFLT: u16 = 2;
+const VIRTIO_BALLOON_S_MINFLT: u16 = 3;
+const VIRTIO_BALLOON_S_MEMFREE: u16 = 4;
+const VIRTIO_BALLOON_S_MEMTOT: u16 = 5;
+const VIRTIO_BALLOON_S_AVAIL: u16 = 6;
+const VIRTIO_BALLOON_S_CACHES: u16 = 7;
+// Each statistic is a 16 bits tag followed by a 64 bits value, packed.
+const VIRTIO_BALLOON_STAT_SIZE: u64 = 10;
+
+/// Memory statistics reported by the guest. The sizes are in bytes, and
+/// the statistics the guest doesn't report are `None`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BalloonStats {
+    pub swap_in: Option<u64>,
+    pub swap_out: Option<u64>,
+    pub major_faults: Option<u64>,
+    pub minor_faults: Option<u64>,
+    pub free_memory: Option<u64>,
+    pub total_memory: Option<u64>,
+    pub available_memory: Option<u64>,
+    pub disk_caches: Option<u64>,
+}
+
+impl BalloonStats {
+    fn update(&mut self, tag: u16, val: u64) {
+        match tag {
+            VIRTIO_BALLOON_S_SWAP_IN => self.swap_in = Some(val),
+            VIRTIO_BALLOON_S_SWAP_OUT => self.swap_out = Some(val),
+            VIRTIO_BALLOON_S_MAJFLT => self.major_faults = Some(val),
+            VIRTIO_BALLOON_S_MINFLT => self.minor_faults = Some(val),
+            VIRTIO_BALLOON_S_MEMFREE => self.free_memory = Some(val),
+            VIRTIO_BALLOON_S_MEMTOT => self.total_memory = Some(val),
+            VIRTIO_BALLOON_S_AVAIL => self.available_memory = Some(val),
+            VIRTIO_BALLOON_S_CACHES => self.disk_caches = Some(val),
+            // Ignore the statistics we don't know about.
+            _ => {}
+        }
+    }
+}
+
 struct BalloonEpollHandler {
     mem: GuestMemoryAtomic<GuestMemoryMmap>,
     queues: Vec<Queue>,
@@ -107,6 +177,13 @@ struct BalloonEpollHandler {
     inflate_queue_evt: EventFd,
     deflate_queue_evt: EventFd,
     reporting_queue_evt: Option<EventFd>,
+    stats_queue_evt: Option<EventFd>,
+    stats_request_evt: EventFd,
+    stats: Arc<Mutex<StatsState>>,
+    // Whether the reported pages can be discarded, reading back as zeros
+    // afterwards.
+    discard_reported: bool,
+    counters: BalloonCounters,
     kill_evt: EventFd,
     pause_evt: EventFd,
 }
@@ -233,7 +310,18 @@ impl BalloonEpollHandler {
             let mut descs_len = 0;
             while let Some(desc) = desc_chain.next() {
                 descs_len += desc.len();
-                Self::release_memory_range(desc_chain.memory(), desc.addr(), desc.len() as usize)?;
+                if self.discard_reported {
+                    Self::release_memory_range(
+                        desc_chain.memory(),
+                        desc.addr(),
+                        desc.len() as usize,
+                    )?;
+                }
+            }
+            if self.discard_reported {
+                self.counters
+                    .reported_bytes
+                    .fetch_add(descs_len as u64, Ordering::AcqRel);
             }
 
             self.queues[queue_index]
@@ -249,6 +337,69 @@ impl BalloonEpollHandler {
         }
     }
 
+    // The guest fills a single buffer with its statistics, which is held
+    // until new statistics are requested, then given back for the guest to
+    // fill it again.
+    fn process_stats_queue(&mut self) -> result::Result<(), Error> {
+        while let Some(mut desc_chain) =
+            self.queues[STATS_QUEUE_INDEX].pop_descriptor_chain(self.mem.memory())
+        {
+            let desc = desc_chain.next().ok_or(Error::DescriptorChainTooShort)?;
+            if desc.is_write_only() {
+                return Err(Error::UnexpectedWriteOnlyDescriptor);
+            }
+
+            let mut stats = BalloonStats::default();
+            let mut offset = 0u64;
+            while offset + VIRTIO_BALLOON_STAT_SIZE <= desc.len() as u64 {
+                let addr = desc.addr().unchecked_add(offset);
+                let tag: u16 = desc_chain
+                    .memory()
+                    .read_obj(addr)
+                    .map_err(Error::GuestMemory)?;
+                let val: u64 = desc_chain
+                    .memory()
+                    .read_obj(addr.unchecked_add(size_of::<u16>() as u64))
+                    .map_err(Error::GuestMemory)?;
+                stats.update(tag, val);
+                offset += VIRTIO_BALLOON_STAT_SIZE;
+            }
+            let mut state = self.stats.lock().unwrap();
+            state.stats = Some(stats);
+
+            // Give back a buffer the guest submitted without being asked to.
+            if let Some(desc_index) = state.desc_index.replace(desc_chain.head_index()) {
+                self.queues[STATS_QUEUE_INDEX]
+                    .add_used(desc_chain.memory(), desc_index, 0)
+                    .map_err(Error::QueueAddUsed)?;
+                self.signal(VirtioInterruptType::Queue(STATS_QUEUE_INDEX as u16))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn request_stats(&mut self) -> result::Result<(), Error> {
+        let desc_index = self.stats.lock().unwrap().desc_index.take();
+        if let Some(desc_index) = desc_index {
+            let mem = self.mem.memory();
+            self.queues[STATS_QUEUE_INDEX]
+                .add_used(mem.deref(), desc_index, 0)
+                .map_err(Error::QueueAddUsed)?;
+            self.signal(VirtioInterruptType::Queue(STATS_QUEUE_INDEX as u16))?;
+        }
+
+        Ok(())
+    }
+
+    fn reporting_queue_index(&self) -> usize {
+        if self.stats_queue_evt.is_some() {
+            STATS_QUEUE_INDEX + 1
+        } else {
+            STATS_QUEUE_INDEX
+        }
+    }
+
     fn run(
         &mut self,
         paused: Arc<AtomicBool>,
@@ -260,6 +411,10 @@ impl BalloonEpollHandler {
         if let Some(reporting_queue_evt) = self.reporting_queue_evt.as_ref() {
             helper.add_event(reporting_queue_evt.as_raw_fd(), REPORTING_QUEUE_EVENT)?;
         }
+        if let Some(stats_queue_evt) = self.stats_queue_evt.as_ref() {
+            helper.add_event(stats_queue_evt.as_raw_fd(), STATS_QUEUE_EVENT)?;
+            helper.add_event(self.stats_request_evt.as_raw_fd(), STATS_REQUEST_EVENT)?;
+        }
         helper.run(paused, paused_sync, self)?;
 
         Ok(())
@@ -310,18 +465,53 @@ impl EpollHelperHandler for BalloonEpollHandler {
                             e
                         ))
                     })?;
-                    self.process_reporting_queue(2).map_err(|e| {
+                    self.process_reporting_queue(self.reporting_queue_index())
+                        .map_err(|e| {
+                            EpollHelperError::HandleEvent(anyhow!(
+                                "Failed to signal used inflate queue: {:?}",
+                                e
+                            ))
+                        })?;
+                } else {
+                    return Err(EpollHelperError::HandleEvent(anyhow!(
+                        "Invalid reporting queue event as no eventfd registered"
+                    )));
+                }
+            }
+            STATS_QUEUE_EVENT => {
+                if let Some(stats_queue_evt) = self.stats_queue_evt.as_ref() {
+                    stats_queue_evt.read().map_err(|e| {
                         EpollHelperError::HandleEvent(anyhow!(
-                            "Failed to signal used inflate queue: {:?}",
+                            "Failed to get stats queue event: {:?}",
+                            e
+                        ))
+                    })?;
+                    self.process_stats_queue().map_err(|e| {
+                        EpollHelperError::HandleEvent(anyhow!(
+                            "Failed to process stats queue: {:?}",
                             e
                         ))
                     })?;
                 } else {
                     return Err(EpollHelperError::HandleEvent(anyhow!(
-                        "Invalid reporting queue event as no eventfd registered"
+                        "Invalid stats queue event as no eventfd registered"
                     )));
                 }
             }
+            STATS_REQUEST_EVENT => {
+                self.stats_request_evt.read().map_err(|e| {
+                    EpollHelperError::HandleEvent(anyhow!(
+                        "Failed to get stats request event: {:?}",
+                        e
+                    ))
+                })?;
+                self.request_stats().map_err(|e| {
+                    EpollHelperError::HandleEvent(anyhow!(
+                        "Failed to signal used stats queue: {:?}",
+                        e
+                    ))
+                })?;
+            }
             _ => {
                 return Err(EpollHelperError::HandleEvent(anyhow!(
                     "Unknown event for virtio-balloon"
@@ -338,9 +528,36 @@ pub struct BalloonState {
     pub avail_features: u64,
     pub acked_features: u64,
     pub config: VirtioBalloonConfig,
+    pub stats_desc_index: Option<u16>,
+}
+
+impl VersionMapped for BalloonState {
+    fn version_map() -> VersionMap {
+        // The free page hinting and page poisoning fields of the config were
+        // added with snapshot version 2. Restoring an older state leaves them
+        // zeroed, which is what the guest sees without VIRTIO_BALLOON_F_PAGE_POISON.
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(VirtioBalloonConfig::type_id(), 2);
+        version_map
+    }
+}
+
+#[derive(Default)]
+struct StatsState {
+    // Buffer of the statistics queue, held until new statistics are wanted
+    // from the guest.
+    desc_index: Option<u16>,
+    // Latest statistics reported by the guest.
+    stats: Option<BalloonStats>,
 }
 
-impl VersionMapped for BalloonState {}
+#[derive(Clone, Default)]
+struct BalloonCounters {
+    // Amount of memory reported free by the guest and given back to the host.
+    reported_bytes: Arc<AtomicU64>,
+}
 
 // Virtio device for exposing entropy to the guest OS through virtio.
 pub struct Balloon {
@@ -350,6 +567,9 @@ pub struct Balloon {
     seccomp_action: SeccompAction,
     exit_evt: EventFd,
     interrupt_cb: Option<Arc<dyn VirtioInterrupt>>,
+    counters: BalloonCounters,
+    stats: Arc<Mutex<StatsState>>,
+    stats_request_evt: EventFd,
 }
 
 impl Balloon {
@@ -359,37 +579,47 @@ impl Balloon {
         size: u64,
         deflate_on_oom: bool,
         free_page_reporting: bool,
+        stats: bool,
         seccomp_action: SeccompAction,
         exit_evt: EventFd,
         state: Option<BalloonState>,
     ) -> io::Result<Self> {
         let mut queue_sizes = vec![QUEUE_SIZE; MIN_NUM_QUEUES];
 
-        let (avail_features, acked_features, config, paused) = if let Some(state) = state {
-            info!("Restoring virtio-balloon {}", id);
-            (
-                state.avail_features,
-                state.acked_features,
-                state.config,
-                true,
-            )
-        } else {
-            let mut avail_features = 1u64 << VIRTIO_F_VERSION_1;
-            if deflate_on_oom {
-                avail_features |= 1u64 << VIRTIO_BALLOON_F_DEFLATE_ON_OOM;
-            }
-            if free_page_reporting {
-                avail_features |= 1u64 << VIRTIO_BALLOON_F_REPORTING;
-            }
+        let (avail_features, acked_features, config, stats_desc_index, paused) =
+            if let Some(state) = state {
+                info!("Restoring virtio-balloon {}", id);
+                (
+                    state.avail_features,
+                    state.acked_features,
+                    state.config,
+                    state.stats_desc_index,
+                    true,
+                )
+            } else {
+                let mut avail_features = 1u64 << VIRTIO_F_VERSION_1;
+                if stats {
+                    avail_features |= 1u64 << VIRTIO_BALLOON_F_STATS_VQ;
+                }
+                if deflate_on_oom {
+                    avail_features |= 1u64 << VIRTIO_BALLOON_F_DEFLATE_ON_OOM;
+                }
+                if free_page_reporting {
+                    avail_features |= 1u64 << VIRTIO_BALLOON_F_REPORTING;
+                    avail_features |= 1u64 << VIRTIO_BALLOON_F_PAGE_POISON;
+                }
 
-            let config = VirtioBalloonConfig {
-                num_pages: (size >> VIRTIO_BALLOON_PFN_SHIFT) as u32,
-                ..Default::default()
-            };
+                let config = VirtioBalloonConfig {
+                    num_pages: (size >> VIRTIO_BALLOON_PFN_SHIFT) as u32,
+                    ..Default::default()
+                };
 
-            (avail_features, 0, config, false)
-        };
+                (avail_features, 0, config, None, false)
+            };
 
+        if stats {
+            queue_sizes.push(STATS_QUEUE_SIZE);
+        }
         if free_page_reporting {
             queue_sizes.push(REPORTING_QUEUE_SIZE);
         }
@@ -410,6 +640,12 @@ impl Balloon {
             seccomp_action,
             exit_evt,
             interrupt_cb: None,
+            counters: BalloonCounters::default(),
+            stats: Arc::new(Mutex::new(StatsState {
+                desc_index: stats_desc_index,
+                stats: None,
+            })),
+            stats_request_evt: EventFd::new(libc::EFD_NONBLOCK)?,
         })
     }
 
@@ -430,11 +666,38 @@ impl Balloon {
         (self.config.actual as u64) << VIRTIO_BALLOON_PFN_SHIFT
     }
 
+    // Get the size the virtio-balloon is asked to reach.
+    pub fn get_target(&self) -> u64 {
+        (self.config.num_pages as u64) << VIRTIO_BALLOON_PFN_SHIFT
+    }
+
+    /// Returns the latest memory statistics reported by the guest, and asks
+    /// it for new ones. Nothing is returned until the guest reports its
+    /// statistics for the first time, or if it doesn't support reporting
+    /// them.
+    pub fn stats(&self) -> Option<BalloonStats> {
+        if !self.common.feature_acked(VIRTIO_BALLOON_F_STATS_VQ) {
+            return None;
+        }
+
+        if let Err(e) = self.stats_request_evt.write(1) {
+            error!("Failed to request virtio-balloon statistics: {:?}", e);
+        }
+
+        self.stats.lock().unwrap().stats
+    }
+
+    /// Whether the device is paused, in which case it can't be resized.
+    pub fn is_paused(&self) -> bool {
+        self.common.paused.load(Ordering::SeqCst)
+    }
+
     fn state(&self) -> BalloonState {
         BalloonState {
             avail_features: self.common.avail_features,
             acked_features: self.common.acked_features,
             config: self.config,
+            stats_desc_index: self.stats.lock().unwrap().desc_index,
         }
     }
 
@@ -476,8 +739,12 @@ impl VirtioDevice for Balloon {
     }
 
     fn write_config(&mut self, offset: u64, data: &[u8]) {
-        // The "actual" field is the only mutable field
-        if offset != CONFIG_ACTUAL_OFFSET || data.len() != CONFIG_ACTUAL_SIZE {
+        // The "actual" and "poison_val" fields are the only mutable fields
+        if !matches!(
+            (offset, data.len()),
+            (CONFIG_ACTUAL_OFFSET, CONFIG_ACTUAL_SIZE)
+                | (CONFIG_POISON_VAL_OFFSET, CONFIG_POISON_VAL_SIZE)
+        ) {
             error!(
                 "Attempt to write to read-only field: offset {:x} length {}",
                 offset,
@@ -523,6 +790,14 @@ impl VirtioDevice for Balloon {
         let (_, queue, queue_evt) = queues.remove(0);
         virtqueues.push(queue);
         let deflate_queue_evt = queue_evt;
+        let stats_queue_evt =
+            if self.common.feature_acked(VIRTIO_BALLOON_F_STATS_VQ) && !queues.is_empty() {
+                let (_, queue, queue_evt) = queues.remove(0);
+                virtqueues.push(queue);
+                Some(queue_evt)
+            } else {
+                None
+            };
         let reporting_queue_evt =
             if self.common.feature_acked(VIRTIO_BALLOON_F_REPORTING) && !queues.is_empty() {
                 let (_, queue, queue_evt) = queues.remove(0);
@@ -534,6 +809,22 @@ impl VirtioDevice for Balloon {
 
         self.interrupt_cb = Some(interrupt_cb.clone());
 
+        // Discarded pages read back as zeros, which the guest only expects
+        // if it doesn't poison its free pages, or poisons them with zeros.
+        let discard_reported =
+            !self.common.feature_acked(VIRTIO_BALLOON_F_PAGE_POISON) || self.config.poison_val == 0;
+        if reporting_queue_evt.is_some() && !discard_reported {
+            warn!(
+                "Free pages reported by the guest aren't discarded as they're poisoned with {:#x}",
+                self.config.poison_val
+            );
+        }
+
+        let stats_request_evt = self.stats_request_evt.try_clone().map_err(|e| {
+            error!("failed cloning stats request event: {}", e);
+            ActivateError::BadActivate
+        })?;
+
         let mut handler = BalloonEpollHandler {
             mem,
             queues: virtqueues,
@@ -541,6 +832,11 @@ impl VirtioDevice for Balloon {
             inflate_queue_evt,
             deflate_queue_evt,
             reporting_queue_evt,
+            stats_queue_evt,
+            stats_request_evt,
+            stats: self.stats.clone(),
+            discard_reported,
+            counters: self.counters.clone(),
             kill_evt,
             pause_evt,
         };
@@ -563,8 +859,20 @@ impl VirtioDevice for Balloon {
         Ok(())
     }
 
+    fn counters(&self) -> Option<HashMap<&'static str, Wrapping<u64>>> {
+        let mut counters = HashMap::new();
+
+        counters.insert(
+            "reported_bytes",
+            Wrapping(self.counters.reported_bytes.load(Ordering::Acquire)),
+        );
+
+        Some(counters)
+    }
+
     fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
         let result = self.common.reset();
+        *self.stats.lock().unwrap() = StatsState::default();
         event!("virtio-device", "reset", "id", &self.id);
         result
     }
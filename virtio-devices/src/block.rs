@@ -15,7 +15,7 @@ use super::{
     EPOLL_HELPER_EVENT_LAST,
 };
 use crate::seccomp_filters::Thread;
-use crate::thread_helper::spawn_virtio_thread;
+use crate::thread_helper::{set_virtio_thread_affinity, spawn_virtio_thread};
 use crate::GuestMemoryMmap;
 use crate::VirtioInterrupt;
 use anyhow::anyhow;
@@ -23,9 +23,9 @@ use block_util::{
     async_io::AsyncIo, async_io::AsyncIoError, async_io::DiskFile, build_disk_image_id, Request,
     RequestType, VirtioBlockConfig,
 };
-use rate_limiter::{RateLimiter, TokenType};
+use rate_limiter::{RateLimiter, RateLimiterGroup, TokenType};
 use seccompiler::SeccompAction;
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
 use std::io;
 use std::num::Wrapping;
 use std::ops::Deref;
@@ -84,6 +84,46 @@ pub enum Error {
 
 pub type Result<T> = result::Result<T, Error>;
 
+// Latencies are sorted in buckets of increasing powers of 2 microseconds,
+// the last one catching everything above 2^26 us (about a minute).
+const LATENCY_BUCKETS: usize = 28;
+
+#[derive(Clone)]
+struct LatencyHistogram(Arc<[AtomicU64; LATENCY_BUCKETS]>);
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram(Arc::new(Default::default()))
+    }
+}
+
+impl LatencyHistogram {
+    fn record(&self, latency_us: u64) {
+        let bucket = (u64::BITS - latency_us.leading_zeros()) as usize;
+        self.0[bucket.min(LATENCY_BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Upper bound in microseconds of the latency of the given per-mille of
+    // the requests, 0 if none completed yet.
+    fn percentile(&self, per_mille: u64) -> u64 {
+        let buckets: Vec<u64> = self.0.iter().map(|b| b.load(Ordering::Relaxed)).collect();
+        let total: u64 = buckets.iter().sum();
+        if total == 0 {
+            return 0;
+        }
+
+        let mut count = 0;
+        for (i, bucket) in buckets.iter().enumerate() {
+            count += bucket;
+            if count * 1000 >= total * per_mille {
+                return 1 << i;
+            }
+        }
+
+        1 << (LATENCY_BUCKETS - 1)
+    }
+}
+
 // latency will be records as microseconds, average latency
 // will be save as scaled value.
 #[derive(Clone)]
@@ -98,6 +138,12 @@ pub struct BlockCounters {
     write_latency_min: Arc<AtomicU64>,
     write_latency_max: Arc<AtomicU64>,
     write_latency_avg: Arc<AtomicU64>,
+    read_latency: LatencyHistogram,
+    write_latency: LatencyHistogram,
+    // Requests submitted to the backend and not completed yet, across all
+    // the queues.
+    queue_depth: Arc<AtomicU64>,
+    queue_depth_max: Arc<AtomicU64>,
 }
 
 impl Default for BlockCounters {
@@ -113,6 +159,10 @@ impl Default for BlockCounters {
             write_latency_min: Arc::new(AtomicU64::new(u64::MAX)),
             write_latency_max: Arc::new(AtomicU64::new(0)),
             write_latency_avg: Arc::new(AtomicU64::new(0)),
+            read_latency: LatencyHistogram::default(),
+            write_latency: LatencyHistogram::default(),
+            queue_depth: Arc::new(AtomicU64::new(0)),
+            queue_depth_max: Arc::new(AtomicU64::new(0)),
         }
     }
 }
@@ -150,8 +200,13 @@ impl BlockEpollHandler {
             // "A device MUST set the status byte to VIRTIO_BLK_S_IOERR for a write request
             // if the VIRTIO_BLK_F_RO feature if offered, and MUST NOT write any data."
             if self.read_only
-                && (request.request_type == RequestType::Out
-                    || request.request_type == RequestType::Flush)
+                && matches!(
+                    request.request_type,
+                    RequestType::Out
+                        | RequestType::Flush
+                        | RequestType::Discard
+                        | RequestType::WriteZeroes
+                )
             {
                 desc_chain
                     .memory()
@@ -200,18 +255,44 @@ impl BlockEpollHandler {
 
             request.set_writeback(self.writeback.load(Ordering::Acquire));
 
-            if request
-                .execute_async(
-                    desc_chain.memory(),
-                    self.disk_nsectors,
-                    self.disk_image.as_mut(),
-                    &self.disk_image_id,
-                    desc_chain.head_index() as u64,
-                )
-                .map_err(Error::RequestExecuting)?
-            {
+            let submitted = match request.execute_async(
+                desc_chain.memory(),
+                self.disk_nsectors,
+                self.disk_image.as_mut(),
+                &self.disk_image_id,
+                desc_chain.head_index() as u64,
+            ) {
+                Ok(submitted) => submitted,
+                // Whether a range can be deallocated or zeroed depends on the
+                // host filesystem or device, hence failing to do so is
+                // reported to the guest instead of stopping the queue.
+                Err(e)
+                    if matches!(
+                        request.request_type,
+                        RequestType::Discard | RequestType::WriteZeroes
+                    ) =>
+                {
+                    warn!("Failed to execute request: {:?}", e);
+                    desc_chain
+                        .memory()
+                        .write_obj(e.status(), request.status_addr)
+                        .map_err(Error::RequestStatus)?;
+                    queue
+                        .add_used(desc_chain.memory(), desc_chain.head_index(), 0)
+                        .map_err(Error::QueueAddUsed)?;
+                    used_descs = true;
+                    continue;
+                }
+                Err(e) => return Err(Error::RequestExecuting(e)),
+            };
+
+            if submitted {
                 self.inflight_requests
                     .push_back((desc_chain.head_index(), request));
+                let depth = self.counters.queue_depth.fetch_add(1, Ordering::Relaxed) + 1;
+                self.counters
+                    .queue_depth_max
+                    .fetch_max(depth, Ordering::Relaxed);
             } else {
                 desc_chain
                     .memory()
@@ -277,6 +358,7 @@ impl BlockEpollHandler {
             let desc_index = user_data as u16;
 
             let mut request = self.find_inflight_request(desc_index)?;
+            self.counters.queue_depth.fetch_sub(1, Ordering::Relaxed);
 
             request.complete_async().map_err(Error::RequestCompleting)?;
 
@@ -292,6 +374,7 @@ impl BlockEpollHandler {
                             read_bytes += Wrapping(*data_len as u64);
                         }
                         read_ops += Wrapping(1);
+                        self.counters.read_latency.record(latency);
                         if latency < self.counters.read_latency_min.load(Ordering::Relaxed) {
                             self.counters
                                 .read_latency_min
@@ -314,6 +397,7 @@ impl BlockEpollHandler {
                             write_bytes += Wrapping(*data_len as u64);
                         }
                         write_ops += Wrapping(1);
+                        self.counters.write_latency.record(latency);
                         if latency < self.counters.write_latency_min.load(Ordering::Relaxed) {
                             self.counters
                                 .write_latency_min
@@ -486,8 +570,11 @@ pub struct Block {
     counters: BlockCounters,
     seccomp_action: SeccompAction,
     rate_limiter_config: Option<RateLimiterConfig>,
+    rate_limiter_group: Option<RateLimiterGroup>,
     exit_evt: EventFd,
     read_only: bool,
+    // Host CPUs the thread of each queue is pinned onto
+    queue_affinity: BTreeMap<u16, Vec<usize>>,
 }
 
 #[derive(Versionize)]
@@ -514,8 +601,10 @@ impl Block {
         queue_size: u16,
         seccomp_action: SeccompAction,
         rate_limiter_config: Option<RateLimiterConfig>,
+        rate_limiter_group: Option<RateLimiterGroup>,
         exit_evt: EventFd,
         state: Option<BlockState>,
+        queue_affinity: BTreeMap<u16, Vec<usize>>,
     ) -> io::Result<Self> {
         let (disk_nsectors, avail_features, acked_features, config, paused) =
             if let Some(state) = state {
@@ -589,6 +678,17 @@ impl Block {
                     config.num_queues = num_queues as u16;
                 }
 
+                if !read_only && disk_image.supports_discard() {
+                    avail_features |=
+                        (1u64 << VIRTIO_BLK_F_DISCARD) | (1u64 << VIRTIO_BLK_F_WRITE_ZEROES);
+                    config.max_discard_sectors = u32::MAX;
+                    config.max_discard_seg = 1;
+                    config.discard_sector_alignment = (logical_block_size / SECTOR_SIZE) as u32;
+                    config.max_write_zeroes_sectors = u32::MAX;
+                    config.max_write_zeroes_seg = 1;
+                    config.write_zeroes_may_unmap = 1;
+                }
+
                 (disk_nsectors, avail_features, 0, config, false)
             };
 
@@ -612,8 +712,10 @@ impl Block {
             counters: BlockCounters::default(),
             seccomp_action,
             rate_limiter_config,
+            rate_limiter_group,
             exit_evt,
             read_only,
+            queue_affinity,
         })
     }
 
@@ -712,6 +814,8 @@ impl VirtioDevice for Block {
 
         let disk_image_id = build_disk_image_id(&self.disk_path);
         self.update_writeback();
+        // Requests left in flight by a previous activation are gone
+        self.counters.queue_depth.store(0, Ordering::Relaxed);
 
         let mut epoll_threads = Vec::new();
         for i in 0..queues.len() {
@@ -719,11 +823,17 @@ impl VirtioDevice for Block {
             let queue_size = queue.size();
             let (kill_evt, pause_evt) = self.common.dup_eventfds();
 
-            let rate_limiter: Option<RateLimiter> = self
-                .rate_limiter_config
-                .map(RateLimiterConfig::try_into)
-                .transpose()
-                .map_err(ActivateError::CreateRateLimiter)?;
+            let rate_limiter: Option<RateLimiter> = if let Some(group) = &self.rate_limiter_group {
+                Some(
+                    RateLimiter::new_in_group(group.clone())
+                        .map_err(ActivateError::CreateRateLimiter)?,
+                )
+            } else {
+                self.rate_limiter_config
+                    .map(RateLimiterConfig::try_into)
+                    .transpose()
+                    .map_err(ActivateError::CreateRateLimiter)?
+            };
 
             let mut handler = BlockEpollHandler {
                 queue_index: i as u16,
@@ -764,6 +874,12 @@ impl VirtioDevice for Block {
                 &self.exit_evt,
                 move || handler.run(paused, paused_sync.unwrap()),
             )?;
+
+            if let (Some(host_cpus), Some(thread)) =
+                (self.queue_affinity.get(&(i as u16)), epoll_threads.last())
+            {
+                set_virtio_thread_affinity(thread, host_cpus)?;
+            }
         }
 
         self.common.epoll_threads = Some(epoll_threads);
@@ -821,6 +937,34 @@ impl VirtioDevice for Block {
             "read_latency_avg",
             Wrapping(self.counters.read_latency_avg.load(Ordering::Acquire) / LATENCY_SCALE),
         );
+        for (name, per_mille) in [
+            ("read_latency_p50", 500),
+            ("read_latency_p90", 900),
+            ("read_latency_p99", 990),
+        ] {
+            counters.insert(
+                name,
+                Wrapping(self.counters.read_latency.percentile(per_mille)),
+            );
+        }
+        for (name, per_mille) in [
+            ("write_latency_p50", 500),
+            ("write_latency_p90", 900),
+            ("write_latency_p99", 990),
+        ] {
+            counters.insert(
+                name,
+                Wrapping(self.counters.write_latency.percentile(per_mille)),
+            );
+        }
+        counters.insert(
+            "queue_depth",
+            Wrapping(self.counters.queue_depth.load(Ordering::Acquire)),
+        );
+        counters.insert(
+            "queue_depth_max",
+            Wrapping(self.counters.queue_depth_max.load(Ordering::Acquire)),
+        );
 
         Some(counters)
     }
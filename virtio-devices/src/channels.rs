@@ -0,0 +1,1049 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Multiport virtio-console device exposing named channels to the guest.
+//!
+//! Each port is announced to the guest with its name, which the guest makes
+//! available as `/dev/virtio-ports/<name>`. On the host side, a port is either
+//! a Unix socket the VMM listens on, accepting one client at a time, or a PTY.
+//! The guest is told whether the host side of a port is connected, so that
+//! applications in the guest can tell when someone listens to them.
+
+use super::Error as DeviceError;
+use super::{
+    ActivateError, ActivateResult, EpollHelper, EpollHelperError, EpollHelperHandler, VirtioCommon,
+    VirtioConsoleConfig, VirtioDevice, VirtioDeviceType, VirtioInterruptType,
+    EPOLL_HELPER_EVENT_LAST, VIRTIO_F_IOMMU_PLATFORM, VIRTIO_F_VERSION_1,
+};
+use crate::seccomp_filters::Thread;
+use crate::thread_helper::spawn_virtio_thread;
+use crate::GuestMemoryMmap;
+use crate::VirtioInterrupt;
+use anyhow::anyhow;
+use seccompiler::SeccompAction;
+use serial_buffer::SerialBuffer;
+use std::cmp;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::result;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Barrier, Mutex};
+use thiserror::Error;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+use virtio_queue::{Queue, QueueT};
+use vm_memory::{ByteValued, Bytes, GuestAddressSpace, GuestMemoryAtomic};
+use vm_migration::VersionMapped;
+use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
+use vm_virtio::{AccessPlatform, Translatable};
+use vmm_sys_util::eventfd::EventFd;
+
+const QUEUE_SIZE: u16 = 256;
+
+// The control queues come right after the queues of the first port.
+const CONTROL_RX_QUEUE: usize = 2;
+const CONTROL_TX_QUEUE: usize = 3;
+
+// New descriptors are pending on the control queues.
+const CONTROL_RX_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 1;
+const CONTROL_TX_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 2;
+// Each port gets PORT_EVENTS consecutive events starting from
+// PORT_EVENT_BASE + port * PORT_EVENTS, in the order below.
+const PORT_EVENT_BASE: u16 = EPOLL_HELPER_EVENT_LAST + 3;
+const PORT_EVENTS: u16 = 4;
+// New descriptors are pending on the port queues.
+const PORT_RX_QUEUE_EVENT: u16 = 0;
+const PORT_TX_QUEUE_EVENT: u16 = 1;
+// A client is connecting to the socket of the port.
+const PORT_LISTENER_EVENT: u16 = 2;
+// Data is available from the host side of the port.
+const PORT_INPUT_EVENT: u16 = 3;
+
+// Multiport feature bit
+const VIRTIO_CONSOLE_F_MULTIPORT: u64 = 1;
+
+// Control messages, from the virtio specification.
+const VIRTIO_CONSOLE_DEVICE_READY: u16 = 0;
+const VIRTIO_CONSOLE_DEVICE_ADD: u16 = 1;
+const VIRTIO_CONSOLE_PORT_READY: u16 = 3;
+const VIRTIO_CONSOLE_PORT_OPEN: u16 = 6;
+const VIRTIO_CONSOLE_PORT_NAME: u16 = 7;
+
+// Input of a port read from the host side and not yet taken by the guest.
+// The host side isn't read anymore past this size.
+const MAX_BUFFERED_INPUT: usize = 64 << 10;
+
+#[derive(Error, Debug)]
+enum Error {
+    #[error("Descriptor chain too short")]
+    DescriptorChainTooShort,
+    #[error("Failed to read from guest memory: {0}")]
+    GuestMemoryRead(vm_memory::guest_memory::Error),
+    #[error("Failed to write to guest memory: {0}")]
+    GuestMemoryWrite(vm_memory::guest_memory::Error),
+    #[error("Failed to add used index: {0}")]
+    QueueAddUsed(virtio_queue::Error),
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+struct VirtioConsoleControl {
+    id: u32,
+    event: u16,
+    value: u16,
+}
+
+// SAFETY: it only has data and has no implicit padding.
+unsafe impl ByteValued for VirtioConsoleControl {}
+
+fn control_message(id: u32, event: u16, value: u16, data: &[u8]) -> Vec<u8> {
+    let mut message = VirtioConsoleControl { id, event, value }
+        .as_slice()
+        .to_vec();
+    message.extend_from_slice(data);
+    message
+}
+
+/// Host side of a channel.
+pub enum ChannelBackend {
+    /// Unix socket the VMM listens on.
+    Socket(PathBuf),
+    /// Main and sub sides of a PTY. The sub side is only held so that the
+    /// PTY doesn't hang up while no one has it open.
+    Pty(File, File),
+}
+
+/// Port of the device, named `name` in the guest.
+pub struct ChannelPort {
+    pub name: String,
+    pub backend: ChannelBackend,
+}
+
+enum PortBackend {
+    Socket(UnixListener, PathBuf),
+    Pty(File, File),
+}
+
+struct Port {
+    name: String,
+    backend: PortBackend,
+}
+
+impl Port {
+    fn handler(
+        &self,
+        rx_queue: Queue,
+        rx_queue_evt: EventFd,
+        tx_queue: Queue,
+        tx_queue_evt: EventFd,
+    ) -> io::Result<PortHandler> {
+        let (listener, pty, out) = match &self.backend {
+            PortBackend::Socket(listener, _) => (Some(listener.try_clone()?), None, None),
+            PortBackend::Pty(main, _) => (
+                None,
+                Some(main.try_clone()?),
+                Some(SerialBuffer::new(
+                    Box::new(main.try_clone()?),
+                    Arc::new(AtomicBool::new(true)),
+                )),
+            ),
+        };
+
+        Ok(PortHandler {
+            name: self.name.clone(),
+            rx_queue,
+            rx_queue_evt,
+            tx_queue,
+            tx_queue_evt,
+            listener,
+            client: None,
+            pty,
+            out,
+            input_registered: false,
+        })
+    }
+}
+
+// State of the ports shared with the epoll thread.
+#[derive(Default)]
+struct PortStatus {
+    // The guest is ready to receive the name and the state of the port.
+    ready: bool,
+    in_buffer: VecDeque<u8>,
+}
+
+struct PortHandler {
+    name: String,
+    rx_queue: Queue,
+    rx_queue_evt: EventFd,
+    tx_queue: Queue,
+    tx_queue_evt: EventFd,
+    listener: Option<UnixListener>,
+    client: Option<UnixStream>,
+    pty: Option<File>,
+    out: Option<SerialBuffer>,
+    input_registered: bool,
+}
+
+impl PortHandler {
+    fn connected(&self) -> bool {
+        self.client.is_some() || self.pty.is_some()
+    }
+
+    fn input_fd(&self) -> Option<RawFd> {
+        if let Some(client) = &self.client {
+            Some(client.as_raw_fd())
+        } else {
+            self.pty.as_ref().map(|pty| pty.as_raw_fd())
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(client) = &mut self.client {
+            client.read(buf)
+        } else if let Some(pty) = &mut self.pty {
+            pty.read(buf)
+        } else {
+            Ok(0)
+        }
+    }
+}
+
+fn port_rx_queue_index(port: usize) -> u16 {
+    if port == 0 {
+        0
+    } else {
+        (port as u16 + 1) * 2
+    }
+}
+
+struct ChannelsEpollHandler {
+    mem: GuestMemoryAtomic<GuestMemoryMmap>,
+    control_rx_queue: Queue,
+    control_rx_evt: EventFd,
+    control_tx_queue: Queue,
+    control_tx_evt: EventFd,
+    ports: Vec<PortHandler>,
+    status: Arc<Mutex<Vec<PortStatus>>>,
+    pending_control: VecDeque<Vec<u8>>,
+    interrupt_cb: Arc<dyn VirtioInterrupt>,
+    kill_evt: EventFd,
+    pause_evt: EventFd,
+    access_platform: Option<Arc<dyn AccessPlatform>>,
+}
+
+impl ChannelsEpollHandler {
+    fn signal_used_queue(&self, queue_index: u16) -> result::Result<(), DeviceError> {
+        self.interrupt_cb
+            .trigger(VirtioInterruptType::Queue(queue_index))
+            .map_err(|e| {
+                error!("Failed to signal used queue: {:?}", e);
+                DeviceError::FailedSignalingUsedQueue(e)
+            })
+    }
+
+    fn queue_port_open(&mut self, port: usize) {
+        let connected = self.ports[port].connected();
+        self.pending_control.push_back(control_message(
+            port as u32,
+            VIRTIO_CONSOLE_PORT_OPEN,
+            connected as u16,
+            &[],
+        ));
+    }
+
+    // Sends the pending control messages to the guest, as long as it provides
+    // buffers to hold them.
+    fn process_control_rx_queue(&mut self) -> result::Result<bool, Error> {
+        let mut used_descs = false;
+
+        while !self.pending_control.is_empty() {
+            let mut desc_chain = match self
+                .control_rx_queue
+                .pop_descriptor_chain(self.mem.memory())
+            {
+                Some(desc_chain) => desc_chain,
+                None => break,
+            };
+            let desc = desc_chain.next().ok_or(Error::DescriptorChainTooShort)?;
+            let message = self.pending_control.pop_front().unwrap();
+            let len = cmp::min(desc.len() as usize, message.len());
+            if len < message.len() {
+                warn!("Control message truncated to {} bytes", len);
+            }
+
+            desc_chain
+                .memory()
+                .write_slice(
+                    &message[..len],
+                    desc.addr()
+                        .translate_gva(self.access_platform.as_ref(), desc.len() as usize),
+                )
+                .map_err(Error::GuestMemoryWrite)?;
+
+            self.control_rx_queue
+                .add_used(desc_chain.memory(), desc_chain.head_index(), len as u32)
+                .map_err(Error::QueueAddUsed)?;
+            used_descs = true;
+        }
+
+        Ok(used_descs)
+    }
+
+    // Handles the control messages sent by the guest, queueing the answers
+    // they call for.
+    fn process_control_tx_queue(&mut self) -> result::Result<bool, Error> {
+        let mut used_descs = false;
+
+        while let Some(mut desc_chain) = self
+            .control_tx_queue
+            .pop_descriptor_chain(self.mem.memory())
+        {
+            let desc = desc_chain.next().ok_or(Error::DescriptorChainTooShort)?;
+            let control: VirtioConsoleControl = desc_chain
+                .memory()
+                .read_obj(
+                    desc.addr()
+                        .translate_gva(self.access_platform.as_ref(), desc.len() as usize),
+                )
+                .map_err(Error::GuestMemoryRead)?;
+
+            self.control_tx_queue
+                .add_used(desc_chain.memory(), desc_chain.head_index(), 0)
+                .map_err(Error::QueueAddUsed)?;
+            used_descs = true;
+
+            self.handle_control(control);
+        }
+
+        Ok(used_descs)
+    }
+
+    fn handle_control(&mut self, control: VirtioConsoleControl) {
+        let port = control.id as usize;
+        match control.event {
+            VIRTIO_CONSOLE_DEVICE_READY => {
+                if control.value == 0 {
+                    error!("Guest failed to initialize the virtio-console device");
+                    return;
+                }
+                for id in 0..self.ports.len() {
+                    self.pending_control.push_back(control_message(
+                        id as u32,
+                        VIRTIO_CONSOLE_DEVICE_ADD,
+                        0,
+                        &[],
+                    ));
+                }
+            }
+            VIRTIO_CONSOLE_PORT_READY if port < self.ports.len() => {
+                if control.value == 0 {
+                    error!("Guest failed to add port {}", self.ports[port].name);
+                    return;
+                }
+                self.status.lock().unwrap()[port].ready = true;
+                let name = self.ports[port].name.clone();
+                self.pending_control.push_back(control_message(
+                    control.id,
+                    VIRTIO_CONSOLE_PORT_NAME,
+                    0,
+                    name.as_bytes(),
+                ));
+                self.queue_port_open(port);
+            }
+            VIRTIO_CONSOLE_PORT_OPEN if port < self.ports.len() => {
+                debug!(
+                    "Port {} {} by the guest",
+                    self.ports[port].name,
+                    if control.value != 0 {
+                        "opened"
+                    } else {
+                        "closed"
+                    }
+                );
+            }
+            _ => {
+                warn!(
+                    "Unexpected control message {} for port {}",
+                    control.event, control.id
+                );
+            }
+        }
+    }
+
+    // Fills the buffers of the guest with the input of the port.
+    fn process_port_rx_queue(&mut self, port: usize) -> result::Result<bool, Error> {
+        let mut status = self.status.lock().unwrap();
+        let in_buffer = &mut status[port].in_buffer;
+        let rx_queue = &mut self.ports[port].rx_queue;
+        let mut used_descs = false;
+
+        while !in_buffer.is_empty() {
+            let mut desc_chain = match rx_queue.pop_descriptor_chain(self.mem.memory()) {
+                Some(desc_chain) => desc_chain,
+                None => break,
+            };
+            let desc = desc_chain.next().ok_or(Error::DescriptorChainTooShort)?;
+            let len = cmp::min(desc.len(), in_buffer.len() as u32);
+            let source_slice = in_buffer.drain(..len as usize).collect::<Vec<u8>>();
+
+            desc_chain
+                .memory()
+                .write_slice(
+                    &source_slice[..],
+                    desc.addr()
+                        .translate_gva(self.access_platform.as_ref(), desc.len() as usize),
+                )
+                .map_err(Error::GuestMemoryWrite)?;
+
+            rx_queue
+                .add_used(desc_chain.memory(), desc_chain.head_index(), len)
+                .map_err(Error::QueueAddUsed)?;
+            used_descs = true;
+        }
+
+        Ok(used_descs)
+    }
+
+    // Sends the output of the guest to the host side of the port, dropping
+    // it if the host side isn't connected.
+    fn process_port_tx_queue(&mut self, port: usize) -> result::Result<bool, Error> {
+        let port = &mut self.ports[port];
+        let mut used_descs = false;
+
+        while let Some(mut desc_chain) = port.tx_queue.pop_descriptor_chain(self.mem.memory()) {
+            let mut len = 0;
+            while let Some(desc) = desc_chain.next() {
+                if desc.is_write_only() {
+                    break;
+                }
+                if let Some(out) = &mut port.out {
+                    desc_chain
+                        .memory()
+                        .write_to(
+                            desc.addr()
+                                .translate_gva(self.access_platform.as_ref(), desc.len() as usize),
+                            out,
+                            desc.len() as usize,
+                        )
+                        .map_err(Error::GuestMemoryRead)?;
+                }
+                len += desc.len();
+            }
+            if let Some(out) = &mut port.out {
+                if let Err(e) = out.flush() {
+                    warn!("Failed to write output of port {}: {}", port.name, e);
+                }
+            }
+
+            port.tx_queue
+                .add_used(desc_chain.memory(), desc_chain.head_index(), len)
+                .map_err(Error::QueueAddUsed)?;
+            used_descs = true;
+        }
+
+        Ok(used_descs)
+    }
+
+    fn port_event(port: usize, event: u16) -> u16 {
+        PORT_EVENT_BASE + port as u16 * PORT_EVENTS + event
+    }
+
+    fn register_input(
+        &mut self,
+        helper: &mut EpollHelper,
+        port: usize,
+    ) -> result::Result<(), EpollHelperError> {
+        if self.ports[port].input_registered {
+            return Ok(());
+        }
+        if let Some(fd) = self.ports[port].input_fd() {
+            helper.add_event(fd, Self::port_event(port, PORT_INPUT_EVENT))?;
+            self.ports[port].input_registered = true;
+        }
+
+        Ok(())
+    }
+
+    fn unregister_input(
+        &mut self,
+        helper: &mut EpollHelper,
+        port: usize,
+    ) -> result::Result<(), EpollHelperError> {
+        if !self.ports[port].input_registered {
+            return Ok(());
+        }
+        if let Some(fd) = self.ports[port].input_fd() {
+            helper.del_event_custom(
+                fd,
+                Self::port_event(port, PORT_INPUT_EVENT),
+                epoll::Events::EPOLLIN,
+            )?;
+        }
+        self.ports[port].input_registered = false;
+
+        Ok(())
+    }
+
+    fn accept(
+        &mut self,
+        helper: &mut EpollHelper,
+        port: usize,
+    ) -> result::Result<(), EpollHelperError> {
+        let stream = match self.ports[port].listener.as_ref().unwrap().accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(e) => {
+                error!(
+                    "Failed accepting connection to {}: {}",
+                    self.ports[port].name, e
+                );
+                return Ok(());
+            }
+        };
+        if self.ports[port].client.is_some() {
+            warn!(
+                "Rejecting connection to {}, a client is already connected",
+                self.ports[port].name
+            );
+            return Ok(());
+        }
+
+        let writer = match stream
+            .set_nonblocking(true)
+            .and_then(|_| stream.try_clone())
+        {
+            Ok(writer) => writer,
+            Err(e) => {
+                error!(
+                    "Failed setting up connection to {}: {}",
+                    self.ports[port].name, e
+                );
+                return Ok(());
+            }
+        };
+        self.ports[port].out = Some(SerialBuffer::new(
+            Box::new(writer),
+            Arc::new(AtomicBool::new(true)),
+        ));
+        self.ports[port].client = Some(stream);
+        self.register_input(helper, port)?;
+
+        if self.status.lock().unwrap()[port].ready {
+            self.queue_port_open(port);
+        }
+
+        Ok(())
+    }
+
+    fn disconnect(
+        &mut self,
+        helper: &mut EpollHelper,
+        port: usize,
+    ) -> result::Result<(), EpollHelperError> {
+        self.unregister_input(helper, port)?;
+        if self.ports[port].client.take().is_none() {
+            return Ok(());
+        }
+        self.ports[port].out = None;
+
+        if self.status.lock().unwrap()[port].ready {
+            self.queue_port_open(port);
+        }
+
+        Ok(())
+    }
+
+    // Reads the input available from the host side of the port. The client
+    // of a socket is disconnected when the connection is closed.
+    fn read_input(
+        &mut self,
+        helper: &mut EpollHelper,
+        port: usize,
+    ) -> result::Result<(), EpollHelperError> {
+        let mut input = [0u8; 4096];
+        match self.ports[port].read(&mut input) {
+            Ok(0) => self.disconnect(helper, port)?,
+            Ok(count) => {
+                let full = {
+                    let mut status = self.status.lock().unwrap();
+                    let in_buffer = &mut status[port].in_buffer;
+                    in_buffer.extend(&input[..count]);
+                    in_buffer.len() >= MAX_BUFFERED_INPUT
+                };
+                // Stop reading until the guest catches up.
+                if full {
+                    self.unregister_input(helper, port)?;
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => {
+                warn!(
+                    "Failed to read input of port {}: {}",
+                    self.ports[port].name, e
+                );
+                self.disconnect(helper, port)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn run_port_rx_queue(
+        &mut self,
+        helper: &mut EpollHelper,
+        port: usize,
+    ) -> result::Result<(), EpollHelperError> {
+        let needs_notification = self.process_port_rx_queue(port).map_err(|e| {
+            EpollHelperError::HandleEvent(anyhow!("Failed to process port rx queue: {:?}", e))
+        })?;
+        if needs_notification {
+            self.signal_used_queue(port_rx_queue_index(port))
+                .map_err(|e| {
+                    EpollHelperError::HandleEvent(anyhow!("Failed to signal used queue: {:?}", e))
+                })?;
+        }
+
+        if self.status.lock().unwrap()[port].in_buffer.len() < MAX_BUFFERED_INPUT {
+            self.register_input(helper, port)?;
+        }
+
+        Ok(())
+    }
+
+    fn run_control_rx_queue(&mut self) -> result::Result<(), EpollHelperError> {
+        let needs_notification = self.process_control_rx_queue().map_err(|e| {
+            EpollHelperError::HandleEvent(anyhow!("Failed to process control rx queue: {:?}", e))
+        })?;
+        if needs_notification {
+            self.signal_used_queue(CONTROL_RX_QUEUE as u16)
+                .map_err(|e| {
+                    EpollHelperError::HandleEvent(anyhow!("Failed to signal used queue: {:?}", e))
+                })?;
+        }
+
+        Ok(())
+    }
+
+    fn run(
+        &mut self,
+        paused: Arc<AtomicBool>,
+        paused_sync: Arc<Barrier>,
+    ) -> result::Result<(), EpollHelperError> {
+        let mut helper = EpollHelper::new(&self.kill_evt, &self.pause_evt)?;
+        helper.add_event(self.control_rx_evt.as_raw_fd(), CONTROL_RX_EVENT)?;
+        helper.add_event(self.control_tx_evt.as_raw_fd(), CONTROL_TX_EVENT)?;
+        for port in 0..self.ports.len() {
+            helper.add_event(
+                self.ports[port].rx_queue_evt.as_raw_fd(),
+                Self::port_event(port, PORT_RX_QUEUE_EVENT),
+            )?;
+            helper.add_event(
+                self.ports[port].tx_queue_evt.as_raw_fd(),
+                Self::port_event(port, PORT_TX_QUEUE_EVENT),
+            )?;
+            if let Some(listener) = &self.ports[port].listener {
+                helper.add_event(
+                    listener.as_raw_fd(),
+                    Self::port_event(port, PORT_LISTENER_EVENT),
+                )?;
+            }
+            self.register_input(&mut helper, port)?;
+        }
+
+        // When restoring, the guest doesn't ask again for the state of the
+        // ports it knows about, which may have changed in the meantime.
+        for port in 0..self.ports.len() {
+            if self.status.lock().unwrap()[port].ready {
+                self.queue_port_open(port);
+            }
+        }
+        self.run_control_rx_queue()?;
+
+        helper.run(paused, paused_sync, self)?;
+
+        Ok(())
+    }
+}
+
+impl EpollHelperHandler for ChannelsEpollHandler {
+    fn handle_event(
+        &mut self,
+        helper: &mut EpollHelper,
+        event: &epoll::Event,
+    ) -> result::Result<(), EpollHelperError> {
+        let ev_type = event.data as u16;
+
+        match ev_type {
+            CONTROL_RX_EVENT => {
+                self.control_rx_evt.read().map_err(|e| {
+                    EpollHelperError::HandleEvent(anyhow!("Failed to get queue event: {:?}", e))
+                })?;
+                self.run_control_rx_queue()?;
+            }
+            CONTROL_TX_EVENT => {
+                self.control_tx_evt.read().map_err(|e| {
+                    EpollHelperError::HandleEvent(anyhow!("Failed to get queue event: {:?}", e))
+                })?;
+                let needs_notification = self.process_control_tx_queue().map_err(|e| {
+                    EpollHelperError::HandleEvent(anyhow!(
+                        "Failed to process control tx queue: {:?}",
+                        e
+                    ))
+                })?;
+                if needs_notification {
+                    self.signal_used_queue(CONTROL_TX_QUEUE as u16)
+                        .map_err(|e| {
+                            EpollHelperError::HandleEvent(anyhow!(
+                                "Failed to signal used queue: {:?}",
+                                e
+                            ))
+                        })?;
+                }
+                self.run_control_rx_queue()?;
+            }
+            ev if ev >= PORT_EVENT_BASE
+                && ev < PORT_EVENT_BASE + self.ports.len() as u16 * PORT_EVENTS =>
+            {
+                let port = ((ev - PORT_EVENT_BASE) / PORT_EVENTS) as usize;
+                match (ev - PORT_EVENT_BASE) % PORT_EVENTS {
+                    PORT_RX_QUEUE_EVENT => {
+                        self.ports[port].rx_queue_evt.read().map_err(|e| {
+                            EpollHelperError::HandleEvent(anyhow!(
+                                "Failed to get queue event: {:?}",
+                                e
+                            ))
+                        })?;
+                        self.run_port_rx_queue(helper, port)?;
+                    }
+                    PORT_TX_QUEUE_EVENT => {
+                        self.ports[port].tx_queue_evt.read().map_err(|e| {
+                            EpollHelperError::HandleEvent(anyhow!(
+                                "Failed to get queue event: {:?}",
+                                e
+                            ))
+                        })?;
+                        let needs_notification = self.process_port_tx_queue(port).map_err(|e| {
+                            EpollHelperError::HandleEvent(anyhow!(
+                                "Failed to process port tx queue: {:?}",
+                                e
+                            ))
+                        })?;
+                        if needs_notification {
+                            self.signal_used_queue(port_rx_queue_index(port) + 1)
+                                .map_err(|e| {
+                                    EpollHelperError::HandleEvent(anyhow!(
+                                        "Failed to signal used queue: {:?}",
+                                        e
+                                    ))
+                                })?;
+                        }
+                    }
+                    PORT_LISTENER_EVENT => {
+                        self.accept(helper, port)?;
+                        self.run_control_rx_queue()?;
+                    }
+                    _ => {
+                        self.read_input(helper, port)?;
+                        self.run_port_rx_queue(helper, port)?;
+                        self.run_control_rx_queue()?;
+                    }
+                }
+            }
+            _ => {
+                return Err(EpollHelperError::HandleEvent(anyhow!(
+                    "Unknown event for virtio-console channels"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Virtio console device exposing named channels to the guest.
+pub struct Channels {
+    common: VirtioCommon,
+    id: String,
+    config: VirtioConsoleConfig,
+    ports: Vec<Port>,
+    status: Arc<Mutex<Vec<PortStatus>>>,
+    seccomp_action: SeccompAction,
+    exit_evt: EventFd,
+}
+
+#[derive(Versionize)]
+pub struct ChannelsState {
+    avail_features: u64,
+    acked_features: u64,
+    ready: Vec<bool>,
+    in_buffers: Vec<Vec<u8>>,
+}
+
+impl VersionMapped for ChannelsState {}
+
+impl Channels {
+    /// Create a new virtio console device with one port per channel.
+    pub fn new(
+        id: String,
+        ports: Vec<ChannelPort>,
+        iommu: bool,
+        seccomp_action: SeccompAction,
+        exit_evt: EventFd,
+        state: Option<ChannelsState>,
+    ) -> io::Result<Channels> {
+        let ports = ports
+            .into_iter()
+            .map(|port| {
+                let backend = match port.backend {
+                    ChannelBackend::Socket(path) => {
+                        let listener = UnixListener::bind(&path)?;
+                        listener.set_nonblocking(true)?;
+                        PortBackend::Socket(listener, path)
+                    }
+                    ChannelBackend::Pty(main, sub) => PortBackend::Pty(main, sub),
+                };
+                Ok(Port {
+                    name: port.name,
+                    backend,
+                })
+            })
+            .collect::<io::Result<Vec<Port>>>()?;
+
+        let (avail_features, acked_features, mut status, paused) = if let Some(state) = state {
+            info!("Restoring virtio-console {}", id);
+            let status = state
+                .ready
+                .into_iter()
+                .zip(state.in_buffers)
+                .map(|(ready, in_buffer)| PortStatus {
+                    ready,
+                    in_buffer: in_buffer.into(),
+                })
+                .collect::<Vec<PortStatus>>();
+            (state.avail_features, state.acked_features, status, true)
+        } else {
+            let mut avail_features =
+                1u64 << VIRTIO_F_VERSION_1 | 1u64 << VIRTIO_CONSOLE_F_MULTIPORT;
+            if iommu {
+                avail_features |= 1u64 << VIRTIO_F_IOMMU_PLATFORM;
+            }
+            (avail_features, 0, Vec::new(), false)
+        };
+
+        status.resize_with(ports.len(), PortStatus::default);
+
+        // Every port has a receive and a transmit queue, and the device has
+        // a pair of control queues on top of that.
+        let num_queues = (ports.len() + 1) * 2;
+
+        Ok(Channels {
+            common: VirtioCommon {
+                device_type: VirtioDeviceType::Console as u32,
+                queue_sizes: vec![QUEUE_SIZE; num_queues],
+                avail_features,
+                acked_features,
+                paused_sync: Some(Arc::new(Barrier::new(2))),
+                min_queues: num_queues as u16,
+                paused: Arc::new(AtomicBool::new(paused)),
+                ..Default::default()
+            },
+            id,
+            config: VirtioConsoleConfig {
+                max_nr_ports: ports.len() as u32,
+                ..Default::default()
+            },
+            ports,
+            status: Arc::new(Mutex::new(status)),
+            seccomp_action,
+            exit_evt,
+        })
+    }
+
+    fn state(&self) -> ChannelsState {
+        let status = self.status.lock().unwrap();
+        ChannelsState {
+            avail_features: self.common.avail_features,
+            acked_features: self.common.acked_features,
+            ready: status.iter().map(|port| port.ready).collect(),
+            in_buffers: status
+                .iter()
+                .map(|port| port.in_buffer.clone().into())
+                .collect(),
+        }
+    }
+
+    #[cfg(fuzzing)]
+    pub fn wait_for_epoll_threads(&mut self) {
+        self.common.wait_for_epoll_threads();
+    }
+}
+
+impl Drop for Channels {
+    fn drop(&mut self) {
+        if let Some(kill_evt) = self.common.kill_evt.take() {
+            // Ignore the result because there is nothing we can do about it.
+            let _ = kill_evt.write(1);
+        }
+        self.common.wait_for_epoll_threads();
+    }
+}
+
+impl VirtioDevice for Channels {
+    fn device_type(&self) -> u32 {
+        self.common.device_type
+    }
+
+    fn queue_max_sizes(&self) -> &[u16] {
+        &self.common.queue_sizes
+    }
+
+    fn features(&self) -> u64 {
+        self.common.avail_features
+    }
+
+    fn ack_features(&mut self, value: u64) {
+        self.common.ack_features(value)
+    }
+
+    fn read_config(&self, offset: u64, data: &mut [u8]) {
+        self.read_config_from_slice(self.config.as_slice(), offset, data);
+    }
+
+    fn activate(
+        &mut self,
+        mem: GuestMemoryAtomic<GuestMemoryMmap>,
+        interrupt_cb: Arc<dyn VirtioInterrupt>,
+        mut queues: Vec<(usize, Queue, EventFd)>,
+    ) -> ActivateResult {
+        self.common.activate(&queues, &interrupt_cb)?;
+        let (kill_evt, pause_evt) = self.common.dup_eventfds();
+
+        // The queues of the first port come before the control queues.
+        let (_, port0_rx_queue, port0_rx_queue_evt) = queues.remove(0);
+        let (_, port0_tx_queue, port0_tx_queue_evt) = queues.remove(0);
+        let (_, control_rx_queue, control_rx_evt) = queues.remove(0);
+        let (_, control_tx_queue, control_tx_evt) = queues.remove(0);
+        queues.insert(0, (1, port0_tx_queue, port0_tx_queue_evt));
+        queues.insert(0, (0, port0_rx_queue, port0_rx_queue_evt));
+
+        let mut ports = Vec::new();
+        let mut queues = queues.into_iter();
+        for port in self.ports.iter() {
+            let (_, rx_queue, rx_queue_evt) = queues.next().unwrap();
+            let (_, tx_queue, tx_queue_evt) = queues.next().unwrap();
+            let handler = port
+                .handler(rx_queue, rx_queue_evt, tx_queue, tx_queue_evt)
+                .map_err(|e| {
+                    error!("failed cloning host side of port {}: {}", port.name, e);
+                    ActivateError::BadActivate
+                })?;
+            ports.push(handler);
+        }
+
+        let mut handler = ChannelsEpollHandler {
+            mem,
+            control_rx_queue,
+            control_rx_evt,
+            control_tx_queue,
+            control_tx_evt,
+            ports,
+            status: self.status.clone(),
+            pending_control: VecDeque::new(),
+            interrupt_cb,
+            kill_evt,
+            pause_evt,
+            access_platform: self.common.access_platform.clone(),
+        };
+
+        let paused = self.common.paused.clone();
+        let paused_sync = self.common.paused_sync.clone();
+        let mut epoll_threads = Vec::new();
+
+        spawn_virtio_thread(
+            &self.id,
+            &self.seccomp_action,
+            Thread::VirtioChannels,
+            &mut epoll_threads,
+            &self.exit_evt,
+            move || handler.run(paused, paused_sync.unwrap()),
+        )?;
+
+        self.common.epoll_threads = Some(epoll_threads);
+
+        event!("virtio-device", "activated", "id", &self.id);
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
+        let result = self.common.reset();
+        // The driver announces itself again once it's reloaded.
+        for port in self.status.lock().unwrap().iter_mut() {
+            *port = PortStatus::default();
+        }
+        event!("virtio-device", "reset", "id", &self.id);
+        result
+    }
+
+    fn shutdown(&mut self) {
+        for port in self.ports.iter() {
+            if let PortBackend::Socket(_, path) = &port.backend {
+                std::fs::remove_file(path).ok();
+            }
+        }
+    }
+
+    fn set_access_platform(&mut self, access_platform: Arc<dyn AccessPlatform>) {
+        self.common.set_access_platform(access_platform)
+    }
+}
+
+impl Pausable for Channels {
+    fn pause(&mut self) -> result::Result<(), MigratableError> {
+        self.common.pause()
+    }
+
+    fn resume(&mut self) -> result::Result<(), MigratableError> {
+        self.common.resume()
+    }
+}
+
+impl Snapshottable for Channels {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn snapshot(&mut self) -> std::result::Result<Snapshot, MigratableError> {
+        Snapshot::new_from_versioned_state(&self.state())
+    }
+}
+impl Transportable for Channels {}
+impl Migratable for Channels {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_port_queues() {
+        assert_eq!(port_rx_queue_index(0), 0);
+        assert_eq!(port_rx_queue_index(1), 4);
+        assert_eq!(port_rx_queue_index(2), 6);
+    }
+
+    #[test]
+    fn test_control_message() {
+        assert_eq!(
+            control_message(2, VIRTIO_CONSOLE_PORT_NAME, 0, b"agent"),
+            [2, 0, 0, 0, 7, 0, 0, 0, b'a', b'g', b'e', b'n', b't']
+        );
+        assert_eq!(
+            control_message(1, VIRTIO_CONSOLE_PORT_OPEN, 1, &[]),
+            [1, 0, 0, 0, 6, 0, 1, 0]
+        );
+    }
+}
@@ -68,10 +68,10 @@ enum Error {
 #[derive(Copy, Clone, Debug, Versionize)]
 #[repr(C, packed)]
 pub struct VirtioConsoleConfig {
-    cols: u16,
-    rows: u16,
-    max_nr_ports: u32,
-    emerg_wr: u32,
+    pub(crate) cols: u16,
+    pub(crate) rows: u16,
+    pub(crate) max_nr_ports: u32,
+    pub(crate) emerg_wr: u32,
 }
 
 impl Default for VirtioConsoleConfig {
@@ -0,0 +1,318 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Symmetric algorithms offloaded to the host kernel crypto API through
+//! AF_ALG sockets, benefiting from any hardware acceleration the host kernel
+//! has drivers for.
+
+use super::{AeadAlgo, AeadCipher, AesMode, Status, SymCipher, AEAD_NONCE_LEN};
+use std::fs::File;
+use std::io;
+use std::mem::size_of;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::ptr::null_mut;
+
+// Algorithm used to check the availability of AF_ALG when creating the
+// device.
+const PROBE_ALGORITHM: &str = "cbc(aes)";
+
+// Socket bound to an algorithm, along with the socket accepted from it once
+// the key is set, which runs the operations of a session.
+struct AlgSocket {
+    _tfm: File,
+    op: File,
+}
+
+// Returns a socket bound to the given algorithm.
+fn bind(alg_type: &str, alg_name: &str) -> io::Result<File> {
+    // SAFETY: FFI call with valid arguments.
+    let fd = unsafe { libc::socket(libc::AF_ALG, libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: fd is a valid socket we own.
+    let tfm = unsafe { File::from_raw_fd(fd) };
+
+    // SAFETY: sockaddr_alg only holds integers and byte arrays.
+    let mut addr: libc::sockaddr_alg = unsafe { std::mem::zeroed() };
+    addr.salg_family = libc::AF_ALG as u16;
+    if alg_type.len() >= addr.salg_type.len() || alg_name.len() >= addr.salg_name.len() {
+        return Err(io::Error::from_raw_os_error(libc::ENAMETOOLONG));
+    }
+    addr.salg_type[..alg_type.len()].copy_from_slice(alg_type.as_bytes());
+    addr.salg_name[..alg_name.len()].copy_from_slice(alg_name.as_bytes());
+
+    // SAFETY: addr is a properly initialized sockaddr_alg.
+    let ret = unsafe {
+        libc::bind(
+            tfm.as_raw_fd(),
+            &addr as *const libc::sockaddr_alg as *const libc::sockaddr,
+            size_of::<libc::sockaddr_alg>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(tfm)
+}
+
+impl AlgSocket {
+    fn new(alg_type: &str, alg_name: &str, key: &[u8], tag_len: Option<usize>) -> io::Result<Self> {
+        let tfm = bind(alg_type, alg_name)?;
+
+        // SAFETY: the kernel reads key.len() bytes from the key.
+        let ret = unsafe {
+            libc::setsockopt(
+                tfm.as_raw_fd(),
+                libc::SOL_ALG,
+                libc::ALG_SET_KEY,
+                key.as_ptr() as *const libc::c_void,
+                key.len() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        if let Some(tag_len) = tag_len {
+            // SAFETY: the tag size is given through the option length.
+            let ret = unsafe {
+                libc::setsockopt(
+                    tfm.as_raw_fd(),
+                    libc::SOL_ALG,
+                    libc::ALG_SET_AEAD_AUTHSIZE,
+                    null_mut(),
+                    tag_len as libc::socklen_t,
+                )
+            };
+            if ret < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        // SAFETY: FFI call with valid arguments.
+        let fd =
+            unsafe { libc::accept4(tfm.as_raw_fd(), null_mut(), null_mut(), libc::SOCK_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: fd is a valid socket we own.
+        let op = unsafe { File::from_raw_fd(fd) };
+
+        Ok(AlgSocket { _tfm: tfm, op })
+    }
+
+    // Runs a single operation on `input`, returning the `output_len` bytes
+    // produced by the kernel.
+    fn run(
+        &self,
+        encrypt: bool,
+        iv: &[u8],
+        assoc_len: Option<usize>,
+        input: &[u8],
+        output_len: usize,
+    ) -> io::Result<Vec<u8>> {
+        let op_len = size_of::<u32>();
+        let iv_len = size_of::<u32>() + iv.len();
+        // SAFETY: CMSG_SPACE only computes a size.
+        let control_len = unsafe {
+            libc::CMSG_SPACE(op_len as u32)
+                + libc::CMSG_SPACE(iv_len as u32)
+                + libc::CMSG_SPACE(size_of::<u32>() as u32)
+        } as usize;
+        // Use 64 bits words so that the control messages are aligned.
+        let mut control = vec![0u64; (control_len + 7) / 8];
+
+        let mut iov = libc::iovec {
+            iov_base: input.as_ptr() as *mut libc::c_void,
+            iov_len: input.len(),
+        };
+        // SAFETY: msghdr only holds integers and pointers.
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = control_len as _;
+
+        let op = if encrypt {
+            libc::ALG_OP_ENCRYPT
+        } else {
+            libc::ALG_OP_DECRYPT
+        } as u32;
+        let mut iv_data = (iv.len() as u32).to_ne_bytes().to_vec();
+        iv_data.extend_from_slice(iv);
+        let mut messages = vec![
+            (libc::ALG_SET_OP, op.to_ne_bytes().to_vec()),
+            (libc::ALG_SET_IV, iv_data),
+        ];
+        if let Some(assoc_len) = assoc_len {
+            messages.push((
+                libc::ALG_SET_AEAD_ASSOCLEN,
+                (assoc_len as u32).to_ne_bytes().to_vec(),
+            ));
+        }
+
+        let mut used_len = 0;
+        // SAFETY: the control buffer is large enough for all the messages,
+        // and each of them is written within the space CMSG_NXTHDR gives.
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            for (cmsg_type, data) in messages {
+                (*cmsg).cmsg_level = libc::SOL_ALG;
+                (*cmsg).cmsg_type = cmsg_type;
+                (*cmsg).cmsg_len = libc::CMSG_LEN(data.len() as u32) as _;
+                std::ptr::copy_nonoverlapping(data.as_ptr(), libc::CMSG_DATA(cmsg), data.len());
+                used_len += libc::CMSG_SPACE(data.len() as u32) as usize;
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+        msg.msg_controllen = used_len as _;
+
+        // SAFETY: msg points to the input and control buffers, both valid
+        // until the call returns.
+        let ret = unsafe { libc::sendmsg(self.op.as_raw_fd(), &msg, 0) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if ret as usize != input.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("Short write to AF_ALG socket: {} of {}", ret, input.len()),
+            ));
+        }
+
+        let mut output = vec![0u8; output_len];
+        // SAFETY: output is output_len bytes long.
+        let ret = unsafe {
+            libc::read(
+                self.op.as_raw_fd(),
+                output.as_mut_ptr() as *mut libc::c_void,
+                output_len,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        output.truncate(ret as usize);
+
+        Ok(output)
+    }
+}
+
+fn open_error(name: &str, e: io::Error) -> Status {
+    match e.raw_os_error() {
+        // The host kernel doesn't provide the algorithm.
+        Some(libc::ENOENT) => Status::NotSupported,
+        // The key size isn't supported by the algorithm.
+        Some(libc::EINVAL) => Status::NotSupported,
+        _ => {
+            error!("Failed to set up {} through AF_ALG: {}", name, e);
+            Status::Err
+        }
+    }
+}
+
+/// Checks that the host kernel crypto API can be used.
+pub(super) fn probe() -> io::Result<()> {
+    bind("skcipher", PROBE_ALGORITHM).map(|_| ())
+}
+
+pub(super) struct AfAlgCipher {
+    socket: AlgSocket,
+    encrypt: bool,
+}
+
+impl AfAlgCipher {
+    pub fn new(mode: AesMode, key: &[u8], encrypt: bool) -> Result<Self, Status> {
+        let name = match mode {
+            AesMode::Ecb => "ecb(aes)",
+            AesMode::Cbc => "cbc(aes)",
+            AesMode::Ctr => "ctr(aes)",
+        };
+        let socket =
+            AlgSocket::new("skcipher", name, key, None).map_err(|e| open_error(name, e))?;
+
+        Ok(AfAlgCipher { socket, encrypt })
+    }
+}
+
+impl SymCipher for AfAlgCipher {
+    fn process(&mut self, iv: &[u8], src: &[u8]) -> Result<Vec<u8>, Status> {
+        if src.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        match self.socket.run(self.encrypt, iv, None, src, src.len()) {
+            Ok(output) if output.len() == src.len() => Ok(output),
+            Ok(_) => Err(Status::Err),
+            // Lengths which aren't a multiple of the block size, or IVs of
+            // the wrong size.
+            Err(e) if e.raw_os_error() == Some(libc::EINVAL) => Err(Status::BadMsg),
+            Err(e) => {
+                error!("Failed to run AF_ALG cipher operation: {}", e);
+                Err(Status::Err)
+            }
+        }
+    }
+}
+
+pub(super) struct AfAlgAead {
+    socket: AlgSocket,
+    tag_len: usize,
+    encrypt: bool,
+}
+
+impl AfAlgAead {
+    pub fn new(algo: AeadAlgo, key: &[u8], tag_len: usize, encrypt: bool) -> Result<Self, Status> {
+        let name = match algo {
+            AeadAlgo::AesGcm => "gcm(aes)",
+            AeadAlgo::ChaCha20Poly1305 => "rfc7539(chacha20,poly1305)",
+        };
+        let socket =
+            AlgSocket::new("aead", name, key, Some(tag_len)).map_err(|e| open_error(name, e))?;
+
+        Ok(AfAlgAead {
+            socket,
+            tag_len,
+            encrypt,
+        })
+    }
+}
+
+impl AeadCipher for AfAlgAead {
+    fn process(&mut self, iv: &[u8], aad: &[u8], src: &[u8]) -> Result<Vec<u8>, Status> {
+        if iv.len() != AEAD_NONCE_LEN {
+            return Err(Status::BadMsg);
+        }
+
+        // The kernel takes the associated data followed by the text, and
+        // gives them back the same way.
+        let output_len = if self.encrypt {
+            src.len() + self.tag_len
+        } else {
+            src.len().checked_sub(self.tag_len).ok_or(Status::BadMsg)?
+        };
+        let mut input = aad.to_vec();
+        input.extend_from_slice(src);
+
+        match self.socket.run(
+            self.encrypt,
+            iv,
+            Some(aad.len()),
+            &input,
+            aad.len() + output_len,
+        ) {
+            Ok(output) if output.len() == aad.len() + output_len => {
+                Ok(output[aad.len()..].to_vec())
+            }
+            Ok(_) => Err(Status::Err),
+            Err(e) if e.raw_os_error() == Some(libc::EBADMSG) => Err(Status::BadMsg),
+            Err(e) => {
+                error!("Failed to run AF_ALG AEAD operation: {}", e);
+                Err(Status::Err)
+            }
+        }
+    }
+}
@@ -0,0 +1,190 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! RSA operations, always implemented within the VMM since the host kernel
+//! doesn't expose asymmetric ciphers through AF_ALG.
+
+use super::Status;
+use rsa::pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey};
+use rsa::rand_core::OsRng;
+use rsa::{
+    BigUint, Pkcs1v15Encrypt, Pkcs1v15Sign, PublicKey, PublicKeyParts, RsaPrivateKey, RsaPublicKey,
+};
+
+// Got from include/uapi/linux/virtio_crypto.h
+pub(super) const VIRTIO_CRYPTO_AKCIPHER_KEY_TYPE_PUBLIC: u32 = 1;
+pub(super) const VIRTIO_CRYPTO_AKCIPHER_KEY_TYPE_PRIVATE: u32 = 2;
+const VIRTIO_CRYPTO_RSA_RAW_PADDING: u32 = 0;
+const VIRTIO_CRYPTO_RSA_PKCS1_PADDING: u32 = 1;
+const VIRTIO_CRYPTO_RSA_NO_HASH: u32 = 0;
+const VIRTIO_CRYPTO_RSA_MD5: u32 = 4;
+const VIRTIO_CRYPTO_RSA_SHA1: u32 = 5;
+const VIRTIO_CRYPTO_RSA_SHA256: u32 = 6;
+const VIRTIO_CRYPTO_RSA_SHA384: u32 = 7;
+const VIRTIO_CRYPTO_RSA_SHA512: u32 = 8;
+const VIRTIO_CRYPTO_RSA_SHA224: u32 = 9;
+
+// DER encoded DigestInfo prefixes of the PKCS #1 v1.5 signatures, from RFC
+// 8017, section 9.2.
+const MD5_PREFIX: &[u8] = &[
+    0x30, 0x20, 0x30, 0x0c, 0x06, 0x08, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x02, 0x05, 0x05, 0x00,
+    0x04, 0x10,
+];
+const SHA1_PREFIX: &[u8] = &[
+    0x30, 0x21, 0x30, 0x09, 0x06, 0x05, 0x2b, 0x0e, 0x03, 0x02, 0x1a, 0x05, 0x00, 0x04, 0x14,
+];
+const SHA224_PREFIX: &[u8] = &[
+    0x30, 0x2d, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x04, 0x05,
+    0x00, 0x04, 0x1c,
+];
+const SHA256_PREFIX: &[u8] = &[
+    0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x05,
+    0x00, 0x04, 0x20,
+];
+const SHA384_PREFIX: &[u8] = &[
+    0x30, 0x41, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x02, 0x05,
+    0x00, 0x04, 0x30,
+];
+const SHA512_PREFIX: &[u8] = &[
+    0x30, 0x51, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x03, 0x05,
+    0x00, 0x04, 0x40,
+];
+
+fn signature_scheme(hash: u32) -> Result<Pkcs1v15Sign, Status> {
+    let (hash_len, prefix) = match hash {
+        VIRTIO_CRYPTO_RSA_NO_HASH => return Ok(Pkcs1v15Sign::new_raw()),
+        VIRTIO_CRYPTO_RSA_MD5 => (16, MD5_PREFIX),
+        VIRTIO_CRYPTO_RSA_SHA1 => (20, SHA1_PREFIX),
+        VIRTIO_CRYPTO_RSA_SHA224 => (28, SHA224_PREFIX),
+        VIRTIO_CRYPTO_RSA_SHA256 => (32, SHA256_PREFIX),
+        VIRTIO_CRYPTO_RSA_SHA384 => (48, SHA384_PREFIX),
+        VIRTIO_CRYPTO_RSA_SHA512 => (64, SHA512_PREFIX),
+        _ => return Err(Status::NotSupported),
+    };
+
+    Ok(Pkcs1v15Sign {
+        hash_len: Some(hash_len),
+        prefix: prefix.into(),
+    })
+}
+
+// Left pads a big endian integer with zeros to the size of the modulus.
+fn to_padded_bytes(value: &BigUint, size: usize) -> Vec<u8> {
+    let bytes = value.to_bytes_be();
+    let mut padded = vec![0u8; size.saturating_sub(bytes.len())];
+    padded.extend_from_slice(&bytes);
+    padded
+}
+
+/// RSA session, holding the key given by the guest in the PKCS #1 DER
+/// format.
+pub(super) struct RsaSession {
+    public_key: RsaPublicKey,
+    private_key: Option<RsaPrivateKey>,
+    padding: u32,
+    hash: u32,
+}
+
+impl RsaSession {
+    pub fn new(key_type: u32, key: &[u8], padding: u32, hash: u32) -> Result<Self, Status> {
+        if !matches!(
+            padding,
+            VIRTIO_CRYPTO_RSA_RAW_PADDING | VIRTIO_CRYPTO_RSA_PKCS1_PADDING
+        ) {
+            return Err(Status::NotSupported);
+        }
+        // Only make sure the hash is known, it only matters to signatures.
+        if padding == VIRTIO_CRYPTO_RSA_PKCS1_PADDING {
+            signature_scheme(hash)?;
+        }
+
+        let (public_key, private_key) = match key_type {
+            VIRTIO_CRYPTO_AKCIPHER_KEY_TYPE_PUBLIC => (
+                RsaPublicKey::from_pkcs1_der(key).map_err(|_| Status::BadMsg)?,
+                None,
+            ),
+            VIRTIO_CRYPTO_AKCIPHER_KEY_TYPE_PRIVATE => {
+                let private_key = RsaPrivateKey::from_pkcs1_der(key).map_err(|_| Status::BadMsg)?;
+                (private_key.to_public_key(), Some(private_key))
+            }
+            _ => return Err(Status::BadMsg),
+        };
+
+        Ok(RsaSession {
+            public_key,
+            private_key,
+            padding,
+            hash,
+        })
+    }
+
+    fn private_key(&self) -> Result<&RsaPrivateKey, Status> {
+        self.private_key.as_ref().ok_or(Status::NotSupported)
+    }
+
+    // Computes `input ^ exponent mod n`, without any padding.
+    fn raw(&self, input: &[u8], exponent: &BigUint) -> Result<Vec<u8>, Status> {
+        let n = self.public_key.n();
+        let value = BigUint::from_bytes_be(input);
+        if &value >= n {
+            return Err(Status::BadMsg);
+        }
+
+        Ok(to_padded_bytes(
+            &value.modpow(exponent, n),
+            self.public_key.size(),
+        ))
+    }
+
+    pub fn encrypt(&self, src: &[u8]) -> Result<Vec<u8>, Status> {
+        if self.padding == VIRTIO_CRYPTO_RSA_RAW_PADDING {
+            self.raw(src, self.public_key.e())
+        } else {
+            self.public_key
+                .encrypt(&mut OsRng, Pkcs1v15Encrypt, src)
+                .map_err(|_| Status::BadMsg)
+        }
+    }
+
+    pub fn decrypt(&self, src: &[u8]) -> Result<Vec<u8>, Status> {
+        let private_key = self.private_key()?;
+        if self.padding == VIRTIO_CRYPTO_RSA_RAW_PADDING {
+            self.raw(src, private_key.d())
+        } else {
+            private_key
+                .decrypt(Pkcs1v15Encrypt, src)
+                .map_err(|_| Status::BadMsg)
+        }
+    }
+
+    pub fn sign(&self, digest: &[u8]) -> Result<Vec<u8>, Status> {
+        let private_key = self.private_key()?;
+        if self.padding == VIRTIO_CRYPTO_RSA_RAW_PADDING {
+            self.raw(digest, private_key.d())
+        } else {
+            private_key
+                .sign(signature_scheme(self.hash)?, digest)
+                .map_err(|_| Status::BadMsg)
+        }
+    }
+
+    pub fn verify(&self, signature: &[u8], digest: &[u8]) -> Result<(), Status> {
+        let valid = if self.padding == VIRTIO_CRYPTO_RSA_RAW_PADDING {
+            let size = self.public_key.size();
+            digest.len() <= size
+                && self.raw(signature, self.public_key.e())?
+                    == to_padded_bytes(&BigUint::from_bytes_be(digest), size)
+        } else {
+            self.public_key
+                .verify(signature_scheme(self.hash)?, digest, signature)
+                .is_ok()
+        };
+
+        if valid {
+            Ok(())
+        } else {
+            Err(Status::KeyRejected)
+        }
+    }
+}
@@ -0,0 +1,1139 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Virtio crypto device, offering the guest symmetric ciphers (AES in the
+//! ECB, CBC and CTR modes), AEAD ciphers (AES-GCM and ChaCha20-Poly1305) and
+//! RSA.
+//!
+//! The symmetric algorithms are run either by the host kernel, through AF_ALG
+//! sockets, or by a software implementation within the VMM. RSA is always
+//! implemented in software.
+
+mod af_alg;
+mod akcipher;
+mod software;
+
+use self::af_alg::{AfAlgAead, AfAlgCipher};
+use self::akcipher::RsaSession;
+use self::software::{SoftwareAead, SoftwareCipher};
+use super::{
+    ActivateError, ActivateResult, EpollHelper, EpollHelperError, EpollHelperHandler, VirtioCommon,
+    VirtioDevice, VirtioDeviceType, EPOLL_HELPER_EVENT_LAST, VIRTIO_F_IOMMU_PLATFORM,
+    VIRTIO_F_VERSION_1,
+};
+use crate::regions::{self, parse_chain, Regions};
+use crate::seccomp_filters::Thread;
+use crate::thread_helper::spawn_virtio_thread;
+use crate::GuestMemoryMmap;
+use crate::{VirtioInterrupt, VirtioInterruptType};
+use anyhow::anyhow;
+use seccompiler::SeccompAction;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::result;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Barrier, Mutex};
+use thiserror::Error;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+use virtio_queue::{Queue, QueueT};
+use vm_memory::{ByteValued, GuestAddressSpace, GuestMemoryAtomic};
+use vm_migration::VersionMapped;
+use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
+use vm_virtio::AccessPlatform;
+use vmm_sys_util::eventfd::EventFd;
+
+const QUEUE_SIZE: u16 = 256;
+
+/// Highest number of data queues a crypto device can have.
+pub const CRYPTO_MAX_DATA_QUEUES: usize = 64;
+
+// New descriptors are pending on the control queue.
+const CONTROL_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 1;
+// New descriptors are pending on a data queue, the event of the Nth one
+// being DATA_QUEUE_EVENT + N.
+const DATA_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 2;
+
+// Largest content of a data request, keeping the requests offloaded to the
+// host kernel within the default size of the AF_ALG socket buffers.
+const MAX_REQUEST_SIZE: usize = 64 * 1024;
+const MAX_CIPHER_KEY_LEN: u32 = 32;
+// Large enough for the PKCS #1 encoding of a 4096 bits private key.
+const MAX_AKCIPHER_KEY_LEN: usize = 4096;
+const MAX_SESSIONS: usize = 1024;
+
+const AEAD_NONCE_LEN: usize = 12;
+const AEAD_TAG_LEN: usize = 16;
+
+// Got from include/uapi/linux/virtio_crypto.h
+const VIRTIO_CRYPTO_SERVICE_CIPHER: u32 = 0;
+const VIRTIO_CRYPTO_SERVICE_AEAD: u32 = 3;
+const VIRTIO_CRYPTO_SERVICE_AKCIPHER: u32 = 4;
+
+const VIRTIO_CRYPTO_CIPHER_CREATE_SESSION: u32 = 0x002;
+const VIRTIO_CRYPTO_CIPHER_DESTROY_SESSION: u32 = 0x003;
+const VIRTIO_CRYPTO_HASH_DESTROY_SESSION: u32 = 0x103;
+const VIRTIO_CRYPTO_MAC_DESTROY_SESSION: u32 = 0x203;
+const VIRTIO_CRYPTO_AEAD_CREATE_SESSION: u32 = 0x302;
+const VIRTIO_CRYPTO_AEAD_DESTROY_SESSION: u32 = 0x303;
+const VIRTIO_CRYPTO_AKCIPHER_CREATE_SESSION: u32 = 0x404;
+const VIRTIO_CRYPTO_AKCIPHER_DESTROY_SESSION: u32 = 0x405;
+
+const VIRTIO_CRYPTO_CIPHER_ENCRYPT: u32 = 0x000;
+const VIRTIO_CRYPTO_CIPHER_DECRYPT: u32 = 0x001;
+const VIRTIO_CRYPTO_AEAD_ENCRYPT: u32 = 0x300;
+const VIRTIO_CRYPTO_AEAD_DECRYPT: u32 = 0x301;
+const VIRTIO_CRYPTO_AKCIPHER_ENCRYPT: u32 = 0x400;
+const VIRTIO_CRYPTO_AKCIPHER_DECRYPT: u32 = 0x401;
+const VIRTIO_CRYPTO_AKCIPHER_SIGN: u32 = 0x402;
+const VIRTIO_CRYPTO_AKCIPHER_VERIFY: u32 = 0x403;
+
+const VIRTIO_CRYPTO_CIPHER_AES_ECB: u32 = 2;
+const VIRTIO_CRYPTO_CIPHER_AES_CBC: u32 = 3;
+const VIRTIO_CRYPTO_CIPHER_AES_CTR: u32 = 4;
+const VIRTIO_CRYPTO_AEAD_GCM: u32 = 1;
+const VIRTIO_CRYPTO_AEAD_CHACHA20_POLY1305: u32 = 3;
+const VIRTIO_CRYPTO_AKCIPHER_RSA: u32 = 1;
+
+const VIRTIO_CRYPTO_OP_ENCRYPT: u32 = 1;
+const VIRTIO_CRYPTO_OP_DECRYPT: u32 = 2;
+const VIRTIO_CRYPTO_SYM_OP_CIPHER: u32 = 1;
+
+const VIRTIO_CRYPTO_S_HW_READY: u32 = 1;
+
+// Size of the virtio_crypto_op_ctrl_req and virtio_crypto_op_data_req
+// structures, each made of a header followed by a union of the parameters
+// of the various requests.
+const CTRL_REQ_SIZE: usize = 72;
+const DATA_REQ_SIZE: usize = 72;
+// Size of the virtio_crypto_session_input structure.
+const SESSION_INPUT_SIZE: usize = 16;
+// Size of the virtio_crypto_inhdr structure, holding the status of a request.
+const INHDR_SIZE: usize = 1;
+
+// Offsets of the fields of the control requests.
+const CTRL_OPCODE: usize = 0;
+const CTRL_SESSION_ALGO: usize = 16;
+const CTRL_SESSION_ID: usize = 16;
+const CTRL_CIPHER_KEY_LEN: usize = 20;
+const CTRL_CIPHER_OP: usize = 24;
+const CTRL_SYM_OP_TYPE: usize = 64;
+const CTRL_AEAD_KEY_LEN: usize = 20;
+const CTRL_AEAD_TAG_LEN: usize = 24;
+const CTRL_AEAD_OP: usize = 32;
+const CTRL_AKCIPHER_KEY_TYPE: usize = 20;
+const CTRL_AKCIPHER_KEY_LEN: usize = 24;
+const CTRL_RSA_PADDING: usize = 28;
+const CTRL_RSA_HASH: usize = 32;
+
+// Offsets of the fields of the data requests.
+const DATA_OPCODE: usize = 0;
+const DATA_SESSION_ID: usize = 8;
+const DATA_CIPHER_IV_LEN: usize = 24;
+const DATA_CIPHER_SRC_LEN: usize = 28;
+const DATA_CIPHER_DST_LEN: usize = 32;
+const DATA_SYM_OP_TYPE: usize = 64;
+const DATA_AEAD_IV_LEN: usize = 24;
+const DATA_AEAD_AAD_LEN: usize = 28;
+const DATA_AEAD_SRC_LEN: usize = 32;
+const DATA_AEAD_DST_LEN: usize = 36;
+const DATA_AKCIPHER_SRC_LEN: usize = 24;
+const DATA_AKCIPHER_DST_LEN: usize = 28;
+
+#[derive(Error, Debug)]
+enum Error {
+    #[error("Descriptor chain too short")]
+    DescriptorChainTooShort,
+    #[error("Failed to read from guest memory: {0}")]
+    GuestMemoryRead(vm_memory::guest_memory::Error),
+    #[error("Failed to write to guest memory: {0}")]
+    GuestMemoryWrite(vm_memory::guest_memory::Error),
+    #[error("Failed adding used index: {0}")]
+    QueueAddUsed(virtio_queue::Error),
+    #[error("Failed to signal used queue: {0}")]
+    SignalUsedQueue(io::Error),
+}
+
+impl From<regions::Error> for Error {
+    fn from(e: regions::Error) -> Self {
+        match e {
+            regions::Error::DescriptorChainTooShort => Error::DescriptorChainTooShort,
+            regions::Error::GuestMemoryRead(e) => Error::GuestMemoryRead(e),
+            regions::Error::GuestMemoryWrite(e) => Error::GuestMemoryWrite(e),
+        }
+    }
+}
+
+/// Status of a request, as reported to the guest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Status {
+    Ok = 0,
+    Err = 1,
+    BadMsg = 2,
+    NotSupported = 3,
+    InvalidSession = 4,
+    NoSpace = 5,
+    KeyRejected = 6,
+}
+
+/// Implementation of the symmetric algorithms.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CryptoBackend {
+    /// Host kernel crypto API, through AF_ALG sockets.
+    AfAlg,
+    /// Software implementation within the VMM.
+    Software,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum AesMode {
+    Ecb,
+    Cbc,
+    Ctr,
+}
+
+impl AesMode {
+    fn from_u32(algo: u32) -> Option<Self> {
+        match algo {
+            VIRTIO_CRYPTO_CIPHER_AES_ECB => Some(AesMode::Ecb),
+            VIRTIO_CRYPTO_CIPHER_AES_CBC => Some(AesMode::Cbc),
+            VIRTIO_CRYPTO_CIPHER_AES_CTR => Some(AesMode::Ctr),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum AeadAlgo {
+    AesGcm,
+    ChaCha20Poly1305,
+}
+
+impl AeadAlgo {
+    fn from_u32(algo: u32) -> Option<Self> {
+        match algo {
+            VIRTIO_CRYPTO_AEAD_GCM => Some(AeadAlgo::AesGcm),
+            VIRTIO_CRYPTO_AEAD_CHACHA20_POLY1305 => Some(AeadAlgo::ChaCha20Poly1305),
+            _ => None,
+        }
+    }
+}
+
+/// Symmetric cipher set up with the key and the direction of a session.
+trait SymCipher: Send {
+    fn process(&mut self, iv: &[u8], src: &[u8]) -> result::Result<Vec<u8>, Status>;
+}
+
+/// AEAD cipher set up with the key, the tag length and the direction of a
+/// session. Encryption appends the tag to the output, and decryption expects
+/// it at the end of the input.
+trait AeadCipher: Send {
+    fn process(&mut self, iv: &[u8], aad: &[u8], src: &[u8]) -> result::Result<Vec<u8>, Status>;
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+// Takes the next `len` bytes of the variable length part of a request.
+fn take<'a>(payload: &mut &'a [u8], len: u32) -> result::Result<&'a [u8], Status> {
+    let len = len as usize;
+    if len > payload.len() {
+        return Err(Status::BadMsg);
+    }
+    let (head, tail) = payload.split_at(len);
+    *payload = tail;
+    Ok(head)
+}
+
+fn encrypt_op(op: u32) -> result::Result<bool, Status> {
+    match op {
+        VIRTIO_CRYPTO_OP_ENCRYPT => Ok(true),
+        VIRTIO_CRYPTO_OP_DECRYPT => Ok(false),
+        _ => Err(Status::BadMsg),
+    }
+}
+
+enum SessionOp {
+    Cipher(Box<dyn SymCipher>),
+    Aead(Box<dyn AeadCipher>),
+    Akcipher(Box<RsaSession>),
+}
+
+// Length of the key following a session creation request.
+fn session_key_len(request: &[u8]) -> u32 {
+    match read_u32(request, CTRL_OPCODE) {
+        VIRTIO_CRYPTO_CIPHER_CREATE_SESSION => read_u32(request, CTRL_CIPHER_KEY_LEN),
+        VIRTIO_CRYPTO_AEAD_CREATE_SESSION => read_u32(request, CTRL_AEAD_KEY_LEN),
+        VIRTIO_CRYPTO_AKCIPHER_CREATE_SESSION => read_u32(request, CTRL_AKCIPHER_KEY_LEN),
+        _ => 0,
+    }
+}
+
+fn create_session(
+    backend: CryptoBackend,
+    request: &[u8],
+    key: &[u8],
+) -> result::Result<SessionOp, Status> {
+    let algo = read_u32(request, CTRL_SESSION_ALGO);
+    match read_u32(request, CTRL_OPCODE) {
+        VIRTIO_CRYPTO_CIPHER_CREATE_SESSION => {
+            // Chaining a cipher with a hash isn't supported.
+            if read_u32(request, CTRL_SYM_OP_TYPE) != VIRTIO_CRYPTO_SYM_OP_CIPHER {
+                return Err(Status::NotSupported);
+            }
+            let algo = AesMode::from_u32(algo).ok_or(Status::NotSupported)?;
+            let encrypt = encrypt_op(read_u32(request, CTRL_CIPHER_OP))?;
+            let cipher: Box<dyn SymCipher> = match backend {
+                CryptoBackend::AfAlg => Box::new(AfAlgCipher::new(algo, key, encrypt)?),
+                CryptoBackend::Software => Box::new(SoftwareCipher::new(algo, key, encrypt)?),
+            };
+            Ok(SessionOp::Cipher(cipher))
+        }
+        VIRTIO_CRYPTO_AEAD_CREATE_SESSION => {
+            let algo = AeadAlgo::from_u32(algo).ok_or(Status::NotSupported)?;
+            let tag_len = read_u32(request, CTRL_AEAD_TAG_LEN) as usize;
+            let encrypt = encrypt_op(read_u32(request, CTRL_AEAD_OP))?;
+            let aead: Box<dyn AeadCipher> = match backend {
+                CryptoBackend::AfAlg => Box::new(AfAlgAead::new(algo, key, tag_len, encrypt)?),
+                CryptoBackend::Software => {
+                    Box::new(SoftwareAead::new(algo, key, tag_len, encrypt)?)
+                }
+            };
+            Ok(SessionOp::Aead(aead))
+        }
+        VIRTIO_CRYPTO_AKCIPHER_CREATE_SESSION => {
+            if algo != VIRTIO_CRYPTO_AKCIPHER_RSA {
+                return Err(Status::NotSupported);
+            }
+            Ok(SessionOp::Akcipher(Box::new(RsaSession::new(
+                read_u32(request, CTRL_AKCIPHER_KEY_TYPE),
+                key,
+                read_u32(request, CTRL_RSA_PADDING),
+                read_u32(request, CTRL_RSA_HASH),
+            )?)))
+        }
+        // Hashes and MACs aren't supported.
+        _ => Err(Status::NotSupported),
+    }
+}
+
+struct Session {
+    // Creation request and key, kept to recreate the session on restore.
+    request: Vec<u8>,
+    key: Vec<u8>,
+    op: SessionOp,
+}
+
+#[derive(Default)]
+struct Sessions {
+    next_id: u64,
+    sessions: BTreeMap<u64, Session>,
+}
+
+impl Sessions {
+    fn restore(backend: CryptoBackend, state: &CryptoState) -> io::Result<Self> {
+        let mut sessions = BTreeMap::new();
+        for session in state.sessions.iter() {
+            let op = create_session(backend, &session.request, &session.key).map_err(|status| {
+                io::Error::new(
+                    io::ErrorKind::Other,
+                    format!(
+                        "Failed to restore crypto session {}: {:?}",
+                        session.id, status
+                    ),
+                )
+            })?;
+            sessions.insert(
+                session.id,
+                Session {
+                    request: session.request.clone(),
+                    key: session.key.clone(),
+                    op,
+                },
+            );
+        }
+
+        Ok(Sessions {
+            next_id: state.next_session_id,
+            sessions,
+        })
+    }
+
+    fn create(
+        &mut self,
+        backend: CryptoBackend,
+        request: Vec<u8>,
+        key: Vec<u8>,
+    ) -> result::Result<u64, Status> {
+        if self.sessions.len() >= MAX_SESSIONS {
+            return Err(Status::NoSpace);
+        }
+
+        let op = create_session(backend, &request, &key)?;
+        let id = self.next_id;
+        self.next_id += 1;
+        self.sessions.insert(id, Session { request, key, op });
+
+        Ok(id)
+    }
+
+    // Runs a data request, given its header and the variable length part
+    // following it, returning the output to write to the guest.
+    fn execute(
+        &mut self,
+        request: &[u8],
+        mut payload: &[u8],
+        dst_len: usize,
+    ) -> result::Result<Vec<u8>, Status> {
+        let session = self
+            .sessions
+            .get_mut(&read_u64(request, DATA_SESSION_ID))
+            .ok_or(Status::InvalidSession)?;
+
+        let output = match (read_u32(request, DATA_OPCODE), &mut session.op) {
+            (
+                VIRTIO_CRYPTO_CIPHER_ENCRYPT | VIRTIO_CRYPTO_CIPHER_DECRYPT,
+                SessionOp::Cipher(cipher),
+            ) => {
+                if read_u32(request, DATA_SYM_OP_TYPE) != VIRTIO_CRYPTO_SYM_OP_CIPHER {
+                    return Err(Status::NotSupported);
+                }
+                let iv = take(&mut payload, read_u32(request, DATA_CIPHER_IV_LEN))?;
+                let src = take(&mut payload, read_u32(request, DATA_CIPHER_SRC_LEN))?;
+                if (read_u32(request, DATA_CIPHER_DST_LEN) as usize) < src.len() {
+                    return Err(Status::BadMsg);
+                }
+                cipher.process(iv, src)?
+            }
+            (VIRTIO_CRYPTO_AEAD_ENCRYPT | VIRTIO_CRYPTO_AEAD_DECRYPT, SessionOp::Aead(aead)) => {
+                let iv = take(&mut payload, read_u32(request, DATA_AEAD_IV_LEN))?;
+                let aad = take(&mut payload, read_u32(request, DATA_AEAD_AAD_LEN))?;
+                let src = take(&mut payload, read_u32(request, DATA_AEAD_SRC_LEN))?;
+                let output = aead.process(iv, aad, src)?;
+                if (read_u32(request, DATA_AEAD_DST_LEN) as usize) < output.len() {
+                    return Err(Status::BadMsg);
+                }
+                output
+            }
+            (opcode, SessionOp::Akcipher(rsa)) => {
+                let src = take(&mut payload, read_u32(request, DATA_AKCIPHER_SRC_LEN))?;
+                match opcode {
+                    VIRTIO_CRYPTO_AKCIPHER_ENCRYPT => rsa.encrypt(src)?,
+                    VIRTIO_CRYPTO_AKCIPHER_DECRYPT => rsa.decrypt(src)?,
+                    VIRTIO_CRYPTO_AKCIPHER_SIGN => rsa.sign(src)?,
+                    // Both the signature and the digest are given by the
+                    // guest, nothing is written back but the status.
+                    VIRTIO_CRYPTO_AKCIPHER_VERIFY => {
+                        let digest = take(&mut payload, read_u32(request, DATA_AKCIPHER_DST_LEN))?;
+                        rsa.verify(src, digest)?;
+                        Vec::new()
+                    }
+                    _ => return Err(Status::InvalidSession),
+                }
+            }
+            // The session doesn't belong to the service of the operation.
+            _ => return Err(Status::InvalidSession),
+        };
+
+        if output.len() > dst_len {
+            return Err(Status::BadMsg);
+        }
+
+        Ok(output)
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Versionize)]
+#[allow(dead_code)]
+pub struct VirtioCryptoConfig {
+    status: u32,
+    max_dataqueues: u32,
+    crypto_services: u32,
+    cipher_algo_l: u32,
+    cipher_algo_h: u32,
+    hash_algo: u32,
+    mac_algo_l: u32,
+    mac_algo_h: u32,
+    aead_algo: u32,
+    max_cipher_key_len: u32,
+    max_auth_key_len: u32,
+    akcipher_algo: u32,
+    max_size: u64,
+}
+
+// SAFETY: it only has data and has no implicit padding.
+unsafe impl ByteValued for VirtioCryptoConfig {}
+
+struct DataQueue {
+    index: usize,
+    queue: Queue,
+    evt: EventFd,
+}
+
+struct CryptoEpollHandler {
+    mem: GuestMemoryAtomic<GuestMemoryMmap>,
+    control_queue: Queue,
+    control_queue_index: usize,
+    control_queue_evt: EventFd,
+    data_queues: Vec<DataQueue>,
+    interrupt_cb: Arc<dyn VirtioInterrupt>,
+    kill_evt: EventFd,
+    pause_evt: EventFd,
+    access_platform: Option<Arc<dyn AccessPlatform>>,
+    backend: CryptoBackend,
+    sessions: Arc<Mutex<Sessions>>,
+}
+
+impl CryptoEpollHandler {
+    fn process_control_queue(&mut self) -> result::Result<bool, Error> {
+        let mut used_descs = false;
+        while let Some(mut desc_chain) = self.control_queue.pop_descriptor_chain(self.mem.memory())
+        {
+            let (readable, writable) = parse_chain(&mut desc_chain, self.access_platform.as_ref());
+            let len = self.handle_control_request(desc_chain.memory(), &readable, &writable)?;
+
+            self.control_queue
+                .add_used(desc_chain.memory(), desc_chain.head_index(), len as u32)
+                .map_err(Error::QueueAddUsed)?;
+            used_descs = true;
+        }
+
+        Ok(used_descs)
+    }
+
+    // Creates or destroys a session, returning the number of bytes written
+    // to the guest.
+    fn handle_control_request(
+        &mut self,
+        mem: &GuestMemoryMmap,
+        readable: &Regions,
+        writable: &Regions,
+    ) -> result::Result<usize, Error> {
+        let mut request = vec![0u8; CTRL_REQ_SIZE];
+        readable.read(mem, 0, &mut request)?;
+
+        match read_u32(&request, CTRL_OPCODE) {
+            VIRTIO_CRYPTO_CIPHER_DESTROY_SESSION
+            | VIRTIO_CRYPTO_HASH_DESTROY_SESSION
+            | VIRTIO_CRYPTO_MAC_DESTROY_SESSION
+            | VIRTIO_CRYPTO_AEAD_DESTROY_SESSION
+            | VIRTIO_CRYPTO_AKCIPHER_DESTROY_SESSION => {
+                let id = read_u64(&request, CTRL_SESSION_ID);
+                let status = match self.sessions.lock().unwrap().sessions.remove(&id) {
+                    Some(_) => Status::Ok,
+                    None => Status::InvalidSession,
+                };
+                writable.write(mem, 0, &[status as u8])?;
+
+                Ok(INHDR_SIZE)
+            }
+            _ => {
+                let key_len = session_key_len(&request) as usize;
+                let result =
+                    if key_len > MAX_AKCIPHER_KEY_LEN || CTRL_REQ_SIZE + key_len > readable.len() {
+                        Err(Status::BadMsg)
+                    } else {
+                        let mut key = vec![0u8; key_len];
+                        readable.read(mem, CTRL_REQ_SIZE, &mut key)?;
+                        self.sessions
+                            .lock()
+                            .unwrap()
+                            .create(self.backend, request, key)
+                    };
+
+                let mut input = [0u8; SESSION_INPUT_SIZE];
+                match result {
+                    Ok(id) => input[..8].copy_from_slice(&id.to_le_bytes()),
+                    Err(status) => input[8..12].copy_from_slice(&(status as u32).to_le_bytes()),
+                }
+                writable.write(mem, 0, &input)?;
+
+                Ok(SESSION_INPUT_SIZE)
+            }
+        }
+    }
+
+    fn process_data_queue(&mut self, queue: usize) -> result::Result<bool, Error> {
+        let mut used_descs = false;
+        while let Some(mut desc_chain) = self.data_queues[queue]
+            .queue
+            .pop_descriptor_chain(self.mem.memory())
+        {
+            let (readable, writable) = parse_chain(&mut desc_chain, self.access_platform.as_ref());
+            let len = self.handle_data_request(desc_chain.memory(), &readable, &writable)?;
+
+            self.data_queues[queue]
+                .queue
+                .add_used(desc_chain.memory(), desc_chain.head_index(), len as u32)
+                .map_err(Error::QueueAddUsed)?;
+            used_descs = true;
+        }
+
+        Ok(used_descs)
+    }
+
+    // Runs a data request, returning the number of bytes written to the
+    // guest. The status is always the last byte of the writable part.
+    fn handle_data_request(
+        &mut self,
+        mem: &GuestMemoryMmap,
+        readable: &Regions,
+        writable: &Regions,
+    ) -> result::Result<usize, Error> {
+        if readable.len() < DATA_REQ_SIZE || writable.len() < INHDR_SIZE {
+            return Err(Error::DescriptorChainTooShort);
+        }
+
+        let mut request = vec![0u8; DATA_REQ_SIZE];
+        readable.read(mem, 0, &mut request)?;
+
+        let payload_len = readable.len() - DATA_REQ_SIZE;
+        let dst_len = writable.len() - INHDR_SIZE;
+        let result = if payload_len > MAX_REQUEST_SIZE || dst_len > MAX_REQUEST_SIZE {
+            Err(Status::BadMsg)
+        } else {
+            let mut payload = vec![0u8; payload_len];
+            readable.read(mem, DATA_REQ_SIZE, &mut payload)?;
+            self.sessions
+                .lock()
+                .unwrap()
+                .execute(&request, &payload, dst_len)
+        };
+
+        let status = match result {
+            Ok(output) => {
+                writable.write(mem, 0, &output)?;
+                Status::Ok
+            }
+            Err(status) => status,
+        };
+        writable.write(mem, dst_len, &[status as u8])?;
+
+        Ok(writable.len())
+    }
+
+    fn signal_used_queue(&self, queue_index: usize) -> result::Result<(), Error> {
+        self.interrupt_cb
+            .trigger(VirtioInterruptType::Queue(queue_index as u16))
+            .map_err(|e| {
+                error!("Failed to signal used queue: {:?}", e);
+                Error::SignalUsedQueue(e)
+            })
+    }
+
+    fn handle_result(
+        &self,
+        queue_index: usize,
+        result: result::Result<bool, Error>,
+    ) -> result::Result<(), EpollHelperError> {
+        let needs_notification = result.map_err(|e| {
+            EpollHelperError::HandleEvent(anyhow!("Failed to process queue : {:?}", e))
+        })?;
+        if needs_notification {
+            self.signal_used_queue(queue_index).map_err(|e| {
+                EpollHelperError::HandleEvent(anyhow!("Failed to signal used queue: {:?}", e))
+            })?;
+        }
+
+        Ok(())
+    }
+
+    fn run(
+        &mut self,
+        paused: Arc<AtomicBool>,
+        paused_sync: Arc<Barrier>,
+    ) -> result::Result<(), EpollHelperError> {
+        let mut helper = EpollHelper::new(&self.kill_evt, &self.pause_evt)?;
+        helper.add_event(self.control_queue_evt.as_raw_fd(), CONTROL_QUEUE_EVENT)?;
+        for (i, data_queue) in self.data_queues.iter().enumerate() {
+            helper.add_event(data_queue.evt.as_raw_fd(), DATA_QUEUE_EVENT + i as u16)?;
+        }
+        helper.run(paused, paused_sync, self)?;
+
+        Ok(())
+    }
+}
+
+impl EpollHelperHandler for CryptoEpollHandler {
+    fn handle_event(
+        &mut self,
+        _helper: &mut EpollHelper,
+        event: &epoll::Event,
+    ) -> result::Result<(), EpollHelperError> {
+        let ev_type = event.data as u16;
+        match ev_type {
+            CONTROL_QUEUE_EVENT => {
+                self.control_queue_evt.read().map_err(|e| {
+                    EpollHelperError::HandleEvent(anyhow!("Failed to get queue event: {:?}", e))
+                })?;
+                let result = self.process_control_queue();
+                self.handle_result(self.control_queue_index, result)?;
+            }
+            _ if ev_type >= DATA_QUEUE_EVENT
+                && ((ev_type - DATA_QUEUE_EVENT) as usize) < self.data_queues.len() =>
+            {
+                let queue = (ev_type - DATA_QUEUE_EVENT) as usize;
+                self.data_queues[queue].evt.read().map_err(|e| {
+                    EpollHelperError::HandleEvent(anyhow!("Failed to get queue event: {:?}", e))
+                })?;
+                let result = self.process_data_queue(queue);
+                self.handle_result(self.data_queues[queue].index, result)?;
+            }
+            _ => {
+                return Err(EpollHelperError::HandleEvent(anyhow!(
+                    "Unexpected event: {}",
+                    ev_type
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Versionize)]
+pub struct CryptoSessionState {
+    pub id: u64,
+    pub request: Vec<u8>,
+    pub key: Vec<u8>,
+}
+
+#[derive(Versionize)]
+pub struct CryptoState {
+    pub avail_features: u64,
+    pub acked_features: u64,
+    pub config: VirtioCryptoConfig,
+    pub sessions: Vec<CryptoSessionState>,
+    pub next_session_id: u64,
+}
+
+impl VersionMapped for CryptoState {}
+
+/// Virtio crypto device.
+pub struct Crypto {
+    common: VirtioCommon,
+    id: String,
+    backend: CryptoBackend,
+    config: VirtioCryptoConfig,
+    sessions: Arc<Mutex<Sessions>>,
+    seccomp_action: SeccompAction,
+    exit_evt: EventFd,
+}
+
+impl Crypto {
+    /// Create a new virtio-crypto device with `num_queues` data queues,
+    /// running the symmetric algorithms with the given backend.
+    pub fn new(
+        id: String,
+        backend: CryptoBackend,
+        num_queues: usize,
+        iommu: bool,
+        seccomp_action: SeccompAction,
+        exit_evt: EventFd,
+        state: Option<CryptoState>,
+    ) -> io::Result<Crypto> {
+        if backend == CryptoBackend::AfAlg {
+            af_alg::probe()?;
+        }
+
+        let (avail_features, acked_features, config, sessions, paused) = if let Some(state) = state
+        {
+            info!("Restoring virtio-crypto {}", id);
+            (
+                state.avail_features,
+                state.acked_features,
+                state.config,
+                Sessions::restore(backend, &state)?,
+                true,
+            )
+        } else {
+            let mut avail_features = 1u64 << VIRTIO_F_VERSION_1;
+
+            if iommu {
+                avail_features |= 1u64 << VIRTIO_F_IOMMU_PLATFORM;
+            }
+
+            let config = VirtioCryptoConfig {
+                status: VIRTIO_CRYPTO_S_HW_READY,
+                max_dataqueues: num_queues as u32,
+                crypto_services: 1 << VIRTIO_CRYPTO_SERVICE_CIPHER
+                    | 1 << VIRTIO_CRYPTO_SERVICE_AEAD
+                    | 1 << VIRTIO_CRYPTO_SERVICE_AKCIPHER,
+                cipher_algo_l: 1 << VIRTIO_CRYPTO_CIPHER_AES_ECB
+                    | 1 << VIRTIO_CRYPTO_CIPHER_AES_CBC
+                    | 1 << VIRTIO_CRYPTO_CIPHER_AES_CTR,
+                aead_algo: 1 << VIRTIO_CRYPTO_AEAD_GCM | 1 << VIRTIO_CRYPTO_AEAD_CHACHA20_POLY1305,
+                max_cipher_key_len: MAX_CIPHER_KEY_LEN,
+                akcipher_algo: 1 << VIRTIO_CRYPTO_AKCIPHER_RSA,
+                max_size: MAX_REQUEST_SIZE as u64,
+                ..Default::default()
+            };
+
+            (avail_features, 0, config, Sessions::default(), false)
+        };
+
+        Ok(Crypto {
+            common: VirtioCommon {
+                device_type: VirtioDeviceType::Crypto as u32,
+                queue_sizes: vec![QUEUE_SIZE; config.max_dataqueues as usize + 1],
+                paused_sync: Some(Arc::new(Barrier::new(2))),
+                avail_features,
+                acked_features,
+                min_queues: 2,
+                paused: Arc::new(AtomicBool::new(paused)),
+                ..Default::default()
+            },
+            id,
+            backend,
+            config,
+            sessions: Arc::new(Mutex::new(sessions)),
+            seccomp_action,
+            exit_evt,
+        })
+    }
+
+    fn state(&self) -> CryptoState {
+        let sessions = self.sessions.lock().unwrap();
+        CryptoState {
+            avail_features: self.common.avail_features,
+            acked_features: self.common.acked_features,
+            config: self.config,
+            sessions: sessions
+                .sessions
+                .iter()
+                .map(|(id, session)| CryptoSessionState {
+                    id: *id,
+                    request: session.request.clone(),
+                    key: session.key.clone(),
+                })
+                .collect(),
+            next_session_id: sessions.next_id,
+        }
+    }
+
+    #[cfg(fuzzing)]
+    pub fn wait_for_epoll_threads(&mut self) {
+        self.common.wait_for_epoll_threads();
+    }
+}
+
+impl Drop for Crypto {
+    fn drop(&mut self) {
+        if let Some(kill_evt) = self.common.kill_evt.take() {
+            // Ignore the result because there is nothing we can do about it.
+            let _ = kill_evt.write(1);
+        }
+        self.common.wait_for_epoll_threads();
+    }
+}
+
+impl VirtioDevice for Crypto {
+    fn device_type(&self) -> u32 {
+        self.common.device_type
+    }
+
+    fn queue_max_sizes(&self) -> &[u16] {
+        &self.common.queue_sizes
+    }
+
+    fn features(&self) -> u64 {
+        self.common.avail_features
+    }
+
+    fn ack_features(&mut self, value: u64) {
+        self.common.ack_features(value)
+    }
+
+    fn read_config(&self, offset: u64, data: &mut [u8]) {
+        self.read_config_from_slice(self.config.as_slice(), offset, data);
+    }
+
+    fn activate(
+        &mut self,
+        mem: GuestMemoryAtomic<GuestMemoryMmap>,
+        interrupt_cb: Arc<dyn VirtioInterrupt>,
+        queues: Vec<(usize, Queue, EventFd)>,
+    ) -> ActivateResult {
+        self.common.activate(&queues, &interrupt_cb)?;
+        let (kill_evt, pause_evt) = self.common.dup_eventfds();
+
+        // The control queue comes after all the data queues, whether the
+        // driver uses them all or not.
+        let control_queue_index = self.config.max_dataqueues as usize;
+        let mut control_queue = None;
+        let mut data_queues = Vec::new();
+        for (index, queue, evt) in queues {
+            if index == control_queue_index {
+                control_queue = Some((queue, evt));
+            } else {
+                data_queues.push(DataQueue { index, queue, evt });
+            }
+        }
+        let (control_queue, control_queue_evt) = control_queue.ok_or_else(|| {
+            error!("Missing virtio-crypto control queue");
+            ActivateError::BadActivate
+        })?;
+
+        let mut handler = CryptoEpollHandler {
+            mem,
+            control_queue,
+            control_queue_index,
+            control_queue_evt,
+            data_queues,
+            interrupt_cb,
+            kill_evt,
+            pause_evt,
+            access_platform: self.common.access_platform.clone(),
+            backend: self.backend,
+            sessions: self.sessions.clone(),
+        };
+
+        let paused = self.common.paused.clone();
+        let paused_sync = self.common.paused_sync.clone();
+        let mut epoll_threads = Vec::new();
+        spawn_virtio_thread(
+            &self.id,
+            &self.seccomp_action,
+            Thread::VirtioCrypto,
+            &mut epoll_threads,
+            &self.exit_evt,
+            move || handler.run(paused, paused_sync.unwrap()),
+        )?;
+
+        self.common.epoll_threads = Some(epoll_threads);
+
+        event!("virtio-device", "activated", "id", &self.id);
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
+        let result = self.common.reset();
+        // The sessions belong to the driver which created them.
+        *self.sessions.lock().unwrap() = Sessions::default();
+        event!("virtio-device", "reset", "id", &self.id);
+        result
+    }
+
+    fn set_access_platform(&mut self, access_platform: Arc<dyn AccessPlatform>) {
+        self.common.set_access_platform(access_platform)
+    }
+}
+
+impl Pausable for Crypto {
+    fn pause(&mut self) -> result::Result<(), MigratableError> {
+        self.common.pause()
+    }
+
+    fn resume(&mut self) -> result::Result<(), MigratableError> {
+        self.common.resume()
+    }
+}
+
+impl Snapshottable for Crypto {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn snapshot(&mut self) -> std::result::Result<Snapshot, MigratableError> {
+        Snapshot::new_from_versioned_state(&self.state())
+    }
+}
+
+impl Transportable for Crypto {}
+impl Migratable for Crypto {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::pkcs1::EncodeRsaPrivateKey;
+    use rsa::rand_core::OsRng;
+    use rsa::RsaPrivateKey;
+
+    fn ctrl_request(opcode: u32, fields: &[(usize, u32)]) -> Vec<u8> {
+        let mut request = vec![0u8; CTRL_REQ_SIZE];
+        request[CTRL_OPCODE..CTRL_OPCODE + 4].copy_from_slice(&opcode.to_le_bytes());
+        for (offset, value) in fields {
+            request[*offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        request
+    }
+
+    fn data_request(opcode: u32, session: u64, fields: &[(usize, u32)]) -> Vec<u8> {
+        let mut request = ctrl_request(opcode, fields);
+        request[DATA_SESSION_ID..DATA_SESSION_ID + 8].copy_from_slice(&session.to_le_bytes());
+        request
+    }
+
+    #[test]
+    fn test_cipher_session() {
+        // Test vector from NIST SP 800-38A, F.2.1.
+        let key = [
+            0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf,
+            0x4f, 0x3c,
+        ];
+        let iv: Vec<u8> = (0..16).collect();
+        let plaintext = [
+            0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93,
+            0x17, 0x2a,
+        ];
+        let ciphertext = [
+            0x76, 0x49, 0xab, 0xac, 0x81, 0x19, 0xb2, 0x46, 0xce, 0xe9, 0x8e, 0x9b, 0x12, 0xe9,
+            0x19, 0x7d,
+        ];
+
+        let mut sessions = Sessions::default();
+        let request = ctrl_request(
+            VIRTIO_CRYPTO_CIPHER_CREATE_SESSION,
+            &[
+                (CTRL_SESSION_ALGO, VIRTIO_CRYPTO_CIPHER_AES_CBC),
+                (CTRL_CIPHER_KEY_LEN, 16),
+                (CTRL_CIPHER_OP, VIRTIO_CRYPTO_OP_ENCRYPT),
+                (CTRL_SYM_OP_TYPE, VIRTIO_CRYPTO_SYM_OP_CIPHER),
+            ],
+        );
+        let id = sessions
+            .create(CryptoBackend::Software, request, key.to_vec())
+            .unwrap();
+
+        let request = data_request(
+            VIRTIO_CRYPTO_CIPHER_ENCRYPT,
+            id,
+            &[
+                (DATA_CIPHER_IV_LEN, 16),
+                (DATA_CIPHER_SRC_LEN, 16),
+                (DATA_CIPHER_DST_LEN, 16),
+                (DATA_SYM_OP_TYPE, VIRTIO_CRYPTO_SYM_OP_CIPHER),
+            ],
+        );
+        let payload = [iv.as_slice(), &plaintext].concat();
+        assert_eq!(
+            sessions.execute(&request, &payload, 16).unwrap(),
+            ciphertext
+        );
+        // The source is truncated.
+        assert_eq!(
+            sessions.execute(&request, &payload[..31], 16),
+            Err(Status::BadMsg)
+        );
+
+        let request = data_request(VIRTIO_CRYPTO_CIPHER_ENCRYPT, id + 1, &[]);
+        assert_eq!(
+            sessions.execute(&request, &payload, 16),
+            Err(Status::InvalidSession)
+        );
+    }
+
+    #[test]
+    fn test_aead_session() {
+        let key = [0x42u8; 32];
+        let mut sessions = Sessions::default();
+        let mut create = |op| {
+            let request = ctrl_request(
+                VIRTIO_CRYPTO_AEAD_CREATE_SESSION,
+                &[
+                    (CTRL_SESSION_ALGO, VIRTIO_CRYPTO_AEAD_CHACHA20_POLY1305),
+                    (CTRL_AEAD_KEY_LEN, 32),
+                    (CTRL_AEAD_TAG_LEN, AEAD_TAG_LEN as u32),
+                    (CTRL_AEAD_OP, op),
+                ],
+            );
+            sessions
+                .create(CryptoBackend::Software, request, key.to_vec())
+                .unwrap()
+        };
+        let encrypt = create(VIRTIO_CRYPTO_OP_ENCRYPT);
+        let decrypt = create(VIRTIO_CRYPTO_OP_DECRYPT);
+
+        let nonce = [0x24u8; AEAD_NONCE_LEN];
+        let aad = b"header";
+        let plaintext = b"some plaintext";
+        let request = data_request(
+            VIRTIO_CRYPTO_AEAD_ENCRYPT,
+            encrypt,
+            &[
+                (DATA_AEAD_IV_LEN, nonce.len() as u32),
+                (DATA_AEAD_AAD_LEN, aad.len() as u32),
+                (DATA_AEAD_SRC_LEN, plaintext.len() as u32),
+                (DATA_AEAD_DST_LEN, (plaintext.len() + AEAD_TAG_LEN) as u32),
+            ],
+        );
+        let payload = [nonce.as_slice(), aad, plaintext].concat();
+        let ciphertext = sessions.execute(&request, &payload, 64).unwrap();
+        assert_eq!(ciphertext.len(), plaintext.len() + AEAD_TAG_LEN);
+
+        let request = data_request(
+            VIRTIO_CRYPTO_AEAD_DECRYPT,
+            decrypt,
+            &[
+                (DATA_AEAD_IV_LEN, nonce.len() as u32),
+                (DATA_AEAD_AAD_LEN, aad.len() as u32),
+                (DATA_AEAD_SRC_LEN, ciphertext.len() as u32),
+                (DATA_AEAD_DST_LEN, plaintext.len() as u32),
+            ],
+        );
+        let mut payload = [nonce.as_slice(), aad, &ciphertext].concat();
+        assert_eq!(sessions.execute(&request, &payload, 64).unwrap(), plaintext);
+
+        // Tampering with the ciphertext makes the tag check fail.
+        payload[AEAD_NONCE_LEN + aad.len()] ^= 1;
+        assert_eq!(
+            sessions.execute(&request, &payload, 64),
+            Err(Status::BadMsg)
+        );
+    }
+
+    #[test]
+    fn test_rsa_session() {
+        let private_key = RsaPrivateKey::new(&mut OsRng, 512).unwrap();
+        let key = private_key.to_pkcs1_der().unwrap().as_bytes().to_vec();
+
+        let mut sessions = Sessions::default();
+        let request = ctrl_request(
+            VIRTIO_CRYPTO_AKCIPHER_CREATE_SESSION,
+            &[
+                (CTRL_SESSION_ALGO, VIRTIO_CRYPTO_AKCIPHER_RSA),
+                (
+                    CTRL_AKCIPHER_KEY_TYPE,
+                    akcipher::VIRTIO_CRYPTO_AKCIPHER_KEY_TYPE_PRIVATE,
+                ),
+                (CTRL_AKCIPHER_KEY_LEN, key.len() as u32),
+                // PKCS #1 padding with SHA-256 digests.
+                (CTRL_RSA_PADDING, 1),
+                (CTRL_RSA_HASH, 6),
+            ],
+        );
+        let id = sessions
+            .create(CryptoBackend::Software, request, key)
+            .unwrap();
+
+        let digest = [0x5au8; 32];
+        let request = data_request(
+            VIRTIO_CRYPTO_AKCIPHER_SIGN,
+            id,
+            &[(DATA_AKCIPHER_SRC_LEN, 32), (DATA_AKCIPHER_DST_LEN, 64)],
+        );
+        let signature = sessions.execute(&request, &digest, 64).unwrap();
+        assert_eq!(signature.len(), 64);
+
+        let request = data_request(
+            VIRTIO_CRYPTO_AKCIPHER_VERIFY,
+            id,
+            &[(DATA_AKCIPHER_SRC_LEN, 64), (DATA_AKCIPHER_DST_LEN, 32)],
+        );
+        let mut payload = [signature.as_slice(), &digest].concat();
+        assert_eq!(sessions.execute(&request, &payload, 0).unwrap(), Vec::new());
+        payload[64] ^= 1;
+        assert_eq!(
+            sessions.execute(&request, &payload, 0),
+            Err(Status::KeyRejected)
+        );
+    }
+}
@@ -0,0 +1,178 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Symmetric algorithms implemented within the VMM, always available.
+
+use super::{AeadAlgo, AeadCipher, AesMode, Status, SymCipher, AEAD_NONCE_LEN, AEAD_TAG_LEN};
+use aes::cipher::block_padding::NoPadding;
+use aes::cipher::consts::{U12, U16};
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{
+    BlockCipher, BlockDecrypt, BlockDecryptMut, BlockEncrypt, BlockEncryptMut, BlockSizeUser,
+    KeyInit, KeyIvInit, StreamCipher,
+};
+use aes::{Aes128, Aes192, Aes256};
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::AesGcm;
+use chacha20poly1305::ChaCha20Poly1305;
+
+const AES_BLOCK_SIZE: usize = 16;
+
+// Runs `$body` with `$aes` being the AES variant matching the length of the
+// key.
+macro_rules! with_aes {
+    ($key:expr, $aes:ident => $body:expr) => {
+        match $key.len() {
+            16 => {
+                type $aes = Aes128;
+                $body
+            }
+            24 => {
+                type $aes = Aes192;
+                $body
+            }
+            32 => {
+                type $aes = Aes256;
+                $body
+            }
+            _ => Err(Status::NotSupported),
+        }
+    };
+}
+
+pub(super) struct SoftwareCipher {
+    mode: AesMode,
+    key: Vec<u8>,
+    encrypt: bool,
+}
+
+impl SoftwareCipher {
+    pub fn new(mode: AesMode, key: &[u8], encrypt: bool) -> Result<Self, Status> {
+        if !matches!(key.len(), 16 | 24 | 32) {
+            return Err(Status::NotSupported);
+        }
+
+        Ok(SoftwareCipher {
+            mode,
+            key: key.to_vec(),
+            encrypt,
+        })
+    }
+
+    fn apply<C>(&self, iv: &[u8], data: &mut [u8]) -> Result<(), Status>
+    where
+        C: BlockCipher
+            + BlockEncrypt
+            + BlockDecrypt
+            + BlockEncryptMut
+            + BlockDecryptMut
+            + BlockSizeUser<BlockSize = U16>
+            + KeyInit,
+    {
+        match self.mode {
+            AesMode::Ecb => {
+                if data.len() % AES_BLOCK_SIZE != 0 {
+                    return Err(Status::BadMsg);
+                }
+                let cipher = C::new_from_slice(&self.key).map_err(|_| Status::NotSupported)?;
+                for block in data.chunks_exact_mut(AES_BLOCK_SIZE) {
+                    let block = GenericArray::from_mut_slice(block);
+                    if self.encrypt {
+                        cipher.encrypt_block(block);
+                    } else {
+                        cipher.decrypt_block(block);
+                    }
+                }
+            }
+            AesMode::Cbc => {
+                if data.len() % AES_BLOCK_SIZE != 0 {
+                    return Err(Status::BadMsg);
+                }
+                let len = data.len();
+                if self.encrypt {
+                    cbc::Encryptor::<C>::new_from_slices(&self.key, iv)
+                        .map_err(|_| Status::BadMsg)?
+                        .encrypt_padded_mut::<NoPadding>(data, len)
+                        .map_err(|_| Status::BadMsg)?;
+                } else {
+                    cbc::Decryptor::<C>::new_from_slices(&self.key, iv)
+                        .map_err(|_| Status::BadMsg)?
+                        .decrypt_padded_mut::<NoPadding>(data)
+                        .map_err(|_| Status::BadMsg)?;
+                }
+            }
+            // Encryption and decryption are the same operation in CTR mode.
+            AesMode::Ctr => ctr::Ctr128BE::<C>::new_from_slices(&self.key, iv)
+                .map_err(|_| Status::BadMsg)?
+                .try_apply_keystream(data)
+                .map_err(|_| Status::BadMsg)?,
+        }
+
+        Ok(())
+    }
+}
+
+impl SymCipher for SoftwareCipher {
+    fn process(&mut self, iv: &[u8], src: &[u8]) -> Result<Vec<u8>, Status> {
+        let mut data = src.to_vec();
+        with_aes!(self.key, Aes => self.apply::<Aes>(iv, &mut data))?;
+        Ok(data)
+    }
+}
+
+pub(super) struct SoftwareAead {
+    algo: AeadAlgo,
+    key: Vec<u8>,
+    encrypt: bool,
+}
+
+impl SoftwareAead {
+    pub fn new(algo: AeadAlgo, key: &[u8], tag_len: usize, encrypt: bool) -> Result<Self, Status> {
+        let valid_key = match algo {
+            AeadAlgo::AesGcm => matches!(key.len(), 16 | 24 | 32),
+            AeadAlgo::ChaCha20Poly1305 => key.len() == 32,
+        };
+        if !valid_key || tag_len != AEAD_TAG_LEN {
+            return Err(Status::NotSupported);
+        }
+
+        Ok(SoftwareAead {
+            algo,
+            key: key.to_vec(),
+            encrypt,
+        })
+    }
+
+    fn apply<A: Aead + KeyInit>(
+        &self,
+        iv: &[u8],
+        aad: &[u8],
+        src: &[u8],
+    ) -> Result<Vec<u8>, Status> {
+        let cipher = A::new_from_slice(&self.key).map_err(|_| Status::NotSupported)?;
+        let nonce = GenericArray::from_slice(iv);
+        let payload = Payload { msg: src, aad };
+        if self.encrypt {
+            cipher.encrypt(nonce, payload).map_err(|_| Status::Err)
+        } else {
+            // The only way decryption fails is the tag not matching.
+            cipher.decrypt(nonce, payload).map_err(|_| Status::BadMsg)
+        }
+    }
+}
+
+impl AeadCipher for SoftwareAead {
+    fn process(&mut self, iv: &[u8], aad: &[u8], src: &[u8]) -> Result<Vec<u8>, Status> {
+        if iv.len() != AEAD_NONCE_LEN {
+            return Err(Status::BadMsg);
+        }
+
+        match self.algo {
+            AeadAlgo::AesGcm => {
+                with_aes!(self.key, Aes => self.apply::<AesGcm<Aes, U12>>(iv, aad, src))
+            }
+            AeadAlgo::ChaCha20Poly1305 => self.apply::<ChaCha20Poly1305>(iv, aad, src),
+        }
+    }
+}
@@ -0,0 +1,1298 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Virtio GPU device, limited to 2D operations on a single scanout.
+//!
+//! The content of the scanout is exposed to the host through a VNC server
+//! listening on a Unix socket.
+
+mod vnc;
+
+use self::vnc::{Rect, VncServer, BYTES_PER_PIXEL};
+use super::Error as DeviceError;
+use super::{
+    ActivateError, ActivateResult, EpollHelper, EpollHelperError, EpollHelperHandler, VirtioCommon,
+    VirtioDevice, VirtioDeviceType, EPOLL_HELPER_EVENT_LAST, VIRTIO_F_IOMMU_PLATFORM,
+    VIRTIO_F_VERSION_1,
+};
+use crate::seccomp_filters::Thread;
+use crate::thread_helper::spawn_virtio_thread;
+use crate::{ConsoleInput, GuestMemoryMmap};
+use crate::{VirtioInterrupt, VirtioInterruptType};
+use anyhow::anyhow;
+use seccompiler::SeccompAction;
+use std::collections::BTreeMap;
+use std::io;
+use std::mem::size_of;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::result;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Barrier, Mutex};
+use thiserror::Error;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+use virtio_queue::{DescriptorChain, Queue, QueueT};
+use vm_memory::{
+    ByteValued, Bytes, GuestAddress, GuestAddressSpace, GuestMemory, GuestMemoryAtomic,
+    GuestMemoryLoadGuard,
+};
+use vm_migration::VersionMapped;
+use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
+use vm_virtio::{AccessPlatform, Translatable};
+use vmm_sys_util::eventfd::EventFd;
+
+const QUEUE_SIZE: u16 = 256;
+const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE, QUEUE_SIZE];
+
+// New descriptors are pending on the control queue.
+const CTRL_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 1;
+// New descriptors are pending on the cursor queue.
+const CURSOR_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 2;
+// A VNC client is connecting.
+const VNC_LISTENER_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 3;
+// Activity on the socket of the VNC client.
+const VNC_CLIENT_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 4;
+
+// Host memory the guest can allocate for its resources.
+const MAX_RESOURCES_SIZE: usize = 256 << 20;
+// Guest pages backing a resource.
+const MAX_BACKING_ENTRIES: usize = 1 << 16;
+// Size of the largest request, an attach backing command with all its entries.
+const MAX_REQUEST_SIZE: usize = size_of::<VirtioGpuResourceAttachBacking>()
+    + MAX_BACKING_ENTRIES * size_of::<VirtioGpuMemEntry>();
+
+// Got from include/uapi/linux/virtio_gpu.h
+const VIRTIO_GPU_MAX_SCANOUTS: usize = 16;
+
+const VIRTIO_GPU_CMD_GET_DISPLAY_INFO: u32 = 0x100;
+const VIRTIO_GPU_CMD_RESOURCE_CREATE_2D: u32 = 0x101;
+const VIRTIO_GPU_CMD_RESOURCE_UNREF: u32 = 0x102;
+const VIRTIO_GPU_CMD_SET_SCANOUT: u32 = 0x103;
+const VIRTIO_GPU_CMD_RESOURCE_FLUSH: u32 = 0x104;
+const VIRTIO_GPU_CMD_TRANSFER_TO_HOST_2D: u32 = 0x105;
+const VIRTIO_GPU_CMD_RESOURCE_ATTACH_BACKING: u32 = 0x106;
+const VIRTIO_GPU_CMD_RESOURCE_DETACH_BACKING: u32 = 0x107;
+
+const VIRTIO_GPU_CMD_UPDATE_CURSOR: u32 = 0x300;
+const VIRTIO_GPU_CMD_MOVE_CURSOR: u32 = 0x301;
+
+const VIRTIO_GPU_RESP_OK_NODATA: u32 = 0x1100;
+const VIRTIO_GPU_RESP_OK_DISPLAY_INFO: u32 = 0x1101;
+
+const VIRTIO_GPU_RESP_ERR_UNSPEC: u32 = 0x1200;
+const VIRTIO_GPU_RESP_ERR_OUT_OF_MEMORY: u32 = 0x1201;
+const VIRTIO_GPU_RESP_ERR_INVALID_SCANOUT_ID: u32 = 0x1202;
+const VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID: u32 = 0x1203;
+const VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER: u32 = 0x1205;
+
+const VIRTIO_GPU_FLAG_FENCE: u32 = 1 << 0;
+
+const VIRTIO_GPU_FORMAT_B8G8R8A8_UNORM: u32 = 1;
+const VIRTIO_GPU_FORMAT_B8G8R8X8_UNORM: u32 = 2;
+const VIRTIO_GPU_FORMAT_A8R8G8B8_UNORM: u32 = 3;
+const VIRTIO_GPU_FORMAT_X8R8G8B8_UNORM: u32 = 4;
+const VIRTIO_GPU_FORMAT_R8G8B8A8_UNORM: u32 = 67;
+const VIRTIO_GPU_FORMAT_X8B8G8R8_UNORM: u32 = 68;
+const VIRTIO_GPU_FORMAT_A8B8G8R8_UNORM: u32 = 121;
+const VIRTIO_GPU_FORMAT_R8G8B8X8_UNORM: u32 = 134;
+
+#[derive(Error, Debug)]
+enum Error {
+    #[error("Descriptor chain too short")]
+    DescriptorChainTooShort,
+    #[error("Request too large")]
+    RequestTooLarge,
+    #[error("Failed to read from guest memory: {0}")]
+    GuestMemoryRead(vm_memory::guest_memory::Error),
+    #[error("Failed to write to guest memory: {0}")]
+    GuestMemoryWrite(vm_memory::guest_memory::Error),
+    #[error("Failed adding used index: {0}")]
+    QueueAddUsed(virtio_queue::Error),
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+#[allow(dead_code)]
+struct VirtioGpuCtrlHdr {
+    type_: u32,
+    flags: u32,
+    fence_id: u64,
+    ctx_id: u32,
+    ring_idx: u8,
+    padding: [u8; 3],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+#[allow(dead_code)]
+struct VirtioGpuRect {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+#[allow(dead_code)]
+struct VirtioGpuDisplayOne {
+    r: VirtioGpuRect,
+    enabled: u32,
+    flags: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+#[allow(dead_code)]
+struct VirtioGpuRespDisplayInfo {
+    hdr: VirtioGpuCtrlHdr,
+    pmodes: [VirtioGpuDisplayOne; VIRTIO_GPU_MAX_SCANOUTS],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+#[allow(dead_code)]
+struct VirtioGpuResourceCreate2d {
+    hdr: VirtioGpuCtrlHdr,
+    resource_id: u32,
+    format: u32,
+    width: u32,
+    height: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+#[allow(dead_code)]
+struct VirtioGpuResourceUnref {
+    hdr: VirtioGpuCtrlHdr,
+    resource_id: u32,
+    padding: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+#[allow(dead_code)]
+struct VirtioGpuSetScanout {
+    hdr: VirtioGpuCtrlHdr,
+    r: VirtioGpuRect,
+    scanout_id: u32,
+    resource_id: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+#[allow(dead_code)]
+struct VirtioGpuResourceFlush {
+    hdr: VirtioGpuCtrlHdr,
+    r: VirtioGpuRect,
+    resource_id: u32,
+    padding: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+#[allow(dead_code)]
+struct VirtioGpuTransferToHost2d {
+    hdr: VirtioGpuCtrlHdr,
+    r: VirtioGpuRect,
+    offset: u64,
+    resource_id: u32,
+    padding: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+#[allow(dead_code)]
+struct VirtioGpuResourceAttachBacking {
+    hdr: VirtioGpuCtrlHdr,
+    resource_id: u32,
+    nr_entries: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+#[allow(dead_code)]
+struct VirtioGpuMemEntry {
+    addr: u64,
+    length: u32,
+    padding: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+#[allow(dead_code)]
+struct VirtioGpuResourceDetachBacking {
+    hdr: VirtioGpuCtrlHdr,
+    resource_id: u32,
+    padding: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+#[allow(dead_code)]
+struct VirtioGpuCursorPos {
+    scanout_id: u32,
+    x: u32,
+    y: u32,
+    padding: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+#[allow(dead_code)]
+struct VirtioGpuUpdateCursor {
+    hdr: VirtioGpuCtrlHdr,
+    pos: VirtioGpuCursorPos,
+    resource_id: u32,
+    hot_x: u32,
+    hot_y: u32,
+    padding: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Versionize)]
+#[allow(dead_code)]
+pub struct VirtioGpuConfig {
+    events_read: u32,
+    events_clear: u32,
+    num_scanouts: u32,
+    num_capsets: u32,
+}
+
+// SAFETY: the following structures only have data and no implicit padding.
+unsafe impl ByteValued for VirtioGpuCtrlHdr {}
+// SAFETY: see above
+unsafe impl ByteValued for VirtioGpuRect {}
+// SAFETY: see above
+unsafe impl ByteValued for VirtioGpuDisplayOne {}
+// SAFETY: see above
+unsafe impl ByteValued for VirtioGpuRespDisplayInfo {}
+// SAFETY: see above
+unsafe impl ByteValued for VirtioGpuResourceCreate2d {}
+// SAFETY: see above
+unsafe impl ByteValued for VirtioGpuResourceUnref {}
+// SAFETY: see above
+unsafe impl ByteValued for VirtioGpuSetScanout {}
+// SAFETY: see above
+unsafe impl ByteValued for VirtioGpuResourceFlush {}
+// SAFETY: see above
+unsafe impl ByteValued for VirtioGpuTransferToHost2d {}
+// SAFETY: see above
+unsafe impl ByteValued for VirtioGpuResourceAttachBacking {}
+// SAFETY: see above
+unsafe impl ByteValued for VirtioGpuMemEntry {}
+// SAFETY: see above
+unsafe impl ByteValued for VirtioGpuResourceDetachBacking {}
+// SAFETY: see above
+unsafe impl ByteValued for VirtioGpuCursorPos {}
+// SAFETY: see above
+unsafe impl ByteValued for VirtioGpuUpdateCursor {}
+// SAFETY: see above
+unsafe impl ByteValued for VirtioGpuConfig {}
+
+// Reads a structure from a request, which is not guaranteed to be aligned.
+fn read_request<T: ByteValued + Default>(request: &[u8], offset: usize) -> Option<T> {
+    let mut obj = T::default();
+    let len = obj.as_slice().len();
+    obj.as_mut_slice()
+        .copy_from_slice(request.get(offset..offset.checked_add(len)?)?);
+    Some(obj)
+}
+
+// Converts a pixel of a guest resource into blue, green, red and alpha
+// components.
+fn pixel_to_bgra(format: u32, pixel: &[u8]) -> [u8; 4] {
+    match format {
+        VIRTIO_GPU_FORMAT_B8G8R8A8_UNORM => [pixel[0], pixel[1], pixel[2], pixel[3]],
+        VIRTIO_GPU_FORMAT_B8G8R8X8_UNORM => [pixel[0], pixel[1], pixel[2], 0xff],
+        VIRTIO_GPU_FORMAT_A8R8G8B8_UNORM => [pixel[3], pixel[2], pixel[1], pixel[0]],
+        VIRTIO_GPU_FORMAT_X8R8G8B8_UNORM => [pixel[3], pixel[2], pixel[1], 0xff],
+        VIRTIO_GPU_FORMAT_R8G8B8A8_UNORM => [pixel[2], pixel[1], pixel[0], pixel[3]],
+        VIRTIO_GPU_FORMAT_R8G8B8X8_UNORM => [pixel[2], pixel[1], pixel[0], 0xff],
+        VIRTIO_GPU_FORMAT_A8B8G8R8_UNORM => [pixel[1], pixel[2], pixel[3], pixel[0]],
+        _ => [pixel[1], pixel[2], pixel[3], 0xff],
+    }
+}
+
+fn format_supported(format: u32) -> bool {
+    matches!(
+        format,
+        VIRTIO_GPU_FORMAT_B8G8R8A8_UNORM
+            | VIRTIO_GPU_FORMAT_B8G8R8X8_UNORM
+            | VIRTIO_GPU_FORMAT_A8R8G8B8_UNORM
+            | VIRTIO_GPU_FORMAT_X8R8G8B8_UNORM
+            | VIRTIO_GPU_FORMAT_R8G8B8A8_UNORM
+            | VIRTIO_GPU_FORMAT_X8B8G8R8_UNORM
+            | VIRTIO_GPU_FORMAT_A8B8G8R8_UNORM
+            | VIRTIO_GPU_FORMAT_R8G8B8X8_UNORM
+    )
+}
+
+// Guest resource, whose content is copied from its backing pages on transfer.
+struct Resource {
+    format: u32,
+    width: u32,
+    height: u32,
+    backing: Vec<(GuestAddress, u32)>,
+    data: Vec<u8>,
+}
+
+impl Resource {
+    fn stride(&self) -> usize {
+        self.width as usize * BYTES_PER_PIXEL
+    }
+
+    // Copies `buf.len()` bytes from the backing pages, starting at `offset`.
+    fn read_backing(
+        &self,
+        mem: &GuestMemoryMmap,
+        mut offset: u64,
+        buf: &mut [u8],
+    ) -> result::Result<(), u32> {
+        let mut done = 0;
+        for (addr, len) in self.backing.iter() {
+            if done == buf.len() {
+                break;
+            }
+            let len = *len as u64;
+            if offset >= len {
+                offset -= len;
+                continue;
+            }
+            let count = std::cmp::min(len - offset, (buf.len() - done) as u64) as usize;
+            mem.read_slice(&mut buf[done..done + count], addr.unchecked_add(offset))
+                .map_err(|_| VIRTIO_GPU_RESP_ERR_UNSPEC)?;
+            done += count;
+            offset = 0;
+        }
+
+        if done != buf.len() {
+            return Err(VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER);
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Scanout {
+    resource_id: u32,
+    rect: VirtioGpuRect,
+}
+
+// State shared between the device and its epoll thread.
+#[derive(Default)]
+struct GpuResources {
+    resources: BTreeMap<u32, Resource>,
+    scanout: Option<Scanout>,
+    size: usize,
+}
+
+struct GpuEpollHandler {
+    mem: GuestMemoryAtomic<GuestMemoryMmap>,
+    queues: Vec<Queue>,
+    interrupt_cb: Arc<dyn VirtioInterrupt>,
+    ctrl_queue_evt: EventFd,
+    cursor_queue_evt: EventFd,
+    kill_evt: EventFd,
+    pause_evt: EventFd,
+    access_platform: Option<Arc<dyn AccessPlatform>>,
+    resources: Arc<Mutex<GpuResources>>,
+    width: u32,
+    height: u32,
+    vnc: VncServer,
+}
+
+impl GpuEpollHandler {
+    // Reads the device readable part of a descriptor chain, returning it
+    // along with the address and length of the device writable part.
+    fn read_request(
+        &self,
+        desc_chain: &mut DescriptorChain<GuestMemoryLoadGuard<GuestMemoryMmap>>,
+    ) -> result::Result<(Vec<u8>, Option<(GuestAddress, u32)>), Error> {
+        let mut request = Vec::new();
+        let mut response = None;
+        while let Some(desc) = desc_chain.next() {
+            if desc.is_write_only() {
+                response = Some((
+                    desc.addr()
+                        .translate_gva(self.access_platform.as_ref(), desc.len() as usize),
+                    desc.len(),
+                ));
+                break;
+            }
+
+            let start = request.len();
+            if start + desc.len() as usize > MAX_REQUEST_SIZE {
+                return Err(Error::RequestTooLarge);
+            }
+            request.resize(start + desc.len() as usize, 0);
+            desc_chain
+                .memory()
+                .read_slice(
+                    &mut request[start..],
+                    desc.addr()
+                        .translate_gva(self.access_platform.as_ref(), desc.len() as usize),
+                )
+                .map_err(Error::GuestMemoryRead)?;
+        }
+
+        if request.len() < size_of::<VirtioGpuCtrlHdr>() {
+            return Err(Error::DescriptorChainTooShort);
+        }
+
+        Ok((request, response))
+    }
+
+    fn process_queue(&mut self, queue_index: usize) -> result::Result<bool, Error> {
+        let mut used_descs = false;
+        while let Some(mut desc_chain) =
+            self.queues[queue_index].pop_descriptor_chain(self.mem.memory())
+        {
+            let (request, response) = self.read_request(&mut desc_chain)?;
+            // Checked when reading the request
+            let hdr: VirtioGpuCtrlHdr = read_request(&request, 0).unwrap();
+
+            let mut resp = match self.handle_command(hdr.type_, &request) {
+                Ok(Some(display_info)) => display_info.as_slice().to_vec(),
+                Ok(None) => VirtioGpuCtrlHdr {
+                    type_: VIRTIO_GPU_RESP_OK_NODATA,
+                    ..Default::default()
+                }
+                .as_slice()
+                .to_vec(),
+                Err(status) => {
+                    warn!(
+                        "virtio-gpu command 0x{:x} failed: 0x{:x}",
+                        hdr.type_, status
+                    );
+                    VirtioGpuCtrlHdr {
+                        type_: status,
+                        ..Default::default()
+                    }
+                    .as_slice()
+                    .to_vec()
+                }
+            };
+
+            // Commands are processed synchronously, so fences are already
+            // signaled when answering.
+            if hdr.flags & VIRTIO_GPU_FLAG_FENCE != 0 {
+                let mut resp_hdr: VirtioGpuCtrlHdr = read_request(&resp, 0).unwrap();
+                resp_hdr.flags |= VIRTIO_GPU_FLAG_FENCE;
+                resp_hdr.fence_id = hdr.fence_id;
+                resp_hdr.ctx_id = hdr.ctx_id;
+                resp_hdr.ring_idx = hdr.ring_idx;
+                resp[..size_of::<VirtioGpuCtrlHdr>()].copy_from_slice(resp_hdr.as_slice());
+            }
+
+            let mut len = 0;
+            if let Some((addr, resp_len)) = response {
+                let count = std::cmp::min(resp.len(), resp_len as usize);
+                desc_chain
+                    .memory()
+                    .write_slice(&resp[..count], addr)
+                    .map_err(Error::GuestMemoryWrite)?;
+                len = count as u32;
+            }
+
+            self.queues[queue_index]
+                .add_used(desc_chain.memory(), desc_chain.head_index(), len)
+                .map_err(Error::QueueAddUsed)?;
+            used_descs = true;
+        }
+
+        Ok(used_descs)
+    }
+
+    // Returns the display information for the commands expecting it, or the
+    // error status to report to the guest.
+    fn handle_command(
+        &mut self,
+        command: u32,
+        request: &[u8],
+    ) -> result::Result<Option<VirtioGpuRespDisplayInfo>, u32> {
+        match command {
+            VIRTIO_GPU_CMD_GET_DISPLAY_INFO => {
+                let mut display_info = VirtioGpuRespDisplayInfo::default();
+                display_info.hdr.type_ = VIRTIO_GPU_RESP_OK_DISPLAY_INFO;
+                display_info.pmodes[0] = VirtioGpuDisplayOne {
+                    r: VirtioGpuRect {
+                        x: 0,
+                        y: 0,
+                        width: self.width,
+                        height: self.height,
+                    },
+                    enabled: 1,
+                    flags: 0,
+                };
+                Ok(Some(display_info))
+            }
+            VIRTIO_GPU_CMD_RESOURCE_CREATE_2D => {
+                let req: VirtioGpuResourceCreate2d =
+                    read_request(request, 0).ok_or(VIRTIO_GPU_RESP_ERR_UNSPEC)?;
+                self.resource_create_2d(&req).map(|_| None)
+            }
+            VIRTIO_GPU_CMD_RESOURCE_UNREF => {
+                let req: VirtioGpuResourceUnref =
+                    read_request(request, 0).ok_or(VIRTIO_GPU_RESP_ERR_UNSPEC)?;
+                let mut resources = self.resources.lock().unwrap();
+                let resource = resources
+                    .resources
+                    .remove(&req.resource_id)
+                    .ok_or(VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID)?;
+                resources.size -= resource.data.len();
+                if matches!(resources.scanout, Some(s) if s.resource_id == req.resource_id) {
+                    resources.scanout = None;
+                }
+                Ok(None)
+            }
+            VIRTIO_GPU_CMD_SET_SCANOUT => {
+                let req: VirtioGpuSetScanout =
+                    read_request(request, 0).ok_or(VIRTIO_GPU_RESP_ERR_UNSPEC)?;
+                self.set_scanout(&req).map(|_| None)
+            }
+            VIRTIO_GPU_CMD_RESOURCE_FLUSH => {
+                let req: VirtioGpuResourceFlush =
+                    read_request(request, 0).ok_or(VIRTIO_GPU_RESP_ERR_UNSPEC)?;
+                self.resource_flush(req.resource_id, &req.r).map(|_| None)
+            }
+            VIRTIO_GPU_CMD_TRANSFER_TO_HOST_2D => {
+                let req: VirtioGpuTransferToHost2d =
+                    read_request(request, 0).ok_or(VIRTIO_GPU_RESP_ERR_UNSPEC)?;
+                self.transfer_to_host_2d(&req).map(|_| None)
+            }
+            VIRTIO_GPU_CMD_RESOURCE_ATTACH_BACKING => {
+                let req: VirtioGpuResourceAttachBacking =
+                    read_request(request, 0).ok_or(VIRTIO_GPU_RESP_ERR_UNSPEC)?;
+                self.resource_attach_backing(&req, request).map(|_| None)
+            }
+            VIRTIO_GPU_CMD_RESOURCE_DETACH_BACKING => {
+                let req: VirtioGpuResourceDetachBacking =
+                    read_request(request, 0).ok_or(VIRTIO_GPU_RESP_ERR_UNSPEC)?;
+                self.resources
+                    .lock()
+                    .unwrap()
+                    .resources
+                    .get_mut(&req.resource_id)
+                    .ok_or(VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID)?
+                    .backing
+                    .clear();
+                Ok(None)
+            }
+            VIRTIO_GPU_CMD_UPDATE_CURSOR => {
+                let req: VirtioGpuUpdateCursor =
+                    read_request(request, 0).ok_or(VIRTIO_GPU_RESP_ERR_UNSPEC)?;
+                self.update_cursor(&req).map(|_| None)
+            }
+            // The cursor is drawn by the VNC client, following its own pointer.
+            VIRTIO_GPU_CMD_MOVE_CURSOR => Ok(None),
+            _ => Err(VIRTIO_GPU_RESP_ERR_UNSPEC),
+        }
+    }
+
+    fn resource_create_2d(&mut self, req: &VirtioGpuResourceCreate2d) -> result::Result<(), u32> {
+        let mut resources = self.resources.lock().unwrap();
+        if req.resource_id == 0 || resources.resources.contains_key(&req.resource_id) {
+            return Err(VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID);
+        }
+        if !format_supported(req.format) || req.width == 0 || req.height == 0 {
+            return Err(VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER);
+        }
+
+        let size = (req.width as usize)
+            .checked_mul(req.height as usize)
+            .and_then(|pixels| pixels.checked_mul(BYTES_PER_PIXEL))
+            .filter(|size| resources.size + size <= MAX_RESOURCES_SIZE)
+            .ok_or(VIRTIO_GPU_RESP_ERR_OUT_OF_MEMORY)?;
+
+        resources.size += size;
+        resources.resources.insert(
+            req.resource_id,
+            Resource {
+                format: req.format,
+                width: req.width,
+                height: req.height,
+                backing: Vec::new(),
+                data: vec![0; size],
+            },
+        );
+
+        Ok(())
+    }
+
+    fn resource_attach_backing(
+        &mut self,
+        req: &VirtioGpuResourceAttachBacking,
+        request: &[u8],
+    ) -> result::Result<(), u32> {
+        if req.nr_entries as usize > MAX_BACKING_ENTRIES {
+            return Err(VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER);
+        }
+
+        let mem = self.mem.memory();
+        let mut backing = Vec::with_capacity(req.nr_entries as usize);
+        for i in 0..req.nr_entries as usize {
+            let offset =
+                size_of::<VirtioGpuResourceAttachBacking>() + i * size_of::<VirtioGpuMemEntry>();
+            let entry: VirtioGpuMemEntry =
+                read_request(request, offset).ok_or(VIRTIO_GPU_RESP_ERR_UNSPEC)?;
+            let addr = GuestAddress(entry.addr)
+                .translate_gpa(self.access_platform.as_ref(), entry.length as usize);
+            if !mem.check_range(addr, entry.length as usize) {
+                return Err(VIRTIO_GPU_RESP_ERR_UNSPEC);
+            }
+            backing.push((addr, entry.length));
+        }
+
+        self.resources
+            .lock()
+            .unwrap()
+            .resources
+            .get_mut(&req.resource_id)
+            .ok_or(VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID)?
+            .backing = backing;
+
+        Ok(())
+    }
+
+    fn transfer_to_host_2d(&mut self, req: &VirtioGpuTransferToHost2d) -> result::Result<(), u32> {
+        let mut resources = self.resources.lock().unwrap();
+        let resource = resources
+            .resources
+            .get_mut(&req.resource_id)
+            .ok_or(VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID)?;
+
+        let r = &req.r;
+        if r.x
+            .checked_add(r.width)
+            .map_or(true, |x| x > resource.width)
+            || r.y
+                .checked_add(r.height)
+                .map_or(true, |y| y > resource.height)
+        {
+            return Err(VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER);
+        }
+
+        let mem = self.mem.memory();
+        let stride = resource.stride();
+        let mut data = std::mem::take(&mut resource.data);
+        let result = if r.x == 0 && r.width == resource.width {
+            // Whole lines, copied at once
+            let start = r.y as usize * stride;
+            let end = start + r.height as usize * stride;
+            resource.read_backing(&mem, req.offset, &mut data[start..end])
+        } else {
+            (0..r.height as usize).try_for_each(|h| {
+                let start = (r.y as usize + h) * stride + r.x as usize * BYTES_PER_PIXEL;
+                let end = start + r.width as usize * BYTES_PER_PIXEL;
+                let offset = req
+                    .offset
+                    .checked_add((stride * h) as u64)
+                    .ok_or(VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER)?;
+                resource.read_backing(&mem, offset, &mut data[start..end])
+            })
+        };
+        resource.data = data;
+
+        result
+    }
+
+    fn set_scanout(&mut self, req: &VirtioGpuSetScanout) -> result::Result<(), u32> {
+        if req.scanout_id != 0 {
+            return Err(VIRTIO_GPU_RESP_ERR_INVALID_SCANOUT_ID);
+        }
+
+        let mut resources = self.resources.lock().unwrap();
+        if req.resource_id == 0 {
+            resources.scanout = None;
+            return Ok(());
+        }
+
+        let resource = resources
+            .resources
+            .get(&req.resource_id)
+            .ok_or(VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID)?;
+        let r = &req.r;
+        if r.width == 0
+            || r.height == 0
+            || r.x
+                .checked_add(r.width)
+                .map_or(true, |x| x > resource.width)
+            || r.y
+                .checked_add(r.height)
+                .map_or(true, |y| y > resource.height)
+        {
+            return Err(VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER);
+        }
+
+        resources.scanout = Some(Scanout {
+            resource_id: req.resource_id,
+            rect: req.r,
+        });
+        drop(resources);
+
+        self.vnc.resize(req.r.width, req.r.height);
+        self.resource_flush(req.resource_id, &req.r)
+    }
+
+    // Copies the flushed area of the resource to the VNC framebuffer, if the
+    // resource is being scanned out.
+    fn resource_flush(&mut self, resource_id: u32, r: &VirtioGpuRect) -> result::Result<(), u32> {
+        let resources = self.resources.lock().unwrap();
+        let resource = resources
+            .resources
+            .get(&resource_id)
+            .ok_or(VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID)?;
+        let scanout = match resources.scanout {
+            Some(scanout) if scanout.resource_id == resource_id => scanout.rect,
+            _ => return Ok(()),
+        };
+
+        // Intersection of the flushed area with the scanout one
+        let x1 = std::cmp::max(r.x, scanout.x);
+        let y1 = std::cmp::max(r.y, scanout.y);
+        let x2 = std::cmp::min(r.x.saturating_add(r.width), scanout.x + scanout.width);
+        let y2 = std::cmp::min(r.y.saturating_add(r.height), scanout.y + scanout.height);
+        if x1 >= x2 || y1 >= y2 {
+            return Ok(());
+        }
+
+        let src_stride = resource.stride();
+        let dst_stride = self.vnc.width() as usize * BYTES_PER_PIXEL;
+        let framebuffer = self.vnc.framebuffer_mut();
+        for y in y1..y2 {
+            let src = y as usize * src_stride + x1 as usize * BYTES_PER_PIXEL;
+            let dst =
+                (y - scanout.y) as usize * dst_stride + (x1 - scanout.x) as usize * BYTES_PER_PIXEL;
+            let len = (x2 - x1) as usize * BYTES_PER_PIXEL;
+            for (dst, src) in framebuffer[dst..dst + len]
+                .chunks_exact_mut(BYTES_PER_PIXEL)
+                .zip(resource.data[src..src + len].chunks_exact(BYTES_PER_PIXEL))
+            {
+                dst.copy_from_slice(&pixel_to_bgra(resource.format, src));
+            }
+        }
+
+        self.vnc.damage(Rect {
+            x: x1 - scanout.x,
+            y: y1 - scanout.y,
+            width: x2 - x1,
+            height: y2 - y1,
+        });
+
+        Ok(())
+    }
+
+    fn update_cursor(&mut self, req: &VirtioGpuUpdateCursor) -> result::Result<(), u32> {
+        if req.resource_id == 0 {
+            self.vnc.set_cursor(0, 0, 0, 0, Vec::new());
+            return Ok(());
+        }
+
+        let resources = self.resources.lock().unwrap();
+        let resource = resources
+            .resources
+            .get(&req.resource_id)
+            .ok_or(VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID)?;
+        let pixels = resource
+            .data
+            .chunks_exact(BYTES_PER_PIXEL)
+            .flat_map(|pixel| pixel_to_bgra(resource.format, pixel))
+            .collect();
+        self.vnc.set_cursor(
+            req.hot_x,
+            req.hot_y,
+            resource.width,
+            resource.height,
+            pixels,
+        );
+
+        Ok(())
+    }
+
+    // Refreshes the resources from their backing pages, and the VNC
+    // framebuffer from the scanout, after the device has been restored.
+    fn refresh(&mut self) {
+        let mem = self.mem.memory();
+        for resource in self.resources.lock().unwrap().resources.values_mut() {
+            let mut data = std::mem::take(&mut resource.data);
+            if let Err(e) = resource.read_backing(&mem, 0, &mut data) {
+                warn!("Failed refreshing virtio-gpu resource: 0x{:x}", e);
+            }
+            resource.data = data;
+        }
+
+        let scanout = self.resources.lock().unwrap().scanout;
+        if let Some(scanout) = scanout {
+            self.vnc.resize(scanout.rect.width, scanout.rect.height);
+            if let Err(e) = self.resource_flush(scanout.resource_id, &scanout.rect) {
+                warn!("Failed refreshing virtio-gpu scanout: 0x{:x}", e);
+            }
+        }
+    }
+
+    fn signal_used_queue(&self, queue_index: u16) -> result::Result<(), DeviceError> {
+        self.interrupt_cb
+            .trigger(VirtioInterruptType::Queue(queue_index))
+            .map_err(|e| {
+                error!("Failed to signal used queue: {:?}", e);
+                DeviceError::FailedSignalingUsedQueue(e)
+            })
+    }
+
+    fn handle_queue_event(
+        &mut self,
+        helper: &mut EpollHelper,
+        queue_index: u16,
+    ) -> result::Result<(), EpollHelperError> {
+        let needs_notification = self.process_queue(queue_index as usize).map_err(|e| {
+            EpollHelperError::HandleEvent(anyhow!("Failed to process queue : {:?}", e))
+        })?;
+        if needs_notification {
+            self.signal_used_queue(queue_index).map_err(|e| {
+                EpollHelperError::HandleEvent(anyhow!("Failed to signal used queue: {:?}", e))
+            })?;
+        }
+
+        self.vnc.flush(helper)
+    }
+
+    fn run(
+        &mut self,
+        paused: Arc<AtomicBool>,
+        paused_sync: Arc<Barrier>,
+    ) -> result::Result<(), EpollHelperError> {
+        let mut helper = EpollHelper::new(&self.kill_evt, &self.pause_evt)?;
+        helper.add_event(self.ctrl_queue_evt.as_raw_fd(), CTRL_QUEUE_EVENT)?;
+        helper.add_event(self.cursor_queue_evt.as_raw_fd(), CURSOR_QUEUE_EVENT)?;
+        helper.add_event(self.vnc.listener_fd(), VNC_LISTENER_EVENT)?;
+        helper.run(paused, paused_sync, self)?;
+
+        Ok(())
+    }
+}
+
+impl EpollHelperHandler for GpuEpollHandler {
+    fn handle_event(
+        &mut self,
+        helper: &mut EpollHelper,
+        event: &epoll::Event,
+    ) -> result::Result<(), EpollHelperError> {
+        let ev_type = event.data as u16;
+        match ev_type {
+            CTRL_QUEUE_EVENT => {
+                self.ctrl_queue_evt.read().map_err(|e| {
+                    EpollHelperError::HandleEvent(anyhow!("Failed to get queue event: {:?}", e))
+                })?;
+                self.handle_queue_event(helper, 0)?;
+            }
+            CURSOR_QUEUE_EVENT => {
+                self.cursor_queue_evt.read().map_err(|e| {
+                    EpollHelperError::HandleEvent(anyhow!("Failed to get queue event: {:?}", e))
+                })?;
+                self.handle_queue_event(helper, 1)?;
+            }
+            VNC_LISTENER_EVENT => self.vnc.accept(helper)?,
+            VNC_CLIENT_EVENT => self
+                .vnc
+                .handle_client_event(helper, epoll::Events::from_bits_truncate(event.events))?,
+            _ => {
+                return Err(EpollHelperError::HandleEvent(anyhow!(
+                    "Unexpected event: {}",
+                    ev_type
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Versionize)]
+pub struct GpuMemEntryState {
+    pub addr: u64,
+    pub length: u32,
+}
+
+#[derive(Versionize)]
+pub struct GpuResourceState {
+    pub id: u32,
+    pub format: u32,
+    pub width: u32,
+    pub height: u32,
+    pub backing: Vec<GpuMemEntryState>,
+}
+
+#[derive(Versionize)]
+pub struct GpuState {
+    pub avail_features: u64,
+    pub acked_features: u64,
+    pub config: VirtioGpuConfig,
+    pub resources: Vec<GpuResourceState>,
+    // Resource being scanned out, 0 if none
+    pub scanout_resource_id: u32,
+    pub scanout_x: u32,
+    pub scanout_y: u32,
+    pub scanout_width: u32,
+    pub scanout_height: u32,
+}
+
+impl VersionMapped for GpuState {}
+
+/// Virtio device exposing a 2D display to the guest.
+pub struct Gpu {
+    common: VirtioCommon,
+    id: String,
+    config: VirtioGpuConfig,
+    width: u32,
+    height: u32,
+    path: PathBuf,
+    listener: UnixListener,
+    resources: Arc<Mutex<GpuResources>>,
+    restored: bool,
+    console_input: ConsoleInput,
+    seccomp_action: SeccompAction,
+    exit_evt: EventFd,
+}
+
+impl Gpu {
+    /// Create a new virtio-gpu device whose display, of the given size, is
+    /// served over VNC on the `socket` Unix socket. The keyboard and pointer
+    /// events of the VNC client are sent to `console_input`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        id: String,
+        socket: PathBuf,
+        width: u32,
+        height: u32,
+        iommu: bool,
+        console_input: ConsoleInput,
+        seccomp_action: SeccompAction,
+        exit_evt: EventFd,
+        state: Option<GpuState>,
+    ) -> io::Result<Gpu> {
+        let listener = UnixListener::bind(&socket)?;
+
+        let mut resources = GpuResources::default();
+        let (avail_features, acked_features, config, paused) = if let Some(state) = state {
+            info!("Restoring virtio-gpu {}", id);
+            for resource in state.resources {
+                let size = resource.width as usize * resource.height as usize * BYTES_PER_PIXEL;
+                resources.size += size;
+                resources.resources.insert(
+                    resource.id,
+                    Resource {
+                        format: resource.format,
+                        width: resource.width,
+                        height: resource.height,
+                        backing: resource
+                            .backing
+                            .iter()
+                            .map(|entry| (GuestAddress(entry.addr), entry.length))
+                            .collect(),
+                        data: vec![0; size],
+                    },
+                );
+            }
+            if state.scanout_resource_id != 0 {
+                resources.scanout = Some(Scanout {
+                    resource_id: state.scanout_resource_id,
+                    rect: VirtioGpuRect {
+                        x: state.scanout_x,
+                        y: state.scanout_y,
+                        width: state.scanout_width,
+                        height: state.scanout_height,
+                    },
+                });
+            }
+            (
+                state.avail_features,
+                state.acked_features,
+                state.config,
+                true,
+            )
+        } else {
+            let mut avail_features = 1u64 << VIRTIO_F_VERSION_1;
+
+            if iommu {
+                avail_features |= 1u64 << VIRTIO_F_IOMMU_PLATFORM;
+            }
+
+            let config = VirtioGpuConfig {
+                num_scanouts: 1,
+                ..Default::default()
+            };
+
+            (avail_features, 0, config, false)
+        };
+
+        Ok(Gpu {
+            common: VirtioCommon {
+                device_type: VirtioDeviceType::Gpu as u32,
+                queue_sizes: QUEUE_SIZES.to_vec(),
+                paused_sync: Some(Arc::new(Barrier::new(2))),
+                avail_features,
+                acked_features,
+                min_queues: 2,
+                paused: Arc::new(AtomicBool::new(paused)),
+                ..Default::default()
+            },
+            id,
+            config,
+            width,
+            height,
+            path: socket,
+            listener,
+            resources: Arc::new(Mutex::new(resources)),
+            restored: paused,
+            console_input,
+            seccomp_action,
+            exit_evt,
+        })
+    }
+
+    fn state(&self) -> GpuState {
+        let resources = self.resources.lock().unwrap();
+        let scanout = resources.scanout.unwrap_or_default();
+        GpuState {
+            avail_features: self.common.avail_features,
+            acked_features: self.common.acked_features,
+            config: self.config,
+            resources: resources
+                .resources
+                .iter()
+                .map(|(id, resource)| GpuResourceState {
+                    id: *id,
+                    format: resource.format,
+                    width: resource.width,
+                    height: resource.height,
+                    backing: resource
+                        .backing
+                        .iter()
+                        .map(|(addr, length)| GpuMemEntryState {
+                            addr: addr.0,
+                            length: *length,
+                        })
+                        .collect(),
+                })
+                .collect(),
+            scanout_resource_id: scanout.resource_id,
+            scanout_x: scanout.rect.x,
+            scanout_y: scanout.rect.y,
+            scanout_width: scanout.rect.width,
+            scanout_height: scanout.rect.height,
+        }
+    }
+
+    #[cfg(fuzzing)]
+    pub fn wait_for_epoll_threads(&mut self) {
+        self.common.wait_for_epoll_threads();
+    }
+}
+
+impl Drop for Gpu {
+    fn drop(&mut self) {
+        if let Some(kill_evt) = self.common.kill_evt.take() {
+            // Ignore the result because there is nothing we can do about it.
+            let _ = kill_evt.write(1);
+        }
+        self.common.wait_for_epoll_threads();
+    }
+}
+
+impl VirtioDevice for Gpu {
+    fn device_type(&self) -> u32 {
+        self.common.device_type
+    }
+
+    fn queue_max_sizes(&self) -> &[u16] {
+        &self.common.queue_sizes
+    }
+
+    fn features(&self) -> u64 {
+        self.common.avail_features
+    }
+
+    fn ack_features(&mut self, value: u64) {
+        self.common.ack_features(value)
+    }
+
+    fn read_config(&self, offset: u64, data: &mut [u8]) {
+        self.read_config_from_slice(self.config.as_slice(), offset, data);
+    }
+
+    fn write_config(&mut self, offset: u64, data: &[u8]) {
+        // Writing "events_clear" acknowledges the events, and is the only
+        // writable field.
+        if offset != 4 || data.len() != 4 {
+            error!(
+                "Attempt to write to read-only field: offset {:x} length {}",
+                offset,
+                data.len()
+            );
+            return;
+        }
+
+        let events_clear = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+        self.config.events_read &= !events_clear;
+    }
+
+    fn activate(
+        &mut self,
+        mem: GuestMemoryAtomic<GuestMemoryMmap>,
+        interrupt_cb: Arc<dyn VirtioInterrupt>,
+        mut queues: Vec<(usize, Queue, EventFd)>,
+    ) -> ActivateResult {
+        self.common.activate(&queues, &interrupt_cb)?;
+        let (kill_evt, pause_evt) = self.common.dup_eventfds();
+
+        let listener = self.listener.try_clone().map_err(|e| {
+            error!("failed cloning VNC listener: {}", e);
+            ActivateError::BadActivate
+        })?;
+        let vnc = VncServer::new(
+            listener,
+            self.width,
+            self.height,
+            VNC_CLIENT_EVENT,
+            self.console_input.clone(),
+        )
+        .map_err(|e| {
+            error!("failed creating VNC server: {}", e);
+            ActivateError::BadActivate
+        })?;
+
+        let (_, ctrl_queue, ctrl_queue_evt) = queues.remove(0);
+        let (_, cursor_queue, cursor_queue_evt) = queues.remove(0);
+
+        let mut handler = GpuEpollHandler {
+            mem,
+            queues: vec![ctrl_queue, cursor_queue],
+            interrupt_cb,
+            ctrl_queue_evt,
+            cursor_queue_evt,
+            kill_evt,
+            pause_evt,
+            access_platform: self.common.access_platform.clone(),
+            resources: self.resources.clone(),
+            width: self.width,
+            height: self.height,
+            vnc,
+        };
+
+        if self.restored {
+            handler.refresh();
+            self.restored = false;
+        }
+
+        let paused = self.common.paused.clone();
+        let paused_sync = self.common.paused_sync.clone();
+        let mut epoll_threads = Vec::new();
+        spawn_virtio_thread(
+            &self.id,
+            &self.seccomp_action,
+            Thread::VirtioGpu,
+            &mut epoll_threads,
+            &self.exit_evt,
+            move || handler.run(paused, paused_sync.unwrap()),
+        )?;
+
+        self.common.epoll_threads = Some(epoll_threads);
+
+        event!("virtio-device", "activated", "id", &self.id);
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
+        let result = self.common.reset();
+        // Resources don't survive the reset of the device.
+        *self.resources.lock().unwrap() = GpuResources::default();
+        event!("virtio-device", "reset", "id", &self.id);
+        result
+    }
+
+    fn shutdown(&mut self) {
+        std::fs::remove_file(&self.path).ok();
+    }
+
+    fn set_access_platform(&mut self, access_platform: Arc<dyn AccessPlatform>) {
+        self.common.set_access_platform(access_platform)
+    }
+}
+
+impl Pausable for Gpu {
+    fn pause(&mut self) -> result::Result<(), MigratableError> {
+        self.common.pause()
+    }
+
+    fn resume(&mut self) -> result::Result<(), MigratableError> {
+        self.common.resume()
+    }
+}
+
+impl Snapshottable for Gpu {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn snapshot(&mut self) -> std::result::Result<Snapshot, MigratableError> {
+        Snapshot::new_from_versioned_state(&self.state())
+    }
+}
+
+impl Transportable for Gpu {}
+impl Migratable for Gpu {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pixel_to_bgra() {
+        let pixel = [1, 2, 3, 4];
+        assert_eq!(
+            pixel_to_bgra(VIRTIO_GPU_FORMAT_B8G8R8A8_UNORM, &pixel),
+            [1, 2, 3, 4]
+        );
+        assert_eq!(
+            pixel_to_bgra(VIRTIO_GPU_FORMAT_B8G8R8X8_UNORM, &pixel),
+            [1, 2, 3, 0xff]
+        );
+        assert_eq!(
+            pixel_to_bgra(VIRTIO_GPU_FORMAT_A8R8G8B8_UNORM, &pixel),
+            [4, 3, 2, 1]
+        );
+        assert_eq!(
+            pixel_to_bgra(VIRTIO_GPU_FORMAT_R8G8B8A8_UNORM, &pixel),
+            [3, 2, 1, 4]
+        );
+        assert_eq!(
+            pixel_to_bgra(VIRTIO_GPU_FORMAT_A8B8G8R8_UNORM, &pixel),
+            [2, 3, 4, 1]
+        );
+        assert_eq!(
+            pixel_to_bgra(VIRTIO_GPU_FORMAT_X8B8G8R8_UNORM, &pixel),
+            [2, 3, 4, 0xff]
+        );
+    }
+
+    #[test]
+    fn test_read_request() {
+        let mut request = vec![0u8; 1];
+        let entry = VirtioGpuMemEntry {
+            addr: 0x1000,
+            length: 0x2000,
+            padding: 0,
+        };
+        request.extend_from_slice(entry.as_slice());
+
+        let read: VirtioGpuMemEntry = read_request(&request, 1).unwrap();
+        assert_eq!(read.addr, 0x1000);
+        assert_eq!(read.length, 0x2000);
+        assert!(read_request::<VirtioGpuMemEntry>(&request, 2).is_none());
+        assert!(read_request::<VirtioGpuMemEntry>(&request, usize::MAX).is_none());
+    }
+}
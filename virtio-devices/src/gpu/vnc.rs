@@ -0,0 +1,932 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Minimal VNC (RFB 3.3/3.7/3.8) server exposing the scanout of the virtio-gpu
+//! device over a Unix socket.
+//!
+//! Only the "None" security type and the "Raw" encoding are implemented, along
+//! with the "DesktopSize" and "Cursor" pseudo-encodings. A single client is
+//! served at a time, a new connection replacing the previous one. Everything
+//! runs from the epoll thread of the device, sockets being non-blocking.
+//!
+//! The keyboard and pointer events of the client are forwarded to the input
+//! devices fed from the console.
+
+use crate::{ConsoleInput, EpollHelper, EpollHelperError};
+use std::cmp;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use thiserror::Error;
+
+// The framebuffer is stored as 32 bits pixels, blue first.
+pub const BYTES_PER_PIXEL: usize = 4;
+
+const RFB_VERSION: &[u8; 12] = b"RFB 003.008\n";
+const RFB_SECURITY_NONE: u8 = 1;
+const RFB_DESKTOP_NAME: &[u8] = b"Cloud Hypervisor";
+
+// Client to server messages
+const RFB_SET_PIXEL_FORMAT: u8 = 0;
+const RFB_SET_ENCODINGS: u8 = 2;
+const RFB_FRAMEBUFFER_UPDATE_REQUEST: u8 = 3;
+const RFB_KEY_EVENT: u8 = 4;
+const RFB_POINTER_EVENT: u8 = 5;
+const RFB_CLIENT_CUT_TEXT: u8 = 6;
+
+// Server to client messages
+const RFB_FRAMEBUFFER_UPDATE: u8 = 0;
+
+// Encodings
+const RFB_ENCODING_RAW: i32 = 0;
+const RFB_ENCODING_CURSOR: i32 = -239;
+const RFB_ENCODING_DESKTOP_SIZE: i32 = -223;
+
+// Clipboard contents aren't used, but still need to be consumed.
+const MAX_CUT_TEXT_LEN: usize = 1 << 20;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Client disconnected")]
+    Disconnected,
+    #[error("Client socket failure: {0}")]
+    Socket(#[source] io::Error),
+    #[error("Unsupported protocol version: {0:?}")]
+    UnsupportedVersion(Vec<u8>),
+    #[error("Unsupported security type: {0}")]
+    UnsupportedSecurityType(u8),
+    #[error("Unsupported message type: {0}")]
+    UnsupportedMessage(u8),
+    #[error("Unsupported pixel format: {0:?}")]
+    UnsupportedPixelFormat(PixelFormat),
+    #[error("Client cut text too long: {0}")]
+    CutTextTooLong(usize),
+    #[error("Client doesn't support resizing the desktop")]
+    ResizeUnsupported,
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    fn union(&self, other: &Rect) -> Rect {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+        let x = cmp::min(self.x, other.x);
+        let y = cmp::min(self.y, other.y);
+        let right = cmp::max(self.x + self.width, other.x + other.width);
+        let bottom = cmp::max(self.y + self.height, other.y + other.height);
+        Rect {
+            x,
+            y,
+            width: right - x,
+            height: bottom - y,
+        }
+    }
+
+    fn clip(&self, width: u32, height: u32) -> Rect {
+        let x = cmp::min(self.x, width);
+        let y = cmp::min(self.y, height);
+        Rect {
+            x,
+            y,
+            width: cmp::min(self.width, width - x),
+            height: cmp::min(self.height, height - y),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PixelFormat {
+    bits_per_pixel: u8,
+    depth: u8,
+    big_endian: bool,
+    true_colour: bool,
+    red_max: u16,
+    green_max: u16,
+    blue_max: u16,
+    red_shift: u8,
+    green_shift: u8,
+    blue_shift: u8,
+}
+
+impl Default for PixelFormat {
+    // Matches the layout of the framebuffer, so that no conversion is needed
+    // unless the client asks for another format.
+    fn default() -> Self {
+        PixelFormat {
+            bits_per_pixel: 32,
+            depth: 24,
+            big_endian: false,
+            true_colour: true,
+            red_max: 255,
+            green_max: 255,
+            blue_max: 255,
+            red_shift: 16,
+            green_shift: 8,
+            blue_shift: 0,
+        }
+    }
+}
+
+impl PixelFormat {
+    fn from_slice(data: &[u8]) -> Self {
+        PixelFormat {
+            bits_per_pixel: data[0],
+            depth: data[1],
+            big_endian: data[2] != 0,
+            true_colour: data[3] != 0,
+            red_max: u16::from_be_bytes([data[4], data[5]]),
+            green_max: u16::from_be_bytes([data[6], data[7]]),
+            blue_max: u16::from_be_bytes([data[8], data[9]]),
+            red_shift: data[10],
+            green_shift: data[11],
+            blue_shift: data[12],
+        }
+    }
+
+    fn write_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[
+            self.bits_per_pixel,
+            self.depth,
+            self.big_endian as u8,
+            self.true_colour as u8,
+        ]);
+        out.extend_from_slice(&self.red_max.to_be_bytes());
+        out.extend_from_slice(&self.green_max.to_be_bytes());
+        out.extend_from_slice(&self.blue_max.to_be_bytes());
+        out.extend_from_slice(&[self.red_shift, self.green_shift, self.blue_shift, 0, 0, 0]);
+    }
+
+    fn is_supported(&self) -> bool {
+        self.true_colour && matches!(self.bits_per_pixel, 8 | 16 | 32)
+    }
+
+    fn bytes_per_pixel(&self) -> usize {
+        self.bits_per_pixel as usize / 8
+    }
+
+    // Appends a framebuffer pixel (blue, green, red) in the client format.
+    fn write_pixel(&self, pixel: &[u8], out: &mut Vec<u8>) {
+        if *self == PixelFormat::default() {
+            out.extend_from_slice(&[pixel[0], pixel[1], pixel[2], 0]);
+            return;
+        }
+
+        let scale = |component: u8, max: u16| component as u32 * max as u32 / 255;
+        let value = scale(pixel[2], self.red_max) << self.red_shift
+            | scale(pixel[1], self.green_max) << self.green_shift
+            | scale(pixel[0], self.blue_max) << self.blue_shift;
+        let bytes = if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        };
+        let len = self.bytes_per_pixel();
+        if self.big_endian {
+            out.extend_from_slice(&bytes[4 - len..]);
+        } else {
+            out.extend_from_slice(&bytes[..len]);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ClientState {
+    Version,
+    Security,
+    Init,
+    Connected,
+}
+
+struct Cursor {
+    hot_x: u32,
+    hot_y: u32,
+    width: u32,
+    height: u32,
+    // Blue, green, red and alpha components of each pixel.
+    pixels: Vec<u8>,
+}
+
+struct VncClient {
+    stream: UnixStream,
+    state: ClientState,
+    minor_version: u8,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    pixel_format: PixelFormat,
+    desktop_size: bool,
+    cursor: bool,
+    update_requested: bool,
+    dirty: Rect,
+    resized: bool,
+    cursor_changed: bool,
+    writable: bool,
+    console_input: ConsoleInput,
+}
+
+impl VncClient {
+    fn new(stream: UnixStream, console_input: ConsoleInput) -> Self {
+        VncClient {
+            stream,
+            state: ClientState::Version,
+            minor_version: 8,
+            input: Vec::new(),
+            output: RFB_VERSION.to_vec(),
+            pixel_format: PixelFormat::default(),
+            desktop_size: false,
+            cursor: false,
+            update_requested: false,
+            dirty: Rect::default(),
+            resized: false,
+            cursor_changed: false,
+            writable: false,
+            console_input,
+        }
+    }
+
+    fn read_input(&mut self) -> Result<()> {
+        let mut buf = [0u8; 4096];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => return Err(Error::Disconnected),
+                Ok(n) => self.input.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(Error::Socket(e)),
+            }
+        }
+    }
+
+    fn flush_output(&mut self) -> Result<()> {
+        while !self.output.is_empty() {
+            match self.stream.write(&self.output) {
+                Ok(0) => return Err(Error::Disconnected),
+                Ok(n) => {
+                    self.output.drain(..n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(Error::Socket(e)),
+            }
+        }
+        Ok(())
+    }
+
+    // Consumes the complete messages received so far, returning once more
+    // data is needed.
+    fn process_input(&mut self, width: u32, height: u32) -> Result<()> {
+        loop {
+            let consumed = match self.state {
+                ClientState::Version => self.process_version()?,
+                ClientState::Security => self.process_security()?,
+                ClientState::Init => self.process_init(width, height)?,
+                ClientState::Connected => self.process_message(width, height)?,
+            };
+            if consumed == 0 {
+                return Ok(());
+            }
+            self.input.drain(..consumed);
+        }
+    }
+
+    fn process_version(&mut self) -> Result<usize> {
+        if self.input.len() < RFB_VERSION.len() {
+            return Ok(0);
+        }
+
+        let version = &self.input[..RFB_VERSION.len()];
+        if !version.starts_with(b"RFB 003.") {
+            return Err(Error::UnsupportedVersion(version.to_vec()));
+        }
+        self.minor_version = match &version[8..11] {
+            b"003" => 3,
+            b"007" => 7,
+            _ => 8,
+        };
+
+        if self.minor_version == 3 {
+            // The server picks the security type on its own.
+            self.output
+                .extend_from_slice(&(RFB_SECURITY_NONE as u32).to_be_bytes());
+            self.state = ClientState::Init;
+        } else {
+            self.output.extend_from_slice(&[1, RFB_SECURITY_NONE]);
+            self.state = ClientState::Security;
+        }
+
+        Ok(RFB_VERSION.len())
+    }
+
+    fn process_security(&mut self) -> Result<usize> {
+        if self.input.is_empty() {
+            return Ok(0);
+        }
+
+        if self.input[0] != RFB_SECURITY_NONE {
+            return Err(Error::UnsupportedSecurityType(self.input[0]));
+        }
+        if self.minor_version >= 8 {
+            self.output.extend_from_slice(&0u32.to_be_bytes());
+        }
+        self.state = ClientState::Init;
+
+        Ok(1)
+    }
+
+    fn process_init(&mut self, width: u32, height: u32) -> Result<usize> {
+        // The shared flag is meaningless as a single client is served.
+        if self.input.is_empty() {
+            return Ok(0);
+        }
+
+        self.output.extend_from_slice(&(width as u16).to_be_bytes());
+        self.output
+            .extend_from_slice(&(height as u16).to_be_bytes());
+        self.pixel_format.write_to(&mut self.output);
+        self.output
+            .extend_from_slice(&(RFB_DESKTOP_NAME.len() as u32).to_be_bytes());
+        self.output.extend_from_slice(RFB_DESKTOP_NAME);
+        self.state = ClientState::Connected;
+
+        Ok(1)
+    }
+
+    fn process_message(&mut self, width: u32, height: u32) -> Result<usize> {
+        let input = &self.input;
+        if input.is_empty() {
+            return Ok(0);
+        }
+
+        let be16 = |offset: usize| u16::from_be_bytes([input[offset], input[offset + 1]]);
+        let be32 = |offset: usize| {
+            u32::from_be_bytes([
+                input[offset],
+                input[offset + 1],
+                input[offset + 2],
+                input[offset + 3],
+            ])
+        };
+
+        match input[0] {
+            RFB_SET_PIXEL_FORMAT => {
+                if input.len() < 20 {
+                    return Ok(0);
+                }
+                let pixel_format = PixelFormat::from_slice(&input[4..20]);
+                if !pixel_format.is_supported() {
+                    return Err(Error::UnsupportedPixelFormat(pixel_format));
+                }
+                self.pixel_format = pixel_format;
+                Ok(20)
+            }
+            RFB_SET_ENCODINGS => {
+                if input.len() < 4 {
+                    return Ok(0);
+                }
+                let count = be16(2) as usize;
+                let len = 4 + 4 * count;
+                if input.len() < len {
+                    return Ok(0);
+                }
+                // Raw is always supported, only the pseudo-encodings matter.
+                self.desktop_size = false;
+                self.cursor = false;
+                for i in 0..count {
+                    match be32(4 + 4 * i) as i32 {
+                        RFB_ENCODING_DESKTOP_SIZE => self.desktop_size = true,
+                        RFB_ENCODING_CURSOR => self.cursor = true,
+                        _ => {}
+                    }
+                }
+                Ok(len)
+            }
+            RFB_FRAMEBUFFER_UPDATE_REQUEST => {
+                if input.len() < 10 {
+                    return Ok(0);
+                }
+                let incremental = input[1] != 0;
+                let rect = Rect {
+                    x: be16(2) as u32,
+                    y: be16(4) as u32,
+                    width: be16(6) as u32,
+                    height: be16(8) as u32,
+                };
+                if !incremental {
+                    self.dirty = self.dirty.union(&rect.clip(width, height));
+                }
+                self.update_requested = true;
+                Ok(10)
+            }
+            RFB_KEY_EVENT => {
+                if input.len() < 8 {
+                    return Ok(0);
+                }
+                self.console_input.key(be32(4), input[1] != 0);
+                Ok(8)
+            }
+            RFB_POINTER_EVENT => {
+                if input.len() < 6 {
+                    return Ok(0);
+                }
+                self.console_input
+                    .pointer(input[1], be16(2) as u32, be16(4) as u32, width, height);
+                Ok(6)
+            }
+            RFB_CLIENT_CUT_TEXT => {
+                if input.len() < 8 {
+                    return Ok(0);
+                }
+                let text_len = be32(4) as usize;
+                if text_len > MAX_CUT_TEXT_LEN {
+                    return Err(Error::CutTextTooLong(text_len));
+                }
+                if input.len() < 8 + text_len {
+                    return Ok(0);
+                }
+                Ok(8 + text_len)
+            }
+            message_type => Err(Error::UnsupportedMessage(message_type)),
+        }
+    }
+
+    // Sends a framebuffer update if the client asked for one and something
+    // changed since the last one.
+    fn send_update(&mut self, framebuffer: &Framebuffer, cursor: Option<&Cursor>) -> Result<()> {
+        if self.state != ClientState::Connected || !self.update_requested {
+            return Ok(());
+        }
+        // Throttle updates to the pace at which the client receives them.
+        if !self.output.is_empty() {
+            return Ok(());
+        }
+        if self.resized && !self.desktop_size {
+            return Err(Error::ResizeUnsupported);
+        }
+
+        let dirty = self.dirty.clip(framebuffer.width, framebuffer.height);
+        let send_cursor = self.cursor_changed && self.cursor;
+        let mut rects = 0u16;
+        if self.resized {
+            rects += 1;
+        }
+        if send_cursor {
+            rects += 1;
+        }
+        if !dirty.is_empty() {
+            rects += 1;
+        }
+        if rects == 0 {
+            return Ok(());
+        }
+
+        let out = &mut self.output;
+        out.extend_from_slice(&[RFB_FRAMEBUFFER_UPDATE, 0]);
+        out.extend_from_slice(&rects.to_be_bytes());
+
+        let write_rect_header = |out: &mut Vec<u8>, rect: Rect, encoding: i32| {
+            out.extend_from_slice(&(rect.x as u16).to_be_bytes());
+            out.extend_from_slice(&(rect.y as u16).to_be_bytes());
+            out.extend_from_slice(&(rect.width as u16).to_be_bytes());
+            out.extend_from_slice(&(rect.height as u16).to_be_bytes());
+            out.extend_from_slice(&encoding.to_be_bytes());
+        };
+
+        if self.resized {
+            let rect = Rect {
+                x: 0,
+                y: 0,
+                width: framebuffer.width,
+                height: framebuffer.height,
+            };
+            write_rect_header(out, rect, RFB_ENCODING_DESKTOP_SIZE);
+        }
+
+        if send_cursor {
+            let empty = Cursor {
+                hot_x: 0,
+                hot_y: 0,
+                width: 0,
+                height: 0,
+                pixels: Vec::new(),
+            };
+            let cursor = cursor.unwrap_or(&empty);
+            let rect = Rect {
+                x: cursor.hot_x,
+                y: cursor.hot_y,
+                width: cursor.width,
+                height: cursor.height,
+            };
+            write_rect_header(out, rect, RFB_ENCODING_CURSOR);
+            for pixel in cursor.pixels.chunks_exact(BYTES_PER_PIXEL) {
+                self.pixel_format.write_pixel(pixel, out);
+            }
+            // One bit per pixel, set where the cursor is opaque enough.
+            let mask_stride = (cursor.width as usize + 7) / 8;
+            let stride = cmp::max(cursor.width as usize * BYTES_PER_PIXEL, 1);
+            for row in cursor.pixels.chunks_exact(stride) {
+                let mut mask = vec![0u8; mask_stride];
+                for (i, pixel) in row.chunks_exact(BYTES_PER_PIXEL).enumerate() {
+                    if pixel[3] >= 0x80 {
+                        mask[i / 8] |= 0x80 >> (i % 8);
+                    }
+                }
+                out.extend_from_slice(&mask);
+            }
+        }
+
+        if !dirty.is_empty() {
+            write_rect_header(out, dirty, RFB_ENCODING_RAW);
+            let stride = framebuffer.width as usize * BYTES_PER_PIXEL;
+            for y in dirty.y..dirty.y + dirty.height {
+                let start = y as usize * stride + dirty.x as usize * BYTES_PER_PIXEL;
+                let end = start + dirty.width as usize * BYTES_PER_PIXEL;
+                for pixel in framebuffer.data[start..end].chunks_exact(BYTES_PER_PIXEL) {
+                    self.pixel_format.write_pixel(pixel, out);
+                }
+            }
+        }
+
+        self.update_requested = false;
+        self.dirty = Rect::default();
+        self.resized = false;
+        self.cursor_changed = false;
+
+        self.flush_output()
+    }
+}
+
+struct Framebuffer {
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+}
+
+pub struct VncServer {
+    listener: UnixListener,
+    client: Option<VncClient>,
+    framebuffer: Framebuffer,
+    cursor: Option<Cursor>,
+    client_event: u16,
+    console_input: ConsoleInput,
+}
+
+impl VncServer {
+    /// Serves the framebuffer to the clients connecting to `listener`, the
+    /// events of the client socket being reported to the epoll loop with the
+    /// `client_event` identifier. The input of the clients is sent to
+    /// `console_input`.
+    pub fn new(
+        listener: UnixListener,
+        width: u32,
+        height: u32,
+        client_event: u16,
+        console_input: ConsoleInput,
+    ) -> io::Result<Self> {
+        listener.set_nonblocking(true)?;
+        Ok(VncServer {
+            listener,
+            client: None,
+            framebuffer: Framebuffer {
+                width,
+                height,
+                data: vec![0; width as usize * height as usize * BYTES_PER_PIXEL],
+            },
+            cursor: None,
+            client_event,
+            console_input,
+        })
+    }
+
+    pub fn listener_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+
+    pub fn width(&self) -> u32 {
+        self.framebuffer.width
+    }
+
+    /// Framebuffer rows, blue first, `width() * BYTES_PER_PIXEL` bytes each.
+    pub fn framebuffer_mut(&mut self) -> &mut [u8] {
+        &mut self.framebuffer.data
+    }
+
+    /// Changes the size of the framebuffer, clearing its content.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if width == self.framebuffer.width && height == self.framebuffer.height {
+            return;
+        }
+
+        self.framebuffer = Framebuffer {
+            width,
+            height,
+            data: vec![0; width as usize * height as usize * BYTES_PER_PIXEL],
+        };
+        if let Some(client) = self.client.as_mut() {
+            client.resized = true;
+            client.dirty = Rect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            };
+        }
+    }
+
+    /// Changes the cursor image, `pixels` holding the blue, green, red and
+    /// alpha components of each pixel. An empty image hides the cursor.
+    pub fn set_cursor(&mut self, hot_x: u32, hot_y: u32, width: u32, height: u32, pixels: Vec<u8>) {
+        self.cursor = if width == 0 || height == 0 {
+            None
+        } else {
+            Some(Cursor {
+                hot_x,
+                hot_y,
+                width,
+                height,
+                pixels,
+            })
+        };
+        if let Some(client) = self.client.as_mut() {
+            client.cursor_changed = true;
+        }
+    }
+
+    /// Notifies the client that part of the framebuffer has been updated.
+    pub fn damage(&mut self, rect: Rect) {
+        if let Some(client) = self.client.as_mut() {
+            client.dirty = client
+                .dirty
+                .union(&rect.clip(self.framebuffer.width, self.framebuffer.height));
+        }
+    }
+
+    pub fn accept(
+        &mut self,
+        helper: &mut EpollHelper,
+    ) -> std::result::Result<(), EpollHelperError> {
+        let stream = match self.listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(e) => {
+                error!("Failed accepting VNC connection: {}", e);
+                return Ok(());
+            }
+        };
+        if let Err(e) = stream.set_nonblocking(true) {
+            error!("Failed setting VNC connection non-blocking: {}", e);
+            return Ok(());
+        }
+
+        if let Some(client) = self.client.take() {
+            info!("Replacing the current VNC client with a new one");
+            self.disconnect(helper, client)?;
+        }
+
+        helper.add_event_custom(
+            stream.as_raw_fd(),
+            self.client_event,
+            epoll::Events::EPOLLIN | epoll::Events::EPOLLOUT,
+        )?;
+        let mut client = VncClient::new(stream, self.console_input.clone());
+        client.writable = true;
+        client.cursor_changed = self.cursor.is_some();
+        self.client = Some(client);
+
+        self.flush(helper)
+    }
+
+    /// Handles the activity on the client socket.
+    pub fn handle_client_event(
+        &mut self,
+        helper: &mut EpollHelper,
+        events: epoll::Events,
+    ) -> std::result::Result<(), EpollHelperError> {
+        let client = match self.client.as_mut() {
+            Some(client) => client,
+            None => return Ok(()),
+        };
+
+        let result = if events.contains(epoll::Events::EPOLLIN) {
+            let (width, height) = (self.framebuffer.width, self.framebuffer.height);
+            client
+                .read_input()
+                .and_then(|_| client.process_input(width, height))
+        } else if events.intersects(epoll::Events::EPOLLERR | epoll::Events::EPOLLHUP) {
+            Err(Error::Disconnected)
+        } else {
+            Ok(())
+        };
+
+        if let Err(e) = result {
+            self.drop_client(helper, e)?;
+            return Ok(());
+        }
+
+        self.flush(helper)
+    }
+
+    /// Sends the pending updates to the client, if any.
+    pub fn flush(&mut self, helper: &mut EpollHelper) -> std::result::Result<(), EpollHelperError> {
+        let client = match self.client.as_mut() {
+            Some(client) => client,
+            None => return Ok(()),
+        };
+
+        let result = client
+            .flush_output()
+            .and_then(|_| client.send_update(&self.framebuffer, self.cursor.as_ref()));
+        if let Err(e) = result {
+            return self.drop_client(helper, e);
+        }
+
+        // Only wait for the socket to become writable while there is
+        // something left to send.
+        let writable = !client.output.is_empty();
+        if writable != client.writable {
+            let mut events = epoll::Events::EPOLLIN;
+            if writable {
+                events |= epoll::Events::EPOLLOUT;
+            }
+            helper.mod_event_custom(client.stream.as_raw_fd(), self.client_event, events)?;
+            client.writable = writable;
+        }
+
+        Ok(())
+    }
+
+    fn drop_client(
+        &mut self,
+        helper: &mut EpollHelper,
+        error: Error,
+    ) -> std::result::Result<(), EpollHelperError> {
+        match error {
+            Error::Disconnected => info!("VNC client disconnected"),
+            e => warn!("Dropping VNC client: {}", e),
+        }
+        if let Some(client) = self.client.take() {
+            self.disconnect(helper, client)?;
+        }
+        Ok(())
+    }
+
+    fn disconnect(
+        &self,
+        helper: &mut EpollHelper,
+        client: VncClient,
+    ) -> std::result::Result<(), EpollHelperError> {
+        helper.del_event_custom(
+            client.stream.as_raw_fd(),
+            self.client_event,
+            epoll::Events::EPOLLIN,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rect_union_clip() {
+        let a = Rect {
+            x: 10,
+            y: 10,
+            width: 10,
+            height: 10,
+        };
+        let b = Rect {
+            x: 0,
+            y: 15,
+            width: 5,
+            height: 20,
+        };
+        assert_eq!(
+            a.union(&b),
+            Rect {
+                x: 0,
+                y: 10,
+                width: 20,
+                height: 25
+            }
+        );
+        assert_eq!(a.union(&Rect::default()), a);
+        assert_eq!(
+            a.union(&b).clip(15, 30),
+            Rect {
+                x: 0,
+                y: 10,
+                width: 15,
+                height: 20
+            }
+        );
+        assert!(a.clip(5, 5).is_empty());
+    }
+
+    #[test]
+    fn test_handshake() {
+        let (stream, _) = UnixStream::pair().unwrap();
+        let mut client = VncClient::new(stream, ConsoleInput::default());
+        client.output.clear();
+
+        client.input.extend_from_slice(b"RFB 003.008\n");
+        client.input.push(RFB_SECURITY_NONE);
+        client.input.push(1);
+        client.process_input(640, 480).unwrap();
+        assert_eq!(client.state, ClientState::Connected);
+        assert!(client.input.is_empty());
+
+        let mut expected = vec![1, RFB_SECURITY_NONE, 0, 0, 0, 0];
+        expected.extend_from_slice(&[0x02, 0x80, 0x01, 0xe0]);
+        PixelFormat::default().write_to(&mut expected);
+        expected.extend_from_slice(&(RFB_DESKTOP_NAME.len() as u32).to_be_bytes());
+        expected.extend_from_slice(RFB_DESKTOP_NAME);
+        assert_eq!(client.output, expected);
+    }
+
+    #[test]
+    fn test_partial_messages() {
+        let (stream, _) = UnixStream::pair().unwrap();
+        let mut client = VncClient::new(stream, ConsoleInput::default());
+        client.state = ClientState::Connected;
+
+        // SetEncodings with the DesktopSize pseudo-encoding, split in two.
+        client
+            .input
+            .extend_from_slice(&[RFB_SET_ENCODINGS, 0, 0, 2, 0, 0]);
+        client.process_input(640, 480).unwrap();
+        assert_eq!(client.input.len(), 6);
+        assert!(!client.desktop_size);
+
+        client.input.extend_from_slice(&[0, 0]);
+        client
+            .input
+            .extend_from_slice(&RFB_ENCODING_DESKTOP_SIZE.to_be_bytes());
+        client
+            .input
+            .extend_from_slice(&[RFB_FRAMEBUFFER_UPDATE_REQUEST, 0]);
+        client
+            .input
+            .extend_from_slice(&[0, 0, 0, 0, 0x10, 0, 0x10, 0]);
+        client.process_input(640, 480).unwrap();
+        assert!(client.input.is_empty());
+        assert!(client.desktop_size);
+        assert!(!client.cursor);
+        assert!(client.update_requested);
+        assert_eq!(
+            client.dirty,
+            Rect {
+                x: 0,
+                y: 0,
+                width: 640,
+                height: 480
+            }
+        );
+
+        client.input.push(0xff);
+        assert!(matches!(
+            client.process_input(640, 480),
+            Err(Error::UnsupportedMessage(0xff))
+        ));
+    }
+
+    #[test]
+    fn test_pixel_conversion() {
+        let mut out = Vec::new();
+        PixelFormat::default().write_pixel(&[1, 2, 3, 4], &mut out);
+        assert_eq!(out, vec![1, 2, 3, 0]);
+
+        // RGB565, big endian.
+        let rgb565 = PixelFormat {
+            bits_per_pixel: 16,
+            depth: 16,
+            big_endian: true,
+            true_colour: true,
+            red_max: 31,
+            green_max: 63,
+            blue_max: 31,
+            red_shift: 11,
+            green_shift: 5,
+            blue_shift: 0,
+        };
+        out.clear();
+        rgb565.write_pixel(&[0xff, 0, 0xff, 0], &mut out);
+        assert_eq!(out, vec![0xf8, 0x1f]);
+    }
+}
@@ -0,0 +1,450 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Input events coming from the remote console, i.e. the VNC server of the
+//! virtio-gpu device, translated into Linux input events for the virtio-input
+//! devices fed from it.
+
+use super::{
+    VirtioInputEvent, ABS_X, ABS_Y, BTN_LEFT, BTN_MIDDLE, BTN_RIGHT, EV_ABS, EV_KEY, EV_REL,
+    EV_SYN, MAX_PENDING_EVENTS, REL_WHEEL, REL_X, REL_Y, SYN_REPORT,
+};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use vmm_sys_util::eventfd::{EventFd, EFD_NONBLOCK};
+
+// Range of the absolute coordinates reported by the tablet.
+pub const TABLET_ABS_MAX: u32 = 0x7fff;
+
+// Buttons of the RFB pointer events mask, in order.
+const POINTER_BUTTONS: [u16; 3] = [BTN_LEFT, BTN_MIDDLE, BTN_RIGHT];
+// Buttons of the pointer events injected through the API, in order.
+const INJECTED_BUTTONS: [u16; 3] = [BTN_LEFT, BTN_RIGHT, BTN_MIDDLE];
+const POINTER_WHEEL_UP: u8 = 1 << 3;
+const POINTER_WHEEL_DOWN: u8 = 1 << 4;
+
+/// Kind of device fed from the remote console.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConsoleInputKind {
+    Keyboard,
+    Mouse,
+    Tablet,
+}
+
+/// Events waiting to be sent to the guest by an input device, along with the
+/// eventfd notifying the device about them.
+pub struct InputEvents {
+    events: Mutex<VecDeque<VirtioInputEvent>>,
+    pub evt: EventFd,
+}
+
+impl InputEvents {
+    fn new() -> std::io::Result<Self> {
+        Ok(InputEvents {
+            events: Mutex::new(VecDeque::new()),
+            evt: EventFd::new(EFD_NONBLOCK)?,
+        })
+    }
+
+    fn push(&self, events: &[VirtioInputEvent]) {
+        let mut pending = self.events.lock().unwrap();
+        pending.extend(events);
+        while pending.len() > MAX_PENDING_EVENTS {
+            pending.pop_front();
+        }
+        drop(pending);
+
+        if let Err(e) = self.evt.write(1) {
+            error!("Failed notifying input events: {}", e);
+        }
+    }
+
+    pub fn take(&self) -> VecDeque<VirtioInputEvent> {
+        std::mem::take(&mut self.events.lock().unwrap())
+    }
+}
+
+#[derive(Default)]
+struct ConsoleInputState {
+    devices: Vec<(ConsoleInputKind, Arc<InputEvents>)>,
+    buttons: u8,
+    position: Option<(u32, u32)>,
+    injected_buttons: u8,
+}
+
+impl ConsoleInputState {
+    fn send(&self, kind: ConsoleInputKind, events: &[VirtioInputEvent]) {
+        for (_, device) in self.devices.iter().filter(|(k, _)| *k == kind) {
+            device.push(events);
+        }
+    }
+}
+
+/// Dispatcher of the keyboard and pointer events received by the remote
+/// console to the input devices fed from it.
+#[derive(Clone, Default)]
+pub struct ConsoleInput {
+    state: Arc<Mutex<ConsoleInputState>>,
+}
+
+impl ConsoleInput {
+    /// Registers an input device of the given kind, returning the queue its
+    /// events are sent to.
+    pub fn subscribe(&self, kind: ConsoleInputKind) -> std::io::Result<Arc<InputEvents>> {
+        let events = Arc::new(InputEvents::new()?);
+        self.state
+            .lock()
+            .unwrap()
+            .devices
+            .push((kind, events.clone()));
+        Ok(events)
+    }
+
+    /// Returns whether an input device of the given kind is fed from the
+    /// console.
+    pub fn has_device(&self, kind: ConsoleInputKind) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .devices
+            .iter()
+            .any(|(k, _)| *k == kind)
+    }
+
+    /// Handles a key being pressed or released, identified by its X keysym.
+    pub fn key(&self, keysym: u32, down: bool) {
+        match keysym_to_key(keysym) {
+            Some(code) => self.inject_key(code, down),
+            None => debug!("Ignoring unknown keysym 0x{:x}", keysym),
+        }
+    }
+
+    /// Handles a key being pressed or released, identified by its Linux code.
+    pub fn inject_key(&self, code: u16, down: bool) {
+        self.state.lock().unwrap().send(
+            ConsoleInputKind::Keyboard,
+            &[
+                VirtioInputEvent::new(EV_KEY, code, down as u32),
+                VirtioInputEvent::new(EV_SYN, SYN_REPORT, 0),
+            ],
+        );
+    }
+
+    /// Handles the pointer moving to `(x, y)` on a `width` x `height` display,
+    /// with the RFB `buttons` mask pressed.
+    pub fn pointer(&self, buttons: u8, x: u32, y: u32, width: u32, height: u32) {
+        let mut state = self.state.lock().unwrap();
+
+        let mut button_events = Vec::new();
+        for (i, button) in POINTER_BUTTONS.iter().enumerate() {
+            let mask = 1 << i;
+            if (buttons ^ state.buttons) & mask != 0 {
+                button_events.push(VirtioInputEvent::new(
+                    EV_KEY,
+                    *button,
+                    (buttons & mask != 0) as u32,
+                ));
+            }
+        }
+        // The wheel is reported as a button press for each step.
+        let pressed = buttons & !state.buttons;
+        if pressed & POINTER_WHEEL_UP != 0 {
+            button_events.push(VirtioInputEvent::new(EV_REL, REL_WHEEL, 1));
+        }
+        if pressed & POINTER_WHEEL_DOWN != 0 {
+            button_events.push(VirtioInputEvent::new(EV_REL, REL_WHEEL, -1i32 as u32));
+        }
+        let syn = VirtioInputEvent::new(EV_SYN, SYN_REPORT, 0);
+
+        let scale = |pos: u32, size: u32| {
+            (pos.min(size.saturating_sub(1)) as u64 * TABLET_ABS_MAX as u64
+                / std::cmp::max(size.saturating_sub(1), 1) as u64) as u32
+        };
+        let mut tablet_events = vec![
+            VirtioInputEvent::new(EV_ABS, ABS_X, scale(x, width)),
+            VirtioInputEvent::new(EV_ABS, ABS_Y, scale(y, height)),
+        ];
+        tablet_events.extend(&button_events);
+        tablet_events.push(syn);
+        state.send(ConsoleInputKind::Tablet, &tablet_events);
+
+        let (last_x, last_y) = state.position.unwrap_or((x, y));
+        let mut mouse_events = Vec::new();
+        if x != last_x {
+            mouse_events.push(VirtioInputEvent::new(EV_REL, REL_X, x.wrapping_sub(last_x)));
+        }
+        if y != last_y {
+            mouse_events.push(VirtioInputEvent::new(EV_REL, REL_Y, y.wrapping_sub(last_y)));
+        }
+        mouse_events.extend(&button_events);
+        if !mouse_events.is_empty() {
+            mouse_events.push(syn);
+            state.send(ConsoleInputKind::Mouse, &mouse_events);
+        }
+
+        state.buttons = buttons;
+        state.position = Some((x, y));
+    }
+
+    /// Handles a relative motion of the mouse, with the left (bit 0), right
+    /// (bit 1) and middle (bit 2) `buttons` pressed.
+    pub fn inject_pointer(&self, dx: i32, dy: i32, buttons: u8) {
+        let mut state = self.state.lock().unwrap();
+
+        let mut events = Vec::new();
+        if dx != 0 {
+            events.push(VirtioInputEvent::new(EV_REL, REL_X, dx as u32));
+        }
+        if dy != 0 {
+            events.push(VirtioInputEvent::new(EV_REL, REL_Y, dy as u32));
+        }
+        for (i, button) in INJECTED_BUTTONS.iter().enumerate() {
+            let mask = 1 << i;
+            if (buttons ^ state.injected_buttons) & mask != 0 {
+                events.push(VirtioInputEvent::new(
+                    EV_KEY,
+                    *button,
+                    (buttons & mask != 0) as u32,
+                ));
+            }
+        }
+        if !events.is_empty() {
+            events.push(VirtioInputEvent::new(EV_SYN, SYN_REPORT, 0));
+            state.send(ConsoleInputKind::Mouse, &events);
+        }
+
+        state.injected_buttons = buttons;
+    }
+}
+
+// Linux key codes, from include/uapi/linux/input-event-codes.h, of the keys
+// typing the ASCII characters on a US keyboard layout.
+const ASCII_KEYS: [(u8, u8, u16); 47] = [
+    (b'1', b'!', 2),
+    (b'2', b'@', 3),
+    (b'3', b'#', 4),
+    (b'4', b'$', 5),
+    (b'5', b'%', 6),
+    (b'6', b'^', 7),
+    (b'7', b'&', 8),
+    (b'8', b'*', 9),
+    (b'9', b'(', 10),
+    (b'0', b')', 11),
+    (b'-', b'_', 12),
+    (b'=', b'+', 13),
+    (b'q', b'Q', 16),
+    (b'w', b'W', 17),
+    (b'e', b'E', 18),
+    (b'r', b'R', 19),
+    (b't', b'T', 20),
+    (b'y', b'Y', 21),
+    (b'u', b'U', 22),
+    (b'i', b'I', 23),
+    (b'o', b'O', 24),
+    (b'p', b'P', 25),
+    (b'[', b'{', 26),
+    (b']', b'}', 27),
+    (b'a', b'A', 30),
+    (b's', b'S', 31),
+    (b'd', b'D', 32),
+    (b'f', b'F', 33),
+    (b'g', b'G', 34),
+    (b'h', b'H', 35),
+    (b'j', b'J', 36),
+    (b'k', b'K', 37),
+    (b'l', b'L', 38),
+    (b';', b':', 39),
+    (b'\'', b'"', 40),
+    (b'`', b'~', 41),
+    (b'\\', b'|', 43),
+    (b'z', b'Z', 44),
+    (b'x', b'X', 45),
+    (b'c', b'C', 46),
+    (b'v', b'V', 47),
+    (b'b', b'B', 48),
+    (b'n', b'N', 49),
+    (b'm', b'M', 50),
+    (b',', b'<', 51),
+    (b'.', b'>', 52),
+    (b'/', b'?', 53),
+];
+
+// X keysyms, from X11/keysymdef.h, of the other keys.
+const FUNCTION_KEYS: [(u32, u16); 42] = [
+    (0x0020, 57),  // space
+    (0xff08, 14),  // BackSpace
+    (0xff09, 15),  // Tab
+    (0xff0d, 28),  // Return
+    (0xff13, 119), // Pause
+    (0xff14, 70),  // Scroll_Lock
+    (0xff15, 99),  // Sys_Req
+    (0xff1b, 1),   // Escape
+    (0xff50, 102), // Home
+    (0xff51, 105), // Left
+    (0xff52, 103), // Up
+    (0xff53, 106), // Right
+    (0xff54, 108), // Down
+    (0xff55, 104), // Page_Up
+    (0xff56, 109), // Page_Down
+    (0xff57, 107), // End
+    (0xff61, 99),  // Print
+    (0xff63, 110), // Insert
+    (0xff67, 127), // Menu
+    (0xff7f, 69),  // Num_Lock
+    (0xff8d, 96),  // KP_Enter
+    (0xffbe, 59),  // F1
+    (0xffbf, 60),  // F2
+    (0xffc0, 61),  // F3
+    (0xffc1, 62),  // F4
+    (0xffc2, 63),  // F5
+    (0xffc3, 64),  // F6
+    (0xffc4, 65),  // F7
+    (0xffc5, 66),  // F8
+    (0xffc6, 67),  // F9
+    (0xffc7, 68),  // F10
+    (0xffc8, 87),  // F11
+    (0xffc9, 88),  // F12
+    (0xffe1, 42),  // Shift_L
+    (0xffe2, 54),  // Shift_R
+    (0xffe3, 29),  // Control_L
+    (0xffe4, 97),  // Control_R
+    (0xffe5, 58),  // Caps_Lock
+    (0xffe9, 56),  // Alt_L
+    (0xffea, 100), // Alt_R
+    (0xffeb, 125), // Super_L
+    (0xffec, 126), // Super_R
+];
+
+const KEYSYM_DELETE: u32 = 0xffff;
+const KEYSYM_ISO_LEVEL3_SHIFT: u32 = 0xfe03;
+const KEYSYM_META_L: u32 = 0xffe7;
+const KEYSYM_META_R: u32 = 0xffe8;
+
+// Linux key codes of the keypad keys, which can only be injected through
+// the API.
+const KEYPAD_KEYS: [u16; 16] = [
+    55, 71, 72, 73, 74, 75, 76, 77, 78, 79, 80, 81, 82, 83, 86, 98,
+];
+
+/// Linux key codes of all the keys the console keyboard can report.
+pub fn console_keys() -> impl Iterator<Item = u16> {
+    ASCII_KEYS
+        .iter()
+        .map(|(_, _, code)| *code)
+        .chain(FUNCTION_KEYS.iter().map(|(_, code)| *code))
+        .chain(KEYPAD_KEYS)
+        .chain([111])
+}
+
+// Translates an X keysym into the Linux code of the key producing it.
+fn keysym_to_key(keysym: u32) -> Option<u16> {
+    match keysym {
+        KEYSYM_DELETE => return Some(111),
+        KEYSYM_ISO_LEVEL3_SHIFT => return Some(100),
+        KEYSYM_META_L => return Some(125),
+        KEYSYM_META_R => return Some(126),
+        _ => {}
+    }
+
+    if let Some((_, code)) = FUNCTION_KEYS.iter().find(|(sym, _)| *sym == keysym) {
+        return Some(*code);
+    }
+
+    // Latin-1 keysyms match the ASCII characters.
+    let c = u8::try_from(keysym).ok()?;
+    ASCII_KEYS
+        .iter()
+        .find(|(lower, upper, _)| *lower == c || *upper == c)
+        .map(|(_, _, code)| *code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keysym_to_key() {
+        assert_eq!(keysym_to_key(b'a' as u32), Some(30));
+        assert_eq!(keysym_to_key(b'A' as u32), Some(30));
+        assert_eq!(keysym_to_key(b'!' as u32), Some(2));
+        assert_eq!(keysym_to_key(0xff0d), Some(28));
+        assert_eq!(keysym_to_key(0xffff), Some(111));
+        assert_eq!(keysym_to_key(0x20ac), None);
+    }
+
+    #[test]
+    fn test_pointer() {
+        let console = ConsoleInput::default();
+        let tablet = console.subscribe(ConsoleInputKind::Tablet).unwrap();
+        let mouse = console.subscribe(ConsoleInputKind::Mouse).unwrap();
+        let keyboard = console.subscribe(ConsoleInputKind::Keyboard).unwrap();
+
+        console.pointer(0, 10, 20, 100, 100);
+        console.pointer(1, 15, 10, 100, 100);
+
+        let events: Vec<VirtioInputEvent> = tablet.take().into_iter().collect();
+        assert_eq!(
+            events,
+            vec![
+                VirtioInputEvent::new(EV_ABS, ABS_X, 10 * TABLET_ABS_MAX / 99),
+                VirtioInputEvent::new(EV_ABS, ABS_Y, 20 * TABLET_ABS_MAX / 99),
+                VirtioInputEvent::new(EV_SYN, SYN_REPORT, 0),
+                VirtioInputEvent::new(EV_ABS, ABS_X, 15 * TABLET_ABS_MAX / 99),
+                VirtioInputEvent::new(EV_ABS, ABS_Y, 10 * TABLET_ABS_MAX / 99),
+                VirtioInputEvent::new(EV_KEY, BTN_LEFT, 1),
+                VirtioInputEvent::new(EV_SYN, SYN_REPORT, 0),
+            ]
+        );
+
+        let events: Vec<VirtioInputEvent> = mouse.take().into_iter().collect();
+        assert_eq!(
+            events,
+            vec![
+                VirtioInputEvent::new(EV_REL, REL_X, 5),
+                VirtioInputEvent::new(EV_REL, REL_Y, -10i32 as u32),
+                VirtioInputEvent::new(EV_KEY, BTN_LEFT, 1),
+                VirtioInputEvent::new(EV_SYN, SYN_REPORT, 0),
+            ]
+        );
+
+        assert!(keyboard.take().is_empty());
+    }
+
+    #[test]
+    fn test_inject() {
+        let console = ConsoleInput::default();
+        assert!(!console.has_device(ConsoleInputKind::Mouse));
+        let mouse = console.subscribe(ConsoleInputKind::Mouse).unwrap();
+        let keyboard = console.subscribe(ConsoleInputKind::Keyboard).unwrap();
+        assert!(console.has_device(ConsoleInputKind::Mouse));
+        assert!(!console.has_device(ConsoleInputKind::Tablet));
+
+        console.inject_key(111, true);
+        console.inject_key(111, false);
+        let events: Vec<VirtioInputEvent> = keyboard.take().into_iter().collect();
+        assert_eq!(
+            events,
+            vec![
+                VirtioInputEvent::new(EV_KEY, 111, 1),
+                VirtioInputEvent::new(EV_SYN, SYN_REPORT, 0),
+                VirtioInputEvent::new(EV_KEY, 111, 0),
+                VirtioInputEvent::new(EV_SYN, SYN_REPORT, 0),
+            ]
+        );
+
+        console.inject_pointer(-3, 0, 0x2);
+        console.inject_pointer(0, 0, 0);
+        let events: Vec<VirtioInputEvent> = mouse.take().into_iter().collect();
+        assert_eq!(
+            events,
+            vec![
+                VirtioInputEvent::new(EV_REL, REL_X, -3i32 as u32),
+                VirtioInputEvent::new(EV_KEY, BTN_RIGHT, 1),
+                VirtioInputEvent::new(EV_SYN, SYN_REPORT, 0),
+                VirtioInputEvent::new(EV_KEY, BTN_RIGHT, 0),
+                VirtioInputEvent::new(EV_SYN, SYN_REPORT, 0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,231 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Host evdev nodes (`/dev/input/eventN`) backing virtio-input devices.
+//!
+//! The capabilities of the host device are reported as-is to the guest, and
+//! the events are forwarded without any translation in both directions. The
+//! host device is grabbed so that the host doesn't process its events too.
+
+use super::{
+    test_bit, InputDeviceInfo, VirtioInputAbsInfo, VirtioInputDevIds, VirtioInputEvent, EV_ABS,
+};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::mem::size_of;
+use std::os::raw::{c_uint, c_ulong};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use vm_memory::ByteValued;
+use vmm_sys_util::ioctl::{ioctl_with_mut_ptr, ioctl_with_mut_ref, ioctl_with_val, _IOC_READ};
+use vmm_sys_util::{ioctl_ioc_nr, ioctl_ior_nr, ioctl_iow_nr};
+
+// Got from include/uapi/linux/input.h
+const EVDEV: c_uint = 0x45; // 'E'
+const EV_CNT: usize = 0x20;
+const ABS_CNT: usize = 0x40;
+// Size of the largest bitmap, the one of the EV_KEY event codes.
+const KEY_CNT: usize = 0x300;
+const MAX_NAME_LEN: usize = 128;
+
+ioctl_ior_nr!(EVIOCGID, EVDEV, 0x02, InputId);
+ioctl_ioc_nr!(EVIOCGNAME, _IOC_READ, EVDEV, 0x06, len, len);
+ioctl_ioc_nr!(EVIOCGUNIQ, _IOC_READ, EVDEV, 0x08, len, len);
+ioctl_ioc_nr!(EVIOCGPROP, _IOC_READ, EVDEV, 0x09, len, len);
+ioctl_ioc_nr!(EVIOCGBIT, _IOC_READ, EVDEV, 0x20 + ev, len, ev, len);
+ioctl_ioc_nr!(
+    EVIOCGABS,
+    _IOC_READ,
+    EVDEV,
+    0x40 + abs,
+    size_of::<InputAbsInfo>() as c_uint,
+    abs
+);
+ioctl_iow_nr!(EVIOCGRAB, EVDEV, 0x90, ::std::os::raw::c_int);
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct InputId {
+    bustype: u16,
+    vendor: u16,
+    product: u16,
+    version: u16,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+#[allow(dead_code)]
+struct InputAbsInfo {
+    value: i32,
+    minimum: i32,
+    maximum: i32,
+    fuzz: i32,
+    flat: i32,
+    resolution: i32,
+}
+
+/// Opens the evdev node at `path` and grabs it for the exclusive use of the
+/// guest.
+pub fn open(path: &Path) -> io::Result<File> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)?;
+
+    // SAFETY: the file is an open evdev node and the ioctl takes no pointer.
+    let ret = unsafe { ioctl_with_val(&file, EVIOCGRAB(), 1) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(file)
+}
+
+// Reads a variable length property of the device into a buffer of `len`
+// bytes, returning the number of bytes written by the kernel.
+fn read_property(file: &File, request: c_ulong, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0u8; len];
+    // SAFETY: the kernel writes at most `len` bytes, the size encoded in the
+    // request, into the buffer.
+    let ret = unsafe { ioctl_with_mut_ptr(file, request, buf.as_mut_ptr()) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    buf.truncate(ret as usize);
+
+    Ok(buf)
+}
+
+// Strips the trailing NUL bytes from a string property.
+fn read_string(file: &File, request: c_ulong) -> io::Result<Vec<u8>> {
+    let mut s = read_property(file, request, MAX_NAME_LEN)?;
+    while s.last() == Some(&0) {
+        s.pop();
+    }
+    Ok(s)
+}
+
+/// Queries the identity and capabilities of the evdev device.
+pub fn device_info(file: &File) -> io::Result<InputDeviceInfo> {
+    let mut id = InputId::default();
+    // SAFETY: the kernel fills the input_id structure and nothing more.
+    let ret = unsafe { ioctl_with_mut_ref(file, EVIOCGID(), &mut id) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let name = read_string(file, EVIOCGNAME(MAX_NAME_LEN as c_uint))?;
+    // Many devices don't have a unique identifier.
+    let serial = read_string(file, EVIOCGUNIQ(MAX_NAME_LEN as c_uint)).unwrap_or_default();
+    let properties = read_property(file, EVIOCGPROP(MAX_NAME_LEN as c_uint))?;
+
+    let types = read_property(file, EVIOCGBIT(0, (EV_CNT / 8) as c_uint))?;
+    let mut events = Vec::new();
+    // The bitmap of the supported event types starts with EV_SYN, which isn't
+    // reported to the guest.
+    for ev in 1..EV_CNT {
+        if !test_bit(&types, ev) {
+            continue;
+        }
+        let codes = read_property(file, EVIOCGBIT(ev as c_uint, (KEY_CNT / 8) as c_uint))?;
+        events.push((ev as u8, codes));
+    }
+
+    let mut abs_info = Vec::new();
+    if let Some((_, axes)) = events.iter().find(|(ev, _)| *ev as u16 == EV_ABS) {
+        for abs in 0..ABS_CNT {
+            if !test_bit(axes, abs) {
+                continue;
+            }
+            let mut info = InputAbsInfo::default();
+            // SAFETY: the kernel fills the input_absinfo structure and
+            // nothing more.
+            let ret = unsafe { ioctl_with_mut_ref(file, EVIOCGABS(abs as c_uint), &mut info) };
+            if ret < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            abs_info.push((
+                abs as u8,
+                VirtioInputAbsInfo {
+                    min: info.minimum as u32,
+                    max: info.maximum as u32,
+                    fuzz: info.fuzz as u32,
+                    flat: info.flat as u32,
+                    res: info.resolution as u32,
+                },
+            ));
+        }
+    }
+
+    Ok(InputDeviceInfo {
+        name,
+        serial,
+        ids: VirtioInputDevIds {
+            bustype: id.bustype,
+            vendor: id.vendor,
+            product: id.product,
+            version: id.version,
+        },
+        properties,
+        events,
+        abs_info,
+    })
+}
+
+// Layout of input_event on 64 bits architectures.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+#[allow(dead_code)]
+struct InputEvent {
+    sec: i64,
+    usec: i64,
+    type_: u16,
+    code: u16,
+    value: i32,
+}
+
+/// Reads the events available from the device, until none is left.
+pub fn read_events(file: &mut File, events: &mut Vec<VirtioInputEvent>) -> io::Result<()> {
+    let event_size = size_of::<InputEvent>();
+    let mut buf = [0u8; 64 * size_of::<InputEvent>()];
+    loop {
+        let len = match file.read(&mut buf) {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        // The kernel only returns whole events.
+        for chunk in buf[..len].chunks_exact(event_size) {
+            let mut event = InputEvent::default();
+            event.as_mut_slice().copy_from_slice(chunk);
+            events.push(VirtioInputEvent::new(
+                event.type_,
+                event.code,
+                event.value as u32,
+            ));
+        }
+    }
+}
+
+/// Writes an event, usually a LED or force-feedback one, to the device.
+pub fn write_event(file: &mut File, event: &VirtioInputEvent) -> io::Result<()> {
+    // The kernel timestamps the event itself.
+    let input_event = InputEvent {
+        type_: event.type_,
+        code: event.code,
+        value: event.value as i32,
+        ..Default::default()
+    };
+    file.write_all(input_event.as_slice())
+}
+
+// SAFETY: the following structures only have data and no implicit padding.
+unsafe impl ByteValued for InputId {}
+// SAFETY: see above
+unsafe impl ByteValued for InputAbsInfo {}
+// SAFETY: see above
+unsafe impl ByteValued for InputEvent {}
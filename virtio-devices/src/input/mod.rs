@@ -0,0 +1,810 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Virtio input device, exposing a keyboard, a mouse or a tablet to the guest.
+//!
+//! The events either come from a host evdev node, or from the keyboard and
+//! pointer of the remote console served by the virtio-gpu device.
+
+mod console;
+mod evdev;
+
+pub use self::console::{ConsoleInput, ConsoleInputKind, InputEvents};
+
+use self::console::{console_keys, TABLET_ABS_MAX};
+use super::Error as DeviceError;
+use super::{
+    ActivateError, ActivateResult, EpollHelper, EpollHelperError, EpollHelperHandler, VirtioCommon,
+    VirtioDevice, VirtioDeviceType, EPOLL_HELPER_EVENT_LAST, VIRTIO_F_IOMMU_PLATFORM,
+    VIRTIO_F_VERSION_1,
+};
+use crate::seccomp_filters::Thread;
+use crate::thread_helper::spawn_virtio_thread;
+use crate::GuestMemoryMmap;
+use crate::{VirtioInterrupt, VirtioInterruptType};
+use anyhow::anyhow;
+use seccompiler::SeccompAction;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::mem::size_of;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::result;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Barrier};
+use thiserror::Error;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+use virtio_queue::{Queue, QueueT};
+use vm_memory::{ByteValued, Bytes, GuestAddressSpace, GuestMemoryAtomic};
+use vm_migration::VersionMapped;
+use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
+use vm_virtio::{AccessPlatform, Translatable};
+use vmm_sys_util::eventfd::EventFd;
+
+const QUEUE_SIZE: u16 = 256;
+const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE; 2];
+
+const EVENT_QUEUE: usize = 0;
+const STATUS_QUEUE: usize = 1;
+
+// New descriptors are pending on the event queue.
+const EVENT_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 1;
+// New descriptors are pending on the status queue.
+const STATUS_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 2;
+// New events are available from the input source.
+const INPUT_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 3;
+
+// Events are dropped, oldest first, when the guest doesn't consume them.
+const MAX_PENDING_EVENTS: usize = 1024;
+
+// Got from include/uapi/linux/virtio_input.h
+const VIRTIO_INPUT_CFG_ID_NAME: u8 = 0x01;
+const VIRTIO_INPUT_CFG_ID_SERIAL: u8 = 0x02;
+const VIRTIO_INPUT_CFG_ID_DEVIDS: u8 = 0x03;
+const VIRTIO_INPUT_CFG_PROP_BITS: u8 = 0x10;
+const VIRTIO_INPUT_CFG_EV_BITS: u8 = 0x11;
+const VIRTIO_INPUT_CFG_ABS_INFO: u8 = 0x12;
+
+// Size of the union holding the data selected by "select" and "subsel".
+const CONFIG_DATA_SIZE: usize = 128;
+const CONFIG_DATA_OFFSET: usize = 8;
+
+// Got from include/uapi/linux/input-event-codes.h
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const EV_REL: u16 = 0x02;
+const EV_ABS: u16 = 0x03;
+const EV_LED: u16 = 0x11;
+const SYN_REPORT: u16 = 0;
+const REL_X: u16 = 0x00;
+const REL_Y: u16 = 0x01;
+const REL_WHEEL: u16 = 0x08;
+const ABS_X: u16 = 0x00;
+const ABS_Y: u16 = 0x01;
+const BTN_LEFT: u16 = 0x110;
+const BTN_RIGHT: u16 = 0x111;
+const BTN_MIDDLE: u16 = 0x112;
+const LED_NUML: u16 = 0x00;
+const LED_CAPSL: u16 = 0x01;
+const LED_SCROLLL: u16 = 0x02;
+const BUS_VIRTUAL: u16 = 0x06;
+
+#[derive(Error, Debug)]
+enum Error {
+    #[error("Descriptor chain too short")]
+    DescriptorChainTooShort,
+    #[error("Invalid descriptor")]
+    InvalidDescriptor,
+    #[error("Failed to read from guest memory: {0}")]
+    GuestMemoryRead(vm_memory::guest_memory::Error),
+    #[error("Failed to write to guest memory: {0}")]
+    GuestMemoryWrite(vm_memory::guest_memory::Error),
+    #[error("Failed adding used index: {0}")]
+    QueueAddUsed(virtio_queue::Error),
+    #[error("Failed to read input events: {0}")]
+    ReadInput(io::Error),
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct VirtioInputEvent {
+    pub type_: u16,
+    pub code: u16,
+    pub value: u32,
+}
+
+impl VirtioInputEvent {
+    pub fn new(type_: u16, code: u16, value: u32) -> Self {
+        VirtioInputEvent { type_, code, value }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct VirtioInputAbsInfo {
+    pub min: u32,
+    pub max: u32,
+    pub fuzz: u32,
+    pub flat: u32,
+    pub res: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct VirtioInputDevIds {
+    pub bustype: u16,
+    pub vendor: u16,
+    pub product: u16,
+    pub version: u16,
+}
+
+// SAFETY: the following structures only have data and no implicit padding.
+unsafe impl ByteValued for VirtioInputEvent {}
+// SAFETY: see above
+unsafe impl ByteValued for VirtioInputAbsInfo {}
+// SAFETY: see above
+unsafe impl ByteValued for VirtioInputDevIds {}
+
+// Tells whether `bit` is set in a bitmap stored as little-endian bytes.
+fn test_bit(bitmap: &[u8], bit: usize) -> bool {
+    bitmap
+        .get(bit / 8)
+        .map_or(false, |b| b & (1 << (bit % 8)) != 0)
+}
+
+// Builds a bitmap, stored as little-endian bytes, with the `bits` set.
+fn bitmap(bits: impl IntoIterator<Item = u16>) -> Vec<u8> {
+    let mut bitmap = Vec::new();
+    for bit in bits {
+        let byte = bit as usize / 8;
+        if bitmap.len() <= byte {
+            bitmap.resize(byte + 1, 0);
+        }
+        bitmap[byte] |= 1 << (bit % 8);
+    }
+    bitmap
+}
+
+/// Identity and capabilities of an input device, as reported to the guest
+/// through the configuration space.
+#[derive(Clone, Debug, Default)]
+pub struct InputDeviceInfo {
+    pub name: Vec<u8>,
+    pub serial: Vec<u8>,
+    pub ids: VirtioInputDevIds,
+    pub properties: Vec<u8>,
+    /// Bitmap of the supported codes for each event type.
+    pub events: Vec<(u8, Vec<u8>)>,
+    pub abs_info: Vec<(u8, VirtioInputAbsInfo)>,
+}
+
+impl InputDeviceInfo {
+    fn console(kind: ConsoleInputKind) -> Self {
+        let buttons = [BTN_LEFT, BTN_RIGHT, BTN_MIDDLE];
+        let (name, product, events) = match kind {
+            ConsoleInputKind::Keyboard => (
+                "Cloud Hypervisor Keyboard",
+                1,
+                vec![
+                    (EV_KEY, bitmap(console_keys())),
+                    (EV_LED, bitmap([LED_NUML, LED_CAPSL, LED_SCROLLL])),
+                ],
+            ),
+            ConsoleInputKind::Mouse => (
+                "Cloud Hypervisor Mouse",
+                2,
+                vec![
+                    (EV_KEY, bitmap(buttons)),
+                    (EV_REL, bitmap([REL_X, REL_Y, REL_WHEEL])),
+                ],
+            ),
+            ConsoleInputKind::Tablet => (
+                "Cloud Hypervisor Tablet",
+                3,
+                vec![
+                    (EV_KEY, bitmap(buttons)),
+                    (EV_REL, bitmap([REL_WHEEL])),
+                    (EV_ABS, bitmap([ABS_X, ABS_Y])),
+                ],
+            ),
+        };
+
+        let abs_info = if kind == ConsoleInputKind::Tablet {
+            let info = VirtioInputAbsInfo {
+                max: TABLET_ABS_MAX,
+                ..Default::default()
+            };
+            vec![(ABS_X as u8, info), (ABS_Y as u8, info)]
+        } else {
+            Vec::new()
+        };
+
+        InputDeviceInfo {
+            name: name.as_bytes().to_vec(),
+            serial: Vec::new(),
+            ids: VirtioInputDevIds {
+                bustype: BUS_VIRTUAL,
+                vendor: 0,
+                product,
+                version: 1,
+            },
+            properties: Vec::new(),
+            events: events
+                .into_iter()
+                .map(|(ev, codes)| (ev as u8, codes))
+                .collect(),
+            abs_info,
+        }
+    }
+
+    // Returns the data selected by the driver through the "select" and
+    // "subsel" fields of the configuration space.
+    fn query(&self, select: u8, subsel: u8) -> Vec<u8> {
+        let mut data = match select {
+            VIRTIO_INPUT_CFG_ID_NAME if subsel == 0 => self.name.clone(),
+            VIRTIO_INPUT_CFG_ID_SERIAL if subsel == 0 => self.serial.clone(),
+            VIRTIO_INPUT_CFG_ID_DEVIDS if subsel == 0 => self.ids.as_slice().to_vec(),
+            VIRTIO_INPUT_CFG_PROP_BITS if subsel == 0 => self.properties.clone(),
+            VIRTIO_INPUT_CFG_EV_BITS => self
+                .events
+                .iter()
+                .find(|(ev, _)| *ev == subsel)
+                .map(|(_, codes)| codes.clone())
+                .unwrap_or_default(),
+            VIRTIO_INPUT_CFG_ABS_INFO => self
+                .abs_info
+                .iter()
+                .find(|(abs, _)| *abs == subsel)
+                .map(|(_, info)| info.as_slice().to_vec())
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        };
+
+        data.truncate(CONFIG_DATA_SIZE);
+        // Empty bitmaps must be reported with a null size.
+        if matches!(
+            select,
+            VIRTIO_INPUT_CFG_PROP_BITS | VIRTIO_INPUT_CFG_EV_BITS
+        ) {
+            while data.last() == Some(&0) {
+                data.pop();
+            }
+        }
+        data
+    }
+}
+
+enum InputBackend {
+    Console(Arc<InputEvents>),
+    Evdev(File),
+}
+
+impl InputBackend {
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(match self {
+            InputBackend::Console(events) => InputBackend::Console(events.clone()),
+            InputBackend::Evdev(file) => InputBackend::Evdev(file.try_clone()?),
+        })
+    }
+}
+
+impl AsRawFd for InputBackend {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            InputBackend::Console(events) => events.evt.as_raw_fd(),
+            InputBackend::Evdev(file) => file.as_raw_fd(),
+        }
+    }
+}
+
+struct InputEpollHandler {
+    mem: GuestMemoryAtomic<GuestMemoryMmap>,
+    queues: Vec<Queue>,
+    interrupt_cb: Arc<dyn VirtioInterrupt>,
+    event_queue_evt: EventFd,
+    status_queue_evt: EventFd,
+    kill_evt: EventFd,
+    pause_evt: EventFd,
+    access_platform: Option<Arc<dyn AccessPlatform>>,
+    backend: InputBackend,
+    pending: VecDeque<VirtioInputEvent>,
+}
+
+impl InputEpollHandler {
+    fn read_input(&mut self) -> result::Result<(), Error> {
+        match &mut self.backend {
+            InputBackend::Console(events) => {
+                events.evt.read().map_err(Error::ReadInput)?;
+                self.pending.extend(events.take());
+            }
+            InputBackend::Evdev(file) => {
+                let mut events = Vec::new();
+                let result = evdev::read_events(file, &mut events);
+                // Forward what was read before the failure, if any.
+                self.pending.extend(events);
+                result.map_err(Error::ReadInput)?;
+            }
+        }
+
+        while self.pending.len() > MAX_PENDING_EVENTS {
+            self.pending.pop_front();
+        }
+
+        Ok(())
+    }
+
+    fn process_event_queue(&mut self) -> result::Result<bool, Error> {
+        let queue = &mut self.queues[EVENT_QUEUE];
+
+        let mut used_descs = false;
+        while !self.pending.is_empty() {
+            let mut desc_chain = match queue.pop_descriptor_chain(self.mem.memory()) {
+                Some(desc_chain) => desc_chain,
+                None => break,
+            };
+            let desc = desc_chain.next().ok_or(Error::DescriptorChainTooShort)?;
+
+            let len = size_of::<VirtioInputEvent>();
+            if !desc.is_write_only() || (desc.len() as usize) < len {
+                return Err(Error::InvalidDescriptor);
+            }
+
+            let event = self.pending.pop_front().unwrap();
+            desc_chain
+                .memory()
+                .write_obj(
+                    event,
+                    desc.addr()
+                        .translate_gva(self.access_platform.as_ref(), len),
+                )
+                .map_err(Error::GuestMemoryWrite)?;
+
+            queue
+                .add_used(desc_chain.memory(), desc_chain.head_index(), len as u32)
+                .map_err(Error::QueueAddUsed)?;
+            used_descs = true;
+        }
+
+        Ok(used_descs)
+    }
+
+    fn process_status_queue(&mut self) -> result::Result<bool, Error> {
+        let queue = &mut self.queues[STATUS_QUEUE];
+
+        let mut used_descs = false;
+        while let Some(mut desc_chain) = queue.pop_descriptor_chain(self.mem.memory()) {
+            let desc = desc_chain.next().ok_or(Error::DescriptorChainTooShort)?;
+
+            let len = size_of::<VirtioInputEvent>();
+            if desc.is_write_only() || (desc.len() as usize) < len {
+                return Err(Error::InvalidDescriptor);
+            }
+
+            let event: VirtioInputEvent = desc_chain
+                .memory()
+                .read_obj(
+                    desc.addr()
+                        .translate_gva(self.access_platform.as_ref(), len),
+                )
+                .map_err(Error::GuestMemoryRead)?;
+
+            // The LEDs of the console keyboard aren't shown anywhere.
+            if let InputBackend::Evdev(file) = &mut self.backend {
+                if let Err(e) = evdev::write_event(file, &event) {
+                    warn!("Failed writing status event {:?}: {}", event, e);
+                }
+            }
+
+            queue
+                .add_used(desc_chain.memory(), desc_chain.head_index(), 0)
+                .map_err(Error::QueueAddUsed)?;
+            used_descs = true;
+        }
+
+        Ok(used_descs)
+    }
+
+    fn signal_used_queue(&self, queue_index: usize) -> result::Result<(), DeviceError> {
+        self.interrupt_cb
+            .trigger(VirtioInterruptType::Queue(queue_index as u16))
+            .map_err(|e| {
+                error!("Failed to signal used queue: {:?}", e);
+                DeviceError::FailedSignalingUsedQueue(e)
+            })
+    }
+
+    fn handle_result(
+        &self,
+        queue_index: usize,
+        result: result::Result<bool, Error>,
+    ) -> result::Result<(), EpollHelperError> {
+        let needs_notification = result.map_err(|e| {
+            EpollHelperError::HandleEvent(anyhow!("Failed to process queue : {:?}", e))
+        })?;
+        if needs_notification {
+            self.signal_used_queue(queue_index).map_err(|e| {
+                EpollHelperError::HandleEvent(anyhow!("Failed to signal used queue: {:?}", e))
+            })?;
+        }
+
+        Ok(())
+    }
+
+    fn run(
+        &mut self,
+        paused: Arc<AtomicBool>,
+        paused_sync: Arc<Barrier>,
+    ) -> result::Result<(), EpollHelperError> {
+        let mut helper = EpollHelper::new(&self.kill_evt, &self.pause_evt)?;
+        helper.add_event(self.event_queue_evt.as_raw_fd(), EVENT_QUEUE_EVENT)?;
+        helper.add_event(self.status_queue_evt.as_raw_fd(), STATUS_QUEUE_EVENT)?;
+        helper.add_event(self.backend.as_raw_fd(), INPUT_EVENT)?;
+        helper.run(paused, paused_sync, self)?;
+
+        Ok(())
+    }
+}
+
+impl EpollHelperHandler for InputEpollHandler {
+    fn handle_event(
+        &mut self,
+        helper: &mut EpollHelper,
+        event: &epoll::Event,
+    ) -> result::Result<(), EpollHelperError> {
+        let ev_type = event.data as u16;
+        match ev_type {
+            EVENT_QUEUE_EVENT => {
+                self.event_queue_evt.read().map_err(|e| {
+                    EpollHelperError::HandleEvent(anyhow!("Failed to get queue event: {:?}", e))
+                })?;
+                let result = self.process_event_queue();
+                self.handle_result(EVENT_QUEUE, result)?;
+            }
+            STATUS_QUEUE_EVENT => {
+                self.status_queue_evt.read().map_err(|e| {
+                    EpollHelperError::HandleEvent(anyhow!("Failed to get queue event: {:?}", e))
+                })?;
+                let result = self.process_status_queue();
+                self.handle_result(STATUS_QUEUE, result)?;
+            }
+            INPUT_EVENT => {
+                // A host device going away, e.g. unplugged, only stops the
+                // flow of events.
+                if let Err(e) = self.read_input() {
+                    error!("Stopped reading input events: {:?}", e);
+                    helper.del_event_custom(
+                        self.backend.as_raw_fd(),
+                        INPUT_EVENT,
+                        epoll::Events::EPOLLIN,
+                    )?;
+                }
+                let result = self.process_event_queue();
+                self.handle_result(EVENT_QUEUE, result)?;
+            }
+            _ => {
+                return Err(EpollHelperError::HandleEvent(anyhow!(
+                    "Unexpected event: {}",
+                    ev_type
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Versionize)]
+pub struct InputState {
+    pub avail_features: u64,
+    pub acked_features: u64,
+    pub select: u8,
+    pub subsel: u8,
+}
+
+impl VersionMapped for InputState {}
+
+/// Virtio device for forwarding keyboard and pointer events to the guest.
+pub struct Input {
+    common: VirtioCommon,
+    id: String,
+    info: InputDeviceInfo,
+    select: u8,
+    subsel: u8,
+    backend: InputBackend,
+    seccomp_action: SeccompAction,
+    exit_evt: EventFd,
+}
+
+impl Input {
+    /// Create a new virtio input device fed from the remote console.
+    pub fn new_console(
+        id: String,
+        kind: ConsoleInputKind,
+        console_input: &ConsoleInput,
+        iommu: bool,
+        seccomp_action: SeccompAction,
+        exit_evt: EventFd,
+        state: Option<InputState>,
+    ) -> io::Result<Input> {
+        let backend = InputBackend::Console(console_input.subscribe(kind)?);
+        Ok(Self::new(
+            id,
+            InputDeviceInfo::console(kind),
+            backend,
+            iommu,
+            seccomp_action,
+            exit_evt,
+            state,
+        ))
+    }
+
+    /// Create a new virtio input device fed from the host evdev node at
+    /// `path`, which is grabbed for the exclusive use of the guest.
+    pub fn new_evdev(
+        id: String,
+        path: &Path,
+        iommu: bool,
+        seccomp_action: SeccompAction,
+        exit_evt: EventFd,
+        state: Option<InputState>,
+    ) -> io::Result<Input> {
+        let file = evdev::open(path)?;
+        let info = evdev::device_info(&file)?;
+        Ok(Self::new(
+            id,
+            info,
+            InputBackend::Evdev(file),
+            iommu,
+            seccomp_action,
+            exit_evt,
+            state,
+        ))
+    }
+
+    fn new(
+        id: String,
+        info: InputDeviceInfo,
+        backend: InputBackend,
+        iommu: bool,
+        seccomp_action: SeccompAction,
+        exit_evt: EventFd,
+        state: Option<InputState>,
+    ) -> Input {
+        let (avail_features, acked_features, select, subsel, paused) = if let Some(state) = state {
+            info!("Restoring virtio-input {}", id);
+            (
+                state.avail_features,
+                state.acked_features,
+                state.select,
+                state.subsel,
+                true,
+            )
+        } else {
+            let mut avail_features = 1u64 << VIRTIO_F_VERSION_1;
+
+            if iommu {
+                avail_features |= 1u64 << VIRTIO_F_IOMMU_PLATFORM;
+            }
+
+            (avail_features, 0, 0, 0, false)
+        };
+
+        Input {
+            common: VirtioCommon {
+                device_type: VirtioDeviceType::Input as u32,
+                queue_sizes: QUEUE_SIZES.to_vec(),
+                paused_sync: Some(Arc::new(Barrier::new(2))),
+                avail_features,
+                acked_features,
+                min_queues: 2,
+                paused: Arc::new(AtomicBool::new(paused)),
+                ..Default::default()
+            },
+            id,
+            info,
+            select,
+            subsel,
+            backend,
+            seccomp_action,
+            exit_evt,
+        }
+    }
+
+    fn state(&self) -> InputState {
+        InputState {
+            avail_features: self.common.avail_features,
+            acked_features: self.common.acked_features,
+            select: self.select,
+            subsel: self.subsel,
+        }
+    }
+
+    fn config(&self) -> [u8; CONFIG_DATA_OFFSET + CONFIG_DATA_SIZE] {
+        let data = self.info.query(self.select, self.subsel);
+        let mut config = [0u8; CONFIG_DATA_OFFSET + CONFIG_DATA_SIZE];
+        config[0] = self.select;
+        config[1] = self.subsel;
+        config[2] = data.len() as u8;
+        config[CONFIG_DATA_OFFSET..CONFIG_DATA_OFFSET + data.len()].copy_from_slice(&data);
+        config
+    }
+
+    #[cfg(fuzzing)]
+    pub fn wait_for_epoll_threads(&mut self) {
+        self.common.wait_for_epoll_threads();
+    }
+}
+
+impl Drop for Input {
+    fn drop(&mut self) {
+        if let Some(kill_evt) = self.common.kill_evt.take() {
+            // Ignore the result because there is nothing we can do about it.
+            let _ = kill_evt.write(1);
+        }
+        self.common.wait_for_epoll_threads();
+    }
+}
+
+impl VirtioDevice for Input {
+    fn device_type(&self) -> u32 {
+        self.common.device_type
+    }
+
+    fn queue_max_sizes(&self) -> &[u16] {
+        &self.common.queue_sizes
+    }
+
+    fn features(&self) -> u64 {
+        self.common.avail_features
+    }
+
+    fn ack_features(&mut self, value: u64) {
+        self.common.ack_features(value)
+    }
+
+    fn read_config(&self, offset: u64, data: &mut [u8]) {
+        self.read_config_from_slice(&self.config(), offset, data);
+    }
+
+    fn write_config(&mut self, offset: u64, data: &[u8]) {
+        // Only "select" and "subsel" are writable.
+        if offset + data.len() as u64 > 2 {
+            error!(
+                "Attempt to write to read-only field: offset {:x} length {}",
+                offset,
+                data.len()
+            );
+            return;
+        }
+
+        for (i, byte) in data.iter().enumerate() {
+            match offset as usize + i {
+                0 => self.select = *byte,
+                _ => self.subsel = *byte,
+            }
+        }
+    }
+
+    fn activate(
+        &mut self,
+        mem: GuestMemoryAtomic<GuestMemoryMmap>,
+        interrupt_cb: Arc<dyn VirtioInterrupt>,
+        mut queues: Vec<(usize, Queue, EventFd)>,
+    ) -> ActivateResult {
+        self.common.activate(&queues, &interrupt_cb)?;
+        let (kill_evt, pause_evt) = self.common.dup_eventfds();
+
+        let backend = self.backend.try_clone().map_err(|e| {
+            error!("failed cloning input source: {}", e);
+            ActivateError::BadActivate
+        })?;
+
+        let (_, event_queue, event_queue_evt) = queues.remove(0);
+        let (_, status_queue, status_queue_evt) = queues.remove(0);
+
+        let mut handler = InputEpollHandler {
+            mem,
+            queues: vec![event_queue, status_queue],
+            interrupt_cb,
+            event_queue_evt,
+            status_queue_evt,
+            kill_evt,
+            pause_evt,
+            access_platform: self.common.access_platform.clone(),
+            backend,
+            pending: VecDeque::new(),
+        };
+
+        let paused = self.common.paused.clone();
+        let paused_sync = self.common.paused_sync.clone();
+        let mut epoll_threads = Vec::new();
+        spawn_virtio_thread(
+            &self.id,
+            &self.seccomp_action,
+            Thread::VirtioInput,
+            &mut epoll_threads,
+            &self.exit_evt,
+            move || handler.run(paused, paused_sync.unwrap()),
+        )?;
+
+        self.common.epoll_threads = Some(epoll_threads);
+
+        event!("virtio-device", "activated", "id", &self.id);
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
+        let result = self.common.reset();
+        event!("virtio-device", "reset", "id", &self.id);
+        result
+    }
+
+    fn set_access_platform(&mut self, access_platform: Arc<dyn AccessPlatform>) {
+        self.common.set_access_platform(access_platform)
+    }
+}
+
+impl Pausable for Input {
+    fn pause(&mut self) -> result::Result<(), MigratableError> {
+        self.common.pause()
+    }
+
+    fn resume(&mut self) -> result::Result<(), MigratableError> {
+        self.common.resume()
+    }
+}
+
+impl Snapshottable for Input {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn snapshot(&mut self) -> std::result::Result<Snapshot, MigratableError> {
+        Snapshot::new_from_versioned_state(&self.state())
+    }
+}
+
+impl Transportable for Input {}
+impl Migratable for Input {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bitmap() {
+        let bits = bitmap([0, 9, 17]);
+        assert_eq!(bits, vec![0x01, 0x02, 0x02]);
+        assert!(test_bit(&bits, 9));
+        assert!(!test_bit(&bits, 10));
+        assert!(!test_bit(&bits, 100));
+    }
+
+    #[test]
+    fn test_query() {
+        let info = InputDeviceInfo::console(ConsoleInputKind::Tablet);
+
+        assert_eq!(
+            info.query(VIRTIO_INPUT_CFG_ID_NAME, 0),
+            b"Cloud Hypervisor Tablet".to_vec()
+        );
+        assert!(info.query(VIRTIO_INPUT_CFG_ID_NAME, 1).is_empty());
+        assert!(info.query(VIRTIO_INPUT_CFG_ID_SERIAL, 0).is_empty());
+        assert!(info.query(VIRTIO_INPUT_CFG_PROP_BITS, 0).is_empty());
+        assert_eq!(
+            info.query(VIRTIO_INPUT_CFG_EV_BITS, EV_REL as u8),
+            vec![0x00, 0x01]
+        );
+        assert!(info
+            .query(VIRTIO_INPUT_CFG_EV_BITS, EV_LED as u8)
+            .is_empty());
+
+        let abs: VirtioInputAbsInfo =
+            *VirtioInputAbsInfo::from_slice(&info.query(VIRTIO_INPUT_CFG_ABS_INFO, ABS_Y as u8))
+                .unwrap();
+        assert_eq!(abs.max, TABLET_ABS_MAX);
+        assert!(info.query(VIRTIO_INPUT_CFG_ABS_INFO, 2).is_empty());
+    }
+}
@@ -24,31 +24,47 @@ use thiserror::Error;
 mod device;
 pub mod balloon;
 pub mod block;
+mod channels;
 mod console;
+mod crypto;
 pub mod epoll_helper;
+mod gpu;
+mod input;
 mod iommu;
 pub mod mem;
 pub mod net;
 mod pmem;
+mod regions;
 mod rng;
+mod scsi;
 pub mod seccomp_filters;
+mod sound;
+mod sound_backend;
 mod thread_helper;
 pub mod transport;
 pub mod vdpa;
+pub mod vhost_net;
 pub mod vhost_user;
 pub mod vsock;
 pub mod watchdog;
 
 pub use self::balloon::*;
 pub use self::block::*;
+pub use self::channels::*;
 pub use self::console::*;
+pub use self::crypto::*;
 pub use self::device::*;
 pub use self::epoll_helper::*;
+pub use self::gpu::*;
+pub use self::input::*;
 pub use self::iommu::*;
 pub use self::mem::*;
 pub use self::net::*;
 pub use self::pmem::*;
 pub use self::rng::*;
+pub use self::scsi::*;
+pub use self::sound::*;
+pub use self::sound_backend::*;
 pub use self::vdpa::*;
 pub use self::vsock::*;
 pub use self::watchdog::*;
@@ -94,6 +110,10 @@ pub enum ActivateError {
     CreateRateLimiter(std::io::Error),
     #[error("Failed to activate the vDPA device: {0}")]
     ActivateVdpa(vdpa::Error),
+    #[error("Failed to setup vhost-net: {0}")]
+    VhostNetSetup(vhost_net::Error),
+    #[error("Failed to set thread affinity: {0}")]
+    SetThreadAffinity(std::io::Error),
 }
 
 pub type ActivateResult = std::result::Result<(), ActivateError>;
@@ -110,6 +130,8 @@ pub enum Error {
     VhostUserUpdateMemory(vhost_user::Error),
     #[error("Failed to add memory region vhost-user: {0}")]
     VhostUserAddMemoryRegion(vhost_user::Error),
+    #[error("Failed to update memory vhost-net: {0}")]
+    VhostNetUpdateMemory(vhost_net::Error),
     #[error("Failed to set shared memory region")]
     SetShmRegionsNotSupported,
     #[error("Failed to process net queue: {0}")]
@@ -151,6 +173,21 @@ impl TryInto<rate_limiter::RateLimiter> for RateLimiterConfig {
     }
 }
 
+impl From<RateLimiterConfig> for rate_limiter::RateLimiterGroup {
+    fn from(config: RateLimiterConfig) -> Self {
+        let bw = config.bandwidth.unwrap_or_default();
+        let ops = config.ops.unwrap_or_default();
+        rate_limiter::RateLimiterGroup::new(
+            bw.size,
+            bw.one_time_burst.unwrap_or(0),
+            bw.refill_time,
+            ops.size,
+            ops.one_time_burst.unwrap_or(0),
+            ops.refill_time,
+        )
+    }
+}
+
 /// Convert an absolute address into an address space (GuestMemory)
 /// to a host pointer and verify that the provided size define a valid
 /// range within a single memory region.
@@ -271,7 +271,7 @@ impl VirtioMemConfig {
         // in the usable region.
         if addr % self.block_size != 0
             || size == 0
-            || (addr < self.addr || addr + size >= self.addr + self.usable_region_size)
+            || (addr < self.addr || addr + size > self.addr + self.usable_region_size)
         {
             return false;
         }
@@ -477,7 +477,20 @@ impl MemEpollHandler {
             return VIRTIO_MEM_RESP_ERROR;
         }
 
+        let handlers = self.dma_mapping_handlers.lock().unwrap();
         if !plug {
+            // The pages pinned for DMA are only given back to the host once
+            // they are unmapped, which must happen before discarding them.
+            for (_, handler) in handlers.iter() {
+                if let Err(e) = handler.unmap(addr, size) {
+                    error!(
+                        "failed DMA unmapping addr 0x{:x} size 0x{:x}: {}",
+                        addr, size, e
+                    );
+                    return VIRTIO_MEM_RESP_ERROR;
+                }
+            }
+
             if let Err(e) = self.discard_memory_range(offset, size) {
                 error!("failed discarding memory range: {:?}", e);
                 return VIRTIO_MEM_RESP_ERROR;
@@ -489,7 +502,6 @@ impl MemEpollHandler {
             .unwrap()
             .set_range(first_block_index, nb_blocks, plug);
 
-        let handlers = self.dma_mapping_handlers.lock().unwrap();
         if plug {
             let mut gpa = addr;
             for _ in 0..nb_blocks {
@@ -508,16 +520,6 @@ impl MemEpollHandler {
 
             config.plugged_size += size;
         } else {
-            for (_, handler) in handlers.iter() {
-                if let Err(e) = handler.unmap(addr, size) {
-                    error!(
-                        "failed DMA unmapping addr 0x{:x} size 0x{:x}: {}",
-                        addr, size, e
-                    );
-                    return VIRTIO_MEM_RESP_ERROR;
-                }
-            }
-
             config.plugged_size -= size;
         }
 
@@ -526,10 +528,6 @@ impl MemEpollHandler {
 
     fn unplug_all(&mut self) -> u16 {
         let mut config = self.config.lock().unwrap();
-        if let Err(e) = self.discard_memory_range(0, config.region_size) {
-            error!("failed discarding memory range: {:?}", e);
-            return VIRTIO_MEM_RESP_ERROR;
-        }
 
         // Remaining plugged blocks are unmapped.
         if config.plugged_size > 0 {
@@ -550,6 +548,11 @@ impl MemEpollHandler {
             }
         }
 
+        if let Err(e) = self.discard_memory_range(0, config.region_size) {
+            error!("failed discarding memory range: {:?}", e);
+            return VIRTIO_MEM_RESP_ERROR;
+        }
+
         self.blocks_state.lock().unwrap().set_range(
             0,
             (config.region_size / config.block_size) as u16,
@@ -881,6 +884,13 @@ impl Mem {
         Ok(())
     }
 
+    /// Size of the memory plugged by the guest. It only follows the
+    /// requested size once the guest driver has processed a resize, and may
+    /// stay above it if the guest can't give some of its memory back.
+    pub fn plugged_size(&self) -> u64 {
+        self.config.lock().unwrap().plugged_size
+    }
+
     fn state(&self) -> MemState {
         MemState {
             avail_features: self.common.avail_features,
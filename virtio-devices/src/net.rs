@@ -12,27 +12,36 @@ use super::{
     EPOLL_HELPER_EVENT_LAST,
 };
 use crate::seccomp_filters::Thread;
-use crate::thread_helper::spawn_virtio_thread;
-use crate::GuestMemoryMmap;
+use crate::thread_helper::{set_virtio_thread_affinity, spawn_virtio_thread};
+use crate::vhost_net::{self, VhostNetQueuePair};
 use crate::VirtioInterrupt;
+use crate::{GuestMemoryMmap, GuestRegionMmap};
 use anyhow::anyhow;
 use net_util::CtrlQueue;
 use net_util::{
-    build_net_config_space, build_net_config_space_with_mq, open_tap,
-    virtio_features_to_tap_offload, MacAddr, NetCounters, NetQueuePair, OpenTapError, RxVirtio,
-    Tap, TapError, TxVirtio, VirtioNetConfig,
+    build_net_config_space, build_net_config_space_with_mq, open_tap, set_vnet_hdr_hash,
+    virtio_features_to_tap_offload, vnet_hdr_len, MacAddr, NetCounters, NetQueuePair,
+    NetQueuePairError, OpenTapError, RssConfig, RxVirtio, Tap, TapError, TxVirtio, VirtioNetConfig,
+    RSS_MAX_INDIRECTION_TABLE_LEN, RSS_MAX_KEY_SIZE, RSS_SUPPORTED_HASH_TYPES,
+    VIRTIO_NET_F_GUEST_USO4, VIRTIO_NET_F_GUEST_USO6, VIRTIO_NET_F_HASH_REPORT,
+    VIRTIO_NET_F_HOST_USO, VIRTIO_NET_F_RSS, VNET_HDR_HASH_LEN,
 };
+use once_cell::sync::Lazy;
+use rate_limiter::RateLimiterGroup;
 use seccompiler::SeccompAction;
 use std::net::Ipv4Addr;
 use std::num::Wrapping;
 use std::ops::Deref;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::result;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Barrier};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Barrier, Mutex, RwLock};
 use std::thread;
 use std::vec::Vec;
-use std::{collections::HashMap, convert::TryInto};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    convert::TryInto,
+};
 use thiserror::Error;
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
@@ -155,6 +164,12 @@ pub const RX_RATE_LIMITER_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 5;
 // New 'wake up' event from the tx rate limiter
 pub const TX_RATE_LIMITER_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 6;
 
+// Largest frame read from the TAP, a 64 KiB GSO frame with its Ethernet and
+// VLAN headers, preceded by the virtio-net header with the hash.
+const MAX_RX_FRAME_LEN: usize = 65_535 + 18 + VNET_HDR_HASH_LEN;
+// Offset of the number of buffers in the virtio-net header.
+const VNET_HDR_NUM_BUFFERS_OFFSET: usize = 10;
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Failed to open taps: {0}")]
@@ -163,24 +178,65 @@ pub enum Error {
     TapError(TapError),
     #[error("Error calling dup() on tap fd: {0}")]
     DuplicateTapFd(std::io::Error),
+    #[error("vhost-net is not available: {0}")]
+    VhostNet(vhost_net::Error),
 }
 
 pub type Result<T> = result::Result<T, Error>;
 
+#[derive(Clone, Default)]
+struct NetQueueCounters {
+    rx_interrupts: Arc<AtomicU64>,
+    tx_interrupts: Arc<AtomicU64>,
+    // Times the guest ran out of receive buffers while frames were being
+    // received, the TAP interface dropping the frames beyond its own queue.
+    rx_no_buffers: Arc<AtomicU64>,
+}
+
+// Counter names must be static, the ones of each queue pair are only built
+// once for all the devices.
+fn queue_counter_name(queue_pair: usize, counter: &'static str) -> &'static str {
+    static NAMES: Lazy<Mutex<HashMap<(usize, &'static str), &'static str>>> =
+        Lazy::new(|| Mutex::new(HashMap::new()));
+    *NAMES
+        .lock()
+        .unwrap()
+        .entry((queue_pair, counter))
+        .or_insert_with(|| Box::leak(format!("qp{queue_pair}_{counter}").into_boxed_str()))
+}
+
+// Reads a counter from the statistics of the TAP interface.
+fn tap_statistic(if_name: &str, statistic: &str) -> Option<u64> {
+    std::fs::read_to_string(format!("/sys/class/net/{if_name}/statistics/{statistic}"))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
 struct NetEpollHandler {
     net: NetQueuePair,
+    queue_counters: NetQueueCounters,
     mem: GuestMemoryAtomic<GuestMemoryMmap>,
     interrupt_cb: Arc<dyn VirtioInterrupt>,
     kill_evt: EventFd,
     pause_evt: EventFd,
     queue_index_base: u16,
-    queue_pair: (Queue, Queue),
+    // Receive queues of all the queue pairs, since frames can be steered to
+    // any of them.
+    rx_queues: Vec<Arc<Mutex<Queue>>>,
+    tx_queue: Queue,
     queue_evt_pair: (EventFd, EventFd),
     // Always generate interrupts until the driver has signalled to the device.
     // This mitigates a problem with interrupts from tap events being "lost" upon
     // a restore as the vCPU thread isn't ready to handle the interrupt. This causes
     // issues when combined with VIRTIO_RING_F_EVENT_IDX interrupt suppression.
     driver_awake: bool,
+    rss: Arc<RwLock<Option<RssConfig>>>,
+    // Whether the hash of each frame is reported in its virtio-net header.
+    hash_report: bool,
+    // Frame read from the TAP, before being copied to the guest.
+    rx_frame: Vec<u8>,
 }
 
 impl NetEpollHandler {
@@ -225,11 +281,14 @@ impl NetEpollHandler {
     fn process_tx(&mut self) -> result::Result<(), DeviceError> {
         if self
             .net
-            .process_tx(&self.mem.memory(), &mut self.queue_pair.1)
+            .process_tx(&self.mem.memory(), &mut self.tx_queue)
             .map_err(DeviceError::NetQueuePair)?
             || !self.driver_awake
         {
             self.signal_used_queue(self.queue_index_base + 1)?;
+            self.queue_counters
+                .tx_interrupts
+                .fetch_add(1, Ordering::Relaxed);
             debug!("Signalling TX queue");
         } else {
             debug!("Not signalling TX queue");
@@ -252,13 +311,31 @@ impl NetEpollHandler {
     }
 
     fn handle_rx_tap_event(&mut self) -> result::Result<(), DeviceError> {
-        if self
+        // Frames are read through an intermediate buffer when they have to
+        // be hashed, which is otherwise avoided.
+        if self.hash_report || self.rss.read().unwrap().is_some() {
+            return self.process_rx_hashed();
+        }
+
+        let queue_pair = usize::from(self.queue_index_base / 2);
+        let needs_notification = self
             .net
-            .process_rx(&self.mem.memory(), &mut self.queue_pair.0)
-            .map_err(DeviceError::NetQueuePair)?
-            || !self.driver_awake
-        {
+            .process_rx(
+                &self.mem.memory(),
+                &mut self.rx_queues[queue_pair].lock().unwrap(),
+            )
+            .map_err(DeviceError::NetQueuePair)?;
+        if !self.net.rx_desc_avail {
+            self.queue_counters
+                .rx_no_buffers
+                .fetch_add(1, Ordering::Relaxed);
+        }
+
+        if needs_notification || !self.driver_awake {
             self.signal_used_queue(self.queue_index_base)?;
+            self.queue_counters
+                .rx_interrupts
+                .fetch_add(1, Ordering::Relaxed);
             debug!("Signalling RX queue");
         } else {
             debug!("Not signalling RX queue");
@@ -266,6 +343,106 @@ impl NetEpollHandler {
         Ok(())
     }
 
+    // Receives the frames from the TAP, steering each of them to a receive
+    // queue from its hash when RSS is enabled. A frame is dropped when its
+    // queue has no buffer available, as the TAP can't be told to hold it.
+    fn process_rx_hashed(&mut self) -> result::Result<(), DeviceError> {
+        let mem = self.mem.memory();
+        let own_queue = usize::from(self.queue_index_base / 2);
+        let hdr_len = self.net.rx.vnet_hdr_len;
+        if self.rx_frame.is_empty() {
+            self.rx_frame.resize(MAX_RX_FRAME_LEN, 0);
+        }
+
+        let rss = self.rss.read().unwrap();
+        let mut used_queues = BTreeSet::new();
+        loop {
+            let rate_limit_reached = self
+                .net
+                .rx_rate_limiter
+                .as_ref()
+                .map_or(false, |r| r.is_blocked());
+            if rate_limit_reached {
+                break;
+            }
+
+            let len = match self
+                .net
+                .read_rx_frame(&mut self.rx_frame)
+                .map_err(DeviceError::NetQueuePair)?
+            {
+                Some(len) => len,
+                None => break,
+            };
+            let frame = &mut self.rx_frame[..len];
+
+            // A frame never spreads over several descriptor chains.
+            frame[VNET_HDR_NUM_BUFFERS_OFFSET..VNET_HDR_NUM_BUFFERS_OFFSET + 2]
+                .copy_from_slice(&1u16.to_le_bytes());
+            let hash = rss.as_ref().and_then(|rss| rss.hash(&frame[hdr_len..]));
+            if self.hash_report {
+                set_vnet_hdr_hash(frame, hash);
+            }
+            let queue = rss
+                .as_ref()
+                .and_then(|rss| rss.queue(hash.map(|(value, _)| value)))
+                .map_or(own_queue, usize::from);
+
+            let delivered = self
+                .net
+                .write_rx_frame(
+                    mem.deref(),
+                    &mut self.rx_queues[queue].lock().unwrap(),
+                    frame,
+                )
+                .map_err(DeviceError::NetQueuePair)?;
+            if delivered {
+                used_queues.insert(queue);
+            } else {
+                self.queue_counters
+                    .rx_no_buffers
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        drop(rss);
+
+        // Keep listening to the TAP unless the rate limit is reached, since
+        // frames go to any queue.
+        let rate_limit_reached = self
+            .net
+            .rx_rate_limiter
+            .as_ref()
+            .map_or(false, |r| r.is_blocked());
+        if rate_limit_reached && self.net.rx_tap_listening {
+            net_util::unregister_listener(
+                self.net.epoll_fd.unwrap(),
+                self.net.tap.as_raw_fd(),
+                epoll::Events::EPOLLIN,
+                u64::from(self.net.tap_rx_event_id),
+            )
+            .map_err(DeviceError::IoError)?;
+            self.net.rx_tap_listening = false;
+        }
+
+        for queue in used_queues {
+            let needs_notification = self.rx_queues[queue]
+                .lock()
+                .unwrap()
+                .needs_notification(mem.deref())
+                .map_err(|e| {
+                    DeviceError::NetQueuePair(NetQueuePairError::QueueNeedsNotification(e))
+                })?;
+            if needs_notification || !self.driver_awake {
+                self.signal_used_queue(queue as u16 * 2)?;
+                self.queue_counters
+                    .rx_interrupts
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        Ok(())
+    }
+
     fn run(
         &mut self,
         paused: Arc<AtomicBool>,
@@ -284,14 +461,13 @@ impl NetEpollHandler {
         let mem = self.mem.memory();
         // If there are some already available descriptors on the RX queue,
         // then we can start the thread while listening onto the TAP.
-        if self
-            .queue_pair
-            .0
+        let rx_queue = self.rx_queues[usize::from(self.queue_index_base / 2)]
+            .lock()
+            .unwrap();
+        if rx_queue
             .used_idx(mem.deref(), Ordering::Acquire)
             .map_err(EpollHelperError::QueueRingIndex)?
-            < self
-                .queue_pair
-                .0
+            < rx_queue
                 .avail_idx(mem.deref(), Ordering::Acquire)
                 .map_err(EpollHelperError::QueueRingIndex)?
         {
@@ -299,6 +475,7 @@ impl NetEpollHandler {
             self.net.rx_tap_listening = true;
             info!("Listener registered at start");
         }
+        drop(rx_queue);
 
         // The NetQueuePair needs the epoll fd.
         self.net.epoll_fd = Some(helper.as_raw_fd());
@@ -418,9 +595,22 @@ pub struct Net {
     config: VirtioNetConfig,
     ctrl_queue_epoll_thread: Option<thread::JoinHandle<()>>,
     counters: NetCounters,
+    queue_counters: Vec<NetQueueCounters>,
+    // Name of the TAP interface, to read its statistics from
+    if_name: String,
     seccomp_action: SeccompAction,
     rate_limiter_config: Option<RateLimiterConfig>,
+    rate_limiter_group: Option<RateLimiterGroup>,
     exit_evt: EventFd,
+    // Host CPUs the thread of each queue pair is pinned onto
+    queue_affinity: BTreeMap<u16, Vec<usize>>,
+    // Hash configuration set by the guest, shared with the threads
+    rss: Arc<RwLock<Option<RssConfig>>>,
+    // Whether the queue pairs are processed by the vhost-net module of the
+    // host kernel rather than by the VMM.
+    vhost: bool,
+    vhost_queue_pairs: Vec<VhostNetQueuePair>,
+    guest_memory: Option<GuestMemoryAtomic<GuestMemoryMmap>>,
 }
 
 #[derive(Versionize)]
@@ -429,6 +619,7 @@ pub struct NetState {
     pub acked_features: u64,
     pub config: VirtioNetConfig,
     pub queue_size: Vec<u16>,
+    pub rss: Option<RssConfig>,
 }
 
 impl VersionMapped for NetState {}
@@ -445,17 +636,29 @@ impl Net {
         queue_size: u16,
         seccomp_action: SeccompAction,
         rate_limiter_config: Option<RateLimiterConfig>,
+        rate_limiter_group: Option<RateLimiterGroup>,
         exit_evt: EventFd,
         state: Option<NetState>,
         offload_tso: bool,
         offload_ufo: bool,
+        offload_uso: bool,
         offload_csum: bool,
+        rss: bool,
+        vhost: bool,
+        queue_affinity: BTreeMap<u16, Vec<usize>>,
     ) -> Result<Self> {
         assert!(!taps.is_empty());
 
+        if vhost {
+            vhost_net::probe().map_err(Error::VhostNet)?;
+        }
+
         let mtu = taps[0].mtu().map_err(Error::TapError)? as u16;
+        let if_name = String::from_utf8_lossy(&taps[0].get_if_name())
+            .trim_end_matches('\0')
+            .to_string();
 
-        let (avail_features, acked_features, config, queue_sizes, paused) =
+        let (avail_features, acked_features, config, queue_sizes, rss_config, paused) =
             if let Some(state) = state {
                 info!("Restoring virtio-net {}", id);
                 (
@@ -463,6 +666,7 @@ impl Net {
                     state.acked_features,
                     state.config,
                     state.queue_size,
+                    state.rss,
                     true,
                 )
             } else {
@@ -491,6 +695,14 @@ impl Net {
                     if offload_ufo {
                         avail_features |= 1 << VIRTIO_NET_F_HOST_UFO | 1 << VIRTIO_NET_F_GUEST_UFO;
                     }
+
+                    // The tap interface of older kernels can't take segmented
+                    // UDP frames.
+                    if offload_uso && taps[0].supports_uso() {
+                        avail_features |= 1 << VIRTIO_NET_F_HOST_USO
+                            | 1 << VIRTIO_NET_F_GUEST_USO4
+                            | 1 << VIRTIO_NET_F_GUEST_USO6;
+                    }
                 }
 
                 avail_features |= 1 << VIRTIO_NET_F_CTRL_VQ;
@@ -514,11 +726,19 @@ impl Net {
                     );
                 }
 
+                if rss {
+                    avail_features |= 1 << VIRTIO_NET_F_RSS | 1 << VIRTIO_NET_F_HASH_REPORT;
+                    config.rss_max_key_size = RSS_MAX_KEY_SIZE;
+                    config.rss_max_indirection_table_length = RSS_MAX_INDIRECTION_TABLE_LEN;
+                    config.supported_hash_types = RSS_SUPPORTED_HASH_TYPES;
+                }
+
                 (
                     avail_features,
                     0,
                     config,
                     vec![queue_size; queue_num],
+                    None,
                     false,
                 )
             };
@@ -529,7 +749,12 @@ impl Net {
                 avail_features,
                 acked_features,
                 queue_sizes,
-                paused_sync: Some(Arc::new(Barrier::new((num_queues / 2) + 1))),
+                // No thread processes the queue pairs with vhost-net.
+                paused_sync: Some(Arc::new(Barrier::new(if vhost {
+                    1
+                } else {
+                    (num_queues / 2) + 1
+                }))),
                 min_queues: 2,
                 paused: Arc::new(AtomicBool::new(paused)),
                 ..Default::default()
@@ -539,9 +764,17 @@ impl Net {
             config,
             ctrl_queue_epoll_thread: None,
             counters: NetCounters::default(),
+            queue_counters: Vec::new(),
+            if_name,
             seccomp_action,
             rate_limiter_config,
+            rate_limiter_group,
             exit_evt,
+            queue_affinity,
+            rss: Arc::new(RwLock::new(rss_config)),
+            vhost,
+            vhost_queue_pairs: Vec::new(),
+            guest_memory: None,
         })
     }
 
@@ -561,11 +794,16 @@ impl Net {
         queue_size: u16,
         seccomp_action: SeccompAction,
         rate_limiter_config: Option<RateLimiterConfig>,
+        rate_limiter_group: Option<RateLimiterGroup>,
         exit_evt: EventFd,
         state: Option<NetState>,
         offload_tso: bool,
         offload_ufo: bool,
+        offload_uso: bool,
         offload_csum: bool,
+        rss: bool,
+        vhost: bool,
+        queue_affinity: BTreeMap<u16, Vec<usize>>,
     ) -> Result<Self> {
         let taps = open_tap(
             if_name,
@@ -587,11 +825,16 @@ impl Net {
             queue_size,
             seccomp_action,
             rate_limiter_config,
+            rate_limiter_group,
             exit_evt,
             state,
             offload_tso,
             offload_ufo,
+            offload_uso,
             offload_csum,
+            rss,
+            vhost,
+            queue_affinity,
         )
     }
 
@@ -605,11 +848,16 @@ impl Net {
         queue_size: u16,
         seccomp_action: SeccompAction,
         rate_limiter_config: Option<RateLimiterConfig>,
+        rate_limiter_group: Option<RateLimiterGroup>,
         exit_evt: EventFd,
         state: Option<NetState>,
         offload_tso: bool,
         offload_ufo: bool,
+        offload_uso: bool,
         offload_csum: bool,
+        rss: bool,
+        vhost: bool,
+        queue_affinity: BTreeMap<u16, Vec<usize>>,
     ) -> Result<Self> {
         let mut taps: Vec<Tap> = Vec::new();
         let num_queue_pairs = fds.len();
@@ -640,11 +888,16 @@ impl Net {
             queue_size,
             seccomp_action,
             rate_limiter_config,
+            rate_limiter_group,
             exit_evt,
             state,
             offload_tso,
             offload_ufo,
+            offload_uso,
             offload_csum,
+            rss,
+            vhost,
+            queue_affinity,
         )
     }
 
@@ -654,7 +907,51 @@ impl Net {
             acked_features: self.common.acked_features,
             config: self.config,
             queue_size: self.common.queue_sizes.clone(),
+            rss: self.rss.read().unwrap().clone(),
+        }
+    }
+
+    // Hands each queue pair over to the vhost-net module, along with its TAP
+    // interface.
+    fn activate_vhost(
+        &mut self,
+        mem: GuestMemoryAtomic<GuestMemoryMmap>,
+        interrupt_cb: &Arc<dyn VirtioInterrupt>,
+        queues: Vec<(usize, Queue, EventFd)>,
+    ) -> ActivateResult {
+        for (tap, queue_pair) in self.taps.iter().zip(queues.chunks_exact(2)) {
+            tap.set_offload(virtio_features_to_tap_offload(self.common.acked_features))
+                .map_err(|e| {
+                    error!("Error programming tap offload: {:?}", e);
+                    ActivateError::BadActivate
+                })?;
+            tap.set_vnet_hdr_size(vnet_hdr_len() as i32).map_err(|e| {
+                error!("Error programming tap vnet header size: {:?}", e);
+                ActivateError::BadActivate
+            })?;
+
+            let (rx_index, rx_queue, rx_queue_evt) = &queue_pair[0];
+            let (tx_index, tx_queue, tx_queue_evt) = &queue_pair[1];
+            let vhost_queue_pair = VhostNetQueuePair::new(mem.clone(), tap.clone())
+                .map_err(ActivateError::VhostNetSetup)?;
+            vhost_queue_pair
+                .activate(
+                    &mem.memory(),
+                    self.common.acked_features,
+                    [
+                        (*rx_index, rx_queue, rx_queue_evt),
+                        (*tx_index, tx_queue, tx_queue_evt),
+                    ],
+                    interrupt_cb,
+                    self.common.access_platform.as_ref(),
+                )
+                .map_err(ActivateError::VhostNetSetup)?;
+            self.vhost_queue_pairs.push(vhost_queue_pair);
         }
+        self.guest_memory = Some(mem);
+
+        event!("virtio-device", "activated", "id", &self.id);
+        Ok(())
     }
 
     #[cfg(fuzzing)]
@@ -721,7 +1018,12 @@ impl VirtioDevice for Net {
                 mem: mem.clone(),
                 kill_evt,
                 pause_evt,
-                ctrl_q: CtrlQueue::new(self.taps.clone()),
+                ctrl_q: CtrlQueue::new(
+                    self.taps.clone(),
+                    (self.common.feature_acked(VIRTIO_NET_F_RSS.into())
+                        || self.common.feature_acked(VIRTIO_NET_F_HASH_REPORT.into()))
+                    .then(|| self.rss.clone()),
+                ),
                 queue: ctrl_queue,
                 queue_evt: ctrl_queue_evt,
                 access_platform: self.common.access_platform.clone(),
@@ -733,7 +1035,8 @@ impl VirtioDevice for Net {
             // Let's update the barrier as we need 1 for each RX/TX pair +
             // 1 for the control queue + 1 for the main thread signalling
             // the pause.
-            self.common.paused_sync = Some(Arc::new(Barrier::new(self.taps.len() + 2)));
+            let queue_pair_threads = if self.vhost { 0 } else { self.taps.len() };
+            self.common.paused_sync = Some(Arc::new(Barrier::new(queue_pair_threads + 2)));
             let paused_sync = self.common.paused_sync.clone();
 
             let mut epoll_threads = Vec::new();
@@ -748,34 +1051,74 @@ impl VirtioDevice for Net {
             self.ctrl_queue_epoll_thread = Some(epoll_threads.remove(0));
         }
 
+        if self.vhost {
+            return self.activate_vhost(mem, &interrupt_cb, queues);
+        }
+
+        // The frames can be steered to the receive queue of any queue pair,
+        // hence all of them are shared between the threads.
+        let mut rx_queues = Vec::new();
+        let mut tx_queues = Vec::new();
+        let mut queue_evt_pairs = Vec::new();
+        while queues.len() >= 2 {
+            let (_, mut rx_queue, rx_queue_evt) = queues.remove(0);
+            let (_, mut tx_queue, tx_queue_evt) = queues.remove(0);
+            rx_queue.set_event_idx(event_idx);
+            tx_queue.set_event_idx(event_idx);
+            rx_queues.push(Arc::new(Mutex::new(rx_queue)));
+            tx_queues.push(tx_queue);
+            queue_evt_pairs.push((rx_queue_evt, tx_queue_evt));
+        }
+
+        let hash_report = self.common.feature_acked(VIRTIO_NET_F_HASH_REPORT.into());
+        let hdr_len = if hash_report {
+            VNET_HDR_HASH_LEN
+        } else {
+            vnet_hdr_len()
+        };
+
         let mut epoll_threads = Vec::new();
         let mut taps = self.taps.clone();
-        for i in 0..queues.len() / 2 {
-            let rx = RxVirtio::new();
-            let tx = TxVirtio::new();
+        self.queue_counters
+            .resize_with(rx_queues.len(), NetQueueCounters::default);
+        for (i, (tx_queue, queue_evt_pair)) in
+            tx_queues.into_iter().zip(queue_evt_pairs).enumerate()
+        {
+            let mut rx = RxVirtio::new();
+            let mut tx = TxVirtio::new();
+            rx.vnet_hdr_len = hdr_len;
+            tx.vnet_hdr_len = hdr_len;
             let rx_tap_listening = false;
 
-            let (_, queue_0, queue_evt_0) = queues.remove(0);
-            let (_, queue_1, queue_evt_1) = queues.remove(0);
-            let mut queue_pair = (queue_0, queue_1);
-            queue_pair.0.set_event_idx(event_idx);
-            queue_pair.1.set_event_idx(event_idx);
-
-            let queue_evt_pair = (queue_evt_0, queue_evt_1);
-
             let (kill_evt, pause_evt) = self.common.dup_eventfds();
 
-            let rx_rate_limiter: Option<rate_limiter::RateLimiter> = self
-                .rate_limiter_config
-                .map(RateLimiterConfig::try_into)
-                .transpose()
-                .map_err(ActivateError::CreateRateLimiter)?;
-
-            let tx_rate_limiter: Option<rate_limiter::RateLimiter> = self
-                .rate_limiter_config
-                .map(RateLimiterConfig::try_into)
-                .transpose()
-                .map_err(ActivateError::CreateRateLimiter)?;
+            let (rx_rate_limiter, tx_rate_limiter) = if let Some(group) = &self.rate_limiter_group {
+                // Both directions are throttled against the budget of the group
+                (
+                    Some(
+                        rate_limiter::RateLimiter::new_in_group(group.clone())
+                            .map_err(ActivateError::CreateRateLimiter)?,
+                    ),
+                    Some(
+                        rate_limiter::RateLimiter::new_in_group(group.clone())
+                            .map_err(ActivateError::CreateRateLimiter)?,
+                    ),
+                )
+            } else {
+                let rx_rate_limiter: Option<rate_limiter::RateLimiter> = self
+                    .rate_limiter_config
+                    .map(RateLimiterConfig::try_into)
+                    .transpose()
+                    .map_err(ActivateError::CreateRateLimiter)?;
+
+                let tx_rate_limiter: Option<rate_limiter::RateLimiter> = self
+                    .rate_limiter_config
+                    .map(RateLimiterConfig::try_into)
+                    .transpose()
+                    .map_err(ActivateError::CreateRateLimiter)?;
+
+                (rx_rate_limiter, tx_rate_limiter)
+            };
 
             let tap = taps.remove(0);
             #[cfg(not(fuzzing))]
@@ -784,6 +1127,11 @@ impl VirtioDevice for Net {
                     error!("Error programming tap offload: {:?}", e);
                     ActivateError::BadActivate
                 })?;
+            #[cfg(not(fuzzing))]
+            tap.set_vnet_hdr_size(hdr_len as i32).map_err(|e| {
+                error!("Error programming tap vnet header size: {:?}", e);
+                ActivateError::BadActivate
+            })?;
 
             let mut handler = NetEpollHandler {
                 net: NetQueuePair {
@@ -802,14 +1150,19 @@ impl VirtioDevice for Net {
                     tx_rate_limiter,
                     access_platform: self.common.access_platform.clone(),
                 },
+                queue_counters: self.queue_counters[i].clone(),
                 mem: mem.clone(),
                 queue_index_base: (i * 2) as u16,
-                queue_pair,
+                rx_queues: rx_queues.clone(),
+                tx_queue,
                 queue_evt_pair,
                 interrupt_cb: interrupt_cb.clone(),
                 kill_evt,
                 pause_evt,
                 driver_awake: false,
+                rss: self.rss.clone(),
+                hash_report,
+                rx_frame: Vec::new(),
             };
 
             let paused = self.common.paused.clone();
@@ -823,6 +1176,12 @@ impl VirtioDevice for Net {
                 &self.exit_evt,
                 move || handler.run(paused, paused_sync.unwrap()),
             )?;
+
+            if let (Some(host_cpus), Some(thread)) =
+                (self.queue_affinity.get(&(i as u16)), epoll_threads.last())
+            {
+                set_virtio_thread_affinity(thread, host_cpus)?;
+            }
         }
 
         self.common.epoll_threads = Some(epoll_threads);
@@ -833,6 +1192,9 @@ impl VirtioDevice for Net {
 
     fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
         let result = self.common.reset();
+        *self.rss.write().unwrap() = None;
+        // Closing the vhost-net devices stops the processing of the queues.
+        self.vhost_queue_pairs.clear();
         event!("virtio-device", "reset", "id", &self.id);
         result
     }
@@ -857,20 +1219,66 @@ impl VirtioDevice for Net {
             Wrapping(self.counters.tx_frames.load(Ordering::Acquire)),
         );
 
+        // The frames received by the guest are sent by the TAP interface
+        if let Some(dropped) = tap_statistic(&self.if_name, "tx_dropped") {
+            counters.insert("rx_dropped", Wrapping(dropped));
+        }
+        if let Some(dropped) = tap_statistic(&self.if_name, "rx_dropped") {
+            counters.insert("tx_dropped", Wrapping(dropped));
+        }
+
+        for (i, queue_counters) in self.queue_counters.iter().enumerate() {
+            for (name, counter) in [
+                ("rx_interrupts", &queue_counters.rx_interrupts),
+                ("tx_interrupts", &queue_counters.tx_interrupts),
+                ("rx_no_buffers", &queue_counters.rx_no_buffers),
+            ] {
+                counters.insert(
+                    queue_counter_name(i, name),
+                    Wrapping(counter.load(Ordering::Acquire)),
+                );
+            }
+        }
+
         Some(counters)
     }
 
     fn set_access_platform(&mut self, access_platform: Arc<dyn AccessPlatform>) {
         self.common.set_access_platform(access_platform)
     }
+
+    fn add_memory_region(
+        &mut self,
+        _region: &Arc<GuestRegionMmap>,
+    ) -> std::result::Result<(), DeviceError> {
+        if let Some(guest_memory) = &self.guest_memory {
+            for queue_pair in self.vhost_queue_pairs.iter() {
+                queue_pair
+                    .update_mem_table(&guest_memory.memory())
+                    .map_err(DeviceError::VhostNetUpdateMemory)?;
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl Pausable for Net {
     fn pause(&mut self) -> result::Result<(), MigratableError> {
+        for queue_pair in self.vhost_queue_pairs.iter() {
+            queue_pair
+                .attach(false)
+                .map_err(|e| MigratableError::Pause(anyhow!("Error pausing vhost-net: {:?}", e)))?;
+        }
         self.common.pause()
     }
 
     fn resume(&mut self) -> result::Result<(), MigratableError> {
+        for queue_pair in self.vhost_queue_pairs.iter() {
+            queue_pair.attach(true).map_err(|e| {
+                MigratableError::Resume(anyhow!("Error resuming vhost-net: {:?}", e))
+            })?;
+        }
         self.common.resume()?;
 
         if let Some(ctrl_queue_epoll_thread) = &self.ctrl_queue_epoll_thread {
@@ -886,8 +1294,26 @@ impl Snapshottable for Net {
     }
 
     fn snapshot(&mut self) -> std::result::Result<Snapshot, MigratableError> {
+        // The state of the queues is only known to the kernel.
+        if !self.vhost_queue_pairs.is_empty() {
+            return Err(MigratableError::Snapshot(anyhow!(
+                "Can't snapshot a virtio-net device using vhost-net"
+            )));
+        }
+
         Snapshot::new_from_versioned_state(&self.state())
     }
 }
 impl Transportable for Net {}
-impl Migratable for Net {}
+impl Migratable for Net {
+    fn start_dirty_log(&mut self) -> std::result::Result<(), MigratableError> {
+        // The guest memory written by the kernel isn't tracked.
+        if self.vhost {
+            return Err(MigratableError::StartDirtyLog(anyhow!(
+                "Can't migrate a virtio-net device using vhost-net"
+            )));
+        }
+
+        Ok(())
+    }
+}
@@ -21,9 +21,10 @@ use seccompiler::SeccompAction;
 use std::fs::File;
 use std::io;
 use std::mem::size_of;
+use std::ops::Deref;
 use std::os::unix::io::AsRawFd;
 use std::result;
-use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Barrier};
 use thiserror::Error;
 use versionize::{VersionMap, Versionize, VersionizeResult};
@@ -97,6 +98,7 @@ enum Error {
 #[derive(Debug, PartialEq, Eq)]
 enum RequestType {
     Flush,
+    Unsupported(u32),
 }
 
 struct Request {
@@ -129,7 +131,7 @@ impl Request {
 
         let request_type = match request.type_ {
             VIRTIO_PMEM_REQ_TYPE_FLUSH => RequestType::Flush,
-            _ => return Err(Error::InvalidRequest),
+            t => RequestType::Unsupported(t),
         };
 
         let status_desc = desc_chain.next().ok_or(Error::DescriptorChainTooShort)?;
@@ -161,24 +163,65 @@ struct PmemEpollHandler {
     kill_evt: EventFd,
     pause_evt: EventFd,
     access_platform: Option<Arc<dyn AccessPlatform>>,
+    // Set once flushing the backing file failed. The data written back at
+    // that time may have been dropped by the host, which a later successful
+    // flush wouldn't report, so every later flush fails as well.
+    flush_failed: Arc<AtomicBool>,
 }
 
 impl PmemEpollHandler {
+    // Writes back the data of the guest to the backing file, making it
+    // durable.
+    fn flush(&self) -> u32 {
+        if self.flush_failed.load(Ordering::Acquire) {
+            return VIRTIO_PMEM_RESP_TYPE_EIO;
+        }
+
+        // The size of the file never changes, syncing its data is enough.
+        match self.disk.sync_data() {
+            Ok(()) => VIRTIO_PMEM_RESP_TYPE_OK,
+            Err(e) => {
+                error!("failed flushing disk image: {}", e);
+                self.flush_failed.store(true, Ordering::Release);
+                VIRTIO_PMEM_RESP_TYPE_EIO
+            }
+        }
+    }
+
     fn process_queue(&mut self) -> result::Result<bool, Error> {
-        let mut used_descs = false;
+        let mut requests = Vec::new();
         while let Some(mut desc_chain) = self.queue.pop_descriptor_chain(self.mem.memory()) {
-            let len = match Request::parse(&mut desc_chain, self.access_platform.as_ref()) {
-                Ok(ref req) if (req.type_ == RequestType::Flush) => {
-                    let status_code = match self.disk.sync_all() {
-                        Ok(()) => VIRTIO_PMEM_RESP_TYPE_OK,
-                        Err(e) => {
-                            error!("failed flushing disk image: {}", e);
+            let request = Request::parse(&mut desc_chain, self.access_platform.as_ref())
+                .map_err(|e| error!("Failed to parse available descriptor chain: {:?}", e))
+                .ok();
+            requests.push((desc_chain.head_index(), request));
+        }
+        if requests.is_empty() {
+            return Ok(false);
+        }
+
+        // A single flush completes all the flush requests pending at once,
+        // as it makes durable everything they expect to be.
+        let flush_status = requests
+            .iter()
+            .any(|(_, r)| matches!(r, Some(r) if r.type_ == RequestType::Flush))
+            .then(|| self.flush());
+
+        let mem = self.mem.memory();
+        for (head_index, request) in requests {
+            let len = match request {
+                Some(req) => {
+                    let status_code = match req.type_ {
+                        RequestType::Flush => flush_status.unwrap(),
+                        RequestType::Unsupported(t) => {
+                            // Currently, there is only one virtio-pmem request, FLUSH.
+                            error!("Invalid virtio request type {}", t);
                             VIRTIO_PMEM_RESP_TYPE_EIO
                         }
                     };
 
                     let resp = VirtioPmemResp { ret: status_code };
-                    match desc_chain.memory().write_obj(resp, req.status_addr) {
+                    match mem.write_obj(resp, req.status_addr) {
                         Ok(_) => size_of::<VirtioPmemResp>() as u32,
                         Err(e) => {
                             error!("bad guest memory address: {}", e);
@@ -186,24 +229,15 @@ impl PmemEpollHandler {
                         }
                     }
                 }
-                Ok(ref req) => {
-                    // Currently, there is only one virtio-pmem request, FLUSH.
-                    error!("Invalid virtio request type {:?}", req.type_);
-                    0
-                }
-                Err(e) => {
-                    error!("Failed to parse available descriptor chain: {:?}", e);
-                    0
-                }
+                None => 0,
             };
 
             self.queue
-                .add_used(desc_chain.memory(), desc_chain.head_index(), len)
+                .add_used(mem.deref(), head_index, len)
                 .map_err(Error::QueueAddUsed)?;
-            used_descs = true;
         }
 
-        Ok(used_descs)
+        Ok(true)
     }
 
     fn signal_used_queue(&self) -> result::Result<(), DeviceError> {
@@ -273,6 +307,7 @@ pub struct Pmem {
     mapping: UserspaceMapping,
     seccomp_action: SeccompAction,
     exit_evt: EventFd,
+    flush_failed: Arc<AtomicBool>,
 
     // Hold ownership of the memory that is allocated for the device
     // which will be automatically dropped when the device is dropped
@@ -341,6 +376,7 @@ impl Pmem {
             seccomp_action,
             _region,
             exit_evt,
+            flush_failed: Arc::new(AtomicBool::new(false)),
         })
     }
 
@@ -414,6 +450,7 @@ impl VirtioDevice for Pmem {
                 kill_evt,
                 pause_evt,
                 access_platform: self.common.access_platform.clone(),
+                flush_failed: self.flush_failed.clone(),
             };
 
             let paused = self.common.paused.clone();
@@ -0,0 +1,128 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Access to the guest memory described by a descriptor chain, for the
+//! devices whose requests and responses can be split across descriptors in
+//! any way.
+
+use crate::GuestMemoryMmap;
+use std::cmp;
+use std::result;
+use std::sync::Arc;
+use thiserror::Error;
+use virtio_queue::DescriptorChain;
+use vm_memory::{Bytes, GuestAddress, GuestMemoryLoadGuard};
+use vm_virtio::{AccessPlatform, Translatable};
+
+#[derive(Error, Debug)]
+pub(crate) enum Error {
+    #[error("Descriptor chain too short")]
+    DescriptorChainTooShort,
+    #[error("Failed to read from guest memory: {0}")]
+    GuestMemoryRead(vm_memory::guest_memory::Error),
+    #[error("Failed to write to guest memory: {0}")]
+    GuestMemoryWrite(vm_memory::guest_memory::Error),
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// Guest memory described by the readable or writable part of a descriptor
+/// chain, accessed as a single buffer.
+#[derive(Default)]
+pub(crate) struct Regions(pub(crate) Vec<(GuestAddress, usize)>);
+
+impl Regions {
+    pub(crate) fn len(&self) -> usize {
+        self.0.iter().map(|(_, len)| len).sum()
+    }
+
+    // Calls `f` on each part of the `offset..offset + len` range, along with
+    // the offset of the part in that range.
+    fn access<F>(&self, mut offset: usize, len: usize, mut f: F) -> Result<()>
+    where
+        F: FnMut(GuestAddress, usize, usize) -> Result<()>,
+    {
+        if offset.checked_add(len).map_or(true, |end| end > self.len()) {
+            return Err(Error::DescriptorChainTooShort);
+        }
+
+        let mut done = 0;
+        for (addr, region_len) in self.0.iter() {
+            if done == len {
+                break;
+            }
+            if offset >= *region_len {
+                offset -= region_len;
+                continue;
+            }
+            let count = cmp::min(region_len - offset, len - done);
+            f(addr.unchecked_add(offset as u64), done, count)?;
+            done += count;
+            offset = 0;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn read(&self, mem: &GuestMemoryMmap, offset: usize, buf: &mut [u8]) -> Result<()> {
+        self.access(offset, buf.len(), |addr, start, count| {
+            mem.read_slice(&mut buf[start..start + count], addr)
+                .map_err(Error::GuestMemoryRead)
+        })
+    }
+
+    pub(crate) fn write(&self, mem: &GuestMemoryMmap, offset: usize, buf: &[u8]) -> Result<()> {
+        self.access(offset, buf.len(), |addr, start, count| {
+            mem.write_slice(&buf[start..start + count], addr)
+                .map_err(Error::GuestMemoryWrite)
+        })
+    }
+}
+
+/// Splits a descriptor chain into its device readable and writable parts.
+pub(crate) fn parse_chain(
+    desc_chain: &mut DescriptorChain<GuestMemoryLoadGuard<GuestMemoryMmap>>,
+    access_platform: Option<&Arc<dyn AccessPlatform>>,
+) -> (Regions, Regions) {
+    let mut readable = Regions::default();
+    let mut writable = Regions::default();
+    for desc in desc_chain.by_ref() {
+        let region = (
+            desc.addr()
+                .translate_gva(access_platform, desc.len() as usize),
+            desc.len() as usize,
+        );
+        if desc.is_write_only() {
+            writable.0.push(region);
+        } else {
+            readable.0.push(region);
+        }
+    }
+
+    (readable, writable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_regions() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let regions = Regions(vec![(GuestAddress(0x1000), 4), (GuestAddress(0x2000), 8)]);
+        assert_eq!(regions.len(), 12);
+
+        regions.write(&mem, 2, &[1, 2, 3, 4, 5, 6]).unwrap();
+        let mut data = [0u8; 4];
+        mem.read_slice(&mut data, GuestAddress(0x1000)).unwrap();
+        assert_eq!(data, [0, 0, 1, 2]);
+        mem.read_slice(&mut data, GuestAddress(0x2000)).unwrap();
+        assert_eq!(data, [3, 4, 5, 6]);
+
+        let mut data = [0u8; 6];
+        regions.read(&mem, 2, &mut data).unwrap();
+        assert_eq!(data, [1, 2, 3, 4, 5, 6]);
+        assert!(regions.read(&mem, 8, &mut data).is_err());
+    }
+}
@@ -0,0 +1,765 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Logical units whose SCSI commands are emulated on top of a raw image: disks
+//! and CD-ROM drives, the latter supporting media changes.
+
+use super::{CommandResult, MediaError, Sense};
+use std::cmp;
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+
+// Opcodes of the emulated commands, from SPC, SBC and MMC.
+const TEST_UNIT_READY: u8 = 0x00;
+const REQUEST_SENSE: u8 = 0x03;
+const READ_6: u8 = 0x08;
+const WRITE_6: u8 = 0x0a;
+pub(super) const INQUIRY: u8 = 0x12;
+const MODE_SELECT_6: u8 = 0x15;
+const MODE_SENSE_6: u8 = 0x1a;
+const START_STOP_UNIT: u8 = 0x1b;
+const PREVENT_ALLOW_MEDIUM_REMOVAL: u8 = 0x1e;
+const READ_CAPACITY_10: u8 = 0x25;
+const READ_10: u8 = 0x28;
+const WRITE_10: u8 = 0x2a;
+const VERIFY_10: u8 = 0x2f;
+const SYNCHRONIZE_CACHE_10: u8 = 0x35;
+const READ_TOC: u8 = 0x43;
+const GET_CONFIGURATION: u8 = 0x46;
+const GET_EVENT_STATUS_NOTIFICATION: u8 = 0x4a;
+const MODE_SELECT_10: u8 = 0x55;
+const MODE_SENSE_10: u8 = 0x5a;
+const READ_16: u8 = 0x88;
+const WRITE_16: u8 = 0x8a;
+const SYNCHRONIZE_CACHE_16: u8 = 0x91;
+const SERVICE_ACTION_IN_16: u8 = 0x9e;
+pub(super) const REPORT_LUNS: u8 = 0xa0;
+const READ_12: u8 = 0xa8;
+const WRITE_12: u8 = 0xaa;
+
+const READ_CAPACITY_16: u8 = 0x10;
+
+// Peripheral device types
+const TYPE_DISK: u8 = 0x00;
+const TYPE_ROM: u8 = 0x05;
+
+const DISK_BLOCK_SIZE: u32 = 512;
+const CDROM_BLOCK_SIZE: u32 = 2048;
+
+// Mode pages
+const MODE_PAGE_CACHING: u8 = 0x08;
+const MODE_PAGE_CAPABILITIES: u8 = 0x2a;
+const MODE_PAGE_ALL: u8 = 0x3f;
+
+// Media event codes reported by GET EVENT STATUS NOTIFICATION
+const MEDIA_EVENT_NO_CHANGE: u8 = 0;
+const MEDIA_EVENT_NEW_MEDIA: u8 = 2;
+const MEDIA_EVENT_MEDIA_REMOVAL: u8 = 3;
+
+const MMC_PROFILE_NONE: u16 = 0x0000;
+const MMC_PROFILE_CDROM: u16 = 0x0008;
+
+const VENDOR: &[u8; 8] = b"CLOUDHV ";
+const REVISION: &[u8; 4] = b"1.0 ";
+
+fn be16(buf: &[u8]) -> u16 {
+    u16::from_be_bytes([buf[0], buf[1]])
+}
+
+fn be32(buf: &[u8]) -> u32 {
+    u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]])
+}
+
+fn be64(buf: &[u8]) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&buf[..8]);
+    u64::from_be_bytes(bytes)
+}
+
+// Pads or truncates `s` to `len` bytes, as expected for the ASCII fields of
+// the INQUIRY data.
+fn ascii_field(s: &str, len: usize) -> Vec<u8> {
+    let mut field = s.as_bytes().to_vec();
+    field.resize(len, b' ');
+    field
+}
+
+// Size of each command, so that commands whose CDB is too short are rejected.
+// Variable length and vendor specific commands are reported as 0.
+pub(super) fn cdb_len(opcode: u8) -> usize {
+    match opcode >> 5 {
+        0 => 6,
+        1 | 2 => 10,
+        4 => 16,
+        5 => 12,
+        _ => 0,
+    }
+}
+
+// Standard INQUIRY data, for a unit of the given peripheral type.
+pub(super) fn standard_inquiry(peripheral: u8, removable: bool, product: &str) -> Vec<u8> {
+    let mut data = vec![
+        peripheral,
+        if removable { 0x80 } else { 0 },
+        // SPC-3
+        0x05,
+        // Response data format
+        0x02,
+        // Additional length
+        31,
+        0,
+        0,
+        // Command queuing
+        0x02,
+    ];
+    data.extend_from_slice(VENDOR);
+    data.extend_from_slice(&ascii_field(product, 16));
+    data.extend_from_slice(REVISION);
+    data
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum UnitKind {
+    Disk,
+    Cdrom,
+}
+
+// Transfer of blocks requested by a READ or WRITE command.
+struct Transfer {
+    lba: u64,
+    blocks: u64,
+}
+
+pub(super) struct EmulatedLun {
+    kind: UnitKind,
+    file: Option<File>,
+    readonly: bool,
+    serial: String,
+    num_blocks: u64,
+    // The guest prevents the removal of the medium.
+    locked: bool,
+    // Reported to the guest with the next command.
+    unit_attention: Option<Sense>,
+    media_event: u8,
+}
+
+impl EmulatedLun {
+    pub fn new(
+        kind: UnitKind,
+        file: Option<File>,
+        readonly: bool,
+        serial: String,
+    ) -> io::Result<Self> {
+        let mut lun = EmulatedLun {
+            kind,
+            file: None,
+            readonly: readonly || kind == UnitKind::Cdrom,
+            serial,
+            num_blocks: 0,
+            locked: false,
+            unit_attention: None,
+            media_event: MEDIA_EVENT_NO_CHANGE,
+        };
+        lun.set_file(file)?;
+        // The guest doesn't need to be told about the initial medium.
+        lun.unit_attention = None;
+        lun.media_event = MEDIA_EVENT_NO_CHANGE;
+
+        Ok(lun)
+    }
+
+    fn block_size(&self) -> u32 {
+        match self.kind {
+            UnitKind::Disk => DISK_BLOCK_SIZE,
+            UnitKind::Cdrom => CDROM_BLOCK_SIZE,
+        }
+    }
+
+    fn set_file(&mut self, file: Option<File>) -> io::Result<()> {
+        self.num_blocks = match &file {
+            Some(file) => file.metadata()?.len() / self.block_size() as u64,
+            None => 0,
+        };
+        self.media_event = if file.is_some() {
+            MEDIA_EVENT_NEW_MEDIA
+        } else {
+            MEDIA_EVENT_MEDIA_REMOVAL
+        };
+        self.unit_attention = Some(Sense::MEDIUM_MAY_HAVE_CHANGED);
+        self.file = file;
+
+        Ok(())
+    }
+
+    /// Inserts a new medium, or ejects the current one when `file` is None.
+    pub fn change_media(&mut self, file: Option<File>) -> Result<(), MediaError> {
+        if self.kind != UnitKind::Cdrom {
+            return Err(MediaError::NotRemovable);
+        }
+        if self.locked && self.file.is_some() {
+            return Err(MediaError::Locked);
+        }
+
+        self.set_file(file).map_err(MediaError::Open)
+    }
+
+    pub fn execute(&mut self, cdb: &[u8], data_out: &[u8]) -> CommandResult {
+        if cdb.is_empty() || cdb.len() < cdb_len(cdb[0]) {
+            return CommandResult::check_condition(Sense::INVALID_OPCODE);
+        }
+
+        // The unit attention is reported by the first command which isn't
+        // meant to be used to find out about the state of the unit.
+        match cdb[0] {
+            INQUIRY | REQUEST_SENSE | GET_EVENT_STATUS_NOTIFICATION => {}
+            _ => {
+                if let Some(sense) = self.unit_attention.take() {
+                    return CommandResult::check_condition(sense);
+                }
+            }
+        }
+
+        match cdb[0] {
+            TEST_UNIT_READY | VERIFY_10 => match self.check_medium() {
+                Ok(()) => CommandResult::good(Vec::new()),
+                Err(result) => result,
+            },
+            REQUEST_SENSE => self.request_sense(cdb),
+            INQUIRY => self.inquiry(cdb),
+            MODE_SENSE_6 | MODE_SENSE_10 => self.mode_sense(cdb),
+            // Nothing can be changed, but the guest may try to.
+            MODE_SELECT_6 | MODE_SELECT_10 => CommandResult::good(Vec::new()),
+            START_STOP_UNIT => self.start_stop_unit(cdb),
+            PREVENT_ALLOW_MEDIUM_REMOVAL => {
+                self.locked = cdb[4] & 0x1 != 0;
+                CommandResult::good(Vec::new())
+            }
+            READ_CAPACITY_10 => self.read_capacity_10(),
+            SERVICE_ACTION_IN_16 if cdb[1] & 0x1f == READ_CAPACITY_16 => self.read_capacity_16(cdb),
+            READ_6 | READ_10 | READ_12 | READ_16 => self.read(cdb),
+            WRITE_6 | WRITE_10 | WRITE_12 | WRITE_16 => self.write(cdb, data_out),
+            SYNCHRONIZE_CACHE_10 | SYNCHRONIZE_CACHE_16 => self.synchronize_cache(),
+            READ_TOC if self.kind == UnitKind::Cdrom => self.read_toc(cdb),
+            GET_CONFIGURATION if self.kind == UnitKind::Cdrom => self.get_configuration(cdb),
+            GET_EVENT_STATUS_NOTIFICATION if self.kind == UnitKind::Cdrom => {
+                self.get_event_status_notification(cdb)
+            }
+            _ => CommandResult::check_condition(Sense::INVALID_OPCODE),
+        }
+    }
+
+    // Fails the commands which need a medium when there's none.
+    fn check_medium(&self) -> Result<(), CommandResult> {
+        if self.file.is_none() {
+            Err(CommandResult::check_condition(Sense::MEDIUM_NOT_PRESENT))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn request_sense(&mut self, cdb: &[u8]) -> CommandResult {
+        let sense = if let Some(sense) = self.unit_attention.take() {
+            sense
+        } else if self.file.is_none() {
+            Sense::MEDIUM_NOT_PRESENT
+        } else {
+            Sense::NO_SENSE
+        };
+
+        CommandResult::good_truncated(sense.to_fixed_format(), cdb[4] as usize)
+    }
+
+    fn inquiry(&self, cdb: &[u8]) -> CommandResult {
+        let allocation_length = be16(&cdb[3..5]) as usize;
+        let peripheral = match self.kind {
+            UnitKind::Disk => TYPE_DISK,
+            UnitKind::Cdrom => TYPE_ROM,
+        };
+
+        // Standard data
+        if cdb[1] & 0x1 == 0 {
+            if cdb[2] != 0 {
+                return CommandResult::check_condition(Sense::INVALID_FIELD_IN_CDB);
+            }
+            let product = match self.kind {
+                UnitKind::Disk => "VIRTUAL DISK",
+                UnitKind::Cdrom => "VIRTUAL CDROM",
+            };
+            let data = standard_inquiry(peripheral, self.kind == UnitKind::Cdrom, product);
+            return CommandResult::good_truncated(data, allocation_length);
+        }
+
+        // Vital product data
+        let mut supported_pages = vec![0x00, 0x80, 0x83];
+        if self.kind == UnitKind::Disk {
+            supported_pages.extend_from_slice(&[0xb0, 0xb1]);
+        }
+        let page = cdb[2];
+        let payload = match page {
+            0x00 => supported_pages.clone(),
+            0x80 => self.serial.as_bytes().to_vec(),
+            0x83 => {
+                // T10 vendor identification designator
+                let mut identifier = VENDOR.to_vec();
+                identifier.extend_from_slice(self.serial.as_bytes());
+                let mut designator = vec![0x02, 0x01, 0x00, identifier.len() as u8];
+                designator.extend_from_slice(&identifier);
+                designator
+            }
+            // Block limits, none of them being reported.
+            0xb0 if self.kind == UnitKind::Disk => vec![0; 0x3c],
+            // Block device characteristics
+            0xb1 if self.kind == UnitKind::Disk => {
+                let mut characteristics = vec![0; 0x3c];
+                // Non-rotating medium
+                characteristics[1] = 0x01;
+                characteristics
+            }
+            _ => return CommandResult::check_condition(Sense::INVALID_FIELD_IN_CDB),
+        };
+
+        let mut data = vec![peripheral, page];
+        data.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        data.extend_from_slice(&payload);
+        CommandResult::good_truncated(data, allocation_length)
+    }
+
+    fn mode_page(&self, page: u8) -> Option<Vec<u8>> {
+        match page {
+            MODE_PAGE_CACHING => {
+                let mut data = vec![0; 20];
+                data[0] = MODE_PAGE_CACHING;
+                data[1] = 18;
+                // Write cache enabled
+                data[2] = 0x04;
+                Some(data)
+            }
+            MODE_PAGE_CAPABILITIES if self.kind == UnitKind::Cdrom => {
+                let mut data = vec![0; 22];
+                data[0] = MODE_PAGE_CAPABILITIES;
+                data[1] = 20;
+                // Reads CD-R media
+                data[2] = 0x01;
+                // Tray loading mechanism, supports eject and lock
+                data[6] = 0x20 | 0x08 | 0x01 | if self.locked { 0x02 } else { 0 };
+                Some(data)
+            }
+            _ => None,
+        }
+    }
+
+    fn mode_sense(&self, cdb: &[u8]) -> CommandResult {
+        let ten = cdb[0] == MODE_SENSE_10;
+        let disable_block_descriptors = cdb[1] & 0x08 != 0;
+        let page_control = cdb[2] >> 6;
+        let page = cdb[2] & 0x3f;
+        let allocation_length = if ten {
+            be16(&cdb[7..9]) as usize
+        } else {
+            cdb[4] as usize
+        };
+
+        // Saved values
+        if page_control == 3 {
+            return CommandResult::check_condition(Sense::SAVING_PARAMETERS_NOT_SUPPORTED);
+        }
+
+        let pages = if page == MODE_PAGE_ALL {
+            [MODE_PAGE_CACHING, MODE_PAGE_CAPABILITIES]
+                .iter()
+                .filter_map(|page| self.mode_page(*page))
+                .flatten()
+                .collect()
+        } else if let Some(data) = self.mode_page(page) {
+            data
+        } else {
+            return CommandResult::check_condition(Sense::INVALID_FIELD_IN_CDB);
+        };
+
+        let mut block_descriptor = Vec::new();
+        if !disable_block_descriptors && self.kind == UnitKind::Disk {
+            let blocks = cmp::min(self.num_blocks, 0xff_ffff) as u32;
+            block_descriptor.extend_from_slice(&blocks.to_be_bytes());
+            block_descriptor.extend_from_slice(&self.block_size().to_be_bytes());
+        }
+
+        let device_specific = if self.readonly { 0x80 } else { 0 };
+        let mut data = if ten {
+            let mut header = vec![0; 8];
+            let len = 6 + block_descriptor.len() + pages.len();
+            header[0..2].copy_from_slice(&(len as u16).to_be_bytes());
+            header[3] = device_specific;
+            header[6..8].copy_from_slice(&(block_descriptor.len() as u16).to_be_bytes());
+            header
+        } else {
+            let len = 3 + block_descriptor.len() + pages.len();
+            vec![len as u8, 0, device_specific, block_descriptor.len() as u8]
+        };
+        data.extend_from_slice(&block_descriptor);
+        data.extend_from_slice(&pages);
+
+        CommandResult::good_truncated(data, allocation_length)
+    }
+
+    fn start_stop_unit(&mut self, cdb: &[u8]) -> CommandResult {
+        let load_eject = cdb[4] & 0x2 != 0;
+        let start = cdb[4] & 0x1 != 0;
+
+        // Loading doesn't do anything, as the tray can't hold a medium
+        // which hasn't been inserted.
+        if self.kind == UnitKind::Cdrom && load_eject && !start && self.file.is_some() {
+            if self.locked {
+                return CommandResult::check_condition(Sense::MEDIUM_REMOVAL_PREVENTED);
+            }
+            self.file = None;
+            self.num_blocks = 0;
+            self.media_event = MEDIA_EVENT_MEDIA_REMOVAL;
+        }
+
+        CommandResult::good(Vec::new())
+    }
+
+    fn read_capacity_10(&self) -> CommandResult {
+        if let Err(result) = self.check_medium() {
+            return result;
+        }
+
+        let last_lba = cmp::min(self.num_blocks.saturating_sub(1), u32::MAX as u64) as u32;
+        let mut data = last_lba.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.block_size().to_be_bytes());
+        CommandResult::good(data)
+    }
+
+    fn read_capacity_16(&self, cdb: &[u8]) -> CommandResult {
+        if let Err(result) = self.check_medium() {
+            return result;
+        }
+
+        let mut data = vec![0; 32];
+        data[0..8].copy_from_slice(&self.num_blocks.saturating_sub(1).to_be_bytes());
+        data[8..12].copy_from_slice(&self.block_size().to_be_bytes());
+        CommandResult::good_truncated(data, be32(&cdb[10..14]) as usize)
+    }
+
+    fn transfer(&self, cdb: &[u8]) -> Result<Transfer, CommandResult> {
+        self.check_medium()?;
+
+        let (lba, blocks) = match cdb[0] {
+            READ_6 | WRITE_6 => {
+                let lba = (((cdb[1] & 0x1f) as u64) << 16) | be16(&cdb[2..4]) as u64;
+                // A transfer length of 0 stands for 256 blocks.
+                let blocks = if cdb[4] == 0 { 256 } else { cdb[4] as u64 };
+                (lba, blocks)
+            }
+            READ_10 | WRITE_10 => (be32(&cdb[2..6]) as u64, be16(&cdb[7..9]) as u64),
+            READ_12 | WRITE_12 => (be32(&cdb[2..6]) as u64, be32(&cdb[6..10]) as u64),
+            _ => (be64(&cdb[2..10]), be32(&cdb[10..14]) as u64),
+        };
+
+        if lba
+            .checked_add(blocks)
+            .map_or(true, |end| end > self.num_blocks)
+        {
+            return Err(CommandResult::check_condition(Sense::LBA_OUT_OF_RANGE));
+        }
+
+        Ok(Transfer { lba, blocks })
+    }
+
+    fn read(&mut self, cdb: &[u8]) -> CommandResult {
+        let transfer = match self.transfer(cdb) {
+            Ok(transfer) => transfer,
+            Err(result) => return result,
+        };
+
+        let block_size = self.block_size() as u64;
+        let mut data = vec![0; (transfer.blocks * block_size) as usize];
+        let file = self.file.as_ref().unwrap();
+        if let Err(e) = file.read_exact_at(&mut data, transfer.lba * block_size) {
+            error!("Failed reading SCSI disk image: {}", e);
+            return CommandResult::check_condition(Sense::UNRECOVERED_READ_ERROR);
+        }
+
+        CommandResult::good(data)
+    }
+
+    fn write(&mut self, cdb: &[u8], data_out: &[u8]) -> CommandResult {
+        let transfer = match self.transfer(cdb) {
+            Ok(transfer) => transfer,
+            Err(result) => return result,
+        };
+        if self.readonly {
+            return CommandResult::check_condition(Sense::WRITE_PROTECTED);
+        }
+
+        let block_size = self.block_size() as u64;
+        let len = (transfer.blocks * block_size) as usize;
+        if data_out.len() < len {
+            return CommandResult::check_condition(Sense::INVALID_FIELD_IN_CDB);
+        }
+        let file = self.file.as_ref().unwrap();
+        if let Err(e) = file.write_all_at(&data_out[..len], transfer.lba * block_size) {
+            error!("Failed writing SCSI disk image: {}", e);
+            return CommandResult::check_condition(Sense::WRITE_ERROR);
+        }
+
+        CommandResult::good(Vec::new())
+    }
+
+    fn synchronize_cache(&self) -> CommandResult {
+        if let Some(file) = &self.file {
+            if let Err(e) = file.sync_data() {
+                error!("Failed flushing SCSI disk image: {}", e);
+                return CommandResult::check_condition(Sense::WRITE_ERROR);
+            }
+        }
+
+        CommandResult::good(Vec::new())
+    }
+
+    fn read_toc(&self, cdb: &[u8]) -> CommandResult {
+        if let Err(result) = self.check_medium() {
+            return result;
+        }
+
+        let msf = cdb[1] & 0x2 != 0;
+        let format = cdb[2] & 0xf;
+        let track = cdb[6];
+        let allocation_length = be16(&cdb[7..9]) as usize;
+
+        // The address of a block, as an LBA or as minutes, seconds and
+        // frames, the latter starting from the 2 seconds pregap.
+        let address = |lba: u64| -> [u8; 4] {
+            if msf {
+                let frames = lba + 150;
+                [
+                    0,
+                    (frames / (60 * 75)) as u8,
+                    ((frames / 75) % 60) as u8,
+                    (frames % 75) as u8,
+                ]
+            } else {
+                (lba as u32).to_be_bytes()
+            }
+        };
+
+        // The disc holds a single data track.
+        let mut data = vec![0, 0, 1, 1];
+        match format {
+            // Table of contents
+            0 => {
+                if track > 1 && track != 0xaa {
+                    return CommandResult::check_condition(Sense::INVALID_FIELD_IN_CDB);
+                }
+                if track <= 1 {
+                    data.extend_from_slice(&[0, 0x14, 1, 0]);
+                    data.extend_from_slice(&address(0));
+                }
+                // Lead-out
+                data.extend_from_slice(&[0, 0x14, 0xaa, 0]);
+                data.extend_from_slice(&address(self.num_blocks));
+            }
+            // Session information
+            1 => {
+                data.extend_from_slice(&[0, 0x14, 1, 0]);
+                data.extend_from_slice(&address(0));
+            }
+            _ => return CommandResult::check_condition(Sense::INVALID_FIELD_IN_CDB),
+        }
+        let len = (data.len() - 2) as u16;
+        data[0..2].copy_from_slice(&len.to_be_bytes());
+
+        CommandResult::good_truncated(data, allocation_length)
+    }
+
+    fn get_configuration(&self, cdb: &[u8]) -> CommandResult {
+        let allocation_length = be16(&cdb[7..9]) as usize;
+        let current_profile = if self.file.is_some() {
+            MMC_PROFILE_CDROM
+        } else {
+            MMC_PROFILE_NONE
+        };
+
+        let mut data = vec![0; 8];
+        data[6..8].copy_from_slice(&current_profile.to_be_bytes());
+        // Profile list feature, holding the CD-ROM profile.
+        data.extend_from_slice(&[0x00, 0x00, 0x03, 0x04]);
+        data.extend_from_slice(&MMC_PROFILE_CDROM.to_be_bytes());
+        data.extend_from_slice(&[(current_profile == MMC_PROFILE_CDROM) as u8, 0]);
+        let len = (data.len() - 4) as u32;
+        data[0..4].copy_from_slice(&len.to_be_bytes());
+
+        CommandResult::good_truncated(data, allocation_length)
+    }
+
+    fn get_event_status_notification(&mut self, cdb: &[u8]) -> CommandResult {
+        // Only the polled mode is supported.
+        if cdb[1] & 0x1 == 0 {
+            return CommandResult::check_condition(Sense::INVALID_FIELD_IN_CDB);
+        }
+        let allocation_length = be16(&cdb[7..9]) as usize;
+        const MEDIA_CLASS: u8 = 1 << 4;
+
+        let data = if cdb[4] & MEDIA_CLASS != 0 {
+            let event = self.media_event;
+            self.media_event = MEDIA_EVENT_NO_CHANGE;
+            vec![
+                0,
+                6,
+                // Media class
+                4,
+                MEDIA_CLASS,
+                event,
+                // Media present
+                if self.file.is_some() { 0x2 } else { 0 },
+                0,
+                0,
+            ]
+        } else {
+            // No event available for the requested classes
+            vec![0, 2, 0x80, MEDIA_CLASS]
+        };
+
+        CommandResult::good_truncated(data, allocation_length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{STATUS_CHECK_CONDITION, STATUS_GOOD};
+    use super::*;
+    use vmm_sys_util::tempfile::TempFile;
+
+    fn image(blocks: u64, block_size: u32) -> File {
+        let file = TempFile::new().unwrap().into_file();
+        file.set_len(blocks * block_size as u64).unwrap();
+        file
+    }
+
+    fn sense_key(result: &CommandResult) -> (u8, u8, u8) {
+        (result.sense[2], result.sense[12], result.sense[13])
+    }
+
+    #[test]
+    fn test_disk_read_write() {
+        let mut lun =
+            EmulatedLun::new(UnitKind::Disk, Some(image(16, 512)), false, "0".to_owned()).unwrap();
+
+        let result = lun.execute(&[READ_CAPACITY_10, 0, 0, 0, 0, 0, 0, 0, 0, 0], &[]);
+        assert_eq!(result.status, STATUS_GOOD);
+        assert_eq!(result.data, [0, 0, 0, 15, 0, 0, 2, 0]);
+
+        let data = vec![0xa5; 1024];
+        let result = lun.execute(&[WRITE_10, 0, 0, 0, 0, 14, 0, 0, 2, 0], &data);
+        assert_eq!(result.status, STATUS_GOOD);
+        let result = lun.execute(&[READ_10, 0, 0, 0, 0, 15, 0, 0, 1, 0], &[]);
+        assert_eq!(result.status, STATUS_GOOD);
+        assert_eq!(result.data, vec![0xa5; 512]);
+
+        let result = lun.execute(&[READ_10, 0, 0, 0, 0, 15, 0, 0, 2, 0], &[]);
+        assert_eq!(result.status, STATUS_CHECK_CONDITION);
+        assert_eq!(sense_key(&result), (0x05, 0x21, 0x00));
+    }
+
+    #[test]
+    fn test_readonly_disk() {
+        let mut lun =
+            EmulatedLun::new(UnitKind::Disk, Some(image(16, 512)), true, "0".to_owned()).unwrap();
+
+        let result = lun.execute(&[WRITE_6, 0, 0, 0, 1, 0], &[0; 512]);
+        assert_eq!(result.status, STATUS_CHECK_CONDITION);
+        assert_eq!(sense_key(&result), (0x07, 0x27, 0x00));
+
+        let result = lun.execute(&[MODE_SENSE_6, 0, MODE_PAGE_CACHING, 0, 0xff, 0], &[]);
+        assert_eq!(result.status, STATUS_GOOD);
+        assert_eq!(result.data[2], 0x80);
+        assert_eq!(result.data[0] as usize, result.data.len() - 1);
+    }
+
+    #[test]
+    fn test_inquiry() {
+        let mut lun = EmulatedLun::new(UnitKind::Cdrom, None, false, "cd0".to_owned()).unwrap();
+
+        let result = lun.execute(&[INQUIRY, 0, 0, 0, 36, 0], &[]);
+        assert_eq!(result.status, STATUS_GOOD);
+        assert_eq!(result.data.len(), 36);
+        assert_eq!(&result.data[..2], &[TYPE_ROM, 0x80]);
+        assert_eq!(&result.data[16..29], b"VIRTUAL CDROM");
+
+        let result = lun.execute(&[INQUIRY, 1, 0x80, 0, 0xff, 0], &[]);
+        assert_eq!(result.status, STATUS_GOOD);
+        assert_eq!(result.data, [TYPE_ROM, 0x80, 0, 3, b'c', b'd', b'0']);
+
+        let result = lun.execute(&[INQUIRY, 1, 0xb0, 0, 0xff, 0], &[]);
+        assert_eq!(result.status, STATUS_CHECK_CONDITION);
+    }
+
+    #[test]
+    fn test_cdrom_media_change() {
+        let mut lun = EmulatedLun::new(UnitKind::Cdrom, None, false, "cd0".to_owned()).unwrap();
+
+        let result = lun.execute(&[TEST_UNIT_READY, 0, 0, 0, 0, 0], &[]);
+        assert_eq!(sense_key(&result), (0x02, 0x3a, 0x00));
+
+        lun.change_media(Some(image(8, 2048))).unwrap();
+        let result = lun.execute(&[TEST_UNIT_READY, 0, 0, 0, 0, 0], &[]);
+        assert_eq!(sense_key(&result), (0x06, 0x28, 0x00));
+        let result = lun.execute(&[TEST_UNIT_READY, 0, 0, 0, 0, 0], &[]);
+        assert_eq!(result.status, STATUS_GOOD);
+
+        let gesn = [GET_EVENT_STATUS_NOTIFICATION, 1, 0, 0, 0x10, 0, 0, 0, 8, 0];
+        let result = lun.execute(&gesn, &[]);
+        assert_eq!(result.data[4..6], [MEDIA_EVENT_NEW_MEDIA, 0x2]);
+        let result = lun.execute(&gesn, &[]);
+        assert_eq!(result.data[4..6], [MEDIA_EVENT_NO_CHANGE, 0x2]);
+
+        let result = lun.execute(&[READ_CAPACITY_10, 0, 0, 0, 0, 0, 0, 0, 0, 0], &[]);
+        assert_eq!(result.data, [0, 0, 0, 7, 0, 0, 8, 0]);
+
+        // The guest prevents the removal of the medium.
+        lun.execute(&[PREVENT_ALLOW_MEDIUM_REMOVAL, 0, 0, 0, 1, 0], &[]);
+        assert!(matches!(lun.change_media(None), Err(MediaError::Locked)));
+        let result = lun.execute(&[START_STOP_UNIT, 0, 0, 0, 0x2, 0], &[]);
+        assert_eq!(sense_key(&result), (0x05, 0x53, 0x02));
+
+        lun.execute(&[PREVENT_ALLOW_MEDIUM_REMOVAL, 0, 0, 0, 0, 0], &[]);
+        let result = lun.execute(&[START_STOP_UNIT, 0, 0, 0, 0x2, 0], &[]);
+        assert_eq!(result.status, STATUS_GOOD);
+        let result = lun.execute(&gesn, &[]);
+        assert_eq!(result.data[4..6], [MEDIA_EVENT_MEDIA_REMOVAL, 0]);
+    }
+
+    #[test]
+    fn test_read_toc() {
+        let mut lun = EmulatedLun::new(
+            UnitKind::Cdrom,
+            Some(image(300, 2048)),
+            false,
+            "0".to_owned(),
+        )
+        .unwrap();
+
+        let result = lun.execute(&[READ_TOC, 0, 0, 0, 0, 0, 0, 0, 0xff, 0], &[]);
+        assert_eq!(result.status, STATUS_GOOD);
+        assert_eq!(
+            result.data,
+            [0, 18, 1, 1, 0, 0x14, 1, 0, 0, 0, 0, 0, 0, 0x14, 0xaa, 0, 0, 0, 0x01, 0x2c]
+        );
+
+        // Lead-out at 300 + 150 frames, that is 6 seconds.
+        let result = lun.execute(&[READ_TOC, 0x2, 0, 0, 0, 0, 0xaa, 0, 0xff, 0], &[]);
+        assert_eq!(result.data, [0, 10, 1, 1, 0, 0x14, 0xaa, 0, 0, 0, 6, 0]);
+    }
+
+    #[test]
+    fn test_disk_not_removable() {
+        let mut lun =
+            EmulatedLun::new(UnitKind::Disk, Some(image(16, 512)), false, "0".to_owned()).unwrap();
+        assert!(matches!(
+            lun.change_media(None),
+            Err(MediaError::NotRemovable)
+        ));
+    }
+}
@@ -0,0 +1,829 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Virtio SCSI controller, exposing a single target with up to 16384 logical
+//! units.
+//!
+//! Each logical unit is either emulated on top of a raw image, as a disk or as
+//! a CD-ROM drive whose medium can be changed at runtime, or passed through to
+//! a host SCSI device.
+
+mod emulated;
+mod passthrough;
+
+use self::emulated::{EmulatedLun, UnitKind, INQUIRY, REPORT_LUNS};
+use self::passthrough::PassthroughLun;
+use super::{
+    ActivateError, ActivateResult, EpollHelper, EpollHelperError, EpollHelperHandler, VirtioCommon,
+    VirtioDevice, VirtioDeviceType, EPOLL_HELPER_EVENT_LAST, VIRTIO_F_IOMMU_PLATFORM,
+    VIRTIO_F_VERSION_1,
+};
+use crate::regions::{self, parse_chain, Regions};
+use crate::seccomp_filters::Thread;
+use crate::thread_helper::spawn_virtio_thread;
+use crate::GuestMemoryMmap;
+use crate::{VirtioInterrupt, VirtioInterruptType};
+use anyhow::anyhow;
+use seccompiler::SeccompAction;
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::mem::size_of;
+use std::os::unix::io::AsRawFd;
+use std::result;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Barrier, Mutex};
+use thiserror::Error;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+use virtio_queue::{Queue, QueueT};
+use vm_memory::{ByteValued, GuestAddressSpace, GuestMemoryAtomic};
+use vm_migration::VersionMapped;
+use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
+use vm_virtio::AccessPlatform;
+use vmm_sys_util::eventfd::EventFd;
+
+const QUEUE_SIZE: u16 = 256;
+const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE; 3];
+
+const CONTROL_QUEUE: usize = 0;
+const REQUEST_QUEUE: usize = 2;
+
+// New descriptors are pending on the control queue.
+const CONTROL_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 1;
+// New descriptors are pending on the request queue.
+const REQUEST_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 2;
+
+/// Highest logical unit number a controller can expose.
+pub const SCSI_MAX_LUN: u16 = 16383;
+
+const MAX_SECTORS: u32 = 0xffff;
+// Largest transfer accepted from the guest, matching the advertised maximum
+// number of 512 bytes sectors.
+const MAX_TRANSFER_SIZE: usize = MAX_SECTORS as usize * 512;
+
+const DEFAULT_SENSE_SIZE: u32 = 96;
+const DEFAULT_CDB_SIZE: u32 = 32;
+const MAX_SENSE_SIZE: u32 = 252;
+const MAX_CDB_SIZE: u32 = 255;
+
+// Offset and size of the sense_size and cdb_size fields, the only ones the
+// driver may write.
+const CONFIG_WRITABLE_OFFSET: u64 = 20;
+const CONFIG_WRITABLE_SIZE: usize = 8;
+
+// Got from include/uapi/linux/virtio_scsi.h
+const VIRTIO_SCSI_T_TMF: u32 = 0;
+const VIRTIO_SCSI_T_AN_QUERY: u32 = 1;
+const VIRTIO_SCSI_T_AN_SUBSCRIBE: u32 = 2;
+
+const VIRTIO_SCSI_S_OK: u8 = 0;
+const VIRTIO_SCSI_S_OVERRUN: u8 = 1;
+const VIRTIO_SCSI_S_BAD_TARGET: u8 = 3;
+const VIRTIO_SCSI_S_FAILURE: u8 = 9;
+const VIRTIO_SCSI_S_FUNCTION_COMPLETE: u8 = 0;
+
+// SCSI status codes
+pub(crate) const STATUS_GOOD: u8 = 0x00;
+pub(crate) const STATUS_CHECK_CONDITION: u8 = 0x02;
+
+// Peripheral qualifier and type reported for logical units which don't exist.
+const PERIPHERAL_NOT_CONNECTED: u8 = 0x7f;
+
+#[derive(Error, Debug)]
+enum Error {
+    #[error("Descriptor chain too short")]
+    DescriptorChainTooShort,
+    #[error("Failed to read from guest memory: {0}")]
+    GuestMemoryRead(vm_memory::guest_memory::Error),
+    #[error("Failed to write to guest memory: {0}")]
+    GuestMemoryWrite(vm_memory::guest_memory::Error),
+    #[error("Failed adding used index: {0}")]
+    QueueAddUsed(virtio_queue::Error),
+    #[error("Failed to signal used queue: {0}")]
+    SignalUsedQueue(io::Error),
+}
+
+impl From<regions::Error> for Error {
+    fn from(e: regions::Error) -> Self {
+        match e {
+            regions::Error::DescriptorChainTooShort => Error::DescriptorChainTooShort,
+            regions::Error::GuestMemoryRead(e) => Error::GuestMemoryRead(e),
+            regions::Error::GuestMemoryWrite(e) => Error::GuestMemoryWrite(e),
+        }
+    }
+}
+
+/// Errors changing the medium of a logical unit.
+#[derive(Error, Debug)]
+pub enum MediaError {
+    #[error("No logical unit {0}")]
+    NoSuchLun(u16),
+    #[error("Logical unit has no removable medium")]
+    NotRemovable,
+    #[error("Medium removal is prevented by the guest")]
+    Locked,
+    #[error("Failed to open the new medium: {0}")]
+    Open(#[source] io::Error),
+}
+
+/// Sense data reported along with a CHECK CONDITION status.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Sense {
+    key: u8,
+    asc: u8,
+    ascq: u8,
+}
+
+impl Sense {
+    pub const NO_SENSE: Sense = Sense::new(0x00, 0x00, 0x00);
+    pub const MEDIUM_NOT_PRESENT: Sense = Sense::new(0x02, 0x3a, 0x00);
+    pub const UNRECOVERED_READ_ERROR: Sense = Sense::new(0x03, 0x11, 0x00);
+    pub const WRITE_ERROR: Sense = Sense::new(0x03, 0x0c, 0x00);
+    pub const INVALID_OPCODE: Sense = Sense::new(0x05, 0x20, 0x00);
+    pub const LBA_OUT_OF_RANGE: Sense = Sense::new(0x05, 0x21, 0x00);
+    pub const INVALID_FIELD_IN_CDB: Sense = Sense::new(0x05, 0x24, 0x00);
+    pub const LUN_NOT_SUPPORTED: Sense = Sense::new(0x05, 0x25, 0x00);
+    pub const SAVING_PARAMETERS_NOT_SUPPORTED: Sense = Sense::new(0x05, 0x39, 0x00);
+    pub const MEDIUM_REMOVAL_PREVENTED: Sense = Sense::new(0x05, 0x53, 0x02);
+    pub const MEDIUM_MAY_HAVE_CHANGED: Sense = Sense::new(0x06, 0x28, 0x00);
+    pub const WRITE_PROTECTED: Sense = Sense::new(0x07, 0x27, 0x00);
+
+    const fn new(key: u8, asc: u8, ascq: u8) -> Self {
+        Sense { key, asc, ascq }
+    }
+
+    /// Fixed format sense data, as described by SPC.
+    pub fn to_fixed_format(self) -> Vec<u8> {
+        vec![
+            0x70, 0, self.key, 0, 0, 0, 0, 10, 0, 0, 0, 0, self.asc, self.ascq, 0, 0, 0, 0,
+        ]
+    }
+}
+
+/// Outcome of a SCSI command.
+#[derive(Debug)]
+pub(crate) struct CommandResult {
+    pub status: u8,
+    pub sense: Vec<u8>,
+    // Data returned to the guest.
+    pub data: Vec<u8>,
+}
+
+impl CommandResult {
+    pub fn good(data: Vec<u8>) -> Self {
+        CommandResult {
+            status: STATUS_GOOD,
+            sense: Vec::new(),
+            data,
+        }
+    }
+
+    /// Successful command whose data is limited by the allocation length
+    /// given by the guest.
+    pub fn good_truncated(mut data: Vec<u8>, allocation_length: usize) -> Self {
+        data.truncate(allocation_length);
+        CommandResult::good(data)
+    }
+
+    pub fn check_condition(sense: Sense) -> Self {
+        CommandResult {
+            status: STATUS_CHECK_CONDITION,
+            sense: sense.to_fixed_format(),
+            data: Vec::new(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Versionize)]
+#[allow(dead_code)]
+pub struct VirtioScsiConfig {
+    num_queues: u32,
+    seg_max: u32,
+    max_sectors: u32,
+    cmd_per_lun: u32,
+    event_info_size: u32,
+    sense_size: u32,
+    cdb_size: u32,
+    max_channel: u16,
+    max_target: u16,
+    max_lun: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+#[allow(dead_code)]
+struct VirtioScsiCmdRespHeader {
+    sense_len: u32,
+    resid: u32,
+    status_qualifier: u16,
+    status: u8,
+    response: u8,
+}
+
+// SAFETY: the following structures only have data and no implicit padding.
+unsafe impl ByteValued for VirtioScsiConfig {}
+// SAFETY: see above
+unsafe impl ByteValued for VirtioScsiCmdRespHeader {}
+
+// Size of the packed request header: the LUN, the tag, the task attribute,
+// the priority and the command reference number.
+const CMD_REQ_HEADER_SIZE: usize = 19;
+const CMD_RESP_HEADER_SIZE: usize = size_of::<VirtioScsiCmdRespHeader>();
+
+// Decodes the LUN field of a request, only accepting the single level
+// addressing of the first target, the way the Linux driver builds it.
+fn decode_lun(lun: &[u8; 8]) -> Option<u16> {
+    if lun[0] != 1 || lun[1] != 0 {
+        return None;
+    }
+
+    Some((((lun[2] & 0x3f) as u16) << 8) | lun[3] as u16)
+}
+
+// Encodes a LUN the way the REPORT LUNS command returns it, using the
+// peripheral addressing method when possible and the flat space one
+// otherwise.
+fn encode_lun(lun: u16) -> [u8; 8] {
+    if lun < 256 {
+        [0, lun as u8, 0, 0, 0, 0, 0, 0]
+    } else {
+        [0x40 | (lun >> 8) as u8, lun as u8, 0, 0, 0, 0, 0, 0]
+    }
+}
+
+fn report_luns(luns: &BTreeMap<u16, Lun>, cdb: &[u8]) -> CommandResult {
+    if cdb.len() < 12 {
+        return CommandResult::check_condition(Sense::INVALID_OPCODE);
+    }
+
+    let mut data = ((luns.len() * 8) as u32).to_be_bytes().to_vec();
+    data.extend_from_slice(&[0; 4]);
+    for lun in luns.keys() {
+        data.extend_from_slice(&encode_lun(*lun));
+    }
+    let allocation_length = u32::from_be_bytes([cdb[6], cdb[7], cdb[8], cdb[9]]);
+
+    CommandResult::good_truncated(data, allocation_length as usize)
+}
+
+// Answers the commands sent to a logical unit which doesn't exist.
+fn missing_lun(cdb: &[u8]) -> CommandResult {
+    if cdb[0] == INQUIRY && cdb.len() >= 6 && cdb[1] & 0x1 == 0 {
+        let allocation_length = u16::from_be_bytes([cdb[3], cdb[4]]);
+        CommandResult::good_truncated(
+            emulated::standard_inquiry(PERIPHERAL_NOT_CONNECTED, false, ""),
+            allocation_length as usize,
+        )
+    } else {
+        CommandResult::check_condition(Sense::LUN_NOT_SUPPORTED)
+    }
+}
+
+enum Lun {
+    Emulated(EmulatedLun),
+    Passthrough(PassthroughLun),
+}
+
+/// Backend of a logical unit.
+pub enum ScsiLunBackend {
+    /// Raw disk image.
+    Disk { file: File, readonly: bool },
+    /// CD-ROM drive, empty when no image is given.
+    Cdrom(Option<File>),
+    /// Host SCSI generic or block device the commands are passed through to.
+    Passthrough(File),
+}
+
+/// Logical unit of a SCSI controller.
+pub struct ScsiLun {
+    pub lun: u16,
+    pub backend: ScsiLunBackend,
+    /// Serial number reported by the emulated logical units.
+    pub serial: String,
+}
+
+struct ScsiEpollHandler {
+    mem: GuestMemoryAtomic<GuestMemoryMmap>,
+    queues: Vec<Queue>,
+    interrupt_cb: Arc<dyn VirtioInterrupt>,
+    control_queue_evt: EventFd,
+    request_queue_evt: EventFd,
+    kill_evt: EventFd,
+    pause_evt: EventFd,
+    access_platform: Option<Arc<dyn AccessPlatform>>,
+    luns: Arc<Mutex<BTreeMap<u16, Lun>>>,
+    sense_size: usize,
+    cdb_size: usize,
+}
+
+impl ScsiEpollHandler {
+    fn process_control_queue(&mut self) -> result::Result<bool, Error> {
+        let mut used_descs = false;
+        while let Some(mut desc_chain) =
+            self.queues[CONTROL_QUEUE].pop_descriptor_chain(self.mem.memory())
+        {
+            let (readable, writable) = parse_chain(&mut desc_chain, self.access_platform.as_ref());
+            let mut request_type = [0u8; 4];
+            readable.read(desc_chain.memory(), 0, &mut request_type)?;
+
+            // Commands are completed synchronously, hence there is never any
+            // task to abort or to reset.
+            let response = match u32::from_le_bytes(request_type) {
+                VIRTIO_SCSI_T_TMF => vec![VIRTIO_SCSI_S_FUNCTION_COMPLETE],
+                // No asynchronous notification is ever reported, which is
+                // told through an empty event_actual field.
+                VIRTIO_SCSI_T_AN_QUERY | VIRTIO_SCSI_T_AN_SUBSCRIBE => {
+                    let mut response = 0u32.to_le_bytes().to_vec();
+                    response.push(VIRTIO_SCSI_S_OK);
+                    response
+                }
+                request_type => {
+                    warn!("Unsupported virtio-scsi control request {}", request_type);
+                    Vec::new()
+                }
+            };
+            let len = cmp::min(response.len(), writable.len());
+            writable.write(desc_chain.memory(), 0, &response[..len])?;
+
+            self.queues[CONTROL_QUEUE]
+                .add_used(desc_chain.memory(), desc_chain.head_index(), len as u32)
+                .map_err(Error::QueueAddUsed)?;
+            used_descs = true;
+        }
+
+        Ok(used_descs)
+    }
+
+    fn process_request_queue(&mut self) -> result::Result<bool, Error> {
+        let mut used_descs = false;
+        while let Some(mut desc_chain) =
+            self.queues[REQUEST_QUEUE].pop_descriptor_chain(self.mem.memory())
+        {
+            let (readable, writable) = parse_chain(&mut desc_chain, self.access_platform.as_ref());
+            let len = self.handle_request(desc_chain.memory(), &readable, &writable)?;
+
+            self.queues[REQUEST_QUEUE]
+                .add_used(desc_chain.memory(), desc_chain.head_index(), len as u32)
+                .map_err(Error::QueueAddUsed)?;
+            used_descs = true;
+        }
+
+        Ok(used_descs)
+    }
+
+    // Runs the command of a request, returning the number of bytes written
+    // to the guest.
+    fn handle_request(
+        &mut self,
+        mem: &GuestMemoryMmap,
+        readable: &Regions,
+        writable: &Regions,
+    ) -> result::Result<usize, Error> {
+        let req_len = CMD_REQ_HEADER_SIZE + self.cdb_size;
+        let resp_len = CMD_RESP_HEADER_SIZE + self.sense_size;
+        if readable.len() < req_len || writable.len() < resp_len {
+            return Err(Error::DescriptorChainTooShort);
+        }
+
+        let mut lun = [0u8; 8];
+        readable.read(mem, 0, &mut lun)?;
+        let mut cdb = vec![0u8; self.cdb_size];
+        readable.read(mem, CMD_REQ_HEADER_SIZE, &mut cdb)?;
+
+        let data_out_len = readable.len() - req_len;
+        let data_in_len = writable.len() - resp_len;
+        let mut resp = VirtioScsiCmdRespHeader::default();
+        if data_out_len > MAX_TRANSFER_SIZE || data_in_len > MAX_TRANSFER_SIZE {
+            resp.response = VIRTIO_SCSI_S_OVERRUN;
+            writable.write(mem, 0, resp.as_slice())?;
+            return Ok(CMD_RESP_HEADER_SIZE);
+        }
+
+        let lun = match decode_lun(&lun) {
+            Some(lun) => lun,
+            None => {
+                resp.response = VIRTIO_SCSI_S_BAD_TARGET;
+                writable.write(mem, 0, resp.as_slice())?;
+                return Ok(CMD_RESP_HEADER_SIZE);
+            }
+        };
+
+        let mut data_out = vec![0u8; data_out_len];
+        readable.read(mem, req_len, &mut data_out)?;
+
+        let result = {
+            let mut luns = self.luns.lock().unwrap();
+            if cdb[0] == REPORT_LUNS {
+                Ok(report_luns(&luns, &cdb))
+            } else {
+                match luns.get_mut(&lun) {
+                    Some(Lun::Emulated(unit)) => Ok(unit.execute(&cdb, &data_out)),
+                    Some(Lun::Passthrough(unit)) => unit.execute(&cdb, &data_out, data_in_len),
+                    None => Ok(missing_lun(&cdb)),
+                }
+            }
+        };
+
+        let result = match result {
+            Ok(result) => result,
+            Err(e) => {
+                error!("Failed passing SCSI command through: {}", e);
+                resp.response = VIRTIO_SCSI_S_FAILURE;
+                writable.write(mem, 0, resp.as_slice())?;
+                return Ok(CMD_RESP_HEADER_SIZE);
+            }
+        };
+
+        let data_len = cmp::min(result.data.len(), data_in_len);
+        writable.write(mem, resp_len, &result.data[..data_len])?;
+        let sense_len = cmp::min(result.sense.len(), self.sense_size);
+        writable.write(mem, CMD_RESP_HEADER_SIZE, &result.sense[..sense_len])?;
+
+        resp.sense_len = sense_len as u32;
+        resp.resid = (data_in_len - data_len) as u32;
+        resp.status = result.status;
+        resp.response = if result.data.len() > data_in_len {
+            VIRTIO_SCSI_S_OVERRUN
+        } else {
+            VIRTIO_SCSI_S_OK
+        };
+        writable.write(mem, 0, resp.as_slice())?;
+
+        Ok(resp_len + data_len)
+    }
+
+    fn signal_used_queue(&self, queue_index: usize) -> result::Result<(), Error> {
+        self.interrupt_cb
+            .trigger(VirtioInterruptType::Queue(queue_index as u16))
+            .map_err(|e| {
+                error!("Failed to signal used queue: {:?}", e);
+                Error::SignalUsedQueue(e)
+            })
+    }
+
+    fn handle_result(
+        &self,
+        queue_index: usize,
+        result: result::Result<bool, Error>,
+    ) -> result::Result<(), EpollHelperError> {
+        let needs_notification = result.map_err(|e| {
+            EpollHelperError::HandleEvent(anyhow!("Failed to process queue : {:?}", e))
+        })?;
+        if needs_notification {
+            self.signal_used_queue(queue_index).map_err(|e| {
+                EpollHelperError::HandleEvent(anyhow!("Failed to signal used queue: {:?}", e))
+            })?;
+        }
+
+        Ok(())
+    }
+
+    fn run(
+        &mut self,
+        paused: Arc<AtomicBool>,
+        paused_sync: Arc<Barrier>,
+    ) -> result::Result<(), EpollHelperError> {
+        let mut helper = EpollHelper::new(&self.kill_evt, &self.pause_evt)?;
+        helper.add_event(self.control_queue_evt.as_raw_fd(), CONTROL_QUEUE_EVENT)?;
+        helper.add_event(self.request_queue_evt.as_raw_fd(), REQUEST_QUEUE_EVENT)?;
+        helper.run(paused, paused_sync, self)?;
+
+        Ok(())
+    }
+}
+
+impl EpollHelperHandler for ScsiEpollHandler {
+    fn handle_event(
+        &mut self,
+        _helper: &mut EpollHelper,
+        event: &epoll::Event,
+    ) -> result::Result<(), EpollHelperError> {
+        let ev_type = event.data as u16;
+        match ev_type {
+            CONTROL_QUEUE_EVENT => {
+                self.control_queue_evt.read().map_err(|e| {
+                    EpollHelperError::HandleEvent(anyhow!("Failed to get queue event: {:?}", e))
+                })?;
+                let result = self.process_control_queue();
+                self.handle_result(CONTROL_QUEUE, result)?;
+            }
+            REQUEST_QUEUE_EVENT => {
+                self.request_queue_evt.read().map_err(|e| {
+                    EpollHelperError::HandleEvent(anyhow!("Failed to get queue event: {:?}", e))
+                })?;
+                let result = self.process_request_queue();
+                self.handle_result(REQUEST_QUEUE, result)?;
+            }
+            _ => {
+                return Err(EpollHelperError::HandleEvent(anyhow!(
+                    "Unexpected event: {}",
+                    ev_type
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Versionize)]
+pub struct ScsiState {
+    pub avail_features: u64,
+    pub acked_features: u64,
+    pub config: VirtioScsiConfig,
+}
+
+impl VersionMapped for ScsiState {}
+
+/// Virtio SCSI controller.
+pub struct Scsi {
+    common: VirtioCommon,
+    id: String,
+    config: VirtioScsiConfig,
+    luns: Arc<Mutex<BTreeMap<u16, Lun>>>,
+    seccomp_action: SeccompAction,
+    exit_evt: EventFd,
+}
+
+impl Scsi {
+    /// Create a new virtio-scsi controller exposing the given logical units.
+    pub fn new(
+        id: String,
+        luns: Vec<ScsiLun>,
+        iommu: bool,
+        seccomp_action: SeccompAction,
+        exit_evt: EventFd,
+        state: Option<ScsiState>,
+    ) -> io::Result<Scsi> {
+        let luns = luns
+            .into_iter()
+            .map(|lun| {
+                let unit = match lun.backend {
+                    ScsiLunBackend::Disk { file, readonly } => Lun::Emulated(EmulatedLun::new(
+                        UnitKind::Disk,
+                        Some(file),
+                        readonly,
+                        lun.serial,
+                    )?),
+                    ScsiLunBackend::Cdrom(file) => {
+                        Lun::Emulated(EmulatedLun::new(UnitKind::Cdrom, file, true, lun.serial)?)
+                    }
+                    ScsiLunBackend::Passthrough(file) => {
+                        Lun::Passthrough(PassthroughLun::new(file)?)
+                    }
+                };
+                Ok((lun.lun, unit))
+            })
+            .collect::<io::Result<BTreeMap<u16, Lun>>>()?;
+
+        let (avail_features, acked_features, config, paused) = if let Some(state) = state {
+            info!("Restoring virtio-scsi {}", id);
+            (
+                state.avail_features,
+                state.acked_features,
+                state.config,
+                true,
+            )
+        } else {
+            let mut avail_features = 1u64 << VIRTIO_F_VERSION_1;
+
+            if iommu {
+                avail_features |= 1u64 << VIRTIO_F_IOMMU_PLATFORM;
+            }
+
+            let config = VirtioScsiConfig {
+                num_queues: 1,
+                seg_max: QUEUE_SIZE as u32 - 2,
+                max_sectors: MAX_SECTORS,
+                cmd_per_lun: 128,
+                event_info_size: 16,
+                sense_size: DEFAULT_SENSE_SIZE,
+                cdb_size: DEFAULT_CDB_SIZE,
+                max_channel: 0,
+                max_target: 0,
+                max_lun: SCSI_MAX_LUN as u32,
+            };
+
+            (avail_features, 0, config, false)
+        };
+
+        Ok(Scsi {
+            common: VirtioCommon {
+                device_type: VirtioDeviceType::Scsi as u32,
+                queue_sizes: QUEUE_SIZES.to_vec(),
+                paused_sync: Some(Arc::new(Barrier::new(2))),
+                avail_features,
+                acked_features,
+                min_queues: 3,
+                paused: Arc::new(AtomicBool::new(paused)),
+                ..Default::default()
+            },
+            id,
+            config,
+            luns: Arc::new(Mutex::new(luns)),
+            seccomp_action,
+            exit_evt,
+        })
+    }
+
+    /// Inserts a new medium in a CD-ROM drive, or ejects the current one when
+    /// `file` is None. The guest is told about the change through a unit
+    /// attention.
+    pub fn change_media(&self, lun: u16, file: Option<File>) -> result::Result<(), MediaError> {
+        match self.luns.lock().unwrap().get_mut(&lun) {
+            Some(Lun::Emulated(unit)) => unit.change_media(file),
+            Some(Lun::Passthrough(_)) => Err(MediaError::NotRemovable),
+            None => Err(MediaError::NoSuchLun(lun)),
+        }
+    }
+
+    fn state(&self) -> ScsiState {
+        ScsiState {
+            avail_features: self.common.avail_features,
+            acked_features: self.common.acked_features,
+            config: self.config,
+        }
+    }
+
+    #[cfg(fuzzing)]
+    pub fn wait_for_epoll_threads(&mut self) {
+        self.common.wait_for_epoll_threads();
+    }
+}
+
+impl Drop for Scsi {
+    fn drop(&mut self) {
+        if let Some(kill_evt) = self.common.kill_evt.take() {
+            // Ignore the result because there is nothing we can do about it.
+            let _ = kill_evt.write(1);
+        }
+        self.common.wait_for_epoll_threads();
+    }
+}
+
+impl VirtioDevice for Scsi {
+    fn device_type(&self) -> u32 {
+        self.common.device_type
+    }
+
+    fn queue_max_sizes(&self) -> &[u16] {
+        &self.common.queue_sizes
+    }
+
+    fn features(&self) -> u64 {
+        self.common.avail_features
+    }
+
+    fn ack_features(&mut self, value: u64) {
+        self.common.ack_features(value)
+    }
+
+    fn read_config(&self, offset: u64, data: &mut [u8]) {
+        self.read_config_from_slice(self.config.as_slice(), offset, data);
+    }
+
+    fn write_config(&mut self, offset: u64, data: &[u8]) {
+        // Only the sense_size and cdb_size fields are writable.
+        if offset < CONFIG_WRITABLE_OFFSET
+            || offset + data.len() as u64 > CONFIG_WRITABLE_OFFSET + CONFIG_WRITABLE_SIZE as u64
+        {
+            error!(
+                "Attempt to write to read-only field: offset {:x} length {}",
+                offset,
+                data.len()
+            );
+            return;
+        }
+
+        let start = offset as usize;
+        self.config.as_mut_slice()[start..start + data.len()].copy_from_slice(data);
+        self.config.sense_size = cmp::min(self.config.sense_size, MAX_SENSE_SIZE);
+        self.config.cdb_size = cmp::min(self.config.cdb_size, MAX_CDB_SIZE);
+    }
+
+    fn activate(
+        &mut self,
+        mem: GuestMemoryAtomic<GuestMemoryMmap>,
+        interrupt_cb: Arc<dyn VirtioInterrupt>,
+        queues: Vec<(usize, Queue, EventFd)>,
+    ) -> ActivateResult {
+        self.common.activate(&queues, &interrupt_cb)?;
+        let (kill_evt, pause_evt) = self.common.dup_eventfds();
+
+        if self.config.cdb_size == 0 {
+            error!("Invalid virtio-scsi CDB size");
+            return Err(ActivateError::BadActivate);
+        }
+
+        let mut virtqueues = Vec::new();
+        let mut queue_evts = Vec::new();
+        for (_, queue, queue_evt) in queues {
+            virtqueues.push(queue);
+            queue_evts.push(queue_evt);
+        }
+        // The event queue isn't used, as media changes are reported through
+        // unit attentions and logical units can't be hotplugged.
+        let request_queue_evt = queue_evts.remove(REQUEST_QUEUE);
+        let control_queue_evt = queue_evts.remove(CONTROL_QUEUE);
+
+        let mut handler = ScsiEpollHandler {
+            mem,
+            queues: virtqueues,
+            interrupt_cb,
+            control_queue_evt,
+            request_queue_evt,
+            kill_evt,
+            pause_evt,
+            access_platform: self.common.access_platform.clone(),
+            luns: self.luns.clone(),
+            sense_size: self.config.sense_size as usize,
+            cdb_size: self.config.cdb_size as usize,
+        };
+
+        let paused = self.common.paused.clone();
+        let paused_sync = self.common.paused_sync.clone();
+        let mut epoll_threads = Vec::new();
+        spawn_virtio_thread(
+            &self.id,
+            &self.seccomp_action,
+            Thread::VirtioScsi,
+            &mut epoll_threads,
+            &self.exit_evt,
+            move || handler.run(paused, paused_sync.unwrap()),
+        )?;
+
+        self.common.epoll_threads = Some(epoll_threads);
+
+        event!("virtio-device", "activated", "id", &self.id);
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
+        let result = self.common.reset();
+        self.config.sense_size = DEFAULT_SENSE_SIZE;
+        self.config.cdb_size = DEFAULT_CDB_SIZE;
+        event!("virtio-device", "reset", "id", &self.id);
+        result
+    }
+
+    fn set_access_platform(&mut self, access_platform: Arc<dyn AccessPlatform>) {
+        self.common.set_access_platform(access_platform)
+    }
+}
+
+impl Pausable for Scsi {
+    fn pause(&mut self) -> result::Result<(), MigratableError> {
+        self.common.pause()
+    }
+
+    fn resume(&mut self) -> result::Result<(), MigratableError> {
+        self.common.resume()
+    }
+}
+
+impl Snapshottable for Scsi {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn snapshot(&mut self) -> std::result::Result<Snapshot, MigratableError> {
+        Snapshot::new_from_versioned_state(&self.state())
+    }
+}
+
+impl Transportable for Scsi {}
+impl Migratable for Scsi {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lun_addressing() {
+        assert_eq!(decode_lun(&[1, 0, 0x40, 0x05, 0, 0, 0, 0]), Some(5));
+        assert_eq!(decode_lun(&[1, 0, 0x41, 0x2c, 0, 0, 0, 0]), Some(300));
+        // Only the first target exists.
+        assert_eq!(decode_lun(&[1, 1, 0x40, 0x05, 0, 0, 0, 0]), None);
+        assert_eq!(decode_lun(&[0, 0, 0, 0, 0, 0, 0, 0]), None);
+
+        assert_eq!(encode_lun(5), [0, 5, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(encode_lun(300), [0x41, 0x2c, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_missing_lun() {
+        let mut inquiry = [0u8; 6];
+        inquiry[0] = INQUIRY;
+        inquiry[4] = 36;
+        let result = missing_lun(&inquiry);
+        assert_eq!(result.status, STATUS_GOOD);
+        assert_eq!(result.data[0], PERIPHERAL_NOT_CONNECTED);
+
+        let result = missing_lun(&[0u8; 6]);
+        assert_eq!(result.status, STATUS_CHECK_CONDITION);
+        assert_eq!(result.sense, Sense::LUN_NOT_SUPPORTED.to_fixed_format());
+    }
+}
@@ -0,0 +1,170 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Logical units passing the SCSI commands through to a host device, either a
+//! SCSI generic node (`/dev/sgN`) or a block device, using the SG_IO ioctl.
+
+use super::emulated::cdb_len;
+use super::{CommandResult, STATUS_GOOD};
+use std::cmp;
+use std::fs::File;
+use std::io;
+use std::os::raw::{c_int, c_uint, c_ulong, c_void};
+use std::os::unix::io::AsRawFd;
+use std::ptr::null_mut;
+
+// Got from include/scsi/sg.h
+const SG_IO: c_ulong = 0x2285;
+const SG_GET_VERSION_NUM: c_ulong = 0x2282;
+const SG_DXFER_NONE: c_int = -1;
+const SG_DXFER_TO_DEV: c_int = -2;
+const SG_DXFER_FROM_DEV: c_int = -3;
+const SG_INFO_OK_MASK: c_uint = 0x1;
+const SG_INFO_OK: c_uint = 0x0;
+
+// Time given to the host device to complete a command, in milliseconds.
+const COMMAND_TIMEOUT_MS: c_uint = 30_000;
+const MAX_SENSE_LEN: usize = 252;
+
+#[repr(C)]
+struct SgIoHdr {
+    interface_id: c_int,
+    dxfer_direction: c_int,
+    cmd_len: u8,
+    mx_sb_len: u8,
+    iovec_count: u16,
+    dxfer_len: c_uint,
+    dxferp: *mut c_void,
+    cmdp: *const u8,
+    sbp: *mut u8,
+    timeout: c_uint,
+    flags: c_uint,
+    pack_id: c_int,
+    usr_ptr: *mut c_void,
+    status: u8,
+    masked_status: u8,
+    msg_status: u8,
+    sb_len_wr: u8,
+    host_status: u16,
+    driver_status: u16,
+    resid: c_int,
+    duration: c_uint,
+    info: c_uint,
+}
+
+pub(super) struct PassthroughLun {
+    file: File,
+}
+
+impl PassthroughLun {
+    /// Checks that the host device understands the SG_IO ioctl.
+    pub fn new(file: File) -> io::Result<Self> {
+        let mut version: c_int = 0;
+        // SAFETY: the ioctl only writes an integer to the given pointer.
+        let ret = unsafe { libc::ioctl(file.as_raw_fd(), SG_GET_VERSION_NUM as _, &mut version) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(PassthroughLun { file })
+    }
+
+    /// Runs the command on the host device, reading at most `data_in_len`
+    /// bytes from it. Bidirectional commands aren't supported.
+    pub fn execute(
+        &mut self,
+        cdb: &[u8],
+        data_out: &[u8],
+        data_in_len: usize,
+    ) -> io::Result<CommandResult> {
+        if !data_out.is_empty() && data_in_len != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "bidirectional commands aren't supported",
+            ));
+        }
+
+        // The guest hands over buffers of the negotiated CDB size, which
+        // may be larger than the command itself.
+        let cmd_len = match cdb_len(cdb[0]) {
+            0 => cdb.len(),
+            len => cmp::min(len, cdb.len()),
+        };
+
+        let mut data_in = vec![0u8; data_in_len];
+        let mut sense = vec![0u8; MAX_SENSE_LEN];
+        let (direction, dxferp, dxfer_len) = if !data_out.is_empty() {
+            (
+                SG_DXFER_TO_DEV,
+                data_out.as_ptr() as *mut c_void,
+                data_out.len(),
+            )
+        } else if data_in_len != 0 {
+            (
+                SG_DXFER_FROM_DEV,
+                data_in.as_mut_ptr() as *mut c_void,
+                data_in_len,
+            )
+        } else {
+            (SG_DXFER_NONE, null_mut(), 0)
+        };
+
+        let mut hdr = SgIoHdr {
+            interface_id: 'S' as c_int,
+            dxfer_direction: direction,
+            cmd_len: cmd_len as u8,
+            mx_sb_len: sense.len() as u8,
+            iovec_count: 0,
+            dxfer_len: dxfer_len as c_uint,
+            dxferp,
+            cmdp: cdb.as_ptr(),
+            sbp: sense.as_mut_ptr(),
+            timeout: COMMAND_TIMEOUT_MS,
+            flags: 0,
+            pack_id: 0,
+            usr_ptr: null_mut(),
+            status: 0,
+            masked_status: 0,
+            msg_status: 0,
+            sb_len_wr: 0,
+            host_status: 0,
+            driver_status: 0,
+            resid: 0,
+            duration: 0,
+            info: 0,
+        };
+
+        // SAFETY: the header points to buffers living until the ioctl
+        // returns, each of them being as large as advertised.
+        let ret = unsafe { libc::ioctl(self.file.as_raw_fd(), SG_IO as _, &mut hdr) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Failures which aren't reported through the SCSI status, such as a
+        // lost connection to the device.
+        if hdr.info & SG_INFO_OK_MASK != SG_INFO_OK && hdr.status == STATUS_GOOD {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!(
+                    "host status {:#x}, driver status {:#x}",
+                    hdr.host_status, hdr.driver_status
+                ),
+            ));
+        }
+
+        if direction == SG_DXFER_FROM_DEV {
+            data_in.truncate(data_in_len.saturating_sub(hdr.resid.max(0) as usize));
+        } else {
+            data_in.clear();
+        }
+        sense.truncate(hdr.sb_len_wr as usize);
+
+        Ok(CommandResult {
+            status: hdr.status,
+            sense,
+            data: data_in,
+        })
+    }
+}
@@ -4,6 +4,8 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+#[cfg(feature = "alsa")]
+use seccompiler::SeccompCmpOp::MaskedEq;
 use seccompiler::{
     BpfProgram, Error, SeccompAction, SeccompCmpArgLen as ArgLen, SeccompCmpOp::Eq,
     SeccompCondition as Cond, SeccompFilter, SeccompRule,
@@ -13,13 +15,19 @@ use std::convert::TryInto;
 pub enum Thread {
     VirtioBalloon,
     VirtioBlock,
+    VirtioChannels,
     VirtioConsole,
+    VirtioCrypto,
+    VirtioGpu,
+    VirtioInput,
     VirtioIommu,
     VirtioMem,
     VirtioNet,
     VirtioNetCtl,
     VirtioPmem,
     VirtioRng,
+    VirtioScsi,
+    VirtioSound,
     VirtioVhostBlock,
     VirtioVhostFs,
     VirtioVhostNet,
@@ -57,6 +65,20 @@ const VFIO_IOMMU_UNMAP_DMA: u64 = 0x3b72;
 // See include/uapi/linux/if_tun.h in the kernel code.
 const TUNSETOFFLOAD: u64 = 0x4004_54d0;
 
+// See include/uapi/linux/fs.h in the kernel code.
+const BLKDISCARD: u64 = 0x1277;
+const BLKZEROOUT: u64 = 0x127f;
+
+// See include/scsi/sg.h in the kernel code.
+const SG_IO: u64 = 0x2285;
+
+// See include/uapi/sound/asound.h in the kernel code, all the PCM ioctls
+// issued by alsa-lib are of type 'A'.
+#[cfg(feature = "alsa")]
+const SNDRV_PCM_IOCTL_MASK: u64 = 0xff00;
+#[cfg(feature = "alsa")]
+const SNDRV_PCM_IOCTL_TYPE: u64 = 0x4100;
+
 fn create_virtio_console_ioctl_seccomp_rule() -> Vec<SeccompRule> {
     or![and![Cond::new(1, ArgLen::Dword, Eq, TIOCGWINSZ).unwrap()]]
 }
@@ -79,6 +101,13 @@ fn virtio_balloon_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
     vec![(libc::SYS_fallocate, vec![])]
 }
 
+fn create_virtio_block_ioctl_seccomp_rule() -> Vec<SeccompRule> {
+    or![
+        and![Cond::new(1, ArgLen::Dword, Eq, BLKDISCARD).unwrap()],
+        and![Cond::new(1, ArgLen::Dword, Eq, BLKZEROOUT).unwrap()],
+    ]
+}
+
 fn virtio_block_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
     vec![
         (libc::SYS_fallocate, vec![]),
@@ -87,6 +116,7 @@ fn virtio_block_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
         (libc::SYS_ftruncate, vec![]),
         (libc::SYS_getrandom, vec![]),
         (libc::SYS_io_uring_enter, vec![]),
+        (libc::SYS_ioctl, create_virtio_block_ioctl_seccomp_rule()),
         (libc::SYS_lseek, vec![]),
         (libc::SYS_prctl, vec![]),
         (libc::SYS_pread64, vec![]),
@@ -99,6 +129,17 @@ fn virtio_block_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
     ]
 }
 
+fn virtio_channels_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
+    vec![
+        (libc::SYS_accept4, vec![]),
+        (libc::SYS_fcntl, vec![]),
+        (libc::SYS_ioctl, create_virtio_gpu_ioctl_seccomp_rule()),
+        (libc::SYS_prctl, vec![]),
+        (libc::SYS_sched_getaffinity, vec![]),
+        (libc::SYS_set_robust_list, vec![]),
+    ]
+}
+
 fn virtio_console_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
     vec![
         (libc::SYS_ioctl, create_virtio_console_ioctl_seccomp_rule()),
@@ -108,6 +149,48 @@ fn virtio_console_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
     ]
 }
 
+fn create_virtio_crypto_socket_seccomp_rule() -> Vec<SeccompRule> {
+    or![and![
+        Cond::new(0, ArgLen::Dword, Eq, libc::AF_ALG as u64).unwrap()
+    ]]
+}
+
+fn virtio_crypto_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
+    vec![
+        (libc::SYS_accept4, vec![]),
+        (libc::SYS_bind, vec![]),
+        (libc::SYS_getrandom, vec![]),
+        (libc::SYS_prctl, vec![]),
+        (libc::SYS_sched_getaffinity, vec![]),
+        (libc::SYS_sendmsg, vec![]),
+        (libc::SYS_set_robust_list, vec![]),
+        (libc::SYS_setsockopt, vec![]),
+        (libc::SYS_socket, create_virtio_crypto_socket_seccomp_rule()),
+    ]
+}
+
+fn create_virtio_gpu_ioctl_seccomp_rule() -> Vec<SeccompRule> {
+    or![and![Cond::new(1, ArgLen::Dword, Eq, FIONBIO).unwrap()]]
+}
+
+fn virtio_gpu_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
+    vec![
+        (libc::SYS_accept4, vec![]),
+        (libc::SYS_ioctl, create_virtio_gpu_ioctl_seccomp_rule()),
+        (libc::SYS_prctl, vec![]),
+        (libc::SYS_sched_getaffinity, vec![]),
+        (libc::SYS_set_robust_list, vec![]),
+    ]
+}
+
+fn virtio_input_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
+    vec![
+        (libc::SYS_prctl, vec![]),
+        (libc::SYS_sched_getaffinity, vec![]),
+        (libc::SYS_set_robust_list, vec![]),
+    ]
+}
+
 fn virtio_iommu_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
     vec![(libc::SYS_ioctl, create_virtio_iommu_ioctl_seccomp_rule())]
 }
@@ -138,7 +221,7 @@ fn virtio_net_ctl_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
 }
 
 fn virtio_pmem_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
-    vec![(libc::SYS_fsync, vec![])]
+    vec![(libc::SYS_fdatasync, vec![]), (libc::SYS_fsync, vec![])]
 }
 
 fn virtio_rng_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
@@ -149,9 +232,49 @@ fn virtio_rng_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
     ]
 }
 
+fn create_virtio_scsi_ioctl_seccomp_rule() -> Vec<SeccompRule> {
+    or![and![Cond::new(1, ArgLen::Dword, Eq, SG_IO).unwrap()]]
+}
+
+fn virtio_scsi_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
+    vec![
+        (libc::SYS_fdatasync, vec![]),
+        (libc::SYS_fsync, vec![]),
+        (libc::SYS_ioctl, create_virtio_scsi_ioctl_seccomp_rule()),
+        (libc::SYS_prctl, vec![]),
+        (libc::SYS_pread64, vec![]),
+        (libc::SYS_pwrite64, vec![]),
+        (libc::SYS_sched_getaffinity, vec![]),
+        (libc::SYS_set_robust_list, vec![]),
+    ]
+}
+
+fn virtio_sound_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
+    #[allow(unused_mut)]
+    let mut rules = vec![
+        (libc::SYS_prctl, vec![]),
+        (libc::SYS_sched_getaffinity, vec![]),
+        (libc::SYS_set_robust_list, vec![]),
+        (libc::SYS_timerfd_settime, vec![]),
+    ];
+    #[cfg(feature = "alsa")]
+    rules.push((
+        libc::SYS_ioctl,
+        or![and![Cond::new(
+            1,
+            ArgLen::Dword,
+            MaskedEq(SNDRV_PCM_IOCTL_MASK),
+            SNDRV_PCM_IOCTL_TYPE
+        )
+        .unwrap()]],
+    ));
+    rules
+}
+
 fn virtio_vhost_fs_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
     vec![
         (libc::SYS_connect, vec![]),
+        (libc::SYS_msync, vec![]),
         (libc::SYS_nanosleep, vec![]),
         (libc::SYS_pread64, vec![]),
         (libc::SYS_pwrite64, vec![]),
@@ -214,13 +337,19 @@ fn get_seccomp_rules(thread_type: Thread) -> Vec<(i64, Vec<SeccompRule>)> {
     let mut rules = match thread_type {
         Thread::VirtioBalloon => virtio_balloon_thread_rules(),
         Thread::VirtioBlock => virtio_block_thread_rules(),
+        Thread::VirtioChannels => virtio_channels_thread_rules(),
         Thread::VirtioConsole => virtio_console_thread_rules(),
+        Thread::VirtioCrypto => virtio_crypto_thread_rules(),
+        Thread::VirtioGpu => virtio_gpu_thread_rules(),
+        Thread::VirtioInput => virtio_input_thread_rules(),
         Thread::VirtioIommu => virtio_iommu_thread_rules(),
         Thread::VirtioMem => virtio_mem_thread_rules(),
         Thread::VirtioNet => virtio_net_thread_rules(),
         Thread::VirtioNetCtl => virtio_net_ctl_thread_rules(),
         Thread::VirtioPmem => virtio_pmem_thread_rules(),
         Thread::VirtioRng => virtio_rng_thread_rules(),
+        Thread::VirtioScsi => virtio_scsi_thread_rules(),
+        Thread::VirtioSound => virtio_sound_thread_rules(),
         Thread::VirtioVhostBlock => virtio_vhost_block_thread_rules(),
         Thread::VirtioVhostFs => virtio_vhost_fs_thread_rules(),
         Thread::VirtioVhostNet => virtio_vhost_net_thread_rules(),
@@ -0,0 +1,1002 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Virtio sound device, exposing one playback and one capture PCM stream.
+//!
+//! Both streams carry interleaved signed 16 bits little-endian stereo frames
+//! at 48 kHz, exchanged with the host through a backend per stream.
+
+use super::{
+    ActivateError, ActivateResult, EpollHelper, EpollHelperError, EpollHelperHandler, VirtioCommon,
+    VirtioDevice, VirtioDeviceType, EPOLL_HELPER_EVENT_LAST, VIRTIO_F_IOMMU_PLATFORM,
+    VIRTIO_F_VERSION_1,
+};
+use crate::regions::{self, parse_chain, Regions};
+use crate::seccomp_filters::Thread;
+use crate::sound_backend::PcmBackend;
+use crate::thread_helper::spawn_virtio_thread;
+use crate::GuestMemoryMmap;
+use crate::{VirtioInterrupt, VirtioInterruptType};
+use anyhow::anyhow;
+use seccompiler::SeccompAction;
+use std::cmp;
+use std::collections::VecDeque;
+use std::io;
+use std::mem::size_of;
+use std::os::unix::io::AsRawFd;
+use std::result;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Barrier, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+use virtio_queue::{Queue, QueueT};
+use vm_memory::{ByteValued, GuestAddressSpace, GuestMemoryAtomic};
+use vm_migration::VersionMapped;
+use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
+use vm_virtio::AccessPlatform;
+use vmm_sys_util::eventfd::EventFd;
+use vmm_sys_util::timerfd::TimerFd;
+
+const QUEUE_SIZE: u16 = 256;
+const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE; 4];
+
+const CONTROL_QUEUE: usize = 0;
+const TX_QUEUE: usize = 2;
+const RX_QUEUE: usize = 3;
+
+// New descriptors are pending on the control queue.
+const CONTROL_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 1;
+// New descriptors are pending on the transmit queue.
+const TX_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 2;
+// New descriptors are pending on the receive queue.
+const RX_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 3;
+// A period of the playback stream elapsed.
+const PLAYBACK_TIMER_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 4;
+// A period of the capture stream elapsed.
+const CAPTURE_TIMER_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 5;
+
+const PLAYBACK_STREAM: usize = 0;
+const CAPTURE_STREAM: usize = 1;
+const NUM_STREAMS: usize = 2;
+
+// Control requests are small, apart from the information queries whose
+// answer is written to the device writable part.
+const MAX_CONTROL_REQUEST_SIZE: usize = 64;
+
+const CHANNELS: u8 = 2;
+const FRAME_RATE: u64 = 48000;
+const FRAME_SIZE: u32 = CHANNELS as u32 * 2;
+
+// Got from include/uapi/linux/virtio_snd.h
+const VIRTIO_SND_R_JACK_INFO: u32 = 1;
+const VIRTIO_SND_R_PCM_INFO: u32 = 0x0100;
+const VIRTIO_SND_R_PCM_SET_PARAMS: u32 = 0x0101;
+const VIRTIO_SND_R_PCM_PREPARE: u32 = 0x0102;
+const VIRTIO_SND_R_PCM_RELEASE: u32 = 0x0103;
+const VIRTIO_SND_R_PCM_START: u32 = 0x0104;
+const VIRTIO_SND_R_PCM_STOP: u32 = 0x0105;
+const VIRTIO_SND_R_CHMAP_INFO: u32 = 0x0200;
+
+const VIRTIO_SND_S_OK: u32 = 0x8000;
+const VIRTIO_SND_S_BAD_MSG: u32 = 0x8001;
+const VIRTIO_SND_S_NOT_SUPP: u32 = 0x8002;
+const VIRTIO_SND_S_IO_ERR: u32 = 0x8003;
+
+const VIRTIO_SND_D_OUTPUT: u8 = 0;
+const VIRTIO_SND_D_INPUT: u8 = 1;
+
+const VIRTIO_SND_PCM_FMT_S16: u8 = 5;
+const VIRTIO_SND_PCM_RATE_48000: u8 = 7;
+
+#[derive(Error, Debug)]
+enum Error {
+    #[error("Descriptor chain too short")]
+    DescriptorChainTooShort,
+    #[error("Failed to read from guest memory: {0}")]
+    GuestMemoryRead(vm_memory::guest_memory::Error),
+    #[error("Failed to write to guest memory: {0}")]
+    GuestMemoryWrite(vm_memory::guest_memory::Error),
+    #[error("Failed adding used index: {0}")]
+    QueueAddUsed(virtio_queue::Error),
+    #[error("Failed to signal used queue: {0}")]
+    SignalUsedQueue(io::Error),
+    #[error("Failed to read timer: {0}")]
+    TimerRead(vmm_sys_util::errno::Error),
+    #[error("Failed to set timer: {0}")]
+    TimerSet(vmm_sys_util::errno::Error),
+}
+
+impl From<regions::Error> for Error {
+    fn from(e: regions::Error) -> Self {
+        match e {
+            regions::Error::DescriptorChainTooShort => Error::DescriptorChainTooShort,
+            regions::Error::GuestMemoryRead(e) => Error::GuestMemoryRead(e),
+            regions::Error::GuestMemoryWrite(e) => Error::GuestMemoryWrite(e),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Versionize)]
+#[allow(dead_code)]
+pub struct VirtioSoundConfig {
+    jacks: u32,
+    streams: u32,
+    chmaps: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+#[allow(dead_code)]
+struct VirtioSndQueryInfo {
+    code: u32,
+    start_id: u32,
+    count: u32,
+    size: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+#[allow(dead_code)]
+struct VirtioSndPcmInfo {
+    hda_fn_nid: u32,
+    features: u32,
+    formats: u64,
+    rates: u64,
+    direction: u8,
+    channels_min: u8,
+    channels_max: u8,
+    padding: [u8; 5],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+#[allow(dead_code)]
+struct VirtioSndPcmHdr {
+    code: u32,
+    stream_id: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+#[allow(dead_code)]
+struct VirtioSndPcmSetParams {
+    hdr: VirtioSndPcmHdr,
+    buffer_bytes: u32,
+    period_bytes: u32,
+    features: u32,
+    channels: u8,
+    format: u8,
+    rate: u8,
+    padding: u8,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+#[allow(dead_code)]
+struct VirtioSndPcmXfer {
+    stream_id: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+#[allow(dead_code)]
+struct VirtioSndPcmStatus {
+    status: u32,
+    latency_bytes: u32,
+}
+
+// SAFETY: the following structures only have data and no implicit padding.
+unsafe impl ByteValued for VirtioSoundConfig {}
+// SAFETY: see above
+unsafe impl ByteValued for VirtioSndQueryInfo {}
+// SAFETY: see above
+unsafe impl ByteValued for VirtioSndPcmInfo {}
+// SAFETY: see above
+unsafe impl ByteValued for VirtioSndPcmHdr {}
+// SAFETY: see above
+unsafe impl ByteValued for VirtioSndPcmSetParams {}
+// SAFETY: see above
+unsafe impl ByteValued for VirtioSndPcmXfer {}
+// SAFETY: see above
+unsafe impl ByteValued for VirtioSndPcmStatus {}
+
+// Reads a structure from a request, which is not guaranteed to be aligned.
+fn read_request<T: ByteValued + Default>(request: &[u8]) -> Option<T> {
+    let mut obj = T::default();
+    let len = obj.as_slice().len();
+    obj.as_mut_slice().copy_from_slice(request.get(..len)?);
+    Some(obj)
+}
+
+fn status_response(status: u32) -> Vec<u8> {
+    status.to_le_bytes().to_vec()
+}
+
+// Answers a query for the `items` the device has, each reported on the size
+// the driver expects.
+fn query_info<T: ByteValued>(request: &[u8], items: &[T]) -> Vec<u8> {
+    let query = match read_request::<VirtioSndQueryInfo>(request) {
+        Some(query) => query,
+        None => return status_response(VIRTIO_SND_S_BAD_MSG),
+    };
+    let end = query.start_id as u64 + query.count as u64;
+    if end > items.len() as u64 || (query.size as usize) < size_of::<T>() {
+        return status_response(VIRTIO_SND_S_BAD_MSG);
+    }
+
+    let mut response = status_response(VIRTIO_SND_S_OK);
+    for item in &items[query.start_id as usize..end as usize] {
+        let start = response.len();
+        response.extend_from_slice(item.as_slice());
+        response.resize(start + query.size as usize, 0);
+    }
+
+    response
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum StreamState {
+    Idle,
+    ParamsSet,
+    Prepared,
+    Running,
+    Stopped,
+}
+
+// I/O message queued by the guest, whose audio data is transferred as the
+// periods of the stream elapse.
+struct PcmBuffer {
+    head_index: u16,
+    // Whether the device writes audio data to the buffer
+    capture: bool,
+    readable: Regions,
+    writable: Regions,
+    // Offset of the audio data in the readable or writable regions
+    data_offset: usize,
+    data_len: usize,
+    transferred: usize,
+}
+
+// Backends are shared with the device, which hands them over again when
+// reactivated after a reset.
+type SharedPcmBackend = Arc<Mutex<Box<dyn PcmBackend>>>;
+
+struct PcmStream {
+    direction: u8,
+    // Without backend, the stream plays to nowhere or captures silence
+    backend: Option<SharedPcmBackend>,
+    state: StreamState,
+    buffer_bytes: u32,
+    period_bytes: u32,
+    timer: TimerFd,
+    buffers: VecDeque<PcmBuffer>,
+}
+
+impl PcmStream {
+    fn new(direction: u8, backend: Option<SharedPcmBackend>) -> io::Result<Self> {
+        let timer = TimerFd::new()?;
+        // The timer is read after being reported by epoll, but may have been
+        // disarmed in between, when the stream is stopped.
+        // SAFETY: FFI calls with a valid fd.
+        let ret = unsafe {
+            let fd = timer.as_raw_fd();
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK)
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(PcmStream {
+            direction,
+            backend,
+            state: StreamState::Idle,
+            buffer_bytes: 0,
+            period_bytes: 0,
+            timer,
+            buffers: VecDeque::new(),
+        })
+    }
+
+    fn queue_index(&self) -> usize {
+        if self.direction == VIRTIO_SND_D_OUTPUT {
+            TX_QUEUE
+        } else {
+            RX_QUEUE
+        }
+    }
+
+    fn info(&self) -> VirtioSndPcmInfo {
+        VirtioSndPcmInfo {
+            formats: 1 << VIRTIO_SND_PCM_FMT_S16,
+            rates: 1 << VIRTIO_SND_PCM_RATE_48000,
+            direction: self.direction,
+            channels_min: CHANNELS,
+            channels_max: CHANNELS,
+            ..Default::default()
+        }
+    }
+
+    fn period(&self) -> Duration {
+        Duration::from_nanos(
+            self.period_bytes as u64 * 1_000_000_000 / (FRAME_RATE * FRAME_SIZE as u64),
+        )
+    }
+}
+
+struct SoundEpollHandler {
+    mem: GuestMemoryAtomic<GuestMemoryMmap>,
+    queues: Vec<Queue>,
+    interrupt_cb: Arc<dyn VirtioInterrupt>,
+    control_queue_evt: EventFd,
+    tx_queue_evt: EventFd,
+    rx_queue_evt: EventFd,
+    kill_evt: EventFd,
+    pause_evt: EventFd,
+    access_platform: Option<Arc<dyn AccessPlatform>>,
+    streams: Vec<PcmStream>,
+}
+
+impl SoundEpollHandler {
+    fn process_control_queue(&mut self) -> result::Result<bool, Error> {
+        let mut used_descs = false;
+        while let Some(mut desc_chain) =
+            self.queues[CONTROL_QUEUE].pop_descriptor_chain(self.mem.memory())
+        {
+            let (readable, writable) = parse_chain(&mut desc_chain, self.access_platform.as_ref());
+            let mut request = vec![0; cmp::min(readable.len(), MAX_CONTROL_REQUEST_SIZE)];
+            readable.read(desc_chain.memory(), 0, &mut request)?;
+
+            let response = self.handle_control_request(&request)?;
+            let len = cmp::min(response.len(), writable.len());
+            writable.write(desc_chain.memory(), 0, &response[..len])?;
+
+            self.queues[CONTROL_QUEUE]
+                .add_used(desc_chain.memory(), desc_chain.head_index(), len as u32)
+                .map_err(Error::QueueAddUsed)?;
+            used_descs = true;
+        }
+
+        Ok(used_descs)
+    }
+
+    fn handle_control_request(&mut self, request: &[u8]) -> result::Result<Vec<u8>, Error> {
+        let code = match read_request::<u32>(request) {
+            Some(code) => code,
+            None => return Ok(status_response(VIRTIO_SND_S_BAD_MSG)),
+        };
+
+        match code {
+            // The device has neither jacks nor channel maps to report.
+            VIRTIO_SND_R_JACK_INFO | VIRTIO_SND_R_CHMAP_INFO => Ok(status_response(
+                match read_request::<VirtioSndQueryInfo>(request) {
+                    Some(query) if query.count == 0 => VIRTIO_SND_S_OK,
+                    _ => VIRTIO_SND_S_BAD_MSG,
+                },
+            )),
+            VIRTIO_SND_R_PCM_INFO => {
+                let infos: Vec<VirtioSndPcmInfo> =
+                    self.streams.iter().map(|stream| stream.info()).collect();
+                Ok(query_info(request, &infos))
+            }
+            VIRTIO_SND_R_PCM_SET_PARAMS => Ok(status_response(
+                match read_request::<VirtioSndPcmSetParams>(request) {
+                    Some(params) => self.set_params(&params),
+                    None => VIRTIO_SND_S_BAD_MSG,
+                },
+            )),
+            VIRTIO_SND_R_PCM_PREPARE
+            | VIRTIO_SND_R_PCM_RELEASE
+            | VIRTIO_SND_R_PCM_START
+            | VIRTIO_SND_R_PCM_STOP => {
+                let hdr = match read_request::<VirtioSndPcmHdr>(request) {
+                    Some(hdr) if (hdr.stream_id as usize) < self.streams.len() => hdr,
+                    _ => return Ok(status_response(VIRTIO_SND_S_BAD_MSG)),
+                };
+                Ok(status_response(
+                    self.change_state(code, hdr.stream_id as usize)?,
+                ))
+            }
+            _ => Ok(status_response(VIRTIO_SND_S_NOT_SUPP)),
+        }
+    }
+
+    fn set_params(&mut self, params: &VirtioSndPcmSetParams) -> u32 {
+        let stream = match self.streams.get_mut(params.hdr.stream_id as usize) {
+            Some(stream) => stream,
+            None => return VIRTIO_SND_S_BAD_MSG,
+        };
+        if matches!(stream.state, StreamState::Running | StreamState::Stopped) {
+            return VIRTIO_SND_S_BAD_MSG;
+        }
+        if params.features != 0
+            || params.channels != CHANNELS
+            || params.format != VIRTIO_SND_PCM_FMT_S16
+            || params.rate != VIRTIO_SND_PCM_RATE_48000
+        {
+            return VIRTIO_SND_S_NOT_SUPP;
+        }
+        if params.period_bytes == 0
+            || params.period_bytes % FRAME_SIZE != 0
+            || params.buffer_bytes < params.period_bytes
+        {
+            return VIRTIO_SND_S_BAD_MSG;
+        }
+
+        stream.buffer_bytes = params.buffer_bytes;
+        stream.period_bytes = params.period_bytes;
+        stream.state = StreamState::ParamsSet;
+
+        VIRTIO_SND_S_OK
+    }
+
+    fn change_state(&mut self, code: u32, stream_id: usize) -> result::Result<u32, Error> {
+        let stream = &mut self.streams[stream_id];
+        let (allowed, next) = match code {
+            VIRTIO_SND_R_PCM_PREPARE => (
+                matches!(stream.state, StreamState::ParamsSet | StreamState::Prepared),
+                StreamState::Prepared,
+            ),
+            VIRTIO_SND_R_PCM_RELEASE => (
+                matches!(stream.state, StreamState::Prepared | StreamState::Stopped),
+                StreamState::ParamsSet,
+            ),
+            VIRTIO_SND_R_PCM_START => (
+                matches!(stream.state, StreamState::Prepared | StreamState::Stopped),
+                StreamState::Running,
+            ),
+            _ => (stream.state == StreamState::Running, StreamState::Stopped),
+        };
+        if !allowed {
+            return Ok(VIRTIO_SND_S_BAD_MSG);
+        }
+
+        let mut released_queue = None;
+        match next {
+            StreamState::Running => {
+                if let Some(backend) = stream.backend.as_ref() {
+                    if let Err(e) = backend.lock().unwrap().start() {
+                        error!("Failed starting sound stream: {}", e);
+                        return Ok(VIRTIO_SND_S_IO_ERR);
+                    }
+                }
+                let period = stream.period();
+                stream
+                    .timer
+                    .reset(period, Some(period))
+                    .map_err(Error::TimerSet)?;
+            }
+            StreamState::Stopped => {
+                stream.timer.clear().map_err(Error::TimerSet)?;
+                if let Some(backend) = stream.backend.as_ref() {
+                    backend.lock().unwrap().stop();
+                }
+            }
+            StreamState::ParamsSet => {
+                // The pending I/O messages are returned to the driver when
+                // the stream is released.
+                let mem = self.mem.memory();
+                let queue_index = stream.queue_index();
+                while let Some(buffer) = stream.buffers.pop_front() {
+                    complete_buffer(
+                        &mut self.queues[queue_index],
+                        &mem,
+                        &buffer,
+                        VIRTIO_SND_S_OK,
+                    )?;
+                    released_queue = Some(queue_index);
+                }
+            }
+            _ => {}
+        }
+        self.streams[stream_id].state = next;
+
+        if let Some(queue_index) = released_queue {
+            self.signal_used_queue(queue_index)?;
+        }
+
+        Ok(VIRTIO_SND_S_OK)
+    }
+
+    fn process_io_queue(&mut self, queue_index: usize) -> result::Result<bool, Error> {
+        let direction = if queue_index == TX_QUEUE {
+            VIRTIO_SND_D_OUTPUT
+        } else {
+            VIRTIO_SND_D_INPUT
+        };
+
+        let mut used_descs = false;
+        while let Some(mut desc_chain) =
+            self.queues[queue_index].pop_descriptor_chain(self.mem.memory())
+        {
+            let (readable, writable) = parse_chain(&mut desc_chain, self.access_platform.as_ref());
+            let mut xfer = VirtioSndPcmXfer::default();
+            readable.read(desc_chain.memory(), 0, xfer.as_mut_slice())?;
+            let status_len = size_of::<VirtioSndPcmStatus>();
+            if writable.len() < status_len {
+                return Err(Error::DescriptorChainTooShort);
+            }
+
+            let (data_offset, data_len) = if direction == VIRTIO_SND_D_OUTPUT {
+                (
+                    size_of::<VirtioSndPcmXfer>(),
+                    readable.len() - size_of::<VirtioSndPcmXfer>(),
+                )
+            } else {
+                (0, writable.len() - status_len)
+            };
+            let buffer = PcmBuffer {
+                head_index: desc_chain.head_index(),
+                capture: direction == VIRTIO_SND_D_INPUT,
+                readable,
+                writable,
+                data_offset,
+                data_len,
+                transferred: 0,
+            };
+
+            match self.streams.get_mut(xfer.stream_id as usize) {
+                Some(stream)
+                    if stream.direction == direction
+                        && matches!(
+                            stream.state,
+                            StreamState::Prepared | StreamState::Running | StreamState::Stopped
+                        ) =>
+                {
+                    stream.buffers.push_back(buffer);
+                }
+                _ => {
+                    complete_buffer(
+                        &mut self.queues[queue_index],
+                        desc_chain.memory(),
+                        &buffer,
+                        VIRTIO_SND_S_BAD_MSG,
+                    )?;
+                    used_descs = true;
+                }
+            }
+        }
+
+        Ok(used_descs)
+    }
+
+    // Transfers the audio data of the periods which elapsed since the last
+    // call, completing the I/O messages which have been fully transferred.
+    fn process_period(&mut self, stream_id: usize) -> result::Result<bool, Error> {
+        let stream = &mut self.streams[stream_id];
+        let periods = match stream.timer.wait() {
+            Ok(periods) => periods,
+            Err(e) if e.errno() == libc::EAGAIN => return Ok(false),
+            Err(e) => return Err(Error::TimerRead(e)),
+        };
+        if stream.state != StreamState::Running {
+            return Ok(false);
+        }
+
+        // Don't try to catch up with more than the buffer of the stream,
+        // which can happen after the device has been paused.
+        let periods = cmp::min(periods, (stream.buffer_bytes / stream.period_bytes) as u64);
+        let mut budget = periods as usize * stream.period_bytes as usize;
+        let mem = self.mem.memory();
+        let queue_index = stream.queue_index();
+        let mut used_descs = false;
+        while budget > 0 {
+            let buffer = match stream.buffers.front_mut() {
+                Some(buffer) => buffer,
+                None => break,
+            };
+
+            let count = cmp::min(budget, buffer.data_len - buffer.transferred);
+            let mut data = vec![0; count];
+            let offset = buffer.data_offset + buffer.transferred;
+            if stream.direction == VIRTIO_SND_D_OUTPUT {
+                buffer.readable.read(&mem, offset, &mut data)?;
+                if let Some(backend) = stream.backend.as_ref() {
+                    backend.lock().unwrap().write(&data);
+                }
+            } else {
+                if let Some(backend) = stream.backend.as_ref() {
+                    backend.lock().unwrap().read(&mut data);
+                }
+                buffer.writable.write(&mem, offset, &data)?;
+            }
+            buffer.transferred += count;
+            budget -= count;
+
+            if buffer.transferred == buffer.data_len {
+                // Checked when the buffer was queued
+                let buffer = stream.buffers.pop_front().unwrap();
+                complete_buffer(
+                    &mut self.queues[queue_index],
+                    &mem,
+                    &buffer,
+                    VIRTIO_SND_S_OK,
+                )?;
+                used_descs = true;
+            }
+        }
+
+        Ok(used_descs)
+    }
+
+    fn signal_used_queue(&self, queue_index: usize) -> result::Result<(), Error> {
+        self.interrupt_cb
+            .trigger(VirtioInterruptType::Queue(queue_index as u16))
+            .map_err(|e| {
+                error!("Failed to signal used queue: {:?}", e);
+                Error::SignalUsedQueue(e)
+            })
+    }
+
+    fn handle_result(
+        &self,
+        queue_index: usize,
+        result: result::Result<bool, Error>,
+    ) -> result::Result<(), EpollHelperError> {
+        let needs_notification = result.map_err(|e| {
+            EpollHelperError::HandleEvent(anyhow!("Failed to process queue : {:?}", e))
+        })?;
+        if needs_notification {
+            self.signal_used_queue(queue_index).map_err(|e| {
+                EpollHelperError::HandleEvent(anyhow!("Failed to signal used queue: {:?}", e))
+            })?;
+        }
+
+        Ok(())
+    }
+
+    fn run(
+        &mut self,
+        paused: Arc<AtomicBool>,
+        paused_sync: Arc<Barrier>,
+    ) -> result::Result<(), EpollHelperError> {
+        let mut helper = EpollHelper::new(&self.kill_evt, &self.pause_evt)?;
+        helper.add_event(self.control_queue_evt.as_raw_fd(), CONTROL_QUEUE_EVENT)?;
+        helper.add_event(self.tx_queue_evt.as_raw_fd(), TX_QUEUE_EVENT)?;
+        helper.add_event(self.rx_queue_evt.as_raw_fd(), RX_QUEUE_EVENT)?;
+        helper.add_event(
+            self.streams[PLAYBACK_STREAM].timer.as_raw_fd(),
+            PLAYBACK_TIMER_EVENT,
+        )?;
+        helper.add_event(
+            self.streams[CAPTURE_STREAM].timer.as_raw_fd(),
+            CAPTURE_TIMER_EVENT,
+        )?;
+        helper.run(paused, paused_sync, self)?;
+
+        Ok(())
+    }
+}
+
+fn complete_buffer(
+    queue: &mut Queue,
+    mem: &GuestMemoryMmap,
+    buffer: &PcmBuffer,
+    status: u32,
+) -> result::Result<(), Error> {
+    let status = VirtioSndPcmStatus {
+        status,
+        latency_bytes: 0,
+    };
+    let status_offset = buffer.writable.len() - size_of::<VirtioSndPcmStatus>();
+    buffer
+        .writable
+        .write(mem, status_offset, status.as_slice())?;
+
+    // Only the captured data and the status are written by the device.
+    let len = if buffer.capture {
+        buffer.transferred + size_of::<VirtioSndPcmStatus>()
+    } else {
+        size_of::<VirtioSndPcmStatus>()
+    };
+    queue
+        .add_used(mem, buffer.head_index, len as u32)
+        .map_err(Error::QueueAddUsed)
+}
+
+impl EpollHelperHandler for SoundEpollHandler {
+    fn handle_event(
+        &mut self,
+        _helper: &mut EpollHelper,
+        event: &epoll::Event,
+    ) -> result::Result<(), EpollHelperError> {
+        let ev_type = event.data as u16;
+        match ev_type {
+            CONTROL_QUEUE_EVENT => {
+                self.control_queue_evt.read().map_err(|e| {
+                    EpollHelperError::HandleEvent(anyhow!("Failed to get queue event: {:?}", e))
+                })?;
+                let result = self.process_control_queue();
+                self.handle_result(CONTROL_QUEUE, result)?;
+            }
+            TX_QUEUE_EVENT => {
+                self.tx_queue_evt.read().map_err(|e| {
+                    EpollHelperError::HandleEvent(anyhow!("Failed to get queue event: {:?}", e))
+                })?;
+                let result = self.process_io_queue(TX_QUEUE);
+                self.handle_result(TX_QUEUE, result)?;
+            }
+            RX_QUEUE_EVENT => {
+                self.rx_queue_evt.read().map_err(|e| {
+                    EpollHelperError::HandleEvent(anyhow!("Failed to get queue event: {:?}", e))
+                })?;
+                let result = self.process_io_queue(RX_QUEUE);
+                self.handle_result(RX_QUEUE, result)?;
+            }
+            PLAYBACK_TIMER_EVENT => {
+                let result = self.process_period(PLAYBACK_STREAM);
+                self.handle_result(TX_QUEUE, result)?;
+            }
+            CAPTURE_TIMER_EVENT => {
+                let result = self.process_period(CAPTURE_STREAM);
+                self.handle_result(RX_QUEUE, result)?;
+            }
+            _ => {
+                return Err(EpollHelperError::HandleEvent(anyhow!(
+                    "Unexpected event: {}",
+                    ev_type
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Versionize)]
+pub struct SoundState {
+    pub avail_features: u64,
+    pub acked_features: u64,
+    pub config: VirtioSoundConfig,
+}
+
+impl VersionMapped for SoundState {}
+
+/// Virtio device exposing a sound card to the guest.
+pub struct Sound {
+    common: VirtioCommon,
+    id: String,
+    config: VirtioSoundConfig,
+    playback: Option<SharedPcmBackend>,
+    capture: Option<SharedPcmBackend>,
+    seccomp_action: SeccompAction,
+    exit_evt: EventFd,
+}
+
+impl Sound {
+    /// Create a new virtio-sound device, playing to the `playback` backend
+    /// and capturing from the `capture` one. A stream without backend plays
+    /// to nowhere, or captures silence.
+    pub fn new(
+        id: String,
+        playback: Option<Box<dyn PcmBackend>>,
+        capture: Option<Box<dyn PcmBackend>>,
+        iommu: bool,
+        seccomp_action: SeccompAction,
+        exit_evt: EventFd,
+        state: Option<SoundState>,
+    ) -> Sound {
+        let (avail_features, acked_features, config, paused) = if let Some(state) = state {
+            info!("Restoring virtio-sound {}", id);
+            (
+                state.avail_features,
+                state.acked_features,
+                state.config,
+                true,
+            )
+        } else {
+            let mut avail_features = 1u64 << VIRTIO_F_VERSION_1;
+
+            if iommu {
+                avail_features |= 1u64 << VIRTIO_F_IOMMU_PLATFORM;
+            }
+
+            let config = VirtioSoundConfig {
+                jacks: 0,
+                streams: NUM_STREAMS as u32,
+                chmaps: 0,
+            };
+
+            (avail_features, 0, config, false)
+        };
+
+        Sound {
+            common: VirtioCommon {
+                device_type: VirtioDeviceType::Sound as u32,
+                queue_sizes: QUEUE_SIZES.to_vec(),
+                paused_sync: Some(Arc::new(Barrier::new(2))),
+                avail_features,
+                acked_features,
+                min_queues: 4,
+                paused: Arc::new(AtomicBool::new(paused)),
+                ..Default::default()
+            },
+            id,
+            config,
+            playback: playback.map(|backend| Arc::new(Mutex::new(backend))),
+            capture: capture.map(|backend| Arc::new(Mutex::new(backend))),
+            seccomp_action,
+            exit_evt,
+        }
+    }
+
+    fn state(&self) -> SoundState {
+        SoundState {
+            avail_features: self.common.avail_features,
+            acked_features: self.common.acked_features,
+            config: self.config,
+        }
+    }
+
+    #[cfg(fuzzing)]
+    pub fn wait_for_epoll_threads(&mut self) {
+        self.common.wait_for_epoll_threads();
+    }
+}
+
+impl Drop for Sound {
+    fn drop(&mut self) {
+        if let Some(kill_evt) = self.common.kill_evt.take() {
+            // Ignore the result because there is nothing we can do about it.
+            let _ = kill_evt.write(1);
+        }
+        self.common.wait_for_epoll_threads();
+    }
+}
+
+impl VirtioDevice for Sound {
+    fn device_type(&self) -> u32 {
+        self.common.device_type
+    }
+
+    fn queue_max_sizes(&self) -> &[u16] {
+        &self.common.queue_sizes
+    }
+
+    fn features(&self) -> u64 {
+        self.common.avail_features
+    }
+
+    fn ack_features(&mut self, value: u64) {
+        self.common.ack_features(value)
+    }
+
+    fn read_config(&self, offset: u64, data: &mut [u8]) {
+        self.read_config_from_slice(self.config.as_slice(), offset, data);
+    }
+
+    fn activate(
+        &mut self,
+        mem: GuestMemoryAtomic<GuestMemoryMmap>,
+        interrupt_cb: Arc<dyn VirtioInterrupt>,
+        queues: Vec<(usize, Queue, EventFd)>,
+    ) -> ActivateResult {
+        self.common.activate(&queues, &interrupt_cb)?;
+        let (kill_evt, pause_evt) = self.common.dup_eventfds();
+
+        let streams = [
+            (VIRTIO_SND_D_OUTPUT, &self.playback),
+            (VIRTIO_SND_D_INPUT, &self.capture),
+        ]
+        .iter()
+        .map(|(direction, backend)| PcmStream::new(*direction, (*backend).clone()))
+        .collect::<io::Result<Vec<PcmStream>>>()
+        .map_err(|e| {
+            error!("failed creating sound stream timer: {}", e);
+            ActivateError::BadActivate
+        })?;
+
+        let mut virtqueues = Vec::new();
+        let mut queue_evts = Vec::new();
+        for (_, queue, queue_evt) in queues {
+            virtqueues.push(queue);
+            queue_evts.push(queue_evt);
+        }
+        // The event queue isn't used, as the device doesn't report any event.
+        let rx_queue_evt = queue_evts.remove(RX_QUEUE);
+        let tx_queue_evt = queue_evts.remove(TX_QUEUE);
+        let control_queue_evt = queue_evts.remove(CONTROL_QUEUE);
+
+        let mut handler = SoundEpollHandler {
+            mem,
+            queues: virtqueues,
+            interrupt_cb,
+            control_queue_evt,
+            tx_queue_evt,
+            rx_queue_evt,
+            kill_evt,
+            pause_evt,
+            access_platform: self.common.access_platform.clone(),
+            streams,
+        };
+
+        let paused = self.common.paused.clone();
+        let paused_sync = self.common.paused_sync.clone();
+        let mut epoll_threads = Vec::new();
+        spawn_virtio_thread(
+            &self.id,
+            &self.seccomp_action,
+            Thread::VirtioSound,
+            &mut epoll_threads,
+            &self.exit_evt,
+            move || handler.run(paused, paused_sync.unwrap()),
+        )?;
+
+        self.common.epoll_threads = Some(epoll_threads);
+
+        event!("virtio-device", "activated", "id", &self.id);
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
+        let result = self.common.reset();
+        event!("virtio-device", "reset", "id", &self.id);
+        result
+    }
+
+    fn set_access_platform(&mut self, access_platform: Arc<dyn AccessPlatform>) {
+        self.common.set_access_platform(access_platform)
+    }
+}
+
+impl Pausable for Sound {
+    fn pause(&mut self) -> result::Result<(), MigratableError> {
+        self.common.pause()
+    }
+
+    fn resume(&mut self) -> result::Result<(), MigratableError> {
+        self.common.resume()
+    }
+}
+
+impl Snapshottable for Sound {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn snapshot(&mut self) -> std::result::Result<Snapshot, MigratableError> {
+        Snapshot::new_from_versioned_state(&self.state())
+    }
+}
+
+impl Transportable for Sound {}
+impl Migratable for Sound {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_info() {
+        let stream = PcmStream::new(VIRTIO_SND_D_OUTPUT, None).unwrap();
+        let infos = [stream.info()];
+        let info_size = size_of::<VirtioSndPcmInfo>() as u32;
+
+        let query = VirtioSndQueryInfo {
+            code: VIRTIO_SND_R_PCM_INFO,
+            start_id: 0,
+            count: 1,
+            size: info_size + 8,
+        };
+        let response = query_info(query.as_slice(), &infos);
+        assert_eq!(response.len(), 4 + info_size as usize + 8);
+        assert_eq!(response[..4], VIRTIO_SND_S_OK.to_le_bytes());
+        assert_eq!(response[4..4 + info_size as usize], *infos[0].as_slice());
+
+        for (start_id, count, size) in [(1, 1, info_size), (0, 2, info_size), (0, 1, 4)] {
+            let query = VirtioSndQueryInfo {
+                code: VIRTIO_SND_R_PCM_INFO,
+                start_id,
+                count,
+                size,
+            };
+            assert_eq!(
+                query_info(query.as_slice(), &infos),
+                status_response(VIRTIO_SND_S_BAD_MSG)
+            );
+        }
+    }
+}
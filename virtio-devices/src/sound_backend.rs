@@ -0,0 +1,312 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Host side of the virtio-sound PCM streams.
+//!
+//! The frames exchanged with a backend are interleaved signed 16 bits
+//! little-endian stereo frames at 48 kHz. Backends are driven by the device
+//! at the stream rate, and must never block: the frames which can't be
+//! played are dropped, and silence is recorded when no frames are available.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+// Largest write to a pipe guaranteed to be atomic.
+const PIPE_BUF: usize = 4096;
+
+/// Host side of a PCM stream, either playing or capturing frames.
+pub trait PcmBackend: Send {
+    /// Called when the guest starts the stream.
+    fn start(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called when the guest stops or releases the stream.
+    fn stop(&mut self) {}
+
+    /// Plays the frames from `data`.
+    fn write(&mut self, data: &[u8]);
+
+    /// Fills `data` with captured frames.
+    fn read(&mut self, data: &mut [u8]);
+}
+
+/// Backend exchanging the frames through files, usually named pipes
+/// connected to the sound server of the host.
+pub struct PipeBackend {
+    file: File,
+}
+
+impl PipeBackend {
+    /// Backend playing the frames to the file at `path`.
+    pub fn playback(path: &Path) -> io::Result<Self> {
+        // Opening a named pipe for reading and writing doesn't wait for a
+        // reader to show up.
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)?;
+
+        Ok(PipeBackend { file })
+    }
+
+    /// Backend capturing the frames from the file at `path`.
+    pub fn capture(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)?;
+
+        Ok(PipeBackend { file })
+    }
+}
+
+impl PcmBackend for PipeBackend {
+    // Drops what doesn't fit in the pipe, as nobody is reading it.
+    fn write(&mut self, data: &[u8]) {
+        for chunk in data.chunks(PIPE_BUF) {
+            if let Err(e) = self.file.write_all(chunk) {
+                if e.kind() != io::ErrorKind::WouldBlock {
+                    warn!("Failed writing sound playback data: {}", e);
+                }
+                return;
+            }
+        }
+    }
+
+    // Completes with silence when nothing is available.
+    fn read(&mut self, data: &mut [u8]) {
+        let mut done = 0;
+        while done < data.len() {
+            match self.file.read(&mut data[done..]) {
+                Ok(0) => break,
+                Ok(count) => done += count,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    if e.kind() != io::ErrorKind::WouldBlock {
+                        warn!("Failed reading sound capture data: {}", e);
+                    }
+                    break;
+                }
+            }
+        }
+        data[done..].fill(0);
+    }
+}
+
+#[cfg(feature = "alsa")]
+pub use alsa::AlsaBackend;
+
+#[cfg(feature = "alsa")]
+mod alsa {
+    use super::PcmBackend;
+    use std::ffi::CString;
+    use std::io;
+    use std::os::raw::{c_char, c_int, c_long, c_uint, c_ulong, c_void};
+
+    // See include/pcm.h in the alsa-lib code.
+    const SND_PCM_STREAM_PLAYBACK: c_int = 0;
+    const SND_PCM_STREAM_CAPTURE: c_int = 1;
+    const SND_PCM_NONBLOCK: c_int = 1;
+    const SND_PCM_FORMAT_S16_LE: c_int = 2;
+    const SND_PCM_ACCESS_RW_INTERLEAVED: c_int = 3;
+
+    const CHANNELS: c_uint = 2;
+    const RATE: c_uint = 48_000;
+    const FRAME_SIZE: usize = 4;
+    // Latency requested to ALSA, in microseconds
+    const LATENCY_US: c_uint = 100_000;
+
+    #[allow(non_camel_case_types)]
+    enum snd_pcm_t {}
+
+    #[link(name = "asound")]
+    extern "C" {
+        fn snd_pcm_open(
+            pcm: *mut *mut snd_pcm_t,
+            name: *const c_char,
+            stream: c_int,
+            mode: c_int,
+        ) -> c_int;
+        fn snd_pcm_set_params(
+            pcm: *mut snd_pcm_t,
+            format: c_int,
+            access: c_int,
+            channels: c_uint,
+            rate: c_uint,
+            soft_resample: c_int,
+            latency: c_uint,
+        ) -> c_int;
+        fn snd_pcm_prepare(pcm: *mut snd_pcm_t) -> c_int;
+        fn snd_pcm_drop(pcm: *mut snd_pcm_t) -> c_int;
+        fn snd_pcm_recover(pcm: *mut snd_pcm_t, err: c_int, silent: c_int) -> c_int;
+        fn snd_pcm_writei(pcm: *mut snd_pcm_t, buffer: *const c_void, size: c_ulong) -> c_long;
+        fn snd_pcm_readi(pcm: *mut snd_pcm_t, buffer: *mut c_void, size: c_ulong) -> c_long;
+        fn snd_pcm_close(pcm: *mut snd_pcm_t) -> c_int;
+    }
+
+    fn alsa_result(ret: c_int) -> io::Result<()> {
+        if ret < 0 {
+            Err(io::Error::from_raw_os_error(-ret))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Backend playing or capturing the frames through an ALSA PCM device.
+    pub struct AlsaBackend {
+        pcm: *mut snd_pcm_t,
+    }
+
+    // SAFETY: the PCM handle is only used from one thread at a time.
+    unsafe impl Send for AlsaBackend {}
+
+    impl AlsaBackend {
+        // The device is opened right away, as opening it can involve
+        // system calls the virtio-sound thread isn't allowed to make.
+        fn open(device: &str, stream: c_int) -> io::Result<Self> {
+            let name =
+                CString::new(device).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            let mut pcm = std::ptr::null_mut();
+            // SAFETY: FFI call with a valid name and pointer to the handle
+            alsa_result(unsafe {
+                snd_pcm_open(&mut pcm, name.as_ptr(), stream, SND_PCM_NONBLOCK)
+            })?;
+            let backend = AlsaBackend { pcm };
+
+            // SAFETY: FFI call with a valid PCM handle
+            alsa_result(unsafe {
+                snd_pcm_set_params(
+                    backend.pcm,
+                    SND_PCM_FORMAT_S16_LE,
+                    SND_PCM_ACCESS_RW_INTERLEAVED,
+                    CHANNELS,
+                    RATE,
+                    1,
+                    LATENCY_US,
+                )
+            })?;
+
+            Ok(backend)
+        }
+
+        /// Backend playing the frames to the ALSA PCM `device`.
+        pub fn playback(device: &str) -> io::Result<Self> {
+            Self::open(device, SND_PCM_STREAM_PLAYBACK)
+        }
+
+        /// Backend capturing the frames from the ALSA PCM `device`.
+        pub fn capture(device: &str) -> io::Result<Self> {
+            Self::open(device, SND_PCM_STREAM_CAPTURE)
+        }
+
+        // Recovers from an underrun or overrun, returning whether the
+        // transfer can be retried.
+        fn recover(&mut self, err: c_long) -> bool {
+            if err == -(libc::EAGAIN as c_long) {
+                return false;
+            }
+            // SAFETY: FFI call with a valid PCM handle
+            if let Err(e) = alsa_result(unsafe { snd_pcm_recover(self.pcm, err as c_int, 1) }) {
+                warn!("Failed recovering ALSA PCM: {}", e);
+                return false;
+            }
+            true
+        }
+    }
+
+    impl PcmBackend for AlsaBackend {
+        fn start(&mut self) -> io::Result<()> {
+            // SAFETY: FFI call with a valid PCM handle
+            alsa_result(unsafe { snd_pcm_prepare(self.pcm) })
+        }
+
+        fn stop(&mut self) {
+            // SAFETY: FFI call with a valid PCM handle
+            if let Err(e) = alsa_result(unsafe { snd_pcm_drop(self.pcm) }) {
+                warn!("Failed stopping ALSA PCM: {}", e);
+            }
+        }
+
+        // Drops the frames which don't fit in the ALSA buffer.
+        fn write(&mut self, data: &[u8]) {
+            let mut done = 0;
+            while done < data.len() {
+                let frames = ((data.len() - done) / FRAME_SIZE) as c_ulong;
+                // SAFETY: FFI call with a valid PCM handle, the buffer
+                // holds the given number of frames
+                let ret = unsafe {
+                    snd_pcm_writei(self.pcm, data[done..].as_ptr() as *const c_void, frames)
+                };
+                if ret == 0 {
+                    return;
+                } else if ret < 0 {
+                    if !self.recover(ret) {
+                        return;
+                    }
+                } else {
+                    done += ret as usize * FRAME_SIZE;
+                }
+            }
+        }
+
+        // Completes with silence when not enough frames were captured.
+        fn read(&mut self, data: &mut [u8]) {
+            let mut done = 0;
+            while done < data.len() {
+                let frames = ((data.len() - done) / FRAME_SIZE) as c_ulong;
+                // SAFETY: FFI call with a valid PCM handle, the buffer has
+                // room for the given number of frames
+                let ret = unsafe {
+                    snd_pcm_readi(self.pcm, data[done..].as_mut_ptr() as *mut c_void, frames)
+                };
+                if ret == 0 {
+                    break;
+                } else if ret < 0 {
+                    if !self.recover(ret) {
+                        break;
+                    }
+                } else {
+                    done += ret as usize * FRAME_SIZE;
+                }
+            }
+            data[done..].fill(0);
+        }
+    }
+
+    impl Drop for AlsaBackend {
+        fn drop(&mut self) {
+            // SAFETY: FFI call with a valid PCM handle, not used afterwards
+            unsafe { snd_pcm_close(self.pcm) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vmm_sys_util::tempdir::TempDir;
+
+    #[test]
+    fn test_pipe_backend() {
+        let dir = TempDir::new_with_prefix("/tmp/ch").unwrap();
+        let path = dir.as_path().join("sound");
+        let mut playback = PipeBackend::playback(&path).unwrap();
+        let mut capture = PipeBackend::capture(&path).unwrap();
+
+        playback.write(&[1, 2, 3, 4]);
+        let mut data = [0xff; 8];
+        capture.read(&mut data);
+        assert_eq!(data, [1, 2, 3, 4, 0, 0, 0, 0]);
+
+        // Silence when nothing is available
+        let mut data = [0xff; 4];
+        capture.read(&mut data);
+        assert_eq!(data, [0; 4]);
+    }
+}
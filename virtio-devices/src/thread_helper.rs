@@ -10,6 +10,8 @@ use crate::{
 };
 use seccompiler::{apply_filter, SeccompAction};
 use std::{
+    io,
+    os::unix::thread::JoinHandleExt,
     panic::AssertUnwindSafe,
     thread::{self, JoinHandle},
 };
@@ -64,3 +66,35 @@ where
             ActivateError::ThreadSpawn(e)
         })
 }
+
+// Pin the thread onto the given host CPUs, letting the I/O work of several
+// queues be consolidated onto the same host CPUs.
+pub(crate) fn set_virtio_thread_affinity(
+    thread: &JoinHandle<()>,
+    host_cpus: &[usize],
+) -> Result<(), ActivateError> {
+    // SAFETY: all zeros is a valid pattern
+    let mut cpuset: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    // SAFETY: FFI call, trivially safe
+    unsafe { libc::CPU_ZERO(&mut cpuset) };
+    for host_cpu in host_cpus {
+        // SAFETY: FFI call, trivially safe
+        unsafe { libc::CPU_SET(*host_cpu, &mut cpuset) };
+    }
+
+    // SAFETY: FFI call with a valid thread and CPU set
+    let ret = unsafe {
+        libc::pthread_setaffinity_np(
+            thread.as_pthread_t(),
+            std::mem::size_of::<libc::cpu_set_t>(),
+            &cpuset,
+        )
+    };
+    if ret != 0 {
+        return Err(ActivateError::SetThreadAffinity(
+            io::Error::from_raw_os_error(ret),
+        ));
+    }
+
+    Ok(())
+}
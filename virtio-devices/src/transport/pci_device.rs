@@ -657,6 +657,12 @@ impl VirtioPciDevice {
         self.common_config.driver_status == DEVICE_INIT as u8
     }
 
+    /// Number of MSI-X interrupts injected on behalf of the device. Those
+    /// directly injected by a vhost-user backend are not accounted for.
+    pub fn interrupt_count(&self) -> Option<u64> {
+        self.interrupt_source_group.trigger_count()
+    }
+
     pub fn config_bar_addr(&self) -> u64 {
         self.configuration.get_bar_addr(self.settings_bar as usize)
     }
@@ -0,0 +1,213 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Offload of the receive and transmit queues of a virtio-net queue pair to
+//! the vhost-net module of the host kernel. The kernel reads the queues and
+//! the TAP interface directly, being kicked through the ioeventfd of each
+//! queue and notifying the guest through its irqfd.
+
+use crate::{GuestMemoryMmap, VirtioInterrupt, VirtioInterruptType};
+use net_util::Tap;
+use std::fs::OpenOptions;
+use std::io;
+use std::sync::Arc;
+use thiserror::Error;
+use vhost::net::VhostNet;
+use vhost::vhost_kern::net::Net as VhostKernNet;
+use vhost::{VhostBackend, VhostUserMemoryRegionInfo, VringConfigData};
+use virtio_bindings::virtio_config::{
+    VIRTIO_F_ANY_LAYOUT, VIRTIO_F_NOTIFY_ON_EMPTY, VIRTIO_F_VERSION_1,
+};
+use virtio_bindings::virtio_net::VIRTIO_NET_F_MRG_RXBUF;
+use virtio_bindings::virtio_ring::{VIRTIO_RING_F_EVENT_IDX, VIRTIO_RING_F_INDIRECT_DESC};
+use virtio_queue::{Descriptor, Queue, QueueT};
+use vm_memory::{Address, GuestMemory, GuestMemoryAtomic, GuestMemoryRegion};
+use vm_virtio::{AccessPlatform, Translatable};
+use vmm_sys_util::eventfd::EventFd;
+
+const VHOST_NET_PATH: &str = "/dev/vhost-net";
+
+// Features handled by vhost-net itself, the offloads being programmed on the
+// TAP interface instead.
+const VHOST_NET_FEATURES: u64 = 1 << VIRTIO_F_VERSION_1
+    | 1 << VIRTIO_RING_F_EVENT_IDX
+    | 1 << VIRTIO_RING_F_INDIRECT_DESC
+    | 1 << VIRTIO_NET_F_MRG_RXBUF
+    | 1 << VIRTIO_F_ANY_LAYOUT
+    | 1 << VIRTIO_F_NOTIFY_ON_EMPTY;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Failed to open /dev/vhost-net: {0}")]
+    Open(io::Error),
+    #[error("Failed to create vhost-net: {0}")]
+    CreateVhostNet(vhost::Error),
+    #[error("Missing irqfd for queue {0}")]
+    MissingIrqfd(usize),
+    #[error("Failed to set the TAP backend: {0}")]
+    SetBackend(vhost::Error),
+    #[error("Failed to set virtio features: {0}")]
+    SetFeatures(vhost::Error),
+    #[error("Failed to set memory table: {0}")]
+    SetMemTable(vhost::Error),
+    #[error("Failed to set owner: {0}")]
+    SetOwner(vhost::Error),
+    #[error("Failed to set vring address: {0}")]
+    SetVringAddr(vhost::Error),
+    #[error("Failed to set vring base: {0}")]
+    SetVringBase(vhost::Error),
+    #[error("Failed to set vring eventfd when buffer are used: {0}")]
+    SetVringCall(vhost::Error),
+    #[error("Failed to set vring eventfd when new descriptors are available: {0}")]
+    SetVringKick(vhost::Error),
+    #[error("Failed to set vring size: {0}")]
+    SetVringNum(vhost::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Checks that the vhost-net module is available to the VMM.
+pub fn probe() -> Result<()> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(VHOST_NET_PATH)
+        .map(|_| ())
+        .map_err(Error::Open)
+}
+
+// The kernel rejects the features it doesn't know about, such as the
+// offloads or the control queue the VMM takes care of.
+fn vhost_features(acked_features: u64) -> u64 {
+    acked_features & VHOST_NET_FEATURES
+}
+
+fn memory_regions(mem: &GuestMemoryMmap) -> Vec<VhostUserMemoryRegionInfo> {
+    mem.iter()
+        .map(|region| VhostUserMemoryRegionInfo {
+            guest_phys_addr: region.start_addr().raw_value(),
+            memory_size: region.len(),
+            userspace_addr: region.as_ptr() as u64,
+            mmap_offset: 0,
+            mmap_handle: -1,
+        })
+        .collect()
+}
+
+/// Queue pair processed by the host kernel.
+pub struct VhostNetQueuePair {
+    vhost: VhostKernNet<GuestMemoryAtomic<GuestMemoryMmap>>,
+    tap: Tap,
+}
+
+impl VhostNetQueuePair {
+    pub fn new(mem: GuestMemoryAtomic<GuestMemoryMmap>, tap: Tap) -> Result<Self> {
+        let vhost = VhostKernNet::new(mem).map_err(Error::CreateVhostNet)?;
+        vhost.set_owner().map_err(Error::SetOwner)?;
+
+        Ok(VhostNetQueuePair { vhost, tap })
+    }
+
+    /// Hands the queues over to the kernel. The queues are given with their
+    /// index in the device, the receive queue first.
+    pub fn activate(
+        &self,
+        mem: &GuestMemoryMmap,
+        acked_features: u64,
+        queues: [(usize, &Queue, &EventFd); 2],
+        interrupt_cb: &Arc<dyn VirtioInterrupt>,
+        access_platform: Option<&Arc<dyn AccessPlatform>>,
+    ) -> Result<()> {
+        self.vhost
+            .set_features(vhost_features(acked_features))
+            .map_err(Error::SetFeatures)?;
+        self.update_mem_table(mem)?;
+
+        for (vring_index, (queue_index, queue, queue_evt)) in queues.into_iter().enumerate() {
+            let queue_size = queue.size();
+            self.vhost
+                .set_vring_num(vring_index, queue_size)
+                .map_err(Error::SetVringNum)?;
+
+            let config_data = VringConfigData {
+                queue_max_size: queue.max_size(),
+                queue_size,
+                flags: 0u32,
+                desc_table_addr: queue.desc_table().translate_gpa(
+                    access_platform,
+                    queue_size as usize * std::mem::size_of::<Descriptor>(),
+                ),
+                used_ring_addr: queue
+                    .used_ring()
+                    .translate_gpa(access_platform, 4 + queue_size as usize * 8),
+                avail_ring_addr: queue
+                    .avail_ring()
+                    .translate_gpa(access_platform, 4 + queue_size as usize * 2),
+                log_addr: None,
+            };
+            self.vhost
+                .set_vring_addr(vring_index, &config_data)
+                .map_err(Error::SetVringAddr)?;
+            self.vhost
+                .set_vring_base(vring_index, queue.next_avail())
+                .map_err(Error::SetVringBase)?;
+
+            // Without an irqfd, the kernel would have no way to notify the
+            // guest.
+            let irqfd = interrupt_cb
+                .notifier(VirtioInterruptType::Queue(queue_index as u16))
+                .ok_or(Error::MissingIrqfd(queue_index))?;
+            self.vhost
+                .set_vring_call(vring_index, &irqfd)
+                .map_err(Error::SetVringCall)?;
+            self.vhost
+                .set_vring_kick(vring_index, queue_evt)
+                .map_err(Error::SetVringKick)?;
+        }
+
+        self.attach(true)
+    }
+
+    /// Starts or stops the processing of the queues by the kernel.
+    pub fn attach(&self, attach: bool) -> Result<()> {
+        let file = attach.then(|| self.tap.file());
+        for vring_index in 0..2 {
+            self.vhost
+                .set_backend(vring_index, file)
+                .map_err(Error::SetBackend)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn update_mem_table(&self, mem: &GuestMemoryMmap) -> Result<()> {
+        self.vhost
+            .set_mem_table(&memory_regions(mem))
+            .map_err(Error::SetMemTable)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use virtio_bindings::virtio_net::{
+        VIRTIO_NET_F_CSUM, VIRTIO_NET_F_CTRL_VQ, VIRTIO_NET_F_GUEST_TSO4, VIRTIO_NET_F_MAC,
+    };
+
+    #[test]
+    fn test_vhost_features() {
+        let acked_features = 1 << VIRTIO_F_VERSION_1
+            | 1 << VIRTIO_RING_F_EVENT_IDX
+            | 1 << VIRTIO_NET_F_MRG_RXBUF
+            | 1 << VIRTIO_NET_F_CSUM
+            | 1 << VIRTIO_NET_F_GUEST_TSO4
+            | 1 << VIRTIO_NET_F_MAC
+            | 1 << VIRTIO_NET_F_CTRL_VQ;
+        assert_eq!(
+            vhost_features(acked_features),
+            1 << VIRTIO_F_VERSION_1 | 1 << VIRTIO_RING_F_EVENT_IDX | 1 << VIRTIO_NET_F_MRG_RXBUF
+        );
+        assert_eq!(vhost_features(u64::MAX), VHOST_NET_FEATURES);
+    }
+}
@@ -10,7 +10,10 @@ use crate::{
     VirtioInterrupt, VIRTIO_F_IOMMU_PLATFORM, VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_VERSION_1,
 };
 use crate::{GuestMemoryMmap, GuestRegionMmap};
-use net_util::{build_net_config_space, CtrlQueue, MacAddr, VirtioNetConfig};
+use net_util::{
+    build_net_config_space, CtrlQueue, MacAddr, VirtioNetConfig, VIRTIO_NET_F_GUEST_USO4,
+    VIRTIO_NET_F_GUEST_USO6, VIRTIO_NET_F_HOST_USO,
+};
 use seccompiler::SeccompAction;
 use std::result;
 use std::sync::atomic::AtomicBool;
@@ -80,6 +83,7 @@ impl Net {
         state: Option<State>,
         offload_tso: bool,
         offload_ufo: bool,
+        offload_uso: bool,
         offload_csum: bool,
     ) -> Result<Net> {
         let mut num_queues = vu_cfg.num_queues;
@@ -149,6 +153,12 @@ impl Net {
                 if offload_ufo {
                     avail_features |= 1 << VIRTIO_NET_F_HOST_UFO | 1 << VIRTIO_NET_F_GUEST_UFO;
                 }
+
+                if offload_uso {
+                    avail_features |= 1 << VIRTIO_NET_F_HOST_USO
+                        | 1 << VIRTIO_NET_F_GUEST_USO4
+                        | 1 << VIRTIO_NET_F_GUEST_USO6;
+                }
             }
 
             let mut config = VirtioNetConfig::default();
@@ -315,7 +325,7 @@ impl VirtioDevice for Net {
                 mem: mem.clone(),
                 kill_evt,
                 pause_evt,
-                ctrl_q: CtrlQueue::new(Vec::new()),
+                ctrl_q: CtrlQueue::new(Vec::new(), None),
                 queue: ctrl_queue,
                 queue_evt: ctrl_queue_evt,
                 access_platform: None,
@@ -44,7 +44,7 @@ const TIMER_EXPIRED_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 2;
 // This needs to match what the driver is using.
 const WATCHDOG_TIMER_INTERVAL: i64 = 15;
 
-// Number of seconds since last ping to trigger reboot
+// Number of seconds since last ping to trigger the expiry action
 const WATCHDOG_TIMEOUT: u64 = WATCHDOG_TIMER_INTERVAL as u64 + 5;
 
 #[derive(Error, Debug)]
@@ -70,7 +70,7 @@ struct WatchdogEpollHandler {
     pause_evt: EventFd,
     timer: File,
     last_ping_time: Arc<Mutex<Option<Instant>>>,
-    reset_evt: EventFd,
+    expiry_evt: EventFd,
 }
 
 impl WatchdogEpollHandler {
@@ -166,12 +166,17 @@ impl EpollHelperHandler for WatchdogEpollHandler {
                     EpollHelperError::HandleEvent(anyhow!("Error reading from timer fd: {:}", e))
                 })?;
 
-                if let Some(last_ping_time) = self.last_ping_time.lock().unwrap().as_ref() {
-                    let now = Instant::now();
-                    let gap = now.duration_since(*last_ping_time).as_secs();
+                let mut last_ping_time = self.last_ping_time.lock().unwrap();
+                if let Some(gap) = last_ping_time.map(|t| t.elapsed().as_secs()) {
                     if gap > WATCHDOG_TIMEOUT {
                         error!("Watchdog triggered: {} seconds since last ping", gap);
-                        self.reset_evt.write(1).ok();
+                        // The watchdog stays disarmed until the next ping, so
+                        // that the expiry action is only taken once.
+                        last_ping_time.take();
+                        timerfd_setup(&self.timer, 0).map_err(|e| {
+                            EpollHelperError::HandleEvent(anyhow!("Error clearing timer: {:?}", e))
+                        })?;
+                        self.expiry_evt.write(1).ok();
                     }
                 }
             }
@@ -191,7 +196,7 @@ pub struct Watchdog {
     common: VirtioCommon,
     id: String,
     seccomp_action: SeccompAction,
-    reset_evt: EventFd,
+    expiry_evt: EventFd,
     last_ping_time: Arc<Mutex<Option<Instant>>>,
     timer: File,
     exit_evt: EventFd,
@@ -207,10 +212,11 @@ pub struct WatchdogState {
 impl VersionMapped for WatchdogState {}
 
 impl Watchdog {
-    /// Create a new virtio watchdog device that will reboot VM if the guest hangs
+    /// Create a new virtio watchdog device that will signal `expiry_evt` if
+    /// the guest hangs
     pub fn new(
         id: String,
-        reset_evt: EventFd,
+        expiry_evt: EventFd,
         seccomp_action: SeccompAction,
         exit_evt: EventFd,
         state: Option<WatchdogState>,
@@ -220,7 +226,7 @@ impl Watchdog {
             info!("Restoring virtio-watchdog {}", id);
 
             // When restoring enable the watchdog if it was previously enabled.
-            // We reset the timer to ensure that we don't unnecessarily reboot
+            // We reset the timer to ensure that we don't unnecessarily expire
             // due to the offline time.
             if state.enabled {
                 last_ping_time = Some(Instant::now());
@@ -251,7 +257,7 @@ impl Watchdog {
             },
             id,
             seccomp_action,
-            reset_evt,
+            expiry_evt,
             last_ping_time: Arc::new(Mutex::new(last_ping_time)),
             timer,
             exit_evt,
@@ -341,8 +347,8 @@ impl VirtioDevice for Watchdog {
         self.common.activate(&queues, &interrupt_cb)?;
         let (kill_evt, pause_evt) = self.common.dup_eventfds();
 
-        let reset_evt = self.reset_evt.try_clone().map_err(|e| {
-            error!("Failed to clone reset_evt eventfd: {}", e);
+        let expiry_evt = self.expiry_evt.try_clone().map_err(|e| {
+            error!("Failed to clone expiry_evt eventfd: {}", e);
             ActivateError::BadActivate
         })?;
 
@@ -362,7 +368,7 @@ impl VirtioDevice for Watchdog {
             pause_evt,
             timer,
             last_ping_time: self.last_ping_time.clone(),
-            reset_evt,
+            expiry_evt,
         };
 
         let paused = self.common.paused.clone();
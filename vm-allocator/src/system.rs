@@ -162,4 +162,14 @@ impl SystemAllocator {
     pub fn free_mmio_hole_addresses(&mut self, address: GuestAddress, size: GuestUsize) {
         self.mmio_hole_address_space.free(address, size)
     }
+
+    /// Start address of the MMIO address space in the 32 bits hole.
+    pub fn mmio_hole_base(&self) -> GuestAddress {
+        self.mmio_hole_address_space.base()
+    }
+
+    /// Last address of the MMIO address space in the 32 bits hole.
+    pub fn mmio_hole_end(&self) -> GuestAddress {
+        self.mmio_hole_address_space.end()
+    }
 }
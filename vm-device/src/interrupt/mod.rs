@@ -153,4 +153,12 @@ pub trait InterruptSourceGroup: Send + Sync {
         config: InterruptSourceConfig,
         masked: bool,
     ) -> Result<()>;
+
+    /// Returns the number of interrupts injected through trigger(), if the
+    /// group keeps track of it.
+    ///
+    /// Interrupts injected by writing to a notifier are not accounted for.
+    fn trigger_count(&self) -> Option<u64> {
+        None
+    }
 }
@@ -5,20 +5,55 @@
 
 use crate::protocol::MemoryRangeTable;
 use anyhow::anyhow;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use versionize::{VersionMap, Versionize};
 
 pub mod protocol;
 
-/// Global VMM version for versioning
-const MAJOR_VERSION: u16 = 30;
-const MINOR_VERSION: u16 = 0;
-const VMM_VERSION: u16 = MAJOR_VERSION << 12 | MINOR_VERSION & 0b1111;
+/// Version of the snapshot format, recorded along with the state of each
+/// component. It must be bumped whenever the serialized state of a
+/// component changes, so that the state saved by a previous release can
+/// still be restored through the component up-conversion hooks.
+pub const SNAPSHOT_VERSION: u16 = 2;
 
+// Version of the states saved before the snapshot version was recorded
+const LEGACY_SNAPSHOT_VERSION: u16 = 1;
+
+/// Up-conversion of a versioned state.
+///
+/// The version map describes the version of the state for each snapshot
+/// version, the fields added or removed along the way being tagged with
+/// the `#[version]` attribute, which also takes the semantic hooks
+/// converting the state from one version to the next.
 pub trait VersionMapped {
     fn version_map() -> VersionMap {
-        VersionMap::new()
+        snapshot_version_map()
+    }
+}
+
+/// Version map with one version for each snapshot version, all the types
+/// keeping their initial version.
+fn snapshot_version_map() -> VersionMap {
+    let mut version_map = VersionMap::new();
+    for _ in LEGACY_SNAPSHOT_VERSION..SNAPSHOT_VERSION {
+        version_map.new_version();
+    }
+    version_map
+}
+
+/// Up-conversion of a JSON state.
+///
+/// Added fields are better handled with `#[serde(default)]`, other changes
+/// are handled by converting the state saved with a given snapshot version
+/// to the layout of the next one.
+pub trait StateUpgrade {
+    fn upgrade_state(
+        _version: u16,
+        state: serde_json::Value,
+    ) -> Result<serde_json::Value, MigratableError> {
+        Ok(state)
     }
 }
 
@@ -79,16 +114,75 @@ pub trait Pausable {
 /// separate sections.
 /// Splitting a component migration data into different sections
 /// allows for easier and forward compatible extensions.
-#[derive(Clone, Default, Deserialize, Serialize)]
-pub struct SnapshotData(pub Vec<u8>);
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(from = "SnapshotDataFormat")]
+pub struct SnapshotData {
+    /// Snapshot version the state was saved with
+    pub version: u16,
+    /// Serialized state
+    pub state: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SnapshotDataFormat {
+    Versioned { version: u16, state: Vec<u8> },
+    Legacy(Vec<u8>),
+}
+
+impl From<SnapshotDataFormat> for SnapshotData {
+    fn from(format: SnapshotDataFormat) -> Self {
+        match format {
+            SnapshotDataFormat::Versioned { version, state } => SnapshotData { version, state },
+            SnapshotDataFormat::Legacy(state) => SnapshotData {
+                version: LEGACY_SNAPSHOT_VERSION,
+                state,
+            },
+        }
+    }
+}
+
+impl Default for SnapshotData {
+    fn default() -> Self {
+        SnapshotData::new(Vec::new())
+    }
+}
 
 impl SnapshotData {
+    /// Create from state serialized with the current snapshot version
+    pub fn new(state: Vec<u8>) -> Self {
+        SnapshotData {
+            version: SNAPSHOT_VERSION,
+            state,
+        }
+    }
+
+    fn check_version(&self) -> Result<(), MigratableError> {
+        if self.version > SNAPSHOT_VERSION {
+            return Err(MigratableError::Restore(anyhow!(
+                "Snapshot version {} is newer than the supported version {}",
+                self.version,
+                SNAPSHOT_VERSION
+            )));
+        }
+
+        Ok(())
+    }
+
     /// Generate the state data from the snapshot data
-    pub fn to_state<'a, T>(&'a self) -> Result<T, MigratableError>
+    pub fn to_state<T>(&self) -> Result<T, MigratableError>
     where
-        T: Deserialize<'a>,
+        T: DeserializeOwned + StateUpgrade,
     {
-        serde_json::from_slice(&self.0)
+        self.check_version()?;
+
+        let mut state: serde_json::Value = serde_json::from_slice(&self.state)
+            .map_err(|e| MigratableError::Restore(anyhow!("Error deserialising: {}", e)))?;
+        for version in self.version..SNAPSHOT_VERSION {
+            state = T::upgrade_state(version, state)?;
+        }
+
+        serde_json::from_value(state)
             .map_err(|e| MigratableError::Restore(anyhow!("Error deserialising: {}", e)))
     }
 
@@ -97,7 +191,9 @@ impl SnapshotData {
     where
         T: Versionize + VersionMapped,
     {
-        T::deserialize(&mut self.0.as_slice(), &T::version_map(), VMM_VERSION)
+        self.check_version()?;
+
+        T::deserialize(&mut self.state.as_slice(), &T::version_map(), self.version)
             .map_err(|e| MigratableError::Restore(anyhow!("Error deserialising: {}", e)))
     }
 
@@ -109,7 +205,7 @@ impl SnapshotData {
         let data = serde_json::to_vec(state)
             .map_err(|e| MigratableError::Snapshot(anyhow!("Error serialising: {}", e)))?;
 
-        Ok(SnapshotData(data))
+        Ok(SnapshotData::new(data))
     }
 
     /// Create from versioned state
@@ -119,10 +215,10 @@ impl SnapshotData {
     {
         let mut data = Vec::new();
         state
-            .serialize(&mut data, &T::version_map(), VMM_VERSION)
+            .serialize(&mut data, &T::version_map(), SNAPSHOT_VERSION)
             .map_err(|e| MigratableError::Snapshot(anyhow!("Error serialising: {}", e)))?;
 
-        Ok(SnapshotData(data))
+        Ok(SnapshotData::new(data))
     }
 }
 
@@ -179,9 +275,9 @@ impl Snapshot {
     }
 
     /// Generate the state data from the snapshot
-    pub fn to_state<'a, T>(&'a self) -> Result<T, MigratableError>
+    pub fn to_state<T>(&self) -> Result<T, MigratableError>
     where
-        T: Deserialize<'a>,
+        T: DeserializeOwned + StateUpgrade,
     {
         self.snapshot_data
             .as_ref()
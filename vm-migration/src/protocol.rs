@@ -4,8 +4,10 @@
 //
 
 use crate::{MigratableError, VersionMapped};
+use anyhow::anyhow;
 use serde::{Deserialize, Serialize};
 use std::io::{Read, Write};
+use std::str::FromStr;
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
 use vm_memory::ByteValued;
@@ -46,6 +48,59 @@ use vm_memory::ByteValued;
 // (n-1): Source -> Dest : send "complete command"
 // n: Dest -> Source: sends "ok response"
 //
+// "Postcopy version": (Fetching the remaining memory on demand)
+// 1..7: Same as the first version, with a single memory pass
+// 8: Source -> Dest : sends "state command" followed by state data, length
+//                     in command is length of state data
+// 9: Dest -> Source : sends "ok response"
+// 10: Source -> Dest : sends "postcopy command" followed by the table of u64
+//                     pairs (GPA, size) dirtied since the memory pass
+// 11: Dest -> Source : sends "ok response" once the missing memory has been
+//                     discarded and registered for userfaultfd
+// 12: Source -> Dest : send "complete command"
+// 13: Dest -> Source: sends "ok response" and resumes the VM
+// 14..(n-2): Dest -> Source : sends "memory command" followed by a table of
+//                     u64 pairs (GPA, size), either for a page the guest
+//                     faulted on or for the next chunk of missing memory
+// 15..(n-1): Source -> Dest : sends the memory described by the table
+// (n-1): Dest -> Source : send "complete command" once no memory is missing
+// n: Source -> Dest: sends "ok response"
+//
+// "Multiple streams version": (Transferring memory in parallel)
+// 1..5: Same as the first version
+// 6: Source -> Dest : sends "streams command" followed by the number of
+//                     additional connections as a u32, length in command is 4
+// 7: Dest -> Source : sends "ok response" and accepts the connections
+// 8: Source -> Dest : opens the additional connections
+// 9..(n-4): Source -> Dest : on each additional connection, sends "memory
+//                     command" followed by the table, the memory and the
+//                     CRC32C of the memory as a u32
+//           Dest -> Source : sends "ok response" on the same connection if
+//                     the checksum matches
+// (n-3): Source -> Dest : sends "complete command" on each additional
+//                     connection, the destination replies with "ok response"
+// (n-2)..n: Same as the first version, starting with the "state command"
+//
+// "Compressed version": (Compressing the memory sent)
+// 1..3: Same as the first version
+// 4: Source -> Dest : sends "compression command" followed by the algorithm
+//                     as a u32, length in command is 4
+// 5: Dest -> Source : sends "ok response" if the algorithm is supported,
+//                     "error response" otherwise, in which case the memory
+//                     is sent uncompressed
+// 6..n: Same as the other versions, except that the memory following a
+//                     table is split into chunks of at most 1MiB, each of
+//                     them sent as its compressed size as a u32 followed by
+//                     the compressed data
+//
+// "Clone version": (Sharing the memory of a paused VM copy-on-write)
+// 1..3: Same as the local version
+// 4: Source -> Dest : sends "clone command", length in command is 0
+// 5: Dest -> Source : sends "ok response"
+// 6..n: Same as the local version. The destination maps the memory it
+//                     receives privately so that its writes never reach the
+//                     source, which must stay paused.
+//
 // The destination can at any time send an "error response" to cancel
 // The source can at any time send an "abandon request" to cancel
 
@@ -60,6 +115,10 @@ pub enum Command {
     Complete,
     Abandon,
     MemoryFd,
+    Postcopy,
+    Streams,
+    Compression,
+    Clone,
 }
 
 impl Default for Command {
@@ -108,6 +167,22 @@ impl Request {
         Self::new(Command::MemoryFd, length)
     }
 
+    pub fn streams(length: u64) -> Self {
+        Self::new(Command::Streams, length)
+    }
+
+    pub fn postcopy(length: u64) -> Self {
+        Self::new(Command::Postcopy, length)
+    }
+
+    pub fn compression(length: u64) -> Self {
+        Self::new(Command::Compression, length)
+    }
+
+    pub fn clone_vm() -> Self {
+        Self::new(Command::Clone, 0)
+    }
+
     pub fn complete() -> Self {
         Self::new(Command::Complete, 0)
     }
@@ -138,6 +213,42 @@ impl Request {
     }
 }
 
+/// Algorithm compressing the memory sent during a migration.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Zstd = 1,
+    Lz4 = 2,
+}
+
+impl TryFrom<u32> for Compression {
+    type Error = MigratableError;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(Self::Zstd),
+            2 => Ok(Self::Lz4),
+            _ => Err(MigratableError::MigrateReceive(anyhow!(
+                "Unsupported compression algorithm: {}",
+                value
+            ))),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zstd" => Ok(Self::Zstd),
+            "lz4" => Ok(Self::Lz4),
+            _ => Err(s.to_owned()),
+        }
+    }
+}
+
 #[repr(u16)]
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub enum Status {
@@ -288,10 +399,47 @@ impl MemoryRangeTable {
         self.data.is_empty()
     }
 
+    /// Amount of memory described by the table.
+    pub fn memory_size(&self) -> u64 {
+        self.data.iter().map(|r| r.length).sum()
+    }
+
     pub fn extend(&mut self, table: Self) {
         self.data.extend(table.data)
     }
 
+    /// Splits the table into `count` tables describing roughly the same
+    /// amount of memory. Ranges are only split on `align` boundaries.
+    pub fn partition(&self, count: usize, align: u64) -> Vec<Self> {
+        let mut tables = vec![Self::default(); count];
+        let total: u64 = self.data.iter().map(|r| r.length).sum();
+        let share = std::cmp::max((total / count as u64) / align * align, align);
+
+        let mut index = 0;
+        let mut filled = 0;
+        for range in self.data.iter() {
+            let mut gpa = range.gpa;
+            let mut length = range.length;
+            while length > 0 {
+                if filled >= share && index < count - 1 {
+                    index += 1;
+                    filled = 0;
+                }
+                let chunk = if index == count - 1 {
+                    length
+                } else {
+                    length.min(share - filled)
+                };
+                tables[index].push(MemoryRange { gpa, length: chunk });
+                filled += chunk;
+                gpa += chunk;
+                length -= chunk;
+            }
+        }
+
+        tables
+    }
+
     pub fn new_from_tables(tables: Vec<Self>) -> Self {
         let mut data = Vec::new();
         for table in tables {
@@ -31,12 +31,15 @@ pub enum VirtioDeviceType {
     Console = 3,
     Rng = 4,
     Balloon = 5,
+    Scsi = 8,
     Fs9P = 9,
     Gpu = 16,
     Input = 18,
     Vsock = 19,
+    Crypto = 20,
     Iommu = 23,
     Mem = 24,
+    Sound = 25,
     Fs = 26,
     Pmem = 27,
     Watchdog = 35, // Temporary until official number allocated
@@ -51,12 +54,15 @@ impl From<u32> for VirtioDeviceType {
             3 => VirtioDeviceType::Console,
             4 => VirtioDeviceType::Rng,
             5 => VirtioDeviceType::Balloon,
+            8 => VirtioDeviceType::Scsi,
             9 => VirtioDeviceType::Fs9P,
             16 => VirtioDeviceType::Gpu,
             18 => VirtioDeviceType::Input,
             19 => VirtioDeviceType::Vsock,
+            20 => VirtioDeviceType::Crypto,
             23 => VirtioDeviceType::Iommu,
             24 => VirtioDeviceType::Mem,
+            25 => VirtioDeviceType::Sound,
             26 => VirtioDeviceType::Fs,
             27 => VirtioDeviceType::Pmem,
             35 => VirtioDeviceType::Watchdog,
@@ -76,12 +82,15 @@ impl fmt::Display for VirtioDeviceType {
             VirtioDeviceType::Console => "console",
             VirtioDeviceType::Rng => "rng",
             VirtioDeviceType::Balloon => "balloon",
+            VirtioDeviceType::Scsi => "scsi",
             VirtioDeviceType::Gpu => "gpu",
             VirtioDeviceType::Fs9P => "9p",
             VirtioDeviceType::Input => "input",
             VirtioDeviceType::Vsock => "vsock",
+            VirtioDeviceType::Crypto => "crypto",
             VirtioDeviceType::Iommu => "iommu",
             VirtioDeviceType::Mem => "mem",
+            VirtioDeviceType::Sound => "sound",
             VirtioDeviceType::Fs => "fs",
             VirtioDeviceType::Pmem => "pmem",
             VirtioDeviceType::Watchdog => "watchdog",
@@ -0,0 +1,47 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+// The firmware isn't part of the source tree, it must be provided by whoever
+// builds with the "builtin_fw" feature.
+fn builtin_firmware() -> Result<PathBuf, String> {
+    let firmware = env::var_os("CH_BUILTIN_FW").ok_or("CH_BUILTIN_FW is not set")?;
+    let firmware = Path::new(&firmware)
+        .canonicalize()
+        .map_err(|e| format!("invalid CH_BUILTIN_FW {firmware:?}: {e}"))?;
+    if !firmware.is_file() {
+        return Err(format!("invalid CH_BUILTIN_FW {firmware:?}: not a file"));
+    }
+
+    Ok(firmware)
+}
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=CH_BUILTIN_FW");
+
+    if env::var_os("CARGO_FEATURE_BUILTIN_FW").is_none()
+        || env::var("CARGO_CFG_TARGET_ARCH").as_deref() != Ok("x86_64")
+    {
+        return;
+    }
+
+    // Building without a firmware must keep working, e.g. when enabling all
+    // the features, the builtin firmware is then simply not available.
+    let firmware = match builtin_firmware() {
+        Ok(firmware) => firmware,
+        Err(e) => {
+            println!("cargo:warning=No firmware embedded with the \"builtin_fw\" feature: {e}");
+            return;
+        }
+    };
+
+    println!("cargo:rerun-if-changed={}", firmware.display());
+    // The path is made absolute as include_bytes!() would otherwise resolve
+    // it relatively to the including source file.
+    println!("cargo:rustc-env=CH_BUILTIN_FW_IMAGE={}", firmware.display());
+    println!("cargo:rustc-cfg=builtin_fw_image");
+}
@@ -14,7 +14,7 @@ use acpi_tables::{rsdp::Rsdp, sdt::Sdt, Aml};
 use arch::aarch64::DeviceInfoForFdt;
 #[cfg(target_arch = "aarch64")]
 use arch::DeviceType;
-use arch::NumaNodes;
+use arch::{NumaNode, NumaNodes};
 use bitflags::bitflags;
 use pci::PciBdf;
 use std::sync::{Arc, Mutex};
@@ -30,6 +30,8 @@ pub const ACPI_APIC_PROCESSOR: u8 = 0;
 pub const ACPI_APIC_IO: u8 = 1;
 #[cfg(target_arch = "x86_64")]
 pub const ACPI_APIC_XRUPT_OVERRIDE: u8 = 2;
+#[cfg(target_arch = "x86_64")]
+pub const ACPI_X2APIC_PROCESSOR: u8 = 9;
 #[cfg(target_arch = "aarch64")]
 pub const ACPI_APIC_GENERIC_CPU_INTERFACE: u8 = 11;
 #[cfg(target_arch = "aarch64")]
@@ -93,6 +95,39 @@ struct ProcessorGiccAffinity {
     pub clock_domain: u32,
 }
 
+#[allow(dead_code)]
+#[repr(packed)]
+#[derive(Default)]
+struct MemoryProximityDomainAttributes {
+    pub type_: u16,
+    _reserved1: u16,
+    pub length: u32,
+    pub flags: u16,
+    _reserved2: u16,
+    pub initiator_proximity_domain: u32,
+    pub memory_proximity_domain: u32,
+    _reserved3: u32,
+    _reserved4: u64,
+    _reserved5: u64,
+}
+
+#[allow(dead_code)]
+#[repr(packed)]
+#[derive(Default)]
+struct LocalityLatencyBandwidthInfo {
+    pub type_: u16,
+    _reserved1: u16,
+    pub length: u32,
+    pub flags: u8,
+    pub data_type: u8,
+    pub min_transfer_size: u8,
+    _reserved2: u8,
+    pub num_initiators: u32,
+    pub num_targets: u32,
+    _reserved3: u32,
+    pub entry_base_unit: u64,
+}
+
 bitflags! {
     pub struct MemAffinityFlags: u32 {
         const NOFLAGS = 0;
@@ -277,7 +312,32 @@ fn create_tpm2_table() -> Sdt {
     tpm
 }
 
-fn create_srat_table(numa_nodes: &NumaNodes) -> Sdt {
+// A single node holding all the memory and vCPUs, for guests without any
+// NUMA configuration.
+fn default_numa_nodes(
+    cpu_manager: &Arc<Mutex<CpuManager>>,
+    memory_manager: &Arc<Mutex<MemoryManager>>,
+) -> NumaNodes {
+    let node = NumaNode {
+        memory_regions: memory_manager
+            .lock()
+            .unwrap()
+            .memory_zones()
+            .values()
+            .flat_map(|zone| zone.regions().clone())
+            .collect(),
+        cpus: (0..cpu_manager.lock().unwrap().max_vcpus()).collect(),
+        ..Default::default()
+    };
+
+    NumaNodes::from([(0, node)])
+}
+
+fn create_srat_table(
+    numa_nodes: &NumaNodes,
+    memory_hotplug_range: Option<(u64, u64)>,
+    #[cfg(target_arch = "x86_64")] cpu_manager: &Arc<Mutex<CpuManager>>,
+) -> Sdt {
     let mut srat = Sdt::new(*b"SRAT", 36, 3, *b"CLOUDH", *b"CHSRAT  ", 1);
     // SRAT reserved 12 bytes
     srat.append_slice(&[0u8; 12]);
@@ -305,6 +365,18 @@ fn create_srat_table(numa_nodes: &NumaNodes) -> Sdt {
             ))
         }
 
+        // The DIMMs hotplugged through ACPI belong to the first node.
+        if let Some((base, size)) = memory_hotplug_range {
+            if Some(node_id) == numa_nodes.keys().next() {
+                srat.append(MemoryAffinity::from_range(
+                    base,
+                    size,
+                    proximity_domain,
+                    MemAffinityFlags::ENABLE | MemAffinityFlags::HOTPLUGGABLE,
+                ))
+            }
+        }
+
         #[cfg(target_arch = "x86_64")]
         for section in &node.sgx_epc_sections {
             srat.append(MemoryAffinity::from_range(
@@ -316,7 +388,10 @@ fn create_srat_table(numa_nodes: &NumaNodes) -> Sdt {
         }
 
         for cpu in &node.cpus {
-            let x2apic_id = *cpu as u32;
+            #[cfg(target_arch = "x86_64")]
+            let x2apic_id = cpu_manager.lock().unwrap().apic_id(*cpu);
+            #[cfg(target_arch = "aarch64")]
+            let x2apic_id = *cpu;
 
             // Flags
             // - Enabled = 1 (bit 0)
@@ -370,6 +445,125 @@ fn create_slit_table(numa_nodes: &NumaNodes) -> Sdt {
     slit
 }
 
+// Same defaults as the SLIT
+fn numa_distance(numa_nodes: &NumaNodes, from: u32, to: u32) -> u64 {
+    if from == to {
+        10
+    } else {
+        numa_nodes
+            .get(&from)
+            .and_then(|node| node.distances.get(&to))
+            .map_or(20, |distance| u64::from(*distance))
+    }
+}
+
+// Only created when the NUMA nodes come with their memory latency and
+// bandwidth, which are the ones seen from the closest vCPUs. The values for
+// the other vCPUs are derived from the NUMA distances.
+fn create_hmat_table(numa_nodes: &NumaNodes) -> Option<Sdt> {
+    const HMAT_MEMORY_PROXIMITY_DOMAIN_ATTRIBUTES: u16 = 0;
+    const HMAT_LOCALITY_LATENCY_BANDWIDTH_INFO: u16 = 1;
+    const HMAT_INITIATOR_PROXIMITY_DOMAIN_VALID: u16 = 1 << 0;
+    const HMAT_MEMORY_HIERARCHY: u8 = 0;
+    const HMAT_ACCESS_LATENCY: u8 = 0;
+    const HMAT_ACCESS_BANDWIDTH: u8 = 3;
+    // 0xffff is reserved and 0 means unreachable
+    const HMAT_MAX_ENTRY: u64 = 0xfffe;
+
+    let initiators: Vec<u32> = numa_nodes
+        .iter()
+        .filter(|(_, node)| !node.cpus.is_empty())
+        .map(|(id, _)| *id)
+        .collect();
+    let targets: Vec<(u32, u32, u32)> = numa_nodes
+        .iter()
+        .filter(|(_, node)| !node.memory_regions.is_empty() || !node.hotplug_regions.is_empty())
+        .map(|(id, node)| Some((*id, node.memory_latency?, node.memory_bandwidth?)))
+        .collect::<Option<Vec<_>>>()?;
+    if initiators.is_empty() || targets.is_empty() {
+        return None;
+    }
+
+    let closest_initiator = |target: u32| {
+        *initiators
+            .iter()
+            .min_by_key(|initiator| numa_distance(numa_nodes, target, **initiator))
+            .unwrap()
+    };
+
+    let mut hmat = Sdt::new(*b"HMAT", 36, 2, *b"CLOUDH", *b"CHHMAT  ", 1);
+    // HMAT reserved 4 bytes
+    hmat.append_slice(&[0u8; 4]);
+
+    assert_eq!(std::mem::size_of::<MemoryProximityDomainAttributes>(), 40);
+    for (target, _, _) in targets.iter() {
+        hmat.append(MemoryProximityDomainAttributes {
+            type_: HMAT_MEMORY_PROXIMITY_DOMAIN_ATTRIBUTES,
+            length: 40,
+            flags: HMAT_INITIATOR_PROXIMITY_DOMAIN_VALID,
+            initiator_proximity_domain: closest_initiator(*target),
+            memory_proximity_domain: *target,
+            ..Default::default()
+        });
+    }
+
+    // Latencies grow and bandwidths shrink with the distance, relatively to
+    // the ones from the closest initiator.
+    let latency_entries: Vec<u64> = initiators
+        .iter()
+        .flat_map(|initiator| {
+            targets.iter().map(move |(target, latency, _)| {
+                u64::from(*latency) * numa_distance(numa_nodes, *target, *initiator)
+                    / numa_distance(numa_nodes, *target, closest_initiator(*target))
+            })
+        })
+        .collect();
+    let bandwidth_entries: Vec<u64> = initiators
+        .iter()
+        .flat_map(|initiator| {
+            targets.iter().map(move |(target, _, bandwidth)| {
+                u64::from(*bandwidth)
+                    * numa_distance(numa_nodes, *target, closest_initiator(*target))
+                    / numa_distance(numa_nodes, *target, *initiator)
+            })
+        })
+        .collect();
+
+    assert_eq!(std::mem::size_of::<LocalityLatencyBandwidthInfo>(), 32);
+    for (data_type, entries, unit) in [
+        // Latencies are expressed in picoseconds
+        (HMAT_ACCESS_LATENCY, latency_entries, 1000),
+        // Bandwidths are expressed in MB/s
+        (HMAT_ACCESS_BANDWIDTH, bandwidth_entries, 1),
+    ] {
+        // Scale the entries so that they fit on 16 bits
+        let max_entry = entries.iter().max().copied().unwrap_or(0);
+        let scale = ((max_entry + HMAT_MAX_ENTRY - 1) / HMAT_MAX_ENTRY).max(1);
+
+        hmat.append(LocalityLatencyBandwidthInfo {
+            type_: HMAT_LOCALITY_LATENCY_BANDWIDTH_INFO,
+            length: (32 + 4 * (initiators.len() + targets.len()) + 2 * entries.len()) as u32,
+            flags: HMAT_MEMORY_HIERARCHY,
+            data_type,
+            num_initiators: initiators.len() as u32,
+            num_targets: targets.len() as u32,
+            entry_base_unit: unit * scale,
+            ..Default::default()
+        });
+        for initiator in initiators.iter() {
+            hmat.append(*initiator);
+        }
+        for (target, _, _) in targets.iter() {
+            hmat.append(*target);
+        }
+        for entry in entries.iter() {
+            hmat.append((entry / scale).max(1) as u16);
+        }
+    }
+
+    Some(hmat)
+}
+
 #[cfg(target_arch = "aarch64")]
 fn create_gtdt_table() -> Sdt {
     const ARCH_TIMER_NS_EL2_IRQ: u32 = 10;
@@ -749,20 +943,40 @@ pub fn create_acpi_tables(
         prev_tbl_len = tpm2.len() as u64;
         prev_tbl_off = tpm2_offset;
     }
-    // SRAT and SLIT
-    // Only created if the NUMA nodes list is not empty.
-    if !numa_nodes.is_empty() {
-        // SRAT
-        let srat = create_srat_table(numa_nodes);
+    // SRAT
+    // Only created if the NUMA nodes list is not empty, or if memory can be
+    // hotplugged through ACPI since some guests (e.g. Windows) only accept
+    // memory within the hotpluggable ranges it describes.
+    let memory_hotplug_range = memory_manager.lock().unwrap().acpi_hotplug_range();
+    if !numa_nodes.is_empty() || memory_hotplug_range.is_some() {
+        let default_nodes;
+        let srat_numa_nodes = if numa_nodes.is_empty() {
+            default_nodes = default_numa_nodes(cpu_manager, memory_manager);
+            &default_nodes
+        } else {
+            numa_nodes
+        };
+        let srat = create_srat_table(
+            srat_numa_nodes,
+            memory_hotplug_range,
+            #[cfg(target_arch = "x86_64")]
+            cpu_manager,
+        );
         let srat_offset = prev_tbl_off.checked_add(prev_tbl_len).unwrap();
         guest_mem
             .write_slice(srat.as_slice(), srat_offset)
             .expect("Error writing SRAT table");
         tables.push(srat_offset.0);
 
-        // SLIT
+        prev_tbl_len = srat.len() as u64;
+        prev_tbl_off = srat_offset;
+    }
+
+    // SLIT
+    // Only created if the NUMA nodes list is not empty.
+    if !numa_nodes.is_empty() {
         let slit = create_slit_table(numa_nodes);
-        let slit_offset = srat_offset.checked_add(srat.len() as u64).unwrap();
+        let slit_offset = prev_tbl_off.checked_add(prev_tbl_len).unwrap();
         guest_mem
             .write_slice(slit.as_slice(), slit_offset)
             .expect("Error writing SRAT table");
@@ -770,6 +984,18 @@ pub fn create_acpi_tables(
 
         prev_tbl_len = slit.len() as u64;
         prev_tbl_off = slit_offset;
+
+        // HMAT
+        if let Some(hmat) = create_hmat_table(numa_nodes) {
+            let hmat_offset = prev_tbl_off.checked_add(prev_tbl_len).unwrap();
+            guest_mem
+                .write_slice(hmat.as_slice(), hmat_offset)
+                .expect("Error writing HMAT table");
+            tables.push(hmat_offset.0);
+
+            prev_tbl_len = hmat.len() as u64;
+            prev_tbl_off = hmat_offset;
+        }
     };
 
     #[cfg(target_arch = "aarch64")]
@@ -852,10 +1078,20 @@ pub fn create_acpi_tables_tdx(
     // Only created if the NUMA nodes list is not empty.
     if !numa_nodes.is_empty() {
         // SRAT
-        tables.push(create_srat_table(numa_nodes));
+        tables.push(create_srat_table(
+            numa_nodes,
+            memory_manager.lock().unwrap().acpi_hotplug_range(),
+            #[cfg(target_arch = "x86_64")]
+            cpu_manager,
+        ));
 
         // SLIT
         tables.push(create_slit_table(numa_nodes));
+
+        // HMAT
+        if let Some(hmat) = create_hmat_table(numa_nodes) {
+            tables.push(hmat);
+        }
     };
 
     // VIOT
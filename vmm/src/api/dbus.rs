@@ -0,0 +1,280 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! D-Bus frontend to the VMM API.
+//!
+//! The `org.cloudhypervisor.DBusApi1` interface mirrors the REST API: each
+//! method takes and returns the same JSON documents as the matching HTTP
+//! endpoint, and forwards the request to the VMM thread through the internal
+//! API.
+
+use super::{ApiError, ApiRequest, VmAction};
+use crate::config::{
+    DeviceConfig, DiskConfig, FsConfig, NetConfig, PmemConfig, RestoreConfig, UserDeviceConfig,
+    VdpaConfig, VmConfig, VsockConfig,
+};
+use crate::seccomp_filters::{get_seccomp_filter, Thread};
+use crate::{Error as VmmError, Result};
+use futures::executor;
+use hypervisor::HypervisorType;
+use seccompiler::{apply_filter, SeccompAction};
+use serde::de::DeserializeOwned;
+use std::panic::AssertUnwindSafe;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use vmm_sys_util::eventfd::EventFd;
+use zbus::fdo::{Error as DBusError, Result as DBusResult};
+use zbus::{dbus_interface, ConnectionBuilder};
+
+/// Where the D-Bus interface is exposed.
+pub struct DBusApiOptions {
+    /// Well-known name requested on the bus
+    pub service_name: String,
+    /// Path of the object implementing the interface
+    pub object_path: String,
+    /// Connect to the system bus rather than to the session bus
+    pub system_bus: bool,
+}
+
+pub struct DBusApi {
+    api_notifier: EventFd,
+    // The interface must be Sync, which Sender is not
+    api_sender: Mutex<Sender<ApiRequest>>,
+}
+
+fn api_error(e: ApiError) -> DBusError {
+    DBusError::Failed(format!("{e:?}"))
+}
+
+fn from_json<T: DeserializeOwned>(json: &str) -> DBusResult<T> {
+    serde_json::from_str(json).map_err(|e| DBusError::InvalidArgs(e.to_string()))
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> DBusResult<String> {
+    serde_json::to_string(value).map_err(|e| DBusError::Failed(e.to_string()))
+}
+
+impl DBusApi {
+    fn new(api_notifier: EventFd, api_sender: Sender<ApiRequest>) -> Self {
+        DBusApi {
+            api_notifier,
+            api_sender: Mutex::new(api_sender),
+        }
+    }
+
+    fn api(&self) -> DBusResult<(EventFd, Sender<ApiRequest>)> {
+        let api_notifier = self
+            .api_notifier
+            .try_clone()
+            .map_err(|e| DBusError::IOError(e.to_string()))?;
+        let api_sender = self.api_sender.lock().unwrap().clone();
+
+        Ok((api_notifier, api_sender))
+    }
+
+    // D-Bus has no optional values, an action without any response body
+    // returns an empty string.
+    fn vm_action(&self, action: VmAction) -> DBusResult<String> {
+        let (api_notifier, api_sender) = self.api()?;
+        let body = super::vm_action(api_notifier, api_sender, action).map_err(api_error)?;
+
+        Ok(body
+            .map(|body| String::from_utf8_lossy(body.raw()).into_owned())
+            .unwrap_or_default())
+    }
+}
+
+#[dbus_interface(name = "org.cloudhypervisor.DBusApi1")]
+impl DBusApi {
+    fn vmm_ping(&self) -> DBusResult<String> {
+        let (api_notifier, api_sender) = self.api()?;
+        let ping = super::vmm_ping(api_notifier, api_sender).map_err(api_error)?;
+        to_json(&ping)
+    }
+
+    fn vmm_shutdown(&self) -> DBusResult<()> {
+        let (api_notifier, api_sender) = self.api()?;
+        super::vmm_shutdown(api_notifier, api_sender).map_err(api_error)
+    }
+
+    fn vm_create(&self, vm_config: String) -> DBusResult<()> {
+        let vm_config: VmConfig = from_json(&vm_config)?;
+        let (api_notifier, api_sender) = self.api()?;
+        super::vm_create(api_notifier, api_sender, Arc::new(Mutex::new(vm_config)))
+            .map_err(api_error)
+    }
+
+    fn vm_delete(&self) -> DBusResult<String> {
+        self.vm_action(VmAction::Delete)
+    }
+
+    fn vm_boot(&self) -> DBusResult<String> {
+        self.vm_action(VmAction::Boot)
+    }
+
+    fn vm_shutdown(&self) -> DBusResult<String> {
+        self.vm_action(VmAction::Shutdown)
+    }
+
+    fn vm_reboot(&self) -> DBusResult<String> {
+        self.vm_action(VmAction::Reboot)
+    }
+
+    fn vm_pause(&self) -> DBusResult<String> {
+        self.vm_action(VmAction::Pause)
+    }
+
+    fn vm_resume(&self) -> DBusResult<String> {
+        self.vm_action(VmAction::Resume)
+    }
+
+    fn vm_power_button(&self) -> DBusResult<String> {
+        self.vm_action(VmAction::PowerButton)
+    }
+
+    fn vm_info(&self) -> DBusResult<String> {
+        let (api_notifier, api_sender) = self.api()?;
+        let info = super::vm_info(api_notifier, api_sender).map_err(api_error)?;
+        to_json(&info)
+    }
+
+    fn vm_counters(&self) -> DBusResult<String> {
+        self.vm_action(VmAction::Counters)
+    }
+
+    fn vm_resize(&self, vm_resize: String) -> DBusResult<String> {
+        self.vm_action(VmAction::Resize(Arc::new(from_json(&vm_resize)?)))
+    }
+
+    fn vm_resize_zone(&self, vm_resize_zone: String) -> DBusResult<String> {
+        self.vm_action(VmAction::ResizeZone(Arc::new(from_json(&vm_resize_zone)?)))
+    }
+
+    fn vm_add_device(&self, device_config: String) -> DBusResult<String> {
+        let device_config: DeviceConfig = from_json(&device_config)?;
+        self.vm_action(VmAction::AddDevice(Arc::new(device_config)))
+    }
+
+    fn vm_add_disk(&self, disk_config: String) -> DBusResult<String> {
+        let disk_config: DiskConfig = from_json(&disk_config)?;
+        self.vm_action(VmAction::AddDisk(Arc::new(disk_config)))
+    }
+
+    fn vm_add_fs(&self, fs_config: String) -> DBusResult<String> {
+        let fs_config: FsConfig = from_json(&fs_config)?;
+        self.vm_action(VmAction::AddFs(Arc::new(fs_config)))
+    }
+
+    fn vm_add_net(&self, net_config: String) -> DBusResult<String> {
+        let net_config: NetConfig = from_json(&net_config)?;
+        // File descriptors can't be passed in a JSON document
+        if net_config.fds.is_some() {
+            return Err(DBusError::InvalidArgs(
+                "Network interfaces can't be added from file descriptors".to_string(),
+            ));
+        }
+        self.vm_action(VmAction::AddNet(Arc::new(net_config)))
+    }
+
+    fn vm_add_pmem(&self, pmem_config: String) -> DBusResult<String> {
+        let pmem_config: PmemConfig = from_json(&pmem_config)?;
+        self.vm_action(VmAction::AddPmem(Arc::new(pmem_config)))
+    }
+
+    fn vm_add_vdpa(&self, vdpa_config: String) -> DBusResult<String> {
+        let vdpa_config: VdpaConfig = from_json(&vdpa_config)?;
+        self.vm_action(VmAction::AddVdpa(Arc::new(vdpa_config)))
+    }
+
+    fn vm_add_vsock(&self, vsock_config: String) -> DBusResult<String> {
+        let vsock_config: VsockConfig = from_json(&vsock_config)?;
+        self.vm_action(VmAction::AddVsock(Arc::new(vsock_config)))
+    }
+
+    fn vm_add_user_device(&self, user_device_config: String) -> DBusResult<String> {
+        let user_device_config: UserDeviceConfig = from_json(&user_device_config)?;
+        self.vm_action(VmAction::AddUserDevice(Arc::new(user_device_config)))
+    }
+
+    fn vm_remove_device(&self, vm_remove_device: String) -> DBusResult<String> {
+        self.vm_action(VmAction::RemoveDevice(Arc::new(from_json(
+            &vm_remove_device,
+        )?)))
+    }
+
+    fn vm_snapshot(&self, vm_snapshot_config: String) -> DBusResult<String> {
+        self.vm_action(VmAction::Snapshot(Arc::new(from_json(
+            &vm_snapshot_config,
+        )?)))
+    }
+
+    fn vm_restore(&self, restore_config: String) -> DBusResult<String> {
+        let restore_config: RestoreConfig = from_json(&restore_config)?;
+        self.vm_action(VmAction::Restore(Arc::new(restore_config)))
+    }
+}
+
+pub fn start_dbus_thread(
+    options: DBusApiOptions,
+    api_notifier: EventFd,
+    api_sender: Sender<ApiRequest>,
+    seccomp_action: &SeccompAction,
+    exit_evt: EventFd,
+    hypervisor_type: HypervisorType,
+) -> Result<thread::JoinHandle<Result<()>>> {
+    // Retrieve seccomp filter for D-Bus thread
+    let dbus_seccomp_filter = get_seccomp_filter(seccomp_action, Thread::DBusApi, hypervisor_type)
+        .map_err(VmmError::CreateSeccompFilter)?;
+
+    let api = DBusApi::new(api_notifier, api_sender);
+    // The connection is driven from the D-Bus thread only, rather than from
+    // an executor thread zbus would spawn outside of the seccomp filter.
+    let connection = executor::block_on(async move {
+        let builder = if options.system_bus {
+            ConnectionBuilder::system()?
+        } else {
+            ConnectionBuilder::session()?
+        };
+        builder
+            .internal_executor(false)
+            .name(options.service_name)?
+            .serve_at(options.object_path, api)?
+            .build()
+            .await
+    })
+    .map_err(VmmError::CreateDBusSession)?;
+
+    thread::Builder::new()
+        .name("dbus-api".to_string())
+        .spawn(move || {
+            // Apply seccomp filter for D-Bus thread.
+            if !dbus_seccomp_filter.is_empty() {
+                apply_filter(&dbus_seccomp_filter)
+                    .map_err(VmmError::ApplySeccompFilter)
+                    .map_err(|e| {
+                        error!("Error applying seccomp filter: {:?}", e);
+                        exit_evt.write(1).ok();
+                        e
+                    })?;
+            }
+
+            std::panic::catch_unwind(AssertUnwindSafe(move || {
+                executor::block_on(async {
+                    loop {
+                        connection.executor().tick().await;
+                    }
+                })
+            }))
+            .map_err(|_| {
+                error!("dbus-api thread panicked");
+                exit_evt.write(1).ok()
+            })
+            .ok();
+
+            Ok(())
+        })
+        .map_err(VmmError::DBusThreadSpawn)
+}
@@ -3,25 +3,34 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
-use crate::api::http_endpoint::{VmActionHandler, VmCreate, VmInfo, VmmPing, VmmShutdown};
+use crate::api::http_endpoint::{
+    Metrics, OpenApi, VmActionHandler, VmCreate, VmInfo, VmmPing, VmmShutdown,
+};
 use crate::api::{ApiError, ApiRequest, VmAction};
 use crate::seccomp_filters::{get_seccomp_filter, Thread};
 use crate::{Error as VmmError, Result};
 use hypervisor::HypervisorType;
-use micro_http::{Body, HttpServer, MediaType, Method, Request, Response, StatusCode, Version};
+use micro_http::{
+    Body, ConnectionError, HttpConnection, MediaType, Method, Request, Response, StatusCode,
+    Version,
+};
 use once_cell::sync::Lazy;
 use seccompiler::{apply_filter, SeccompAction};
 use serde_json::Error as SerdeError;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
-use std::os::unix::io::{IntoRawFd, RawFd};
-use std::os::unix::net::UnixListener;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
 use std::panic::AssertUnwindSafe;
 use std::path::PathBuf;
 use std::sync::mpsc::Sender;
 use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
 use vmm_sys_util::eventfd::EventFd;
+use vmm_sys_util::sock_ctrl_msg::ScmSocket;
 
 /// Errors associated with VMM management
 #[derive(Debug)]
@@ -35,6 +44,12 @@ pub enum HttpError {
     /// Undefined endpoints
     NotFound,
 
+    /// Missing or invalid authentication token
+    Unauthorized,
+
+    /// Attempt to modify the VMM or the VM through a read-only API
+    ReadOnly,
+
     /// Internal Server Error
     InternalServerError,
 
@@ -50,6 +65,115 @@ impl From<serde_json::Error> for HttpError {
 
 const HTTP_ROOT: &str = "/api/v1";
 
+// Maximum number of events handled at once by the HTTP server
+const HTTP_MAX_EVENTS: usize = 16;
+
+// All the connections are served from a single thread, which must not be
+// stalled by a client that stops sending its request or reading the response.
+const HTTP_IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Access control applied to the clients of the HTTP API.
+#[derive(Clone, Debug, Default)]
+pub struct HttpAccessConfig {
+    /// Users allowed to connect, based on the peer credentials
+    pub allowed_uids: Vec<u32>,
+    /// Groups allowed to connect, based on the peer credentials
+    pub allowed_gids: Vec<u32>,
+    /// Token expected from the `Authorization: Bearer` header
+    pub token: Option<String>,
+    /// Reject the requests modifying the VMM or the VM
+    pub read_only: bool,
+}
+
+impl HttpAccessConfig {
+    // A peer is allowed if no restriction is set, or if either its user or
+    // its group is part of the allowed ones.
+    fn peer_allowed(&self, stream: &HttpStream) -> bool {
+        if self.allowed_uids.is_empty() && self.allowed_gids.is_empty() {
+            return true;
+        }
+
+        // Peer credentials are only available from UNIX sockets
+        let stream = match stream {
+            HttpStream::Unix(stream) => stream,
+            _ => return false,
+        };
+
+        match peer_credentials(stream) {
+            Ok(cred) => {
+                self.allowed_uids.contains(&cred.uid) || self.allowed_gids.contains(&cred.gid)
+            }
+            Err(e) => {
+                warn!("Error retrieving the API client credentials: {}", e);
+                false
+            }
+        }
+    }
+
+    // Returns the response to send back if the request must be rejected.
+    fn check_request(&self, request: &Request) -> Option<Response> {
+        if let Some(token) = &self.token {
+            let authorized = request
+                .headers
+                .custom_entries()
+                .iter()
+                .any(|(name, value)| {
+                    name.eq_ignore_ascii_case("Authorization")
+                        && value
+                            .strip_prefix("Bearer ")
+                            .map(|t| constant_time_eq(t.trim().as_bytes(), token.as_bytes()))
+                            .unwrap_or(false)
+                });
+            if !authorized {
+                return Some(error_response(
+                    HttpError::Unauthorized,
+                    StatusCode::Unauthorized,
+                ));
+            }
+        }
+
+        // Only the GET requests are free of side effects
+        if self.read_only && request.method() != Method::Get {
+            return Some(error_response(
+                HttpError::ReadOnly,
+                StatusCode::MethodNotAllowed,
+            ));
+        }
+
+        None
+    }
+}
+
+// Compare the tokens without leaking how much of them matched through the
+// time spent comparing.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn peer_credentials(stream: &UnixStream) -> io::Result<libc::ucred> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: valid socket, and the buffer matches the size of the option
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(cred)
+}
+
 pub fn error_response(error: HttpError, status: StatusCode) -> Response {
     let mut response = Response::new(Version::Http11, status);
     response.set_body(Body::new(format!("{error:?}")));
@@ -175,6 +299,14 @@ pub static HTTP_ROUTES: Lazy<HttpRoutes> = Lazy::new(|| {
         endpoint!("/vm.boot"),
         Box::new(VmActionHandler::new(VmAction::Boot)),
     );
+    r.routes.insert(
+        endpoint!("/vm.change-media"),
+        Box::new(VmActionHandler::new(VmAction::ChangeMedia(Arc::default()))),
+    );
+    r.routes.insert(
+        endpoint!("/vm.clone"),
+        Box::new(VmActionHandler::new(VmAction::Clone(Arc::default()))),
+    );
     r.routes.insert(
         endpoint!("/vm.counters"),
         Box::new(VmActionHandler::new(VmAction::Counters)),
@@ -185,7 +317,37 @@ pub static HTTP_ROUTES: Lazy<HttpRoutes> = Lazy::new(|| {
         endpoint!("/vm.delete"),
         Box::new(VmActionHandler::new(VmAction::Delete)),
     );
+    r.routes.insert(
+        endpoint!("/vm.dirty-rate"),
+        Box::new(VmActionHandler::new(VmAction::DirtyRate(Arc::default()))),
+    );
+    r.routes.insert(
+        endpoint!("/vm.guest-exec"),
+        Box::new(VmActionHandler::new(VmAction::GuestExec(Arc::default()))),
+    );
+    r.routes.insert(
+        endpoint!("/vm.guest-file-read"),
+        Box::new(VmActionHandler::new(
+            VmAction::GuestFileRead(Arc::default()),
+        )),
+    );
+    r.routes.insert(
+        endpoint!("/vm.guest-file-write"),
+        Box::new(VmActionHandler::new(VmAction::GuestFileWrite(
+            Arc::default(),
+        ))),
+    );
+    r.routes.insert(
+        endpoint!("/vm.guest-shutdown"),
+        Box::new(VmActionHandler::new(
+            VmAction::GuestShutdown(Arc::default()),
+        )),
+    );
     r.routes.insert(endpoint!("/vm.info"), Box::new(VmInfo {}));
+    r.routes.insert(
+        endpoint!("/vm.nmi"),
+        Box::new(VmActionHandler::new(VmAction::Nmi(Arc::default()))),
+    );
     r.routes.insert(
         endpoint!("/vm.pause"),
         Box::new(VmActionHandler::new(VmAction::Pause)),
@@ -224,12 +386,26 @@ pub static HTTP_ROUTES: Lazy<HttpRoutes> = Lazy::new(|| {
         endpoint!("/vm.resume"),
         Box::new(VmActionHandler::new(VmAction::Resume)),
     );
+    r.routes.insert(
+        endpoint!("/vm.send-keys"),
+        Box::new(VmActionHandler::new(VmAction::SendKeys(Arc::default()))),
+    );
     r.routes.insert(
         endpoint!("/vm.send-migration"),
         Box::new(VmActionHandler::new(
             VmAction::SendMigration(Arc::default()),
         )),
     );
+    r.routes.insert(
+        endpoint!("/vm.send-pointer"),
+        Box::new(VmActionHandler::new(VmAction::SendPointer(Arc::default()))),
+    );
+    r.routes.insert(
+        endpoint!("/vm.set-watchdog-action"),
+        Box::new(VmActionHandler::new(VmAction::SetWatchdogAction(
+            Arc::default(),
+        ))),
+    );
     r.routes.insert(
         endpoint!("/vm.shutdown"),
         Box::new(VmActionHandler::new(VmAction::Shutdown)),
@@ -238,11 +414,17 @@ pub static HTTP_ROUTES: Lazy<HttpRoutes> = Lazy::new(|| {
         endpoint!("/vm.snapshot"),
         Box::new(VmActionHandler::new(VmAction::Snapshot(Arc::default()))),
     );
+    r.routes.insert(
+        endpoint!("/vm.vcpu-stats"),
+        Box::new(VmActionHandler::new(VmAction::VcpuStats)),
+    );
     #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
     r.routes.insert(
         endpoint!("/vm.coredump"),
         Box::new(VmActionHandler::new(VmAction::Coredump(Arc::default()))),
     );
+    r.routes.insert(endpoint!("/metrics"), Box::new(Metrics {}));
+    r.routes.insert(endpoint!("/openapi"), Box::new(OpenApi {}));
     r.routes
         .insert(endpoint!("/vmm.ping"), Box::new(VmmPing {}));
     r.routes
@@ -253,28 +435,335 @@ pub static HTTP_ROUTES: Lazy<HttpRoutes> = Lazy::new(|| {
 
 fn handle_http_request(
     request: &Request,
+    access: &HttpAccessConfig,
     api_notifier: &EventFd,
     api_sender: &Sender<ApiRequest>,
 ) -> Response {
     let path = request.uri().get_abs_path().to_string();
-    let mut response = match HTTP_ROUTES.routes.get(&path) {
-        Some(route) => match api_notifier.try_clone() {
-            Ok(notifier) => route.handle_request(request, notifier, api_sender.clone()),
-            Err(_) => error_response(
-                HttpError::InternalServerError,
-                StatusCode::InternalServerError,
-            ),
-        },
-        None => error_response(HttpError::NotFound, StatusCode::NotFound),
+    let mut response = if let Some(response) = access.check_request(request) {
+        response
+    } else {
+        match HTTP_ROUTES.routes.get(&path) {
+            Some(route) => {
+                let start = Instant::now();
+                let response = match api_notifier.try_clone() {
+                    Ok(notifier) => route.handle_request(request, notifier, api_sender.clone()),
+                    Err(_) => error_response(
+                        HttpError::InternalServerError,
+                        StatusCode::InternalServerError,
+                    ),
+                };
+                crate::metrics::observe_api_latency(&path, start.elapsed());
+                response
+            }
+            None => error_response(HttpError::NotFound, StatusCode::NotFound),
+        }
     };
 
     response.set_server("Cloud Hypervisor API");
-    response.set_content_type(MediaType::ApplicationJson);
+    if path == endpoint!("/metrics") && response.status() == StatusCode::OK {
+        response.set_content_type(MediaType::PlainText);
+    } else {
+        response.set_content_type(MediaType::ApplicationJson);
+    }
     response
 }
 
-fn start_http_thread(
-    mut server: HttpServer,
+// The events are streamed to the subscribers as the body of the response,
+// which lasts until the connection is closed.
+fn subscribe_to_events(fd: RawFd) -> io::Result<()> {
+    // SAFETY: fd is a valid socket
+    let fd = unsafe { libc::dup(fd) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: fd is a valid socket we own
+    let mut stream = unsafe { File::from_raw_fd(fd) };
+    stream.write_all(
+        b"HTTP/1.1 200 \r\n\
+          Server: Cloud Hypervisor API\r\n\
+          Content-Type: application/json\r\n\
+          Connection: close\r\n\r\n",
+    )?;
+
+    event_monitor::add_subscriber(stream)
+}
+
+// Serve the requests received from a connection, returning whether the
+// connection must be kept open.
+fn serve_http_connection(
+    fd: RawFd,
+    connection: &mut HttpConnection<HttpStream>,
+    access: &HttpAccessConfig,
+    api_notifier: &EventFd,
+    api_sender: &Sender<ApiRequest>,
+) -> bool {
+    let mut keep_open = true;
+    match connection.try_read() {
+        Ok(()) => {}
+        Err(ConnectionError::ParseError(e)) => {
+            let mut response = Response::new(Version::Http11, StatusCode::BadRequest);
+            response.set_body(Body::new(format!("{e:?}")));
+            connection.enqueue_response(response);
+            keep_open = false;
+        }
+        Err(_) => return false,
+    }
+
+    let mut events_subscription = false;
+    while let Some(request) = connection.pop_parsed_request() {
+        if request.method() == Method::Get
+            && request.uri().get_abs_path() == endpoint!("/vm.events")
+            && access.check_request(&request).is_none()
+        {
+            events_subscription = true;
+            break;
+        }
+
+        let response = handle_http_request(&request, access, api_notifier, api_sender);
+        connection.enqueue_response(response);
+    }
+
+    while connection.pending_write() {
+        if let Err(e) = connection.try_write() {
+            error!("HTTP server error on response: {:?}", e);
+            return false;
+        }
+    }
+
+    // From now on, the connection only carries the events
+    if events_subscription {
+        if let Err(e) = subscribe_to_events(fd) {
+            error!("HTTP server error on events subscription: {}", e);
+        }
+        return false;
+    }
+
+    keep_open
+}
+
+// Connection accepted from any of the socket families the API can be served
+// over. The standard library has no VSOCK support, so such streams are only
+// wrapped as a File.
+enum HttpStream {
+    Unix(UnixStream),
+    Tcp(TcpStream),
+    Vsock(File),
+}
+
+impl HttpStream {
+    // The socket family is only known once the connection is accepted, as
+    // the listener can be passed by file descriptor.
+    fn from_socket(socket: File) -> io::Result<Self> {
+        // SAFETY: all zeros is a valid value for this C structure
+        let mut addr: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+        let mut len = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        // SAFETY: valid socket and address buffer, of the given size
+        let ret = unsafe {
+            libc::getsockname(
+                socket.as_raw_fd(),
+                &mut addr as *mut libc::sockaddr_storage as *mut libc::sockaddr,
+                &mut len,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(match addr.ss_family as libc::c_int {
+            libc::AF_UNIX => {
+                // SAFETY: the FD is a valid UNIX socket we own
+                HttpStream::Unix(unsafe { UnixStream::from_raw_fd(socket.into_raw_fd()) })
+            }
+            libc::AF_INET | libc::AF_INET6 => {
+                // SAFETY: the FD is a valid TCP socket we own
+                HttpStream::Tcp(unsafe { TcpStream::from_raw_fd(socket.into_raw_fd()) })
+            }
+            libc::AF_VSOCK => HttpStream::Vsock(socket),
+            family => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("unsupported socket family {family}"),
+                ))
+            }
+        })
+    }
+
+    fn set_timeouts(&self, timeout: Duration) -> io::Result<()> {
+        match self {
+            HttpStream::Unix(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))
+            }
+            HttpStream::Tcp(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))
+            }
+            HttpStream::Vsock(stream) => {
+                let timeval = libc::timeval {
+                    tv_sec: timeout.as_secs() as libc::time_t,
+                    tv_usec: timeout.subsec_micros() as libc::suseconds_t,
+                };
+                for option in [libc::SO_RCVTIMEO, libc::SO_SNDTIMEO] {
+                    // SAFETY: valid socket, and the value matches the size
+                    // of the option
+                    let ret = unsafe {
+                        libc::setsockopt(
+                            stream.as_raw_fd(),
+                            libc::SOL_SOCKET,
+                            option,
+                            &timeval as *const libc::timeval as *const libc::c_void,
+                            std::mem::size_of::<libc::timeval>() as libc::socklen_t,
+                        )
+                    };
+                    if ret < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl AsRawFd for HttpStream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            HttpStream::Unix(stream) => stream.as_raw_fd(),
+            HttpStream::Tcp(stream) => stream.as_raw_fd(),
+            HttpStream::Vsock(stream) => stream.as_raw_fd(),
+        }
+    }
+}
+
+impl Read for HttpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            HttpStream::Unix(stream) => stream.read(buf),
+            HttpStream::Tcp(stream) => stream.read(buf),
+            HttpStream::Vsock(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for HttpStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            HttpStream::Unix(stream) => stream.write(buf),
+            HttpStream::Tcp(stream) => stream.write(buf),
+            HttpStream::Vsock(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            HttpStream::Unix(stream) => stream.flush(),
+            HttpStream::Tcp(stream) => stream.flush(),
+            HttpStream::Vsock(stream) => stream.flush(),
+        }
+    }
+}
+
+// Needed by micro_http to receive file descriptors, which only UNIX sockets
+// can carry. Receiving from the other families simply yields no descriptor.
+impl ScmSocket for HttpStream {
+    fn socket_fd(&self) -> RawFd {
+        self.as_raw_fd()
+    }
+}
+
+fn accept_http_connection(listener_fd: RawFd) -> io::Result<HttpStream> {
+    // SAFETY: FFI call, the address of the peer isn't retrieved
+    let fd = unsafe {
+        libc::accept4(
+            listener_fd,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            libc::SOCK_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: fd is a valid socket we own
+    let stream = HttpStream::from_socket(unsafe { File::from_raw_fd(fd) })?;
+    stream.set_timeouts(HTTP_IO_TIMEOUT)?;
+    Ok(stream)
+}
+
+// The connections are accepted here rather than by the micro_http server, so
+// that the credentials of the peers can be checked before serving them.
+fn run_http_server<L: AsRawFd>(
+    listener: L,
+    epoll_file: File,
+    access: HttpAccessConfig,
+    api_notifier: EventFd,
+    api_sender: Sender<ApiRequest>,
+) -> io::Result<()> {
+    let epoll_fd = epoll_file.as_raw_fd();
+    let listener_fd = listener.as_raw_fd();
+    epoll::ctl(
+        epoll_fd,
+        epoll::ControlOptions::EPOLL_CTL_ADD,
+        listener_fd,
+        epoll::Event::new(epoll::Events::EPOLLIN, listener_fd as u64),
+    )?;
+
+    let mut connections: HashMap<RawFd, HttpConnection<HttpStream>> = HashMap::new();
+    let mut events = vec![epoll::Event::new(epoll::Events::empty(), 0); HTTP_MAX_EVENTS];
+    loop {
+        let num_events = match epoll::wait(epoll_fd, -1, &mut events[..]) {
+            Ok(num_events) => num_events,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        for event in events.iter().take(num_events) {
+            let fd = event.data as RawFd;
+            if fd == listener_fd {
+                let stream = match accept_http_connection(listener_fd) {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        error!("HTTP server error on accepting connection: {}", e);
+                        continue;
+                    }
+                };
+
+                if !access.peer_allowed(&stream) {
+                    warn!("HTTP server rejected connection from unauthorized peer");
+                    continue;
+                }
+
+                let stream_fd = stream.as_raw_fd();
+                epoll::ctl(
+                    epoll_fd,
+                    epoll::ControlOptions::EPOLL_CTL_ADD,
+                    stream_fd,
+                    epoll::Event::new(epoll::Events::EPOLLIN, stream_fd as u64),
+                )?;
+                connections.insert(stream_fd, HttpConnection::new(stream));
+            } else if let Some(connection) = connections.get_mut(&fd) {
+                if !serve_http_connection(fd, connection, &access, &api_notifier, &api_sender) {
+                    // The stream outlives the connection when subscribed to
+                    // the events, hence removing it from the epoll set.
+                    epoll::ctl(
+                        epoll_fd,
+                        epoll::ControlOptions::EPOLL_CTL_DEL,
+                        fd,
+                        epoll::Event::new(epoll::Events::empty(), 0),
+                    )
+                    .ok();
+                    connections.remove(&fd);
+                }
+            }
+        }
+    }
+}
+
+fn start_http_thread<L: AsRawFd + Send + 'static>(
+    listener: L,
+    access: HttpAccessConfig,
     api_notifier: EventFd,
     api_sender: Sender<ApiRequest>,
     seccomp_action: &SeccompAction,
@@ -285,6 +774,10 @@ fn start_http_thread(
     let api_seccomp_filter = get_seccomp_filter(seccomp_action, Thread::Api, hypervisor_type)
         .map_err(VmmError::CreateSeccompFilter)?;
 
+    let epoll_fd = epoll::create(true).map_err(VmmError::CreateApiServer)?;
+    // SAFETY: Valid FD just created
+    let epoll_file = unsafe { File::from_raw_fd(epoll_fd) };
+
     thread::Builder::new()
         .name("http-server".to_string())
         .spawn(move || {
@@ -300,25 +793,10 @@ fn start_http_thread(
             }
 
             std::panic::catch_unwind(AssertUnwindSafe(move || {
-                server.start_server().unwrap();
-                loop {
-                    match server.requests() {
-                        Ok(request_vec) => {
-                            for server_request in request_vec {
-                                if let Err(e) = server.respond(server_request.process(|request| {
-                                    handle_http_request(request, &api_notifier, &api_sender)
-                                })) {
-                                    error!("HTTP server error on response: {}", e);
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            error!(
-                                "HTTP server error on retrieving incoming request. Error: {}",
-                                e
-                            );
-                        }
-                    }
+                if let Err(e) =
+                    run_http_server(listener, epoll_file, access, api_notifier, api_sender)
+                {
+                    error!("HTTP server error: {}", e);
                 }
             }))
             .map_err(|_| {
@@ -334,6 +812,7 @@ fn start_http_thread(
 
 pub fn start_http_path_thread(
     path: &str,
+    access: HttpAccessConfig,
     api_notifier: EventFd,
     api_sender: Sender<ApiRequest>,
     seccomp_action: &SeccompAction,
@@ -341,12 +820,10 @@ pub fn start_http_path_thread(
     hypervisor_type: HypervisorType,
 ) -> Result<thread::JoinHandle<Result<()>>> {
     let socket_path = PathBuf::from(path);
-    let socket_fd = UnixListener::bind(socket_path).map_err(VmmError::CreateApiServerSocket)?;
-    // SAFETY: Valid FD just opened
-    let server = unsafe { HttpServer::new_from_fd(socket_fd.into_raw_fd()) }
-        .map_err(VmmError::CreateApiServer)?;
+    let listener = UnixListener::bind(socket_path).map_err(VmmError::CreateApiServerSocket)?;
     start_http_thread(
-        server,
+        listener,
+        access,
         api_notifier,
         api_sender,
         seccomp_action,
@@ -355,18 +832,57 @@ pub fn start_http_path_thread(
     )
 }
 
+/// Creates a VSOCK socket listening on the given address, to be served
+/// through `start_http_fd_thread()`.
+pub fn vsock_listener(cid: u32, port: u32) -> io::Result<RawFd> {
+    // SAFETY: FFI call with valid arguments
+    let fd = unsafe { libc::socket(libc::AF_VSOCK, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: fd is a valid socket we own
+    let socket = unsafe { File::from_raw_fd(fd) };
+
+    // SAFETY: all zeros is a valid value for this C structure
+    let mut addr: libc::sockaddr_vm = unsafe { std::mem::zeroed() };
+    addr.svm_family = libc::AF_VSOCK as libc::sa_family_t;
+    addr.svm_cid = cid;
+    addr.svm_port = port;
+    // SAFETY: valid socket and address, of the given size
+    let ret = unsafe {
+        libc::bind(
+            socket.as_raw_fd(),
+            &addr as *const libc::sockaddr_vm as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: valid socket
+    if unsafe { libc::listen(socket.as_raw_fd(), libc::SOMAXCONN) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(socket.into_raw_fd())
+}
+
 pub fn start_http_fd_thread(
     fd: RawFd,
+    access: HttpAccessConfig,
     api_notifier: EventFd,
     api_sender: Sender<ApiRequest>,
     seccomp_action: &SeccompAction,
     exit_evt: EventFd,
     hypervisor_type: HypervisorType,
 ) -> Result<thread::JoinHandle<Result<()>>> {
+    // The listener can be of any socket family, File only owns its FD.
     // SAFETY: Valid FD
-    let server = unsafe { HttpServer::new_from_fd(fd) }.map_err(VmmError::CreateApiServer)?;
+    let listener = unsafe { File::from_raw_fd(fd) };
     start_http_thread(
-        server,
+        listener,
+        access,
         api_notifier,
         api_sender,
         seccomp_action,
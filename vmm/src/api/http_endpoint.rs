@@ -8,10 +8,13 @@ use crate::api::http::{error_response, EndpointHandler, HttpError};
 use crate::api::vm_coredump;
 use crate::api::{
     vm_add_device, vm_add_disk, vm_add_fs, vm_add_net, vm_add_pmem, vm_add_user_device,
-    vm_add_vdpa, vm_add_vsock, vm_boot, vm_counters, vm_create, vm_delete, vm_info, vm_pause,
-    vm_power_button, vm_reboot, vm_receive_migration, vm_remove_device, vm_resize, vm_resize_zone,
-    vm_restore, vm_resume, vm_send_migration, vm_shutdown, vm_snapshot, vmm_ping, vmm_shutdown,
-    ApiRequest, VmAction, VmConfig,
+    vm_add_vdpa, vm_add_vsock, vm_boot, vm_change_media, vm_clone, vm_counters, vm_create,
+    vm_delete, vm_dirty_rate, vm_guest_exec, vm_guest_file_read, vm_guest_file_write,
+    vm_guest_shutdown, vm_info, vm_nmi, vm_pause, vm_power_button, vm_reboot, vm_receive_migration,
+    vm_remove_device, vm_resize, vm_resize_zone, vm_restore, vm_resume, vm_send_keys,
+    vm_send_migration, vm_send_pointer, vm_set_watchdog_action, vm_shutdown, vm_snapshot,
+    vm_vcpu_stats, vmm_metrics, vmm_ping, vmm_shutdown, ApiRequest, VmAction, VmConfig,
+    OPENAPI_SPEC,
 };
 use crate::config::NetConfig;
 use micro_http::{Body, Method, Request, Response, StatusCode, Version};
@@ -153,6 +156,21 @@ impl EndpointHandler for VmActionHandler {
                     api_sender,
                     Arc::new(serde_json::from_slice(body.raw())?),
                 ),
+                SendKeys(_) => vm_send_keys(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                ),
+                SendPointer(_) => vm_send_pointer(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                ),
+                DirtyRate(_) => vm_dirty_rate(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                ),
                 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
                 Coredump(_) => vm_coredump(
                     api_notifier,
@@ -169,6 +187,46 @@ impl EndpointHandler for VmActionHandler {
                     api_sender,
                     Arc::new(serde_json::from_slice(body.raw())?),
                 ),
+                Clone(_) => vm_clone(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                ),
+                GuestExec(_) => vm_guest_exec(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                ),
+                GuestFileRead(_) => vm_guest_file_read(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                ),
+                GuestFileWrite(_) => vm_guest_file_write(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                ),
+                GuestShutdown(_) => vm_guest_shutdown(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                ),
+                SetWatchdogAction(_) => vm_set_watchdog_action(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                ),
+                Nmi(_) => vm_nmi(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                ),
+                ChangeMedia(_) => vm_change_media(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                ),
 
                 _ => return Err(HttpError::BadRequest),
             }
@@ -181,6 +239,9 @@ impl EndpointHandler for VmActionHandler {
                 Pause => vm_pause(api_notifier, api_sender),
                 Resume => vm_resume(api_notifier, api_sender),
                 PowerButton => vm_power_button(api_notifier, api_sender),
+                DirtyRate(_) => vm_dirty_rate(api_notifier, api_sender, Arc::default()),
+                GuestShutdown(_) => vm_guest_shutdown(api_notifier, api_sender, Arc::default()),
+                Nmi(_) => vm_nmi(api_notifier, api_sender, Arc::default()),
                 _ => return Err(HttpError::BadRequest),
             }
         }
@@ -196,6 +257,7 @@ impl EndpointHandler for VmActionHandler {
         use VmAction::*;
         match self.action {
             Counters => vm_counters(api_notifier, api_sender).map_err(HttpError::ApiError),
+            VcpuStats => vm_vcpu_stats(api_notifier, api_sender).map_err(HttpError::ApiError),
             _ => Err(HttpError::BadRequest),
         }
     }
@@ -227,6 +289,36 @@ impl EndpointHandler for VmInfo {
     }
 }
 
+// /api/v1/openapi handler
+pub struct OpenApi {}
+
+impl EndpointHandler for OpenApi {
+    fn get_handler(
+        &self,
+        _api_notifier: EventFd,
+        _api_sender: Sender<ApiRequest>,
+        _body: &Option<Body>,
+    ) -> std::result::Result<Option<Body>, HttpError> {
+        Ok(Some(Body::new(serde_json::to_string(&*OPENAPI_SPEC)?)))
+    }
+}
+
+// /api/v1/metrics handler
+pub struct Metrics {}
+
+impl EndpointHandler for Metrics {
+    fn get_handler(
+        &self,
+        api_notifier: EventFd,
+        api_sender: Sender<ApiRequest>,
+        _body: &Option<Body>,
+    ) -> std::result::Result<Option<Body>, HttpError> {
+        let mut metrics = vmm_metrics(api_notifier, api_sender).map_err(HttpError::ApiError)?;
+        metrics.push_str(&crate::metrics::api_metrics());
+        Ok(Some(Body::new(metrics)))
+    }
+}
+
 // /api/v1/vmm.info handler
 pub struct VmmPing {}
 
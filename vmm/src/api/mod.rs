@@ -28,23 +28,34 @@
 //!    response channel Receiver.
 //! 5. The thread handles the response and forwards potential errors.
 
+#[cfg(feature = "dbus_api")]
+pub use self::dbus::{start_dbus_thread, DBusApiOptions};
 pub use self::http::start_http_fd_thread;
 pub use self::http::start_http_path_thread;
+pub use self::http::HttpAccessConfig;
+pub use vm_migration::protocol::Compression;
 
+#[cfg(feature = "dbus_api")]
+pub mod dbus;
 pub mod http;
 pub mod http_endpoint;
 
 use crate::config::{
     DeviceConfig, DiskConfig, FsConfig, NetConfig, PmemConfig, RestoreConfig, UserDeviceConfig,
-    VdpaConfig, VmConfig, VsockConfig,
+    VdpaConfig, VmConfig, VsockConfig, WatchdogAction,
 };
-use crate::device_tree::DeviceTree;
+use crate::device_tree::{DeviceNode, DeviceTree};
 use crate::vm::{Error as VmError, VmState};
 use micro_http::Body;
+use once_cell::sync::Lazy;
+use pci::PciBdf;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::io;
+use std::path::PathBuf;
 use std::sync::mpsc::{channel, RecvError, SendError, Sender};
 use std::sync::{Arc, Mutex};
+use vm_device::{PciBarType, Resource};
 use vm_migration::MigratableError;
 use vmm_sys_util::eventfd::EventFd;
 
@@ -152,6 +163,39 @@ pub enum ApiError {
 
     /// Error triggering power button
     VmPowerButton(VmError),
+
+    /// The key events could not be sent to the VM.
+    VmSendKeys(VmError),
+
+    /// The pointer event could not be sent to the VM.
+    VmSendPointer(VmError),
+
+    /// The dirty memory rate could not be measured.
+    VmDirtyRate(VmError),
+
+    /// The VM could not be cloned.
+    VmClone(MigratableError),
+
+    /// The command could not be run by the guest agent.
+    VmGuestExec(VmError),
+
+    /// The file could not be read by the guest agent.
+    VmGuestFileRead(VmError),
+
+    /// The file could not be written by the guest agent.
+    VmGuestFileWrite(VmError),
+
+    /// The guest agent could not shut the guest down.
+    VmGuestShutdown(VmError),
+
+    /// The watchdog action could not be changed.
+    VmSetWatchdogAction(VmError),
+
+    /// The NMI could not be injected.
+    VmNmi(VmError),
+
+    /// The medium of the SCSI logical unit could not be changed.
+    VmChangeMedia(VmError),
 }
 pub type ApiResult<T> = std::result::Result<T, ApiError>;
 
@@ -161,18 +205,289 @@ pub struct VmInfo {
     pub state: VmState,
     pub memory_actual_size: u64,
     pub device_tree: Option<Arc<Mutex<DeviceTree>>>,
+    /// PCI b/d/f of the devices which were given an alias, indexed by alias.
+    #[serde(default)]
+    pub device_aliases: BTreeMap<String, PciBdf>,
+    /// Placement of the devices, sorted by identifier.
+    #[serde(default)]
+    pub devices: Vec<DeviceInfo>,
+    #[serde(default)]
+    pub memory_zones: Vec<MemoryZoneInfo>,
+    #[serde(default)]
+    pub vcpus: Vec<VcpuInfo>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct AddressRange {
+    pub base: u64,
+    pub size: u64,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct DeviceInfo {
+    pub id: String,
+    pub parent: Option<String>,
+    pub pci_bdf: Option<PciBdf>,
+    /// Guest physical ranges, including the memory BARs
+    pub mmio_ranges: Vec<AddressRange>,
+    /// Port I/O ranges, including the I/O BARs
+    pub io_ranges: Vec<AddressRange>,
+}
+
+impl From<&DeviceNode> for DeviceInfo {
+    fn from(node: &DeviceNode) -> Self {
+        let mut mmio_ranges = Vec::new();
+        let mut io_ranges = Vec::new();
+        for resource in node.resources.iter() {
+            match resource {
+                Resource::PioAddressRange { base, size } => io_ranges.push(AddressRange {
+                    base: *base as u64,
+                    size: *size as u64,
+                }),
+                Resource::MmioAddressRange { base, size } => mmio_ranges.push(AddressRange {
+                    base: *base,
+                    size: *size,
+                }),
+                Resource::PciBar {
+                    base, size, type_, ..
+                } => {
+                    let range = AddressRange {
+                        base: *base,
+                        size: *size,
+                    };
+                    if *type_ == PciBarType::Io {
+                        io_ranges.push(range);
+                    } else {
+                        mmio_ranges.push(range);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        DeviceInfo {
+            id: node.id.clone(),
+            parent: node.parent.clone(),
+            pci_bdf: node.pci_bdf,
+            mmio_ranges,
+            io_ranges,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct MemoryZoneInfo {
+    pub id: String,
+    /// Guest RAM regions backed by the zone
+    pub regions: Vec<AddressRange>,
+    /// Region reserved for hotplugging memory through virtio-mem
+    pub hotplug_region: Option<AddressRange>,
+    /// Memory requested from the hotplug region
+    pub hotplugged_size: u64,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum VcpuStatus {
+    /// Not plugged into the VM
+    Inactive,
+    Running,
+    Paused,
+    /// Waiting for the guest to online the hotplugged vCPU
+    Inserting,
+    /// Waiting for the guest to eject the vCPU
+    Removing,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct VcpuInfo {
+    pub id: u8,
+    pub status: VcpuStatus,
+    /// Exits from the guest handled by the vCPU thread
+    pub exits: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct VcpuStats {
+    pub id: u8,
+    /// Exits from the guest handled by the vCPU thread
+    pub exits: u64,
+    /// Port I/O exits
+    pub io: u64,
+    /// MMIO exits
+    pub mmio: u64,
+    /// HLT exits
+    pub hlt: u64,
+    /// Guest physical address faults reported to the VMM
+    pub ept_violation: u64,
+}
+
+/// Default time over which the dirty memory rate is measured.
+pub const DEFAULT_DIRTY_RATE_PERIOD_MS: u64 = 1000;
+
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmDirtyRateData {
+    /// Time over which the memory dirtied by the guest is measured
+    #[serde(default)]
+    pub period_ms: Option<u64>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct MemoryRegionDirtyRate {
+    /// Guest physical address of the memory region
+    pub start: u64,
+    pub size: u64,
+    /// Memory dirtied in MB/s
+    pub dirty_rate: f64,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct VmDirtyRate {
+    pub period_ms: u64,
+    /// Memory dirtied across all the regions in MB/s
+    pub dirty_rate: f64,
+    pub regions: Vec<MemoryRegionDirtyRate>,
+}
+
+/// Default time after which a command run by the guest agent is killed.
+pub const DEFAULT_GUEST_EXEC_TIMEOUT_MS: u64 = 30_000;
+
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmGuestExecData {
+    /// Path of the program run in the guest
+    pub path: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Environment of the program, as "NAME=value" strings
+    #[serde(default)]
+    pub env: Vec<String>,
+    /// Base64 encoded data written to the standard input of the program
+    #[serde(default)]
+    pub input_data: Option<String>,
+    /// Time after which the program is killed
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct VmGuestExec {
+    /// Exit code of the program, unless it was killed by a signal
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    #[serde(default)]
+    pub signal: Option<i32>,
+    /// Base64 encoded standard output of the program
+    pub out_data: String,
+    /// Base64 encoded standard error of the program
+    pub err_data: String,
+}
+
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmGuestFileReadData {
+    pub path: String,
+    #[serde(default)]
+    pub offset: Option<u64>,
+    /// Number of bytes to read, up to the end of the file if not set
+    #[serde(default)]
+    pub count: Option<u64>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct VmGuestFileRead {
+    /// Base64 encoded content read from the file
+    pub data: String,
+    /// Whether the end of the file was reached
+    pub eof: bool,
+}
+
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmGuestFileWriteData {
+    pub path: String,
+    /// Base64 encoded content written to the file
+    pub data: String,
+    /// Append to the file rather than replacing its content
+    #[serde(default)]
+    pub append: bool,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct VmGuestFileWrite {
+    /// Number of bytes written
+    pub count: u64,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, Default, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GuestShutdownMode {
+    #[default]
+    Powerdown,
+    Reboot,
+    Halt,
+}
+
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmGuestShutdownData {
+    #[serde(default)]
+    pub mode: GuestShutdownMode,
+}
+
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmNmiData {
+    /// vCPU the NMI is injected into, every vCPU when not specified.
+    #[serde(default)]
+    pub cpu: Option<u32>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmChangeMediaData {
+    /// Identifier of the virtio-scsi controller
+    pub controller: String,
+    /// Logical unit number of the CD-ROM drive
+    pub lun: u16,
+    /// Image inserted in the drive, which is emptied when not specified.
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmSetWatchdogActionData {
+    pub action: WatchdogAction,
 }
 
 #[derive(Clone, Deserialize, Serialize)]
 pub struct VmmPingResponse {
     pub version: String,
+    /// Version of the HTTP API, as found in its OpenAPI specification
+    #[serde(default)]
+    pub api_version: String,
+}
+
+/// OpenAPI specification of the HTTP API exposed by this binary.
+pub static OPENAPI_SPEC: Lazy<serde_json::Value> = Lazy::new(|| {
+    let spec: serde_yaml::Value =
+        serde_yaml::from_str(include_str!("openapi/cloud-hypervisor.yaml"))
+            .expect("Invalid OpenAPI specification");
+    // Going through the serializer turns the response codes used as keys
+    // into strings, as expected from JSON objects.
+    serde_json::to_value(spec).expect("Invalid OpenAPI specification")
+});
+
+/// Version of the HTTP API exposed by this binary.
+pub fn api_version() -> String {
+    OPENAPI_SPEC["info"]["version"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string()
 }
 
 #[derive(Clone, Deserialize, Serialize, Default, Debug)]
 pub struct VmResizeData {
-    pub desired_vcpus: Option<u8>,
+    pub desired_vcpus: Option<u32>,
     pub desired_ram: Option<u64>,
     pub desired_balloon: Option<u64>,
+    /// Specific vCPUs to eject, as an alternative to `desired_vcpus` which
+    /// always ejects the last ones.
+    #[serde(default)]
+    pub removed_vcpus: Option<Vec<u32>>,
 }
 
 #[derive(Clone, Deserialize, Serialize, Default, Debug)]
@@ -186,6 +501,45 @@ pub struct VmRemoveDeviceData {
     pub id: String,
 }
 
+/// Default time input keys and buttons are held down for.
+pub const DEFAULT_INPUT_HOLD_TIME_MS: u64 = 100;
+/// Maximum time input keys and buttons can be held down for.
+pub const MAX_INPUT_HOLD_TIME_MS: u64 = 10_000;
+
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmSendKeysData {
+    /// Key combinations sent one after the other, each made of key names
+    /// joined with '-', e.g. "ctrl-alt-delete".
+    pub keys: Vec<String>,
+    /// How long each combination is held down, and how long to wait before
+    /// the next one.
+    #[serde(default)]
+    pub hold_time_ms: Option<u64>,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PointerButton {
+    Left,
+    Right,
+    Middle,
+}
+
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmSendPointerData {
+    /// Relative horizontal motion
+    #[serde(default)]
+    pub dx: i32,
+    /// Relative vertical motion, growing downwards
+    #[serde(default)]
+    pub dy: i32,
+    /// Buttons held down during the motion, released after `hold_time_ms`
+    #[serde(default)]
+    pub buttons: Vec<PointerButton>,
+    #[serde(default)]
+    pub hold_time_ms: Option<u64>,
+}
+
 #[derive(Clone, Deserialize, Serialize, Default, Debug)]
 pub struct VmSnapshotConfig {
     /// The snapshot destination URL
@@ -202,6 +556,10 @@ pub struct VmCoredumpData {
 pub struct VmReceiveMigrationData {
     /// URL for the reception of migration state
     pub receiver_url: String,
+    /// Directory holding the CA and server certificates to encrypt the
+    /// migration with TLS
+    #[serde(default)]
+    pub tls_dir: Option<PathBuf>,
 }
 
 #[derive(Clone, Deserialize, Serialize, Default, Debug)]
@@ -211,6 +569,31 @@ pub struct VmSendMigrationData {
     /// Send memory across socket without copying
     #[serde(default)]
     pub local: bool,
+    /// Switch to the destination after a single memory pass, fetching the
+    /// remaining memory on demand
+    #[serde(default)]
+    pub postcopy: bool,
+    /// Number of connections transferring the memory in parallel. The memory
+    /// goes through the main connection when lower than 2.
+    #[serde(default)]
+    pub connections: u32,
+    /// Throttle the vCPUs when the memory is dirtied faster than it can be
+    /// sent, so that the migration converges
+    #[serde(default)]
+    pub auto_converge: bool,
+    /// Algorithm compressing the memory, if supported by the destination
+    #[serde(default)]
+    pub compression: Option<Compression>,
+    /// Directory holding the CA and client certificates to encrypt the
+    /// migration with TLS
+    #[serde(default)]
+    pub tls_dir: Option<PathBuf>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmCloneData {
+    /// URL of the VMM receiving the clone, which must be local
+    pub destination_url: String,
 }
 
 pub enum ApiResponsePayload {
@@ -223,6 +606,9 @@ pub enum ApiResponsePayload {
     /// Vmm ping response
     VmmPing(VmmPingResponse),
 
+    /// Metrics in the Prometheus text format
+    VmmMetrics(String),
+
     /// Vm action response
     VmAction(Option<Vec<u8>>),
 }
@@ -256,6 +642,9 @@ pub enum ApiRequest {
     /// Request the VMM API server status
     VmmPing(Sender<ApiResponse>),
 
+    /// Request the metrics of the VMM and its VM.
+    VmmMetrics(Sender<ApiResponse>),
+
     /// Pause a VM.
     VmPause(Sender<ApiResponse>),
 
@@ -331,6 +720,42 @@ pub enum ApiRequest {
 
     // Trigger power button
     VmPowerButton(Sender<ApiResponse>),
+
+    /// Inject key events
+    VmSendKeys(Arc<VmSendKeysData>, Sender<ApiResponse>),
+
+    /// Inject a pointer event
+    VmSendPointer(Arc<VmSendPointerData>, Sender<ApiResponse>),
+
+    /// Measure the rate at which the guest dirties its memory
+    VmDirtyRate(Arc<VmDirtyRateData>, Sender<ApiResponse>),
+
+    /// Clone the paused VM, sharing its memory copy-on-write
+    VmClone(Arc<VmCloneData>, Sender<ApiResponse>),
+
+    /// Run a command through the guest agent
+    VmGuestExec(Arc<VmGuestExecData>, Sender<ApiResponse>),
+
+    /// Read a guest file through the guest agent
+    VmGuestFileRead(Arc<VmGuestFileReadData>, Sender<ApiResponse>),
+
+    /// Write a guest file through the guest agent
+    VmGuestFileWrite(Arc<VmGuestFileWriteData>, Sender<ApiResponse>),
+
+    /// Shut the guest down through the guest agent
+    VmGuestShutdown(Arc<VmGuestShutdownData>, Sender<ApiResponse>),
+
+    /// Change the action taken on watchdog expiry
+    VmSetWatchdogAction(Arc<VmSetWatchdogActionData>, Sender<ApiResponse>),
+
+    /// Inject a Non-Maskable Interrupt
+    VmNmi(Arc<VmNmiData>, Sender<ApiResponse>),
+
+    /// Get the exit statistics of each vCPU
+    VmVcpuStats(Sender<ApiResponse>),
+
+    /// Change the medium of a SCSI CD-ROM drive
+    VmChangeMedia(Arc<VmChangeMediaData>, Sender<ApiResponse>),
 }
 
 pub fn vm_create(
@@ -427,6 +852,42 @@ pub enum VmAction {
 
     /// Power Button for clean shutdown
     PowerButton,
+
+    /// Inject key events
+    SendKeys(Arc<VmSendKeysData>),
+
+    /// Inject a pointer event
+    SendPointer(Arc<VmSendPointerData>),
+
+    /// Measure the dirty memory rate
+    DirtyRate(Arc<VmDirtyRateData>),
+
+    /// Clone the VM
+    Clone(Arc<VmCloneData>),
+
+    /// Run a command in the guest
+    GuestExec(Arc<VmGuestExecData>),
+
+    /// Read a guest file
+    GuestFileRead(Arc<VmGuestFileReadData>),
+
+    /// Write a guest file
+    GuestFileWrite(Arc<VmGuestFileWriteData>),
+
+    /// Shut the guest down from the inside
+    GuestShutdown(Arc<VmGuestShutdownData>),
+
+    /// Change the watchdog action
+    SetWatchdogAction(Arc<VmSetWatchdogActionData>),
+
+    /// Inject an NMI
+    Nmi(Arc<VmNmiData>),
+
+    /// Return the vCPU exit statistics
+    VcpuStats,
+
+    /// Insert or eject a CD-ROM
+    ChangeMedia(Arc<VmChangeMediaData>),
 }
 
 fn vm_action(
@@ -463,6 +924,18 @@ fn vm_action(
         ReceiveMigration(v) => ApiRequest::VmReceiveMigration(v, response_sender),
         SendMigration(v) => ApiRequest::VmSendMigration(v, response_sender),
         PowerButton => ApiRequest::VmPowerButton(response_sender),
+        SendKeys(v) => ApiRequest::VmSendKeys(v, response_sender),
+        SendPointer(v) => ApiRequest::VmSendPointer(v, response_sender),
+        DirtyRate(v) => ApiRequest::VmDirtyRate(v, response_sender),
+        Clone(v) => ApiRequest::VmClone(v, response_sender),
+        GuestExec(v) => ApiRequest::VmGuestExec(v, response_sender),
+        GuestFileRead(v) => ApiRequest::VmGuestFileRead(v, response_sender),
+        GuestFileWrite(v) => ApiRequest::VmGuestFileWrite(v, response_sender),
+        GuestShutdown(v) => ApiRequest::VmGuestShutdown(v, response_sender),
+        SetWatchdogAction(v) => ApiRequest::VmSetWatchdogAction(v, response_sender),
+        Nmi(v) => ApiRequest::VmNmi(v, response_sender),
+        VcpuStats => ApiRequest::VmVcpuStats(response_sender),
+        ChangeMedia(v) => ApiRequest::VmChangeMedia(v, response_sender),
     };
 
     // Send the VM request.
@@ -513,6 +986,90 @@ pub fn vm_power_button(
     vm_action(api_evt, api_sender, VmAction::PowerButton)
 }
 
+pub fn vm_send_keys(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmSendKeysData>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::SendKeys(data))
+}
+
+pub fn vm_send_pointer(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmSendPointerData>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::SendPointer(data))
+}
+
+pub fn vm_dirty_rate(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmDirtyRateData>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::DirtyRate(data))
+}
+
+pub fn vm_guest_exec(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmGuestExecData>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::GuestExec(data))
+}
+
+pub fn vm_guest_file_read(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmGuestFileReadData>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::GuestFileRead(data))
+}
+
+pub fn vm_guest_file_write(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmGuestFileWriteData>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::GuestFileWrite(data))
+}
+
+pub fn vm_guest_shutdown(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmGuestShutdownData>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::GuestShutdown(data))
+}
+
+pub fn vm_set_watchdog_action(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmSetWatchdogActionData>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::SetWatchdogAction(data))
+}
+
+pub fn vm_nmi(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmNmiData>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::Nmi(data))
+}
+
+pub fn vm_vcpu_stats(api_evt: EventFd, api_sender: Sender<ApiRequest>) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::VcpuStats)
+}
+
+pub fn vm_change_media(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmChangeMediaData>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::ChangeMedia(data))
+}
+
 pub fn vm_receive_migration(
     api_evt: EventFd,
     api_sender: Sender<ApiRequest>,
@@ -529,6 +1086,14 @@ pub fn vm_send_migration(
     vm_action(api_evt, api_sender, VmAction::SendMigration(data))
 }
 
+pub fn vm_clone(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmCloneData>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::Clone(data))
+}
+
 pub fn vm_snapshot(
     api_evt: EventFd,
     api_sender: Sender<ApiRequest>,
@@ -587,6 +1152,22 @@ pub fn vmm_ping(api_evt: EventFd, api_sender: Sender<ApiRequest>) -> ApiResult<V
     }
 }
 
+pub fn vmm_metrics(api_evt: EventFd, api_sender: Sender<ApiRequest>) -> ApiResult<String> {
+    let (response_sender, response_receiver) = channel();
+
+    api_sender
+        .send(ApiRequest::VmmMetrics(response_sender))
+        .map_err(ApiError::RequestSend)?;
+    api_evt.write(1).map_err(ApiError::EventFdWrite)?;
+
+    let metrics = response_receiver.recv().map_err(ApiError::ResponseRecv)??;
+
+    match metrics {
+        ApiResponsePayload::VmmMetrics(metrics) => Ok(metrics),
+        _ => Err(ApiError::ResponsePayloadType),
+    }
+}
+
 pub fn vmm_shutdown(api_evt: EventFd, api_sender: Sender<ApiRequest>) -> ApiResult<()> {
     let (response_sender, response_receiver) = channel();
 
@@ -0,0 +1,231 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Controller adjusting the size of the balloon automatically.
+//!
+//! The balloon is inflated, giving memory back to the host, while the host is
+//! under memory pressure and the guest has memory to spare. It is deflated
+//! when the guest runs short of memory, or progressively once the host isn't
+//! under pressure anymore. The host memory pressure is read from the Pressure
+//! Stall Information (PSI) of the host kernel, and the guest memory usage
+//! from the statistics the guest reports through the balloon.
+
+use std::cmp::{max, min};
+use std::fs;
+use std::io;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use virtio_devices::{Balloon, BalloonStats};
+
+// Time between two adjustments of the balloon.
+const POLICY_INTERVAL: Duration = Duration::from_secs(5);
+
+const PSI_MEMORY_PATH: &str = "/proc/pressure/memory";
+
+// Share of the time, in percent over the last 10 seconds, during which some
+// tasks of the host were stalled on memory. The balloon is inflated above the
+// high threshold, and deflated below the low one.
+const HOST_PRESSURE_HIGH: f64 = 10.0;
+const HOST_PRESSURE_LOW: f64 = 1.0;
+
+// Memory kept available to the guest: an eighth of its memory, and never less
+// than 128 MiB.
+const GUEST_RESERVE_SHIFT: u32 = 3;
+const GUEST_RESERVE_MIN: u64 = 128 << 20;
+
+// Largest change of the balloon size in one adjustment, a sixteenth of the
+// guest memory.
+const STEP_SHIFT: u32 = 4;
+
+// Number of intervals the guest is given to reach the size of the balloon,
+// after which the balloon is adjusted from the size the guest reached.
+const MAX_WAIT_INTERVALS: u32 = 6;
+
+// Returns the share of the time some tasks were stalled on memory over the
+// last 10 seconds, from the content of the PSI memory file.
+fn parse_memory_pressure(psi: &str) -> Option<f64> {
+    let some = psi.lines().find(|l| l.starts_with("some "))?;
+    some.split_whitespace()
+        .find_map(|field| field.strip_prefix("avg10="))?
+        .parse()
+        .ok()
+}
+
+fn host_memory_pressure() -> io::Result<f64> {
+    let psi = fs::read_to_string(PSI_MEMORY_PATH)?;
+    parse_memory_pressure(&psi).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid content of {PSI_MEMORY_PATH}: {psi}"),
+        )
+    })
+}
+
+// Returns the size the balloon should be resized to, if it has to change.
+fn next_size(
+    current: u64,
+    host_pressure: f64,
+    stats: &BalloonStats,
+    min_size: u64,
+    max_size: u64,
+) -> Option<u64> {
+    let total = stats.total_memory?;
+    let available = stats.available_memory.or(stats.free_memory)?;
+
+    let reserve = max(total >> GUEST_RESERVE_SHIFT, GUEST_RESERVE_MIN);
+    let step = total >> STEP_SHIFT;
+
+    let target = if available < reserve {
+        // The guest comes first, even when the host is under pressure.
+        current.saturating_sub(min(step, reserve - available))
+    } else if host_pressure >= HOST_PRESSURE_HIGH {
+        current.saturating_add(min(step, available - reserve))
+    } else if host_pressure < HOST_PRESSURE_LOW {
+        current.saturating_sub(step)
+    } else {
+        current
+    };
+    let target = target.clamp(min_size, max(min_size, max_size));
+
+    (target != current).then_some(target)
+}
+
+pub struct BalloonPolicy {
+    balloon: Arc<Mutex<Balloon>>,
+    min_size: u64,
+    max_size: u64,
+    // Number of intervals spent waiting for the guest to reach the size of
+    // the balloon.
+    waiting: u32,
+}
+
+impl BalloonPolicy {
+    pub fn new(balloon: Arc<Mutex<Balloon>>, min_size: u64, max_size: Option<u64>) -> Self {
+        BalloonPolicy {
+            balloon,
+            min_size,
+            max_size: max_size.unwrap_or(u64::MAX),
+            waiting: 0,
+        }
+    }
+
+    /// Adjusts the balloon periodically, until `stop` is disconnected.
+    pub fn run(&mut self, stop: Receiver<()>) {
+        let mut psi_available = true;
+        while let Err(RecvTimeoutError::Timeout) = stop.recv_timeout(POLICY_INTERVAL) {
+            let host_pressure = match host_memory_pressure() {
+                Ok(pressure) => pressure,
+                Err(e) => {
+                    if psi_available {
+                        warn!(
+                            "Host memory pressure unavailable, the balloon won't be inflated: {}",
+                            e
+                        );
+                        psi_available = false;
+                    }
+                    0.0
+                }
+            };
+
+            self.adjust(host_pressure);
+        }
+    }
+
+    fn adjust(&mut self, host_pressure: f64) {
+        let mut balloon = self.balloon.lock().unwrap();
+        if balloon.is_paused() {
+            return;
+        }
+
+        let stats = match balloon.stats() {
+            Some(stats) => stats,
+            None => return,
+        };
+
+        // Let the guest reach the previous size first, unless it can't.
+        let (target, actual) = (balloon.get_target(), balloon.get_actual());
+        if target != actual && self.waiting < MAX_WAIT_INTERVALS {
+            self.waiting += 1;
+            return;
+        }
+        self.waiting = 0;
+        let current = actual;
+
+        if let Some(size) = next_size(current, host_pressure, &stats, self.min_size, self.max_size)
+        {
+            debug!(
+                "Resizing balloon from {} to {} bytes (host memory pressure {}%, guest stats {:?})",
+                current, size, host_pressure, stats
+            );
+            if let Err(e) = balloon.resize(size) {
+                error!("Failed to resize balloon: {:?}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIB: u64 = 1 << 30;
+
+    fn stats(total: u64, available: u64) -> BalloonStats {
+        BalloonStats {
+            total_memory: Some(total),
+            available_memory: Some(available),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_memory_pressure() {
+        let psi = "some avg10=12.50 avg60=3.00 avg300=0.50 total=123456\n\
+                   full avg10=4.00 avg60=1.00 avg300=0.10 total=23456\n";
+        assert_eq!(parse_memory_pressure(psi), Some(12.5));
+        assert_eq!(parse_memory_pressure("full avg10=4.00"), None);
+        assert_eq!(parse_memory_pressure("some avg10=abc"), None);
+    }
+
+    #[test]
+    fn test_next_size() {
+        // Host under pressure, the guest has plenty of memory available.
+        assert_eq!(
+            next_size(0, 20.0, &stats(4 * GIB, 3 * GIB), 0, u64::MAX),
+            Some(GIB / 4)
+        );
+        // The inflation doesn't eat into the reserve of the guest.
+        assert_eq!(
+            next_size(0, 20.0, &stats(4 * GIB, GIB / 2 + GIB / 8), 0, u64::MAX),
+            Some(GIB / 8)
+        );
+        // Nor goes over the maximum size.
+        assert_eq!(next_size(GIB, 20.0, &stats(4 * GIB, 3 * GIB), 0, GIB), None);
+        // The guest is short of memory, even if the host is under pressure.
+        assert_eq!(
+            next_size(GIB, 20.0, &stats(3 * GIB, GIB / 4), 0, u64::MAX),
+            Some(GIB - GIB / 8)
+        );
+        // The host isn't under pressure anymore, down to the minimum size.
+        assert_eq!(
+            next_size(GIB, 0.0, &stats(3 * GIB, 2 * GIB), 0, u64::MAX),
+            Some(GIB - 3 * GIB / 16)
+        );
+        assert_eq!(
+            next_size(GIB, 0.0, &stats(3 * GIB, 2 * GIB), GIB, u64::MAX),
+            None
+        );
+        // Moderate host pressure keeps the balloon as it is.
+        assert_eq!(
+            next_size(GIB, 5.0, &stats(3 * GIB, 2 * GIB), 0, u64::MAX),
+            None
+        );
+        // Nothing is done without the guest statistics.
+        assert_eq!(
+            next_size(0, 20.0, &BalloonStats::default(), 0, u64::MAX),
+            None
+        );
+    }
+}
@@ -0,0 +1,324 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Generation of the cloud-init NoCloud seed.
+//!
+//! The NoCloud datasource looks for a filesystem labelled `CIDATA` holding
+//! the `user-data`, `meta-data` and optional `network-config` files. The
+//! files are packed into a FAT12 image built in memory, which is exposed to
+//! the guest as a read-only disk.
+
+use crate::config::CloudInitConfig;
+use std::ffi::CStr;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::unix::io::{FromRawFd, RawFd};
+
+// Used when no meta-data is provided, cloud-init requires an instance id
+const DEFAULT_META_DATA: &[u8] = b"instance-id: iid-cloud-hypervisor\n";
+
+const SECTOR_SIZE: usize = 512;
+const RESERVED_SECTORS: usize = 1;
+const NUM_FATS: usize = 2;
+const ROOT_ENTRIES: usize = 16;
+const DIR_ENTRY_SIZE: usize = 32;
+// A FAT12 filesystem can't have more clusters than that
+const MAX_CLUSTERS: usize = 4084;
+const MEDIA_DESCRIPTOR: u8 = 0xf8;
+const VOLUME_LABEL: &[u8; 11] = b"CIDATA     ";
+
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_LONG_NAME: u8 = 0x0f;
+const LFN_LAST_ENTRY: u8 = 0x40;
+const LFN_CHARS_PER_ENTRY: usize = 13;
+
+// 2000-01-01, so that the image content only depends on the files
+const FAT_DATE: u16 = (20 << 9) | (1 << 5) | 1;
+
+fn set_fat12_entry(fat: &mut [u8], cluster: usize, value: u16) {
+    let offset = cluster * 3 / 2;
+    if cluster % 2 == 0 {
+        fat[offset] = value as u8;
+        fat[offset + 1] = (fat[offset + 1] & 0xf0) | ((value >> 8) as u8 & 0x0f);
+    } else {
+        fat[offset] = (fat[offset] & 0x0f) | ((value << 4) as u8 & 0xf0);
+        fat[offset + 1] = (value >> 4) as u8;
+    }
+}
+
+// 8.3 alias of a long file name, e.g. "USER-D~1" for "user-data"
+fn short_name(name: &str) -> [u8; 11] {
+    let mut short = [b' '; 11];
+    let base: Vec<u8> = name
+        .bytes()
+        .filter(|b| b.is_ascii_alphanumeric() || *b == b'-')
+        .map(|b| b.to_ascii_uppercase())
+        .take(6)
+        .collect();
+    short[..base.len()].copy_from_slice(&base);
+    short[base.len()..base.len() + 2].copy_from_slice(b"~1");
+    short
+}
+
+fn lfn_checksum(short: &[u8; 11]) -> u8 {
+    short
+        .iter()
+        .fold(0u8, |sum, c| sum.rotate_right(1).wrapping_add(*c))
+}
+
+// Long file name entries, in the order they're stored on disk
+fn lfn_entries(name: &str, short: &[u8; 11]) -> Vec<[u8; DIR_ENTRY_SIZE]> {
+    let mut chars: Vec<u16> = name.encode_utf16().collect();
+    if chars.len() % LFN_CHARS_PER_ENTRY != 0 {
+        chars.push(0);
+    }
+    while chars.len() % LFN_CHARS_PER_ENTRY != 0 {
+        chars.push(0xffff);
+    }
+
+    let checksum = lfn_checksum(short);
+    let count = chars.len() / LFN_CHARS_PER_ENTRY;
+    let mut entries = Vec::new();
+    for (i, part) in chars.chunks(LFN_CHARS_PER_ENTRY).enumerate() {
+        let mut entry = [0u8; DIR_ENTRY_SIZE];
+        entry[0] = i as u8 + 1;
+        if i + 1 == count {
+            entry[0] |= LFN_LAST_ENTRY;
+        }
+        entry[11] = ATTR_LONG_NAME;
+        entry[13] = checksum;
+        // The characters are split across three fields of the entry
+        let offsets = (1..11)
+            .step_by(2)
+            .chain((14..26).step_by(2))
+            .chain((28..32).step_by(2));
+        for (c, offset) in part.iter().zip(offsets) {
+            entry[offset..offset + 2].copy_from_slice(&c.to_le_bytes());
+        }
+        entries.push(entry);
+    }
+    entries.reverse();
+
+    entries
+}
+
+fn short_entry(name: &[u8; 11], attr: u8, cluster: u16, size: u32) -> [u8; DIR_ENTRY_SIZE] {
+    let mut entry = [0u8; DIR_ENTRY_SIZE];
+    entry[0..11].copy_from_slice(name);
+    entry[11] = attr;
+    entry[16..18].copy_from_slice(&FAT_DATE.to_le_bytes());
+    entry[18..20].copy_from_slice(&FAT_DATE.to_le_bytes());
+    entry[24..26].copy_from_slice(&FAT_DATE.to_le_bytes());
+    entry[26..28].copy_from_slice(&cluster.to_le_bytes());
+    entry[28..32].copy_from_slice(&size.to_le_bytes());
+    entry
+}
+
+/// Builds a FAT12 image labelled `CIDATA` holding the given files.
+pub fn build_image(files: &[(&str, &[u8])]) -> io::Result<Vec<u8>> {
+    let clusters_for = |cluster_size: usize| -> usize {
+        files
+            .iter()
+            .map(|(_, data)| (data.len() + cluster_size - 1) / cluster_size)
+            .sum::<usize>()
+            .max(1)
+    };
+
+    let mut sectors_per_cluster = 1;
+    while clusters_for(sectors_per_cluster * SECTOR_SIZE) > MAX_CLUSTERS {
+        sectors_per_cluster *= 2;
+        if sectors_per_cluster > 128 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cloud-init files are too large",
+            ));
+        }
+    }
+    let cluster_size = sectors_per_cluster * SECTOR_SIZE;
+    let clusters = clusters_for(cluster_size);
+
+    let fat_sectors = ((clusters + 2) * 3 / 2 + 1 + SECTOR_SIZE - 1) / SECTOR_SIZE;
+    let root_sectors = ROOT_ENTRIES * DIR_ENTRY_SIZE / SECTOR_SIZE;
+    let data_start = RESERVED_SECTORS + NUM_FATS * fat_sectors + root_sectors;
+    let total_sectors = data_start + clusters * sectors_per_cluster;
+
+    let mut image = vec![0u8; total_sectors * SECTOR_SIZE];
+
+    // Boot sector, with the extended BIOS parameter block
+    let boot = &mut image[..SECTOR_SIZE];
+    boot[0..3].copy_from_slice(&[0xeb, 0x3c, 0x90]);
+    boot[3..11].copy_from_slice(b"CLOUDHV ");
+    boot[11..13].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+    boot[13] = sectors_per_cluster as u8;
+    boot[14..16].copy_from_slice(&(RESERVED_SECTORS as u16).to_le_bytes());
+    boot[16] = NUM_FATS as u8;
+    boot[17..19].copy_from_slice(&(ROOT_ENTRIES as u16).to_le_bytes());
+    if total_sectors <= u16::MAX as usize {
+        boot[19..21].copy_from_slice(&(total_sectors as u16).to_le_bytes());
+    } else {
+        boot[32..36].copy_from_slice(&(total_sectors as u32).to_le_bytes());
+    }
+    boot[21] = MEDIA_DESCRIPTOR;
+    boot[22..24].copy_from_slice(&(fat_sectors as u16).to_le_bytes());
+    boot[24..26].copy_from_slice(&32u16.to_le_bytes());
+    boot[26..28].copy_from_slice(&64u16.to_le_bytes());
+    boot[36] = 0x80;
+    boot[38] = 0x29;
+    boot[39..43].copy_from_slice(&0x4349_4441u32.to_le_bytes());
+    boot[43..54].copy_from_slice(VOLUME_LABEL);
+    boot[54..62].copy_from_slice(b"FAT12   ");
+    boot[510..512].copy_from_slice(&[0x55, 0xaa]);
+
+    let mut fat = vec![0u8; fat_sectors * SECTOR_SIZE];
+    set_fat12_entry(&mut fat, 0, 0xf00 | MEDIA_DESCRIPTOR as u16);
+    set_fat12_entry(&mut fat, 1, 0xfff);
+
+    let mut root = vec![short_entry(VOLUME_LABEL, ATTR_VOLUME_ID, 0, 0)];
+    let mut next_cluster = 2;
+    for (name, data) in files {
+        let count = (data.len() + cluster_size - 1) / cluster_size;
+        let first_cluster = if count > 0 { next_cluster } else { 0 };
+        for cluster in next_cluster..next_cluster + count {
+            let next = if cluster + 1 == next_cluster + count {
+                0xfff
+            } else {
+                cluster as u16 + 1
+            };
+            set_fat12_entry(&mut fat, cluster, next);
+        }
+
+        let offset = (data_start + (next_cluster - 2) * sectors_per_cluster) * SECTOR_SIZE;
+        image[offset..offset + data.len()].copy_from_slice(data);
+        next_cluster += count;
+
+        let short = short_name(name);
+        root.extend(lfn_entries(name, &short));
+        root.push(short_entry(
+            &short,
+            ATTR_ARCHIVE,
+            first_cluster as u16,
+            data.len() as u32,
+        ));
+    }
+
+    if root.len() > ROOT_ENTRIES {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "too many cloud-init files",
+        ));
+    }
+
+    for i in 0..NUM_FATS {
+        let offset = (RESERVED_SECTORS + i * fat_sectors) * SECTOR_SIZE;
+        image[offset..offset + fat.len()].copy_from_slice(&fat);
+    }
+    let offset = (RESERVED_SECTORS + NUM_FATS * fat_sectors) * SECTOR_SIZE;
+    for (i, entry) in root.iter().enumerate() {
+        let offset = offset + i * DIR_ENTRY_SIZE;
+        image[offset..offset + DIR_ENTRY_SIZE].copy_from_slice(entry);
+    }
+
+    Ok(image)
+}
+
+/// Builds the NoCloud seed described by the configuration into an anonymous
+/// memory file.
+pub fn create_seed(config: &CloudInitConfig) -> io::Result<File> {
+    let user_data = fs::read(&config.user_data)?;
+    let meta_data = match &config.meta_data {
+        Some(path) => fs::read(path)?,
+        None => DEFAULT_META_DATA.to_vec(),
+    };
+    let network_config = config.network_config.as_ref().map(fs::read).transpose()?;
+
+    let mut files = vec![
+        ("user-data", user_data.as_slice()),
+        ("meta-data", meta_data.as_slice()),
+    ];
+    if let Some(network_config) = &network_config {
+        files.push(("network-config", network_config.as_slice()));
+    }
+    let image = build_image(&files)?;
+
+    let name = CStr::from_bytes_with_nul(b"ch_cloud_init\0").unwrap();
+    // SAFETY: FFI call with a valid name
+    let fd = unsafe { libc::syscall(libc::SYS_memfd_create, name.as_ptr(), libc::MFD_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: fd is a valid file descriptor we own
+    let mut file = unsafe { File::from_raw_fd(fd as RawFd) };
+    file.write_all(&image)?;
+
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fat12_entry(fat: &[u8], cluster: usize) -> u16 {
+        let offset = cluster * 3 / 2;
+        let value = u16::from_le_bytes([fat[offset], fat[offset + 1]]);
+        if cluster % 2 == 0 {
+            value & 0xfff
+        } else {
+            value >> 4
+        }
+    }
+
+    #[test]
+    fn test_fat12_entries() {
+        let mut fat = vec![0u8; 6];
+        set_fat12_entry(&mut fat, 2, 0x123);
+        set_fat12_entry(&mut fat, 3, 0xabc);
+        assert_eq!(fat12_entry(&fat, 2), 0x123);
+        assert_eq!(fat12_entry(&fat, 3), 0xabc);
+    }
+
+    #[test]
+    fn test_short_name() {
+        assert_eq!(&short_name("user-data"), b"USER-D~1   ");
+        assert_eq!(&short_name("network-config"), b"NETWOR~1   ");
+    }
+
+    #[test]
+    fn test_build_image() {
+        let user_data = vec![b'a'; 1000];
+        let image = build_image(&[
+            ("user-data", user_data.as_slice()),
+            ("meta-data", &b"meta"[..]),
+        ])
+        .unwrap();
+        assert_eq!(&image[510..512], &[0x55, 0xaa]);
+        assert_eq!(&image[43..54], VOLUME_LABEL);
+
+        let fat_sectors = u16::from_le_bytes([image[22], image[23]]) as usize;
+        let fat = &image[SECTOR_SIZE..SECTOR_SIZE * (1 + fat_sectors)];
+        // user-data spans two clusters, meta-data a single one
+        assert_eq!(fat12_entry(fat, 2), 3);
+        assert_eq!(fat12_entry(fat, 3), 0xfff);
+        assert_eq!(fat12_entry(fat, 4), 0xfff);
+
+        let root = (1 + 2 * fat_sectors) * SECTOR_SIZE;
+        let entries: Vec<&[u8]> = image[root..root + SECTOR_SIZE]
+            .chunks(DIR_ENTRY_SIZE)
+            .collect();
+        assert_eq!(&entries[0][0..11], VOLUME_LABEL);
+        assert_eq!(entries[1][11], ATTR_LONG_NAME);
+        assert_eq!(&entries[2][0..11], b"USER-D~1   ");
+        assert_eq!(entries[1][13], lfn_checksum(&short_name("user-data")));
+        assert_eq!(&entries[4][0..11], b"META-D~1   ");
+        assert_eq!(&entries[4][28..32], &4u32.to_le_bytes());
+
+        let data = root + SECTOR_SIZE;
+        assert_eq!(&image[data..data + 1000], user_data.as_slice());
+        assert_eq!(
+            &image[data + 2 * SECTOR_SIZE..data + 2 * SECTOR_SIZE + 4],
+            b"meta"
+        );
+    }
+}
@@ -11,13 +11,15 @@ use serde::{Deserialize, Serialize};
 use std::collections::{BTreeSet, HashMap};
 use std::convert::From;
 use std::fmt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::result;
 use std::str::FromStr;
 use thiserror::Error;
 use virtio_devices::{RateLimiterConfig, TokenBucketConfig};
 
 const MAX_NUM_PCI_SEGMENTS: u16 = 16;
+// Bound by the 3 hexadecimal digits naming the vCPUs in the ACPI tables
+const MAX_NUM_VCPUS: u32 = 4096;
 
 /// Errors associated with VM configuration parameters.
 #[derive(Debug, Error)]
@@ -26,6 +28,8 @@ pub enum Error {
     ParseFsTagMissing,
     /// Filesystem socket is missing
     ParseFsSockMissing,
+    /// Cache size given without DAX
+    InvalidCacheSizeWithDaxOff,
     /// Missing persistent memory file parameter.
     ParsePmemFileMissing,
     /// Missing vsock socket path parameter.
@@ -98,6 +102,56 @@ pub enum Error {
     ParseTpm(OptionParserError),
     /// Missing path for TPM device
     ParseTpmPathMissing,
+    /// Failed parsing I/O thread
+    ParseIoThread(OptionParserError),
+    /// Missing identifier for I/O thread
+    ParseIoThreadIdMissing,
+    /// Missing host CPUs for I/O thread
+    ParseIoThreadHostCpusMissing,
+    /// Failed parsing rate limiter group
+    ParseRateLimiterGroup(OptionParserError),
+    /// Missing identifier for rate limiter group
+    ParseRateLimiterGroupIdMissing,
+    /// Failed parsing hibernation parameters
+    ParseHibernation(OptionParserError),
+    /// Missing path for hibernation
+    ParseHibernationPathMissing,
+    /// Failed parsing cloud-init parameters
+    ParseCloudInit(OptionParserError),
+    /// Missing user-data for cloud-init
+    ParseCloudInitUserDataMissing,
+    /// Failed parsing Ignition parameters
+    ParseIgnition(OptionParserError),
+    /// Missing path for the Ignition config
+    ParseIgnitionPathMissing,
+    /// Failed parsing fw_cfg parameters
+    ParseFwCfg(OptionParserError),
+    /// Failed parsing pvpanic parameters
+    ParsePvPanic(OptionParserError),
+    /// Failed parsing GPU parameters
+    ParseGpu(OptionParserError),
+    /// Missing GPU socket path parameter
+    ParseGpuSockMissing,
+    /// Failed parsing sound parameters
+    ParseSound(OptionParserError),
+    /// Failed parsing input parameters
+    ParseInput(OptionParserError),
+    /// Failed parsing channel parameters
+    ParseChannel(OptionParserError),
+    /// Missing channel name parameter
+    ParseChannelNameMissing,
+    /// Failed parsing SCSI controller parameters
+    ParseScsi(OptionParserError),
+    /// Failed parsing SCSI logical unit parameters
+    ParseScsiLun(OptionParserError),
+    /// Missing SCSI logical unit number or controller parameter
+    ParseScsiLunMissing,
+    /// SCSI logical unit attached to an unknown controller
+    ParseScsiLunUnknownController(String),
+    /// Failed parsing crypto device parameters
+    ParseCrypto(OptionParserError),
+    /// Invalid watchdog action
+    ParseWatchdogAction(String),
 }
 
 #[derive(Debug, PartialEq, Eq, Error)]
@@ -110,6 +164,8 @@ pub enum ValidationError {
     ConsoleFileMissing,
     /// Max is less than boot
     CpusMaxLowerThanBoot,
+    /// Max is greater than the supported number of vCPUs
+    TooManyVcpus(u32),
     /// Both socket and path specified
     DiskSocketAndPath,
     /// Using vhost user requires shared memory
@@ -127,6 +183,9 @@ pub enum ValidationError {
     #[cfg(target_arch = "aarch64")]
     /// Dies per package must be 1
     CpuTopologyDiesPerPackage,
+    #[cfg(target_arch = "x86_64")]
+    /// More efficiency cores than cores per die
+    CpuTopologyEfficiencyCores,
     /// Virtio needs a min of 2 queues
     VnetQueueLowerThan2,
     /// The input queue number for virtio_net must match the number of input fds
@@ -135,28 +194,67 @@ pub enum ValidationError {
     VnetReservedFd,
     /// Hardware checksum offload is disabled.
     NoHardwareChecksumOffload,
+    /// RSS is only implemented by the virtio-net device of the VMM
+    RssWithVhostUser,
+    /// vhost-net doesn't support the given option
+    VhostNetUnsupportedOption(&'static str),
     /// Hugepages not turned on
     HugePageSizeWithoutHugePages,
     /// Huge page size is not power of 2
     InvalidHugePageSize(u64),
+    /// Prefault threads specified but prefault not enabled
+    PrefaultThreadsWithoutPrefault,
+    /// Invalid number of prefault threads
+    InvalidPrefaultThreads(u32),
+    /// Invalid 32-bit MMIO hole size
+    #[cfg(target_arch = "x86_64")]
+    InvalidMmio32HoleSize(u64),
     /// CPU Hotplug is not permitted with TDX
     #[cfg(feature = "tdx")]
     TdxNoCpuHotplug,
     /// Missing firmware for TDX
     #[cfg(feature = "tdx")]
     TdxFirmwareMissing,
+    /// A Quote Generation Service is only relevant to TDX
+    #[cfg(feature = "tdx")]
+    TdxQgsWithoutTdx,
+    /// CPU Hotplug is not permitted with SEV
+    #[cfg(feature = "sev_snp")]
+    SevNoCpuHotplug,
+    /// Memory hotplug is not permitted with SEV-ES
+    #[cfg(feature = "sev_snp")]
+    SevEsNoMemoryHotplug,
+    /// Missing firmware for SEV
+    #[cfg(feature = "sev_snp")]
+    SevFirmwareMissing,
+    /// SEV-ES and SEV-SNP can't be enabled together
+    #[cfg(feature = "sev_snp")]
+    SevEsWithSevSnp,
+    /// TDX and SEV can't be enabled together
+    #[cfg(all(feature = "tdx", feature = "sev_snp"))]
+    TdxWithSev,
     /// Insuffient vCPUs for queues
     TooManyQueues,
     /// Need shared memory for vfio-user
     UserDevicesRequireSharedMemory,
     /// Memory zone is reused across NUMA nodes
     MemoryZoneReused(String, u32, u32),
+    /// Memory latency and bandwidth must be set for all NUMA nodes or none
+    NumaMemoryAttributesIncomplete(u32),
     /// Invalid number of PCI segments
     InvalidNumPciSegments(u16),
     /// Invalid PCI segment id
     InvalidPciSegment(u16),
+    /// Invalid reserved region
+    #[cfg(target_arch = "x86_64")]
+    InvalidReservedRegion(u64, u64),
+    /// SMBIOS file can't be combined with serial_number, uuid or oem_strings
+    #[cfg(target_arch = "x86_64")]
+    SmbiosFileWithSmbiosFields,
     /// Balloon too big
     BalloonLargerThanRam(u64, u64),
+    /// Balloon minimum size greater than its maximum size
+    InvalidBalloonSizeBounds(u64, u64),
     /// On a IOMMU segment but not behind IOMMU
     OnIommuSegment(u16),
     // On a IOMMU segment but IOMMU not suported
@@ -171,6 +269,81 @@ pub enum ValidationError {
     DuplicateDevicePath(String),
     /// Provided MTU is lower than what the VIRTIO specification expects
     InvalidMtu(u16),
+    /// Builtin firmware requested but not embedded in the binary
+    BuiltinFirmwareUnsupported,
+    /// Automatic vCPU placement can't be combined with explicit affinity
+    CpuPlacementWithAffinity,
+    /// Real-time scheduling priority out of range
+    InvalidCpuSchedulingPriority(u8),
+    /// I/O thread identifier is not unique
+    IoThreadNotUnique(String),
+    /// I/O thread without any host CPU
+    IoThreadHostCpusEmpty(String),
+    /// Device assigned to an I/O thread that doesn't exist
+    UnknownIoThread(String),
+    /// Rate limiter group identifier is not unique
+    RateLimiterGroupNotUnique(String),
+    /// Device assigned to a rate limiter group that doesn't exist
+    UnknownRateLimiterGroup(String),
+    /// Device with its own rate limits assigned to a rate limiter group
+    RateLimiterWithGroup,
+    /// Unknown guest CPU model
+    #[cfg(target_arch = "x86_64")]
+    UnknownCpuModel(String),
+    /// The APIC IDs don't match the maximum number of vCPUs
+    #[cfg(target_arch = "x86_64")]
+    ApicIdsCountMismatch(usize, u32),
+    /// The APIC IDs must be unique, starting with 0 for the boot vCPU
+    #[cfg(target_arch = "x86_64")]
+    InvalidApicIds,
+    /// The SVE vector length must be a multiple of 128 bits up to 2048 bits
+    #[cfg(target_arch = "aarch64")]
+    InvalidSveVectorLength(u32),
+    /// MTE requires guest RAM to be backed by anonymous memory
+    #[cfg(target_arch = "aarch64")]
+    MteRequiresAnonymousMemory,
+    /// The Ignition config is passed through SMBIOS, replaced by the SMBIOS file
+    #[cfg(target_arch = "x86_64")]
+    IgnitionWithSmbiosFile,
+    /// The Ignition config can only be passed through fw_cfg on this architecture
+    #[cfg(not(target_arch = "x86_64"))]
+    IgnitionWithoutFwCfg,
+    /// Invalid fw_cfg file name
+    InvalidFwCfgFileName(String),
+    /// fw_cfg file names must be unique
+    FwCfgFileNotUnique(String),
+    /// Dumping the guest on panic requires a destination
+    PvPanicCoredumpPathMissing,
+    /// Dumping the guest on panic isn't supported by this build
+    #[cfg(not(all(target_arch = "x86_64", feature = "guest_debug")))]
+    PvPanicCoredumpUnsupported,
+    /// The ALSA sound backend isn't supported by this build
+    #[cfg(not(feature = "alsa"))]
+    SoundAlsaUnsupported,
+    /// The GPU display can't be empty
+    InvalidGpuResolution,
+    /// Input devices need exactly one of a kind or an evdev node
+    InvalidInputSource,
+    /// Input devices fed from the console require the GPU device
+    InputRequiresGpu,
+    /// Channels need exactly one of a socket or a PTY
+    InvalidChannelBackend(String),
+    /// Channel names can't be empty or contain slashes
+    InvalidChannelName(String),
+    /// Channel names must be unique
+    ChannelNameNotUnique(String),
+    /// SCSI logical unit number too large
+    InvalidScsiLun(u16),
+    /// SCSI logical unit numbers must be unique per controller
+    ScsiLunNotUnique(u16),
+    /// Only CD-ROM drives may have no image
+    ScsiLunPathMissing(u16),
+    /// Passed through SCSI logical units can't be CD-ROM drives or read-only
+    InvalidScsiPassthrough(u16),
+    /// Crypto device needs at least one data queue, within the maximum
+    InvalidCryptoQueues(usize),
+    /// DAX window size must be a non-zero multiple of 2 MiB
+    InvalidFsCacheSize(u64),
 }
 
 type ValidationResult<T> = std::result::Result<T, ValidationError>;
@@ -183,6 +356,9 @@ impl fmt::Display for ValidationError {
             KernelMissing => write!(f, "No kernel specified"),
             ConsoleFileMissing => write!(f, "Path missing when using file console mode"),
             CpusMaxLowerThanBoot => write!(f, "Max CPUs lower than boot CPUs"),
+            TooManyVcpus(n) => {
+                write!(f, "Max CPUs ({n}) greater than {MAX_NUM_VCPUS}")
+            }
             DiskSocketAndPath => write!(f, "Disk path and vhost socket both provided"),
             VhostUserRequiresSharedMemory => {
                 write!(
@@ -200,6 +376,11 @@ impl fmt::Display for ValidationError {
             ),
             #[cfg(target_arch = "aarch64")]
             CpuTopologyDiesPerPackage => write!(f, "Dies per package must be 1"),
+            #[cfg(target_arch = "x86_64")]
+            CpuTopologyEfficiencyCores => write!(
+                f,
+                "Number of efficiency cores per die exceeds the number of cores per die"
+            ),
             VnetQueueLowerThan2 => write!(f, "Number of queues to virtio_net less than 2"),
             VnetQueueFdMismatch => write!(
                 f,
@@ -208,14 +389,33 @@ impl fmt::Display for ValidationError {
             VnetReservedFd => write!(f, "Reserved fd number (<= 2)"),
             NoHardwareChecksumOffload => write!(
                 f,
-                "\"offload_tso\" and \"offload_ufo\" depend on \"offload_tso\""
+                "\"offload_tso\", \"offload_ufo\" and \"offload_uso\" depend on \"offload_csum\""
             ),
+            RssWithVhostUser => write!(f, "\"rss\" is not supported with \"vhost_user\""),
+            VhostNetUnsupportedOption(option) => {
+                write!(f, "\"{option}\" is not supported with \"vhost\"")
+            }
             HugePageSizeWithoutHugePages => {
                 write!(f, "Huge page size specified but huge pages not enabled")
             }
             InvalidHugePageSize(s) => {
                 write!(f, "Huge page size is not power of 2: {s}")
             }
+            PrefaultThreadsWithoutPrefault => {
+                write!(f, "Prefault threads specified but prefault not enabled")
+            }
+            InvalidPrefaultThreads(n) => {
+                write!(f, "Invalid number of prefault threads: {n}")
+            }
+            #[cfg(target_arch = "x86_64")]
+            InvalidMmio32HoleSize(s) => {
+                write!(
+                    f,
+                    "32-bit MMIO hole size must be a multiple of 2MiB between {}MiB and {}MiB: {s}",
+                    arch::layout::MEM_32BIT_RESERVED_SIZE >> 20,
+                    arch::layout::MEM_32BIT_RESERVED_MAX_SIZE >> 20
+                )
+            }
             #[cfg(feature = "tdx")]
             TdxNoCpuHotplug => {
                 write!(f, "CPU hotplug is not permitted with TDX")
@@ -224,6 +424,30 @@ impl fmt::Display for ValidationError {
             TdxFirmwareMissing => {
                 write!(f, "No TDX firmware specified")
             }
+            #[cfg(feature = "tdx")]
+            TdxQgsWithoutTdx => {
+                write!(f, "A Quote Generation Service requires TDX to be enabled")
+            }
+            #[cfg(feature = "sev_snp")]
+            SevNoCpuHotplug => {
+                write!(f, "CPU hotplug is not permitted with SEV-SNP or SEV-ES")
+            }
+            #[cfg(feature = "sev_snp")]
+            SevEsNoMemoryHotplug => {
+                write!(f, "Memory hotplug is not permitted with SEV-ES")
+            }
+            #[cfg(feature = "sev_snp")]
+            SevFirmwareMissing => {
+                write!(f, "No SEV-SNP or SEV-ES firmware specified")
+            }
+            #[cfg(feature = "sev_snp")]
+            SevEsWithSevSnp => {
+                write!(f, "SEV-ES and SEV-SNP are mutually exclusive")
+            }
+            #[cfg(all(feature = "tdx", feature = "sev_snp"))]
+            TdxWithSev => {
+                write!(f, "TDX and SEV are mutually exclusive")
+            }
             TooManyQueues => {
                 write!(f, "Number of vCPUs is insufficient for number of queues")
             }
@@ -233,6 +457,12 @@ impl fmt::Display for ValidationError {
                     "Using user devices requires using shared memory or huge pages"
                 )
             }
+            NumaMemoryAttributesIncomplete(n) => {
+                write!(
+                    f,
+                    "Memory latency and bandwidth must be set for all NUMA nodes or none (node {n})"
+                )
+            }
             MemoryZoneReused(s, u1, u2) => {
                 write!(
                     f,
@@ -248,12 +478,32 @@ impl fmt::Display for ValidationError {
             InvalidPciSegment(pci_segment) => {
                 write!(f, "Invalid PCI segment id: {pci_segment}")
             }
+            #[cfg(target_arch = "x86_64")]
+            SmbiosFileWithSmbiosFields => {
+                write!(
+                    f,
+                    "SMBIOS file can't be combined with serial_number, uuid or oem_strings"
+                )
+            }
+            #[cfg(target_arch = "x86_64")]
+            InvalidReservedRegion(start, size) => {
+                write!(
+                    f,
+                    "Invalid reserved region: start 0x{start:x} size 0x{size:x}"
+                )
+            }
             BalloonLargerThanRam(balloon_size, ram_size) => {
                 write!(
                     f,
                     "Ballon size ({balloon_size}) greater than RAM ({ram_size})"
                 )
             }
+            InvalidBalloonSizeBounds(min_size, max_size) => {
+                write!(
+                    f,
+                    "Balloon minimum size ({min_size}) greater than its maximum size ({max_size})"
+                )
+            }
             OnIommuSegment(pci_segment) => {
                 write!(
                     f,
@@ -282,6 +532,121 @@ impl fmt::Display for ValidationError {
                     "Provided MTU {mtu} is lower than 1280 (expected by VIRTIO specification)"
                 )
             }
+            BuiltinFirmwareUnsupported => write!(
+                f,
+                "Builtin firmware is not supported by this build (requires \"builtin_fw\" feature and CH_BUILTIN_FW)"
+            ),
+            CpuPlacementWithAffinity => write!(
+                f,
+                "Automatic vCPU placement and explicit vCPU affinity are mutually exclusive"
+            ),
+            InvalidCpuSchedulingPriority(p) => write!(
+                f,
+                "Real-time vCPU scheduling priority must be between 1 and 99: {p}"
+            ),
+            IoThreadNotUnique(id) => write!(f, "I/O thread identifier {id} is not unique"),
+            IoThreadHostCpusEmpty(id) => write!(f, "I/O thread {id} has no host CPU"),
+            UnknownIoThread(id) => write!(f, "Unknown I/O thread: {id}"),
+            RateLimiterGroupNotUnique(id) => {
+                write!(f, "Rate limiter group identifier {id} is not unique")
+            }
+            UnknownRateLimiterGroup(id) => write!(f, "Unknown rate limiter group: {id}"),
+            RateLimiterWithGroup => write!(
+                f,
+                "Rate limits and rate limiter group are mutually exclusive"
+            ),
+            #[cfg(target_arch = "x86_64")]
+            UnknownCpuModel(s) => write!(f, "Unknown CPU model: {s}"),
+            #[cfg(target_arch = "x86_64")]
+            ApicIdsCountMismatch(count, max_vcpus) => write!(
+                f,
+                "Number of APIC IDs ({count}) different from the maximum number of vCPUs ({max_vcpus})"
+            ),
+            #[cfg(target_arch = "x86_64")]
+            InvalidApicIds => write!(
+                f,
+                "APIC IDs must be unique and the first one (boot vCPU) must be 0"
+            ),
+            #[cfg(target_arch = "aarch64")]
+            InvalidSveVectorLength(vl) => write!(
+                f,
+                "SVE vector length {vl} must be a multiple of 128 bits between 128 and 2048 bits"
+            ),
+            #[cfg(target_arch = "aarch64")]
+            MteRequiresAnonymousMemory => write!(
+                f,
+                "MTE requires guest RAM not to be backed by hugepages or files"
+            ),
+            #[cfg(target_arch = "x86_64")]
+            IgnitionWithSmbiosFile => {
+                write!(f, "Ignition config can't be combined with an SMBIOS file")
+            }
+            #[cfg(not(target_arch = "x86_64"))]
+            IgnitionWithoutFwCfg => {
+                write!(f, "Ignition config requires the fw_cfg device")
+            }
+            InvalidFwCfgFileName(name) => {
+                write!(f, "Invalid fw_cfg file name: {name}")
+            }
+            FwCfgFileNotUnique(name) => {
+                write!(f, "fw_cfg file name not unique: {name}")
+            }
+            PvPanicCoredumpPathMissing => {
+                write!(f, "pvpanic coredump action requires a coredump path")
+            }
+            #[cfg(not(all(target_arch = "x86_64", feature = "guest_debug")))]
+            PvPanicCoredumpUnsupported => {
+                write!(
+                    f,
+                    "pvpanic coredump action requires the guest_debug feature on x86-64"
+                )
+            }
+            #[cfg(not(feature = "alsa"))]
+            SoundAlsaUnsupported => {
+                write!(f, "ALSA sound backend requires the alsa feature")
+            }
+            InvalidGpuResolution => write!(f, "GPU display width and height must be non-zero"),
+            InvalidInputSource => {
+                write!(f, "Input device requires exactly one of kind or evdev")
+            }
+            InputRequiresGpu => {
+                write!(f, "Input device fed from the console requires --gpu")
+            }
+            InvalidChannelBackend(s) => {
+                write!(f, "Channel {s} requires exactly one of socket or pty")
+            }
+            InvalidChannelName(s) => write!(f, "Invalid channel name: \"{s}\""),
+            ChannelNameNotUnique(s) => write!(f, "Channel name {s} is not unique"),
+            InvalidScsiLun(lun) => {
+                write!(
+                    f,
+                    "SCSI logical unit {lun} exceeds the maximum of {}",
+                    virtio_devices::SCSI_MAX_LUN
+                )
+            }
+            ScsiLunNotUnique(lun) => write!(f, "SCSI logical unit {lun} is not unique"),
+            ScsiLunPathMissing(lun) => {
+                write!(f, "SCSI logical unit {lun} requires a path unless cdrom=on")
+            }
+            InvalidScsiPassthrough(lun) => {
+                write!(
+                    f,
+                    "SCSI logical unit {lun} can't be passed through as a CD-ROM or read-only"
+                )
+            }
+            InvalidCryptoQueues(n) => {
+                write!(
+                    f,
+                    "Crypto device needs between 1 and {} data queues, not {n}",
+                    virtio_devices::CRYPTO_MAX_DATA_QUEUES
+                )
+            }
+            InvalidFsCacheSize(size) => {
+                write!(
+                    f,
+                    "Filesystem DAX window size {size} is not a non-zero multiple of 2 MiB"
+                )
+            }
         }
     }
 }
@@ -301,6 +666,9 @@ impl fmt::Display for Error {
             ParseFileSystem(o) => write!(f, "Error parsing --fs: {o}"),
             ParseFsSockMissing => write!(f, "Error parsing --fs: socket missing"),
             ParseFsTagMissing => write!(f, "Error parsing --fs: tag missing"),
+            InvalidCacheSizeWithDaxOff => {
+                write!(f, "Error parsing --fs: cache_size used with dax=off")
+            }
             ParsePersistentMemory(o) => write!(f, "Error parsing --pmem: {o}"),
             ParsePmemFileMissing => write!(f, "Error parsing --pmem: file missing"),
             ParseVsock(o) => write!(f, "Error parsing --vsock: {o}"),
@@ -336,6 +704,43 @@ impl fmt::Display for Error {
             ParseVdpaPathMissing => write!(f, "Error parsing --vdpa: path missing"),
             ParseTpm(o) => write!(f, "Error parsing --tpm: {o}"),
             ParseTpmPathMissing => write!(f, "Error parsing --tpm: path missing"),
+            ParseIoThread(o) => write!(f, "Error parsing --iothread: {o}"),
+            ParseIoThreadIdMissing => write!(f, "Error parsing --iothread: id missing"),
+            ParseIoThreadHostCpusMissing => {
+                write!(f, "Error parsing --iothread: host_cpus missing")
+            }
+            ParseRateLimiterGroup(o) => write!(f, "Error parsing --rate-limit-group: {o}"),
+            ParseRateLimiterGroupIdMissing => {
+                write!(f, "Error parsing --rate-limit-group: id missing")
+            }
+            ParseHibernation(o) => write!(f, "Error parsing --hibernation: {o}"),
+            ParseHibernationPathMissing => write!(f, "Error parsing --hibernation: path missing"),
+            ParseCloudInit(o) => write!(f, "Error parsing --cloud-init: {o}"),
+            ParseCloudInitUserDataMissing => {
+                write!(f, "Error parsing --cloud-init: user-data missing")
+            }
+            ParseIgnition(o) => write!(f, "Error parsing --ignition: {o}"),
+            ParseIgnitionPathMissing => write!(f, "Error parsing --ignition: path missing"),
+            ParseFwCfg(o) => write!(f, "Error parsing --fw-cfg: {o}"),
+            ParsePvPanic(o) => write!(f, "Error parsing --pvpanic: {o}"),
+            ParseGpu(o) => write!(f, "Error parsing --gpu: {o}"),
+            ParseGpuSockMissing => write!(f, "Error parsing --gpu: socket missing"),
+            ParseSound(o) => write!(f, "Error parsing --sound: {o}"),
+            ParseInput(o) => write!(f, "Error parsing --input: {o}"),
+            ParseChannel(o) => write!(f, "Error parsing --channel: {o}"),
+            ParseChannelNameMissing => write!(f, "Error parsing --channel: name missing"),
+            ParseScsi(o) => write!(f, "Error parsing --scsi: {o}"),
+            ParseScsiLun(o) => write!(f, "Error parsing --scsi-lun: {o}"),
+            ParseScsiLunMissing => {
+                write!(f, "Error parsing --scsi-lun: controller or lun missing")
+            }
+            ParseScsiLunUnknownController(c) => {
+                write!(f, "Error parsing --scsi-lun: unknown controller {c}")
+            }
+            ParseCrypto(o) => write!(f, "Error parsing --crypto: {o}"),
+            ParseWatchdogAction(a) => {
+                write!(f, "Error parsing --watchdog-action: invalid action {a}")
+            }
         }
     }
 }
@@ -355,6 +760,8 @@ pub struct VmParams<'a> {
     pub memory: &'a str,
     pub memory_zones: Option<Vec<&'a str>>,
     pub firmware: Option<&'a str>,
+    #[cfg(target_arch = "aarch64")]
+    pub firmware_vars: Option<&'a str>,
     pub kernel: Option<&'a str>,
     pub initramfs: Option<&'a str>,
     pub cmdline: Option<&'a str>,
@@ -374,10 +781,25 @@ pub struct VmParams<'a> {
     pub sgx_epc: Option<Vec<&'a str>>,
     pub numa: Option<Vec<&'a str>>,
     pub watchdog: bool,
+    pub watchdog_action: Option<&'a str>,
     #[cfg(feature = "guest_debug")]
     pub gdb: bool,
     pub platform: Option<&'a str>,
     pub tpm: Option<&'a str>,
+    pub iothreads: Option<Vec<&'a str>>,
+    pub rate_limit_groups: Option<Vec<&'a str>>,
+    pub hibernation: Option<&'a str>,
+    pub cloud_init: Option<&'a str>,
+    pub ignition: Option<&'a str>,
+    pub fw_cfg: Option<&'a str>,
+    pub pvpanic: Option<&'a str>,
+    pub gpu: Option<&'a str>,
+    pub sound: Option<&'a str>,
+    pub input: Option<Vec<&'a str>>,
+    pub channels: Option<Vec<&'a str>>,
+    pub scsi: Option<Vec<&'a str>>,
+    pub scsi_luns: Option<Vec<&'a str>>,
+    pub crypto: Option<&'a str>,
 }
 
 #[derive(Debug)]
@@ -397,17 +819,233 @@ impl FromStr for HotplugMethod {
     }
 }
 
+#[derive(Debug)]
+pub enum ParseWatchdogActionError {
+    InvalidValue(String),
+}
+
+impl FromStr for WatchdogAction {
+    type Err = ParseWatchdogActionError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "reset" => Ok(WatchdogAction::Reset),
+            "poweroff" => Ok(WatchdogAction::Poweroff),
+            "pause" => Ok(WatchdogAction::Pause),
+            "none" => Ok(WatchdogAction::None),
+            _ => Err(ParseWatchdogActionError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParsePanicActionError {
+    InvalidValue(String),
+}
+
+#[derive(Debug)]
+pub enum ParseInputKindError {
+    InvalidValue(String),
+}
+
+impl FromStr for InputKind {
+    type Err = ParseInputKindError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "keyboard" => Ok(InputKind::Keyboard),
+            "mouse" => Ok(InputKind::Mouse),
+            "tablet" => Ok(InputKind::Tablet),
+            _ => Err(ParseInputKindError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseCryptoBackendError {
+    InvalidValue(String),
+}
+
+impl FromStr for CryptoBackend {
+    type Err = ParseCryptoBackendError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "af-alg" => Ok(CryptoBackend::AfAlg),
+            "software" => Ok(CryptoBackend::Software),
+            _ => Err(ParseCryptoBackendError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseSoundBackendError {
+    InvalidValue(String),
+}
+
+impl FromStr for SoundBackend {
+    type Err = ParseSoundBackendError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "pipe" => Ok(SoundBackend::Pipe),
+            "alsa" => Ok(SoundBackend::Alsa),
+            _ => Err(ParseSoundBackendError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+impl FromStr for PanicAction {
+    type Err = ParsePanicActionError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "pause" => Ok(PanicAction::Pause),
+            "shutdown" => Ok(PanicAction::Shutdown),
+            "coredump" => Ok(PanicAction::Coredump),
+            _ => Err(ParsePanicActionError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+#[cfg(feature = "tdx")]
+#[derive(Debug)]
+pub enum ParseQuoteGenerationServiceError {
+    InvalidValue(String),
+}
+
+#[cfg(feature = "tdx")]
+impl FromStr for QuoteGenerationService {
+    type Err = ParseQuoteGenerationServiceError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || ParseQuoteGenerationServiceError::InvalidValue(s.to_owned());
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err(invalid());
+            }
+            return Ok(QuoteGenerationService::Unix(PathBuf::from(path)));
+        }
+        if let Some(address) = s.strip_prefix("vsock:") {
+            let (cid, port) = address.split_once(':').ok_or_else(invalid)?;
+            return Ok(QuoteGenerationService::Vsock {
+                cid: cid.parse().map_err(|_| invalid())?,
+                port: port.parse().map_err(|_| invalid())?,
+            });
+        }
+        Err(invalid())
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[derive(Debug)]
+pub enum ParseMemmapTypeError {
+    InvalidValue(String),
+}
+
+#[cfg(target_arch = "x86_64")]
+impl FromStr for MemmapType {
+    type Err = ParseMemmapTypeError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ram" => Ok(MemmapType::Ram),
+            "acpi_reclaim" => Ok(MemmapType::AcpiReclaim),
+            "acpi_nvs" => Ok(MemmapType::AcpiNvs),
+            "pmem" => Ok(MemmapType::Pmem),
+            "soft_reserved" => Ok(MemmapType::SoftReserved),
+            _ => Err(ParseMemmapTypeError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseCpuPlacementError {
+    InvalidValue(String),
+}
+
+impl FromStr for CpuPlacement {
+    type Err = ParseCpuPlacementError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto-numa" => Ok(CpuPlacement::AutoNuma),
+            "compact" => Ok(CpuPlacement::Compact),
+            "scatter" => Ok(CpuPlacement::Scatter),
+            _ => Err(ParseCpuPlacementError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseCpuSchedulingError {
+    InvalidValue(String),
+}
+
+impl FromStr for CpuScheduling {
+    type Err = ParseCpuSchedulingError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (policy, priority) = s
+            .split_once(':')
+            .ok_or_else(|| ParseCpuSchedulingError::InvalidValue(s.to_owned()))?;
+        let policy = match policy.to_lowercase().as_str() {
+            "fifo" => CpuSchedulingPolicy::Fifo,
+            "rr" => CpuSchedulingPolicy::RoundRobin,
+            _ => return Err(ParseCpuSchedulingError::InvalidValue(s.to_owned())),
+        };
+        let priority = priority
+            .parse::<u8>()
+            .map_err(|_| ParseCpuSchedulingError::InvalidValue(s.to_owned()))?;
+
+        Ok(CpuScheduling { policy, priority })
+    }
+}
+
 pub enum CpuTopologyParseError {
     InvalidValue(String),
 }
 
+#[cfg(target_arch = "x86_64")]
+pub enum ReservedRegionParseError {
+    InvalidValue(String),
+}
+
+#[cfg(target_arch = "x86_64")]
+impl FromStr for ReservedRegion {
+    type Err = ReservedRegionParseError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        // Both the start and the size can be given as hexadecimal values or
+        // as sizes, e.g. "0xfd000000:0x1000" or "64M:1M".
+        let parse = |v: &str| -> Option<u64> {
+            match v.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16).ok(),
+                None => v.parse::<ByteSized>().ok().map(|v| v.0),
+            }
+        };
+
+        let (start, size) = s
+            .split_once(':')
+            .and_then(|(start, size)| Some((parse(start)?, parse(size)?)))
+            .ok_or_else(|| Self::Err::InvalidValue(s.to_owned()))?;
+
+        Ok(ReservedRegion { start, size })
+    }
+}
+
 impl FromStr for CpuTopology {
     type Err = CpuTopologyParseError;
 
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
         let parts: Vec<&str> = s.split(':').collect();
 
-        if parts.len() != 4 {
+        // The optional fifth part is the number of efficiency cores per die
+        #[cfg(target_arch = "x86_64")]
+        let valid_len = parts.len() == 4 || parts.len() == 5;
+        #[cfg(not(target_arch = "x86_64"))]
+        let valid_len = parts.len() == 4;
+        if !valid_len {
             return Err(Self::Err::InvalidValue(s.to_owned()));
         }
 
@@ -424,6 +1062,13 @@ impl FromStr for CpuTopology {
             packages: parts[3]
                 .parse()
                 .map_err(|_| Self::Err::InvalidValue(s.to_owned()))?,
+            #[cfg(target_arch = "x86_64")]
+            efficiency_cores_per_die: parts
+                .get(4)
+                .map(|p| p.parse())
+                .transpose()
+                .map_err(|_| Self::Err::InvalidValue(s.to_owned()))?
+                .unwrap_or(0),
         };
 
         Ok(t)
@@ -440,14 +1085,23 @@ impl CpusConfig {
             .add("kvm_hyperv")
             .add("max_phys_bits")
             .add("affinity")
-            .add("features");
+            .add("features")
+            .add("placement")
+            .add("sched");
+        #[cfg(target_arch = "x86_64")]
+        parser
+            .add("model")
+            .add("microcode_revision")
+            .add("apic_ids");
+        #[cfg(target_arch = "aarch64")]
+        parser.add("sve_vl");
         parser.parse(cpus).map_err(Error::ParseCpus)?;
 
-        let boot_vcpus: u8 = parser
+        let boot_vcpus: u32 = parser
             .convert("boot")
             .map_err(Error::ParseCpus)?
             .unwrap_or(DEFAULT_VCPUS);
-        let max_vcpus: u8 = parser
+        let max_vcpus: u32 = parser
             .convert("max")
             .map_err(Error::ParseCpus)?
             .unwrap_or(boot_vcpus);
@@ -462,7 +1116,7 @@ impl CpusConfig {
             .map_err(Error::ParseCpus)?
             .unwrap_or(DEFAULT_MAX_PHYS_BITS);
         let affinity = parser
-            .convert::<Tuple<u8, Vec<u8>>>("affinity")
+            .convert::<Tuple<u32, Vec<u8>>>("affinity")
             .map_err(Error::ParseCpus)?
             .map(|v| {
                 v.0.iter()
@@ -486,22 +1140,103 @@ impl CpusConfig {
         for s in features_list.0 {
             match <std::string::String as AsRef<str>>::as_ref(&s) {
                 #[cfg(target_arch = "x86_64")]
-                "amx" => {
+                "amx" | "+amx" => {
                     features.amx = true;
                     Ok(())
                 }
+                #[cfg(target_arch = "x86_64")]
+                "sgx_provision" => {
+                    features.sgx_provision = true;
+                    Ok(())
+                }
+                #[cfg(target_arch = "aarch64")]
+                "pmu" => {
+                    features.pmu = true;
+                    Ok(())
+                }
+                #[cfg(target_arch = "aarch64")]
+                "mte" => {
+                    features.mte = true;
+                    Ok(())
+                }
+                #[cfg(target_arch = "aarch64")]
+                "pauth" => {
+                    features.pauth = true;
+                    Ok(())
+                }
+                #[cfg(target_arch = "aarch64")]
+                "nested" => {
+                    features.nested = true;
+                    Ok(())
+                }
+                #[cfg(target_arch = "x86_64")]
+                f if f.starts_with('-') && arch::x86_64::is_kvm_pv_feature(&f[1..]) => {
+                    features.kvm_disable.push(f[1..].to_string());
+                    Ok(())
+                }
+                #[cfg(target_arch = "x86_64")]
+                f if f.starts_with('+') && arch::CpuidFeature::from_name(&f[1..]).is_some() => {
+                    features.cpuid_enable.push(f[1..].to_string());
+                    Ok(())
+                }
+                #[cfg(target_arch = "x86_64")]
+                f if f.starts_with('-') && arch::CpuidFeature::from_name(&f[1..]).is_some() => {
+                    features.cpuid_disable.push(f[1..].to_string());
+                    Ok(())
+                }
                 _ => Err(Error::InvalidCpuFeatures(s)),
             }?;
         }
-
-        Ok(CpusConfig {
-            boot_vcpus,
-            max_vcpus,
-            topology,
-            kvm_hyperv,
+        let placement = parser
+            .convert::<CpuPlacement>("placement")
+            .map_err(Error::ParseCpus)?;
+        let sched = parser
+            .convert::<CpuScheduling>("sched")
+            .map_err(Error::ParseCpus)?;
+        #[cfg(target_arch = "x86_64")]
+        let model = parser.get("model");
+        #[cfg(target_arch = "x86_64")]
+        let microcode_revision = parser
+            .get("microcode_revision")
+            .map(|v| {
+                match v.strip_prefix("0x") {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => v.parse().ok(),
+                }
+                .ok_or_else(|| {
+                    Error::ParseCpus(OptionParserError::Conversion(
+                        "microcode_revision".to_string(),
+                        v.clone(),
+                    ))
+                })
+            })
+            .transpose()?;
+        #[cfg(target_arch = "x86_64")]
+        let apic_ids = parser
+            .convert::<IntegerList>("apic_ids")
+            .map_err(Error::ParseCpus)?
+            .map(|v| v.0.iter().map(|e| *e as u32).collect());
+        #[cfg(target_arch = "aarch64")]
+        let sve_vl = parser.convert("sve_vl").map_err(Error::ParseCpus)?;
+
+        Ok(CpusConfig {
+            boot_vcpus,
+            max_vcpus,
+            topology,
+            kvm_hyperv,
             max_phys_bits,
             affinity,
             features,
+            placement,
+            sched,
+            #[cfg(target_arch = "x86_64")]
+            model,
+            #[cfg(target_arch = "x86_64")]
+            microcode_revision,
+            #[cfg(target_arch = "x86_64")]
+            apic_ids,
+            #[cfg(target_arch = "aarch64")]
+            sve_vl,
         })
     }
 }
@@ -516,7 +1251,15 @@ impl PlatformConfig {
             .add("uuid")
             .add("oem_strings");
         #[cfg(feature = "tdx")]
-        parser.add("tdx");
+        parser.add("tdx").add("tdx_qgs");
+        #[cfg(feature = "sev_snp")]
+        parser.add("sev_snp").add("sev_es");
+        #[cfg(target_arch = "x86_64")]
+        parser
+            .add("ps2")
+            .add("reserved_regions")
+            .add("smbios_file")
+            .add("mptable");
         parser.parse(platform).map_err(Error::ParsePlatform)?;
 
         let num_pci_segments: u16 = parser
@@ -541,6 +1284,53 @@ impl PlatformConfig {
             .map_err(Error::ParsePlatform)?
             .unwrap_or(Toggle(false))
             .0;
+        #[cfg(feature = "tdx")]
+        let tdx_qgs = parser
+            .convert::<QuoteGenerationService>("tdx_qgs")
+            .map_err(Error::ParsePlatform)?;
+        #[cfg(feature = "sev_snp")]
+        let sev_snp = parser
+            .convert::<Toggle>("sev_snp")
+            .map_err(Error::ParsePlatform)?
+            .unwrap_or(Toggle(false))
+            .0;
+        #[cfg(feature = "sev_snp")]
+        let sev_es = parser
+            .convert::<Toggle>("sev_es")
+            .map_err(Error::ParsePlatform)?
+            .unwrap_or(Toggle(false))
+            .0;
+        #[cfg(target_arch = "x86_64")]
+        let ps2 = parser
+            .convert::<Toggle>("ps2")
+            .map_err(Error::ParsePlatform)?
+            .unwrap_or(Toggle(false))
+            .0;
+        #[cfg(target_arch = "x86_64")]
+        let reserved_regions = parser
+            .convert::<StringList>("reserved_regions")
+            .map_err(Error::ParsePlatform)?
+            .map(|v| {
+                v.0.iter()
+                    .map(|r| {
+                        r.parse::<ReservedRegion>().map_err(|_| {
+                            Error::ParsePlatform(OptionParserError::Conversion(
+                                "reserved_regions".to_owned(),
+                                r.to_owned(),
+                            ))
+                        })
+                    })
+                    .collect::<Result<Vec<ReservedRegion>>>()
+            })
+            .transpose()?;
+        #[cfg(target_arch = "x86_64")]
+        let smbios_file = parser.get("smbios_file").map(PathBuf::from);
+        #[cfg(target_arch = "x86_64")]
+        let mptable = parser
+            .convert::<Toggle>("mptable")
+            .map_err(Error::ParsePlatform)?
+            .unwrap_or(Toggle(true))
+            .0;
         Ok(PlatformConfig {
             num_pci_segments,
             iommu_segments,
@@ -549,6 +1339,20 @@ impl PlatformConfig {
             oem_strings,
             #[cfg(feature = "tdx")]
             tdx,
+            #[cfg(feature = "tdx")]
+            tdx_qgs,
+            #[cfg(feature = "sev_snp")]
+            sev_snp,
+            #[cfg(feature = "sev_snp")]
+            sev_es,
+            #[cfg(target_arch = "x86_64")]
+            ps2,
+            #[cfg(target_arch = "x86_64")]
+            reserved_regions,
+            #[cfg(target_arch = "x86_64")]
+            smbios_file,
+            #[cfg(target_arch = "x86_64")]
+            mptable,
         })
     }
 
@@ -567,6 +1371,41 @@ impl PlatformConfig {
             }
         }
 
+        #[cfg(feature = "tdx")]
+        if !self.tdx && self.tdx_qgs.is_some() {
+            return Err(ValidationError::TdxQgsWithoutTdx);
+        }
+
+        #[cfg(feature = "sev_snp")]
+        if self.sev_snp && self.sev_es {
+            return Err(ValidationError::SevEsWithSevSnp);
+        }
+
+        #[cfg(all(feature = "tdx", feature = "sev_snp"))]
+        if self.tdx && (self.sev_snp || self.sev_es) {
+            return Err(ValidationError::TdxWithSev);
+        }
+
+        // The SMBIOS table from the file is installed as is
+        #[cfg(target_arch = "x86_64")]
+        if self.smbios_file.is_some()
+            && (self.serial_number.is_some() || self.uuid.is_some() || self.oem_strings.is_some())
+        {
+            return Err(ValidationError::SmbiosFileWithSmbiosFields);
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        if let Some(reserved_regions) = &self.reserved_regions {
+            for region in reserved_regions {
+                if region.size == 0 || region.start.checked_add(region.size).is_none() {
+                    return Err(ValidationError::InvalidReservedRegion(
+                        region.start,
+                        region.size,
+                    ));
+                }
+            }
+        }
+
         Ok(())
     }
 }
@@ -586,6 +1425,8 @@ impl MemoryConfig {
             .add("hugepage_size")
             .add("prefault")
             .add("thp");
+        #[cfg(target_arch = "x86_64")]
+        parser.add("mmio32_hole_size");
         parser.parse(memory).map_err(Error::ParseMemory)?;
 
         let size = parser
@@ -634,6 +1475,11 @@ impl MemoryConfig {
             .map_err(Error::ParseMemory)?
             .unwrap_or(Toggle(true))
             .0;
+        #[cfg(target_arch = "x86_64")]
+        let mmio32_hole_size = parser
+            .convert::<ByteSized>("mmio32_hole_size")
+            .map_err(Error::ParseMemory)?
+            .map(|v| v.0);
 
         let zones: Option<Vec<MemoryZoneConfig>> = if let Some(memory_zones) = &memory_zones {
             let mut zones = Vec::new();
@@ -649,7 +1495,10 @@ impl MemoryConfig {
                     .add("host_numa_node")
                     .add("hotplug_size")
                     .add("hotplugged_size")
-                    .add("prefault");
+                    .add("prefault")
+                    .add("prefault_threads");
+                #[cfg(target_arch = "x86_64")]
+                parser.add("memmap_type");
                 parser.parse(memory_zone).map_err(Error::ParseMemoryZone)?;
 
                 let id = parser.get("id").ok_or(Error::ParseMemoryZoneIdMissing)?;
@@ -690,6 +1539,14 @@ impl MemoryConfig {
                     .map_err(Error::ParseMemoryZone)?
                     .unwrap_or(Toggle(false))
                     .0;
+                let prefault_threads = parser
+                    .convert::<u32>("prefault_threads")
+                    .map_err(Error::ParseMemoryZone)?;
+                #[cfg(target_arch = "x86_64")]
+                let memmap_type = parser
+                    .convert::<MemmapType>("memmap_type")
+                    .map_err(Error::ParseMemoryZone)?
+                    .unwrap_or_default();
 
                 zones.push(MemoryZoneConfig {
                     id,
@@ -702,6 +1559,9 @@ impl MemoryConfig {
                     hotplug_size,
                     hotplugged_size,
                     prefault,
+                    prefault_threads,
+                    #[cfg(target_arch = "x86_64")]
+                    memmap_type,
                 });
             }
             Some(zones)
@@ -721,6 +1581,8 @@ impl MemoryConfig {
             prefault,
             zones,
             thp,
+            #[cfg(target_arch = "x86_64")]
+            mmio32_hole_size,
         })
     }
 
@@ -762,8 +1624,11 @@ impl DiskConfig {
             .add("ops_one_time_burst")
             .add("ops_refill_time")
             .add("id")
+            .add("alias")
             .add("_disable_io_uring")
-            .add("pci_segment");
+            .add("pci_segment")
+            .add("iothreads")
+            .add("rate_limit_group");
         parser.parse(disk).map_err(Error::ParseDisk)?;
 
         let path = parser.get("path").map(PathBuf::from);
@@ -797,6 +1662,7 @@ impl DiskConfig {
             .0;
         let vhost_socket = parser.get("socket");
         let id = parser.get("id");
+        let alias = parser.get("alias");
         let disable_io_uring = parser
             .convert::<Toggle>("_disable_io_uring")
             .map_err(Error::ParseDisk)?
@@ -806,6 +1672,11 @@ impl DiskConfig {
             .convert("pci_segment")
             .map_err(Error::ParseDisk)?
             .unwrap_or_default();
+        let iothreads = parser
+            .convert::<StringList>("iothreads")
+            .map_err(Error::ParseDisk)?
+            .map(|v| v.0);
+        let rate_limit_group = parser.get("rate_limit_group");
         let bw_size = parser
             .convert("bw_size")
             .map_err(Error::ParseDisk)?
@@ -868,8 +1739,11 @@ impl DiskConfig {
             vhost_socket,
             rate_limiter_config,
             id,
+            alias,
             disable_io_uring,
             pci_segment,
+            iothreads,
+            rate_limit_group,
         })
     }
 
@@ -894,6 +1768,12 @@ impl DiskConfig {
             }
         }
 
+        vm_config.validate_iothreads_reference(&self.iothreads)?;
+        vm_config.validate_rate_limit_group_reference(
+            &self.rate_limit_group,
+            &self.rate_limiter_config,
+        )?;
+
         Ok(())
     }
 }
@@ -927,6 +1807,7 @@ impl NetConfig {
             .add("host_mac")
             .add("offload_tso")
             .add("offload_ufo")
+            .add("offload_uso")
             .add("offload_csum")
             .add("mtu")
             .add("iommu")
@@ -936,6 +1817,7 @@ impl NetConfig {
             .add("socket")
             .add("vhost_mode")
             .add("id")
+            .add("alias")
             .add("fd")
             .add("bw_size")
             .add("bw_one_time_burst")
@@ -943,7 +1825,11 @@ impl NetConfig {
             .add("ops_size")
             .add("ops_one_time_burst")
             .add("ops_refill_time")
-            .add("pci_segment");
+            .add("pci_segment")
+            .add("iothreads")
+            .add("rate_limit_group")
+            .add("rss")
+            .add("vhost");
         parser.parse(net).map_err(Error::ParseNetwork)?;
 
         let tap = parser.get("tap");
@@ -970,6 +1856,11 @@ impl NetConfig {
             .map_err(Error::ParseNetwork)?
             .unwrap_or(Toggle(true))
             .0;
+        let offload_uso = parser
+            .convert::<Toggle>("offload_uso")
+            .map_err(Error::ParseNetwork)?
+            .unwrap_or(Toggle(true))
+            .0;
         let offload_csum = parser
             .convert::<Toggle>("offload_csum")
             .map_err(Error::ParseNetwork)?
@@ -1000,6 +1891,7 @@ impl NetConfig {
             .map_err(Error::ParseNetwork)?
             .unwrap_or_default();
         let id = parser.get("id");
+        let alias = parser.get("alias");
         let fds = parser
             .convert::<IntegerList>("fd")
             .map_err(Error::ParseNetwork)?
@@ -1008,6 +1900,21 @@ impl NetConfig {
             .convert("pci_segment")
             .map_err(Error::ParseNetwork)?
             .unwrap_or_default();
+        let iothreads = parser
+            .convert::<StringList>("iothreads")
+            .map_err(Error::ParseNetwork)?
+            .map(|v| v.0);
+        let rate_limit_group = parser.get("rate_limit_group");
+        let rss = parser
+            .convert::<Toggle>("rss")
+            .map_err(Error::ParseNetwork)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let vhost = parser
+            .convert::<Toggle>("vhost")
+            .map_err(Error::ParseNetwork)?
+            .unwrap_or(Toggle(false))
+            .0;
         let bw_size = parser
             .convert("bw_size")
             .map_err(Error::ParseNetwork)?
@@ -1073,12 +1980,18 @@ impl NetConfig {
             vhost_socket,
             vhost_mode,
             id,
+            alias,
             fds,
             rate_limiter_config,
             pci_segment,
             offload_tso,
             offload_ufo,
+            offload_uso,
             offload_csum,
+            iothreads,
+            rate_limit_group,
+            rss,
+            vhost,
         };
         Ok(config)
     }
@@ -1120,16 +2033,41 @@ impl NetConfig {
             }
         }
 
+        vm_config.validate_iothreads_reference(&self.iothreads)?;
+        vm_config.validate_rate_limit_group_reference(
+            &self.rate_limit_group,
+            &self.rate_limiter_config,
+        )?;
+
         if let Some(mtu) = self.mtu {
             if mtu < virtio_devices::net::MIN_MTU {
                 return Err(ValidationError::InvalidMtu(mtu));
             }
         }
 
-        if !self.offload_csum && (self.offload_tso || self.offload_ufo) {
+        if !self.offload_csum && (self.offload_tso || self.offload_ufo || self.offload_uso) {
             return Err(ValidationError::NoHardwareChecksumOffload);
         }
 
+        if self.rss && self.vhost_user {
+            return Err(ValidationError::RssWithVhostUser);
+        }
+
+        // The queues are processed by the kernel, which doesn't know about
+        // the features implemented by the VMM.
+        if self.vhost {
+            let unsupported = [
+                ("vhost_user", self.vhost_user),
+                ("rss", self.rss),
+                ("iommu", self.iommu),
+                ("rate_limiter_config", self.rate_limiter_config.is_some()),
+                ("rate_limit_group", self.rate_limit_group.is_some()),
+            ];
+            if let Some((option, _)) = unsupported.iter().find(|(_, set)| *set) {
+                return Err(ValidationError::VhostNetUnsupportedOption(*option));
+            }
+        }
+
         Ok(())
     }
 }
@@ -1140,6 +2078,9 @@ impl Clone for NetConfig {
             tap: self.tap.clone(),
             vhost_socket: self.vhost_socket.clone(),
             id: self.id.clone(),
+            alias: self.alias.clone(),
+            iothreads: self.iothreads.clone(),
+            rate_limit_group: self.rate_limit_group.clone(),
             fds: self
                 .fds
                 .as_ref()
@@ -1193,6 +2134,9 @@ impl BalloonConfig {
         parser.add("size");
         parser.add("deflate_on_oom");
         parser.add("free_page_reporting");
+        parser.add("auto_size");
+        parser.add("min_size");
+        parser.add("max_size");
         parser.parse(balloon).map_err(Error::ParseBalloon)?;
 
         let size = parser
@@ -1213,10 +2157,30 @@ impl BalloonConfig {
             .unwrap_or(Toggle(false))
             .0;
 
+        let auto_size = parser
+            .convert::<Toggle>("auto_size")
+            .map_err(Error::ParseBalloon)?
+            .unwrap_or(Toggle(false))
+            .0;
+
+        let min_size = parser
+            .convert::<ByteSized>("min_size")
+            .map_err(Error::ParseBalloon)?
+            .map(|v| v.0)
+            .unwrap_or(0);
+
+        let max_size = parser
+            .convert::<ByteSized>("max_size")
+            .map_err(Error::ParseBalloon)?
+            .map(|v| v.0);
+
         Ok(BalloonConfig {
             size,
             deflate_on_oom,
             free_page_reporting,
+            auto_size,
+            min_size,
+            max_size,
         })
     }
 }
@@ -1229,7 +2193,10 @@ impl FsConfig {
             .add("queue_size")
             .add("num_queues")
             .add("socket")
+            .add("dax")
+            .add("cache_size")
             .add("id")
+            .add("alias")
             .add("pci_segment");
         parser.parse(fs).map_err(Error::ParseFileSystem)?;
 
@@ -1245,7 +2212,22 @@ impl FsConfig {
             .map_err(Error::ParseFileSystem)?
             .unwrap_or_else(default_fsconfig_num_queues);
 
+        let dax = parser
+            .convert::<Toggle>("dax")
+            .map_err(Error::ParseFileSystem)?
+            .unwrap_or_default()
+            .0;
+        let cache_size = parser
+            .convert::<ByteSized>("cache_size")
+            .map_err(Error::ParseFileSystem)?
+            .map(|s| s.0);
+        if cache_size.is_some() && !dax {
+            return Err(Error::InvalidCacheSizeWithDaxOff);
+        }
+        let cache_size = cache_size.unwrap_or_else(default_fsconfig_cache_size);
+
         let id = parser.get("id");
+        let alias = parser.get("alias");
 
         let pci_segment = parser
             .convert("pci_segment")
@@ -1257,7 +2239,10 @@ impl FsConfig {
             socket,
             num_queues,
             queue_size,
+            dax,
+            cache_size,
             id,
+            alias,
             pci_segment,
         })
     }
@@ -1267,6 +2252,11 @@ impl FsConfig {
             return Err(ValidationError::TooManyQueues);
         }
 
+        // The window is mapped with huge pages when possible.
+        if self.dax && (self.cache_size == 0 || self.cache_size % 0x20_0000 != 0) {
+            return Err(ValidationError::InvalidFsCacheSize(self.cache_size));
+        }
+
         if let Some(platform_config) = vm_config.platform.as_ref() {
             if self.pci_segment >= platform_config.num_pci_segments {
                 return Err(ValidationError::InvalidPciSegment(self.pci_segment));
@@ -1294,6 +2284,7 @@ impl PmemConfig {
             .add("iommu")
             .add("discard_writes")
             .add("id")
+            .add("alias")
             .add("pci_segment");
         parser.parse(pmem).map_err(Error::ParsePersistentMemory)?;
 
@@ -1313,6 +2304,7 @@ impl PmemConfig {
             .unwrap_or(Toggle(false))
             .0;
         let id = parser.get("id");
+        let alias = parser.get("alias");
         let pci_segment = parser
             .convert("pci_segment")
             .map_err(Error::ParsePersistentMemory)?
@@ -1324,6 +2316,7 @@ impl PmemConfig {
             iommu,
             discard_writes,
             id,
+            alias,
             pci_segment,
         })
     }
@@ -1389,7 +2382,12 @@ impl ConsoleConfig {
 impl DeviceConfig {
     pub fn parse(device: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
-        parser.add("path").add("id").add("iommu").add("pci_segment");
+        parser
+            .add("path")
+            .add("id")
+            .add("alias")
+            .add("iommu")
+            .add("pci_segment");
         parser.parse(device).map_err(Error::ParseDevice)?;
 
         let path = parser
@@ -1402,6 +2400,7 @@ impl DeviceConfig {
             .unwrap_or(Toggle(false))
             .0;
         let id = parser.get("id");
+        let alias = parser.get("alias");
         let pci_segment = parser
             .convert::<u16>("pci_segment")
             .map_err(Error::ParseDevice)?
@@ -1411,6 +2410,7 @@ impl DeviceConfig {
             path,
             iommu,
             id,
+            alias,
             pci_segment,
         })
     }
@@ -1435,7 +2435,11 @@ impl DeviceConfig {
 impl UserDeviceConfig {
     pub fn parse(user_device: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
-        parser.add("socket").add("id").add("pci_segment");
+        parser
+            .add("socket")
+            .add("id")
+            .add("alias")
+            .add("pci_segment");
         parser.parse(user_device).map_err(Error::ParseUserDevice)?;
 
         let socket = parser
@@ -1443,6 +2447,7 @@ impl UserDeviceConfig {
             .map(PathBuf::from)
             .ok_or(Error::ParseUserDeviceSocketMissing)?;
         let id = parser.get("id");
+        let alias = parser.get("alias");
         let pci_segment = parser
             .convert::<u16>("pci_segment")
             .map_err(Error::ParseUserDevice)?
@@ -1451,6 +2456,7 @@ impl UserDeviceConfig {
         Ok(UserDeviceConfig {
             socket,
             id,
+            alias,
             pci_segment,
         })
     }
@@ -1482,6 +2488,7 @@ impl VdpaConfig {
             .add("num_queues")
             .add("iommu")
             .add("id")
+            .add("alias")
             .add("pci_segment");
         parser.parse(vdpa).map_err(Error::ParseVdpa)?;
 
@@ -1499,6 +2506,7 @@ impl VdpaConfig {
             .unwrap_or(Toggle(false))
             .0;
         let id = parser.get("id");
+        let alias = parser.get("alias");
         let pci_segment = parser
             .convert("pci_segment")
             .map_err(Error::ParseVdpa)?
@@ -1509,6 +2517,7 @@ impl VdpaConfig {
             num_queues,
             iommu,
             id,
+            alias,
             pci_segment,
         })
     }
@@ -1538,6 +2547,7 @@ impl VsockConfig {
             .add("cid")
             .add("iommu")
             .add("id")
+            .add("alias")
             .add("pci_segment");
         parser.parse(vsock).map_err(Error::ParseVsock)?;
 
@@ -1555,6 +2565,7 @@ impl VsockConfig {
             .map_err(Error::ParseVsock)?
             .ok_or(Error::ParseVsockCidMissing)?;
         let id = parser.get("id");
+        let alias = parser.get("alias");
         let pci_segment = parser
             .convert("pci_segment")
             .map_err(Error::ParseVsock)?
@@ -1565,6 +2576,7 @@ impl VsockConfig {
             socket,
             iommu,
             id,
+            alias,
             pci_segment,
         })
     }
@@ -1617,7 +2629,9 @@ impl NumaConfig {
             .add("cpus")
             .add("distances")
             .add("memory_zones")
-            .add("sgx_epc_sections");
+            .add("sgx_epc_sections")
+            .add("memory_latency")
+            .add("memory_bandwidth");
         parser.parse(numa).map_err(Error::ParseNuma)?;
 
         let guest_numa_id = parser
@@ -1627,7 +2641,7 @@ impl NumaConfig {
         let cpus = parser
             .convert::<IntegerList>("cpus")
             .map_err(Error::ParseNuma)?
-            .map(|v| v.0.iter().map(|e| *e as u8).collect());
+            .map(|v| v.0.iter().map(|e| *e as u32).collect());
         let distances = parser
             .convert::<Tuple<u64, u64>>("distances")
             .map_err(Error::ParseNuma)?
@@ -1648,6 +2662,12 @@ impl NumaConfig {
             .convert::<StringList>("sgx_epc_sections")
             .map_err(Error::ParseNuma)?
             .map(|v| v.0);
+        let memory_latency = parser
+            .convert::<u32>("memory_latency")
+            .map_err(Error::ParseNuma)?;
+        let memory_bandwidth = parser
+            .convert::<u32>("memory_bandwidth")
+            .map_err(Error::ParseNuma)?;
 
         Ok(NumaConfig {
             guest_numa_id,
@@ -1656,6 +2676,8 @@ impl NumaConfig {
             memory_zones,
             #[cfg(target_arch = "x86_64")]
             sgx_epc_sections,
+            memory_latency,
+            memory_bandwidth,
         })
     }
 }
@@ -1703,751 +2725,2451 @@ impl TpmConfig {
     }
 }
 
-impl VmConfig {
-    fn validate_identifier(
-        id_list: &mut BTreeSet<String>,
-        id: &Option<String>,
-    ) -> ValidationResult<()> {
-        if let Some(id) = id.as_ref() {
-            if id.starts_with("__") {
-                return Err(ValidationError::InvalidIdentifier(id.clone()));
-            }
+impl IoThreadConfig {
+    pub fn parse(iothread: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser.add("id").add("host_cpus");
+        parser.parse(iothread).map_err(Error::ParseIoThread)?;
+        let id = parser.get("id").ok_or(Error::ParseIoThreadIdMissing)?;
+        let host_cpus = parser
+            .convert::<IntegerList>("host_cpus")
+            .map_err(Error::ParseIoThread)?
+            .map(|v| v.0.iter().map(|e| *e as usize).collect())
+            .ok_or(Error::ParseIoThreadHostCpusMissing)?;
+        Ok(IoThreadConfig { id, host_cpus })
+    }
+}
 
-            if !id_list.insert(id.clone()) {
-                return Err(ValidationError::IdentifierNotUnique(id.clone()));
-            }
-        }
+impl CloudInitConfig {
+    pub fn parse(cloud_init: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser
+            .add("user-data")
+            .add("meta-data")
+            .add("network-config");
+        parser.parse(cloud_init).map_err(Error::ParseCloudInit)?;
+        let user_data = parser
+            .get("user-data")
+            .map(PathBuf::from)
+            .ok_or(Error::ParseCloudInitUserDataMissing)?;
+        let meta_data = parser.get("meta-data").map(PathBuf::from);
+        let network_config = parser.get("network-config").map(PathBuf::from);
+        Ok(CloudInitConfig {
+            user_data,
+            meta_data,
+            network_config,
+        })
+    }
+}
 
-        Ok(())
+impl IgnitionConfig {
+    pub fn parse(ignition: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser.add("path");
+        parser.parse(ignition).map_err(Error::ParseIgnition)?;
+        let path = parser
+            .get("path")
+            .map(PathBuf::from)
+            .ok_or(Error::ParseIgnitionPathMissing)?;
+        Ok(IgnitionConfig { path })
     }
+}
 
-    pub fn backed_by_shared_memory(&self) -> bool {
-        if self.memory.shared || self.memory.hugepages {
-            return true;
-        }
+impl FwCfgConfig {
+    pub fn parse(fw_cfg: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser.add("payload").add("files");
+        parser.parse(fw_cfg).map_err(Error::ParseFwCfg)?;
+        let payload = parser
+            .convert::<Toggle>("payload")
+            .map_err(Error::ParseFwCfg)?
+            .unwrap_or(Toggle(default_fwcfgconfig_payload()))
+            .0;
+        // Files are given as "<name>:<path>", e.g. "opt/org.example/blob:/path/to/blob"
+        let files = parser
+            .convert::<StringList>("files")
+            .map_err(Error::ParseFwCfg)?
+            .map(|v| {
+                v.0.iter()
+                    .map(|f| {
+                        f.split_once(':')
+                            .map(|(name, path)| FwCfgFile {
+                                name: name.to_owned(),
+                                path: PathBuf::from(path),
+                            })
+                            .ok_or_else(|| {
+                                Error::ParseFwCfg(OptionParserError::Conversion(
+                                    "files".to_owned(),
+                                    f.to_owned(),
+                                ))
+                            })
+                    })
+                    .collect::<Result<Vec<FwCfgFile>>>()
+            })
+            .transpose()?;
+        Ok(FwCfgConfig { payload, files })
+    }
+}
 
-        if self.memory.size == 0 {
-            for zone in self.memory.zones.as_ref().unwrap() {
-                if !zone.shared && !zone.hugepages {
-                    return false;
-                }
-            }
-            true
-        } else {
-            false
-        }
+impl PvPanicConfig {
+    pub fn parse(pvpanic: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser.add("on_panic").add("coredump_path");
+        parser.parse(pvpanic).map_err(Error::ParsePvPanic)?;
+        let on_panic = parser
+            .convert("on_panic")
+            .map_err(Error::ParsePvPanic)?
+            .unwrap_or_default();
+        let coredump_path = parser.get("coredump_path").map(PathBuf::from);
+        Ok(PvPanicConfig {
+            on_panic,
+            coredump_path,
+        })
     }
+}
 
-    // Also enables virtio-iommu if the config needs it
-    // Returns the list of unique identifiers provided through the
-    // configuration.
-    pub fn validate(&mut self) -> ValidationResult<BTreeSet<String>> {
-        let mut id_list = BTreeSet::new();
+impl GpuConfig {
+    pub fn parse(gpu: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser.add("socket").add("width").add("height").add("iommu");
+        parser.parse(gpu).map_err(Error::ParseGpu)?;
 
-        self.payload
-            .as_ref()
-            .ok_or(ValidationError::KernelMissing)?;
+        let socket = parser
+            .get("socket")
+            .map(PathBuf::from)
+            .ok_or(Error::ParseGpuSockMissing)?;
+        let width = parser
+            .convert("width")
+            .map_err(Error::ParseGpu)?
+            .unwrap_or_else(default_gpuconfig_width);
+        let height = parser
+            .convert("height")
+            .map_err(Error::ParseGpu)?
+            .unwrap_or_else(default_gpuconfig_height);
+        let iommu = parser
+            .convert::<Toggle>("iommu")
+            .map_err(Error::ParseGpu)?
+            .unwrap_or(Toggle(false))
+            .0;
 
-        #[cfg(feature = "tdx")]
-        {
-            let tdx_enabled = self.platform.as_ref().map(|p| p.tdx).unwrap_or(false);
-            // At this point we know payload isn't None.
-            if tdx_enabled && self.payload.as_ref().unwrap().firmware.is_none() {
-                return Err(ValidationError::TdxFirmwareMissing);
-            }
-            if tdx_enabled && (self.cpus.max_vcpus != self.cpus.boot_vcpus) {
-                return Err(ValidationError::TdxNoCpuHotplug);
-            }
-        }
+        Ok(GpuConfig {
+            socket,
+            width,
+            height,
+            iommu,
+        })
+    }
+}
 
-        if self.console.mode == ConsoleOutputMode::Tty && self.serial.mode == ConsoleOutputMode::Tty
-        {
-            return Err(ValidationError::DoubleTtyMode);
-        }
+impl SoundConfig {
+    pub fn parse(sound: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser
+            .add("backend")
+            .add("playback")
+            .add("capture")
+            .add("iommu");
+        parser.parse(sound).map_err(Error::ParseSound)?;
 
-        if self.console.mode == ConsoleOutputMode::File && self.console.file.is_none() {
-            return Err(ValidationError::ConsoleFileMissing);
-        }
+        let backend = parser
+            .convert("backend")
+            .map_err(Error::ParseSound)?
+            .unwrap_or_default();
+        let playback = parser.get("playback").map(PathBuf::from);
+        let capture = parser.get("capture").map(PathBuf::from);
+        let iommu = parser
+            .convert::<Toggle>("iommu")
+            .map_err(Error::ParseSound)?
+            .unwrap_or(Toggle(false))
+            .0;
 
-        if self.serial.mode == ConsoleOutputMode::File && self.serial.file.is_none() {
-            return Err(ValidationError::ConsoleFileMissing);
-        }
+        Ok(SoundConfig {
+            backend,
+            playback,
+            capture,
+            iommu,
+        })
+    }
+}
 
-        if self.cpus.max_vcpus < self.cpus.boot_vcpus {
-            return Err(ValidationError::CpusMaxLowerThanBoot);
+impl InputConfig {
+    pub fn parse(input: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser
+            .add("kind")
+            .add("evdev")
+            .add("iommu")
+            .add("id")
+            .add("pci_segment");
+        parser.parse(input).map_err(Error::ParseInput)?;
+
+        let kind = parser.convert("kind").map_err(Error::ParseInput)?;
+        let evdev = parser.get("evdev").map(PathBuf::from);
+        let iommu = parser
+            .convert::<Toggle>("iommu")
+            .map_err(Error::ParseInput)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let id = parser.get("id");
+        let pci_segment = parser
+            .convert("pci_segment")
+            .map_err(Error::ParseInput)?
+            .unwrap_or_default();
+
+        Ok(InputConfig {
+            kind,
+            evdev,
+            iommu,
+            id,
+            pci_segment,
+        })
+    }
+
+    pub fn validate(&self, vm_config: &VmConfig) -> ValidationResult<()> {
+        match (&self.kind, &self.evdev) {
+            (Some(_), None) => {
+                if vm_config.gpu.is_none() {
+                    return Err(ValidationError::InputRequiresGpu);
+                }
+            }
+            (None, Some(_)) => {}
+            _ => return Err(ValidationError::InvalidInputSource),
         }
 
-        if let Some(disks) = &self.disks {
-            for disk in disks {
-                if disk.vhost_socket.as_ref().and(disk.path.as_ref()).is_some() {
-                    return Err(ValidationError::DiskSocketAndPath);
-                }
-                if disk.vhost_user && !self.backed_by_shared_memory() {
-                    return Err(ValidationError::VhostUserRequiresSharedMemory);
-                }
-                if disk.vhost_user && disk.vhost_socket.is_none() {
-                    return Err(ValidationError::VhostUserMissingSocket);
-                }
-                disk.validate(self)?;
-                self.iommu |= disk.iommu;
-
-                Self::validate_identifier(&mut id_list, &disk.id)?;
+        if let Some(platform_config) = vm_config.platform.as_ref() {
+            if self.pci_segment >= platform_config.num_pci_segments {
+                return Err(ValidationError::InvalidPciSegment(self.pci_segment));
             }
-        }
 
-        if let Some(nets) = &self.net {
-            for net in nets {
-                if net.vhost_user && !self.backed_by_shared_memory() {
-                    return Err(ValidationError::VhostUserRequiresSharedMemory);
+            if let Some(iommu_segments) = platform_config.iommu_segments.as_ref() {
+                if iommu_segments.contains(&self.pci_segment) && !self.iommu {
+                    return Err(ValidationError::OnIommuSegment(self.pci_segment));
                 }
-                net.validate(self)?;
-                self.iommu |= net.iommu;
-
-                Self::validate_identifier(&mut id_list, &net.id)?;
             }
         }
 
-        if let Some(fses) = &self.fs {
-            if !fses.is_empty() && !self.backed_by_shared_memory() {
-                return Err(ValidationError::VhostUserRequiresSharedMemory);
-            }
-            for fs in fses {
-                fs.validate(self)?;
+        Ok(())
+    }
+}
 
-                Self::validate_identifier(&mut id_list, &fs.id)?;
-            }
-        }
+impl ChannelConfig {
+    pub fn parse(channel: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser.add("name").add("socket").add("pty");
+        parser.parse(channel).map_err(Error::ParseChannel)?;
+
+        let name = parser.get("name").ok_or(Error::ParseChannelNameMissing)?;
+        let socket = parser.get("socket").map(PathBuf::from);
+        let pty = parser
+            .convert::<Toggle>("pty")
+            .map_err(Error::ParseChannel)?
+            .unwrap_or(Toggle(false))
+            .0;
 
-        if let Some(pmems) = &self.pmem {
-            for pmem in pmems {
-                pmem.validate(self)?;
-                self.iommu |= pmem.iommu;
+        Ok(ChannelConfig {
+            name,
+            socket,
+            pty,
+            file: None,
+        })
+    }
 
-                Self::validate_identifier(&mut id_list, &pmem.id)?;
-            }
+    pub fn validate(&self) -> ValidationResult<()> {
+        if self.name.is_empty() || self.name.contains('/') {
+            return Err(ValidationError::InvalidChannelName(self.name.clone()));
         }
 
-        self.iommu |= self.rng.iommu;
-        self.iommu |= self.console.iommu;
-
-        if let Some(t) = &self.cpus.topology {
-            if t.threads_per_core == 0
-                || t.cores_per_die == 0
-                || t.dies_per_package == 0
-                || t.packages == 0
-            {
-                return Err(ValidationError::CpuTopologyZeroPart);
-            }
-
-            // The setting of dies doesen't apply on AArch64.
-            // Only '1' value is accepted, so its impact on the vcpu topology
-            // setting can be ignored.
-            #[cfg(target_arch = "aarch64")]
-            if t.dies_per_package != 1 {
-                return Err(ValidationError::CpuTopologyDiesPerPackage);
-            }
-
-            let total = t.threads_per_core * t.cores_per_die * t.dies_per_package * t.packages;
-            if total != self.cpus.max_vcpus {
-                return Err(ValidationError::CpuTopologyCount);
-            }
+        if self.socket.is_some() == self.pty {
+            return Err(ValidationError::InvalidChannelBackend(self.name.clone()));
         }
 
-        if let Some(hugepage_size) = &self.memory.hugepage_size {
-            if !self.memory.hugepages {
-                return Err(ValidationError::HugePageSizeWithoutHugePages);
-            }
-            if !hugepage_size.is_power_of_two() {
-                return Err(ValidationError::InvalidHugePageSize(*hugepage_size));
-            }
-        }
+        Ok(())
+    }
+}
 
-        if let Some(user_devices) = &self.user_devices {
-            if !user_devices.is_empty() && !self.backed_by_shared_memory() {
-                return Err(ValidationError::UserDevicesRequireSharedMemory);
-            }
+impl ScsiConfig {
+    pub fn parse(scsi: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser
+            .add("iommu")
+            .add("id")
+            .add("alias")
+            .add("pci_segment");
+        parser.parse(scsi).map_err(Error::ParseScsi)?;
 
-            for user_device in user_devices {
-                user_device.validate(self)?;
+        let iommu = parser
+            .convert::<Toggle>("iommu")
+            .map_err(Error::ParseScsi)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let id = parser.get("id");
+        let alias = parser.get("alias");
+        let pci_segment = parser
+            .convert("pci_segment")
+            .map_err(Error::ParseScsi)?
+            .unwrap_or_default();
 
-                Self::validate_identifier(&mut id_list, &user_device.id)?;
-            }
-        }
+        Ok(ScsiConfig {
+            luns: Vec::new(),
+            iommu,
+            id,
+            alias,
+            pci_segment,
+        })
+    }
 
-        if let Some(vdpa_devices) = &self.vdpa {
-            for vdpa_device in vdpa_devices {
-                vdpa_device.validate(self)?;
-                self.iommu |= vdpa_device.iommu;
+    pub fn validate(&self, vm_config: &VmConfig) -> ValidationResult<()> {
+        let mut luns = BTreeSet::new();
+        for lun in &self.luns {
+            lun.validate()?;
 
-                Self::validate_identifier(&mut id_list, &vdpa_device.id)?;
+            if !luns.insert(lun.lun) {
+                return Err(ValidationError::ScsiLunNotUnique(lun.lun));
             }
         }
 
-        if let Some(balloon) = &self.balloon {
-            let mut ram_size = self.memory.size;
-
-            if let Some(zones) = &self.memory.zones {
-                for zone in zones {
-                    ram_size += zone.size;
-                }
+        if let Some(platform_config) = vm_config.platform.as_ref() {
+            if self.pci_segment >= platform_config.num_pci_segments {
+                return Err(ValidationError::InvalidPciSegment(self.pci_segment));
             }
 
-            if balloon.size >= ram_size {
-                return Err(ValidationError::BalloonLargerThanRam(
-                    balloon.size,
-                    ram_size,
-                ));
+            if let Some(iommu_segments) = platform_config.iommu_segments.as_ref() {
+                if iommu_segments.contains(&self.pci_segment) && !self.iommu {
+                    return Err(ValidationError::OnIommuSegment(self.pci_segment));
+                }
             }
         }
 
-        if let Some(devices) = &self.devices {
-            let mut device_paths = BTreeSet::new();
-            for device in devices {
-                if !device_paths.insert(device.path.to_string_lossy()) {
-                    return Err(ValidationError::DuplicateDevicePath(
-                        device.path.to_string_lossy().to_string(),
-                    ));
-                }
+        Ok(())
+    }
+}
 
-                device.validate(self)?;
-                self.iommu |= device.iommu;
+impl ScsiLunConfig {
+    /// Parses a logical unit, returning it along with the identifier of the
+    /// controller it belongs to.
+    pub fn parse(scsi_lun: &str) -> Result<(String, Self)> {
+        let mut parser = OptionParser::new();
+        parser
+            .add("controller")
+            .add("lun")
+            .add("path")
+            .add("readonly")
+            .add("cdrom")
+            .add("passthrough")
+            .add("serial");
+        parser.parse(scsi_lun).map_err(Error::ParseScsiLun)?;
+
+        let controller = parser.get("controller").ok_or(Error::ParseScsiLunMissing)?;
+        let lun = parser
+            .convert("lun")
+            .map_err(Error::ParseScsiLun)?
+            .ok_or(Error::ParseScsiLunMissing)?;
+        let path = parser.get("path").map(PathBuf::from);
+        let readonly = parser
+            .convert::<Toggle>("readonly")
+            .map_err(Error::ParseScsiLun)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let cdrom = parser
+            .convert::<Toggle>("cdrom")
+            .map_err(Error::ParseScsiLun)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let passthrough = parser
+            .convert::<Toggle>("passthrough")
+            .map_err(Error::ParseScsiLun)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let serial = parser.get("serial");
+
+        Ok((
+            controller,
+            ScsiLunConfig {
+                lun,
+                path,
+                readonly,
+                cdrom,
+                passthrough,
+                serial,
+            },
+        ))
+    }
 
-                Self::validate_identifier(&mut id_list, &device.id)?;
-            }
+    pub fn validate(&self) -> ValidationResult<()> {
+        if self.lun > virtio_devices::SCSI_MAX_LUN {
+            return Err(ValidationError::InvalidScsiLun(self.lun));
         }
 
-        if let Some(vsock) = &self.vsock {
-            vsock.validate(self)?;
-            self.iommu |= vsock.iommu;
-
-            Self::validate_identifier(&mut id_list, &vsock.id)?;
+        if self.path.is_none() && !self.cdrom {
+            return Err(ValidationError::ScsiLunPathMissing(self.lun));
         }
 
-        if let Some(numa) = &self.numa {
-            let mut used_numa_node_memory_zones = HashMap::new();
-            for numa_node in numa.iter() {
-                for memory_zone in numa_node.memory_zones.clone().unwrap().iter() {
-                    if !used_numa_node_memory_zones.contains_key(memory_zone) {
-                        used_numa_node_memory_zones
-                            .insert(memory_zone.to_string(), numa_node.guest_numa_id);
-                    } else {
-                        return Err(ValidationError::MemoryZoneReused(
-                            memory_zone.to_string(),
-                            *used_numa_node_memory_zones.get(memory_zone).unwrap(),
-                            numa_node.guest_numa_id,
-                        ));
-                    }
-                }
-            }
+        if self.passthrough && (self.cdrom || self.readonly) {
+            return Err(ValidationError::InvalidScsiPassthrough(self.lun));
         }
 
-        if let Some(zones) = &self.memory.zones {
-            for zone in zones.iter() {
-                let id = zone.id.clone();
-                Self::validate_identifier(&mut id_list, &Some(id))?;
-            }
-        }
+        Ok(())
+    }
+}
 
-        #[cfg(target_arch = "x86_64")]
-        if let Some(sgx_epcs) = &self.sgx_epc {
-            for sgx_epc in sgx_epcs.iter() {
-                let id = sgx_epc.id.clone();
-                Self::validate_identifier(&mut id_list, &Some(id))?;
-            }
-        }
+impl CryptoConfig {
+    pub fn parse(crypto: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser.add("backend").add("num_queues").add("iommu");
+        parser.parse(crypto).map_err(Error::ParseCrypto)?;
 
-        self.platform.as_ref().map(|p| p.validate()).transpose()?;
-        self.iommu |= self
-            .platform
-            .as_ref()
-            .map(|p| p.iommu_segments.is_some())
+        let backend = parser
+            .convert("backend")
+            .map_err(Error::ParseCrypto)?
             .unwrap_or_default();
+        let num_queues = parser
+            .convert("num_queues")
+            .map_err(Error::ParseCrypto)?
+            .unwrap_or_else(default_cryptoconfig_num_queues);
+        let iommu = parser
+            .convert::<Toggle>("iommu")
+            .map_err(Error::ParseCrypto)?
+            .unwrap_or(Toggle(false))
+            .0;
 
-        Ok(id_list)
+        Ok(CryptoConfig {
+            backend,
+            num_queues,
+            iommu,
+        })
     }
 
-    pub fn parse(vm_params: VmParams) -> Result<Self> {
-        let mut disks: Option<Vec<DiskConfig>> = None;
-        if let Some(disk_list) = &vm_params.disks {
-            let mut disk_config_list = Vec::new();
-            for item in disk_list.iter() {
-                let disk_config = DiskConfig::parse(item)?;
-                disk_config_list.push(disk_config);
-            }
-            disks = Some(disk_config_list);
+    pub fn validate(&self) -> ValidationResult<()> {
+        if self.num_queues == 0 || self.num_queues > virtio_devices::CRYPTO_MAX_DATA_QUEUES {
+            return Err(ValidationError::InvalidCryptoQueues(self.num_queues));
         }
 
-        let mut net: Option<Vec<NetConfig>> = None;
-        if let Some(net_list) = &vm_params.net {
-            let mut net_config_list = Vec::new();
-            for item in net_list.iter() {
-                let net_config = NetConfig::parse(item)?;
-                net_config_list.push(net_config);
-            }
-            net = Some(net_config_list);
-        }
+        Ok(())
+    }
+}
 
-        let rng = RngConfig::parse(vm_params.rng)?;
+impl HibernationConfig {
+    pub fn parse(hibernation: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser.add("path");
+        parser.parse(hibernation).map_err(Error::ParseHibernation)?;
+        let path = parser
+            .get("path")
+            .map(PathBuf::from)
+            .ok_or(Error::ParseHibernationPathMissing)?;
+        Ok(HibernationConfig { path })
+    }
+}
 
-        let mut balloon: Option<BalloonConfig> = None;
-        if let Some(balloon_params) = &vm_params.balloon {
-            balloon = Some(BalloonConfig::parse(balloon_params)?);
+impl RateLimiterGroupConfig {
+    pub fn parse(rate_limit_group: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser
+            .add("id")
+            .add("bw_size")
+            .add("bw_one_time_burst")
+            .add("bw_refill_time")
+            .add("ops_size")
+            .add("ops_one_time_burst")
+            .add("ops_refill_time");
+        parser
+            .parse(rate_limit_group)
+            .map_err(Error::ParseRateLimiterGroup)?;
+
+        let id = parser
+            .get("id")
+            .ok_or(Error::ParseRateLimiterGroupIdMissing)?;
+        let bw_size = parser
+            .convert("bw_size")
+            .map_err(Error::ParseRateLimiterGroup)?
+            .unwrap_or_default();
+        let bw_one_time_burst = parser
+            .convert("bw_one_time_burst")
+            .map_err(Error::ParseRateLimiterGroup)?
+            .unwrap_or_default();
+        let bw_refill_time = parser
+            .convert("bw_refill_time")
+            .map_err(Error::ParseRateLimiterGroup)?
+            .unwrap_or_default();
+        let ops_size = parser
+            .convert("ops_size")
+            .map_err(Error::ParseRateLimiterGroup)?
+            .unwrap_or_default();
+        let ops_one_time_burst = parser
+            .convert("ops_one_time_burst")
+            .map_err(Error::ParseRateLimiterGroup)?
+            .unwrap_or_default();
+        let ops_refill_time = parser
+            .convert("ops_refill_time")
+            .map_err(Error::ParseRateLimiterGroup)?
+            .unwrap_or_default();
+        let bandwidth = if bw_size != 0 && bw_refill_time != 0 {
+            Some(TokenBucketConfig {
+                size: bw_size,
+                one_time_burst: Some(bw_one_time_burst),
+                refill_time: bw_refill_time,
+            })
+        } else {
+            None
+        };
+        let ops = if ops_size != 0 && ops_refill_time != 0 {
+            Some(TokenBucketConfig {
+                size: ops_size,
+                one_time_burst: Some(ops_one_time_burst),
+                refill_time: ops_refill_time,
+            })
+        } else {
+            None
+        };
+
+        Ok(RateLimiterGroupConfig {
+            id,
+            rate_limiter_config: RateLimiterConfig { bandwidth, ops },
+        })
+    }
+}
+
+impl VmConfig {
+    fn validate_identifier(
+        id_list: &mut BTreeSet<String>,
+        id: &Option<String>,
+    ) -> ValidationResult<()> {
+        if let Some(id) = id.as_ref() {
+            if id.starts_with("__") {
+                return Err(ValidationError::InvalidIdentifier(id.clone()));
+            }
+
+            if !id_list.insert(id.clone()) {
+                return Err(ValidationError::IdentifierNotUnique(id.clone()));
+            }
         }
 
-        let mut fs: Option<Vec<FsConfig>> = None;
-        if let Some(fs_list) = &vm_params.fs {
-            let mut fs_config_list = Vec::new();
-            for item in fs_list.iter() {
-                fs_config_list.push(FsConfig::parse(item)?);
+        Ok(())
+    }
+
+    pub fn backed_by_shared_memory(&self) -> bool {
+        if self.memory.shared || self.memory.hugepages {
+            return true;
+        }
+
+        if self.memory.size == 0 {
+            for zone in self.memory.zones.as_ref().unwrap() {
+                if !zone.shared && !zone.hugepages {
+                    return false;
+                }
             }
-            fs = Some(fs_config_list);
+            true
+        } else {
+            false
         }
+    }
 
-        let mut pmem: Option<Vec<PmemConfig>> = None;
-        if let Some(pmem_list) = &vm_params.pmem {
-            let mut pmem_config_list = Vec::new();
-            for item in pmem_list.iter() {
-                let pmem_config = PmemConfig::parse(item)?;
-                pmem_config_list.push(pmem_config);
+    /// Returns the (alias, id) pairs for all the devices which have been
+    /// given an alias and already have an identifier assigned.
+    pub fn device_aliases(&self) -> Vec<(String, String)> {
+        let mut aliases = Vec::new();
+        let mut add_alias = |alias: &Option<String>, id: &Option<String>| {
+            if let (Some(alias), Some(id)) = (alias, id) {
+                aliases.push((alias.clone(), id.clone()));
             }
-            pmem = Some(pmem_config_list);
+        };
+
+        for disk in self.disks.iter().flatten() {
+            add_alias(&disk.alias, &disk.id);
+        }
+        for net in self.net.iter().flatten() {
+            add_alias(&net.alias, &net.id);
+        }
+        for fs in self.fs.iter().flatten() {
+            add_alias(&fs.alias, &fs.id);
+        }
+        for pmem in self.pmem.iter().flatten() {
+            add_alias(&pmem.alias, &pmem.id);
+        }
+        for device in self.devices.iter().flatten() {
+            add_alias(&device.alias, &device.id);
+        }
+        for user_device in self.user_devices.iter().flatten() {
+            add_alias(&user_device.alias, &user_device.id);
+        }
+        for vdpa in self.vdpa.iter().flatten() {
+            add_alias(&vdpa.alias, &vdpa.id);
+        }
+        if let Some(vsock) = &self.vsock {
+            add_alias(&vsock.alias, &vsock.id);
+        }
+        for scsi in self.scsi.iter().flatten() {
+            add_alias(&scsi.alias, &scsi.id);
         }
 
-        let console = ConsoleConfig::parse(vm_params.console)?;
-        let serial = ConsoleConfig::parse(vm_params.serial)?;
+        aliases
+    }
 
-        let mut devices: Option<Vec<DeviceConfig>> = None;
-        if let Some(device_list) = &vm_params.devices {
-            let mut device_config_list = Vec::new();
-            for item in device_list.iter() {
-                let device_config = DeviceConfig::parse(item)?;
-                device_config_list.push(device_config);
+    /// Translates a device alias into the matching device identifier. The
+    /// input is returned untouched if it is not a known alias, as it is then
+    /// expected to be an identifier already.
+    pub fn resolve_device_id(&self, id: &str) -> String {
+        self.device_aliases()
+            .into_iter()
+            .find(|(alias, _)| alias == id)
+            .map(|(_, id)| id)
+            .unwrap_or_else(|| id.to_owned())
+    }
+
+    /// Duplicates the entries of the devices which were given an alias under
+    /// that alias, for the API users referring to the devices this way.
+    pub fn add_device_alias_entries<T: Clone>(&self, entries: &mut HashMap<String, T>) {
+        for (alias, id) in self.device_aliases() {
+            if let Some(entry) = entries.get(&id).cloned() {
+                entries.insert(alias, entry);
             }
-            devices = Some(device_config_list);
         }
+    }
 
-        let mut user_devices: Option<Vec<UserDeviceConfig>> = None;
-        if let Some(user_device_list) = &vm_params.user_devices {
-            let mut user_device_config_list = Vec::new();
-            for item in user_device_list.iter() {
-                let user_device_config = UserDeviceConfig::parse(item)?;
-                user_device_config_list.push(user_device_config);
+    // Also enables virtio-iommu if the config needs it
+    // Returns the list of unique identifiers provided through the
+    // configuration.
+    fn validate_iothreads_reference(
+        &self,
+        iothreads: &Option<Vec<String>>,
+    ) -> ValidationResult<()> {
+        for id in iothreads.iter().flatten() {
+            if !self.iothreads.iter().flatten().any(|t| &t.id == id) {
+                return Err(ValidationError::UnknownIoThread(id.clone()));
             }
-            user_devices = Some(user_device_config_list);
         }
 
-        let mut vdpa: Option<Vec<VdpaConfig>> = None;
-        if let Some(vdpa_list) = &vm_params.vdpa {
-            let mut vdpa_config_list = Vec::new();
-            for item in vdpa_list.iter() {
-                let vdpa_config = VdpaConfig::parse(item)?;
-                vdpa_config_list.push(vdpa_config);
+        Ok(())
+    }
+
+    fn validate_rate_limit_group_reference(
+        &self,
+        rate_limit_group: &Option<String>,
+        rate_limiter_config: &Option<RateLimiterConfig>,
+    ) -> ValidationResult<()> {
+        if let Some(id) = rate_limit_group {
+            if rate_limiter_config.is_some() {
+                return Err(ValidationError::RateLimiterWithGroup);
+            }
+            if !self.rate_limit_groups.iter().flatten().any(|g| &g.id == id) {
+                return Err(ValidationError::UnknownRateLimiterGroup(id.clone()));
             }
-            vdpa = Some(vdpa_config_list);
         }
 
-        let mut vsock: Option<VsockConfig> = None;
-        if let Some(vs) = &vm_params.vsock {
-            let vsock_config = VsockConfig::parse(vs)?;
-            vsock = Some(vsock_config);
-        }
+        Ok(())
+    }
 
-        let platform = vm_params.platform.map(PlatformConfig::parse).transpose()?;
+    pub fn validate(&mut self) -> ValidationResult<BTreeSet<String>> {
+        let mut id_list = BTreeSet::new();
 
-        #[cfg(target_arch = "x86_64")]
-        let mut sgx_epc: Option<Vec<SgxEpcConfig>> = None;
-        #[cfg(target_arch = "x86_64")]
+        let payload = self
+            .payload
+            .as_ref()
+            .ok_or(ValidationError::KernelMissing)?;
+
+        if !cfg!(builtin_fw_image) && payload.kernel.as_deref() == Some(Path::new(BUILTIN_FIRMWARE))
         {
-            if let Some(sgx_epc_list) = &vm_params.sgx_epc {
-                let mut sgx_epc_config_list = Vec::new();
-                for item in sgx_epc_list.iter() {
-                    let sgx_epc_config = SgxEpcConfig::parse(item)?;
-                    sgx_epc_config_list.push(sgx_epc_config);
-                }
-                sgx_epc = Some(sgx_epc_config_list);
+            return Err(ValidationError::BuiltinFirmwareUnsupported);
+        }
+
+        #[cfg(feature = "tdx")]
+        {
+            let tdx_enabled = self.platform.as_ref().map(|p| p.tdx).unwrap_or(false);
+            // At this point we know payload isn't None.
+            if tdx_enabled && self.payload.as_ref().unwrap().firmware.is_none() {
+                return Err(ValidationError::TdxFirmwareMissing);
+            }
+            if tdx_enabled && (self.cpus.max_vcpus != self.cpus.boot_vcpus) {
+                return Err(ValidationError::TdxNoCpuHotplug);
             }
         }
 
-        let mut numa: Option<Vec<NumaConfig>> = None;
-        if let Some(numa_list) = &vm_params.numa {
-            let mut numa_config_list = Vec::new();
-            for item in numa_list.iter() {
-                let numa_config = NumaConfig::parse(item)?;
-                numa_config_list.push(numa_config);
+        #[cfg(feature = "sev_snp")]
+        {
+            let sev_enabled = self.is_sev_enabled();
+            // At this point we know payload isn't None.
+            if sev_enabled && self.payload.as_ref().unwrap().firmware.is_none() {
+                return Err(ValidationError::SevFirmwareMissing);
+            }
+            if sev_enabled && (self.cpus.max_vcpus != self.cpus.boot_vcpus) {
+                return Err(ValidationError::SevNoCpuHotplug);
+            }
+            // Hotplugged memory wouldn't be pinned, which is required for
+            // its encrypted content to remain valid.
+            if self.is_sev_es_enabled() && self.memory.hotplug_size.is_some() {
+                return Err(ValidationError::SevEsNoMemoryHotplug);
             }
-            numa = Some(numa_config_list);
         }
 
-        let payload = if vm_params.kernel.is_some() || vm_params.firmware.is_some() {
-            Some(PayloadConfig {
-                kernel: vm_params.kernel.map(PathBuf::from),
-                initramfs: vm_params.initramfs.map(PathBuf::from),
-                cmdline: vm_params.cmdline.map(|s| s.to_string()),
-                firmware: vm_params.firmware.map(PathBuf::from),
-            })
-        } else {
-            None
-        };
+        if self.console.mode == ConsoleOutputMode::Tty && self.serial.mode == ConsoleOutputMode::Tty
+        {
+            return Err(ValidationError::DoubleTtyMode);
+        }
 
-        let mut tpm: Option<TpmConfig> = None;
-        if let Some(tc) = vm_params.tpm {
-            let tpm_conf = TpmConfig::parse(tc)?;
-            tpm = Some(TpmConfig {
-                socket: tpm_conf.socket,
-            });
+        if self.console.mode == ConsoleOutputMode::File && self.console.file.is_none() {
+            return Err(ValidationError::ConsoleFileMissing);
         }
 
-        #[cfg(feature = "guest_debug")]
-        let gdb = vm_params.gdb;
+        if self.serial.mode == ConsoleOutputMode::File && self.serial.file.is_none() {
+            return Err(ValidationError::ConsoleFileMissing);
+        }
 
-        let mut config = VmConfig {
-            cpus: CpusConfig::parse(vm_params.cpus)?,
-            memory: MemoryConfig::parse(vm_params.memory, vm_params.memory_zones)?,
-            payload,
-            disks,
-            net,
-            rng,
-            balloon,
-            fs,
-            pmem,
-            serial,
-            console,
-            devices,
-            user_devices,
-            vdpa,
-            vsock,
-            iommu: false, // updated in VmConfig::validate()
-            #[cfg(target_arch = "x86_64")]
-            sgx_epc,
-            numa,
-            watchdog: vm_params.watchdog,
-            #[cfg(feature = "guest_debug")]
-            gdb,
-            platform,
-            tpm,
-        };
-        config.validate().map_err(Error::Validation)?;
-        Ok(config)
-    }
+        if self.cpus.max_vcpus < self.cpus.boot_vcpus {
+            return Err(ValidationError::CpusMaxLowerThanBoot);
+        }
 
-    #[cfg(feature = "tdx")]
-    pub fn is_tdx_enabled(&self) -> bool {
-        self.platform.as_ref().map(|p| p.tdx).unwrap_or(false)
-    }
-}
+        if self.cpus.max_vcpus > MAX_NUM_VCPUS {
+            return Err(ValidationError::TooManyVcpus(self.cpus.max_vcpus));
+        }
 
-#[cfg(test)]
-mod tests {
-    use std::{fs::File, os::fd::AsRawFd};
+        if self.cpus.placement.is_some() && self.cpus.affinity.is_some() {
+            return Err(ValidationError::CpuPlacementWithAffinity);
+        }
 
-    use super::*;
-    use net_util::MacAddr;
+        if let Some(sched) = &self.cpus.sched {
+            if !(1..=99).contains(&sched.priority) {
+                return Err(ValidationError::InvalidCpuSchedulingPriority(
+                    sched.priority,
+                ));
+            }
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        if let Some(model) = &self.cpus.model {
+            if arch::CpuModel::from_name(model).is_none() {
+                return Err(ValidationError::UnknownCpuModel(model.clone()));
+            }
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        if let Some(apic_ids) = &self.cpus.apic_ids {
+            if apic_ids.len() != self.cpus.max_vcpus as usize {
+                return Err(ValidationError::ApicIdsCountMismatch(
+                    apic_ids.len(),
+                    self.cpus.max_vcpus,
+                ));
+            }
+            // KVM treats the vCPU created with ID 0 as the boot vCPU
+            let unique_apic_ids: BTreeSet<&u32> = apic_ids.iter().collect();
+            if apic_ids[0] != 0 || unique_apic_ids.len() != apic_ids.len() {
+                return Err(ValidationError::InvalidApicIds);
+            }
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        if let Some(sve_vl) = self.cpus.sve_vl {
+            if sve_vl == 0 || sve_vl > 2048 || sve_vl % 128 != 0 {
+                return Err(ValidationError::InvalidSveVectorLength(sve_vl));
+            }
+        }
+
+        // Tags can only be stored along with anonymous and shmem pages
+        #[cfg(target_arch = "aarch64")]
+        if self.cpus.features.mte {
+            let zones = self.memory.zones.as_deref().unwrap_or_default();
+            if self.memory.hugepages || zones.iter().any(|z| z.hugepages || z.file.is_some()) {
+                return Err(ValidationError::MteRequiresAnonymousMemory);
+            }
+        }
+
+        if let Some(iothreads) = &self.iothreads {
+            let mut iothread_ids = BTreeSet::new();
+            for iothread in iothreads {
+                if !iothread_ids.insert(iothread.id.as_str()) {
+                    return Err(ValidationError::IoThreadNotUnique(iothread.id.clone()));
+                }
+                if iothread.host_cpus.is_empty() {
+                    return Err(ValidationError::IoThreadHostCpusEmpty(iothread.id.clone()));
+                }
+            }
+        }
+
+        // Without fw_cfg, the Ignition config is passed through the SMBIOS
+        // OEM strings
+        #[cfg(target_arch = "x86_64")]
+        if self.ignition.is_some()
+            && self.fw_cfg.is_none()
+            && self
+                .platform
+                .as_ref()
+                .map_or(false, |p| p.smbios_file.is_some())
+        {
+            return Err(ValidationError::IgnitionWithSmbiosFile);
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        if self.ignition.is_some() && self.fw_cfg.is_none() {
+            return Err(ValidationError::IgnitionWithoutFwCfg);
+        }
+
+        if let Some(fw_cfg) = &self.fw_cfg {
+            let mut names = BTreeSet::new();
+            for file in fw_cfg.files.iter().flatten() {
+                // The name must fit in a directory entry, along with its
+                // terminating NUL character.
+                if file.name.is_empty() || file.name.len() >= 56 {
+                    return Err(ValidationError::InvalidFwCfgFileName(file.name.clone()));
+                }
+                if !names.insert(file.name.as_str()) {
+                    return Err(ValidationError::FwCfgFileNotUnique(file.name.clone()));
+                }
+            }
+        }
+
+        if let Some(pvpanic) = &self.pvpanic {
+            if pvpanic.on_panic == PanicAction::Coredump {
+                #[cfg(not(all(target_arch = "x86_64", feature = "guest_debug")))]
+                return Err(ValidationError::PvPanicCoredumpUnsupported);
+                #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+                if pvpanic.coredump_path.is_none() {
+                    return Err(ValidationError::PvPanicCoredumpPathMissing);
+                }
+            }
+        }
+
+        if let Some(rate_limit_groups) = &self.rate_limit_groups {
+            let mut rate_limit_group_ids = BTreeSet::new();
+            for group in rate_limit_groups {
+                if !rate_limit_group_ids.insert(group.id.as_str()) {
+                    return Err(ValidationError::RateLimiterGroupNotUnique(group.id.clone()));
+                }
+            }
+        }
+
+        if let Some(disks) = &self.disks {
+            for disk in disks {
+                if disk.vhost_socket.as_ref().and(disk.path.as_ref()).is_some() {
+                    return Err(ValidationError::DiskSocketAndPath);
+                }
+                if disk.vhost_user && !self.backed_by_shared_memory() {
+                    return Err(ValidationError::VhostUserRequiresSharedMemory);
+                }
+                if disk.vhost_user && disk.vhost_socket.is_none() {
+                    return Err(ValidationError::VhostUserMissingSocket);
+                }
+                disk.validate(self)?;
+                self.iommu |= disk.iommu;
+
+                Self::validate_identifier(&mut id_list, &disk.id)?;
+                Self::validate_identifier(&mut id_list, &disk.alias)?;
+            }
+        }
+
+        if let Some(nets) = &self.net {
+            for net in nets {
+                if net.vhost_user && !self.backed_by_shared_memory() {
+                    return Err(ValidationError::VhostUserRequiresSharedMemory);
+                }
+                net.validate(self)?;
+                self.iommu |= net.iommu;
+
+                Self::validate_identifier(&mut id_list, &net.id)?;
+                Self::validate_identifier(&mut id_list, &net.alias)?;
+            }
+        }
+
+        if let Some(fses) = &self.fs {
+            if !fses.is_empty() && !self.backed_by_shared_memory() {
+                return Err(ValidationError::VhostUserRequiresSharedMemory);
+            }
+            for fs in fses {
+                fs.validate(self)?;
+
+                Self::validate_identifier(&mut id_list, &fs.id)?;
+                Self::validate_identifier(&mut id_list, &fs.alias)?;
+            }
+        }
+
+        if let Some(pmems) = &self.pmem {
+            for pmem in pmems {
+                pmem.validate(self)?;
+                self.iommu |= pmem.iommu;
+
+                Self::validate_identifier(&mut id_list, &pmem.id)?;
+                Self::validate_identifier(&mut id_list, &pmem.alias)?;
+            }
+        }
+
+        self.iommu |= self.rng.iommu;
+        self.iommu |= self.console.iommu;
+
+        if let Some(gpu) = &self.gpu {
+            if gpu.width == 0 || gpu.height == 0 {
+                return Err(ValidationError::InvalidGpuResolution);
+            }
+            self.iommu |= gpu.iommu;
+        }
+
+        if let Some(sound) = &self.sound {
+            #[cfg(not(feature = "alsa"))]
+            if sound.backend == SoundBackend::Alsa {
+                return Err(ValidationError::SoundAlsaUnsupported);
+            }
+            self.iommu |= sound.iommu;
+        }
+
+        if let Some(t) = &self.cpus.topology {
+            if t.threads_per_core == 0
+                || t.cores_per_die == 0
+                || t.dies_per_package == 0
+                || t.packages == 0
+            {
+                return Err(ValidationError::CpuTopologyZeroPart);
+            }
+
+            // The setting of dies doesen't apply on AArch64.
+            // Only '1' value is accepted, so its impact on the vcpu topology
+            // setting can be ignored.
+            #[cfg(target_arch = "aarch64")]
+            if t.dies_per_package != 1 {
+                return Err(ValidationError::CpuTopologyDiesPerPackage);
+            }
+
+            let total = u32::from(t.threads_per_core)
+                * u32::from(t.cores_per_die)
+                * u32::from(t.dies_per_package)
+                * u32::from(t.packages);
+            if total != self.cpus.max_vcpus {
+                return Err(ValidationError::CpuTopologyCount);
+            }
+
+            #[cfg(target_arch = "x86_64")]
+            if t.efficiency_cores_per_die > t.cores_per_die {
+                return Err(ValidationError::CpuTopologyEfficiencyCores);
+            }
+        }
+
+        if let Some(hugepage_size) = &self.memory.hugepage_size {
+            if !self.memory.hugepages {
+                return Err(ValidationError::HugePageSizeWithoutHugePages);
+            }
+            if !hugepage_size.is_power_of_two() {
+                return Err(ValidationError::InvalidHugePageSize(*hugepage_size));
+            }
+        }
+
+        if let Some(zones) = &self.memory.zones {
+            for zone in zones {
+                if let Some(hugepage_size) = zone.hugepage_size {
+                    if !zone.hugepages {
+                        return Err(ValidationError::HugePageSizeWithoutHugePages);
+                    }
+                    if !hugepage_size.is_power_of_two() {
+                        return Err(ValidationError::InvalidHugePageSize(hugepage_size));
+                    }
+                }
+                if let Some(prefault_threads) = zone.prefault_threads {
+                    if !zone.prefault {
+                        return Err(ValidationError::PrefaultThreadsWithoutPrefault);
+                    }
+                    if prefault_threads == 0 {
+                        return Err(ValidationError::InvalidPrefaultThreads(prefault_threads));
+                    }
+                }
+            }
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        if let Some(mmio32_hole_size) = self.memory.mmio32_hole_size {
+            if !(arch::layout::MEM_32BIT_RESERVED_SIZE..=arch::layout::MEM_32BIT_RESERVED_MAX_SIZE)
+                .contains(&mmio32_hole_size)
+                || mmio32_hole_size % (2 << 20) != 0
+            {
+                return Err(ValidationError::InvalidMmio32HoleSize(mmio32_hole_size));
+            }
+        }
+
+        if let Some(user_devices) = &self.user_devices {
+            if !user_devices.is_empty() && !self.backed_by_shared_memory() {
+                return Err(ValidationError::UserDevicesRequireSharedMemory);
+            }
+
+            for user_device in user_devices {
+                user_device.validate(self)?;
+
+                Self::validate_identifier(&mut id_list, &user_device.id)?;
+                Self::validate_identifier(&mut id_list, &user_device.alias)?;
+            }
+        }
+
+        if let Some(vdpa_devices) = &self.vdpa {
+            for vdpa_device in vdpa_devices {
+                vdpa_device.validate(self)?;
+                self.iommu |= vdpa_device.iommu;
+
+                Self::validate_identifier(&mut id_list, &vdpa_device.id)?;
+                Self::validate_identifier(&mut id_list, &vdpa_device.alias)?;
+            }
+        }
+
+        if let Some(inputs) = &self.input {
+            for input in inputs {
+                input.validate(self)?;
+                self.iommu |= input.iommu;
+
+                Self::validate_identifier(&mut id_list, &input.id)?;
+            }
+        }
+
+        if let Some(channels) = &self.channels {
+            let mut names = BTreeSet::new();
+            for channel in channels {
+                channel.validate()?;
+
+                if !names.insert(&channel.name) {
+                    return Err(ValidationError::ChannelNameNotUnique(channel.name.clone()));
+                }
+            }
+        }
+
+        if let Some(scsi_controllers) = &self.scsi {
+            for scsi in scsi_controllers {
+                scsi.validate(self)?;
+                self.iommu |= scsi.iommu;
+
+                Self::validate_identifier(&mut id_list, &scsi.id)?;
+                Self::validate_identifier(&mut id_list, &scsi.alias)?;
+            }
+        }
+
+        if let Some(crypto) = &self.crypto {
+            crypto.validate()?;
+            self.iommu |= crypto.iommu;
+        }
+
+        if let Some(balloon) = &self.balloon {
+            let mut ram_size = self.memory.size;
+
+            if let Some(zones) = &self.memory.zones {
+                for zone in zones {
+                    ram_size += zone.size;
+                }
+            }
+
+            if balloon.size >= ram_size {
+                return Err(ValidationError::BalloonLargerThanRam(
+                    balloon.size,
+                    ram_size,
+                ));
+            }
+
+            if let Some(max_size) = balloon.max_size {
+                if balloon.min_size > max_size {
+                    return Err(ValidationError::InvalidBalloonSizeBounds(
+                        balloon.min_size,
+                        max_size,
+                    ));
+                }
+                if max_size >= ram_size {
+                    return Err(ValidationError::BalloonLargerThanRam(max_size, ram_size));
+                }
+            } else if balloon.min_size >= ram_size {
+                return Err(ValidationError::BalloonLargerThanRam(
+                    balloon.min_size,
+                    ram_size,
+                ));
+            }
+        }
+
+        if let Some(devices) = &self.devices {
+            let mut device_paths = BTreeSet::new();
+            for device in devices {
+                if !device_paths.insert(device.path.to_string_lossy()) {
+                    return Err(ValidationError::DuplicateDevicePath(
+                        device.path.to_string_lossy().to_string(),
+                    ));
+                }
+
+                device.validate(self)?;
+                self.iommu |= device.iommu;
+
+                Self::validate_identifier(&mut id_list, &device.id)?;
+                Self::validate_identifier(&mut id_list, &device.alias)?;
+            }
+        }
+
+        if let Some(vsock) = &self.vsock {
+            vsock.validate(self)?;
+            self.iommu |= vsock.iommu;
+
+            Self::validate_identifier(&mut id_list, &vsock.id)?;
+            Self::validate_identifier(&mut id_list, &vsock.alias)?;
+        }
+
+        if let Some(numa) = &self.numa {
+            let mut used_numa_node_memory_zones = HashMap::new();
+            for numa_node in numa.iter() {
+                for memory_zone in numa_node.memory_zones.clone().unwrap().iter() {
+                    if !used_numa_node_memory_zones.contains_key(memory_zone) {
+                        used_numa_node_memory_zones
+                            .insert(memory_zone.to_string(), numa_node.guest_numa_id);
+                    } else {
+                        return Err(ValidationError::MemoryZoneReused(
+                            memory_zone.to_string(),
+                            *used_numa_node_memory_zones.get(memory_zone).unwrap(),
+                            numa_node.guest_numa_id,
+                        ));
+                    }
+                }
+            }
+
+            // The HMAT describes the memory of every NUMA node
+            if numa
+                .iter()
+                .any(|n| n.memory_latency.is_some() || n.memory_bandwidth.is_some())
+            {
+                if let Some(numa_node) = numa
+                    .iter()
+                    .find(|n| n.memory_latency.is_none() || n.memory_bandwidth.is_none())
+                {
+                    return Err(ValidationError::NumaMemoryAttributesIncomplete(
+                        numa_node.guest_numa_id,
+                    ));
+                }
+            }
+        }
+
+        if let Some(zones) = &self.memory.zones {
+            for zone in zones.iter() {
+                let id = zone.id.clone();
+                Self::validate_identifier(&mut id_list, &Some(id))?;
+            }
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        if let Some(sgx_epcs) = &self.sgx_epc {
+            for sgx_epc in sgx_epcs.iter() {
+                let id = sgx_epc.id.clone();
+                Self::validate_identifier(&mut id_list, &Some(id))?;
+            }
+        }
+
+        self.platform.as_ref().map(|p| p.validate()).transpose()?;
+        self.iommu |= self
+            .platform
+            .as_ref()
+            .map(|p| p.iommu_segments.is_some())
+            .unwrap_or_default();
+
+        Ok(id_list)
+    }
+
+    pub fn parse(vm_params: VmParams) -> Result<Self> {
+        let mut disks: Option<Vec<DiskConfig>> = None;
+        if let Some(disk_list) = &vm_params.disks {
+            let mut disk_config_list = Vec::new();
+            for item in disk_list.iter() {
+                let disk_config = DiskConfig::parse(item)?;
+                disk_config_list.push(disk_config);
+            }
+            disks = Some(disk_config_list);
+        }
+
+        let mut net: Option<Vec<NetConfig>> = None;
+        if let Some(net_list) = &vm_params.net {
+            let mut net_config_list = Vec::new();
+            for item in net_list.iter() {
+                let net_config = NetConfig::parse(item)?;
+                net_config_list.push(net_config);
+            }
+            net = Some(net_config_list);
+        }
+
+        let rng = RngConfig::parse(vm_params.rng)?;
+
+        let mut balloon: Option<BalloonConfig> = None;
+        if let Some(balloon_params) = &vm_params.balloon {
+            balloon = Some(BalloonConfig::parse(balloon_params)?);
+        }
+
+        let mut fs: Option<Vec<FsConfig>> = None;
+        if let Some(fs_list) = &vm_params.fs {
+            let mut fs_config_list = Vec::new();
+            for item in fs_list.iter() {
+                fs_config_list.push(FsConfig::parse(item)?);
+            }
+            fs = Some(fs_config_list);
+        }
+
+        let mut pmem: Option<Vec<PmemConfig>> = None;
+        if let Some(pmem_list) = &vm_params.pmem {
+            let mut pmem_config_list = Vec::new();
+            for item in pmem_list.iter() {
+                let pmem_config = PmemConfig::parse(item)?;
+                pmem_config_list.push(pmem_config);
+            }
+            pmem = Some(pmem_config_list);
+        }
+
+        let console = ConsoleConfig::parse(vm_params.console)?;
+        let serial = ConsoleConfig::parse(vm_params.serial)?;
+
+        let mut devices: Option<Vec<DeviceConfig>> = None;
+        if let Some(device_list) = &vm_params.devices {
+            let mut device_config_list = Vec::new();
+            for item in device_list.iter() {
+                let device_config = DeviceConfig::parse(item)?;
+                device_config_list.push(device_config);
+            }
+            devices = Some(device_config_list);
+        }
+
+        let mut user_devices: Option<Vec<UserDeviceConfig>> = None;
+        if let Some(user_device_list) = &vm_params.user_devices {
+            let mut user_device_config_list = Vec::new();
+            for item in user_device_list.iter() {
+                let user_device_config = UserDeviceConfig::parse(item)?;
+                user_device_config_list.push(user_device_config);
+            }
+            user_devices = Some(user_device_config_list);
+        }
+
+        let mut vdpa: Option<Vec<VdpaConfig>> = None;
+        if let Some(vdpa_list) = &vm_params.vdpa {
+            let mut vdpa_config_list = Vec::new();
+            for item in vdpa_list.iter() {
+                let vdpa_config = VdpaConfig::parse(item)?;
+                vdpa_config_list.push(vdpa_config);
+            }
+            vdpa = Some(vdpa_config_list);
+        }
+
+        let mut vsock: Option<VsockConfig> = None;
+        if let Some(vs) = &vm_params.vsock {
+            let vsock_config = VsockConfig::parse(vs)?;
+            vsock = Some(vsock_config);
+        }
+
+        let platform = vm_params.platform.map(PlatformConfig::parse).transpose()?;
+
+        #[cfg(target_arch = "x86_64")]
+        let mut sgx_epc: Option<Vec<SgxEpcConfig>> = None;
+        #[cfg(target_arch = "x86_64")]
+        {
+            if let Some(sgx_epc_list) = &vm_params.sgx_epc {
+                let mut sgx_epc_config_list = Vec::new();
+                for item in sgx_epc_list.iter() {
+                    let sgx_epc_config = SgxEpcConfig::parse(item)?;
+                    sgx_epc_config_list.push(sgx_epc_config);
+                }
+                sgx_epc = Some(sgx_epc_config_list);
+            }
+        }
+
+        let mut numa: Option<Vec<NumaConfig>> = None;
+        if let Some(numa_list) = &vm_params.numa {
+            let mut numa_config_list = Vec::new();
+            for item in numa_list.iter() {
+                let numa_config = NumaConfig::parse(item)?;
+                numa_config_list.push(numa_config);
+            }
+            numa = Some(numa_config_list);
+        }
+
+        let payload = if vm_params.kernel.is_some() || vm_params.firmware.is_some() {
+            Some(PayloadConfig {
+                kernel: vm_params.kernel.map(PathBuf::from),
+                initramfs: vm_params.initramfs.map(PathBuf::from),
+                cmdline: vm_params.cmdline.map(|s| s.to_string()),
+                firmware: vm_params.firmware.map(PathBuf::from),
+                #[cfg(target_arch = "aarch64")]
+                firmware_vars: vm_params.firmware_vars.map(PathBuf::from),
+            })
+        } else {
+            None
+        };
+
+        let mut tpm: Option<TpmConfig> = None;
+        if let Some(tc) = vm_params.tpm {
+            let tpm_conf = TpmConfig::parse(tc)?;
+            tpm = Some(TpmConfig {
+                socket: tpm_conf.socket,
+            });
+        }
+
+        let mut iothreads: Option<Vec<IoThreadConfig>> = None;
+        if let Some(iothread_list) = &vm_params.iothreads {
+            let mut iothread_config_list = Vec::new();
+            for item in iothread_list.iter() {
+                let iothread_config = IoThreadConfig::parse(item)?;
+                iothread_config_list.push(iothread_config);
+            }
+            iothreads = Some(iothread_config_list);
+        }
+
+        let mut rate_limit_groups: Option<Vec<RateLimiterGroupConfig>> = None;
+        if let Some(rate_limit_group_list) = &vm_params.rate_limit_groups {
+            let mut rate_limit_group_config_list = Vec::new();
+            for item in rate_limit_group_list.iter() {
+                let rate_limit_group_config = RateLimiterGroupConfig::parse(item)?;
+                rate_limit_group_config_list.push(rate_limit_group_config);
+            }
+            rate_limit_groups = Some(rate_limit_group_config_list);
+        }
+
+        let hibernation = vm_params
+            .hibernation
+            .map(HibernationConfig::parse)
+            .transpose()?;
+
+        let cloud_init = vm_params
+            .cloud_init
+            .map(CloudInitConfig::parse)
+            .transpose()?;
+
+        let ignition = vm_params.ignition.map(IgnitionConfig::parse).transpose()?;
+
+        let fw_cfg = vm_params.fw_cfg.map(FwCfgConfig::parse).transpose()?;
+        let pvpanic = vm_params.pvpanic.map(PvPanicConfig::parse).transpose()?;
+        let gpu = vm_params.gpu.map(GpuConfig::parse).transpose()?;
+        let sound = vm_params.sound.map(SoundConfig::parse).transpose()?;
+
+        let mut input: Option<Vec<InputConfig>> = None;
+        if let Some(input_list) = &vm_params.input {
+            let mut input_config_list = Vec::new();
+            for item in input_list.iter() {
+                let input_config = InputConfig::parse(item)?;
+                input_config_list.push(input_config);
+            }
+            input = Some(input_config_list);
+        }
+
+        let mut channels: Option<Vec<ChannelConfig>> = None;
+        if let Some(channel_list) = &vm_params.channels {
+            let mut channel_config_list = Vec::new();
+            for item in channel_list.iter() {
+                let channel_config = ChannelConfig::parse(item)?;
+                channel_config_list.push(channel_config);
+            }
+            channels = Some(channel_config_list);
+        }
+
+        let mut scsi: Option<Vec<ScsiConfig>> = None;
+        if let Some(scsi_list) = &vm_params.scsi {
+            let mut scsi_config_list = Vec::new();
+            for item in scsi_list.iter() {
+                let scsi_config = ScsiConfig::parse(item)?;
+                scsi_config_list.push(scsi_config);
+            }
+            scsi = Some(scsi_config_list);
+        }
+        if let Some(scsi_lun_list) = &vm_params.scsi_luns {
+            for item in scsi_lun_list.iter() {
+                let (controller, scsi_lun_config) = ScsiLunConfig::parse(item)?;
+                scsi.iter_mut()
+                    .flatten()
+                    .find(|scsi_config| scsi_config.id.as_ref() == Some(&controller))
+                    .ok_or(Error::ParseScsiLunUnknownController(controller))?
+                    .luns
+                    .push(scsi_lun_config);
+            }
+        }
+        let crypto = vm_params.crypto.map(CryptoConfig::parse).transpose()?;
+        let watchdog_action = vm_params
+            .watchdog_action
+            .map(|action| {
+                action
+                    .parse()
+                    .map_err(|_| Error::ParseWatchdogAction(action.to_owned()))
+            })
+            .transpose()?
+            .unwrap_or_default();
+
+        #[cfg(feature = "guest_debug")]
+        let gdb = vm_params.gdb;
+
+        let mut config = VmConfig {
+            cpus: CpusConfig::parse(vm_params.cpus)?,
+            memory: MemoryConfig::parse(vm_params.memory, vm_params.memory_zones)?,
+            payload,
+            disks,
+            net,
+            rng,
+            balloon,
+            fs,
+            pmem,
+            serial,
+            console,
+            devices,
+            user_devices,
+            vdpa,
+            vsock,
+            iommu: false, // updated in VmConfig::validate()
+            #[cfg(target_arch = "x86_64")]
+            sgx_epc,
+            numa,
+            watchdog: vm_params.watchdog,
+            watchdog_action,
+            #[cfg(feature = "guest_debug")]
+            gdb,
+            platform,
+            tpm,
+            iothreads,
+            rate_limit_groups,
+            hibernation,
+            cloud_init,
+            ignition,
+            fw_cfg,
+            pvpanic,
+            gpu,
+            sound,
+            input,
+            channels,
+            scsi,
+            crypto,
+        };
+        config.validate().map_err(Error::Validation)?;
+        Ok(config)
+    }
+
+    #[cfg(feature = "tdx")]
+    pub fn is_tdx_enabled(&self) -> bool {
+        self.platform.as_ref().map(|p| p.tdx).unwrap_or(false)
+    }
+
+    #[cfg(feature = "sev_snp")]
+    pub fn is_sev_snp_enabled(&self) -> bool {
+        self.platform.as_ref().map(|p| p.sev_snp).unwrap_or(false)
+    }
+
+    #[cfg(feature = "sev_snp")]
+    pub fn is_sev_es_enabled(&self) -> bool {
+        self.platform.as_ref().map(|p| p.sev_es).unwrap_or(false)
+    }
+
+    #[cfg(feature = "sev_snp")]
+    pub fn is_sev_enabled(&self) -> bool {
+        self.is_sev_snp_enabled() || self.is_sev_es_enabled()
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    pub fn is_ps2_enabled(&self) -> bool {
+        self.platform.as_ref().map(|p| p.ps2).unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, os::fd::AsRawFd};
+
+    use super::*;
+    use net_util::MacAddr;
+
+    #[test]
+    fn test_cpu_parsing() -> Result<()> {
+        assert_eq!(CpusConfig::parse("")?, CpusConfig::default());
+
+        assert_eq!(
+            CpusConfig::parse("boot=1")?,
+            CpusConfig {
+                boot_vcpus: 1,
+                max_vcpus: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            CpusConfig::parse("boot=1,max=2")?,
+            CpusConfig {
+                boot_vcpus: 1,
+                max_vcpus: 2,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            CpusConfig::parse("boot=8,topology=2:2:1:2")?,
+            CpusConfig {
+                boot_vcpus: 8,
+                max_vcpus: 8,
+                topology: Some(CpuTopology {
+                    threads_per_core: 2,
+                    cores_per_die: 2,
+                    dies_per_package: 1,
+                    packages: 2,
+                    #[cfg(target_arch = "x86_64")]
+                    efficiency_cores_per_die: 0,
+                }),
+                ..Default::default()
+            }
+        );
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            CpusConfig::parse("boot=12,topology=1:6:1:2:4")?,
+            CpusConfig {
+                boot_vcpus: 12,
+                max_vcpus: 12,
+                topology: Some(CpuTopology {
+                    threads_per_core: 1,
+                    cores_per_die: 6,
+                    dies_per_package: 1,
+                    packages: 2,
+                    efficiency_cores_per_die: 4,
+                }),
+                ..Default::default()
+            }
+        );
+
+        assert!(CpusConfig::parse("boot=8,topology=2:2:1").is_err());
+        assert!(CpusConfig::parse("boot=8,topology=2:2:1:x").is_err());
+        assert_eq!(
+            CpusConfig::parse("boot=1,kvm_hyperv=on")?,
+            CpusConfig {
+                boot_vcpus: 1,
+                max_vcpus: 1,
+                kvm_hyperv: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            CpusConfig::parse("boot=2,affinity=[0@[0,2],1@[1,3]]")?,
+            CpusConfig {
+                boot_vcpus: 2,
+                max_vcpus: 2,
+                affinity: Some(vec![
+                    CpuAffinity {
+                        vcpu: 0,
+                        host_cpus: vec![0, 2],
+                    },
+                    CpuAffinity {
+                        vcpu: 1,
+                        host_cpus: vec![1, 3],
+                    }
+                ]),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            CpusConfig::parse("boot=2,placement=auto-numa")?,
+            CpusConfig {
+                boot_vcpus: 2,
+                max_vcpus: 2,
+                placement: Some(CpuPlacement::AutoNuma),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            CpusConfig::parse("boot=2,placement=scatter")?,
+            CpusConfig {
+                boot_vcpus: 2,
+                max_vcpus: 2,
+                placement: Some(CpuPlacement::Scatter),
+                ..Default::default()
+            },
+        );
+        assert!(CpusConfig::parse("boot=2,placement=random").is_err());
+        assert_eq!(
+            CpusConfig::parse("boot=2,sched=fifo:50")?,
+            CpusConfig {
+                boot_vcpus: 2,
+                max_vcpus: 2,
+                sched: Some(CpuScheduling {
+                    policy: CpuSchedulingPolicy::Fifo,
+                    priority: 50,
+                }),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            CpusConfig::parse("boot=2,sched=rr:10")?,
+            CpusConfig {
+                boot_vcpus: 2,
+                max_vcpus: 2,
+                sched: Some(CpuScheduling {
+                    policy: CpuSchedulingPolicy::RoundRobin,
+                    priority: 10,
+                }),
+                ..Default::default()
+            },
+        );
+        assert!(CpusConfig::parse("boot=2,sched=idle:10").is_err());
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            CpusConfig::parse("features=[amx,-avx512f,+invtsc]")?,
+            CpusConfig {
+                features: CpuFeatures {
+                    amx: true,
+                    cpuid_enable: vec!["invtsc".to_string()],
+                    cpuid_disable: vec!["avx512f".to_string()],
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            CpusConfig::parse("features=+amx")?,
+            CpusConfig {
+                features: CpuFeatures {
+                    amx: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            CpusConfig::parse("features=sgx_provision")?,
+            CpusConfig {
+                features: CpuFeatures {
+                    sgx_provision: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            CpusConfig::parse("features=[-kvm_steal_time,-kvmclock]")?,
+            CpusConfig {
+                features: CpuFeatures {
+                    kvm_disable: vec!["kvm_steal_time".to_string(), "kvmclock".to_string()],
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        #[cfg(target_arch = "x86_64")]
+        assert!(CpusConfig::parse("features=[-foo]").is_err());
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            CpusConfig::parse("boot=2,model=Skylake-Server")?,
+            CpusConfig {
+                boot_vcpus: 2,
+                max_vcpus: 2,
+                model: Some("Skylake-Server".to_string()),
+                ..Default::default()
+            },
+        );
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            CpusConfig::parse("boot=2,microcode_revision=0xf0")?,
+            CpusConfig {
+                boot_vcpus: 2,
+                max_vcpus: 2,
+                microcode_revision: Some(0xf0),
+                ..Default::default()
+            },
+        );
+        #[cfg(target_arch = "x86_64")]
+        assert!(CpusConfig::parse("microcode_revision=0xfoo").is_err());
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            CpusConfig::parse("boot=2,max=4,apic_ids=[0,2,8,10]")?,
+            CpusConfig {
+                boot_vcpus: 2,
+                max_vcpus: 4,
+                apic_ids: Some(vec![0, 2, 8, 10]),
+                ..Default::default()
+            },
+        );
+        #[cfg(target_arch = "aarch64")]
+        assert_eq!(
+            CpusConfig::parse("features=pmu")?,
+            CpusConfig {
+                features: CpuFeatures {
+                    pmu: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        #[cfg(target_arch = "aarch64")]
+        assert_eq!(
+            CpusConfig::parse("features=[pmu,mte]")?,
+            CpusConfig {
+                features: CpuFeatures {
+                    pmu: true,
+                    mte: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        #[cfg(target_arch = "aarch64")]
+        assert_eq!(
+            CpusConfig::parse("features=pauth")?,
+            CpusConfig {
+                features: CpuFeatures {
+                    pauth: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        #[cfg(target_arch = "aarch64")]
+        assert_eq!(
+            CpusConfig::parse("features=nested")?,
+            CpusConfig {
+                features: CpuFeatures {
+                    nested: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        #[cfg(target_arch = "aarch64")]
+        assert_eq!(
+            CpusConfig::parse("boot=2,sve_vl=512")?,
+            CpusConfig {
+                boot_vcpus: 2,
+                max_vcpus: 2,
+                sve_vl: Some(512),
+                ..Default::default()
+            },
+        );
+
+        Ok(())
+    }
 
     #[test]
-    fn test_cpu_parsing() -> Result<()> {
-        assert_eq!(CpusConfig::parse("")?, CpusConfig::default());
+    #[cfg(target_arch = "x86_64")]
+    fn test_platform_parsing() -> Result<()> {
+        assert_eq!(PlatformConfig::parse("")?, PlatformConfig::default());
+        assert_eq!(
+            PlatformConfig::parse("num_pci_segments=2")?,
+            PlatformConfig {
+                num_pci_segments: 2,
+                ..Default::default()
+            }
+        );
+        assert!(PlatformConfig::parse("ps2=maybe").is_err());
+        #[cfg(feature = "tdx")]
+        {
+            assert_eq!(
+                PlatformConfig::parse("tdx=on,tdx_qgs=vsock:2:4050")?,
+                PlatformConfig {
+                    tdx: true,
+                    tdx_qgs: Some(QuoteGenerationService::Vsock { cid: 2, port: 4050 }),
+                    ..Default::default()
+                }
+            );
+            assert_eq!(
+                PlatformConfig::parse("tdx=on,tdx_qgs=unix:/run/qgs.socket")?,
+                PlatformConfig {
+                    tdx: true,
+                    tdx_qgs: Some(QuoteGenerationService::Unix(PathBuf::from(
+                        "/run/qgs.socket"
+                    ))),
+                    ..Default::default()
+                }
+            );
+            assert!(PlatformConfig::parse("tdx=on,tdx_qgs=vsock:2").is_err());
+            assert!(PlatformConfig::parse("tdx=on,tdx_qgs=/run/qgs.socket").is_err());
+        }
+        #[cfg(feature = "sev_snp")]
+        assert_eq!(
+            PlatformConfig::parse("sev_snp=on")?,
+            PlatformConfig {
+                sev_snp: true,
+                ..Default::default()
+            }
+        );
+        #[cfg(feature = "sev_snp")]
+        assert_eq!(
+            PlatformConfig::parse("sev_es=on")?,
+            PlatformConfig {
+                sev_es: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            PlatformConfig::parse("ps2=on")?,
+            PlatformConfig {
+                ps2: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            PlatformConfig::parse("reserved_regions=[0xfd000000:0x1000,64M:1M]")?,
+            PlatformConfig {
+                reserved_regions: Some(vec![
+                    ReservedRegion {
+                        start: 0xfd00_0000,
+                        size: 0x1000,
+                    },
+                    ReservedRegion {
+                        start: 64 << 20,
+                        size: 1 << 20,
+                    },
+                ]),
+                ..Default::default()
+            }
+        );
+        assert!(PlatformConfig::parse("reserved_regions=[0x1000]").is_err());
+        assert_eq!(
+            PlatformConfig::parse("smbios_file=/path/to/smbios.bin")?,
+            PlatformConfig {
+                smbios_file: Some(PathBuf::from("/path/to/smbios.bin")),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            PlatformConfig::parse("mptable=off")?,
+            PlatformConfig {
+                mptable: false,
+                ..Default::default()
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mem_parsing() -> Result<()> {
+        assert_eq!(MemoryConfig::parse("", None)?, MemoryConfig::default());
+        // Default string
+        assert_eq!(
+            MemoryConfig::parse("size=512M", None)?,
+            MemoryConfig::default()
+        );
+        assert_eq!(
+            MemoryConfig::parse("size=512M,mergeable=on", None)?,
+            MemoryConfig {
+                size: 512 << 20,
+                mergeable: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            MemoryConfig::parse("mergeable=on", None)?,
+            MemoryConfig {
+                mergeable: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            MemoryConfig::parse("size=1G,mergeable=off", None)?,
+            MemoryConfig {
+                size: 1 << 30,
+                mergeable: false,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            MemoryConfig::parse("hotplug_method=acpi", None)?,
+            MemoryConfig {
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            MemoryConfig::parse("hotplug_method=acpi,hotplug_size=512M", None)?,
+            MemoryConfig {
+                hotplug_size: Some(512 << 20),
+                ..Default::default()
+            }
+        );
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            MemoryConfig::parse("size=8G,mmio32_hole_size=2G", None)?,
+            MemoryConfig {
+                size: 8 << 30,
+                mmio32_hole_size: Some(2 << 30),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            MemoryConfig::parse("hotplug_method=virtio-mem,hotplug_size=512M", None)?,
+            MemoryConfig {
+                hotplug_size: Some(512 << 20),
+                hotplug_method: HotplugMethod::VirtioMem,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            MemoryConfig::parse("hugepages=on,size=1G,hugepage_size=2M", None)?,
+            MemoryConfig {
+                hugepage_size: Some(2 << 20),
+                size: 1 << 30,
+                hugepages: true,
+                ..Default::default()
+            }
+        );
+        #[cfg(target_arch = "x86_64")]
+        {
+            let memory = MemoryConfig::parse(
+                "size=0",
+                Some(vec![
+                    "id=mem0,size=1G",
+                    "id=mem1,size=1G,memmap_type=soft_reserved",
+                ]),
+            )?;
+            let zones = memory.zones.unwrap();
+            assert_eq!(zones[0].memmap_type, MemmapType::Ram);
+            assert_eq!(zones[1].memmap_type, MemmapType::SoftReserved);
+            assert!(
+                MemoryConfig::parse("size=0", Some(vec!["id=mem0,size=1G,memmap_type=foo"]))
+                    .is_err()
+            );
+        }
+        Ok(())
+    }
 
+    #[test]
+    fn test_disk_parsing() -> Result<()> {
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,id=mydisk0")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                id: Some("mydisk0".to_owned()),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("vhost_user=true,socket=/tmp/sock")?,
+            DiskConfig {
+                vhost_socket: Some(String::from("/tmp/sock")),
+                vhost_user: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,iommu=on")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                iommu: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,iommu=on,queue_size=256")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                iommu: true,
+                queue_size: 256,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,iommu=on,queue_size=256,num_queues=4")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                iommu: true,
+                queue_size: 256,
+                num_queues: 4,
+                ..Default::default()
+            }
+        );
         assert_eq!(
-            CpusConfig::parse("boot=1")?,
-            CpusConfig {
-                boot_vcpus: 1,
-                max_vcpus: 1,
+            DiskConfig::parse("path=/path/to_file,direct=on")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                direct: true,
                 ..Default::default()
             }
         );
         assert_eq!(
-            CpusConfig::parse("boot=1,max=2")?,
-            CpusConfig {
-                boot_vcpus: 1,
-                max_vcpus: 2,
+            DiskConfig::parse("path=/path/to_file")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
                 ..Default::default()
             }
         );
         assert_eq!(
-            CpusConfig::parse("boot=8,topology=2:2:1:2")?,
-            CpusConfig {
-                boot_vcpus: 8,
-                max_vcpus: 8,
-                topology: Some(CpuTopology {
-                    threads_per_core: 2,
-                    cores_per_die: 2,
-                    dies_per_package: 1,
-                    packages: 2
-                }),
+            DiskConfig::parse("path=/path/to_file")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
                 ..Default::default()
             }
         );
-
-        assert!(CpusConfig::parse("boot=8,topology=2:2:1").is_err());
-        assert!(CpusConfig::parse("boot=8,topology=2:2:1:x").is_err());
         assert_eq!(
-            CpusConfig::parse("boot=1,kvm_hyperv=on")?,
-            CpusConfig {
-                boot_vcpus: 1,
-                max_vcpus: 1,
-                kvm_hyperv: true,
+            DiskConfig::parse("path=/path/to_file,id=mydisk0,alias=rootfs")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                id: Some("mydisk0".to_owned()),
+                alias: Some("rootfs".to_owned()),
                 ..Default::default()
             }
         );
         assert_eq!(
-            CpusConfig::parse("boot=2,affinity=[0@[0,2],1@[1,3]]")?,
-            CpusConfig {
-                boot_vcpus: 2,
-                max_vcpus: 2,
-                affinity: Some(vec![
-                    CpuAffinity {
-                        vcpu: 0,
-                        host_cpus: vec![0, 2],
-                    },
-                    CpuAffinity {
-                        vcpu: 1,
-                        host_cpus: vec![1, 3],
-                    }
-                ]),
+            DiskConfig::parse("path=/path/to_file,num_queues=2,iothreads=[io0,io1]")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                num_queues: 2,
+                iothreads: Some(vec!["io0".to_owned(), "io1".to_owned()]),
                 ..Default::default()
-            },
+            }
         );
 
         Ok(())
     }
 
     #[test]
-    fn test_mem_parsing() -> Result<()> {
-        assert_eq!(MemoryConfig::parse("", None)?, MemoryConfig::default());
-        // Default string
+    fn test_net_parsing() -> Result<()> {
+        // mac address is random
         assert_eq!(
-            MemoryConfig::parse("size=512M", None)?,
-            MemoryConfig::default()
+            NetConfig::parse("mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef")?,
+            NetConfig {
+                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
+                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
+                fds: None,
+                id: None,
+                tap: None,
+                vhost_socket: None,
+                ..Default::default()
+            }
         );
+
         assert_eq!(
-            MemoryConfig::parse("size=512M,mergeable=on", None)?,
-            MemoryConfig {
-                size: 512 << 20,
-                mergeable: true,
+            NetConfig::parse("mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef,id=mynet0")?,
+            NetConfig {
+                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
+                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
+                id: Some("mynet0".to_owned()),
+                fds: None,
+                tap: None,
+                vhost_socket: None,
                 ..Default::default()
             }
         );
+
         assert_eq!(
-            MemoryConfig::parse("mergeable=on", None)?,
-            MemoryConfig {
-                mergeable: true,
+            NetConfig::parse(
+                "mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef,tap=tap0,ip=192.168.100.1,mask=255.255.255.128"
+            )?,
+            NetConfig {
+                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
+                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
+                tap: Some("tap0".to_owned()),
+                ip: "192.168.100.1".parse().unwrap(),
+                mask: "255.255.255.128".parse().unwrap(),
+                fds: None,
+                id: None,
+                vhost_socket: None,
                 ..Default::default()
             }
         );
+
         assert_eq!(
-            MemoryConfig::parse("size=1G,mergeable=off", None)?,
-            MemoryConfig {
-                size: 1 << 30,
-                mergeable: false,
+            NetConfig::parse(
+                "mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef,vhost_user=true,socket=/tmp/sock"
+            )?,
+            NetConfig {
+                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
+                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
+                vhost_user: true,
+                vhost_socket: Some("/tmp/sock".to_owned()),
+                fds: None,
+                id: None,
+                tap: None,
                 ..Default::default()
             }
         );
+
         assert_eq!(
-            MemoryConfig::parse("hotplug_method=acpi", None)?,
-            MemoryConfig {
+            NetConfig::parse("mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef,num_queues=4,queue_size=1024,iommu=on")?,
+            NetConfig {
+                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
+                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
+                num_queues: 4,
+                queue_size: 1024,
+                iommu: true,
+                fds: None,
+                id: None,
+                tap: None,
+                vhost_socket: None,
                 ..Default::default()
             }
         );
+
         assert_eq!(
-            MemoryConfig::parse("hotplug_method=acpi,hotplug_size=512M", None)?,
-            MemoryConfig {
-                hotplug_size: Some(512 << 20),
+            NetConfig::parse("mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef,iothreads=[io0]")?,
+            NetConfig {
+                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
+                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
+                iothreads: Some(vec!["io0".to_owned()]),
                 ..Default::default()
             }
         );
+
         assert_eq!(
-            MemoryConfig::parse("hotplug_method=virtio-mem,hotplug_size=512M", None)?,
-            MemoryConfig {
-                hotplug_size: Some(512 << 20),
-                hotplug_method: HotplugMethod::VirtioMem,
+            NetConfig::parse(
+                "mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef,num_queues=4,rss=on"
+            )?,
+            NetConfig {
+                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
+                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
+                num_queues: 4,
+                rss: true,
                 ..Default::default()
             }
         );
+
         assert_eq!(
-            MemoryConfig::parse("hugepages=on,size=1G,hugepage_size=2M", None)?,
-            MemoryConfig {
-                hugepage_size: Some(2 << 20),
-                size: 1 << 30,
-                hugepages: true,
+            NetConfig::parse("mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef,offload_uso=off")?,
+            NetConfig {
+                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
+                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
+                offload_uso: false,
+                ..Default::default()
+            }
+        );
+
+        assert_eq!(
+            NetConfig::parse("mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef,tap=tap0,vhost=on")?,
+            NetConfig {
+                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
+                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
+                tap: Some("tap0".to_owned()),
+                vhost: true,
                 ..Default::default()
             }
         );
+
+        // SAFETY: Safe as the file was just opened
+        let fd1 = unsafe { libc::dup(File::open("/dev/null").unwrap().as_raw_fd()) };
+        // SAFETY: Safe as the file was just opened
+        let fd2 = unsafe { libc::dup(File::open("/dev/null").unwrap().as_raw_fd()) };
+
+        assert_eq!(
+            &format!(
+                "{:?}",
+                NetConfig::parse(&format!(
+                    "mac=de:ad:be:ef:12:34,fd=[{fd1},{fd2}],num_queues=4"
+                ))?
+            ),
+            &format!("NetConfig {{ tap: None, ip: 192.168.249.1, mask: 255.255.255.0, \
+                mac: MacAddr {{ bytes: [222, 173, 190, 239, 18, 52] }}, host_mac: None, mtu: None, \
+                iommu: false, num_queues: 4, queue_size: 256, vhost_user: false, vhost_socket: None, \
+                vhost_mode: Client, id: None, alias: None, fds: Some([{fd1}, {fd2}]), \
+                rate_limiter_config: None, pci_segment: 0, offload_tso: true, offload_ufo: true, offload_uso: true, \
+                offload_csum: true, \
+                iothreads: None, rate_limit_group: None, rss: false, vhost: false }}")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_iothread_parsing() -> Result<()> {
+        assert!(IoThreadConfig::parse("").is_err());
+        assert!(IoThreadConfig::parse("host_cpus=[0]").is_err());
+        assert!(IoThreadConfig::parse("id=io0").is_err());
+        assert_eq!(
+            IoThreadConfig::parse("id=io0,host_cpus=[2-3,6]")?,
+            IoThreadConfig {
+                id: "io0".to_owned(),
+                host_cpus: vec![2, 3, 6],
+            }
+        );
+
         Ok(())
     }
 
     #[test]
-    fn test_disk_parsing() -> Result<()> {
+    fn test_rate_limit_group_parsing() -> Result<()> {
+        assert!(RateLimiterGroupConfig::parse("").is_err());
+        assert!(RateLimiterGroupConfig::parse("bw_size=1000,bw_refill_time=100").is_err());
         assert_eq!(
-            DiskConfig::parse("path=/path/to_file")?,
-            DiskConfig {
-                path: Some(PathBuf::from("/path/to_file")),
-                ..Default::default()
+            RateLimiterGroupConfig::parse("id=group0,bw_size=1000,bw_refill_time=100")?,
+            RateLimiterGroupConfig {
+                id: "group0".to_owned(),
+                rate_limiter_config: RateLimiterConfig {
+                    bandwidth: Some(TokenBucketConfig {
+                        size: 1000,
+                        one_time_burst: Some(0),
+                        refill_time: 100,
+                    }),
+                    ops: None,
+                },
             }
         );
         assert_eq!(
-            DiskConfig::parse("path=/path/to_file,id=mydisk0")?,
+            DiskConfig::parse("path=/path/to_file,rate_limit_group=group0")?,
             DiskConfig {
                 path: Some(PathBuf::from("/path/to_file")),
-                id: Some("mydisk0".to_owned()),
+                rate_limit_group: Some("group0".to_owned()),
                 ..Default::default()
             }
         );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cloud_init_parsing() -> Result<()> {
+        assert!(CloudInitConfig::parse("").is_err());
+        assert!(CloudInitConfig::parse("meta-data=/path/to/meta-data").is_err());
+        assert_eq!(
+            CloudInitConfig::parse("user-data=/path/to/user-data")?,
+            CloudInitConfig {
+                user_data: PathBuf::from("/path/to/user-data"),
+                meta_data: None,
+                network_config: None,
+            }
+        );
+        assert_eq!(
+            CloudInitConfig::parse(
+                "user-data=/path/to/user-data,meta-data=/path/to/meta-data,network-config=/path/to/network-config"
+            )?,
+            CloudInitConfig {
+                user_data: PathBuf::from("/path/to/user-data"),
+                meta_data: Some(PathBuf::from("/path/to/meta-data")),
+                network_config: Some(PathBuf::from("/path/to/network-config")),
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ignition_parsing() -> Result<()> {
+        assert!(IgnitionConfig::parse("").is_err());
+        assert_eq!(
+            IgnitionConfig::parse("path=/path/to/config.ign")?,
+            IgnitionConfig {
+                path: PathBuf::from("/path/to/config.ign"),
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fw_cfg_parsing() -> Result<()> {
+        assert_eq!(FwCfgConfig::parse("")?, FwCfgConfig::default());
+        assert_eq!(
+            FwCfgConfig::parse("payload=off")?,
+            FwCfgConfig {
+                payload: false,
+                files: None,
+            }
+        );
+        assert_eq!(
+            FwCfgConfig::parse("files=[opt/org.test/a:/path/to/a,opt/org.test/b:/path/to/b]")?,
+            FwCfgConfig {
+                payload: true,
+                files: Some(vec![
+                    FwCfgFile {
+                        name: "opt/org.test/a".to_string(),
+                        path: PathBuf::from("/path/to/a"),
+                    },
+                    FwCfgFile {
+                        name: "opt/org.test/b".to_string(),
+                        path: PathBuf::from("/path/to/b"),
+                    },
+                ]),
+            }
+        );
+        assert!(FwCfgConfig::parse("files=[opt/org.test/a]").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pvpanic_parsing() -> Result<()> {
+        assert_eq!(PvPanicConfig::parse("")?, PvPanicConfig::default());
+        assert_eq!(
+            PvPanicConfig::parse("on_panic=shutdown")?,
+            PvPanicConfig {
+                on_panic: PanicAction::Shutdown,
+                coredump_path: None,
+            }
+        );
+        assert_eq!(
+            PvPanicConfig::parse("on_panic=coredump,coredump_path=/path/to/dump")?,
+            PvPanicConfig {
+                on_panic: PanicAction::Coredump,
+                coredump_path: Some(PathBuf::from("/path/to/dump")),
+            }
+        );
+        assert!(PvPanicConfig::parse("on_panic=reboot").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gpu_parsing() -> Result<()> {
+        assert!(GpuConfig::parse("").is_err());
         assert_eq!(
-            DiskConfig::parse("vhost_user=true,socket=/tmp/sock")?,
-            DiskConfig {
-                vhost_socket: Some(String::from("/tmp/sock")),
-                vhost_user: true,
-                ..Default::default()
+            GpuConfig::parse("socket=/tmp/vnc.sock")?,
+            GpuConfig {
+                socket: PathBuf::from("/tmp/vnc.sock"),
+                width: 1024,
+                height: 768,
+                iommu: false,
             }
         );
         assert_eq!(
-            DiskConfig::parse("path=/path/to_file,iommu=on")?,
-            DiskConfig {
-                path: Some(PathBuf::from("/path/to_file")),
+            GpuConfig::parse("socket=/tmp/vnc.sock,width=1920,height=1080,iommu=on")?,
+            GpuConfig {
+                socket: PathBuf::from("/tmp/vnc.sock"),
+                width: 1920,
+                height: 1080,
                 iommu: true,
-                ..Default::default()
             }
         );
+        assert!(GpuConfig::parse("socket=/tmp/vnc.sock,width=wide").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sound_parsing() -> Result<()> {
+        assert_eq!(SoundConfig::parse("")?, SoundConfig::default());
         assert_eq!(
-            DiskConfig::parse("path=/path/to_file,iommu=on,queue_size=256")?,
-            DiskConfig {
-                path: Some(PathBuf::from("/path/to_file")),
-                iommu: true,
-                queue_size: 256,
-                ..Default::default()
+            SoundConfig::parse("playback=/tmp/playback,capture=/tmp/capture")?,
+            SoundConfig {
+                backend: SoundBackend::Pipe,
+                playback: Some(PathBuf::from("/tmp/playback")),
+                capture: Some(PathBuf::from("/tmp/capture")),
+                iommu: false,
             }
         );
         assert_eq!(
-            DiskConfig::parse("path=/path/to_file,iommu=on,queue_size=256,num_queues=4")?,
-            DiskConfig {
-                path: Some(PathBuf::from("/path/to_file")),
+            SoundConfig::parse("playback=/tmp/playback,iommu=on")?,
+            SoundConfig {
+                backend: SoundBackend::Pipe,
+                playback: Some(PathBuf::from("/tmp/playback")),
+                capture: None,
                 iommu: true,
-                queue_size: 256,
-                num_queues: 4,
-                ..Default::default()
             }
         );
         assert_eq!(
-            DiskConfig::parse("path=/path/to_file,direct=on")?,
-            DiskConfig {
-                path: Some(PathBuf::from("/path/to_file")),
-                direct: true,
-                ..Default::default()
+            SoundConfig::parse("backend=alsa,playback=plughw:0,capture=plughw:1")?,
+            SoundConfig {
+                backend: SoundBackend::Alsa,
+                playback: Some(PathBuf::from("plughw:0")),
+                capture: Some(PathBuf::from("plughw:1")),
+                iommu: false,
             }
         );
+        assert!(SoundConfig::parse("output=/tmp/playback").is_err());
+        assert!(SoundConfig::parse("backend=oss").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_crypto_parsing() -> Result<()> {
+        assert_eq!(CryptoConfig::parse("")?, CryptoConfig::default());
         assert_eq!(
-            DiskConfig::parse("path=/path/to_file")?,
-            DiskConfig {
-                path: Some(PathBuf::from("/path/to_file")),
-                ..Default::default()
+            CryptoConfig::parse("backend=af-alg,num_queues=4")?,
+            CryptoConfig {
+                backend: CryptoBackend::AfAlg,
+                num_queues: 4,
+                iommu: false,
             }
         );
         assert_eq!(
-            DiskConfig::parse("path=/path/to_file")?,
-            DiskConfig {
-                path: Some(PathBuf::from("/path/to_file")),
-                ..Default::default()
+            CryptoConfig::parse("backend=software,iommu=on")?,
+            CryptoConfig {
+                backend: CryptoBackend::Software,
+                num_queues: 1,
+                iommu: true,
             }
         );
+        assert!(CryptoConfig::parse("backend=openssl").is_err());
 
         Ok(())
     }
 
     #[test]
-    fn test_net_parsing() -> Result<()> {
-        // mac address is random
+    fn test_input_parsing() -> Result<()> {
+        assert_eq!(InputConfig::parse("")?, InputConfig::default());
         assert_eq!(
-            NetConfig::parse("mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef")?,
-            NetConfig {
-                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
-                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
-                fds: None,
-                id: None,
-                tap: None,
-                vhost_socket: None,
+            InputConfig::parse("kind=tablet")?,
+            InputConfig {
+                kind: Some(InputKind::Tablet),
                 ..Default::default()
             }
         );
-
         assert_eq!(
-            NetConfig::parse("mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef,id=mynet0")?,
-            NetConfig {
-                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
-                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
-                id: Some("mynet0".to_owned()),
-                fds: None,
-                tap: None,
-                vhost_socket: None,
+            InputConfig::parse("evdev=/dev/input/event3,iommu=on,id=kbd0")?,
+            InputConfig {
+                evdev: Some(PathBuf::from("/dev/input/event3")),
+                iommu: true,
+                id: Some("kbd0".to_owned()),
                 ..Default::default()
             }
         );
+        assert!(InputConfig::parse("kind=joystick").is_err());
+
+        Ok(())
+    }
 
+    #[test]
+    fn test_channel_parsing() -> Result<()> {
+        assert!(ChannelConfig::parse("").is_err());
+        assert!(ChannelConfig::parse("socket=/tmp/agent.sock").is_err());
         assert_eq!(
-            NetConfig::parse(
-                "mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef,tap=tap0,ip=192.168.100.1,mask=255.255.255.128"
-            )?,
-            NetConfig {
-                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
-                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
-                tap: Some("tap0".to_owned()),
-                ip: "192.168.100.1".parse().unwrap(),
-                mask: "255.255.255.128".parse().unwrap(),
-                fds: None,
-                id: None,
-                vhost_socket: None,
+            ChannelConfig::parse("name=org.example.agent,socket=/tmp/agent.sock")?,
+            ChannelConfig {
+                name: "org.example.agent".to_owned(),
+                socket: Some(PathBuf::from("/tmp/agent.sock")),
                 ..Default::default()
             }
         );
-
         assert_eq!(
-            NetConfig::parse(
-                "mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef,vhost_user=true,socket=/tmp/sock"
-            )?,
-            NetConfig {
-                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
-                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
-                vhost_user: true,
-                vhost_socket: Some("/tmp/sock".to_owned()),
-                fds: None,
-                id: None,
-                tap: None,
+            ChannelConfig::parse("name=metrics,pty=on")?,
+            ChannelConfig {
+                name: "metrics".to_owned(),
+                pty: true,
                 ..Default::default()
             }
         );
+        assert!(ChannelConfig::parse("name=metrics,file=/tmp/metrics").is_err());
+
+        Ok(())
+    }
 
+    #[test]
+    fn test_scsi_parsing() -> Result<()> {
+        assert_eq!(ScsiConfig::parse("")?, ScsiConfig::default());
         assert_eq!(
-            NetConfig::parse("mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef,num_queues=4,queue_size=1024,iommu=on")?,
-            NetConfig {
-                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
-                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
-                num_queues: 4,
-                queue_size: 1024,
+            ScsiConfig::parse("id=scsi0,alias=cdroms,iommu=on,pci_segment=1")?,
+            ScsiConfig {
+                id: Some("scsi0".to_owned()),
+                alias: Some("cdroms".to_owned()),
                 iommu: true,
-                fds: None,
-                id: None,
-                tap: None,
-                vhost_socket: None,
+                pci_segment: 1,
                 ..Default::default()
             }
         );
+        assert!(ScsiConfig::parse("lun=0").is_err());
 
-        // SAFETY: Safe as the file was just opened
-        let fd1 = unsafe { libc::dup(File::open("/dev/null").unwrap().as_raw_fd()) };
-        // SAFETY: Safe as the file was just opened
-        let fd2 = unsafe { libc::dup(File::open("/dev/null").unwrap().as_raw_fd()) };
+        assert!(ScsiLunConfig::parse("lun=0,path=/path/to/disk.img").is_err());
+        assert!(ScsiLunConfig::parse("controller=scsi0,path=/path/to/disk.img").is_err());
+        assert_eq!(
+            ScsiLunConfig::parse("controller=scsi0,lun=1,path=/path/to/disk.img,readonly=on")?,
+            (
+                "scsi0".to_owned(),
+                ScsiLunConfig {
+                    lun: 1,
+                    path: Some(PathBuf::from("/path/to/disk.img")),
+                    readonly: true,
+                    ..Default::default()
+                }
+            )
+        );
+        assert_eq!(
+            ScsiLunConfig::parse("controller=scsi0,lun=2,cdrom=on,serial=cd0")?,
+            (
+                "scsi0".to_owned(),
+                ScsiLunConfig {
+                    lun: 2,
+                    cdrom: true,
+                    serial: Some("cd0".to_owned()),
+                    ..Default::default()
+                }
+            )
+        );
+
+        Ok(())
+    }
 
+    #[test]
+    fn test_hibernation_parsing() -> Result<()> {
+        assert!(HibernationConfig::parse("").is_err());
         assert_eq!(
-            &format!(
-                "{:?}",
-                NetConfig::parse(&format!(
-                    "mac=de:ad:be:ef:12:34,fd=[{fd1},{fd2}],num_queues=4"
-                ))?
-            ),
-            &format!("NetConfig {{ tap: None, ip: 192.168.249.1, mask: 255.255.255.0, \
-                mac: MacAddr {{ bytes: [222, 173, 190, 239, 18, 52] }}, host_mac: None, mtu: None, \
-                iommu: false, num_queues: 4, queue_size: 256, vhost_user: false, vhost_socket: None, \
-                vhost_mode: Client, id: None, fds: Some([{fd1}, {fd2}]), \
-                rate_limiter_config: None, pci_segment: 0, offload_tso: true, offload_ufo: true, offload_csum: true }}")
+            HibernationConfig::parse("path=/var/lib/hibernation")?,
+            HibernationConfig {
+                path: PathBuf::from("/var/lib/hibernation"),
+            }
         );
 
         Ok(())
@@ -2480,6 +5202,35 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_parse_balloon() -> Result<()> {
+        assert_eq!(
+            BalloonConfig::parse("size=1G,deflate_on_oom=on")?,
+            BalloonConfig {
+                size: 1 << 30,
+                deflate_on_oom: true,
+                free_page_reporting: false,
+                auto_size: false,
+                min_size: 0,
+                max_size: None,
+            }
+        );
+        assert_eq!(
+            BalloonConfig::parse("auto_size=on,min_size=256M,max_size=2G")?,
+            BalloonConfig {
+                size: 0,
+                deflate_on_oom: false,
+                free_page_reporting: false,
+                auto_size: true,
+                min_size: 256 << 20,
+                max_size: Some(2 << 30),
+            }
+        );
+        assert!(BalloonConfig::parse("auto_size=maybe").is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn test_parse_fs() -> Result<()> {
         // "tag" and "socket" must be supplied
@@ -2512,6 +5263,26 @@ mod tests {
                 ..Default::default()
             }
         );
+        assert_eq!(
+            FsConfig::parse("tag=mytag,socket=/tmp/sock,dax=on")?,
+            FsConfig {
+                socket: PathBuf::from("/tmp/sock"),
+                tag: "mytag".to_owned(),
+                dax: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            FsConfig::parse("tag=mytag,socket=/tmp/sock,dax=on,cache_size=4G")?,
+            FsConfig {
+                socket: PathBuf::from("/tmp/sock"),
+                tag: "mytag".to_owned(),
+                dax: true,
+                cache_size: 4 << 30,
+                ..Default::default()
+            }
+        );
+        assert!(FsConfig::parse("tag=mytag,socket=/tmp/sock,cache_size=4G").is_err());
 
         Ok(())
     }
@@ -2717,6 +5488,24 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_numa_parsing() -> Result<()> {
+        assert_eq!(
+            NumaConfig::parse(
+                "guest_numa_id=1,memory_zones=mem1,memory_latency=250,memory_bandwidth=20000"
+            )?,
+            NumaConfig {
+                guest_numa_id: 1,
+                memory_zones: Some(vec!["mem1".to_string()]),
+                memory_latency: Some(250),
+                memory_bandwidth: Some(20000),
+                ..Default::default()
+            }
+        );
+        assert!(NumaConfig::parse("memory_latency=fast").is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_config_validation() {
         let mut valid_config = VmConfig {
@@ -2737,6 +5526,8 @@ mod tests {
                 prefault: false,
                 zones: None,
                 thp: true,
+                #[cfg(target_arch = "x86_64")]
+                mmio32_hole_size: None,
             },
             payload: Some(PayloadConfig {
                 kernel: Some(PathBuf::from("/path/to/kernel")),
@@ -2770,45 +5561,302 @@ mod tests {
             sgx_epc: None,
             numa: None,
             watchdog: false,
+            watchdog_action: WatchdogAction::Reset,
             #[cfg(feature = "guest_debug")]
             gdb: false,
             platform: None,
             tpm: None,
+            iothreads: None,
+            rate_limit_groups: None,
+            hibernation: None,
+            cloud_init: None,
+            ignition: None,
+            fw_cfg: None,
+            pvpanic: None,
+            gpu: None,
+            sound: None,
+            input: None,
+            channels: None,
+            scsi: None,
+            crypto: None,
         };
 
-        assert!(valid_config.validate().is_ok());
+        assert!(valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.serial.mode = ConsoleOutputMode::Tty;
+        invalid_config.console.mode = ConsoleOutputMode::Tty;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::DoubleTtyMode)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.payload = None;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::KernelMissing)
+        );
+
+        #[cfg(not(builtin_fw_image))]
+        {
+            let mut invalid_config = valid_config.clone();
+            invalid_config.payload = Some(PayloadConfig {
+                kernel: Some(PathBuf::from(BUILTIN_FIRMWARE)),
+                ..Default::default()
+            });
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::BuiltinFirmwareUnsupported)
+            );
+        }
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.serial.mode = ConsoleOutputMode::File;
+        invalid_config.serial.file = None;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::ConsoleFileMissing)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.cpus.max_vcpus = 16;
+        invalid_config.cpus.boot_vcpus = 32;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::CpusMaxLowerThanBoot)
+        );
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.cpus.boot_vcpus = 512;
+        still_valid_config.cpus.max_vcpus = 512;
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.cpus.max_vcpus = 8192;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::TooManyVcpus(8192))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.cpus.placement = Some(CpuPlacement::Compact);
+        invalid_config.cpus.affinity = Some(vec![CpuAffinity {
+            vcpu: 0,
+            host_cpus: vec![0],
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::CpuPlacementWithAffinity)
+        );
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.cpus.sched = Some(CpuScheduling {
+            policy: CpuSchedulingPolicy::Fifo,
+            priority: 99,
+        });
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.cpus.sched = Some(CpuScheduling {
+            policy: CpuSchedulingPolicy::RoundRobin,
+            priority: 0,
+        });
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidCpuSchedulingPriority(0))
+        );
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.iothreads = Some(vec![IoThreadConfig {
+            id: "io0".to_owned(),
+            host_cpus: vec![0],
+        }]);
+        still_valid_config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("/path/to/image")),
+            iothreads: Some(vec!["io0".to_owned()]),
+            ..Default::default()
+        }]);
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = still_valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("/path/to/image")),
+            iothreads: Some(vec!["io1".to_owned()]),
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::UnknownIoThread("io1".to_owned()))
+        );
+
+        let mut invalid_config = still_valid_config.clone();
+        invalid_config.iothreads = Some(vec![
+            IoThreadConfig {
+                id: "io0".to_owned(),
+                host_cpus: vec![0],
+            },
+            IoThreadConfig {
+                id: "io0".to_owned(),
+                host_cpus: vec![1],
+            },
+        ]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::IoThreadNotUnique("io0".to_owned()))
+        );
+
+        let mut invalid_config = still_valid_config.clone();
+        invalid_config.iothreads = Some(vec![IoThreadConfig {
+            id: "io0".to_owned(),
+            host_cpus: Vec::new(),
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::IoThreadHostCpusEmpty("io0".to_owned()))
+        );
 
-        let mut invalid_config = valid_config.clone();
-        invalid_config.serial.mode = ConsoleOutputMode::Tty;
-        invalid_config.console.mode = ConsoleOutputMode::Tty;
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.rate_limit_groups = Some(vec![RateLimiterGroupConfig {
+            id: "group0".to_owned(),
+            rate_limiter_config: RateLimiterConfig::default(),
+        }]);
+        still_valid_config.net = Some(vec![NetConfig {
+            rate_limit_group: Some("group0".to_owned()),
+            ..Default::default()
+        }]);
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = still_valid_config.clone();
+        invalid_config.net = Some(vec![NetConfig {
+            rate_limit_group: Some("group1".to_owned()),
+            ..Default::default()
+        }]);
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::DoubleTtyMode)
+            Err(ValidationError::UnknownRateLimiterGroup(
+                "group1".to_owned()
+            ))
         );
 
-        let mut invalid_config = valid_config.clone();
-        invalid_config.payload = None;
+        let mut invalid_config = still_valid_config.clone();
+        invalid_config.net = Some(vec![NetConfig {
+            rate_limit_group: Some("group0".to_owned()),
+            rate_limiter_config: Some(RateLimiterConfig::default()),
+            ..Default::default()
+        }]);
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::KernelMissing)
+            Err(ValidationError::RateLimiterWithGroup)
         );
 
-        let mut invalid_config = valid_config.clone();
-        invalid_config.serial.mode = ConsoleOutputMode::File;
-        invalid_config.serial.file = None;
+        let mut invalid_config = still_valid_config.clone();
+        invalid_config
+            .rate_limit_groups
+            .as_mut()
+            .unwrap()
+            .push(RateLimiterGroupConfig {
+                id: "group0".to_owned(),
+                rate_limiter_config: RateLimiterConfig::default(),
+            });
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::ConsoleFileMissing)
+            Err(ValidationError::RateLimiterGroupNotUnique(
+                "group0".to_owned()
+            ))
         );
 
         let mut invalid_config = valid_config.clone();
-        invalid_config.cpus.max_vcpus = 16;
-        invalid_config.cpus.boot_vcpus = 32;
+        invalid_config.numa = Some(vec![
+            NumaConfig {
+                guest_numa_id: 0,
+                memory_zones: Some(vec![]),
+                memory_latency: Some(100),
+                memory_bandwidth: Some(50000),
+                ..Default::default()
+            },
+            NumaConfig {
+                guest_numa_id: 1,
+                memory_zones: Some(vec![]),
+                memory_latency: Some(250),
+                ..Default::default()
+            },
+        ]);
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::CpusMaxLowerThanBoot)
+            Err(ValidationError::NumaMemoryAttributesIncomplete(1))
         );
 
+        #[cfg(target_arch = "x86_64")]
+        {
+            let mut invalid_config = valid_config.clone();
+            invalid_config.cpus.model = Some("Pentium".to_string());
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::UnknownCpuModel("Pentium".to_string()))
+            );
+
+            let mut still_valid_config = valid_config.clone();
+            still_valid_config.cpus.apic_ids = Some(vec![0]);
+            assert!(still_valid_config.validate().is_ok());
+
+            let mut invalid_config = valid_config.clone();
+            invalid_config.cpus.apic_ids = Some(vec![0, 2]);
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::ApicIdsCountMismatch(2, 1))
+            );
+
+            let mut invalid_config = valid_config.clone();
+            invalid_config.cpus.apic_ids = Some(vec![2]);
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::InvalidApicIds)
+            );
+
+            let mut invalid_config = valid_config.clone();
+            invalid_config.cpus.boot_vcpus = 2;
+            invalid_config.cpus.max_vcpus = 2;
+            invalid_config.cpus.apic_ids = Some(vec![0, 0]);
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::InvalidApicIds)
+            );
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        {
+            let mut still_valid_config = valid_config.clone();
+            still_valid_config.cpus.sve_vl = Some(2048);
+            assert!(still_valid_config.validate().is_ok());
+
+            let mut invalid_config = valid_config.clone();
+            invalid_config.cpus.sve_vl = Some(384 + 64);
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::InvalidSveVectorLength(448))
+            );
+
+            let mut invalid_config = valid_config.clone();
+            invalid_config.cpus.sve_vl = Some(4096);
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::InvalidSveVectorLength(4096))
+            );
+
+            let mut still_valid_config = valid_config.clone();
+            still_valid_config.cpus.features.mte = true;
+            assert!(still_valid_config.validate().is_ok());
+
+            let mut invalid_config = valid_config.clone();
+            invalid_config.cpus.features.mte = true;
+            invalid_config.memory.hugepages = true;
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::MteRequiresAnonymousMemory)
+            );
+        }
+
         let mut invalid_config = valid_config.clone();
         invalid_config.cpus.max_vcpus = 16;
         invalid_config.cpus.boot_vcpus = 16;
@@ -2817,12 +5865,32 @@ mod tests {
             cores_per_die: 8,
             dies_per_package: 1,
             packages: 2,
+            #[cfg(target_arch = "x86_64")]
+            efficiency_cores_per_die: 0,
         });
         assert_eq!(
             invalid_config.validate(),
             Err(ValidationError::CpuTopologyCount)
         );
 
+        #[cfg(target_arch = "x86_64")]
+        {
+            let mut invalid_config = valid_config.clone();
+            invalid_config.cpus.max_vcpus = 16;
+            invalid_config.cpus.boot_vcpus = 16;
+            invalid_config.cpus.topology = Some(CpuTopology {
+                threads_per_core: 1,
+                cores_per_die: 8,
+                dies_per_package: 1,
+                packages: 2,
+                efficiency_cores_per_die: 9,
+            });
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::CpuTopologyEfficiencyCores)
+            );
+        }
+
         let mut invalid_config = valid_config.clone();
         invalid_config.disks = Some(vec![DiskConfig {
             vhost_socket: Some("/path/to/sock".to_owned()),
@@ -2918,6 +5986,52 @@ mod tests {
             Err(ValidationError::NoHardwareChecksumOffload)
         );
 
+        let mut invalid_config = valid_config.clone();
+        invalid_config.net = Some(vec![NetConfig {
+            offload_csum: false,
+            offload_tso: false,
+            offload_ufo: false,
+            fds: None,
+            id: None,
+            tap: None,
+            vhost_socket: None,
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::NoHardwareChecksumOffload)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.net = Some(vec![NetConfig {
+            vhost_user: true,
+            vhost_socket: Some("/tmp/sock".to_owned()),
+            rss: true,
+            fds: None,
+            id: None,
+            tap: None,
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::RssWithVhostUser)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.net = Some(vec![NetConfig {
+            vhost: true,
+            rss: true,
+            fds: None,
+            id: None,
+            tap: None,
+            vhost_socket: None,
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::VhostNetUnsupportedOption("rss"))
+        );
+
         let mut invalid_config = valid_config.clone();
         invalid_config.fs = Some(vec![FsConfig {
             ..Default::default()
@@ -2956,6 +6070,62 @@ mod tests {
             Err(ValidationError::InvalidHugePageSize(3 << 20))
         );
 
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.memory = MemoryConfig::parse(
+            "size=0",
+            Some(vec![
+                "id=mem0,size=1G,hugepages=on,hugepage_size=1G,prefault=on,prefault_threads=8"
+                    .to_string(),
+            ]),
+        )
+        .unwrap();
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.memory = MemoryConfig::parse(
+            "size=0",
+            Some(vec!["id=mem0,size=1G,prefault_threads=8".to_string()]),
+        )
+        .unwrap();
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::PrefaultThreadsWithoutPrefault)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.memory = MemoryConfig::parse(
+            "size=0",
+            Some(vec![
+                "id=mem0,size=1G,prefault=on,prefault_threads=0".to_string()
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidPrefaultThreads(0))
+        );
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            let mut still_valid_config = valid_config.clone();
+            still_valid_config.memory.mmio32_hole_size = Some(2 << 30);
+            assert!(still_valid_config.validate().is_ok());
+
+            let mut invalid_config = valid_config.clone();
+            invalid_config.memory.mmio32_hole_size = Some(256 << 20);
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::InvalidMmio32HoleSize(256 << 20))
+            );
+
+            let mut invalid_config = valid_config.clone();
+            invalid_config.memory.mmio32_hole_size = Some((1 << 30) + 1);
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::InvalidMmio32HoleSize((1 << 30) + 1))
+            );
+        }
+
         let mut still_valid_config = valid_config.clone();
         still_valid_config.platform = Some(PlatformConfig {
             num_pci_segments: 16,
@@ -2992,6 +6162,96 @@ mod tests {
             Err(ValidationError::InvalidPciSegment(17))
         );
 
+        #[cfg(target_arch = "x86_64")]
+        {
+            let mut invalid_config = valid_config.clone();
+            invalid_config.platform = Some(PlatformConfig {
+                reserved_regions: Some(vec![ReservedRegion {
+                    start: 0x1000,
+                    size: 0,
+                }]),
+                ..Default::default()
+            });
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::InvalidReservedRegion(0x1000, 0))
+            );
+
+            let mut invalid_config = valid_config.clone();
+            invalid_config.platform = Some(PlatformConfig {
+                smbios_file: Some(PathBuf::from("/path/to/smbios.bin")),
+                serial_number: Some("serial".to_string()),
+                ..Default::default()
+            });
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::SmbiosFileWithSmbiosFields)
+            );
+
+            let mut invalid_config = valid_config.clone();
+            invalid_config.platform = Some(PlatformConfig {
+                smbios_file: Some(PathBuf::from("/path/to/smbios.bin")),
+                ..Default::default()
+            });
+            invalid_config.ignition = Some(IgnitionConfig {
+                path: PathBuf::from("/path/to/config.ign"),
+            });
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::IgnitionWithSmbiosFile)
+            );
+        }
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.fw_cfg = Some(FwCfgConfig {
+            files: Some(vec![
+                FwCfgFile {
+                    name: "opt/org.test/a".to_string(),
+                    path: PathBuf::from("/path/to/a"),
+                },
+                FwCfgFile {
+                    name: "opt/org.test/a".to_string(),
+                    path: PathBuf::from("/path/to/b"),
+                },
+            ]),
+            ..Default::default()
+        });
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::FwCfgFileNotUnique(
+                "opt/org.test/a".to_string()
+            ))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.pvpanic = Some(PvPanicConfig {
+            on_panic: PanicAction::Coredump,
+            coredump_path: None,
+        });
+        #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::PvPanicCoredumpPathMissing)
+        );
+        #[cfg(not(all(target_arch = "x86_64", feature = "guest_debug")))]
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::PvPanicCoredumpUnsupported)
+        );
+
+        #[cfg(not(feature = "alsa"))]
+        {
+            let mut invalid_config = valid_config.clone();
+            invalid_config.sound = Some(SoundConfig {
+                backend: SoundBackend::Alsa,
+                ..Default::default()
+            });
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::SoundAlsaUnsupported)
+            );
+        }
+
         let mut still_valid_config = valid_config.clone();
         still_valid_config.platform = Some(PlatformConfig {
             num_pci_segments: 16,
@@ -3205,6 +6465,225 @@ mod tests {
         ]);
         assert!(still_valid_config.validate().is_ok());
 
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.disks = Some(vec![DiskConfig {
+            path: Some("/path/to/image".into()),
+            id: Some("disk0".to_owned()),
+            alias: Some("rootfs".to_owned()),
+            ..Default::default()
+        }]);
+        assert!(still_valid_config.validate().is_ok());
+        assert_eq!(still_valid_config.resolve_device_id("rootfs"), "disk0");
+        assert_eq!(still_valid_config.resolve_device_id("disk0"), "disk0");
+        assert_eq!(
+            still_valid_config.device_aliases(),
+            vec![("rootfs".to_owned(), "disk0".to_owned())]
+        );
+        let mut counters = HashMap::from([("disk0".to_owned(), 1), ("net0".to_owned(), 2)]);
+        still_valid_config.add_device_alias_entries(&mut counters);
+        assert_eq!(
+            counters,
+            HashMap::from([
+                ("disk0".to_owned(), 1),
+                ("rootfs".to_owned(), 1),
+                ("net0".to_owned(), 2)
+            ])
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            path: Some("/path/to/image".into()),
+            id: Some("disk0".to_owned()),
+            ..Default::default()
+        }]);
+        invalid_config.net = Some(vec![NetConfig {
+            alias: Some("disk0".to_owned()),
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::IdentifierNotUnique("disk0".to_owned()))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.input = Some(vec![InputConfig {
+            kind: Some(InputKind::Keyboard),
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InputRequiresGpu)
+        );
+        invalid_config.gpu = Some(GpuConfig {
+            socket: PathBuf::from("/tmp/vnc.sock"),
+            width: default_gpuconfig_width(),
+            height: default_gpuconfig_height(),
+            iommu: false,
+        });
+        assert!(invalid_config.validate().is_ok());
+        invalid_config.input = Some(vec![InputConfig {
+            kind: Some(InputKind::Keyboard),
+            evdev: Some(PathBuf::from("/dev/input/event0")),
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidInputSource)
+        );
+        invalid_config.input = Some(vec![InputConfig::default()]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidInputSource)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.channels = Some(vec![ChannelConfig {
+            name: "agent".to_owned(),
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidChannelBackend("agent".to_owned()))
+        );
+        invalid_config.channels = Some(vec![ChannelConfig {
+            name: "agent".to_owned(),
+            socket: Some(PathBuf::from("/tmp/agent.sock")),
+            pty: true,
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidChannelBackend("agent".to_owned()))
+        );
+        invalid_config.channels = Some(vec![ChannelConfig {
+            name: "../agent".to_owned(),
+            pty: true,
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidChannelName("../agent".to_owned()))
+        );
+        invalid_config.channels = Some(vec![
+            ChannelConfig {
+                name: "agent".to_owned(),
+                socket: Some(PathBuf::from("/tmp/agent.sock")),
+                ..Default::default()
+            },
+            ChannelConfig {
+                name: "agent".to_owned(),
+                pty: true,
+                ..Default::default()
+            },
+        ]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::ChannelNameNotUnique("agent".to_owned()))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.scsi = Some(vec![ScsiConfig {
+            luns: vec![ScsiLunConfig {
+                lun: 0,
+                cdrom: true,
+                ..Default::default()
+            }],
+            ..Default::default()
+        }]);
+        assert!(invalid_config.validate().is_ok());
+        invalid_config.scsi = Some(vec![ScsiConfig {
+            luns: vec![ScsiLunConfig {
+                lun: 16384,
+                path: Some(PathBuf::from("/path/to/disk.img")),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidScsiLun(16384))
+        );
+        invalid_config.scsi = Some(vec![ScsiConfig {
+            luns: vec![ScsiLunConfig {
+                lun: 1,
+                ..Default::default()
+            }],
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::ScsiLunPathMissing(1))
+        );
+        invalid_config.scsi = Some(vec![ScsiConfig {
+            luns: vec![ScsiLunConfig {
+                lun: 2,
+                path: Some(PathBuf::from("/dev/sg0")),
+                passthrough: true,
+                readonly: true,
+                ..Default::default()
+            }],
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidScsiPassthrough(2))
+        );
+        invalid_config.scsi = Some(vec![ScsiConfig {
+            luns: vec![
+                ScsiLunConfig {
+                    lun: 3,
+                    path: Some(PathBuf::from("/path/to/disk.img")),
+                    ..Default::default()
+                },
+                ScsiLunConfig {
+                    lun: 3,
+                    cdrom: true,
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::ScsiLunNotUnique(3))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.balloon = Some(BalloonConfig {
+            size: 0,
+            deflate_on_oom: false,
+            free_page_reporting: false,
+            auto_size: true,
+            min_size: 1 << 30,
+            max_size: Some(1 << 29),
+        });
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidBalloonSizeBounds(1 << 30, 1 << 29))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.crypto = Some(CryptoConfig {
+            num_queues: 0,
+            ..Default::default()
+        });
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidCryptoQueues(0))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.memory.shared = true;
+        invalid_config.fs = Some(vec![FsConfig {
+            dax: true,
+            cache_size: 0x10_0000,
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidFsCacheSize(0x10_0000))
+        );
+
         let mut invalid_config = valid_config;
         invalid_config.devices = Some(vec![
             DeviceConfig {
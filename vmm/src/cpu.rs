@@ -11,7 +11,8 @@
 // SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
 //
 
-use crate::config::CpusConfig;
+use crate::api::{VcpuInfo, VcpuStats, VcpuStatus};
+use crate::config::{CpuPlacement, CpuSchedulingPolicy, CpusConfig};
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 use crate::coredump::{
     CpuElf64Writable, CpuSegment, CpuState as DumpCpusState, DumpState, Elf64Writable,
@@ -23,6 +24,8 @@ use crate::gdb::{get_raw_tid, Debuggable, DebuggableError};
 #[cfg(target_arch = "x86_64")]
 use crate::memory_manager::MemoryManager;
 use crate::seccomp_filters::{get_seccomp_filter, Thread};
+#[cfg(feature = "tdx")]
+use crate::tdx_quote::QuoteRequest;
 #[cfg(target_arch = "x86_64")]
 use crate::vm::physical_bits;
 use crate::GuestMemoryMmap;
@@ -52,21 +55,28 @@ use hypervisor::arch::x86::MsrEntry;
 use hypervisor::arch::x86::{SpecialRegisters, StandardRegisters};
 #[cfg(target_arch = "aarch64")]
 use hypervisor::kvm::kvm_bindings;
+#[cfg(feature = "sev_snp")]
+use hypervisor::kvm::SevSnpExitDetails;
 #[cfg(feature = "tdx")]
 use hypervisor::kvm::{TdxExitDetails, TdxExitStatus};
-use hypervisor::{CpuState, HypervisorCpuError, HypervisorType, VmExit, VmOps};
+use hypervisor::{CpuState, HypervisorCpuError, HypervisorType, VcpuExitCounters, VmExit, VmOps};
 use libc::{c_void, siginfo_t};
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 use linux_loader::elf::Elf64_Nhdr;
 use seccompiler::{apply_filter, SeccompAction};
+use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 use std::io::Write;
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 use std::mem::size_of;
 use std::os::unix::thread::JoinHandleExt;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Barrier, Mutex};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+#[cfg(feature = "tdx")]
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Barrier, Mutex, Weak};
+use std::time::{Duration, Instant};
 use std::{cmp, io, result, thread};
 use thiserror::Error;
 use tracer::trace_scoped;
@@ -78,7 +88,7 @@ use vm_memory::{Bytes, GuestAddressSpace};
 use vm_memory::{GuestAddress, GuestMemoryAtomic};
 use vm_migration::{
     snapshot_from_id, Migratable, MigratableError, Pausable, Snapshot, SnapshotData, Snapshottable,
-    Transportable,
+    StateUpgrade, Transportable,
 };
 use vmm_sys_util::eventfd::EventFd;
 use vmm_sys_util::signal::{register_signal_handler, SIGRTMIN};
@@ -97,6 +107,13 @@ macro_rules! extract_bits_64 {
 
 pub const CPU_MANAGER_ACPI_SIZE: usize = 0xc;
 
+/// Highest share of the time, in percent, the vCPUs can be throttled for.
+pub const MAX_VCPU_THROTTLE: u8 = 99;
+
+// The throttled vCPUs run for this period before being kept off the host
+// CPUs long enough to match the throttle percentage.
+const VCPU_THROTTLE_PERIOD: Duration = Duration::from_millis(10);
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("Error creating vCPU: {0}")]
@@ -122,6 +139,13 @@ pub enum Error {
     #[error("Error initialising vCPU: {0}")]
     VcpuArmInit(#[source] hypervisor::HypervisorCpuError),
 
+    #[cfg(target_arch = "aarch64")]
+    #[error("Nested virtualization is not supported by the host")]
+    NestedVirtNotSupported,
+
+    #[error("Error spawning vCPU throttling thread: {0}")]
+    ThrottleSpawn(#[source] io::Error),
+
     #[error("Failed to join on vCPU threads: {0:?}")]
     ThreadCleanup(std::boxed::Box<dyn std::any::Any + std::marker::Send>),
 
@@ -131,6 +155,9 @@ pub enum Error {
     #[error("Requested vCPUs exceed maximum")]
     DesiredVCpuCountExceedsMax,
 
+    #[error("Cannot remove vCPU {0}")]
+    InvalidVcpuRemoval(u32),
+
     #[error("Cannot create seccomp filter: {0}")]
     CreateSeccompFilter(#[source] seccompiler::Error),
 
@@ -165,6 +192,22 @@ pub enum Error {
     #[cfg(target_arch = "x86_64")]
     #[error("Error setting up AMX: {0}")]
     AmxEnable(#[source] anyhow::Error),
+
+    #[cfg(target_arch = "x86_64")]
+    #[error("Error opening SGX provisioning device: {0}")]
+    SgxProvisionOpen(#[source] io::Error),
+
+    #[cfg(target_arch = "x86_64")]
+    #[error("Error enabling SGX provisioning: {0}")]
+    SgxEnableProvisioning(#[source] hypervisor::HypervisorVmError),
+
+    #[cfg(target_arch = "x86_64")]
+    #[error("vCPU {0} is not present")]
+    InvalidVcpu(u32),
+
+    #[cfg(all(feature = "mshv", target_arch = "x86_64"))]
+    #[error("NMI injection is only supported with KVM")]
+    NmiUnsupported,
 }
 pub type Result<T> = result::Result<T, Error>;
 
@@ -179,6 +222,18 @@ struct LocalApic {
     pub flags: u32,
 }
 
+#[cfg(target_arch = "x86_64")]
+#[allow(dead_code)]
+#[repr(packed)]
+struct LocalX2Apic {
+    pub r#type: u8,
+    pub length: u8,
+    _reserved: u16,
+    pub apic_id: u32,
+    pub flags: u32,
+    pub processor_id: u32,
+}
+
 #[allow(dead_code)]
 #[repr(packed)]
 #[derive(Default)]
@@ -265,6 +320,180 @@ struct ProcessorHierarchyNode {
     pub num_private_resources: u32,
 }
 
+#[cfg(target_arch = "aarch64")]
+#[allow(dead_code)]
+#[repr(packed)]
+struct CacheTypeNode {
+    pub r#type: u8,
+    pub length: u8,
+    pub reserved: u16,
+    pub flags: u32,
+    pub next_level_of_cache: u32,
+    pub size: u32,
+    pub number_of_sets: u32,
+    pub associativity: u8,
+    pub attributes: u8,
+    pub line_size: u16,
+}
+
+// Size, number of sets, associativity, allocation type, cache type, write
+// policy and line size are all valid.
+#[cfg(target_arch = "aarch64")]
+const PPTT_CACHE_FLAGS: u32 = 0x7f;
+// Read/write allocate, write-back caches.
+#[cfg(target_arch = "aarch64")]
+const PPTT_CACHE_ALLOCATION: u8 = 0x3;
+
+#[cfg(target_arch = "aarch64")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CacheType {
+    Data,
+    Instruction,
+    Unified,
+}
+
+/// Description of one of the host caches, as found under
+/// /sys/devices/system/cpu/cpu0/cache.
+#[cfg(target_arch = "aarch64")]
+#[derive(Clone, Copy, Debug)]
+struct CacheInfo {
+    level: u8,
+    cache_type: CacheType,
+    size: u32,
+    number_of_sets: u32,
+    associativity: u8,
+    line_size: u16,
+}
+
+#[cfg(target_arch = "aarch64")]
+impl CacheInfo {
+    // Parse sizes such as "64K" or "32M".
+    fn parse_size(s: &str) -> Option<u32> {
+        let s = s.trim();
+        let (value, shift) = match s.strip_suffix('K') {
+            Some(v) => (v, 10),
+            None => match s.strip_suffix('M') {
+                Some(v) => (v, 20),
+                None => (s, 0),
+            },
+        };
+        value.parse::<u32>().ok()?.checked_mul(1 << shift)
+    }
+
+    fn from_sysfs(path: &std::path::Path) -> Option<Self> {
+        let read = |name: &str| std::fs::read_to_string(path.join(name)).ok();
+        let cache_type = match read("type")?.trim() {
+            "Data" => CacheType::Data,
+            "Instruction" => CacheType::Instruction,
+            "Unified" => CacheType::Unified,
+            _ => return None,
+        };
+
+        Some(CacheInfo {
+            level: read("level")?.trim().parse().ok()?,
+            cache_type,
+            size: Self::parse_size(&read("size")?)?,
+            number_of_sets: read("number_of_sets")?.trim().parse().ok()?,
+            associativity: read("ways_of_associativity")?.trim().parse().ok()?,
+            line_size: read("coherency_line_size")?.trim().parse().ok()?,
+        })
+    }
+
+    // Caches of the first host CPU, sorted from the last level to the first
+    // one. Any cache which can't be described leaves the list empty.
+    fn host_caches() -> Vec<Self> {
+        let mut caches = Vec::new();
+        for index in 0.. {
+            let path = std::path::PathBuf::from(format!(
+                "/sys/devices/system/cpu/cpu0/cache/index{index}"
+            ));
+            if !path.exists() {
+                break;
+            }
+            match Self::from_sysfs(&path) {
+                Some(cache) => caches.push(cache),
+                None => return Vec::new(),
+            }
+        }
+        caches.sort_by(|a, b| b.level.cmp(&a.level));
+        caches
+    }
+
+    // Levels 1 and 2 are private to each core, while further levels are
+    // shared by all the cores of a package.
+    fn is_shared(&self) -> bool {
+        self.level > 2
+    }
+
+    fn node(&self, next_level_of_cache: u32) -> CacheTypeNode {
+        let cache_type = match self.cache_type {
+            CacheType::Data => 0,
+            CacheType::Instruction => 1,
+            CacheType::Unified => 2,
+        };
+        CacheTypeNode {
+            r#type: 1,
+            length: 24,
+            reserved: 0,
+            flags: PPTT_CACHE_FLAGS,
+            next_level_of_cache,
+            size: self.size,
+            number_of_sets: self.number_of_sets,
+            associativity: self.associativity,
+            attributes: PPTT_CACHE_ALLOCATION | cache_type << 2,
+            line_size: self.line_size,
+        }
+    }
+}
+
+// Append the cache nodes for the given caches, sorted from the last level to
+// the first one, and return the offsets of the first level ones. Those are the
+// private resources of the processor node owning the caches, the other ones
+// being reachable through the next level of cache links.
+#[cfg(target_arch = "aarch64")]
+fn append_pptt_caches(pptt: &mut Sdt, caches: &[CacheInfo]) -> Vec<u32> {
+    let mut next_level_offset = 0;
+    let mut next_level = None;
+    let mut level_offset = 0;
+    let mut first_level_offsets = Vec::new();
+    for cache in caches {
+        if next_level != Some(cache.level) {
+            // Moving to a lower level, which links to the previous one.
+            if next_level.is_some() {
+                next_level_offset = level_offset;
+            }
+            next_level = Some(cache.level);
+            first_level_offsets.clear();
+        }
+        level_offset = pptt.len() as u32;
+        pptt.append(cache.node(next_level_offset));
+        first_level_offsets.push(level_offset);
+    }
+    first_level_offsets
+}
+
+#[cfg(target_arch = "aarch64")]
+fn append_processor_hierarchy_node(
+    pptt: &mut Sdt,
+    flags: u32,
+    parent: u32,
+    acpi_processor_id: u32,
+    private_resources: &[u32],
+) {
+    pptt.append(ProcessorHierarchyNode {
+        r#type: 0,
+        length: (20 + 4 * private_resources.len()) as u8,
+        reserved: 0,
+        flags,
+        parent,
+        acpi_processor_id,
+        num_private_resources: private_resources.len() as u32,
+    });
+    for resource in private_resources {
+        pptt.append(*resource);
+    }
+}
+
 #[allow(dead_code)]
 #[repr(packed)]
 #[derive(Default)]
@@ -288,7 +517,9 @@ macro_rules! round_up {
 pub struct Vcpu {
     // The hypervisor abstracted CPU.
     vcpu: Arc<dyn hypervisor::Vcpu>,
-    id: u8,
+    id: u32,
+    #[cfg(target_arch = "x86_64")]
+    apic_id: u32,
     #[cfg(target_arch = "aarch64")]
     mpidr: u64,
     saved_state: Option<CpuState>,
@@ -300,20 +531,29 @@ impl Vcpu {
     /// # Arguments
     ///
     /// * `id` - Represents the CPU number between [0, max vcpus).
+    /// * `apic_id` - (x86_64) APIC ID of the CPU, used as the hypervisor vCPU ID.
     /// * `vm` - The virtual machine this vcpu will get attached to.
     /// * `vm_ops` - Optional object for exit handling.
     pub fn new(
-        id: u8,
+        id: u32,
+        #[cfg(target_arch = "x86_64")] apic_id: u32,
         vm: &Arc<dyn hypervisor::Vm>,
         vm_ops: Option<Arc<dyn VmOps>>,
     ) -> Result<Self> {
+        // The hypervisor derives the initial APIC ID from the vCPU ID
+        #[cfg(target_arch = "x86_64")]
+        let vcpu_id = apic_id;
+        #[cfg(target_arch = "aarch64")]
+        let vcpu_id = id;
         let vcpu = vm
-            .create_vcpu(id, vm_ops)
+            .create_vcpu(vcpu_id, vm_ops)
             .map_err(|e| Error::VcpuCreate(e.into()))?;
         // Initially the cpuid per vCPU is the one supported by this VM.
         Ok(Vcpu {
             vcpu,
             id,
+            #[cfg(target_arch = "x86_64")]
+            apic_id,
             #[cfg(target_arch = "aarch64")]
             mpidr: 0,
             saved_state: None,
@@ -327,23 +567,38 @@ impl Vcpu {
     /// * `kernel_entry_point` - Kernel entry point address in guest memory and boot protocol used.
     /// * `guest_memory` - Guest memory.
     /// * `cpuid` - (x86_64) CpuId, wrapper over the `kvm_cpuid2` structure.
+    /// * `core_type` - (x86_64) Core type of the vCPU on a hybrid topology.
+    /// * `sve_vl` - (aarch64) Maximum SVE vector length, SVE being disabled if `None`.
+    /// * `features` - (aarch64) Optional CPU features exposed to the guest.
     pub fn configure(
         &mut self,
         #[cfg(target_arch = "aarch64")] vm: &Arc<dyn hypervisor::Vm>,
+        #[cfg(target_arch = "aarch64")] sve_vl: Option<u32>,
+        #[cfg(target_arch = "aarch64")] features: &crate::config::CpuFeatures,
         boot_setup: Option<(EntryPoint, &GuestMemoryAtomic<GuestMemoryMmap>)>,
         #[cfg(target_arch = "x86_64")] cpuid: Vec<CpuIdEntry>,
         #[cfg(target_arch = "x86_64")] kvm_hyperv: bool,
+        #[cfg(target_arch = "x86_64")] core_type: Option<arch::CoreType>,
+        #[cfg(target_arch = "x86_64")] microcode_revision: Option<u32>,
     ) -> Result<()> {
         #[cfg(target_arch = "aarch64")]
         {
-            self.init(vm)?;
+            self.init(vm, sve_vl, features)?;
             self.mpidr = arch::configure_vcpu(&self.vcpu, self.id, boot_setup)
                 .map_err(Error::VcpuConfiguration)?;
         }
         info!("Configuring vCPU: cpu_id = {}", self.id);
         #[cfg(target_arch = "x86_64")]
-        arch::configure_vcpu(&self.vcpu, self.id, boot_setup, cpuid, kvm_hyperv)
-            .map_err(Error::VcpuConfiguration)?;
+        arch::configure_vcpu(
+            &self.vcpu,
+            self.apic_id,
+            boot_setup,
+            cpuid,
+            kvm_hyperv,
+            core_type,
+            microcode_revision,
+        )
+        .map_err(Error::VcpuConfiguration)?;
 
         Ok(())
     }
@@ -362,7 +617,12 @@ impl Vcpu {
 
     /// Initializes an aarch64 specific vcpu for booting Linux.
     #[cfg(target_arch = "aarch64")]
-    pub fn init(&self, vm: &Arc<dyn hypervisor::Vm>) -> Result<()> {
+    pub fn init(
+        &self,
+        vm: &Arc<dyn hypervisor::Vm>,
+        sve_vl: Option<u32>,
+        features: &crate::config::CpuFeatures,
+    ) -> Result<()> {
         let mut kvi: kvm_bindings::kvm_vcpu_init = kvm_bindings::kvm_vcpu_init::default();
 
         // This reads back the kernel's preferred target type.
@@ -370,12 +630,32 @@ impl Vcpu {
             .map_err(Error::VcpuArmPreferredTarget)?;
         // We already checked that the capability is supported.
         kvi.features[0] |= 1 << kvm_bindings::KVM_ARM_VCPU_PSCI_0_2;
-        kvi.features[0] |= 1 << kvm_bindings::KVM_ARM_VCPU_PMU_V3;
+        if features.pmu {
+            kvi.features[0] |= 1 << kvm_bindings::KVM_ARM_VCPU_PMU_V3;
+        }
+        // Address and generic authentication can only be enabled together.
+        if features.pauth {
+            kvi.features[0] |= 1 << hypervisor::kvm::KVM_ARM_VCPU_PTRAUTH_ADDRESS;
+            kvi.features[0] |= 1 << hypervisor::kvm::KVM_ARM_VCPU_PTRAUTH_GENERIC;
+        }
+        if features.nested {
+            kvi.features[0] |= 1 << hypervisor::kvm::KVM_ARM_VCPU_HAS_EL2;
+        }
         // Non-boot cpus are powered off initially.
         if self.id > 0 {
             kvi.features[0] |= 1 << kvm_bindings::KVM_ARM_VCPU_POWER_OFF;
         }
-        self.vcpu.vcpu_init(&kvi).map_err(Error::VcpuArmInit)
+        if sve_vl.is_some() {
+            kvi.features[0] |= 1 << hypervisor::kvm::KVM_ARM_VCPU_SVE;
+        }
+        self.vcpu.vcpu_init(&kvi).map_err(Error::VcpuArmInit)?;
+        // The SVE configuration must be finalized before accessing any
+        // register.
+        if let Some(sve_vl) = sve_vl {
+            self.vcpu.init_sve(sve_vl).map_err(Error::VcpuArmInit)?;
+        }
+
+        Ok(())
     }
 
     /// Runs the VCPU until it exits, returning the reason.
@@ -407,6 +687,15 @@ impl Snapshottable for Vcpu {
     }
 }
 
+#[derive(Serialize, Deserialize)]
+struct CpuManagerState {
+    // As specific vCPUs can be removed, the present ones aren't necessarily
+    // the first ones.
+    present_vcpus: Vec<u32>,
+}
+
+impl StateUpgrade for CpuManagerState {}
+
 pub struct CpuManager {
     hypervisor_type: HypervisorType,
     config: CpusConfig,
@@ -418,21 +707,26 @@ pub struct CpuManager {
     vm: Arc<dyn hypervisor::Vm>,
     vcpus_kill_signalled: Arc<AtomicBool>,
     vcpus_pause_signalled: Arc<AtomicBool>,
+    vcpus_throttle: Arc<AtomicU8>,
+    throttle_thread: Option<thread::JoinHandle<()>>,
     exit_evt: EventFd,
     #[cfg_attr(target_arch = "aarch64", allow(dead_code))]
     reset_evt: EventFd,
     #[cfg(feature = "guest_debug")]
     vm_debug_evt: EventFd,
     vcpu_states: Vec<VcpuState>,
-    selected_cpu: u8,
+    selected_cpu: u32,
     vcpus: Vec<Arc<Mutex<Vcpu>>>,
     seccomp_action: SeccompAction,
     vm_ops: Arc<dyn VmOps>,
     #[cfg_attr(target_arch = "aarch64", allow(dead_code))]
     acpi_address: Option<GuestAddress>,
-    proximity_domain_per_cpu: BTreeMap<u8, u32>,
-    affinity: BTreeMap<u8, Vec<u8>>,
+    proximity_domain_per_cpu: BTreeMap<u32, u32>,
+    affinity: BTreeMap<u32, Vec<u8>>,
     dynamic: bool,
+    restored_vcpus: Option<Vec<u32>>,
+    #[cfg(feature = "tdx")]
+    tdx_quote_sender: Option<Sender<QuoteRequest>>,
 }
 
 const CPU_ENABLE_FLAG: usize = 0;
@@ -450,11 +744,12 @@ impl BusDevice for CpuManager {
 
         match offset {
             CPU_SELECTION_OFFSET => {
-                data[0] = self.selected_cpu;
+                let len = cmp::min(data.len(), 4);
+                data[..len].copy_from_slice(&self.selected_cpu.to_le_bytes()[..len]);
             }
             CPU_STATUS_OFFSET => {
                 if self.selected_cpu < self.max_vcpus() {
-                    let state = &self.vcpu_states[usize::from(self.selected_cpu)];
+                    let state = &self.vcpu_states[self.selected_cpu as usize];
                     if state.active() {
                         data[0] |= 1 << CPU_ENABLE_FLAG;
                     }
@@ -480,11 +775,14 @@ impl BusDevice for CpuManager {
     fn write(&mut self, _base: u64, offset: u64, data: &[u8]) -> Option<Arc<Barrier>> {
         match offset {
             CPU_SELECTION_OFFSET => {
-                self.selected_cpu = data[0];
+                let mut bytes = [0u8; 4];
+                let len = cmp::min(data.len(), 4);
+                bytes[..len].copy_from_slice(&data[..len]);
+                self.selected_cpu = u32::from_le_bytes(bytes);
             }
             CPU_STATUS_OFFSET => {
                 if self.selected_cpu < self.max_vcpus() {
-                    let state = &mut self.vcpu_states[usize::from(self.selected_cpu)];
+                    let state = &mut self.vcpu_states[self.selected_cpu as usize];
                     // The ACPI code writes back a 1 to acknowledge the insertion
                     if (data[0] & (1 << CPU_INSERTING_FLAG) == 1 << CPU_INSERTING_FLAG)
                         && state.inserting
@@ -525,6 +823,10 @@ struct VcpuState {
     handle: Option<thread::JoinHandle<()>>,
     kill: Arc<AtomicBool>,
     vcpu_run_interrupted: Arc<AtomicBool>,
+    #[cfg(target_arch = "x86_64")]
+    nmi_pending: Arc<AtomicBool>,
+    exits: Arc<AtomicU64>,
+    exit_counters: Option<Arc<VcpuExitCounters>>,
 }
 
 impl VcpuState {
@@ -550,6 +852,16 @@ impl VcpuState {
         }
     }
 
+    // Interrupt the vCPU run without waiting for it to be acknowledged
+    fn kick_thread(&self) {
+        if let Some(handle) = self.handle.as_ref() {
+            // SAFETY: FFI call with correct arguments
+            unsafe {
+                libc::pthread_kill(handle.as_pthread_t() as _, SIGRTMIN());
+            }
+        }
+    }
+
     fn join_thread(&mut self) -> Result<()> {
         if let Some(handle) = self.handle.take() {
             handle.join().map_err(Error::ThreadCleanup)?
@@ -565,6 +877,194 @@ impl VcpuState {
     }
 }
 
+// Host CPU as described by sysfs, used to compute the vCPU affinities when
+// an automatic placement policy has been requested.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct HostCpu {
+    id: u8,
+    node: u32,
+    package: u32,
+    core: u32,
+}
+
+// Parse a sysfs CPU list such as "0-3,8,10-11".
+fn parse_host_cpu_list(list: &str) -> Vec<u32> {
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|r| !r.is_empty()) {
+        let (start, end) = range.split_once('-').unwrap_or((range, range));
+        match (start.parse::<u32>(), end.parse::<u32>()) {
+            (Ok(start), Ok(end)) => cpus.extend(start..=end),
+            _ => warn!("Invalid host CPU range: {}", range),
+        }
+    }
+    cpus
+}
+
+fn host_cpus() -> io::Result<Vec<HostCpu>> {
+    host_cpus_from_sysfs(Path::new("/sys/devices/system"))
+}
+
+// The topology is read from the given sysfs directory, which is only
+// different from /sys/devices/system for testing purpose.
+fn host_cpus_from_sysfs(sysfs: &Path) -> io::Result<Vec<HostCpu>> {
+    let online = std::fs::read_to_string(sysfs.join("cpu/online"))?;
+
+    let mut node_per_cpu = BTreeMap::new();
+    // Hosts without NUMA support don't expose this directory, in which case
+    // all the CPUs are considered part of node 0.
+    if let Ok(entries) = std::fs::read_dir(sysfs.join("node")) {
+        for entry in entries.flatten() {
+            let node = match entry
+                .file_name()
+                .to_str()
+                .and_then(|n| n.strip_prefix("node"))
+                .and_then(|n| n.parse::<u32>().ok())
+            {
+                Some(node) => node,
+                None => continue,
+            };
+            let cpu_list = std::fs::read_to_string(entry.path().join("cpulist"))?;
+            for cpu in parse_host_cpu_list(&cpu_list) {
+                node_per_cpu.insert(cpu, node);
+            }
+        }
+    }
+
+    let topology = |cpu: u32, name: &str| {
+        std::fs::read_to_string(sysfs.join(format!("cpu/cpu{cpu}/topology/{name}")))
+            .ok()
+            .and_then(|v| v.trim().parse::<u32>().ok())
+    };
+
+    // vCPU affinities are expressed with u8, host CPUs beyond can't be used.
+    Ok(parse_host_cpu_list(&online)
+        .into_iter()
+        .filter(|cpu| *cpu <= u8::MAX as u32)
+        .map(|cpu| HostCpu {
+            id: cpu as u8,
+            node: node_per_cpu.get(&cpu).copied().unwrap_or(0),
+            package: topology(cpu, "physical_package_id").unwrap_or(0),
+            core: topology(cpu, "core_id").unwrap_or(cpu),
+        })
+        .collect())
+}
+
+// Mark the given vCPUs for removal, returning the number of vCPUs the VM will
+// be left with once the guest has ejected them. No vCPU is marked if any of
+// them can't be removed.
+fn mark_vcpus_removing(vcpu_states: &mut [VcpuState], cpu_ids: &[u32]) -> Result<u32> {
+    for cpu_id in cpu_ids {
+        // The boot vCPU can't be ejected.
+        if *cpu_id == 0
+            || !vcpu_states
+                .get(*cpu_id as usize)
+                .map_or(false, |state| state.active())
+        {
+            return Err(Error::InvalidVcpuRemoval(*cpu_id));
+        }
+    }
+
+    for cpu_id in cpu_ids {
+        vcpu_states[*cpu_id as usize].removing = true;
+    }
+
+    let present_vcpus = vcpu_states.iter().filter(|state| state.active()).count();
+    let removing_vcpus = vcpu_states
+        .iter()
+        .filter(|state| state.active() && state.removing)
+        .count();
+
+    Ok((present_vcpus - removing_vcpus) as u32)
+}
+
+// Compute the host CPU set each vCPU should be pinned onto according to the
+// placement policy. The affinity is computed for every possible vCPU so that
+// hotplugged vCPUs follow the same policy as the boot ones.
+fn placement_affinity(
+    placement: CpuPlacement,
+    max_vcpus: u32,
+    host_cpus: &[HostCpu],
+    numa_nodes: &NumaNodes,
+    host_numa_nodes: &BTreeMap<u32, u32>,
+) -> BTreeMap<u32, Vec<u8>> {
+    let mut affinity = BTreeMap::new();
+    if host_cpus.is_empty() {
+        return affinity;
+    }
+
+    let ordered_cpus: Vec<u8> = match placement {
+        CpuPlacement::AutoNuma => {
+            for (guest_node, numa_node) in numa_nodes.iter() {
+                let host_node = match host_numa_nodes.get(guest_node) {
+                    Some(host_node) => *host_node,
+                    None => continue,
+                };
+                let cpuset: Vec<u8> = host_cpus
+                    .iter()
+                    .filter(|c| c.node == host_node)
+                    .map(|c| c.id)
+                    .collect();
+                if cpuset.is_empty() {
+                    warn!("No host CPU available on host NUMA node {}", host_node);
+                    continue;
+                }
+                for vcpu in numa_node.cpus.iter() {
+                    affinity.insert(*vcpu, cpuset.clone());
+                }
+            }
+            return affinity;
+        }
+        CpuPlacement::Compact => {
+            let mut cpus = host_cpus.to_vec();
+            cpus.sort_by_key(|c| (c.node, c.package, c.core, c.id));
+            cpus.iter().map(|c| c.id).collect()
+        }
+        CpuPlacement::Scatter => {
+            // Group the hardware threads per core and the cores per package,
+            // then pick the first thread of each core of each package in a
+            // round robin fashion before moving to the sibling threads.
+            let mut packages: BTreeMap<(u32, u32), BTreeMap<u32, Vec<u8>>> = BTreeMap::new();
+            for cpu in host_cpus.iter() {
+                packages
+                    .entry((cpu.node, cpu.package))
+                    .or_default()
+                    .entry(cpu.core)
+                    .or_default()
+                    .push(cpu.id);
+            }
+            let packages: Vec<Vec<Vec<u8>>> = packages
+                .into_values()
+                .map(|cores| cores.into_values().collect())
+                .collect();
+            let max_cores = packages.iter().map(|p| p.len()).max().unwrap_or(0);
+            let max_threads = packages
+                .iter()
+                .flatten()
+                .map(|t| t.len())
+                .max()
+                .unwrap_or(0);
+
+            let mut cpus = Vec::with_capacity(host_cpus.len());
+            for thread in 0..max_threads {
+                for core in 0..max_cores {
+                    for package in packages.iter() {
+                        if let Some(id) = package.get(core).and_then(|t| t.get(thread)) {
+                            cpus.push(*id);
+                        }
+                    }
+                }
+            }
+            cpus
+        }
+    };
+
+    for vcpu in 0..max_vcpus {
+        affinity.insert(vcpu, vec![ordered_cpus[vcpu as usize % ordered_cpus.len()]]);
+    }
+
+    affinity
+}
+
 impl CpuManager {
     #[allow(unused_variables)]
     #[allow(clippy::too_many_arguments)]
@@ -578,10 +1078,17 @@ impl CpuManager {
         seccomp_action: SeccompAction,
         vm_ops: Arc<dyn VmOps>,
         #[cfg(feature = "tdx")] tdx_enabled: bool,
+        #[cfg(feature = "sev_snp")] sev_enabled: bool,
         numa_nodes: &NumaNodes,
+        host_numa_nodes: &BTreeMap<u32, u32>,
     ) -> Result<Arc<Mutex<CpuManager>>> {
-        let mut vcpu_states = Vec::with_capacity(usize::from(config.max_vcpus));
-        vcpu_states.resize_with(usize::from(config.max_vcpus), VcpuState::default);
+        #[cfg(target_arch = "aarch64")]
+        if config.features.nested && !vm.has_nested_virt() {
+            return Err(Error::NestedVirtNotSupported);
+        }
+
+        let mut vcpu_states = Vec::with_capacity(config.max_vcpus as usize);
+        vcpu_states.resize_with(config.max_vcpus as usize, VcpuState::default);
         let hypervisor_type = hypervisor.hypervisor_type();
 
         #[cfg(target_arch = "x86_64")]
@@ -616,7 +1123,18 @@ impl CpuManager {
             }
         }
 
-        let proximity_domain_per_cpu: BTreeMap<u8, u32> = {
+        // Allow SGX enclaves from the guest to access the provisioning key
+        #[cfg(target_arch = "x86_64")]
+        if config.features.sgx_provision {
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .open("/dev/sgx_provision")
+                .map_err(Error::SgxProvisionOpen)?;
+            vm.enable_sgx_attribute(file)
+                .map_err(Error::SgxEnableProvisioning)?;
+        }
+
+        let proximity_domain_per_cpu: BTreeMap<u32, u32> = {
             let mut cpu_list = Vec::new();
             for (proximity_domain, numa_node) in numa_nodes.iter() {
                 for cpu in numa_node.cpus.iter() {
@@ -633,6 +1151,20 @@ impl CpuManager {
                 .iter()
                 .map(|a| (a.vcpu, a.host_cpus.clone()))
                 .collect()
+        } else if let Some(placement) = config.placement {
+            match host_cpus() {
+                Ok(host_cpus) => placement_affinity(
+                    placement,
+                    config.max_vcpus,
+                    &host_cpus,
+                    numa_nodes,
+                    host_numa_nodes,
+                ),
+                Err(e) => {
+                    warn!("Could not retrieve the host CPU topology: {}", e);
+                    BTreeMap::new()
+                }
+            }
         } else {
             BTreeMap::new()
         };
@@ -641,6 +1173,8 @@ impl CpuManager {
         let dynamic = !tdx_enabled;
         #[cfg(not(feature = "tdx"))]
         let dynamic = true;
+        #[cfg(feature = "sev_snp")]
+        let dynamic = dynamic && !sev_enabled;
 
         Ok(Arc::new(Mutex::new(CpuManager {
             hypervisor_type,
@@ -651,19 +1185,24 @@ impl CpuManager {
             vm,
             vcpus_kill_signalled: Arc::new(AtomicBool::new(false)),
             vcpus_pause_signalled: Arc::new(AtomicBool::new(false)),
+            vcpus_throttle: Arc::new(AtomicU8::new(0)),
+            throttle_thread: None,
             vcpu_states,
             exit_evt,
             reset_evt,
             #[cfg(feature = "guest_debug")]
             vm_debug_evt,
             selected_cpu: 0,
-            vcpus: Vec::with_capacity(usize::from(config.max_vcpus)),
+            vcpus: Vec::with_capacity(config.max_vcpus as usize),
             seccomp_action,
             vm_ops,
             acpi_address: None,
             proximity_domain_per_cpu,
             affinity,
             dynamic,
+            restored_vcpus: None,
+            #[cfg(feature = "tdx")]
+            tdx_quote_sender: None,
         })))
     }
 
@@ -673,6 +1212,8 @@ impl CpuManager {
         memory_manager: &Arc<Mutex<MemoryManager>>,
         hypervisor: &Arc<dyn hypervisor::Hypervisor>,
         #[cfg(feature = "tdx")] tdx_enabled: bool,
+        #[cfg(feature = "sev_snp")] sev_snp_enabled: bool,
+        #[cfg(feature = "sev_snp")] sev_es_enabled: bool,
     ) -> Result<()> {
         let sgx_epc_sections = memory_manager
             .lock()
@@ -684,15 +1225,33 @@ impl CpuManager {
             let phys_bits = physical_bits(self.config.max_phys_bits);
             arch::generate_common_cpuid(
                 hypervisor,
-                self.config
-                    .topology
-                    .clone()
-                    .map(|t| (t.threads_per_core, t.cores_per_die, t.dies_per_package)),
-                sgx_epc_sections,
-                phys_bits,
-                self.config.kvm_hyperv,
-                #[cfg(feature = "tdx")]
-                tdx_enabled,
+                arch::CpuidConfig {
+                    topology: self
+                        .config
+                        .topology
+                        .clone()
+                        .map(|t| (t.threads_per_core, t.cores_per_die, t.dies_per_package)),
+                    sgx_epc_sections,
+                    sgx_provisioning: self.config.features.sgx_provision,
+                    phys_bits,
+                    kvm_hyperv: self.config.kvm_hyperv,
+                    cpu_model: self.config.model.clone(),
+                    enabled_features: self.config.features.cpuid_enable.clone(),
+                    disabled_features: self.config.features.cpuid_disable.clone(),
+                    amx: self.config.features.amx,
+                    disabled_kvm_features: self.config.features.kvm_disable.clone(),
+                    hybrid: self
+                        .config
+                        .topology
+                        .as_ref()
+                        .map_or(false, |t| t.efficiency_cores_per_die > 0),
+                    #[cfg(feature = "sev_snp")]
+                    sev_snp: sev_snp_enabled,
+                    #[cfg(feature = "sev_snp")]
+                    sev_es: sev_es_enabled,
+                    #[cfg(feature = "tdx")]
+                    tdx: tdx_enabled,
+                },
             )
             .map_err(Error::CommonCpuId)?
         };
@@ -700,15 +1259,22 @@ impl CpuManager {
         Ok(())
     }
 
-    fn create_vcpu(&mut self, cpu_id: u8, snapshot: Option<Snapshot>) -> Result<Arc<Mutex<Vcpu>>> {
+    fn create_vcpu(&mut self, cpu_id: u32, snapshot: Option<Snapshot>) -> Result<Arc<Mutex<Vcpu>>> {
         info!("Creating vCPU: cpu_id = {}", cpu_id);
 
-        let mut vcpu = Vcpu::new(cpu_id, &self.vm, Some(self.vm_ops.clone()))?;
+        let mut vcpu = Vcpu::new(
+            cpu_id,
+            #[cfg(target_arch = "x86_64")]
+            self.apic_id(cpu_id),
+            &self.vm,
+            Some(self.vm_ops.clone()),
+        )?;
+        self.vcpu_states[cpu_id as usize].exit_counters = Some(vcpu.vcpu.exit_counters());
 
         if let Some(snapshot) = snapshot {
             // AArch64 vCPUs should be initialized after created.
             #[cfg(target_arch = "aarch64")]
-            vcpu.init(&self.vm)?;
+            vcpu.init(&self.vm, self.config.sve_vl, &self.config.features)?;
 
             let state: CpuState = snapshot.to_state().map_err(|e| {
                 Error::VcpuCreate(anyhow!("Could not get vCPU state from snapshot {:?}", e))
@@ -739,18 +1305,58 @@ impl CpuManager {
         assert!(!self.cpuid.is_empty());
 
         #[cfg(target_arch = "x86_64")]
-        vcpu.configure(boot_setup, self.cpuid.clone(), self.config.kvm_hyperv)?;
+        {
+            let core_type = self.core_type(vcpu.id);
+            vcpu.configure(
+                boot_setup,
+                self.cpuid.clone(),
+                self.config.kvm_hyperv,
+                core_type,
+                self.config.microcode_revision,
+            )?;
+        }
 
         #[cfg(target_arch = "aarch64")]
-        vcpu.configure(&self.vm, boot_setup)?;
+        vcpu.configure(
+            &self.vm,
+            self.config.sve_vl,
+            &self.config.features,
+            boot_setup,
+        )?;
 
         Ok(())
     }
 
+    /// APIC ID of the vCPU, either explicitly configured or matching its ID.
+    #[cfg(target_arch = "x86_64")]
+    pub fn apic_id(&self, cpu_id: u32) -> u32 {
+        self.config
+            .apic_ids
+            .as_ref()
+            .map_or(cpu_id, |apic_ids| apic_ids[cpu_id as usize])
+    }
+
+    // On a hybrid topology, the last efficiency_cores_per_die cores of each
+    // die are efficiency cores while the other ones are performance cores.
+    #[cfg(target_arch = "x86_64")]
+    fn core_type(&self, cpu_id: u32) -> Option<arch::CoreType> {
+        let t = self.config.topology.as_ref()?;
+        if t.efficiency_cores_per_die == 0 {
+            return None;
+        }
+
+        let core = (cpu_id / u32::from(t.threads_per_core)) % u32::from(t.cores_per_die);
+        if core >= u32::from(t.cores_per_die - t.efficiency_cores_per_die) {
+            Some(arch::CoreType::Efficiency)
+        } else {
+            Some(arch::CoreType::Performance)
+        }
+    }
+
     /// Only create new vCPUs if there aren't any inactive ones to reuse
     fn create_vcpus(
         &mut self,
-        desired_vcpus: u8,
+        desired_vcpus: u32,
         snapshot: Option<Snapshot>,
     ) -> Result<Vec<Arc<Mutex<Vcpu>>>> {
         let mut vcpus: Vec<Arc<Mutex<Vcpu>>> = vec![];
@@ -767,7 +1373,7 @@ impl CpuManager {
         }
 
         // Only create vCPUs in excess of all the allocated vCPUs.
-        for cpu_id in self.vcpus.len() as u8..desired_vcpus {
+        for cpu_id in self.vcpus.len() as u32..desired_vcpus {
             vcpus.push(self.create_vcpu(
                 cpu_id,
                 // TODO: The special format of the CPU id can be removed once
@@ -805,7 +1411,7 @@ impl CpuManager {
     fn start_vcpu(
         &mut self,
         vcpu: Arc<Mutex<Vcpu>>,
-        vcpu_id: u8,
+        vcpu_id: u32,
         vcpu_thread_barrier: Arc<Barrier>,
         inserting: bool,
     ) -> Result<()> {
@@ -816,14 +1422,22 @@ impl CpuManager {
         #[cfg(feature = "guest_debug")]
         let vm_debug_evt = self.vm_debug_evt.try_clone().unwrap();
         let panic_exit_evt = self.exit_evt.try_clone().unwrap();
+        #[cfg(feature = "sev_snp")]
+        let vm = self.vm.clone();
+        #[cfg(feature = "tdx")]
+        let tdx_quote_sender = self.tdx_quote_sender.clone();
         let vcpu_kill_signalled = self.vcpus_kill_signalled.clone();
         let vcpu_pause_signalled = self.vcpus_pause_signalled.clone();
+        let vcpu_throttle = self.vcpus_throttle.clone();
 
-        let vcpu_kill = self.vcpu_states[usize::from(vcpu_id)].kill.clone();
-        let vcpu_run_interrupted = self.vcpu_states[usize::from(vcpu_id)]
+        let vcpu_kill = self.vcpu_states[vcpu_id as usize].kill.clone();
+        #[cfg(target_arch = "x86_64")]
+        let vcpu_nmi_pending = self.vcpu_states[vcpu_id as usize].nmi_pending.clone();
+        let vcpu_run_interrupted = self.vcpu_states[vcpu_id as usize]
             .vcpu_run_interrupted
             .clone();
         let panic_vcpu_run_interrupted = vcpu_run_interrupted.clone();
+        let vcpu_exits = self.vcpu_states[vcpu_id as usize].exits.clone();
 
         // Prepare the CPU set the current vCPU is expected to run onto.
         let cpuset = self.affinity.get(&vcpu_id).map(|host_cpus| {
@@ -838,6 +1452,15 @@ impl CpuManager {
             cpuset
         });
 
+        // Real-time scheduling policy and priority of the vCPU thread
+        let sched = self.config.sched.map(|sched| {
+            let policy = match sched.policy {
+                CpuSchedulingPolicy::Fifo => libc::SCHED_FIFO,
+                CpuSchedulingPolicy::RoundRobin => libc::SCHED_RR,
+            };
+            (policy, sched.priority as libc::c_int)
+        });
+
         // Retrieve seccomp filter for vcpu thread
         let vcpu_seccomp_filter =
             get_seccomp_filter(&self.seccomp_action, Thread::Vcpu, self.hypervisor_type)
@@ -873,6 +1496,24 @@ impl CpuManager {
                         }
                     }
 
+                    // Switch the thread to the real-time scheduling policy
+                    if let Some((policy, priority)) = sched {
+                        let param = libc::sched_param {
+                            sched_priority: priority,
+                        };
+                        // SAFETY: FFI call with correct arguments
+                        let ret = unsafe { libc::sched_setscheduler(0, policy, &param) };
+
+                        if ret != 0 {
+                            error!(
+                                "Failed setting the real-time scheduling policy of vCPU {}: {}",
+                                vcpu_id,
+                                io::Error::last_os_error()
+                            );
+                            return;
+                        }
+                    }
+
                     // Apply seccomp filter for vcpu thread.
                     if !vcpu_seccomp_filter.is_empty() {
                         if let Err(e) =
@@ -890,7 +1531,20 @@ impl CpuManager {
                     vcpu_thread_barrier.wait();
 
                     std::panic::catch_unwind(move || {
+                        let mut last_throttle = Instant::now();
                         loop {
+                            // When throttled, the vCPU is kicked out of the
+                            // guest every period, and sleeps for long enough
+                            // to run only for the expected share of the time.
+                            let throttle = vcpu_throttle.load(Ordering::SeqCst);
+                            if throttle > 0 && last_throttle.elapsed() >= VCPU_THROTTLE_PERIOD {
+                                thread::sleep(
+                                    VCPU_THROTTLE_PERIOD * throttle as u32
+                                        / (100 - throttle) as u32,
+                                );
+                                last_throttle = Instant::now();
+                            }
+
                             // If we are being told to pause, we park the thread
                             // until the pause boolean is toggled.
                             // The resume operation is responsible for toggling
@@ -948,8 +1602,18 @@ impl CpuManager {
                             let mut vcpu = vcpu.lock().unwrap();
                             #[cfg(not(feature = "tdx"))]
                             let vcpu = vcpu.lock().unwrap();
+                            #[cfg(target_arch = "x86_64")]
+                            if vcpu_nmi_pending.swap(false, Ordering::SeqCst) {
+                                if let Err(e) = vcpu.vcpu.nmi() {
+                                    error!("Failed injecting NMI on vCPU {}: {}", vcpu_id, e);
+                                }
+                            }
+
+                            let exit = vcpu.run();
+                            vcpu_exits.fetch_add(1, Ordering::Relaxed);
+
                             // vcpu.run() returns false on a triple-fault so trigger a reset
-                            match vcpu.run() {
+                            match exit {
                                 Ok(run) => match run {
                                     #[cfg(feature = "kvm")]
                                     VmExit::Debug => {
@@ -989,16 +1653,29 @@ impl CpuManager {
                                     #[cfg(feature = "tdx")]
                                     VmExit::Tdx => {
                                         if let Some(vcpu) = Arc::get_mut(&mut vcpu.vcpu) {
-                                            match vcpu.get_tdx_exit_details() {
-                                                Ok(details) => match details {
-                                                    TdxExitDetails::GetQuote => warn!("TDG_VP_VMCALL_GET_QUOTE not supported"),
-                                                    TdxExitDetails::SetupEventNotifyInterrupt => {
-                                                        warn!("TDG_VP_VMCALL_SETUP_EVENT_NOTIFY_INTERRUPT not supported")
+                                            let status = match vcpu.get_tdx_exit_details() {
+                                                Ok(TdxExitDetails::GetQuote { gpa, size }) => {
+                                                    // The quote is generated asynchronously,
+                                                    // the guest polling the shared buffer
+                                                    // for completion.
+                                                    match tdx_quote_sender.as_ref().map(|s| s.send(QuoteRequest { gpa, size })) {
+                                                        Some(Ok(())) => TdxExitStatus::Success,
+                                                        _ => {
+                                                            warn!("TDG_VP_VMCALL_GET_QUOTE not supported");
+                                                            TdxExitStatus::InvalidOperand
+                                                        }
                                                     }
-                                                },
-                                                Err(e) => error!("Unexpected TDX VMCALL: {}", e),
-                                            }
-                                            vcpu.set_tdx_status(TdxExitStatus::InvalidOperand);
+                                                }
+                                                Ok(TdxExitDetails::SetupEventNotifyInterrupt) => {
+                                                    warn!("TDG_VP_VMCALL_SETUP_EVENT_NOTIFY_INTERRUPT not supported");
+                                                    TdxExitStatus::InvalidOperand
+                                                }
+                                                Err(e) => {
+                                                    error!("Unexpected TDX VMCALL: {}", e);
+                                                    TdxExitStatus::InvalidOperand
+                                                }
+                                            };
+                                            vcpu.set_tdx_status(status);
                                         } else {
                                             // We should never reach this code as
                                             // this means the design from the code
@@ -1006,6 +1683,39 @@ impl CpuManager {
                                             unreachable!("Couldn't get a mutable reference from Arc<dyn Vcpu> as there are multiple instances");
                                         }
                                     }
+                                    #[cfg(feature = "sev_snp")]
+                                    VmExit::SevSnp => {
+                                        if let Some(vcpu) = Arc::get_mut(&mut vcpu.vcpu) {
+                                            match vcpu.get_sev_snp_exit_details() {
+                                                Ok(SevSnpExitDetails::MapGpaRange { gpa, size, private }) => {
+                                                    let ret = match vm.set_memory_private(gpa, size, private) {
+                                                        Ok(()) => 0,
+                                                        Err(e) => {
+                                                            error!("Failed converting guest memory 0x{:x}-0x{:x}: {}", gpa, gpa + size, e);
+                                                            -libc::EINVAL as u64
+                                                        }
+                                                    };
+                                                    vcpu.set_sev_snp_hypercall_result(ret);
+                                                }
+                                                Ok(SevSnpExitDetails::MemoryFault { gpa, size, private }) => {
+                                                    if let Err(e) = vm.set_memory_private(gpa, size, private) {
+                                                        error!("Failed converting guest memory 0x{:x}-0x{:x}: {}", gpa, gpa + size, e);
+                                                        vcpu_run_interrupted.store(true, Ordering::SeqCst);
+                                                        exit_evt.write(1).unwrap();
+                                                        break;
+                                                    }
+                                                }
+                                                Err(e) => {
+                                                    error!("Unexpected SEV-SNP exit: {}", e);
+                                                    vcpu_run_interrupted.store(true, Ordering::SeqCst);
+                                                    exit_evt.write(1).unwrap();
+                                                    break;
+                                                }
+                                            }
+                                        } else {
+                                            unreachable!("Couldn't get a mutable reference from Arc<dyn Vcpu> as there are multiple instances");
+                                        }
+                                    }
                                     _ => {
                                         error!(
                                             "VCPU generated error: {:?}",
@@ -1037,6 +1747,7 @@ impl CpuManager {
                     .or_else(|_| {
                         panic_vcpu_run_interrupted.store(true, Ordering::SeqCst);
                         error!("vCPU thread panicked");
+                        event!("vcpu", "panicked", "id", vcpu_id.to_string());
                         panic_exit_evt.write(1)
                     })
                     .ok();
@@ -1046,8 +1757,8 @@ impl CpuManager {
 
         // On hot plug calls into this function entry_point is None. It is for
         // those hotplug CPU additions that we need to set the inserting flag.
-        self.vcpu_states[usize::from(vcpu_id)].handle = handle;
-        self.vcpu_states[usize::from(vcpu_id)].inserting = inserting;
+        self.vcpu_states[vcpu_id as usize].handle = handle;
+        self.vcpu_states[vcpu_id as usize].inserting = inserting;
 
         Ok(())
     }
@@ -1055,7 +1766,7 @@ impl CpuManager {
     /// Start up as many vCPUs threads as needed to reach `desired_vcpus`
     fn activate_vcpus(
         &mut self,
-        desired_vcpus: u8,
+        desired_vcpus: u32,
         inserting: bool,
         paused: Option<bool>,
     ) -> Result<()> {
@@ -1063,24 +1774,37 @@ impl CpuManager {
             return Err(Error::DesiredVCpuCountExceedsMax);
         }
 
-        let vcpu_thread_barrier = Arc::new(Barrier::new(
-            (desired_vcpus - self.present_vcpus() + 1) as usize,
-        ));
+        // This reuses any inactive vCPUs as well as any that were newly created.
+        // As specific vCPUs can be removed, the inactive ones aren't
+        // necessarily the last ones.
+        let vcpu_ids: Vec<u32> = (0..self.config.max_vcpus)
+            .filter(|id| !self.vcpu_states[*id as usize].active())
+            .take((desired_vcpus - self.present_vcpus()) as usize)
+            .collect();
+
+        self.start_vcpus(&vcpu_ids, inserting, paused)
+    }
+
+    /// Start up the threads of the given inactive vCPUs
+    fn start_vcpus(
+        &mut self,
+        vcpu_ids: &[u32],
+        inserting: bool,
+        paused: Option<bool>,
+    ) -> Result<()> {
+        let vcpu_thread_barrier = Arc::new(Barrier::new(vcpu_ids.len() + 1));
 
         if let Some(paused) = paused {
             self.vcpus_pause_signalled.store(paused, Ordering::SeqCst);
         }
 
         info!(
-            "Starting vCPUs: desired = {}, allocated = {}, present = {}, paused = {}",
-            desired_vcpus,
-            self.vcpus.len(),
-            self.present_vcpus(),
+            "Starting vCPUs: ids = {:?}, paused = {}",
+            vcpu_ids,
             self.vcpus_pause_signalled.load(Ordering::SeqCst)
         );
 
-        // This reuses any inactive vCPUs as well as any that were newly created
-        for vcpu_id in self.present_vcpus()..desired_vcpus {
+        for vcpu_id in vcpu_ids.iter().copied() {
             let vcpu = Arc::clone(&self.vcpus[vcpu_id as usize]);
             self.start_vcpu(vcpu, vcpu_id, vcpu_thread_barrier.clone(), inserting)?;
         }
@@ -1090,16 +1814,34 @@ impl CpuManager {
         Ok(())
     }
 
-    fn mark_vcpus_for_removal(&mut self, desired_vcpus: u8) {
-        // Mark vCPUs for removal, actual removal happens on ejection
-        for cpu_id in desired_vcpus..self.present_vcpus() {
-            self.vcpu_states[usize::from(cpu_id)].removing = true;
+    fn mark_vcpus_for_removal(&mut self, desired_vcpus: u32) {
+        // Mark the last active vCPUs for removal, actual removal happens on
+        // ejection
+        let removed_vcpus = (self.present_vcpus() - desired_vcpus) as usize;
+        for state in self
+            .vcpu_states
+            .iter_mut()
+            .rev()
+            .filter(|state| state.active())
+            .take(removed_vcpus)
+        {
+            state.removing = true;
+        }
+    }
+
+    // Mark the given vCPUs for removal, returning the number of vCPUs the VM
+    // will be left with once the guest has ejected them.
+    pub fn remove_vcpus(&mut self, cpu_ids: &[u32]) -> Result<Option<u32>> {
+        if !self.dynamic {
+            return Ok(None);
         }
+
+        mark_vcpus_removing(&mut self.vcpu_states, cpu_ids).map(Some)
     }
 
-    fn remove_vcpu(&mut self, cpu_id: u8) -> Result<()> {
+    fn remove_vcpu(&mut self, cpu_id: u32) -> Result<()> {
         info!("Removing vCPU: cpu_id = {}", cpu_id);
-        let mut state = &mut self.vcpu_states[usize::from(cpu_id)];
+        let mut state = &mut self.vcpu_states[cpu_id as usize];
         state.kill.store(true, Ordering::SeqCst);
         state.signal_thread();
         state.join_thread()?;
@@ -1117,7 +1859,37 @@ impl CpuManager {
     ) -> Result<Vec<Arc<Mutex<Vcpu>>>> {
         trace_scoped!("create_boot_vcpus");
 
-        self.create_vcpus(self.boot_vcpus(), snapshot)
+        // When restoring, the vCPUs that were present might not be the first
+        // ones if some have been removed. Older snapshots don't record them,
+        // in which case only the boot vCPUs could have been present.
+        if let Some(snapshot) = snapshot.as_ref() {
+            let present_vcpus = if snapshot.snapshot_data.is_some() {
+                snapshot
+                    .to_state::<CpuManagerState>()
+                    .map_err(|e| Error::VcpuCreate(e.into()))?
+                    .present_vcpus
+            } else {
+                (0..self.boot_vcpus()).collect()
+            };
+            if present_vcpus.iter().any(|id| *id >= self.max_vcpus()) {
+                return Err(Error::DesiredVCpuCountExceedsMax);
+            }
+            self.restored_vcpus = Some(present_vcpus);
+        }
+
+        // On aarch64 the vGIC must be aware of all the vCPUs when it gets
+        // created, meaning the ones that can be hotplugged must be created
+        // upfront as well. Only the boot vCPUs are started though.
+        #[cfg(target_arch = "x86_64")]
+        let vcpus = self
+            .restored_vcpus
+            .as_ref()
+            .and_then(|ids| ids.iter().max())
+            .map_or(self.boot_vcpus(), |id| id + 1);
+        #[cfg(target_arch = "aarch64")]
+        let vcpus = self.max_vcpus();
+
+        self.create_vcpus(vcpus, snapshot)
     }
 
     // Starts all the vCPUs that the VM is booting with. Blocks until all vCPUs are running.
@@ -1126,7 +1898,11 @@ impl CpuManager {
     }
 
     pub fn start_restored_vcpus(&mut self) -> Result<()> {
-        self.activate_vcpus(self.vcpus.len() as u8, false, Some(true))
+        let vcpu_ids = self
+            .restored_vcpus
+            .take()
+            .unwrap_or_else(|| (0..self.boot_vcpus()).collect());
+        self.start_vcpus(&vcpu_ids, false, Some(true))
             .map_err(|e| {
                 Error::StartRestoreVcpu(anyhow!("Failed to start restored vCPUs: {:#?}", e))
             })?;
@@ -1134,7 +1910,7 @@ impl CpuManager {
         Ok(())
     }
 
-    pub fn resize(&mut self, desired_vcpus: u8) -> Result<bool> {
+    pub fn resize(&mut self, desired_vcpus: u32) -> Result<bool> {
         if desired_vcpus.cmp(&self.present_vcpus()) == cmp::Ordering::Equal {
             return Ok(false);
         }
@@ -1161,6 +1937,11 @@ impl CpuManager {
     }
 
     pub fn shutdown(&mut self) -> Result<()> {
+        // Let the TDX quote generation thread terminate once the vCPU
+        // threads are gone
+        #[cfg(feature = "tdx")]
+        self.tdx_quote_sender.take();
+
         // Tell the vCPUs to stop themselves next time they go through the loop
         self.vcpus_kill_signalled.store(true, Ordering::SeqCst);
 
@@ -1187,6 +1968,29 @@ impl CpuManager {
         Ok(())
     }
 
+    // The application processors of a SEV-ES guest can't be started through
+    // INIT-SIPI, as their state is encrypted. They directly start from the
+    // reset vector advertised by the firmware instead.
+    #[cfg(feature = "sev_snp")]
+    pub fn setup_sev_es_ap_reset_vector(&self, reset_vector: u32) -> Result<()> {
+        for vcpu in &self.vcpus {
+            let vcpu = vcpu.lock().unwrap();
+            if vcpu.id == 0 {
+                continue;
+            }
+            arch::x86_64::regs::setup_sev_es_ap_regs(&vcpu.vcpu, reset_vector).map_err(|e| {
+                Error::VcpuConfiguration(arch::x86_64::Error::RegsConfiguration(e).into())
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Lets the vCPUs forward the quote requests of a TDX guest.
+    #[cfg(feature = "tdx")]
+    pub fn set_tdx_quote_sender(&mut self, sender: Sender<QuoteRequest>) {
+        self.tdx_quote_sender = Some(sender);
+    }
+
     #[cfg(feature = "tdx")]
     pub fn initialize_tdx(&self, hob_address: u64) -> Result<()> {
         for vcpu in &self.vcpus {
@@ -1199,30 +2003,135 @@ impl CpuManager {
         Ok(())
     }
 
-    pub fn boot_vcpus(&self) -> u8 {
+    pub fn boot_vcpus(&self) -> u32 {
         self.config.boot_vcpus
     }
 
-    pub fn max_vcpus(&self) -> u8 {
+    /// State of every vCPU the VM can have, plugged or not.
+    pub fn vcpus_info(&self) -> Vec<VcpuInfo> {
+        let paused = self.vcpus_pause_signalled.load(Ordering::SeqCst);
+        self.vcpu_states
+            .iter()
+            .enumerate()
+            .map(|(id, state)| VcpuInfo {
+                id: id as u8,
+                status: if state.removing {
+                    VcpuStatus::Removing
+                } else if state.inserting {
+                    VcpuStatus::Inserting
+                } else if !state.active() {
+                    VcpuStatus::Inactive
+                } else if paused {
+                    VcpuStatus::Paused
+                } else {
+                    VcpuStatus::Running
+                },
+                exits: state.exits.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// Number of exits from the guest of each active vCPU.
+    pub fn vcpus_exits(&self) -> Vec<(u8, u64)> {
+        self.vcpu_states
+            .iter()
+            .enumerate()
+            .filter(|(_, state)| state.active())
+            .map(|(id, state)| (id as u8, state.exits.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    /// Exits of each active vCPU, broken down by reason.
+    pub fn vcpus_stats(&self) -> Vec<VcpuStats> {
+        self.vcpu_states
+            .iter()
+            .enumerate()
+            .filter(|(_, state)| state.active())
+            .map(|(id, state)| {
+                let stats = state
+                    .exit_counters
+                    .as_ref()
+                    .map(|counters| counters.stats())
+                    .unwrap_or_default();
+                VcpuStats {
+                    id: id as u8,
+                    exits: state.exits.load(Ordering::Relaxed),
+                    io: stats.io,
+                    mmio: stats.mmio,
+                    hlt: stats.hlt,
+                    ept_violation: stats.ept_violation,
+                }
+            })
+            .collect()
+    }
+
+    pub fn max_vcpus(&self) -> u32 {
         self.config.max_vcpus
     }
 
+    /// Keeps the vCPUs from running for the given percentage of the time,
+    /// 0 lifting the throttling.
+    pub fn set_throttle(cpu_manager: &Arc<Mutex<CpuManager>>, percentage: u8) -> Result<()> {
+        let mut locked = cpu_manager.lock().unwrap();
+        let percentage = percentage.min(MAX_VCPU_THROTTLE);
+        locked.vcpus_throttle.store(percentage, Ordering::SeqCst);
+
+        if percentage == 0 {
+            if let Some(handle) = locked.throttle_thread.take() {
+                // The thread needs the lock to notice it must stop
+                drop(locked);
+                handle.join().map_err(Error::ThreadCleanup)?;
+            }
+            return Ok(());
+        }
+
+        if locked.throttle_thread.is_none() {
+            let cpu_manager: Weak<Mutex<CpuManager>> = Arc::downgrade(cpu_manager);
+            let handle = thread::Builder::new()
+                .name("vcpu_throttle".to_string())
+                .spawn(move || loop {
+                    thread::sleep(VCPU_THROTTLE_PERIOD);
+                    let cpu_manager = match cpu_manager.upgrade() {
+                        Some(cpu_manager) => cpu_manager,
+                        None => break,
+                    };
+                    let cpu_manager = cpu_manager.lock().unwrap();
+                    if cpu_manager.vcpus_throttle.load(Ordering::SeqCst) == 0 {
+                        break;
+                    }
+                    for state in cpu_manager.vcpu_states.iter() {
+                        state.kick_thread();
+                    }
+                })
+                .map_err(Error::ThrottleSpawn)?;
+            locked.throttle_thread = Some(handle);
+        }
+
+        Ok(())
+    }
+
+    pub fn throttle(&self) -> u8 {
+        self.vcpus_throttle.load(Ordering::SeqCst)
+    }
+
     #[cfg(target_arch = "x86_64")]
     pub fn common_cpuid(&self) -> Vec<CpuIdEntry> {
         assert!(!self.cpuid.is_empty());
         self.cpuid.clone()
     }
 
-    fn present_vcpus(&self) -> u8 {
+    fn present_vcpus(&self) -> u32 {
         self.vcpu_states
             .iter()
-            .fold(0, |acc, state| acc + state.active() as u8)
+            .fold(0, |acc, state| acc + state.active() as u32)
     }
 
     #[cfg(target_arch = "aarch64")]
     pub fn get_mpidrs(&self) -> Vec<u64> {
+        // Hotplug relies on ACPI, only the boot vCPUs are described in the FDT.
         self.vcpus
             .iter()
+            .take(self.boot_vcpus() as usize)
             .map(|cpu| cpu.lock().unwrap().get_mpidr())
             .collect()
     }
@@ -1254,18 +2163,30 @@ impl CpuManager {
             madt.write(36, arch::layout::APIC_START);
 
             for cpu in 0..self.config.max_vcpus {
-                let lapic = LocalApic {
-                    r#type: acpi::ACPI_APIC_PROCESSOR,
-                    length: 8,
-                    processor_id: cpu,
-                    apic_id: cpu,
-                    flags: if cpu < self.config.boot_vcpus {
-                        1 << MADT_CPU_ENABLE_FLAG
-                    } else {
-                        0
-                    } | 1 << MADT_CPU_ONLINE_CAPABLE_FLAG,
-                };
-                madt.append(lapic);
+                let flags = if cpu < self.config.boot_vcpus {
+                    1 << MADT_CPU_ENABLE_FLAG
+                } else {
+                    0
+                } | 1 << MADT_CPU_ONLINE_CAPABLE_FLAG;
+                let apic_id = self.apic_id(cpu);
+                if apic_id <= MAX_XAPIC_ID && cpu <= MAX_XAPIC_ID {
+                    madt.append(LocalApic {
+                        r#type: acpi::ACPI_APIC_PROCESSOR,
+                        length: 8,
+                        processor_id: cpu as u8,
+                        apic_id: apic_id as u8,
+                        flags,
+                    });
+                } else {
+                    madt.append(LocalX2Apic {
+                        r#type: acpi::ACPI_X2APIC_PROCESSOR,
+                        length: 16,
+                        _reserved: 0,
+                        apic_id,
+                        flags,
+                        processor_id: cpu,
+                    });
+                }
             }
 
             madt.append(Ioapic {
@@ -1294,7 +2215,9 @@ impl CpuManager {
              */
 
             // See section 5.2.12.14 GIC CPU Interface (GICC) Structure in ACPI spec.
-            for cpu in 0..self.config.boot_vcpus {
+            // All the possible vCPUs are described, the ones which are not
+            // present being only online capable until they are hotplugged.
+            for cpu in 0..self.config.max_vcpus {
                 let vcpu = &self.vcpus[cpu as usize];
                 let mpidr = vcpu.lock().unwrap().get_mpidr();
                 /* ARMv8 MPIDR format:
@@ -1306,15 +2229,25 @@ impl CpuManager {
                      Bits [7:0] Aff0 : Match Aff0 of target processor MPIDR
                 */
                 let mpidr_mask = 0xff_00ff_ffff;
+                // The PMU interrupt is a PPI, its GSIV is offset by 16
+                let performance_interrupt = if self.config.features.pmu {
+                    arch::aarch64::fdt::AARCH64_PMU_IRQ + 16
+                } else {
+                    0
+                };
                 let gicc = GicC {
                     r#type: acpi::ACPI_APIC_GENERIC_CPU_INTERFACE,
                     length: 80,
                     reserved0: 0,
-                    cpu_interface_number: cpu as u32,
-                    uid: cpu as u32,
-                    flags: 1,
+                    cpu_interface_number: cpu,
+                    uid: cpu,
+                    flags: if cpu < self.config.boot_vcpus {
+                        1 << MADT_CPU_ENABLE_FLAG
+                    } else {
+                        1 << MADT_GICC_ONLINE_CAPABLE_FLAG
+                    },
                     parking_version: 0,
-                    performance_interrupt: 0,
+                    performance_interrupt,
                     parked_address: 0,
                     base_address: 0,
                     gicv_base_address: 0,
@@ -1329,7 +2262,7 @@ impl CpuManager {
 
                 madt.append(gicc);
             }
-            let vgic_config = Gic::create_default_config(self.config.boot_vcpus.into());
+            let vgic_config = Gic::create_default_config(self.config.max_vcpus.into());
 
             // GIC Distributor structure. See section 5.2.12.15 in ACPI spec.
             let gicd = GicD {
@@ -1379,64 +2312,57 @@ impl CpuManager {
         // If topology is not specified, the default setting is:
         // 1 package, multiple cores, 1 thread per core
         // This is also the behavior when PPTT is missing.
-        let (threads_per_core, cores_per_package, packages) =
-            self.get_vcpu_topology().unwrap_or((1, self.max_vcpus(), 1));
+        let (threads_per_core, cores_per_package, packages) = self
+            .get_vcpu_topology()
+            .map(|(t, c, p)| (u32::from(t), u32::from(c), u32::from(p)))
+            .unwrap_or((1, self.max_vcpus(), 1));
+
+        // The cache hierarchy mirrors the host one, laid out on top of the
+        // guest topology.
+        let (shared_caches, private_caches): (Vec<CacheInfo>, Vec<CacheInfo>) =
+            CacheInfo::host_caches()
+                .into_iter()
+                .partition(|cache| cache.is_shared());
 
         let mut pptt = Sdt::new(*b"PPTT", 36, 2, *b"CLOUDH", *b"CHPPTT  ", 1);
 
         for cluster_idx in 0..packages {
             if cpus < self.config.boot_vcpus as usize {
+                let cluster_caches = append_pptt_caches(&mut pptt, &shared_caches);
                 let cluster_offset = pptt.len() - pptt_start;
-                let cluster_hierarchy_node = ProcessorHierarchyNode {
-                    r#type: 0,
-                    length: 20,
-                    reserved: 0,
-                    flags: 0x2,
-                    parent: 0,
-                    acpi_processor_id: cluster_idx as u32,
-                    num_private_resources: 0,
-                };
-                pptt.append(cluster_hierarchy_node);
+                append_processor_hierarchy_node(&mut pptt, 0x2, 0, cluster_idx, &cluster_caches);
 
                 for core_idx in 0..cores_per_package {
-                    let core_offset = pptt.len() - pptt_start;
+                    let core_caches = append_pptt_caches(&mut pptt, &private_caches);
 
                     if threads_per_core > 1 {
-                        let core_hierarchy_node = ProcessorHierarchyNode {
-                            r#type: 0,
-                            length: 20,
-                            reserved: 0,
-                            flags: 0x2,
-                            parent: cluster_offset as u32,
-                            acpi_processor_id: core_idx as u32,
-                            num_private_resources: 0,
-                        };
-                        pptt.append(core_hierarchy_node);
+                        let core_offset = pptt.len() - pptt_start;
+                        append_processor_hierarchy_node(
+                            &mut pptt,
+                            0x2,
+                            cluster_offset as u32,
+                            core_idx,
+                            &core_caches,
+                        );
 
                         for _thread_idx in 0..threads_per_core {
-                            let thread_hierarchy_node = ProcessorHierarchyNode {
-                                r#type: 0,
-                                length: 20,
-                                reserved: 0,
-                                flags: 0xE,
-                                parent: core_offset as u32,
-                                acpi_processor_id: uid as u32,
-                                num_private_resources: 0,
-                            };
-                            pptt.append(thread_hierarchy_node);
+                            append_processor_hierarchy_node(
+                                &mut pptt,
+                                0xE,
+                                core_offset as u32,
+                                uid as u32,
+                                &[],
+                            );
                             uid += 1;
                         }
                     } else {
-                        let thread_hierarchy_node = ProcessorHierarchyNode {
-                            r#type: 0,
-                            length: 20,
-                            reserved: 0,
-                            flags: 0xA,
-                            parent: cluster_offset as u32,
-                            acpi_processor_id: uid as u32,
-                            num_private_resources: 0,
-                        };
-                        pptt.append(thread_hierarchy_node);
+                        append_processor_hierarchy_node(
+                            &mut pptt,
+                            0xA,
+                            cluster_offset as u32,
+                            uid as u32,
+                            &core_caches,
+                        );
                         uid += 1;
                     }
                 }
@@ -1449,8 +2375,8 @@ impl CpuManager {
     }
 
     #[cfg(feature = "guest_debug")]
-    fn get_regs(&self, cpu_id: u8) -> Result<StandardRegisters> {
-        self.vcpus[usize::from(cpu_id)]
+    fn get_regs(&self, cpu_id: u32) -> Result<StandardRegisters> {
+        self.vcpus[cpu_id as usize]
             .lock()
             .unwrap()
             .vcpu
@@ -1459,8 +2385,8 @@ impl CpuManager {
     }
 
     #[cfg(feature = "guest_debug")]
-    fn set_regs(&self, cpu_id: u8, regs: &StandardRegisters) -> Result<()> {
-        self.vcpus[usize::from(cpu_id)]
+    fn set_regs(&self, cpu_id: u32, regs: &StandardRegisters) -> Result<()> {
+        self.vcpus[cpu_id as usize]
             .lock()
             .unwrap()
             .vcpu
@@ -1469,8 +2395,8 @@ impl CpuManager {
     }
 
     #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
-    fn get_sregs(&self, cpu_id: u8) -> Result<SpecialRegisters> {
-        self.vcpus[usize::from(cpu_id)]
+    fn get_sregs(&self, cpu_id: u32) -> Result<SpecialRegisters> {
+        self.vcpus[cpu_id as usize]
             .lock()
             .unwrap()
             .vcpu
@@ -1479,8 +2405,8 @@ impl CpuManager {
     }
 
     #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
-    fn set_sregs(&self, cpu_id: u8, sregs: &SpecialRegisters) -> Result<()> {
-        self.vcpus[usize::from(cpu_id)]
+    fn set_sregs(&self, cpu_id: u32, sregs: &SpecialRegisters) -> Result<()> {
+        self.vcpus[cpu_id as usize]
             .lock()
             .unwrap()
             .vcpu
@@ -1492,10 +2418,10 @@ impl CpuManager {
     fn translate_gva(
         &self,
         _guest_memory: &GuestMemoryAtomic<GuestMemoryMmap>,
-        cpu_id: u8,
+        cpu_id: u32,
         gva: u64,
     ) -> Result<u64> {
-        let (gpa, _) = self.vcpus[usize::from(cpu_id)]
+        let (gpa, _) = self.vcpus[cpu_id as usize]
             .lock()
             .unwrap()
             .vcpu
@@ -1524,22 +2450,22 @@ impl CpuManager {
     fn translate_gva(
         &self,
         guest_memory: &GuestMemoryAtomic<GuestMemoryMmap>,
-        cpu_id: u8,
+        cpu_id: u32,
         gva: u64,
     ) -> Result<u64> {
-        let tcr_el1: u64 = self.vcpus[usize::from(cpu_id)]
+        let tcr_el1: u64 = self.vcpus[cpu_id as usize]
             .lock()
             .unwrap()
             .vcpu
             .get_sys_reg(regs::TCR_EL1)
             .map_err(|e| Error::TranslateVirtualAddress(e.into()))?;
-        let ttbr1_el1: u64 = self.vcpus[usize::from(cpu_id)]
+        let ttbr1_el1: u64 = self.vcpus[cpu_id as usize]
             .lock()
             .unwrap()
             .vcpu
             .get_sys_reg(regs::TTBR1_EL1)
             .map_err(|e| Error::TranslateVirtualAddress(e.into()))?;
-        let id_aa64mmfr0_el1: u64 = self.vcpus[usize::from(cpu_id)]
+        let id_aa64mmfr0_el1: u64 = self.vcpus[cpu_id as usize]
             .lock()
             .unwrap()
             .vcpu
@@ -1672,6 +2598,37 @@ impl CpuManager {
         Ok(descaddr)
     }
 
+    /// Injects an NMI into the vCPU `vcpu_id`, or into every present vCPU.
+    /// The vCPUs are kicked out of the guest to pick it up, a paused vCPU
+    /// getting it once resumed.
+    #[cfg(target_arch = "x86_64")]
+    pub fn nmi(&self, vcpu_id: Option<u32>) -> Result<()> {
+        #[cfg(feature = "mshv")]
+        if matches!(self.hypervisor_type, HypervisorType::Mshv) {
+            return Err(Error::NmiUnsupported);
+        }
+
+        let states: Vec<&VcpuState> = match vcpu_id {
+            Some(id) => vec![self
+                .vcpu_states
+                .get(id as usize)
+                .filter(|state| state.active())
+                .ok_or(Error::InvalidVcpu(id))?],
+            None => self
+                .vcpu_states
+                .iter()
+                .filter(|state| state.active())
+                .collect(),
+        };
+
+        for state in states {
+            state.nmi_pending.store(true, Ordering::SeqCst);
+            state.kick_thread();
+        }
+
+        Ok(())
+    }
+
     pub(crate) fn set_acpi_address(&mut self, acpi_address: GuestAddress) {
         self.acpi_address = Some(acpi_address);
     }
@@ -1685,32 +2642,56 @@ impl CpuManager {
 }
 
 struct Cpu {
-    cpu_id: u8,
+    cpu_id: u32,
+    #[cfg(target_arch = "x86_64")]
+    apic_id: u32,
     proximity_domain: u32,
     dynamic: bool,
 }
 
-#[cfg(target_arch = "x86_64")]
 const MADT_CPU_ENABLE_FLAG: usize = 0;
 
 #[cfg(target_arch = "x86_64")]
 const MADT_CPU_ONLINE_CAPABLE_FLAG: usize = 1;
 
+#[cfg(target_arch = "aarch64")]
+const MADT_GICC_ONLINE_CAPABLE_FLAG: usize = 3;
+
+// Highest APIC ID the local APIC structure can describe, the local x2APIC
+// structure being used beyond.
+#[cfg(target_arch = "x86_64")]
+const MAX_XAPIC_ID: u32 = 254;
+
 impl Cpu {
     #[cfg(target_arch = "x86_64")]
     fn generate_mat(&self) -> Vec<u8> {
-        let lapic = LocalApic {
-            r#type: 0,
-            length: 8,
-            processor_id: self.cpu_id,
-            apic_id: self.cpu_id,
-            flags: 1 << MADT_CPU_ENABLE_FLAG,
-        };
-
         let mut mat_data: Vec<u8> = Vec::new();
-        mat_data.resize(std::mem::size_of_val(&lapic), 0);
-        // SAFETY: mat_data is large enough to hold lapic
-        unsafe { *(mat_data.as_mut_ptr() as *mut LocalApic) = lapic };
+        if self.apic_id <= MAX_XAPIC_ID && self.cpu_id <= MAX_XAPIC_ID {
+            let lapic = LocalApic {
+                r#type: 0,
+                length: 8,
+                processor_id: self.cpu_id as u8,
+                apic_id: self.apic_id as u8,
+                flags: 1 << MADT_CPU_ENABLE_FLAG,
+            };
+
+            mat_data.resize(std::mem::size_of_val(&lapic), 0);
+            // SAFETY: mat_data is large enough to hold lapic
+            unsafe { *(mat_data.as_mut_ptr() as *mut LocalApic) = lapic };
+        } else {
+            let x2apic = LocalX2Apic {
+                r#type: 9,
+                length: 16,
+                _reserved: 0,
+                apic_id: self.apic_id,
+                flags: 1 << MADT_CPU_ENABLE_FLAG,
+                processor_id: self.cpu_id,
+            };
+
+            mat_data.resize(std::mem::size_of_val(&x2apic), 0);
+            // SAFETY: mat_data is large enough to hold x2apic
+            unsafe { *(mat_data.as_mut_ptr() as *mut LocalX2Apic) = x2apic };
+        }
 
         mat_data
     }
@@ -1723,11 +2704,10 @@ impl Aml for Cpu {
         #[allow(clippy::if_same_then_else)]
         if self.dynamic {
             aml::Device::new(
-                format!("C{:03}", self.cpu_id).as_str().into(),
+                format!("C{:03X}", self.cpu_id).as_str().into(),
                 vec![
                     &aml::Name::new("_HID".into(), &"ACPI0007"),
                     &aml::Name::new("_UID".into(), &self.cpu_id),
-                    // Currently, AArch64 cannot support following fields.
                     /*
                     _STA return value:
                     Bit [0] – Set if the device is present.
@@ -1737,7 +2717,6 @@ impl Aml for Cpu {
                     Bit [4] – Set if the battery is present.
                     Bits [31:5] – Reserved (must be cleared).
                     */
-                    #[cfg(target_arch = "x86_64")]
                     &aml::Method::new(
                         "_STA".into(),
                         0,
@@ -1760,7 +2739,6 @@ impl Aml for Cpu {
                     #[cfg(target_arch = "x86_64")]
                     &aml::Name::new("_MAT".into(), &aml::BufferData::new(mat_data)),
                     // Trigger CPU ejection
-                    #[cfg(target_arch = "x86_64")]
                     &aml::Method::new(
                         "_EJ0".into(),
                         1,
@@ -1773,11 +2751,10 @@ impl Aml for Cpu {
             .to_aml_bytes(sink);
         } else {
             aml::Device::new(
-                format!("C{:03}", self.cpu_id).as_str().into(),
+                format!("C{:03X}", self.cpu_id).as_str().into(),
                 vec![
                     &aml::Name::new("_HID".into(), &"ACPI0007"),
                     &aml::Name::new("_UID".into(), &self.cpu_id),
-                    #[cfg(target_arch = "x86_64")]
                     &aml::Method::new(
                         "_STA".into(),
                         0,
@@ -1804,12 +2781,12 @@ impl Aml for Cpu {
 }
 
 struct CpuNotify {
-    cpu_id: u8,
+    cpu_id: u32,
 }
 
 impl Aml for CpuNotify {
     fn to_aml_bytes(&self, sink: &mut dyn acpi_tables::AmlSink) {
-        let object = aml::Path::new(&format!("C{:03}", self.cpu_id));
+        let object = aml::Path::new(&format!("C{:03X}", self.cpu_id));
         aml::If::new(
             &aml::Equal::new(&aml::Arg(0), &self.cpu_id),
             vec![&aml::Notify::new(&object, &aml::Arg(1))],
@@ -1819,7 +2796,7 @@ impl Aml for CpuNotify {
 }
 
 struct CpuMethods {
-    max_vcpus: u8,
+    max_vcpus: u32,
     dynamic: bool,
 }
 
@@ -1857,7 +2834,7 @@ impl Aml for CpuMethods {
 
             let mut cpu_notifies_refs: Vec<&dyn Aml> = Vec::new();
             for cpu_id in 0..self.max_vcpus {
-                cpu_notifies_refs.push(&cpu_notifies[usize::from(cpu_id)]);
+                cpu_notifies_refs.push(&cpu_notifies[cpu_id as usize]);
             }
 
             aml::Method::new("CTFY".into(), 2, true, cpu_notifies_refs).to_aml_bytes(sink);
@@ -1938,7 +2915,6 @@ impl Aml for CpuMethods {
 
 impl Aml for CpuManager {
     fn to_aml_bytes(&self, sink: &mut dyn acpi_tables::AmlSink) {
-        #[cfg(target_arch = "x86_64")]
         if let Some(acpi_address) = self.acpi_address {
             // CPU hotplug controller
             aml::Device::new(
@@ -2010,6 +2986,8 @@ impl Aml for CpuManager {
             let proximity_domain = *self.proximity_domain_per_cpu.get(&cpu_id).unwrap_or(&0);
             let cpu_device = Cpu {
                 cpu_id,
+                #[cfg(target_arch = "x86_64")]
+                apic_id: self.apic_id(cpu_id),
                 proximity_domain,
                 dynamic: self.dynamic,
             };
@@ -2079,9 +3057,19 @@ impl Snapshottable for CpuManager {
     }
 
     fn snapshot(&mut self) -> std::result::Result<Snapshot, MigratableError> {
-        let mut cpu_manager_snapshot = Snapshot::default();
-
-        // The CpuManager snapshot is a collection of all vCPUs snapshots.
+        let present_vcpus = self
+            .vcpu_states
+            .iter()
+            .enumerate()
+            .filter(|(_, state)| state.active())
+            .map(|(id, _)| id as u32)
+            .collect();
+        let mut cpu_manager_snapshot =
+            Snapshot::from_data(SnapshotData::new_from_state(&CpuManagerState {
+                present_vcpus,
+            })?);
+
+        // The CpuManager snapshot also holds all vCPUs snapshots.
         for vcpu in &self.vcpus {
             let mut vcpu = vcpu.lock().unwrap();
             cpu_manager_snapshot.add_snapshot(vcpu.id(), vcpu.snapshot()?);
@@ -2123,7 +3111,7 @@ impl Debuggable for CpuManager {
     fn read_regs(&self, cpu_id: usize) -> std::result::Result<CoreRegs, DebuggableError> {
         // General registers: RAX, RBX, RCX, RDX, RSI, RDI, RBP, RSP, r8-r15
         let gregs = self
-            .get_regs(cpu_id as u8)
+            .get_regs(cpu_id as u32)
             .map_err(DebuggableError::ReadRegs)?;
         let regs = [
             gregs.rax, gregs.rbx, gregs.rcx, gregs.rdx, gregs.rsi, gregs.rdi, gregs.rbp, gregs.rsp,
@@ -2137,7 +3125,7 @@ impl Debuggable for CpuManager {
 
         // Segment registers: CS, SS, DS, ES, FS, GS
         let sregs = self
-            .get_sregs(cpu_id as u8)
+            .get_sregs(cpu_id as u32)
             .map_err(DebuggableError::ReadRegs)?;
         let segments = X86SegmentRegs {
             cs: sregs.cs.selector as u32,
@@ -2162,7 +3150,7 @@ impl Debuggable for CpuManager {
     #[cfg(target_arch = "aarch64")]
     fn read_regs(&self, cpu_id: usize) -> std::result::Result<CoreRegs, DebuggableError> {
         let gregs = self
-            .get_regs(cpu_id as u8)
+            .get_regs(cpu_id as u32)
             .map_err(DebuggableError::ReadRegs)?;
         Ok(CoreRegs {
             x: gregs.regs.regs,
@@ -2179,7 +3167,7 @@ impl Debuggable for CpuManager {
         regs: &CoreRegs,
     ) -> std::result::Result<(), DebuggableError> {
         let orig_gregs = self
-            .get_regs(cpu_id as u8)
+            .get_regs(cpu_id as u32)
             .map_err(DebuggableError::ReadRegs)?;
         let gregs = StandardRegisters {
             rax: regs.regs[0],
@@ -2203,13 +3191,13 @@ impl Debuggable for CpuManager {
             rflags: (orig_gregs.rflags & !(u32::MAX as u64)) | (regs.eflags as u64),
         };
 
-        self.set_regs(cpu_id as u8, &gregs)
+        self.set_regs(cpu_id as u32, &gregs)
             .map_err(DebuggableError::WriteRegs)?;
 
         // Segment registers: CS, SS, DS, ES, FS, GS
         // Since GDB care only selectors, we call get_sregs() first.
         let mut sregs = self
-            .get_sregs(cpu_id as u8)
+            .get_sregs(cpu_id as u32)
             .map_err(DebuggableError::ReadRegs)?;
         sregs.cs.selector = regs.segments.cs as u16;
         sregs.ss.selector = regs.segments.ss as u16;
@@ -2218,7 +3206,7 @@ impl Debuggable for CpuManager {
         sregs.fs.selector = regs.segments.fs as u16;
         sregs.gs.selector = regs.segments.gs as u16;
 
-        self.set_sregs(cpu_id as u8, &sregs)
+        self.set_sregs(cpu_id as u32, &sregs)
             .map_err(DebuggableError::WriteRegs)?;
 
         // TODO: Add other registers
@@ -2233,14 +3221,14 @@ impl Debuggable for CpuManager {
         regs: &CoreRegs,
     ) -> std::result::Result<(), DebuggableError> {
         let mut gregs = self
-            .get_regs(cpu_id as u8)
+            .get_regs(cpu_id as u32)
             .map_err(DebuggableError::ReadRegs)?;
 
         gregs.regs.regs = regs.x;
         gregs.regs.sp = regs.sp;
         gregs.regs.pc = regs.pc;
 
-        self.set_regs(cpu_id as u8, &gregs)
+        self.set_regs(cpu_id as u32, &gregs)
             .map_err(DebuggableError::WriteRegs)?;
 
         Ok(())
@@ -2258,7 +3246,7 @@ impl Debuggable for CpuManager {
 
         while total_read < len as u64 {
             let gaddr = vaddr.0 + total_read;
-            let paddr = match self.translate_gva(guest_memory, cpu_id as u8, gaddr) {
+            let paddr = match self.translate_gva(guest_memory, cpu_id as u32, gaddr) {
                 Ok(paddr) => paddr,
                 Err(_) if gaddr == u64::MIN => gaddr, // Silently return GVA as GPA if GVA == 0.
                 Err(e) => return Err(DebuggableError::TranslateGva(e)),
@@ -2288,7 +3276,7 @@ impl Debuggable for CpuManager {
 
         while total_written < data.len() as u64 {
             let gaddr = vaddr.0 + total_written;
-            let paddr = match self.translate_gva(guest_memory, cpu_id as u8, gaddr) {
+            let paddr = match self.translate_gva(guest_memory, cpu_id as u32, gaddr) {
                 Ok(paddr) => paddr,
                 Err(_) if gaddr == u64::MIN => gaddr, // Silently return GVA as GPA if GVA == 0.
                 Err(e) => return Err(DebuggableError::TranslateGva(e)),
@@ -2353,7 +3341,7 @@ impl CpuElf64Writable for CpuManager {
             pos += descsz - size_of::<X86_64UserRegs>() - size_of::<u64>();
 
             let orig_rax: u64 = 0;
-            let gregs = self.vcpus[usize::from(vcpu_id)]
+            let gregs = self.vcpus[vcpu_id as usize]
                 .lock()
                 .unwrap()
                 .vcpu
@@ -2368,7 +3356,7 @@ impl CpuElf64Writable for CpuManager {
                 gregs.r9, gregs.r8, gregs.rax, gregs.rcx, gregs.rdx, gregs.rsi, gregs.rdi, orig_rax,
             ];
 
-            let sregs = self.vcpus[usize::from(vcpu_id)]
+            let sregs = self.vcpus[vcpu_id as usize]
                 .lock()
                 .unwrap()
                 .vcpu
@@ -2442,7 +3430,7 @@ impl CpuElf64Writable for CpuManager {
 
             pos += round_up!(COREDUMP_NAME_SIZE as usize, 4);
 
-            let gregs = self.vcpus[usize::from(vcpu_id)]
+            let gregs = self.vcpus[vcpu_id as usize]
                 .lock()
                 .unwrap()
                 .vcpu
@@ -2459,7 +3447,7 @@ impl CpuElf64Writable for CpuManager {
                 gregs.r15,
             ];
 
-            let sregs = self.vcpus[usize::from(vcpu_id)]
+            let sregs = self.vcpus[vcpu_id as usize]
                 .lock()
                 .unwrap()
                 .vcpu
@@ -2525,6 +3513,234 @@ impl CpuElf64Writable for CpuManager {
     }
 }
 
+#[cfg(test)]
+mod placement_tests {
+    use super::*;
+    use arch::NumaNode;
+    use vmm_sys_util::tempdir::TempDir;
+
+    fn host_cpu(id: u8, node: u32, package: u32, core: u32) -> HostCpu {
+        HostCpu {
+            id,
+            node,
+            package,
+            core,
+        }
+    }
+
+    // Two packages of two cores, each core having two hardware threads
+    // numbered the way Linux does, the siblings coming after all the cores.
+    fn smt_host() -> Vec<HostCpu> {
+        (0..8)
+            .map(|id| host_cpu(id, 0, (id as u32 % 4) / 2, id as u32 % 2))
+            .collect()
+    }
+
+    #[test]
+    fn test_host_cpus_from_sysfs() {
+        let dir = TempDir::new_with_prefix("/tmp/ch").unwrap();
+        let sysfs = dir.as_path();
+        let write = |path: &str, content: &str| {
+            let path = sysfs.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        };
+
+        write("cpu/online", "0-2,4\n");
+        for (cpu, package, core) in [(0, 0, 0), (1, 0, 0), (2, 1, 0), (4, 1, 1)] {
+            write(
+                &format!("cpu/cpu{cpu}/topology/physical_package_id"),
+                &format!("{package}\n"),
+            );
+            write(
+                &format!("cpu/cpu{cpu}/topology/core_id"),
+                &format!("{core}\n"),
+            );
+        }
+        // Hosts without NUMA support have all the CPUs on node 0
+        assert_eq!(
+            host_cpus_from_sysfs(sysfs).unwrap(),
+            vec![
+                host_cpu(0, 0, 0, 0),
+                host_cpu(1, 0, 0, 0),
+                host_cpu(2, 0, 1, 0),
+                host_cpu(4, 0, 1, 1),
+            ]
+        );
+
+        write("node/node0/cpulist", "0-1\n");
+        write("node/node1/cpulist", "2,4\n");
+        assert_eq!(
+            host_cpus_from_sysfs(sysfs).unwrap(),
+            vec![
+                host_cpu(0, 0, 0, 0),
+                host_cpu(1, 0, 0, 0),
+                host_cpu(2, 1, 1, 0),
+                host_cpu(4, 1, 1, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_placement_compact() {
+        let affinity = placement_affinity(
+            CpuPlacement::Compact,
+            4,
+            &smt_host(),
+            &NumaNodes::new(),
+            &BTreeMap::new(),
+        );
+        // The sibling threads of a core are used before the next core
+        assert_eq!(
+            affinity.into_values().collect::<Vec<_>>(),
+            vec![vec![0], vec![4], vec![1], vec![5]]
+        );
+    }
+
+    #[test]
+    fn test_placement_scatter() {
+        let affinity = placement_affinity(
+            CpuPlacement::Scatter,
+            8,
+            &smt_host(),
+            &NumaNodes::new(),
+            &BTreeMap::new(),
+        );
+        // One thread of each core of each package first, then the siblings
+        assert_eq!(
+            affinity.into_values().collect::<Vec<_>>(),
+            vec![
+                vec![0],
+                vec![2],
+                vec![1],
+                vec![3],
+                vec![4],
+                vec![6],
+                vec![5],
+                vec![7]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_placement_not_enough_cores() {
+        let host_cpus = [host_cpu(0, 0, 0, 0), host_cpu(1, 0, 0, 1)];
+        for placement in [CpuPlacement::Compact, CpuPlacement::Scatter] {
+            let affinity = placement_affinity(
+                placement,
+                5,
+                &host_cpus,
+                &NumaNodes::new(),
+                &BTreeMap::new(),
+            );
+            // The host CPUs are shared once they have all been used
+            assert_eq!(
+                affinity.into_values().collect::<Vec<_>>(),
+                vec![vec![0], vec![1], vec![0], vec![1], vec![0]]
+            );
+        }
+
+        assert!(placement_affinity(
+            CpuPlacement::Compact,
+            2,
+            &[],
+            &NumaNodes::new(),
+            &BTreeMap::new()
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn test_placement_auto_numa() {
+        let host_cpus = [
+            host_cpu(0, 0, 0, 0),
+            host_cpu(1, 0, 0, 1),
+            host_cpu(2, 1, 1, 0),
+            host_cpu(3, 1, 1, 1),
+        ];
+        let mut numa_nodes = NumaNodes::new();
+        for (node, cpus) in [(0, vec![0, 1]), (1, vec![2, 3]), (2, vec![4])] {
+            numa_nodes.insert(
+                node,
+                NumaNode {
+                    cpus,
+                    ..Default::default()
+                },
+            );
+        }
+        // Guest node 2 is bound to a host node without any CPU
+        let host_numa_nodes = BTreeMap::from([(0, 1), (1, 0), (2, 2)]);
+
+        let affinity = placement_affinity(
+            CpuPlacement::AutoNuma,
+            5,
+            &host_cpus,
+            &numa_nodes,
+            &host_numa_nodes,
+        );
+        assert_eq!(
+            affinity.into_iter().collect::<Vec<_>>(),
+            vec![
+                (0, vec![2, 3]),
+                (1, vec![2, 3]),
+                (2, vec![0, 1]),
+                (3, vec![0, 1])
+            ]
+        );
+    }
+}
+
+#[cfg(test)]
+mod removal_tests {
+    use super::*;
+
+    fn vcpu_states(active: &[bool]) -> Vec<VcpuState> {
+        active
+            .iter()
+            .map(|active| VcpuState {
+                handle: active.then(|| thread::spawn(|| {})),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn removing(vcpu_states: &[VcpuState]) -> Vec<bool> {
+        vcpu_states.iter().map(|state| state.removing).collect()
+    }
+
+    #[test]
+    fn test_remove_specific_vcpus() {
+        let mut states = vcpu_states(&[true, true, true, true]);
+        assert_eq!(mark_vcpus_removing(&mut states, &[1, 2]).unwrap(), 2);
+        assert_eq!(removing(&states), [false, true, true, false]);
+
+        // Marking a vCPU twice doesn't count it twice
+        assert_eq!(mark_vcpus_removing(&mut states, &[2, 3]).unwrap(), 1);
+        assert_eq!(removing(&states), [false, true, true, true]);
+    }
+
+    #[test]
+    fn test_remove_vcpus_after_hole() {
+        // vCPU 1 has already been ejected
+        let mut states = vcpu_states(&[true, false, true, true]);
+        assert_eq!(mark_vcpus_removing(&mut states, &[2]).unwrap(), 2);
+        assert_eq!(removing(&states), [false, false, true, false]);
+    }
+
+    #[test]
+    fn test_remove_invalid_vcpus() {
+        let mut states = vcpu_states(&[true, true, false]);
+        for cpu_ids in [&[0][..], &[2], &[3], &[1, 0], &[1, u32::MAX]] {
+            assert!(matches!(
+                mark_vcpus_removing(&mut states, cpu_ids),
+                Err(Error::InvalidVcpuRemoval(_))
+            ));
+            // Nothing is marked when any vCPU can't be removed
+            assert_eq!(removing(&states), [false, false, false]);
+        }
+    }
+}
+
 #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
 #[cfg(test)]
 mod tests {
@@ -2587,7 +3803,7 @@ mod tests {
         let hv = hypervisor::new().unwrap();
         let vm = hv.create_vm().expect("new VM fd creation failed");
         let vcpu = vm.create_vcpu(0, None).unwrap();
-        setup_msrs(&vcpu).unwrap();
+        setup_msrs(&vcpu, None).unwrap();
 
         // This test will check against the last MSR entry configured (the tenth one).
         // See create_msr_entries for details.
@@ -2621,7 +3837,7 @@ mod tests {
             ..Default::default()
         };
 
-        setup_regs(&vcpu, expected_regs.rip).unwrap();
+        setup_regs(&vcpu, expected_regs.rip, arch::BootProtocol::PvhBoot).unwrap();
 
         let actual_regs: StandardRegisters = vcpu.get_regs().unwrap();
         assert_eq!(actual_regs, expected_regs);
@@ -2632,7 +3848,9 @@ mod tests {
 #[cfg(test)]
 mod tests {
     use arch::{aarch64::regs, layout};
-    use hypervisor::kvm::aarch64::is_system_register;
+    use hypervisor::kvm::aarch64::{
+        is_sve_register, is_system_register, reg_size, KVM_REG_ARM64_SVE_VLS,
+    };
     use hypervisor::kvm::kvm_bindings::{
         kvm_regs, kvm_vcpu_init, user_pt_regs, KVM_REG_ARM64, KVM_REG_ARM64_SYSREG,
         KVM_REG_ARM_CORE, KVM_REG_SIZE_U64,
@@ -2640,6 +3858,9 @@ mod tests {
     use hypervisor::{arm64_core_reg_id, offset_of};
     use std::mem;
 
+    use super::{append_pptt_caches, CacheInfo, CacheType};
+    use acpi_tables::sdt::Sdt;
+
     #[test]
     fn test_setup_regs() {
         let hv = hypervisor::new().unwrap();
@@ -2679,6 +3900,16 @@ mod tests {
         assert!(!is_system_register(regid));
         let regid = KVM_REG_ARM64 | KVM_REG_SIZE_U64 | KVM_REG_ARM64_SYSREG as u64;
         assert!(is_system_register(regid));
+        assert!(!is_system_register(KVM_REG_ARM64_SVE_VLS));
+    }
+
+    #[test]
+    fn test_is_sve_register() {
+        let regid = KVM_REG_ARM64 | KVM_REG_SIZE_U64 | KVM_REG_ARM64_SYSREG as u64;
+        assert!(!is_sve_register(regid));
+        assert!(is_sve_register(KVM_REG_ARM64_SVE_VLS));
+        assert_eq!(reg_size(KVM_REG_ARM64_SVE_VLS), 64);
+        assert_eq!(reg_size(regid), 8);
     }
 
     #[test]
@@ -2726,4 +3957,41 @@ mod tests {
         assert!(res.is_ok());
         assert!(vcpu.set_mp_state(res.unwrap()).is_ok());
     }
+
+    #[test]
+    fn test_pptt_caches() {
+        assert_eq!(CacheInfo::parse_size("64K"), Some(64 << 10));
+        assert_eq!(CacheInfo::parse_size("32M\n"), Some(32 << 20));
+        assert_eq!(CacheInfo::parse_size("512"), Some(512));
+        assert_eq!(CacheInfo::parse_size("K"), None);
+
+        let cache = |level, cache_type| CacheInfo {
+            level,
+            cache_type,
+            size: 64 << 10,
+            number_of_sets: 256,
+            associativity: 4,
+            line_size: 64,
+        };
+        let caches = [
+            cache(2, CacheType::Unified),
+            cache(1, CacheType::Data),
+            cache(1, CacheType::Instruction),
+        ];
+
+        let mut pptt = Sdt::new(*b"PPTT", 36, 2, *b"CLOUDH", *b"CHPPTT  ", 1);
+        let first_level = append_pptt_caches(&mut pptt, &caches);
+        // The L1 caches follow the L2 one, and both link to it.
+        assert_eq!(first_level, vec![60, 84]);
+        assert_eq!(pptt.len(), 36 + 3 * 24);
+        let table = pptt.as_slice();
+        let read_u32 =
+            |offset: usize| u32::from_le_bytes(table[offset..offset + 4].try_into().unwrap());
+        assert_eq!(read_u32(60 + 4), 0x7f);
+        assert_eq!(read_u32(60 + 8), 36);
+        assert_eq!(read_u32(84 + 8), 36);
+        assert_eq!(read_u32(36 + 8), 0);
+        // Read/write allocate instruction cache
+        assert_eq!(table[84 + 21], 0x7);
+    }
 }
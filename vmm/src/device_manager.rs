@@ -9,12 +9,15 @@
 // SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
 //
 
+use crate::cloud_init;
 use crate::config::{
-    ConsoleOutputMode, DeviceConfig, DiskConfig, FsConfig, NetConfig, PmemConfig, UserDeviceConfig,
+    ConsoleOutputMode, CryptoBackend, DeviceConfig, DiskConfig, FsConfig, FwCfgConfig, InputConfig,
+    InputKind, NetConfig, PmemConfig, ScsiConfig, ScsiLunConfig, SoundBackend, UserDeviceConfig,
     VdpaConfig, VhostMode, VmConfig, VsockConfig,
 };
 use crate::cpu::{CpuManager, CPU_MANAGER_ACPI_SIZE};
 use crate::device_tree::{DeviceNode, DeviceTree};
+use crate::interrupt::InterruptRate;
 use crate::interrupt::LegacyUserspaceInterruptManager;
 use crate::interrupt::MsiInterruptManager;
 use crate::memory_manager::{Error as MemoryManagerError, MemoryManager, MEMORY_MANAGER_ACPI_SIZE};
@@ -52,24 +55,26 @@ use devices::{
 };
 use hypervisor::{HypervisorType, IoEventAddress};
 use libc::{
-    cfmakeraw, isatty, tcgetattr, tcsetattr, termios, MAP_NORESERVE, MAP_PRIVATE, MAP_SHARED,
-    O_TMPFILE, PROT_READ, PROT_WRITE, TCSANOW,
+    cfmakeraw, isatty, tcgetattr, tcsetattr, termios, MAP_ANONYMOUS, MAP_NORESERVE, MAP_PRIVATE,
+    MAP_SHARED, O_TMPFILE, PROT_NONE, PROT_READ, PROT_WRITE, TCSANOW,
 };
 use pci::{
     DeviceRelocation, PciBarRegionType, PciBdf, PciDevice, VfioPciDevice, VfioUserDmaMapping,
     VfioUserPciDevice, VfioUserPciDeviceError,
 };
+use rate_limiter::RateLimiterGroup;
 use seccompiler::SeccompAction;
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs::{read_link, File, OpenOptions};
 use std::io::{self, stdout, Seek, SeekFrom};
 use std::mem::zeroed;
 use std::num::Wrapping;
 use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::result;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 use tracer::trace_scoped;
@@ -79,15 +84,19 @@ use virtio_devices::transport::{VirtioPciDevice, VirtioPciDeviceActivator};
 use virtio_devices::vhost_user::VhostUserConfig;
 use virtio_devices::{
     AccessPlatformMapping, ActivateError, VdpaDmaMapping, VirtioMemMappingSource,
+    VirtioSharedMemory, VirtioSharedMemoryList,
+};
+use virtio_devices::{
+    ConsoleInput, ConsoleInputKind, Endpoint, IommuMapping, ScsiLun, ScsiLunBackend,
 };
-use virtio_devices::{Endpoint, IommuMapping};
 use vm_allocator::{AddressAllocator, SystemAllocator};
 use vm_device::dma_mapping::vfio::VfioDmaMapping;
 use vm_device::dma_mapping::ExternalDmaMapping;
 use vm_device::interrupt::{
-    InterruptIndex, InterruptManager, LegacyIrqGroupConfig, MsiIrqGroupConfig,
+    InterruptIndex, InterruptManager, InterruptSourceGroup, LegacyIrqGroupConfig, MsiIrqGroupConfig,
 };
 use vm_device::{Bus, BusDevice, Resource};
+use vm_memory::bitmap::AtomicBitmap;
 use vm_memory::guest_memory::FileOffset;
 use vm_memory::GuestMemoryRegion;
 use vm_memory::{Address, GuestAddress, GuestUsize, MmapRegion};
@@ -95,7 +104,7 @@ use vm_memory::{Address, GuestAddress, GuestUsize, MmapRegion};
 use vm_memory::{GuestAddressSpace, GuestMemory};
 use vm_migration::{
     protocol::MemoryRangeTable, snapshot_from_id, versioned_state_from_id, Migratable,
-    MigratableError, Pausable, Snapshot, SnapshotData, Snapshottable, Transportable,
+    MigratableError, Pausable, Snapshot, SnapshotData, Snapshottable, StateUpgrade, Transportable,
 };
 use vm_virtio::AccessPlatform;
 use vm_virtio::VirtioDeviceType;
@@ -104,15 +113,30 @@ use vmm_sys_util::eventfd::EventFd;
 #[cfg(target_arch = "aarch64")]
 const MMIO_LEN: u64 = 0x1000;
 
+// Same I/O port as QEMU, where firmware and guests look for the device
+#[cfg(target_arch = "x86_64")]
+const FW_CFG_PORT_START: u64 = 0x510;
+
+// Same I/O port as QEMU, where guests look for the pvpanic device
+#[cfg(target_arch = "x86_64")]
+const PVPANIC_PORT: u64 = 0x505;
+
+// Well-known fw_cfg file the Ignition config is fetched from
+const FW_CFG_IGNITION_FILE: &str = "opt/com.coreos/config";
+
 // Singleton devices / devices the user cannot name
 #[cfg(target_arch = "x86_64")]
 const IOAPIC_DEVICE_NAME: &str = "__ioapic";
 const SERIAL_DEVICE_NAME: &str = "__serial";
+#[cfg(target_arch = "x86_64")]
+const I8042_DEVICE_NAME: &str = "__i8042";
+const GED_DEVICE_NAME: &str = "__ged";
 #[cfg(target_arch = "aarch64")]
 const GPIO_DEVICE_NAME: &str = "__gpio";
 const RNG_DEVICE_NAME: &str = "__rng";
 const IOMMU_DEVICE_NAME: &str = "__iommu";
 const BALLOON_DEVICE_NAME: &str = "__balloon";
+const CLOUD_INIT_DEVICE_NAME: &str = "__cloud_init";
 const CONSOLE_DEVICE_NAME: &str = "__console";
 
 // Devices that the user may name and for which we generate
@@ -124,6 +148,12 @@ const PMEM_DEVICE_NAME_PREFIX: &str = "_pmem";
 const VDPA_DEVICE_NAME_PREFIX: &str = "_vdpa";
 const VSOCK_DEVICE_NAME_PREFIX: &str = "_vsock";
 const WATCHDOG_DEVICE_NAME: &str = "__watchdog";
+const GPU_DEVICE_NAME: &str = "__gpu";
+const SOUND_DEVICE_NAME: &str = "__sound";
+const CRYPTO_DEVICE_NAME: &str = "__crypto";
+const CHANNELS_DEVICE_NAME: &str = "__channels";
+const INPUT_DEVICE_NAME_PREFIX: &str = "_input";
+const SCSI_DEVICE_NAME_PREFIX: &str = "_scsi";
 const VFIO_DEVICE_NAME_PREFIX: &str = "_vfio";
 const VFIO_USER_DEVICE_NAME_PREFIX: &str = "_vfio_user";
 const VIRTIO_PCI_DEVICE_NAME_PREFIX: &str = "_virtio-pci";
@@ -143,6 +173,9 @@ pub enum DeviceManagerError {
     /// Cannot create virtio-blk device
     CreateVirtioBlock(io::Error),
 
+    /// Cannot create the cloud-init seed
+    CreateCloudInitSeed(io::Error),
+
     /// Cannot create virtio-net device
     CreateVirtioNet(virtio_devices::net::Error),
 
@@ -191,6 +224,30 @@ pub enum DeviceManagerError {
     /// Cannot create virtio-watchdog device
     CreateVirtioWatchdog(io::Error),
 
+    /// Cannot create virtio-gpu device
+    CreateVirtioGpu(io::Error),
+
+    /// Cannot create virtio-sound device
+    CreateVirtioSound(io::Error),
+
+    /// Cannot create virtio-crypto device
+    CreateVirtioCrypto(io::Error),
+
+    /// Cannot create virtio-console device for the channels
+    CreateVirtioChannels(io::Error),
+
+    /// Cannot create virtio-input device
+    CreateVirtioInput(io::Error),
+
+    /// Cannot create virtio-scsi device
+    CreateVirtioScsi(io::Error),
+
+    /// Cannot open the image or device of a SCSI logical unit
+    ScsiLunOpen(io::Error),
+
+    /// Failed to change the medium of a SCSI logical unit
+    ScsiChangeMedia(virtio_devices::MediaError),
+
     /// Failed to parse disk image format
     DetectImageType(io::Error),
 
@@ -254,6 +311,9 @@ pub enum DeviceManagerError {
     /// Error creating console pty
     ConsolePtyOpen(io::Error),
 
+    /// Error creating channel pty
+    ChannelPtyOpen(io::Error),
+
     /// Error setting pty raw mode
     SetPtyRaw(vmm_sys_util::errno::Error),
 
@@ -386,6 +446,9 @@ pub enum DeviceManagerError {
     /// Expected resources for virtio-pmem could not be found.
     MissingVirtioPmemResources,
 
+    /// Expected resources for virtio-fs could not be found.
+    MissingVirtioFsResources,
+
     /// Missing PCI b/d/f from the DeviceNode.
     MissingDeviceNodePciBdf,
 
@@ -408,6 +471,20 @@ pub enum DeviceManagerError {
     #[cfg(target_arch = "aarch64")]
     AArch64PowerButtonNotification(devices::legacy::GpioDeviceError),
 
+    /// Failed to open the UEFI variable store file
+    #[cfg(target_arch = "aarch64")]
+    OpenFirmwareVars(io::Error),
+
+    /// Failed to create the UEFI variable store flash
+    #[cfg(target_arch = "aarch64")]
+    CreatePflash(devices::legacy::PflashError),
+
+    /// Failed to read a file exposed through fw_cfg
+    ReadFwCfgFile(io::Error),
+
+    /// Failed to add a file to the fw_cfg device
+    AddFwCfgFile(devices::legacy::FwCfgError),
+
     /// Failed to set O_DIRECT flag to file descriptor
     SetDirectIo,
 
@@ -470,6 +547,9 @@ pub enum DeviceManagerError {
 
     /// Failed retrieving device state from snapshot
     RestoreGetState(MigratableError),
+
+    /// No device available to inject input events into
+    NoInputDevice,
 }
 pub type DeviceManagerResult<T> = result::Result<T, DeviceManagerError>;
 
@@ -770,6 +850,8 @@ struct DeviceManagerState {
     device_id_cnt: Wrapping<usize>,
 }
 
+impl StateUpgrade for DeviceManagerState {}
+
 #[derive(Debug)]
 pub struct PtyPair {
     pub main: File,
@@ -900,6 +982,10 @@ pub struct DeviceManager {
     // Exit event
     exit_evt: EventFd,
     reset_evt: EventFd,
+    // Guest panic event, raised by the pvpanic device
+    panic_evt: EventFd,
+    // Watchdog expiry event
+    watchdog_evt: EventFd,
 
     #[cfg(target_arch = "aarch64")]
     id_to_dev_info: HashMap<(DeviceType, String), MmioDeviceInfo>,
@@ -928,6 +1014,14 @@ pub struct DeviceManager {
     // GPIO device for AArch64
     gpio_device: Option<Arc<Mutex<devices::legacy::Gpio>>>,
 
+    #[cfg(target_arch = "x86_64")]
+    // i8042 controller, also emulating a PS/2 keyboard and mouse if enabled
+    i8042: Option<Arc<Mutex<devices::legacy::I8042Device>>>,
+
+    #[cfg(target_arch = "x86_64")]
+    // IRQ assigned to the PS/2 mouse
+    ps2_aux_irq: Option<u32>,
+
     // Flag to force setting the iommu on virtio devices
     force_iommu: bool,
 
@@ -946,6 +1040,26 @@ pub struct DeviceManager {
     // Addresses for ACPI platform devices e.g. ACPI PM timer, sleep/reset registers
     acpi_platform_addresses: AcpiPlatformAddresses,
 
+    // Interrupt groups of the legacy devices, along with the device identifier
+    legacy_interrupt_groups: Vec<(String, Arc<dyn InterruptSourceGroup>)>,
+
+    // Interrupt rate of each device, computed from the interrupt counts
+    // sampled whenever the counters are retrieved
+    interrupt_rates: Mutex<HashMap<String, InterruptRate>>,
+
+    // Rate limiter groups shared by the block and network devices
+    rate_limit_groups: HashMap<String, RateLimiterGroup>,
+
+    // Set when the guest entered the S4 sleep state
+    hibernated: Arc<AtomicBool>,
+
+    // Keyboard and pointer events of the remote console, shared between the
+    // GPU device receiving them and the input devices fed from them
+    console_input: ConsoleInput,
+
+    // virtio-scsi controllers, whose media can be changed at runtime
+    scsi_controllers: BTreeMap<String, Arc<Mutex<virtio_devices::Scsi>>>,
+
     snapshot: Option<Snapshot>,
 }
 
@@ -961,6 +1075,8 @@ impl DeviceManager {
         cpu_manager: Arc<Mutex<CpuManager>>,
         exit_evt: EventFd,
         reset_evt: EventFd,
+        panic_evt: EventFd,
+        watchdog_evt: EventFd,
         seccomp_action: SeccompAction,
         numa_nodes: NumaNodes,
         activate_evt: &EventFd,
@@ -1076,6 +1192,15 @@ impl DeviceManager {
             cpu_manager.lock().unwrap().set_acpi_address(acpi_address);
         }
 
+        let rate_limit_groups = config
+            .lock()
+            .unwrap()
+            .rate_limit_groups
+            .iter()
+            .flatten()
+            .map(|group| (group.id.clone(), group.rate_limiter_config.into()))
+            .collect();
+
         let device_manager = DeviceManager {
             hypervisor_type,
             address_manager: Arc::clone(&address_manager),
@@ -1101,6 +1226,8 @@ impl DeviceManager {
             device_tree,
             exit_evt,
             reset_evt,
+            panic_evt,
+            watchdog_evt,
             #[cfg(target_arch = "aarch64")]
             id_to_dev_info: HashMap::new(),
             seccomp_action,
@@ -1118,12 +1245,22 @@ impl DeviceManager {
             virtio_mem_devices: Vec::new(),
             #[cfg(target_arch = "aarch64")]
             gpio_device: None,
+            #[cfg(target_arch = "x86_64")]
+            i8042: None,
+            #[cfg(target_arch = "x86_64")]
+            ps2_aux_irq: None,
             force_iommu,
             io_uring_supported: None,
             boot_id_list,
             timestamp,
             pending_activations: Arc::new(Mutex::new(Vec::default())),
             acpi_platform_addresses: AcpiPlatformAddresses::default(),
+            legacy_interrupt_groups: Vec::new(),
+            interrupt_rates: Mutex::new(HashMap::new()),
+            rate_limit_groups,
+            hibernated: Arc::new(AtomicBool::new(false)),
+            console_input: ConsoleInput::default(),
+            scsi_controllers: BTreeMap::new(),
             snapshot,
         };
 
@@ -1141,6 +1278,10 @@ impl DeviceManager {
         Ok(device_manager)
     }
 
+    pub fn hibernated(&self) -> bool {
+        self.hibernated.load(Ordering::SeqCst)
+    }
+
     pub fn serial_pty(&self) -> Option<PtyPair> {
         self.serial_pty
             .as_ref()
@@ -1197,6 +1338,7 @@ impl DeviceManager {
 
         #[cfg(target_arch = "x86_64")]
         self.add_legacy_devices(
+            &legacy_interrupt_manager,
             self.reset_evt
                 .try_clone()
                 .map_err(DeviceManagerError::EventFd)?,
@@ -1205,6 +1347,15 @@ impl DeviceManager {
         #[cfg(target_arch = "aarch64")]
         self.add_legacy_devices(&legacy_interrupt_manager)?;
 
+        let fw_cfg = self.config.lock().unwrap().fw_cfg.clone();
+        if let Some(fw_cfg) = fw_cfg {
+            self.add_fw_cfg_device(&fw_cfg)?;
+        }
+
+        if self.config.lock().unwrap().pvpanic.is_some() {
+            self.add_pvpanic_device()?;
+        }
+
         {
             self.ged_notification_device = self.add_acpi_devices(
                 &legacy_interrupt_manager,
@@ -1251,7 +1402,7 @@ impl DeviceManager {
     fn get_msi_iova_space(&mut self) -> (u64, u64) {
         #[cfg(target_arch = "aarch64")]
         {
-            let vcpus = self.config.lock().unwrap().cpus.boot_vcpus;
+            let vcpus = self.config.lock().unwrap().cpus.max_vcpus;
             let vgic_config = gic::Gic::create_default_config(vcpus.into());
             (
                 vgic_config.msi_addr,
@@ -1372,7 +1523,7 @@ impl DeviceManager {
     ) -> DeviceManagerResult<Arc<Mutex<dyn InterruptController>>> {
         let interrupt_controller: Arc<Mutex<gic::Gic>> = Arc::new(Mutex::new(
             gic::Gic::new(
-                self.config.lock().unwrap().cpus.boot_vcpus,
+                self.config.lock().unwrap().cpus.max_vcpus,
                 Arc::clone(&self.msi_interrupt_manager),
                 self.address_manager.vm.clone(),
             )
@@ -1385,12 +1536,13 @@ impl DeviceManager {
         let id = String::from(gic::GIC_SNAPSHOT_ID);
         if let Some(vgic_snapshot) = snapshot_from_id(self.snapshot.as_ref(), &id) {
             // PMU support is optional. Nothing should be impacted if the PMU initialization failed.
-            if self
-                .cpu_manager
-                .lock()
-                .unwrap()
-                .init_pmu(arch::aarch64::fdt::AARCH64_PMU_IRQ + 16)
-                .is_err()
+            if self.config.lock().unwrap().cpus.features.pmu
+                && self
+                    .cpu_manager
+                    .lock()
+                    .unwrap()
+                    .init_pmu(arch::aarch64::fdt::AARCH64_PMU_IRQ + 16)
+                    .is_err()
             {
                 info!("Failed to initialize PMU");
             }
@@ -1465,7 +1617,9 @@ impl DeviceManager {
         exit_evt: EventFd,
     ) -> DeviceManagerResult<Option<Arc<Mutex<devices::AcpiGedDevice>>>> {
         let shutdown_device = Arc::new(Mutex::new(devices::AcpiShutdownDevice::new(
-            exit_evt, reset_evt,
+            exit_evt,
+            reset_evt,
+            self.hibernated.clone(),
         )));
 
         self.bus_devices
@@ -1507,6 +1661,8 @@ impl DeviceManager {
                 irq: ged_irq as InterruptIndex,
             })
             .map_err(DeviceManagerError::CreateInterruptGroup)?;
+        self.legacy_interrupt_groups
+            .push((GED_DEVICE_NAME.to_string(), interrupt_group.clone()));
         let ged_address = self
             .address_manager
             .allocator
@@ -1563,10 +1719,52 @@ impl DeviceManager {
     }
 
     #[cfg(target_arch = "x86_64")]
-    fn add_legacy_devices(&mut self, reset_evt: EventFd) -> DeviceManagerResult<()> {
-        // Add a shutdown device (i8042)
+    fn add_legacy_devices(
+        &mut self,
+        interrupt_manager: &Arc<dyn InterruptManager<GroupConfig = LegacyIrqGroupConfig>>,
+        reset_evt: EventFd,
+    ) -> DeviceManagerResult<()> {
+        // Add a shutdown device (i8042), which also emulates a PS/2 keyboard
+        // and mouse if requested.
+        let ps2_interrupts = if self.config.lock().unwrap().is_ps2_enabled() {
+            // The keyboard is tied to IRQ #1. The mouse usually gets IRQ #12,
+            // but it is part of the dynamically allocated range, so it is
+            // given whatever IRQ is available and reported through ACPI.
+            let keyboard = interrupt_manager
+                .create_group(LegacyIrqGroupConfig { irq: 1 })
+                .map_err(DeviceManagerError::CreateInterruptGroup)?;
+
+            let aux_irq = self
+                .address_manager
+                .allocator
+                .lock()
+                .unwrap()
+                .allocate_irq()
+                .ok_or(DeviceManagerError::AllocateIrq)?;
+            let aux = interrupt_manager
+                .create_group(LegacyIrqGroupConfig {
+                    irq: aux_irq as InterruptIndex,
+                })
+                .map_err(DeviceManagerError::CreateInterruptGroup)?;
+            self.ps2_aux_irq = Some(aux_irq);
+
+            self.legacy_interrupt_groups
+                .push((I8042_DEVICE_NAME.to_string(), keyboard.clone()));
+            self.legacy_interrupt_groups
+                .push((I8042_DEVICE_NAME.to_string(), aux.clone()));
+
+            Some(devices::legacy::Ps2Interrupts { keyboard, aux })
+        } else {
+            None
+        };
+
+        let id = I8042_DEVICE_NAME.to_string();
         let i8042 = Arc::new(Mutex::new(devices::legacy::I8042Device::new(
+            id.clone(),
             reset_evt.try_clone().unwrap(),
+            ps2_interrupts,
+            versioned_state_from_id(self.snapshot.as_ref(), id.as_str())
+                .map_err(DeviceManagerError::RestoreGetState)?,
         )));
 
         self.bus_devices
@@ -1574,8 +1772,15 @@ impl DeviceManager {
 
         self.address_manager
             .io_bus
-            .insert(i8042, 0x61, 0x4)
+            .insert(i8042.clone(), 0x60, 0x5)
             .map_err(DeviceManagerError::BusError)?;
+
+        self.device_tree
+            .lock()
+            .unwrap()
+            .insert(id.clone(), device_node!(id, i8042));
+
+        self.i8042 = Some(i8042);
         {
             // Add a CMOS emulated device
             let mem_size = self
@@ -1587,7 +1792,14 @@ impl DeviceManager {
                 .last_addr()
                 .0
                 + 1;
-            let mem_below_4g = std::cmp::min(arch::layout::MEM_32BIT_RESERVED_START.0, mem_size);
+            let mem_32bit_reserved_start = self
+                .address_manager
+                .allocator
+                .lock()
+                .unwrap()
+                .mmio_hole_base()
+                .0;
+            let mem_below_4g = std::cmp::min(mem_32bit_reserved_start, mem_size);
             let mem_above_4g = mem_size.saturating_sub(arch::layout::RAM_64BIT_START.0);
 
             let cmos = Arc::new(Mutex::new(devices::legacy::Cmos::new(
@@ -1683,6 +1895,8 @@ impl DeviceManager {
                 irq: gpio_irq as InterruptIndex,
             })
             .map_err(DeviceManagerError::CreateInterruptGroup)?;
+        self.legacy_interrupt_groups
+            .push((id.clone(), interrupt_group.clone()));
 
         let gpio_device = Arc::new(Mutex::new(devices::legacy::Gpio::new(
             id.clone(),
@@ -1717,6 +1931,186 @@ impl DeviceManager {
             .unwrap()
             .insert(id.clone(), device_node!(id, gpio_device));
 
+        // Add the flash holding the UEFI variables
+        let firmware_vars = self
+            .config
+            .lock()
+            .unwrap()
+            .payload
+            .as_ref()
+            .and_then(|p| p.firmware_vars.clone());
+        if let Some(firmware_vars) = firmware_vars {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(firmware_vars)
+                .map_err(DeviceManagerError::OpenFirmwareVars)?;
+            let pflash = devices::legacy::Pflash::new(file, arch::layout::UEFI_VARS_SIZE)
+                .map_err(DeviceManagerError::CreatePflash)?;
+            let len = pflash.size();
+            let pflash_device = Arc::new(Mutex::new(pflash));
+
+            self.bus_devices
+                .push(Arc::clone(&pflash_device) as Arc<Mutex<dyn BusDevice>>);
+
+            let addr = arch::layout::UEFI_VARS_START;
+
+            self.address_manager
+                .mmio_bus
+                .insert(pflash_device, addr.0, len)
+                .map_err(DeviceManagerError::BusError)?;
+
+            self.id_to_dev_info.insert(
+                (DeviceType::Pflash, "pflash".to_string()),
+                MmioDeviceInfo {
+                    addr: addr.0,
+                    len,
+                    irq: 0,
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    fn add_fw_cfg_device(&mut self, fw_cfg_config: &FwCfgConfig) -> DeviceManagerResult<()> {
+        let mut fw_cfg =
+            devices::legacy::FwCfg::new(self.memory_manager.lock().unwrap().guest_memory());
+
+        let config = self.config.lock().unwrap().clone();
+        if fw_cfg_config.payload {
+            if let Some(payload) = &config.payload {
+                if let Some(kernel) = &payload.kernel {
+                    let kernel =
+                        std::fs::read(kernel).map_err(DeviceManagerError::ReadFwCfgFile)?;
+                    let (setup, kernel) = split_kernel_setup(kernel);
+                    fw_cfg.add_kernel(setup, kernel);
+                }
+                if let Some(initramfs) = &payload.initramfs {
+                    fw_cfg.add_initramfs(
+                        std::fs::read(initramfs).map_err(DeviceManagerError::ReadFwCfgFile)?,
+                    );
+                }
+                if let Some(cmdline) = &payload.cmdline {
+                    fw_cfg.add_cmdline(cmdline);
+                }
+            }
+        }
+
+        if let Some(ignition) = &config.ignition {
+            let data = std::fs::read(&ignition.path).map_err(DeviceManagerError::ReadFwCfgFile)?;
+            fw_cfg
+                .add_file(FW_CFG_IGNITION_FILE, data)
+                .map_err(DeviceManagerError::AddFwCfgFile)?;
+        }
+
+        for file in fw_cfg_config.files.iter().flatten() {
+            let data = std::fs::read(&file.path).map_err(DeviceManagerError::ReadFwCfgFile)?;
+            fw_cfg
+                .add_file(&file.name, data)
+                .map_err(DeviceManagerError::AddFwCfgFile)?;
+        }
+
+        let fw_cfg = Arc::new(Mutex::new(fw_cfg));
+        self.bus_devices
+            .push(Arc::clone(&fw_cfg) as Arc<Mutex<dyn BusDevice>>);
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            self.address_manager
+                .allocator
+                .lock()
+                .unwrap()
+                .allocate_io_addresses(
+                    Some(GuestAddress(FW_CFG_PORT_START)),
+                    devices::legacy::FW_CFG_REGISTERS_SIZE,
+                    None,
+                )
+                .ok_or(DeviceManagerError::AllocateIoPort)?;
+
+            self.address_manager
+                .io_bus
+                .insert(
+                    fw_cfg,
+                    FW_CFG_PORT_START,
+                    devices::legacy::FW_CFG_REGISTERS_SIZE,
+                )
+                .map_err(DeviceManagerError::BusError)?;
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        {
+            let addr = arch::layout::LEGACY_FW_CFG_MAPPED_IO_START;
+
+            self.address_manager
+                .mmio_bus
+                .insert(fw_cfg, addr.0, devices::legacy::FW_CFG_REGISTERS_SIZE)
+                .map_err(DeviceManagerError::BusError)?;
+
+            self.id_to_dev_info.insert(
+                (DeviceType::FwCfg, "fw_cfg".to_string()),
+                MmioDeviceInfo {
+                    addr: addr.0,
+                    len: devices::legacy::FW_CFG_REGISTERS_SIZE,
+                    irq: 0,
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    fn add_pvpanic_device(&mut self) -> DeviceManagerResult<()> {
+        let pvpanic = Arc::new(Mutex::new(devices::legacy::PvPanic::new(
+            self.panic_evt
+                .try_clone()
+                .map_err(DeviceManagerError::EventFd)?,
+        )));
+        self.bus_devices
+            .push(Arc::clone(&pvpanic) as Arc<Mutex<dyn BusDevice>>);
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            self.address_manager
+                .allocator
+                .lock()
+                .unwrap()
+                .allocate_io_addresses(
+                    Some(GuestAddress(PVPANIC_PORT)),
+                    devices::legacy::PVPANIC_REGISTER_SIZE,
+                    None,
+                )
+                .ok_or(DeviceManagerError::AllocateIoPort)?;
+
+            self.address_manager
+                .io_bus
+                .insert(
+                    pvpanic,
+                    PVPANIC_PORT,
+                    devices::legacy::PVPANIC_REGISTER_SIZE,
+                )
+                .map_err(DeviceManagerError::BusError)?;
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        {
+            let addr = arch::layout::LEGACY_PVPANIC_MAPPED_IO_START;
+
+            self.address_manager
+                .mmio_bus
+                .insert(pvpanic, addr.0, devices::legacy::PVPANIC_REGISTER_SIZE)
+                .map_err(DeviceManagerError::BusError)?;
+
+            self.id_to_dev_info.insert(
+                (DeviceType::PvPanic, "pvpanic".to_string()),
+                MmioDeviceInfo {
+                    addr: addr.0,
+                    len: devices::legacy::PVPANIC_REGISTER_SIZE,
+                    irq: 0,
+                },
+            );
+        }
+
         Ok(())
     }
 
@@ -1736,6 +2130,8 @@ impl DeviceManager {
                 irq: serial_irq as InterruptIndex,
             })
             .map_err(DeviceManagerError::CreateInterruptGroup)?;
+        self.legacy_interrupt_groups
+            .push((id.clone(), interrupt_group.clone()));
 
         let serial = Arc::new(Mutex::new(Serial::new(
             id.clone(),
@@ -1792,6 +2188,8 @@ impl DeviceManager {
                 irq: serial_irq as InterruptIndex,
             })
             .map_err(DeviceManagerError::CreateInterruptGroup)?;
+        self.legacy_interrupt_groups
+            .push((id.clone(), interrupt_group.clone()));
 
         let serial = Arc::new(Mutex::new(devices::legacy::Pl011::new(
             id.clone(),
@@ -2107,6 +2505,24 @@ impl DeviceManager {
         // Add virtio-watchdog device
         devices.append(&mut self.make_virtio_watchdog_devices()?);
 
+        // Add virtio-gpu if required
+        devices.append(&mut self.make_virtio_gpu_devices()?);
+
+        // Add virtio-sound if required
+        devices.append(&mut self.make_virtio_sound_devices()?);
+
+        // Add virtio-input if required
+        devices.append(&mut self.make_virtio_input_devices()?);
+
+        // Add the channels virtio-console if required
+        devices.append(&mut self.make_virtio_channels_devices()?);
+
+        // Add virtio-scsi if required
+        devices.append(&mut self.make_virtio_scsi_devices()?);
+
+        // Add virtio-crypto if required
+        devices.append(&mut self.make_virtio_crypto_devices()?);
+
         // Add vDPA devices if required
         devices.append(&mut self.make_vdpa_devices()?);
 
@@ -2124,6 +2540,30 @@ impl DeviceManager {
         supported
     }
 
+    // Host CPUs the thread of each queue is pinned onto, the queues being
+    // assigned to the given I/O threads in a round robin fashion.
+    fn iothread_queue_affinity(
+        &self,
+        iothreads: &Option<Vec<String>>,
+        num_queues: usize,
+    ) -> BTreeMap<u16, Vec<usize>> {
+        let mut queue_affinity = BTreeMap::new();
+        let iothreads = match iothreads {
+            Some(iothreads) if !iothreads.is_empty() => iothreads,
+            _ => return queue_affinity,
+        };
+
+        let config = self.config.lock().unwrap();
+        for queue in 0..num_queues {
+            let id = &iothreads[queue % iothreads.len()];
+            if let Some(iothread) = config.iothreads.iter().flatten().find(|t| &t.id == id) {
+                queue_affinity.insert(queue as u16, iothread.host_cpus.clone());
+            }
+        }
+
+        queue_affinity
+    }
+
     fn make_virtio_block_device(
         &mut self,
         disk_cfg: &mut DiskConfig,
@@ -2252,6 +2692,10 @@ impl DeviceManager {
                     disk_cfg.queue_size,
                     self.seccomp_action.clone(),
                     disk_cfg.rate_limiter_config,
+                    disk_cfg
+                        .rate_limit_group
+                        .as_ref()
+                        .and_then(|id| self.rate_limit_groups.get(id).cloned()),
                     self.exit_evt
                         .try_clone()
                         .map_err(DeviceManagerError::EventFd)?,
@@ -2259,6 +2703,7 @@ impl DeviceManager {
                         .map(|s| s.to_versioned_state())
                         .transpose()
                         .map_err(DeviceManagerError::RestoreGetState)?,
+                    self.iothread_queue_affinity(&disk_cfg.iothreads, disk_cfg.num_queues),
                 )
                 .map_err(DeviceManagerError::CreateVirtioBlock)?,
             ));
@@ -2297,6 +2742,21 @@ impl DeviceManager {
         }
         self.config.lock().unwrap().disks = block_devices;
 
+        let cloud_init = self.config.lock().unwrap().cloud_init.clone();
+        if let Some(cloud_init_cfg) = &cloud_init {
+            // The seed only lives in memory, the disk is backed by the file
+            // descriptor until the device opens its own.
+            let seed = cloud_init::create_seed(cloud_init_cfg)
+                .map_err(DeviceManagerError::CreateCloudInitSeed)?;
+            let mut disk_cfg = DiskConfig {
+                path: Some(PathBuf::from(format!("/proc/self/fd/{}", seed.as_raw_fd()))),
+                readonly: true,
+                id: Some(CLOUD_INIT_DEVICE_NAME.to_owned()),
+                ..Default::default()
+            };
+            devices.push(self.make_virtio_block_device(&mut disk_cfg)?);
+        }
+
         Ok(devices)
     }
 
@@ -2344,6 +2804,7 @@ impl DeviceManager {
                         .map_err(DeviceManagerError::RestoreGetState)?,
                     net_cfg.offload_tso,
                     net_cfg.offload_ufo,
+                    net_cfg.offload_uso,
                     net_cfg.offload_csum,
                 ) {
                     Ok(vun_device) => vun_device,
@@ -2362,6 +2823,13 @@ impl DeviceManager {
                 .map(|s| s.to_versioned_state())
                 .transpose()
                 .map_err(DeviceManagerError::RestoreGetState)?;
+            let rate_limiter_group = net_cfg
+                .rate_limit_group
+                .as_ref()
+                .and_then(|id| self.rate_limit_groups.get(id).cloned());
+            // Each RX/TX queue pair is handled by a single thread
+            let queue_affinity =
+                self.iothread_queue_affinity(&net_cfg.iothreads, net_cfg.num_queues / 2);
 
             let virtio_net = if let Some(ref tap_if_name) = net_cfg.tap {
                 Arc::new(Mutex::new(
@@ -2378,13 +2846,18 @@ impl DeviceManager {
                         net_cfg.queue_size,
                         self.seccomp_action.clone(),
                         net_cfg.rate_limiter_config,
+                        rate_limiter_group,
                         self.exit_evt
                             .try_clone()
                             .map_err(DeviceManagerError::EventFd)?,
                         state,
                         net_cfg.offload_tso,
                         net_cfg.offload_ufo,
+                        net_cfg.offload_uso,
                         net_cfg.offload_csum,
+                        net_cfg.rss,
+                        net_cfg.vhost,
+                        queue_affinity,
                     )
                     .map_err(DeviceManagerError::CreateVirtioNet)?,
                 ))
@@ -2399,13 +2872,18 @@ impl DeviceManager {
                         net_cfg.queue_size,
                         self.seccomp_action.clone(),
                         net_cfg.rate_limiter_config,
+                        rate_limiter_group,
                         self.exit_evt
                             .try_clone()
                             .map_err(DeviceManagerError::EventFd)?,
                         state,
                         net_cfg.offload_tso,
                         net_cfg.offload_ufo,
+                        net_cfg.offload_uso,
                         net_cfg.offload_csum,
+                        net_cfg.rss,
+                        net_cfg.vhost,
+                        queue_affinity,
                     )
                     .map_err(DeviceManagerError::CreateVirtioNet)?,
                 ))
@@ -2424,13 +2902,18 @@ impl DeviceManager {
                         net_cfg.queue_size,
                         self.seccomp_action.clone(),
                         net_cfg.rate_limiter_config,
+                        rate_limiter_group,
                         self.exit_evt
                             .try_clone()
                             .map_err(DeviceManagerError::EventFd)?,
                         state,
                         net_cfg.offload_tso,
                         net_cfg.offload_ufo,
+                        net_cfg.offload_uso,
                         net_cfg.offload_csum,
+                        net_cfg.rss,
+                        net_cfg.vhost,
+                        queue_affinity,
                     )
                     .map_err(DeviceManagerError::CreateVirtioNet)?,
                 ))
@@ -2534,6 +3017,12 @@ impl DeviceManager {
         let mut node = device_node!(id);
 
         if let Some(fs_socket) = fs_cfg.socket.to_str() {
+            let cache = if fs_cfg.dax {
+                Some(self.make_virtio_fs_cache(&id, fs_cfg, &mut node)?)
+            } else {
+                None
+            };
+
             let virtio_fs_device = Arc::new(Mutex::new(
                 virtio_devices::vhost_user::Fs::new(
                     id.clone(),
@@ -2541,7 +3030,7 @@ impl DeviceManager {
                     &fs_cfg.tag,
                     fs_cfg.num_queues,
                     fs_cfg.queue_size,
-                    None,
+                    cache,
                     self.seccomp_action.clone(),
                     self.exit_evt
                         .try_clone()
@@ -2570,50 +3059,29 @@ impl DeviceManager {
         }
     }
 
-    fn make_virtio_fs_devices(&mut self) -> DeviceManagerResult<Vec<MetaVirtioDevice>> {
-        let mut devices = Vec::new();
-
-        let mut fs_devices = self.config.lock().unwrap().fs.clone();
-        if let Some(fs_list_cfg) = &mut fs_devices {
-            for fs_cfg in fs_list_cfg.iter_mut() {
-                devices.push(self.make_virtio_fs_device(fs_cfg)?);
-            }
-        }
-        self.config.lock().unwrap().fs = fs_devices;
-
-        Ok(devices)
-    }
-
-    fn make_virtio_pmem_device(
+    // Sets up the DAX window of a virtio-fs device, where the backend maps
+    // the content of the files on demand. The window is reserved as an
+    // inaccessible anonymous mapping until then.
+    fn make_virtio_fs_cache(
         &mut self,
-        pmem_cfg: &mut PmemConfig,
-    ) -> DeviceManagerResult<MetaVirtioDevice> {
-        let id = if let Some(id) = &pmem_cfg.id {
-            id.clone()
-        } else {
-            let id = self.next_device_name(PMEM_DEVICE_NAME_PREFIX)?;
-            pmem_cfg.id = Some(id.clone());
-            id
-        };
-
-        info!("Creating virtio-pmem device: {:?}", pmem_cfg);
-
-        let mut node = device_node!(id);
-
+        id: &str,
+        fs_cfg: &FsConfig,
+        node: &mut DeviceNode,
+    ) -> DeviceManagerResult<(VirtioSharedMemoryList, MmapRegion<AtomicBitmap>)> {
         // Look for the id in the device tree. If it can be found, that means
         // the device is being restored, otherwise it's created from scratch.
-        let region_range = if let Some(node) = self.device_tree.lock().unwrap().get(&id) {
-            info!("Restoring virtio-pmem {} resources", id);
+        let cache_range = if let Some(node) = self.device_tree.lock().unwrap().get(id) {
+            info!("Restoring virtio-fs {} resources", id);
 
-            let mut region_range: Option<(u64, u64)> = None;
+            let mut cache_range: Option<(u64, u64)> = None;
             for resource in node.resources.iter() {
                 match resource {
                     Resource::MmioAddressRange { base, size } => {
-                        if region_range.is_some() {
+                        if cache_range.is_some() {
                             return Err(DeviceManagerError::ResourceAlreadyExists);
                         }
 
-                        region_range = Some((*base, *size));
+                        cache_range = Some((*base, *size));
                     }
                     _ => {
                         error!("Unexpected resource {:?} for {}", resource, id);
@@ -2621,26 +3089,152 @@ impl DeviceManager {
                 }
             }
 
-            if region_range.is_none() {
-                return Err(DeviceManagerError::MissingVirtioPmemResources);
+            if cache_range.is_none() {
+                return Err(DeviceManagerError::MissingVirtioFsResources);
             }
 
-            region_range
+            cache_range
         } else {
             None
         };
 
-        let (custom_flags, set_len) = if pmem_cfg.file.is_dir() {
-            if pmem_cfg.size.is_none() {
-                return Err(DeviceManagerError::PmemWithDirectorySizeMissing);
-            }
-            (O_TMPFILE, true)
-        } else {
-            (0, false)
-        };
+        let (cache_base, cache_size) = if let Some((base, size)) = cache_range {
+            // The memory needs to be 2MiB aligned in order to support
+            // hugepages.
+            self.pci_segments[fs_cfg.pci_segment as usize]
+                .allocator
+                .lock()
+                .unwrap()
+                .allocate(
+                    Some(GuestAddress(base)),
+                    size as GuestUsize,
+                    Some(0x0020_0000),
+                )
+                .ok_or(DeviceManagerError::FsRangeAllocation)?;
 
-        let mut file = OpenOptions::new()
-            .read(true)
+            (base, size)
+        } else {
+            let size = fs_cfg.cache_size;
+            // The memory needs to be 2MiB aligned in order to support
+            // hugepages.
+            let base = self.pci_segments[fs_cfg.pci_segment as usize]
+                .allocator
+                .lock()
+                .unwrap()
+                .allocate(None, size as GuestUsize, Some(0x0020_0000))
+                .ok_or(DeviceManagerError::FsRangeAllocation)?;
+
+            (base.raw_value(), size)
+        };
+
+        let mmap_region = MmapRegion::build(
+            None,
+            cache_size as usize,
+            PROT_NONE,
+            MAP_ANONYMOUS | MAP_PRIVATE | MAP_NORESERVE,
+        )
+        .map_err(DeviceManagerError::NewMmapRegion)?;
+        let host_addr: u64 = mmap_region.as_ptr() as u64;
+
+        let mem_slot = self
+            .memory_manager
+            .lock()
+            .unwrap()
+            .create_userspace_mapping(cache_base, cache_size, host_addr, false, false, false)
+            .map_err(DeviceManagerError::MemoryManager)?;
+
+        // Update the node with correct resource information.
+        node.resources.push(Resource::MmioAddressRange {
+            base: cache_base,
+            size: cache_size,
+        });
+
+        Ok((
+            VirtioSharedMemoryList {
+                host_addr,
+                mem_slot,
+                addr: GuestAddress(cache_base),
+                len: cache_size as GuestUsize,
+                region_list: vec![VirtioSharedMemory {
+                    offset: 0,
+                    len: cache_size,
+                }],
+            },
+            mmap_region,
+        ))
+    }
+
+    fn make_virtio_fs_devices(&mut self) -> DeviceManagerResult<Vec<MetaVirtioDevice>> {
+        let mut devices = Vec::new();
+
+        let mut fs_devices = self.config.lock().unwrap().fs.clone();
+        if let Some(fs_list_cfg) = &mut fs_devices {
+            for fs_cfg in fs_list_cfg.iter_mut() {
+                devices.push(self.make_virtio_fs_device(fs_cfg)?);
+            }
+        }
+        self.config.lock().unwrap().fs = fs_devices;
+
+        Ok(devices)
+    }
+
+    fn make_virtio_pmem_device(
+        &mut self,
+        pmem_cfg: &mut PmemConfig,
+    ) -> DeviceManagerResult<MetaVirtioDevice> {
+        let id = if let Some(id) = &pmem_cfg.id {
+            id.clone()
+        } else {
+            let id = self.next_device_name(PMEM_DEVICE_NAME_PREFIX)?;
+            pmem_cfg.id = Some(id.clone());
+            id
+        };
+
+        info!("Creating virtio-pmem device: {:?}", pmem_cfg);
+
+        let mut node = device_node!(id);
+
+        // Look for the id in the device tree. If it can be found, that means
+        // the device is being restored, otherwise it's created from scratch.
+        let region_range = if let Some(node) = self.device_tree.lock().unwrap().get(&id) {
+            info!("Restoring virtio-pmem {} resources", id);
+
+            let mut region_range: Option<(u64, u64)> = None;
+            for resource in node.resources.iter() {
+                match resource {
+                    Resource::MmioAddressRange { base, size } => {
+                        if region_range.is_some() {
+                            return Err(DeviceManagerError::ResourceAlreadyExists);
+                        }
+
+                        region_range = Some((*base, *size));
+                    }
+                    _ => {
+                        error!("Unexpected resource {:?} for {}", resource, id);
+                    }
+                }
+            }
+
+            if region_range.is_none() {
+                return Err(DeviceManagerError::MissingVirtioPmemResources);
+            }
+
+            region_range
+        } else {
+            None
+        };
+
+        let (custom_flags, set_len) = if pmem_cfg.file.is_dir() {
+            if pmem_cfg.size.is_none() {
+                return Err(DeviceManagerError::PmemWithDirectorySizeMissing);
+            }
+            (O_TMPFILE, true)
+        } else {
+            (0, false)
+        };
+
+        let mut file = OpenOptions::new()
+            .read(true)
             .write(!pmem_cfg.discard_writes)
             .custom_flags(custom_flags)
             .open(&pmem_cfg.file)
@@ -2911,6 +3505,7 @@ impl DeviceManager {
                     balloon_config.size,
                     balloon_config.deflate_on_oom,
                     balloon_config.free_page_reporting,
+                    balloon_config.auto_size,
                     self.seccomp_action.clone(),
                     self.exit_evt
                         .try_clone()
@@ -2954,7 +3549,9 @@ impl DeviceManager {
         let virtio_watchdog_device = Arc::new(Mutex::new(
             virtio_devices::Watchdog::new(
                 id.clone(),
-                self.reset_evt.try_clone().unwrap(),
+                self.watchdog_evt
+                    .try_clone()
+                    .map_err(DeviceManagerError::EventFd)?,
                 self.seccomp_action.clone(),
                 self.exit_evt
                     .try_clone()
@@ -2981,6 +3578,408 @@ impl DeviceManager {
         Ok(devices)
     }
 
+    fn make_virtio_gpu_devices(&mut self) -> DeviceManagerResult<Vec<MetaVirtioDevice>> {
+        let mut devices = Vec::new();
+
+        let gpu_config = self.config.lock().unwrap().gpu.clone();
+        if let Some(gpu_config) = gpu_config {
+            let id = String::from(GPU_DEVICE_NAME);
+            info!("Creating virtio-gpu device: {:?}", gpu_config);
+
+            let virtio_gpu_device = Arc::new(Mutex::new(
+                virtio_devices::Gpu::new(
+                    id.clone(),
+                    gpu_config.socket,
+                    gpu_config.width,
+                    gpu_config.height,
+                    gpu_config.iommu,
+                    self.console_input.clone(),
+                    self.seccomp_action.clone(),
+                    self.exit_evt
+                        .try_clone()
+                        .map_err(DeviceManagerError::EventFd)?,
+                    versioned_state_from_id(self.snapshot.as_ref(), id.as_str())
+                        .map_err(DeviceManagerError::RestoreGetState)?,
+                )
+                .map_err(DeviceManagerError::CreateVirtioGpu)?,
+            ));
+            devices.push(MetaVirtioDevice {
+                virtio_device: Arc::clone(&virtio_gpu_device)
+                    as Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
+                iommu: gpu_config.iommu,
+                id: id.clone(),
+                pci_segment: 0,
+                dma_handler: None,
+            });
+
+            self.device_tree
+                .lock()
+                .unwrap()
+                .insert(id.clone(), device_node!(id, virtio_gpu_device));
+        }
+
+        Ok(devices)
+    }
+
+    fn make_virtio_sound_devices(&mut self) -> DeviceManagerResult<Vec<MetaVirtioDevice>> {
+        let mut devices = Vec::new();
+
+        let sound_config = self.config.lock().unwrap().sound.clone();
+        if let Some(sound_config) = sound_config {
+            let id = String::from(SOUND_DEVICE_NAME);
+            info!("Creating virtio-sound device: {:?}", sound_config);
+
+            let playback = sound_config
+                .playback
+                .as_deref()
+                .map(|path| Self::make_sound_backend(sound_config.backend, path, true))
+                .transpose()
+                .map_err(DeviceManagerError::CreateVirtioSound)?;
+            let capture = sound_config
+                .capture
+                .as_deref()
+                .map(|path| Self::make_sound_backend(sound_config.backend, path, false))
+                .transpose()
+                .map_err(DeviceManagerError::CreateVirtioSound)?;
+
+            let virtio_sound_device = Arc::new(Mutex::new(virtio_devices::Sound::new(
+                id.clone(),
+                playback,
+                capture,
+                sound_config.iommu,
+                self.seccomp_action.clone(),
+                self.exit_evt
+                    .try_clone()
+                    .map_err(DeviceManagerError::EventFd)?,
+                versioned_state_from_id(self.snapshot.as_ref(), id.as_str())
+                    .map_err(DeviceManagerError::RestoreGetState)?,
+            )));
+            devices.push(MetaVirtioDevice {
+                virtio_device: Arc::clone(&virtio_sound_device)
+                    as Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
+                iommu: sound_config.iommu,
+                id: id.clone(),
+                pci_segment: 0,
+                dma_handler: None,
+            });
+
+            self.device_tree
+                .lock()
+                .unwrap()
+                .insert(id.clone(), device_node!(id, virtio_sound_device));
+        }
+
+        Ok(devices)
+    }
+
+    fn make_virtio_crypto_devices(&mut self) -> DeviceManagerResult<Vec<MetaVirtioDevice>> {
+        let mut devices = Vec::new();
+
+        let crypto_config = self.config.lock().unwrap().crypto.clone();
+        if let Some(crypto_config) = crypto_config {
+            let id = String::from(CRYPTO_DEVICE_NAME);
+            info!("Creating virtio-crypto device: {:?}", crypto_config);
+
+            let backend = match crypto_config.backend {
+                CryptoBackend::AfAlg => virtio_devices::CryptoBackend::AfAlg,
+                CryptoBackend::Software => virtio_devices::CryptoBackend::Software,
+            };
+
+            let virtio_crypto_device = Arc::new(Mutex::new(
+                virtio_devices::Crypto::new(
+                    id.clone(),
+                    backend,
+                    crypto_config.num_queues,
+                    crypto_config.iommu,
+                    self.seccomp_action.clone(),
+                    self.exit_evt
+                        .try_clone()
+                        .map_err(DeviceManagerError::EventFd)?,
+                    versioned_state_from_id(self.snapshot.as_ref(), id.as_str())
+                        .map_err(DeviceManagerError::RestoreGetState)?,
+                )
+                .map_err(DeviceManagerError::CreateVirtioCrypto)?,
+            ));
+            devices.push(MetaVirtioDevice {
+                virtio_device: Arc::clone(&virtio_crypto_device)
+                    as Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
+                iommu: crypto_config.iommu,
+                id: id.clone(),
+                pci_segment: 0,
+                dma_handler: None,
+            });
+
+            self.device_tree
+                .lock()
+                .unwrap()
+                .insert(id.clone(), device_node!(id, virtio_crypto_device));
+        }
+
+        Ok(devices)
+    }
+
+    fn make_virtio_channels_devices(&mut self) -> DeviceManagerResult<Vec<MetaVirtioDevice>> {
+        let mut devices = Vec::new();
+
+        let mut channels_config = self.config.lock().unwrap().channels.clone();
+        if let Some(channels_config) = channels_config.as_mut() {
+            let id = String::from(CHANNELS_DEVICE_NAME);
+            info!("Creating virtio-console channels: {:?}", channels_config);
+
+            let mut ports = Vec::new();
+            for channel_cfg in channels_config.iter_mut() {
+                let backend = if let Some(socket) = &channel_cfg.socket {
+                    virtio_devices::ChannelBackend::Socket(socket.clone())
+                } else {
+                    let (main, mut sub, path) =
+                        create_pty().map_err(DeviceManagerError::ChannelPtyOpen)?;
+                    self.set_raw_mode(&mut sub)
+                        .map_err(DeviceManagerError::SetPtyRaw)?;
+                    channel_cfg.file = Some(path);
+                    virtio_devices::ChannelBackend::Pty(main, sub)
+                };
+                ports.push(virtio_devices::ChannelPort {
+                    name: channel_cfg.name.clone(),
+                    backend,
+                });
+            }
+
+            let virtio_channels_device = Arc::new(Mutex::new(
+                virtio_devices::Channels::new(
+                    id.clone(),
+                    ports,
+                    self.force_iommu,
+                    self.seccomp_action.clone(),
+                    self.exit_evt
+                        .try_clone()
+                        .map_err(DeviceManagerError::EventFd)?,
+                    versioned_state_from_id(self.snapshot.as_ref(), id.as_str())
+                        .map_err(DeviceManagerError::RestoreGetState)?,
+                )
+                .map_err(DeviceManagerError::CreateVirtioChannels)?,
+            ));
+            devices.push(MetaVirtioDevice {
+                virtio_device: Arc::clone(&virtio_channels_device)
+                    as Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
+                iommu: self.force_iommu,
+                id: id.clone(),
+                pci_segment: 0,
+                dma_handler: None,
+            });
+
+            self.device_tree
+                .lock()
+                .unwrap()
+                .insert(id.clone(), device_node!(id, virtio_channels_device));
+        }
+        // Report the paths of the allocated PTYs
+        self.config.lock().unwrap().channels = channels_config;
+
+        Ok(devices)
+    }
+
+    fn make_virtio_input_device(
+        &mut self,
+        input_cfg: &mut InputConfig,
+    ) -> DeviceManagerResult<MetaVirtioDevice> {
+        let id = if let Some(id) = &input_cfg.id {
+            id.clone()
+        } else {
+            let id = self.next_device_name(INPUT_DEVICE_NAME_PREFIX)?;
+            input_cfg.id = Some(id.clone());
+            id
+        };
+
+        info!("Creating virtio-input device: {:?}", input_cfg);
+
+        let exit_evt = self
+            .exit_evt
+            .try_clone()
+            .map_err(DeviceManagerError::EventFd)?;
+        let state = versioned_state_from_id(self.snapshot.as_ref(), id.as_str())
+            .map_err(DeviceManagerError::RestoreGetState)?;
+        // The configuration has been validated, so that exactly one of the
+        // evdev node and the kind of console device is set.
+        let input_device = if let Some(evdev) = &input_cfg.evdev {
+            virtio_devices::Input::new_evdev(
+                id.clone(),
+                evdev,
+                input_cfg.iommu,
+                self.seccomp_action.clone(),
+                exit_evt,
+                state,
+            )
+        } else {
+            let kind = match input_cfg.kind {
+                Some(InputKind::Keyboard) | None => ConsoleInputKind::Keyboard,
+                Some(InputKind::Mouse) => ConsoleInputKind::Mouse,
+                Some(InputKind::Tablet) => ConsoleInputKind::Tablet,
+            };
+            virtio_devices::Input::new_console(
+                id.clone(),
+                kind,
+                &self.console_input,
+                input_cfg.iommu,
+                self.seccomp_action.clone(),
+                exit_evt,
+                state,
+            )
+        }
+        .map_err(DeviceManagerError::CreateVirtioInput)?;
+        let input_device = Arc::new(Mutex::new(input_device));
+
+        self.device_tree
+            .lock()
+            .unwrap()
+            .insert(id.clone(), device_node!(id, input_device));
+
+        Ok(MetaVirtioDevice {
+            virtio_device: input_device as Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
+            iommu: input_cfg.iommu,
+            id,
+            pci_segment: input_cfg.pci_segment,
+            dma_handler: None,
+        })
+    }
+
+    fn make_virtio_input_devices(&mut self) -> DeviceManagerResult<Vec<MetaVirtioDevice>> {
+        let mut devices = Vec::new();
+        let mut input_devices = self.config.lock().unwrap().input.clone();
+        if let Some(input_list_cfg) = &mut input_devices {
+            for input_cfg in input_list_cfg.iter_mut() {
+                devices.push(self.make_virtio_input_device(input_cfg)?);
+            }
+        }
+        self.config.lock().unwrap().input = input_devices;
+
+        Ok(devices)
+    }
+
+    fn open_scsi_lun(lun_cfg: &ScsiLunConfig) -> DeviceManagerResult<ScsiLunBackend> {
+        let open = |path: &Path, writable: bool| {
+            OpenOptions::new()
+                .read(true)
+                .write(writable)
+                .open(path)
+                .map_err(DeviceManagerError::ScsiLunOpen)
+        };
+
+        // The configuration has been validated, so that only CD-ROM drives
+        // can lack a path.
+        match &lun_cfg.path {
+            Some(path) if lun_cfg.passthrough => Ok(ScsiLunBackend::Passthrough(open(path, true)?)),
+            path if lun_cfg.cdrom => Ok(ScsiLunBackend::Cdrom(
+                path.as_deref().map(|path| open(path, false)).transpose()?,
+            )),
+            Some(path) => Ok(ScsiLunBackend::Disk {
+                file: open(path, !lun_cfg.readonly)?,
+                readonly: lun_cfg.readonly,
+            }),
+            None => Err(DeviceManagerError::NoDiskPath),
+        }
+    }
+
+    fn make_virtio_scsi_device(
+        &mut self,
+        scsi_cfg: &mut ScsiConfig,
+    ) -> DeviceManagerResult<MetaVirtioDevice> {
+        let id = if let Some(id) = &scsi_cfg.id {
+            id.clone()
+        } else {
+            let id = self.next_device_name(SCSI_DEVICE_NAME_PREFIX)?;
+            scsi_cfg.id = Some(id.clone());
+            id
+        };
+
+        info!("Creating virtio-scsi device: {:?}", scsi_cfg);
+
+        let mut luns = Vec::new();
+        for lun_cfg in scsi_cfg.luns.iter() {
+            luns.push(ScsiLun {
+                lun: lun_cfg.lun,
+                backend: Self::open_scsi_lun(lun_cfg)?,
+                serial: lun_cfg
+                    .serial
+                    .clone()
+                    .unwrap_or_else(|| format!("{}-{}", id, lun_cfg.lun)),
+            });
+        }
+
+        let scsi_device = Arc::new(Mutex::new(
+            virtio_devices::Scsi::new(
+                id.clone(),
+                luns,
+                scsi_cfg.iommu,
+                self.seccomp_action.clone(),
+                self.exit_evt
+                    .try_clone()
+                    .map_err(DeviceManagerError::EventFd)?,
+                versioned_state_from_id(self.snapshot.as_ref(), id.as_str())
+                    .map_err(DeviceManagerError::RestoreGetState)?,
+            )
+            .map_err(DeviceManagerError::CreateVirtioScsi)?,
+        ));
+
+        self.scsi_controllers
+            .insert(id.clone(), scsi_device.clone());
+
+        self.device_tree
+            .lock()
+            .unwrap()
+            .insert(id.clone(), device_node!(id, scsi_device));
+
+        Ok(MetaVirtioDevice {
+            virtio_device: scsi_device as Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
+            iommu: scsi_cfg.iommu,
+            id,
+            pci_segment: scsi_cfg.pci_segment,
+            dma_handler: None,
+        })
+    }
+
+    fn make_virtio_scsi_devices(&mut self) -> DeviceManagerResult<Vec<MetaVirtioDevice>> {
+        let mut devices = Vec::new();
+        let mut scsi_devices = self.config.lock().unwrap().scsi.clone();
+        if let Some(scsi_list_cfg) = &mut scsi_devices {
+            for scsi_cfg in scsi_list_cfg.iter_mut() {
+                devices.push(self.make_virtio_scsi_device(scsi_cfg)?);
+            }
+        }
+        self.config.lock().unwrap().scsi = scsi_devices;
+
+        Ok(devices)
+    }
+
+    fn make_sound_backend(
+        backend: SoundBackend,
+        path: &Path,
+        playback: bool,
+    ) -> io::Result<Box<dyn virtio_devices::PcmBackend>> {
+        Ok(match backend {
+            SoundBackend::Pipe => Box::new(if playback {
+                virtio_devices::PipeBackend::playback(path)?
+            } else {
+                virtio_devices::PipeBackend::capture(path)?
+            }),
+            #[cfg(feature = "alsa")]
+            SoundBackend::Alsa => {
+                let device = path.to_string_lossy();
+                Box::new(if playback {
+                    virtio_devices::AlsaBackend::playback(&device)?
+                } else {
+                    virtio_devices::AlsaBackend::capture(&device)?
+                })
+            }
+            #[cfg(not(feature = "alsa"))]
+            SoundBackend::Alsa => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "ALSA sound backend requires the alsa feature",
+                ))
+            }
+        })
+    }
+
     fn make_vdpa_device(
         &mut self,
         vdpa_cfg: &mut VdpaConfig,
@@ -3736,6 +4735,15 @@ impl DeviceManager {
         // Update the PCIU bitmap
         self.pci_segments[device_cfg.pci_segment as usize].pci_devices_up |= 1 << bdf.device();
 
+        event!(
+            "vm",
+            "device-added",
+            "id",
+            &device_name,
+            "bdf",
+            bdf.to_string()
+        );
+
         Ok(PciDeviceInfo {
             id: device_name,
             bdf,
@@ -3753,6 +4761,15 @@ impl DeviceManager {
         // Update the PCIU bitmap
         self.pci_segments[device_cfg.pci_segment as usize].pci_devices_up |= 1 << bdf.device();
 
+        event!(
+            "vm",
+            "device-added",
+            "id",
+            &device_name,
+            "bdf",
+            bdf.to_string()
+        );
+
         Ok(PciDeviceInfo {
             id: device_name,
             bdf,
@@ -4037,6 +5054,15 @@ impl DeviceManager {
         // Update the PCIU bitmap
         self.pci_segments[handle.pci_segment as usize].pci_devices_up |= 1 << bdf.device();
 
+        event!(
+            "vm",
+            "device-added",
+            "id",
+            &handle.id,
+            "bdf",
+            bdf.to_string()
+        );
+
         Ok(PciDeviceInfo { id: handle.id, bdf })
     }
 
@@ -4128,9 +5154,94 @@ impl DeviceManager {
             }
         }
 
+        for (id, interrupts, interrupt_rate) in self.interrupt_counters() {
+            let device_counters = counters.entry(id).or_insert_with(HashMap::new);
+            device_counters.insert("interrupts", Wrapping(interrupts));
+            device_counters.insert("interrupt_rate", Wrapping(interrupt_rate));
+        }
+
         counters
     }
 
+    // Number of interrupts injected by the VMM on behalf of each device, and
+    // the rate (per second) at which they were recently injected. Interrupts
+    // injected without going through the VMM, such as the ones from VFIO
+    // devices or vhost-user backends, are not accounted for.
+    fn interrupt_counters(&self) -> Vec<(String, u64, u64)> {
+        let mut interrupt_counts: HashMap<String, u64> = HashMap::new();
+
+        for (id, interrupt_group) in self.legacy_interrupt_groups.iter() {
+            if let Some(count) = interrupt_group.trigger_count() {
+                *interrupt_counts.entry(id.clone()).or_default() += count;
+            }
+        }
+
+        // Virtio devices are reported under their own identifier rather than
+        // the one from their virtio-pci transport.
+        for node in self.device_tree.lock().unwrap().pci_devices() {
+            if let Some(PciDeviceHandle::Virtio(virtio_pci_device)) = &node.pci_device_handle {
+                if let (Some(id), Some(count)) = (
+                    node.children.first(),
+                    virtio_pci_device.lock().unwrap().interrupt_count(),
+                ) {
+                    *interrupt_counts.entry(id.clone()).or_default() += count;
+                }
+            }
+        }
+
+        let now = Instant::now();
+        let mut interrupt_rates = self.interrupt_rates.lock().unwrap();
+        // Forget about the devices which are gone.
+        interrupt_rates.retain(|id, _| interrupt_counts.contains_key(id));
+        interrupt_counts
+            .into_iter()
+            .map(|(id, count)| {
+                let rate = interrupt_rates
+                    .entry(id.clone())
+                    .or_insert_with(|| InterruptRate::new(self.timestamp))
+                    .rate(now, count);
+                (id, count, rate)
+            })
+            .collect()
+    }
+
+    /// Inserts the image at `path` in a SCSI CD-ROM drive, or ejects its
+    /// medium when `path` is None. The configuration is updated so that the
+    /// new medium is found again after a reboot.
+    pub fn change_media(
+        &mut self,
+        controller: &str,
+        lun: u16,
+        path: Option<&Path>,
+    ) -> DeviceManagerResult<()> {
+        let scsi = self
+            .scsi_controllers
+            .get(controller)
+            .ok_or_else(|| DeviceManagerError::UnknownDeviceId(controller.to_owned()))?;
+        let file = path
+            .map(|path| OpenOptions::new().read(true).open(path))
+            .transpose()
+            .map_err(DeviceManagerError::ScsiLunOpen)?;
+        scsi.lock()
+            .unwrap()
+            .change_media(lun, file)
+            .map_err(DeviceManagerError::ScsiChangeMedia)?;
+
+        let mut config = self.config.lock().unwrap();
+        if let Some(lun_cfg) = config
+            .scsi
+            .iter_mut()
+            .flatten()
+            .filter(|scsi_cfg| scsi_cfg.id.as_deref() == Some(controller))
+            .flat_map(|scsi_cfg| scsi_cfg.luns.iter_mut())
+            .find(|lun_cfg| lun_cfg.lun == lun)
+        {
+            lun_cfg.path = path.map(Path::to_path_buf);
+        }
+
+        Ok(())
+    }
+
     pub fn resize_balloon(&mut self, size: u64) -> DeviceManagerResult<()> {
         if let Some(balloon) = &self.balloon {
             return balloon
@@ -4152,6 +5263,10 @@ impl DeviceManager {
         0
     }
 
+    pub fn balloon(&self) -> Option<Arc<Mutex<virtio_devices::Balloon>>> {
+        self.balloon.clone()
+    }
+
     pub fn device_tree(&self) -> Arc<Mutex<DeviceTree>> {
         self.device_tree.clone()
     }
@@ -4192,6 +5307,21 @@ impl DeviceManager {
             .map_err(DeviceManagerError::PowerButtonNotification);
     }
 
+    pub fn console_input(&self) -> ConsoleInput {
+        self.console_input.clone()
+    }
+
+    pub fn ps2_device(&self) -> DeviceManagerResult<Arc<Mutex<devices::legacy::I8042Device>>> {
+        #[cfg(target_arch = "x86_64")]
+        if self.config.lock().unwrap().is_ps2_enabled() {
+            if let Some(i8042) = &self.i8042 {
+                return Ok(i8042.clone());
+            }
+        }
+
+        Err(DeviceManagerError::NoInputDevice)
+    }
+
     pub fn iommu_attached_devices(&self) -> &Option<(PciBdf, Vec<PciBdf>)> {
         &self.iommu_attached_devices
     }
@@ -4225,6 +5355,27 @@ fn numa_node_id_from_memory_zone_id(numa_nodes: &NumaNodes, memory_zone_id: &str
     None
 }
 
+// Splits an x86 bzImage into its real mode setup code and the protected
+// mode kernel, as firmware loading the kernel from fw_cfg expects. Any other
+// image is exposed as a whole, with an empty setup.
+fn split_kernel_setup(kernel: Vec<u8>) -> (Vec<u8>, Vec<u8>) {
+    #[cfg(target_arch = "x86_64")]
+    if kernel.len() > 0x206 && &kernel[0x202..0x206] == b"HdrS" {
+        let setup_sects = match kernel[0x1f1] {
+            0 => 4,
+            sects => sects as usize,
+        };
+        let setup_size = (setup_sects + 1) * 512;
+        if setup_size < kernel.len() {
+            let mut setup = kernel;
+            let image = setup.split_off(setup_size);
+            return (setup, image);
+        }
+    }
+
+    (Vec::new(), kernel)
+}
+
 struct TpmDevice {}
 
 impl Aml for TpmDevice {
@@ -4399,6 +5550,49 @@ impl Aml for DeviceManager {
             .to_aml_bytes(sink);
         }
 
+        // PS/2 keyboard and mouse, both behind the i8042 controller
+        #[cfg(target_arch = "x86_64")]
+        if let Some(ps2_aux_irq) = self.ps2_aux_irq {
+            aml::Device::new(
+                "_SB_.PS2K".into(),
+                vec![
+                    &aml::Name::new("_HID".into(), &aml::EISAName::new("PNP0303")),
+                    &aml::Name::new("_UID".into(), &aml::ZERO),
+                    &aml::Name::new(
+                        "_CRS".into(),
+                        &aml::ResourceTemplate::new(vec![
+                            &aml::IO::new(0x60, 0x60, 0, 0x1),
+                            &aml::IO::new(0x64, 0x64, 0, 0x1),
+                            &aml::Interrupt::new(true, true, false, false, 1),
+                        ]),
+                    ),
+                ],
+            )
+            .to_aml_bytes(sink);
+
+            aml::Device::new(
+                "_SB_.PS2M".into(),
+                vec![
+                    &aml::Name::new("_HID".into(), &aml::EISAName::new("PNP0F13")),
+                    &aml::Name::new("_UID".into(), &aml::ZERO),
+                    &aml::Name::new(
+                        "_CRS".into(),
+                        &aml::ResourceTemplate::new(vec![&aml::Interrupt::new(
+                            true,
+                            true,
+                            false,
+                            false,
+                            ps2_aux_irq,
+                        )]),
+                    ),
+                ],
+            )
+            .to_aml_bytes(sink);
+        }
+
+        if self.config.lock().unwrap().hibernation.is_some() {
+            aml::Name::new("_S4_".into(), &aml::Package::new(vec![&4u8])).to_aml_bytes(sink);
+        }
         aml::Name::new("_S5_".into(), &aml::Package::new(vec![&5u8])).to_aml_bytes(sink);
 
         aml::Device::new(
@@ -4410,6 +5604,64 @@ impl Aml for DeviceManager {
         )
         .to_aml_bytes(sink);
 
+        if self.config.lock().unwrap().fw_cfg.is_some() {
+            aml::Device::new(
+                "_SB_.FWCF".into(),
+                vec![
+                    &aml::Name::new("_HID".into(), &"QEMU0002"),
+                    &aml::Name::new("_STA".into(), &0x0bu8),
+                    &aml::Name::new(
+                        "_CRS".into(),
+                        &aml::ResourceTemplate::new(vec![
+                            #[cfg(target_arch = "x86_64")]
+                            &aml::IO::new(
+                                FW_CFG_PORT_START as u16,
+                                FW_CFG_PORT_START as u16,
+                                1,
+                                devices::legacy::FW_CFG_REGISTERS_SIZE as u8,
+                            ),
+                            #[cfg(target_arch = "aarch64")]
+                            &aml::Memory32Fixed::new(
+                                true,
+                                arch::layout::LEGACY_FW_CFG_MAPPED_IO_START.raw_value() as u32,
+                                devices::legacy::FW_CFG_REGISTERS_SIZE as u32,
+                            ),
+                        ]),
+                    ),
+                ],
+            )
+            .to_aml_bytes(sink);
+        }
+
+        if self.config.lock().unwrap().pvpanic.is_some() {
+            aml::Device::new(
+                "_SB_.PEVT".into(),
+                vec![
+                    &aml::Name::new("_HID".into(), &"QEMU0001"),
+                    &aml::Name::new("_STA".into(), &0x0fu8),
+                    &aml::Name::new(
+                        "_CRS".into(),
+                        &aml::ResourceTemplate::new(vec![
+                            #[cfg(target_arch = "x86_64")]
+                            &aml::IO::new(
+                                PVPANIC_PORT as u16,
+                                PVPANIC_PORT as u16,
+                                1,
+                                devices::legacy::PVPANIC_REGISTER_SIZE as u8,
+                            ),
+                            #[cfg(target_arch = "aarch64")]
+                            &aml::Memory32Fixed::new(
+                                true,
+                                arch::layout::LEGACY_PVPANIC_MAPPED_IO_START.raw_value() as u32,
+                                devices::legacy::PVPANIC_REGISTER_SIZE as u32,
+                            ),
+                        ]),
+                    ),
+                ],
+            )
+            .to_aml_bytes(sink);
+        }
+
         if self.config.lock().unwrap().tpm.is_some() {
             // Add tpm device
             TpmDevice {}.to_aml_bytes(sink);
@@ -85,6 +85,18 @@ impl DeviceTree {
             .collect()
     }
 
+    // Virtio devices don't carry a PCI b/d/f themselves, their parent
+    // virtio-pci transport node does.
+    pub fn pci_bdf(&self, id: &str) -> Option<PciBdf> {
+        let node = self.0.get(id)?;
+        node.pci_bdf.or_else(|| {
+            node.parent
+                .as_ref()
+                .and_then(|parent| self.0.get(parent))
+                .and_then(|parent| parent.pci_bdf)
+        })
+    }
+
     pub fn remove_node_by_pci_bdf(&mut self, pci_bdf: PciBdf) -> Option<DeviceNode> {
         let mut id = None;
         for (k, v) in self.0.iter() {
@@ -0,0 +1,218 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Client of the agent running in the guest, reached over vsock.
+//!
+//! The agent listens on vsock port `GUEST_AGENT_PORT`. The VMM connects to it
+//! through the Unix socket backing the vsock device, using the "CONNECT
+//! <port>" command understood by the vsock multiplexer. Each command is then
+//! sent as a single line JSON object:
+//!
+//! ```text
+//! {"execute": "guest-exec", "arguments": {"path": "/bin/true"}}
+//! ```
+//!
+//! which the agent answers with a single line, either holding the result as
+//! `{"return": ...}` or the reason of the failure as `{"error": {"desc":
+//! "..."}}`. The arguments and results are the documents of the matching
+//! `vm.guest-*` API endpoints, binary data being base64 encoded.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Vsock port the guest agent listens on.
+pub const GUEST_AGENT_PORT: u32 = 1024;
+
+/// Time the guest agent is given to answer a command.
+pub const GUEST_AGENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Responses are held in memory before being forwarded to the API client
+const MAX_RESPONSE_SIZE: u64 = 64 << 20;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Error connecting to the vsock socket: {0}")]
+    Connect(#[source] io::Error),
+    #[error("No guest agent listening on vsock port {0}")]
+    NotListening(u32),
+    #[error("Error communicating with the guest agent: {0}")]
+    Io(#[source] io::Error),
+    #[error("Invalid response from the guest agent: {0}")]
+    InvalidResponse(#[source] serde_json::Error),
+    #[error("Guest agent response too large or truncated")]
+    IncompleteResponse,
+    #[error("Guest agent command failed: {0}")]
+    Command(String),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Serialize)]
+struct Request<'a, T> {
+    execute: &'a str,
+    arguments: &'a T,
+}
+
+#[derive(Deserialize)]
+struct CommandError {
+    desc: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Response<R> {
+    Return(R),
+    Error(CommandError),
+}
+
+pub struct GuestAgent {
+    stream: BufReader<UnixStream>,
+}
+
+impl GuestAgent {
+    /// Connects to the guest agent through the vsock device backed by the
+    /// Unix socket at `vsock_socket`. Each subsequent operation fails if it
+    /// doesn't complete within `timeout`.
+    pub fn connect(vsock_socket: &Path, timeout: Duration) -> Result<Self> {
+        let mut stream = UnixStream::connect(vsock_socket).map_err(Error::Connect)?;
+        stream
+            .set_read_timeout(Some(timeout))
+            .map_err(Error::Connect)?;
+        stream
+            .set_write_timeout(Some(timeout))
+            .map_err(Error::Connect)?;
+        stream
+            .write_all(format!("CONNECT {GUEST_AGENT_PORT}\n").as_bytes())
+            .map_err(Error::Connect)?;
+
+        // The multiplexer acknowledges the connection with "OK <host port>",
+        // or closes it if nothing listens on the port in the guest.
+        let mut stream = BufReader::new(stream);
+        let mut ack = String::new();
+        stream.read_line(&mut ack).map_err(Error::Connect)?;
+        if !ack.starts_with("OK ") {
+            return Err(Error::NotListening(GUEST_AGENT_PORT));
+        }
+
+        Ok(GuestAgent { stream })
+    }
+
+    /// Runs `command` in the guest and returns its result.
+    pub fn execute<A: Serialize, R: DeserializeOwned>(
+        &mut self,
+        command: &str,
+        arguments: &A,
+    ) -> Result<R> {
+        let mut request = serde_json::to_vec(&Request {
+            execute: command,
+            arguments,
+        })
+        .map_err(|e| Error::Io(e.into()))?;
+        request.push(b'\n');
+        self.stream
+            .get_mut()
+            .write_all(&request)
+            .map_err(Error::Io)?;
+
+        let mut response = Vec::new();
+        (&mut self.stream)
+            .take(MAX_RESPONSE_SIZE)
+            .read_until(b'\n', &mut response)
+            .map_err(Error::Io)?;
+        if response.last() != Some(&b'\n') {
+            return Err(Error::IncompleteResponse);
+        }
+
+        match serde_json::from_slice(&response).map_err(Error::InvalidResponse)? {
+            Response::Return(result) => Ok(result),
+            Response::Error(e) => Err(Error::Command(e.desc)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    // Plays the role of both the vsock multiplexer and the guest agent,
+    // answering the first request with `response`.
+    fn spawn_agent(
+        socket: &Path,
+        listening: bool,
+        response: &'static str,
+    ) -> thread::JoinHandle<String> {
+        let listener = UnixListener::bind(socket).unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut stream = BufReader::new(stream);
+            let mut line = String::new();
+            stream.read_line(&mut line).unwrap();
+            assert_eq!(line, format!("CONNECT {GUEST_AGENT_PORT}\n"));
+            if !listening {
+                return String::new();
+            }
+            stream.get_mut().write_all(b"OK 1073741824\n").unwrap();
+
+            let mut request = String::new();
+            stream.read_line(&mut request).unwrap();
+            stream.get_mut().write_all(response.as_bytes()).unwrap();
+            request
+        })
+    }
+
+    #[test]
+    fn test_execute() {
+        let dir = vmm_sys_util::tempdir::TempDir::new_with_prefix("/tmp/ch").unwrap();
+        let socket = dir.as_path().join("vsock");
+        let agent = spawn_agent(&socket, true, "{\"return\": {\"count\": 3}}\n");
+
+        #[derive(Deserialize)]
+        struct Written {
+            count: u64,
+        }
+        let mut client = GuestAgent::connect(&socket, Duration::from_secs(5)).unwrap();
+        let written: Written = client
+            .execute("guest-file-write", &serde_json::json!({"path": "/a"}))
+            .unwrap();
+        assert_eq!(written.count, 3);
+        assert_eq!(
+            agent.join().unwrap(),
+            "{\"execute\":\"guest-file-write\",\"arguments\":{\"path\":\"/a\"}}\n"
+        );
+    }
+
+    #[test]
+    fn test_execute_error() {
+        let dir = vmm_sys_util::tempdir::TempDir::new_with_prefix("/tmp/ch").unwrap();
+        let socket = dir.as_path().join("vsock");
+        let agent = spawn_agent(&socket, true, "{\"error\": {\"desc\": \"denied\"}}\n");
+
+        let mut client = GuestAgent::connect(&socket, Duration::from_secs(5)).unwrap();
+        match client.execute::<_, serde::de::IgnoredAny>("guest-shutdown", &()) {
+            Err(Error::Command(desc)) => assert_eq!(desc, "denied"),
+            _ => panic!("Expected a command error"),
+        }
+        agent.join().unwrap();
+    }
+
+    #[test]
+    fn test_not_listening() {
+        let dir = vmm_sys_util::tempdir::TempDir::new_with_prefix("/tmp/ch").unwrap();
+        let socket = dir.as_path().join("vsock");
+        let agent = spawn_agent(&socket, false, "");
+
+        assert!(matches!(
+            GuestAgent::connect(&socket, Duration::from_secs(5)),
+            Err(Error::NotListening(GUEST_AGENT_PORT))
+        ));
+        agent.join().unwrap();
+    }
+}
@@ -0,0 +1,31 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Persistence of the VMs whose guest hibernated.
+//!
+//! When the guest enters the ACPI S4 sleep state, it has already written its
+//! memory image to its own storage. All it needs to wake up is being booted
+//! again on the same virtual machine, which is why the configuration of the
+//! VM, including the hotplugged devices and memory, is saved at that point.
+
+use crate::config::VmConfig;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+
+const HIBERNATION_CONFIG_FILE: &str = "config.json";
+
+/// Saves the configuration of the VM the guest hibernated from into the
+/// existing directory `path`.
+pub fn save_config(path: &Path, config: &VmConfig) -> io::Result<()> {
+    let file = File::create(path.join(HIBERNATION_CONFIG_FILE))?;
+    serde_json::to_writer(file, config).map_err(io::Error::from)
+}
+
+/// Loads the configuration of a VM whose guest hibernated.
+pub fn load_config(path: &Path) -> io::Result<VmConfig> {
+    let file = File::open(path.join(HIBERNATION_CONFIG_FILE))?;
+    serde_json::from_reader(BufReader::new(file)).map_err(io::Error::from)
+}
@@ -0,0 +1,303 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Replay of the keyboard and pointer events injected through the API.
+//!
+//! Keys and buttons have to be held down for a while for the guest to notice
+//! them, which is done from a dedicated thread so that the VMM thread keeps
+//! serving other requests meanwhile. Requests are replayed one after the
+//! other, so that their events are never interleaved.
+//!
+//! The events go to the virtio-input keyboard and mouse fed from the console
+//! when there are some, and to the PS/2 keyboard and mouse otherwise.
+
+use devices::legacy::{I8042Device, Key};
+use std::collections::VecDeque;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use virtio_devices::{ConsoleInput, ConsoleInputKind};
+
+pub enum InputRequest {
+    /// Key combinations pressed one after the other.
+    Keys {
+        combinations: Vec<Vec<Key>>,
+        hold_time: Duration,
+    },
+    /// Relative motion, with `buttons` held down during the motion.
+    Pointer {
+        dx: i32,
+        dy: i32,
+        buttons: u8,
+        hold_time: Duration,
+    },
+}
+
+/// Devices the injected events can be sent to.
+#[derive(Clone)]
+pub struct InputDevices {
+    pub ps2_device: Option<Arc<Mutex<I8042Device>>>,
+    pub console_input: ConsoleInput,
+}
+
+impl InputDevices {
+    /// Returns whether there is a device to send the events of the given
+    /// kind to, and whether the guest has enabled it.
+    pub fn ready(&self, kind: ConsoleInputKind) -> Option<bool> {
+        // The virtio-input devices queue the events until the guest gets them.
+        if self.console_input.has_device(kind) {
+            return Some(true);
+        }
+
+        let ps2_device = self.ps2_device.as_ref()?.lock().unwrap();
+        Some(match kind {
+            ConsoleInputKind::Keyboard => ps2_device.keyboard_ready(),
+            _ => ps2_device.mouse_ready(),
+        })
+    }
+
+    fn key_event(&self, key: Key, pressed: bool) -> bool {
+        if self.console_input.has_device(ConsoleInputKind::Keyboard) {
+            self.console_input.inject_key(key.code(), pressed);
+            return true;
+        }
+
+        self.ps2_device.as_ref().map_or(false, |ps2_device| {
+            ps2_device.lock().unwrap().key_event(key, pressed)
+        })
+    }
+
+    fn pointer_event(&self, dx: i32, dy: i32, buttons: u8) -> bool {
+        if self.console_input.has_device(ConsoleInputKind::Mouse) {
+            self.console_input.inject_pointer(dx, dy, buttons);
+            return true;
+        }
+
+        self.ps2_device.as_ref().map_or(false, |ps2_device| {
+            ps2_device.lock().unwrap().pointer_event(dx, dy, buttons)
+        })
+    }
+}
+
+pub struct InputInjector {
+    devices: InputDevices,
+    requests: Receiver<InputRequest>,
+    // Requests received while replaying a previous one.
+    pending: VecDeque<InputRequest>,
+}
+
+impl InputInjector {
+    pub fn new(devices: InputDevices, requests: Receiver<InputRequest>) -> Self {
+        InputInjector {
+            devices,
+            requests,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Replays the requests until every sender is gone.
+    pub fn run(&mut self) {
+        loop {
+            let request = match self.pending.pop_front() {
+                Some(request) => request,
+                None => match self.requests.recv() {
+                    Ok(request) => request,
+                    Err(_) => return,
+                },
+            };
+
+            let running = match request {
+                InputRequest::Keys {
+                    combinations,
+                    hold_time,
+                } => self.send_keys(&combinations, hold_time),
+                InputRequest::Pointer {
+                    dx,
+                    dy,
+                    buttons,
+                    hold_time,
+                } => self.send_pointer(dx, dy, buttons, hold_time),
+            };
+            if !running {
+                return;
+            }
+        }
+    }
+
+    // Waits for `duration`, queueing the requests received meanwhile.
+    // Returns false once every sender is gone.
+    fn wait(&mut self, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match self.requests.recv_timeout(timeout) {
+                Ok(request) => self.pending.push_back(request),
+                Err(RecvTimeoutError::Timeout) => return true,
+                Err(RecvTimeoutError::Disconnected) => return false,
+            }
+        }
+    }
+
+    fn send_keys(&mut self, combinations: &[Vec<Key>], hold_time: Duration) -> bool {
+        for combination in combinations {
+            // Keys are pressed in order and released in reverse order.
+            let pressed = combination
+                .iter()
+                .take_while(|key| self.devices.key_event(**key, true))
+                .count();
+            let complete = pressed == combination.len();
+
+            // The keys which made it are released no matter what, so that
+            // none is left stuck down.
+            let running = !complete || self.wait(hold_time);
+            for key in combination[..pressed].iter().rev() {
+                self.devices.key_event(*key, false);
+            }
+
+            if !complete {
+                warn!("The guest disabled the keyboard, dropping the remaining keys");
+                return true;
+            }
+            if !running || !self.wait(hold_time) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    fn send_pointer(&mut self, dx: i32, dy: i32, buttons: u8, hold_time: Duration) -> bool {
+        if !self.devices.pointer_event(dx, dy, buttons) {
+            warn!("The guest disabled the mouse, dropping the pointer event");
+            return true;
+        }
+
+        // Buttons pressed during the motion are released once held long
+        // enough, which turns a motion-less event into a click.
+        if buttons == 0 {
+            return true;
+        }
+        let running = self.wait(hold_time);
+        self.devices.pointer_event(0, 0, 0);
+
+        running
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use devices::legacy::Ps2Interrupts;
+    use std::sync::mpsc::channel;
+    use vm_device::interrupt::{InterruptIndex, InterruptSourceConfig, InterruptSourceGroup};
+    use vm_device::BusDevice;
+    use vmm_sys_util::eventfd::EventFd;
+
+    struct TestInterrupt;
+
+    impl InterruptSourceGroup for TestInterrupt {
+        fn trigger(&self, _index: InterruptIndex) -> std::io::Result<()> {
+            Ok(())
+        }
+        fn update(
+            &self,
+            _index: InterruptIndex,
+            _config: InterruptSourceConfig,
+            _masked: bool,
+        ) -> std::io::Result<()> {
+            Ok(())
+        }
+        fn notifier(&self, _index: InterruptIndex) -> Option<EventFd> {
+            None
+        }
+    }
+
+    fn read_output(ps2_device: &Mutex<I8042Device>) -> Vec<u8> {
+        let mut ps2_device = ps2_device.lock().unwrap();
+        let mut bytes = Vec::new();
+        let mut data = [0u8];
+        loop {
+            ps2_device.read(0, 0x4, &mut data);
+            if data[0] & 0x1 == 0 {
+                break;
+            }
+            ps2_device.read(0, 0x0, &mut data);
+            bytes.push(data[0]);
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_keys_released_on_stop() {
+        let ps2_device = Arc::new(Mutex::new(I8042Device::new(
+            "i8042".to_string(),
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            Some(Ps2Interrupts {
+                keyboard: Arc::new(TestInterrupt),
+                aux: Arc::new(TestInterrupt),
+            }),
+            None,
+        )));
+        let (sender, receiver) = channel();
+        let mut input_injector = InputInjector::new(
+            InputDevices {
+                ps2_device: Some(ps2_device.clone()),
+                console_input: ConsoleInput::default(),
+            },
+            receiver,
+        );
+
+        sender
+            .send(InputRequest::Keys {
+                combinations: vec![Key::parse_combination("ctrl-a").unwrap()],
+                hold_time: Duration::from_secs(60),
+            })
+            .unwrap();
+        sender
+            .send(InputRequest::Keys {
+                combinations: vec![Key::parse_combination("b").unwrap()],
+                hold_time: Duration::from_secs(60),
+            })
+            .unwrap();
+        drop(sender);
+
+        // The keys held down are released, and the queued request dropped.
+        input_injector.run();
+        assert_eq!(read_output(&ps2_device), vec![0x1d, 0x1e, 0x9e, 0x9d]);
+    }
+
+    #[test]
+    fn test_virtio_input_routing() {
+        let console_input = ConsoleInput::default();
+        let devices = InputDevices {
+            ps2_device: None,
+            console_input: console_input.clone(),
+        };
+        assert_eq!(devices.ready(ConsoleInputKind::Keyboard), None);
+
+        let keyboard = console_input.subscribe(ConsoleInputKind::Keyboard).unwrap();
+        assert_eq!(devices.ready(ConsoleInputKind::Keyboard), Some(true));
+        assert_eq!(devices.ready(ConsoleInputKind::Mouse), None);
+
+        let (sender, receiver) = channel();
+        let mut input_injector = InputInjector::new(devices, receiver);
+        sender
+            .send(InputRequest::Keys {
+                combinations: vec![Key::parse_combination("ctrl-a").unwrap()],
+                hold_time: Duration::ZERO,
+            })
+            .unwrap();
+        drop(sender);
+        input_injector.run();
+
+        // Press and release events, each followed by a synchronization event.
+        let codes: Vec<(u16, u32)> = keyboard
+            .take()
+            .iter()
+            .step_by(2)
+            .map(|event| (event.code, event.value))
+            .collect();
+        assert_eq!(codes, vec![(29, 1), (30, 1), (30, 0), (29, 0)]);
+    }
+}
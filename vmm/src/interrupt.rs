@@ -5,10 +5,11 @@
 
 use devices::interrupt_controller::InterruptController;
 use hypervisor::IrqRoutingEntry;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::io;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use vm_allocator::SystemAllocator;
 use vm_device::interrupt::{
     InterruptIndex, InterruptManager, InterruptSourceConfig, InterruptSourceGroup,
@@ -23,6 +24,7 @@ struct InterruptRoute {
     gsi: u32,
     irq_fd: EventFd,
     registered: AtomicBool,
+    trigger_count: AtomicU64,
 }
 
 impl InterruptRoute {
@@ -36,6 +38,7 @@ impl InterruptRoute {
             gsi,
             irq_fd,
             registered: AtomicBool::new(false),
+            trigger_count: AtomicU64::new(0),
         })
     }
 
@@ -72,7 +75,9 @@ impl InterruptRoute {
     }
 
     pub fn trigger(&self) -> Result<()> {
-        self.irq_fd.write(1)
+        self.irq_fd.write(1)?;
+        self.trigger_count.fetch_add(1, Ordering::Relaxed);
+        Ok(())
     }
 
     pub fn notifier(&self) -> Option<EventFd> {
@@ -176,6 +181,15 @@ impl InterruptSourceGroup for MsiInterruptGroup {
                 route: self.vm.make_routing_entry(route.gsi, &config),
                 masked,
             };
+            // On AArch64, KVM maps the interrupt of an assigned device onto a
+            // GICv4 virtual LPI, for the device to inject it directly, from
+            // the routing in place when the irqfd gets registered. As the
+            // mapping doesn't follow the routing updates, the irqfd is
+            // registered again once the new routing is set. Signals raised in
+            // the meantime are kept pending by the eventfd.
+            #[cfg(target_arch = "aarch64")]
+            route.disable(&self.vm)?;
+            #[cfg(not(target_arch = "aarch64"))]
             if masked {
                 route.disable(&self.vm)?;
             } else {
@@ -183,7 +197,12 @@ impl InterruptSourceGroup for MsiInterruptGroup {
             }
             let mut routes = self.gsi_msi_routes.lock().unwrap();
             routes.insert(route.gsi, entry);
-            return self.set_gsi_routes(&routes);
+            self.set_gsi_routes(&routes)?;
+            #[cfg(target_arch = "aarch64")]
+            if !masked {
+                route.enable(&self.vm)?;
+            }
+            return Ok(());
         }
 
         Err(io::Error::new(
@@ -191,16 +210,30 @@ impl InterruptSourceGroup for MsiInterruptGroup {
             format!("update: Invalid interrupt index {index}"),
         ))
     }
+
+    fn trigger_count(&self) -> Option<u64> {
+        Some(
+            self.irq_routes
+                .values()
+                .map(|route| route.trigger_count.load(Ordering::Relaxed))
+                .sum(),
+        )
+    }
 }
 
 pub struct LegacyUserspaceInterruptGroup {
     ioapic: Arc<Mutex<dyn InterruptController>>,
     irq: u32,
+    trigger_count: AtomicU64,
 }
 
 impl LegacyUserspaceInterruptGroup {
     fn new(ioapic: Arc<Mutex<dyn InterruptController>>, irq: u32) -> Self {
-        LegacyUserspaceInterruptGroup { ioapic, irq }
+        LegacyUserspaceInterruptGroup {
+            ioapic,
+            irq,
+            trigger_count: AtomicU64::new(0),
+        }
     }
 }
 
@@ -215,7 +248,14 @@ impl InterruptSourceGroup for LegacyUserspaceInterruptGroup {
                     io::ErrorKind::Other,
                     format!("failed to inject IRQ #{}: {:?}", self.irq, e),
                 )
-            })
+            })?;
+        self.trigger_count.fetch_add(1, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    fn trigger_count(&self) -> Option<u64> {
+        Some(self.trigger_count.load(Ordering::Relaxed))
     }
 
     fn update(
@@ -232,6 +272,67 @@ impl InterruptSourceGroup for LegacyUserspaceInterruptGroup {
     }
 }
 
+// Period the interrupt rates are averaged over, when the interrupt counts
+// were sampled long enough ago.
+const INTERRUPT_RATE_PERIOD: Duration = Duration::from_secs(10);
+// Minimum time between two interrupt count samples.
+const INTERRUPT_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Interrupt rate of a device, computed from timestamped samples of its
+/// interrupt count. The rate is averaged over the last
+/// `INTERRUPT_RATE_PERIOD` (or since the oldest sample available), so that it
+/// doesn't depend on how often, or by how many clients, it is retrieved.
+pub struct InterruptRate {
+    start: Instant,
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl InterruptRate {
+    /// Creates the rate of a device whose interrupt count was null at
+    /// `start`.
+    pub fn new(start: Instant) -> Self {
+        InterruptRate {
+            start,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Returns the rate, per second, for the interrupt `count` at `now`.
+    pub fn rate(&mut self, now: Instant, count: u64) -> u64 {
+        // Only the newest sample older than the period is kept, as the
+        // reference the rate is computed against.
+        while self.samples.len() > 1
+            && now.saturating_duration_since(self.samples[1].0) >= INTERRUPT_RATE_PERIOD
+        {
+            self.samples.pop_front();
+        }
+
+        let (time, last_count) = match self.samples.front() {
+            Some((time, last_count))
+                if now.saturating_duration_since(*time) >= INTERRUPT_SAMPLE_INTERVAL =>
+            {
+                (*time, *last_count)
+            }
+            // Too short a period would make the rate meaningless.
+            _ => (self.start, 0),
+        };
+
+        // Bound the number of samples, whatever the retrieval frequency.
+        if self.samples.back().map_or(true, |(time, _)| {
+            now.saturating_duration_since(*time) >= INTERRUPT_SAMPLE_INTERVAL
+        }) {
+            self.samples.push_back((now, count));
+        }
+
+        let elapsed = now.saturating_duration_since(time).as_secs_f64();
+        if elapsed > 0.0 {
+            (count.saturating_sub(last_count) as f64 / elapsed) as u64
+        } else {
+            0
+        }
+    }
+}
+
 pub struct LegacyUserspaceInterruptManager {
     ioapic: Arc<Mutex<dyn InterruptController>>,
 }
@@ -301,3 +402,43 @@ impl InterruptManager for MsiInterruptManager {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interrupt_rate() {
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        let mut rate = InterruptRate::new(start);
+
+        // Without any older sample, the rate is computed since the start.
+        assert_eq!(rate.rate(at(2), 200), 100);
+        // Retrieving the rate again straight away doesn't reset it.
+        assert_eq!(rate.rate(at(2), 200), 100);
+        assert_eq!(rate.rate(at(2) + Duration::from_millis(500), 300), 120);
+
+        // Rates are then computed against the previous samples.
+        assert_eq!(rate.rate(at(4), 600), 200);
+        for secs in 5..=20 {
+            rate.rate(at(secs), 600 + (secs - 4) * 50);
+        }
+        // The reference is kept around the rate period, the samples older
+        // than that being dropped.
+        assert!(rate.samples.len() <= 12);
+        assert_eq!(rate.rate(at(21), 1450), 50);
+    }
+
+    #[test]
+    fn test_interrupt_rate_reset() {
+        let start = Instant::now();
+        let mut rate = InterruptRate::new(start);
+
+        assert_eq!(rate.rate(start, 0), 0);
+        assert_eq!(rate.rate(start + Duration::from_secs(1), 100), 100);
+        // A count going backwards, e.g. once a device is replaced, doesn't
+        // underflow.
+        assert_eq!(rate.rate(start + Duration::from_secs(11), 10), 0);
+    }
+}
@@ -9,19 +9,30 @@ extern crate event_monitor;
 extern crate log;
 
 use crate::api::{
-    ApiError, ApiRequest, ApiResponse, ApiResponsePayload, VmInfo, VmReceiveMigrationData,
-    VmSendMigrationData, VmmPingResponse,
+    ApiError, ApiRequest, ApiResponse, ApiResponsePayload, DeviceInfo, MemoryRegionDirtyRate,
+    PointerButton, VmChangeMediaData, VmCloneData, VmDirtyRate, VmDirtyRateData, VmGuestExec,
+    VmGuestExecData, VmGuestFileRead, VmGuestFileReadData, VmGuestFileWrite, VmGuestFileWriteData,
+    VmGuestShutdownData, VmInfo, VmNmiData, VmReceiveMigrationData, VmSendKeysData,
+    VmSendMigrationData, VmSendPointerData, VmSetWatchdogActionData, VmmPingResponse,
+    DEFAULT_DIRTY_RATE_PERIOD_MS, DEFAULT_GUEST_EXEC_TIMEOUT_MS, DEFAULT_INPUT_HOLD_TIME_MS,
+    MAX_INPUT_HOLD_TIME_MS,
 };
 use crate::config::{
-    add_to_config, DeviceConfig, DiskConfig, FsConfig, NetConfig, PmemConfig, RestoreConfig,
-    UserDeviceConfig, VdpaConfig, VmConfig, VsockConfig,
+    add_to_config, DeviceConfig, DiskConfig, FsConfig, NetConfig, PanicAction, PmemConfig,
+    RestoreConfig, UserDeviceConfig, VdpaConfig, VmConfig, VsockConfig, WatchdogAction,
 };
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 use crate::coredump::GuestDebuggable;
+use crate::guest_agent::{GuestAgent, GUEST_AGENT_TIMEOUT};
 use crate::memory_manager::MemoryManager;
 #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
 use crate::migration::get_vm_snapshot;
-use crate::migration::{recv_vm_config, recv_vm_state};
+use crate::migration::{
+    receive_compressed_memory, receive_memory_stream, recv_vm_config, recv_vm_state,
+    tls_client_config, tls_server_config, CompressionPool, MemoryStreams, MigrationListener,
+    MigrationSocket,
+};
+use crate::postcopy::PostcopyReceiver;
 use crate::seccomp_filters::{get_seccomp_filter, Thread};
 use crate::vm::{Error as VmError, Vm, VmState};
 use anyhow::anyhow;
@@ -32,18 +43,16 @@ use seccompiler::{apply_filter, SeccompAction};
 use serde::ser::{SerializeStruct, Serializer};
 use serde::{Deserialize, Serialize};
 use signal_hook::iterator::{Handle, Signals};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
 use std::io;
 use std::io::{Read, Write};
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
-use std::os::unix::net::UnixListener;
-use std::os::unix::net::UnixStream;
 use std::panic::AssertUnwindSafe;
 use std::path::PathBuf;
 use std::sync::mpsc::{Receiver, RecvError, SendError, Sender};
 use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::{result, thread};
 use thiserror::Error;
 use tracer::trace_scoped;
@@ -57,7 +66,9 @@ use vmm_sys_util::terminal::Terminal;
 
 mod acpi;
 pub mod api;
+mod balloon_policy;
 mod clone3;
+mod cloud_init;
 pub mod config;
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 mod coredump;
@@ -66,13 +77,21 @@ pub mod device_manager;
 pub mod device_tree;
 #[cfg(feature = "guest_debug")]
 mod gdb;
+mod guest_agent;
+pub mod hibernation;
+mod input_injection;
 pub mod interrupt;
 pub mod memory_manager;
+mod metrics;
 pub mod migration;
 mod pci_segment;
+mod postcopy;
 pub mod seccomp_filters;
 mod serial_manager;
 mod sigwinch_listener;
+mod snapshot_file;
+#[cfg(feature = "tdx")]
+mod tdx_quote;
 pub mod vm;
 pub mod vm_config;
 
@@ -149,12 +168,22 @@ pub enum Error {
 
     /// Error creating API server
     #[error("Error creating API server {0:?}")]
-    CreateApiServer(micro_http::ServerError),
+    CreateApiServer(#[source] io::Error),
 
     /// Error binding API server socket
     #[error("Error creation API server's socket {0:?}")]
     CreateApiServerSocket(#[source] io::Error),
 
+    /// Error connecting to the D-Bus bus
+    #[cfg(feature = "dbus_api")]
+    #[error("Error creating D-Bus session: {0}")]
+    CreateDBusSession(#[source] zbus::Error),
+
+    /// Cannot create D-Bus thread
+    #[cfg(feature = "dbus_api")]
+    #[error("Error spawning D-Bus thread: {0}")]
+    DBusThreadSpawn(#[source] io::Error),
+
     #[cfg(feature = "guest_debug")]
     #[error("Failed to start the GDB thread: {0}")]
     GdbThreadSpawn(io::Error),
@@ -185,6 +214,8 @@ pub enum EpollDispatch {
     Api = 2,
     ActivateVirtioDevices = 3,
     Debug = 4,
+    Panic = 5,
+    Watchdog = 6,
     Unknown,
 }
 
@@ -197,6 +228,8 @@ impl From<u64> for EpollDispatch {
             2 => Api,
             3 => ActivateVirtioDevices,
             4 => Debug,
+            5 => Panic,
+            6 => Watchdog,
             _ => Unknown,
         }
     }
@@ -284,6 +317,8 @@ pub fn start_vmm_thread(
     vmm_version: String,
     http_path: &Option<String>,
     http_fd: Option<RawFd>,
+    http_access: api::HttpAccessConfig,
+    #[cfg(feature = "dbus_api")] dbus_options: Option<api::DBusApiOptions>,
     api_event: EventFd,
     api_sender: Sender<ApiRequest>,
     api_receiver: Receiver<ApiRequest>,
@@ -303,6 +338,10 @@ pub fn start_vmm_thread(
     let gdb_vm_debug_event = vm_debug_event.try_clone().map_err(Error::EventFdClone)?;
 
     let http_api_event = api_event.try_clone().map_err(Error::EventFdClone)?;
+    #[cfg(feature = "dbus_api")]
+    let dbus_api_event = api_event.try_clone().map_err(Error::EventFdClone)?;
+    #[cfg(feature = "dbus_api")]
+    let dbus_api_sender = api_sender.clone();
     let hypervisor_type = hypervisor.hypervisor_type();
 
     // Retrieve seccomp filter
@@ -311,6 +350,8 @@ pub fn start_vmm_thread(
 
     let vmm_seccomp_action = seccomp_action.clone();
     let exit_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFdCreate)?;
+    #[cfg(feature = "dbus_api")]
+    let dbus_exit_evt = exit_evt.try_clone().map_err(Error::EventFdClone)?;
     let thread = {
         let exit_evt = exit_evt.try_clone().map_err(Error::EventFdClone)?;
         thread::Builder::new()
@@ -348,6 +389,7 @@ pub fn start_vmm_thread(
     if let Some(http_path) = http_path {
         api::start_http_path_thread(
             http_path,
+            http_access,
             http_api_event,
             api_sender,
             seccomp_action,
@@ -357,6 +399,7 @@ pub fn start_vmm_thread(
     } else if let Some(http_fd) = http_fd {
         api::start_http_fd_thread(
             http_fd,
+            http_access,
             http_api_event,
             api_sender,
             seccomp_action,
@@ -365,6 +408,18 @@ pub fn start_vmm_thread(
         )?;
     }
 
+    #[cfg(feature = "dbus_api")]
+    if let Some(dbus_options) = dbus_options {
+        api::start_dbus_thread(
+            dbus_options,
+            dbus_api_event,
+            dbus_api_sender,
+            seccomp_action,
+            dbus_exit_evt,
+            hypervisor_type,
+        )?;
+    }
+
     #[cfg(feature = "guest_debug")]
     if let Some(debug_path) = debug_path {
         let target = gdb::GdbStub::new(
@@ -382,11 +437,25 @@ pub fn start_vmm_thread(
     Ok(thread)
 }
 
+// Keys and buttons are held down from a dedicated thread, the time is still
+// bounded as it delays the following input requests.
+fn input_hold_time(hold_time_ms: Option<u64>) -> result::Result<Duration, VmError> {
+    let hold_time_ms = hold_time_ms.unwrap_or(DEFAULT_INPUT_HOLD_TIME_MS);
+    if hold_time_ms > MAX_INPUT_HOLD_TIME_MS {
+        return Err(VmError::InvalidInputHoldTime(hold_time_ms));
+    }
+
+    Ok(Duration::from_millis(hold_time_ms))
+}
+
 #[derive(Clone, Deserialize, Serialize)]
 struct VmMigrationConfig {
     vm_config: Arc<Mutex<VmConfig>>,
     #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
     common_cpuid: Vec<hypervisor::arch::x86::CpuIdEntry>,
+    #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+    #[serde(default)]
+    msr_features: Vec<hypervisor::arch::x86::MsrEntry>,
     memory_manager_data: MemoryManagerSnapshotData,
 }
 
@@ -394,6 +463,8 @@ pub struct Vmm {
     epoll: EpollContext,
     exit_evt: EventFd,
     reset_evt: EventFd,
+    panic_evt: EventFd,
+    watchdog_evt: EventFd,
     api_evt: EventFd,
     #[cfg(feature = "guest_debug")]
     debug_evt: EventFd,
@@ -407,6 +478,8 @@ pub struct Vmm {
     activate_evt: EventFd,
     signals: Option<Handle>,
     threads: Vec<thread::JoinHandle<()>>,
+    // Latest dirty rate measured, in MB/s
+    dirty_rate: Option<f64>,
 }
 
 impl Vmm {
@@ -492,6 +565,8 @@ impl Vmm {
     ) -> Result<Self> {
         let mut epoll = EpollContext::new().map_err(Error::Epoll)?;
         let reset_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFdCreate)?;
+        let panic_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFdCreate)?;
+        let watchdog_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFdCreate)?;
         let activate_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFdCreate)?;
 
         epoll
@@ -502,6 +577,14 @@ impl Vmm {
             .add_event(&reset_evt, EpollDispatch::Reset)
             .map_err(Error::Epoll)?;
 
+        epoll
+            .add_event(&panic_evt, EpollDispatch::Panic)
+            .map_err(Error::Epoll)?;
+
+        epoll
+            .add_event(&watchdog_evt, EpollDispatch::Watchdog)
+            .map_err(Error::Epoll)?;
+
         epoll
             .add_event(&activate_evt, EpollDispatch::ActivateVirtioDevices)
             .map_err(Error::Epoll)?;
@@ -519,6 +602,8 @@ impl Vmm {
             epoll,
             exit_evt,
             reset_evt,
+            panic_evt,
+            watchdog_evt,
             api_evt,
             #[cfg(feature = "guest_debug")]
             debug_evt,
@@ -532,6 +617,7 @@ impl Vmm {
             activate_evt,
             signals: None,
             threads: vec![],
+            dirty_rate: None,
         })
     }
 
@@ -559,6 +645,11 @@ impl Vmm {
             if self.vm.is_none() {
                 let exit_evt = self.exit_evt.try_clone().map_err(VmError::EventFdClone)?;
                 let reset_evt = self.reset_evt.try_clone().map_err(VmError::EventFdClone)?;
+                let panic_evt = self.panic_evt.try_clone().map_err(VmError::EventFdClone)?;
+                let watchdog_evt = self
+                    .watchdog_evt
+                    .try_clone()
+                    .map_err(VmError::EventFdClone)?;
                 #[cfg(feature = "guest_debug")]
                 let vm_debug_evt = self
                     .vm_debug_evt
@@ -574,6 +665,8 @@ impl Vmm {
                         Arc::clone(vm_config),
                         exit_evt,
                         reset_evt,
+                        panic_evt,
+                        watchdog_evt,
                         #[cfg(feature = "guest_debug")]
                         vm_debug_evt,
                         &self.seccomp_action,
@@ -651,13 +744,22 @@ impl Vmm {
         let vm_snapshot = get_vm_snapshot(&snapshot).map_err(VmError::Restore)?;
 
         #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
-        self.vm_check_cpuid_compatibility(&vm_config, &vm_snapshot.common_cpuid)
-            .map_err(VmError::Restore)?;
+        self.vm_check_cpuid_compatibility(
+            &vm_config,
+            &vm_snapshot.common_cpuid,
+            &vm_snapshot.msr_features,
+        )
+        .map_err(VmError::Restore)?;
 
         self.vm_config = Some(Arc::clone(&vm_config));
 
         let exit_evt = self.exit_evt.try_clone().map_err(VmError::EventFdClone)?;
         let reset_evt = self.reset_evt.try_clone().map_err(VmError::EventFdClone)?;
+        let panic_evt = self.panic_evt.try_clone().map_err(VmError::EventFdClone)?;
+        let watchdog_evt = self
+            .watchdog_evt
+            .try_clone()
+            .map_err(VmError::EventFdClone)?;
         #[cfg(feature = "guest_debug")]
         let debug_evt = self
             .vm_debug_evt
@@ -672,6 +774,8 @@ impl Vmm {
             vm_config,
             exit_evt,
             reset_evt,
+            panic_evt,
+            watchdog_evt,
             #[cfg(feature = "guest_debug")]
             debug_evt,
             &self.seccomp_action,
@@ -703,6 +807,78 @@ impl Vmm {
         }
     }
 
+    fn vm_panic(&mut self) {
+        let pvpanic = self
+            .vm_config
+            .as_ref()
+            .and_then(|config| config.lock().unwrap().pvpanic.clone())
+            .unwrap_or_default();
+
+        warn!("VM panic event, action: {:?}", pvpanic.on_panic);
+        event!(
+            "vm",
+            "panicked",
+            "action",
+            format!("{:?}", pvpanic.on_panic)
+        );
+
+        match pvpanic.on_panic {
+            PanicAction::Shutdown => {
+                if let Err(e) = self.exit_evt.write(1) {
+                    error!("Error shutting down the panicked VM: {:?}", e);
+                }
+            }
+            PanicAction::Pause => {
+                if let Err(e) = self.vm_pause() {
+                    error!("Error pausing the panicked VM: {:?}", e);
+                }
+            }
+            PanicAction::Coredump => {
+                if let Err(e) = self.vm_pause() {
+                    error!("Error pausing the panicked VM: {:?}", e);
+                    return;
+                }
+                // The configuration is validated to have a path when the
+                // coredump action is available.
+                #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
+                if let Some(path) = pvpanic.coredump_path {
+                    if let Err(e) = self.vm_coredump(&format!("file://{}", path.display())) {
+                        error!("Error dumping the panicked VM: {:?}", e);
+                    }
+                }
+            }
+        }
+    }
+
+    fn vm_watchdog_expired(&mut self) {
+        let action = match self.vm_config {
+            Some(ref config) => config.lock().unwrap().watchdog_action,
+            None => return,
+        };
+
+        warn!("VM watchdog expired, action: {:?}", action);
+        event!("vm", "watchdog-expired", "action", format!("{:?}", action));
+
+        match action {
+            WatchdogAction::Reset => {
+                if let Err(e) = self.reset_evt.write(1) {
+                    error!("Error resetting the VM after watchdog expiry: {:?}", e);
+                }
+            }
+            WatchdogAction::Poweroff => {
+                if let Err(e) = self.exit_evt.write(1) {
+                    error!("Error powering off the VM after watchdog expiry: {:?}", e);
+                }
+            }
+            WatchdogAction::Pause => {
+                if let Err(e) = self.vm_pause() {
+                    error!("Error pausing the VM after watchdog expiry: {:?}", e);
+                }
+            }
+            WatchdogAction::None => {}
+        }
+    }
+
     fn vm_shutdown(&mut self) -> result::Result<(), VmError> {
         if let Some(ref mut vm) = self.vm.take() {
             vm.shutdown()
@@ -730,6 +906,11 @@ impl Vmm {
 
         let exit_evt = self.exit_evt.try_clone().map_err(VmError::EventFdClone)?;
         let reset_evt = self.reset_evt.try_clone().map_err(VmError::EventFdClone)?;
+        let panic_evt = self.panic_evt.try_clone().map_err(VmError::EventFdClone)?;
+        let watchdog_evt = self
+            .watchdog_evt
+            .try_clone()
+            .map_err(VmError::EventFdClone)?;
         #[cfg(feature = "guest_debug")]
         let debug_evt = self
             .vm_debug_evt
@@ -752,6 +933,8 @@ impl Vmm {
             config,
             exit_evt,
             reset_evt,
+            panic_evt,
+            watchdog_evt,
             #[cfg(feature = "guest_debug")]
             debug_evt,
             &self.seccomp_action,
@@ -785,16 +968,45 @@ impl Vmm {
 
                 let mut memory_actual_size = config.lock().unwrap().memory.total_size();
                 if let Some(vm) = &self.vm {
+                    // The virtio-mem memory is only plugged or unplugged once
+                    // the guest driver processed the resize.
+                    let (requested, plugged) = vm.virtio_mem_size();
+                    memory_actual_size = memory_actual_size.saturating_sub(requested) + plugged;
                     memory_actual_size -= vm.balloon_size();
                 }
 
                 let device_tree = self.vm.as_ref().map(|vm| vm.device_tree());
 
+                let mut device_aliases = BTreeMap::new();
+                let mut devices = Vec::new();
+                if let Some(device_tree) = &device_tree {
+                    let device_tree = device_tree.lock().unwrap();
+                    for (alias, id) in config.lock().unwrap().device_aliases() {
+                        if let Some(bdf) = device_tree.pci_bdf(&id) {
+                            device_aliases.insert(alias, bdf);
+                        }
+                    }
+                    devices = device_tree
+                        .iter()
+                        .map(|(_, node)| DeviceInfo::from(node))
+                        .collect();
+                    devices.sort_by(|a, b| a.id.cmp(&b.id));
+                }
+
+                let (memory_zones, vcpus) = match &self.vm {
+                    Some(vm) => (vm.memory_zones_info(), vm.vcpus_info()),
+                    None => (Vec::new(), Vec::new()),
+                };
+
                 Ok(VmInfo {
                     config,
                     state,
                     memory_actual_size,
                     device_tree,
+                    device_aliases,
+                    devices,
+                    memory_zones,
+                    vcpus,
                 })
             }
             None => Err(VmError::VmNotCreated),
@@ -804,6 +1016,7 @@ impl Vmm {
     fn vmm_ping(&self) -> VmmPingResponse {
         VmmPingResponse {
             version: self.version.clone(),
+            api_version: api::api_version(),
         }
     }
 
@@ -824,6 +1037,42 @@ impl Vmm {
         Ok(())
     }
 
+    // Save what's needed to wake the guest up if it hibernated
+    fn vm_persist_hibernation(&self) -> result::Result<(), VmError> {
+        match &self.vm {
+            Some(vm) if vm.hibernated() => {}
+            _ => return Ok(()),
+        }
+
+        let config = self
+            .vm_config
+            .as_ref()
+            .ok_or(VmError::VmNotCreated)?
+            .lock()
+            .unwrap();
+        if let Some(hibernation_config) = &config.hibernation {
+            hibernation::save_config(&hibernation_config.path, &config)
+                .map_err(VmError::PersistHibernation)?;
+            event!("vm", "hibernated");
+        }
+
+        Ok(())
+    }
+
+    fn vmm_metrics(&self) -> String {
+        let mut metrics = String::new();
+        if let Some(ref vm) = self.vm {
+            match vm.counters() {
+                Ok(counters) => {
+                    metrics = metrics::vm_metrics(&counters, &vm.vcpus_exits(), self.dirty_rate)
+                }
+                Err(e) => error!("Error when getting counters from the VM: {:?}", e),
+            }
+        }
+
+        metrics
+    }
+
     fn vmm_shutdown(&mut self) -> result::Result<(), VmError> {
         self.vm_delete()?;
         event!("vmm", "shutdown");
@@ -832,20 +1081,26 @@ impl Vmm {
 
     fn vm_resize(
         &mut self,
-        desired_vcpus: Option<u8>,
+        desired_vcpus: Option<u32>,
         desired_ram: Option<u64>,
         desired_balloon: Option<u64>,
+        removed_vcpus: Option<Vec<u32>>,
     ) -> result::Result<(), VmError> {
         self.vm_config.as_ref().ok_or(VmError::VmNotCreated)?;
 
         if let Some(ref mut vm) = self.vm {
-            if let Err(e) = vm.resize(desired_vcpus, desired_ram, desired_balloon) {
+            if let Err(e) = vm.resize(desired_vcpus, desired_ram, desired_balloon, removed_vcpus) {
                 error!("Error when resizing VM: {:?}", e);
                 Err(e)
             } else {
                 Ok(())
             }
         } else {
+            // Ejecting specific vCPUs only makes sense on a running VM
+            if removed_vcpus.is_some() {
+                return Err(VmError::VmNotRunning);
+            }
+
             let mut config = self.vm_config.as_ref().unwrap().lock().unwrap();
             if let Some(desired_vcpus) = desired_vcpus {
                 config.cpus.boot_vcpus = desired_vcpus;
@@ -1124,10 +1379,13 @@ impl Vmm {
 
     fn vm_counters(&mut self) -> result::Result<Option<Vec<u8>>, VmError> {
         if let Some(ref mut vm) = self.vm {
-            let info = vm.counters().map_err(|e| {
+            let mut info = vm.counters().map_err(|e| {
                 error!("Error when getting counters from the VM: {:?}", e);
                 e
             })?;
+            if let Some(config) = &self.vm_config {
+                config.lock().unwrap().add_device_alias_entries(&mut info);
+            }
             serde_json::to_vec(&info)
                 .map(Some)
                 .map_err(VmError::SerializeJson)
@@ -1144,11 +1402,234 @@ impl Vmm {
         }
     }
 
+    fn vm_dirty_rate(
+        &mut self,
+        dirty_rate_data: &VmDirtyRateData,
+    ) -> result::Result<Option<Vec<u8>>, VmError> {
+        // Memory can't be migrated while it is being measured, keep the
+        // API responsive by bounding the period.
+        const MAX_DIRTY_RATE_PERIOD_MS: u64 = 60_000;
+
+        let period_ms = dirty_rate_data
+            .period_ms
+            .unwrap_or(DEFAULT_DIRTY_RATE_PERIOD_MS);
+        if period_ms == 0 || period_ms > MAX_DIRTY_RATE_PERIOD_MS {
+            return Err(VmError::InvalidDirtyRatePeriod(period_ms));
+        }
+
+        if let Some(ref mut vm) = self.vm {
+            let period = Duration::from_millis(period_ms);
+            let to_rate = |bytes: u64| bytes as f64 / 1_000_000.0 / period.as_secs_f64();
+            let regions: Vec<MemoryRegionDirtyRate> = vm
+                .dirty_memory(period)?
+                .into_iter()
+                .map(|(start, size, dirty)| MemoryRegionDirtyRate {
+                    start,
+                    size,
+                    dirty_rate: to_rate(dirty),
+                })
+                .collect();
+            let dirty_rate = VmDirtyRate {
+                period_ms,
+                dirty_rate: regions.iter().map(|r| r.dirty_rate).sum(),
+                regions,
+            };
+            self.dirty_rate = Some(dirty_rate.dirty_rate);
+            serde_json::to_vec(&dirty_rate)
+                .map(Some)
+                .map_err(VmError::SerializeJson)
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
+    // Runs a command through the agent listening on the vsock device of the
+    // guest, failing if it doesn't answer within `timeout`.
+    fn guest_agent_command<A: Serialize, R: serde::de::DeserializeOwned>(
+        &self,
+        command: &str,
+        arguments: &A,
+        timeout: Duration,
+    ) -> result::Result<R, VmError> {
+        if self.vm.is_none() {
+            return Err(VmError::VmNotRunning);
+        }
+
+        let socket = self
+            .vm_config
+            .as_ref()
+            .and_then(|config| {
+                config
+                    .lock()
+                    .unwrap()
+                    .vsock
+                    .as_ref()
+                    .map(|vsock| vsock.socket.clone())
+            })
+            .ok_or(VmError::GuestAgentNoVsock)?;
+
+        GuestAgent::connect(&socket, timeout)
+            .and_then(|mut agent| agent.execute(command, arguments))
+            .map_err(VmError::GuestAgent)
+    }
+
+    fn vm_guest_exec(
+        &self,
+        guest_exec_data: &VmGuestExecData,
+    ) -> result::Result<Option<Vec<u8>>, VmError> {
+        // The VMM waits for the program to complete, keep the API
+        // responsive by bounding the time it runs.
+        const MAX_GUEST_EXEC_TIMEOUT_MS: u64 = 60_000;
+
+        let timeout_ms = guest_exec_data
+            .timeout_ms
+            .unwrap_or(DEFAULT_GUEST_EXEC_TIMEOUT_MS);
+        if timeout_ms == 0 || timeout_ms > MAX_GUEST_EXEC_TIMEOUT_MS {
+            return Err(VmError::InvalidGuestExecTimeout(timeout_ms));
+        }
+
+        // The agent kills the program once the timeout expires, leave it
+        // some time to report how it ended.
+        let guest_exec_data = VmGuestExecData {
+            timeout_ms: Some(timeout_ms),
+            ..guest_exec_data.clone()
+        };
+        let result: VmGuestExec = self.guest_agent_command(
+            "guest-exec",
+            &guest_exec_data,
+            Duration::from_millis(timeout_ms) + GUEST_AGENT_TIMEOUT,
+        )?;
+        serde_json::to_vec(&result)
+            .map(Some)
+            .map_err(VmError::SerializeJson)
+    }
+
+    fn vm_guest_file_read(
+        &self,
+        guest_file_read_data: &VmGuestFileReadData,
+    ) -> result::Result<Option<Vec<u8>>, VmError> {
+        let result: VmGuestFileRead =
+            self.guest_agent_command("guest-file-read", guest_file_read_data, GUEST_AGENT_TIMEOUT)?;
+        serde_json::to_vec(&result)
+            .map(Some)
+            .map_err(VmError::SerializeJson)
+    }
+
+    fn vm_guest_file_write(
+        &self,
+        guest_file_write_data: &VmGuestFileWriteData,
+    ) -> result::Result<Option<Vec<u8>>, VmError> {
+        let result: VmGuestFileWrite = self.guest_agent_command(
+            "guest-file-write",
+            guest_file_write_data,
+            GUEST_AGENT_TIMEOUT,
+        )?;
+        serde_json::to_vec(&result)
+            .map(Some)
+            .map_err(VmError::SerializeJson)
+    }
+
+    fn vm_guest_shutdown(
+        &self,
+        guest_shutdown_data: &VmGuestShutdownData,
+    ) -> result::Result<(), VmError> {
+        // The agent acknowledges the request before shutting the guest down
+        self.guest_agent_command::<_, serde::de::IgnoredAny>(
+            "guest-shutdown",
+            guest_shutdown_data,
+            GUEST_AGENT_TIMEOUT,
+        )
+        .map(|_| ())
+    }
+
+    fn vm_vcpu_stats(&self) -> result::Result<Option<Vec<u8>>, VmError> {
+        if let Some(ref vm) = self.vm {
+            serde_json::to_vec(&vm.vcpus_stats())
+                .map(Some)
+                .map_err(VmError::SerializeJson)
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
+    fn vm_change_media(
+        &self,
+        change_media_data: &VmChangeMediaData,
+    ) -> result::Result<(), VmError> {
+        if let Some(ref vm) = self.vm {
+            vm.change_media(
+                &change_media_data.controller,
+                change_media_data.lun,
+                change_media_data.path.as_deref(),
+            )
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
+    fn vm_nmi(&self, nmi_data: &VmNmiData) -> result::Result<(), VmError> {
+        if let Some(ref vm) = self.vm {
+            vm.nmi(nmi_data.cpu)
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
+    fn vm_set_watchdog_action(
+        &self,
+        watchdog_action_data: &VmSetWatchdogActionData,
+    ) -> result::Result<(), VmError> {
+        // The action is looked up from the configuration on expiry, and kept
+        // across reboots.
+        if let Some(ref config) = self.vm_config {
+            config.lock().unwrap().watchdog_action = watchdog_action_data.action;
+            Ok(())
+        } else {
+            Err(VmError::VmNotCreated)
+        }
+    }
+
+    fn vm_send_keys(&self, send_keys_data: &VmSendKeysData) -> result::Result<(), VmError> {
+        if let Some(ref vm) = self.vm {
+            let hold_time = input_hold_time(send_keys_data.hold_time_ms)?;
+            vm.send_keys(&send_keys_data.keys, hold_time)
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
+    fn vm_send_pointer(
+        &self,
+        send_pointer_data: &VmSendPointerData,
+    ) -> result::Result<(), VmError> {
+        if let Some(ref vm) = self.vm {
+            let buttons =
+                send_pointer_data
+                    .buttons
+                    .iter()
+                    .fold(0u8, |buttons, button| match button {
+                        PointerButton::Left => buttons | 0x1,
+                        PointerButton::Right => buttons | 0x2,
+                        PointerButton::Middle => buttons | 0x4,
+                    });
+            let hold_time = input_hold_time(send_pointer_data.hold_time_ms)?;
+            vm.send_pointer(
+                send_pointer_data.dx,
+                send_pointer_data.dy,
+                buttons,
+                hold_time,
+            )
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
     fn vm_receive_config<T>(
         &mut self,
         req: &Request,
         socket: &mut T,
         existing_memory_files: Option<HashMap<u32, File>>,
+        copy_on_write: bool,
     ) -> std::result::Result<Arc<Mutex<MemoryManager>>, MigratableError>
     where
         T: Read + Write,
@@ -1169,15 +1650,24 @@ impl Vmm {
         self.vm_check_cpuid_compatibility(
             &vm_migration_config.vm_config,
             &vm_migration_config.common_cpuid,
+            &vm_migration_config.msr_features,
         )?;
 
         let config = vm_migration_config.vm_config.clone();
         self.vm_config = Some(vm_migration_config.vm_config);
 
+        #[cfg(target_arch = "aarch64")]
+        let mte_enabled = config.lock().unwrap().cpus.features.mte;
         let vm = Vm::create_hypervisor_vm(
             &self.hypervisor,
             #[cfg(feature = "tdx")]
             false,
+            #[cfg(feature = "sev_snp")]
+            false,
+            #[cfg(feature = "sev_snp")]
+            false,
+            #[cfg(target_arch = "aarch64")]
+            mte_enabled,
         )
         .map_err(|e| {
             MigratableError::MigrateReceive(anyhow!(
@@ -1195,8 +1685,13 @@ impl Vmm {
             phys_bits,
             #[cfg(feature = "tdx")]
             false,
+            #[cfg(feature = "sev_snp")]
+            false,
+            #[cfg(target_arch = "aarch64")]
+            mte_enabled,
             Some(&vm_migration_config.memory_manager_data),
             existing_memory_files,
+            copy_on_write,
             #[cfg(target_arch = "x86_64")]
             None,
         )
@@ -1237,6 +1732,12 @@ impl Vmm {
         let reset_evt = self.reset_evt.try_clone().map_err(|e| {
             MigratableError::MigrateReceive(anyhow!("Error cloning reset EventFd: {}", e))
         })?;
+        let panic_evt = self.panic_evt.try_clone().map_err(|e| {
+            MigratableError::MigrateReceive(anyhow!("Error cloning panic EventFd: {}", e))
+        })?;
+        let watchdog_evt = self.watchdog_evt.try_clone().map_err(|e| {
+            MigratableError::MigrateReceive(anyhow!("Error cloning watchdog EventFd: {}", e))
+        })?;
         #[cfg(feature = "guest_debug")]
         let debug_evt = self.vm_debug_evt.try_clone().map_err(|e| {
             MigratableError::MigrateReceive(anyhow!("Error cloning debug EventFd: {}", e))
@@ -1253,6 +1754,8 @@ impl Vmm {
             hypervisor_vm,
             exit_evt,
             reset_evt,
+            panic_evt,
+            watchdog_evt,
             #[cfg(feature = "guest_debug")]
             debug_evt,
             &self.seccomp_action,
@@ -1285,6 +1788,7 @@ impl Vmm {
         req: &Request,
         socket: &mut T,
         memory_manager: &mut MemoryManager,
+        compression: Option<Compression>,
     ) -> std::result::Result<(), MigratableError>
     where
         T: Read + Write,
@@ -1293,22 +1797,25 @@ impl Vmm {
         let table = MemoryRangeTable::read_from(socket, req.length())?;
 
         // And then read the memory itself
-        memory_manager
-            .receive_memory_regions(&table, socket)
-            .map_err(|e| {
-                Response::error().write_to(socket).ok();
-                e
-            })?;
+        let result = if let Some(compression) = compression {
+            receive_compressed_memory(socket, &memory_manager.guest_memory(), &table, compression)
+        } else {
+            memory_manager.receive_memory_regions(&table, socket)
+        };
+        result.map_err(|e| {
+            Response::error().write_to(socket).ok();
+            e
+        })?;
         Response::ok().write_to(socket)?;
         Ok(())
     }
 
-    fn socket_url_to_path(url: &str) -> result::Result<PathBuf, MigratableError> {
-        url.strip_prefix("unix:")
-            .ok_or_else(|| {
-                MigratableError::MigrateSend(anyhow!("Could not extract path from URL: {}", url))
-            })
-            .map(|s| s.into())
+    fn join_memory_stream(
+        stream: thread::JoinHandle<result::Result<(), MigratableError>>,
+    ) -> result::Result<(), MigratableError> {
+        stream.join().map_err(|_| {
+            MigratableError::MigrateReceive(anyhow!("Migration stream thread panicked"))
+        })?
     }
 
     fn vm_receive_migration(
@@ -1320,20 +1827,22 @@ impl Vmm {
             receive_data_migration.receiver_url
         );
 
-        let path = Self::socket_url_to_path(&receive_data_migration.receiver_url)?;
-        let listener = UnixListener::bind(&path).map_err(|e| {
-            MigratableError::MigrateReceive(anyhow!("Error binding to UNIX socket: {}", e))
-        })?;
-        let (mut socket, _addr) = listener.accept().map_err(|e| {
-            MigratableError::MigrateReceive(anyhow!("Error accepting on UNIX socket: {}", e))
-        })?;
-        std::fs::remove_file(&path).map_err(|e| {
-            MigratableError::MigrateReceive(anyhow!("Error unlinking UNIX socket: {}", e))
-        })?;
+        let tls = receive_data_migration
+            .tls_dir
+            .as_deref()
+            .map(tls_server_config)
+            .transpose()?;
+        let listener = MigrationListener::bind(&receive_data_migration.receiver_url, tls)?;
+        let mut socket = listener.accept()?;
+        event!("vm", "migration-receiving");
 
         let mut started = false;
         let mut memory_manager: Option<Arc<Mutex<MemoryManager>>> = None;
         let mut existing_memory_files = None;
+        let mut postcopy: Option<PostcopyReceiver> = None;
+        let mut streams = Vec::new();
+        let mut compression = None;
+        let mut copy_on_write = false;
         loop {
             let req = Request::read_from(&mut socket)?;
             match req.command() {
@@ -1356,6 +1865,7 @@ impl Vmm {
                         &req,
                         &mut socket,
                         existing_memory_files.take(),
+                        copy_on_write,
                     )?);
                 }
                 Command::State => {
@@ -1366,6 +1876,14 @@ impl Vmm {
                         Response::error().write_to(&mut socket)?;
                         continue;
                     }
+                    // All the memory has been received once the source
+                    // completed the memory streams.
+                    for stream in streams.drain(..) {
+                        Self::join_memory_stream(stream).map_err(|e| {
+                            Response::error().write_to(&mut socket).ok();
+                            e
+                        })?;
+                    }
                     if let Some(mm) = memory_manager.take() {
                         self.vm_receive_state(&req, &mut socket, mm)?;
                     } else {
@@ -1382,7 +1900,12 @@ impl Vmm {
                         continue;
                     }
                     if let Some(mm) = memory_manager.as_ref() {
-                        self.vm_receive_memory(&req, &mut socket, &mut mm.lock().unwrap())?;
+                        self.vm_receive_memory(
+                            &req,
+                            &mut socket,
+                            &mut mm.lock().unwrap(),
+                            compression,
+                        )?;
                     } else {
                         warn!("Configuration not sent yet");
                         Response::error().write_to(&mut socket)?;
@@ -1398,7 +1921,7 @@ impl Vmm {
                     }
 
                     let mut buf = [0u8; 4];
-                    let (_, file) = socket.recv_with_fd(&mut buf).map_err(|e| {
+                    let (_, file) = socket.unix()?.recv_with_fd(&mut buf).map_err(|e| {
                         MigratableError::MigrateReceive(anyhow!(
                             "Error receiving slot from socket: {}",
                             e
@@ -1416,11 +1939,98 @@ impl Vmm {
 
                     Response::ok().write_to(&mut socket)?;
                 }
+                Command::Streams => {
+                    info!("Streams Command Received");
+
+                    if !started {
+                        warn!("Migration not started yet");
+                        Response::error().write_to(&mut socket)?;
+                        continue;
+                    }
+                    let mut count = [0u8; 4];
+                    socket
+                        .read_exact(&mut count)
+                        .map_err(MigratableError::MigrateSocket)?;
+                    if let Some(mm) = memory_manager.as_ref() {
+                        let guest_memory = mm.lock().unwrap().guest_memory();
+                        Response::ok().write_to(&mut socket)?;
+                        for id in 0..u32::from_le_bytes(count) as usize {
+                            let stream = listener.accept()?;
+                            streams.push(receive_memory_stream(
+                                stream,
+                                guest_memory.clone(),
+                                id,
+                                compression,
+                            )?);
+                        }
+                    } else {
+                        warn!("Configuration not sent yet");
+                        Response::error().write_to(&mut socket)?;
+                    }
+                }
+                Command::Compression => {
+                    info!("Compression Command Received");
+
+                    if !started {
+                        warn!("Migration not started yet");
+                        Response::error().write_to(&mut socket)?;
+                        continue;
+                    }
+                    let mut algorithm = [0u8; 4];
+                    socket
+                        .read_exact(&mut algorithm)
+                        .map_err(MigratableError::MigrateSocket)?;
+                    match Compression::try_from(u32::from_le_bytes(algorithm)) {
+                        Ok(c) => {
+                            compression = Some(c);
+                            Response::ok().write_to(&mut socket)?;
+                        }
+                        Err(e) => {
+                            warn!("{}", e);
+                            Response::error().write_to(&mut socket)?;
+                        }
+                    }
+                }
+                Command::Clone => {
+                    info!("Clone Command Received");
+
+                    if !started {
+                        warn!("Migration not started yet");
+                        Response::error().write_to(&mut socket)?;
+                        continue;
+                    }
+                    copy_on_write = true;
+
+                    Response::ok().write_to(&mut socket)?;
+                }
+                Command::Postcopy => {
+                    info!("Postcopy Command Received");
+
+                    if !started {
+                        warn!("Migration not started yet");
+                        Response::error().write_to(&mut socket)?;
+                        continue;
+                    }
+                    if let Some(vm) = self.vm.as_ref() {
+                        let table = MemoryRangeTable::read_from(&mut socket, req.length())?;
+                        postcopy = Some(PostcopyReceiver::new(vm.guest_memory(), &table).map_err(
+                            |e| {
+                                Response::error().write_to(&mut socket).ok();
+                                e
+                            },
+                        )?);
+                        Response::ok().write_to(&mut socket)?;
+                    } else {
+                        warn!("VM not created yet");
+                        Response::error().write_to(&mut socket)?;
+                    }
+                }
                 Command::Complete => {
                     info!("Complete Command Received");
                     if let Some(ref mut vm) = self.vm.as_mut() {
                         vm.resume()?;
                         Response::ok().write_to(&mut socket)?;
+                        event!("vm", "migration-received");
                     } else {
                         warn!("VM not created yet");
                         Response::error().write_to(&mut socket)?;
@@ -1429,6 +2039,8 @@ impl Vmm {
                 }
                 Command::Abandon => {
                     info!("Abandon Command Received");
+                    event!("vm", "migration-abandoned");
+                    postcopy = None;
                     self.vm = None;
                     self.vm_config = None;
                     Response::ok().write_to(&mut socket).ok();
@@ -1437,40 +2049,233 @@ impl Vmm {
             }
         }
 
+        // The VM is running, the missing memory must now be fetched from the
+        // source. Failing to do so leaves the guest without parts of its
+        // memory, there's no other choice than shutting it down.
+        if let Some(postcopy) = postcopy {
+            let exit_evt = self.exit_evt.try_clone().map_err(|e| {
+                MigratableError::MigrateReceive(anyhow!("Error cloning exit EventFd: {}", e))
+            })?;
+            thread::Builder::new()
+                .name("postcopy".to_string())
+                .spawn(move || {
+                    if let Err(e) = postcopy.run(&mut socket) {
+                        error!("Postcopy migration failed: {:?}", e);
+                        exit_evt.write(1).ok();
+                    }
+                })
+                .map_err(|e| {
+                    MigratableError::MigrateReceive(anyhow!(
+                        "Error spawning postcopy thread: {}",
+                        e
+                    ))
+                })?;
+        }
+
         Ok(())
     }
 
-    // Returns true if there were dirty pages to send
-    fn vm_maybe_send_dirty_pages<T>(
+    // Send memory over the additional streams if any, or over the main
+    // connection otherwise, compressing it through the pool if provided.
+    fn vm_send_memory<T>(
         vm: &mut Vm,
         socket: &mut T,
-    ) -> result::Result<bool, MigratableError>
+        streams: Option<&mut MemoryStreams>,
+        compression: Option<&CompressionPool>,
+        table: &MemoryRangeTable,
+    ) -> result::Result<(), MigratableError>
     where
         T: Read + Write,
     {
-        // Send (dirty) memory table
-        let table = vm.dirty_log()?;
-
-        // But if there are no regions go straight to pause
-        if table.regions().is_empty() {
-            return Ok(false);
-        }
+        let result = if let Some(streams) = streams {
+            streams.send(&vm.guest_memory(), table)
+        } else {
+            Request::memory(table.length()).write_to(socket)?;
+            table.write_to(socket)?;
+            // And then the memory itself
+            if let Some(compression) = compression {
+                compression.send_memory(socket, &vm.guest_memory(), table)?;
+            } else {
+                vm.send_memory_regions(table, socket)?;
+            }
+            let res = Response::read_from(socket)?;
+            if res.status() != Status::Ok {
+                Err(MigratableError::MigrateSend(anyhow!(
+                    "Error during memory migration"
+                )))
+            } else {
+                Ok(())
+            }
+        };
 
-        Request::memory(table.length()).write_to(socket).unwrap();
-        table.write_to(socket)?;
-        // And then the memory itself
-        vm.send_memory_regions(&table, socket)?;
-        let res = Response::read_from(socket)?;
-        if res.status() != Status::Ok {
-            warn!("Error during dirty memory migration");
+        if result.is_err() {
+            warn!("Error during memory migration");
             Request::abandon().write_to(socket)?;
             Response::read_from(socket).ok();
-            return Err(MigratableError::MigrateSend(anyhow!(
-                "Error during dirty memory migration"
-            )));
         }
 
-        Ok(true)
+        result
+    }
+
+    // Returns the amount of dirty memory sent
+    fn vm_maybe_send_dirty_pages<T>(
+        vm: &mut Vm,
+        socket: &mut T,
+        streams: Option<&mut MemoryStreams>,
+        compression: Option<&CompressionPool>,
+    ) -> result::Result<u64, MigratableError>
+    where
+        T: Read + Write,
+    {
+        // Send (dirty) memory table
+        let table = vm.dirty_log()?;
+
+        // But if there are no regions go straight to pause
+        if table.regions().is_empty() {
+            return Ok(0);
+        }
+
+        Self::vm_send_memory(vm, socket, streams, compression, &table)?;
+
+        Ok(table.memory_size())
+    }
+
+    // Send the memory requested by the destination until it notifies the
+    // migration is complete.
+    fn vm_serve_postcopy<T>(vm: &mut Vm, socket: &mut T) -> result::Result<(), MigratableError>
+    where
+        T: Read + Write,
+    {
+        loop {
+            let req = Request::read_from(socket)?;
+            match req.command() {
+                Command::Memory => {
+                    let table = MemoryRangeTable::read_from(socket, req.length())?;
+                    vm.send_memory_regions(&table, socket)?;
+                }
+                Command::Complete => {
+                    Response::ok().write_to(socket)?;
+                    break;
+                }
+                _ => {
+                    return Err(MigratableError::MigrateSend(anyhow!(
+                        "Unexpected command during postcopy migration"
+                    )));
+                }
+            }
+        }
+        info!("Postcopy migration complete");
+
+        Ok(())
+    }
+
+    // Throttle the vCPUs further if the memory gets dirtied faster than it
+    // can be sent. The memory dirtied while sending a pass is compared with
+    // the memory sent during that pass, both measured over the same time.
+    // Returns true once the dirty memory left can be sent quickly enough for
+    // the VM to be paused.
+    fn vm_auto_converge(
+        vm: &mut Vm,
+        pass: usize,
+        sent: u64,
+        dirty: u64,
+        elapsed: Duration,
+    ) -> result::Result<bool, MigratableError> {
+        const INITIAL_THROTTLE: u8 = 20;
+        const THROTTLE_INCREMENT: u8 = 10;
+        // Downtime expected from sending the last pass with the VM paused
+        const MAX_DOWNTIME: Duration = Duration::from_millis(100);
+
+        let bandwidth = sent as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+        let dirty_rate = dirty as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+
+        let mut throttle = vm.vcpu_throttle();
+        if dirty_rate > bandwidth {
+            throttle = if throttle == 0 {
+                INITIAL_THROTTLE
+            } else {
+                throttle.saturating_add(THROTTLE_INCREMENT)
+            };
+            vm.set_vcpu_throttle(throttle).map_err(|e| {
+                MigratableError::MigrateSend(anyhow!("Error throttling vCPUs: {:?}", e))
+            })?;
+            throttle = vm.vcpu_throttle();
+        }
+
+        info!(
+            "Migration pass {}: {} bytes sent at {:.0} B/s, {:.0} B/s dirtied, vCPUs throttled {}%",
+            pass, sent, bandwidth, dirty_rate, throttle
+        );
+        event!(
+            "vm",
+            "migration-progress",
+            "pass",
+            pass.to_string(),
+            "bandwidth",
+            (bandwidth as u64).to_string(),
+            "dirty_rate",
+            (dirty_rate as u64).to_string(),
+            "throttle",
+            throttle.to_string()
+        );
+
+        Ok(dirty as f64 <= bandwidth * MAX_DOWNTIME.as_secs_f64())
+    }
+
+    // Gather the configuration the destination needs to create the VM,
+    // including the CPU features it must be compatible with.
+    fn vm_migration_config(
+        vm: &Vm,
+        #[cfg(all(feature = "kvm", target_arch = "x86_64"))] hypervisor: Arc<
+            dyn hypervisor::Hypervisor,
+        >,
+    ) -> result::Result<VmMigrationConfig, MigratableError> {
+        let vm_config = vm.get_config();
+        #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+        let common_cpuid = {
+            let vm_config = vm_config.lock().unwrap();
+            let phys_bits = vm::physical_bits(vm_config.cpus.max_phys_bits);
+            arch::generate_common_cpuid(
+                &hypervisor,
+                arch::CpuidConfig {
+                    phys_bits,
+                    kvm_hyperv: vm_config.cpus.kvm_hyperv,
+                    cpu_model: vm_config.cpus.model.clone(),
+                    enabled_features: vm_config.cpus.features.cpuid_enable.clone(),
+                    disabled_features: vm_config.cpus.features.cpuid_disable.clone(),
+                    amx: vm_config.cpus.features.amx,
+                    disabled_kvm_features: vm_config.cpus.features.kvm_disable.clone(),
+                    hybrid: vm_config
+                        .cpus
+                        .topology
+                        .as_ref()
+                        .map_or(false, |t| t.efficiency_cores_per_die > 0),
+                    #[cfg(feature = "sev_snp")]
+                    sev_snp: vm_config.is_sev_snp_enabled(),
+                    #[cfg(feature = "sev_snp")]
+                    sev_es: vm_config.is_sev_es_enabled(),
+                    #[cfg(feature = "tdx")]
+                    tdx: vm_config.is_tdx_enabled(),
+                    ..Default::default()
+                },
+            )
+            .map_err(|e| {
+                MigratableError::MigrateReceive(anyhow!("Error generating common cpuid': {:?}", e))
+            })?
+        };
+        #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+        let msr_features = arch::generate_msr_features(&hypervisor).map_err(|e| {
+            MigratableError::MigrateSend(anyhow!("Error getting feature MSRs: {:?}", e))
+        })?;
+
+        Ok(VmMigrationConfig {
+            vm_config,
+            #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+            common_cpuid,
+            #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+            msr_features,
+            memory_manager_data: vm.memory_manager_data(),
+        })
     }
 
     fn send_migration(
@@ -1478,72 +2283,101 @@ impl Vmm {
         #[cfg(all(feature = "kvm", target_arch = "x86_64"))] hypervisor: Arc<
             dyn hypervisor::Hypervisor,
         >,
+        socket: &mut MigrationSocket,
+        tls: Option<&Arc<rustls::ClientConfig>>,
         send_data_migration: VmSendMigrationData,
     ) -> result::Result<(), MigratableError> {
-        let path = Self::socket_url_to_path(&send_data_migration.destination_url)?;
-        let mut socket = UnixStream::connect(path).map_err(|e| {
-            MigratableError::MigrateSend(anyhow!("Error connecting to UNIX socket: {}", e))
-        })?;
-
         // Start the migration
-        Request::start().write_to(&mut socket)?;
-        let res = Response::read_from(&mut socket)?;
+        Request::start().write_to(socket)?;
+        let res = Response::read_from(socket)?;
         if res.status() != Status::Ok {
             warn!("Error starting migration");
-            Request::abandon().write_to(&mut socket)?;
-            Response::read_from(&mut socket).ok();
+            Request::abandon().write_to(socket)?;
+            Response::read_from(socket).ok();
             return Err(MigratableError::MigrateSend(anyhow!(
                 "Error starting migration"
             )));
         }
 
-        // Send config
-        let vm_config = vm.get_config();
-        #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
-        let common_cpuid = {
-            let phys_bits = vm::physical_bits(vm_config.lock().unwrap().cpus.max_phys_bits);
-            arch::generate_common_cpuid(
-                &hypervisor,
-                None,
-                None,
-                phys_bits,
-                vm_config.lock().unwrap().cpus.kvm_hyperv,
-                #[cfg(feature = "tdx")]
-                vm_config.lock().unwrap().is_tdx_enabled(),
-            )
-            .map_err(|e| {
-                MigratableError::MigrateReceive(anyhow!("Error generating common cpuid': {:?}", e))
-            })?
-        };
+        // Negotiate the compression of the memory, falling back to sending
+        // it uncompressed if the destination doesn't support the algorithm.
+        let mut compression = None;
+        if let Some(algorithm) = send_data_migration.compression {
+            Request::compression(4).write_to(socket)?;
+            socket
+                .write_all(&(algorithm as u32).to_le_bytes())
+                .map_err(MigratableError::MigrateSocket)?;
+            let res = Response::read_from(socket)?;
+            if res.status() == Status::Ok {
+                compression = Some(algorithm);
+            } else {
+                warn!(
+                    "Destination doesn't support {:?} compression, sending memory uncompressed",
+                    algorithm
+                );
+            }
+        }
 
         if send_data_migration.local {
-            vm.send_memory_fds(&mut socket)?;
+            vm.send_memory_fds(socket.unix()?)?;
         }
 
-        let vm_migration_config = VmMigrationConfig {
-            vm_config,
+        // Send config
+        let vm_migration_config = Self::vm_migration_config(
+            vm,
             #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
-            common_cpuid,
-            memory_manager_data: vm.memory_manager_data(),
-        };
+            hypervisor,
+        )?;
         let config_data = serde_json::to_vec(&vm_migration_config).unwrap();
-        Request::config(config_data.len() as u64).write_to(&mut socket)?;
+        Request::config(config_data.len() as u64).write_to(socket)?;
         socket
             .write_all(&config_data)
             .map_err(MigratableError::MigrateSocket)?;
-        let res = Response::read_from(&mut socket)?;
+        let res = Response::read_from(socket)?;
         if res.status() != Status::Ok {
             warn!("Error during config migration");
-            Request::abandon().write_to(&mut socket)?;
-            Response::read_from(&mut socket).ok();
+            Request::abandon().write_to(socket)?;
+            Response::read_from(socket).ok();
             return Err(MigratableError::MigrateSend(anyhow!(
                 "Error during config migration"
             )));
         }
 
+        // Open the additional connections transferring the memory
+        let mut streams = None;
+        if send_data_migration.connections > 1 {
+            Request::streams(4).write_to(socket)?;
+            socket
+                .write_all(&send_data_migration.connections.to_le_bytes())
+                .map_err(MigratableError::MigrateSocket)?;
+            let res = Response::read_from(socket)?;
+            if res.status() != Status::Ok {
+                warn!("Error setting up migration streams");
+                Request::abandon().write_to(socket)?;
+                Response::read_from(socket).ok();
+                return Err(MigratableError::MigrateSend(anyhow!(
+                    "Error setting up migration streams"
+                )));
+            }
+            streams = Some(MemoryStreams::connect(
+                &send_data_migration.destination_url,
+                send_data_migration.connections,
+                tls,
+                compression,
+            )?);
+        }
+
+        // Each stream compresses its own memory, otherwise the compression
+        // is spread across a pool of threads.
+        let compression_pool = match compression {
+            Some(compression) if streams.is_none() => Some(CompressionPool::new(compression)?),
+            _ => None,
+        };
+
         // Let every Migratable object know about the migration being started.
         vm.start_migration()?;
 
+        let mut postcopy_table = None;
         if send_data_migration.local {
             // Now pause VM
             vm.pause()?;
@@ -1553,64 +2387,115 @@ impl Vmm {
 
             // Send memory table
             let table = vm.memory_range_table()?;
-            Request::memory(table.length())
-                .write_to(&mut socket)
-                .unwrap();
-            table.write_to(&mut socket)?;
-            // And then the memory itself
-            vm.send_memory_regions(&table, &mut socket)?;
-            let res = Response::read_from(&mut socket)?;
-            if res.status() != Status::Ok {
-                warn!("Error during memory migration");
-                Request::abandon().write_to(&mut socket)?;
-                Response::read_from(&mut socket).ok();
-                return Err(MigratableError::MigrateSend(anyhow!(
-                    "Error during memory migration"
-                )));
-            }
+            let mut pass_start = Instant::now();
+            Self::vm_send_memory(
+                vm,
+                socket,
+                streams.as_mut(),
+                compression_pool.as_ref(),
+                &table,
+            )?;
+            let mut sent = table.memory_size();
+
+            if send_data_migration.postcopy {
+                // Switch to the destination right after the first pass, the
+                // memory dirtied in the meantime will be fetched on demand.
+                vm.pause()?;
+                postcopy_table = Some(vm.dirty_log()?);
+            } else {
+                // Try at most 5 passes of dirty memory sending, unless the
+                // vCPUs get throttled until the migration converges.
+                const MAX_DIRTY_MIGRATIONS: usize = 5;
+                const MAX_AUTO_CONVERGE_MIGRATIONS: usize = 30;
+                let max_passes = if send_data_migration.auto_converge {
+                    MAX_AUTO_CONVERGE_MIGRATIONS
+                } else {
+                    MAX_DIRTY_MIGRATIONS
+                };
+                for i in 0..max_passes {
+                    info!("Dirty memory migration {} of {}", i, max_passes);
+                    let elapsed = pass_start.elapsed();
+                    pass_start = Instant::now();
+                    let dirty = Self::vm_maybe_send_dirty_pages(
+                        vm,
+                        socket,
+                        streams.as_mut(),
+                        compression_pool.as_ref(),
+                    )?;
+                    if dirty == 0 {
+                        break;
+                    }
 
-            // Try at most 5 passes of dirty memory sending
-            const MAX_DIRTY_MIGRATIONS: usize = 5;
-            for i in 0..MAX_DIRTY_MIGRATIONS {
-                info!("Dirty memory migration {} of {}", i, MAX_DIRTY_MIGRATIONS);
-                if !Self::vm_maybe_send_dirty_pages(vm, &mut socket)? {
-                    break;
+                    if send_data_migration.auto_converge
+                        && Self::vm_auto_converge(vm, i, sent, dirty, elapsed)?
+                    {
+                        break;
+                    }
+                    sent = dirty;
                 }
-            }
 
-            // Now pause VM
-            vm.pause()?;
+                // Now pause VM
+                vm.pause()?;
 
-            // Send last batch of dirty pages
-            Self::vm_maybe_send_dirty_pages(vm, &mut socket)?;
+                // Send last batch of dirty pages
+                Self::vm_maybe_send_dirty_pages(
+                    vm,
+                    socket,
+                    streams.as_mut(),
+                    compression_pool.as_ref(),
+                )?;
+            }
 
             // Stop logging dirty pages
             vm.stop_dirty_log()?;
         }
+        vm.set_vcpu_throttle(0).map_err(|e| {
+            MigratableError::MigrateSend(anyhow!("Error lifting vCPU throttling: {:?}", e))
+        })?;
+
+        // All the memory has been sent
+        if let Some(streams) = streams {
+            streams.complete()?;
+        }
         // Capture snapshot and send it
         let vm_snapshot = vm.snapshot()?;
         let snapshot_data = serde_json::to_vec(&vm_snapshot).unwrap();
-        Request::state(snapshot_data.len() as u64).write_to(&mut socket)?;
+        Request::state(snapshot_data.len() as u64).write_to(socket)?;
         socket
             .write_all(&snapshot_data)
             .map_err(MigratableError::MigrateSocket)?;
-        let res = Response::read_from(&mut socket)?;
+        let res = Response::read_from(socket)?;
         if res.status() != Status::Ok {
             warn!("Error during state migration");
-            Request::abandon().write_to(&mut socket)?;
-            Response::read_from(&mut socket).ok();
+            Request::abandon().write_to(socket)?;
+            Response::read_from(socket).ok();
             return Err(MigratableError::MigrateSend(anyhow!(
                 "Error during state migration"
             )));
         }
 
+        // Send the memory missing on the destination
+        if let Some(table) = postcopy_table {
+            Request::postcopy(table.length()).write_to(socket)?;
+            table.write_to(socket)?;
+            let res = Response::read_from(socket)?;
+            if res.status() != Status::Ok {
+                warn!("Error setting up postcopy migration");
+                Request::abandon().write_to(socket)?;
+                Response::read_from(socket).ok();
+                return Err(MigratableError::MigrateSend(anyhow!(
+                    "Error setting up postcopy migration"
+                )));
+            }
+        }
+
         // Complete the migration
-        Request::complete().write_to(&mut socket)?;
-        let res = Response::read_from(&mut socket)?;
+        Request::complete().write_to(socket)?;
+        let res = Response::read_from(socket)?;
         if res.status() != Status::Ok {
             warn!("Error completing migration");
-            Request::abandon().write_to(&mut socket)?;
-            Response::read_from(&mut socket).ok();
+            Request::abandon().write_to(socket)?;
+            Response::read_from(socket).ok();
             return Err(MigratableError::MigrateSend(anyhow!(
                 "Error completing migration"
             )));
@@ -1626,8 +2511,11 @@ impl Vmm {
         send_data_migration: VmSendMigrationData,
     ) -> result::Result<(), MigratableError> {
         info!(
-            "Sending migration: destination_url = {}, local = {}",
-            send_data_migration.destination_url, send_data_migration.local
+            "Sending migration: destination_url = {}, local = {}, postcopy = {}, connections = {}",
+            send_data_migration.destination_url,
+            send_data_migration.local,
+            send_data_migration.postcopy,
+            send_data_migration.connections
         );
 
         if !self
@@ -1644,21 +2532,70 @@ impl Vmm {
             )));
         }
 
+        if send_data_migration.local && send_data_migration.compression.is_some() {
+            return Err(MigratableError::MigrateSend(anyhow!(
+                "Local migration doesn't send memory, it can't be compressed"
+            )));
+        }
+
+        if send_data_migration.local && send_data_migration.connections > 1 {
+            return Err(MigratableError::MigrateSend(anyhow!(
+                "Local migration can't use multiple connections"
+            )));
+        }
+
+        // Missing pages are discarded on the destination, which only works
+        // for private anonymous memory.
+        if send_data_migration.postcopy
+            && (send_data_migration.local
+                || self
+                    .vm_config
+                    .as_ref()
+                    .unwrap()
+                    .lock()
+                    .unwrap()
+                    .backed_by_shared_memory())
+        {
+            return Err(MigratableError::MigrateSend(anyhow!(
+                "Postcopy migration requires private memory without hugepages"
+            )));
+        }
+
         if let Some(vm) = self.vm.as_mut() {
+            let tls = send_data_migration
+                .tls_dir
+                .as_deref()
+                .map(tls_client_config)
+                .transpose()?;
+            let mut socket =
+                MigrationSocket::connect(&send_data_migration.destination_url, tls.as_ref())?;
+            let postcopy = send_data_migration.postcopy;
+
+            event!("vm", "migration-started");
             Self::send_migration(
                 vm,
                 #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
                 self.hypervisor.clone(),
+                &mut socket,
+                tls.as_ref(),
                 send_data_migration,
             )
             .map_err(|migration_err| {
                 error!("Migration failed: {:?}", migration_err);
+                event!("vm", "migration-failed");
 
                 // Stop logging dirty pages
                 if let Err(e) = vm.stop_dirty_log() {
                     return e;
                 }
 
+                if let Err(e) = vm.set_vcpu_throttle(0) {
+                    return MigratableError::MigrateSend(anyhow!(
+                        "Error lifting vCPU throttling: {:?}",
+                        e
+                    ));
+                }
+
                 if vm.get_state().unwrap() == VmState::Paused {
                     if let Err(e) = vm.resume() {
                         return e;
@@ -1668,23 +2605,144 @@ impl Vmm {
                 migration_err
             })?;
 
+            // The VM now runs on the destination, so the source must be shut
+            // down even if some memory couldn't be sent.
+            let postcopy_result = if postcopy {
+                Self::vm_serve_postcopy(vm, &mut socket)
+            } else {
+                Ok(())
+            };
+            if postcopy_result.is_ok() {
+                event!("vm", "migration-completed");
+            }
+
             // Shutdown the VM after the migration succeeded
             self.exit_evt.write(1).map_err(|e| {
                 MigratableError::MigrateSend(anyhow!(
                     "Failed shutting down the VM after migration: {:?}",
                     e
                 ))
-            })
+            })?;
+
+            postcopy_result
         } else {
             Err(MigratableError::MigrateSend(anyhow!("VM is not running")))
         }
     }
 
+    fn vm_clone(&mut self, clone_data: VmCloneData) -> result::Result<(), MigratableError> {
+        info!(
+            "Cloning VM: destination_url = {}",
+            clone_data.destination_url
+        );
+
+        if !self
+            .vm_config
+            .as_ref()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .backed_by_shared_memory()
+        {
+            return Err(MigratableError::MigrateSend(anyhow!(
+                "Cloning requires shared memory or hugepages enabled"
+            )));
+        }
+
+        let vm = match self.vm.as_mut() {
+            Some(vm) => vm,
+            None => return Err(MigratableError::MigrateSend(anyhow!("VM is not running"))),
+        };
+
+        // The clone shares the memory of the VM, which must not change
+        // anymore for as long as the clone is running.
+        if vm.get_state().unwrap() != VmState::Paused {
+            return Err(MigratableError::MigrateSend(anyhow!(
+                "Cloning requires the VM to be paused"
+            )));
+        }
+
+        let mut socket = MigrationSocket::connect(&clone_data.destination_url, None)?;
+
+        Request::start().write_to(&mut socket)?;
+        let res = Response::read_from(&mut socket)?;
+        if res.status() != Status::Ok {
+            warn!("Error starting clone");
+            Request::abandon().write_to(&mut socket)?;
+            Response::read_from(&mut socket).ok();
+            return Err(MigratableError::MigrateSend(anyhow!(
+                "Error starting clone"
+            )));
+        }
+
+        Request::clone_vm().write_to(&mut socket)?;
+        let res = Response::read_from(&mut socket)?;
+        if res.status() != Status::Ok {
+            warn!("Destination doesn't support cloning");
+            Request::abandon().write_to(&mut socket)?;
+            Response::read_from(&mut socket).ok();
+            return Err(MigratableError::MigrateSend(anyhow!(
+                "Destination doesn't support cloning"
+            )));
+        }
+
+        vm.send_memory_fds(socket.unix()?)?;
+
+        let vm_migration_config = Self::vm_migration_config(
+            vm,
+            #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+            self.hypervisor.clone(),
+        )?;
+        let config_data = serde_json::to_vec(&vm_migration_config).unwrap();
+        Request::config(config_data.len() as u64).write_to(&mut socket)?;
+        socket
+            .write_all(&config_data)
+            .map_err(MigratableError::MigrateSocket)?;
+        let res = Response::read_from(&mut socket)?;
+        if res.status() != Status::Ok {
+            warn!("Error during config clone");
+            Request::abandon().write_to(&mut socket)?;
+            Response::read_from(&mut socket).ok();
+            return Err(MigratableError::MigrateSend(anyhow!(
+                "Error during config clone"
+            )));
+        }
+
+        let vm_snapshot = vm.snapshot()?;
+        let snapshot_data = serde_json::to_vec(&vm_snapshot).unwrap();
+        Request::state(snapshot_data.len() as u64).write_to(&mut socket)?;
+        socket
+            .write_all(&snapshot_data)
+            .map_err(MigratableError::MigrateSocket)?;
+        let res = Response::read_from(&mut socket)?;
+        if res.status() != Status::Ok {
+            warn!("Error during state clone");
+            Request::abandon().write_to(&mut socket)?;
+            Response::read_from(&mut socket).ok();
+            return Err(MigratableError::MigrateSend(anyhow!(
+                "Error during state clone"
+            )));
+        }
+
+        Request::complete().write_to(&mut socket)?;
+        let res = Response::read_from(&mut socket)?;
+        if res.status() != Status::Ok {
+            warn!("Error completing clone");
+            return Err(MigratableError::MigrateSend(anyhow!(
+                "Error completing clone"
+            )));
+        }
+        info!("Clone complete");
+
+        Ok(())
+    }
+
     #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
     fn vm_check_cpuid_compatibility(
         &self,
         src_vm_config: &Arc<Mutex<VmConfig>>,
         src_vm_cpuid: &[hypervisor::arch::x86::CpuIdEntry],
+        src_vm_msr_features: &[hypervisor::arch::x86::MsrEntry],
     ) -> result::Result<(), MigratableError> {
         // We check the `CPUID` compatibility of between the source vm and destination, which is
         // mostly about feature compatibility and "topology/sgx" leaves are not relevant.
@@ -1694,23 +2752,51 @@ impl Vmm {
             let phys_bits = vm::physical_bits(vm_config.cpus.max_phys_bits);
             arch::generate_common_cpuid(
                 &self.hypervisor.clone(),
-                None,
-                None,
-                phys_bits,
-                vm_config.cpus.kvm_hyperv,
-                #[cfg(feature = "tdx")]
-                vm_config.is_tdx_enabled(),
+                arch::CpuidConfig {
+                    phys_bits,
+                    kvm_hyperv: vm_config.cpus.kvm_hyperv,
+                    cpu_model: vm_config.cpus.model.clone(),
+                    enabled_features: vm_config.cpus.features.cpuid_enable.clone(),
+                    disabled_features: vm_config.cpus.features.cpuid_disable.clone(),
+                    amx: vm_config.cpus.features.amx,
+                    disabled_kvm_features: vm_config.cpus.features.kvm_disable.clone(),
+                    hybrid: vm_config
+                        .cpus
+                        .topology
+                        .as_ref()
+                        .map_or(false, |t| t.efficiency_cores_per_die > 0),
+                    #[cfg(feature = "sev_snp")]
+                    sev_snp: vm_config.is_sev_snp_enabled(),
+                    #[cfg(feature = "sev_snp")]
+                    sev_es: vm_config.is_sev_es_enabled(),
+                    #[cfg(feature = "tdx")]
+                    tdx: vm_config.is_tdx_enabled(),
+                    ..Default::default()
+                },
             )
             .map_err(|e| {
                 MigratableError::MigrateReceive(anyhow!("Error generating common cpuid: {:?}", e))
             })?
         };
-        arch::CpuidFeatureEntry::check_cpuid_compatibility(src_vm_cpuid, dest_cpuid).map_err(|e| {
-            MigratableError::MigrateReceive(anyhow!(
-                "Error checking cpu feature compatibility': {:?}",
-                e
-            ))
-        })
+        arch::CpuidFeatureEntry::check_cpuid_compatibility(src_vm_cpuid, dest_cpuid).map_err(
+            |e| {
+                MigratableError::MigrateReceive(anyhow!(
+                    "Error checking cpu feature compatibility': {:?}",
+                    e
+                ))
+            },
+        )?;
+
+        let dest_msr_features = arch::generate_msr_features(&self.hypervisor).map_err(|e| {
+            MigratableError::MigrateReceive(anyhow!("Error getting feature MSRs: {:?}", e))
+        })?;
+        arch::CpuidFeatureEntry::check_msr_compatibility(src_vm_msr_features, &dest_msr_features)
+            .map_err(|e| {
+                MigratableError::MigrateReceive(anyhow!(
+                    "Error checking MSR feature compatibility: {:?}",
+                    e
+                ))
+            })
     }
 
     fn control_loop(
@@ -1752,6 +2838,9 @@ impl Vmm {
                         info!("VM exit event");
                         // Consume the event.
                         self.exit_evt.read().map_err(Error::EventFdRead)?;
+                        if let Err(e) = self.vm_persist_hibernation() {
+                            error!("Error persisting the hibernated VM: {:?}", e);
+                        }
                         self.vmm_shutdown().map_err(Error::VmmShutdown)?;
 
                         break 'outer;
@@ -1762,6 +2851,16 @@ impl Vmm {
                         self.reset_evt.read().map_err(Error::EventFdRead)?;
                         self.vm_reboot().map_err(Error::VmReboot)?;
                     }
+                    EpollDispatch::Panic => {
+                        // Consume the event.
+                        self.panic_evt.read().map_err(Error::EventFdRead)?;
+                        self.vm_panic();
+                    }
+                    EpollDispatch::Watchdog => {
+                        // Consume the event.
+                        self.watchdog_evt.read().map_err(Error::EventFdRead)?;
+                        self.vm_watchdog_expired();
+                    }
                     EpollDispatch::ActivateVirtioDevices => {
                         if let Some(ref vm) = self.vm {
                             let count = self.activate_evt.read().map_err(Error::EventFdRead)?;
@@ -1834,6 +2933,12 @@ impl Vmm {
 
                                     sender.send(Ok(response)).map_err(Error::ApiResponseSend)?;
                                 }
+                                ApiRequest::VmmMetrics(sender) => {
+                                    let response =
+                                        ApiResponsePayload::VmmMetrics(self.vmm_metrics());
+
+                                    sender.send(Ok(response)).map_err(Error::ApiResponseSend)?;
+                                }
                                 ApiRequest::VmPause(sender) => {
                                     let response = self
                                         .vm_pause()
@@ -1891,6 +2996,7 @@ impl Vmm {
                                             resize_data.desired_vcpus,
                                             resize_data.desired_ram,
                                             resize_data.desired_balloon,
+                                            resize_data.removed_vcpus,
                                         )
                                         .map_err(ApiError::VmResize)
                                         .map(|_| ApiResponsePayload::Empty);
@@ -1992,6 +3098,76 @@ impl Vmm {
                                         .map(|_| ApiResponsePayload::Empty);
                                     sender.send(response).map_err(Error::ApiResponseSend)?;
                                 }
+                                ApiRequest::VmClone(clone_data, sender) => {
+                                    let response = self
+                                        .vm_clone(clone_data.as_ref().clone())
+                                        .map_err(ApiError::VmClone)
+                                        .map(|_| ApiResponsePayload::Empty);
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
+                                ApiRequest::VmGuestExec(guest_exec_data, sender) => {
+                                    let response = self
+                                        .vm_guest_exec(guest_exec_data.as_ref())
+                                        .map_err(ApiError::VmGuestExec)
+                                        .map(ApiResponsePayload::VmAction);
+
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
+                                ApiRequest::VmGuestFileRead(guest_file_read_data, sender) => {
+                                    let response = self
+                                        .vm_guest_file_read(guest_file_read_data.as_ref())
+                                        .map_err(ApiError::VmGuestFileRead)
+                                        .map(ApiResponsePayload::VmAction);
+
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
+                                ApiRequest::VmGuestFileWrite(guest_file_write_data, sender) => {
+                                    let response = self
+                                        .vm_guest_file_write(guest_file_write_data.as_ref())
+                                        .map_err(ApiError::VmGuestFileWrite)
+                                        .map(ApiResponsePayload::VmAction);
+
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
+                                ApiRequest::VmGuestShutdown(guest_shutdown_data, sender) => {
+                                    let response = self
+                                        .vm_guest_shutdown(guest_shutdown_data.as_ref())
+                                        .map_err(ApiError::VmGuestShutdown)
+                                        .map(|_| ApiResponsePayload::Empty);
+
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
+                                ApiRequest::VmNmi(nmi_data, sender) => {
+                                    let response = self
+                                        .vm_nmi(nmi_data.as_ref())
+                                        .map_err(ApiError::VmNmi)
+                                        .map(|_| ApiResponsePayload::Empty);
+
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
+                                ApiRequest::VmChangeMedia(change_media_data, sender) => {
+                                    let response = self
+                                        .vm_change_media(change_media_data.as_ref())
+                                        .map_err(ApiError::VmChangeMedia)
+                                        .map(|_| ApiResponsePayload::Empty);
+
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
+                                ApiRequest::VmVcpuStats(sender) => {
+                                    let response = self
+                                        .vm_vcpu_stats()
+                                        .map_err(ApiError::VmInfo)
+                                        .map(ApiResponsePayload::VmAction);
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
+                                ApiRequest::VmSetWatchdogAction(watchdog_action_data, sender) => {
+                                    let response = self
+                                        .vm_set_watchdog_action(watchdog_action_data.as_ref())
+                                        .map_err(ApiError::VmSetWatchdogAction)
+                                        .map(|_| ApiResponsePayload::Empty);
+
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
                                 ApiRequest::VmPowerButton(sender) => {
                                     let response = self
                                         .vm_power_button()
@@ -2000,6 +3176,30 @@ impl Vmm {
 
                                     sender.send(response).map_err(Error::ApiResponseSend)?;
                                 }
+                                ApiRequest::VmSendKeys(send_keys_data, sender) => {
+                                    let response = self
+                                        .vm_send_keys(send_keys_data.as_ref())
+                                        .map_err(ApiError::VmSendKeys)
+                                        .map(|_| ApiResponsePayload::Empty);
+
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
+                                ApiRequest::VmDirtyRate(dirty_rate_data, sender) => {
+                                    let response = self
+                                        .vm_dirty_rate(dirty_rate_data.as_ref())
+                                        .map_err(ApiError::VmDirtyRate)
+                                        .map(ApiResponsePayload::VmAction);
+
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
+                                ApiRequest::VmSendPointer(send_pointer_data, sender) => {
+                                    let response = self
+                                        .vm_send_pointer(send_pointer_data.as_ref())
+                                        .map_err(ApiError::VmSendPointer)
+                                        .map(|_| ApiResponsePayload::Empty);
+
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
                             }
                         }
                     }
@@ -2080,6 +3280,16 @@ mod unit_tests {
                 max_phys_bits: 46,
                 affinity: None,
                 features: config::CpuFeatures::default(),
+                placement: None,
+                sched: None,
+                #[cfg(target_arch = "x86_64")]
+                model: None,
+                #[cfg(target_arch = "x86_64")]
+                microcode_revision: None,
+                #[cfg(target_arch = "x86_64")]
+                apic_ids: None,
+                #[cfg(target_arch = "aarch64")]
+                sve_vl: None,
             },
             memory: MemoryConfig {
                 size: 536_870_912,
@@ -2093,6 +3303,8 @@ mod unit_tests {
                 prefault: false,
                 zones: None,
                 thp: true,
+                #[cfg(target_arch = "x86_64")]
+                mmio32_hole_size: None,
             },
             payload: Some(PayloadConfig {
                 kernel: Some(PathBuf::from("/path/to/kernel")),
@@ -2126,10 +3338,24 @@ mod unit_tests {
             sgx_epc: None,
             numa: None,
             watchdog: false,
+            watchdog_action: WatchdogAction::Reset,
             #[cfg(feature = "guest_debug")]
             gdb: false,
             platform: None,
             tpm: None,
+            iothreads: None,
+            rate_limit_groups: None,
+            hibernation: None,
+            cloud_init: None,
+            ignition: None,
+            fw_cfg: None,
+            pvpanic: None,
+            gpu: None,
+            sound: None,
+            input: None,
+            channels: None,
+            scsi: None,
+            crypto: None,
         }))
     }
 
@@ -2,14 +2,15 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 //
-#[cfg(target_arch = "x86_64")]
-use crate::config::SgxEpcConfig;
 use crate::config::{HotplugMethod, MemoryConfig, MemoryZoneConfig};
+#[cfg(target_arch = "x86_64")]
+use crate::config::{MemmapType, SgxEpcConfig};
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 use crate::coredump::{
     CoredumpMemoryRegion, CoredumpMemoryRegions, DumpState, GuestDebuggableError,
 };
-use crate::migration::url_to_path;
+use crate::migration::{url_to_path, url_to_snapshot_path};
+use crate::snapshot_file::SnapshotFile;
 use crate::MEMORY_MANAGER_SNAPSHOT_ID;
 use crate::{GuestMemoryMmap, GuestRegionMmap};
 use acpi_tables::{aml, Aml};
@@ -74,6 +75,13 @@ const MPOL_BIND: u32 = 2;
 const MPOL_MF_STRICT: u32 = 1;
 const MPOL_MF_MOVE: u32 = 1 << 1;
 
+// Populate (prefault) page tables writable, available since Linux 5.14
+const MADV_POPULATE_WRITE: i32 = 23;
+
+// Protection flag allowing memory tags to be stored along with the pages
+#[cfg(target_arch = "aarch64")]
+const PROT_MTE: i32 = 0x20;
+
 // Reserve 1 MiB for platform MMIO devices (e.g. ACPI control devices)
 const PLATFORM_DEVICE_AREA_SIZE: u64 = 1 << 20;
 
@@ -185,6 +193,9 @@ pub struct MemoryManager {
     arch_mem_regions: Vec<ArchMemRegion>,
     ram_allocator: AddressAllocator,
     dynamic: bool,
+    // Back guest RAM with memory private to the guest
+    #[cfg(feature = "sev_snp")]
+    private_memory: bool,
 
     // Keep track of calls to create_userspace_mapping() for guest RAM.
     // This is useful for getting the dirty pages as we need to know the
@@ -194,6 +205,9 @@ pub struct MemoryManager {
     pub acpi_address: Option<GuestAddress>,
     #[cfg(target_arch = "aarch64")]
     uefi_flash: Option<GuestMemoryAtomic<GuestMemoryMmap>>,
+    // Map guest RAM with memory tagging allowed
+    #[cfg(target_arch = "aarch64")]
+    mte: bool,
 }
 
 #[derive(Debug)]
@@ -225,6 +239,10 @@ pub enum Error {
     /// Failed to create the user memory region.
     CreateUserMemoryRegion(hypervisor::HypervisorVmError),
 
+    /// Failed to allow memory tagging on guest RAM
+    #[cfg(target_arch = "aarch64")]
+    EnableMemoryTagging(io::Error),
+
     /// Failed to remove the user memory region.
     RemoveUserMemoryRegion(hypervisor::HypervisorVmError),
 
@@ -262,14 +280,6 @@ pub enum Error {
     #[cfg(target_arch = "x86_64")]
     SgxVirtEpcFileSetLen(io::Error),
 
-    /// Failed opening SGX provisioning device
-    #[cfg(target_arch = "x86_64")]
-    SgxProvisionOpen(io::Error),
-
-    /// Failed enabling SGX provisioning
-    #[cfg(target_arch = "x86_64")]
-    SgxEnableProvisioning(hypervisor::HypervisorVmError),
-
     /// Failed creating a new MmapRegion instance.
     #[cfg(target_arch = "x86_64")]
     NewMmapRegion(vm_memory::mmap::MmapRegionError),
@@ -291,6 +301,9 @@ pub enum Error {
     /// Failed applying NUMA memory policy.
     ApplyNumaPolicy(io::Error),
 
+    /// Failed prefaulting the guest memory.
+    PrefaultMemory(io::Error),
+
     /// Memory zone identifier is not unique.
     DuplicateZoneId,
 
@@ -315,6 +328,10 @@ pub enum Error {
     /// Guest address overflow
     GuestAddressOverFlow,
 
+    /// Guest RAM beyond the 4-level paging limit without host 5-level paging support
+    #[cfg(target_arch = "x86_64")]
+    RamAbovePagingLimit,
+
     /// Error opening snapshot file
     SnapshotOpen(io::Error),
 
@@ -495,6 +512,7 @@ impl MemoryManager {
                         Some(pf) => pf,
                         None => zone.prefault,
                     },
+                    zone.prefault_threads,
                     zone.shared,
                     zone.hugepages,
                     zone.hugepage_size,
@@ -553,6 +571,7 @@ impl MemoryManager {
         zones_config: &[MemoryZoneConfig],
         prefault: Option<bool>,
         mut existing_memory_files: HashMap<u32, File>,
+        copy_on_write: bool,
         thp: bool,
     ) -> Result<(Vec<Arc<GuestRegionMmap>>, MemoryZones), Error> {
         let mut memory_regions = Vec::new();
@@ -565,6 +584,15 @@ impl MemoryManager {
         for guest_ram_mapping in guest_ram_mappings {
             for zone_config in zones_config {
                 if guest_ram_mapping.zone_id == zone_config.id {
+                    let existing_memory_file =
+                        existing_memory_files.remove(&guest_ram_mapping.slot);
+                    // A clone maps the memory of its parent privately, so
+                    // that the pages it writes to are copied.
+                    let shared = if existing_memory_file.is_some() {
+                        !copy_on_write
+                    } else {
+                        zone_config.shared
+                    };
                     let region = MemoryManager::create_ram_region(
                         &zone_config.file,
                         guest_ram_mapping.file_offset,
@@ -574,11 +602,12 @@ impl MemoryManager {
                             Some(pf) => pf,
                             None => zone_config.prefault,
                         },
-                        zone_config.shared,
+                        zone_config.prefault_threads,
+                        shared,
                         zone_config.hugepages,
                         zone_config.hugepage_size,
                         zone_config.host_numa_node,
-                        existing_memory_files.remove(&guest_ram_mapping.slot),
+                        existing_memory_file,
                         thp,
                     )?;
                     memory_regions.push(Arc::clone(&region));
@@ -706,6 +735,9 @@ impl MemoryManager {
                 hotplug_size: config.hotplug_size,
                 hotplugged_size: config.hotplugged_size,
                 prefault: config.prefault,
+                prefault_threads: None,
+                #[cfg(target_arch = "x86_64")]
+                memmap_type: MemmapType::Ram,
             }];
 
             Ok((config.size, zones, allow_mem_hotplug))
@@ -793,13 +825,10 @@ impl MemoryManager {
 
         for (zone_id, regions) in list {
             for (region, virtio_mem) in regions {
-                let slot = self.create_userspace_mapping(
+                let slot = self.create_ram_userspace_mapping(
                     region.start_addr().raw_value(),
                     region.len(),
                     region.as_ptr() as u64,
-                    self.mergeable,
-                    false,
-                    self.log_dirty,
                 )?;
 
                 let file_offset = if let Some(file_offset) = region.file_offset() {
@@ -878,14 +907,30 @@ impl MemoryManager {
         prefault: Option<bool>,
         phys_bits: u8,
         #[cfg(feature = "tdx")] tdx_enabled: bool,
+        #[cfg(feature = "sev_snp")] sev_snp_enabled: bool,
+        #[cfg(target_arch = "aarch64")] mte_enabled: bool,
         restore_data: Option<&MemoryManagerSnapshotData>,
         existing_memory_files: Option<HashMap<u32, File>>,
+        copy_on_write: bool,
         #[cfg(target_arch = "x86_64")] sgx_epc_config: Option<Vec<SgxEpcConfig>>,
     ) -> Result<Arc<Mutex<MemoryManager>>, Error> {
         trace_scoped!("MemoryManager::new");
 
         let user_provided_zones = config.size == 0;
 
+        #[cfg(target_arch = "x86_64")]
+        let mem_32bit_reserved_size = config
+            .mmio32_hole_size
+            .unwrap_or(layout::MEM_32BIT_RESERVED_SIZE);
+        #[cfg(target_arch = "x86_64")]
+        let (mem_32bit_devices_start, mem_32bit_devices_size) =
+            layout::mem_32bit_devices_area(mem_32bit_reserved_size);
+        #[cfg(target_arch = "aarch64")]
+        let (mem_32bit_devices_start, mem_32bit_devices_size) = (
+            layout::MEM_32BIT_DEVICES_START,
+            layout::MEM_32BIT_DEVICES_SIZE,
+        );
+
         let mmio_address_space_size = mmio_address_space_size(phys_bits);
         debug_assert_eq!(
             (((mmio_address_space_size) >> 16) << 16),
@@ -916,6 +961,7 @@ impl MemoryManager {
                 &zones,
                 prefault,
                 existing_memory_files.unwrap_or_default(),
+                copy_on_write,
                 config.thp,
             )?;
             let guest_memory =
@@ -936,6 +982,9 @@ impl MemoryManager {
             )
         } else {
             // Init guest memory
+            #[cfg(target_arch = "x86_64")]
+            let arch_mem_regions = arch::arch_memory_regions(ram_size, mem_32bit_reserved_size);
+            #[cfg(target_arch = "aarch64")]
             let arch_mem_regions = arch::arch_memory_regions(ram_size);
 
             let ram_regions: Vec<(GuestAddress, usize)> = arch_mem_regions
@@ -998,6 +1047,7 @@ impl MemoryManager {
                                     Some(pf) => pf,
                                     None => zone.prefault,
                                 },
+                                zone.prefault_threads,
                                 zone.shared,
                                 zone.hugepages,
                                 zone.hugepage_size,
@@ -1030,6 +1080,12 @@ impl MemoryManager {
                 }
             }
 
+            // The guest can only map RAM beyond 64TiB with 5-level paging.
+            #[cfg(target_arch = "x86_64")]
+            if start_of_device_area > layout::RAM_4LEVEL_PAGING_END && !arch::host_supports_la57() {
+                return Err(Error::RamAbovePagingLimit);
+            }
+
             let mut hotplug_slots = Vec::with_capacity(HOTPLUG_COUNT);
             hotplug_slots.resize_with(HOTPLUG_COUNT, HotPlugState::default);
 
@@ -1063,8 +1119,8 @@ impl MemoryManager {
                 },
                 start_of_platform_device_area,
                 PLATFORM_DEVICE_AREA_SIZE,
-                layout::MEM_32BIT_DEVICES_START,
-                layout::MEM_32BIT_DEVICES_SIZE,
+                mem_32bit_devices_start,
+                mem_32bit_devices_size,
                 #[cfg(target_arch = "x86_64")]
                 vec![GsiApic::new(
                     X86_64_IRQ_BASE,
@@ -1078,6 +1134,8 @@ impl MemoryManager {
         let dynamic = true;
         #[cfg(feature = "tdx")]
         let dynamic = !tdx_enabled;
+        #[cfg(feature = "sev_snp")]
+        let dynamic = dynamic && !sev_snp_enabled;
 
         let acpi_address = if dynamic
             && config.hotplug_method == HotplugMethod::Acpi
@@ -1130,9 +1188,13 @@ impl MemoryManager {
             arch_mem_regions,
             ram_allocator,
             dynamic,
+            #[cfg(feature = "sev_snp")]
+            private_memory: sev_snp_enabled,
             #[cfg(target_arch = "aarch64")]
             uefi_flash: None,
             thp: config.thp,
+            #[cfg(target_arch = "aarch64")]
+            mte: mte_enabled,
         };
 
         #[cfg(target_arch = "aarch64")]
@@ -1162,10 +1224,10 @@ impl MemoryManager {
         source_url: Option<&str>,
         prefault: bool,
         phys_bits: u8,
+        #[cfg(target_arch = "aarch64")] mte_enabled: bool,
     ) -> Result<Arc<Mutex<MemoryManager>>, Error> {
         if let Some(source_url) = source_url {
-            let mut memory_file_path = url_to_path(source_url).map_err(Error::Restore)?;
-            memory_file_path.push(String::from(SNAPSHOT_FILENAME));
+            let snapshot_path = url_to_snapshot_path(source_url).map_err(Error::Restore)?;
 
             let mem_snapshot: MemoryManagerSnapshotData =
                 snapshot.to_versioned_state().map_err(Error::Restore)?;
@@ -1177,15 +1239,28 @@ impl MemoryManager {
                 phys_bits,
                 #[cfg(feature = "tdx")]
                 false,
+                #[cfg(feature = "sev_snp")]
+                false,
+                #[cfg(target_arch = "aarch64")]
+                mte_enabled,
                 Some(&mem_snapshot),
                 None,
+                false,
                 #[cfg(target_arch = "x86_64")]
                 None,
             )?;
 
-            mm.lock()
-                .unwrap()
-                .fill_saved_regions(memory_file_path, mem_snapshot.memory_ranges)?;
+            if snapshot_path.is_dir() {
+                mm.lock().unwrap().fill_saved_regions(
+                    snapshot_path.join(SNAPSHOT_FILENAME),
+                    mem_snapshot.memory_ranges,
+                )?;
+            } else {
+                let guest_memory = mm.lock().unwrap().guest_memory();
+                SnapshotFile::open(&snapshot_path)
+                    .and_then(|mut f| f.read_memory(&guest_memory.memory()))
+                    .map_err(Error::Restore)?;
+            }
 
             Ok(mm)
         } else {
@@ -1320,6 +1395,7 @@ impl MemoryManager {
         start_addr: GuestAddress,
         size: usize,
         prefault: bool,
+        prefault_threads: Option<u32>,
         shared: bool,
         hugepages: bool,
         hugepage_size: Option<u64>,
@@ -1329,11 +1405,24 @@ impl MemoryManager {
     ) -> Result<Arc<GuestRegionMmap>, Error> {
         let mut mmap_flags = libc::MAP_NORESERVE;
 
+        // Memory shared with another VM is already populated, and writing
+        // to it would copy all the pages of a clone.
+        let prefault_threads = if !prefault || existing_memory_file.is_some() {
+            None
+        } else {
+            prefault_threads
+        };
+
         // The duplication of mmap_flags ORing here is unfortunate but it also makes
         // the complexity of the handling clear.
         let fo = if let Some(f) = existing_memory_file {
-            // It must be MAP_SHARED as we wouldn't already have an FD
-            mmap_flags |= libc::MAP_SHARED;
+            // The FD is shared with the VM it comes from, either taking over
+            // its memory or copying it on write when cloning it.
+            if shared {
+                mmap_flags |= libc::MAP_SHARED;
+            } else {
+                mmap_flags |= libc::MAP_PRIVATE;
+            }
             Some(FileOffset::new(f, file_offset))
         } else if let Some(backing_file) = backing_file {
             if shared {
@@ -1353,7 +1442,9 @@ impl MemoryManager {
             None
         };
 
-        if prefault {
+        // Prefaulting from multiple threads happens once the NUMA policy is
+        // applied, so that the pages are allocated on the right host node.
+        if prefault && prefault_threads.is_none() {
             mmap_flags |= libc::MAP_POPULATE;
         }
 
@@ -1410,9 +1501,83 @@ impl MemoryManager {
                 .map_err(Error::ApplyNumaPolicy)?;
         }
 
+        if let Some(threads) = prefault_threads {
+            // The chunks of the region must be aligned on the huge page size.
+            // Without an explicit one, 1GiB fits any huge page size.
+            let page_size = if hugepages {
+                hugepage_size.unwrap_or(1 << 30)
+            } else {
+                // SAFETY: FFI call with a valid name
+                unsafe { libc::sysconf(libc::_SC_PAGESIZE) as u64 }
+            };
+            Self::prefault_memory(region.as_ptr() as u64, size as u64, page_size, threads)
+                .map_err(Error::PrefaultMemory)?;
+        }
+
         Ok(Arc::new(region))
     }
 
+    // Fault in the memory range using the given number of threads, each of
+    // them populating its own chunk of the range.
+    fn prefault_memory(addr: u64, size: u64, page_size: u64, threads: u32) -> io::Result<()> {
+        let chunk_size = (size / threads as u64 + page_size - 1) / page_size * page_size;
+        let chunk_size = std::cmp::max(chunk_size, page_size);
+        info!(
+            "Prefaulting memory at 0x{:x} (size = 0x{:x}) with {} threads",
+            addr, size, threads
+        );
+
+        let mut handles = Vec::new();
+        let mut offset = 0;
+        while offset < size {
+            let chunk_addr = addr + offset;
+            let chunk_len = std::cmp::min(chunk_size, size - offset);
+            handles.push(
+                std::thread::Builder::new()
+                    .name("prefault".to_string())
+                    .spawn(move || Self::prefault_chunk(chunk_addr, chunk_len))?,
+            );
+            offset += chunk_len;
+        }
+
+        for handle in handles {
+            handle
+                .join()
+                .map_err(|_| io::Error::new(io::ErrorKind::Other, "prefault thread panicked"))??;
+        }
+
+        Ok(())
+    }
+
+    fn prefault_chunk(addr: u64, len: u64) -> io::Result<()> {
+        // SAFETY: the range is part of a mapping owned by the caller
+        let ret =
+            unsafe { libc::madvise(addr as *mut libc::c_void, len as usize, MADV_POPULATE_WRITE) };
+        if ret == 0 {
+            return Ok(());
+        }
+
+        let e = io::Error::last_os_error();
+        if e.raw_os_error() != Some(libc::EINVAL) {
+            return Err(e);
+        }
+
+        // Older kernels don't support MADV_POPULATE_WRITE, touch every page
+        // instead while preserving its content.
+        // SAFETY: FFI call with a valid name
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) as u64 };
+        let mut offset = 0;
+        while offset < len {
+            let ptr = (addr + offset) as *mut u8;
+            // SAFETY: the address is part of the mapping, which isn't in use
+            // by the guest yet
+            unsafe { std::ptr::write_volatile(ptr, std::ptr::read_volatile(ptr)) };
+            offset += page_size;
+        }
+
+        Ok(())
+    }
+
     // Update the GuestMemoryMmap with the new range
     fn add_region(&mut self, region: Arc<GuestRegionMmap>) -> Result<(), Error> {
         let guest_memory = self
@@ -1443,7 +1608,8 @@ impl MemoryManager {
             .checked_add(1)
             .ok_or(Error::GuestAddressOverFlow)?;
 
-        if mem_end < arch::layout::MEM_32BIT_RESERVED_START {
+        // RAM ending below 4GiB doesn't go past the 32-bit reserved area.
+        if mem_end < arch::layout::RAM_64BIT_START {
             return Ok(arch::layout::RAM_64BIT_START);
         }
 
@@ -1462,6 +1628,7 @@ impl MemoryManager {
             start_addr,
             size,
             self.prefault,
+            None,
             self.shared,
             self.hugepages,
             self.hugepage_size,
@@ -1471,13 +1638,10 @@ impl MemoryManager {
         )?;
 
         // Map it into the guest
-        let slot = self.create_userspace_mapping(
+        let slot = self.create_ram_userspace_mapping(
             region.start_addr().0,
             region.len(),
             region.as_ptr() as u64,
-            self.mergeable,
-            false,
-            self.log_dirty,
         )?;
         self.guest_ram_mappings.push(GuestRamMapping {
             gpa: region.start_addr().raw_value(),
@@ -1537,6 +1701,22 @@ impl MemoryManager {
         Ok(region)
     }
 
+    /// Guest address range, as (base, size), the DIMMs hotplugged through
+    /// ACPI are placed in.
+    pub fn acpi_hotplug_range(&self) -> Option<(u64, u64)> {
+        self.acpi_address?;
+
+        let start = Self::start_addr(self.boot_guest_memory.last_addr(), true).ok()?;
+        Some((
+            start.raw_value(),
+            self.end_of_ram_area.raw_value() + 1 - start.raw_value(),
+        ))
+    }
+
+    pub fn snapshot_memory_ranges(&self) -> &MemoryRangeTable {
+        &self.snapshot_memory_ranges
+    }
+
     pub fn guest_memory(&self) -> GuestMemoryAtomic<GuestMemoryMmap> {
         self.guest_memory.clone()
     }
@@ -1563,6 +1743,66 @@ impl MemoryManager {
         slot_id
     }
 
+    // Guest RAM of a confidential guest is backed by private memory, which
+    // the host can't access once it has been handed over to the guest.
+    fn create_ram_userspace_mapping(
+        &mut self,
+        guest_phys_addr: u64,
+        memory_size: u64,
+        userspace_addr: u64,
+    ) -> Result<u32, Error> {
+        #[cfg(feature = "sev_snp")]
+        if self.private_memory {
+            let slot = self.allocate_memory_slot();
+            let mut mem_region = self.vm.make_user_memory_region(
+                slot,
+                guest_phys_addr,
+                memory_size,
+                userspace_addr,
+                false,
+                false,
+            );
+            mem_region.flags |= hypervisor::USER_MEMORY_REGION_PRIVATE;
+
+            info!(
+                "Creating private userspace mapping: {:x} -> {:x} {:x}, slot {}",
+                guest_phys_addr, userspace_addr, memory_size, slot
+            );
+
+            self.vm
+                .create_user_memory_region(mem_region)
+                .map_err(Error::CreateUserMemoryRegion)?;
+
+            return Ok(slot);
+        }
+
+        // KVM refuses to map memory which can't hold tags into a VM with
+        // MTE enabled.
+        #[cfg(target_arch = "aarch64")]
+        if self.mte {
+            // SAFETY: FFI call on a range of guest RAM mapped by the VMM
+            let ret = unsafe {
+                libc::mprotect(
+                    userspace_addr as *mut libc::c_void,
+                    memory_size as usize,
+                    PROT_READ | PROT_WRITE | PROT_MTE,
+                )
+            };
+            if ret != 0 {
+                return Err(Error::EnableMemoryTagging(io::Error::last_os_error()));
+            }
+        }
+
+        self.create_userspace_mapping(
+            guest_phys_addr,
+            memory_size,
+            userspace_addr,
+            self.mergeable,
+            false,
+            self.log_dirty,
+        )
+    }
+
     pub fn create_userspace_mapping(
         &mut self,
         guest_phys_addr: u64,
@@ -1696,6 +1936,11 @@ impl MemoryManager {
     pub fn virtio_mem_resize(&mut self, id: &str, size: u64) -> Result<(), Error> {
         if let Some(memory_zone) = self.memory_zones.get_mut(id) {
             if let Some(virtio_mem_zone) = &mut memory_zone.virtio_mem_zone {
+                // Nothing to tell the guest if the size doesn't change
+                if virtio_mem_zone.hotplugged_size == size {
+                    return Ok(());
+                }
+
                 if let Some(virtio_mem_device) = virtio_mem_zone.virtio_device.as_ref() {
                     virtio_mem_device
                         .lock()
@@ -1718,6 +1963,26 @@ impl MemoryManager {
         Err(Error::UnknownMemoryZone)
     }
 
+    /// Memory hotplugged through virtio-mem as (requested, plugged). The
+    /// plugged memory differs from the requested one until the guest driver
+    /// has processed a resize.
+    pub fn virtio_mem_size(&self) -> (u64, u64) {
+        let mut requested = 0;
+        let mut plugged = 0;
+        for virtio_mem_zone in self
+            .memory_zones
+            .values()
+            .filter_map(|z| z.virtio_mem_zone.as_ref())
+        {
+            requested += virtio_mem_zone.hotplugged_size;
+            if let Some(virtio_device) = virtio_mem_zone.virtio_device.as_ref() {
+                plugged += virtio_device.lock().unwrap().plugged_size();
+            }
+        }
+
+        (requested, plugged)
+    }
+
     /// In case this function resulted in adding a new memory region to the
     /// guest memory, the new region is returned to the caller. The virtio-mem
     /// use case never adds a new region as the whole hotpluggable memory has
@@ -1734,14 +1999,15 @@ impl MemoryManager {
         let mut region: Option<Arc<GuestRegionMmap>> = None;
         match self.hotplug_method {
             HotplugMethod::VirtioMem => {
-                if desired_ram >= self.boot_ram {
-                    if !self.dynamic {
-                        return Ok(region);
-                    }
-
-                    self.virtio_mem_resize(DEFAULT_MEMORY_ZONE, desired_ram - self.boot_ram)?;
-                    self.current_ram = desired_ram;
+                if !self.dynamic {
+                    return Ok(region);
                 }
+
+                // The boot RAM can't be unplugged, asking for less only
+                // unplugs all the virtio-mem memory.
+                let desired_ram = std::cmp::max(desired_ram, self.boot_ram);
+                self.virtio_mem_resize(DEFAULT_MEMORY_ZONE, desired_ram - self.boot_ram)?;
+                self.current_ram = desired_ram;
             }
             HotplugMethod::Acpi => {
                 if desired_ram > self.current_ram {
@@ -1772,14 +2038,6 @@ impl MemoryManager {
 
     #[cfg(target_arch = "x86_64")]
     pub fn setup_sgx(&mut self, sgx_epc_config: Vec<SgxEpcConfig>) -> Result<(), Error> {
-        let file = OpenOptions::new()
-            .read(true)
-            .open("/dev/sgx_provision")
-            .map_err(Error::SgxProvisionOpen)?;
-        self.vm
-            .enable_sgx_attribute(file)
-            .map_err(Error::SgxEnableProvisioning)?;
-
         // Go over each EPC section and verify its size is a 4k multiple. At
         // the same time, calculate the total size needed for the contiguous
         // EPC region.
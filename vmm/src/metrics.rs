@@ -0,0 +1,204 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Metrics exposed in the Prometheus text format through `/metrics`.
+//!
+//! The metrics related to the VM are collected by the VMM thread on each
+//! scrape, while the latencies of the API are tracked by the HTTP thread as
+//! requests are served.
+
+use once_cell::sync::Lazy;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::num::Wrapping;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// Upper bounds of the API latency histogram buckets, in seconds
+const API_LATENCY_BUCKETS: [f64; 7] = [0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 1.0];
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; API_LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(API_LATENCY_BUCKETS) {
+            if value <= bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+static API_LATENCIES: Lazy<Mutex<BTreeMap<String, Histogram>>> =
+    Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+/// Records the time spent serving a request to the given API endpoint.
+pub fn observe_api_latency(endpoint: &str, duration: Duration) {
+    API_LATENCIES
+        .lock()
+        .unwrap()
+        .entry(endpoint.to_string())
+        .or_default()
+        .observe(duration.as_secs_f64());
+}
+
+// Label values must have their backslashes, double quotes and line feeds
+// escaped.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn write_header(out: &mut String, name: &str, help: &str, kind: &str) {
+    writeln!(out, "# HELP {name} {help}").ok();
+    writeln!(out, "# TYPE {name} {kind}").ok();
+}
+
+/// Formats the latencies of the API endpoints served so far.
+pub fn api_metrics() -> String {
+    let mut out = String::new();
+    let latencies = API_LATENCIES.lock().unwrap();
+    if latencies.is_empty() {
+        return out;
+    }
+
+    let name = "cloud_hypervisor_api_request_duration_seconds";
+    write_header(
+        &mut out,
+        name,
+        "Time spent serving the API requests",
+        "histogram",
+    );
+    for (endpoint, histogram) in latencies.iter() {
+        let endpoint = escape_label(endpoint);
+        for (count, bound) in histogram.buckets.iter().zip(API_LATENCY_BUCKETS) {
+            writeln!(
+                out,
+                "{name}_bucket{{endpoint=\"{endpoint}\",le=\"{bound}\"}} {count}"
+            )
+            .ok();
+        }
+        writeln!(
+            out,
+            "{name}_bucket{{endpoint=\"{endpoint}\",le=\"+Inf\"}} {}",
+            histogram.count
+        )
+        .ok();
+        writeln!(
+            out,
+            "{name}_sum{{endpoint=\"{endpoint}\"}} {}",
+            histogram.sum
+        )
+        .ok();
+        writeln!(
+            out,
+            "{name}_count{{endpoint=\"{endpoint}\"}} {}",
+            histogram.count
+        )
+        .ok();
+    }
+
+    out
+}
+
+/// Formats the metrics of a running VM.
+pub fn vm_metrics(
+    device_counters: &HashMap<String, HashMap<&'static str, Wrapping<u64>>>,
+    vcpu_exits: &[(u8, u64)],
+    dirty_rate: Option<f64>,
+) -> String {
+    let mut out = String::new();
+
+    // Devices expose counters with different meanings, some being totals
+    // and others being the latest values measured, hence untyped.
+    let name = "cloud_hypervisor_device_counter";
+    write_header(
+        &mut out,
+        name,
+        "Counters reported by the devices",
+        "untyped",
+    );
+    let devices: BTreeMap<_, _> = device_counters.iter().collect();
+    for (device, counters) in devices {
+        let device = escape_label(device);
+        let counters: BTreeMap<_, _> = counters.iter().collect();
+        for (counter, value) in counters {
+            writeln!(
+                out,
+                "{name}{{device=\"{device}\",counter=\"{counter}\"}} {}",
+                value.0
+            )
+            .ok();
+        }
+    }
+
+    let name = "cloud_hypervisor_vcpu_exits_total";
+    write_header(
+        &mut out,
+        name,
+        "Exits from the guest handled by the vCPU threads",
+        "counter",
+    );
+    for (vcpu, exits) in vcpu_exits {
+        writeln!(out, "{name}{{vcpu=\"{vcpu}\"}} {exits}").ok();
+    }
+
+    if let Some(dirty_rate) = dirty_rate {
+        let name = "cloud_hypervisor_dirty_rate_megabytes_per_second";
+        write_header(
+            &mut out,
+            name,
+            "Guest memory dirty rate from the latest measurement",
+            "gauge",
+        );
+        writeln!(out, "{name} {dirty_rate}").ok();
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vm_metrics() {
+        let mut device_counters = HashMap::new();
+        device_counters.insert(
+            "_disk0".to_string(),
+            HashMap::from([("read_bytes", Wrapping(4096)), ("read_ops", Wrapping(1))]),
+        );
+
+        let metrics = vm_metrics(&device_counters, &[(0, 10), (1, 20)], Some(1.5));
+        assert_eq!(
+            metrics,
+            "# HELP cloud_hypervisor_device_counter Counters reported by the devices\n\
+             # TYPE cloud_hypervisor_device_counter untyped\n\
+             cloud_hypervisor_device_counter{device=\"_disk0\",counter=\"read_bytes\"} 4096\n\
+             cloud_hypervisor_device_counter{device=\"_disk0\",counter=\"read_ops\"} 1\n\
+             # HELP cloud_hypervisor_vcpu_exits_total Exits from the guest handled by the vCPU threads\n\
+             # TYPE cloud_hypervisor_vcpu_exits_total counter\n\
+             cloud_hypervisor_vcpu_exits_total{vcpu=\"0\"} 10\n\
+             cloud_hypervisor_vcpu_exits_total{vcpu=\"1\"} 20\n\
+             # HELP cloud_hypervisor_dirty_rate_megabytes_per_second Guest memory dirty rate from the latest measurement\n\
+             # TYPE cloud_hypervisor_dirty_rate_megabytes_per_second gauge\n\
+             cloud_hypervisor_dirty_rate_megabytes_per_second 1.5\n"
+        );
+    }
+
+    #[test]
+    fn test_escape_label() {
+        assert_eq!(escape_label("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
@@ -4,23 +4,42 @@
 
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 use crate::coredump::GuestDebuggableError;
-use crate::{config::VmConfig, vm::VmSnapshot};
+use crate::snapshot_file::{Section, SnapshotFile};
+use crate::{config::VmConfig, vm::VmSnapshot, GuestMemoryMmap};
 use anyhow::anyhow;
+use rustls::server::AllowAnyAuthenticatedClient;
+use rustls::{
+    Certificate, ClientConfig, ClientConnection, PrivateKey, RootCertStore, ServerConfig,
+    ServerConnection, ServerName, StreamOwned,
+};
+use std::collections::VecDeque;
 use std::fs::File;
-use std::io::BufReader;
-use std::path::PathBuf;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use vm_memory::{Bytes, GuestAddress, GuestMemoryAtomic};
+use vm_migration::protocol::{Command, Compression, MemoryRangeTable, Request, Response, Status};
 use vm_migration::{MigratableError, Snapshot};
 
 pub const SNAPSHOT_STATE_FILE: &str = "state.json";
 pub const SNAPSHOT_CONFIG_FILE: &str = "config.json";
 
-pub fn url_to_path(url: &str) -> std::result::Result<PathBuf, MigratableError> {
-    let path: PathBuf = url
-        .strip_prefix("file://")
+// A snapshot is either a directory holding one file per component, or a
+// single file.
+pub fn url_to_snapshot_path(url: &str) -> std::result::Result<PathBuf, MigratableError> {
+    url.strip_prefix("file://")
         .ok_or_else(|| {
             MigratableError::MigrateSend(anyhow!("Could not extract path from URL: {}", url))
         })
-        .map(|s| s.into())?;
+        .map(|s| s.into())
+}
+
+pub fn url_to_path(url: &str) -> std::result::Result<PathBuf, MigratableError> {
+    let path = url_to_snapshot_path(url)?;
 
     if !path.is_dir() {
         return Err(MigratableError::MigrateSend(anyhow!(
@@ -44,7 +63,12 @@ pub fn url_to_file(url: &str) -> std::result::Result<PathBuf, GuestDebuggableErr
 }
 
 pub fn recv_vm_config(source_url: &str) -> std::result::Result<VmConfig, MigratableError> {
-    let mut vm_config_path = url_to_path(source_url)?;
+    let snapshot_path = url_to_snapshot_path(source_url)?;
+    if !snapshot_path.is_dir() {
+        return SnapshotFile::open(&snapshot_path)?.read_json(Section::Config);
+    }
+
+    let mut vm_config_path = snapshot_path;
 
     vm_config_path.push(SNAPSHOT_CONFIG_FILE);
 
@@ -56,7 +80,12 @@ pub fn recv_vm_config(source_url: &str) -> std::result::Result<VmConfig, Migrata
 }
 
 pub fn recv_vm_state(source_url: &str) -> std::result::Result<Snapshot, MigratableError> {
-    let mut vm_state_path = url_to_path(source_url)?;
+    let snapshot_path = url_to_snapshot_path(source_url)?;
+    if !snapshot_path.is_dir() {
+        return SnapshotFile::open(&snapshot_path)?.read_json(Section::State);
+    }
+
+    let mut vm_state_path = snapshot_path;
 
     vm_state_path.push(SNAPSHOT_STATE_FILE);
 
@@ -76,3 +105,720 @@ pub fn get_vm_snapshot(snapshot: &Snapshot) -> std::result::Result<VmSnapshot, M
         "Could not find VM config snapshot section"
     )))
 }
+
+// Files expected in the TLS directory
+const TLS_CA_CERT_FILE: &str = "ca-cert.pem";
+const TLS_SERVER_CERT_FILE: &str = "server-cert.pem";
+const TLS_SERVER_KEY_FILE: &str = "server-key.pem";
+const TLS_CLIENT_CERT_FILE: &str = "client-cert.pem";
+const TLS_CLIENT_KEY_FILE: &str = "client-key.pem";
+
+fn tls_error(e: impl std::fmt::Display) -> MigratableError {
+    MigratableError::MigrateSocket(io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+}
+
+fn tls_reader(dir: &Path, name: &str) -> std::result::Result<BufReader<File>, MigratableError> {
+    File::open(dir.join(name)).map(BufReader::new).map_err(|e| {
+        MigratableError::MigrateSocket(io::Error::new(
+            e.kind(),
+            format!("Error opening {}: {}", dir.join(name).display(), e),
+        ))
+    })
+}
+
+fn tls_certs(dir: &Path, name: &str) -> std::result::Result<Vec<Certificate>, MigratableError> {
+    let certs = rustls_pemfile::certs(&mut tls_reader(dir, name)?)
+        .map_err(|e| tls_error(format!("Error parsing {}: {}", name, e)))?;
+    if certs.is_empty() {
+        return Err(tls_error(format!("No certificate found in {}", name)));
+    }
+
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn tls_key(dir: &Path, name: &str) -> std::result::Result<PrivateKey, MigratableError> {
+    let mut reader = tls_reader(dir, name)?;
+    loop {
+        match rustls_pemfile::read_one(&mut reader)
+            .map_err(|e| tls_error(format!("Error parsing {}: {}", name, e)))?
+        {
+            Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => return Ok(PrivateKey(key)),
+            Some(_) => {}
+            None => return Err(tls_error(format!("No private key found in {}", name))),
+        }
+    }
+}
+
+fn tls_roots(dir: &Path) -> std::result::Result<RootCertStore, MigratableError> {
+    let mut roots = RootCertStore::empty();
+    for cert in tls_certs(dir, TLS_CA_CERT_FILE)? {
+        roots.add(&cert).map_err(tls_error)?;
+    }
+
+    Ok(roots)
+}
+
+/// TLS configuration of the source, authenticating the destination against
+/// the CA certificate and presenting the client certificate.
+pub fn tls_client_config(dir: &Path) -> std::result::Result<Arc<ClientConfig>, MigratableError> {
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(tls_roots(dir)?)
+        .with_client_auth_cert(
+            tls_certs(dir, TLS_CLIENT_CERT_FILE)?,
+            tls_key(dir, TLS_CLIENT_KEY_FILE)?,
+        )
+        .map_err(tls_error)?;
+
+    Ok(Arc::new(config))
+}
+
+/// TLS configuration of the destination, only accepting sources presenting
+/// a certificate signed by the CA.
+pub fn tls_server_config(dir: &Path) -> std::result::Result<Arc<ServerConfig>, MigratableError> {
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(tls_roots(dir)?).boxed())
+        .with_single_cert(
+            tls_certs(dir, TLS_SERVER_CERT_FILE)?,
+            tls_key(dir, TLS_SERVER_KEY_FILE)?,
+        )
+        .map_err(tls_error)?;
+
+    Ok(Arc::new(config))
+}
+
+/// Connection used to transfer a VM, either a UNIX or a TCP socket, the
+/// latter being optionally encrypted with TLS.
+pub enum MigrationSocket {
+    Unix(UnixStream),
+    Tcp(TcpStream),
+    TlsClient(Box<StreamOwned<ClientConnection, TcpStream>>),
+    TlsServer(Box<StreamOwned<ServerConnection, TcpStream>>),
+}
+
+impl MigrationSocket {
+    /// Connects to a "unix:<path>" or "tcp:<host>:<port>" URL. The TCP
+    /// connection is encrypted when a TLS configuration is provided.
+    pub fn connect(
+        url: &str,
+        tls: Option<&Arc<ClientConfig>>,
+    ) -> std::result::Result<Self, MigratableError> {
+        if let Some(path) = url.strip_prefix("unix:") {
+            if tls.is_some() {
+                return Err(MigratableError::MigrateSend(anyhow!(
+                    "TLS requires a TCP migration URL"
+                )));
+            }
+            UnixStream::connect(path).map(Self::Unix).map_err(|e| {
+                MigratableError::MigrateSend(anyhow!("Error connecting to UNIX socket: {}", e))
+            })
+        } else if let Some(address) = url.strip_prefix("tcp:") {
+            let socket = TcpStream::connect(address).map_err(|e| {
+                MigratableError::MigrateSend(anyhow!("Error connecting to TCP socket: {}", e))
+            })?;
+            let config = match tls {
+                Some(config) => config,
+                None => return Ok(Self::Tcp(socket)),
+            };
+
+            // The destination certificate must be issued for the host name
+            // or IP address from the URL.
+            let host = address
+                .rsplit_once(':')
+                .map(|(host, _)| host)
+                .unwrap_or(address)
+                .trim_start_matches('[')
+                .trim_end_matches(']');
+            let name = ServerName::try_from(host).map_err(|e| {
+                MigratableError::MigrateSend(anyhow!("Invalid TLS server name {}: {}", host, e))
+            })?;
+            let connection = ClientConnection::new(config.clone(), name).map_err(|e| {
+                MigratableError::MigrateSend(anyhow!("Error setting up TLS connection: {}", e))
+            })?;
+
+            Ok(Self::TlsClient(Box::new(StreamOwned::new(
+                connection, socket,
+            ))))
+        } else {
+            Err(MigratableError::MigrateSend(anyhow!(
+                "Invalid migration URL: {}",
+                url
+            )))
+        }
+    }
+
+    /// UNIX socket, required to send file descriptors.
+    pub fn unix(&mut self) -> std::result::Result<&mut UnixStream, MigratableError> {
+        match self {
+            Self::Unix(socket) => Ok(socket),
+            _ => Err(MigratableError::MigrateSocket(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Sending file descriptors requires a UNIX socket",
+            ))),
+        }
+    }
+}
+
+impl Read for MigrationSocket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Unix(socket) => socket.read(buf),
+            Self::Tcp(socket) => socket.read(buf),
+            Self::TlsClient(socket) => socket.read(buf),
+            Self::TlsServer(socket) => socket.read(buf),
+        }
+    }
+}
+
+impl Write for MigrationSocket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Unix(socket) => socket.write(buf),
+            Self::Tcp(socket) => socket.write(buf),
+            Self::TlsClient(socket) => socket.write(buf),
+            Self::TlsServer(socket) => socket.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Unix(socket) => socket.flush(),
+            Self::Tcp(socket) => socket.flush(),
+            Self::TlsClient(socket) => socket.flush(),
+            Self::TlsServer(socket) => socket.flush(),
+        }
+    }
+}
+
+/// Listener accepting the connections of an incoming migration.
+pub enum MigrationListener {
+    Unix(UnixListener, PathBuf),
+    Tcp(TcpListener, Option<Arc<ServerConfig>>),
+}
+
+impl MigrationListener {
+    /// Listens on a "unix:<path>" or "tcp:<address>:<port>" URL. Accepted
+    /// TCP connections are encrypted when a TLS configuration is provided.
+    pub fn bind(
+        url: &str,
+        tls: Option<Arc<ServerConfig>>,
+    ) -> std::result::Result<Self, MigratableError> {
+        if let Some(path) = url.strip_prefix("unix:") {
+            if tls.is_some() {
+                return Err(MigratableError::MigrateReceive(anyhow!(
+                    "TLS requires a TCP migration URL"
+                )));
+            }
+            UnixListener::bind(path)
+                .map(|l| Self::Unix(l, path.into()))
+                .map_err(|e| {
+                    MigratableError::MigrateReceive(anyhow!("Error binding to UNIX socket: {}", e))
+                })
+        } else if let Some(address) = url.strip_prefix("tcp:") {
+            TcpListener::bind(address)
+                .map(|l| Self::Tcp(l, tls))
+                .map_err(|e| {
+                    MigratableError::MigrateReceive(anyhow!("Error binding to TCP socket: {}", e))
+                })
+        } else {
+            Err(MigratableError::MigrateReceive(anyhow!(
+                "Invalid migration URL: {}",
+                url
+            )))
+        }
+    }
+
+    pub fn accept(&self) -> std::result::Result<MigrationSocket, MigratableError> {
+        let socket = match self {
+            Self::Unix(listener, _) => listener.accept().map(|(s, _)| MigrationSocket::Unix(s)),
+            Self::Tcp(listener, _) => listener.accept().map(|(s, _)| MigrationSocket::Tcp(s)),
+        };
+        let socket = socket.map_err(|e| {
+            MigratableError::MigrateReceive(anyhow!("Error accepting connection: {}", e))
+        })?;
+
+        match (socket, self) {
+            (MigrationSocket::Tcp(socket), Self::Tcp(_, Some(config))) => {
+                let connection = ServerConnection::new(config.clone()).map_err(|e| {
+                    MigratableError::MigrateReceive(anyhow!(
+                        "Error setting up TLS connection: {}",
+                        e
+                    ))
+                })?;
+                Ok(MigrationSocket::TlsServer(Box::new(StreamOwned::new(
+                    connection, socket,
+                ))))
+            }
+            (socket, _) => Ok(socket),
+        }
+    }
+}
+
+impl Drop for MigrationListener {
+    fn drop(&mut self) {
+        if let Self::Unix(_, path) = self {
+            if let Err(e) = std::fs::remove_file(path) {
+                warn!("Error unlinking UNIX socket: {}", e);
+            }
+        }
+    }
+}
+
+// Amount of memory checksummed or compressed and sent at once
+const CHUNK_SIZE: usize = 1 << 20;
+
+// zstd level favouring speed, as the compression must keep up with the link
+const ZSTD_LEVEL: i32 = 1;
+
+fn compress(compression: Compression, data: &[u8]) -> io::Result<Vec<u8>> {
+    match compression {
+        Compression::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL),
+        Compression::Lz4 => Ok(lz4_flex::compress(data)),
+    }
+}
+
+// Decompress data, which must fill the whole buffer
+fn decompress(compression: Compression, data: &[u8], buf: &mut [u8]) -> io::Result<()> {
+    let len = match compression {
+        Compression::Zstd => zstd::bulk::decompress_to_buffer(data, buf)?,
+        Compression::Lz4 => lz4_flex::decompress_into(data, buf)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+    };
+    if len != buf.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Decompressed {} bytes, expected {}", len, buf.len()),
+        ));
+    }
+
+    Ok(())
+}
+
+fn write_compressed_chunk<T>(
+    socket: &mut T,
+    data: &[u8],
+) -> std::result::Result<(), MigratableError>
+where
+    T: Write,
+{
+    socket
+        .write_all(&(data.len() as u32).to_le_bytes())
+        .and_then(|_| socket.write_all(data))
+        .map_err(MigratableError::MigrateSocket)
+}
+
+// Read a chunk of memory, decompressing it if needed
+fn read_chunk<T>(
+    socket: &mut T,
+    compression: Option<Compression>,
+    buf: &mut [u8],
+) -> std::result::Result<(), MigratableError>
+where
+    T: Read,
+{
+    let compression = match compression {
+        Some(compression) => compression,
+        None => {
+            return socket
+                .read_exact(buf)
+                .map_err(MigratableError::MigrateSocket)
+        }
+    };
+
+    let mut len = [0u8; 4];
+    socket
+        .read_exact(&mut len)
+        .map_err(MigratableError::MigrateSocket)?;
+    let len = u32::from_le_bytes(len) as usize;
+    // Compressing a chunk can't make it grow much, anything bigger means the
+    // stream is corrupted.
+    if len > 2 * CHUNK_SIZE {
+        return Err(MigratableError::MigrateReceive(anyhow!(
+            "Invalid compressed chunk size: {}",
+            len
+        )));
+    }
+
+    let mut data = vec![0u8; len];
+    socket
+        .read_exact(&mut data)
+        .map_err(MigratableError::MigrateSocket)?;
+    decompress(compression, &data, buf)
+        .map_err(|e| MigratableError::MigrateReceive(anyhow!("Error decompressing memory: {}", e)))
+}
+
+// Call f on every chunk of the memory described by the table
+fn for_each_chunk<F>(table: &MemoryRangeTable, mut f: F) -> std::result::Result<(), MigratableError>
+where
+    F: FnMut(GuestAddress, usize) -> std::result::Result<(), MigratableError>,
+{
+    for range in table.regions() {
+        let mut offset = 0;
+        while offset < range.length {
+            let len = (range.length - offset).min(CHUNK_SIZE as u64) as usize;
+            f(GuestAddress(range.gpa + offset), len)?;
+            offset += len as u64;
+        }
+    }
+
+    Ok(())
+}
+
+/// Receives the compressed memory described by the table.
+pub fn receive_compressed_memory<T>(
+    socket: &mut T,
+    guest_memory: &GuestMemoryAtomic<GuestMemoryMmap>,
+    table: &MemoryRangeTable,
+    compression: Compression,
+) -> std::result::Result<(), MigratableError>
+where
+    T: Read,
+{
+    let mem = guest_memory.memory();
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    for_each_chunk(table, |addr, len| {
+        read_chunk(socket, Some(compression), &mut buf[..len])?;
+        mem.write_slice(&buf[..len], addr).map_err(|e| {
+            MigratableError::MigrateReceive(anyhow!("Error writing guest memory: {}", e))
+        })
+    })
+}
+
+// Upper bound on the number of threads compressing the memory
+const MAX_COMPRESSION_THREADS: usize = 8;
+
+type CompressionJob = (Vec<u8>, SyncSender<io::Result<Vec<u8>>>);
+
+/// Worker threads compressing the memory, so that the compression of a chunk
+/// overlaps with the transfer of the previous ones.
+pub struct CompressionPool {
+    jobs: Option<SyncSender<CompressionJob>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl CompressionPool {
+    pub fn new(compression: Compression) -> std::result::Result<Self, MigratableError> {
+        let threads = thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(MAX_COMPRESSION_THREADS);
+        let (jobs, receiver) = sync_channel::<CompressionJob>(threads);
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let mut workers = Vec::new();
+        for i in 0..threads {
+            let receiver = receiver.clone();
+            let worker = thread::Builder::new()
+                .name(format!("migration_compress{i}"))
+                .spawn(move || loop {
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        Ok((data, result)) => {
+                            result.send(compress(compression, &data)).ok();
+                        }
+                        Err(_) => break,
+                    }
+                })
+                .map_err(|e| {
+                    MigratableError::MigrateSend(anyhow!(
+                        "Error spawning compression thread: {}",
+                        e
+                    ))
+                })?;
+            workers.push(worker);
+        }
+
+        Ok(Self {
+            jobs: Some(jobs),
+            workers,
+        })
+    }
+
+    // Write the oldest chunk being compressed
+    fn write_next<T>(
+        socket: &mut T,
+        pending: &mut VecDeque<Receiver<io::Result<Vec<u8>>>>,
+    ) -> std::result::Result<(), MigratableError>
+    where
+        T: Write,
+    {
+        if let Some(result) = pending.pop_front() {
+            let data = result
+                .recv()
+                .map_err(|_| {
+                    MigratableError::MigrateSend(anyhow!("Compression thread terminated"))
+                })?
+                .map_err(|e| {
+                    MigratableError::MigrateSend(anyhow!("Error compressing memory: {}", e))
+                })?;
+            write_compressed_chunk(socket, &data)?;
+        }
+
+        Ok(())
+    }
+
+    /// Compresses and sends the memory described by the table, in order.
+    pub fn send_memory<T>(
+        &self,
+        socket: &mut T,
+        guest_memory: &GuestMemoryAtomic<GuestMemoryMmap>,
+        table: &MemoryRangeTable,
+    ) -> std::result::Result<(), MigratableError>
+    where
+        T: Write,
+    {
+        let jobs = self.jobs.as_ref().unwrap();
+        let mem = guest_memory.memory();
+        let mut pending = VecDeque::new();
+        for_each_chunk(table, |addr, len| {
+            let mut data = vec![0u8; len];
+            mem.read_slice(&mut data, addr).map_err(|e| {
+                MigratableError::MigrateSend(anyhow!("Error reading guest memory: {}", e))
+            })?;
+
+            let (sender, receiver) = sync_channel(1);
+            jobs.send((data, sender)).map_err(|_| {
+                MigratableError::MigrateSend(anyhow!("Compression thread terminated"))
+            })?;
+            pending.push_back(receiver);
+
+            // Bound the amount of memory held by the chunks in flight
+            if pending.len() > 2 * self.workers.len() {
+                Self::write_next(socket, &mut pending)?;
+            }
+
+            Ok(())
+        })?;
+
+        while !pending.is_empty() {
+            Self::write_next(socket, &mut pending)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for CompressionPool {
+    fn drop(&mut self) {
+        // Closing the channel terminates the workers
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            worker.join().ok();
+        }
+    }
+}
+
+// Send the memory described by the table, followed by its checksum
+fn send_stream_memory(
+    stream: &mut MigrationSocket,
+    guest_memory: &GuestMemoryAtomic<GuestMemoryMmap>,
+    table: &MemoryRangeTable,
+    compression: Option<Compression>,
+) -> std::result::Result<(), MigratableError> {
+    Request::memory(table.length()).write_to(stream)?;
+    table.write_to(stream)?;
+
+    let mem = guest_memory.memory();
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    let mut crc = 0;
+    // Each stream runs in its own thread, which compresses its own chunks.
+    for_each_chunk(table, |addr, len| {
+        mem.read_slice(&mut buf[..len], addr).map_err(|e| {
+            MigratableError::MigrateSend(anyhow!("Error reading guest memory: {}", e))
+        })?;
+        crc = crc32c::crc32c_append(crc, &buf[..len]);
+        match compression {
+            Some(compression) => {
+                let data = compress(compression, &buf[..len]).map_err(|e| {
+                    MigratableError::MigrateSend(anyhow!("Error compressing memory: {}", e))
+                })?;
+                write_compressed_chunk(stream, &data)
+            }
+            None => stream
+                .write_all(&buf[..len])
+                .map_err(MigratableError::MigrateSocket),
+        }
+    })?;
+    stream
+        .write_all(&crc.to_le_bytes())
+        .map_err(MigratableError::MigrateSocket)?;
+
+    let res = Response::read_from(stream)?;
+    if res.status() != Status::Ok {
+        return Err(MigratableError::MigrateSend(anyhow!(
+            "Error during memory migration over stream"
+        )));
+    }
+
+    Ok(())
+}
+
+// Receive the memory described by the table, rejecting it if its checksum
+// doesn't match.
+fn receive_stream_memory(
+    stream: &mut MigrationSocket,
+    guest_memory: &GuestMemoryAtomic<GuestMemoryMmap>,
+    req: &Request,
+    compression: Option<Compression>,
+) -> std::result::Result<(), MigratableError> {
+    let table = MemoryRangeTable::read_from(stream, req.length())?;
+
+    let mem = guest_memory.memory();
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    let mut crc = 0;
+    let mut result = Ok(());
+    for_each_chunk(&table, |addr, len| {
+        read_chunk(stream, compression, &mut buf[..len])?;
+        crc = crc32c::crc32c_append(crc, &buf[..len]);
+        // Keep reading the stream on error, so that it remains usable
+        if result.is_ok() {
+            result = mem.write_slice(&buf[..len], addr).map_err(|e| {
+                MigratableError::MigrateReceive(anyhow!("Error writing guest memory: {}", e))
+            });
+        }
+        Ok(())
+    })?;
+
+    let mut expected = [0u8; 4];
+    stream
+        .read_exact(&mut expected)
+        .map_err(MigratableError::MigrateSocket)?;
+    if result.is_ok() && u32::from_le_bytes(expected) != crc {
+        result = Err(MigratableError::MigrateReceive(anyhow!(
+            "Checksum mismatch on memory received over stream"
+        )));
+    }
+
+    if result.is_ok() {
+        Response::ok().write_to(stream)?;
+    } else {
+        Response::error().write_to(stream)?;
+    }
+
+    result
+}
+
+/// Additional connections transferring the memory in parallel.
+pub struct MemoryStreams {
+    streams: Vec<MigrationSocket>,
+    compression: Option<Compression>,
+}
+
+impl MemoryStreams {
+    pub fn connect(
+        url: &str,
+        count: u32,
+        tls: Option<&Arc<ClientConfig>>,
+        compression: Option<Compression>,
+    ) -> std::result::Result<Self, MigratableError> {
+        let streams = (0..count)
+            .map(|_| MigrationSocket::connect(url, tls))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            streams,
+            compression,
+        })
+    }
+
+    /// Splits the memory described by the table across the streams, and
+    /// waits for all of it to be acknowledged.
+    pub fn send(
+        &mut self,
+        guest_memory: &GuestMemoryAtomic<GuestMemoryMmap>,
+        table: &MemoryRangeTable,
+    ) -> std::result::Result<(), MigratableError> {
+        // SAFETY: FFI call with a valid name
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+        let tables = table.partition(self.streams.len(), page_size);
+
+        let mut handles = Vec::new();
+        for (i, (mut stream, table)) in self.streams.drain(..).zip(tables).enumerate() {
+            let guest_memory = guest_memory.clone();
+            let compression = self.compression;
+            let handle = thread::Builder::new()
+                .name(format!("migration_stream{i}"))
+                .spawn(move || {
+                    let result = if table.is_empty() {
+                        Ok(())
+                    } else {
+                        send_stream_memory(&mut stream, &guest_memory, &table, compression)
+                    };
+                    (stream, result)
+                })
+                .map_err(|e| {
+                    MigratableError::MigrateSend(anyhow!(
+                        "Error spawning migration stream thread: {}",
+                        e
+                    ))
+                })?;
+            handles.push(handle);
+        }
+
+        let mut result = Ok(());
+        for handle in handles {
+            let (stream, r) = handle.join().map_err(|_| {
+                MigratableError::MigrateSend(anyhow!("Migration stream thread panicked"))
+            })?;
+            self.streams.push(stream);
+            if result.is_ok() {
+                result = r;
+            }
+        }
+
+        result
+    }
+
+    /// Lets the destination know no more memory will be sent.
+    pub fn complete(mut self) -> std::result::Result<(), MigratableError> {
+        for stream in self.streams.iter_mut() {
+            Request::complete().write_to(stream)?;
+            let res = Response::read_from(stream)?;
+            if res.status() != Status::Ok {
+                return Err(MigratableError::MigrateSend(anyhow!(
+                    "Error completing migration stream"
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Receives memory over an additional connection until the source
+/// completes it.
+pub fn receive_memory_stream(
+    mut stream: MigrationSocket,
+    guest_memory: GuestMemoryAtomic<GuestMemoryMmap>,
+    id: usize,
+    compression: Option<Compression>,
+) -> std::result::Result<JoinHandle<std::result::Result<(), MigratableError>>, MigratableError> {
+    thread::Builder::new()
+        .name(format!("migration_stream{id}"))
+        .spawn(move || loop {
+            let req = Request::read_from(&mut stream)?;
+            match req.command() {
+                Command::Memory => {
+                    receive_stream_memory(&mut stream, &guest_memory, &req, compression)?
+                }
+                Command::Complete => {
+                    Response::ok().write_to(&mut stream)?;
+                    return Ok(());
+                }
+                _ => {
+                    Response::error().write_to(&mut stream)?;
+                    return Err(MigratableError::MigrateReceive(anyhow!(
+                        "Unexpected command on migration stream"
+                    )));
+                }
+            }
+        })
+        .map_err(|e| {
+            MigratableError::MigrateReceive(anyhow!(
+                "Error spawning migration stream thread: {}",
+                e
+            ))
+        })
+}
@@ -40,6 +40,10 @@ pub(crate) struct PciSegment {
     pub(crate) start_of_device_area: u64,
     pub(crate) end_of_device_area: u64,
 
+    // 32-bit device memory, only covered by the default segment
+    pub(crate) start_of_mem32_area: u64,
+    pub(crate) end_of_mem32_area: u64,
+
     pub(crate) allocator: Arc<Mutex<AddressAllocator>>,
 }
 
@@ -71,6 +75,8 @@ impl PciSegment {
 
         let start_of_device_area = allocator.lock().unwrap().base().0;
         let end_of_device_area = allocator.lock().unwrap().end().0;
+        let start_of_mem32_area = address_manager.allocator.lock().unwrap().mmio_hole_base().0;
+        let end_of_mem32_area = address_manager.allocator.lock().unwrap().mmio_hole_end().0;
 
         let segment = PciSegment {
             id,
@@ -84,6 +90,8 @@ impl PciSegment {
             allocator,
             start_of_device_area,
             end_of_device_area,
+            start_of_mem32_area,
+            end_of_mem32_area,
             pci_irq_slots: *pci_irq_slots,
         };
 
@@ -350,9 +358,8 @@ impl Aml for PciSegment {
                     &aml::AddressSpace::new_memory(
                         aml::AddressSpaceCachable::NotCacheable,
                         true,
-                        layout::MEM_32BIT_DEVICES_START.0 as u32,
-                        (layout::MEM_32BIT_DEVICES_START.0 + layout::MEM_32BIT_DEVICES_SIZE - 1)
-                            as u32,
+                        self.start_of_mem32_area as u32,
+                        self.end_of_mem32_area as u32,
                     ),
                     &aml::AddressSpace::new_memory(
                         aml::AddressSpaceCachable::NotCacheable,
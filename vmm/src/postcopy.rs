@@ -0,0 +1,447 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Destination side of the postcopy live migration.
+//!
+//! Once the VM runs on the destination, the memory dirtied on the source
+//! after the precopy pass is missing. It is discarded and the guest RAM is
+//! registered with userfaultfd, so that any access to a missing page blocks
+//! until the page has been fetched from the source. The remaining pages are
+//! pulled in the background, serving the faults first.
+
+use crate::GuestMemoryMmap;
+use anyhow::anyhow;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use vm_memory::{Address, GuestAddress, GuestMemory, GuestMemoryAtomic, GuestMemoryRegion};
+use vm_migration::protocol::{MemoryRange, MemoryRangeTable, Request, Response, Status};
+use vm_migration::MigratableError;
+use vmm_sys_util::ioctl::ioctl_with_mut_ref;
+use vmm_sys_util::{ioctl_ioc_nr, ioctl_ior_nr, ioctl_iowr_nr};
+
+// See include/uapi/linux/userfaultfd.h in the kernel code.
+const UFFD_API: u64 = 0xaa;
+const UFFDIO: u32 = 0xaa;
+const UFFDIO_REGISTER_MODE_MISSING: u64 = 1;
+const UFFD_EVENT_PAGEFAULT: u8 = 0x12;
+
+#[repr(C)]
+#[derive(Default)]
+struct UffdioApi {
+    api: u64,
+    features: u64,
+    ioctls: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct UffdioRange {
+    start: u64,
+    len: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct UffdioRegister {
+    range: UffdioRange,
+    mode: u64,
+    ioctls: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct UffdioCopy {
+    dst: u64,
+    src: u64,
+    len: u64,
+    mode: u64,
+    copy: i64,
+}
+
+// Layout of struct uffd_msg for a page fault event
+#[repr(C)]
+#[derive(Default)]
+struct UffdMsg {
+    event: u8,
+    reserved1: u8,
+    reserved2: u16,
+    reserved3: u32,
+    flags: u64,
+    address: u64,
+    ptid: u32,
+    padding: u32,
+}
+
+ioctl_iowr_nr!(UFFDIO_API, UFFDIO, 0x3f, UffdioApi);
+ioctl_iowr_nr!(UFFDIO_REGISTER, UFFDIO, 0x00, UffdioRegister);
+ioctl_ior_nr!(UFFDIO_UNREGISTER, UFFDIO, 0x01, UffdioRange);
+ioctl_iowr_nr!(UFFDIO_COPY, UFFDIO, 0x03, UffdioCopy);
+
+// Amount of missing memory fetched at once in the background
+const CHUNK_SIZE: u64 = 1 << 20;
+
+struct Userfaultfd {
+    file: File,
+}
+
+impl AsRawFd for Userfaultfd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+impl Userfaultfd {
+    fn new() -> io::Result<Self> {
+        // SAFETY: FFI call with valid flags
+        let fd =
+            unsafe { libc::syscall(libc::SYS_userfaultfd, libc::O_CLOEXEC | libc::O_NONBLOCK) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: fd is a valid file descriptor we own
+        let file = unsafe { File::from_raw_fd(fd as RawFd) };
+
+        let mut api = UffdioApi {
+            api: UFFD_API,
+            ..Default::default()
+        };
+        // SAFETY: valid userfaultfd and argument
+        let ret = unsafe { ioctl_with_mut_ref(&file, UFFDIO_API(), &mut api) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self { file })
+    }
+
+    fn register(&self, start: u64, len: u64) -> io::Result<()> {
+        let mut register = UffdioRegister {
+            range: UffdioRange { start, len },
+            mode: UFFDIO_REGISTER_MODE_MISSING,
+            ..Default::default()
+        };
+        // SAFETY: valid userfaultfd and argument
+        let ret = unsafe { ioctl_with_mut_ref(self, UFFDIO_REGISTER(), &mut register) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    fn unregister(&self, start: u64, len: u64) -> io::Result<()> {
+        let mut range = UffdioRange { start, len };
+        // SAFETY: valid userfaultfd and argument
+        let ret = unsafe { ioctl_with_mut_ref(self, UFFDIO_UNREGISTER(), &mut range) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    // Atomically fill the missing pages at dst, waking up the threads
+    // waiting on them. Pages already present are left untouched.
+    fn copy(&self, dst: u64, src: &[u8], page_size: u64) -> io::Result<()> {
+        let len = src.len() as u64;
+        let mut offset = 0;
+        while offset < len {
+            let mut copy = UffdioCopy {
+                dst: dst + offset,
+                src: src.as_ptr() as u64 + offset,
+                len: len - offset,
+                ..Default::default()
+            };
+            // SAFETY: valid userfaultfd, the source buffer outlives the call
+            let ret = unsafe { ioctl_with_mut_ref(self, UFFDIO_COPY(), &mut copy) };
+            if ret == 0 {
+                break;
+            }
+
+            let e = io::Error::last_os_error();
+            if copy.copy > 0 {
+                // Partial copy, retry with what's left
+                offset += copy.copy as u64;
+            } else if e.raw_os_error() == Some(libc::EEXIST) {
+                offset += page_size;
+            } else if e.raw_os_error() != Some(libc::EAGAIN) {
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Return the address of the next page fault, if any
+    fn read_fault(&self) -> io::Result<Option<u64>> {
+        loop {
+            let mut msg = UffdMsg::default();
+            // SAFETY: the buffer is valid and matches the size of a message
+            let ret = unsafe {
+                libc::read(
+                    self.as_raw_fd(),
+                    &mut msg as *mut UffdMsg as *mut libc::c_void,
+                    std::mem::size_of::<UffdMsg>(),
+                )
+            };
+            if ret < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::WouldBlock {
+                    return Ok(None);
+                }
+                return Err(e);
+            }
+
+            if msg.event == UFFD_EVENT_PAGEFAULT {
+                return Ok(Some(msg.address));
+            }
+        }
+    }
+}
+
+// Translate a host address from the guest RAM mappings, given as (host
+// address, guest address, length), into a guest address.
+fn hva_to_gpa(mappings: &[(u64, u64, u64)], hva: u64) -> Option<u64> {
+    mappings
+        .iter()
+        .find(|(start, _, len)| (*start..*start + *len).contains(&hva))
+        .map(|(start, gpa, _)| gpa + hva - start)
+}
+
+// Remove the fetched range from the missing range it belongs to, which is
+// split if the fetched range sits in the middle.
+fn remove_missing(missing: &mut BTreeMap<u64, u64>, gpa: u64, length: u64) {
+    let (start, len) = match missing.range(..=gpa).next_back() {
+        Some((start, len)) if gpa < start + len => (*start, *len),
+        _ => return,
+    };
+
+    missing.remove(&start);
+    if start < gpa {
+        missing.insert(start, gpa - start);
+    }
+    let end = gpa + length;
+    if end < start + len {
+        missing.insert(end, start + len - end);
+    }
+}
+
+/// Fetches the memory missing on the destination from the source.
+pub struct PostcopyReceiver {
+    uffd: Userfaultfd,
+    guest_memory: GuestMemoryAtomic<GuestMemoryMmap>,
+    // Registered ranges as (host address, guest address, length)
+    mappings: Vec<(u64, u64, u64)>,
+    // Missing ranges, indexed by guest address
+    missing: BTreeMap<u64, u64>,
+    page_size: u64,
+}
+
+impl PostcopyReceiver {
+    /// Discards the missing memory and registers the guest RAM so that any
+    /// access to it can be caught.
+    pub fn new(
+        guest_memory: GuestMemoryAtomic<GuestMemoryMmap>,
+        missing: &MemoryRangeTable,
+    ) -> Result<Self, MigratableError> {
+        let uffd = Userfaultfd::new().map_err(|e| {
+            MigratableError::MigrateReceive(anyhow!("Error creating userfaultfd: {}", e))
+        })?;
+
+        // SAFETY: FFI call with a valid name
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+
+        let mem = guest_memory.memory();
+        for range in missing.regions() {
+            let hva = mem.get_host_address(GuestAddress(range.gpa)).map_err(|e| {
+                MigratableError::MigrateReceive(anyhow!("Invalid missing memory: {}", e))
+            })?;
+            // SAFETY: the range is part of the guest RAM mapping
+            let ret = unsafe {
+                libc::madvise(
+                    hva as *mut libc::c_void,
+                    range.length as usize,
+                    libc::MADV_DONTNEED,
+                )
+            };
+            if ret != 0 {
+                return Err(MigratableError::MigrateReceive(anyhow!(
+                    "Error discarding missing memory: {}",
+                    io::Error::last_os_error()
+                )));
+            }
+        }
+
+        let mut mappings = Vec::new();
+        for region in mem.iter() {
+            let hva = region.as_ptr() as u64;
+            uffd.register(hva, region.len()).map_err(|e| {
+                MigratableError::MigrateReceive(anyhow!(
+                    "Error registering memory with userfaultfd: {}",
+                    e
+                ))
+            })?;
+            mappings.push((hva, region.start_addr().raw_value(), region.len()));
+        }
+        drop(mem);
+
+        Ok(Self {
+            uffd,
+            guest_memory,
+            mappings,
+            missing: missing
+                .regions()
+                .iter()
+                .map(|r| (r.gpa, r.length))
+                .collect(),
+            page_size,
+        })
+    }
+
+    fn fetch<T>(&mut self, socket: &mut T, gpa: u64, length: u64) -> Result<(), MigratableError>
+    where
+        T: Read + Write,
+    {
+        let mut table = MemoryRangeTable::default();
+        table.push(MemoryRange { gpa, length });
+        Request::memory(table.length()).write_to(socket)?;
+        table.write_to(socket)?;
+
+        let mut data = vec![0u8; length as usize];
+        socket
+            .read_exact(&mut data)
+            .map_err(MigratableError::MigrateSocket)?;
+
+        let hva = self
+            .guest_memory
+            .memory()
+            .get_host_address(GuestAddress(gpa))
+            .map_err(|e| MigratableError::MigrateReceive(anyhow!("Invalid memory: {}", e)))?;
+        self.uffd
+            .copy(hva as u64, &data, self.page_size)
+            .map_err(|e| {
+                MigratableError::MigrateReceive(anyhow!("Error filling missing memory: {}", e))
+            })?;
+
+        remove_missing(&mut self.missing, gpa, length);
+
+        Ok(())
+    }
+
+    /// Serves the page faults and fetches the missing memory until none is
+    /// left, then lets the source know the migration is over.
+    pub fn run<T>(mut self, socket: &mut T) -> Result<(), MigratableError>
+    where
+        T: Read + Write,
+    {
+        info!(
+            "Postcopy migration: fetching {} missing ranges",
+            self.missing.len()
+        );
+
+        while !self.missing.is_empty() {
+            while let Some(address) = self.uffd.read_fault().map_err(|e| {
+                MigratableError::MigrateReceive(anyhow!("Error reading page fault: {}", e))
+            })? {
+                if let Some(gpa) = hva_to_gpa(&self.mappings, address & !(self.page_size - 1)) {
+                    self.fetch(socket, gpa, self.page_size)?;
+                }
+            }
+
+            if let Some((&gpa, &length)) = self.missing.iter().next() {
+                self.fetch(socket, gpa, length.min(CHUNK_SIZE))?;
+            }
+        }
+
+        for (hva, _, len) in self.mappings.iter() {
+            self.uffd.unregister(*hva, *len).map_err(|e| {
+                MigratableError::MigrateReceive(anyhow!(
+                    "Error unregistering memory from userfaultfd: {}",
+                    e
+                ))
+            })?;
+        }
+
+        Request::complete().write_to(socket)?;
+        let res = Response::read_from(socket)?;
+        if res.status() != Status::Ok {
+            return Err(MigratableError::MigrateReceive(anyhow!(
+                "Error completing postcopy migration"
+            )));
+        }
+        info!("Postcopy migration complete");
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hva_to_gpa() {
+        let mappings = [
+            (0x7f00_0000_0000, 0, 0x1000_0000),
+            (0x7f80_0000_0000, 1 << 32, 0x2000),
+        ];
+
+        assert_eq!(hva_to_gpa(&mappings, 0x7f00_0000_0000), Some(0));
+        assert_eq!(hva_to_gpa(&mappings, 0x7f00_0fff_f000), Some(0xfff_f000));
+        assert_eq!(
+            hva_to_gpa(&mappings, 0x7f80_0000_1000),
+            Some((1 << 32) + 0x1000)
+        );
+
+        // Outside of any region
+        assert_eq!(hva_to_gpa(&mappings, 0x7eff_ffff_f000), None);
+        assert_eq!(hva_to_gpa(&mappings, 0x7f00_1000_0000), None);
+        assert_eq!(hva_to_gpa(&mappings, 0x7f80_0000_2000), None);
+        assert_eq!(hva_to_gpa(&[], 0x7f00_0000_0000), None);
+    }
+
+    #[test]
+    fn test_remove_missing() {
+        let mut missing = BTreeMap::from([(0x1000, 0x6000), (0x10000, 0x1000)]);
+
+        // Head of a range
+        remove_missing(&mut missing, 0x1000, 0x1000);
+        assert_eq!(
+            missing,
+            BTreeMap::from([(0x2000, 0x5000), (0x10000, 0x1000)])
+        );
+
+        // Tail of a range
+        remove_missing(&mut missing, 0x6000, 0x1000);
+        assert_eq!(
+            missing,
+            BTreeMap::from([(0x2000, 0x4000), (0x10000, 0x1000)])
+        );
+
+        // Middle of a range, which gets split
+        remove_missing(&mut missing, 0x3000, 0x1000);
+        assert_eq!(
+            missing,
+            BTreeMap::from([(0x2000, 0x1000), (0x4000, 0x2000), (0x10000, 0x1000)])
+        );
+
+        // Outside of any range
+        remove_missing(&mut missing, 0x0, 0x1000);
+        remove_missing(&mut missing, 0x3000, 0x1000);
+        remove_missing(&mut missing, 0x6000, 0x1000);
+        assert_eq!(
+            missing,
+            BTreeMap::from([(0x2000, 0x1000), (0x4000, 0x2000), (0x10000, 0x1000)])
+        );
+
+        // Whole ranges
+        remove_missing(&mut missing, 0x10000, 0x1000);
+        remove_missing(&mut missing, 0x2000, 0x1000);
+        remove_missing(&mut missing, 0x4000, 0x2000);
+        assert!(missing.is_empty());
+    }
+}
@@ -6,17 +6,24 @@
 
 use hypervisor::HypervisorType;
 use seccompiler::{
-    BackendError, BpfProgram, Error, SeccompAction, SeccompCmpArgLen as ArgLen, SeccompCmpOp::Eq,
+    BackendError, BpfProgram, Error, SeccompAction, SeccompCmpArgLen as ArgLen,
+    SeccompCmpOp::{Eq, MaskedEq},
     SeccompCondition as Cond, SeccompFilter, SeccompRule,
 };
 use std::convert::TryInto;
 
 pub enum Thread {
     Api,
+    BalloonPolicy,
+    #[cfg(feature = "dbus_api")]
+    DBusApi,
+    InputInjection,
     SignalHandler,
     Vcpu,
     Vmm,
     PtyForeground,
+    #[cfg(feature = "tdx")]
+    TdxQuote,
 }
 
 /// Shorthand for chaining `SeccompCondition`s with the `and` operator  in a `SeccompRule`.
@@ -54,6 +61,9 @@ const BLKPBSZGET: u64 = 0x127b;
 const BLKIOMIN: u64 = 0x1278;
 const BLKIOOPT: u64 = 0x1279;
 
+// See include/scsi/sg.h in the kernel code.
+const SG_GET_VERSION_NUM: u64 = 0x2282;
+
 // See include/uapi/linux/if_tun.h in the kernel code.
 const TUNGETIFF: u64 = 0x8004_54d2;
 const TUNSETIFF: u64 = 0x4004_54ca;
@@ -61,6 +71,19 @@ const TUNSETOFFLOAD: u64 = 0x4004_54d0;
 const TUNSETVNETHDRSZ: u64 = 0x4004_54d8;
 const TUNGETFEATURES: u64 = 0x8004_54cf;
 
+// See include/uapi/linux/input.h in the kernel code.
+const EVIOCGID: u64 = 0x8008_4502;
+const EVIOCGNAME: u64 = 0x8080_4506;
+const EVIOCGUNIQ: u64 = 0x8080_4508;
+const EVIOCGPROP: u64 = 0x8080_4509;
+const EVIOCGBIT_TYPES: u64 = 0x8004_4520;
+// The event type, or the axis, is encoded in the low bits.
+const EVIOCGBIT: u64 = 0x8060_4520;
+const EVIOCGBIT_MASK: u64 = 0xffff_ffe0;
+const EVIOCGABS: u64 = 0x8018_4540;
+const EVIOCGABS_MASK: u64 = 0xffff_ffc0;
+const EVIOCGRAB: u64 = 0x4004_4590;
+
 // See include/uapi/linux/sockios.h in the kernel code.
 const SIOCGIFFLAGS: u64 = 0x8913;
 const SIOCGIFHWADDR: u64 = 0x8927;
@@ -92,6 +115,7 @@ const VFIO_DEVICE_IOEVENTFD: u64 = 0x3b74;
 const VHOST_GET_FEATURES: u64 = 0x8008af00;
 const VHOST_SET_FEATURES: u64 = 0x4008af00;
 const VHOST_SET_OWNER: u64 = 0xaf01;
+const VHOST_SET_MEM_TABLE: u64 = 0x4008af03;
 const VHOST_SET_VRING_NUM: u64 = 0x4008af10;
 const VHOST_SET_VRING_ADDR: u64 = 0x4028af11;
 const VHOST_SET_VRING_BASE: u64 = 0x4008af12;
@@ -99,6 +123,7 @@ const VHOST_SET_VRING_KICK: u64 = 0x4008af20;
 const VHOST_SET_VRING_CALL: u64 = 0x4008af21;
 const VHOST_SET_BACKEND_FEATURES: u64 = 0x4008af25;
 const VHOST_GET_BACKEND_FEATURES: u64 = 0x8008af26;
+const VHOST_NET_SET_BACKEND: u64 = 0x4008af30;
 const VHOST_VDPA_GET_DEVICE_ID: u64 = 0x8004af70;
 const VHOST_VDPA_GET_STATUS: u64 = 0x8001af71;
 const VHOST_VDPA_SET_STATUS: u64 = 0x4001af72;
@@ -111,6 +136,12 @@ const VHOST_VDPA_GET_IOVA_RANGE: u64 = 0x8010af78;
 const VHOST_VDPA_GET_CONFIG_SIZE: u64 = 0x8004af79;
 const VHOST_VDPA_SUSPEND: u64 = 0xaf7d;
 
+// See include/uapi/linux/userfaultfd.h in the kernel code.
+const UFFDIO_API: u64 = 0xc018_aa3f;
+const UFFDIO_REGISTER: u64 = 0xc020_aa00;
+const UFFDIO_UNREGISTER: u64 = 0x8010_aa01;
+const UFFDIO_COPY: u64 = 0xc028_aa03;
+
 // See include/uapi/linux/kvm.h in the kernel code.
 #[cfg(feature = "kvm")]
 mod kvm {
@@ -135,6 +166,7 @@ mod kvm {
     pub const KVM_GET_MP_STATE: u64 = 0x8004_ae98;
     pub const KVM_GET_DEVICE_ATTR: u64 = 0x4018_aee2;
     pub const KVM_GET_DIRTY_LOG: u64 = 0x4010_ae42;
+    pub const KVM_RESET_DIRTY_RINGS: u64 = 0xaec7;
     pub const KVM_GET_VCPU_EVENTS: u64 = 0x8040_ae9f;
     pub const KVM_GET_ONE_REG: u64 = 0x4010_aeab;
     pub const KVM_GET_REGS: u64 = 0x8090_ae81;
@@ -142,6 +174,16 @@ mod kvm {
     pub const KVM_CREATE_DEVICE: u64 = 0xc00c_aee0;
     pub const KVM_GET_REG_LIST: u64 = 0xc008_aeb0;
     pub const KVM_MEMORY_ENCRYPT_OP: u64 = 0xc008_aeba;
+    #[cfg(target_arch = "x86_64")]
+    pub const KVM_NMI: u64 = 0xae9a;
+    #[cfg(feature = "sev_snp")]
+    pub const KVM_SET_USER_MEMORY_REGION2: u64 = 0x40a0_ae49;
+    #[cfg(feature = "sev_snp")]
+    pub const KVM_SET_MEMORY_ATTRIBUTES: u64 = 0x4020_aed2;
+    #[cfg(feature = "sev_snp")]
+    pub const KVM_CREATE_GUEST_MEMFD: u64 = 0xc040_aed4;
+    #[cfg(feature = "sev_snp")]
+    pub const KVM_MEMORY_ENCRYPT_REG_REGION: u64 = 0x8010_aebb;
 }
 
 #[cfg(feature = "kvm")]
@@ -201,7 +243,8 @@ fn create_vmm_ioctl_seccomp_rule_common_mshv() -> Result<Vec<SeccompRule>, Backe
 
 #[cfg(feature = "kvm")]
 fn create_vmm_ioctl_seccomp_rule_common_kvm() -> Result<Vec<SeccompRule>, BackendError> {
-    Ok(or![
+    #[allow(unused_mut)]
+    let mut rules = or![
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_CHECK_EXTENSION)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_CREATE_DEVICE,)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_CREATE_IRQCHIP,)?],
@@ -220,6 +263,7 @@ fn create_vmm_ioctl_seccomp_rule_common_kvm() -> Result<Vec<SeccompRule>, Backen
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_GET_VCPU_MMAP_SIZE,)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_IOEVENTFD)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_IRQFD)?],
+        and![Cond::new(1, ArgLen::Dword, Eq, KVM_RESET_DIRTY_RINGS)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_RUN)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_MEMORY_ENCRYPT_OP)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_SET_DEVICE_ATTR,)?],
@@ -230,7 +274,26 @@ fn create_vmm_ioctl_seccomp_rule_common_kvm() -> Result<Vec<SeccompRule>, Backen
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_SET_REGS)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_SET_USER_MEMORY_REGION,)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_SET_VCPU_EVENTS,)?],
-    ])
+    ];
+    #[cfg(feature = "sev_snp")]
+    rules.append(&mut or![
+        and![Cond::new(1, ArgLen::Dword, Eq, KVM_CREATE_GUEST_MEMFD)?],
+        and![Cond::new(
+            1,
+            ArgLen::Dword,
+            Eq,
+            KVM_MEMORY_ENCRYPT_REG_REGION
+        )?],
+        and![Cond::new(1, ArgLen::Dword, Eq, KVM_SET_MEMORY_ATTRIBUTES)?],
+        and![Cond::new(
+            1,
+            ArgLen::Dword,
+            Eq,
+            KVM_SET_USER_MEMORY_REGION2
+        )?],
+    ]);
+
+    Ok(rules)
 }
 
 fn create_vmm_ioctl_seccomp_rule_hypervisor(
@@ -252,6 +315,24 @@ fn create_vmm_ioctl_seccomp_rule_common(
         and![Cond::new(1, ArgLen::Dword, Eq, BLKPBSZGET)?],
         and![Cond::new(1, ArgLen::Dword, Eq, BLKIOMIN)?],
         and![Cond::new(1, ArgLen::Dword, Eq, BLKIOOPT)?],
+        and![Cond::new(1, ArgLen::Dword, Eq, EVIOCGID)?],
+        and![Cond::new(1, ArgLen::Dword, Eq, EVIOCGNAME)?],
+        and![Cond::new(1, ArgLen::Dword, Eq, EVIOCGUNIQ)?],
+        and![Cond::new(1, ArgLen::Dword, Eq, EVIOCGPROP)?],
+        and![Cond::new(1, ArgLen::Dword, Eq, EVIOCGBIT_TYPES)?],
+        and![Cond::new(
+            1,
+            ArgLen::Dword,
+            MaskedEq(EVIOCGBIT_MASK),
+            EVIOCGBIT
+        )?],
+        and![Cond::new(
+            1,
+            ArgLen::Dword,
+            MaskedEq(EVIOCGABS_MASK),
+            EVIOCGABS
+        )?],
+        and![Cond::new(1, ArgLen::Dword, Eq, EVIOCGRAB)?],
         and![Cond::new(1, ArgLen::Dword, Eq, FIOCLEX)?],
         and![Cond::new(1, ArgLen::Dword, Eq, FIONBIO)?],
         and![Cond::new(1, ArgLen::Dword, Eq, SIOCGIFFLAGS)?],
@@ -262,6 +343,7 @@ fn create_vmm_ioctl_seccomp_rule_common(
         and![Cond::new(1, ArgLen::Dword, Eq, SIOCSIFHWADDR)?],
         and![Cond::new(1, ArgLen::Dword, Eq, SIOCSIFMTU)?],
         and![Cond::new(1, ArgLen::Dword, Eq, SIOCSIFNETMASK)?],
+        and![Cond::new(1, ArgLen::Dword, Eq, SG_GET_VERSION_NUM)?],
         and![Cond::new(1, ArgLen::Dword, Eq, TCSETS)?],
         and![Cond::new(1, ArgLen::Dword, Eq, TCGETS)?],
         and![Cond::new(1, ArgLen::Dword, Eq, TIOCGTPEER)?],
@@ -297,6 +379,7 @@ fn create_vmm_ioctl_seccomp_rule_common(
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_GET_FEATURES)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_SET_FEATURES)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_SET_OWNER)?],
+        and![Cond::new(1, ArgLen::Dword, Eq, VHOST_SET_MEM_TABLE)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_SET_VRING_NUM)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_SET_VRING_ADDR)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_SET_VRING_BASE)?],
@@ -304,6 +387,7 @@ fn create_vmm_ioctl_seccomp_rule_common(
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_SET_VRING_CALL)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_SET_BACKEND_FEATURES)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_GET_BACKEND_FEATURES)?],
+        and![Cond::new(1, ArgLen::Dword, Eq, VHOST_NET_SET_BACKEND)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_VDPA_GET_DEVICE_ID)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_VDPA_GET_STATUS)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_VDPA_SET_STATUS)?],
@@ -320,6 +404,10 @@ fn create_vmm_ioctl_seccomp_rule_common(
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_VDPA_GET_IOVA_RANGE)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_VDPA_GET_CONFIG_SIZE)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_VDPA_SUSPEND)?],
+        and![Cond::new(1, ArgLen::Dword, Eq, UFFDIO_API)?],
+        and![Cond::new(1, ArgLen::Dword, Eq, UFFDIO_REGISTER)?],
+        and![Cond::new(1, ArgLen::Dword, Eq, UFFDIO_UNREGISTER)?],
+        and![Cond::new(1, ArgLen::Dword, Eq, UFFDIO_COPY)?],
     ];
 
     let hypervisor_rules = create_vmm_ioctl_seccomp_rule_hypervisor(hypervisor_type)?;
@@ -453,6 +541,42 @@ fn signal_handler_thread_rules() -> Result<Vec<(i64, Vec<SeccompRule>)>, Backend
     ])
 }
 
+fn balloon_policy_thread_rules() -> Result<Vec<(i64, Vec<SeccompRule>)>, BackendError> {
+    Ok(vec![
+        (libc::SYS_brk, vec![]),
+        (libc::SYS_clock_gettime, vec![]),
+        (libc::SYS_close, vec![]),
+        (libc::SYS_exit, vec![]),
+        (libc::SYS_fstat, vec![]),
+        (libc::SYS_futex, vec![]),
+        (libc::SYS_madvise, vec![]),
+        (libc::SYS_mmap, vec![]),
+        (libc::SYS_munmap, vec![]),
+        (libc::SYS_openat, vec![]),
+        (libc::SYS_read, vec![]),
+        (libc::SYS_rt_sigprocmask, vec![]),
+        (libc::SYS_sigaltstack, vec![]),
+        (libc::SYS_statx, vec![]),
+        (libc::SYS_write, vec![]),
+    ])
+}
+
+fn input_injection_thread_rules() -> Result<Vec<(i64, Vec<SeccompRule>)>, BackendError> {
+    Ok(vec![
+        (libc::SYS_brk, vec![]),
+        (libc::SYS_clock_gettime, vec![]),
+        (libc::SYS_close, vec![]),
+        (libc::SYS_exit, vec![]),
+        (libc::SYS_futex, vec![]),
+        (libc::SYS_madvise, vec![]),
+        (libc::SYS_mmap, vec![]),
+        (libc::SYS_munmap, vec![]),
+        (libc::SYS_rt_sigprocmask, vec![]),
+        (libc::SYS_sigaltstack, vec![]),
+        (libc::SYS_write, vec![]),
+    ])
+}
+
 fn create_pty_foreground_ioctl_seccomp_rule() -> Result<Vec<SeccompRule>, BackendError> {
     Ok(or![
         and![Cond::new(1, ArgLen::Dword, Eq, TIOCSCTTY)?],
@@ -482,6 +606,29 @@ fn pty_foreground_thread_rules() -> Result<Vec<(i64, Vec<SeccompRule>)>, Backend
     ])
 }
 
+#[cfg(feature = "tdx")]
+fn tdx_quote_thread_rules() -> Result<Vec<(i64, Vec<SeccompRule>)>, BackendError> {
+    Ok(vec![
+        (libc::SYS_brk, vec![]),
+        (libc::SYS_close, vec![]),
+        (libc::SYS_connect, vec![]),
+        (libc::SYS_exit, vec![]),
+        (libc::SYS_futex, vec![]),
+        (libc::SYS_madvise, vec![]),
+        (libc::SYS_mmap, vec![]),
+        (libc::SYS_mprotect, vec![]),
+        (libc::SYS_munmap, vec![]),
+        (libc::SYS_read, vec![]),
+        (libc::SYS_recvfrom, vec![]),
+        (libc::SYS_rt_sigprocmask, vec![]),
+        (libc::SYS_sendto, vec![]),
+        (libc::SYS_setsockopt, vec![]),
+        (libc::SYS_sigaltstack, vec![]),
+        (libc::SYS_socket, vec![]),
+        (libc::SYS_write, vec![]),
+    ])
+}
+
 // The filter containing the white listed syscall rules required by the VMM to
 // function.
 fn vmm_thread_rules(
@@ -580,6 +727,7 @@ fn vmm_thread_rules(
         (libc::SYS_rt_sigreturn, vec![]),
         (libc::SYS_sched_getaffinity, vec![]),
         (libc::SYS_sched_setaffinity, vec![]),
+        (libc::SYS_sched_setscheduler, vec![]),
         (libc::SYS_sendmsg, vec![]),
         (libc::SYS_sendto, vec![]),
         (libc::SYS_set_robust_list, vec![]),
@@ -592,6 +740,7 @@ fn vmm_thread_rules(
             or![
                 and![Cond::new(0, ArgLen::Dword, Eq, libc::AF_UNIX as u64)?],
                 and![Cond::new(0, ArgLen::Dword, Eq, libc::AF_INET as u64)?],
+                and![Cond::new(0, ArgLen::Dword, Eq, libc::AF_ALG as u64)?],
             ],
         ),
         (libc::SYS_socketpair, vec![]),
@@ -610,6 +759,7 @@ fn vmm_thread_rules(
         (libc::SYS_unlink, vec![]),
         #[cfg(target_arch = "aarch64")]
         (libc::SYS_unlinkat, vec![]),
+        (libc::SYS_userfaultfd, vec![]),
         (libc::SYS_wait4, vec![]),
         (libc::SYS_write, vec![]),
         (libc::SYS_writev, vec![]),
@@ -618,15 +768,30 @@ fn vmm_thread_rules(
 
 #[cfg(feature = "kvm")]
 fn create_vcpu_ioctl_seccomp_rule_kvm() -> Result<Vec<SeccompRule>, BackendError> {
-    Ok(or![
+    #[allow(unused_mut)]
+    let mut rules = or![
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_CHECK_EXTENSION,)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_IOEVENTFD)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_IRQFD,)?],
+        // The dirty rings are harvested when one of them is full
+        and![Cond::new(1, ArgLen::Dword, Eq, KVM_RESET_DIRTY_RINGS,)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_SET_DEVICE_ATTR,)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_SET_GSI_ROUTING,)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_SET_USER_MEMORY_REGION,)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_RUN,)?],
-    ])
+    ];
+    #[cfg(target_arch = "x86_64")]
+    rules.push(and![Cond::new(1, ArgLen::Dword, Eq, KVM_NMI)?]);
+    // Page state changes requested by a SEV-SNP guest
+    #[cfg(feature = "sev_snp")]
+    rules.push(and![Cond::new(
+        1,
+        ArgLen::Dword,
+        Eq,
+        KVM_SET_MEMORY_ATTRIBUTES
+    )?]);
+
+    Ok(rules)
 }
 
 #[cfg(feature = "mshv")]
@@ -748,6 +913,8 @@ fn api_thread_rules() -> Result<Vec<(i64, Vec<SeccompRule>)>, BackendError> {
         (libc::SYS_fcntl, vec![]),
         (libc::SYS_futex, vec![]),
         (libc::SYS_getrandom, vec![]),
+        (libc::SYS_getsockname, vec![]),
+        (libc::SYS_getsockopt, vec![]),
         (libc::SYS_ioctl, create_api_ioctl_seccomp_rule()?),
         (libc::SYS_madvise, vec![]),
         (libc::SYS_mmap, vec![]),
@@ -755,6 +922,36 @@ fn api_thread_rules() -> Result<Vec<(i64, Vec<SeccompRule>)>, BackendError> {
         (libc::SYS_munmap, vec![]),
         (libc::SYS_recvfrom, vec![]),
         (libc::SYS_recvmsg, vec![]),
+        (libc::SYS_setsockopt, vec![]),
+        (libc::SYS_sigaltstack, vec![]),
+        (libc::SYS_write, vec![]),
+    ])
+}
+
+#[cfg(feature = "dbus_api")]
+fn dbus_api_thread_rules() -> Result<Vec<(i64, Vec<SeccompRule>)>, BackendError> {
+    Ok(vec![
+        (libc::SYS_brk, vec![]),
+        (libc::SYS_close, vec![]),
+        (libc::SYS_dup, vec![]),
+        (libc::SYS_epoll_ctl, vec![]),
+        (libc::SYS_epoll_pwait, vec![]),
+        #[cfg(target_arch = "x86_64")]
+        (libc::SYS_epoll_wait, vec![]),
+        (libc::SYS_exit, vec![]),
+        (libc::SYS_fcntl, vec![]),
+        (libc::SYS_futex, vec![]),
+        (libc::SYS_getrandom, vec![]),
+        (libc::SYS_madvise, vec![]),
+        (libc::SYS_mmap, vec![]),
+        (libc::SYS_mprotect, vec![]),
+        (libc::SYS_munmap, vec![]),
+        (libc::SYS_read, vec![]),
+        (libc::SYS_recvmsg, vec![]),
+        (libc::SYS_rt_sigprocmask, vec![]),
+        (libc::SYS_sched_getaffinity, vec![]),
+        (libc::SYS_sendmsg, vec![]),
+        (libc::SYS_set_robust_list, vec![]),
         (libc::SYS_sigaltstack, vec![]),
         (libc::SYS_write, vec![]),
     ])
@@ -766,10 +963,16 @@ fn get_seccomp_rules(
 ) -> Result<Vec<(i64, Vec<SeccompRule>)>, BackendError> {
     match thread_type {
         Thread::Api => Ok(api_thread_rules()?),
+        Thread::BalloonPolicy => Ok(balloon_policy_thread_rules()?),
+        #[cfg(feature = "dbus_api")]
+        Thread::DBusApi => Ok(dbus_api_thread_rules()?),
+        Thread::InputInjection => Ok(input_injection_thread_rules()?),
         Thread::SignalHandler => Ok(signal_handler_thread_rules()?),
         Thread::Vcpu => Ok(vcpu_thread_rules(hypervisor_type)?),
         Thread::Vmm => Ok(vmm_thread_rules(hypervisor_type)?),
         Thread::PtyForeground => Ok(pty_foreground_thread_rules()?),
+        #[cfg(feature = "tdx")]
+        Thread::TdxQuote => Ok(tdx_quote_thread_rules()?),
     }
 }
 
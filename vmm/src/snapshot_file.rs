@@ -0,0 +1,352 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Single file snapshot container.
+//!
+//! As an alternative to a directory holding one file per component, a
+//! snapshot can be stored as a single file, written and read sequentially so
+//! that it can be kept on an object store and shipped as a whole. All the
+//! integers are little endian:
+//!
+//! ```text
+//! header:   magic "CHSNAPSH" | version (u32) | number of sections (u32)
+//! index:    for each section, kind (u32) | reserved (u32) | offset (u64) | length (u64)
+//! sections: VM configuration | VM state | guest memory
+//! ```
+//!
+//! The configuration and the state are stored as JSON, the state holding
+//! the state of each device under its id. The memory section starts with the
+//! size of the extent table (u64) followed by the table itself, then the
+//! content of each extent. Pages only made of zeroes are left out since the
+//! memory of a restored VM starts zeroed.
+
+use crate::GuestMemoryMmap;
+use anyhow::anyhow;
+use serde::de::DeserializeOwned;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use vm_memory::{Bytes, GuestAddress};
+use vm_migration::protocol::{MemoryRange, MemoryRangeTable};
+use vm_migration::MigratableError;
+
+const MAGIC: [u8; 8] = *b"CHSNAPSH";
+const VERSION: u32 = 1;
+const HEADER_SIZE: usize = 16;
+const INDEX_ENTRY_SIZE: usize = 24;
+// Granularity of the zero pages detection
+const PAGE_SIZE: usize = 4096;
+// Amount of guest memory handled at once
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// Sections of a snapshot file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Section {
+    Config = 1,
+    State = 2,
+    Memory = 3,
+}
+
+// Split the memory ranges into extents, leaving the zero pages out
+fn memory_extents(
+    guest_memory: &GuestMemoryMmap,
+    ranges: &MemoryRangeTable,
+) -> Result<MemoryRangeTable, MigratableError> {
+    let mut extents = MemoryRangeTable::default();
+    let mut buf = vec![0u8; CHUNK_SIZE];
+
+    for range in ranges.regions() {
+        let mut extent: Option<MemoryRange> = None;
+        let mut offset = 0;
+        while offset < range.length {
+            let len = (range.length - offset).min(CHUNK_SIZE as u64) as usize;
+            guest_memory
+                .read_slice(&mut buf[..len], GuestAddress(range.gpa + offset))
+                .map_err(|e| MigratableError::MigrateSend(e.into()))?;
+
+            for (i, page) in buf[..len].chunks(PAGE_SIZE).enumerate() {
+                if page.iter().all(|b| *b == 0) {
+                    if let Some(extent) = extent.take() {
+                        extents.push(extent);
+                    }
+                } else if let Some(extent) = extent.as_mut() {
+                    extent.length += page.len() as u64;
+                } else {
+                    extent = Some(MemoryRange {
+                        gpa: range.gpa + offset + (i * PAGE_SIZE) as u64,
+                        length: page.len() as u64,
+                    });
+                }
+            }
+            offset += len as u64;
+        }
+
+        if let Some(extent) = extent {
+            extents.push(extent);
+        }
+    }
+
+    Ok(extents)
+}
+
+fn write_sections<W: Write>(
+    writer: &mut W,
+    config: &[u8],
+    state: &[u8],
+    guest_memory: &GuestMemoryMmap,
+    extents: &MemoryRangeTable,
+) -> io::Result<()> {
+    let memory_length = 8 + extents.length() + extents.memory_size();
+    let sections = [
+        (Section::Config, config.len() as u64),
+        (Section::State, state.len() as u64),
+        (Section::Memory, memory_length),
+    ];
+
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    writer.write_all(&(sections.len() as u32).to_le_bytes())?;
+    let mut offset = (HEADER_SIZE + sections.len() * INDEX_ENTRY_SIZE) as u64;
+    for (section, length) in sections.iter() {
+        writer.write_all(&(*section as u32).to_le_bytes())?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(&offset.to_le_bytes())?;
+        writer.write_all(&length.to_le_bytes())?;
+        offset += length;
+    }
+
+    writer.write_all(config)?;
+    writer.write_all(state)?;
+
+    writer.write_all(&extents.length().to_le_bytes())?;
+    extents
+        .write_to(writer)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{:?}", e)))?;
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    for extent in extents.regions() {
+        let mut offset = 0;
+        while offset < extent.length {
+            let len = (extent.length - offset).min(CHUNK_SIZE as u64) as usize;
+            guest_memory
+                .read_slice(&mut buf[..len], GuestAddress(extent.gpa + offset))
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            writer.write_all(&buf[..len])?;
+            offset += len as u64;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the snapshot of a VM as a single file. The file only shows up
+/// at the given path once complete.
+pub fn write_snapshot_file(
+    path: &Path,
+    config: &[u8],
+    state: &[u8],
+    guest_memory: &GuestMemoryMmap,
+    memory_ranges: &MemoryRangeTable,
+) -> Result<(), MigratableError> {
+    if path.exists() {
+        return Err(MigratableError::MigrateSend(anyhow!(
+            "Snapshot file {:?} already exists",
+            path
+        )));
+    }
+
+    let extents = memory_extents(guest_memory, memory_ranges)?;
+
+    let mut partial_path = path.as_os_str().to_owned();
+    partial_path.push(".partial");
+    let partial_path = PathBuf::from(partial_path);
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&partial_path)
+        .map_err(|e| MigratableError::MigrateSend(e.into()))?;
+
+    let mut writer = BufWriter::new(file);
+    let res = write_sections(&mut writer, config, state, guest_memory, &extents)
+        .and_then(|_| writer.into_inner().map_err(|e| e.into_error()))
+        .and_then(|file| file.sync_all())
+        .and_then(|_| fs::rename(&partial_path, path));
+    if let Err(e) = res {
+        let _ = fs::remove_file(&partial_path);
+        return Err(MigratableError::MigrateSend(anyhow!(
+            "Error writing snapshot file: {}",
+            e
+        )));
+    }
+
+    Ok(())
+}
+
+/// Snapshot stored as a single file.
+pub struct SnapshotFile {
+    file: File,
+    // Sections as (kind, offset, length)
+    index: Vec<(u32, u64, u64)>,
+}
+
+impl SnapshotFile {
+    pub fn open(path: &Path) -> Result<Self, MigratableError> {
+        let mut file = File::open(path).map_err(|e| {
+            MigratableError::MigrateReceive(anyhow!("Error opening snapshot {:?}: {}", path, e))
+        })?;
+
+        let mut header = [0u8; HEADER_SIZE];
+        file.read_exact(&mut header)
+            .map_err(|e| MigratableError::MigrateReceive(e.into()))?;
+        if header[..8] != MAGIC {
+            return Err(MigratableError::MigrateReceive(anyhow!(
+                "{:?} is not a snapshot file",
+                path
+            )));
+        }
+        let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        if version > VERSION {
+            return Err(MigratableError::MigrateReceive(anyhow!(
+                "Unsupported snapshot file version {}",
+                version
+            )));
+        }
+
+        let count = u32::from_le_bytes(header[12..16].try_into().unwrap());
+        let mut index = Vec::new();
+        for _ in 0..count {
+            let mut entry = [0u8; INDEX_ENTRY_SIZE];
+            file.read_exact(&mut entry)
+                .map_err(|e| MigratableError::MigrateReceive(e.into()))?;
+            index.push((
+                u32::from_le_bytes(entry[0..4].try_into().unwrap()),
+                u64::from_le_bytes(entry[8..16].try_into().unwrap()),
+                u64::from_le_bytes(entry[16..24].try_into().unwrap()),
+            ));
+        }
+
+        Ok(SnapshotFile { file, index })
+    }
+
+    fn section(&mut self, section: Section) -> Result<io::Take<&mut File>, MigratableError> {
+        let (offset, length) = self
+            .index
+            .iter()
+            .find(|(kind, _, _)| *kind == section as u32)
+            .map(|(_, offset, length)| (*offset, *length))
+            .ok_or_else(|| {
+                MigratableError::MigrateReceive(anyhow!(
+                    "Missing {:?} section in snapshot file",
+                    section
+                ))
+            })?;
+
+        self.file
+            .seek(SeekFrom::Start(offset))
+            .map_err(|e| MigratableError::MigrateReceive(e.into()))?;
+
+        Ok((&mut self.file).take(length))
+    }
+
+    /// Reads the configuration or the state of the VM.
+    pub fn read_json<T: DeserializeOwned>(
+        &mut self,
+        section: Section,
+    ) -> Result<T, MigratableError> {
+        let reader = BufReader::new(self.section(section)?);
+        serde_json::from_reader(reader).map_err(|e| MigratableError::MigrateReceive(e.into()))
+    }
+
+    /// Fills the guest memory with the saved extents.
+    pub fn read_memory(&mut self, guest_memory: &GuestMemoryMmap) -> Result<(), MigratableError> {
+        let mut reader = BufReader::new(self.section(Section::Memory)?);
+
+        let mut length = [0u8; 8];
+        reader
+            .read_exact(&mut length)
+            .map_err(|e| MigratableError::MigrateReceive(e.into()))?;
+        let extents = MemoryRangeTable::read_from(&mut reader, u64::from_le_bytes(length))?;
+
+        for extent in extents.regions() {
+            let mut offset = 0;
+            while offset < extent.length {
+                let bytes_read = guest_memory
+                    .read_from(
+                        GuestAddress(extent.gpa + offset),
+                        &mut reader,
+                        (extent.length - offset) as usize,
+                    )
+                    .map_err(|e| MigratableError::MigrateReceive(e.into()))?;
+                if bytes_read == 0 {
+                    return Err(MigratableError::MigrateReceive(anyhow!(
+                        "Truncated snapshot file"
+                    )));
+                }
+                offset += bytes_read as u64;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_file() {
+        let guest_memory =
+            GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 16 * PAGE_SIZE)]).unwrap();
+        guest_memory
+            .write_slice(&[0xa5; PAGE_SIZE], GuestAddress(PAGE_SIZE as u64))
+            .unwrap();
+        guest_memory
+            .write_slice(&[0x5a; 2 * PAGE_SIZE], GuestAddress(8 * PAGE_SIZE as u64))
+            .unwrap();
+
+        let mut ranges = MemoryRangeTable::default();
+        ranges.push(MemoryRange {
+            gpa: 0,
+            length: 16 * PAGE_SIZE as u64,
+        });
+
+        // Only the non zero pages are kept
+        let extents = memory_extents(&guest_memory, &ranges).unwrap();
+        let extents: Vec<(u64, u64)> = extents
+            .regions()
+            .iter()
+            .map(|r| (r.gpa, r.length))
+            .collect();
+        assert_eq!(
+            extents,
+            vec![
+                (PAGE_SIZE as u64, PAGE_SIZE as u64),
+                (8 * PAGE_SIZE as u64, 2 * PAGE_SIZE as u64)
+            ]
+        );
+
+        let dir = vmm_sys_util::tempdir::TempDir::new_with_prefix("/tmp/ch").unwrap();
+        let path = dir.as_path().join("snapshot");
+        write_snapshot_file(&path, b"\"config\"", b"\"state\"", &guest_memory, &ranges).unwrap();
+        assert!(write_snapshot_file(&path, b"", b"", &guest_memory, &ranges).is_err());
+
+        let mut snapshot_file = SnapshotFile::open(&path).unwrap();
+        let config: String = snapshot_file.read_json(Section::Config).unwrap();
+        assert_eq!(config, "config");
+        let state: String = snapshot_file.read_json(Section::State).unwrap();
+        assert_eq!(state, "state");
+
+        let restored = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 16 * PAGE_SIZE)]).unwrap();
+        snapshot_file.read_memory(&restored).unwrap();
+        let mut expected = vec![0u8; 16 * PAGE_SIZE];
+        let mut actual = vec![0u8; 16 * PAGE_SIZE];
+        guest_memory
+            .read_slice(&mut expected, GuestAddress(0))
+            .unwrap();
+        restored.read_slice(&mut actual, GuestAddress(0)).unwrap();
+        assert!(expected == actual);
+    }
+}
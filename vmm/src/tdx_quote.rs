@@ -0,0 +1,306 @@
+// Copyright © 2023 Cloud Hypervisor Authors
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Proxy between the TDG.VP.VMCALL<GetQuote> requests issued by a TDX guest
+//! and the Quote Generation Service (QGS) running on the host.
+//!
+//! The guest hands over a shared buffer holding its TDREPORT. The report is
+//! forwarded to the QGS from a dedicated thread, and the quote it returns is
+//! written back to the same buffer, which the guest polls for completion.
+
+use crate::vm_config::QuoteGenerationService;
+use crate::GuestMemoryMmap;
+use std::io::{self, Read, Write};
+use std::mem::size_of;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+use thiserror::Error;
+use vm_memory::{
+    Address, Bytes, GuestAddress, GuestAddressSpace, GuestMemoryAtomic, GuestMemoryError,
+};
+
+// Layout of the shared buffer, as defined by the GHCI specification
+const QUOTE_BUFFER_VERSION: u64 = 1;
+const QUOTE_BUFFER_STATUS_OFFSET: u64 = 8;
+const QUOTE_BUFFER_IN_LEN_OFFSET: u64 = 16;
+const QUOTE_BUFFER_OUT_LEN_OFFSET: u64 = 20;
+const QUOTE_BUFFER_DATA_OFFSET: u64 = 24;
+
+const GET_QUOTE_SUCCESS: u64 = 0;
+const GET_QUOTE_ERROR: u64 = 0x8000_0000_0000_0000;
+const GET_QUOTE_SERVICE_UNAVAILABLE: u64 = 0x8000_0000_0000_0001;
+
+const TDX_REPORT_SIZE: usize = 1024;
+
+// QGS message protocol, every message being preceded by its size encoded
+// as a 32-bit big endian integer.
+const QGS_MSG_MAJOR_VERSION: u16 = 1;
+const QGS_MSG_MINOR_VERSION: u16 = 0;
+const QGS_MSG_GET_QUOTE_REQ: u32 = 0;
+const QGS_MSG_GET_QUOTE_RESP: u32 = 1;
+const QGS_MSG_HEADER_SIZE: usize = 16;
+const QGS_MSG_MAX_SIZE: usize = 1 << 20;
+
+// Generating a quote can involve fetching collateral from the network.
+const QGS_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Error accessing the quote buffer: {0}")]
+    GuestMemory(#[source] GuestMemoryError),
+    #[error("Unsupported quote buffer version: {0}")]
+    InvalidVersion(u64),
+    #[error("Invalid quote buffer length")]
+    InvalidLength,
+    #[error("Error connecting to the Quote Generation Service: {0}")]
+    Connect(#[source] io::Error),
+    #[error("Error communicating with the Quote Generation Service: {0}")]
+    Io(#[source] io::Error),
+    #[error("Invalid response from the Quote Generation Service")]
+    InvalidResponse,
+    #[error("Quote Generation Service failed with error code: {0}")]
+    Service(u32),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Quote request for the shared buffer located at `gpa`, with the shared
+/// bit still set.
+pub struct QuoteRequest {
+    pub gpa: u64,
+    pub size: u64,
+}
+
+pub struct QuoteGenerator {
+    service: QuoteGenerationService,
+    memory: GuestMemoryAtomic<GuestMemoryMmap>,
+    shared_bit_mask: u64,
+}
+
+impl QuoteGenerator {
+    pub fn new(
+        service: QuoteGenerationService,
+        memory: GuestMemoryAtomic<GuestMemoryMmap>,
+        phys_bits: u8,
+    ) -> Self {
+        // The shared bit is the most significant bit of the guest physical
+        // address width, which is either 48 or 52 bits.
+        let shared_bit = if phys_bits > 48 { 51 } else { 47 };
+        QuoteGenerator {
+            service,
+            memory,
+            shared_bit_mask: 1 << shared_bit,
+        }
+    }
+
+    /// Serve the quote requests until every sender is gone.
+    pub fn run(&self, requests: Receiver<QuoteRequest>) {
+        for request in requests {
+            let address = GuestAddress(request.gpa & !self.shared_bit_mask);
+            if let Err(e) = self.process(address, request.size) {
+                error!("Failed generating TDX quote: {}", e);
+                let status = match e {
+                    Error::Connect(_) => GET_QUOTE_SERVICE_UNAVAILABLE,
+                    _ => GET_QUOTE_ERROR,
+                };
+                if let Err(e) = self
+                    .memory
+                    .memory()
+                    .write_obj(status, address.unchecked_add(QUOTE_BUFFER_STATUS_OFFSET))
+                {
+                    error!("Failed updating TDX quote status: {}", e);
+                }
+            }
+        }
+    }
+
+    fn process(&self, address: GuestAddress, size: u64) -> Result<()> {
+        let memory = self.memory.memory();
+
+        let version: u64 = memory.read_obj(address).map_err(Error::GuestMemory)?;
+        if version != QUOTE_BUFFER_VERSION {
+            return Err(Error::InvalidVersion(version));
+        }
+        let data_size = size
+            .checked_sub(QUOTE_BUFFER_DATA_OFFSET)
+            .ok_or(Error::InvalidLength)?;
+        let in_len: u32 = memory
+            .read_obj(address.unchecked_add(QUOTE_BUFFER_IN_LEN_OFFSET))
+            .map_err(Error::GuestMemory)?;
+        if in_len as usize != TDX_REPORT_SIZE || u64::from(in_len) > data_size {
+            return Err(Error::InvalidLength);
+        }
+
+        let data_address = address.unchecked_add(QUOTE_BUFFER_DATA_OFFSET);
+        let mut report = vec![0u8; TDX_REPORT_SIZE];
+        memory
+            .read_slice(&mut report, data_address)
+            .map_err(Error::GuestMemory)?;
+
+        let quote = self.get_quote(&report)?;
+        if quote.len() as u64 > data_size {
+            return Err(Error::InvalidLength);
+        }
+
+        memory
+            .write_slice(&quote, data_address)
+            .map_err(Error::GuestMemory)?;
+        memory
+            .write_obj(
+                quote.len() as u32,
+                address.unchecked_add(QUOTE_BUFFER_OUT_LEN_OFFSET),
+            )
+            .map_err(Error::GuestMemory)?;
+        // The status is updated last as the guest polls on it
+        memory
+            .write_obj(
+                GET_QUOTE_SUCCESS,
+                address.unchecked_add(QUOTE_BUFFER_STATUS_OFFSET),
+            )
+            .map_err(Error::GuestMemory)
+    }
+
+    fn connect(&self) -> io::Result<UnixStream> {
+        let stream = match &self.service {
+            QuoteGenerationService::Unix(path) => UnixStream::connect(path)?,
+            QuoteGenerationService::Vsock { cid, port } => {
+                // SAFETY: FFI call with valid arguments
+                let fd = unsafe {
+                    libc::socket(libc::AF_VSOCK, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0)
+                };
+                if fd < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                // SAFETY: fd is a valid socket we own. The stream is only
+                // used for its generic socket operations.
+                let stream = unsafe { UnixStream::from_raw_fd(fd) };
+
+                // SAFETY: all zeros is a valid pattern
+                let mut addr: libc::sockaddr_vm = unsafe { std::mem::zeroed() };
+                addr.svm_family = libc::AF_VSOCK as libc::sa_family_t;
+                addr.svm_cid = *cid;
+                addr.svm_port = *port;
+                // SAFETY: FFI call with a valid socket and address
+                let ret = unsafe {
+                    libc::connect(
+                        fd,
+                        &addr as *const libc::sockaddr_vm as *const libc::sockaddr,
+                        size_of::<libc::sockaddr_vm>() as libc::socklen_t,
+                    )
+                };
+                if ret < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                stream
+            }
+        };
+        stream.set_read_timeout(Some(QGS_TIMEOUT))?;
+        stream.set_write_timeout(Some(QGS_TIMEOUT))?;
+        Ok(stream)
+    }
+
+    fn get_quote(&self, report: &[u8]) -> Result<Vec<u8>> {
+        let mut stream = self.connect().map_err(Error::Connect)?;
+
+        let request = get_quote_request(report);
+        stream
+            .write_all(&(request.len() as u32).to_be_bytes())
+            .map_err(Error::Io)?;
+        stream.write_all(&request).map_err(Error::Io)?;
+
+        let mut size = [0u8; 4];
+        stream.read_exact(&mut size).map_err(Error::Io)?;
+        let size = u32::from_be_bytes(size) as usize;
+        if size > QGS_MSG_MAX_SIZE {
+            return Err(Error::InvalidResponse);
+        }
+        let mut response = vec![0u8; size];
+        stream.read_exact(&mut response).map_err(Error::Io)?;
+
+        parse_get_quote_response(&response)
+    }
+}
+
+fn get_quote_request(report: &[u8]) -> Vec<u8> {
+    let size = QGS_MSG_HEADER_SIZE + 8 + report.len();
+    let mut request = Vec::with_capacity(size);
+    request.extend_from_slice(&QGS_MSG_MAJOR_VERSION.to_le_bytes());
+    request.extend_from_slice(&QGS_MSG_MINOR_VERSION.to_le_bytes());
+    request.extend_from_slice(&QGS_MSG_GET_QUOTE_REQ.to_le_bytes());
+    request.extend_from_slice(&(size as u32).to_le_bytes());
+    // Error code
+    request.extend_from_slice(&0u32.to_le_bytes());
+    request.extend_from_slice(&(report.len() as u32).to_le_bytes());
+    // No list of attestation key identifiers
+    request.extend_from_slice(&0u32.to_le_bytes());
+    request.extend_from_slice(report);
+    request
+}
+
+fn parse_get_quote_response(response: &[u8]) -> Result<Vec<u8>> {
+    let read_u32 = |offset: usize| -> Result<u32> {
+        response
+            .get(offset..offset + 4)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+            .ok_or(Error::InvalidResponse)
+    };
+
+    let major_version = read_u32(0)? & 0xffff;
+    let msg_type = read_u32(4)?;
+    let error_code = read_u32(12)?;
+    if major_version != u32::from(QGS_MSG_MAJOR_VERSION) || msg_type != QGS_MSG_GET_QUOTE_RESP {
+        return Err(Error::InvalidResponse);
+    }
+    if error_code != 0 {
+        return Err(Error::Service(error_code));
+    }
+
+    let id_size = read_u32(QGS_MSG_HEADER_SIZE)? as usize;
+    let quote_size = read_u32(QGS_MSG_HEADER_SIZE + 4)? as usize;
+    let quote_offset = QGS_MSG_HEADER_SIZE + 8 + id_size;
+    response
+        .get(quote_offset..quote_offset + quote_size)
+        .map(|q| q.to_vec())
+        .ok_or(Error::InvalidResponse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_qgs_messages() {
+        let report = vec![0xa5u8; TDX_REPORT_SIZE];
+        let request = get_quote_request(&report);
+        assert_eq!(request.len(), QGS_MSG_HEADER_SIZE + 8 + TDX_REPORT_SIZE);
+        assert_eq!(&request[8..12], &(request.len() as u32).to_le_bytes());
+        assert_eq!(&request[QGS_MSG_HEADER_SIZE + 8..], report.as_slice());
+
+        let mut response = Vec::new();
+        response.extend_from_slice(&QGS_MSG_MAJOR_VERSION.to_le_bytes());
+        response.extend_from_slice(&QGS_MSG_MINOR_VERSION.to_le_bytes());
+        response.extend_from_slice(&QGS_MSG_GET_QUOTE_RESP.to_le_bytes());
+        response.extend_from_slice(&(QGS_MSG_HEADER_SIZE as u32 + 8 + 2 + 3).to_le_bytes());
+        response.extend_from_slice(&0u32.to_le_bytes());
+        response.extend_from_slice(&2u32.to_le_bytes());
+        response.extend_from_slice(&3u32.to_le_bytes());
+        response.extend_from_slice(&[0xff, 0xff, 1, 2, 3]);
+        assert_eq!(parse_get_quote_response(&response).unwrap(), vec![1, 2, 3]);
+
+        // Truncated quote
+        assert!(matches!(
+            parse_get_quote_response(&response[..response.len() - 1]),
+            Err(Error::InvalidResponse)
+        ));
+
+        // Error reported by the service
+        response[12..16].copy_from_slice(&0x12u32.to_le_bytes());
+        assert!(matches!(
+            parse_get_quote_response(&response),
+            Err(Error::Service(0x12))
+        ));
+    }
+}
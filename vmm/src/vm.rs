@@ -11,11 +11,14 @@
 // SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
 //
 
+use crate::api::{AddressRange, MemoryZoneInfo, VcpuInfo, VcpuStats};
+#[cfg(builtin_fw_image)]
+use crate::config::BUILTIN_FIRMWARE;
 use crate::config::{
     add_to_config, DeviceConfig, DiskConfig, FsConfig, HotplugMethod, NetConfig, PmemConfig,
     UserDeviceConfig, ValidationError, VdpaConfig, VmConfig, VsockConfig,
 };
-use crate::config::{NumaConfig, PayloadConfig};
+use crate::config::{CpuPlacement, NumaConfig, PayloadConfig};
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 use crate::coredump::{
     CpuElf64Writable, DumpState, Elf64Writable, GuestDebuggable, GuestDebuggableError, NoteDescType,
@@ -25,6 +28,7 @@ use crate::device_manager::{Console, DeviceManager, DeviceManagerError, PtyPair}
 use crate::device_tree::DeviceTree;
 #[cfg(feature = "guest_debug")]
 use crate::gdb::{Debuggable, DebuggableError, GdbRequestPayload, GdbResponsePayload};
+use crate::input_injection::{InputDevices, InputInjector, InputRequest};
 use crate::memory_manager::{
     Error as MemoryManagerError, MemoryManager, MemoryManagerSnapshotData,
 };
@@ -32,24 +36,35 @@ use crate::memory_manager::{
 use crate::migration::get_vm_snapshot;
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 use crate::migration::url_to_file;
-use crate::migration::{url_to_path, SNAPSHOT_CONFIG_FILE, SNAPSHOT_STATE_FILE};
+use crate::migration::{
+    url_to_path, url_to_snapshot_path, SNAPSHOT_CONFIG_FILE, SNAPSHOT_STATE_FILE,
+};
 use crate::seccomp_filters::{get_seccomp_filter, Thread};
+use crate::snapshot_file::write_snapshot_file;
 use crate::GuestMemoryMmap;
 use crate::{
-    PciDeviceInfo, CPU_MANAGER_SNAPSHOT_ID, DEVICE_MANAGER_SNAPSHOT_ID, MEMORY_MANAGER_SNAPSHOT_ID,
+    GuestRegionMmap, PciDeviceInfo, CPU_MANAGER_SNAPSHOT_ID, DEVICE_MANAGER_SNAPSHOT_ID,
+    MEMORY_MANAGER_SNAPSHOT_ID,
 };
 use anyhow::anyhow;
 use arch::get_host_cpu_phys_bits;
 #[cfg(target_arch = "x86_64")]
 use arch::layout::{KVM_IDENTITY_MAP_START, KVM_TSS_START};
+#[cfg(feature = "sev_snp")]
+use arch::x86_64::sev_snp::SevSnpSection;
 #[cfg(feature = "tdx")]
 use arch::x86_64::tdx::TdvfSection;
+#[cfg(target_arch = "x86_64")]
+use arch::x86_64::MemmapType;
+#[cfg(target_arch = "x86_64")]
+use arch::BootProtocol;
 use arch::EntryPoint;
 #[cfg(target_arch = "aarch64")]
 use arch::PciSpaceInfo;
 use arch::{NumaNode, NumaNodes};
 #[cfg(target_arch = "aarch64")]
 use devices::interrupt_controller;
+use devices::legacy::Key;
 use devices::AcpiNotificationFlags;
 #[cfg(all(target_arch = "aarch64", feature = "guest_debug"))]
 use gdbstub_arch::aarch64::reg::AArch64CoreRegs as CoreRegs;
@@ -60,6 +75,8 @@ use linux_loader::cmdline::Cmdline;
 #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
 use linux_loader::elf;
 #[cfg(target_arch = "x86_64")]
+use linux_loader::loader::elf::Error::InvalidElfMagicNumber;
+#[cfg(target_arch = "x86_64")]
 use linux_loader::loader::elf::PvhBootCapability::PvhEntryPresent;
 #[cfg(target_arch = "aarch64")]
 use linux_loader::loader::pe::Error::InvalidImageMagicNumber;
@@ -72,6 +89,8 @@ use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fs::{File, OpenOptions};
+#[cfg(target_arch = "x86_64")]
+use std::io::Read;
 use std::io::{self, Seek, SeekFrom, Write};
 #[cfg(feature = "tdx")]
 use std::mem;
@@ -81,25 +100,39 @@ use std::num::Wrapping;
 use std::ops::Deref;
 use std::os::unix::net::UnixStream;
 use std::panic::AssertUnwindSafe;
+use std::path::Path;
 use std::sync::{Arc, Mutex, RwLock};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::{result, str, thread};
 use thiserror::Error;
 use tracer::trace_scoped;
+use virtio_devices::ConsoleInputKind;
 use vm_device::Bus;
 #[cfg(feature = "tdx")]
-use vm_memory::{Address, ByteValued, GuestMemory, GuestMemoryRegion};
-use vm_memory::{Bytes, GuestAddress, GuestAddressSpace, GuestMemoryAtomic};
+use vm_memory::ByteValued;
+use vm_memory::{
+    Address, Bytes, GuestAddress, GuestAddressSpace, GuestMemory, GuestMemoryAtomic,
+    GuestMemoryRegion,
+};
 use vm_migration::protocol::{Request, Response, Status};
 use vm_migration::{
     protocol::MemoryRangeTable, snapshot_from_id, Migratable, MigratableError, Pausable, Snapshot,
-    SnapshotData, Snapshottable, Transportable,
+    SnapshotData, Snapshottable, StateUpgrade, Transportable,
 };
 use vmm_sys_util::eventfd::EventFd;
 use vmm_sys_util::signal::unblock_signal;
 use vmm_sys_util::sock_ctrl_msg::ScmSocket;
 use vmm_sys_util::terminal::Terminal;
 
+// SEV-SNP guest policy: SMT allowed, along with the reserved bit which must
+// be set.
+#[cfg(feature = "sev_snp")]
+const SEV_SNP_POLICY: u64 = 1 << 17 | 1 << 16;
+
+// SEV-ES guest policy: debugging disallowed, encrypted state required.
+#[cfg(feature = "sev_snp")]
+const SEV_ES_POLICY: u32 = 1 << 2 | 1 << 0;
+
 /// Errors associated with VM management
 #[derive(Debug, Error)]
 pub enum Error {
@@ -109,9 +142,25 @@ pub enum Error {
     #[error("Cannot open initramfs file: {0}")]
     InitramfsFile(#[source] io::Error),
 
+    #[cfg(target_arch = "x86_64")]
+    #[error("Cannot read SMBIOS file: {0}")]
+    SmbiosFile(#[source] io::Error),
+
+    #[cfg(target_arch = "x86_64")]
+    #[error("Cannot read Ignition config: {0}")]
+    IgnitionConfig(#[source] io::Error),
+
     #[error("Cannot load the kernel into memory: {0}")]
     KernelLoad(#[source] linux_loader::loader::Error),
 
+    #[cfg(target_arch = "x86_64")]
+    #[error("Cannot load the Multiboot2 kernel into memory: {0:?}")]
+    Multiboot2Load(arch::x86_64::multiboot2::Error),
+
+    #[cfg(target_arch = "x86_64")]
+    #[error("Cannot decompress the bzImage kernel: {0:?}")]
+    KernelDecompress(arch::x86_64::bzimage::Error),
+
     #[cfg(target_arch = "aarch64")]
     #[error("Cannot load the UEFI binary in memory: {0:?}")]
     UefiLoad(arch::aarch64::uefi::Error),
@@ -119,6 +168,10 @@ pub enum Error {
     #[error("Cannot load the initramfs into memory")]
     InitramfsLoad,
 
+    #[cfg(target_arch = "aarch64")]
+    #[error("Cannot enable MTE: {0}")]
+    EnableMte(#[source] hypervisor::HypervisorVmError),
+
     #[error("Cannot load the kernel command line in memory: {0}")]
     LoadCmdLine(#[source] linux_loader::loader::Error),
 
@@ -231,14 +284,29 @@ pub enum Error {
     #[error("Failed resizing a memory zone")]
     ResizeZone,
 
+    #[error("Cannot resize the vCPUs both by count and by removing specific vCPUs")]
+    ResizeVcpusConflict,
+
     #[error("Cannot activate virtio devices: {0:?}")]
     ActivateVirtioDevices(DeviceManagerError),
 
     #[error("Error triggering power button: {0:?}")]
     PowerButton(DeviceManagerError),
 
-    #[error("Kernel lacks PVH header")]
-    KernelMissingPvhHeader,
+    #[error("Cannot inject input events: {0:?}")]
+    InputInjection(DeviceManagerError),
+
+    #[error("Unknown key: {0}")]
+    UnknownKey(String),
+
+    #[error("The guest has not enabled the input device")]
+    InputDeviceNotReady,
+
+    #[error("Cannot change the medium: {0:?}")]
+    ChangeMedia(DeviceManagerError),
+
+    #[error("Invalid input hold time: {0}ms")]
+    InvalidInputHoldTime(u64),
 
     #[error("Failed to allocate firmware RAM: {0:?}")]
     AllocateFirmwareMemory(MemoryManagerError),
@@ -284,6 +352,16 @@ pub enum Error {
     #[error("Error finalizing TDX VM: {0}")]
     FinalizeTdx(#[source] hypervisor::HypervisorVmError),
 
+    #[error("Cannot spawn the input injection thread: {0}")]
+    InputInjectionSpawn(#[source] io::Error),
+
+    #[cfg(feature = "tdx")]
+    #[error("Cannot spawn the TDX quote generation thread: {0}")]
+    TdxQuoteSpawn(#[source] io::Error),
+
+    #[error("Cannot spawn the balloon policy thread: {0}")]
+    BalloonPolicySpawn(#[source] io::Error),
+
     #[cfg(feature = "tdx")]
     #[error("TDX firmware missing")]
     TdxFirmwareMissing,
@@ -292,6 +370,50 @@ pub enum Error {
     #[error("Invalid TDX payload type")]
     InvalidPayloadType,
 
+    #[cfg(feature = "sev_snp")]
+    #[error("Error performing I/O on SEV firmware file: {0}")]
+    LoadSevFirmware(#[source] std::io::Error),
+
+    #[cfg(feature = "sev_snp")]
+    #[error("Error parsing SEV metadata: {0}")]
+    ParseSevMetadata(#[source] arch::x86_64::sev_snp::SevSnpError),
+
+    #[cfg(feature = "sev_snp")]
+    #[error("Error allocating SEV memory: {0:?}")]
+    AllocatingSevMemory(crate::memory_manager::Error),
+
+    #[cfg(feature = "sev_snp")]
+    #[error("Error writing SEV-SNP CPUID page: {0}")]
+    CreateSevSnpCpuidPage(#[source] arch::x86_64::sev_snp::SevSnpError),
+
+    #[cfg(feature = "sev_snp")]
+    #[error("Error enabling SEV-SNP VM: {0}")]
+    InitializeSevSnpVm(#[source] hypervisor::HypervisorVmError),
+
+    #[cfg(feature = "sev_snp")]
+    #[error("Invalid SEV launch memory region: {0}")]
+    SevLaunchMemory(#[source] vm_memory::GuestMemoryError),
+
+    #[cfg(feature = "sev_snp")]
+    #[error("Error updating SEV-SNP launch memory region: {0}")]
+    SevSnpLaunchUpdate(#[source] hypervisor::HypervisorVmError),
+
+    #[cfg(feature = "sev_snp")]
+    #[error("Error finishing SEV-SNP launch: {0}")]
+    SevSnpLaunchFinish(#[source] hypervisor::HypervisorVmError),
+
+    #[cfg(feature = "sev_snp")]
+    #[error("SEV firmware missing")]
+    SevFirmwareMissing,
+
+    #[cfg(feature = "sev_snp")]
+    #[error("Error enabling SEV-ES VM: {0}")]
+    InitializeSevEsVm(#[source] hypervisor::HypervisorVmError),
+
+    #[cfg(feature = "sev_snp")]
+    #[error("Error during SEV-ES launch: {0}")]
+    SevEsLaunch(#[source] hypervisor::HypervisorVmError),
+
     #[cfg(feature = "guest_debug")]
     #[error("Error debugging VM: {0:?}")]
     Debug(DebuggableError),
@@ -305,6 +427,28 @@ pub enum Error {
     #[error("Payload configuration is not bootable")]
     InvalidPayload,
 
+    #[error("Error logging dirty memory: {0}")]
+    DirtyLog(#[source] MigratableError),
+
+    #[error("Invalid dirty rate measurement period: {0}ms")]
+    InvalidDirtyRatePeriod(u64),
+
+    #[error("No vsock device to reach the guest agent")]
+    GuestAgentNoVsock,
+
+    #[error("Invalid guest command timeout: {0}ms")]
+    InvalidGuestExecTimeout(u64),
+
+    #[error("Guest agent error: {0}")]
+    GuestAgent(#[source] crate::guest_agent::Error),
+
+    #[cfg(not(target_arch = "x86_64"))]
+    #[error("NMI injection is only supported on x86-64")]
+    NmiUnsupported,
+
+    #[error("Cannot persist the hibernated VM: {0}")]
+    PersistHibernation(#[source] io::Error),
+
     #[cfg(all(target_arch = "x86_64", feature = "guest_debug"))]
     #[error("Error coredumping VM: {0:?}")]
     Coredump(GuestDebuggableError),
@@ -427,6 +571,70 @@ impl VmOps for VmOpsHandler {
     }
 }
 
+// Firmware image embedded in the binary, the file being provided at build time
+// through the CH_BUILTIN_FW environment variable. The builtin_fw_image cfg is
+// only set by build.rs once the file has been found.
+#[cfg(builtin_fw_image)]
+static BUILTIN_FIRMWARE_IMAGE: &[u8] = include_bytes!(env!("CH_BUILTIN_FW_IMAGE"));
+
+// Systemd imports the SMBIOS OEM strings with this prefix as credentials,
+// from where the guest can provision itself with the Ignition config.
+#[cfg(target_arch = "x86_64")]
+const IGNITION_OEM_STRING_PREFIX: &str = "io.systemd.credential:ignition.config=";
+// The SMBIOS tables must fit below the 1 MiB boundary
+#[cfg(target_arch = "x86_64")]
+const MAX_IGNITION_CONFIG_SIZE: usize = 32 << 10;
+
+#[cfg(target_arch = "x86_64")]
+fn ignition_oem_string(path: &Path) -> io::Result<String> {
+    let config = std::fs::read_to_string(path)?;
+    if config.len() > MAX_IGNITION_CONFIG_SIZE || config.contains('\0') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("must be a text file of at most {MAX_IGNITION_CONFIG_SIZE} bytes"),
+        ));
+    }
+
+    Ok(format!("{IGNITION_OEM_STRING_PREFIX}{config}"))
+}
+
+// Free memory of each host NUMA node having CPUs, as described by sysfs.
+fn host_numa_nodes_free_memory() -> io::Result<BTreeMap<u32, u64>> {
+    let mut free_memory = BTreeMap::new();
+
+    for entry in std::fs::read_dir("/sys/devices/system/node")?.flatten() {
+        let node = match entry
+            .file_name()
+            .to_str()
+            .and_then(|n| n.strip_prefix("node"))
+            .and_then(|n| n.parse::<u32>().ok())
+        {
+            Some(node) => node,
+            None => continue,
+        };
+
+        // The vCPUs are pinned onto the CPUs of the node, memory only nodes
+        // can't be used.
+        if std::fs::read_to_string(entry.path().join("cpulist"))?
+            .trim()
+            .is_empty()
+        {
+            continue;
+        }
+
+        // Lines look like "Node 0 MemFree:        1234567 kB"
+        let meminfo = std::fs::read_to_string(entry.path().join("meminfo"))?;
+        let free = meminfo
+            .lines()
+            .filter_map(|l| l.split_once("MemFree:"))
+            .find_map(|(_, v)| v.trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+            .unwrap_or(0);
+        free_memory.insert(node, free << 10);
+    }
+
+    Ok(free_memory)
+}
+
 pub fn physical_bits(max_phys_bits: u8) -> u8 {
     let host_phys_bits = get_host_cpu_phys_bits();
 
@@ -442,6 +650,10 @@ pub struct Vm {
     config: Arc<Mutex<VmConfig>>,
     on_tty: bool,
     signals: Option<Handle>,
+    // Dropped to stop the balloon policy thread.
+    balloon_policy_stop: Option<std::sync::mpsc::Sender<()>>,
+    // Dropped to stop the input injection thread.
+    input_injector: Option<std::sync::mpsc::Sender<InputRequest>>,
     state: RwLock<VmState>,
     cpu_manager: Arc<Mutex<cpu::CpuManager>>,
     memory_manager: Arc<Mutex<MemoryManager>>,
@@ -468,6 +680,8 @@ impl Vm {
         vm: Arc<dyn hypervisor::Vm>,
         exit_evt: EventFd,
         reset_evt: EventFd,
+        panic_evt: EventFd,
+        watchdog_evt: EventFd,
         #[cfg(feature = "guest_debug")] vm_debug_evt: EventFd,
         seccomp_action: &SeccompAction,
         hypervisor: Arc<dyn hypervisor::Hypervisor>,
@@ -504,6 +718,12 @@ impl Vm {
         let force_iommu = tdx_enabled;
         #[cfg(not(feature = "tdx"))]
         let force_iommu = false;
+        #[cfg(feature = "sev_snp")]
+        let sev_snp_enabled = config.lock().unwrap().is_sev_snp_enabled();
+        #[cfg(feature = "sev_snp")]
+        let sev_es_enabled = config.lock().unwrap().is_sev_es_enabled();
+        #[cfg(feature = "sev_snp")]
+        let force_iommu = force_iommu || sev_snp_enabled || sev_es_enabled;
 
         #[cfg(feature = "guest_debug")]
         let stop_on_boot = config.lock().unwrap().gdb;
@@ -523,6 +743,7 @@ impl Vm {
         });
 
         let cpus_config = { &config.lock().unwrap().cpus.clone() };
+        let host_numa_nodes = Self::host_numa_nodes(&config.lock().unwrap());
         let cpu_manager = cpu::CpuManager::new(
             cpus_config,
             vm.clone(),
@@ -535,7 +756,11 @@ impl Vm {
             vm_ops,
             #[cfg(feature = "tdx")]
             tdx_enabled,
+            #[cfg(feature = "sev_snp")]
+            sev_snp_enabled
+                || sev_es_enabled,
             &numa_nodes,
+            &host_numa_nodes,
         )
         .map_err(Error::CpuManager)?;
 
@@ -548,6 +773,10 @@ impl Vm {
                 &hypervisor,
                 #[cfg(feature = "tdx")]
                 tdx_enabled,
+                #[cfg(feature = "sev_snp")]
+                sev_snp_enabled,
+                #[cfg(feature = "sev_snp")]
+                sev_es_enabled,
             )
             .map_err(Error::CpuManager)?;
 
@@ -556,11 +785,24 @@ impl Vm {
         #[cfg(feature = "tdx")]
         if tdx_enabled {
             let cpuid = cpu_manager.lock().unwrap().common_cpuid();
-            let max_vcpus = cpu_manager.lock().unwrap().max_vcpus() as u32;
+            let max_vcpus = cpu_manager.lock().unwrap().max_vcpus();
             vm.tdx_init(&cpuid, max_vcpus)
                 .map_err(Error::InitializeTdxVm)?;
         }
 
+        // SEV-SNP and SEV-ES must also be initialized before the vCPUs are
+        // created
+        #[cfg(feature = "sev_snp")]
+        if sev_snp_enabled {
+            vm.sev_snp_init(SEV_SNP_POLICY)
+                .map_err(Error::InitializeSevSnpVm)?;
+        }
+        #[cfg(feature = "sev_snp")]
+        if sev_es_enabled {
+            vm.sev_es_init(SEV_ES_POLICY)
+                .map_err(Error::InitializeSevEsVm)?;
+        }
+
         cpu_manager
             .lock()
             .unwrap()
@@ -571,6 +813,8 @@ impl Vm {
         let dynamic = !tdx_enabled;
         #[cfg(not(feature = "tdx"))]
         let dynamic = true;
+        #[cfg(feature = "sev_snp")]
+        let dynamic = dynamic && !sev_snp_enabled && !sev_es_enabled;
 
         let device_manager = DeviceManager::new(
             #[cfg(target_arch = "x86_64")]
@@ -583,6 +827,8 @@ impl Vm {
             cpu_manager.clone(),
             exit_evt.try_clone().map_err(Error::EventFdClone)?,
             reset_evt,
+            panic_evt,
+            watchdog_evt,
             seccomp_action.clone(),
             numa_nodes.clone(),
             &activate_evt,
@@ -647,6 +893,8 @@ impl Vm {
             on_tty,
             threads: Vec::with_capacity(1),
             signals: None,
+            balloon_policy_stop: None,
+            input_injector: None,
             state: RwLock::new(vm_state),
             cpu_manager,
             memory_manager,
@@ -662,6 +910,81 @@ impl Vm {
         })
     }
 
+    // With the "auto-numa" vCPU placement, bind the memory zones of the guest
+    // NUMA nodes that aren't associated with any host NUMA node onto the host
+    // NUMA node with the most free memory. The choice is saved in the config
+    // so that it stays the same across reboots.
+    fn assign_host_numa_nodes(config: &mut VmConfig) {
+        if config.cpus.placement != Some(CpuPlacement::AutoNuma) {
+            return;
+        }
+
+        let mut free_memory = match host_numa_nodes_free_memory() {
+            Ok(free_memory) => free_memory,
+            Err(e) => {
+                warn!("Could not retrieve the host NUMA topology: {}", e);
+                return;
+            }
+        };
+
+        let zones = match config.memory.zones.as_mut() {
+            Some(zones) => zones,
+            None => return,
+        };
+
+        for numa_config in config.numa.iter().flatten() {
+            let zone_ids = numa_config.memory_zones.as_deref().unwrap_or(&[]);
+            let mut node_zones: Vec<_> = zones
+                .iter_mut()
+                .filter(|zone| zone_ids.contains(&zone.id))
+                .collect();
+            if node_zones.is_empty() || node_zones.iter().any(|z| z.host_numa_node.is_some()) {
+                continue;
+            }
+
+            let host_numa_node = match free_memory.iter().max_by_key(|(_, free)| **free) {
+                Some((host_numa_node, _)) => *host_numa_node,
+                None => return,
+            };
+            let size: u64 = node_zones
+                .iter()
+                .map(|z| z.size + z.hotplug_size.unwrap_or(0))
+                .sum();
+            if let Some(free) = free_memory.get_mut(&host_numa_node) {
+                *free = free.saturating_sub(size);
+            }
+
+            info!(
+                "Binding guest NUMA node {} onto host NUMA node {}",
+                numa_config.guest_numa_id, host_numa_node
+            );
+            for zone in node_zones.iter_mut() {
+                zone.host_numa_node = Some(host_numa_node);
+            }
+        }
+    }
+
+    // Map each guest NUMA node onto the host NUMA node its memory zones are
+    // bound to, which is what the "auto-numa" vCPU placement relies on.
+    fn host_numa_nodes(config: &VmConfig) -> BTreeMap<u32, u32> {
+        let mut host_numa_nodes = BTreeMap::new();
+        let zones = config.memory.zones.as_deref().unwrap_or(&[]);
+
+        for numa_config in config.numa.iter().flatten() {
+            let host_numa_node = numa_config
+                .memory_zones
+                .iter()
+                .flatten()
+                .filter_map(|id| zones.iter().find(|zone| &zone.id == id))
+                .find_map(|zone| zone.host_numa_node);
+            if let Some(host_numa_node) = host_numa_node {
+                host_numa_nodes.insert(numa_config.guest_numa_id, host_numa_node);
+            }
+        }
+
+        host_numa_nodes
+    }
+
     fn create_numa_nodes(
         configs: Option<Vec<NumaConfig>>,
         memory_manager: &Arc<Mutex<MemoryManager>>,
@@ -735,6 +1058,9 @@ impl Vm {
                     }
                 }
 
+                node.memory_latency = config.memory_latency;
+                node.memory_bandwidth = config.memory_bandwidth;
+
                 numa_nodes.insert(config.guest_numa_id, node);
             }
         }
@@ -747,6 +1073,8 @@ impl Vm {
         vm_config: Arc<Mutex<VmConfig>>,
         exit_evt: EventFd,
         reset_evt: EventFd,
+        panic_evt: EventFd,
+        watchdog_evt: EventFd,
         #[cfg(feature = "guest_debug")] vm_debug_evt: EventFd,
         seccomp_action: &SeccompAction,
         hypervisor: Arc<dyn hypervisor::Hypervisor>,
@@ -769,10 +1097,32 @@ impl Vm {
             vm_config.lock().unwrap().is_tdx_enabled()
         };
 
+        #[cfg(feature = "sev_snp")]
+        let sev_snp_enabled = if snapshot.is_some() {
+            false
+        } else {
+            vm_config.lock().unwrap().is_sev_snp_enabled()
+        };
+        #[cfg(feature = "sev_snp")]
+        let sev_es_enabled = if snapshot.is_some() {
+            false
+        } else {
+            vm_config.lock().unwrap().is_sev_es_enabled()
+        };
+
+        #[cfg(target_arch = "aarch64")]
+        let mte_enabled = vm_config.lock().unwrap().cpus.features.mte;
+
         let vm = Self::create_hypervisor_vm(
             &hypervisor,
             #[cfg(feature = "tdx")]
             tdx_enabled,
+            #[cfg(feature = "sev_snp")]
+            sev_snp_enabled,
+            #[cfg(feature = "sev_snp")]
+            sev_es_enabled,
+            #[cfg(target_arch = "aarch64")]
+            mte_enabled,
         )?;
 
         let phys_bits = physical_bits(vm_config.lock().unwrap().cpus.max_phys_bits);
@@ -787,12 +1137,16 @@ impl Vm {
                 source_url,
                 prefault.unwrap(),
                 phys_bits,
+                #[cfg(target_arch = "aarch64")]
+                mte_enabled,
             )
             .map_err(Error::MemoryManager)?
         } else {
             #[cfg(target_arch = "x86_64")]
             let sgx_epc_config = vm_config.lock().unwrap().sgx_epc.clone();
 
+            Self::assign_host_numa_nodes(&mut vm_config.lock().unwrap());
+
             MemoryManager::new(
                 vm.clone(),
                 &vm_config.lock().unwrap().memory.clone(),
@@ -800,8 +1154,13 @@ impl Vm {
                 phys_bits,
                 #[cfg(feature = "tdx")]
                 tdx_enabled,
+                #[cfg(feature = "sev_snp")]
+                sev_snp_enabled,
+                #[cfg(target_arch = "aarch64")]
+                mte_enabled,
                 None,
                 None,
+                false,
                 #[cfg(target_arch = "x86_64")]
                 sgx_epc_config,
             )
@@ -814,6 +1173,8 @@ impl Vm {
             vm,
             exit_evt,
             reset_evt,
+            panic_evt,
+            watchdog_evt,
             #[cfg(feature = "guest_debug")]
             vm_debug_evt,
             seccomp_action,
@@ -830,16 +1191,31 @@ impl Vm {
     pub fn create_hypervisor_vm(
         hypervisor: &Arc<dyn hypervisor::Hypervisor>,
         #[cfg(feature = "tdx")] tdx_enabled: bool,
+        #[cfg(feature = "sev_snp")] sev_snp_enabled: bool,
+        #[cfg(feature = "sev_snp")] sev_es_enabled: bool,
+        #[cfg(target_arch = "aarch64")] mte_enabled: bool,
     ) -> Result<Arc<dyn hypervisor::Vm>> {
         hypervisor.check_required_extensions().unwrap();
 
         // 0 for KVM_X86_LEGACY_VM
         // 1 for KVM_X86_TDX_VM
+        // 3 for KVM_X86_SEV_ES_VM
+        // 4 for KVM_X86_SNP_VM
         #[cfg(feature = "tdx")]
-        let vm = hypervisor
-            .create_vm_with_type(u64::from(tdx_enabled))
-            .unwrap();
-        #[cfg(not(feature = "tdx"))]
+        let vm_type = u64::from(tdx_enabled);
+        #[cfg(all(feature = "sev_snp", not(feature = "tdx")))]
+        let vm_type = 0;
+        #[cfg(feature = "sev_snp")]
+        let vm_type = if sev_snp_enabled {
+            4
+        } else if sev_es_enabled {
+            3
+        } else {
+            vm_type
+        };
+        #[cfg(any(feature = "tdx", feature = "sev_snp"))]
+        let vm = hypervisor.create_vm_with_type(vm_type).unwrap();
+        #[cfg(not(any(feature = "tdx", feature = "sev_snp")))]
         let vm = hypervisor.create_vm().unwrap();
 
         #[cfg(target_arch = "x86_64")]
@@ -848,12 +1224,22 @@ impl Vm {
                 .unwrap();
             vm.set_tss_address(KVM_TSS_START.0 as usize).unwrap();
             vm.enable_split_irq().unwrap();
+            vm.enable_x2apic_api().unwrap();
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        if mte_enabled {
+            vm.enable_mte().map_err(Error::EnableMte)?;
         }
 
         Ok(vm)
     }
 
-    fn load_initramfs(&mut self, guest_mem: &GuestMemoryMmap) -> Result<arch::InitramfsConfig> {
+    fn load_initramfs(
+        &mut self,
+        guest_mem: &GuestMemoryMmap,
+        #[cfg(target_arch = "x86_64")] above_4g: bool,
+    ) -> Result<arch::InitramfsConfig> {
         let mut initramfs = self.initramfs.as_ref().unwrap();
         let size: usize = initramfs
             .seek(SeekFrom::End(0))
@@ -862,12 +1248,17 @@ impl Vm {
             .unwrap();
         initramfs.rewind().map_err(|_| Error::InitramfsLoad)?;
 
-        let address =
-            arch::initramfs_load_addr(guest_mem, size).map_err(|_| Error::InitramfsLoad)?;
+        let address = arch::initramfs_load_addr(
+            guest_mem,
+            size,
+            #[cfg(target_arch = "x86_64")]
+            above_4g,
+        )
+        .map_err(|_| Error::InitramfsLoad)?;
         let address = GuestAddress(address);
 
         guest_mem
-            .read_from(address, &mut initramfs, size)
+            .read_exact_from(address, &mut initramfs, size)
             .map_err(|_| Error::InitramfsLoad)?;
 
         info!("Initramfs loaded: address = 0x{:x}", address.0);
@@ -938,11 +1329,15 @@ impl Vm {
         Ok(EntryPoint { entry_addr })
     }
 
+    // `la57` tells whether the guest must start with 5-level paging, in
+    // which case the 64-bit entry point of a kernel is preferred over its
+    // PVH entry point.
     #[cfg(target_arch = "x86_64")]
-    fn load_kernel(
-        mut kernel: File,
+    fn load_kernel<F: Read + Seek>(
+        mut kernel: F,
         cmdline: Option<Cmdline>,
         memory_manager: Arc<Mutex<MemoryManager>>,
+        la57: bool,
     ) -> Result<EntryPoint> {
         info!("Loading kernel");
 
@@ -950,27 +1345,105 @@ impl Vm {
             let guest_memory = memory_manager.lock().as_ref().unwrap().guest_memory();
             guest_memory.memory()
         };
-        let entry_addr = linux_loader::loader::elf::Elf::load(
-            mem.deref(),
-            None,
-            &mut kernel,
-            Some(arch::layout::HIGH_RAM_START),
-        )
-        .map_err(Error::KernelLoad)?;
 
         if let Some(cmdline) = cmdline {
             linux_loader::loader::load_cmdline(mem.deref(), arch::layout::CMDLINE_START, &cmdline)
                 .map_err(Error::LoadCmdLine)?;
         }
 
-        if let PvhEntryPresent(entry_addr) = entry_addr.pvh_boot_cap {
+        // Multiboot2 images are often ELF binaries as well, so the header
+        // must be looked for first.
+        if let Some(entry_addr) = arch::x86_64::multiboot2::load(mem.deref(), &mut kernel)
+            .map_err(Error::Multiboot2Load)?
+        {
+            info!(
+                "Multiboot2 kernel loaded: entry_addr = 0x{:x}, using the Multiboot2 boot protocol",
+                entry_addr.0
+            );
+            return Ok(EntryPoint {
+                entry_addr: Some(entry_addr),
+                protocol: BootProtocol::Multiboot2Boot,
+                setup_header: None,
+            });
+        }
+
+        // Decompressing the kernel embedded in a bzImage is faster than
+        // letting the guest run its own decompressor. The resulting ELF
+        // binary is then loaded as any other ELF kernel.
+        let entry_addr = if let Some(vmlinux) =
+            arch::x86_64::bzimage::decompress(&mut kernel).map_err(Error::KernelDecompress)?
+        {
+            info!("bzImage decompressed: {} bytes", vmlinux.len());
+            linux_loader::loader::elf::Elf::load(
+                mem.deref(),
+                None,
+                &mut io::Cursor::new(vmlinux),
+                Some(arch::layout::HIGH_RAM_START),
+            )
+            .map_err(Error::KernelLoad)?
+        } else {
+            match linux_loader::loader::elf::Elf::load(
+                mem.deref(),
+                None,
+                &mut kernel,
+                Some(arch::layout::HIGH_RAM_START),
+            ) {
+                Ok(entry_addr) => entry_addr,
+                // Not an ELF binary, retry loading it as a bzImage
+                Err(linux_loader::loader::Error::Elf(InvalidElfMagicNumber)) => {
+                    linux_loader::loader::bzimage::BzImage::load(
+                        mem.deref(),
+                        None,
+                        &mut kernel,
+                        Some(arch::layout::HIGH_RAM_START),
+                    )
+                    .map_err(Error::KernelLoad)?
+                }
+                Err(e) => return Err(Error::KernelLoad(e)),
+            }
+        };
+
+        // PVH guests start without paging and set up their own 4-level page
+        // tables, which would prevent them from mapping the RAM beyond the
+        // 4-level paging limit.
+        let pvh_entry_addr = match entry_addr.pvh_boot_cap {
+            PvhEntryPresent(pvh_entry_addr) if !la57 => Some(pvh_entry_addr),
+            _ => None,
+        };
+
+        if let Some(pvh_entry_addr) = pvh_entry_addr {
             // Use the PVH kernel entry point to boot the guest
-            info!("Kernel loaded: entry_addr = 0x{:x}", entry_addr.0);
+            info!("Kernel loaded: entry_addr = 0x{:x}", pvh_entry_addr.0);
             Ok(EntryPoint {
-                entry_addr: Some(entry_addr),
+                entry_addr: Some(pvh_entry_addr),
+                protocol: BootProtocol::PvhBoot,
+                setup_header: None,
+            })
+        } else if entry_addr.setup_header.is_some() {
+            // The 64-bit entry point of a bzImage is located 0x200 bytes
+            // after the start of the protected mode kernel.
+            let entry = entry_addr.kernel_load.unchecked_add(0x200);
+            info!(
+                "bzImage loaded: entry_addr = 0x{:x}, using the Linux boot protocol",
+                entry.0
+            );
+            Ok(EntryPoint {
+                entry_addr: Some(entry),
+                protocol: BootProtocol::LinuxBoot,
+                setup_header: entry_addr.setup_header,
             })
         } else {
-            Err(Error::KernelMissingPvhHeader)
+            // ELF kernel without PVH note or started with 5-level paging,
+            // its entry point follows the Linux 64-bit boot protocol.
+            info!(
+                "Kernel loaded without PVH entry point: entry_addr = 0x{:x}, using the Linux boot protocol",
+                entry_addr.kernel_load.0
+            );
+            Ok(EntryPoint {
+                entry_addr: Some(entry_addr.kernel_load),
+                protocol: BootProtocol::LinuxBoot,
+                setup_header: None,
+            })
         }
     }
 
@@ -988,12 +1461,25 @@ impl Vm {
         ) {
             (Some(firmware), None, None, None) => {
                 let firmware = File::open(firmware).map_err(Error::FirmwareFile)?;
-                Self::load_kernel(firmware, None, memory_manager)
+                Self::load_kernel(firmware, None, memory_manager, false)
+            }
+            #[cfg(builtin_fw_image)]
+            (None, Some(kernel), _, _) if kernel.as_path() == Path::new(BUILTIN_FIRMWARE) => {
+                info!("Using builtin firmware");
+                let cmdline = Self::generate_cmdline(payload)?;
+                Self::load_kernel(
+                    io::Cursor::new(BUILTIN_FIRMWARE_IMAGE),
+                    Some(cmdline),
+                    memory_manager,
+                    false,
+                )
             }
             (None, Some(kernel), _, _) => {
                 let kernel = File::open(kernel).map_err(Error::KernelFile)?;
                 let cmdline = Self::generate_cmdline(payload)?;
-                Self::load_kernel(kernel, Some(cmdline), memory_manager)
+                let la57 = memory_manager.lock().unwrap().start_of_device_area()
+                    > arch::layout::RAM_4LEVEL_PAGING_END;
+                Self::load_kernel(kernel, Some(cmdline), memory_manager, la57)
             }
             _ => Err(Error::InvalidPayload),
         }
@@ -1027,6 +1513,12 @@ impl Vm {
             return Ok(None);
         }
 
+        // SEV firmware is loaded and measured during boot
+        #[cfg(feature = "sev_snp")]
+        if config.lock().unwrap().is_sev_enabled() {
+            return Ok(None);
+        }
+
         config
             .lock()
             .unwrap()
@@ -1044,18 +1536,50 @@ impl Vm {
             .transpose()
     }
 
+    // Describe each memory zone as a memory device, located in the bank
+    // matching its NUMA node.
     #[cfg(target_arch = "x86_64")]
-    fn configure_system(&mut self, rsdp_addr: GuestAddress) -> Result<()> {
+    fn smbios_memory_devices(&self) -> Vec<arch::SmbiosMemoryDevice> {
+        let memory_manager = self.memory_manager.lock().unwrap();
+        let mut zones: Vec<(&String, u64)> = memory_manager
+            .memory_zones()
+            .iter()
+            .map(|(id, zone)| (id, zone.regions().iter().map(|r| r.len()).sum()))
+            .filter(|(_, size)| *size > 0)
+            .collect();
+        zones.sort();
+
+        zones
+            .into_iter()
+            .map(|(id, size)| arch::SmbiosMemoryDevice {
+                size,
+                device_locator: id.clone(),
+                bank_locator: self
+                    .numa_nodes
+                    .iter()
+                    .find(|(_, node)| node.memory_zones.contains(id))
+                    .map(|(node_id, _)| format!("NODE {node_id}")),
+            })
+            .collect()
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn configure_system(&mut self, rsdp_addr: GuestAddress, entry_point: EntryPoint) -> Result<()> {
         trace_scoped!("configure_system");
         info!("Configuring system");
         let mem = self.memory_manager.lock().unwrap().boot_guest_memory();
 
         let initramfs_config = match self.initramfs {
-            Some(_) => Some(self.load_initramfs(&mem)?),
+            Some(_) => Some(self.load_initramfs(&mem, entry_point.initramfs_above_4g())?),
             None => None,
         };
 
-        let boot_vcpus = self.cpu_manager.lock().unwrap().boot_vcpus();
+        let apic_ids: Vec<u32> = {
+            let cpu_manager = self.cpu_manager.lock().unwrap();
+            (0..cpu_manager.boot_vcpus())
+                .map(|cpu_id| cpu_manager.apic_id(cpu_id))
+                .collect()
+        };
         let rsdp_addr = Some(rsdp_addr);
         let sgx_epc_region = self
             .memory_manager
@@ -1081,7 +1605,7 @@ impl Vm {
             .as_ref()
             .and_then(|p| p.uuid.clone());
 
-        let oem_strings = self
+        let mut oem_strings = self
             .config
             .lock()
             .unwrap()
@@ -1089,27 +1613,111 @@ impl Vm {
             .as_ref()
             .and_then(|p| p.oem_strings.clone());
 
+        // The Ignition config is exposed through fw_cfg instead when enabled
+        let ignition = self.config.lock().unwrap().ignition.clone();
+        let fw_cfg_enabled = self.config.lock().unwrap().fw_cfg.is_some();
+        if let Some(ignition) = ignition.filter(|_| !fw_cfg_enabled) {
+            oem_strings
+                .get_or_insert_with(Vec::new)
+                .push(ignition_oem_string(&ignition.path).map_err(Error::IgnitionConfig)?);
+        }
+
         let oem_strings = oem_strings
             .as_deref()
             .map(|strings| strings.iter().map(|s| s.as_ref()).collect::<Vec<&str>>());
 
+        let mut memmap_regions: Vec<(GuestAddress, u64, MemmapType)> = self
+            .config
+            .lock()
+            .unwrap()
+            .platform
+            .as_ref()
+            .and_then(|p| p.reserved_regions.as_ref())
+            .map(|regions| {
+                regions
+                    .iter()
+                    .map(|r| (GuestAddress(r.start), r.size, MemmapType::Reserved))
+                    .collect()
+            })
+            .unwrap_or_default();
+        memmap_regions.extend(self.memory_zones_memmap_regions());
+
+        let memory_devices = self.smbios_memory_devices();
+
+        let smbios_file = self
+            .config
+            .lock()
+            .unwrap()
+            .platform
+            .as_ref()
+            .and_then(|p| p.smbios_file.clone());
+        let smbios_table = smbios_file
+            .map(std::fs::read)
+            .transpose()
+            .map_err(Error::SmbiosFile)?;
+
+        let mptable = self
+            .config
+            .lock()
+            .unwrap()
+            .platform
+            .as_ref()
+            .map_or(true, |p| p.mptable);
+
         arch::configure_system(
             &mem,
             arch::layout::CMDLINE_START,
             &initramfs_config,
-            boot_vcpus,
+            &apic_ids,
             rsdp_addr,
             sgx_epc_region,
             serial_number.as_deref(),
             uuid.as_deref(),
             oem_strings.as_deref(),
+            entry_point.protocol,
+            entry_point.setup_header,
+            &memmap_regions,
+            &memory_devices,
+            smbios_table.as_deref(),
+            mptable,
         )
         .map_err(Error::ConfigureSystem)?;
         Ok(())
     }
 
+    // Regions of the memory zones which must not be reported as RAM.
+    #[cfg(target_arch = "x86_64")]
+    fn memory_zones_memmap_regions(&self) -> Vec<(GuestAddress, u64, MemmapType)> {
+        let mut memmap_regions = Vec::new();
+
+        if let Some(zones) = &self.config.lock().unwrap().memory.zones {
+            let memory_manager = self.memory_manager.lock().unwrap();
+            for zone in zones {
+                let memmap_type = match zone.memmap_type {
+                    crate::config::MemmapType::Ram => continue,
+                    crate::config::MemmapType::AcpiReclaim => MemmapType::AcpiReclaim,
+                    crate::config::MemmapType::AcpiNvs => MemmapType::AcpiNvs,
+                    crate::config::MemmapType::Pmem => MemmapType::Pmem,
+                    crate::config::MemmapType::SoftReserved => MemmapType::SoftReserved,
+                };
+
+                if let Some(memory_zone) = memory_manager.memory_zones().get(&zone.id) {
+                    for region in memory_zone.regions() {
+                        memmap_regions.push((region.start_addr(), region.len(), memmap_type));
+                    }
+                }
+            }
+        }
+
+        memmap_regions
+    }
+
     #[cfg(target_arch = "aarch64")]
-    fn configure_system(&mut self, _rsdp_addr: GuestAddress) -> Result<()> {
+    fn configure_system(
+        &mut self,
+        _rsdp_addr: GuestAddress,
+        _entry_point: EntryPoint,
+    ) -> Result<()> {
         let cmdline = Self::generate_cmdline(
             self.config.lock().unwrap().payload.as_ref().unwrap(),
             &self.device_manager,
@@ -1166,16 +1774,17 @@ impl Vm {
             })?;
 
         // PMU interrupt sticks to PPI, so need to be added by 16 to get real irq number.
-        let pmu_supported = self
-            .cpu_manager
-            .lock()
-            .unwrap()
-            .init_pmu(arch::aarch64::fdt::AARCH64_PMU_IRQ + 16)
-            .map_err(|_| {
-                Error::ConfigureSystem(arch::Error::PlatformSpecific(
-                    arch::aarch64::Error::VcpuInitPmu,
-                ))
-            })?;
+        let pmu_supported = self.config.lock().unwrap().cpus.features.pmu
+            && self
+                .cpu_manager
+                .lock()
+                .unwrap()
+                .init_pmu(arch::aarch64::fdt::AARCH64_PMU_IRQ + 16)
+                .map_err(|_| {
+                    Error::ConfigureSystem(arch::Error::PlatformSpecific(
+                        arch::aarch64::Error::VcpuInitPmu,
+                    ))
+                })?;
 
         arch::configure_system(
             &mem,
@@ -1227,6 +1836,13 @@ impl Vm {
             signals.close();
         }
 
+        // Trigger the termination of the balloon policy thread
+        self.balloon_policy_stop.take();
+
+        // Trigger the termination of the input injection thread, which
+        // releases the keys it is holding down.
+        self.input_injector.take();
+
         // Wake up the DeviceManager threads so they will get terminated cleanly
         self.device_manager
             .lock()
@@ -1253,12 +1869,34 @@ impl Vm {
 
     pub fn resize(
         &mut self,
-        desired_vcpus: Option<u8>,
+        desired_vcpus: Option<u32>,
         desired_memory: Option<u64>,
         desired_balloon: Option<u64>,
+        removed_vcpus: Option<Vec<u32>>,
     ) -> Result<()> {
         event!("vm", "resizing");
 
+        if desired_vcpus.is_some() && removed_vcpus.is_some() {
+            return Err(Error::ResizeVcpusConflict);
+        }
+
+        if let Some(removed_vcpus) = removed_vcpus {
+            let remaining_vcpus = self
+                .cpu_manager
+                .lock()
+                .unwrap()
+                .remove_vcpus(&removed_vcpus)
+                .map_err(Error::CpuManager)?;
+            if let Some(remaining_vcpus) = remaining_vcpus {
+                self.device_manager
+                    .lock()
+                    .unwrap()
+                    .notify_hotplug(AcpiNotificationFlags::CPU_DEVICES_CHANGED)
+                    .map_err(Error::DeviceManager)?;
+                self.config.lock().unwrap().cpus.boot_vcpus = remaining_vcpus;
+            }
+        }
+
         if let Some(desired_vcpus) = desired_vcpus {
             if self
                 .cpu_manager
@@ -1332,6 +1970,8 @@ impl Vm {
             if let Some(balloon_config) = &mut self.config.lock().unwrap().balloon {
                 balloon_config.size = desired_balloon;
             }
+
+            event!("vm", "balloon-resized", "size", desired_balloon.to_string());
         }
 
         event!("vm", "resized");
@@ -1345,28 +1985,27 @@ impl Vm {
         if let Some(zones) = &mut memory_config.zones {
             for zone in zones.iter_mut() {
                 if zone.id == id {
-                    if desired_memory >= zone.size {
-                        let hotplugged_size = desired_memory - zone.size;
-                        self.memory_manager
-                            .lock()
-                            .unwrap()
-                            .resize_zone(&id, desired_memory - zone.size)
-                            .map_err(Error::MemoryManager)?;
-                        // We update the memory zone config regardless of the
-                        // actual 'resize-zone' operation result (happened or
-                        // not), so that if the VM reboots it will be running
-                        // with the last configured memory zone size.
-                        zone.hotplugged_size = Some(hotplugged_size);
-
-                        return Ok(());
+                    // The boot RAM of the zone can't be unplugged, asking for
+                    // less only unplugs all the virtio-mem memory of the zone.
+                    let hotplugged_size = desired_memory.saturating_sub(zone.size);
+                    self.memory_manager
+                        .lock()
+                        .unwrap()
+                        .resize_zone(&id, hotplugged_size)
+                        .map_err(Error::MemoryManager)?;
+                    // We update the memory zone config regardless of the
+                    // actual 'resize-zone' operation result (happened or
+                    // not), so that if the VM reboots it will be running
+                    // with the last configured memory zone size.
+                    zone.hotplugged_size = if hotplugged_size > 0 {
+                        Some(hotplugged_size)
                     } else {
-                        error!(
-                            "Invalid to ask less ({}) than boot RAM ({}) for \
-                            this memory zone",
-                            desired_memory, zone.size,
-                        );
-                        return Err(Error::ResizeZone);
-                    }
+                        None
+                    };
+
+                    event!("vm", "zone-resized", "id", id.as_str());
+
+                    return Ok(());
                 }
             }
         }
@@ -1424,6 +2063,10 @@ impl Vm {
     }
 
     pub fn remove_device(&mut self, id: String) -> Result<()> {
+        // The device can be referred to either by its identifier or by its
+        // user provided alias.
+        let id = self.config.lock().unwrap().resolve_device_id(&id);
+
         self.device_manager
             .lock()
             .unwrap()
@@ -1632,6 +2275,47 @@ impl Vm {
         Ok(self.device_manager.lock().unwrap().counters())
     }
 
+    pub fn vcpus_exits(&self) -> Vec<(u8, u64)> {
+        self.cpu_manager.lock().unwrap().vcpus_exits()
+    }
+
+    pub fn vcpus_info(&self) -> Vec<VcpuInfo> {
+        self.cpu_manager.lock().unwrap().vcpus_info()
+    }
+
+    pub fn vcpus_stats(&self) -> Vec<VcpuStats> {
+        self.cpu_manager.lock().unwrap().vcpus_stats()
+    }
+
+    /// Layout of the memory zones, sorted by identifier.
+    pub fn memory_zones_info(&self) -> Vec<MemoryZoneInfo> {
+        let memory_manager = self.memory_manager.lock().unwrap();
+        let to_range = |region: &Arc<GuestRegionMmap>| AddressRange {
+            base: region.start_addr().raw_value(),
+            size: region.len(),
+        };
+
+        let mut zones: Vec<MemoryZoneInfo> = memory_manager
+            .memory_zones()
+            .iter()
+            .map(|(id, zone)| MemoryZoneInfo {
+                id: id.clone(),
+                regions: zone.regions().iter().map(to_range).collect(),
+                hotplug_region: zone
+                    .virtio_mem_zone()
+                    .as_ref()
+                    .map(|z| to_range(z.region())),
+                hotplugged_size: zone
+                    .virtio_mem_zone()
+                    .as_ref()
+                    .map_or(0, |z| z.hotplugged_size()),
+            })
+            .collect();
+        zones.sort_by(|a, b| a.id.cmp(&b.id));
+
+        zones
+    }
+
     fn signal_handler(mut signals: Signals, console_input_clone: Arc<Console>) {
         for sig in &Vm::HANDLED_SIGNALS {
             unblock_signal(*sig).unwrap();
@@ -1871,11 +2555,19 @@ impl Vm {
         }
 
         // MMIO regions
+        let mem_32bit_devices_start = self
+            .memory_manager
+            .lock()
+            .unwrap()
+            .allocator()
+            .lock()
+            .unwrap()
+            .mmio_hole_base()
+            .raw_value();
         hob.add_mmio_resource(
             &mem,
-            arch::layout::MEM_32BIT_DEVICES_START.raw_value(),
-            arch::layout::APIC_START.raw_value()
-                - arch::layout::MEM_32BIT_DEVICES_START.raw_value(),
+            mem_32bit_devices_start,
+            arch::layout::APIC_START.raw_value() - mem_32bit_devices_start,
         )
         .map_err(Error::PopulateHob)?;
         let start_of_device_area = self
@@ -1940,6 +2632,211 @@ impl Vm {
         Ok(())
     }
 
+    #[cfg(feature = "sev_snp")]
+    fn open_sev_firmware(&self) -> Result<File> {
+        let firmware_path = self
+            .config
+            .lock()
+            .unwrap()
+            .payload
+            .as_ref()
+            .unwrap()
+            .firmware
+            .clone()
+            .ok_or(Error::SevFirmwareMissing)?;
+        File::open(firmware_path).map_err(Error::LoadSevFirmware)
+    }
+
+    // Load the firmware right below 4GiB, where the reset vector is
+    // expected.
+    #[cfg(feature = "sev_snp")]
+    fn load_sev_firmware(&mut self, firmware_file: &mut File) -> Result<(GuestAddress, u64)> {
+        let firmware_len = firmware_file
+            .seek(SeekFrom::End(0))
+            .map_err(Error::LoadSevFirmware)?;
+        let firmware_size = (firmware_len + 0xfff) & !0xfff;
+        // The firmware can't overlap the interrupt address range, spanning
+        // 1MiB from the local APIC.
+        if firmware_size
+            > arch::layout::RAM_64BIT_START.0 - (arch::layout::APIC_START.0 + (1 << 20))
+        {
+            return Err(Error::FirmwareTooLarge);
+        }
+        let firmware_address = GuestAddress(arch::layout::RAM_64BIT_START.0 - firmware_size);
+
+        info!(
+            "Allocating SEV firmware: {:x} {:x}",
+            firmware_address.0, firmware_size
+        );
+        self.memory_manager
+            .lock()
+            .unwrap()
+            .add_ram_region(firmware_address, firmware_size as usize)
+            .map_err(Error::AllocatingSevMemory)?;
+
+        firmware_file
+            .seek(SeekFrom::Start(0))
+            .map_err(Error::LoadSevFirmware)?;
+        let guest_memory = self.memory_manager.lock().as_ref().unwrap().guest_memory();
+        guest_memory
+            .memory()
+            .read_exact_from(firmware_address, firmware_file, firmware_len as usize)
+            .map_err(Error::FirmwareLoad)?;
+
+        Ok((firmware_address, firmware_size))
+    }
+
+    // Load the firmware and back the sections described by its SEV metadata
+    // with RAM when they are not part of it already.
+    #[cfg(feature = "sev_snp")]
+    fn populate_sev_snp_firmware(&mut self) -> Result<(GuestAddress, u64, Vec<SevSnpSection>)> {
+        use arch::x86_64::sev_snp::*;
+
+        let mut firmware_file = self.open_sev_firmware()?;
+        let sections =
+            parse_sev_snp_sections(&mut firmware_file).map_err(Error::ParseSevMetadata)?;
+
+        // Get the memory end *before* we start adding firmware ram regions
+        let boot_guest_memory = self
+            .memory_manager
+            .lock()
+            .as_ref()
+            .unwrap()
+            .boot_guest_memory();
+        let (firmware_address, firmware_size) = self.load_sev_firmware(&mut firmware_file)?;
+        for section in sections.iter() {
+            if boot_guest_memory.address_in_range(GuestAddress(section.address)) {
+                continue;
+            }
+
+            info!("Allocating SEV-SNP section: {:x?}", section);
+            self.memory_manager
+                .lock()
+                .unwrap()
+                .add_ram_region(GuestAddress(section.address), section.size as usize)
+                .map_err(Error::AllocatingSevMemory)?;
+        }
+
+        Ok((firmware_address, firmware_size, sections))
+    }
+
+    // Load the firmware and retrieve the reset vector of the application
+    // processors from it.
+    #[cfg(feature = "sev_snp")]
+    fn populate_sev_es_firmware(&mut self) -> Result<(GuestAddress, u64, u32)> {
+        let mut firmware_file = self.open_sev_firmware()?;
+        let reset_vector = arch::x86_64::sev_snp::sev_es_reset_vector(&mut firmware_file)
+            .map_err(Error::ParseSevMetadata)?;
+        let (firmware_address, firmware_size) = self.load_sev_firmware(&mut firmware_file)?;
+
+        Ok((firmware_address, firmware_size, reset_vector))
+    }
+
+    // Encrypt and measure the initial guest memory, made of the firmware,
+    // the sections described by its SEV metadata and the ACPI tables, then
+    // seal the launch measurement.
+    #[cfg(feature = "sev_snp")]
+    fn sev_snp_launch(
+        &mut self,
+        firmware_address: GuestAddress,
+        firmware_size: u64,
+        sections: &[SevSnpSection],
+    ) -> Result<()> {
+        use arch::x86_64::sev_snp::*;
+        use hypervisor::kvm::SevSnpPageType;
+
+        let guest_memory = self.memory_manager.lock().as_ref().unwrap().guest_memory();
+        let mem = guest_memory.memory();
+
+        let mut regions = vec![
+            (firmware_address, firmware_size, SevSnpPageType::Normal),
+            (
+                arch::layout::RSDP_POINTER,
+                arch::layout::HIGH_RAM_START.0 - arch::layout::RSDP_POINTER.0,
+                SevSnpPageType::Normal,
+            ),
+        ];
+        for section in sections {
+            let page_type = match section.r#type {
+                SevSnpSectionType::SecMem => SevSnpPageType::Zero,
+                SevSnpSectionType::Secrets => SevSnpPageType::Secrets,
+                SevSnpSectionType::Cpuid => {
+                    let cpuid = self.cpu_manager.lock().unwrap().common_cpuid();
+                    create_cpuid_page(&mem, GuestAddress(section.address), &cpuid)
+                        .map_err(Error::CreateSevSnpCpuidPage)?;
+                    SevSnpPageType::Cpuid
+                }
+            };
+            regions.push((GuestAddress(section.address), section.size, page_type));
+        }
+
+        for (address, size, page_type) in regions {
+            info!(
+                "Updating SEV-SNP launch region: {:x} {:x} {:?}",
+                address.0, size, page_type
+            );
+            let host_address = mem
+                .get_host_address(address)
+                .map_err(Error::SevLaunchMemory)?;
+            self.vm
+                .sev_snp_launch_update(host_address as u64, address.0, size, page_type)
+                .map_err(Error::SevSnpLaunchUpdate)?;
+        }
+
+        self.vm
+            .sev_snp_launch_finish(&[0; 32])
+            .map_err(Error::SevSnpLaunchFinish)
+    }
+
+    // Pin the guest memory, then encrypt and measure the firmware, the ACPI
+    // tables and the initial state of the vCPUs. The resulting measurement
+    // is reported so that it can be attested by the guest owner.
+    #[cfg(feature = "sev_snp")]
+    fn sev_es_launch(&mut self, firmware_address: GuestAddress, firmware_size: u64) -> Result<()> {
+        let guest_memory = self.memory_manager.lock().as_ref().unwrap().guest_memory();
+        let mem = guest_memory.memory();
+
+        for region in mem.iter() {
+            self.vm
+                .sev_register_encrypted_region(region.as_ptr() as u64, region.len())
+                .map_err(Error::SevEsLaunch)?;
+        }
+
+        let regions = [
+            (firmware_address, firmware_size),
+            (
+                arch::layout::RSDP_POINTER,
+                arch::layout::HIGH_RAM_START.0 - arch::layout::RSDP_POINTER.0,
+            ),
+        ];
+        for (address, size) in regions {
+            info!("Updating SEV-ES launch region: {:x} {:x}", address.0, size);
+            let host_address = mem
+                .get_host_address(address)
+                .map_err(Error::SevLaunchMemory)?;
+            self.vm
+                .sev_es_launch_update_data(host_address as u64, size)
+                .map_err(Error::SevEsLaunch)?;
+        }
+
+        self.vm
+            .sev_es_launch_update_vmsa()
+            .map_err(Error::SevEsLaunch)?;
+        let measurement = self
+            .vm
+            .sev_es_launch_measure()
+            .map_err(Error::SevEsLaunch)?;
+        info!(
+            "SEV-ES launch measurement: {}",
+            measurement
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect::<String>()
+        );
+
+        self.vm.sev_es_launch_finish().map_err(Error::SevEsLaunch)
+    }
+
     fn setup_signal_handler(&mut self) -> Result<()> {
         let console = self.device_manager.lock().unwrap().console().clone();
         let signals = Signals::new(Vm::HANDLED_SIGNALS);
@@ -1988,6 +2885,169 @@ impl Vm {
         Ok(())
     }
 
+    fn setup_balloon_policy(&mut self) -> Result<()> {
+        let (min_size, max_size) = match &self.config.lock().unwrap().balloon {
+            Some(balloon) if balloon.auto_size => (balloon.min_size, balloon.max_size),
+            _ => return Ok(()),
+        };
+        let balloon = match self.device_manager.lock().unwrap().balloon() {
+            Some(balloon) => balloon,
+            None => return Ok(()),
+        };
+
+        let mut policy = crate::balloon_policy::BalloonPolicy::new(balloon, min_size, max_size);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        self.balloon_policy_stop = Some(sender);
+
+        let exit_evt = self.exit_evt.try_clone().map_err(Error::EventFdClone)?;
+        let balloon_policy_seccomp_filter = get_seccomp_filter(
+            &self.seccomp_action,
+            Thread::BalloonPolicy,
+            self.hypervisor.hypervisor_type(),
+        )
+        .map_err(Error::CreateSeccompFilter)?;
+        self.threads.push(
+            thread::Builder::new()
+                .name("balloon_policy".to_string())
+                .spawn(move || {
+                    if !balloon_policy_seccomp_filter.is_empty() {
+                        if let Err(e) = apply_filter(&balloon_policy_seccomp_filter)
+                            .map_err(Error::ApplySeccompFilter)
+                        {
+                            error!("Error applying seccomp filter: {:?}", e);
+                            exit_evt.write(1).ok();
+                            return;
+                        }
+                    }
+                    std::panic::catch_unwind(AssertUnwindSafe(|| {
+                        policy.run(receiver);
+                    }))
+                    .map_err(|_| {
+                        error!("balloon_policy thread panicked");
+                        exit_evt.write(1).ok()
+                    })
+                    .ok();
+                })
+                .map_err(Error::BalloonPolicySpawn)?,
+        );
+
+        Ok(())
+    }
+
+    // Forward the quote requests of the TDX guest to the host Quote
+    // Generation Service, from a dedicated thread as the service can take a
+    // while to answer.
+    #[cfg(feature = "tdx")]
+    fn setup_tdx_quote_generator(&mut self) -> Result<()> {
+        let (service, max_phys_bits) = {
+            let config = self.config.lock().unwrap();
+            match config.platform.as_ref().and_then(|p| p.tdx_qgs.clone()) {
+                Some(service) => (service, config.cpus.max_phys_bits),
+                None => return Ok(()),
+            }
+        };
+
+        let quote_generator = crate::tdx_quote::QuoteGenerator::new(
+            service,
+            self.memory_manager.lock().unwrap().guest_memory(),
+            physical_bits(max_phys_bits),
+        );
+        let (sender, receiver) = std::sync::mpsc::channel();
+        self.cpu_manager
+            .lock()
+            .unwrap()
+            .set_tdx_quote_sender(sender);
+
+        let exit_evt = self.exit_evt.try_clone().map_err(Error::EventFdClone)?;
+        let tdx_quote_seccomp_filter = get_seccomp_filter(
+            &self.seccomp_action,
+            Thread::TdxQuote,
+            self.hypervisor.hypervisor_type(),
+        )
+        .map_err(Error::CreateSeccompFilter)?;
+        self.threads.push(
+            thread::Builder::new()
+                .name("tdx_quote".to_string())
+                .spawn(move || {
+                    if !tdx_quote_seccomp_filter.is_empty() {
+                        if let Err(e) = apply_filter(&tdx_quote_seccomp_filter)
+                            .map_err(Error::ApplySeccompFilter)
+                        {
+                            error!("Error applying seccomp filter: {:?}", e);
+                            exit_evt.write(1).ok();
+                            return;
+                        }
+                    }
+                    std::panic::catch_unwind(AssertUnwindSafe(|| {
+                        quote_generator.run(receiver);
+                    }))
+                    .map_err(|_| {
+                        error!("tdx_quote thread panicked");
+                        exit_evt.write(1).ok()
+                    })
+                    .ok();
+                })
+                .map_err(Error::TdxQuoteSpawn)?,
+        );
+
+        Ok(())
+    }
+
+    fn input_devices(&self) -> InputDevices {
+        let device_manager = self.device_manager.lock().unwrap();
+        InputDevices {
+            ps2_device: device_manager.ps2_device().ok(),
+            console_input: device_manager.console_input(),
+        }
+    }
+
+    fn setup_input_injector(&mut self) -> Result<()> {
+        let input_devices = self.input_devices();
+        if input_devices.ready(ConsoleInputKind::Keyboard).is_none()
+            && input_devices.ready(ConsoleInputKind::Mouse).is_none()
+        {
+            return Ok(());
+        }
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut input_injector = InputInjector::new(input_devices, receiver);
+        self.input_injector = Some(sender);
+
+        let exit_evt = self.exit_evt.try_clone().map_err(Error::EventFdClone)?;
+        let input_injection_seccomp_filter = get_seccomp_filter(
+            &self.seccomp_action,
+            Thread::InputInjection,
+            self.hypervisor.hypervisor_type(),
+        )
+        .map_err(Error::CreateSeccompFilter)?;
+        self.threads.push(
+            thread::Builder::new()
+                .name("input_injection".to_string())
+                .spawn(move || {
+                    if !input_injection_seccomp_filter.is_empty() {
+                        if let Err(e) = apply_filter(&input_injection_seccomp_filter)
+                            .map_err(Error::ApplySeccompFilter)
+                        {
+                            error!("Error applying seccomp filter: {:?}", e);
+                            exit_evt.write(1).ok();
+                            return;
+                        }
+                    }
+                    std::panic::catch_unwind(AssertUnwindSafe(|| {
+                        input_injector.run();
+                    }))
+                    .map_err(|_| {
+                        error!("input_injection thread panicked");
+                        exit_evt.write(1).ok()
+                    })
+                    .ok();
+                })
+                .map_err(Error::InputInjectionSpawn)?,
+        );
+
+        Ok(())
+    }
+
     fn setup_tty(&self) -> Result<()> {
         if self.on_tty {
             io::stdin()
@@ -2054,6 +3114,8 @@ impl Vm {
 
         self.setup_signal_handler()?;
         self.setup_tty()?;
+        self.setup_balloon_policy()?;
+        self.setup_input_injector()?;
 
         // Load kernel synchronously or if asynchronous then wait for load to
         // finish.
@@ -2062,6 +3124,19 @@ impl Vm {
         #[cfg(feature = "tdx")]
         let tdx_enabled = self.config.lock().unwrap().is_tdx_enabled();
 
+        #[cfg(feature = "sev_snp")]
+        let sev_snp_launch = if self.config.lock().unwrap().is_sev_snp_enabled() {
+            Some(self.populate_sev_snp_firmware()?)
+        } else {
+            None
+        };
+        #[cfg(feature = "sev_snp")]
+        let sev_es_launch = if self.config.lock().unwrap().is_sev_es_enabled() {
+            Some(self.populate_sev_es_firmware()?)
+        } else {
+            None
+        };
+
         // Configure the vcpus that have been created
         let vcpus = self.cpu_manager.lock().unwrap().vcpus();
         for vcpu in vcpus {
@@ -2074,6 +3149,15 @@ impl Vm {
                 .map_err(Error::CpuManager)?;
         }
 
+        #[cfg(feature = "sev_snp")]
+        if let Some((_, _, reset_vector)) = sev_es_launch {
+            self.cpu_manager
+                .lock()
+                .unwrap()
+                .setup_sev_es_ap_reset_vector(reset_vector)
+                .map_err(Error::CpuManager)?;
+        }
+
         #[cfg(feature = "tdx")]
         let (sections, guid_found) = if tdx_enabled {
             self.extract_tdvf_sections()?
@@ -2097,10 +3181,10 @@ impl Vm {
 
         // Configure shared state based on loaded kernel
         entry_point
-            .map(|_| {
+            .map(|entry_point| {
                 // Safe to unwrap rsdp_addr as we know it can't be None when
                 // the entry_point is Some.
-                self.configure_system(rsdp_addr.unwrap())
+                self.configure_system(rsdp_addr.unwrap(), entry_point)
             })
             .transpose()?;
 
@@ -2119,6 +3203,7 @@ impl Vm {
             self.init_tdx_memory(&sections)?;
             // With TDX memory and CPU state configured TDX setup is complete
             self.vm.tdx_finalize().map_err(Error::FinalizeTdx)?;
+            self.setup_tdx_quote_generator()?;
         }
 
         #[cfg(target_arch = "x86_64")]
@@ -2133,6 +3218,17 @@ impl Vm {
             .allocate_address_space()
             .map_err(Error::MemoryManager)?;
 
+        // With the guest memory registered as private, the initial memory
+        // can be encrypted and measured.
+        #[cfg(feature = "sev_snp")]
+        if let Some((firmware_address, firmware_size, sections)) = sev_snp_launch {
+            self.sev_snp_launch(firmware_address, firmware_size, &sections)?;
+        }
+        #[cfg(feature = "sev_snp")]
+        if let Some((firmware_address, firmware_size, _)) = sev_es_launch {
+            self.sev_es_launch(firmware_address, firmware_size)?;
+        }
+
         self.cpu_manager
             .lock()
             .unwrap()
@@ -2169,6 +3265,8 @@ impl Vm {
 
         self.setup_signal_handler()?;
         self.setup_tty()?;
+        self.setup_balloon_policy()?;
+        self.setup_input_injector()?;
 
         event!("vm", "restored");
         Ok(())
@@ -2192,6 +3290,10 @@ impl Vm {
         self.device_manager.lock().unwrap().balloon_size()
     }
 
+    pub fn virtio_mem_size(&self) -> (u64, u64) {
+        self.memory_manager.lock().unwrap().virtio_mem_size()
+    }
+
     pub fn send_memory_fds(
         &mut self,
         socket: &mut UnixStream,
@@ -2277,6 +3379,56 @@ impl Vm {
             .memory_range_table(false)
     }
 
+    pub fn guest_memory(&self) -> GuestMemoryAtomic<GuestMemoryMmap> {
+        self.memory_manager.lock().unwrap().guest_memory()
+    }
+
+    /// Measures the memory dirtied by the guest over the given period. The
+    /// amount of memory dirtied is returned for each guest RAM region, as
+    /// (start address, size, dirtied bytes).
+    pub fn dirty_memory(&mut self, period: Duration) -> Result<Vec<(u64, u64, u64)>> {
+        if self.get_state()? != VmState::Running {
+            return Err(Error::VmNotRunning);
+        }
+
+        self.start_dirty_log().map_err(Error::DirtyLog)?;
+        thread::sleep(period);
+        let table = self.dirty_log();
+        self.stop_dirty_log().map_err(Error::DirtyLog)?;
+        let table = table.map_err(Error::DirtyLog)?;
+
+        let guest_memory = self.guest_memory().memory();
+        Ok(guest_memory
+            .iter()
+            .map(|region| {
+                let start = region.start_addr().raw_value();
+                let end = start + region.len();
+                let dirty = table
+                    .regions()
+                    .iter()
+                    .map(|r| {
+                        let overlap_start = r.gpa.max(start);
+                        let overlap_end = (r.gpa + r.length).min(end);
+                        overlap_end.saturating_sub(overlap_start)
+                    })
+                    .sum();
+                (start, region.len(), dirty)
+            })
+            .collect())
+    }
+
+    pub fn set_vcpu_throttle(&self, percentage: u8) -> Result<()> {
+        cpu::CpuManager::set_throttle(&self.cpu_manager, percentage).map_err(Error::CpuManager)
+    }
+
+    pub fn vcpu_throttle(&self) -> u8 {
+        self.cpu_manager.lock().unwrap().throttle()
+    }
+
+    pub fn hibernated(&self) -> bool {
+        self.device_manager.lock().unwrap().hibernated()
+    }
+
     pub fn device_tree(&self) -> Arc<Mutex<DeviceTree>> {
         self.device_manager.lock().unwrap().device_tree()
     }
@@ -2308,6 +3460,75 @@ impl Vm {
             .map_err(Error::PowerButton)
     }
 
+    pub fn send_keys(&self, keys: &[String], hold_time: Duration) -> Result<()> {
+        // Validate the whole sequence before sending anything to the guest.
+        let combinations = keys
+            .iter()
+            .map(|combination| {
+                Key::parse_combination(combination)
+                    .ok_or_else(|| Error::UnknownKey(combination.clone()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        self.check_input_ready(ConsoleInputKind::Keyboard)?;
+
+        // The keys are held down from the input injection thread.
+        self.send_input(InputRequest::Keys {
+            combinations,
+            hold_time,
+        })
+    }
+
+    fn check_input_ready(&self, kind: ConsoleInputKind) -> Result<()> {
+        match self.input_devices().ready(kind) {
+            Some(true) => Ok(()),
+            Some(false) => Err(Error::InputDeviceNotReady),
+            None => Err(Error::InputInjection(DeviceManagerError::NoInputDevice)),
+        }
+    }
+
+    fn send_input(&self, request: InputRequest) -> Result<()> {
+        self.input_injector
+            .as_ref()
+            .ok_or(Error::InputDeviceNotReady)?
+            .send(request)
+            .map_err(|_| Error::InputDeviceNotReady)
+    }
+
+    pub fn change_media(&self, controller: &str, lun: u16, path: Option<&Path>) -> Result<()> {
+        let controller = self.config.lock().unwrap().resolve_device_id(controller);
+        self.device_manager
+            .lock()
+            .unwrap()
+            .change_media(&controller, lun, path)
+            .map_err(Error::ChangeMedia)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    pub fn nmi(&self, vcpu_id: Option<u32>) -> Result<()> {
+        self.cpu_manager
+            .lock()
+            .unwrap()
+            .nmi(vcpu_id)
+            .map_err(Error::CpuManager)
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    pub fn nmi(&self, _vcpu_id: Option<u32>) -> Result<()> {
+        Err(Error::NmiUnsupported)
+    }
+
+    pub fn send_pointer(&self, dx: i32, dy: i32, buttons: u8, hold_time: Duration) -> Result<()> {
+        self.check_input_ready(ConsoleInputKind::Mouse)?;
+
+        self.send_input(InputRequest::Pointer {
+            dx,
+            dy,
+            buttons,
+            hold_time,
+        })
+    }
+
     pub fn memory_manager_data(&self) -> MemoryManagerSnapshotData {
         self.memory_manager.lock().unwrap().snapshot_data()
     }
@@ -2366,7 +3587,7 @@ impl Vm {
         &mut self,
         destination_url: &str,
     ) -> std::result::Result<DumpState, GuestDebuggableError> {
-        let nr_cpus = self.config.lock().unwrap().cpus.boot_vcpus as u32;
+        let nr_cpus = self.config.lock().unwrap().cpus.boot_vcpus;
         let elf_note_size = self.get_note_size(NoteDescType::ElfAndVmm, nr_cpus) as isize;
         let mut elf_phdr_num = 1;
         let elf_sh_info = 0;
@@ -2484,8 +3705,13 @@ pub struct VmSnapshot {
     pub clock: Option<hypervisor::ClockData>,
     #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
     pub common_cpuid: Vec<hypervisor::arch::x86::CpuIdEntry>,
+    #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+    #[serde(default)]
+    pub msr_features: Vec<hypervisor::arch::x86::MsrEntry>,
 }
 
+impl StateUpgrade for VmSnapshot {}
+
 pub const VM_SNAPSHOT_ID: &str = "vm";
 impl Snapshottable for Vm {
     fn id(&self) -> String {
@@ -2507,6 +3733,13 @@ impl Snapshottable for Vm {
             }
         }
 
+        #[cfg(feature = "sev_snp")]
+        if self.config.lock().unwrap().is_sev_enabled() {
+            return Err(MigratableError::Snapshot(anyhow!(
+                "Snapshot not possible with SEV VM"
+            )));
+        }
+
         let current_state = self.get_state().unwrap();
         if current_state != VmState::Paused {
             return Err(MigratableError::Snapshot(anyhow!(
@@ -2516,30 +3749,48 @@ impl Snapshottable for Vm {
 
         #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
         let common_cpuid = {
-            let phys_bits = physical_bits(self.config.lock().unwrap().cpus.max_phys_bits);
+            let config = self.config.lock().unwrap();
+            let phys_bits = physical_bits(config.cpus.max_phys_bits);
             arch::generate_common_cpuid(
                 &self.hypervisor,
-                None,
-                None,
-                phys_bits,
-                self.config.lock().unwrap().cpus.kvm_hyperv,
-                #[cfg(feature = "tdx")]
-                tdx_enabled,
+                arch::CpuidConfig {
+                    phys_bits,
+                    kvm_hyperv: config.cpus.kvm_hyperv,
+                    cpu_model: config.cpus.model.clone(),
+                    enabled_features: config.cpus.features.cpuid_enable.clone(),
+                    disabled_features: config.cpus.features.cpuid_disable.clone(),
+                    amx: config.cpus.features.amx,
+                    disabled_kvm_features: config.cpus.features.kvm_disable.clone(),
+                    hybrid: config
+                        .cpus
+                        .topology
+                        .as_ref()
+                        .map_or(false, |t| t.efficiency_cores_per_die > 0),
+                    #[cfg(feature = "tdx")]
+                    tdx: tdx_enabled,
+                    ..Default::default()
+                },
             )
             .map_err(|e| {
                 MigratableError::MigrateReceive(anyhow!("Error generating common cpuid: {:?}", e))
             })?
         };
+        #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+        let msr_features = arch::generate_msr_features(&self.hypervisor).map_err(|e| {
+            MigratableError::Snapshot(anyhow!("Error getting feature MSRs: {:?}", e))
+        })?;
 
         let vm_snapshot_data = serde_json::to_vec(&VmSnapshot {
             #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
             clock: self.saved_clock,
             #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
             common_cpuid,
+            #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+            msr_features,
         })
         .map_err(|e| MigratableError::Snapshot(e.into()))?;
 
-        let mut vm_snapshot = Snapshot::from_data(SnapshotData(vm_snapshot_data));
+        let mut vm_snapshot = Snapshot::from_data(SnapshotData::new(vm_snapshot_data));
 
         let (id, snapshot) = {
             let mut cpu_manager = self.cpu_manager.lock().unwrap();
@@ -2562,12 +3813,40 @@ impl Snapshottable for Vm {
     }
 }
 
+impl Vm {
+    // Store the whole snapshot in a single file
+    fn send_snapshot_file(
+        &self,
+        snapshot: &Snapshot,
+        path: &Path,
+    ) -> std::result::Result<(), MigratableError> {
+        let vm_config = serde_json::to_vec(self.config.lock().unwrap().deref())
+            .map_err(|e| MigratableError::MigrateSend(e.into()))?;
+        let vm_state =
+            serde_json::to_vec(snapshot).map_err(|e| MigratableError::MigrateSend(e.into()))?;
+
+        let memory_manager = self.memory_manager.lock().unwrap();
+        write_snapshot_file(
+            path,
+            &vm_config,
+            &vm_state,
+            &memory_manager.guest_memory().memory(),
+            memory_manager.snapshot_memory_ranges(),
+        )
+    }
+}
+
 impl Transportable for Vm {
     fn send(
         &self,
         snapshot: &Snapshot,
         destination_url: &str,
     ) -> std::result::Result<(), MigratableError> {
+        let snapshot_path = url_to_snapshot_path(destination_url)?;
+        if !snapshot_path.is_dir() {
+            return self.send_snapshot_file(snapshot, &snapshot_path);
+        }
+
         let mut snapshot_config_path = url_to_path(destination_url)?;
         snapshot_config_path.push(SNAPSHOT_CONFIG_FILE);
 
@@ -2757,6 +4036,13 @@ impl GuestDebuggable for Vm {
             }
         }
 
+        #[cfg(feature = "sev_snp")]
+        if self.config.lock().unwrap().is_sev_enabled() {
+            return Err(GuestDebuggableError::Coredump(anyhow!(
+                "Coredump not possible with SEV VM"
+            )));
+        }
+
         let current_state = self.get_state().unwrap();
         if current_state != VmState::Paused {
             return Err(GuestDebuggableError::Coredump(anyhow!(
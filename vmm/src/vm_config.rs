@@ -9,7 +9,7 @@ use virtio_devices::RateLimiterConfig;
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct CpuAffinity {
-    pub vcpu: u8,
+    pub vcpu: u32,
     pub host_cpus: Vec<u8>,
 }
 
@@ -18,6 +18,55 @@ pub struct CpuFeatures {
     #[cfg(target_arch = "x86_64")]
     #[serde(default)]
     pub amx: bool,
+    #[cfg(target_arch = "x86_64")]
+    #[serde(default)]
+    pub sgx_provision: bool,
+    #[cfg(target_arch = "x86_64")]
+    #[serde(default)]
+    pub cpuid_enable: Vec<String>,
+    #[cfg(target_arch = "x86_64")]
+    #[serde(default)]
+    pub cpuid_disable: Vec<String>,
+    #[cfg(target_arch = "x86_64")]
+    #[serde(default)]
+    pub kvm_disable: Vec<String>,
+    #[cfg(target_arch = "aarch64")]
+    #[serde(default)]
+    pub pmu: bool,
+    #[cfg(target_arch = "aarch64")]
+    #[serde(default)]
+    pub mte: bool,
+    #[cfg(target_arch = "aarch64")]
+    #[serde(default)]
+    pub pauth: bool,
+    #[cfg(target_arch = "aarch64")]
+    #[serde(default)]
+    pub nested: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum CpuPlacement {
+    /// Pin the vCPUs of each guest NUMA node onto the host CPUs of the host
+    /// NUMA node backing its memory.
+    AutoNuma,
+    /// Pack the vCPUs onto as few host cores and packages as possible.
+    Compact,
+    /// Spread the vCPUs across as many host packages and cores as possible.
+    Scatter,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum CpuSchedulingPolicy {
+    /// First in, first out real-time policy (SCHED_FIFO)
+    Fifo,
+    /// Round robin real-time policy (SCHED_RR)
+    RoundRobin,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CpuScheduling {
+    pub policy: CpuSchedulingPolicy,
+    pub priority: u8,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
@@ -26,6 +75,11 @@ pub struct CpuTopology {
     pub cores_per_die: u8,
     pub dies_per_package: u8,
     pub packages: u8,
+    /// Number of efficiency cores among the cores of each die, the remaining
+    /// ones being performance cores. Non zero values describe a hybrid CPU.
+    #[cfg(target_arch = "x86_64")]
+    #[serde(default)]
+    pub efficiency_cores_per_die: u8,
 }
 
 // When booting with PVH boot the maximum physical addressable size
@@ -39,8 +93,8 @@ pub fn default_cpuconfig_max_phys_bits() -> u8 {
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct CpusConfig {
-    pub boot_vcpus: u8,
-    pub max_vcpus: u8,
+    pub boot_vcpus: u32,
+    pub max_vcpus: u32,
     #[serde(default)]
     pub topology: Option<CpuTopology>,
     #[serde(default)]
@@ -51,9 +105,25 @@ pub struct CpusConfig {
     pub affinity: Option<Vec<CpuAffinity>>,
     #[serde(default)]
     pub features: CpuFeatures,
+    #[serde(default)]
+    pub placement: Option<CpuPlacement>,
+    #[serde(default)]
+    pub sched: Option<CpuScheduling>,
+    #[cfg(target_arch = "x86_64")]
+    #[serde(default)]
+    pub model: Option<String>,
+    #[cfg(target_arch = "x86_64")]
+    #[serde(default)]
+    pub microcode_revision: Option<u32>,
+    #[cfg(target_arch = "x86_64")]
+    #[serde(default)]
+    pub apic_ids: Option<Vec<u32>>,
+    #[cfg(target_arch = "aarch64")]
+    #[serde(default)]
+    pub sve_vl: Option<u32>,
 }
 
-pub const DEFAULT_VCPUS: u8 = 1;
+pub const DEFAULT_VCPUS: u32 = 1;
 
 impl Default for CpusConfig {
     fn default() -> Self {
@@ -65,6 +135,16 @@ impl Default for CpusConfig {
             max_phys_bits: DEFAULT_MAX_PHYS_BITS,
             affinity: None,
             features: CpuFeatures::default(),
+            placement: None,
+            sched: None,
+            #[cfg(target_arch = "x86_64")]
+            model: None,
+            #[cfg(target_arch = "x86_64")]
+            microcode_revision: None,
+            #[cfg(target_arch = "x86_64")]
+            apic_ids: None,
+            #[cfg(target_arch = "aarch64")]
+            sve_vl: None,
         }
     }
 }
@@ -74,6 +154,11 @@ pub fn default_platformconfig_num_pci_segments() -> u16 {
     DEFAULT_NUM_PCI_SEGMENTS
 }
 
+#[cfg(target_arch = "x86_64")]
+pub fn default_platformconfig_mptable() -> bool {
+    true
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct PlatformConfig {
     #[serde(default = "default_platformconfig_num_pci_segments")]
@@ -89,6 +174,43 @@ pub struct PlatformConfig {
     #[cfg(feature = "tdx")]
     #[serde(default)]
     pub tdx: bool,
+    #[cfg(feature = "tdx")]
+    #[serde(default)]
+    pub tdx_qgs: Option<QuoteGenerationService>,
+    #[cfg(feature = "sev_snp")]
+    #[serde(default)]
+    pub sev_snp: bool,
+    #[cfg(feature = "sev_snp")]
+    #[serde(default)]
+    pub sev_es: bool,
+    #[cfg(target_arch = "x86_64")]
+    #[serde(default)]
+    pub ps2: bool,
+    #[cfg(target_arch = "x86_64")]
+    #[serde(default)]
+    pub reserved_regions: Option<Vec<ReservedRegion>>,
+    #[cfg(target_arch = "x86_64")]
+    #[serde(default)]
+    pub smbios_file: Option<PathBuf>,
+    #[cfg(target_arch = "x86_64")]
+    #[serde(default = "default_platformconfig_mptable")]
+    pub mptable: bool,
+}
+
+/// Host Quote Generation Service TDX guests get their quotes from.
+#[cfg(feature = "tdx")]
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum QuoteGenerationService {
+    Unix(PathBuf),
+    Vsock { cid: u32, port: u32 },
+}
+
+/// Guest physical range reported as reserved through the E820 memory map.
+#[cfg(target_arch = "x86_64")]
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ReservedRegion {
+    pub start: u64,
+    pub size: u64,
 }
 
 impl Default for PlatformConfig {
@@ -101,6 +223,20 @@ impl Default for PlatformConfig {
             oem_strings: None,
             #[cfg(feature = "tdx")]
             tdx: false,
+            #[cfg(feature = "tdx")]
+            tdx_qgs: None,
+            #[cfg(feature = "sev_snp")]
+            sev_snp: false,
+            #[cfg(feature = "sev_snp")]
+            sev_es: false,
+            #[cfg(target_arch = "x86_64")]
+            ps2: false,
+            #[cfg(target_arch = "x86_64")]
+            reserved_regions: None,
+            #[cfg(target_arch = "x86_64")]
+            smbios_file: None,
+            #[cfg(target_arch = "x86_64")]
+            mptable: true,
         }
     }
 }
@@ -125,6 +261,11 @@ pub struct MemoryZoneConfig {
     pub hotplugged_size: Option<u64>,
     #[serde(default)]
     pub prefault: bool,
+    #[serde(default)]
+    pub prefault_threads: Option<u32>,
+    #[cfg(target_arch = "x86_64")]
+    #[serde(default)]
+    pub memmap_type: MemmapType,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, Default)]
@@ -134,6 +275,17 @@ pub enum HotplugMethod {
     VirtioMem,
 }
 
+#[cfg(target_arch = "x86_64")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, Default)]
+pub enum MemmapType {
+    #[default]
+    Ram,
+    AcpiReclaim,
+    AcpiNvs,
+    Pmem,
+    SoftReserved,
+}
+
 fn default_memoryconfig_thp() -> bool {
     true
 }
@@ -161,6 +313,11 @@ pub struct MemoryConfig {
     pub zones: Option<Vec<MemoryZoneConfig>>,
     #[serde(default = "default_memoryconfig_thp")]
     pub thp: bool,
+    /// Size of the 32-bit MMIO hole, reserved for the 32-bit PCI devices
+    /// and the PCI MMCONFIG space right below 4GiB.
+    #[cfg(target_arch = "x86_64")]
+    #[serde(default)]
+    pub mmio32_hole_size: Option<u64>,
 }
 
 pub const DEFAULT_MEMORY_MB: u64 = 512;
@@ -179,6 +336,8 @@ impl Default for MemoryConfig {
             prefault: false,
             zones: None,
             thp: true,
+            #[cfg(target_arch = "x86_64")]
+            mmio32_hole_size: None,
         }
     }
 }
@@ -210,11 +369,17 @@ pub struct DiskConfig {
     pub rate_limiter_config: Option<RateLimiterConfig>,
     #[serde(default)]
     pub id: Option<String>,
+    #[serde(default)]
+    pub alias: Option<String>,
     // For testing use only. Not exposed in API.
     #[serde(default)]
     pub disable_io_uring: bool,
     #[serde(default)]
     pub pci_segment: u16,
+    #[serde(default)]
+    pub iothreads: Option<Vec<String>>,
+    #[serde(default)]
+    pub rate_limit_group: Option<String>,
 }
 
 pub const DEFAULT_DISK_NUM_QUEUES: usize = 1;
@@ -241,9 +406,12 @@ impl Default for DiskConfig {
             vhost_user: false,
             vhost_socket: None,
             id: None,
+            alias: None,
             disable_io_uring: false,
             rate_limiter_config: None,
             pci_segment: 0,
+            iothreads: None,
+            rate_limit_group: None,
         }
     }
 }
@@ -276,6 +444,8 @@ pub struct NetConfig {
     #[serde(default)]
     pub id: Option<String>,
     #[serde(default)]
+    pub alias: Option<String>,
+    #[serde(default)]
     pub fds: Option<Vec<i32>>,
     #[serde(default)]
     pub rate_limiter_config: Option<RateLimiterConfig>,
@@ -286,7 +456,17 @@ pub struct NetConfig {
     #[serde(default = "default_netconfig_true")]
     pub offload_ufo: bool,
     #[serde(default = "default_netconfig_true")]
+    pub offload_uso: bool,
+    #[serde(default = "default_netconfig_true")]
     pub offload_csum: bool,
+    #[serde(default)]
+    pub iothreads: Option<Vec<String>>,
+    #[serde(default)]
+    pub rate_limit_group: Option<String>,
+    #[serde(default)]
+    pub rss: bool,
+    #[serde(default)]
+    pub vhost: bool,
 }
 
 pub fn default_netconfig_true() -> bool {
@@ -337,12 +517,18 @@ impl Default for NetConfig {
             vhost_socket: None,
             vhost_mode: VhostMode::Client,
             id: None,
+            alias: None,
             fds: None,
             rate_limiter_config: None,
             pci_segment: 0,
             offload_tso: true,
             offload_ufo: true,
+            offload_uso: true,
             offload_csum: true,
+            iothreads: None,
+            rate_limit_group: None,
+            rss: false,
+            vhost: false,
         }
     }
 }
@@ -374,6 +560,16 @@ pub struct BalloonConfig {
     /// Option to enable free page reporting from the guest.
     #[serde(default)]
     pub free_page_reporting: bool,
+    /// Option to let the VMM adjust the size of the balloon based on the
+    /// host memory pressure and the guest memory statistics.
+    #[serde(default)]
+    pub auto_size: bool,
+    /// Smallest size the balloon is automatically resized to.
+    #[serde(default)]
+    pub min_size: u64,
+    /// Largest size the balloon is automatically resized to.
+    #[serde(default)]
+    pub max_size: Option<u64>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
@@ -385,8 +581,14 @@ pub struct FsConfig {
     #[serde(default = "default_fsconfig_queue_size")]
     pub queue_size: u16,
     #[serde(default)]
+    pub dax: bool,
+    #[serde(default = "default_fsconfig_cache_size")]
+    pub cache_size: u64,
+    #[serde(default)]
     pub id: Option<String>,
     #[serde(default)]
+    pub alias: Option<String>,
+    #[serde(default)]
     pub pci_segment: u16,
 }
 
@@ -398,6 +600,10 @@ pub fn default_fsconfig_queue_size() -> u16 {
     1024
 }
 
+pub fn default_fsconfig_cache_size() -> u64 {
+    0x0002_0000_0000
+}
+
 impl Default for FsConfig {
     fn default() -> Self {
         Self {
@@ -405,7 +611,10 @@ impl Default for FsConfig {
             socket: PathBuf::new(),
             num_queues: default_fsconfig_num_queues(),
             queue_size: default_fsconfig_queue_size(),
+            dax: false,
+            cache_size: default_fsconfig_cache_size(),
             id: None,
+            alias: None,
             pci_segment: 0,
         }
     }
@@ -423,6 +632,8 @@ pub struct PmemConfig {
     #[serde(default)]
     pub id: Option<String>,
     #[serde(default)]
+    pub alias: Option<String>,
+    #[serde(default)]
     pub pci_segment: u16,
 }
 
@@ -456,6 +667,8 @@ pub struct DeviceConfig {
     #[serde(default)]
     pub id: Option<String>,
     #[serde(default)]
+    pub alias: Option<String>,
+    #[serde(default)]
     pub pci_segment: u16,
 }
 
@@ -465,6 +678,8 @@ pub struct UserDeviceConfig {
     #[serde(default)]
     pub id: Option<String>,
     #[serde(default)]
+    pub alias: Option<String>,
+    #[serde(default)]
     pub pci_segment: u16,
 }
 
@@ -478,6 +693,8 @@ pub struct VdpaConfig {
     #[serde(default)]
     pub id: Option<String>,
     #[serde(default)]
+    pub alias: Option<String>,
+    #[serde(default)]
     pub pci_segment: u16,
 }
 
@@ -494,6 +711,8 @@ pub struct VsockConfig {
     #[serde(default)]
     pub id: Option<String>,
     #[serde(default)]
+    pub alias: Option<String>,
+    #[serde(default)]
     pub pci_segment: u16,
 }
 
@@ -520,7 +739,7 @@ pub struct NumaConfig {
     #[serde(default)]
     pub guest_numa_id: u32,
     #[serde(default)]
-    pub cpus: Option<Vec<u8>>,
+    pub cpus: Option<Vec<u32>>,
     #[serde(default)]
     pub distances: Option<Vec<NumaDistance>>,
     #[serde(default)]
@@ -528,12 +747,26 @@ pub struct NumaConfig {
     #[cfg(target_arch = "x86_64")]
     #[serde(default)]
     pub sgx_epc_sections: Option<Vec<String>>,
+    /// Latency in nanoseconds of the memory accesses from the closest vCPUs
+    #[serde(default)]
+    pub memory_latency: Option<u32>,
+    /// Bandwidth in MB/s of the memory accesses from the closest vCPUs
+    #[serde(default)]
+    pub memory_bandwidth: Option<u32>,
 }
 
+// Kernel path selecting the firmware embedded in the binary, available when
+// built with the "builtin_fw" feature and a firmware given through
+// CH_BUILTIN_FW.
+pub const BUILTIN_FIRMWARE: &str = "builtin-fw";
+
 #[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
 pub struct PayloadConfig {
     #[serde(default)]
     pub firmware: Option<PathBuf>,
+    #[cfg(target_arch = "aarch64")]
+    #[serde(default)]
+    pub firmware_vars: Option<PathBuf>,
     #[serde(default)]
     pub kernel: Option<PathBuf>,
     #[serde(default)]
@@ -563,6 +796,236 @@ pub struct TpmConfig {
     pub socket: PathBuf,
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct HibernationConfig {
+    pub path: PathBuf,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct IgnitionConfig {
+    pub path: PathBuf,
+}
+
+/// Blob exposed to the guest as a named fw_cfg file.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FwCfgFile {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FwCfgConfig {
+    #[serde(default = "default_fwcfgconfig_payload")]
+    pub payload: bool,
+    #[serde(default)]
+    pub files: Option<Vec<FwCfgFile>>,
+}
+
+pub fn default_fwcfgconfig_payload() -> bool {
+    true
+}
+
+impl Default for FwCfgConfig {
+    fn default() -> Self {
+        FwCfgConfig {
+            payload: default_fwcfgconfig_payload(),
+            files: None,
+        }
+    }
+}
+
+/// Action taken when the guest stops pinging the watchdog device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, Default)]
+pub enum WatchdogAction {
+    #[default]
+    Reset,
+    Poweroff,
+    Pause,
+    None,
+}
+
+/// Action taken when the guest reports a panic through the pvpanic device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, Default)]
+pub enum PanicAction {
+    #[default]
+    Pause,
+    Shutdown,
+    Coredump,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Default)]
+pub struct PvPanicConfig {
+    #[serde(default)]
+    pub on_panic: PanicAction,
+    #[serde(default)]
+    pub coredump_path: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct GpuConfig {
+    pub socket: PathBuf,
+    #[serde(default = "default_gpuconfig_width")]
+    pub width: u32,
+    #[serde(default = "default_gpuconfig_height")]
+    pub height: u32,
+    #[serde(default)]
+    pub iommu: bool,
+}
+
+pub fn default_gpuconfig_width() -> u32 {
+    1024
+}
+
+pub fn default_gpuconfig_height() -> u32 {
+    768
+}
+
+/// Host side of the sound streams.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, Default)]
+pub enum SoundBackend {
+    /// Raw frames exchanged through files, usually named pipes.
+    #[default]
+    Pipe,
+    /// ALSA PCM devices.
+    Alsa,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Default)]
+pub struct SoundConfig {
+    #[serde(default)]
+    pub backend: SoundBackend,
+    #[serde(default)]
+    pub playback: Option<PathBuf>,
+    #[serde(default)]
+    pub capture: Option<PathBuf>,
+    #[serde(default)]
+    pub iommu: bool,
+}
+
+/// Implementation of the symmetric algorithms of the crypto device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, Default)]
+pub enum CryptoBackend {
+    /// Host kernel crypto API, through AF_ALG sockets.
+    AfAlg,
+    #[default]
+    Software,
+}
+
+pub fn default_cryptoconfig_num_queues() -> usize {
+    1
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CryptoConfig {
+    #[serde(default)]
+    pub backend: CryptoBackend,
+    #[serde(default = "default_cryptoconfig_num_queues")]
+    pub num_queues: usize,
+    #[serde(default)]
+    pub iommu: bool,
+}
+
+impl Default for CryptoConfig {
+    fn default() -> Self {
+        CryptoConfig {
+            backend: CryptoBackend::default(),
+            num_queues: default_cryptoconfig_num_queues(),
+            iommu: false,
+        }
+    }
+}
+
+/// Kind of input device fed from the remote console.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum InputKind {
+    Keyboard,
+    Mouse,
+    Tablet,
+}
+
+/// Input device, fed either from the remote console served by the GPU
+/// device, given its `kind`, or from a host `evdev` node.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Default)]
+pub struct InputConfig {
+    #[serde(default)]
+    pub kind: Option<InputKind>,
+    #[serde(default)]
+    pub evdev: Option<PathBuf>,
+    #[serde(default)]
+    pub iommu: bool,
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub pci_segment: u16,
+}
+
+/// Named port of the multiport virtio-console device, backed on the host by
+/// either a Unix `socket` or a PTY. The path of the PTY allocated for the
+/// port is reported through `file`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Default)]
+pub struct ChannelConfig {
+    pub name: String,
+    #[serde(default)]
+    pub socket: Option<PathBuf>,
+    #[serde(default)]
+    pub pty: bool,
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+}
+
+/// Logical unit of a virtio-scsi controller. Disks and CD-ROM drives are
+/// emulated on top of the raw image at `path`, while `passthrough` units send
+/// the SCSI commands to the host device at `path`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Default)]
+pub struct ScsiLunConfig {
+    pub lun: u16,
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+    #[serde(default)]
+    pub readonly: bool,
+    #[serde(default)]
+    pub cdrom: bool,
+    #[serde(default)]
+    pub passthrough: bool,
+    #[serde(default)]
+    pub serial: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Default)]
+pub struct ScsiConfig {
+    #[serde(default)]
+    pub luns: Vec<ScsiLunConfig>,
+    #[serde(default)]
+    pub iommu: bool,
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub alias: Option<String>,
+    #[serde(default)]
+    pub pci_segment: u16,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CloudInitConfig {
+    pub user_data: PathBuf,
+    #[serde(default)]
+    pub meta_data: Option<PathBuf>,
+    #[serde(default)]
+    pub network_config: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RateLimiterGroupConfig {
+    pub id: String,
+    pub rate_limiter_config: RateLimiterConfig,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct IoThreadConfig {
+    pub id: String,
+    pub host_cpus: Vec<usize>,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct VmConfig {
     #[serde(default)]
@@ -592,8 +1055,23 @@ pub struct VmConfig {
     pub numa: Option<Vec<NumaConfig>>,
     #[serde(default)]
     pub watchdog: bool,
+    #[serde(default)]
+    pub watchdog_action: WatchdogAction,
     #[cfg(feature = "guest_debug")]
     pub gdb: bool,
     pub platform: Option<PlatformConfig>,
     pub tpm: Option<TpmConfig>,
+    pub iothreads: Option<Vec<IoThreadConfig>>,
+    pub rate_limit_groups: Option<Vec<RateLimiterGroupConfig>>,
+    pub hibernation: Option<HibernationConfig>,
+    pub cloud_init: Option<CloudInitConfig>,
+    pub ignition: Option<IgnitionConfig>,
+    pub fw_cfg: Option<FwCfgConfig>,
+    pub pvpanic: Option<PvPanicConfig>,
+    pub gpu: Option<GpuConfig>,
+    pub sound: Option<SoundConfig>,
+    pub input: Option<Vec<InputConfig>>,
+    pub channels: Option<Vec<ChannelConfig>>,
+    pub scsi: Option<Vec<ScsiConfig>>,
+    pub crypto: Option<CryptoConfig>,
 }